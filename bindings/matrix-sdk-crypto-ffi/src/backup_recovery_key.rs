@@ -94,6 +94,28 @@ impl BackupRecoveryKey {
         Ok(Arc::new(Self { inner: BackupDecryptionKey::from_base58(&key)?, passphrase_info: None }))
     }
 
+    /// Try to create a [`BackupRecoveryKey`] from a recovery key entered by
+    /// the user, in the base 58 or the base 64 format.
+    ///
+    /// Whitespace and dashes used to group the characters are ignored.
+    #[uniffi::constructor]
+    pub fn from_recovery_key(key: String) -> Result<Arc<Self>, DecodeError> {
+        Ok(Arc::new(Self {
+            inner: BackupDecryptionKey::from_recovery_key(&key)?,
+            passphrase_info: None,
+        }))
+    }
+
+    /// Try to create a [`BackupRecoveryKey`] from the contents of a recovery
+    /// key file, like the "Security Key" file exported by Element.
+    #[uniffi::constructor]
+    pub fn from_recovery_key_file(contents: Vec<u8>) -> Result<Arc<Self>, DecodeError> {
+        Ok(Arc::new(Self {
+            inner: BackupDecryptionKey::from_recovery_key_file(&contents)?,
+            passphrase_info: None,
+        }))
+    }
+
     /// Create a new [`BackupRecoveryKey`] from the given passphrase.
     #[uniffi::constructor]
     pub fn new_from_passphrase(passphrase: String) -> Arc<Self> {
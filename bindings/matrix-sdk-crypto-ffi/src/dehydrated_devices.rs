@@ -20,6 +20,8 @@ pub enum DehydrationError {
     Json(#[from] serde_json::Error),
     #[error(transparent)]
     Store(#[from] matrix_sdk_crypto::CryptoStoreError),
+    #[error(transparent)]
+    Olm(#[from] matrix_sdk_crypto::OlmError),
     #[error("The pickle key has an invalid length, expected 32 bytes, got {0}")]
     PickleKeyLength(usize),
 }
@@ -74,20 +76,13 @@ impl DehydratedDevices {
         let device_id: OwnedDeviceId = device_id.into();
 
         let mut key = get_pickle_key(&pickle_key)?;
+        let inner = self.runtime.block_on(self.inner.rehydrate(&key, &device_id, device_data));
 
-        let ret = RehydratedDevice {
-            runtime: self.runtime.to_owned(),
-            inner: ManuallyDrop::new(self.runtime.block_on(self.inner.rehydrate(
-                &key,
-                &device_id,
-                device_data,
-            ))?),
-        }
-        .into();
-
+        // Zeroize the key before bailing out on errors.
         key.zeroize();
 
-        Ok(ret)
+        Ok(RehydratedDevice { runtime: self.runtime.to_owned(), inner: ManuallyDrop::new(inner?) }
+            .into())
     }
 }
 
@@ -109,7 +104,7 @@ impl Drop for RehydratedDevice {
 
 #[uniffi::export]
 impl RehydratedDevice {
-    pub fn receive_events(&self, events: String) -> Result<(), crate::CryptoStoreError> {
+    pub fn receive_events(&self, events: String) -> Result<(), DehydrationError> {
         let events: Vec<Raw<AnyToDeviceEvent>> = serde_json::from_str(&events)?;
         self.runtime.block_on(self.inner.receive_events(events))?;
 
@@ -141,13 +136,11 @@ impl DehydratedDevice {
         pickle_key: Vec<u8>,
     ) -> Result<UploadDehydratedDeviceRequest, DehydrationError> {
         let mut key = get_pickle_key(&pickle_key)?;
-
-        let request =
-            self.runtime.block_on(self.inner.keys_for_upload(device_display_name, &key))?;
+        let request = self.runtime.block_on(self.inner.keys_for_upload(device_display_name, &key));
 
         key.zeroize();
 
-        Ok(request.into())
+        request?.try_into()
     }
 }
 
@@ -157,10 +150,14 @@ pub struct UploadDehydratedDeviceRequest {
     body: String,
 }
 
-impl From<dehydrated_device::put_dehydrated_device::unstable::Request>
+impl TryFrom<dehydrated_device::put_dehydrated_device::unstable::Request>
     for UploadDehydratedDeviceRequest
 {
-    fn from(value: dehydrated_device::put_dehydrated_device::unstable::Request) -> Self {
+    type Error = DehydrationError;
+
+    fn try_from(
+        value: dehydrated_device::put_dehydrated_device::unstable::Request,
+    ) -> Result<Self, Self::Error> {
         let body = json!({
             "device_id": value.device_id,
             "device_data": value.device_data,
@@ -170,10 +167,9 @@ impl From<dehydrated_device::put_dehydrated_device::unstable::Request>
             "fallback_keys": value.fallback_keys,
         });
 
-        let body = serde_json::to_string(&body)
-            .expect("We should be able to serialize the PUT dehydrated devices request body");
+        let body = serde_json::to_string(&body)?;
 
-        Self { body }
+        Ok(Self { body })
     }
 }
 
@@ -189,3 +185,36 @@ fn get_pickle_key(pickle_key: &[u8]) -> Result<Box<[u8; 32]>, DehydrationError>
         Err(DehydrationError::PickleKeyLength(pickle_key_length))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::DehydrationError;
+    use crate::OlmMachine;
+
+    #[test]
+    fn test_rehydrate_errors() {
+        let dir = tempdir().unwrap();
+        let machine = OlmMachine::new(
+            "@alice:localhost".to_owned(),
+            "ALICEDEVICE".to_owned(),
+            dir.path().to_str().unwrap().to_owned(),
+            None,
+        )
+        .unwrap();
+        let dehydrated_devices = machine.dehydrated_devices();
+
+        // `RehydratedDevice` isn't `Debug`, so `unwrap_err()` can't be used.
+        let result =
+            dehydrated_devices.rehydrate(vec![0; 16], "DEHYDRATED".to_owned(), "{}".to_owned());
+        assert!(matches!(result, Err(DehydrationError::PickleKeyLength(16))));
+
+        let result = dehydrated_devices.rehydrate(
+            vec![0; 32],
+            "DEHYDRATED".to_owned(),
+            "not json".to_owned(),
+        );
+        assert!(matches!(result, Err(DehydrationError::Json(_))));
+    }
+}
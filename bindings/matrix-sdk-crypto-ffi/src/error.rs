@@ -1,5 +1,7 @@
 #![allow(missing_docs)]
 
+use std::collections::HashMap;
+
 use matrix_sdk_crypto::{
     store::CryptoStoreError as InnerStoreError, KeyExportError, MegolmError, OlmError,
     SecretImportError as RustSecretImportError, SignatureError as InnerSignatureError,
@@ -59,6 +61,44 @@ pub enum CryptoStoreError {
     Identifier(#[from] IdParseError),
 }
 
+#[derive(Debug, thiserror::Error, uniffi::Error)]
+pub enum ShareRoomKeyError {
+    /// Some devices of the recipients aren't verified, and the encryption
+    /// settings ask to fail instead of sharing the room key with them.
+    ///
+    /// The device IDs are grouped by user ID.
+    #[error("the room key wasn't shared because some devices aren't verified")]
+    UnverifiedDevices { devices: HashMap<String, Vec<String>> },
+    /// One of the given user or room IDs couldn't be parsed.
+    #[error("identifier parsing error: {error}")]
+    Identifier { error: String },
+    /// Any other error that happened while sharing the room key.
+    #[error("olm error: {error}")]
+    Olm { error: String },
+}
+
+impl From<OlmError> for ShareRoomKeyError {
+    fn from(value: OlmError) -> Self {
+        match value {
+            OlmError::UnverifiedDevices(devices) => Self::UnverifiedDevices {
+                devices: devices
+                    .into_iter()
+                    .map(|(user_id, device_ids)| {
+                        (user_id.into(), device_ids.into_iter().map(Into::into).collect())
+                    })
+                    .collect(),
+            },
+            _ => Self::Olm { error: value.to_string() },
+        }
+    }
+}
+
+impl From<IdParseError> for ShareRoomKeyError {
+    fn from(err: IdParseError) -> Self {
+        Self::Identifier { error: err.to_string() }
+    }
+}
+
 #[derive(Debug, thiserror::Error, uniffi::Error)]
 pub enum DecryptionError {
     #[error("serialization error: {error}")]
@@ -105,10 +145,13 @@ impl From<InnerStoreError> for DecryptionError {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
+
     use assert_matches2::assert_let;
-    use matrix_sdk_crypto::MegolmError;
+    use matrix_sdk_crypto::{MegolmError, OlmError};
+    use ruma::{device_id, user_id};
 
-    use super::DecryptionError;
+    use super::{DecryptionError, ShareRoomKeyError};
 
     #[test]
     fn test_withheld_error_mapping() {
@@ -123,4 +166,18 @@ mod tests {
         );
         assert_eq!("m.unverified", code)
     }
+
+    #[test]
+    fn test_unverified_devices_error_mapping() {
+        let inner_error = OlmError::UnverifiedDevices(BTreeMap::from([(
+            user_id!("@alice:localhost").to_owned(),
+            vec![device_id!("ALICEDEVICE").to_owned()],
+        )]));
+
+        let binding_error: ShareRoomKeyError = inner_error.into();
+
+        assert_let!(ShareRoomKeyError::UnverifiedDevices { devices } = binding_error);
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices["@alice:localhost"], ["ALICEDEVICE"]);
+    }
 }
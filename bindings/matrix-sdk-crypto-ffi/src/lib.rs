@@ -24,7 +24,8 @@ pub use backup_recovery_key::{
 };
 pub use device::Device;
 pub use error::{
-    CryptoStoreError, DecryptionError, KeyImportError, SecretImportError, SignatureError,
+    CryptoStoreError, DecryptionError, KeyImportError, SecretImportError, ShareRoomKeyError,
+    SignatureError,
 };
 use js_int::UInt;
 pub use logger::{set_logger, Logger};
@@ -247,6 +248,7 @@ async fn migrate_data(
         shared: data.account.shared,
         uploaded_signed_key_count: data.account.uploaded_signed_key_count as u64,
         creation_local_time: MilliSecondsSinceUnixEpoch(UInt::default()),
+        fallback_key_creation_time: None,
     };
     let account = matrix_sdk_crypto::olm::Account::from_pickle(pickled_account)?;
 
@@ -636,6 +638,11 @@ pub struct EncryptionSettings {
     /// Should untrusted devices receive the room key, or should they be
     /// excluded from the conversation.
     pub only_allow_trusted_devices: bool,
+    /// Should sharing the room key fail with a
+    /// [`ShareRoomKeyError::UnverifiedDevices`] error if some devices of the
+    /// recipients aren't verified, instead of sharing the room key with them.
+    #[uniffi(default = false)]
+    pub error_on_unverified_devices: bool,
 }
 
 impl From<EncryptionSettings> for RustEncryptionSettings {
@@ -646,6 +653,7 @@ impl From<EncryptionSettings> for RustEncryptionSettings {
             rotation_period_msgs: v.rotation_period_msgs,
             history_visibility: v.history_visibility.into(),
             only_allow_trusted_devices: v.only_allow_trusted_devices,
+            error_on_unverified_devices: v.error_on_unverified_devices,
         }
     }
 }
@@ -823,6 +831,11 @@ pub struct RoomSettings {
     /// Should untrusted devices receive the room key, or should they be
     /// excluded from the conversation.
     pub only_allow_trusted_devices: bool,
+    /// Should sharing the room key fail with a
+    /// [`ShareRoomKeyError::UnverifiedDevices`] error if some devices of the
+    /// recipients aren't verified, instead of sharing the room key with them.
+    #[uniffi(default = false)]
+    pub error_on_unverified_devices: bool,
 }
 
 impl TryFrom<RustRoomSettings> for RoomSettings {
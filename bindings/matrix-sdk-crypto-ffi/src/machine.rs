@@ -49,7 +49,9 @@ use zeroize::Zeroize;
 
 use crate::{
     dehydrated_devices::DehydratedDevices,
-    error::{CryptoStoreError, DecryptionError, SecretImportError, SignatureError},
+    error::{
+        CryptoStoreError, DecryptionError, SecretImportError, ShareRoomKeyError, SignatureError,
+    },
     parse_user_id,
     responses::{response_from_string, OwnedResponse},
     BackupKeys, BackupRecoveryKey, BootstrapCrossSigningResult, CrossSigningKeyExport,
@@ -731,12 +733,15 @@ impl OlmMachine {
     /// room and should receive the room key.
     ///
     /// * `settings` - The settings that should be used for the room key.
+    ///
+    /// Returns a [`ShareRoomKeyError::UnverifiedDevices`] error listing the
+    /// unverified devices of the users if the settings ask for it.
     pub fn share_room_key(
         &self,
         room_id: String,
         users: Vec<String>,
         settings: EncryptionSettings,
-    ) -> Result<Vec<Request>, CryptoStoreError> {
+    ) -> Result<Vec<Request>, ShareRoomKeyError> {
         let users: Vec<OwnedUserId> =
             users.into_iter().filter_map(|u| UserId::parse(u).ok()).collect();
 
@@ -278,6 +278,9 @@ impl AuthenticationService {
         // the username that was entered.
         client.login(username, password, initial_device_name, device_id).map_err(|e| match e {
             ClientError::Generic { msg } => AuthenticationError::Generic { message: msg },
+            e @ ClientError::UnverifiedDevices { .. } => {
+                AuthenticationError::Generic { message: e.to_string() }
+            }
         })?;
         let whoami = client.whoami()?;
         let session =
@@ -0,0 +1,276 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Bounded catch-up syncs, for the background schedulers of the mobile
+//! platforms, like WorkManager on Android and BGTaskScheduler on iOS.
+
+use std::time::Duration;
+
+use matrix_sdk::config::SyncSettings;
+use ruma::{
+    api::client::{
+        filter::{FilterDefinition, RoomEventFilter, RoomFilter},
+        sync::sync_events::v3::Filter,
+    },
+    assign, UInt,
+};
+use tracing::{debug, info, instrument};
+
+use crate::error::ClientError;
+
+/// The limits of a [`BackgroundSyncTask`].
+#[derive(uniffi::Record)]
+pub struct BackgroundSyncLimits {
+    /// The time after which no new sync request is started, in milliseconds.
+    ///
+    /// A request that is in flight when this time is reached is completed, so
+    /// the slice can take slightly longer.
+    pub max_duration_ms: u64,
+    /// The maximum number of timeline events returned for every room by a
+    /// sync request.
+    ///
+    /// This is the only limit on the size of the responses: the number of
+    /// rooms, their state, the account data and the to-device events of a
+    /// response aren't bounded.
+    pub max_timeline_events_per_room: u32,
+}
+
+/// The report of a [`BackgroundSyncTask`] run.
+#[derive(uniffi::Record)]
+pub struct BackgroundSyncReport {
+    /// Whether the client didn't catch up with the homeserver before the
+    /// maximum duration was reached, or couldn't sync because another
+    /// process of the app was syncing, so another run should be scheduled.
+    pub more_work_remains: bool,
+    /// The number of sync responses that were processed.
+    pub num_responses: u32,
+    /// The number of room updates that were received, counting a room once
+    /// per response.
+    pub num_room_updates: u32,
+}
+
+/// A task performing a bounded slice of sync, to catch up with the homeserver
+/// from a background job of the platform, without running the full sync
+/// service of the app.
+///
+/// The progress is persisted in the stores of the client after every sync
+/// response, so the next run, or the app when it comes back to the
+/// foreground, continues where this one stopped.
+#[derive(uniffi::Object)]
+pub struct BackgroundSyncTask {
+    client: matrix_sdk::Client,
+    max_duration: Duration,
+    max_timeline_events_per_room: u32,
+}
+
+impl BackgroundSyncTask {
+    pub(crate) fn new(client: matrix_sdk::Client, limits: BackgroundSyncLimits) -> Self {
+        Self {
+            client,
+            max_duration: Duration::from_millis(limits.max_duration_ms),
+            max_timeline_events_per_room: limits.max_timeline_events_per_room,
+        }
+    }
+
+    fn sync_settings(&self) -> SyncSettings {
+        let timeline = assign!(RoomEventFilter::default(), {
+            limit: Some(UInt::from(self.max_timeline_events_per_room)),
+        });
+        let room = assign!(RoomFilter::default(), { timeline });
+        let filter = assign!(FilterDefinition::default(), { room });
+
+        // Don't wait for new events, only fetch the ones that are pending.
+        SyncSettings::new().timeout(Duration::ZERO).filter(Filter::FilterDefinition(filter))
+    }
+}
+
+#[uniffi::export(async_runtime = "tokio")]
+impl BackgroundSyncTask {
+    /// Sync until the client caught up with the homeserver, or the maximum
+    /// duration is reached.
+    #[instrument(skip_all)]
+    pub async fn run(&self) -> Result<BackgroundSyncReport, ClientError> {
+        let clock = self.client.clock();
+        let deadline = clock.now() + self.max_duration;
+        let mut report = BackgroundSyncReport {
+            more_work_remains: false,
+            num_responses: 0,
+            num_room_updates: 0,
+        };
+
+        loop {
+            if clock.now() >= deadline {
+                info!("Reached the maximum duration before catching up");
+                report.more_work_remains = true;
+                break;
+            }
+
+            let response = self.client.sync_once(self.sync_settings()).await?;
+
+            if response.is_passive {
+                // The process holding the cross-process sync lock is syncing, we can't
+                // know whether it caught up.
+                info!("Another process is syncing, stopping");
+                report.more_work_remains = true;
+                break;
+            }
+
+            let rooms = &response.rooms;
+            let num_room_updates = rooms.join.len() + rooms.invite.len() + rooms.leave.len();
+            report.num_responses += 1;
+            report.num_room_updates = report
+                .num_room_updates
+                .saturating_add(u32::try_from(num_room_updates).unwrap_or(u32::MAX));
+
+            debug!(num_room_updates, "Processed a background sync response");
+
+            if num_room_updates == 0 && response.to_device.is_empty() {
+                // Nothing was pending anymore, the client caught up.
+                break;
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use matrix_sdk::{
+        clock::TestClock,
+        config::{RequestConfig, StoreConfig},
+        matrix_auth::{MatrixSession, MatrixSessionTokens},
+        Client, MemoryStore, SessionMeta,
+    };
+    use matrix_sdk_test::{
+        async_test, test_json, JoinedRoomBuilder, SyncResponseBuilder, DEFAULT_TEST_ROOM_ID,
+    };
+    use ruma::{api::MatrixVersion, device_id, user_id};
+    use serde_json::json;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, Request, ResponseTemplate,
+    };
+
+    use super::{BackgroundSyncLimits, BackgroundSyncTask};
+
+    async fn test_client(server: &MockServer, clock: TestClock, store: Arc<MemoryStore>) -> Client {
+        let client = Client::builder()
+            .homeserver_url(server.uri())
+            .server_versions([MatrixVersion::V1_0])
+            .store_config(StoreConfig::new().state_store(store))
+            .request_config(RequestConfig::new().disable_retry())
+            .clock(Arc::new(clock))
+            .build()
+            .await
+            .unwrap();
+
+        client
+            .restore_session(MatrixSession {
+                meta: SessionMeta {
+                    user_id: user_id!("@example:localhost").to_owned(),
+                    device_id: device_id!("DEVICEID").to_owned(),
+                },
+                tokens: MatrixSessionTokens {
+                    access_token: "1234".to_owned(),
+                    refresh_token: None,
+                },
+            })
+            .await
+            .unwrap();
+
+        client
+    }
+
+    fn limits(max_duration_ms: u64) -> BackgroundSyncLimits {
+        BackgroundSyncLimits { max_duration_ms, max_timeline_events_per_room: 10 }
+    }
+
+    #[async_test]
+    async fn test_run_stops_at_the_deadline() {
+        let server = MockServer::start().await;
+        let clock = TestClock::new();
+        let client = test_client(&server, clock.clone(), Arc::new(MemoryStore::new())).await;
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        sync_builder.add_joined_room(JoinedRoomBuilder::new(&DEFAULT_TEST_ROOM_ID));
+        let body = sync_builder.build_json_sync_response();
+
+        // Every response takes a second, and there's always something new.
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/r0/sync"))
+            .respond_with(move |_: &Request| {
+                clock.advance(Duration::from_secs(1));
+                ResponseTemplate::new(200).set_body_json(&body)
+            })
+            .mount(&server)
+            .await;
+
+        let report = BackgroundSyncTask::new(client, limits(2_500)).run().await.unwrap();
+
+        assert!(report.more_work_remains);
+        assert_eq!(report.num_responses, 3);
+        assert_eq!(report.num_room_updates, 3);
+    }
+
+    #[async_test]
+    async fn test_run_stops_at_an_empty_response() {
+        let server = MockServer::start().await;
+        let client = test_client(&server, TestClock::new(), Arc::new(MemoryStore::new())).await;
+
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/r0/sync"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "next_batch": "1" })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let report = BackgroundSyncTask::new(client, limits(60_000)).run().await.unwrap();
+
+        assert!(!report.more_work_remains);
+        assert_eq!(report.num_responses, 1);
+        assert_eq!(report.num_room_updates, 0);
+        server.verify().await;
+    }
+
+    #[async_test]
+    async fn test_run_doesnt_catch_up_while_another_process_syncs() {
+        let server = MockServer::start().await;
+        let store = Arc::new(MemoryStore::new());
+
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/r0/sync"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::SYNC))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        // The app syncs, and keeps the cross-process sync lock for a while.
+        let app_client = test_client(&server, TestClock::new(), store.clone()).await;
+        app_client.enable_cross_process_sync_lock("app".to_owned());
+        app_client.sync_once(Default::default()).await.unwrap();
+
+        let client = test_client(&server, TestClock::new(), store).await;
+        client.enable_cross_process_sync_lock("background".to_owned());
+
+        let report = BackgroundSyncTask::new(client, limits(60_000)).run().await.unwrap();
+
+        // The empty response of the passive sync doesn't mean the client caught up.
+        assert!(report.more_work_remains);
+        assert_eq!(report.num_responses, 0);
+        server.verify().await;
+    }
+}
@@ -6,6 +6,7 @@ use std::{
 
 use anyhow::{anyhow, Context as _};
 use matrix_sdk::{
+    features::{ClientFeature as SdkClientFeature, ClientFeatureFlags as SdkClientFeatureFlags},
     media::{MediaFileHandle as SdkMediaFileHandle, MediaFormat, MediaRequest, MediaThumbnailSize},
     oidc::{
         types::{
@@ -45,12 +46,13 @@ use ruma::{
 };
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast::error::RecvError, Mutex as AsyncMutex};
 use tracing::{debug, error};
 use url::Url;
 
 use super::{room::Room, session_verification::SessionVerificationController, RUNTIME};
 use crate::{
+    background_sync::{BackgroundSyncLimits, BackgroundSyncTask},
     client,
     encryption::Encryption,
     notification::NotificationClientBuilder,
@@ -672,6 +674,15 @@ impl Client {
         })
     }
 
+    /// Search for users in the user directory and in the members of the
+    /// joined rooms, the users sharing the most rooms with us come first.
+    ///
+    /// The results are paginated with [`UserSearch::next_page`], the search
+    /// must be kept to get the following pages.
+    pub fn user_search(&self, search_term: String) -> Arc<UserSearch> {
+        Arc::new(UserSearch { inner: AsyncMutex::new(self.inner.user_search(&search_term)) })
+    }
+
     pub fn get_profile(&self, user_id: String) -> Result<UserProfile, ClientError> {
         RUNTIME.block_on(async move {
             let owned_user_id = UserId::parse(user_id.clone())?;
@@ -698,6 +709,12 @@ impl Client {
         SyncServiceBuilder::new((*self.inner).clone())
     }
 
+    /// Get a task performing bounded catch-up syncs, to be run by the
+    /// background scheduler of the platform.
+    pub fn background_sync_task(&self, limits: BackgroundSyncLimits) -> Arc<BackgroundSyncTask> {
+        Arc::new(BackgroundSyncTask::new((*self.inner).clone(), limits))
+    }
+
     pub fn get_notification_settings(&self) -> Arc<NotificationSettings> {
         RUNTIME.block_on(async move {
             Arc::new(NotificationSettings::new(
@@ -710,6 +727,84 @@ impl Client {
     pub fn encryption(&self) -> Arc<Encryption> {
         Arc::new(self.inner.encryption().into())
     }
+
+    /// Get whether each of the features that can be toggled at runtime is
+    /// enabled.
+    pub fn feature_flags(&self) -> ClientFeatureFlags {
+        self.inner.features().get().into()
+    }
+
+    /// Enable or disable a feature of the client, like sending read receipts
+    /// or typing notifications.
+    pub fn set_feature_enabled(
+        &self,
+        feature: ClientFeature,
+        enabled: bool,
+    ) -> Result<(), ClientError> {
+        RUNTIME.block_on(async move {
+            self.inner.features().set_enabled(feature.into(), enabled).await?;
+            Ok(())
+        })
+    }
+
+    /// Listen to the changes of the features of the client, including the
+    /// ones that come from the account data during a sync.
+    pub fn feature_flags_listener(
+        &self,
+        listener: Box<dyn ClientFeatureFlagsListener>,
+    ) -> Arc<TaskHandle> {
+        let mut subscriber = self.inner.features().subscribe();
+
+        Arc::new(TaskHandle::new(RUNTIME.spawn(async move {
+            while let Some(flags) = subscriber.next().await {
+                listener.on_update(flags.into());
+            }
+        })))
+    }
+}
+
+#[uniffi::export(callback_interface)]
+pub trait ClientFeatureFlagsListener: Sync + Send {
+    fn on_update(&self, flags: ClientFeatureFlags);
+}
+
+/// A feature of the client that can be toggled at runtime.
+#[derive(Clone, Copy, uniffi::Enum)]
+pub enum ClientFeature {
+    ReadReceipts,
+    TypingNotifications,
+    Presence,
+    UrlPreviews,
+}
+
+impl From<ClientFeature> for SdkClientFeature {
+    fn from(value: ClientFeature) -> Self {
+        match value {
+            ClientFeature::ReadReceipts => Self::ReadReceipts,
+            ClientFeature::TypingNotifications => Self::TypingNotifications,
+            ClientFeature::Presence => Self::Presence,
+            ClientFeature::UrlPreviews => Self::UrlPreviews,
+        }
+    }
+}
+
+#[derive(Clone, Copy, uniffi::Record)]
+pub struct ClientFeatureFlags {
+    pub read_receipts: bool,
+    pub typing_notifications: bool,
+    pub presence: bool,
+    pub url_previews: bool,
+}
+
+impl From<SdkClientFeatureFlags> for ClientFeatureFlags {
+    fn from(value: SdkClientFeatureFlags) -> Self {
+        Self {
+            read_receipts: value.read_receipts,
+            typing_notifications: value.typing_notifications,
+            presence: value.presence,
+            url_previews: value.url_previews,
+        }
+    }
 }
 
 #[derive(uniffi::Enum)]
@@ -763,6 +858,38 @@ impl From<&search_users::v3::User> for UserProfile {
     }
 }
 
+/// A paginated search for users, created with [`Client::user_search`].
+#[derive(uniffi::Object)]
+pub struct UserSearch {
+    inner: AsyncMutex<matrix_sdk::user_search::UserSearch>,
+}
+
+#[uniffi::export(async_runtime = "tokio")]
+impl UserSearch {
+    /// Get the next page of results, with at most `page_size` users.
+    ///
+    /// An empty page is returned once all the results were returned.
+    pub async fn next_page(&self, page_size: u32) -> Vec<UserProfile> {
+        let page = self.inner.lock().await.next_page(page_size as usize).await;
+        page.into_iter().map(UserProfile::from).collect()
+    }
+
+    /// Whether all the results were returned already.
+    pub async fn is_at_last_page(&self) -> bool {
+        self.inner.lock().await.is_at_last_page()
+    }
+}
+
+impl From<matrix_sdk::user_search::UserSearchResult> for UserProfile {
+    fn from(value: matrix_sdk::user_search::UserSearchResult) -> Self {
+        UserProfile {
+            user_id: value.user_id.to_string(),
+            display_name: value.display_name,
+            avatar_url: value.avatar_url.map(|url| url.to_string()),
+        }
+    }
+}
+
 impl Client {
     fn process_session_change(&self, session_change: SessionChange) {
         if let Some(delegate) = self.delegate.read().unwrap().clone() {
@@ -34,6 +34,7 @@ pub struct ClientBuilder {
     proxy: Option<String>,
     disable_ssl_verification: bool,
     disable_automatic_token_refresh: bool,
+    encryption_settings: EncryptionSettings,
     inner: MatrixClientBuilder,
     cross_process_refresh_lock_id: Option<String>,
     session_delegate: Option<Arc<dyn ClientSessionDelegate>>,
@@ -130,6 +131,18 @@ impl ClientBuilder {
         Arc::new(builder)
     }
 
+    /// Whether sending an event in an encrypted room should fail with a
+    /// `ClientError::UnverifiedDevices` error if some devices of the room
+    /// members aren't verified, instead of sharing the room key with them.
+    pub fn error_on_unverified_devices(
+        self: Arc<Self>,
+        error_on_unverified_devices: bool,
+    ) -> Arc<Self> {
+        let mut builder = unwrap_or_clone_arc(self);
+        builder.encryption_settings.error_on_unverified_devices = error_on_unverified_devices;
+        Arc::new(builder)
+    }
+
     pub fn build(self: Arc<Self>) -> Result<Arc<Client>, ClientError> {
         Ok(self.build_inner()?)
     }
@@ -168,7 +181,7 @@ impl ClientBuilder {
 
     pub(crate) fn build_inner(self: Arc<Self>) -> anyhow::Result<Arc<Client>> {
         let builder = unwrap_or_clone_arc(self);
-        let mut inner_builder = builder.inner;
+        let mut inner_builder = builder.inner.with_encryption_settings(builder.encryption_settings);
 
         if let (Some(base_path), Some(username)) = (builder.base_path, &builder.username) {
             // Determine store path
@@ -254,8 +267,10 @@ impl Default for ClientBuilder {
             auto_enable_cross_signing: true,
             auto_enable_backups: true,
             backup_download_strategy: BackupDownloadStrategy::AfterDecryptionFailure,
+            backup_upload_strategy: Default::default(),
+            room_key_rotation_limits: Default::default(),
+            error_on_unverified_devices: false,
         };
-        let inner = MatrixClient::builder().with_encryption_settings(encryption_settings);
 
         Self {
             base_path: None,
@@ -269,7 +284,8 @@ impl Default for ClientBuilder {
             proxy: None,
             disable_ssl_verification: false,
             disable_automatic_token_refresh: false,
-            inner,
+            encryption_settings,
+            inner: MatrixClient::builder(),
             cross_process_refresh_lock_id: None,
             session_delegate: None,
         }
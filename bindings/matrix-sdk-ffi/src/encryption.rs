@@ -24,11 +24,21 @@ pub trait BackupStateListener: Sync + Send {
     fn on_update(&self, status: BackupState);
 }
 
+#[uniffi::export(callback_interface)]
+pub trait BackupStatusListener: Sync + Send {
+    fn on_update(&self, status: BackupStatus);
+}
+
 #[uniffi::export(callback_interface)]
 pub trait BackupSteadyStateListener: Sync + Send {
     fn on_update(&self, status: BackupUploadState);
 }
 
+#[uniffi::export(callback_interface)]
+pub trait BackupUploadProgressListener: Sync + Send {
+    fn on_update(&self, progress: BackupUploadState);
+}
+
 #[uniffi::export(callback_interface)]
 pub trait RecoveryStateListener: Sync + Send {
     fn on_update(&self, status: RecoveryState);
@@ -79,10 +89,17 @@ impl From<matrix_sdk::encryption::recovery::RecoveryError> for RecoveryError {
             recovery::RecoveryError::SecretStorage(e) => {
                 Self::SecretStorage { error_message: e.to_string() }
             }
+            recovery::RecoveryError::Backup(e) => Self::Client { source: ClientError::from(e) },
         }
     }
 }
 
+impl From<backups::BackupError> for RecoveryError {
+    fn from(value: backups::BackupError) -> Self {
+        Self::Client { source: ClientError::from(value) }
+    }
+}
+
 pub type Result<A, E = RecoveryError> = std::result::Result<A, E>;
 
 impl From<matrix_sdk::encryption::backups::futures::SteadyStateError> for SteadyStateError {
@@ -104,6 +121,7 @@ pub enum BackupState {
     Enabled,
     Downloading,
     Disabling,
+    VersionChanged,
 }
 
 impl From<backups::BackupState> for BackupState {
@@ -116,6 +134,44 @@ impl From<backups::BackupState> for BackupState {
             backups::BackupState::Enabled => Self::Enabled,
             backups::BackupState::Downloading => Self::Downloading,
             backups::BackupState::Disabling => Self::Disabling,
+            backups::BackupState::VersionChanged => Self::VersionChanged,
+        }
+    }
+}
+
+/// A summary of the state of the room key backup, meant to be displayed to
+/// the user.
+#[derive(uniffi::Enum)]
+pub enum BackupStatus {
+    Unknown,
+    Creating,
+    Enabling,
+    Downloading { done: u64, total: u64 },
+    Enabled,
+    Uploading { done: u64, total: u64 },
+    Disabled,
+    Switching,
+    Error,
+}
+
+impl From<backups::BackupStatus> for BackupStatus {
+    fn from(value: backups::BackupStatus) -> Self {
+        match value {
+            backups::BackupStatus::Unknown => Self::Unknown,
+            backups::BackupStatus::Creating => Self::Creating,
+            backups::BackupStatus::Enabling => Self::Enabling,
+            backups::BackupStatus::Downloading { done, total } => Self::Downloading {
+                done: done.try_into().unwrap_or(u64::MAX),
+                total: total.try_into().unwrap_or(u64::MAX),
+            },
+            backups::BackupStatus::Enabled => Self::Enabled,
+            backups::BackupStatus::Uploading { done, total } => Self::Uploading {
+                done: done.try_into().unwrap_or(u64::MAX),
+                total: total.try_into().unwrap_or(u64::MAX),
+            },
+            backups::BackupStatus::Disabled => Self::Disabled,
+            backups::BackupStatus::Switching => Self::Switching,
+            backups::BackupStatus::Error => Self::Error,
         }
     }
 }
@@ -134,6 +190,24 @@ impl From<backups::UploadState> for BackupUploadState {
     }
 }
 
+/// How many room keys we know about, and how many of them are backed up.
+#[derive(uniffi::Record)]
+pub struct RoomKeyCounts {
+    /// The total number of room keys.
+    pub total: u64,
+    /// The number of room keys that are backed up.
+    pub backed_up: u64,
+}
+
+impl From<matrix_sdk::crypto::store::RoomKeyCounts> for RoomKeyCounts {
+    fn from(value: matrix_sdk::crypto::store::RoomKeyCounts) -> Self {
+        Self {
+            total: value.total.try_into().unwrap_or(u64::MAX),
+            backed_up: value.backed_up.try_into().unwrap_or(u64::MAX),
+        }
+    }
+}
+
 #[derive(uniffi::Enum)]
 pub enum RecoveryState {
     Unknown,
@@ -176,7 +250,7 @@ impl From<recovery::EnableProgress> for EnableRecoveryProgress {
             recovery::EnableProgress::CreatingRecoveryKey => Self::CreatingRecoveryKey,
             recovery::EnableProgress::BackingUp(counts) => Self::BackingUp {
                 backed_up_count: counts.backed_up.try_into().unwrap_or(u32::MAX),
-                total_count: counts.backed_up.try_into().unwrap_or(u32::MAX),
+                total_count: counts.total.try_into().unwrap_or(u32::MAX),
             },
             recovery::EnableProgress::RoomKeyUploadError => Self::RoomKeyUploadError,
             recovery::EnableProgress::Done { recovery_key } => {
@@ -205,6 +279,59 @@ impl Encryption {
         self.inner.backups().state().into()
     }
 
+    /// Listen to the status of the backup, including the progress of the
+    /// download and upload of room keys, for example to show it in the
+    /// settings of the app.
+    ///
+    /// The listener is called with the current status first.
+    pub fn backup_status_listener(
+        &self,
+        listener: Box<dyn BackupStatusListener>,
+    ) -> Arc<TaskHandle> {
+        let mut stream = Box::pin(self.inner.backups().status_stream());
+
+        let stream_task = TaskHandle::new(RUNTIME.spawn(async move {
+            while let Some(status) = stream.next().await {
+                listener.on_update(status.into());
+            }
+        }));
+
+        stream_task.into()
+    }
+
+    /// Get the current status of the backup, the same one the listener of
+    /// [`Encryption::backup_status_listener`] is called with.
+    pub fn backup_status(&self) -> BackupStatus {
+        self.inner.backups().status().into()
+    }
+
+    /// Listen to the progress of the upload of the room keys to the backup,
+    /// for example to show how many keys are backed up while the initial
+    /// backup runs.
+    ///
+    /// The listener is called with the current progress first.
+    pub fn backup_upload_progress_listener(
+        &self,
+        listener: Box<dyn BackupUploadProgressListener>,
+    ) -> Arc<TaskHandle> {
+        let mut stream = self.inner.backups().upload_progress_stream();
+
+        let stream_task = TaskHandle::new(RUNTIME.spawn(async move {
+            while let Some(progress) = stream.next().await {
+                let Ok(progress) = progress else { continue };
+                listener.on_update(progress.into());
+            }
+        }));
+
+        stream_task.into()
+    }
+
+    /// Get the number of room keys that we know about, and how many of them
+    /// are backed up.
+    pub async fn room_key_counts(&self) -> Result<RoomKeyCounts, ClientError> {
+        Ok(self.inner.backups().room_key_counts().await?.into())
+    }
+
     /// Does a backup exist on the server?
     ///
     /// Because the homeserver doesn't notify us about changes to the backup
@@ -241,6 +368,12 @@ impl Encryption {
         Ok(self.inner.recovery().enable_backup().await?)
     }
 
+    /// Delete the backup this client uses from the homeserver, and stop
+    /// backing up room keys.
+    pub async fn disable_and_delete_backup(&self) -> Result<()> {
+        Ok(self.inner.backups().disable_and_delete().await?)
+    }
+
     pub async fn is_last_device(&self) -> Result<bool> {
         Ok(self.inner.recovery().are_we_the_last_man_standing().await?)
     }
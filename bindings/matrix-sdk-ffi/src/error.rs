@@ -1,8 +1,10 @@
-use std::fmt::Display;
+use std::{collections::HashMap, fmt::Display};
 
 use matrix_sdk::{
-    self, encryption::CryptoStoreError, oidc::OidcError, HttpError, IdParseError,
-    NotificationSettingsError as SdkNotificationSettingsError, StoreError,
+    self,
+    encryption::{backups::BackupError, CryptoStoreError, OlmError},
+    oidc::OidcError,
+    HttpError, IdParseError, NotificationSettingsError as SdkNotificationSettingsError, StoreError,
 };
 use matrix_sdk_ui::{encryption_sync_service, notification_client, sync_service, timeline};
 use uniffi::UnexpectedUniFFICallbackError;
@@ -11,6 +13,12 @@ use uniffi::UnexpectedUniFFICallbackError;
 pub enum ClientError {
     #[error("client error: {msg}")]
     Generic { msg: String },
+    /// Some devices of the room members aren't verified, and the client was
+    /// built to fail instead of sharing the room key with them.
+    ///
+    /// The device IDs are grouped by user ID.
+    #[error("the room key wasn't shared because some devices aren't verified")]
+    UnverifiedDevices { devices: HashMap<String, Vec<String>> },
 }
 
 impl ClientError {
@@ -19,6 +27,25 @@ impl ClientError {
     }
 }
 
+/// The unverified devices that prevented sharing a room key, grouped by user
+/// ID, if this is the cause of the given error.
+pub(crate) fn unverified_devices(
+    error: &matrix_sdk::Error,
+) -> Option<HashMap<String, Vec<String>>> {
+    let matrix_sdk::Error::OlmError(OlmError::UnverifiedDevices(devices)) = error else {
+        return None;
+    };
+
+    Some(
+        devices
+            .iter()
+            .map(|(user_id, device_ids)| {
+                (user_id.to_string(), device_ids.iter().map(ToString::to_string).collect())
+            })
+            .collect(),
+    )
+}
+
 impl From<anyhow::Error> for ClientError {
     fn from(e: anyhow::Error) -> ClientError {
         ClientError::Generic { msg: format!("{e:#}") }
@@ -33,6 +60,15 @@ impl From<UnexpectedUniFFICallbackError> for ClientError {
 
 impl From<matrix_sdk::Error> for ClientError {
     fn from(e: matrix_sdk::Error) -> Self {
+        match unverified_devices(&e) {
+            Some(devices) => Self::UnverifiedDevices { devices },
+            None => Self::new(e),
+        }
+    }
+}
+
+impl From<BackupError> for ClientError {
+    fn from(e: BackupError) -> Self {
         Self::new(e)
     }
 }
@@ -21,6 +21,7 @@ macro_rules! unwrap_or_clone_arc_into_variant {
 }
 
 mod authentication_service;
+mod background_sync;
 mod chunk_iterator;
 mod client;
 mod client_builder;
@@ -36,6 +37,7 @@ mod room_info;
 mod room_list;
 mod room_member;
 mod ruma;
+mod send_queue;
 mod session_verification;
 mod sync_service;
 mod task_handle;
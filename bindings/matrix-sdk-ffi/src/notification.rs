@@ -1,6 +1,7 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use matrix_sdk_ui::notification_client::{
+    BudgetedNotification as MatrixBudgetedNotification,
     NotificationClient as MatrixNotificationClient,
     NotificationClientBuilder as MatrixNotificationClientBuilder,
     NotificationItem as MatrixNotificationItem, NotificationProcessSetup,
@@ -78,6 +79,33 @@ impl NotificationItem {
     }
 }
 
+#[derive(uniffi::Enum)]
+pub enum BudgetedNotification {
+    /// The notification was resolved within the time budget.
+    Complete { item: NotificationItem },
+    /// The notification was filtered out by the user's push rules.
+    FilteredOut,
+    /// The time budget was exceeded, only the room name is available, if the
+    /// room is known locally.
+    Partial { room_id: String, event_id: String, room_display_name: Option<String> },
+}
+
+impl From<MatrixBudgetedNotification> for BudgetedNotification {
+    fn from(value: MatrixBudgetedNotification) -> Self {
+        match value {
+            MatrixBudgetedNotification::Complete(item) => {
+                Self::Complete { item: NotificationItem::from_inner(item) }
+            }
+            MatrixBudgetedNotification::FilteredOut => Self::FilteredOut,
+            MatrixBudgetedNotification::Partial(item) => Self::Partial {
+                room_id: item.room_id.to_string(),
+                event_id: item.event_id.to_string(),
+                room_display_name: item.room_display_name,
+            },
+        }
+    }
+}
+
 #[derive(Clone, uniffi::Object)]
 pub struct NotificationClientBuilder {
     client: Arc<Client>,
@@ -148,4 +176,24 @@ impl NotificationClient {
             }
         })
     }
+
+    /// See also documentation of
+    /// `MatrixNotificationClient::get_notification_within`.
+    pub fn get_notification_within(
+        &self,
+        room_id: String,
+        event_id: String,
+        budget_ms: u64,
+    ) -> Result<BudgetedNotification, ClientError> {
+        let room_id = RoomId::parse(room_id)?;
+        let event_id = EventId::parse(event_id)?;
+        RUNTIME.block_on(async move {
+            let notification = self
+                .inner
+                .get_notification_within(&room_id, &event_id, Duration::from_millis(budget_ms))
+                .await
+                .map_err(ClientError::from)?;
+            Ok(notification.into())
+        })
+    }
 }
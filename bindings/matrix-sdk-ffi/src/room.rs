@@ -20,6 +20,7 @@ use crate::{
     room_info::RoomInfo,
     room_member::{MessageLikeEventType, RoomMember, StateEventType},
     ruma::ImageInfo,
+    send_queue::SendQueue,
     timeline::{EventTimelineItem, Timeline},
     utils::u64_to_uint,
     TaskHandle,
@@ -150,6 +151,13 @@ impl Room {
         }
     }
 
+    /// The queue of the events being sent to this room.
+    ///
+    /// It's backed by the local echoes of the room's [`Timeline`].
+    pub async fn send_queue(&self) -> Arc<SendQueue> {
+        SendQueue::new(self.inner.clone(), self.timeline().await)
+    }
+
     pub async fn poll_history(&self) -> Arc<Timeline> {
         Timeline::new(self.inner.poll_history().await)
     }
@@ -494,6 +502,12 @@ impl Room {
     pub async fn typing_notice(&self, is_typing: bool) -> Result<(), ClientError> {
         Ok(self.inner.typing_notice(is_typing).await?)
     }
+
+    /// Mark the room as unread, or clear that mark, for all of the user's
+    /// clients.
+    pub async fn set_unread(&self, unread: bool) -> Result<(), ClientError> {
+        Ok(self.inner.set_unread(unread).await?)
+    }
 }
 
 #[uniffi::export(callback_interface)]
@@ -40,6 +40,8 @@ pub struct RoomInfo {
     /// Events causing mentions/highlights for the user, according to their
     /// notification settings.
     num_unread_mentions: u64,
+    /// Whether the user manually marked the room as unread.
+    is_marked_unread: bool,
 }
 
 impl RoomInfo {
@@ -48,7 +50,7 @@ impl RoomInfo {
         avatar_url: Option<OwnedMxcUri>,
         latest_event: Option<Arc<EventTimelineItem>>,
     ) -> matrix_sdk::Result<Self> {
-        let unread_notification_counts = room.unread_notification_counts();
+        let unread_notification_counts = room.total_unread_notification_counts();
 
         Ok(Self {
             id: room.room_id().to_string(),
@@ -87,6 +89,7 @@ impl RoomInfo {
             num_unread_messages: room.num_unread_messages(),
             num_unread_notifications: room.num_unread_notifications(),
             num_unread_mentions: room.num_unread_mentions(),
+            is_marked_unread: room.is_marked_unread(),
         })
     }
 }
@@ -8,18 +8,19 @@ use matrix_sdk::{
             v4::RoomSubscription as RumaRoomSubscription,
             UnreadNotificationsCount as RumaUnreadNotificationsCount,
         },
-        assign, RoomId,
+        assign, OwnedRoomId, RoomId,
     },
     RoomListEntry as MatrixRoomListEntry,
 };
 use matrix_sdk_ui::room_list_service::filters::{
     new_filter_all, new_filter_all_non_left, new_filter_fuzzy_match_room_name, new_filter_none,
-    new_filter_normalized_match_room_name,
+    new_filter_normalized_match_room_name, new_filter_unread,
 };
 use tokio::sync::RwLock;
 
 use crate::{
     error::ClientError,
+    helpers::unwrap_or_clone_arc,
     room::Room,
     room_info::RoomInfo,
     timeline::{EventTimelineItem, Timeline},
@@ -125,6 +126,16 @@ impl RoomListService {
         self.inner.apply_input(input.into()).await.map(|_| ()).map_err(Into::into)
     }
 
+    /// Split all the rooms into sections, like invites, favourites or direct
+    /// messages, and the custom sections of the configuration.
+    async fn sections(
+        &self,
+        config: Arc<RoomSectionsConfig>,
+    ) -> Result<Arc<RoomSections>, RoomListError> {
+        let sections = self.inner.sections(config.to_sdk()).await.map_err(RoomListError::from)?;
+        Ok(Arc::new(RoomSections { inner: sections }))
+    }
+
     fn sync_indicator(
         &self,
         delay_before_showing_in_ms: u32,
@@ -357,6 +368,216 @@ pub trait RoomListEntriesListener: Send + Sync + Debug {
     fn on_update(&self, room_entries_update: Vec<RoomListEntriesUpdate>);
 }
 
+#[derive(Clone, uniffi::Enum)]
+pub enum RoomSection {
+    Invites,
+    Favourites,
+    People,
+    Rooms,
+    LowPriority,
+    Custom { name: String },
+}
+
+impl From<RoomSection> for matrix_sdk_ui::room_list_service::RoomSection {
+    fn from(value: RoomSection) -> Self {
+        match value {
+            RoomSection::Invites => Self::Invites,
+            RoomSection::Favourites => Self::Favourites,
+            RoomSection::People => Self::People,
+            RoomSection::Rooms => Self::Rooms,
+            RoomSection::LowPriority => Self::LowPriority,
+            RoomSection::Custom { name } => Self::Custom(name),
+        }
+    }
+}
+
+#[derive(Clone, Copy, uniffi::Enum)]
+pub enum SectionSortOrder {
+    Activity,
+    Alphabetical,
+    Manual,
+}
+
+impl From<SectionSortOrder> for matrix_sdk_ui::room_list_service::SectionSortOrder {
+    fn from(value: SectionSortOrder) -> Self {
+        match value {
+            SectionSortOrder::Activity => Self::Activity,
+            SectionSortOrder::Alphabetical => Self::Alphabetical,
+            SectionSortOrder::Manual => Self::Manual,
+        }
+    }
+}
+
+/// Decides which rooms belong to a custom section.
+#[uniffi::export(callback_interface)]
+pub trait RoomSectionPredicate: Send + Sync + Debug {
+    fn matches(&self, room_id: String) -> bool;
+}
+
+#[derive(Clone)]
+struct CustomSection {
+    name: String,
+    predicate: Arc<dyn RoomSectionPredicate>,
+    sort_order: SectionSortOrder,
+}
+
+/// The configuration of the [`RoomSections`], see
+/// [`RoomListService::sections`].
+#[derive(Clone, Default, uniffi::Object)]
+pub struct RoomSectionsConfig {
+    custom_sections: Vec<CustomSection>,
+    sort_orders: Vec<(RoomSection, SectionSortOrder)>,
+}
+
+#[uniffi::export]
+impl RoomSectionsConfig {
+    #[uniffi::constructor]
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Add a custom section, containing the rooms matching the predicate.
+    ///
+    /// Custom sections take precedence over all the default sections except
+    /// the invites, and over the custom sections added after them.
+    pub fn custom_section(
+        self: Arc<Self>,
+        name: String,
+        predicate: Box<dyn RoomSectionPredicate>,
+        sort_order: SectionSortOrder,
+    ) -> Arc<Self> {
+        let mut config = unwrap_or_clone_arc(self);
+        config.custom_sections.push(CustomSection {
+            name,
+            predicate: predicate.into(),
+            sort_order,
+        });
+        Arc::new(config)
+    }
+
+    /// Set how the rooms of a default section are sorted.
+    pub fn sort_order(
+        self: Arc<Self>,
+        section: RoomSection,
+        sort_order: SectionSortOrder,
+    ) -> Arc<Self> {
+        let mut config = unwrap_or_clone_arc(self);
+        config.sort_orders.push((section, sort_order));
+        Arc::new(config)
+    }
+}
+
+impl RoomSectionsConfig {
+    fn to_sdk(&self) -> matrix_sdk_ui::room_list_service::RoomSectionsConfig {
+        let mut config = matrix_sdk_ui::room_list_service::RoomSectionsConfig::new();
+
+        for custom in &self.custom_sections {
+            let predicate = custom.predicate.clone();
+            config = config.custom_section(
+                matrix_sdk_ui::room_list_service::CustomSection::new(
+                    custom.name.clone(),
+                    move |room| predicate.matches(room.room_id().to_string()),
+                )
+                .sort_order(custom.sort_order.into()),
+            );
+        }
+
+        for (section, sort_order) in &self.sort_orders {
+            config = config.sort_order(section.clone().into(), (*sort_order).into());
+        }
+
+        config
+    }
+}
+
+#[derive(uniffi::Object)]
+pub struct RoomSections {
+    inner: matrix_sdk_ui::room_list_service::RoomSections,
+}
+
+#[uniffi::export(async_runtime = "tokio")]
+impl RoomSections {
+    /// Get the IDs of the rooms of a section, and listen to their updates.
+    fn entries(
+        &self,
+        section: RoomSection,
+        listener: Box<dyn RoomSectionEntriesListener>,
+    ) -> RoomSectionEntriesResult {
+        let (entries, entries_stream) = self.inner.entries(section.into());
+
+        RoomSectionEntriesResult {
+            entries: entries.iter().map(ToString::to_string).collect(),
+            entries_stream: Arc::new(TaskHandle::new(RUNTIME.spawn(async move {
+                pin_mut!(entries_stream);
+
+                while let Some(diff) = entries_stream.next().await {
+                    listener.on_update(diff.into_iter().map(Into::into).collect());
+                }
+            }))),
+        }
+    }
+
+    /// Assign the rooms to sections again, when something the predicate of a
+    /// custom section depends on changed.
+    async fn refresh(&self) {
+        self.inner.refresh().await;
+    }
+}
+
+#[derive(uniffi::Record)]
+pub struct RoomSectionEntriesResult {
+    pub entries: Vec<String>,
+    pub entries_stream: Arc<TaskHandle>,
+}
+
+#[derive(uniffi::Enum)]
+pub enum RoomSectionEntriesUpdate {
+    Append { values: Vec<String> },
+    Clear,
+    PushFront { value: String },
+    PushBack { value: String },
+    PopFront,
+    PopBack,
+    Insert { index: u32, value: String },
+    Set { index: u32, value: String },
+    Remove { index: u32 },
+    Truncate { length: u32 },
+    Reset { values: Vec<String> },
+}
+
+impl From<VectorDiff<OwnedRoomId>> for RoomSectionEntriesUpdate {
+    fn from(other: VectorDiff<OwnedRoomId>) -> Self {
+        match other {
+            VectorDiff::Append { values } => {
+                Self::Append { values: values.iter().map(ToString::to_string).collect() }
+            }
+            VectorDiff::Clear => Self::Clear,
+            VectorDiff::PushFront { value } => Self::PushFront { value: value.to_string() },
+            VectorDiff::PushBack { value } => Self::PushBack { value: value.to_string() },
+            VectorDiff::PopFront => Self::PopFront,
+            VectorDiff::PopBack => Self::PopBack,
+            VectorDiff::Insert { index, value } => {
+                Self::Insert { index: u32::try_from(index).unwrap(), value: value.to_string() }
+            }
+            VectorDiff::Set { index, value } => {
+                Self::Set { index: u32::try_from(index).unwrap(), value: value.to_string() }
+            }
+            VectorDiff::Remove { index } => Self::Remove { index: u32::try_from(index).unwrap() },
+            VectorDiff::Truncate { length } => {
+                Self::Truncate { length: u32::try_from(length).unwrap() }
+            }
+            VectorDiff::Reset { values } => {
+                Self::Reset { values: values.iter().map(ToString::to_string).collect() }
+            }
+        }
+    }
+}
+
+#[uniffi::export(callback_interface)]
+pub trait RoomSectionEntriesListener: Send + Sync + Debug {
+    fn on_update(&self, room_section_entries_update: Vec<RoomSectionEntriesUpdate>);
+}
+
 #[derive(uniffi::Object)]
 pub struct RoomListDynamicEntriesController {
     inner: matrix_sdk_ui::room_list_service::RoomListDynamicEntriesController,
@@ -381,6 +602,7 @@ impl RoomListDynamicEntriesController {
             Kind::All => self.inner.set_filter(new_filter_all()),
             Kind::AllNonLeft => self.inner.set_filter(new_filter_all_non_left(&self.client)),
             Kind::None => self.inner.set_filter(new_filter_none()),
+            Kind::Unread => self.inner.set_filter(new_filter_unread(&self.client)),
             Kind::NormalizedMatchRoomName { pattern } => {
                 self.inner.set_filter(new_filter_normalized_match_room_name(&self.client, &pattern))
             }
@@ -404,6 +626,7 @@ pub enum RoomListEntriesDynamicFilterKind {
     All,
     AllNonLeft,
     None,
+    Unread,
     NormalizedMatchRoomName { pattern: String },
     FuzzyMatchRoomName { pattern: String },
 }
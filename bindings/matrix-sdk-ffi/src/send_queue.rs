@@ -0,0 +1,299 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The queue of the events being sent to a room.
+//!
+//! The queue is backed by the local echoes of the room's timeline: every
+//! message that is enqueued shows up as a local echo, whose send state is
+//! reported to the [`SendQueueListener`]s.
+//!
+//! When an item fails because the homeserver couldn't be reached, sending is
+//! paused for the room, including its other timelines, until
+//! [`SendQueue::resume_sending`] is called.
+
+use std::{collections::HashMap, sync::Arc};
+
+use futures_util::{pin_mut, StreamExt};
+use matrix_sdk::{room::RoomSendQueue, HttpError};
+use ruma::{
+    api::client::error::ErrorKind, events::room::message::RoomMessageEventContentWithoutRelation,
+    OwnedTransactionId, TransactionId,
+};
+
+use crate::{
+    client::ProgressWatcher,
+    error::unverified_devices,
+    ruma::FileInfo,
+    task_handle::TaskHandle,
+    timeline::{SendAttachmentJoinHandle, Timeline},
+    RUNTIME,
+};
+
+#[uniffi::export(callback_interface)]
+pub trait SendingPausedListener: Sync + Send {
+    fn on_update(&self, paused: bool);
+}
+
+#[uniffi::export(callback_interface)]
+pub trait SendQueueListener: Sync + Send {
+    fn on_update(&self, update: SendQueueUpdate);
+}
+
+/// Why sending an item of the queue failed.
+#[derive(Clone, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum SendErrorKind {
+    /// The homeserver couldn't be reached.
+    Network,
+    /// The homeserver refused the event.
+    Forbidden,
+    /// Some devices of the room members aren't verified, and the client was
+    /// built to fail instead of sharing the room key with them.
+    ///
+    /// The device IDs are grouped by user ID.
+    UnverifiedDevices { devices: HashMap<String, Vec<String>> },
+    /// Any other error.
+    Other,
+}
+
+impl From<&matrix_sdk::Error> for SendErrorKind {
+    fn from(error: &matrix_sdk::Error) -> Self {
+        if let Some(devices) = unverified_devices(error) {
+            return Self::UnverifiedDevices { devices };
+        }
+
+        match error {
+            matrix_sdk::Error::Http(HttpError::Reqwest(_)) => Self::Network,
+            _ if matches!(error.client_api_error_kind(), Some(ErrorKind::Forbidden)) => {
+                Self::Forbidden
+            }
+            _ => Self::Other,
+        }
+    }
+}
+
+/// The state of an item of a [`SendQueue`].
+#[derive(Clone, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum SendQueueItemState {
+    /// The item is waiting for the items before it to be sent.
+    Queued,
+    /// The item is being sent.
+    Sending,
+    /// The item has been sent successfully.
+    Sent { event_id: String },
+    /// Sending the item failed, it can be retried with [`SendQueue::retry`].
+    Failed { kind: SendErrorKind, error: String },
+    /// The item wasn't sent because an item before it failed, it can be
+    /// retried with [`SendQueue::retry`].
+    Cancelled,
+    /// The item has been removed from the queue with [`SendQueue::abort`].
+    Aborted,
+}
+
+/// A change of the state of an item of a [`SendQueue`].
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct SendQueueUpdate {
+    pub transaction_id: String,
+    pub state: SendQueueItemState,
+}
+
+/// The queue of the events being sent to a room.
+#[derive(uniffi::Object)]
+pub struct SendQueue {
+    timeline: Arc<Timeline>,
+    /// The state of the sending of events to the room, shared with its other
+    /// timelines.
+    room_queue: RoomSendQueue,
+}
+
+impl SendQueue {
+    pub(crate) fn new(room: matrix_sdk::Room, timeline: Arc<Timeline>) -> Arc<Self> {
+        Arc::new(Self { timeline, room_queue: room.send_queue() })
+    }
+
+    /// Collect the states of the items in the queue, in order.
+    async fn states(&self) -> Vec<(OwnedTransactionId, SendQueueItemState)> {
+        // Nothing is being sent while sending is paused.
+        let mut is_first_unsent = !self.timeline.inner.is_sending_paused();
+
+        self.timeline
+            .inner
+            .items()
+            .await
+            .iter()
+            .filter_map(|item| item.as_event())
+            .filter_map(|event| Some((event.transaction_id()?.to_owned(), event.send_state()?)))
+            .map(|(txn_id, send_state)| {
+                use matrix_sdk_ui::timeline::EventSendState;
+
+                let state = match send_state {
+                    EventSendState::NotSentYet if is_first_unsent => {
+                        is_first_unsent = false;
+                        SendQueueItemState::Sending
+                    }
+                    EventSendState::NotSentYet => SendQueueItemState::Queued,
+                    EventSendState::SendingFailed { error } => SendQueueItemState::Failed {
+                        kind: error.as_ref().into(),
+                        error: error.to_string(),
+                    },
+                    EventSendState::Cancelled => SendQueueItemState::Cancelled,
+                    EventSendState::Sent { event_id } => {
+                        SendQueueItemState::Sent { event_id: event_id.to_string() }
+                    }
+                };
+
+                (txn_id, state)
+            })
+            .collect()
+    }
+}
+
+#[uniffi::export(async_runtime = "tokio")]
+impl SendQueue {
+    /// Add a message at the end of the queue.
+    pub fn enqueue_message(&self, msg: Arc<RoomMessageEventContentWithoutRelation>) {
+        self.timeline.clone().send(msg);
+    }
+
+    /// Upload the file at the given path and send it once it's done.
+    ///
+    /// Attachments don't get a local echo, so their progress is only reported
+    /// by the returned handle and the `progress_watcher`, not by the
+    /// [`SendQueueListener`]s.
+    pub fn enqueue_file(
+        &self,
+        url: String,
+        file_info: FileInfo,
+        progress_watcher: Option<Box<dyn ProgressWatcher>>,
+    ) -> Arc<SendAttachmentJoinHandle> {
+        self.timeline.clone().send_file(url, file_info, progress_watcher)
+    }
+
+    /// Send again the item with the given transaction ID, after it failed or
+    /// was cancelled.
+    pub fn retry(&self, transaction_id: String) {
+        self.timeline.clone().retry_send(transaction_id);
+    }
+
+    /// Remove the item with the given transaction ID from the queue, if it
+    /// hasn't been sent yet.
+    pub async fn abort(&self, transaction_id: String) -> bool {
+        let txn_id: &TransactionId = transaction_id.as_str().into();
+        self.room_queue.mark_aborted(txn_id);
+
+        let found = self.timeline.inner.cancel_send(txn_id).await;
+        if !found {
+            self.room_queue.take_aborted(txn_id);
+        }
+
+        found
+    }
+
+    /// Whether sending is paused for this room because the homeserver couldn't
+    /// be reached.
+    ///
+    /// The items enqueued while sending is paused are only sent once
+    /// [`SendQueue::resume_sending`] is called.
+    pub fn is_sending_paused(&self) -> bool {
+        self.timeline.inner.is_sending_paused()
+    }
+
+    /// Listen to the changes of [`SendQueue::is_sending_paused`].
+    ///
+    /// The listener is called right away with the current value.
+    pub fn subscribe_to_sending_paused(
+        &self,
+        listener: Box<dyn SendingPausedListener>,
+    ) -> Arc<TaskHandle> {
+        let mut subscriber = self.timeline.inner.subscribe_to_sending_paused();
+
+        Arc::new(TaskHandle::new(RUNTIME.spawn(async move {
+            listener.on_update(subscriber.get());
+
+            while let Some(paused) = subscriber.next().await {
+                listener.on_update(paused);
+            }
+        })))
+    }
+
+    /// Resume sending after it was paused because the homeserver couldn't be
+    /// reached.
+    ///
+    /// The items that failed because of the network, and the ones that were
+    /// cancelled after them, are retried.
+    pub async fn resume_sending(&self) {
+        self.timeline.inner.resume_sending().await;
+    }
+
+    /// Listen to the changes of the states of the items in the queue.
+    ///
+    /// The listener is called right away with the current state of every item
+    /// in the queue.
+    pub async fn subscribe(
+        self: Arc<Self>,
+        listener: Box<dyn SendQueueListener>,
+    ) -> Arc<TaskHandle> {
+        let (_, timeline_stream) = self.timeline.inner.subscribe_batched().await;
+        let mut paused = self.timeline.inner.subscribe_to_sending_paused();
+
+        Arc::new(TaskHandle::new(RUNTIME.spawn(async move {
+            pin_mut!(timeline_stream);
+
+            let mut known_states = HashMap::new();
+
+            loop {
+                let states = self.states().await;
+
+                for (txn_id, state) in &states {
+                    if known_states.get(txn_id) == Some(state) {
+                        continue;
+                    }
+
+                    listener.on_update(SendQueueUpdate {
+                        transaction_id: txn_id.to_string(),
+                        state: state.clone(),
+                    });
+                }
+
+                let states: HashMap<_, _> = states.into_iter().collect();
+
+                // Local echoes also disappear when they are replaced by their remote echo, so
+                // only the items that were removed with `abort` are reported as aborted.
+                for txn_id in known_states.keys() {
+                    if states.contains_key(txn_id) {
+                        continue;
+                    }
+
+                    if self.room_queue.take_aborted(txn_id) {
+                        listener.on_update(SendQueueUpdate {
+                            transaction_id: txn_id.to_string(),
+                            state: SendQueueItemState::Aborted,
+                        });
+                    }
+                }
+
+                known_states = states;
+
+                // The state of the first unsent item depends on whether sending is paused.
+                tokio::select! {
+                    diffs = timeline_stream.next() => {
+                        if diffs.is_none() {
+                            break;
+                        }
+                    }
+                    Some(_) = paused.next() => {}
+                }
+            }
+        })))
+    }
+}
@@ -17,8 +17,8 @@ use std::{fmt::Debug, sync::Arc};
 use futures_util::pin_mut;
 use matrix_sdk::Client;
 use matrix_sdk_ui::sync_service::{
-    State as MatrixSyncServiceState, SyncService as MatrixSyncService,
-    SyncServiceBuilder as MatrixSyncServiceBuilder,
+    BandwidthProfile as MatrixBandwidthProfile, State as MatrixSyncServiceState,
+    SyncService as MatrixSyncService, SyncServiceBuilder as MatrixSyncServiceBuilder,
 };
 
 use crate::{
@@ -50,6 +50,41 @@ pub trait SyncServiceStateObserver: Send + Sync + Debug {
     fn on_update(&self, state: SyncServiceState);
 }
 
+/// How much data the syncs use, see [`SyncService::set_bandwidth_profile`].
+#[derive(uniffi::Enum)]
+pub enum BandwidthProfile {
+    /// The default profile.
+    Normal,
+    /// A profile using as little data as possible, for metered connections.
+    ///
+    /// The timelines are shorter, less data is synced for the rooms, the media
+    /// prefetching is deferred and the URL previews are suspended.
+    LowData,
+}
+
+impl From<MatrixBandwidthProfile> for BandwidthProfile {
+    fn from(value: MatrixBandwidthProfile) -> Self {
+        match value {
+            MatrixBandwidthProfile::Normal => Self::Normal,
+            MatrixBandwidthProfile::LowData => Self::LowData,
+        }
+    }
+}
+
+impl From<BandwidthProfile> for MatrixBandwidthProfile {
+    fn from(value: BandwidthProfile) -> Self {
+        match value {
+            BandwidthProfile::Normal => Self::Normal,
+            BandwidthProfile::LowData => Self::LowData,
+        }
+    }
+}
+
+#[uniffi::export(callback_interface)]
+pub trait BandwidthProfileListener: Send + Sync + Debug {
+    fn on_update(&self, profile: BandwidthProfile);
+}
+
 #[derive(uniffi::Object)]
 pub struct SyncService {
     pub(crate) inner: Arc<MatrixSyncService>,
@@ -80,6 +115,40 @@ impl SyncService {
             }
         })))
     }
+
+    /// Get the bandwidth profile in use, and listen to its changes.
+    pub fn bandwidth_profile(
+        &self,
+        listener: Box<dyn BandwidthProfileListener>,
+    ) -> Arc<TaskHandle> {
+        let mut profile_stream = self.inner.bandwidth_profile();
+        listener.on_update(profile_stream.get().into());
+
+        Arc::new(TaskHandle::new(RUNTIME.spawn(async move {
+            while let Some(profile) = profile_stream.next().await {
+                listener.on_update(profile.into());
+            }
+        })))
+    }
+
+    /// Choose the bandwidth profile to use.
+    ///
+    /// With `None`, the low data profile is used while the connection is
+    /// metered, see [`SyncService::set_connection_metered`].
+    pub async fn set_bandwidth_profile(
+        &self,
+        profile: Option<BandwidthProfile>,
+    ) -> Result<(), ClientError> {
+        Ok(self.inner.set_bandwidth_profile(profile.map(Into::into)).await?)
+    }
+
+    /// Signal whether the connection of the device is metered.
+    ///
+    /// Unless a profile was chosen with [`SyncService::set_bandwidth_profile`],
+    /// the low data profile is used while the connection is metered.
+    pub async fn set_connection_metered(&self, metered: bool) -> Result<(), ClientError> {
+        Ok(self.inner.set_connection_metered(metered).await?)
+    }
 }
 
 #[derive(Clone, uniffi::Object)]
@@ -12,9 +12,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
 
 use matrix_sdk_ui::timeline::{PollResult, TimelineDetails};
+use ruma::{events::StateEventType, Int, OwnedUserId};
 use tracing::warn;
 
 use super::ProfileDetails;
@@ -40,6 +44,16 @@ impl TimelineItemContent {
                 }
             }
             Content::Poll(poll_state) => TimelineItemContentKind::from(poll_state.results()),
+            Content::CallInvite(invite) => TimelineItemContentKind::CallInvite {
+                call_id: invite.call_id().to_owned(),
+                lifetime: invite.lifetime().into(),
+                offer_type: invite.offer_type().into(),
+            },
+            Content::CallNotify(notify) => TimelineItemContentKind::CallNotify {
+                call_id: notify.call_id().to_owned(),
+                application: notify.application().to_owned(),
+                notify_type: notify.notify_type().into(),
+            },
             Content::UnableToDecrypt(msg) => {
                 TimelineItemContentKind::UnableToDecrypt { msg: EncryptedMessage::new(msg) }
             }
@@ -112,6 +126,16 @@ pub enum TimelineItemContentKind {
         end_time: Option<u64>,
         has_been_edited: bool,
     },
+    CallInvite {
+        call_id: String,
+        lifetime: u64,
+        offer_type: CallOfferType,
+    },
+    CallNotify {
+        call_id: String,
+        application: String,
+        notify_type: CallNotifyType,
+    },
     UnableToDecrypt {
         msg: EncryptedMessage,
     },
@@ -211,6 +235,8 @@ pub enum EncryptedMessage {
     MegolmV1AesSha2 {
         /// The ID of the session used to encrypt the message.
         session_id: String,
+        /// Our best guess at the reason why the message can't be decrypted.
+        cause: UtdCause,
     },
     Unknown,
 }
@@ -224,15 +250,71 @@ impl EncryptedMessage {
                 let sender_key = sender_key.clone();
                 Self::OlmV1Curve25519AesSha2 { sender_key }
             }
-            Message::MegolmV1AesSha2 { session_id, .. } => {
+            Message::MegolmV1AesSha2 { session_id, cause, .. } => {
                 let session_id = session_id.clone();
-                Self::MegolmV1AesSha2 { session_id }
+                Self::MegolmV1AesSha2 { session_id, cause: (*cause).into() }
             }
             Message::Unknown => Self::Unknown,
         }
     }
 }
 
+#[derive(Clone, Copy, uniffi::Enum)]
+pub enum UtdCause {
+    /// We don't know why the message can't be decrypted.
+    Unknown,
+    /// We weren't a member of the room when the message was sent, so the
+    /// sender didn't share the room key with us.
+    Membership,
+}
+
+impl From<matrix_sdk_ui::timeline::UtdCause> for UtdCause {
+    fn from(cause: matrix_sdk_ui::timeline::UtdCause) -> Self {
+        use matrix_sdk_ui::timeline::UtdCause as Cause;
+
+        match cause {
+            Cause::Unknown => Self::Unknown,
+            Cause::Membership => Self::Membership,
+        }
+    }
+}
+
+#[derive(Clone, Copy, uniffi::Enum)]
+pub enum CallOfferType {
+    Voice,
+    Video,
+}
+
+impl From<matrix_sdk_ui::timeline::CallOfferType> for CallOfferType {
+    fn from(offer_type: matrix_sdk_ui::timeline::CallOfferType) -> Self {
+        use matrix_sdk_ui::timeline::CallOfferType as OfferType;
+
+        match offer_type {
+            OfferType::Voice => Self::Voice,
+            OfferType::Video => Self::Video,
+        }
+    }
+}
+
+#[derive(Clone, Copy, uniffi::Enum)]
+pub enum CallNotifyType {
+    /// The call should ring, like a phone call.
+    Ring,
+    /// The room members should only get a notification.
+    Notify,
+}
+
+impl From<matrix_sdk_ui::timeline::CallNotifyType> for CallNotifyType {
+    fn from(notify_type: matrix_sdk_ui::timeline::CallNotifyType) -> Self {
+        use matrix_sdk_ui::timeline::CallNotifyType as NotifyType;
+
+        match notify_type {
+            NotifyType::Ring => Self::Ring,
+            NotifyType::Notify => Self::Notify,
+        }
+    }
+}
+
 #[derive(Clone, uniffi::Record)]
 pub struct Reaction {
     pub key: String,
@@ -298,23 +380,46 @@ pub enum OtherState {
     PolicyRuleServer,
     PolicyRuleUser,
     RoomAliases,
-    RoomAvatar { url: Option<String> },
-    RoomCanonicalAlias,
+    RoomAvatar {
+        url: Option<String>,
+    },
+    RoomCanonicalAlias {
+        alias: Option<String>,
+    },
     RoomCreate,
     RoomEncryption,
     RoomGuestAccess,
-    RoomHistoryVisibility,
-    RoomJoinRules,
-    RoomName { name: Option<String> },
+    RoomHistoryVisibility {
+        history_visibility: Option<String>,
+    },
+    RoomJoinRules {
+        join_rule: Option<String>,
+    },
+    RoomName {
+        name: Option<String>,
+    },
     RoomPinnedEvents,
-    RoomPowerLevels,
+    RoomPowerLevels {
+        users: HashMap<String, i64>,
+        previous_users: Option<HashMap<String, i64>>,
+    },
     RoomServerAcl,
-    RoomThirdPartyInvite { display_name: Option<String> },
-    RoomTombstone,
-    RoomTopic { topic: Option<String> },
+    RoomThirdPartyInvite {
+        display_name: Option<String>,
+    },
+    RoomTombstone {
+        replacement_room: Option<String>,
+    },
+    RoomTopic {
+        topic: Option<String>,
+    },
     SpaceChild,
     SpaceParent,
-    Custom { event_type: String },
+    /// A MatrixRTC membership, the state key is the ID of the member.
+    CallMember,
+    Custom {
+        event_type: String,
+    },
 }
 
 impl From<&matrix_sdk_ui::timeline::AnyOtherFullStateEventContent> for OtherState {
@@ -336,12 +441,36 @@ impl From<&matrix_sdk_ui::timeline::AnyOtherFullStateEventContent> for OtherStat
                 };
                 Self::RoomAvatar { url }
             }
-            Content::RoomCanonicalAlias(_) => Self::RoomCanonicalAlias,
+            Content::RoomCanonicalAlias(c) => {
+                let alias = match c {
+                    FullContent::Original { content, .. } => {
+                        content.alias.as_ref().map(ToString::to_string)
+                    }
+                    FullContent::Redacted(_) => None,
+                };
+                Self::RoomCanonicalAlias { alias }
+            }
             Content::RoomCreate(_) => Self::RoomCreate,
             Content::RoomEncryption(_) => Self::RoomEncryption,
             Content::RoomGuestAccess(_) => Self::RoomGuestAccess,
-            Content::RoomHistoryVisibility(_) => Self::RoomHistoryVisibility,
-            Content::RoomJoinRules(_) => Self::RoomJoinRules,
+            Content::RoomHistoryVisibility(c) => {
+                let history_visibility = match c {
+                    FullContent::Original { content, .. } => {
+                        Some(content.history_visibility.to_string())
+                    }
+                    FullContent::Redacted(_) => None,
+                };
+                Self::RoomHistoryVisibility { history_visibility }
+            }
+            Content::RoomJoinRules(c) => {
+                let join_rule = match c {
+                    FullContent::Original { content, .. } => {
+                        Some(content.join_rule.kind().to_string())
+                    }
+                    FullContent::Redacted(_) => None,
+                };
+                Self::RoomJoinRules { join_rule }
+            }
             Content::RoomName(c) => {
                 let name = match c {
                     FullContent::Original { content, .. } => Some(content.name.clone()),
@@ -350,7 +479,16 @@ impl From<&matrix_sdk_ui::timeline::AnyOtherFullStateEventContent> for OtherStat
                 Self::RoomName { name }
             }
             Content::RoomPinnedEvents(_) => Self::RoomPinnedEvents,
-            Content::RoomPowerLevels(_) => Self::RoomPowerLevels,
+            Content::RoomPowerLevels(c) => {
+                let (users, previous_users) = match c {
+                    FullContent::Original { content, prev_content } => (
+                        power_levels_users(&content.users),
+                        prev_content.as_ref().map(|prev| power_levels_users(&prev.users)),
+                    ),
+                    FullContent::Redacted(_) => (HashMap::new(), None),
+                };
+                Self::RoomPowerLevels { users, previous_users }
+            }
             Content::RoomServerAcl(_) => Self::RoomServerAcl,
             Content::RoomThirdPartyInvite(c) => {
                 let display_name = match c {
@@ -359,7 +497,15 @@ impl From<&matrix_sdk_ui::timeline::AnyOtherFullStateEventContent> for OtherStat
                 };
                 Self::RoomThirdPartyInvite { display_name }
             }
-            Content::RoomTombstone(_) => Self::RoomTombstone,
+            Content::RoomTombstone(c) => {
+                let replacement_room = match c {
+                    FullContent::Original { content, .. } => {
+                        Some(content.replacement_room.to_string())
+                    }
+                    FullContent::Redacted(_) => None,
+                };
+                Self::RoomTombstone { replacement_room }
+            }
             Content::RoomTopic(c) => {
                 let topic = match c {
                     FullContent::Original { content, .. } => Some(content.topic.clone()),
@@ -369,11 +515,23 @@ impl From<&matrix_sdk_ui::timeline::AnyOtherFullStateEventContent> for OtherStat
             }
             Content::SpaceChild(_) => Self::SpaceChild,
             Content::SpaceParent(_) => Self::SpaceParent,
+            Content::_Custom { event_type }
+                if *event_type == StateEventType::CallMember.to_string() =>
+            {
+                Self::CallMember
+            }
             Content::_Custom { event_type, .. } => Self::Custom { event_type: event_type.clone() },
         }
     }
 }
 
+fn power_levels_users(users: &BTreeMap<OwnedUserId, Int>) -> HashMap<String, i64> {
+    users
+        .iter()
+        .map(|(user_id, power_level)| (user_id.to_string(), (*power_level).into()))
+        .collect()
+}
+
 #[derive(uniffi::Record)]
 pub struct PollAnswer {
     pub id: String,
@@ -821,8 +821,27 @@ impl EventTimelineItem {
         self.0.send_state().map(Into::into)
     }
 
+    /// The read receipts on the main timeline of this item, by user ID.
     pub fn read_receipts(&self) -> HashMap<String, Receipt> {
-        self.0.read_receipts().iter().map(|(k, v)| (k.to_string(), v.clone().into())).collect()
+        self.0
+            .read_receipts()
+            .iter()
+            .filter(|((_, thread_root), _)| thread_root.is_none())
+            .map(|((user_id, _), receipt)| (user_id.to_string(), receipt.clone().into()))
+            .collect()
+    }
+
+    /// The read receipts of this item in the thread with the given root, by
+    /// user ID.
+    pub fn thread_read_receipts(&self, thread_root_event_id: String) -> HashMap<String, Receipt> {
+        self.0
+            .read_receipts()
+            .iter()
+            .filter(|((_, thread_root), _)| {
+                thread_root.as_ref().is_some_and(|root| root.as_str() == thread_root_event_id)
+            })
+            .map(|((user_id, _), receipt)| (user_id.to_string(), receipt.clone().into()))
+            .collect()
     }
 
     pub fn origin(&self) -> Option<EventItemOrigin> {
@@ -837,11 +856,18 @@ impl EventTimelineItem {
 #[derive(uniffi::Record)]
 pub struct Receipt {
     pub timestamp: Option<u64>,
+    /// The ID of the root event of the thread of the receipt, if it's a
+    /// threaded receipt.
+    pub thread_root_event_id: Option<String>,
 }
 
 impl From<ruma::events::receipt::Receipt> for Receipt {
     fn from(value: ruma::events::receipt::Receipt) -> Self {
-        Receipt { timestamp: value.ts.map(|ts| ts.0.into()) }
+        let thread_root_event_id = match value.thread {
+            ReceiptThread::Thread(root_event_id) => Some(root_event_id.to_string()),
+            _ => None,
+        };
+        Receipt { timestamp: value.ts.map(|ts| ts.0.into()), thread_root_event_id }
     }
 }
 
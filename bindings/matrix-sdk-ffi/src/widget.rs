@@ -3,8 +3,12 @@ use std::sync::{Arc, Mutex};
 use language_tags::LanguageTag;
 use matrix_sdk::{
     async_trait,
-    widget::{MessageLikeEventFilter, StateEventFilter},
+    widget::{
+        EphemeralEventFilter, MessageLikeEventFilter, PickedFile, StateEventFilter, TimelineRooms,
+        ToDeviceEventFilter,
+    },
 };
+use ruma::RoomId;
 use tracing::error;
 
 use crate::{room::Room, RUNTIME};
@@ -31,6 +35,20 @@ pub struct WidgetDriver(Mutex<Option<matrix_sdk::widget::WidgetDriver>>);
 
 #[uniffi::export(async_runtime = "tokio")]
 impl WidgetDriver {
+    /// Let the widget ask the user to pick a file with the given file picker,
+    /// if it has the capability to do so.
+    ///
+    /// This must be called before [`WidgetDriver::run`].
+    pub fn set_file_picker(&self, file_picker: Box<dyn WidgetFilePicker>) {
+        let mut driver = self.0.lock().unwrap();
+        let Some(inner) = driver.take() else {
+            error!("Can't set the file picker of a WidgetDriver that already runs");
+            return;
+        };
+
+        *driver = Some(inner.with_file_picker(FilePickerWrap(file_picker.into())));
+    }
+
     pub async fn run(
         &self,
         room: Arc<Room>,
@@ -116,7 +134,8 @@ pub async fn generate_webview_url(
 /// This controls the url parameters: `perParticipantE2EE`, `password`.
 #[derive(uniffi::Enum, Clone)]
 pub enum EncryptionSystem {
-    /// Equivalent to the element call url parameter: `enableE2EE=false`
+    /// Equivalent to the element call url parameter: `perParticipantE2EE=false`
+    /// and no password.
     Unencrypted,
     /// Equivalent to the element call url parameter:
     /// `perParticipantE2EE=true`
@@ -139,6 +158,26 @@ impl From<EncryptionSystem> for matrix_sdk::widget::EncryptionSystem {
     }
 }
 
+/// Defines the intent of showing the call.
+///
+/// This controls whether to show or skip the lobby.
+#[derive(uniffi::Enum, Clone)]
+pub enum Intent {
+    /// The user wants to start a call.
+    StartCall,
+    /// The user wants to join an existing call.
+    JoinExisting,
+}
+
+impl From<Intent> for matrix_sdk::widget::Intent {
+    fn from(value: Intent) -> Self {
+        match value {
+            Intent::StartCall => Self::StartCall,
+            Intent::JoinExisting => Self::JoinExisting,
+        }
+    }
+}
+
 /// Properties to create a new virtual Element Call widget.
 #[derive(uniffi::Record, Clone)]
 pub struct VirtualElementCallWidgetOptions {
@@ -207,6 +246,30 @@ pub struct VirtualElementCallWidgetOptions {
     ///
     /// Use `EncryptionSystem::Unencrypted` to disable encryption.
     pub encryption: EncryptionSystem,
+
+    /// The intent of showing the call.
+    ///
+    /// If the user wants to start a call or join an existing one.
+    pub intent: Option<Intent>,
+
+    /// The PostHog user id, to send the analytics of Element Call with the
+    /// same id as the client.
+    pub posthog_user_id: Option<String>,
+
+    /// The url of the PostHog server to send the analytics to.
+    pub posthog_api_host: Option<String>,
+
+    /// The key of the PostHog project to send the analytics to.
+    pub posthog_api_key: Option<String>,
+
+    /// The url where the rageshakes (bug reports) are submitted.
+    pub rageshake_submit_url: Option<String>,
+
+    /// The Sentry DSN to report the errors of Element Call to.
+    pub sentry_dsn: Option<String>,
+
+    /// The Sentry environment of the reported errors.
+    pub sentry_environment: Option<String>,
 }
 
 impl From<VirtualElementCallWidgetOptions> for matrix_sdk::widget::VirtualElementCallWidgetOptions {
@@ -224,6 +287,13 @@ impl From<VirtualElementCallWidgetOptions> for matrix_sdk::widget::VirtualElemen
             font: value.font,
             analytics_id: value.analytics_id,
             encryption: value.encryption.into(),
+            intent: value.intent.map(Into::into),
+            posthog_user_id: value.posthog_user_id,
+            posthog_api_host: value.posthog_api_host,
+            posthog_api_key: value.posthog_api_key,
+            rageshake_submit_url: value.rageshake_submit_url,
+            sentry_dsn: value.sentry_dsn,
+            sentry_environment: value.sentry_environment,
         }
     }
 }
@@ -247,6 +317,60 @@ pub fn new_virtual_element_call_widget(
         .map(|w| w.into())?)
 }
 
+/// Like [`new_virtual_element_call_widget`], but the URL of Element Call must
+/// also follow the given policy, e.g. to only load it from an allowed domain.
+///  # Arguments
+/// * - `props` A struct containing the configuration parameters for a element
+///   call widget.
+/// * - `url_policy` The rules that the URL of Element Call must follow.
+#[uniffi::export]
+pub fn new_virtual_element_call_widget_with_url_policy(
+    props: VirtualElementCallWidgetOptions,
+    url_policy: WidgetUrlPolicy,
+) -> Result<WidgetSettings, ParseError> {
+    let url_policy = matrix_sdk::widget::WidgetUrlPolicy::from(url_policy);
+    Ok(matrix_sdk::widget::WidgetSettings::new_virtual_element_call_widget_with_url_policy(
+        props.into(),
+        &url_policy,
+    )
+    .map(|w| w.into())?)
+}
+
+/// The rules that the URL of a widget must follow to be loaded.
+///
+/// A widget URL must use `https`, or `http` if it points to a loopback
+/// address. If `allowed_domains` is set, the host of the URL must also be one
+/// of the allowed domains, or one of their subdomains.
+#[derive(uniffi::Record, Clone)]
+pub struct WidgetUrlPolicy {
+    /// The domains that widgets can be loaded from, or `None` to accept
+    /// widgets from any domain.
+    pub allowed_domains: Option<Vec<String>>,
+}
+
+impl From<WidgetUrlPolicy> for matrix_sdk::widget::WidgetUrlPolicy {
+    fn from(value: WidgetUrlPolicy) -> Self {
+        let policy = matrix_sdk::widget::WidgetUrlPolicy::new();
+        match value.allowed_domains {
+            Some(domains) => policy.with_allowed_domains(domains),
+            None => policy,
+        }
+    }
+}
+
+/// Check the URL of a widget against the given policy, for example the one
+/// of a widget state event before offering to launch it.
+///
+/// Returns the sanitized URL if it follows the policy.
+#[uniffi::export]
+pub fn validate_widget_url(
+    raw_url: String,
+    url_policy: WidgetUrlPolicy,
+) -> Result<String, ParseError> {
+    let url_policy = matrix_sdk::widget::WidgetUrlPolicy::from(url_policy);
+    Ok(url_policy.validate(&raw_url)?.to_string())
+}
+
 /// The Capabilities required to run a element call widget.
 ///
 /// This is intended to be used in combination with: `acquire_capabilities` of
@@ -284,6 +408,10 @@ pub fn get_element_call_required_permissions() -> WidgetCapabilities {
             },
         ],
         requires_client: true,
+        send_delayed_event: true,
+        update_delayed_event: true,
+        pick_file: false,
+        timeline_rooms: WidgetTimelineRooms::OwnRoom,
     }
 }
 
@@ -345,6 +473,64 @@ pub struct WidgetCapabilities {
     /// This means clients should not offer to open the widget in a separate
     /// browser/tab/webview that is not connected to the postmessage widget-api.
     pub requires_client: bool,
+    /// If this capability is requested by the widget, it can send events with
+    /// a delay.
+    pub send_delayed_event: bool,
+    /// If this capability is requested by the widget, it can cancel, restart
+    /// or send right away the delayed events.
+    pub update_delayed_event: bool,
+    /// If this capability is requested by the widget, it can ask the user to
+    /// pick a file, which is uploaded by the client.
+    pub pick_file: bool,
+    /// The rooms, besides the room of the widget, in which the `read` and
+    /// `send` filters apply.
+    pub timeline_rooms: WidgetTimelineRooms,
+}
+
+/// The rooms in which a widget can read and send events.
+#[derive(uniffi::Enum)]
+pub enum WidgetTimelineRooms {
+    /// Only the room of the widget.
+    OwnRoom,
+    /// The room of the widget, and these other rooms.
+    Rooms { room_ids: Vec<String> },
+    /// All the rooms the user is joined to.
+    AllJoined,
+}
+
+impl From<WidgetTimelineRooms> for TimelineRooms {
+    fn from(value: WidgetTimelineRooms) -> Self {
+        match value {
+            WidgetTimelineRooms::OwnRoom => Self::OwnRoom,
+            WidgetTimelineRooms::Rooms { room_ids } => Self::Rooms(
+                room_ids
+                    .iter()
+                    .filter_map(|room_id| match RoomId::parse(room_id) {
+                        Ok(room_id) => Some(room_id),
+                        Err(e) => {
+                            error!(
+                                "Ignoring invalid room ID `{room_id}` in widget capabilities: {e}"
+                            );
+                            None
+                        }
+                    })
+                    .collect(),
+            ),
+            WidgetTimelineRooms::AllJoined => Self::AllJoined,
+        }
+    }
+}
+
+impl From<TimelineRooms> for WidgetTimelineRooms {
+    fn from(value: TimelineRooms) -> Self {
+        match value {
+            TimelineRooms::OwnRoom => Self::OwnRoom,
+            TimelineRooms::Rooms(room_ids) => {
+                Self::Rooms { room_ids: room_ids.into_iter().map(|id| id.to_string()).collect() }
+            }
+            TimelineRooms::AllJoined => Self::AllJoined,
+        }
+    }
 }
 
 impl From<WidgetCapabilities> for matrix_sdk::widget::Capabilities {
@@ -353,6 +539,10 @@ impl From<WidgetCapabilities> for matrix_sdk::widget::Capabilities {
             read: value.read.into_iter().map(Into::into).collect(),
             send: value.send.into_iter().map(Into::into).collect(),
             requires_client: value.requires_client,
+            send_delayed_event: value.send_delayed_event,
+            update_delayed_event: value.update_delayed_event,
+            pick_file: value.pick_file,
+            timeline_rooms: value.timeline_rooms.into(),
         }
     }
 }
@@ -363,6 +553,10 @@ impl From<matrix_sdk::widget::Capabilities> for WidgetCapabilities {
             read: value.read.into_iter().map(Into::into).collect(),
             send: value.send.into_iter().map(Into::into).collect(),
             requires_client: value.requires_client,
+            send_delayed_event: value.send_delayed_event,
+            update_delayed_event: value.update_delayed_event,
+            pick_file: value.pick_file,
+            timeline_rooms: value.timeline_rooms.into(),
         }
     }
 }
@@ -378,6 +572,10 @@ pub enum WidgetEventFilter {
     StateWithType { event_type: String },
     /// Matches state events with the given `type` and `state_key`.
     StateWithTypeAndStateKey { event_type: String, state_key: String },
+    /// Matches ephemeral events with the given `type`.
+    EphemeralWithType { event_type: String },
+    /// Matches to-device events with the given `type`.
+    ToDeviceWithType { event_type: String },
 }
 
 impl From<WidgetEventFilter> for matrix_sdk::widget::EventFilter {
@@ -395,6 +593,12 @@ impl From<WidgetEventFilter> for matrix_sdk::widget::EventFilter {
             WidgetEventFilter::StateWithTypeAndStateKey { event_type, state_key } => {
                 Self::State(StateEventFilter::WithTypeAndStateKey(event_type.into(), state_key))
             }
+            WidgetEventFilter::EphemeralWithType { event_type } => {
+                Self::Ephemeral(EphemeralEventFilter::WithType(event_type.into()))
+            }
+            WidgetEventFilter::ToDeviceWithType { event_type } => {
+                Self::ToDevice(ToDeviceEventFilter::WithType(event_type.into()))
+            }
         }
     }
 }
@@ -416,6 +620,12 @@ impl From<matrix_sdk::widget::EventFilter> for WidgetEventFilter {
             F::State(StateEventFilter::WithTypeAndStateKey(event_type, state_key)) => {
                 Self::StateWithTypeAndStateKey { event_type: event_type.to_string(), state_key }
             }
+            F::Ephemeral(EphemeralEventFilter::WithType(event_type)) => {
+                Self::EphemeralWithType { event_type: event_type.to_string() }
+            }
+            F::ToDevice(ToDeviceEventFilter::WithType(event_type)) => {
+                Self::ToDeviceWithType { event_type: event_type.to_string() }
+            }
         }
     }
 }
@@ -445,6 +655,51 @@ impl matrix_sdk::widget::CapabilitiesProvider for CapabilitiesProviderWrap {
     }
 }
 
+#[uniffi::export(callback_interface)]
+pub trait WidgetFilePicker: Send + Sync {
+    /// Let the user pick a file, and return it, or `None` if the user didn't
+    /// pick any.
+    ///
+    /// `accepted_types` contains the MIME types or file extensions that the
+    /// widget asked for, it is empty if the widget accepts any file.
+    fn pick_file(&self, accepted_types: Vec<String>) -> Option<WidgetPickedFile>;
+}
+
+/// A file picked by the user for a widget.
+#[derive(uniffi::Record)]
+pub struct WidgetPickedFile {
+    /// The name of the file.
+    pub name: String,
+    /// The MIME type of the file, `application/octet-stream` is used if it
+    /// isn't valid.
+    pub content_type: String,
+    /// The content of the file.
+    pub data: Vec<u8>,
+}
+
+impl From<WidgetPickedFile> for PickedFile {
+    fn from(value: WidgetPickedFile) -> Self {
+        let content_type = value.content_type.parse().unwrap_or(mime::APPLICATION_OCTET_STREAM);
+        PickedFile { name: value.name, content_type, data: value.data }
+    }
+}
+
+struct FilePickerWrap(Arc<dyn WidgetFilePicker>);
+
+#[async_trait]
+impl matrix_sdk::widget::FilePicker for FilePickerWrap {
+    async fn pick_file(&self, accepted_types: Vec<String>) -> Option<PickedFile> {
+        let this = self.0.clone();
+        // The user needs to pick the file, so use one of tokio's blocking task
+        // threads, like for the capabilities.
+        RUNTIME
+            .spawn_blocking(move || this.pick_file(accepted_types).map(Into::into))
+            .await
+            // propagate panics from the blocking task
+            .unwrap()
+    }
+}
+
 #[derive(Debug, thiserror::Error, uniffi::Error)]
 #[uniffi(flat_error)]
 pub enum ParseError {
@@ -470,6 +725,24 @@ pub enum ParseError {
     Overflow,
     #[error("unknown URL parsing error")]
     Other,
+    #[error("the scheme of the URL is not allowed for widgets")]
+    ForbiddenScheme,
+    #[error("the domain of the URL is not allowed for widgets")]
+    DomainNotAllowed,
+}
+
+impl From<matrix_sdk::widget::WidgetUrlError> for ParseError {
+    fn from(value: matrix_sdk::widget::WidgetUrlError) -> Self {
+        use matrix_sdk::widget::WidgetUrlError;
+
+        match value {
+            WidgetUrlError::Parse(error) => error.into(),
+            WidgetUrlError::ForbiddenScheme(_) => Self::ForbiddenScheme,
+            WidgetUrlError::MissingHost => Self::EmptyHost,
+            WidgetUrlError::DomainNotAllowed(_) => Self::DomainNotAllowed,
+            WidgetUrlError::NotAWidget => Self::Other,
+        }
+    }
 }
 
 impl From<url::ParseError> for ParseError {
@@ -13,19 +13,26 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#[cfg(feature = "e2e-encryption")]
+use std::ops::Deref;
 use std::{
     collections::{BTreeMap, BTreeSet},
     fmt, iter,
+    sync::{Arc, RwLock as StdRwLock},
 };
-#[cfg(feature = "e2e-encryption")]
-use std::{ops::Deref, sync::Arc};
 
 use eyeball::{SharedObservable, Subscriber};
-use matrix_sdk_common::instant::Instant;
+use matrix_sdk_common::{
+    clock::{system_clock, Clock},
+    instant::Instant,
+    store_locks::CrossProcessStoreLock,
+};
+#[cfg(feature = "automatic-room-key-forwarding")]
+use matrix_sdk_crypto::RoomKeyForwardingPolicy;
 #[cfg(feature = "e2e-encryption")]
 use matrix_sdk_crypto::{
-    store::DynCryptoStore, EncryptionSettings, EncryptionSyncChanges, OlmError, OlmMachine,
-    ToDeviceRequest,
+    store::DynCryptoStore, CryptoIdentityExport, EncryptionSettings, EncryptionSyncChanges,
+    OlmError, OlmMachine, RoomKeyRotationLimits, ToDeviceRequest,
 };
 #[cfg(feature = "e2e-encryption")]
 use ruma::events::{
@@ -39,18 +46,20 @@ use ruma::{
         room::{
             member::{MembershipState, SyncRoomMemberEvent},
             power_levels::{RoomPowerLevelsEvent, RoomPowerLevelsEventContent},
+            redaction::SyncRoomRedactionEvent,
         },
         AnyGlobalAccountDataEvent, AnyRoomAccountDataEvent, AnyStrippedStateEvent,
         AnySyncEphemeralRoomEvent, AnySyncMessageLikeEvent, AnySyncStateEvent,
-        AnySyncTimelineEvent, GlobalAccountDataEventType, StateEventType,
+        AnySyncTimelineEvent, GlobalAccountDataEventType, RoomAccountDataEvent,
+        RoomAccountDataEventType, StateEventType, StaticEventContent,
     },
     push::{Action, PushConditionRoomCtx, Ruleset},
     serde::Raw,
     MilliSecondsSinceUnixEpoch, OwnedRoomId, OwnedUserId, RoomId, RoomVersionId, UInt, UserId,
 };
-use tokio::sync::RwLock;
 #[cfg(feature = "e2e-encryption")]
 use tokio::sync::RwLockReadGuard;
+use tokio::sync::{broadcast, RwLock};
 use tracing::{debug, info, instrument, trace, warn};
 
 #[cfg(all(feature = "e2e-encryption", feature = "experimental-sliding-sync"))]
@@ -58,17 +67,25 @@ use crate::latest_event::{is_suitable_for_latest_event, LatestEvent, PossibleLat
 use crate::{
     deserialized_responses::{AmbiguityChanges, MembersResponse, SyncTimelineEvent},
     error::Result,
-    rooms::{Room, RoomInfo, RoomState},
+    rooms::{Room, RoomInfo, RoomInfoUpdate, RoomState},
+    state_validation::{StateDivergence, StateValidator},
     store::{
-        ambiguity_map::AmbiguityCache, DynStateStore, MemoryStore, Result as StoreResult,
-        StateChanges, StateStoreDataKey, StateStoreDataValue, StateStoreExt, Store, StoreConfig,
+        ambiguity_map::AmbiguityCache, DynStateStore, LockableStateStore, MemoryStore,
+        Result as StoreResult, StateChanges, StateStoreDataKey, StateStoreDataValue, StateStoreExt,
+        Store, StoreConfig, StoreError,
     },
-    sync::{JoinedRoom, LeftRoom, Rooms, SyncResponse, Timeline},
+    sync::{JoinedRoom, LeftRoom, Rooms, SyncResponse, SyncResponsePostProcessor, Timeline},
+    sync_metrics::{RoomSyncMetrics, SyncMetricsHook, SyncMetricsRecorder},
+    MarkedUnreadEventContent, RoomPrivacyOverrides, RoomPrivacyOverridesEventContent,
     RoomStateFilter, SessionMeta,
 };
 #[cfg(feature = "e2e-encryption")]
 use crate::{error::Error, RoomMemberships};
 
+/// The capacity of the channel of the inconsistencies found in the room state
+/// of the sync responses.
+const STATE_DIVERGENCE_CHANNEL_CAPACITY: usize = 32;
+
 /// A no IO Client implementation.
 ///
 /// This Client is a state machine that receives responses and events and
@@ -88,8 +105,34 @@ pub struct BaseClient {
     /// [`BaseClient::set_session_meta`]
     #[cfg(feature = "e2e-encryption")]
     olm_machine: Arc<RwLock<Option<OlmMachine>>>,
+    /// Observable of when the `OlmMachine` is replaced by a new one.
+    #[cfg(feature = "e2e-encryption")]
+    olm_machine_reloads: SharedObservable<()>,
+    /// The upper bounds for the rotation periods of the room keys we create,
+    /// whatever the rooms ask for.
+    #[cfg(feature = "e2e-encryption")]
+    room_key_rotation_limits: RoomKeyRotationLimits,
+    /// Whether sharing a room key should fail if some devices of the
+    /// recipients aren't verified.
+    #[cfg(feature = "e2e-encryption")]
+    error_on_unverified_devices: bool,
+    /// Which devices the `OlmMachine` forwards room keys to, kept here so it
+    /// is applied again when the `OlmMachine` is reloaded.
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    room_key_forwarding_policy: Arc<StdRwLock<Option<RoomKeyForwardingPolicy>>>,
+    /// Whether the room state of the sync responses is checked for
+    /// inconsistencies.
+    pub(crate) state_validation: bool,
+    /// The sender of the inconsistencies found in the room state of the sync
+    /// responses.
+    pub(crate) state_divergence_sender: broadcast::Sender<StateDivergence>,
+    /// The hook receiving the timings of the processing of the sync
+    /// responses.
+    pub(crate) sync_metrics_hook: Option<Arc<dyn SyncMetricsHook>>,
     /// Observable of when a user is ignored/unignored.
     pub(crate) ignore_user_list_changes: SharedObservable<()>,
+    /// The extensions called on every processed sync response.
+    sync_response_post_processors: Arc<StdRwLock<Vec<Arc<dyn SyncResponsePostProcessor>>>>,
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -116,12 +159,24 @@ impl BaseClient {
     /// previous login call.
     pub fn with_store_config(config: StoreConfig) -> Self {
         BaseClient {
-            store: Store::new(config.state_store),
+            store: Store::new(config.state_store, system_clock()),
             #[cfg(feature = "e2e-encryption")]
             crypto_store: config.crypto_store,
             #[cfg(feature = "e2e-encryption")]
             olm_machine: Default::default(),
+            #[cfg(feature = "e2e-encryption")]
+            olm_machine_reloads: Default::default(),
+            #[cfg(feature = "e2e-encryption")]
+            room_key_rotation_limits: Default::default(),
+            #[cfg(feature = "e2e-encryption")]
+            error_on_unverified_devices: false,
+            #[cfg(feature = "automatic-room-key-forwarding")]
+            room_key_forwarding_policy: Default::default(),
+            state_validation: false,
+            state_divergence_sender: broadcast::channel(STATE_DIVERGENCE_CHANNEL_CAPACITY).0,
+            sync_metrics_hook: None,
             ignore_user_list_changes: Default::default(),
+            sync_response_post_processors: Default::default(),
         }
     }
 
@@ -133,7 +188,154 @@ impl BaseClient {
         #[cfg(feature = "e2e-encryption")]
         let config = config.crypto_store(self.crypto_store.clone());
 
-        Self::with_store_config(config)
+        let mut client = Self::with_store_config(config)
+            .with_clock(self.clock().clone())
+            .with_state_validation(self.state_validation);
+        client.sync_metrics_hook = self.sync_metrics_hook.clone();
+
+        #[cfg(feature = "e2e-encryption")]
+        {
+            client.room_key_rotation_limits = self.room_key_rotation_limits;
+            client.error_on_unverified_devices = self.error_on_unverified_devices;
+        }
+
+        #[cfg(feature = "automatic-room-key-forwarding")]
+        {
+            *client.room_key_forwarding_policy.write().unwrap() = self.room_key_forwarding_policy();
+        }
+
+        client
+    }
+
+    /// Use the given [`Clock`] as the source of time for this client and its
+    /// rooms, instead of the system time.
+    ///
+    /// This must be called before the session is set, rooms that were already
+    /// loaded keep the clock they were created with.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.store.clock = clock;
+        self
+    }
+
+    /// Check the room state of the sync responses, of `/sync` and of sliding
+    /// sync, for inconsistencies that a correct homeserver can't produce, like
+    /// duplicate state keys or membership changes without a membership event.
+    ///
+    /// The inconsistencies are logged and sent to the receivers of
+    /// [`BaseClient::subscribe_to_state_divergences()`], the state is still
+    /// stored as the homeserver sent it. The checks are disabled by default.
+    pub fn with_state_validation(mut self, enabled: bool) -> Self {
+        self.state_validation = enabled;
+        self
+    }
+
+    /// Get a receiver of the inconsistencies found in the room state of the
+    /// sync responses, when the checks are enabled with
+    /// [`BaseClient::with_state_validation()`].
+    pub fn subscribe_to_state_divergences(&self) -> broadcast::Receiver<StateDivergence> {
+        self.state_divergence_sender.subscribe()
+    }
+
+    /// Send the timings of the processing of every sync response, broken down
+    /// per room, to the given hook.
+    ///
+    /// See [`SyncMetricsHook`] for more details.
+    pub fn with_sync_metrics_hook(mut self, hook: Arc<dyn SyncMetricsHook>) -> Self {
+        self.sync_metrics_hook = Some(hook);
+        self
+    }
+
+    /// Get a receiver of the [`RoomInfoUpdate`]s of all the rooms of this
+    /// client.
+    ///
+    /// Every update says what changed in the room info, which allows to skip
+    /// the computations that don't depend on the parts that changed.
+    pub fn subscribe_to_room_info_updates(&self) -> broadcast::Receiver<RoomInfoUpdate> {
+        self.store.room_info_update_sender.subscribe()
+    }
+
+    /// Register an extension that gets called on every sync response processed
+    /// by this client.
+    ///
+    /// See [`SyncResponsePostProcessor`] for more details. Post-processors
+    /// aren't kept by [`BaseClient::clone_with_in_memory_state_store()`].
+    pub fn add_sync_response_post_processor(
+        &self,
+        post_processor: Arc<dyn SyncResponsePostProcessor>,
+    ) {
+        self.sync_response_post_processors.write().unwrap().push(post_processor);
+    }
+
+    /// Call the registered [`SyncResponsePostProcessor`]s on the given sync
+    /// response.
+    pub(crate) async fn run_sync_response_post_processors(&self, response: &mut SyncResponse) {
+        // Don't hold the lock across the await points.
+        let post_processors = self.sync_response_post_processors.read().unwrap().clone();
+
+        for post_processor in post_processors {
+            post_processor.process(self, response).await;
+        }
+    }
+
+    /// The [`Clock`] used as the source of time by this client.
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        &self.store.clock
+    }
+
+    /// Rotate the room keys we create at least as often as the given limits
+    /// require, even if the `m.room.encryption` state event of a room allows
+    /// using them for longer.
+    #[cfg(feature = "e2e-encryption")]
+    pub fn with_room_key_rotation_limits(mut self, limits: RoomKeyRotationLimits) -> Self {
+        self.room_key_rotation_limits = limits;
+        self
+    }
+
+    /// Fail to share the room keys we create if some devices of the
+    /// recipients aren't verified, instead of silently not sharing the room
+    /// keys with them.
+    ///
+    /// See [`EncryptionSettings::error_on_unverified_devices`].
+    #[cfg(feature = "e2e-encryption")]
+    pub fn with_error_on_unverified_devices(mut self, error_on_unverified_devices: bool) -> Self {
+        self.error_on_unverified_devices = error_on_unverified_devices;
+        self
+    }
+
+    /// Only forward room keys to the devices allowed by the given policy.
+    ///
+    /// See [`BaseClient::set_room_key_forwarding_policy()`].
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    pub fn with_room_key_forwarding_policy(self, policy: Option<RoomKeyForwardingPolicy>) -> Self {
+        *self.room_key_forwarding_policy.write().unwrap() = policy;
+        self
+    }
+
+    /// Set which devices the room keys are forwarded to, when they request
+    /// them.
+    ///
+    /// The policy is applied to the current `OlmMachine`, and to the ones
+    /// that replace it. If it is `None`, the default policy of the
+    /// `OlmMachine` is used.
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    pub async fn set_room_key_forwarding_policy(&self, policy: Option<RoomKeyForwardingPolicy>) {
+        // Hold the lock of the `OlmMachine` so it can't be replaced with the
+        // old policy in the meantime.
+        let olm_machine = self.olm_machine.read().await;
+
+        *self.room_key_forwarding_policy.write().unwrap() = policy;
+
+        if let Some(olm_machine) = olm_machine.as_ref() {
+            olm_machine.set_room_key_forwarding_policy(policy);
+        }
+    }
+
+    /// Get which devices the room keys are forwarded to.
+    ///
+    /// See [`BaseClient::set_room_key_forwarding_policy()`].
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    pub fn room_key_forwarding_policy(&self) -> Option<RoomKeyForwardingPolicy> {
+        *self.room_key_forwarding_policy.read().unwrap()
     }
 
     /// Get the session meta information.
@@ -208,15 +410,61 @@ impl BaseClient {
         let session_meta = self.session_meta().ok_or(Error::OlmError(OlmError::MissingSession))?;
 
         // Recreate it.
-        let olm_machine = OlmMachine::with_store(
+        let olm_machine = OlmMachine::with_store_and_clock(
             &session_meta.user_id,
             &session_meta.device_id,
             self.crypto_store.clone(),
+            self.clock().clone(),
         )
         .await
         .map_err(OlmError::from)?;
 
-        *self.olm_machine.write().await = Some(olm_machine);
+        let mut current = self.olm_machine.write().await;
+
+        // Apply the policy while holding the lock, so it can't be changed for
+        // the old `OlmMachine` only.
+        #[cfg(feature = "automatic-room-key-forwarding")]
+        olm_machine.set_room_key_forwarding_policy(self.room_key_forwarding_policy());
+
+        *current = Some(olm_machine);
+        drop(current);
+        self.olm_machine_reloads.set(());
+
+        Ok(())
+    }
+
+    /// Replace the `OlmMachine` with one that restores the given crypto
+    /// identity.
+    ///
+    /// The export must belong to the user and device of the current session.
+    /// This replaces the Olm account of the crypto store, so it must be done
+    /// right after logging in, before the device keys were uploaded, otherwise
+    /// [`CryptoStoreError::AccountAlreadyShared`] is returned.
+    ///
+    /// [`CryptoStoreError::AccountAlreadyShared`]: crate::crypto::CryptoStoreError::AccountAlreadyShared
+    #[cfg(feature = "e2e-encryption")]
+    pub async fn import_crypto_identity(&self, export: CryptoIdentityExport) -> Result<()> {
+        let session_meta = self.session_meta().ok_or(Error::OlmError(OlmError::MissingSession))?;
+
+        let mut olm_machine = self.olm_machine.write().await;
+
+        let restored = OlmMachine::with_crypto_identity_and_clock(
+            &session_meta.user_id,
+            &session_meta.device_id,
+            self.crypto_store.clone(),
+            export,
+            self.clock().clone(),
+        )
+        .await
+        .map_err(OlmError::from)?;
+
+        #[cfg(feature = "automatic-room-key-forwarding")]
+        restored.set_room_key_forwarding_policy(self.room_key_forwarding_policy());
+
+        *olm_machine = Some(restored);
+        drop(olm_machine);
+        self.olm_machine_reloads.set(());
+
         Ok(())
     }
 
@@ -226,6 +474,31 @@ impl BaseClient {
         self.store.sync_token.read().await.clone()
     }
 
+    /// Creates a `CrossProcessStoreLock` for the state store, that will contain
+    /// the given key and value when hold.
+    pub fn create_state_store_lock(
+        &self,
+        lock_key: String,
+        lock_value: String,
+    ) -> CrossProcessStoreLock<LockableStateStore> {
+        CrossProcessStoreLock::new(
+            LockableStateStore(self.store.inner.clone()),
+            lock_key,
+            lock_value,
+        )
+    }
+
+    /// Reload the sync token and the infos of the rooms from the state store.
+    ///
+    /// This is needed when another process shares the state store and wrote
+    /// to it under our feet.
+    #[instrument(skip(self))]
+    pub async fn reload_from_store(&self) -> Result<()> {
+        let _sync_lock = self.sync_lock().write().await;
+        self.store.reload().await?;
+        Ok(())
+    }
+
     #[cfg(feature = "e2e-encryption")]
     async fn handle_verification_event(
         &self,
@@ -285,6 +558,7 @@ impl BaseClient {
         changes: &mut StateChanges,
         notifications: &mut BTreeMap<OwnedRoomId, Vec<Notification>>,
         ambiguity_cache: &mut AmbiguityCache,
+        metrics: &mut RoomSyncMetrics,
     ) -> Result<Timeline> {
         let mut timeline = Timeline::new(limited, prev_batch);
         let mut push_context = self.get_push_room_context(room, room_info, changes).await?;
@@ -355,11 +629,14 @@ impl BaseClient {
                             AnySyncMessageLikeEvent::RoomEncrypted(
                                 SyncMessageLikeEvent::Original(_),
                             ) => {
-                                if let Ok(Some(e)) = Box::pin(
+                                let start = Instant::now();
+                                let decrypted = Box::pin(
                                     self.decrypt_sync_room_event(&event.event, room.room_id()),
                                 )
-                                .await
-                                {
+                                .await;
+                                metrics.decryption += start.elapsed();
+
+                                if let Ok(Some(e)) = decrypted {
                                     event = e;
                                 }
                             }
@@ -382,6 +659,8 @@ impl BaseClient {
                         AnySyncTimelineEvent::MessageLike(_) => (),
                     }
 
+                    let start = Instant::now();
+
                     if let Some(context) = &mut push_context {
                         self.update_push_room_context(
                             context,
@@ -410,6 +689,8 @@ impl BaseClient {
                         }
                         event.push_actions = actions.to_owned();
                     }
+
+                    metrics.notifications += start.elapsed();
                 }
                 Err(e) => {
                     warn!("Error deserializing event {e:?}");
@@ -510,10 +791,31 @@ impl BaseClient {
         &self,
         room_id: &RoomId,
         events: &[Raw<AnyRoomAccountDataEvent>],
+        room_info: &mut RoomInfo,
         changes: &mut StateChanges,
     ) {
         for raw_event in events {
             if let Ok(event) = raw_event.deserialize() {
+                if event.event_type()
+                    == RoomAccountDataEventType::from(MarkedUnreadEventContent::TYPE)
+                {
+                    match raw_event
+                        .deserialize_as::<RoomAccountDataEvent<MarkedUnreadEventContent>>()
+                    {
+                        Ok(event) => room_info.set_marked_unread(event.content.unread),
+                        Err(e) => warn!("Failed to deserialize the marked unread flag: {e}"),
+                    }
+                } else if event.event_type()
+                    == RoomAccountDataEventType::from(RoomPrivacyOverridesEventContent::TYPE)
+                {
+                    match raw_event
+                        .deserialize_as::<RoomAccountDataEvent<RoomPrivacyOverridesEventContent>>()
+                    {
+                        Ok(event) => room_info.set_privacy_overrides(event.content.overrides),
+                        Err(e) => warn!("Failed to deserialize the privacy overrides: {e}"),
+                    }
+                }
+
                 changes.add_room_account_data(room_id, event, raw_event.clone());
             }
         }
@@ -700,10 +1002,29 @@ impl BaseClient {
     /// # Arguments
     ///
     /// * `response` - The response that we received after a successful sync.
-    #[instrument(skip_all)]
     pub async fn receive_sync_response(
         &self,
         response: api::sync::sync_events::v3::Response,
+    ) -> Result<SyncResponse> {
+        self.receive_sync_response_with_custom_values(response, BTreeMap::new()).await
+    }
+
+    /// Receive a response from a sync call, and set the given custom values of
+    /// the state store in the same write as the sync token of the response.
+    ///
+    /// The custom values aren't set if the response was already received.
+    ///
+    /// # Arguments
+    ///
+    /// * `response` - The response that we received after a successful sync.
+    ///
+    /// * `custom_values` - The custom values to set, see
+    ///   [`StateChanges::custom_values`].
+    #[instrument(skip_all)]
+    pub async fn receive_sync_response_with_custom_values(
+        &self,
+        response: api::sync::sync_events::v3::Response,
+        custom_values: BTreeMap<Vec<u8>, Vec<u8>>,
     ) -> Result<SyncResponse> {
         // The server might respond multiple times with the same sync token, in
         // that case we already received this response and there's nothing to
@@ -714,7 +1035,9 @@ impl BaseClient {
         }
 
         let now = Instant::now();
+        let mut metrics_recorder = SyncMetricsRecorder::new(Some(response.next_batch.clone()));
         let mut changes = Box::new(StateChanges::new(response.next_batch.clone()));
+        changes.custom_values = custom_values;
 
         #[cfg(feature = "e2e-encryption")]
         let to_device = self
@@ -741,8 +1064,13 @@ impl BaseClient {
 
         let mut new_rooms = Rooms::default();
         let mut notifications = Default::default();
+        let mut state_validator =
+            self.state_validation.then(|| StateValidator::new(response.next_batch.clone()));
 
         for (room_id, new_info) in response.rooms.join {
+            let room_start = Instant::now();
+            let mut room_metrics = RoomSyncMetrics::new(room_id.clone());
+            let previous_state = self.store.get_room(&room_id).map(|room| room.state());
             let room = self.store.get_or_create_room(&room_id, RoomState::Joined);
             let mut room_info = room.clone_info();
             room_info.mark_as_joined();
@@ -755,6 +1083,19 @@ impl BaseClient {
             let (raw_state_events, state_events): (Vec<_>, Vec<_>) =
                 state_events.into_iter().unzip();
 
+            if let Some(validator) = &mut state_validator {
+                validator.check_state(&room_id, &new_info.state.events);
+                validator.check_membership(
+                    &room_id,
+                    room.own_user_id(),
+                    previous_state,
+                    RoomState::Joined,
+                    &state_events,
+                    &new_info.timeline.events,
+                );
+            }
+
+            let state_start = Instant::now();
             let mut user_ids = self
                 .handle_state(
                     &raw_state_events,
@@ -764,6 +1105,7 @@ impl BaseClient {
                     &mut ambiguity_cache,
                 )
                 .await?;
+            room_metrics.state = state_start.elapsed();
 
             for raw in &new_info.ephemeral.events {
                 match raw.deserialize() {
@@ -798,11 +1140,17 @@ impl BaseClient {
                     &mut changes,
                     &mut notifications,
                     &mut ambiguity_cache,
+                    &mut room_metrics,
                 )
                 .await?;
 
-            self.handle_room_account_data(&room_id, &new_info.account_data.events, &mut changes)
-                .await;
+            self.handle_room_account_data(
+                &room_id,
+                &new_info.account_data.events,
+                &mut room_info,
+                &mut changes,
+            )
+            .await;
 
             #[cfg(feature = "e2e-encryption")]
             if room_info.is_encrypted() {
@@ -823,21 +1171,32 @@ impl BaseClient {
             let notification_count = new_info.unread_notifications.into();
             room_info.update_notification_count(notification_count);
 
-            new_rooms.join.insert(
-                room_id,
-                JoinedRoom::new(
-                    timeline,
-                    new_info.state.events,
-                    new_info.account_data.events,
-                    new_info.ephemeral.events,
-                    notification_count,
-                ),
+            let thread_notification_counts: BTreeMap<_, _> = new_info
+                .unread_thread_notifications
+                .into_iter()
+                .map(|(thread_root, counts)| (thread_root, counts.into()))
+                .collect();
+            room_info.update_thread_notification_counts(thread_notification_counts.clone());
+
+            let mut joined_room = JoinedRoom::new(
+                timeline,
+                new_info.state.events,
+                new_info.account_data.events,
+                new_info.ephemeral.events,
+                notification_count,
             );
+            joined_room.unread_thread_notifications = thread_notification_counts;
+
+            metrics_recorder.add_room(room_metrics, room_start);
+            new_rooms.join.insert(room_id, joined_room);
 
             changes.add_room(room_info);
         }
 
         for (room_id, new_info) in response.rooms.leave {
+            let room_start = Instant::now();
+            let mut room_metrics = RoomSyncMetrics::new(room_id.clone());
+            let previous_state = self.store.get_room(&room_id).map(|room| room.state());
             let room = self.store.get_or_create_room(&room_id, RoomState::Left);
             let mut room_info = room.clone_info();
             room_info.mark_as_left();
@@ -847,6 +1206,19 @@ impl BaseClient {
             let (raw_state_events, state_events): (Vec<_>, Vec<_>) =
                 state_events.into_iter().unzip();
 
+            if let Some(validator) = &mut state_validator {
+                validator.check_state(&room_id, &new_info.state.events);
+                validator.check_membership(
+                    &room_id,
+                    room.own_user_id(),
+                    previous_state,
+                    RoomState::Left,
+                    &state_events,
+                    &new_info.timeline.events,
+                );
+            }
+
+            let state_start = Instant::now();
             let mut user_ids = self
                 .handle_state(
                     &raw_state_events,
@@ -856,6 +1228,7 @@ impl BaseClient {
                     &mut ambiguity_cache,
                 )
                 .await?;
+            room_metrics.state = state_start.elapsed();
 
             let timeline = self
                 .handle_timeline(
@@ -869,13 +1242,20 @@ impl BaseClient {
                     &mut changes,
                     &mut notifications,
                     &mut ambiguity_cache,
+                    &mut room_metrics,
                 )
                 .await?;
 
-            self.handle_room_account_data(&room_id, &new_info.account_data.events, &mut changes)
-                .await;
+            self.handle_room_account_data(
+                &room_id,
+                &new_info.account_data.events,
+                &mut room_info,
+                &mut changes,
+            )
+            .await;
 
             changes.add_room(room_info);
+            metrics_recorder.add_room(room_metrics, room_start);
             new_rooms.leave.insert(
                 room_id,
                 LeftRoom::new(timeline, new_info.state.events, new_info.account_data.events),
@@ -883,14 +1263,30 @@ impl BaseClient {
         }
 
         for (room_id, new_info) in response.rooms.invite {
+            let room_start = Instant::now();
+            let mut room_metrics = RoomSyncMetrics::new(room_id.clone());
+            let previous_state = self.store.get_room(&room_id).map(|room| room.state());
             let room = self.store.get_or_create_room(&room_id, RoomState::Invited);
+
+            if let Some(validator) = &mut state_validator {
+                validator.check_state(&room_id, &new_info.invite_state.events);
+                validator.check_invite_membership(
+                    &room_id,
+                    room.own_user_id(),
+                    previous_state,
+                    &new_info.invite_state.events,
+                );
+            }
+
             let mut room_info = room.clone_info();
             room_info.mark_as_invited();
             room_info.mark_state_fully_synced();
 
             self.handle_invited_state(&new_info.invite_state.events, &mut room_info, &mut changes);
+            room_metrics.state = room_start.elapsed();
 
             changes.add_room(room_info);
+            metrics_recorder.add_room(room_metrics, room_start);
 
             new_rooms.invite.insert(room_id, new_info);
         }
@@ -914,14 +1310,21 @@ impl BaseClient {
         changes.ambiguity_maps = ambiguity_cache.cache;
 
         let sync_lock = self.sync_lock().write().await;
+        let store_write_start = Instant::now();
         self.store.save_changes(&changes).await?;
+        metrics_recorder.set_store_write(store_write_start);
         *self.store.sync_token.write().await = Some(response.next_batch.clone());
         self.apply_changes(&changes);
         drop(sync_lock);
 
         info!("Processed a sync response in {:?}", now.elapsed());
+        metrics_recorder.report(self.sync_metrics_hook.as_deref());
+
+        if let Some(validator) = state_validator {
+            validator.report(&self.state_divergence_sender);
+        }
 
-        let response = SyncResponse {
+        let mut response = SyncResponse {
             rooms: new_rooms,
             presence: response.presence.events,
             account_data: response.account_data.events,
@@ -930,6 +1333,8 @@ impl BaseClient {
             notifications,
         };
 
+        self.run_sync_response_post_processors(&mut response).await;
+
         Ok(response)
     }
 
@@ -943,6 +1348,159 @@ impl BaseClient {
                 room.set_room_info(room_info.clone())
             }
         }
+
+        for (room_id, redactions) in &changes.redactions {
+            if let Some(room) = self.store.get_room(room_id) {
+                for (redacted_event_id, redaction) in redactions {
+                    room.on_event_redacted(redacted_event_id, redaction, false);
+                }
+            }
+        }
+    }
+
+    /// Apply a redaction that was sent by this client, without waiting for it
+    /// to come back via sync.
+    ///
+    /// The redacted event is scrubbed from the room info, the state store and
+    /// the in-memory caches of the room, and the subscribers of
+    /// [`Room::subscribe_to_redactions()`] are notified.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The ID of the room the redaction was sent to.
+    ///
+    /// * `redaction` - The redaction event, as it will be received via sync.
+    #[instrument(skip(self, redaction))]
+    pub async fn receive_local_redaction(
+        &self,
+        room_id: &RoomId,
+        redaction: &Raw<SyncRoomRedactionEvent>,
+    ) -> Result<()> {
+        let Some(room) = self.store.get_room(room_id) else {
+            warn!("Can't apply a local redaction in an unknown room");
+            return Ok(());
+        };
+
+        let event = redaction.deserialize().map_err(StoreError::Json)?;
+
+        let _sync_lock = self.sync_lock().read().await;
+
+        let mut room_info = room.clone_info();
+        let room_version = room_info.room_version().unwrap_or(&RoomVersionId::V1).to_owned();
+        let Some(redacts) = event.redacts(&room_version) else {
+            warn!("Can't apply a local redaction, redacts field is missing");
+            return Ok(());
+        };
+
+        room_info.handle_redaction(&event, redaction);
+
+        let mut changes = StateChanges::default();
+        changes.add_redaction(room_id, redacts, redaction.clone());
+        changes.add_room(room_info.clone());
+
+        self.store.save_changes(&changes).await?;
+
+        // Not using `apply_changes()`, the subscribers must know that this
+        // redaction wasn't received via sync.
+        room.set_room_info(room_info);
+        room.on_event_redacted(redacts, redaction, true);
+
+        Ok(())
+    }
+
+    /// Shelve the invite to the given room as spam, or restore it.
+    ///
+    /// See [`Room::is_spam_invite`].
+    #[instrument(skip(self))]
+    pub async fn set_spam_invite(&self, room_id: &RoomId, is_spam: bool) -> Result<()> {
+        let Some(room) = self.store.get_room(room_id) else {
+            warn!("Can't shelve an invite to an unknown room");
+            return Ok(());
+        };
+
+        let _sync_lock = self.sync_lock().read().await;
+
+        let mut room_info = room.clone_info();
+        room_info.set_spam_invite(is_spam);
+
+        let mut changes = StateChanges::default();
+        changes.add_room(room_info);
+
+        self.store.save_changes(&changes).await?;
+        self.apply_changes(&changes);
+
+        Ok(())
+    }
+
+    /// Set what the user doesn't share with the other members of the given
+    /// room.
+    ///
+    /// The overrides are only stored locally, see [`Room::privacy_overrides`].
+    #[instrument(skip(self))]
+    pub async fn set_room_privacy_overrides(
+        &self,
+        room_id: &RoomId,
+        overrides: RoomPrivacyOverrides,
+    ) -> Result<()> {
+        let Some(room) = self.store.get_room(room_id) else {
+            warn!("Can't set the privacy overrides of an unknown room");
+            return Ok(());
+        };
+
+        let _sync_lock = self.sync_lock().read().await;
+
+        let mut room_info = room.clone_info();
+        room_info.set_privacy_overrides(overrides);
+
+        let mut changes = StateChanges::default();
+        changes.add_room(room_info);
+
+        self.store.save_changes(&changes).await?;
+        self.apply_changes(&changes);
+
+        Ok(())
+    }
+
+    /// Forget the locally cached events of the given room that were sent
+    /// before `cutoff`, and the receipts sent before it.
+    ///
+    /// This is used to enforce the retention policy of a room. Returns the
+    /// events that were forgotten, so that the data attached to them, like
+    /// their media, can be removed too.
+    #[instrument(skip(self))]
+    pub async fn purge_room_events_before(
+        &self,
+        room_id: &RoomId,
+        cutoff: MilliSecondsSinceUnixEpoch,
+    ) -> Result<Vec<SyncTimelineEvent>> {
+        let Some(room) = self.store.get_room(room_id) else {
+            return Ok(Vec::new());
+        };
+
+        let _sync_lock = self.sync_lock().read().await;
+
+        let mut changes = StateChanges::default();
+        let purged = room.purge_events_before(cutoff, &mut changes);
+
+        self.store.remove_room_receipts_before(room_id, cutoff).await?;
+
+        if !changes.room_infos.is_empty() {
+            self.store.save_changes(&changes).await?;
+            self.apply_changes(&changes);
+        }
+
+        Ok(purged)
+    }
+
+    /// Remove everything known locally about the given room.
+    ///
+    /// The room won't be returned by [`BaseClient::get_room()`] anymore, until
+    /// it's received again from the homeserver.
+    #[instrument(skip(self))]
+    pub async fn forget_room(&self, room_id: &RoomId) -> Result<()> {
+        let _sync_lock = self.sync_lock().read().await;
+        self.store.forget_room(room_id).await?;
+        Ok(())
     }
 
     /// Receive a get member events response and convert it to a deserialized
@@ -1099,10 +1657,10 @@ impl BaseClient {
     pub async fn share_room_key(&self, room_id: &RoomId) -> Result<Vec<Arc<ToDeviceRequest>>> {
         match self.olm_machine().await.as_ref() {
             Some(o) => {
-                let (history_visibility, settings) = self
+                let history_visibility = self
                     .get_room(room_id)
-                    .map(|r| (r.history_visibility(), r.encryption_settings()))
-                    .unwrap_or((HistoryVisibility::Joined, None));
+                    .map(|r| r.history_visibility())
+                    .unwrap_or(HistoryVisibility::Joined);
 
                 // Don't share the group session with members that are invited
                 // if the history visibility is set to `Joined`
@@ -1114,8 +1672,8 @@ impl BaseClient {
 
                 let members = self.store.get_user_ids(room_id, filter).await?;
 
-                let settings = settings.ok_or(Error::EncryptionNotEnabled)?;
-                let settings = EncryptionSettings::new(settings, history_visibility, false);
+                let settings =
+                    self.room_encryption_settings(room_id).ok_or(Error::EncryptionNotEnabled)?;
 
                 Ok(o.share_room_key(room_id, members.iter().map(Deref::deref), settings).await?)
             }
@@ -1123,6 +1681,27 @@ impl BaseClient {
         }
     }
 
+    /// Get the settings that are used to create the room keys of the given
+    /// room.
+    ///
+    /// The rotation periods are the ones of the `m.room.encryption` state
+    /// event of the room, restricted by the limits set with
+    /// [`BaseClient::with_room_key_rotation_limits`].
+    ///
+    /// Returns `None` if the room is unknown or not encrypted.
+    #[cfg(feature = "e2e-encryption")]
+    pub fn room_encryption_settings(&self, room_id: &RoomId) -> Option<EncryptionSettings> {
+        let room = self.get_room(room_id)?;
+        let content = room.encryption_settings()?;
+
+        let settings = EncryptionSettings {
+            error_on_unverified_devices: self.error_on_unverified_devices,
+            ..EncryptionSettings::new(content, room.history_visibility(), false)
+        };
+
+        Some(settings.with_rotation_limits(self.room_key_rotation_limits))
+    }
+
     /// Get the room with the given room id.
     ///
     /// # Arguments
@@ -1277,6 +1856,19 @@ impl BaseClient {
         self.ignore_user_list_changes.subscribe()
     }
 
+    /// Returns a subscriber that publishes an event every time the
+    /// `OlmMachine` is replaced by a new one, for example when it is reloaded
+    /// because another process modified the crypto store.
+    ///
+    /// The streams of the previous `OlmMachine`, like
+    /// [`Store::secrets_stream()`](matrix_sdk_crypto::store::Store::secrets_stream),
+    /// don't receive updates anymore and must be replaced by the ones of the
+    /// new `OlmMachine`.
+    #[cfg(feature = "e2e-encryption")]
+    pub fn subscribe_to_olm_machine_reloads(&self) -> Subscriber<()> {
+        self.olm_machine_reloads.subscribe()
+    }
+
     pub(crate) fn deserialize_state_events(
         raw_events: &[Raw<AnySyncStateEvent>],
     ) -> Vec<(Raw<AnySyncStateEvent>, AnySyncStateEvent)> {
@@ -1301,18 +1893,32 @@ impl Default for BaseClient {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use async_trait::async_trait;
     use matrix_sdk_test::{
-        async_test, response_from_file, sync_timeline_event, InvitedRoomBuilder, JoinedRoomBuilder,
-        LeftRoomBuilder, StrippedStateTestEvent, SyncResponseBuilder,
+        async_test, response_from_file, sync_timeline_event, EphemeralTestEvent,
+        InvitedRoomBuilder, JoinedRoomBuilder, LeftRoomBuilder, StateTestEvent,
+        StrippedStateTestEvent, SyncResponseBuilder,
     };
     use ruma::{
         api::{client as api, IncomingResponse},
-        room_id, user_id, RoomId, UserId,
+        event_id,
+        events::receipt::{ReceiptThread, ReceiptType},
+        room_id,
+        serde::Raw,
+        uint, user_id, MilliSecondsSinceUnixEpoch, OwnedRoomId, RoomId, UserId,
     };
     use serde_json::json;
 
     use super::BaseClient;
-    use crate::{store::StateStoreExt, DisplayName, Room, RoomState, SessionMeta, StateChanges};
+    use crate::{
+        state_validation::StateDivergenceKind,
+        store::StateStoreExt,
+        sync::{SyncResponse, SyncResponsePostProcessor},
+        sync_metrics::{SyncMetrics, SyncMetricsHook},
+        DisplayName, Room, RoomState, SessionMeta, StateChanges,
+    };
 
     #[async_test]
     async fn invite_after_leaving() {
@@ -1474,6 +2080,291 @@ mod tests {
     // events. In the meantime, there are tests for the most difficult logic
     // inside Room.  --andyb
 
+    #[async_test]
+    async fn test_thread_notification_counts() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+
+        let client = logged_in_client(user_id).await;
+
+        let mut ev_builder = SyncResponseBuilder::new();
+
+        let response = ev_builder
+            .add_joined_room(
+                JoinedRoomBuilder::new(room_id)
+                    .set_unread_notifications_count(json!({
+                        "highlight_count": 0,
+                        "notification_count": 1,
+                    }))
+                    .set_unread_thread_notifications_count(json!({
+                        "$thread_root:example.org": {
+                            "highlight_count": 1,
+                            "notification_count": 2,
+                        },
+                    })),
+            )
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        let room = client.get_room(room_id).unwrap();
+        assert_eq!(room.unread_notification_counts().notification_count, 1);
+        assert_eq!(room.unread_notification_counts().highlight_count, 0);
+
+        let thread_counts =
+            room.thread_notification_counts()[event_id!("$thread_root:example.org")];
+        assert_eq!(thread_counts.notification_count, 2);
+        assert_eq!(thread_counts.highlight_count, 1);
+
+        let total = room.total_unread_notification_counts();
+        assert_eq!(total.notification_count, 3);
+        assert_eq!(total.highlight_count, 1);
+
+        // Threads that aren't mentioned anymore have been read.
+        let response = ev_builder
+            .add_joined_room(JoinedRoomBuilder::new(room_id).set_unread_notifications_count(
+                json!({
+                    "highlight_count": 0,
+                    "notification_count": 1,
+                }),
+            ))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        assert!(room.thread_notification_counts().is_empty());
+        assert_eq!(room.total_unread_notification_counts().highlight_count, 0);
+    }
+
+    #[async_test]
+    async fn test_local_redaction() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+
+        let client = logged_in_client(user_id).await;
+
+        let response = SyncResponseBuilder::new()
+            .add_joined_room(
+                JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::RoomTopic),
+            )
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        let room = client.get_room(room_id).unwrap();
+        assert_eq!(room.topic().as_deref(), Some("😀"));
+
+        let mut redactions = room.subscribe_to_redactions();
+
+        let redaction = Raw::new(&json!({
+            "type": "m.room.redaction",
+            "event_id": "$redaction",
+            "sender": user_id,
+            "origin_server_ts": 151957878,
+            "redacts": "$151957878228ssqrJ:localhost",
+            "content": {
+                "redacts": "$151957878228ssqrJ:localhost",
+            },
+        }))
+        .unwrap()
+        .cast();
+        client.receive_local_redaction(room_id, &redaction).await.unwrap();
+
+        // The room info was updated and the subscribers were notified.
+        assert_eq!(room.topic(), None);
+        let applied = redactions.try_recv().unwrap();
+        assert_eq!(applied.redacts, event_id!("$151957878228ssqrJ:localhost"));
+        assert!(applied.is_local);
+    }
+
+    #[async_test]
+    async fn test_purge_room_events_before() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+
+        let client = logged_in_client(user_id).await;
+
+        let response = SyncResponseBuilder::new()
+            .add_joined_room(
+                JoinedRoomBuilder::new(room_id)
+                    .add_ephemeral_event(EphemeralTestEvent::ReadReceipt),
+            )
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        let room = client.get_room(room_id).unwrap();
+        let receipt_user_id = user_id!("@example:localhost");
+        let mut purges = room.subscribe_to_purges();
+        assert_eq!(purges.get(), None);
+        assert!(room
+            .load_user_receipt(ReceiptType::Read, ReceiptThread::Unthreaded, receipt_user_id)
+            .await
+            .unwrap()
+            .is_some());
+
+        // The receipt was sent before the cutoff.
+        let cutoff = MilliSecondsSinceUnixEpoch(uint!(1_500_000_000));
+        client.purge_room_events_before(room_id, cutoff).await.unwrap();
+
+        // The subscribers were told to purge the events they hold.
+        assert_eq!(purges.next().await, Some(Some(cutoff)));
+        // The receipt was removed from the store.
+        assert!(room
+            .load_user_receipt(ReceiptType::Read, ReceiptThread::Unthreaded, receipt_user_id)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[cfg(feature = "e2e-encryption")]
+    #[async_test]
+    async fn test_room_encryption_settings_with_rotation_limits() {
+        use std::time::Duration;
+
+        use matrix_sdk_crypto::RoomKeyRotationLimits;
+
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+
+        let limits = RoomKeyRotationLimits {
+            max_rotation_period: Some(Duration::from_secs(3600)),
+            max_rotation_period_msgs: None,
+        };
+        let client = logged_in_client(user_id).await.with_room_key_rotation_limits(limits);
+
+        assert!(client.room_encryption_settings(room_id).is_none());
+
+        let response = SyncResponseBuilder::new()
+            .add_joined_room(
+                JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::Encryption),
+            )
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        // The room asks for a week and 100 messages, the limits only restrict the
+        // period.
+        let settings = client.room_encryption_settings(room_id).unwrap();
+        assert_eq!(settings.rotation_period, Duration::from_secs(3600));
+        assert_eq!(settings.rotation_period_msgs, 100);
+    }
+
+    #[async_test]
+    async fn test_sync_response_post_processors() {
+        #[derive(Debug, Default)]
+        struct RoomCounter {
+            seen_rooms: Mutex<Vec<OwnedRoomId>>,
+        }
+
+        #[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+        #[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+        impl SyncResponsePostProcessor for RoomCounter {
+            async fn process(&self, client: &BaseClient, response: &mut SyncResponse) {
+                // The changes are saved before the post-processors are called.
+                for room_id in response.rooms.join.keys() {
+                    assert!(client.get_room(room_id).is_some());
+                }
+
+                self.seen_rooms.lock().unwrap().extend(response.rooms.join.keys().cloned());
+                // Augment the response for the next post-processors.
+                response.rooms.join.clear();
+            }
+        }
+
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+        let client = logged_in_client(user_id).await;
+
+        let first = Arc::new(RoomCounter::default());
+        let second = Arc::new(RoomCounter::default());
+        client.add_sync_response_post_processor(first.clone());
+        client.add_sync_response_post_processor(second.clone());
+
+        let response = SyncResponseBuilder::new()
+            .add_joined_room(JoinedRoomBuilder::new(room_id))
+            .build_sync_response();
+        let response = client.receive_sync_response(response).await.unwrap();
+
+        assert_eq!(*first.seen_rooms.lock().unwrap(), vec![room_id.to_owned()]);
+        assert!(second.seen_rooms.lock().unwrap().is_empty());
+        assert!(response.rooms.join.is_empty());
+    }
+
+    #[async_test]
+    async fn test_state_validation() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+        let client = logged_in_client(user_id).await.with_state_validation(true);
+        let mut divergences = client.subscribe_to_state_divergences();
+
+        let mut ev_builder = SyncResponseBuilder::new();
+        let response = ev_builder
+            .add_joined_room(JoinedRoomBuilder::new(room_id).add_state_event(
+                StateTestEvent::Custom(json!({
+                    "content": { "membership": "join" },
+                    "event_id": "$join",
+                    "origin_server_ts": 1,
+                    "sender": user_id,
+                    "state_key": user_id,
+                    "type": "m.room.member",
+                })),
+            ))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+        assert!(divergences.try_recv().is_err());
+
+        // The room is left without a membership event, the divergence is
+        // reported but the room is left anyway.
+        let response =
+            ev_builder.add_left_room(LeftRoomBuilder::new(room_id)).build_sync_response();
+        let sync_token = response.next_batch.clone();
+        client.receive_sync_response(response).await.unwrap();
+        assert_eq!(client.get_room(room_id).unwrap().state(), RoomState::Left);
+
+        let divergence = divergences.try_recv().unwrap();
+        assert_eq!(divergence.room_id, room_id);
+        assert_eq!(divergence.sync_token, sync_token);
+        assert_eq!(
+            divergence.kind,
+            StateDivergenceKind::MembershipChangeWithoutEvent {
+                previous: RoomState::Joined,
+                current: RoomState::Left,
+            }
+        );
+        assert!(divergences.try_recv().is_err());
+    }
+
+    #[async_test]
+    async fn test_sync_metrics_hook() {
+        #[derive(Debug, Default)]
+        struct MetricsCollector {
+            metrics: Mutex<Vec<SyncMetrics>>,
+        }
+
+        impl SyncMetricsHook for MetricsCollector {
+            fn on_sync_processed(&self, metrics: &SyncMetrics) {
+                self.metrics.lock().unwrap().push(metrics.clone());
+            }
+        }
+
+        let user_id = user_id!("@alice:example.org");
+        let joined_room_id = room_id!("!joined:example.org");
+        let left_room_id = room_id!("!left:example.org");
+        let collector = Arc::new(MetricsCollector::default());
+        let client = logged_in_client(user_id).await.with_sync_metrics_hook(collector.clone());
+
+        let response = SyncResponseBuilder::new()
+            .add_joined_room(JoinedRoomBuilder::new(joined_room_id))
+            .add_left_room(LeftRoomBuilder::new(left_room_id))
+            .build_sync_response();
+        let sync_token = response.next_batch.clone();
+        client.receive_sync_response(response).await.unwrap();
+
+        let metrics = collector.metrics.lock().unwrap();
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(metrics[0].sync_token.as_deref(), Some(sync_token.as_str()));
+        assert!(metrics[0].store_write <= metrics[0].total);
+
+        let room_ids: Vec<_> = metrics[0].rooms.iter().map(|room| &room.room_id).collect();
+        assert_eq!(room_ids, [joined_room_id, left_room_id]);
+    }
+
     async fn logged_in_client(user_id: &UserId) -> BaseClient {
         let client = BaseClient::new();
         client
@@ -37,6 +37,10 @@ pub enum Error {
     #[error("The room where a group session should be shared is not encrypted")]
     EncryptionNotEnabled,
 
+    /// The client isn't logged in, so the operation can't be performed.
+    #[error("the client isn't logged in")]
+    NotLoggedIn,
+
     /// A generic error returned when the state store fails not due to
     /// IO or (de)serialization.
     #[error(transparent)]
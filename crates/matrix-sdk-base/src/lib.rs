@@ -30,6 +30,7 @@ mod error;
 pub mod latest_event;
 pub mod media;
 mod rooms;
+pub mod state_validation;
 
 mod read_receipts;
 pub use read_receipts::PreviousEventsProvider;
@@ -38,6 +39,7 @@ mod sliding_sync;
 
 pub mod store;
 pub mod sync;
+pub mod sync_metrics;
 mod utils;
 
 pub use client::BaseClient;
@@ -47,8 +49,10 @@ pub use http;
 pub use matrix_sdk_crypto as crypto;
 pub use once_cell;
 pub use rooms::{
-    DisplayName, Room, RoomCreateWithCreatorEventContent, RoomInfo, RoomMember, RoomMemberships,
-    RoomState, RoomStateFilter,
+    apply_redaction, AppliedRedaction, DisplayName, MarkedUnreadEventContent,
+    PendingRoomProfileChange, Room, RoomCreateWithCreatorEventContent, RoomInfo,
+    RoomInfoChangeReasons, RoomInfoUpdate, RoomMember, RoomMemberships, RoomPrivacyOverrides,
+    RoomPrivacyOverridesEventContent, RoomProfileChange, RoomState, RoomStateFilter,
 };
 pub use store::{StateChanges, StateStore, StateStoreDataKey, StateStoreDataValue, StoreError};
 pub use utils::{
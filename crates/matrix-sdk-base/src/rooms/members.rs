@@ -220,6 +220,54 @@ impl RoomMember {
     pub fn is_ignored(&self) -> bool {
         self.is_ignored
     }
+
+    /// Get the reason given for the latest membership change of this member,
+    /// if any.
+    ///
+    /// This is the reason that was provided when the member was e.g. kicked,
+    /// banned or invited, or when they left the room on their own.
+    pub fn reason(&self) -> Option<&str> {
+        self.event.original_content()?.reason.as_deref()
+    }
+
+    /// Get the user that invited this member, if the member is currently
+    /// invited to the room.
+    pub fn invited_by(&self) -> Option<&UserId> {
+        (*self.membership() == MembershipState::Invite).then_some(self.event.sender())
+    }
+
+    /// Get the user that banned this member, if the member is currently banned
+    /// from the room.
+    pub fn banned_by(&self) -> Option<&UserId> {
+        (*self.membership() == MembershipState::Ban).then_some(self.event.sender())
+    }
+
+    /// Get the user that kicked this member, if the latest membership change
+    /// was a kick.
+    ///
+    /// A kick is a change from a `join` to a `leave` membership that was sent
+    /// by somebody else than the member themselves. Unbans and revoked invites
+    /// are not kicks.
+    pub fn kicked_by(&self) -> Option<&UserId> {
+        let sender = self.event.sender();
+        if *self.membership() != MembershipState::Leave || sender == self.user_id() {
+            return None;
+        }
+
+        let prev_content = self.event.as_sync()?.as_original()?.unsigned.prev_content.as_ref()?;
+        (prev_content.membership == MembershipState::Join).then_some(sender)
+    }
+
+    /// Get the display name of the third-party invite this membership was
+    /// created from, if any.
+    ///
+    /// The display name is only returned if the signed data of the invite
+    /// matches this member, a mismatching `mxid` means that the invite wasn't
+    /// meant for this user.
+    pub fn third_party_invite_display_name(&self) -> Option<&str> {
+        let invite = self.event.original_content()?.third_party_invite.as_ref()?;
+        (invite.signed.mxid == self.user_id()).then_some(invite.display_name.as_str())
+    }
 }
 
 // Information about a room member.
@@ -11,7 +11,10 @@ use std::{
 
 use bitflags::bitflags;
 pub use members::RoomMember;
-pub use normal::{Room, RoomInfo, RoomState, RoomStateFilter};
+pub use normal::{
+    apply_redaction, AppliedRedaction, PendingRoomProfileChange, Room, RoomInfo,
+    RoomInfoChangeReasons, RoomInfoUpdate, RoomProfileChange, RoomState, RoomStateFilter,
+};
 use ruma::{
     assign,
     events::{
@@ -430,6 +433,67 @@ impl RoomCreateWithCreatorEventContent {
     }
 }
 
+/// The content of an `m.marked_unread` room account data event, allowing the
+/// user to mark a room as unread manually ([MSC2867]).
+///
+/// [MSC2867]: https://github.com/matrix-org/matrix-spec-proposals/pull/2867
+#[derive(Clone, Debug, Deserialize, Serialize, EventContent)]
+#[ruma_event(type = "m.marked_unread", kind = RoomAccountData)]
+pub struct MarkedUnreadEventContent {
+    /// Whether the room is marked as unread.
+    pub unread: bool,
+}
+
+impl MarkedUnreadEventContent {
+    /// Constructs a `MarkedUnreadEventContent` with the given flag.
+    pub fn new(unread: bool) -> Self {
+        Self { unread }
+    }
+}
+
+/// Per-room overrides of what the user shares with the other members of a
+/// room.
+///
+/// They are stored locally with the room, and can be shared with the other
+/// clients of the user with the [`RoomPrivacyOverridesEventContent`] room
+/// account data.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RoomPrivacyOverrides {
+    /// Don't send public read receipts in this room, they are sent as private
+    /// read receipts instead.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub suppress_read_receipts: bool,
+
+    /// Don't notify the other members of this room that the user is typing.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub suppress_typing_notifications: bool,
+}
+
+impl RoomPrivacyOverrides {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The content of an `org.matrix.rust-sdk.privacy_overrides` room account data
+/// event, sharing the [`RoomPrivacyOverrides`] of a room with the other
+/// clients of the user.
+#[derive(Clone, Debug, Deserialize, Serialize, EventContent)]
+#[ruma_event(type = "org.matrix.rust-sdk.privacy_overrides", kind = RoomAccountData)]
+pub struct RoomPrivacyOverridesEventContent {
+    /// The overrides of the room.
+    #[serde(flatten)]
+    pub overrides: RoomPrivacyOverrides,
+}
+
+impl RoomPrivacyOverridesEventContent {
+    /// Constructs a `RoomPrivacyOverridesEventContent` with the given
+    /// overrides.
+    pub fn new(overrides: RoomPrivacyOverrides) -> Self {
+        Self { overrides }
+    }
+}
+
 /// Redacted form of [`RoomCreateWithCreatorEventContent`].
 pub type RedactedRoomCreateWithCreatorEventContent = RoomCreateWithCreatorEventContent;
 
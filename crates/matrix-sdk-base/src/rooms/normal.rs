@@ -23,10 +23,9 @@ use std::{
 use bitflags::bitflags;
 use eyeball::{SharedObservable, Subscriber};
 use futures_util::stream::{self, StreamExt};
+use matrix_sdk_common::clock::Clock;
 #[cfg(all(feature = "e2e-encryption", feature = "experimental-sliding-sync"))]
 use matrix_sdk_common::ring_buffer::RingBuffer;
-#[cfg(feature = "experimental-sliding-sync")]
-use ruma::events::AnySyncTimelineEvent;
 use ruma::{
     api::client::sync::sync_events::v3::RoomSummary as RumaSummary,
     events::{
@@ -34,6 +33,7 @@ use ruma::{
         ignored_user_list::IgnoredUserListEventContent,
         receipt::{Receipt, ReceiptThread, ReceiptType},
         room::{
+            avatar::{self, RoomAvatarEventContent},
             encryption::RoomEncryptionEventContent,
             guest_access::GuestAccess,
             history_visibility::HistoryVisibility,
@@ -42,33 +42,51 @@ use ruma::{
             name::RoomNameEventContent,
             redaction::SyncRoomRedactionEvent,
             tombstone::RoomTombstoneEventContent,
+            topic::RoomTopicEventContent,
         },
-        tag::Tags,
-        AnyRoomAccountDataEvent, AnyStrippedStateEvent, AnySyncStateEvent,
-        RoomAccountDataEventType,
+        tag::{TagName, Tags},
+        AnyRoomAccountDataEvent, AnyStrippedStateEvent, AnySyncStateEvent, AnySyncTimelineEvent,
+        RoomAccountDataEventType, StateEventContent,
     },
     room::RoomType,
     serde::Raw,
-    EventId, OwnedEventId, OwnedMxcUri, OwnedRoomAliasId, OwnedRoomId, OwnedUserId, RoomAliasId,
-    RoomId, RoomVersionId, UserId,
+    EventId, MilliSecondsSinceUnixEpoch, MxcUri, OwnedEventId, OwnedMxcUri, OwnedRoomAliasId,
+    OwnedRoomId, OwnedUserId, RoomAliasId, RoomId, RoomVersionId, UserId,
 };
 use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
 use tracing::{debug, field::debug, info, instrument, trace, warn};
 
 use super::{
     members::{MemberInfo, MemberRoomInfo},
-    BaseRoomInfo, DisplayName, RoomCreateWithCreatorEventContent, RoomMember,
+    BaseRoomInfo, DisplayName, RoomCreateWithCreatorEventContent, RoomMember, RoomPrivacyOverrides,
 };
 #[cfg(feature = "experimental-sliding-sync")]
 use crate::latest_event::LatestEvent;
 use crate::{
-    deserialized_responses::MemberEvent,
+    deserialized_responses::{MemberEvent, SyncTimelineEvent},
     read_receipts::RoomReadReceipts,
     store::{DynStateStore, Result as StoreResult, StateStoreExt},
     sync::UnreadNotificationsCount,
     MinimalStateEvent, OriginalMinimalStateEvent, RoomMemberships,
 };
 
+/// A redaction that was applied to the local data of a room.
+///
+/// See [`Room::subscribe_to_redactions()`].
+#[derive(Clone, Debug)]
+pub struct AppliedRedaction {
+    /// The ID of the redacted event.
+    pub redacts: OwnedEventId,
+
+    /// The redaction event.
+    pub redaction: Raw<SyncRoomRedactionEvent>,
+
+    /// Whether the redaction was sent by this client and applied before it
+    /// was received via sync.
+    pub is_local: bool,
+}
+
 /// The underlying room data structure collecting state for joined, left and
 /// invited rooms.
 #[derive(Debug, Clone)]
@@ -77,6 +95,11 @@ pub struct Room {
     own_user_id: OwnedUserId,
     inner: SharedObservable<RoomInfo>,
     store: Arc<DynStateStore>,
+    clock: Arc<dyn Clock>,
+    redactions_sender: broadcast::Sender<AppliedRedaction>,
+    /// The timestamp before which the events of this room were last purged.
+    purge_cutoff: SharedObservable<Option<MilliSecondsSinceUnixEpoch>>,
+    room_info_update_sender: broadcast::Sender<RoomInfoUpdate>,
 
     /// The most recent few encrypted events. When the keys come through to
     /// decrypt these, the most recent relevant one will replace
@@ -91,9 +114,101 @@ pub struct Room {
     pub latest_encrypted_events: Arc<SyncRwLock<RingBuffer<Raw<AnySyncTimelineEvent>>>>,
 }
 
+/// A notification that the [`RoomInfo`] of a room has been updated.
+#[derive(Clone, Debug)]
+pub struct RoomInfoUpdate {
+    /// The room whose info was updated.
+    pub room_id: OwnedRoomId,
+
+    /// What changed in the room info.
+    ///
+    /// This is empty if only details that aren't tracked by
+    /// [`RoomInfoChangeReasons`] changed, or if nothing changed at all.
+    pub reasons: RoomInfoChangeReasons,
+}
+
+/// A change of the name, topic or avatar of a room, that can be applied to
+/// the [`RoomInfo`] before the homeserver confirms it.
+///
+/// See [`RoomInfo::apply_profile_change()`].
+#[derive(Clone, Debug)]
+pub enum RoomProfileChange {
+    /// The room has a new name.
+    Name(String),
+
+    /// The room has a new topic.
+    Topic(String),
+
+    /// The room has a new avatar, or no avatar anymore if `url` is `None`.
+    Avatar {
+        /// The URL of the new avatar.
+        url: Option<OwnedMxcUri>,
+
+        /// The metadata of the new avatar.
+        info: Option<Box<avatar::ImageInfo>>,
+    },
+}
+
+/// A [`RoomProfileChange`] that was applied to the [`RoomInfo`] and that the
+/// homeserver didn't confirm yet.
+///
+/// It is either confirmed with [`RoomInfo::confirm_profile_change()`] once
+/// the homeserver accepted it, or undone with
+/// [`RoomInfo::revert_profile_change()`] if sending it failed.
+#[derive(Clone, Debug)]
+pub struct PendingRoomProfileChange(PendingInner);
+
+#[derive(Clone, Debug)]
+enum PendingInner {
+    Name { name: String, previous: Option<MinimalStateEvent<RoomNameEventContent>> },
+    Topic { topic: String, previous: Option<MinimalStateEvent<RoomTopicEventContent>> },
+    Avatar { url: Option<OwnedMxcUri>, previous: Option<MinimalStateEvent<RoomAvatarEventContent>> },
+}
+
+/// A state event that was created locally and isn't known by the homeserver
+/// yet, hence without event ID.
+fn local_state_event<C: StateEventContent>(content: C) -> MinimalStateEvent<C> {
+    MinimalStateEvent::Original(OriginalMinimalStateEvent { content, event_id: None })
+}
+
+/// The local state event whose content matches the predicate, if the event
+/// is one.
+fn as_local_change<C: StateEventContent>(
+    event: &mut Option<MinimalStateEvent<C>>,
+    matches_content: impl FnOnce(&C) -> bool,
+) -> Option<&mut OriginalMinimalStateEvent<C>> {
+    match event {
+        Some(MinimalStateEvent::Original(ev))
+            if ev.event_id.is_none() && matches_content(&ev.content) =>
+        {
+            Some(ev)
+        }
+        _ => None,
+    }
+}
+
+bitflags! {
+    /// The parts of a [`RoomInfo`] that changed in a [`RoomInfoUpdate`].
+    #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+    pub struct RoomInfoChangeReasons: u8 {
+        /// The name or the canonical alias of the room changed.
+        const NAME          = 0b00000001;
+        /// The avatar of the room changed.
+        const AVATAR        = 0b00000010;
+        /// The member counts or the heroes of the room changed.
+        const MEMBERS       = 0b00000100;
+        /// The unread messages or notifications counts changed.
+        const UNREAD_COUNTS = 0b00001000;
+        /// The latest event of the room changed.
+        const LATEST_EVENT  = 0b00010000;
+        /// The room became encrypted.
+        const ENCRYPTION    = 0b00100000;
+    }
+}
+
 /// The room summary containing member counts and members that should be used to
 /// calculate the room display name.
-#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
 pub struct RoomSummary {
     /// The heroes of the room, members that should be used for the room display
     /// name.
@@ -139,22 +254,30 @@ impl Room {
     pub(crate) fn new(
         own_user_id: &UserId,
         store: Arc<DynStateStore>,
+        clock: Arc<dyn Clock>,
+        room_info_update_sender: broadcast::Sender<RoomInfoUpdate>,
         room_id: &RoomId,
         room_state: RoomState,
     ) -> Self {
         let room_info = RoomInfo::new(room_id, room_state);
-        Self::restore(own_user_id, store, room_info)
+        Self::restore(own_user_id, store, clock, room_info_update_sender, room_info)
     }
 
     pub(crate) fn restore(
         own_user_id: &UserId,
         store: Arc<DynStateStore>,
+        clock: Arc<dyn Clock>,
+        room_info_update_sender: broadcast::Sender<RoomInfoUpdate>,
         room_info: RoomInfo,
     ) -> Self {
         Self {
             own_user_id: own_user_id.into(),
             room_id: room_info.room_id.clone(),
             store,
+            clock,
+            redactions_sender: broadcast::channel(32).0,
+            purge_cutoff: Default::default(),
+            room_info_update_sender,
             inner: SharedObservable::new(room_info),
             #[cfg(all(feature = "e2e-encryption", feature = "experimental-sliding-sync"))]
             latest_encrypted_events: Arc::new(SyncRwLock::new(RingBuffer::new(
@@ -178,16 +301,71 @@ impl Room {
         self.inner.read().room_state
     }
 
+    /// Whether this room is an invite that was shelved as spam.
+    ///
+    /// See [`RoomInfo::set_spam_invite`].
+    pub fn is_spam_invite(&self) -> bool {
+        let info = self.inner.read();
+        info.room_state == RoomState::Invited && info.is_spam_invite
+    }
+
+    /// Whether the user marked this room as unread manually, with the
+    /// `m.marked_unread` room account data.
+    ///
+    /// See [`MarkedUnreadEventContent`](crate::MarkedUnreadEventContent).
+    pub fn is_marked_unread(&self) -> bool {
+        self.inner.read().is_marked_unread
+    }
+
+    /// What the user doesn't share with the other members of this room, like
+    /// read receipts or typing notifications.
+    ///
+    /// See [`RoomPrivacyOverrides`].
+    pub fn privacy_overrides(&self) -> RoomPrivacyOverrides {
+        self.inner.read().privacy_overrides
+    }
+
     /// Whether this room's [`RoomType`] is `m.space`.
     pub fn is_space(&self) -> bool {
         self.inner.read().room_type().is_some_and(|t| *t == RoomType::Space)
     }
 
     /// Get the unread notification counts.
+    ///
+    /// If the sync filter enabled `unread_thread_notifications`, these only
+    /// count the notifications of the main timeline, see
+    /// [`Self::thread_notification_counts`] for the ones in threads and
+    /// [`Self::total_unread_notification_counts`] for the sum of both.
     pub fn unread_notification_counts(&self) -> UnreadNotificationsCount {
         self.inner.read().notification_counts
     }
 
+    /// Get the unread notification counts of each thread in this room, keyed
+    /// by the ID of the thread root.
+    ///
+    /// These are only provided by the server if the sync filter enabled
+    /// `unread_thread_notifications` (MSC3773). Threads without unread
+    /// notifications are omitted.
+    pub fn thread_notification_counts(&self) -> BTreeMap<OwnedEventId, UnreadNotificationsCount> {
+        self.inner.read().thread_notification_counts.clone()
+    }
+
+    /// Get the unread notification counts of the room, including the ones in
+    /// threads.
+    ///
+    /// This is the count that should be used for the badge of a room, so that
+    /// mentions in threads aren't hidden when the server reports thread
+    /// notifications separately.
+    pub fn total_unread_notification_counts(&self) -> UnreadNotificationsCount {
+        let info = self.inner.read();
+        info.thread_notification_counts.values().fold(info.notification_counts, |total, thread| {
+            UnreadNotificationsCount {
+                highlight_count: total.highlight_count + thread.highlight_count,
+                notification_count: total.notification_count + thread.notification_count,
+            }
+        })
+    }
+
     /// Get the number of unread messages (computed client-side).
     ///
     /// This might be more precise than [`Self::unread_notification_counts`] for
@@ -342,6 +520,11 @@ impl Room {
 
     /// Get the `m.room.encryption` content that enabled end to end encryption
     /// in the room.
+    ///
+    /// These are the settings the room asks for. The room keys we create may
+    /// be rotated more often than this, if the client restricts the rotation
+    /// periods. The settings that are effectively used are returned by
+    /// `BaseClient::room_encryption_settings`.
     pub fn encryption_settings(&self) -> Option<RoomEncryptionEventContent> {
         self.inner.read().base_info.encryption.clone()
     }
@@ -356,6 +539,17 @@ impl Room {
         self.inner.read().history_visibility().clone()
     }
 
+    /// Can the history of this room be read by anyone, even if they aren't
+    /// members of the room.
+    pub fn is_world_readable(&self) -> bool {
+        matches!(self.history_visibility(), HistoryVisibility::WorldReadable)
+    }
+
+    /// Are guest users allowed to join this room.
+    pub fn guests_can_join(&self) -> bool {
+        matches!(self.guest_access(), GuestAccess::CanJoin)
+    }
+
     /// Is the room considered to be public.
     pub fn is_public(&self) -> bool {
         matches!(self.join_rule(), JoinRule::Public)
@@ -398,8 +592,10 @@ impl Room {
 
     /// Is there a non expired membership with application "m.call" and scope
     /// "m.room" in this room
+    ///
+    /// Expiry of the memberships is checked against the client's [`Clock`].
     pub fn has_active_room_call(&self) -> bool {
-        self.inner.read().has_active_room_call()
+        self.inner.read().has_active_room_call_at(self.clock.now_ms())
     }
 
     /// Returns a Vec of userId's that participate in the room call.
@@ -411,7 +607,7 @@ impl Room {
     ///
     /// The vector is ordered by oldest membership user to newest.
     pub fn active_room_call_participants(&self) -> Vec<OwnedUserId> {
-        self.inner.read().active_room_call_participants()
+        self.inner.read().active_room_call_participants_at(self.clock.now_ms())
     }
 
     /// Return the cached display name of the room if it was provided via sync,
@@ -432,6 +628,48 @@ impl Room {
         self.inner.read().latest_event.as_deref().cloned()
     }
 
+    /// Subscribe to the redactions of the events of this room.
+    ///
+    /// A redaction is sent once it has been applied to the room info and the
+    /// state store, both for redactions received via sync and redactions sent
+    /// by this client. The latter are sent again when they are received via
+    /// sync.
+    pub fn subscribe_to_redactions(&self) -> broadcast::Receiver<AppliedRedaction> {
+        self.redactions_sender.subscribe()
+    }
+
+    /// Scrub the given redacted event from the in-memory caches of this room,
+    /// and notify the subscribers to redactions.
+    ///
+    /// It is the responsibility of the caller to apply the redaction to the
+    /// room info and the state store before calling this function.
+    pub(crate) fn on_event_redacted(
+        &self,
+        redacted_event_id: &EventId,
+        redaction: &Raw<SyncRoomRedactionEvent>,
+        is_local: bool,
+    ) {
+        #[cfg(all(feature = "e2e-encryption", feature = "experimental-sliding-sync"))]
+        {
+            let mut latest_encrypted_events = self.latest_encrypted_events.write().unwrap();
+            let position = latest_encrypted_events.iter().position(|event| {
+                event.get_field::<OwnedEventId>("event_id").ok().flatten().as_deref()
+                    == Some(redacted_event_id)
+            });
+
+            if let Some(index) = position {
+                latest_encrypted_events.remove(index);
+            }
+        }
+
+        // Sending fails if there are no subscribers, which is fine.
+        let _ = self.redactions_sender.send(AppliedRedaction {
+            redacts: redacted_event_id.to_owned(),
+            redaction: redaction.clone(),
+            is_local,
+        });
+    }
+
     /// Return the most recent few encrypted events. When the keys come through
     /// to decrypt these, the most recent relevant one will replace
     /// latest_event. (We can't tell which one is relevant until
@@ -467,6 +705,83 @@ impl Room {
         room_info.latest_event = Some(latest_event);
     }
 
+    /// Forget the events of this room that were sent before `cutoff` and that
+    /// are cached in memory, and notify the subscribers to
+    /// [`Room::subscribe_to_purges()`].
+    ///
+    /// If the room info holds events that must be purged too, the updated room
+    /// info is added to `changes`. It is the responsibility of the caller to
+    /// save it into the state store.
+    ///
+    /// Returns the events that were forgotten.
+    #[cfg_attr(not(feature = "experimental-sliding-sync"), allow(unused_variables))]
+    pub(crate) fn purge_events_before(
+        &self,
+        cutoff: MilliSecondsSinceUnixEpoch,
+        changes: &mut crate::StateChanges,
+    ) -> Vec<SyncTimelineEvent> {
+        #[cfg(feature = "experimental-sliding-sync")]
+        let purged = {
+            let is_expired = |event: &Raw<AnySyncTimelineEvent>| {
+                event
+                    .get_field::<MilliSecondsSinceUnixEpoch>("origin_server_ts")
+                    .ok()
+                    .flatten()
+                    .is_some_and(|ts| ts < cutoff)
+            };
+
+            let mut purged = Vec::new();
+
+            #[cfg(feature = "e2e-encryption")]
+            {
+                let mut latest_encrypted_events = self.latest_encrypted_events.write().unwrap();
+                let (expired, kept): (Vec<_>, Vec<_>) =
+                    latest_encrypted_events.drain(..).partition(|event| is_expired(event));
+                for event in kept {
+                    latest_encrypted_events.push(event);
+                }
+                purged.extend(expired.into_iter().map(SyncTimelineEvent::new));
+            }
+
+            let latest_event_expired = self
+                .inner
+                .read()
+                .latest_event
+                .as_ref()
+                .is_some_and(|latest_event| is_expired(&latest_event.event().event));
+
+            if latest_event_expired {
+                debug!("Removing the expired latest event");
+
+                let room_info = changes
+                    .room_infos
+                    .entry(self.room_id().to_owned())
+                    .or_insert_with(|| self.clone_info());
+                if let Some(latest_event) = room_info.latest_event.take() {
+                    purged.push(latest_event.event().clone());
+                }
+            }
+
+            purged
+        };
+        #[cfg(not(feature = "experimental-sliding-sync"))]
+        let purged = Vec::new();
+
+        self.purge_cutoff.set(Some(cutoff));
+
+        purged
+    }
+
+    /// Subscribe to the purges of the events of this room that are older than
+    /// its retention policy allows.
+    ///
+    /// The subscriber yields the timestamp before which the events were
+    /// purged, so that the events held outside of this crate, e.g. in a
+    /// timeline, can be purged too.
+    pub fn subscribe_to_purges(&self) -> Subscriber<Option<MilliSecondsSinceUnixEpoch>> {
+        self.purge_cutoff.subscribe()
+    }
+
     /// Get the list of users ids that are considered to be joined members of
     /// this room.
     pub async fn joined_user_ids(&self) -> StoreResult<Vec<OwnedUserId>> {
@@ -632,8 +947,19 @@ impl Room {
 
     /// Update the inner summary with the given RoomInfo, and notify
     /// subscribers.
+    ///
+    /// A [`RoomInfoUpdate`] describing what changed is also sent to the
+    /// subscribers of [`BaseClient::subscribe_to_room_info_updates()`].
+    ///
+    /// [`BaseClient::subscribe_to_room_info_updates()`]: crate::BaseClient::subscribe_to_room_info_updates
     pub fn set_room_info(&self, room_info: RoomInfo) {
+        let reasons = room_info.changes_since(&self.inner.read());
         self.inner.set(room_info);
+
+        // It's fine if nobody is listening.
+        let _ = self
+            .room_info_update_sender
+            .send(RoomInfoUpdate { room_id: self.room_id.clone(), reasons });
     }
 
     /// Get the `RoomMember` with the given `user_id`.
@@ -718,6 +1044,12 @@ impl Room {
         }
     }
 
+    /// Whether this room is the server notices room of the user, i.e. whether
+    /// it is tagged with `m.server_notice`.
+    pub async fn is_server_notices_room(&self) -> StoreResult<bool> {
+        Ok(self.tags().await?.is_some_and(|tags| tags.contains_key(&TagName::ServerNotice)))
+    }
+
     /// Get the receipt as an `OwnedEventId` and `Receipt` tuple for the given
     /// `receipt_type`, `thread` and `user_id` in this room.
     pub async fn load_user_receipt(
@@ -761,6 +1093,11 @@ pub struct RoomInfo {
     /// have access to the content of the encrypted events.
     pub(crate) notification_counts: UnreadNotificationsCount,
 
+    /// The unread notifications counts of each thread, keyed by thread root,
+    /// as returned by the server.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) thread_notification_counts: BTreeMap<OwnedEventId, UnreadNotificationsCount>,
+
     /// The summary of this room.
     pub(crate) summary: RoomSummary,
 
@@ -784,6 +1121,18 @@ pub struct RoomInfo {
     #[serde(default)]
     pub(crate) read_receipts: RoomReadReceipts,
 
+    /// Whether the invite to this room was shelved as spam.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) is_spam_invite: bool,
+
+    /// Whether the user marked the room as unread manually.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) is_marked_unread: bool,
+
+    /// What the user doesn't share with the other members of this room.
+    #[serde(default, skip_serializing_if = "RoomPrivacyOverrides::is_default")]
+    pub(crate) privacy_overrides: RoomPrivacyOverrides,
+
     /// Base room info which holds some basic event contents important for the
     /// room state.
     pub(crate) base_info: Box<BaseRoomInfo>,
@@ -813,6 +1162,7 @@ impl RoomInfo {
             room_id: room_id.into(),
             room_state,
             notification_counts: Default::default(),
+            thread_notification_counts: Default::default(),
             summary: Default::default(),
             members_synced: false,
             last_prev_batch: None,
@@ -821,6 +1171,9 @@ impl RoomInfo {
             #[cfg(feature = "experimental-sliding-sync")]
             latest_event: None,
             read_receipts: Default::default(),
+            is_spam_invite: false,
+            is_marked_unread: false,
+            privacy_overrides: Default::default(),
             base_info: Box::new(BaseRoomInfo::new()),
         }
     }
@@ -845,6 +1198,24 @@ impl RoomInfo {
         self.room_state = room_state;
     }
 
+    /// Shelve the invite to this room as spam, or restore it.
+    ///
+    /// The flag is only taken into account while the room is in the invited
+    /// state.
+    pub fn set_spam_invite(&mut self, is_spam: bool) {
+        self.is_spam_invite = is_spam;
+    }
+
+    /// Set whether the user marked this room as unread manually.
+    pub fn set_marked_unread(&mut self, unread: bool) {
+        self.is_marked_unread = unread;
+    }
+
+    /// Set what the user doesn't share with the other members of this room.
+    pub fn set_privacy_overrides(&mut self, overrides: RoomPrivacyOverrides) {
+        self.privacy_overrides = overrides;
+    }
+
     /// Mark this Room as having all the members synced.
     pub fn mark_members_synced(&mut self) {
         self.members_synced = true;
@@ -964,11 +1335,118 @@ impl RoomInfo {
         }));
     }
 
+    /// Apply a change of the name, topic or avatar of the room locally,
+    /// before the homeserver confirms it.
+    ///
+    /// The change is overwritten by the next state event of the same type
+    /// received from the homeserver. The returned value is used to confirm
+    /// the change with [`RoomInfo::confirm_profile_change()`] once it was
+    /// sent, or to undo it with [`RoomInfo::revert_profile_change()`] if
+    /// sending it failed.
+    pub fn apply_profile_change(&mut self, change: RoomProfileChange) -> PendingRoomProfileChange {
+        let pending = match change {
+            RoomProfileChange::Name(name) => {
+                let content = RoomNameEventContent::new(name.clone());
+                let previous = self.base_info.name.replace(local_state_event(content));
+                PendingInner::Name { name, previous }
+            }
+            RoomProfileChange::Topic(topic) => {
+                let content = RoomTopicEventContent::new(topic.clone());
+                let previous = self.base_info.topic.replace(local_state_event(content));
+                PendingInner::Topic { topic, previous }
+            }
+            RoomProfileChange::Avatar { url, info } => {
+                let mut content = RoomAvatarEventContent::new();
+                content.url = url.clone();
+                content.info = info;
+                let previous = self.base_info.avatar.replace(local_state_event(content));
+                PendingInner::Avatar { url, previous }
+            }
+        };
+
+        PendingRoomProfileChange(pending)
+    }
+
+    /// Confirm a change applied with [`RoomInfo::apply_profile_change()`],
+    /// with the ID of the state event the homeserver created for it.
+    ///
+    /// The change is then handled like the state events received from the
+    /// homeserver. Nothing happens if it was overwritten in the meantime.
+    /// Returns whether the change was confirmed.
+    pub fn confirm_profile_change(
+        &mut self,
+        pending: &PendingRoomProfileChange,
+        event_id: OwnedEventId,
+    ) -> bool {
+        let local_event_id = match &pending.0 {
+            PendingInner::Name { name, .. } => {
+                as_local_change(&mut self.base_info.name, |c| c.name == *name)
+                    .map(|ev| &mut ev.event_id)
+            }
+            PendingInner::Topic { topic, .. } => {
+                as_local_change(&mut self.base_info.topic, |c| c.topic == *topic)
+                    .map(|ev| &mut ev.event_id)
+            }
+            PendingInner::Avatar { url, .. } => {
+                as_local_change(&mut self.base_info.avatar, |c| c.url == *url)
+                    .map(|ev| &mut ev.event_id)
+            }
+        };
+
+        let Some(local_event_id) = local_event_id else {
+            return false;
+        };
+        *local_event_id = Some(event_id);
+
+        true
+    }
+
+    /// Undo a change applied with [`RoomInfo::apply_profile_change()`].
+    ///
+    /// Nothing happens if the change was overwritten in the meantime, by a
+    /// state event received from the homeserver or by another local change.
+    /// Returns whether the change was undone.
+    pub fn revert_profile_change(&mut self, pending: PendingRoomProfileChange) -> bool {
+        match pending.0 {
+            PendingInner::Name { name, previous } => {
+                if as_local_change(&mut self.base_info.name, |c| c.name == name).is_none() {
+                    return false;
+                }
+                self.base_info.name = previous;
+            }
+            PendingInner::Topic { topic, previous } => {
+                if as_local_change(&mut self.base_info.topic, |c| c.topic == topic).is_none() {
+                    return false;
+                }
+                self.base_info.topic = previous;
+            }
+            PendingInner::Avatar { url, previous } => {
+                if as_local_change(&mut self.base_info.avatar, |c| c.url == url).is_none() {
+                    return false;
+                }
+                self.base_info.avatar = previous;
+            }
+        }
+
+        true
+    }
+
     /// Update the notifications count
     pub fn update_notification_count(&mut self, notification_counts: UnreadNotificationsCount) {
         self.notification_counts = notification_counts;
     }
 
+    /// Update the notifications count of the threads of this room.
+    ///
+    /// Threads that are missing from the map don't have any unread
+    /// notifications.
+    pub fn update_thread_notification_counts(
+        &mut self,
+        thread_notification_counts: BTreeMap<OwnedEventId, UnreadNotificationsCount>,
+    ) {
+        self.thread_notification_counts = thread_notification_counts;
+    }
+
     /// Update the RoomSummary
     ///
     /// Returns true if the Summary modified the info, false otherwise.
@@ -1080,6 +1558,57 @@ impl RoomInfo {
         (!name.is_empty()).then_some(name)
     }
 
+    fn avatar_url(&self) -> Option<&MxcUri> {
+        self.base_info.avatar.as_ref()?.as_original()?.content.url.as_deref()
+    }
+
+    /// Find out which parts of this room info differ from the `previous` one.
+    pub fn changes_since(&self, previous: &RoomInfo) -> RoomInfoChangeReasons {
+        let mut reasons = RoomInfoChangeReasons::empty();
+
+        if self.name() != previous.name() || self.canonical_alias() != previous.canonical_alias() {
+            reasons |= RoomInfoChangeReasons::NAME;
+        }
+
+        if self.avatar_url() != previous.avatar_url() {
+            reasons |= RoomInfoChangeReasons::AVATAR;
+        }
+
+        if self.summary != previous.summary {
+            reasons |= RoomInfoChangeReasons::MEMBERS;
+        }
+
+        if self.notification_counts != previous.notification_counts
+            || self.thread_notification_counts != previous.thread_notification_counts
+            || self.read_receipts.num_unread != previous.read_receipts.num_unread
+            || self.read_receipts.num_notifications != previous.read_receipts.num_notifications
+            || self.read_receipts.num_mentions != previous.read_receipts.num_mentions
+        {
+            reasons |= RoomInfoChangeReasons::UNREAD_COUNTS;
+        }
+
+        #[cfg(feature = "experimental-sliding-sync")]
+        {
+            // Compare whether the event was decrypted too, since an encrypted latest
+            // event can be replaced by its decrypted version, with the same ID.
+            let latest_event = |info: &RoomInfo| {
+                info.latest_event
+                    .as_ref()
+                    .map(|event| (event.event_id(), event.event().encryption_info.is_some()))
+            };
+
+            if latest_event(self) != latest_event(previous) {
+                reasons |= RoomInfoChangeReasons::LATEST_EVENT;
+            }
+        }
+
+        if self.is_encrypted() != previous.is_encrypted() {
+            reasons |= RoomInfoChangeReasons::ENCRYPTION;
+        }
+
+        reasons
+    }
+
     fn tombstone(&self) -> Option<&RoomTombstoneEventContent> {
         Some(&self.base_info.tombstone.as_ref()?.as_original()?.content)
     }
@@ -1091,8 +1620,14 @@ impl RoomInfo {
     /// Get a list of all the valid (non expired) matrixRTC memberships and
     /// associated UserId's in this room.
     ///
+    /// Memberships are considered expired if they were created more than their
+    /// `expires` duration before `now`.
+    ///
     /// The vector is ordered by oldest membership to newest.
-    fn active_matrix_rtc_memberships(&self) -> Vec<(OwnedUserId, &Membership)> {
+    fn active_matrix_rtc_memberships(
+        &self,
+        now: MilliSecondsSinceUnixEpoch,
+    ) -> Vec<(OwnedUserId, &Membership)> {
         let mut v = self
             .base_info
             .rtc_member
@@ -1100,8 +1635,9 @@ impl RoomInfo {
             .filter_map(|(user_id, ev)| {
                 ev.as_original().map(|ev| {
                     ev.content
-                        .active_memberships(None)
-                        .into_iter()
+                        .memberships
+                        .iter()
+                        .filter(move |m| !is_membership_expired(m, now))
                         .map(move |m| (user_id.clone(), m))
                 })
             })
@@ -1116,8 +1652,11 @@ impl RoomInfo {
     /// returns Memberships with application "m.call" and scope "m.room".
     ///
     /// The vector is ordered by oldest membership user to newest.
-    fn active_room_call_memberships(&self) -> Vec<(OwnedUserId, &Membership)> {
-        self.active_matrix_rtc_memberships()
+    fn active_room_call_memberships(
+        &self,
+        now: MilliSecondsSinceUnixEpoch,
+    ) -> Vec<(OwnedUserId, &Membership)> {
+        self.active_matrix_rtc_memberships(now)
             .into_iter()
             .filter(|(_user_id, m)| m.is_room_call())
             .collect()
@@ -1126,7 +1665,13 @@ impl RoomInfo {
     /// Is there a non expired membership with application "m.call" and scope
     /// "m.room" in this room.
     pub fn has_active_room_call(&self) -> bool {
-        !self.active_room_call_memberships().is_empty()
+        self.has_active_room_call_at(MilliSecondsSinceUnixEpoch::now())
+    }
+
+    /// Same as [`has_active_room_call`](Self::has_active_room_call), but checks
+    /// the expiry of the memberships against the given time.
+    pub(crate) fn has_active_room_call_at(&self, now: MilliSecondsSinceUnixEpoch) -> bool {
+        !self.active_room_call_memberships(now).is_empty()
     }
 
     /// Returns a Vec of userId's that participate in the room call.
@@ -1138,12 +1683,39 @@ impl RoomInfo {
     ///
     /// The vector is ordered by oldest membership user to newest.
     pub fn active_room_call_participants(&self) -> Vec<OwnedUserId> {
-        self.active_room_call_memberships().iter().map(|(user_id, _)| user_id.clone()).collect()
+        self.active_room_call_participants_at(MilliSecondsSinceUnixEpoch::now())
+    }
+
+    /// Same as
+    /// [`active_room_call_participants`](Self::active_room_call_participants),
+    /// but checks the expiry of the memberships against the given time.
+    pub(crate) fn active_room_call_participants_at(
+        &self,
+        now: MilliSecondsSinceUnixEpoch,
+    ) -> Vec<OwnedUserId> {
+        self.active_room_call_memberships(now).iter().map(|(user_id, _)| user_id.clone()).collect()
     }
 }
 
-#[cfg(feature = "experimental-sliding-sync")]
-fn apply_redaction(
+/// Whether the given matrixRTC membership has expired at `now`.
+///
+/// The `created_ts` of memberships we store is always set, see
+/// `BaseRoomInfo::handle_state_event`, a membership without one is considered
+/// active.
+fn is_membership_expired(membership: &Membership, now: MilliSecondsSinceUnixEpoch) -> bool {
+    let Some(created_ts) = membership.created_ts else {
+        return false;
+    };
+
+    let expires = u64::try_from(membership.expires.as_millis()).unwrap_or(u64::MAX);
+    u64::from(created_ts.0).saturating_add(expires) < u64::from(now.0)
+}
+
+/// Get the redacted form of `event`, as the homeserver would serve it after
+/// `raw_redaction`, with the redaction in its `unsigned.redacted_because`.
+///
+/// Returns `None` if the event or the redaction isn't valid canonical JSON.
+pub fn apply_redaction(
     event: &Raw<AnySyncTimelineEvent>,
     raw_redaction: &Raw<SyncRoomRedactionEvent>,
     room_version: &RoomVersionId,
@@ -1153,7 +1725,7 @@ fn apply_redaction(
     let mut event_json = match event.deserialize_as() {
         Ok(json) => json,
         Err(e) => {
-            warn!("Failed to deserialize latest event: {e}");
+            warn!("Failed to deserialize the redacted event: {e}");
             return None;
         }
     };
@@ -1169,7 +1741,7 @@ fn apply_redaction(
     let redact_result = redact_in_place(&mut event_json, room_version, Some(redacted_because));
 
     if let Err(e) = redact_result {
-        warn!("Failed to redact latest event: {e}");
+        warn!("Failed to redact the event: {e}");
         return None;
     }
 
@@ -1237,8 +1809,11 @@ mod tests {
     };
 
     use assign::assign;
+    use matrix_sdk_common::clock::{system_clock, TestClock};
     #[cfg(feature = "experimental-sliding-sync")]
-    use matrix_sdk_common::deserialized_responses::SyncTimelineEvent;
+    use matrix_sdk_common::deserialized_responses::{
+        AlgorithmInfo, EncryptionInfo, SyncTimelineEvent, VerificationState,
+    };
     use matrix_sdk_test::{async_test, ALICE, BOB, CAROL};
     use ruma::{
         api::client::sync::sync_events::v3::RoomSummary as RumaSummary,
@@ -1248,29 +1823,33 @@ mod tests {
                 Membership, MembershipInit, OriginalSyncCallMemberEvent,
             },
             room::{
+                avatar,
                 canonical_alias::RoomCanonicalAliasEventContent,
                 member::{
                     MembershipState, RoomMemberEventContent, StrippedRoomMemberEvent,
                     SyncRoomMemberEvent,
                 },
                 name::RoomNameEventContent,
+                topic::RoomTopicEventContent,
             },
             AnySyncStateEvent, StateEventType, StateUnsigned, SyncStateEvent,
         },
-        room_alias_id, room_id,
+        owned_event_id, owned_mxc_uri, room_alias_id, room_id,
         serde::Raw,
         user_id, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedUserId, UserId,
     };
     use serde_json::json;
+    use tokio::sync::broadcast;
 
     #[cfg(feature = "experimental-sliding-sync")]
     use super::SyncInfo;
-    use super::{Room, RoomInfo, RoomState};
+    use super::{Room, RoomInfo, RoomInfoChangeReasons, RoomProfileChange, RoomState};
     #[cfg(any(feature = "experimental-sliding-sync", feature = "e2e-encryption"))]
     use crate::latest_event::LatestEvent;
     use crate::{
         store::{MemoryStore, StateChanges, StateStore},
-        DisplayName, MinimalStateEvent, OriginalMinimalStateEvent,
+        sync::UnreadNotificationsCount,
+        BaseClient, DisplayName, MinimalStateEvent, OriginalMinimalStateEvent, SessionMeta,
     };
 
     #[test]
@@ -1289,6 +1868,7 @@ mod tests {
                 highlight_count: 1,
                 notification_count: 2,
             },
+            thread_notification_counts: Default::default(),
             summary: RoomSummary {
                 heroes: vec!["Somebody".to_owned()],
                 joined_member_count: 5,
@@ -1303,6 +1883,9 @@ mod tests {
             ))),
             base_info: Box::new(BaseRoomInfo::new()),
             read_receipts: Default::default(),
+            is_spam_invite: false,
+            is_marked_unread: false,
+            privacy_overrides: Default::default(),
         };
 
         let info_json = json!({
@@ -1427,7 +2010,16 @@ mod tests {
         let user_id = user_id!("@me:example.org");
         let room_id = room_id!("!test:localhost");
 
-        (store.clone(), Room::new(user_id, store, room_id, room_type))
+        let room = Room::new(
+            user_id,
+            store.clone(),
+            system_clock(),
+            broadcast::channel(1).0,
+            room_id,
+            room_type,
+        );
+
+        (store, room)
     }
 
     fn make_stripped_member_event(user_id: &UserId, name: &str) -> Raw<StrippedRoomMemberEvent> {
@@ -1537,6 +2129,147 @@ mod tests {
         })
     }
 
+    #[test]
+    fn test_room_profile_change_revert() {
+        let mut info = RoomInfo::new(room_id!("!test:localhost"), RoomState::Joined);
+        info.base_info.name = Some(MinimalStateEvent::Original(OriginalMinimalStateEvent {
+            content: RoomNameEventContent::new("Old name".to_owned()),
+            event_id: Some(owned_event_id!("$name")),
+        }));
+
+        let revert = info.apply_profile_change(RoomProfileChange::Name("New name".to_owned()));
+        assert_eq!(info.name(), Some("New name"));
+
+        assert!(info.revert_profile_change(revert));
+        assert_eq!(info.name(), Some("Old name"));
+
+        // A local change that was overwritten by a state event from the
+        // homeserver isn't reverted.
+        let revert = info.apply_profile_change(RoomProfileChange::Topic("New topic".to_owned()));
+        assert_eq!(info.topic(), Some("New topic"));
+
+        info.base_info.topic = Some(MinimalStateEvent::Original(OriginalMinimalStateEvent {
+            content: RoomTopicEventContent::new("Remote topic".to_owned()),
+            event_id: Some(owned_event_id!("$topic")),
+        }));
+
+        assert!(!info.revert_profile_change(revert));
+        assert_eq!(info.topic(), Some("Remote topic"));
+    }
+
+    #[test]
+    fn test_room_profile_change_confirm() {
+        let mut info = RoomInfo::new(room_id!("!test:localhost"), RoomState::Joined);
+
+        let mut image_info = avatar::ImageInfo::new();
+        image_info.mimetype = Some("image/png".to_owned());
+        let url = owned_mxc_uri!("mxc://localhost/avatar");
+
+        let pending = info.apply_profile_change(RoomProfileChange::Avatar {
+            url: Some(url.clone()),
+            info: Some(Box::new(image_info)),
+        });
+
+        // The metadata of the avatar is kept with the local change.
+        let avatar = info.base_info.avatar.as_ref().unwrap().as_original().unwrap();
+        assert_eq!(avatar.content.url.as_ref(), Some(&url));
+        assert_eq!(avatar.content.info.as_ref().unwrap().mimetype.as_deref(), Some("image/png"));
+        assert_eq!(avatar.event_id, None);
+
+        // Once confirmed, the change gets the ID of the state event and can't be
+        // reverted anymore.
+        assert!(info.confirm_profile_change(&pending, owned_event_id!("$avatar")));
+        let avatar = info.base_info.avatar.as_ref().unwrap().as_original().unwrap();
+        assert_eq!(avatar.event_id, Some(owned_event_id!("$avatar")));
+
+        assert!(!info.confirm_profile_change(&pending, owned_event_id!("$other")));
+        assert!(!info.revert_profile_change(pending));
+        assert_eq!(info.avatar_url(), Some(&*url));
+    }
+
+    #[async_test]
+    async fn test_room_info_updates_have_change_reasons() {
+        let client = BaseClient::new();
+        client
+            .set_session_meta(SessionMeta {
+                user_id: user_id!("@alice:example.org").into(),
+                device_id: ruma::device_id!("AYEAYEAYE").into(),
+            })
+            .await
+            .unwrap();
+
+        let room_id = room_id!("!test:localhost");
+        let room = client.get_or_create_room(room_id, RoomState::Joined);
+        let mut updates = client.subscribe_to_room_info_updates();
+
+        let mut room_info = room.clone_info();
+        room_info.update_name("new name".to_owned());
+        room.set_room_info(room_info);
+
+        let update = updates.recv().await.unwrap();
+        assert_eq!(update.room_id, room_id);
+        assert_eq!(update.reasons, RoomInfoChangeReasons::NAME);
+
+        let mut room_info = room.clone_info();
+        room_info.update_notification_count(UnreadNotificationsCount {
+            highlight_count: 1,
+            notification_count: 2,
+        });
+        room_info.update_summary(&assign!(RumaSummary::new(), {
+            joined_member_count: Some(3u32.into()),
+        }));
+        room.set_room_info(room_info);
+
+        let update = updates.recv().await.unwrap();
+        assert_eq!(
+            update.reasons,
+            RoomInfoChangeReasons::UNREAD_COUNTS | RoomInfoChangeReasons::MEMBERS
+        );
+
+        // Setting the same room info again is notified, without any reason.
+        room.set_room_info(room.clone_info());
+        let update = updates.recv().await.unwrap();
+        assert!(update.reasons.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "experimental-sliding-sync")]
+    fn test_latest_event_change_reasons() {
+        let (_store, room) = make_room(RoomState::Joined);
+        let mut room_info = room.clone_info();
+
+        let previous = room_info.clone();
+        room_info.latest_event = Some(make_latest_event("$A"));
+        assert_eq!(room_info.changes_since(&previous), RoomInfoChangeReasons::LATEST_EVENT);
+
+        // The same event again isn't a change.
+        let previous = room_info.clone();
+        room_info.latest_event = Some(make_latest_event("$A"));
+        assert!(room_info.changes_since(&previous).is_empty());
+
+        // Its decrypted version is, even if it has the same ID.
+        let mut decrypted = SyncTimelineEvent::new(
+            Raw::from_json_string(json!({ "event_id": "$A" }).to_string()).unwrap(),
+        );
+        decrypted.encryption_info = Some(EncryptionInfo {
+            sender: ALICE.to_owned(),
+            sender_device: None,
+            algorithm_info: AlgorithmInfo::MegolmV1AesSha2 {
+                curve25519_key: "curve25519_key".to_owned(),
+                sender_claimed_keys: Default::default(),
+            },
+            verification_state: VerificationState::Verified,
+        });
+        let previous = room_info.clone();
+        room_info.latest_event = Some(Box::new(LatestEvent::new(decrypted)));
+        assert_eq!(room_info.changes_since(&previous), RoomInfoChangeReasons::LATEST_EVENT);
+
+        // And so is another event.
+        let previous = room_info.clone();
+        room_info.latest_event = Some(make_latest_event("$B"));
+        assert_eq!(room_info.changes_since(&previous), RoomInfoChangeReasons::LATEST_EVENT);
+    }
+
     #[async_test]
     async fn test_display_name_dm_invited() {
         let (store, room) = make_room(RoomState::Invited);
@@ -1667,6 +2400,90 @@ mod tests {
         assert_eq!(room.display_name().await.unwrap(), DisplayName::EmptyWas("Matthew".to_owned()));
     }
 
+    #[async_test]
+    async fn test_member_membership_change_details() {
+        let (store, room) = make_room(RoomState::Joined);
+        let room_id = room_id!("!test:localhost");
+        let matthew = user_id!("@matthew:example.org");
+        let me = user_id!("@me:example.org");
+        let mut changes = StateChanges::new("".to_owned());
+
+        let ban_event: Raw<SyncRoomMemberEvent> = Raw::new(&json!({
+            "type": "m.room.member",
+            "content": {
+                "membership": "ban",
+                "reason": "Spamming",
+            },
+            "sender": me,
+            "state_key": matthew,
+            "event_id": "$h29iv0s2:example.com",
+            "origin_server_ts": 209,
+        }))
+        .unwrap()
+        .cast();
+
+        let members = changes
+            .state
+            .entry(room_id.to_owned())
+            .or_default()
+            .entry(StateEventType::RoomMember)
+            .or_default();
+        members.insert(matthew.into(), ban_event.cast());
+        members.insert(me.into(), make_member_event(me, "Me").cast());
+
+        store.save_changes(&changes).await.unwrap();
+
+        let banned = room.get_member(matthew).await.unwrap().unwrap();
+        assert_eq!(banned.reason(), Some("Spamming"));
+        assert_eq!(banned.banned_by(), Some(me));
+        assert_eq!(banned.invited_by(), None);
+        assert_eq!(banned.kicked_by(), None);
+        assert_eq!(banned.third_party_invite_display_name(), None);
+
+        let own_member = room.get_member(me).await.unwrap().unwrap();
+        assert_eq!(own_member.reason(), None);
+        assert_eq!(own_member.banned_by(), None);
+
+        // A leave sent by somebody else is only a kick if the member had joined
+        // the room before.
+        let leave_event = |prev_membership: &str| -> Raw<SyncRoomMemberEvent> {
+            Raw::new(&json!({
+                "type": "m.room.member",
+                "content": {
+                    "membership": "leave",
+                },
+                "sender": me,
+                "state_key": matthew,
+                "event_id": "$h29iv0s3:example.com",
+                "origin_server_ts": 210,
+                "unsigned": {
+                    "prev_content": {
+                        "membership": prev_membership,
+                    },
+                },
+            }))
+            .unwrap()
+            .cast()
+        };
+
+        for (prev_membership, kicked_by) in [("join", Some(me)), ("ban", None), ("invite", None)] {
+            let mut changes = StateChanges::new("".to_owned());
+            changes
+                .state
+                .entry(room_id.to_owned())
+                .or_default()
+                .entry(StateEventType::RoomMember)
+                .or_default()
+                .insert(matthew.into(), leave_event(prev_membership).cast());
+            store.save_changes(&changes).await.unwrap();
+
+            let member = room.get_member(matthew).await.unwrap().unwrap();
+            assert_eq!(member.kicked_by(), kicked_by, "previous membership: {prev_membership}");
+            assert_eq!(member.banned_by(), None);
+            assert_eq!(member.invited_by(), None);
+        }
+    }
+
     #[test]
     fn setting_the_name_on_room_info_creates_a_fake_event() {
         // Given a room
@@ -1880,7 +2697,11 @@ mod tests {
     /// `user_c`: two memberships (two devices)
     fn create_call_with_member_events_for_user(a: &UserId, b: &UserId, c: &UserId) -> Room {
         let (_, room) = make_room(RoomState::Joined);
+        receive_call_member_events_for_user(&room, a, b, c);
+        room
+    }
 
+    fn receive_call_member_events_for_user(room: &Room, a: &UserId, b: &UserId, c: &UserId) {
         let a_empty = call_member_state_event(Vec::new(), "$1234", a);
 
         // make b 10min old
@@ -1894,9 +2715,7 @@ mod tests {
         let c_two = call_member_state_event(vec![m_init_c1, m_init_c2], "$123456", c);
 
         // Intentionally use a non time sorted receive order.
-        receive_state_events(&room, vec![&c_two, &a_empty, &b_one]);
-
-        room
+        receive_state_events(room, vec![&c_two, &a_empty, &b_one]);
     }
 
     #[test]
@@ -1926,4 +2745,28 @@ mod tests {
         assert_eq!(Vec::<OwnedUserId>::new(), room.active_room_call_participants());
         assert!(!room.has_active_room_call());
     }
+
+    #[test]
+    fn active_call_expires_with_the_clock() {
+        let clock = TestClock::new();
+        let room = Room::new(
+            user_id!("@me:example.org"),
+            Arc::new(MemoryStore::new()),
+            Arc::new(clock.clone()),
+            broadcast::channel(1).0,
+            room_id!("!test:localhost"),
+            RoomState::Joined,
+        );
+        receive_call_member_events_for_user(&room, &ALICE, &BOB, &CAROL);
+        assert!(room.has_active_room_call());
+
+        // Memberships expire one hour after their creation. CAROL's memberships
+        // were created 10 and 20 minutes ago, BOB's 1 minute ago.
+        clock.advance(Duration::from_secs(45 * 60));
+        assert_eq!(vec![CAROL.to_owned(), BOB.to_owned()], room.active_room_call_participants());
+
+        clock.advance(Duration::from_secs(15 * 60));
+        assert_eq!(Vec::<OwnedUserId>::new(), room.active_room_call_participants());
+        assert!(!room.has_active_room_call());
+    }
 }
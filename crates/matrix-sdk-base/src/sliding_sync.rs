@@ -16,7 +16,7 @@ use std::collections::BTreeMap;
 #[cfg(feature = "e2e-encryption")]
 use std::ops::Deref;
 
-use matrix_sdk_common::deserialized_responses::SyncTimelineEvent;
+use matrix_sdk_common::{deserialized_responses::SyncTimelineEvent, instant::Instant};
 #[cfg(feature = "e2e-encryption")]
 use ruma::events::AnyToDeviceEvent;
 use ruma::{
@@ -43,8 +43,10 @@ use crate::{
     error::Result,
     read_receipts::{compute_notifications, PreviousEventsProvider},
     rooms::RoomState,
+    state_validation::StateValidator,
     store::{ambiguity_map::AmbiguityCache, StateChanges, Store},
     sync::{JoinedRoom, LeftRoom, Rooms, SyncResponse},
+    sync_metrics::{RoomSyncMetrics, SyncMetricsRecorder},
     Room, RoomInfo,
 };
 
@@ -143,6 +145,7 @@ impl BaseClient {
             return Ok(SyncResponse::default());
         };
 
+        let mut metrics_recorder = SyncMetricsRecorder::new(None);
         let mut changes = StateChanges::default();
 
         let store = self.store.clone();
@@ -155,8 +158,34 @@ impl BaseClient {
 
         let mut new_rooms = Rooms::default();
         let mut notifications = Default::default();
+        let mut state_validator =
+            self.state_validation.then(|| StateValidator::new(response.pos.clone()));
 
         for (room_id, response_room_data) in rooms {
+            let room_start = Instant::now();
+            let mut room_metrics = RoomSyncMetrics::new(room_id.clone());
+
+            // The state of the room is deduced from the membership events in
+            // sliding sync, so only the state keys and the invites are checked.
+            if let Some(validator) = &mut state_validator {
+                validator.check_state(room_id, &response_room_data.required_state);
+
+                if let Some(invite_state) = &response_room_data.invite_state {
+                    validator.check_state(room_id, invite_state);
+                    // The invite state can be minimal, without the content of
+                    // the membership event, so a missing membership event isn't
+                    // reported.
+                    if let Some(session_meta) = self.session_meta() {
+                        validator.check_invite_membership(
+                            room_id,
+                            &session_meta.user_id,
+                            None,
+                            invite_state,
+                        );
+                    }
+                }
+            }
+
             let (room_info, joined_room, left_room, invited_room) = self
                 .process_sliding_sync_room(
                     room_id,
@@ -166,10 +195,12 @@ impl BaseClient {
                     &mut changes,
                     &mut notifications,
                     &mut ambiguity_cache,
+                    &mut room_metrics,
                 )
                 .await?;
 
             changes.add_room(room_info);
+            metrics_recorder.add_room(room_metrics, room_start);
 
             if let Some(joined_room) = joined_room {
                 new_rooms.join.insert(room_id.clone(), joined_room);
@@ -184,6 +215,45 @@ impl BaseClient {
             }
         }
 
+        // Room account data can change for rooms that have no other update in
+        // this response, for example when another client marks a room as
+        // unread, so it is handled independently of the rooms too.
+        for (room_id, events) in &account_data.rooms {
+            if rooms.contains_key(room_id) {
+                continue;
+            }
+
+            let Some(mut room_info) = changes
+                .room_infos
+                .get(room_id)
+                .cloned()
+                .or_else(|| self.get_room(room_id).map(|r| r.clone_info()))
+            else {
+                trace!(?room_id, "Skipping the account data of an unknown room");
+                continue;
+            };
+
+            self.handle_room_account_data(room_id, events, &mut room_info, &mut changes).await;
+
+            match room_info.state() {
+                RoomState::Joined => new_rooms
+                    .join
+                    .entry(room_id.to_owned())
+                    .or_insert_with(JoinedRoom::default)
+                    .account_data
+                    .extend(events.iter().cloned()),
+                RoomState::Left => {
+                    new_rooms.leave.insert(
+                        room_id.to_owned(),
+                        LeftRoom::new(Default::default(), Vec::new(), events.to_vec()),
+                    );
+                }
+                RoomState::Invited => {}
+            }
+
+            changes.add_room(room_info);
+        }
+
         // Handle read receipts and typing notifications independently of the rooms:
         // these both live in a different subsection of the server's response,
         // so they may exist without any update for the associated room.
@@ -267,11 +337,18 @@ impl BaseClient {
         changes.ambiguity_maps = ambiguity_cache.cache;
 
         trace!("ready to submit changes to store");
+        let store_write_start = Instant::now();
         store.save_changes(&changes).await?;
+        metrics_recorder.set_store_write(store_write_start);
         self.apply_changes(&changes);
         trace!("applied changes");
+        metrics_recorder.report(self.sync_metrics_hook.as_deref());
+
+        if let Some(validator) = state_validator {
+            validator.report(&self.state_divergence_sender);
+        }
 
-        Ok(SyncResponse {
+        let mut response = SyncResponse {
             rooms: new_rooms,
             ambiguity_changes: AmbiguityChanges { changes: ambiguity_cache.changes },
             notifications,
@@ -279,7 +356,11 @@ impl BaseClient {
             presence: Default::default(),
             account_data: account_data.global.clone(),
             to_device: Default::default(),
-        })
+        };
+
+        self.run_sync_response_post_processors(&mut response).await;
+
+        Ok(response)
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -292,6 +373,7 @@ impl BaseClient {
         changes: &mut StateChanges,
         notifications: &mut BTreeMap<OwnedRoomId, Vec<Notification>>,
         ambiguity_cache: &mut AmbiguityCache,
+        metrics: &mut RoomSyncMetrics,
     ) -> Result<(RoomInfo, Option<JoinedRoom>, Option<LeftRoom>, Option<InvitedRoom>)> {
         let mut state_events = Self::deserialize_state_events(&room_data.required_state);
         state_events.extend(Self::deserialize_state_events_from_timeline(&room_data.timeline));
@@ -310,6 +392,7 @@ impl BaseClient {
 
         room_info.mark_state_partially_synced();
 
+        let state_start = Instant::now();
         let mut user_ids = if !state_events.is_empty() {
             self.handle_state(
                 &raw_state_events,
@@ -322,9 +405,10 @@ impl BaseClient {
         } else {
             Default::default()
         };
+        metrics.state = state_start.elapsed();
 
         let room_account_data = if let Some(events) = account_data.rooms.get(room_id) {
-            self.handle_room_account_data(room_id, events, changes).await;
+            self.handle_room_account_data(room_id, events, &mut room_info, changes).await;
             Some(events.to_vec())
         } else {
             None
@@ -346,6 +430,7 @@ impl BaseClient {
                 changes,
                 notifications,
                 ambiguity_cache,
+                metrics,
             )
             .await?;
 
@@ -668,7 +753,9 @@ mod tests {
     };
 
     use assert_matches::assert_matches;
-    use matrix_sdk_common::{deserialized_responses::SyncTimelineEvent, ring_buffer::RingBuffer};
+    use matrix_sdk_common::{
+        clock::system_clock, deserialized_responses::SyncTimelineEvent, ring_buffer::RingBuffer,
+    };
     use matrix_sdk_test::async_test;
     use ruma::{
         api::client::sync::sync_events::{v4, UnreadNotificationsCount},
@@ -689,9 +776,13 @@ mod tests {
         uint, user_id, MxcUri, OwnedRoomId, OwnedUserId, RoomAliasId, RoomId, UserId,
     };
     use serde_json::json;
+    use tokio::sync::broadcast;
 
     use super::cache_latest_events;
-    use crate::{store::MemoryStore, BaseClient, Room, RoomState, SessionMeta};
+    use crate::{
+        state_validation::StateDivergenceKind, store::MemoryStore, BaseClient, Room, RoomState,
+        SessionMeta,
+    };
 
     #[async_test]
     async fn test_notification_count_set() {
@@ -756,6 +847,35 @@ mod tests {
         assert!(sync_resp.rooms.invite.get(room_id).is_none());
     }
 
+    #[async_test]
+    async fn room_account_data_is_processed_for_rooms_without_updates() {
+        // Given a logged-in client with a room
+        let client = logged_in_client().await;
+        let room_id = room_id!("!r:e.uk");
+        let response = response_with_room(room_id, v4::SlidingSyncRoom::new()).await;
+        client.process_sliding_sync(&response, &()).await.expect("Failed to process sync");
+        assert!(!client.get_room(room_id).unwrap().is_marked_unread());
+
+        // When another client marks the room as unread, without any other update
+        // for the room
+        let mut response = v4::Response::new("6".to_owned());
+        response.extensions.account_data.rooms.insert(
+            room_id.to_owned(),
+            vec![Raw::new(&json!({
+                "type": "m.marked_unread",
+                "content": { "unread": true },
+            }))
+            .unwrap()
+            .cast()],
+        );
+        let sync_resp =
+            client.process_sliding_sync(&response, &()).await.expect("Failed to process sync");
+
+        // Then the room is marked as unread
+        assert!(client.get_room(room_id).unwrap().is_marked_unread());
+        assert_eq!(sync_resp.rooms.join.get(room_id).unwrap().account_data.len(), 1);
+    }
+
     #[async_test]
     async fn room_name_is_found_when_processing_sliding_sync_response() {
         // Given a logged-in client
@@ -780,6 +900,57 @@ mod tests {
         assert!(sync_resp.rooms.invite.get(room_id).is_none());
     }
 
+    #[async_test]
+    async fn test_state_validation_of_sliding_sync_rooms() {
+        let client = BaseClient::new().with_state_validation(true);
+        client
+            .set_session_meta(SessionMeta {
+                user_id: user_id!("@u:e.uk").to_owned(),
+                device_id: device_id!("XYZ").to_owned(),
+            })
+            .await
+            .expect("Failed to set session meta");
+        let mut divergences = client.subscribe_to_state_divergences();
+
+        let user_id = user_id!("@u:e.uk");
+        let joined_room_id = room_id!("!joined:e.uk");
+        let invited_room_id = room_id!("!invited:e.uk");
+
+        // A joined room with the same state key twice in its state.
+        let mut joined_room = room_with_avatar(mxc_uri!("mxc://e.uk/first"), user_id);
+        joined_room
+            .required_state
+            .extend(room_with_avatar(mxc_uri!("mxc://e.uk/second"), user_id).required_state);
+
+        // An invite whose membership event isn't an invite.
+        let mut invited_room = v4::SlidingSyncRoom::new();
+        invited_room.invite_state = Some(vec![Raw::new(&json!({
+            "type": "m.room.member",
+            "state_key": user_id,
+            "sender": "@o:e.uk",
+            "content": { "membership": "join" },
+        }))
+        .unwrap()
+        .cast()]);
+
+        let mut response = v4::Response::new("5".to_owned());
+        response.rooms.insert(joined_room_id.to_owned(), joined_room);
+        response.rooms.insert(invited_room_id.to_owned(), invited_room);
+        client.process_sliding_sync(&response, &()).await.expect("Failed to process sync");
+
+        let mut divergences = [divergences.try_recv().unwrap(), divergences.try_recv().unwrap()];
+        assert!(divergences.iter().all(|divergence| divergence.sync_token == "5"));
+        divergences.sort_by(|a, b| a.room_id.cmp(&b.room_id));
+
+        assert_eq!(divergences[0].room_id, invited_room_id);
+        assert_matches!(
+            &divergences[0].kind,
+            StateDivergenceKind::MembershipMismatch { membership: MembershipState::Join, .. }
+        );
+        assert_eq!(divergences[1].room_id, joined_room_id);
+        assert_matches!(&divergences[1].kind, StateDivergenceKind::DuplicateStateKey { .. });
+    }
+
     #[async_test]
     async fn invited_room_name_is_found_when_processing_sliding_sync_response() {
         // Given a logged-in client
@@ -1466,6 +1637,8 @@ mod tests {
         Room::new(
             user_id!("@u:e.co"),
             Arc::new(MemoryStore::new()),
+            system_clock(),
+            broadcast::channel(1).0,
             room_id!("!r:e.co"),
             RoomState::Joined,
         )
@@ -0,0 +1,471 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sanity checks of the room state received in sync responses.
+//!
+//! The homeserver is supposed to resolve the state of the rooms before sending
+//! it, so some situations can't happen with a correct homeserver, like the
+//! same state key appearing twice in the state of a room, or the user leaving
+//! a room without a membership event telling so. When the checks are enabled
+//! with [`BaseClient::with_state_validation()`], these situations are reported
+//! as [`StateDivergence`]s, with enough context to report the problem to the
+//! homeserver developers.
+//!
+//! [`BaseClient::with_state_validation()`]: crate::BaseClient::with_state_validation
+
+use std::collections::BTreeMap;
+
+use ruma::{
+    events::{
+        room::member::MembershipState, AnyStrippedStateEvent, AnySyncStateEvent,
+        AnySyncTimelineEvent, StateEventType,
+    },
+    serde::Raw,
+    OwnedEventId, OwnedRoomId, RoomId, UserId,
+};
+use serde::Deserialize;
+use tokio::sync::broadcast;
+use tracing::warn;
+
+use crate::RoomState;
+
+/// An inconsistency found in the room state of a sync response.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StateDivergence {
+    /// The room with the inconsistent state.
+    pub room_id: OwnedRoomId,
+
+    /// The `next_batch` token of the sync response with the inconsistent
+    /// state.
+    pub sync_token: String,
+
+    /// What is inconsistent.
+    pub kind: StateDivergenceKind,
+}
+
+/// The kind of a [`StateDivergence`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum StateDivergenceKind {
+    /// The same state key appears several times in the `state` of the room,
+    /// which should be resolved by the homeserver.
+    ///
+    /// The last event wins when the state is stored.
+    DuplicateStateKey {
+        /// The type of the state events.
+        event_type: StateEventType,
+        /// The state key of the state events.
+        state_key: String,
+        /// The IDs of the state events with this type and state key, in the
+        /// order of the response.
+        event_ids: Vec<OwnedEventId>,
+    },
+
+    /// The membership of the user in the room changed, but the response
+    /// doesn't contain a membership event of the user.
+    MembershipChangeWithoutEvent {
+        /// The state of the room before the response.
+        previous: RoomState,
+        /// The state of the room according to the section of the response it
+        /// is in.
+        current: RoomState,
+    },
+
+    /// The latest membership event of the user in the response contradicts
+    /// the section of the response the room is in, like a `leave` membership
+    /// for a joined room.
+    MembershipMismatch {
+        /// The state of the room according to the section of the response it
+        /// is in.
+        room_state: RoomState,
+        /// The membership of the latest membership event of the user.
+        membership: MembershipState,
+        /// The ID of the latest membership event of the user, if it has one.
+        ///
+        /// The stripped state events of invited rooms don't have an ID.
+        event_id: Option<OwnedEventId>,
+    },
+}
+
+/// The fields of a state event needed by the checks, that can be read even if
+/// the event doesn't deserialize as a known event.
+#[derive(Deserialize)]
+struct StateEventFields {
+    #[serde(rename = "type")]
+    event_type: StateEventType,
+    state_key: String,
+    event_id: Option<OwnedEventId>,
+}
+
+/// Collects the [`StateDivergence`]s of a sync response, to report them once
+/// the response was processed.
+pub(crate) struct StateValidator {
+    sync_token: String,
+    divergences: Vec<StateDivergence>,
+}
+
+impl StateValidator {
+    pub(crate) fn new(sync_token: String) -> Self {
+        Self { sync_token, divergences: Vec::new() }
+    }
+
+    /// Check that the `state` of a room, or the stripped state of an invite,
+    /// doesn't contain the same state key twice.
+    pub(crate) fn check_state<T>(&mut self, room_id: &RoomId, raw_events: &[Raw<T>]) {
+        let mut event_ids = BTreeMap::<(StateEventType, String), Vec<Option<OwnedEventId>>>::new();
+
+        for raw_event in raw_events {
+            let Ok(fields) = raw_event.deserialize_as::<StateEventFields>() else {
+                // Already reported when deserializing the state events.
+                continue;
+            };
+
+            event_ids
+                .entry((fields.event_type, fields.state_key))
+                .or_default()
+                .push(fields.event_id);
+        }
+
+        for ((event_type, state_key), event_ids) in event_ids {
+            if event_ids.len() > 1 {
+                self.push(
+                    room_id,
+                    StateDivergenceKind::DuplicateStateKey {
+                        event_type,
+                        state_key,
+                        event_ids: event_ids.into_iter().flatten().collect(),
+                    },
+                );
+            }
+        }
+    }
+
+    /// Check that the membership events of the user in the `state` and the
+    /// `timeline` of a room are consistent with the state of the room before
+    /// and after the response.
+    ///
+    /// `previous` is `None` if the room wasn't known before the response.
+    pub(crate) fn check_membership(
+        &mut self,
+        room_id: &RoomId,
+        own_user_id: &UserId,
+        previous: Option<RoomState>,
+        current: RoomState,
+        state_events: &[AnySyncStateEvent],
+        timeline_events: &[Raw<AnySyncTimelineEvent>],
+    ) {
+        let own_member_events = state_events
+            .iter()
+            .cloned()
+            .chain(timeline_events.iter().filter_map(|raw| match raw.deserialize() {
+                Ok(AnySyncTimelineEvent::State(event)) => Some(event),
+                _ => None,
+            }))
+            .filter_map(|event| match event {
+                AnySyncStateEvent::RoomMember(member) if member.state_key() == own_user_id => {
+                    Some(member)
+                }
+                _ => None,
+            });
+
+        let Some(latest) = own_member_events.last() else {
+            if let Some(previous) = previous.filter(|previous| *previous != current) {
+                self.push(
+                    room_id,
+                    StateDivergenceKind::MembershipChangeWithoutEvent { previous, current },
+                );
+            }
+            return;
+        };
+
+        let membership = latest.membership().clone();
+
+        if RoomState::from(&membership) != current {
+            self.push(
+                room_id,
+                StateDivergenceKind::MembershipMismatch {
+                    room_state: current,
+                    membership,
+                    event_id: Some(latest.event_id().to_owned()),
+                },
+            );
+        }
+    }
+
+    /// Check that the latest membership event of the user in the stripped
+    /// state of an invite is an invite.
+    ///
+    /// `previous` is `None` if the room wasn't known before the response, or
+    /// if the response isn't expected to contain the membership event.
+    pub(crate) fn check_invite_membership(
+        &mut self,
+        room_id: &RoomId,
+        own_user_id: &UserId,
+        previous: Option<RoomState>,
+        invite_state: &[Raw<AnyStrippedStateEvent>],
+    ) {
+        let latest = invite_state.iter().rev().find_map(|raw| match raw.deserialize() {
+            Ok(AnyStrippedStateEvent::RoomMember(member)) if member.state_key == *own_user_id => {
+                Some(member)
+            }
+            _ => None,
+        });
+
+        let Some(latest) = latest else {
+            if let Some(previous) = previous.filter(|previous| *previous != RoomState::Invited) {
+                self.push(
+                    room_id,
+                    StateDivergenceKind::MembershipChangeWithoutEvent {
+                        previous,
+                        current: RoomState::Invited,
+                    },
+                );
+            }
+            return;
+        };
+
+        if latest.content.membership != MembershipState::Invite {
+            self.push(
+                room_id,
+                StateDivergenceKind::MembershipMismatch {
+                    room_state: RoomState::Invited,
+                    membership: latest.content.membership,
+                    event_id: None,
+                },
+            );
+        }
+    }
+
+    fn push(&mut self, room_id: &RoomId, kind: StateDivergenceKind) {
+        warn!(?room_id, sync_token = self.sync_token, ?kind, "Inconsistent room state in sync");
+
+        self.divergences.push(StateDivergence {
+            room_id: room_id.to_owned(),
+            sync_token: self.sync_token.clone(),
+            kind,
+        });
+    }
+
+    /// Send the divergences that were found to the subscribers.
+    pub(crate) fn report(self, sender: &broadcast::Sender<StateDivergence>) {
+        for divergence in self.divergences {
+            // It's fine if there are no subscribers, the divergences were
+            // logged already.
+            let _ = sender.send(divergence);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma::{
+        events::{
+            room::member::MembershipState, AnyStrippedStateEvent, AnySyncStateEvent, StateEventType,
+        },
+        owned_event_id, room_id,
+        serde::Raw,
+        user_id,
+    };
+    use serde_json::{json, Value as JsonValue};
+    use tokio::sync::broadcast;
+
+    use super::{StateDivergenceKind, StateValidator};
+    use crate::RoomState;
+
+    fn state_event(json: JsonValue) -> Raw<AnySyncStateEvent> {
+        Raw::new(&json).unwrap().cast()
+    }
+
+    fn member_event(membership: &str, event_id: &str) -> Raw<AnySyncStateEvent> {
+        state_event(json!({
+            "content": { "membership": membership },
+            "event_id": event_id,
+            "origin_server_ts": 1,
+            "sender": "@alice:localhost",
+            "state_key": "@alice:localhost",
+            "type": "m.room.member",
+        }))
+    }
+
+    #[test]
+    fn test_duplicate_state_keys() {
+        let room_id = room_id!("!room:localhost");
+        let mut validator = StateValidator::new("token".to_owned());
+
+        let events = [
+            state_event(json!({
+                "content": { "name": "First" },
+                "event_id": "$first",
+                "origin_server_ts": 1,
+                "sender": "@alice:localhost",
+                "state_key": "",
+                "type": "m.room.name",
+            })),
+            state_event(json!({
+                "content": { "topic": "Topic" },
+                "event_id": "$topic",
+                "origin_server_ts": 1,
+                "sender": "@alice:localhost",
+                "state_key": "",
+                "type": "m.room.topic",
+            })),
+            state_event(json!({
+                "content": { "name": "Second" },
+                "event_id": "$second",
+                "origin_server_ts": 2,
+                "sender": "@alice:localhost",
+                "state_key": "",
+                "type": "m.room.name",
+            })),
+        ];
+        validator.check_state(room_id, &events);
+
+        let (sender, mut receiver) = broadcast::channel(10);
+        validator.report(&sender);
+
+        let divergence = receiver.try_recv().unwrap();
+        assert_eq!(divergence.room_id, room_id);
+        assert_eq!(divergence.sync_token, "token");
+        assert_eq!(
+            divergence.kind,
+            StateDivergenceKind::DuplicateStateKey {
+                event_type: StateEventType::RoomName,
+                state_key: String::new(),
+                event_ids: vec![owned_event_id!("$first"), owned_event_id!("$second")],
+            }
+        );
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_membership_checks() {
+        let room_id = room_id!("!room:localhost");
+        let user_id = user_id!("@alice:localhost");
+        let (sender, mut receiver) = broadcast::channel(10);
+
+        // Leaving a joined room with a membership event is fine.
+        let mut validator = StateValidator::new("token".to_owned());
+        let leave = member_event("leave", "$leave");
+        validator.check_membership(
+            room_id,
+            user_id,
+            Some(RoomState::Joined),
+            RoomState::Left,
+            &[leave.deserialize().unwrap()],
+            &[],
+        );
+        validator.report(&sender);
+        assert!(receiver.try_recv().is_err());
+
+        // Leaving a joined room without a membership event is reported.
+        let mut validator = StateValidator::new("token".to_owned());
+        validator.check_membership(
+            room_id,
+            user_id,
+            Some(RoomState::Joined),
+            RoomState::Left,
+            &[],
+            &[],
+        );
+        validator.report(&sender);
+        assert_eq!(
+            receiver.try_recv().unwrap().kind,
+            StateDivergenceKind::MembershipChangeWithoutEvent {
+                previous: RoomState::Joined,
+                current: RoomState::Left,
+            }
+        );
+
+        // A room that wasn't known before can come without a membership event.
+        let mut validator = StateValidator::new("token".to_owned());
+        validator.check_membership(room_id, user_id, None, RoomState::Joined, &[], &[]);
+        validator.report(&sender);
+        assert!(receiver.try_recv().is_err());
+
+        // The latest membership event must match the section of the room.
+        let mut validator = StateValidator::new("token".to_owned());
+        let join = member_event("join", "$join");
+        validator.check_membership(
+            room_id,
+            user_id,
+            Some(RoomState::Joined),
+            RoomState::Joined,
+            &[join.deserialize().unwrap()],
+            &[leave.cast()],
+        );
+        validator.report(&sender);
+        assert_eq!(
+            receiver.try_recv().unwrap().kind,
+            StateDivergenceKind::MembershipMismatch {
+                room_state: RoomState::Joined,
+                membership: MembershipState::Leave,
+                event_id: Some(owned_event_id!("$leave")),
+            }
+        );
+    }
+
+    #[test]
+    fn test_invite_membership_checks() {
+        let room_id = room_id!("!room:localhost");
+        let user_id = user_id!("@alice:localhost");
+        let (sender, mut receiver) = broadcast::channel(10);
+
+        let stripped_member = |membership: &str| -> Raw<AnyStrippedStateEvent> {
+            Raw::new(&json!({
+                "content": { "membership": membership },
+                "sender": "@bob:localhost",
+                "state_key": "@alice:localhost",
+                "type": "m.room.member",
+            }))
+            .unwrap()
+            .cast()
+        };
+
+        // An invite with the invite event is fine.
+        let mut validator = StateValidator::new("token".to_owned());
+        validator.check_invite_membership(
+            room_id,
+            user_id,
+            Some(RoomState::Left),
+            &[stripped_member("invite")],
+        );
+        validator.report(&sender);
+        assert!(receiver.try_recv().is_err());
+
+        // An invite to a room we left without the invite event is reported.
+        let mut validator = StateValidator::new("token".to_owned());
+        validator.check_invite_membership(room_id, user_id, Some(RoomState::Left), &[]);
+        validator.report(&sender);
+        assert_eq!(
+            receiver.try_recv().unwrap().kind,
+            StateDivergenceKind::MembershipChangeWithoutEvent {
+                previous: RoomState::Left,
+                current: RoomState::Invited,
+            }
+        );
+
+        // The membership event of the invite must be an invite.
+        let mut validator = StateValidator::new("token".to_owned());
+        validator.check_invite_membership(room_id, user_id, None, &[stripped_member("join")]);
+        validator.report(&sender);
+        assert_eq!(
+            receiver.try_recv().unwrap().kind,
+            StateDivergenceKind::MembershipMismatch {
+                room_state: RoomState::Invited,
+                membership: MembershipState::Join,
+                event_id: None,
+            }
+        );
+    }
+}
@@ -1,10 +1,14 @@
 //! Trait and macro of integration tests for StateStore implementations.
 
-use std::collections::{BTreeMap, BTreeSet};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::Duration,
+};
 
 use assert_matches::assert_matches;
 use assert_matches2::assert_let;
 use async_trait::async_trait;
+use matrix_sdk_common::clock::{Clock, SystemClock};
 use matrix_sdk_test::test_json;
 use ruma::{
     api::client::media::get_content_thumbnail::v3::Method,
@@ -27,13 +31,13 @@ use ruma::{
     },
     mxc_uri, room_id,
     serde::Raw,
-    uint, user_id, EventId, OwnedEventId, OwnedUserId, RoomId, UserId,
+    uint, user_id, EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedUserId, RoomId, UserId,
 };
 use serde_json::{json, value::Value as JsonValue};
 
 use super::DynStateStore;
 use crate::{
-    deserialized_responses::MemberEvent,
+    deserialized_responses::{MemberEvent, RawAnySyncOrStrippedState},
     media::{MediaFormat, MediaRequest, MediaThumbnailSize},
     store::{Result, StateStoreExt},
     RoomInfo, RoomMemberships, RoomState, StateChanges, StateStoreDataKey, StateStoreDataValue,
@@ -66,6 +70,8 @@ pub trait StateStoreIntegrationTests {
     async fn test_power_level_saving(&self);
     /// Test user receipts saving.
     async fn test_receipts_saving(&self);
+    /// Test the removal of old receipts.
+    async fn test_receipts_removal_before(&self);
     /// Test custom storage.
     async fn test_custom_storage(&self) -> Result<()>;
     /// Test invited room saving.
@@ -78,6 +84,8 @@ pub trait StateStoreIntegrationTests {
     async fn test_presence_saving(&self);
     /// Test display names saving.
     async fn test_display_names_saving(&self);
+    /// Test leased locks.
+    async fn test_leased_locks(&self);
 }
 
 #[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
@@ -366,6 +374,14 @@ impl StateStoreIntegrationTests for DynStateStore {
             1,
             "Expected to find 1 room topic"
         );
+        let all_state = self.get_all_state_events(room_id).await?;
+        assert_eq!(all_state.len(), 4, "Expected to find 4 state events");
+        assert!(all_state.iter().all(|ev| matches!(ev, RawAnySyncOrStrippedState::Sync(_))));
+        let all_stripped_state = self.get_all_state_events(stripped_room_id()).await?;
+        assert!(!all_stripped_state.is_empty(), "Expected to find stripped state events");
+        assert!(all_stripped_state
+            .iter()
+            .all(|ev| matches!(ev, RawAnySyncOrStrippedState::Stripped(_))));
         assert!(self.get_profile(room_id, user_id).await?.is_some());
         assert!(self.get_member_event(room_id, user_id).await?.is_some());
         assert_eq!(
@@ -913,6 +929,86 @@ impl StateStoreIntegrationTests for DynStateStore {
         assert_eq!(second_event_threaded_receipts[0].1.ts.unwrap().0, third_receipt_ts);
     }
 
+    async fn test_receipts_removal_before(&self) {
+        let room_id = room_id!("!test_receipts_removal_before:localhost");
+        let other_user_id = user_id!("@other:localhost");
+
+        let old_event_id = event_id!("$old:localhost");
+        let recent_event_id = event_id!("$recent:localhost");
+
+        let receipt_event = serde_json::from_value(json!({
+            old_event_id: {
+                "m.read": {
+                    user_id(): {
+                        "ts": 1_000,
+                    }
+                }
+            },
+            recent_event_id: {
+                "m.read": {
+                    other_user_id: {
+                        "ts": 3_000,
+                    }
+                }
+            }
+        }))
+        .expect("json creation failed");
+
+        let mut changes = StateChanges::default();
+        changes.add_receipts(room_id, receipt_event);
+        self.save_changes(&changes).await.expect("saving receipts failed");
+
+        self.remove_room_receipts_before(room_id, MilliSecondsSinceUnixEpoch(uint!(2_000)))
+            .await
+            .expect("removing old receipts failed");
+
+        // The receipt sent before the cutoff is gone.
+        assert!(self
+            .get_user_room_receipt_event(
+                room_id,
+                ReceiptType::Read,
+                ReceiptThread::Unthreaded,
+                user_id()
+            )
+            .await
+            .expect("failed to read the old user room receipt")
+            .is_none());
+        assert!(self
+            .get_event_room_receipt_events(
+                room_id,
+                ReceiptType::Read,
+                ReceiptThread::Unthreaded,
+                old_event_id
+            )
+            .await
+            .expect("failed to read the old event room receipts")
+            .is_empty());
+
+        // The receipt sent after the cutoff is kept.
+        let (event_id, _) = self
+            .get_user_room_receipt_event(
+                room_id,
+                ReceiptType::Read,
+                ReceiptThread::Unthreaded,
+                other_user_id,
+            )
+            .await
+            .expect("failed to read the recent user room receipt")
+            .unwrap();
+        assert_eq!(event_id, recent_event_id);
+        let recent_receipts = self
+            .get_event_room_receipt_events(
+                room_id,
+                ReceiptType::Read,
+                ReceiptThread::Unthreaded,
+                recent_event_id,
+            )
+            .await
+            .expect("failed to read the recent event room receipts");
+        assert_eq!(recent_receipts.len(), 1);
+        assert_eq!(recent_receipts[0].0, other_user_id);
+    }
+
     async fn test_custom_storage(&self) -> Result<()> {
         let key = "my_key";
         let value = &[0, 1, 2, 3];
@@ -923,6 +1019,14 @@ impl StateStoreIntegrationTests for DynStateStore {
 
         assert_eq!(Some(value.as_ref()), read.as_deref());
 
+        // Custom values can be saved along with other changes too.
+        let mut changes = StateChanges::default();
+        changes.custom_values.insert(key.as_bytes().to_vec(), vec![4, 5]);
+        self.save_changes(&changes).await?;
+
+        let read = self.get_custom_value(key.as_bytes()).await?;
+        assert_eq!(Some([4, 5].as_ref()), read.as_deref());
+
         Ok(())
     }
 
@@ -1163,6 +1267,25 @@ impl StateStoreIntegrationTests for DynStateStore {
         let names = self.get_users_with_display_names(room_id, &[]).await;
         assert!(names.unwrap().is_empty());
     }
+
+    async fn test_leased_locks(&self) {
+        let key = "sync_token_lock";
+
+        // Nobody holds the lock yet.
+        assert!(self.try_take_leased_lock(60_000, key, "alice").await.unwrap());
+        // The holder can extend the lease.
+        assert!(self.try_take_leased_lock(60_000, key, "alice").await.unwrap());
+        // Someone else can't take it while the lease is running.
+        assert!(!self.try_take_leased_lock(60_000, key, "bob").await.unwrap());
+        // Other keys are independent.
+        assert!(self.try_take_leased_lock(60_000, "other_lock", "bob").await.unwrap());
+
+        // Someone else can take it once the lease expired.
+        assert!(self.try_take_leased_lock(0, key, "alice").await.unwrap());
+        SystemClock.sleep(Duration::from_millis(10)).await;
+        assert!(self.try_take_leased_lock(60_000, key, "bob").await.unwrap());
+        assert!(!self.try_take_leased_lock(60_000, key, "alice").await.unwrap());
+    }
 }
 
 /// Macro building to allow your StateStore implementation to run the entire
@@ -1264,6 +1387,12 @@ macro_rules! statestore_integration_tests {
             store.test_receipts_saving().await;
         }
 
+        #[async_test]
+        async fn test_receipts_removal_before() {
+            let store = get_store().await.expect("creating store failed").into_state_store();
+            store.test_receipts_removal_before().await;
+        }
+
         #[async_test]
         async fn test_custom_storage() -> StoreResult<()> {
             let store = get_store().await?.into_state_store();
@@ -1299,6 +1428,12 @@ macro_rules! statestore_integration_tests {
             let store = get_store().await.expect("creating store failed").into_state_store();
             store.test_display_names_saving().await;
         }
+
+        #[async_test]
+        async fn test_leased_locks() {
+            let store = get_store().await.expect("creating store failed").into_state_store();
+            store.test_leased_locks().await;
+        }
     };
 }
 
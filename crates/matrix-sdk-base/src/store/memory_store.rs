@@ -13,8 +13,9 @@
 // limitations under the License.
 
 use std::{
-    collections::{BTreeMap, BTreeSet, HashMap},
+    collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap},
     sync::RwLock as StdRwLock,
+    time::Duration,
 };
 
 use async_trait::async_trait;
@@ -29,8 +30,8 @@ use ruma::{
         AnySyncStateEvent, GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType,
     },
     serde::Raw,
-    CanonicalJsonObject, EventId, MxcUri, OwnedEventId, OwnedMxcUri, OwnedRoomId, OwnedUserId,
-    RoomId, RoomVersionId, UserId,
+    CanonicalJsonObject, EventId, MilliSecondsSinceUnixEpoch, MxcUri, OwnedEventId, OwnedMxcUri,
+    OwnedRoomId, OwnedUserId, RoomId, RoomVersionId, UserId,
 };
 use tracing::{debug, warn};
 
@@ -80,6 +81,7 @@ pub struct MemoryStore {
     >,
     media: StdRwLock<RingBuffer<(OwnedMxcUri, String /* unique key */, Vec<u8>)>>,
     custom: StdRwLock<HashMap<Vec<u8>, Vec<u8>>>,
+    leases: StdRwLock<HashMap<String, (String, Instant)>>,
 }
 
 impl MemoryStore {
@@ -199,6 +201,10 @@ impl StateStore for MemoryStore {
             *self.sync_token.write().unwrap() = Some(s.to_owned());
         }
 
+        for (key, value) in &changes.custom_values {
+            self.custom.write().unwrap().insert(key.clone(), value.clone());
+        }
+
         for (room, users) in &changes.profiles {
             for (user_id, profile) in users {
                 self.profiles
@@ -520,6 +526,35 @@ impl StateStore for MemoryStore {
         )
     }
 
+    async fn get_all_state_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>, Self::Error> {
+        if let Some(stripped_state) = self.stripped_room_state.read().unwrap().get(room_id) {
+            if !stripped_state.is_empty() {
+                return Ok(stripped_state
+                    .values()
+                    .flat_map(|events| events.values().cloned())
+                    .map(RawAnySyncOrStrippedState::Stripped)
+                    .collect());
+            }
+        }
+
+        Ok(self
+            .room_state
+            .read()
+            .unwrap()
+            .get(room_id)
+            .map(|state| {
+                state
+                    .values()
+                    .flat_map(|events| events.values().cloned())
+                    .map(RawAnySyncOrStrippedState::Sync)
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
     async fn get_profile(
         &self,
         room_id: &RoomId,
@@ -768,6 +803,59 @@ impl StateStore for MemoryStore {
 
         Ok(())
     }
+
+    async fn remove_room_receipts_before(
+        &self,
+        room_id: &RoomId,
+        before: MilliSecondsSinceUnixEpoch,
+    ) -> Result<()> {
+        let is_expired = |receipt: &Receipt| receipt.ts.is_some_and(|ts| ts < before);
+
+        if let Some(receipts) = self.room_user_receipts.write().unwrap().get_mut(room_id) {
+            for user_receipts in receipts.values_mut() {
+                user_receipts.retain(|_, (_, receipt)| !is_expired(receipt));
+            }
+        }
+
+        if let Some(receipts) = self.room_event_receipts.write().unwrap().get_mut(room_id) {
+            for event_receipts in receipts.values_mut() {
+                for user_receipts in event_receipts.values_mut() {
+                    user_receipts.retain(|_, receipt| !is_expired(receipt));
+                }
+                event_receipts.retain(|_, user_receipts| !user_receipts.is_empty());
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn try_take_leased_lock(
+        &self,
+        lease_duration_ms: u32,
+        key: &str,
+        holder: &str,
+    ) -> Result<bool> {
+        let now = Instant::now();
+        let expiration = now + Duration::from_millis(lease_duration_ms.into());
+
+        match self.leases.write().unwrap().entry(key.to_owned()) {
+            Entry::Occupied(mut o) => {
+                let prev = o.get_mut();
+                if prev.0 == holder || prev.1 < now {
+                    // We had the lease before and extend it, or it expired and we
+                    // steal it.
+                    *prev = (holder.to_owned(), expiration);
+                    Ok(true)
+                } else {
+                    Ok(false)
+                }
+            }
+            Entry::Vacant(v) => {
+                v.insert((holder.to_owned(), expiration));
+                Ok(true)
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -111,6 +111,7 @@ impl RoomInfoV1 {
             room_id,
             room_state: room_type,
             notification_counts,
+            thread_notification_counts: Default::default(),
             summary,
             members_synced,
             last_prev_batch,
@@ -119,6 +120,9 @@ impl RoomInfoV1 {
             #[cfg(feature = "experimental-sliding-sync")]
             latest_event: latest_event.map(|ev| Box::new(LatestEvent::new(ev))),
             read_receipts: Default::default(),
+            is_spam_invite: false,
+            is_marked_unread: false,
+            privacy_overrides: Default::default(),
             base_info: base_info.migrate(create),
         }
     }
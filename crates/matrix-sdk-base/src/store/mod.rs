@@ -37,6 +37,7 @@ use once_cell::sync::OnceCell;
 pub mod integration_tests;
 mod traits;
 
+use matrix_sdk_common::{clock::Clock, store_locks::BackingStore};
 #[cfg(feature = "e2e-encryption")]
 use matrix_sdk_crypto::store::{DynCryptoStore, IntoCryptoStore};
 pub use matrix_sdk_store_encryption::Error as StoreEncryptionError;
@@ -51,16 +52,20 @@ use ruma::{
     serde::Raw,
     EventId, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId, UserId,
 };
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, RwLock};
 
 /// BoxStream of owned Types
 pub type BoxStream<T> = Pin<Box<dyn futures_util::Stream<Item = T> + Send>>;
 
 use crate::{
-    rooms::{RoomInfo, RoomState},
+    rooms::{RoomInfo, RoomInfoUpdate, RoomState},
     MinimalRoomMemberEvent, Room, RoomStateFilter, SessionMeta,
 };
 
+/// How many room info updates can be buffered for a slow subscriber, before it
+/// starts missing some.
+const ROOM_INFO_UPDATE_CHANNEL_CAPACITY: usize = 1024;
+
 pub(crate) mod ambiguity_map;
 mod memory_store;
 pub mod migration_helpers;
@@ -149,17 +154,23 @@ pub(crate) struct Store {
     /// might acquire read access, such that access to different rooms can be
     /// parallelized.
     sync_lock: Arc<RwLock<()>>,
+    /// The clock that is handed to the rooms of this store.
+    pub(crate) clock: Arc<dyn Clock>,
+    /// The sender of the updates of the infos of the rooms of this store.
+    pub(crate) room_info_update_sender: broadcast::Sender<RoomInfoUpdate>,
 }
 
 impl Store {
     /// Create a new store, wrapping the given `StateStore`
-    pub fn new(inner: Arc<DynStateStore>) -> Self {
+    pub fn new(inner: Arc<DynStateStore>, clock: Arc<dyn Clock>) -> Self {
         Self {
             inner,
             session_meta: Default::default(),
             sync_token: Default::default(),
             rooms: Default::default(),
             sync_lock: Default::default(),
+            clock,
+            room_info_update_sender: broadcast::channel(ROOM_INFO_UPDATE_CHANNEL_CAPACITY).0,
         }
     }
 
@@ -176,7 +187,13 @@ impl Store {
     /// This method panics if it is called twice.
     pub async fn set_session_meta(&self, session_meta: SessionMeta) -> Result<()> {
         for info in self.inner.get_room_infos().await? {
-            let room = Room::restore(&session_meta.user_id, self.inner.clone(), info);
+            let room = Room::restore(
+                &session_meta.user_id,
+                self.inner.clone(),
+                self.clock.clone(),
+                self.room_info_update_sender.clone(),
+                info,
+            );
             self.rooms.write().unwrap().insert(room.room_id().to_owned(), room);
         }
 
@@ -215,6 +232,48 @@ impl Store {
         self.rooms.read().unwrap().get(room_id).cloned()
     }
 
+    /// Remove the room with the given room id from this store, both from
+    /// memory and from the underlying `StateStore`.
+    pub async fn forget_room(&self, room_id: &RoomId) -> Result<()> {
+        self.inner.remove_room(room_id).await?;
+        self.rooms.write().unwrap().remove(room_id);
+        Ok(())
+    }
+
+    /// Reload the sync token and the infos of the rooms from the underlying
+    /// `StateStore`, in case another process wrote to it.
+    ///
+    /// The rooms that aren't in the `StateStore` anymore, because the other
+    /// process forgot them, are forgotten too.
+    pub async fn reload(&self) -> crate::Result<()> {
+        let user_id = &self.session_meta.get().ok_or(crate::Error::NotLoggedIn)?.user_id;
+
+        let infos = self.inner.get_room_infos().await?;
+        let room_ids: BTreeSet<_> = infos.iter().map(|info| info.room_id().to_owned()).collect();
+        self.rooms.write().unwrap().retain(|room_id, _| room_ids.contains(room_id));
+
+        for info in infos {
+            if let Some(room) = self.get_room(info.room_id()) {
+                room.set_room_info(info);
+            } else {
+                let room = Room::restore(
+                    user_id,
+                    self.inner.clone(),
+                    self.clock.clone(),
+                    self.room_info_update_sender.clone(),
+                    info,
+                );
+                self.rooms.write().unwrap().insert(room.room_id().to_owned(), room);
+            }
+        }
+
+        let token =
+            self.get_kv_data(StateStoreDataKey::SyncToken).await?.and_then(|s| s.into_sync_token());
+        *self.sync_token.write().await = token;
+
+        Ok(())
+    }
+
     /// Lookup the Room for the given RoomId, or create one, if it didn't exist
     /// yet in the store
     pub fn get_or_create_room(&self, room_id: &RoomId, room_type: RoomState) -> Room {
@@ -225,7 +284,16 @@ impl Store {
             .write()
             .unwrap()
             .entry(room_id.to_owned())
-            .or_insert_with(|| Room::new(user_id, self.inner.clone(), room_id, room_type))
+            .or_insert_with(|| {
+                Room::new(
+                    user_id,
+                    self.inner.clone(),
+                    self.clock.clone(),
+                    self.room_info_update_sender.clone(),
+                    room_id,
+                    room_type,
+                )
+            })
             .clone()
     }
 }
@@ -250,6 +318,25 @@ impl Deref for Store {
     }
 }
 
+/// A state store that implements primitives for cross-process locking.
+#[derive(Clone, Debug)]
+pub struct LockableStateStore(pub(crate) Arc<DynStateStore>);
+
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+impl BackingStore for LockableStateStore {
+    type Error = StoreError;
+
+    async fn try_lock(
+        &self,
+        lease_duration_ms: u32,
+        key: &str,
+        holder: &str,
+    ) -> StdResult<bool, Self::Error> {
+        self.0.try_take_leased_lock(lease_duration_ms, key, holder).await
+    }
+}
+
 /// Store state changes and pass them to the StateStore.
 #[derive(Clone, Debug, Default)]
 pub struct StateChanges {
@@ -290,6 +377,10 @@ pub struct StateChanges {
     /// A map from room id to a map of a display name and a set of user ids that
     /// share that display name in the given room.
     pub ambiguity_maps: BTreeMap<OwnedRoomId, BTreeMap<String, BTreeSet<OwnedUserId>>>,
+
+    /// Custom values to set along with the other changes, like with
+    /// [`StateStore::set_custom_value()`].
+    pub custom_values: BTreeMap<Vec<u8>, Vec<u8>>,
 }
 
 impl StateChanges {
@@ -32,7 +32,7 @@ use ruma::{
         RoomAccountDataEventType, StateEventType, StaticEventContent, StaticStateEventContent,
     },
     serde::Raw,
-    EventId, MxcUri, OwnedEventId, OwnedUserId, RoomId, UserId,
+    EventId, MilliSecondsSinceUnixEpoch, MxcUri, OwnedEventId, OwnedUserId, RoomId, UserId,
 };
 
 use super::{StateChanges, StoreError};
@@ -150,6 +150,20 @@ pub trait StateStore: AsyncTraitDeps {
         state_keys: &[&str],
     ) -> Result<Vec<RawAnySyncOrStrippedState>, Self::Error>;
 
+    /// Get all the current state events of a given room, in no particular
+    /// order.
+    ///
+    /// If the room has stripped state events, like when the user is invited
+    /// to it, only those are returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The id of the room to find events for.
+    async fn get_all_state_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>, Self::Error>;
+
     /// Get the current profile for the given user in the given room.
     ///
     /// # Arguments
@@ -366,6 +380,41 @@ pub trait StateStore: AsyncTraitDeps {
     ///
     /// * `room_id` - The `RoomId` of the room to delete.
     async fn remove_room(&self, room_id: &RoomId) -> Result<(), Self::Error>;
+
+    /// Removes the receipts of a room that were sent before the given time.
+    ///
+    /// This is used to purge the events of a room that are older than its
+    /// retention policy allows: a receipt sent before that time can only point
+    /// to an event that is expired too.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The `RoomId` of the room whose receipts to remove.
+    ///
+    /// * `before` - The time before which the receipts are removed.
+    async fn remove_room_receipts_before(
+        &self,
+        room_id: &RoomId,
+        before: MilliSecondsSinceUnixEpoch,
+    ) -> Result<(), Self::Error>;
+
+    /// Try to take a leased lock.
+    ///
+    /// This attempts to take a lock for the given lease duration.
+    ///
+    /// - If we already had the lease, this will extend the lease.
+    /// - If we didn't, but the previous lease has expired, we will acquire the
+    ///   lock.
+    /// - If there was no previous lease, we will acquire the lock.
+    /// - Otherwise, we don't get the lock.
+    ///
+    /// Returns whether taking the lock succeeded.
+    async fn try_take_leased_lock(
+        &self,
+        lease_duration_ms: u32,
+        key: &str,
+        holder: &str,
+    ) -> Result<bool, Self::Error>;
 }
 
 #[repr(transparent)]
@@ -446,6 +495,13 @@ impl<T: StateStore> StateStore for EraseStateStoreError<T> {
         self.0.get_state_events_for_keys(room_id, event_type, state_keys).await.map_err(Into::into)
     }
 
+    async fn get_all_state_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>, Self::Error> {
+        self.0.get_all_state_events(room_id).await.map_err(Into::into)
+    }
+
     async fn get_profile(
         &self,
         room_id: &RoomId,
@@ -589,6 +645,23 @@ impl<T: StateStore> StateStore for EraseStateStoreError<T> {
     async fn remove_room(&self, room_id: &RoomId) -> Result<(), Self::Error> {
         self.0.remove_room(room_id).await.map_err(Into::into)
     }
+
+    async fn remove_room_receipts_before(
+        &self,
+        room_id: &RoomId,
+        before: MilliSecondsSinceUnixEpoch,
+    ) -> Result<(), Self::Error> {
+        self.0.remove_room_receipts_before(room_id, before).await.map_err(Into::into)
+    }
+
+    async fn try_take_leased_lock(
+        &self,
+        lease_duration_ms: u32,
+        key: &str,
+        holder: &str,
+    ) -> Result<bool, Self::Error> {
+        self.0.try_take_leased_lock(lease_duration_ms, key, holder).await.map_err(Into::into)
+    }
 }
 
 /// Convenience functionality for state stores.
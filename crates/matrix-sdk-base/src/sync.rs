@@ -16,7 +16,8 @@
 
 use std::{collections::BTreeMap, fmt};
 
-use matrix_sdk_common::deserialized_responses::SyncTimelineEvent;
+use async_trait::async_trait;
+use matrix_sdk_common::{deserialized_responses::SyncTimelineEvent, AsyncTraitDeps};
 use ruma::{
     api::client::{
         push::get_notifications::v3::Notification,
@@ -29,7 +30,7 @@ use ruma::{
         AnySyncEphemeralRoomEvent, AnySyncStateEvent, AnyToDeviceEvent,
     },
     serde::Raw,
-    OwnedRoomId,
+    OwnedEventId, OwnedRoomId,
 };
 use serde::{Deserialize, Serialize};
 
@@ -38,6 +39,7 @@ use crate::{
         DebugInvitedRoom, DebugListOfRawEvents, DebugListOfRawEventsNoId, DebugNotificationMap,
     },
     deserialized_responses::AmbiguityChanges,
+    BaseClient,
 };
 
 /// Internal representation of a `/sync` response.
@@ -60,6 +62,24 @@ pub struct SyncResponse {
     pub notifications: BTreeMap<OwnedRoomId, Vec<Notification>>,
 }
 
+/// An extension point to observe, or augment, every sync response processed by
+/// a [`BaseClient`].
+///
+/// Post-processors are registered with
+/// [`BaseClient::add_sync_response_post_processor()`]. They are called in the
+/// order they were registered, once the changes of the sync response have been
+/// saved to the store, and before the response is returned to the higher
+/// layers, which emit the notifications and call the event handlers.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait SyncResponsePostProcessor: AsyncTraitDeps {
+    /// Process the given sync response.
+    ///
+    /// Changes made to the response are seen by the next post-processors and
+    /// by the higher layers, but they aren't saved to the store.
+    async fn process(&self, client: &BaseClient, response: &mut SyncResponse);
+}
+
 #[cfg(not(tarpaulin_include))]
 impl fmt::Debug for SyncResponse {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -100,6 +120,11 @@ impl fmt::Debug for Rooms {
 pub struct JoinedRoom {
     /// Counts of unread notifications for this room.
     pub unread_notifications: UnreadNotificationsCount,
+    /// Counts of unread notifications for each thread in this room, keyed by
+    /// thread root.
+    ///
+    /// Only provided if the sync filter enabled `unread_thread_notifications`.
+    pub unread_thread_notifications: BTreeMap<OwnedEventId, UnreadNotificationsCount>,
     /// The timeline of messages and state changes in the room.
     pub timeline: Timeline,
     /// Updates to the state, between the time indicated by the `since`
@@ -119,6 +144,7 @@ impl fmt::Debug for JoinedRoom {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("JoinedRoom")
             .field("unread_notifications", &self.unread_notifications)
+            .field("unread_thread_notifications", &self.unread_thread_notifications)
             .field("timeline", &self.timeline)
             .field("state", &DebugListOfRawEvents(&self.state))
             .field("account_data", &DebugListOfRawEventsNoId(&self.account_data))
@@ -135,7 +161,14 @@ impl JoinedRoom {
         ephemeral: Vec<Raw<AnySyncEphemeralRoomEvent>>,
         unread_notifications: UnreadNotificationsCount,
     ) -> Self {
-        Self { unread_notifications, timeline, state, account_data, ephemeral }
+        Self {
+            unread_notifications,
+            unread_thread_notifications: BTreeMap::new(),
+            timeline,
+            state,
+            account_data,
+            ephemeral,
+        }
     }
 }
 
@@ -0,0 +1,176 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Timings of the processing of the sync responses.
+//!
+//! When a [`SyncMetricsHook`] is set with
+//! [`BaseClient::with_sync_metrics_hook()`], it receives the time spent on
+//! every sync response, broken down per room, which allows to find the rooms
+//! that make the sync slow on large accounts.
+//!
+//! [`BaseClient::with_sync_metrics_hook()`]: crate::BaseClient::with_sync_metrics_hook
+
+use std::time::Duration;
+
+use matrix_sdk_common::instant::Instant;
+use ruma::OwnedRoomId;
+
+use crate::AsyncTraitDeps;
+
+/// A hook that receives the timings of every sync response processed by a
+/// [`BaseClient`](crate::BaseClient).
+pub trait SyncMetricsHook: AsyncTraitDeps {
+    /// A sync response was processed.
+    ///
+    /// This is called once the changes of the response were saved to the
+    /// store, it should return quickly to not slow the sync down.
+    fn on_sync_processed(&self, metrics: &SyncMetrics);
+}
+
+/// The timings of the processing of a sync response.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct SyncMetrics {
+    /// The `next_batch` token of the sync response, if it has one.
+    pub sync_token: Option<String>,
+
+    /// The time spent processing the whole response.
+    pub total: Duration,
+
+    /// The time spent saving the changes of the response to the store.
+    ///
+    /// The changes of all the rooms are saved together, so this can't be
+    /// broken down per room.
+    pub store_write: Duration,
+
+    /// The timings of the rooms of the response, in the order they were
+    /// processed.
+    pub rooms: Vec<RoomSyncMetrics>,
+}
+
+impl SyncMetrics {
+    /// The timings of the rooms that took the longest to process, slowest
+    /// first.
+    pub fn slowest_rooms(&self, count: usize) -> Vec<&RoomSyncMetrics> {
+        let mut rooms: Vec<_> = self.rooms.iter().collect();
+        rooms.sort_by(|a, b| b.total.cmp(&a.total));
+        rooms.truncate(count);
+        rooms
+    }
+}
+
+/// The timings of the processing of a room in a sync response.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct RoomSyncMetrics {
+    /// The room.
+    pub room_id: OwnedRoomId,
+
+    /// The time spent processing the room.
+    pub total: Duration,
+
+    /// The time spent processing the state events of the room, including
+    /// loading the data of the members from the store.
+    pub state: Duration,
+
+    /// The time spent decrypting the events of the timeline.
+    pub decryption: Duration,
+
+    /// The time spent evaluating the push rules on the events of the
+    /// timeline.
+    pub notifications: Duration,
+}
+
+impl RoomSyncMetrics {
+    pub(crate) fn new(room_id: OwnedRoomId) -> Self {
+        Self {
+            room_id,
+            total: Duration::ZERO,
+            state: Duration::ZERO,
+            decryption: Duration::ZERO,
+            notifications: Duration::ZERO,
+        }
+    }
+}
+
+/// Collects the [`SyncMetrics`] of a sync response while it is processed.
+pub(crate) struct SyncMetricsRecorder {
+    start: Instant,
+    metrics: SyncMetrics,
+}
+
+impl SyncMetricsRecorder {
+    pub(crate) fn new(sync_token: Option<String>) -> Self {
+        Self {
+            start: Instant::now(),
+            metrics: SyncMetrics {
+                sync_token,
+                total: Duration::ZERO,
+                store_write: Duration::ZERO,
+                rooms: Vec::new(),
+            },
+        }
+    }
+
+    /// Add the timings of a room, whose processing started at `start`.
+    pub(crate) fn add_room(&mut self, mut room: RoomSyncMetrics, start: Instant) {
+        room.total = start.elapsed();
+        self.metrics.rooms.push(room);
+    }
+
+    /// Set the time spent saving the changes, which started at `start`.
+    pub(crate) fn set_store_write(&mut self, start: Instant) {
+        self.metrics.store_write = start.elapsed();
+    }
+
+    /// Send the timings to the hook, if any.
+    pub(crate) fn report(mut self, hook: Option<&dyn SyncMetricsHook>) {
+        if let Some(hook) = hook {
+            self.metrics.total = self.start.elapsed();
+            hook.on_sync_processed(&self.metrics);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ruma::owned_room_id;
+
+    use super::{RoomSyncMetrics, SyncMetrics};
+
+    #[test]
+    fn test_slowest_rooms() {
+        let room = |room_id, millis| RoomSyncMetrics {
+            total: Duration::from_millis(millis),
+            ..RoomSyncMetrics::new(room_id)
+        };
+
+        let metrics = SyncMetrics {
+            sync_token: None,
+            total: Duration::from_millis(100),
+            store_write: Duration::from_millis(10),
+            rooms: vec![
+                room(owned_room_id!("!a:localhost"), 5),
+                room(owned_room_id!("!b:localhost"), 50),
+                room(owned_room_id!("!c:localhost"), 20),
+            ],
+        };
+
+        let slowest: Vec<_> =
+            metrics.slowest_rooms(2).into_iter().map(|room| room.room_id.as_str()).collect();
+        assert_eq!(slowest, ["!b:localhost", "!c:localhost"]);
+    }
+}
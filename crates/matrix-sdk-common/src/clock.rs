@@ -0,0 +1,207 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An injectable source of time.
+//!
+//! Everything in the SDK that needs to know the current time, or needs to wait
+//! for some amount of time, should go through a [`Clock`] instead of calling
+//! into the system directly. This allows tests to swap in a [`TestClock`] and
+//! fast-forward time deterministically.
+
+use std::{fmt, sync::Arc, time::Duration};
+
+#[cfg(target_arch = "wasm32")]
+use gloo_timers::future::TimeoutFuture;
+use instant::Instant;
+use ruma::{MilliSecondsSinceUnixEpoch, UInt};
+use tokio::sync::watch;
+
+use crate::AsyncTraitDeps;
+
+/// A source of time, and a way to wait for time to pass.
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+pub trait Clock: AsyncTraitDeps {
+    /// The current monotonic instant.
+    fn now(&self) -> Instant;
+
+    /// The current wall-clock time, in milliseconds since the Unix epoch.
+    fn now_ms(&self) -> MilliSecondsSinceUnixEpoch;
+
+    /// Wait until the given duration has elapsed, according to this clock.
+    async fn sleep(&self, duration: Duration);
+}
+
+/// Get the default [`Clock`], backed by the system time.
+pub fn system_clock() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+/// A [`Clock`] backed by the system time.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn now_ms(&self) -> MilliSecondsSinceUnixEpoch {
+        MilliSecondsSinceUnixEpoch::now()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        #[cfg(not(target_arch = "wasm32"))]
+        tokio::time::sleep(duration).await;
+
+        #[cfg(target_arch = "wasm32")]
+        TimeoutFuture::new(u32::try_from(duration.as_millis()).unwrap_or(u32::MAX)).await;
+    }
+}
+
+/// A [`Clock`] that only moves forward when told to.
+///
+/// Time is frozen at the moment the clock was created, until
+/// [`TestClock::advance`] is called. Pending [`Clock::sleep`] calls complete as
+/// soon as the clock has been advanced past their deadline.
+///
+/// Cloning a `TestClock` returns a handle to the same clock.
+#[derive(Clone)]
+pub struct TestClock {
+    inner: Arc<TestClockInner>,
+}
+
+struct TestClockInner {
+    start: Instant,
+    start_ms: MilliSecondsSinceUnixEpoch,
+    elapsed: watch::Sender<Duration>,
+}
+
+impl TestClock {
+    /// Create a new `TestClock`, frozen at the current system time.
+    pub fn new() -> Self {
+        Self::starting_at(MilliSecondsSinceUnixEpoch::now())
+    }
+
+    /// Create a new `TestClock`, frozen at the given wall-clock time.
+    pub fn starting_at(start_ms: MilliSecondsSinceUnixEpoch) -> Self {
+        let (elapsed, _) = watch::channel(Duration::ZERO);
+        Self { inner: Arc::new(TestClockInner { start: Instant::now(), start_ms, elapsed }) }
+    }
+
+    /// Move the clock forward by the given duration, waking up all the sleepers
+    /// whose deadline has been reached.
+    pub fn advance(&self, duration: Duration) {
+        self.inner.elapsed.send_modify(|elapsed| *elapsed += duration);
+    }
+
+    /// The total time this clock has been advanced by since its creation.
+    pub fn elapsed(&self) -> Duration {
+        *self.inner.elapsed.borrow()
+    }
+}
+
+impl Default for TestClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for TestClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TestClock")
+            .field("start_ms", &self.inner.start_ms)
+            .field("elapsed", &self.elapsed())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+impl Clock for TestClock {
+    fn now(&self) -> Instant {
+        self.inner.start + self.elapsed()
+    }
+
+    fn now_ms(&self) -> MilliSecondsSinceUnixEpoch {
+        let elapsed_ms = u64::try_from(self.elapsed().as_millis()).unwrap_or(u64::MAX);
+        MilliSecondsSinceUnixEpoch(UInt::new_saturating(
+            u64::from(self.inner.start_ms.0).saturating_add(elapsed_ms),
+        ))
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        let mut elapsed = self.inner.elapsed.subscribe();
+        let deadline = *elapsed.borrow() + duration;
+
+        // The sender lives as long as `self`, so this can't fail.
+        let _ = elapsed.wait_for(|elapsed| *elapsed >= deadline).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use matrix_sdk_test::async_test;
+    use ruma::{uint, MilliSecondsSinceUnixEpoch};
+
+    use super::{Clock, TestClock};
+
+    #[async_test]
+    async fn test_clock_only_moves_when_advanced() {
+        let clock = TestClock::starting_at(MilliSecondsSinceUnixEpoch(uint!(1_000)));
+        let start = clock.now();
+
+        assert_eq!(clock.now_ms(), MilliSecondsSinceUnixEpoch(uint!(1_000)));
+        assert_eq!(clock.now(), start);
+
+        clock.advance(Duration::from_secs(2));
+
+        assert_eq!(clock.now_ms(), MilliSecondsSinceUnixEpoch(uint!(3_000)));
+        assert_eq!(clock.now() - start, Duration::from_secs(2));
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[async_test]
+    async fn test_clock_wakes_up_sleepers() {
+        use std::sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        };
+
+        let clock = TestClock::new();
+        let woke_up = Arc::new(AtomicBool::new(false));
+
+        let task = tokio::spawn({
+            let clock = clock.clone();
+            let woke_up = woke_up.clone();
+            async move {
+                clock.sleep(Duration::from_secs(10)).await;
+                woke_up.store(true, Ordering::SeqCst);
+            }
+        });
+
+        tokio::task::yield_now().await;
+        clock.advance(Duration::from_secs(5));
+        tokio::task::yield_now().await;
+        assert!(!woke_up.load(Ordering::SeqCst));
+
+        clock.advance(Duration::from_secs(5));
+        task.await.unwrap();
+        assert!(woke_up.load(Ordering::SeqCst));
+    }
+}
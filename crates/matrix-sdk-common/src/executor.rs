@@ -27,7 +27,7 @@ use std::{
 #[cfg(target_arch = "wasm32")]
 use futures_util::{future::RemoteHandle, FutureExt};
 #[cfg(not(target_arch = "wasm32"))]
-pub use tokio::task::{spawn, JoinError, JoinHandle};
+pub use tokio::task::{spawn, yield_now, JoinError, JoinHandle};
 
 #[cfg(target_arch = "wasm32")]
 pub fn spawn<F, T>(future: F) -> JoinHandle<T>
@@ -54,3 +54,27 @@ impl<T: 'static> Future for JoinHandle<T> {
         Pin::new(&mut self.handle).poll(cx).map(Ok)
     }
 }
+
+/// Yield execution back to the executor, to let the other tasks make progress.
+#[cfg(target_arch = "wasm32")]
+pub async fn yield_now() {
+    struct YieldNow {
+        yielded: bool,
+    }
+
+    impl Future for YieldNow {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            if self.yielded {
+                return Poll::Ready(());
+            }
+
+            self.yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    YieldNow { yielded: false }.await
+}
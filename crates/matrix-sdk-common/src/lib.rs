@@ -20,6 +20,7 @@ pub use instant;
 #[doc(no_inline)]
 pub use ruma;
 
+pub mod clock;
 pub mod debug;
 pub mod deserialized_responses;
 pub mod executor;
@@ -129,6 +129,13 @@ impl Default for PkDecryption {
     }
 }
 
+/// The encryption half of Olm's PkEncryption, encrypting messages for the
+/// owner of a Curve25519 key.
+///
+/// This is the scheme used by the `m.megolm_backup.v1.curve25519-aes-sha2`
+/// backup algorithm, but also by other services that accept messages
+/// encrypted with a public key, like content scanners.
+#[derive(Debug)]
 pub struct PkEncryption {
     public_key: Curve25519PublicKey,
 }
@@ -146,10 +153,13 @@ impl From<Curve25519PublicKey> for PkEncryption {
 }
 
 impl PkEncryption {
+    /// Create a `PkEncryption` encrypting messages for the owner of the given
+    /// public key.
     pub fn from_key(public_key: Curve25519PublicKey) -> Self {
         Self { public_key }
     }
 
+    /// Encrypt the given message.
     pub fn encrypt(&self, message: &[u8]) -> Message {
         let ephemeral_key = Curve25519SecretKey::new();
         let shared_secret = ephemeral_key.diffie_hellman(&self.public_key);
@@ -174,14 +184,19 @@ pub enum MessageDecodeError {
     Key(#[from] KeyError),
 }
 
+/// A message encrypted with a [`PkEncryption`].
 #[derive(Debug)]
 pub struct Message {
+    /// The encrypted message.
     pub ciphertext: Vec<u8>,
+    /// The MAC of the message.
     pub mac: Vec<u8>,
+    /// The ephemeral Curve25519 key used to derive the encryption key.
     pub ephemeral_key: Curve25519PublicKey,
 }
 
 impl Message {
+    /// Decode a message from the base64 encoding of its parts.
     pub fn from_base64(
         ciphertext: &str,
         mac: &str,
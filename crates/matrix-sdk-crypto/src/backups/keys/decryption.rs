@@ -57,6 +57,12 @@ pub enum DecodeError {
     /// The recovery key, a Curve25519 public key, couldn't be decoded.
     #[error(transparent)]
     PublicKey(#[from] vodozemac::KeyError),
+    /// The recovery key file isn't valid UTF-8.
+    #[error(transparent)]
+    Utf8(#[from] std::str::Utf8Error),
+    /// The recovery key file doesn't contain a recovery key.
+    #[error("The recovery key file doesn't contain a recovery key")]
+    EmptyFile,
 }
 
 impl TryFrom<String> for BackupDecryptionKey {
@@ -89,6 +95,8 @@ impl BackupDecryptionKey {
     const PREFIX: [u8; 2] = [0x8b, 0x01];
     const PREFIX_PARITY: u8 = Self::PREFIX[0] ^ Self::PREFIX[1];
     const DISPLAY_CHUNK_SIZE: usize = 4;
+    /// The length of an unpadded base64 export of the key.
+    const BASE64_LENGTH: usize = 43;
 
     fn parity_byte(bytes: &[u8]) -> u8 {
         bytes.iter().fold(Self::PREFIX_PARITY, |acc, x| acc ^ x)
@@ -116,8 +124,11 @@ impl BackupDecryptionKey {
     }
 
     /// Try to create a [`BackupDecryptionKey`] from a base64 export.
+    ///
+    /// Whitespace and padding in the export are ignored.
     pub fn from_base64(key: &str) -> Result<Self, DecodeError> {
-        let decoded = Zeroizing::new(vodozemac::base64_decode(key)?);
+        let key = Zeroizing::new(Self::normalize(key, false));
+        let decoded = Zeroizing::new(vodozemac::base64_decode(key.trim_end_matches('='))?);
 
         if decoded.len() != Self::KEY_SIZE {
             Err(DecodeError::Length(Self::KEY_SIZE, decoded.len()))
@@ -130,11 +141,14 @@ impl BackupDecryptionKey {
     }
 
     /// Try to create a [`BackupDecryptionKey`] from a base58 export.
+    ///
+    /// Whitespace and dashes in the export, as added by some clients to group
+    /// the characters, are ignored.
     pub fn from_base58(value: &str) -> Result<Self, DecodeError> {
-        // Remove any whitespace we might have
-        let value: String = value.chars().filter(|c| !c.is_whitespace()).collect();
+        let value = Zeroizing::new(Self::normalize(value, true));
 
-        let decoded = bs58::decode(value).with_alphabet(bs58::Alphabet::BITCOIN).into_vec()?;
+        let decoded =
+            bs58::decode(value.as_str()).with_alphabet(bs58::Alphabet::BITCOIN).into_vec()?;
         let mut decoded = Cursor::new(decoded);
 
         let mut prefix = [0u8; 2];
@@ -159,6 +173,60 @@ impl BackupDecryptionKey {
         }
     }
 
+    /// Try to create a [`BackupDecryptionKey`] from a recovery key typed or
+    /// pasted by the user.
+    ///
+    /// Both the base58 format, which is the one displayed by Element as the
+    /// "Security Key", and the base64 format are accepted. Whitespace and
+    /// dashes grouping the characters of a base58 key are ignored.
+    pub fn from_recovery_key(value: &str) -> Result<Self, DecodeError> {
+        if Self::looks_like_base64(value) {
+            Self::from_base64(value)
+        } else {
+            Self::from_base58(value)
+        }
+    }
+
+    /// Try to create a [`BackupDecryptionKey`] from the contents of a recovery
+    /// key file, like the "Security Key" `.txt` file exported by Element.
+    ///
+    /// The file can contain the key on a single line or split on several
+    /// lines, with a byte order mark and Windows line endings. The format of
+    /// the key is detected like in [`BackupDecryptionKey::from_recovery_key`].
+    pub fn from_recovery_key_file(contents: &[u8]) -> Result<Self, DecodeError> {
+        let contents = std::str::from_utf8(contents)?;
+        let contents = contents.trim_start_matches('\u{feff}').trim();
+
+        if contents.is_empty() {
+            return Err(DecodeError::EmptyFile);
+        }
+
+        // The key could be surrounded by lines of text, try each line on its
+        // own before the whole file.
+        let mut lines = contents.lines().map(str::trim).filter(|line| !line.is_empty());
+        if let Some(key) = lines.find_map(|line| Self::from_recovery_key(line).ok()) {
+            return Ok(key);
+        }
+
+        Self::from_recovery_key(contents)
+    }
+
+    /// Whether the given recovery key is in the base64 format rather than the
+    /// base58 one.
+    fn looks_like_base64(value: &str) -> bool {
+        let value = Zeroizing::new(Self::normalize(value, false));
+
+        // The base64 alphabet has characters that the base58 one doesn't, and
+        // a base64 export of the key is shorter than a base58 one.
+        value.contains(['+', '/', '=']) || value.len() == Self::BASE64_LENGTH
+    }
+
+    /// Remove the characters used to format a recovery key for display from
+    /// it.
+    fn normalize(value: &str, remove_dashes: bool) -> String {
+        value.chars().filter(|c| !c.is_whitespace() && !(remove_dashes && *c == '-')).collect()
+    }
+
     /// Export the `[`BackupDecryptionKey`] as a base58 encoded string.
     pub fn to_base58(&self) -> String {
         let bytes = Zeroizing::new(
@@ -254,6 +322,7 @@ impl BackupDecryptionKey {
 
 #[cfg(test)]
 mod tests {
+    use assert_matches::assert_matches;
     use matrix_sdk_test::async_test;
     use ruma::api::client::backup::KeyBackupData;
     use serde_json::json;
@@ -331,6 +400,65 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn recovery_key_formats() -> Result<(), DecodeError> {
+        let key = BackupDecryptionKey::new().expect("Can't create a new recovery key");
+
+        for export in [key.to_base58(), key.to_string(), key.to_base64()] {
+            let decoded_key = BackupDecryptionKey::from_recovery_key(&export)?;
+            assert_eq!(key.inner, decoded_key.inner, "The decoded key doesn't match the original");
+        }
+
+        // Other clients group the characters with dashes.
+        let test_key = BackupDecryptionKey::from_recovery_key(
+            "EsTc-LW2K-PGiF-wKEA-3As5-g5c4-BXwk-qeeJ-ZJV8-Q9fu-gUMN-UE4d",
+        )?;
+        assert_eq!(test_key.as_bytes(), &TEST_KEY);
+
+        // A padded base64 key.
+        let padded = format!("{}=", key.to_base64());
+        let decoded_key = BackupDecryptionKey::from_recovery_key(&padded)?;
+        assert_eq!(key.inner, decoded_key.inner);
+
+        BackupDecryptionKey::from_recovery_key("EsTc LW2K PGiF")
+            .expect_err("The recovery key is too short");
+
+        Ok(())
+    }
+
+    #[test]
+    fn recovery_key_file() -> Result<(), DecodeError> {
+        // The file exported by Element.
+        let test_key = BackupDecryptionKey::from_recovery_key_file(
+            b"EsTc LW2K PGiF wKEA 3As5 g5c4 BXwk qeeJ ZJV8 Q9fu gUMN UE4d\n",
+        )?;
+        assert_eq!(test_key.as_bytes(), &TEST_KEY);
+
+        // With a byte order mark, Windows line endings and the key split on
+        // several lines.
+        let test_key = BackupDecryptionKey::from_recovery_key_file(
+            "\u{feff}EsTc LW2K PGiF wKEA 3As5 g5c4\r\nBXwk qeeJ ZJV8 Q9fu gUMN UE4d\r\n".as_bytes(),
+        )?;
+        assert_eq!(test_key.as_bytes(), &TEST_KEY);
+
+        // With some text around the key.
+        let test_key = BackupDecryptionKey::from_recovery_key_file(
+            b"Security Key:\n\nEsTc LW2K PGiF wKEA 3As5 g5c4 BXwk qeeJ ZJV8 Q9fu gUMN UE4d\n",
+        )?;
+        assert_eq!(test_key.as_bytes(), &TEST_KEY);
+
+        assert_matches!(
+            BackupDecryptionKey::from_recovery_key_file(b" \n"),
+            Err(DecodeError::EmptyFile)
+        );
+        assert_matches!(
+            BackupDecryptionKey::from_recovery_key_file(&[0xff, 0xfe]),
+            Err(DecodeError::Utf8(_))
+        );
+
+        Ok(())
+    }
+
     #[test]
     fn test_decrypt_key() {
         let decryption_key =
@@ -45,7 +45,7 @@ use crate::{
 
 mod keys;
 
-pub use keys::{DecodeError, DecryptionError, MegolmV1BackupKey};
+pub use keys::{DecodeError, DecryptionError, MegolmV1BackupKey, PkEncryption, PkMessage};
 
 /// A state machine that handles backing up room keys.
 ///
@@ -424,6 +424,19 @@ impl BackupMachine {
         Ok(())
     }
 
+    /// Disable the backup, and delete the backup decryption key and the backup
+    /// version from the crypto store.
+    ///
+    /// Unlike [`BackupMachine::disable_backup()`], this forgets about the
+    /// backup entirely, so it won't be resumed when the [`OlmMachine`] is
+    /// restored.
+    ///
+    /// [`OlmMachine`]: crate::OlmMachine
+    pub async fn delete_backup(&self) -> Result<(), CryptoStoreError> {
+        self.disable_backup().await?;
+        self.store.delete_backup_keys().await
+    }
+
     /// Store the backup decryption key in the crypto store.
     ///
     /// This is useful if the client wants to support gossiping of the backup
@@ -447,6 +460,18 @@ impl BackupMachine {
     /// out to backup the room keys.
     pub async fn backup(
         &self,
+    ) -> Result<Option<(OwnedTransactionId, KeysBackupRequest)>, CryptoStoreError> {
+        self.backup_with_batch_size(Self::BACKUP_BATCH_SIZE).await
+    }
+
+    /// Encrypt a batch of at most `batch_size` room keys and return a request
+    /// that needs to be sent out to backup the room keys.
+    ///
+    /// If a request was returned already and wasn't marked as sent yet, it is
+    /// returned again, whatever its size.
+    pub async fn backup_with_batch_size(
+        &self,
+        batch_size: usize,
     ) -> Result<Option<(OwnedTransactionId, KeysBackupRequest)>, CryptoStoreError> {
         let mut request = self.pending_backup.write().await;
 
@@ -457,7 +482,7 @@ impl BackupMachine {
         } else {
             trace!("Backing up, creating a new request");
 
-            let new_request = self.backup_helper().await?;
+            let new_request = self.backup_helper(batch_size).await?;
             *request = new_request.clone();
 
             Ok(new_request.map(|r| (r.request_id, r.request)))
@@ -508,7 +533,10 @@ impl BackupMachine {
         Ok(())
     }
 
-    async fn backup_helper(&self) -> Result<Option<PendingBackup>, CryptoStoreError> {
+    async fn backup_helper(
+        &self,
+        batch_size: usize,
+    ) -> Result<Option<PendingBackup>, CryptoStoreError> {
         let Some(backup_key) = &*self.backup_key.read().await else {
             warn!("Trying to backup room keys but no backup key was found");
             return Ok(None);
@@ -519,8 +547,7 @@ impl BackupMachine {
             return Ok(None);
         };
 
-        let sessions =
-            self.store.inbound_group_sessions_for_backup(Self::BACKUP_BATCH_SIZE).await?;
+        let sessions = self.store.inbound_group_sessions_for_backup(batch_size).await?;
 
         if sessions.is_empty() {
             trace!(?backup_key, "No room keys need to be backed up");
@@ -44,6 +44,7 @@
 use std::sync::Arc;
 
 use hkdf::Hkdf;
+use matrix_sdk_common::clock::Clock;
 use ruma::{
     api::client::dehydrated_device::{put_dehydrated_device, DehydratedDeviceData},
     assign,
@@ -95,7 +96,8 @@ impl DehydratedDevices {
         let user_id = self.inner.user_id();
         let user_identity = self.inner.store().private_identity();
 
-        let account = Account::new(user_id);
+        let clock = self.inner.clock().clone();
+        let account = Account::new_at(user_id, clock.now_ms());
         let store = Arc::new(CryptoStoreWrapper::new(user_id, MemoryStore::new()));
 
         let verification_machine = VerificationMachine::new(
@@ -108,7 +110,7 @@ impl DehydratedDevices {
             Store::new(account.static_data().clone(), user_identity, store, verification_machine);
         store.save_pending_changes(crate::store::PendingChanges { account: Some(account) }).await?;
 
-        Ok(DehydratedDevice { store })
+        Ok(DehydratedDevice { store, clock })
     }
 
     /// Rehydrate the dehydrated device.
@@ -269,6 +271,7 @@ impl RehydratedDevice {
 #[derive(Debug)]
 pub struct DehydratedDevice {
     store: Store,
+    clock: Arc<dyn Clock>,
 }
 
 impl DehydratedDevice {
@@ -318,7 +321,7 @@ impl DehydratedDevice {
         let mut transaction = self.store.transaction().await;
 
         let account = transaction.account().await?;
-        account.generate_fallback_key_helper();
+        account.generate_fallback_key_helper(self.clock.now_ms());
 
         let (device_keys, one_time_keys, fallback_keys) = account.keys_for_upload();
 
@@ -12,6 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::BTreeMap;
+
 use ruma::{CanonicalJsonError, IdParseError, OwnedDeviceId, OwnedRoomId, OwnedUserId};
 use serde_json::Error as SerdeError;
 use thiserror::Error;
@@ -73,6 +75,28 @@ pub enum OlmError {
             have a valid Olm session with us"
     )]
     MissingSession,
+
+    /// The room key wasn't shared because some devices of the recipients
+    /// aren't verified, and the encryption settings require an error in that
+    /// case.
+    ///
+    /// The devices are grouped by user. Once they are verified, blacklisted or
+    /// ignored, sharing the room key can be retried.
+    #[error("the room key wasn't shared because some devices aren't verified: {0:?}")]
+    UnverifiedDevices(BTreeMap<OwnedUserId, Vec<OwnedDeviceId>>),
+
+    /// The room key wasn't shared because some recipients replaced their
+    /// identity, and the encryption settings require an error in that case.
+    ///
+    /// Once the new identities are verified, or the changes are acknowledged
+    /// with [`UserIdentity::pin_current_identity()`] or
+    /// [`UserIdentity::withdraw_verification()`], sharing the room key can be
+    /// retried.
+    ///
+    /// [`UserIdentity::pin_current_identity()`]: crate::UserIdentity::pin_current_identity
+    /// [`UserIdentity::withdraw_verification()`]: crate::UserIdentity::withdraw_verification
+    #[error("the room key wasn't shared because some users changed their identity: {0:?}")]
+    IdentityViolations(Vec<OwnedUserId>),
 }
 
 /// Error representing a failure during a group encryption operation.
@@ -16,6 +16,7 @@ use std::io::{Cursor, Read, Seek, SeekFrom};
 
 use byteorder::{BigEndian, ReadBytesExt};
 use rand::{thread_rng, RngCore};
+use serde::de::DeserializeOwned;
 use serde_json::Error as SerdeError;
 use thiserror::Error;
 use vodozemac::{base64_decode, base64_encode};
@@ -24,6 +25,7 @@ use zeroize::Zeroize;
 use crate::{
     ciphers::{AesHmacSha2Key, IV_SIZE, MAC_SIZE, SALT_SIZE},
     olm::ExportedRoomKey,
+    store::CryptoIdentityExport,
 };
 
 const VERSION: u8 = 1;
@@ -31,6 +33,9 @@ const VERSION: u8 = 1;
 const HEADER: &str = "-----BEGIN MEGOLM SESSION DATA-----";
 const FOOTER: &str = "-----END MEGOLM SESSION DATA-----";
 
+const IDENTITY_HEADER: &str = "-----BEGIN MATRIX CRYPTO IDENTITY-----";
+const IDENTITY_FOOTER: &str = "-----END MATRIX CRYPTO IDENTITY-----";
+
 /// Error representing a failure during key export or import.
 #[derive(Error, Debug)]
 pub enum KeyExportError {
@@ -78,27 +83,10 @@ pub enum KeyExportError {
 /// # };
 /// ```
 pub fn decrypt_room_key_export(
-    mut input: impl Read,
+    input: impl Read,
     passphrase: &str,
 ) -> Result<Vec<ExportedRoomKey>, KeyExportError> {
-    let mut x: String = String::new();
-
-    input.read_to_string(&mut x)?;
-
-    if !(x.trim_start().starts_with(HEADER) && x.trim_end().ends_with(FOOTER)) {
-        return Err(KeyExportError::InvalidHeaders);
-    }
-
-    let payload: String =
-        x.lines().filter(|l| !(l.starts_with(HEADER) || l.starts_with(FOOTER))).collect();
-
-    let mut decrypted = decrypt_helper(&payload, passphrase)?;
-
-    let ret = serde_json::from_str(&decrypted);
-
-    decrypted.zeroize();
-
-    Ok(ret?)
+    decrypt_export(input, passphrase, HEADER, FOOTER)
 }
 
 /// Encrypt the list of exported room keys using the given passphrase.
@@ -147,6 +135,99 @@ pub fn encrypt_room_key_export(
     Ok([HEADER.to_owned(), ciphertext, FOOTER.to_owned()].join("\n"))
 }
 
+/// Try to decrypt a reader into the crypto identity of a device.
+///
+/// # Arguments
+///
+/// * `passphrase` - The passphrase that was used to encrypt the crypto
+/// identity.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use std::io::Cursor;
+/// # use matrix_sdk_crypto::{OlmMachine, decrypt_crypto_identity_export};
+/// # use matrix_sdk_crypto::store::MemoryStore;
+/// # use ruma::{device_id, user_id};
+/// # let alice = user_id!("@alice:example.org");
+/// # async {
+/// # let export = Cursor::new("".to_owned());
+/// let identity = decrypt_crypto_identity_export(export, "1234").unwrap();
+/// let machine = OlmMachine::with_crypto_identity(
+///     alice,
+///     device_id!("DEVICEID"),
+///     MemoryStore::new(),
+///     identity,
+/// )
+/// .await
+/// .unwrap();
+/// # };
+/// ```
+pub fn decrypt_crypto_identity_export(
+    input: impl Read,
+    passphrase: &str,
+) -> Result<CryptoIdentityExport, KeyExportError> {
+    decrypt_export(input, passphrase, IDENTITY_HEADER, IDENTITY_FOOTER)
+}
+
+/// Encrypt the crypto identity of a device using the given passphrase.
+///
+/// The format is the same as the one of room key exports, with different
+/// headers.
+///
+/// # Arguments
+///
+/// * `identity` - The crypto identity that should be encrypted.
+///
+/// * `passphrase` - The passphrase that will be used to encrypt the crypto
+/// identity.
+///
+/// * `rounds` - The number of rounds that should be used for the key
+/// derivation, see [`encrypt_room_key_export()`].
+///
+/// # Panics
+///
+/// This method will panic if it can't get enough randomness from the OS to
+/// encrypt the crypto identity securely.
+pub fn encrypt_crypto_identity_export(
+    identity: &CryptoIdentityExport,
+    passphrase: &str,
+    rounds: u32,
+) -> Result<String, SerdeError> {
+    let mut plaintext = serde_json::to_string(identity)?.into_bytes();
+    let ciphertext = encrypt_helper(&plaintext, passphrase, rounds);
+
+    plaintext.zeroize();
+
+    Ok([IDENTITY_HEADER.to_owned(), ciphertext, IDENTITY_FOOTER.to_owned()].join("\n"))
+}
+
+fn decrypt_export<T: DeserializeOwned>(
+    mut input: impl Read,
+    passphrase: &str,
+    header: &str,
+    footer: &str,
+) -> Result<T, KeyExportError> {
+    let mut x: String = String::new();
+
+    input.read_to_string(&mut x)?;
+
+    if !(x.trim_start().starts_with(header) && x.trim_end().ends_with(footer)) {
+        return Err(KeyExportError::InvalidHeaders);
+    }
+
+    let payload: String =
+        x.lines().filter(|l| !(l.starts_with(header) || l.starts_with(footer))).collect();
+
+    let mut decrypted = decrypt_helper(&payload, passphrase)?;
+
+    let ret = serde_json::from_str(&decrypted);
+
+    decrypted.zeroize();
+
+    Ok(ret?)
+}
+
 fn encrypt_helper(plaintext: &[u8], passphrase: &str, rounds: u32) -> String {
     let mut salt = [0u8; SALT_SIZE];
     let mut rng = thread_rng();
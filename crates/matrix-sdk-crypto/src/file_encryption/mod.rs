@@ -4,4 +4,7 @@ mod key_export;
 pub use attachments::{
     AttachmentDecryptor, AttachmentEncryptor, DecryptorError, MediaEncryptionInfo,
 };
-pub use key_export::{decrypt_room_key_export, encrypt_room_key_export, KeyExportError};
+pub use key_export::{
+    decrypt_crypto_identity_export, decrypt_room_key_export, encrypt_crypto_identity_export,
+    encrypt_room_key_export, KeyExportError,
+};
@@ -37,10 +37,14 @@ use ruma::{
     DeviceId, DeviceKeyAlgorithm, OwnedDeviceId, OwnedTransactionId, OwnedUserId, RoomId,
     TransactionId, UserId,
 };
+#[cfg(feature = "automatic-room-key-forwarding")]
+use tokio::sync::broadcast;
 use tracing::{debug, field::debug, info, instrument, trace, warn, Span};
 use vodozemac::{megolm::SessionOrdering, Curve25519PublicKey};
 
 use super::{GossipRequest, GossippedSecret, RequestEvent, RequestInfo, SecretInfo, WaitQueue};
+#[cfg(feature = "automatic-room-key-forwarding")]
+use super::{RoomKeyForwardingPolicy, RoomKeyRequestDecision, RoomKeyRequestOutcome};
 use crate::{
     error::{EventError, OlmError, OlmResult},
     identities::IdentityManager,
@@ -77,6 +81,17 @@ pub(crate) struct GossipMachineInner {
     /// Whether we should respond to incoming `m.room_key_request` messages.
     room_key_forwarding_enabled: AtomicBool,
 
+    /// Which devices we forward room keys to, if `None` the room keys are
+    /// forwarded to our own verified devices and to the devices that already
+    /// received them.
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    room_key_forwarding_policy: StdRwLock<Option<RoomKeyForwardingPolicy>>,
+
+    /// The sender side of a broadcast channel which sends out the outcomes of
+    /// the incoming `m.room_key_request` messages.
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    room_key_request_outcomes: broadcast::Sender<RoomKeyRequestOutcome>,
+
     /// Whether we should send out `m.room_key_request` messages.
     room_key_requests_enabled: AtomicBool,
 
@@ -106,6 +121,10 @@ impl GossipMachine {
                 wait_queue: WaitQueue::new(),
                 users_for_key_claim,
                 room_key_forwarding_enabled,
+                #[cfg(feature = "automatic-room-key-forwarding")]
+                room_key_forwarding_policy: Default::default(),
+                #[cfg(feature = "automatic-room-key-forwarding")]
+                room_key_request_outcomes: broadcast::Sender::new(10),
                 room_key_requests_enabled,
                 identity_manager,
             }),
@@ -125,6 +144,23 @@ impl GossipMachine {
         self.inner.room_key_forwarding_enabled.load(Ordering::SeqCst)
     }
 
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    pub fn set_room_key_forwarding_policy(&self, policy: Option<RoomKeyForwardingPolicy>) {
+        *self.inner.room_key_forwarding_policy.write().unwrap() = policy;
+    }
+
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    pub fn room_key_forwarding_policy(&self) -> Option<RoomKeyForwardingPolicy> {
+        *self.inner.room_key_forwarding_policy.read().unwrap()
+    }
+
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    pub fn subscribe_to_room_key_request_outcomes(
+        &self,
+    ) -> broadcast::Receiver<RoomKeyRequestOutcome> {
+        self.inner.room_key_request_outcomes.subscribe()
+    }
+
     /// Configure whether we should send outgoing `m.room_key_request`s on
     /// decryption failure.
     #[cfg(feature = "automatic-room-key-forwarding")]
@@ -442,7 +478,22 @@ impl GossipMachine {
             return Ok(None);
         };
 
-        match self.should_share_key(&device, session).await {
+        let decision = self.should_share_key(&device, session).await;
+
+        let _ = self.inner.room_key_request_outcomes.send(RoomKeyRequestOutcome {
+            sender: event.sender.clone(),
+            requesting_device_id: event.content.requesting_device_id.clone(),
+            room_id: session.room_id().to_owned(),
+            session_id: session.session_id().to_owned(),
+            decision: match &decision {
+                Ok(message_index) => {
+                    RoomKeyRequestDecision::Forwarded { message_index: *message_index }
+                }
+                Err(e) => RoomKeyRequestDecision::Rejected(e.clone()),
+            },
+        });
+
+        match decision {
             Ok(message_index) => {
                 self.try_to_forward_room_key(event, device, session, message_index).await
             }
@@ -625,6 +676,32 @@ impl GossipMachine {
         use super::KeyForwardDecision;
         use crate::olm::ShareState;
 
+        let policy = self.room_key_forwarding_policy();
+        let is_own_device = device.user_id() == self.user_id();
+
+        match policy {
+            Some(RoomKeyForwardingPolicy::Never) => {
+                return Err(KeyForwardDecision::ForwardingDisabled);
+            }
+            Some(RoomKeyForwardingPolicy::OwnVerifiedDevices) => {
+                return if !is_own_device {
+                    Err(KeyForwardDecision::ForbiddenByPolicy)
+                } else if device.is_verified() {
+                    Ok(None)
+                } else {
+                    Err(KeyForwardDecision::UntrustedDevice)
+                };
+            }
+            Some(RoomKeyForwardingPolicy::VerifiedUsers) if !device.is_verified() => {
+                return Err(if is_own_device {
+                    KeyForwardDecision::UntrustedDevice
+                } else {
+                    KeyForwardDecision::ForbiddenByPolicy
+                });
+            }
+            Some(RoomKeyForwardingPolicy::VerifiedUsers) | None => {}
+        }
+
         let outbound_session = self
             .inner
             .outbound_group_sessions
@@ -803,9 +880,27 @@ impl GossipMachine {
     }
 
     /// Mark the outgoing request as sent.
+    #[cfg(test)]
     pub async fn mark_outgoing_request_as_sent(
         &self,
         id: &TransactionId,
+    ) -> Result<(), CryptoStoreError> {
+        let mut changes = Changes::default();
+        self.collect_outgoing_request_as_sent(id, &mut changes).await?;
+
+        if !changes.is_empty() {
+            self.inner.store.save_changes(changes).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Mark the outgoing request as sent, adding the change to `changes` so
+    /// it can be saved along with other changes.
+    pub async fn collect_outgoing_request_as_sent(
+        &self,
+        id: &TransactionId,
+        changes: &mut Changes,
     ) -> Result<(), CryptoStoreError> {
         let info = self.inner.store.get_outgoing_secret_requests(id).await?;
 
@@ -817,7 +912,7 @@ impl GossipMachine {
                 "Marking outgoing secret request as sent"
             );
             info.sent_out = true;
-            self.save_outgoing_key_info(info).await?;
+            changes.key_requests.push(info);
         }
 
         self.inner.outgoing_requests.write().unwrap().remove(id);
@@ -1111,7 +1206,7 @@ mod tests {
     use super::GossipMachine;
     #[cfg(feature = "automatic-room-key-forwarding")]
     use crate::{
-        gossiping::KeyForwardDecision,
+        gossiping::{KeyForwardDecision, RoomKeyForwardingPolicy},
         olm::OutboundGroupSession,
         types::{
             events::{
@@ -1667,6 +1762,80 @@ mod tests {
         assert_matches!(machine.should_share_key(&own_device, &other_inbound).await, Ok(None));
     }
 
+    #[async_test]
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    async fn test_should_share_key_with_policy() {
+        let machine = get_machine_test_helper().await;
+        let account = account();
+
+        let own_device =
+            machine.inner.store.get_device(alice_id(), alice2_device_id()).await.unwrap().unwrap();
+
+        let bob_device = ReadOnlyDevice::from_account(&bob_account());
+        machine.inner.store.save_devices(&[bob_device]).await.unwrap();
+        let bob_device =
+            machine.inner.store.get_device(bob_id(), bob_device_id()).await.unwrap().unwrap();
+
+        let (outbound, inbound) = account.create_group_session_pair_with_defaults(room_id()).await;
+
+        let mut changes = Changes::default();
+        changes.outbound_group_sessions.push(outbound.clone());
+        changes.inbound_group_sessions.push(inbound.clone());
+        machine.inner.store.save_changes(changes).await.unwrap();
+        machine.inner.outbound_group_sessions.insert(outbound.clone());
+
+        // The session was shared with Bob, so the key is forwarded to him
+        // without a policy.
+        outbound
+            .mark_shared_with(
+                bob_device.user_id(),
+                bob_device.device_id(),
+                bob_device.curve25519_key().unwrap(),
+            )
+            .await;
+        own_device.set_trust_state(LocalTrust::Verified);
+        assert_matches!(machine.should_share_key(&bob_device, &inbound).await, Ok(Some(0)));
+
+        // Nothing is forwarded if forwarding is disabled by the policy.
+        machine.set_room_key_forwarding_policy(Some(RoomKeyForwardingPolicy::Never));
+        assert_matches!(
+            machine.should_share_key(&own_device, &inbound).await,
+            Err(KeyForwardDecision::ForwardingDisabled)
+        );
+        assert_matches!(
+            machine.should_share_key(&bob_device, &inbound).await,
+            Err(KeyForwardDecision::ForwardingDisabled)
+        );
+
+        // Only our own verified devices get the keys.
+        machine.set_room_key_forwarding_policy(Some(RoomKeyForwardingPolicy::OwnVerifiedDevices));
+        assert_matches!(machine.should_share_key(&own_device, &inbound).await, Ok(None));
+        bob_device.set_trust_state(LocalTrust::Verified);
+        assert_matches!(
+            machine.should_share_key(&bob_device, &inbound).await,
+            Err(KeyForwardDecision::ForbiddenByPolicy)
+        );
+        own_device.set_trust_state(LocalTrust::Unset);
+        assert_matches!(
+            machine.should_share_key(&own_device, &inbound).await,
+            Err(KeyForwardDecision::UntrustedDevice)
+        );
+
+        // The verified devices of other users get the keys too, if the session
+        // was shared with them.
+        machine.set_room_key_forwarding_policy(Some(RoomKeyForwardingPolicy::VerifiedUsers));
+        assert_matches!(machine.should_share_key(&bob_device, &inbound).await, Ok(Some(0)));
+        bob_device.set_trust_state(LocalTrust::Unset);
+        assert_matches!(
+            machine.should_share_key(&bob_device, &inbound).await,
+            Err(KeyForwardDecision::ForbiddenByPolicy)
+        );
+        assert_matches!(
+            machine.should_share_key(&own_device, &inbound).await,
+            Err(KeyForwardDecision::UntrustedDevice)
+        );
+    }
+
     #[cfg(feature = "automatic-room-key-forwarding")]
     async fn test_key_share_cycle(algorithm: EventEncryptionAlgorithm) {
         let (alice_machine, group_session, bob_machine) =
@@ -20,6 +20,8 @@ use std::{
 };
 
 pub(crate) use machine::GossipMachine;
+#[cfg(feature = "automatic-room-key-forwarding")]
+use ruma::OwnedRoomId;
 use ruma::{
     events::{
         room_key_request::{Action, ToDeviceRoomKeyRequestEventContent},
@@ -80,6 +82,65 @@ pub enum KeyForwardDecision {
     /// accidentally or maliciously changed their curve25519 sender key.
     #[error("the device has changed their curve25519 sender key")]
     ChangedSenderKey,
+    /// The [`RoomKeyForwardingPolicy`] is [`RoomKeyForwardingPolicy::Never`].
+    #[error("room key forwarding is disabled")]
+    ForwardingDisabled,
+    /// The [`RoomKeyForwardingPolicy`] doesn't allow forwarding room keys to
+    /// the requesting device.
+    #[error("the room key forwarding policy doesn't allow forwarding to the requesting device")]
+    ForbiddenByPolicy,
+}
+
+/// Which devices we forward room keys to, when they request them with an
+/// `m.room_key_request`.
+///
+/// Whatever the policy, room keys are only forwarded if room key forwarding
+/// is enabled, see [`OlmMachine::set_room_key_forwarding_enabled()`].
+///
+/// [`OlmMachine::set_room_key_forwarding_enabled()`]: crate::OlmMachine::set_room_key_forwarding_enabled
+#[cfg(feature = "automatic-room-key-forwarding")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RoomKeyForwardingPolicy {
+    /// Never forward room keys.
+    Never,
+    /// Only forward room keys to our own verified devices.
+    OwnVerifiedDevices,
+    /// Forward room keys to our own verified devices, and to the verified
+    /// devices of other users that already received the room key when it was
+    /// shared.
+    VerifiedUsers,
+}
+
+/// What was decided about an incoming `m.room_key_request`.
+#[cfg(feature = "automatic-room-key-forwarding")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RoomKeyRequestDecision {
+    /// The room key is forwarded to the requesting device, once an Olm session
+    /// with it is established if there isn't one yet.
+    Forwarded {
+        /// The first message index of the forwarded room key, if it isn't
+        /// forwarded from the earliest known index.
+        message_index: Option<u32>,
+    },
+    /// The room key isn't forwarded.
+    Rejected(KeyForwardDecision),
+}
+
+/// An incoming `m.room_key_request` for a room key we have, and what was
+/// decided about it.
+#[cfg(feature = "automatic-room-key-forwarding")]
+#[derive(Clone, Debug)]
+pub struct RoomKeyRequestOutcome {
+    /// The user that requested the room key.
+    pub sender: OwnedUserId,
+    /// The device that requested the room key.
+    pub requesting_device_id: OwnedDeviceId,
+    /// The room of the requested room key.
+    pub room_id: OwnedRoomId,
+    /// The ID of the requested room key.
+    pub session_id: String,
+    /// What was decided about the request.
+    pub decision: RoomKeyRequestDecision,
 }
 
 /// A struct describing an outgoing key request.
@@ -440,6 +440,32 @@ impl Device {
 
         self.encrypt(event_type, content).await
     }
+
+    /// Encrypt the given to-device event content for this `Device`, and save
+    /// the Olm session that was used.
+    ///
+    /// An Olm session with the device must exist already, which can be
+    /// ensured with [`OlmMachine::get_missing_sessions()`].
+    ///
+    /// # Arguments
+    ///
+    /// * `event_type` - The type of the event that should be encrypted.
+    ///
+    /// * `content` - The content of the event that should be encrypted.
+    ///
+    /// [`OlmMachine::get_missing_sessions()`]: crate::OlmMachine::get_missing_sessions
+    pub async fn encrypt_event_raw(
+        &self,
+        event_type: &str,
+        content: &Raw<AnyToDeviceEventContent>,
+    ) -> OlmResult<Raw<ToDeviceEncryptedEventContent>> {
+        let (used_session, encrypted) = self.encrypt(event_type, content).await?;
+
+        let changes = Changes { sessions: vec![used_session], ..Default::default() };
+        self.verification_machine.store.save_changes(changes).await?;
+
+        Ok(encrypted)
+    }
 }
 
 /// A read only view over all devices belonging to a user.
@@ -746,9 +746,70 @@ impl IdentityManager {
             .await?;
         }
 
+        self.mark_verified_identities(&mut changes).await?;
+
         Ok((changes, changed_identity))
     }
 
+    /// Remember which identities of other users are verified by our own
+    /// identity, to notice when a verified user replaces their identity.
+    ///
+    /// Unchanged identities that weren't known to be verified are persisted
+    /// right away, without reporting them as changed: only our bookkeeping of
+    /// them changed, not their keys.
+    async fn mark_verified_identities(&self, changes: &mut IdentityChanges) -> StoreResult<()> {
+        let own_identity = changes
+            .new
+            .iter()
+            .chain(&changes.changed)
+            .chain(&changes.unchanged)
+            .find_map(|i| i.own().cloned());
+
+        let own_identity = match own_identity {
+            Some(identity) => identity,
+            None => {
+                let Some(identity) =
+                    self.store.get_user_identity(self.user_id()).await?.and_then(|i| i.into_own())
+                else {
+                    return Ok(());
+                };
+                identity
+            }
+        };
+
+        for identity in changes.new.iter_mut().chain(&mut changes.changed) {
+            if let ReadOnlyUserIdentities::Other(identity) = identity {
+                if own_identity.is_identity_signed(identity).is_ok() {
+                    identity.mark_as_previously_verified();
+                }
+            }
+        }
+
+        let mut newly_verified = Vec::new();
+
+        for identity in &mut changes.unchanged {
+            if let ReadOnlyUserIdentities::Other(identity) = identity {
+                if own_identity.is_identity_signed(identity).is_ok()
+                    && identity.mark_as_previously_verified()
+                {
+                    newly_verified.push(identity.clone().into());
+                }
+            }
+        }
+
+        if !newly_verified.is_empty() {
+            // Save them in the underlying store directly, the wrapper would
+            // broadcast them as changed identities.
+            let changes = Changes {
+                identities: IdentityChanges { changed: newly_verified, ..Default::default() },
+                ..Default::default()
+            };
+            (*self.store).save_changes(changes).await?;
+        }
+
+        Ok(())
+    }
+
     /// Generate an "out-of-band" key query request for the given set of users.
     ///
     /// Unlike the regular key query requests returned by `users_for_key_query`,
@@ -1340,8 +1401,12 @@ pub(crate) mod tests {
         device_id, key_query, manager_test_helper, other_key_query, other_user_id, user_id,
     };
     use crate::{
-        identities::manager::testing::{other_key_query_cross_signed, own_key_query},
+        identities::{
+            manager::testing::{other_key_query_cross_signed, own_key_query},
+            ReadOnlyUserIdentity,
+        },
         olm::PrivateCrossSigningIdentity,
+        store::{Changes, IdentityChanges},
     };
 
     fn key_query_with_failures() -> KeysQueryResponse {
@@ -1789,4 +1854,63 @@ pub(crate) mod tests {
 
         manager.take();
     }
+
+    #[async_test]
+    async fn test_newly_verified_identity_is_not_reported_as_changed() {
+        let manager = manager_test_helper(user_id(), device_id()).await;
+
+        // Our own identity is known.
+        let private_identity = manager.store.private_identity();
+        let identity_request = private_identity.lock().await.as_upload_request().await;
+        let device_keys =
+            manager.store.cache().await.unwrap().account().await.unwrap().device_keys();
+        manager
+            .receive_keys_query_response(
+                &TransactionId::new(),
+                &key_query(identity_request, device_keys),
+            )
+            .await
+            .unwrap();
+
+        // Bob's identity is signed by our user-signing key, but we don't know
+        // yet that it is verified.
+        let bob_id = user_id!("@bob:localhost");
+        let bob_private = PrivateCrossSigningIdentity::new(bob_id.to_owned());
+        let mut bob = ReadOnlyUserIdentity::from_private(&bob_private).await;
+        let user_signing = private_identity.lock().await.user_signing_key.clone();
+        bob.master_key = user_signing
+            .lock()
+            .await
+            .as_ref()
+            .unwrap()
+            .sign_user(&bob)
+            .unwrap()
+            .try_into()
+            .unwrap();
+
+        let changes = Changes {
+            identities: IdentityChanges { new: vec![bob.clone().into()], ..Default::default() },
+            ..Default::default()
+        };
+        manager.store.save_changes(changes).await.unwrap();
+
+        let stream = manager.store.identities_stream_raw();
+        pin_mut!(stream);
+
+        let response = KeysQueryResponse::try_from_http_response(response_from_file(&json!({
+            "device_keys": {},
+            "failures": {},
+            "master_keys": { bob_id.as_str(): bob.master_key() },
+            "self_signing_keys": { bob_id.as_str(): bob.self_signing_key() },
+        })))
+        .unwrap();
+        manager.receive_keys_query_response(&TransactionId::new(), &response).await.unwrap();
+
+        // The identity didn't change, so no update is emitted, but it is now
+        // remembered as verified.
+        assert_pending!(stream);
+
+        let stored = manager.store.get_user_identity(bob_id).await.unwrap().unwrap();
+        assert!(stored.other().unwrap().was_previously_verified());
+    }
 }
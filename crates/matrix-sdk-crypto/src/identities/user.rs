@@ -229,6 +229,78 @@ impl UserIdentity {
         self.own_identity.as_ref().is_some_and(|o| o.is_identity_signed(&self.inner).is_ok())
     }
 
+    /// Was this identity verified at some point, but isn't verified anymore?
+    ///
+    /// This happens when the user replaced their cross-signing keys after we
+    /// verified them. Until the new identity is verified, or the violation is
+    /// acknowledged with [`UserIdentity::withdraw_verification()`], sharing
+    /// room keys with this user fails if the encryption settings require
+    /// verified devices.
+    pub fn has_verification_violation(&self) -> bool {
+        self.was_previously_verified() && !self.is_verified()
+    }
+
+    /// Accept the current master key of this user, resolving a pin violation.
+    ///
+    /// See [`ReadOnlyUserIdentity::has_pin_violation()`].
+    pub async fn pin_current_identity(&self) -> Result<(), CryptoStoreError> {
+        let mut identity = self.inner.clone();
+        identity.pin_current_master_key();
+        self.save_changed_identity(identity).await
+    }
+
+    /// Forget that this identity was verified at some point, resolving a
+    /// verification violation.
+    ///
+    /// The current master key is pinned as well, the user is then treated like
+    /// any other unverified user. See
+    /// [`UserIdentity::has_verification_violation()`].
+    pub async fn withdraw_verification(&self) -> Result<(), CryptoStoreError> {
+        let mut identity = self.inner.clone();
+        identity.withdraw_verification();
+        self.save_changed_identity(identity).await
+    }
+
+    /// Remember that this identity is verified, if it is.
+    ///
+    /// Identities are marked as verified when they are received, which misses
+    /// the identities that become verified later on, once our user-signing key
+    /// is imported. This is needed for
+    /// [`UserIdentity::has_verification_violation()`] to notice when such an
+    /// identity changes.
+    ///
+    /// Returns `true` if the identity wasn't marked as verified already.
+    pub async fn remember_verification(&self) -> Result<bool, CryptoStoreError> {
+        if !self.is_verified() {
+            return Ok(false);
+        }
+
+        let mut identity = self.inner.clone();
+
+        if identity.mark_as_previously_verified() {
+            self.save_changed_identity(identity).await?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    async fn save_changed_identity(
+        &self,
+        identity: ReadOnlyUserIdentity,
+    ) -> Result<(), CryptoStoreError> {
+        let changes = Changes {
+            identities: IdentityChanges {
+                changed: vec![identity.into()],
+                new: vec![],
+                unchanged: vec![],
+            },
+            ..Default::default()
+        };
+
+        self.verification_machine.store.save_changes(changes).await
+    }
+
     /// Manually verify this user.
     ///
     /// This method will attempt to sign the user identity using our private
@@ -371,6 +443,15 @@ pub struct ReadOnlyUserIdentity {
     user_id: OwnedUserId,
     pub(crate) master_key: MasterPubkey,
     self_signing_key: SelfSigningPubkey,
+    /// The master key we accepted for this user, if it isn't the current one.
+    ///
+    /// The first master key we see for a user is pinned, as well as the
+    /// master key of a verified identity.
+    #[serde(default)]
+    pinned_master_key: Option<MasterPubkey>,
+    /// Whether we verified this identity at some point.
+    #[serde(default)]
+    previously_verified: bool,
 }
 
 impl PartialEq for ReadOnlyUserIdentity {
@@ -386,11 +467,15 @@ impl PartialEq for ReadOnlyUserIdentity {
     ///
     /// The verification state of an identity depends on the signatures of the
     /// master key, requiring their inclusion in our `PartialEq` implementation.
+    /// For the same reason, the pinned master key and whether the identity was
+    /// previously verified are compared as well.
     fn eq(&self, other: &Self) -> bool {
         self.user_id == other.user_id
             && self.master_key == other.master_key
             && self.self_signing_key == other.self_signing_key
             && self.master_key.signatures() == other.master_key.signatures()
+            && self.pinned_master_key == other.pinned_master_key
+            && self.previously_verified == other.previously_verified
     }
 }
 
@@ -411,7 +496,13 @@ impl ReadOnlyUserIdentity {
     ) -> Result<Self, SignatureError> {
         master_key.verify_subkey(&self_signing_key)?;
 
-        Ok(Self { user_id: master_key.user_id().into(), master_key, self_signing_key })
+        Ok(Self {
+            user_id: master_key.user_id().into(),
+            master_key,
+            self_signing_key,
+            pinned_master_key: None,
+            previously_verified: false,
+        })
     }
 
     #[cfg(test)]
@@ -420,7 +511,13 @@ impl ReadOnlyUserIdentity {
         let self_signing_key =
             identity.self_signing_key.lock().await.as_ref().unwrap().public_key.clone();
 
-        Self { user_id: identity.user_id().into(), master_key, self_signing_key }
+        Self {
+            user_id: identity.user_id().into(),
+            master_key,
+            self_signing_key,
+            pinned_master_key: None,
+            previously_verified: false,
+        }
     }
 
     /// Get the user id of this identity.
@@ -438,8 +535,50 @@ impl ReadOnlyUserIdentity {
         &self.self_signing_key
     }
 
+    /// Did the master key of this user change since we accepted it?
+    ///
+    /// This is the case if the user replaced their cross-signing keys, and we
+    /// neither verified the new identity nor pinned it with
+    /// [`UserIdentity::pin_current_identity()`].
+    pub fn has_pin_violation(&self) -> bool {
+        self.pinned_master_key.is_some()
+    }
+
+    /// Did we verify this identity at some point?
+    ///
+    /// This stays true when the identity changes, see
+    /// [`UserIdentity::has_verification_violation()`].
+    pub fn was_previously_verified(&self) -> bool {
+        self.previously_verified
+    }
+
+    /// Remember that this identity is verified, and pin its master key.
+    ///
+    /// Returns `true` if this changed the identity.
+    pub(crate) fn mark_as_previously_verified(&mut self) -> bool {
+        let changed = !self.previously_verified || self.pinned_master_key.is_some();
+
+        self.previously_verified = true;
+        self.pinned_master_key = None;
+
+        changed
+    }
+
+    /// Pin the current master key of this identity.
+    pub(crate) fn pin_current_master_key(&mut self) {
+        self.pinned_master_key = None;
+    }
+
+    /// Forget that this identity was verified, and pin its master key.
+    pub(crate) fn withdraw_verification(&mut self) {
+        self.previously_verified = false;
+        self.pinned_master_key = None;
+    }
+
     /// Update the identity with a new master key and self signing key.
     ///
+    /// The previously pinned master key and verification state are kept.
+    ///
     /// # Arguments
     ///
     /// * `master_key` - The new master key of the user identity.
@@ -456,7 +595,13 @@ impl ReadOnlyUserIdentity {
     ) -> Result<bool, SignatureError> {
         master_key.verify_subkey(&self_signing_key)?;
 
-        let new = Self::new(master_key, self_signing_key)?;
+        let mut new = Self::new(master_key, self_signing_key)?;
+
+        let pinned_master_key =
+            self.pinned_master_key.clone().unwrap_or_else(|| self.master_key.clone());
+        new.pinned_master_key = (pinned_master_key != new.master_key).then_some(pinned_master_key);
+        new.previously_verified = self.previously_verified;
+
         let changed = new != *self;
 
         *self = new;
@@ -784,7 +929,7 @@ pub(crate) mod tests {
 
     use super::{
         testing::{device, get_other_identity, get_own_identity},
-        ReadOnlyOwnUserIdentity, ReadOnlyUserIdentities,
+        ReadOnlyOwnUserIdentity, ReadOnlyUserIdentities, ReadOnlyUserIdentity, UserIdentity,
     };
     use crate::{
         identities::{manager::testing::own_key_query, Device},
@@ -984,6 +1129,85 @@ pub(crate) mod tests {
         );
     }
 
+    #[async_test]
+    async fn test_identity_change_pinning() {
+        let user_id = user_id!("@example2:localhost");
+        let first_private = PrivateCrossSigningIdentity::new(user_id.to_owned());
+        let second_private = PrivateCrossSigningIdentity::new(user_id.to_owned());
+        let first = ReadOnlyUserIdentity::from_private(&first_private).await;
+        let second = ReadOnlyUserIdentity::from_private(&second_private).await;
+
+        let mut identity = first.clone();
+        assert!(!identity.has_pin_violation());
+        assert!(!identity.was_previously_verified());
+
+        assert!(identity.mark_as_previously_verified());
+        assert!(!identity.mark_as_previously_verified());
+
+        // The user replaces their identity, the old master key stays pinned.
+        let changed = identity
+            .update(second.master_key().clone(), second.self_signing_key().clone())
+            .unwrap();
+        assert!(changed);
+        assert!(identity.has_pin_violation());
+        assert!(identity.was_previously_verified());
+
+        identity.withdraw_verification();
+        assert!(!identity.has_pin_violation());
+        assert!(!identity.was_previously_verified());
+
+        // Withdrawing the verification pinned the second master key, so going
+        // back to the first one is a violation, and going back to the pinned
+        // one isn't.
+        identity.update(first.master_key().clone(), first.self_signing_key().clone()).unwrap();
+        assert!(identity.has_pin_violation());
+        identity.update(second.master_key().clone(), second.self_signing_key().clone()).unwrap();
+        assert!(!identity.has_pin_violation());
+    }
+
+    #[async_test]
+    async fn test_remember_verification() {
+        let account = Account::with_device_id(user_id!("@alice:localhost"), device_id!("ALICE"));
+        let (private_identity, _, _) = PrivateCrossSigningIdentity::with_account(&account).await;
+        let own_identity = private_identity.to_public_identity().await.unwrap();
+
+        let bob_account = Account::with_device_id(user_id!("@bob:localhost"), device_id!("BOB"));
+        let (bob_private, _, _) = PrivateCrossSigningIdentity::with_account(&bob_account).await;
+        let bob_public = ReadOnlyUserIdentity::from_private(&bob_private).await;
+
+        let store = Arc::new(CryptoStoreWrapper::new(account.user_id(), MemoryStore::new()));
+        let verification_machine = VerificationMachine::new(
+            account.static_data.clone(),
+            Arc::new(Mutex::new(private_identity.clone())),
+            store.clone(),
+        );
+
+        // Bob isn't verified, there is nothing to remember.
+        let identity = UserIdentity {
+            inner: bob_public.clone(),
+            own_identity: Some(own_identity.clone()),
+            verification_machine: verification_machine.clone(),
+        };
+        assert!(!identity.remember_verification().await.unwrap());
+
+        // Bob's master key gets signed by our user-signing key.
+        let mut signed = bob_public.clone();
+        let user_signing = private_identity.user_signing_key.lock().await;
+        signed.master_key =
+            user_signing.as_ref().unwrap().sign_user(&bob_public).unwrap().try_into().unwrap();
+        drop(user_signing);
+
+        let identity =
+            UserIdentity { inner: signed, own_identity: Some(own_identity), verification_machine };
+        assert!(identity.is_verified());
+        assert!(!identity.was_previously_verified());
+
+        assert!(identity.remember_verification().await.unwrap());
+
+        let stored = store.get_user_identity(bob_public.user_id()).await.unwrap().unwrap();
+        assert!(stored.other().unwrap().was_previously_verified());
+    }
+
     #[test]
     fn filter_devices_to_request() {
         let response = own_key_query();
@@ -25,6 +25,7 @@ mod gossiping;
 mod identities;
 mod machine;
 pub mod olm;
+pub mod pseudo_ids;
 pub mod requests;
 pub mod secret_storage;
 mod session_manager;
@@ -72,10 +73,15 @@ impl RoomKeyImportResult {
 
 pub use error::{EventError, MegolmError, OlmError, SessionCreationError, SignatureError};
 pub use file_encryption::{
-    decrypt_room_key_export, encrypt_room_key_export, AttachmentDecryptor, AttachmentEncryptor,
-    DecryptorError, KeyExportError, MediaEncryptionInfo,
+    decrypt_crypto_identity_export, decrypt_room_key_export, encrypt_crypto_identity_export,
+    encrypt_room_key_export, AttachmentDecryptor, AttachmentEncryptor, DecryptorError,
+    KeyExportError, MediaEncryptionInfo,
 };
 pub use gossiping::{GossipRequest, GossippedSecret};
+#[cfg(feature = "automatic-room-key-forwarding")]
+pub use gossiping::{
+    KeyForwardDecision, RoomKeyForwardingPolicy, RoomKeyRequestDecision, RoomKeyRequestOutcome,
+};
 pub use identities::{
     Device, LocalTrust, OwnUserIdentity, ReadOnlyDevice, ReadOnlyOwnUserIdentity,
     ReadOnlyUserIdentities, ReadOnlyUserIdentity, UserDevices, UserIdentities, UserIdentity,
@@ -83,13 +89,14 @@ pub use identities::{
 pub use machine::{CrossSigningBootstrapRequests, EncryptionSyncChanges, OlmMachine};
 #[cfg(feature = "qrcode")]
 pub use matrix_sdk_qrcode;
-pub use olm::{Account, CrossSigningStatus, EncryptionSettings, Session};
+pub use olm::{Account, CrossSigningStatus, EncryptionSettings, RoomKeyRotationLimits, Session};
 pub use requests::{
     IncomingResponse, KeysBackupRequest, KeysQueryRequest, OutgoingRequest, OutgoingRequests,
     OutgoingVerificationRequest, RoomMessageRequest, ToDeviceRequest, UploadSigningKeysRequest,
 };
 pub use store::{
-    CrossSigningKeyExport, CryptoStoreError, SecretImportError, SecretInfo, TrackedUser,
+    CrossSigningKeyExport, CryptoIdentityExport, CryptoStoreError, SecretImportError, SecretInfo,
+    TrackedUser,
 };
 pub use verification::{
     format_emojis, AcceptSettings, AcceptedProtocols, CancelInfo, Emoji, EmojiShortAuthString, Sas,
@@ -18,10 +18,17 @@ use std::{
     time::Duration,
 };
 
+#[cfg(feature = "automatic-room-key-forwarding")]
+use futures_core::Stream;
+#[cfg(feature = "automatic-room-key-forwarding")]
+use futures_util::StreamExt;
 use itertools::Itertools;
-use matrix_sdk_common::deserialized_responses::{
-    AlgorithmInfo, DeviceLinkProblem, EncryptionInfo, TimelineEvent, VerificationLevel,
-    VerificationState,
+use matrix_sdk_common::{
+    clock::{system_clock, Clock},
+    deserialized_responses::{
+        AlgorithmInfo, DeviceLinkProblem, EncryptionInfo, TimelineEvent, VerificationLevel,
+        VerificationState,
+    },
 };
 use ruma::{
     api::client::{
@@ -40,11 +47,13 @@ use ruma::{
         AnyToDeviceEvent, MessageLikeEventContent,
     },
     serde::Raw,
-    DeviceId, DeviceKeyAlgorithm, OwnedDeviceId, OwnedDeviceKeyId, OwnedTransactionId, OwnedUserId,
-    RoomId, TransactionId, UInt, UserId,
+    DeviceId, DeviceKeyAlgorithm, MilliSecondsSinceUnixEpoch, OwnedDeviceId, OwnedDeviceKeyId,
+    OwnedTransactionId, OwnedUserId, RoomId, TransactionId, UInt, UserId,
 };
 use serde_json::value::to_raw_value;
 use tokio::sync::Mutex;
+#[cfg(feature = "automatic-room-key-forwarding")]
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
 use tracing::{
     debug, error,
     field::{debug, display},
@@ -63,9 +72,10 @@ use crate::{
     identities::{user::UserIdentities, Device, IdentityManager, UserDevices},
     olm::{
         Account, CrossSigningStatus, EncryptionSettings, ExportedRoomKey, IdentityKeys,
-        InboundGroupSession, OlmDecryptionInfo, PrivateCrossSigningIdentity, SessionType,
-        StaticAccountData,
+        InboundGroupSession, KeyDistributionLogEntry, OlmDecryptionInfo,
+        PrivateCrossSigningIdentity, SessionType, StaticAccountData,
     },
+    pseudo_ids::PseudoIds,
     requests::{IncomingResponse, OutgoingRequest, UploadSigningKeysRequest},
     session_manager::{GroupSessionManager, SessionManager},
     store::{
@@ -89,9 +99,11 @@ use crate::{
         Signatures,
     },
     verification::{Verification, VerificationMachine, VerificationRequest},
-    CrossSigningKeyExport, CryptoStoreError, KeysQueryRequest, LocalTrust, ReadOnlyDevice,
-    RoomKeyImportResult, SignatureError, ToDeviceRequest,
+    CrossSigningKeyExport, CryptoIdentityExport, CryptoStoreError, KeysQueryRequest, LocalTrust,
+    ReadOnlyDevice, RoomKeyImportResult, SignatureError, ToDeviceRequest,
 };
+#[cfg(feature = "automatic-room-key-forwarding")]
+use crate::{RoomKeyForwardingPolicy, RoomKeyRequestOutcome};
 
 /// State machine implementation of the Olm/Megolm encryption protocol used for
 /// Matrix end to end encryption.
@@ -129,6 +141,8 @@ pub struct OlmMachineInner {
     identity_manager: IdentityManager,
     /// A state machine that handles creating room key backups.
     backup_machine: BackupMachine,
+    /// The source of the local time, used to date our keys.
+    clock: Arc<dyn Clock>,
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -179,6 +193,7 @@ impl OlmMachine {
             static_account,
             self.store().private_identity(),
             None,
+            self.inner.clock.clone(),
         ))
     }
 
@@ -188,12 +203,13 @@ impl OlmMachine {
         account: StaticAccountData,
         user_identity: Arc<Mutex<PrivateCrossSigningIdentity>>,
         maybe_backup_key: Option<MegolmV1BackupKey>,
+        clock: Arc<dyn Clock>,
     ) -> Self {
         let verification_machine =
             VerificationMachine::new(account.clone(), user_identity.clone(), store.clone());
         let store = Store::new(account, user_identity.clone(), store, verification_machine.clone());
 
-        let group_session_manager = GroupSessionManager::new(store.clone());
+        let group_session_manager = GroupSessionManager::new(store.clone(), clock.clone());
 
         let identity_manager = IdentityManager::new(store.clone());
 
@@ -221,6 +237,7 @@ impl OlmMachine {
             key_request_machine,
             identity_manager,
             backup_machine,
+            clock,
         });
 
         Self { inner }
@@ -245,11 +262,26 @@ impl OlmMachine {
     /// the encryption keys.
     ///
     /// [`CryptoStore`]: crate::store::CryptoStore
-    #[instrument(skip(store), fields(ed25519_key, curve25519_key))]
     pub async fn with_store(
         user_id: &UserId,
         device_id: &DeviceId,
         store: impl IntoCryptoStore,
+    ) -> StoreResult<Self> {
+        Self::with_store_and_clock(user_id, device_id, store, system_clock()).await
+    }
+
+    /// Create a new OlmMachine with the given [`CryptoStore`], using the given
+    /// [`Clock`] as the source of the local time.
+    ///
+    /// See [`OlmMachine::with_store()`] for more details.
+    ///
+    /// [`CryptoStore`]: crate::store::CryptoStore
+    #[instrument(skip(store, clock), fields(ed25519_key, curve25519_key))]
+    pub async fn with_store_and_clock(
+        user_id: &UserId,
+        device_id: &DeviceId,
+        store: impl IntoCryptoStore,
+        clock: Arc<dyn Clock>,
     ) -> StoreResult<Self> {
         let store = store.into_crypto_store();
 
@@ -271,7 +303,7 @@ impl OlmMachine {
             }
 
             None => {
-                let account = Account::with_device_id(user_id, device_id);
+                let account = Account::with_device_id_at(user_id, device_id, clock.now_ms());
                 let static_account = account.static_data().clone();
 
                 Span::current()
@@ -289,8 +321,9 @@ impl OlmMachine {
                     devices: DeviceChanges { new: vec![device], ..Default::default() },
                     ..Default::default()
                 };
-                store.save_changes(changes).await?;
-                store.save_pending_changes(PendingChanges { account: Some(account) }).await?;
+                store
+                    .save_changes_batch(PendingChanges { account: Some(account) }, changes)
+                    .await?;
 
                 debug!("Created a new Olm account");
 
@@ -327,7 +360,14 @@ impl OlmMachine {
 
         let identity = Arc::new(Mutex::new(identity));
         let store = Arc::new(CryptoStoreWrapper::new(user_id, store));
-        Ok(OlmMachine::new_helper(device_id, store, static_account, identity, maybe_backup_key))
+        Ok(OlmMachine::new_helper(
+            device_id,
+            store,
+            static_account,
+            identity,
+            maybe_backup_key,
+            clock,
+        ))
     }
 
     /// Get the crypto store associated with this `OlmMachine` instance.
@@ -335,6 +375,11 @@ impl OlmMachine {
         &self.inner.store
     }
 
+    /// Get the source of the local time of this `OlmMachine`.
+    pub(crate) fn clock(&self) -> &Arc<dyn Clock> {
+        &self.inner.clock
+    }
+
     /// The unique user id that owns this `OlmMachine` instance.
     pub fn user_id(&self) -> &UserId {
         &self.inner.user_id
@@ -406,6 +451,48 @@ impl OlmMachine {
         self.inner.key_request_machine.is_room_key_forwarding_enabled()
     }
 
+    /// Set which devices we forward room keys to, when room key forwarding is
+    /// enabled.
+    ///
+    /// If the policy is `None`, the room keys are forwarded to our own
+    /// verified devices, and to the devices that already received them when
+    /// they were shared.
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    pub fn set_room_key_forwarding_policy(&self, policy: Option<RoomKeyForwardingPolicy>) {
+        self.inner.key_request_machine.set_room_key_forwarding_policy(policy)
+    }
+
+    /// Get which devices we forward room keys to.
+    ///
+    /// See also [`OlmMachine::set_room_key_forwarding_policy`].
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    pub fn room_key_forwarding_policy(&self) -> Option<RoomKeyForwardingPolicy> {
+        self.inner.key_request_machine.room_key_forwarding_policy()
+    }
+
+    /// Receive the outcomes of the incoming `m.room_key_request`s for room keys
+    /// we have as a [`Stream`], to audit which room keys were forwarded to
+    /// which devices.
+    ///
+    /// If the reader of the stream lags too far behind, a warning will be
+    /// logged and items will be dropped.
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    pub fn room_key_request_outcomes_stream(&self) -> impl Stream<Item = RoomKeyRequestOutcome> {
+        let stream = BroadcastStream::new(
+            self.inner.key_request_machine.subscribe_to_room_key_request_outcomes(),
+        );
+
+        stream.filter_map(|result| async move {
+            match result {
+                Ok(outcome) => Some(outcome),
+                Err(BroadcastStreamRecvError::Lagged(lag)) => {
+                    warn!("room_key_request_outcomes_stream missed {lag} updates");
+                    None
+                }
+            }
+        })
+    }
+
     /// Get the outgoing requests that need to be sent out.
     ///
     /// This returns a list of [`OutgoingRequest`]. Those requests need to be
@@ -931,6 +1018,35 @@ impl OlmMachine {
         self.inner.group_session_manager.share_room_key(room_id, users, encryption_settings).await
     }
 
+    /// Get the key distribution log of the given room, to audit who could
+    /// have decrypted the messages sent from this device.
+    ///
+    /// The log records every device that an outbound group session of the
+    /// room was shared with, or withheld from with a withheld code, once the
+    /// to-device request was sent. Only the entries of the latest 1000 room
+    /// key shares of the room are kept.
+    ///
+    /// # Arguments
+    ///
+    /// `room_id` - The room id of the room to get the log of.
+    ///
+    /// `session_id` - Only return the entries of the outbound group session
+    /// with this ID, for example the one of the `session_id` field of an
+    /// encrypted event.
+    pub async fn key_distribution_log(
+        &self,
+        room_id: &RoomId,
+        session_id: Option<&str>,
+    ) -> StoreResult<Vec<KeyDistributionLogEntry>> {
+        let mut log = self.inner.store.key_distribution_log(room_id).await?;
+
+        if let Some(session_id) = session_id {
+            log.retain(|entry| entry.session_id == session_id);
+        }
+
+        Ok(log)
+    }
+
     /// Receive an unencrypted verification event.
     ///
     /// This method can be used to pass verification events that are happening
@@ -1023,8 +1139,15 @@ impl OlmMachine {
     /// Mark an outgoing to-device requests as sent.
     async fn mark_to_device_request_as_sent(&self, request_id: &TransactionId) -> StoreResult<()> {
         self.inner.verification_machine.mark_request_as_sent(request_id);
-        self.inner.key_request_machine.mark_outgoing_request_as_sent(request_id).await?;
-        self.inner.group_session_manager.mark_request_as_sent(request_id).await?;
+
+        // Save everything the request changed in a single batch.
+        let mut changes = Changes::default();
+        self.inner
+            .key_request_machine
+            .collect_outgoing_request_as_sent(request_id, &mut changes)
+            .await?;
+        self.inner.group_session_manager.mark_request_as_sent(request_id, changes).await?;
+
         self.inner.session_manager.mark_outgoing_request_as_sent(request_id);
         Ok(())
     }
@@ -1202,13 +1325,13 @@ impl OlmMachine {
         let (events, changes) =
             self.preprocess_sync_changes(&mut store_transaction, sync_changes).await?;
 
-        // Technically save_changes also does the same work, so if it's slow we could
-        // refactor this to do it only once.
+        // Technically committing the changes also does the same work, so if it's slow
+        // we could refactor this to do it only once.
         let room_key_updates: Vec<_> =
             changes.inbound_group_sessions.iter().map(RoomKeyInfo::from).collect();
 
-        self.store().save_changes(changes).await?;
-        store_transaction.commit().await?;
+        // Persist all the changes of this sync at once, with the account.
+        store_transaction.commit_with_changes(changes).await?;
 
         Ok((events, room_key_updates))
     }
@@ -1230,6 +1353,7 @@ impl OlmMachine {
             account.update_key_counts(
                 sync_changes.one_time_keys_counts,
                 sync_changes.unused_fallback_keys,
+                self.inner.clock.now_ms(),
             )
         }
 
@@ -1887,6 +2011,162 @@ impl OlmMachine {
         self.store().import_cross_signing_keys(export).await
     }
 
+    /// Export the complete crypto identity of this device.
+    ///
+    /// The export contains the Olm account, the private cross signing keys,
+    /// the backup decryption key and all the room keys. It can be used to
+    /// restore this device on a new client with
+    /// [`OlmMachine::with_crypto_identity()`], for example if secret storage
+    /// or the key backup aren't available.
+    ///
+    /// **Warning**: The export allows to impersonate this device, it should
+    /// be encrypted with [`encrypt_crypto_identity_export()`] before leaving
+    /// the client.
+    ///
+    /// [`encrypt_crypto_identity_export()`]: crate::encrypt_crypto_identity_export
+    pub async fn export_crypto_identity(&self) -> StoreResult<CryptoIdentityExport> {
+        let account = self.inner.store.cache().await?.account().await?.pickle();
+        let cross_signing_keys = self.export_cross_signing_keys().await?;
+        let backup_keys = self.store().load_backup_keys().await?;
+        let room_keys = self.export_room_keys(|_| true).await?;
+
+        Ok(CryptoIdentityExport {
+            account,
+            cross_signing_keys,
+            backup_decryption_key: backup_keys.decryption_key,
+            backup_version: backup_keys.backup_version,
+            room_keys,
+        })
+    }
+
+    /// Create a new `OlmMachine` that restores the crypto identity exported by
+    /// [`OlmMachine::export_crypto_identity()`].
+    ///
+    /// The Olm account of the export replaces the one of the store, if any,
+    /// so this should only be used with a new store, before the device keys
+    /// were uploaded. If the device keys of the account of the store were
+    /// uploaded already, [`CryptoStoreError::AccountAlreadyShared`] is
+    /// returned, unless it's the account of the export.
+    ///
+    /// The private cross signing keys are imported without being checked
+    /// against the public ones, they are removed again by the next
+    /// `/keys/query` if they don't match.
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - The unique id of the user that owns this machine.
+    ///
+    /// * `device_id` - The unique id of the device that owns this machine.
+    ///
+    /// * `store` - A `CryptoStore` implementation that will be used to store
+    /// the encryption keys.
+    ///
+    /// * `export` - The crypto identity to restore, it must belong to the
+    /// given user and device.
+    pub async fn with_crypto_identity(
+        user_id: &UserId,
+        device_id: &DeviceId,
+        store: impl IntoCryptoStore,
+        export: CryptoIdentityExport,
+    ) -> StoreResult<Self> {
+        Self::with_crypto_identity_and_clock(user_id, device_id, store, export, system_clock())
+            .await
+    }
+
+    /// Create a new `OlmMachine` that restores the given crypto identity,
+    /// using the given [`Clock`] as the source of the local time.
+    ///
+    /// See [`OlmMachine::with_crypto_identity()`] for more details.
+    #[instrument(skip(store, export, clock))]
+    pub async fn with_crypto_identity_and_clock(
+        user_id: &UserId,
+        device_id: &DeviceId,
+        store: impl IntoCryptoStore,
+        export: CryptoIdentityExport,
+        clock: Arc<dyn Clock>,
+    ) -> StoreResult<Self> {
+        let CryptoIdentityExport {
+            account,
+            cross_signing_keys,
+            backup_decryption_key,
+            backup_version,
+            room_keys,
+        } = export;
+
+        if user_id != account.user_id || device_id != account.device_id {
+            return Err(CryptoStoreError::MismatchedAccount {
+                expected: (account.user_id, account.device_id),
+                got: (user_id.to_owned(), device_id.to_owned()),
+            });
+        }
+
+        let store = store.into_crypto_store();
+        let account = Account::from_pickle(account)?;
+
+        // Replacing an account whose device keys were uploaded would leave the
+        // homeserver with the keys of another account for this device.
+        if let Some(current) = store.load_account().await? {
+            let current_keys = current.identity_keys();
+            let keys = account.identity_keys();
+
+            if current.shared()
+                && (current_keys.ed25519 != keys.ed25519
+                    || current_keys.curve25519 != keys.curve25519)
+            {
+                return Err(CryptoStoreError::AccountAlreadyShared);
+            }
+        }
+
+        // The device was ours before the export, so we can mark it as verified,
+        // like a newly created one.
+        let device = ReadOnlyDevice::from_account(&account);
+        device.set_trust_state(LocalTrust::Verified);
+
+        let changes = Changes {
+            devices: DeviceChanges { new: vec![device], ..Default::default() },
+            ..Default::default()
+        };
+        store.save_changes_batch(PendingChanges { account: Some(account) }, changes).await?;
+
+        let machine = Self::with_store_and_clock(user_id, device_id, store, clock).await?;
+
+        if let Some(keys) = cross_signing_keys {
+            let identity = machine.inner.user_identity.lock().await;
+
+            match identity
+                .import_secrets_unchecked(
+                    keys.master_key.as_deref(),
+                    keys.self_signing_key.as_deref(),
+                    keys.user_signing_key.as_deref(),
+                )
+                .await
+            {
+                Ok(()) => {
+                    let changes =
+                        Changes { private_identity: Some(identity.clone()), ..Default::default() };
+                    machine.store().save_changes(changes).await?;
+                }
+                Err(e) => warn!(error = ?e, "Couldn't restore the private cross signing keys"),
+            }
+        }
+
+        if backup_decryption_key.is_some() {
+            machine
+                .backup_machine()
+                .save_decryption_key(backup_decryption_key, backup_version)
+                .await?;
+        }
+
+        let result = machine.store().import_exported_room_keys(room_keys, |_, _| {}).await?;
+        info!(
+            imported = result.imported_count,
+            total = result.total_count,
+            "Restored a crypto identity"
+        );
+
+        Ok(machine)
+    }
+
     async fn sign_with_master_key(
         &self,
         message: &str,
@@ -2049,6 +2329,11 @@ impl OlmMachine {
         DehydratedDevices { inner: self.to_owned() }
     }
 
+    /// Manage our pseudoIDs in rooms, see [`crate::pseudo_ids`].
+    pub fn pseudo_ids(&self) -> PseudoIds {
+        PseudoIds { store: self.store().clone() }
+    }
+
     #[cfg(any(feature = "testing", test))]
     /// Returns whether this `OlmMachine` is the same another one.
     ///
@@ -2057,13 +2342,68 @@ impl OlmMachine {
         Arc::ptr_eq(&self.inner, &other.inner)
     }
 
-    /// Testing purposes only.
-    #[cfg(any(feature = "testing", test))]
+    /// Get the number of signed one-time keys the server told us it has for
+    /// this device.
     pub async fn uploaded_key_count(&self) -> Result<u64, CryptoStoreError> {
         let cache = self.inner.store.cache().await?;
         let account = cache.account().await?;
         Ok(account.uploaded_key_count())
     }
+
+    /// Update the number of signed one-time keys the server has for this
+    /// device, outside of a sync response.
+    ///
+    /// New one-time keys are generated if there aren't enough of them on the
+    /// server, they will be part of the next keys upload request returned by
+    /// [`OlmMachine::outgoing_requests()`].
+    pub async fn update_key_counts(
+        &self,
+        one_time_key_counts: &BTreeMap<DeviceKeyAlgorithm, UInt>,
+    ) -> OlmResult<()> {
+        self.inner
+            .store
+            .with_transaction(|mut tr| async {
+                let account = tr.account().await?;
+                account.update_one_time_key_counts(one_time_key_counts);
+                Ok((tr, ()))
+            })
+            .await
+    }
+
+    /// Get the age of the fallback key of this device at the given time.
+    ///
+    /// Returns `None` if we don't have a fallback key, or if its creation time
+    /// isn't known.
+    pub async fn fallback_key_age(
+        &self,
+        now: MilliSecondsSinceUnixEpoch,
+    ) -> Result<Option<Duration>, CryptoStoreError> {
+        let cache = self.inner.store.cache().await?;
+        let account = cache.account().await?;
+        Ok(account.fallback_key_age(now))
+    }
+
+    /// Replace the fallback key of this device if it is older than `max_age`
+    /// at the given time.
+    ///
+    /// The new fallback key will be part of the next keys upload request
+    /// returned by [`OlmMachine::outgoing_requests()`].
+    ///
+    /// Returns `true` if the fallback key was replaced.
+    pub async fn rotate_fallback_key(
+        &self,
+        max_age: Duration,
+        now: MilliSecondsSinceUnixEpoch,
+    ) -> OlmResult<bool> {
+        self.inner
+            .store
+            .with_transaction(|mut tr| async {
+                let account = tr.account().await?;
+                let rotated = account.rotate_fallback_key(max_age, now);
+                Ok((tr, rotated))
+            })
+            .await
+    }
 }
 
 /// A set of requests to be executed when bootstrapping cross-signing using
@@ -2121,6 +2461,7 @@ pub(crate) mod testing {
 pub(crate) mod tests {
     use std::{
         collections::BTreeMap,
+        io::Cursor,
         iter,
         sync::Arc,
         time::{Duration, SystemTime},
@@ -2167,10 +2508,11 @@ pub(crate) mod tests {
 
     use super::{testing::response_from_file, CrossSigningBootstrapRequests};
     use crate::{
+        decrypt_crypto_identity_export, encrypt_crypto_identity_export,
         error::EventError,
         machine::{EncryptionSyncChanges, OlmMachine},
         olm::{InboundGroupSession, OutboundGroupSession, VerifyJson},
-        store::Changes,
+        store::{BackupDecryptionKey, Changes, MemoryStore},
         types::{
             events::{
                 room::encrypted::{EncryptedToDeviceEvent, ToDeviceEncryptedEventContent},
@@ -2181,8 +2523,8 @@ pub(crate) mod tests {
         },
         utilities::json_convert,
         verification::tests::{bob_id, outgoing_request_to_event, request_to_event},
-        Account, EncryptionSettings, LocalTrust, MegolmError, OlmError, OutgoingRequests,
-        ReadOnlyDevice, ToDeviceRequest, UserIdentities,
+        Account, CryptoStoreError, EncryptionSettings, KeyExportError, LocalTrust, MegolmError,
+        OlmError, OutgoingRequests, ReadOnlyDevice, ToDeviceRequest, UserIdentities,
     };
 
     /// These keys need to be periodically uploaded to the server.
@@ -2243,7 +2585,7 @@ pub(crate) mod tests {
             .store()
             .with_transaction(|mut tr| async {
                 let account = tr.account().await.unwrap();
-                account.generate_fallback_key_helper();
+                account.generate_fallback_key_helper(MilliSecondsSinceUnixEpoch::now());
                 account.update_uploaded_key_count(0);
                 account.generate_one_time_keys();
                 let request = machine
@@ -4071,4 +4413,103 @@ pub(crate) mod tests {
         // The waiting should successfully complete.
         wait.await.unwrap();
     }
+
+    #[async_test]
+    async fn test_crypto_identity_export_roundtrip() {
+        let (machine, _) = get_prepared_machine_test_helper(user_id(), false).await;
+        machine.bootstrap_cross_signing(false).await.unwrap();
+
+        let room_id = room_id!("!test:localhost");
+        machine.create_outbound_group_session_with_defaults_test_helper(room_id).await.unwrap();
+
+        let decryption_key = BackupDecryptionKey::new().unwrap();
+        machine
+            .backup_machine()
+            .save_decryption_key(Some(decryption_key.clone()), Some("1".to_owned()))
+            .await
+            .unwrap();
+
+        let export = machine.export_crypto_identity().await.unwrap();
+        let encrypted = encrypt_crypto_identity_export(&export, "1234", 1).unwrap();
+
+        assert_matches!(
+            decrypt_crypto_identity_export(Cursor::new(encrypted.clone()), "wrong"),
+            Err(KeyExportError::InvalidMac)
+        );
+        let export = decrypt_crypto_identity_export(Cursor::new(encrypted), "1234").unwrap();
+
+        // The export can only be restored for the device it was made for.
+        assert_matches!(
+            OlmMachine::with_crypto_identity(
+                user_id(),
+                alice_device_id(),
+                MemoryStore::new(),
+                machine.export_crypto_identity().await.unwrap(),
+            )
+            .await,
+            Err(CryptoStoreError::MismatchedAccount { .. })
+        );
+
+        let restored = OlmMachine::with_crypto_identity(
+            machine.user_id(),
+            machine.device_id(),
+            MemoryStore::new(),
+            export,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(restored.identity_keys().ed25519, machine.identity_keys().ed25519);
+        assert_eq!(restored.identity_keys().curve25519, machine.identity_keys().curve25519);
+
+        let status = restored.cross_signing_status().await;
+        assert!(status.has_master && status.has_self_signing && status.has_user_signing);
+
+        let backup_keys = restored.store().load_backup_keys().await.unwrap();
+        assert_eq!(backup_keys.decryption_key.unwrap().to_base64(), decryption_key.to_base64());
+        assert_eq!(backup_keys.backup_version.as_deref(), Some("1"));
+
+        let room_keys = restored.export_room_keys(|s| s.room_id() == room_id).await.unwrap();
+        assert_eq!(room_keys.len(), 1);
+    }
+
+    #[async_test]
+    async fn test_crypto_identity_import_keeps_a_shared_account() {
+        let store = Arc::new(MemoryStore::new());
+        let machine =
+            OlmMachine::with_store(user_id(), alice_device_id(), store.clone()).await.unwrap();
+        let own_export = machine.export_crypto_identity().await.unwrap();
+
+        // Another account for the same device, for example from a backup.
+        let other = OlmMachine::new(user_id(), alice_device_id()).await;
+        let other_export = other.export_crypto_identity().await.unwrap();
+
+        // The device keys of our account are uploaded.
+        machine
+            .store()
+            .with_transaction(|mut tr| async {
+                tr.account().await.unwrap().mark_as_shared();
+                Ok((tr, ()))
+            })
+            .await
+            .unwrap();
+
+        assert_matches!(
+            OlmMachine::with_crypto_identity(
+                user_id(),
+                alice_device_id(),
+                store.clone(),
+                other_export
+            )
+            .await,
+            Err(CryptoStoreError::AccountAlreadyShared)
+        );
+
+        // Restoring the account we already have is harmless.
+        let restored =
+            OlmMachine::with_crypto_identity(user_id(), alice_device_id(), store, own_export)
+                .await
+                .unwrap();
+        assert_eq!(restored.identity_keys().curve25519, machine.identity_keys().curve25519);
+    }
 }
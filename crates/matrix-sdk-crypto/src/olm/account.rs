@@ -17,6 +17,7 @@ use std::{
     fmt,
     ops::{Deref, Not as _},
     sync::Arc,
+    time::Duration,
 };
 
 use ruma::{
@@ -327,6 +328,9 @@ pub struct Account {
     /// needs to set this for us, depending on the count we will suggest the
     /// client to upload new keys.
     uploaded_signed_key_count: u64,
+    /// The local time at which the current fallback key was created, if it is
+    /// known.
+    fallback_key_creation_time: Option<MilliSecondsSinceUnixEpoch>,
 }
 
 impl Deref for Account {
@@ -358,6 +362,10 @@ pub struct PickledAccount {
     /// as creation time of own device
     #[serde(default = "default_account_creation_time")]
     pub creation_local_time: MilliSecondsSinceUnixEpoch,
+    /// The local time creation of the current fallback key (milliseconds since
+    /// epoch), if it is known.
+    #[serde(default)]
+    pub fallback_key_creation_time: Option<MilliSecondsSinceUnixEpoch>,
 }
 
 fn default_account_creation_time() -> MilliSecondsSinceUnixEpoch {
@@ -378,7 +386,12 @@ pub type OneTimeKeys = BTreeMap<OwnedDeviceKeyId, Raw<ruma::encryption::OneTimeK
 pub type FallbackKeys = OneTimeKeys;
 
 impl Account {
-    fn new_helper(mut account: InnerAccount, user_id: &UserId, device_id: &DeviceId) -> Self {
+    fn new_helper(
+        mut account: InnerAccount,
+        user_id: &UserId,
+        device_id: &DeviceId,
+        creation_local_time: MilliSecondsSinceUnixEpoch,
+    ) -> Self {
         let identity_keys = account.identity_keys();
 
         // Let's generate some initial one-time keys while we're here. Since we know
@@ -399,29 +412,44 @@ impl Account {
                 user_id: user_id.into(),
                 device_id: device_id.into(),
                 identity_keys: Arc::new(identity_keys),
-                creation_local_time: MilliSecondsSinceUnixEpoch::now(),
+                creation_local_time,
             },
             inner: Box::new(account),
             shared: false,
             uploaded_signed_key_count: 0,
+            fallback_key_creation_time: None,
         }
     }
 
     /// Create a fresh new account, this will generate the identity key-pair.
     pub fn with_device_id(user_id: &UserId, device_id: &DeviceId) -> Self {
+        Self::with_device_id_at(user_id, device_id, MilliSecondsSinceUnixEpoch::now())
+    }
+
+    /// Create a fresh new account created at the given local time.
+    pub(crate) fn with_device_id_at(
+        user_id: &UserId,
+        device_id: &DeviceId,
+        now: MilliSecondsSinceUnixEpoch,
+    ) -> Self {
         let account = InnerAccount::new();
 
-        Self::new_helper(account, user_id, device_id)
+        Self::new_helper(account, user_id, device_id, now)
     }
 
     /// Create a new random Olm Account, the long-term Curve25519 identity key
     /// encoded as base64 will be used for the device ID.
     pub fn new(user_id: &UserId) -> Self {
+        Self::new_at(user_id, MilliSecondsSinceUnixEpoch::now())
+    }
+
+    /// Create a new random Olm Account created at the given local time.
+    pub(crate) fn new_at(user_id: &UserId, now: MilliSecondsSinceUnixEpoch) -> Self {
         let account = InnerAccount::new();
         let device_id: OwnedDeviceId =
             base64_encode(account.identity_keys().curve25519.as_bytes()).into();
 
-        Self::new_helper(account, user_id, &device_id)
+        Self::new_helper(account, user_id, &device_id, now)
     }
 
     /// Get the immutable data for this account.
@@ -473,10 +501,31 @@ impl Account {
         self.inner.max_number_of_one_time_keys()
     }
 
+    /// Update the one-time and fallback key counts reported by the server.
+    ///
+    /// A new fallback key is created at the given time if the server doesn't
+    /// have an unused one.
     pub(crate) fn update_key_counts(
         &mut self,
         one_time_key_counts: &BTreeMap<DeviceKeyAlgorithm, UInt>,
         unused_fallback_keys: Option<&[DeviceKeyAlgorithm]>,
+        now: MilliSecondsSinceUnixEpoch,
+    ) {
+        self.update_one_time_key_counts(one_time_key_counts);
+
+        if let Some(unused) = unused_fallback_keys {
+            if !unused.contains(&DeviceKeyAlgorithm::SignedCurve25519) {
+                // Generate a new fallback key if we don't have one.
+                self.generate_fallback_key_helper(now);
+            }
+        }
+    }
+
+    /// Update the one-time key count reported by the server, generating new
+    /// one-time keys if needed.
+    pub(crate) fn update_one_time_key_counts(
+        &mut self,
+        one_time_key_counts: &BTreeMap<DeviceKeyAlgorithm, UInt>,
     ) {
         if let Some(count) = one_time_key_counts.get(&DeviceKeyAlgorithm::SignedCurve25519) {
             let count: u64 = (*count).into();
@@ -495,13 +544,6 @@ impl Account {
             self.update_uploaded_key_count(count);
             self.generate_one_time_keys();
         }
-
-        if let Some(unused) = unused_fallback_keys {
-            if !unused.contains(&DeviceKeyAlgorithm::SignedCurve25519) {
-                // Generate a new fallback key if we don't have one.
-                self.generate_fallback_key_helper();
-            }
-        }
     }
 
     /// Generate new one-time keys that need to be uploaded to the server.
@@ -543,9 +585,10 @@ impl Account {
         }
     }
 
-    pub(crate) fn generate_fallback_key_helper(&mut self) {
+    pub(crate) fn generate_fallback_key_helper(&mut self, now: MilliSecondsSinceUnixEpoch) {
         if self.inner.fallback_key().is_empty() {
             let removed_fallback_key = self.inner.generate_fallback_key();
+            self.fallback_key_creation_time = Some(now);
 
             debug!(
                 ?removed_fallback_key,
@@ -554,6 +597,46 @@ impl Account {
         }
     }
 
+    /// Get the age of the current fallback key at the given time.
+    ///
+    /// Returns `None` if we don't have a fallback key, or if it was created
+    /// before we started keeping track of its creation time.
+    pub fn fallback_key_age(&self, now: MilliSecondsSinceUnixEpoch) -> Option<Duration> {
+        let created: u64 = self.fallback_key_creation_time?.get().into();
+        let now: u64 = now.get().into();
+
+        Some(Duration::from_millis(now.saturating_sub(created)))
+    }
+
+    /// Replace the fallback key with a new one if it is older than `max_age`
+    /// at the given time.
+    ///
+    /// A fallback key whose creation time isn't known, because it was created
+    /// before we started keeping track of it, is considered too old.
+    ///
+    /// The new fallback key still needs to be uploaded.
+    ///
+    /// Returns `true` if a new fallback key was generated.
+    pub(crate) fn rotate_fallback_key(
+        &mut self,
+        max_age: Duration,
+        now: MilliSecondsSinceUnixEpoch,
+    ) -> bool {
+        let age = self.fallback_key_age(now);
+
+        match age {
+            Some(age) if age < max_age => false,
+            _ => {
+                let removed_fallback_key = self.inner.generate_fallback_key();
+                self.fallback_key_creation_time = Some(now);
+
+                debug!(?age, ?removed_fallback_key, "Rotated the fallback key");
+
+                true
+            }
+        }
+    }
+
     fn fallback_key(&self) -> HashMap<KeyId, Curve25519PublicKey> {
         self.inner.fallback_key()
     }
@@ -595,6 +678,7 @@ impl Account {
             shared: self.shared(),
             uploaded_signed_key_count: self.uploaded_key_count(),
             creation_local_time: self.static_data.creation_local_time,
+            fallback_key_creation_time: self.fallback_key_creation_time,
         }
     }
 
@@ -651,6 +735,7 @@ impl Account {
             inner: Box::new(account),
             shared: pickle.shared,
             uploaded_signed_key_count: pickle.uploaded_signed_key_count,
+            fallback_key_creation_time: pickle.fallback_key_creation_time,
         })
     }
 
@@ -1123,7 +1208,7 @@ impl Account {
         // First mark the current keys as published, as updating the key counts might
         // generate some new keys if we're still below the limit.
         self.mark_keys_as_published();
-        self.update_key_counts(&response.one_time_key_counts, None);
+        self.update_one_time_key_counts(&response.one_time_key_counts);
 
         Ok(())
     }
@@ -1383,6 +1468,7 @@ mod tests {
     use std::{
         collections::{BTreeMap, BTreeSet},
         ops::Deref,
+        time::Duration,
     };
 
     use anyhow::Result;
@@ -1460,7 +1546,7 @@ mod tests {
 
         // A `None` here means that the server doesn't support fallback keys, no
         // fallback key gets uploaded.
-        account.update_key_counts(&one_time_keys, None);
+        account.update_key_counts(&one_time_keys, None, MilliSecondsSinceUnixEpoch::now());
         let (_, _, fallback_keys) = account.keys_for_upload();
         assert!(fallback_keys.is_empty());
 
@@ -1468,20 +1554,71 @@ mod tests {
         // there isn't a unused fallback key on the server. This time we upload
         // a fallback key.
         let unused_fallback_keys = &[];
-        account.update_key_counts(&one_time_keys, Some(unused_fallback_keys.as_ref()));
+        account.update_key_counts(
+            &one_time_keys,
+            Some(unused_fallback_keys.as_ref()),
+            MilliSecondsSinceUnixEpoch::now(),
+        );
         let (_, _, fallback_keys) = account.keys_for_upload();
         assert!(!fallback_keys.is_empty());
         account.mark_keys_as_published();
 
         // There's an unused fallback key on the server, nothing to do here.
         let unused_fallback_keys = &[DeviceKeyAlgorithm::SignedCurve25519];
-        account.update_key_counts(&one_time_keys, Some(unused_fallback_keys.as_ref()));
+        account.update_key_counts(
+            &one_time_keys,
+            Some(unused_fallback_keys.as_ref()),
+            MilliSecondsSinceUnixEpoch::now(),
+        );
         let (_, _, fallback_keys) = account.keys_for_upload();
         assert!(fallback_keys.is_empty());
 
         Ok(())
     }
 
+    #[test]
+    fn test_fallback_key_rotation() {
+        const ONE_HOUR: Duration = Duration::from_secs(60 * 60);
+
+        let mut account = Account::with_device_id(user_id(), device_id());
+        let one_time_keys = BTreeMap::from([(DeviceKeyAlgorithm::SignedCurve25519, 50u8.into())]);
+
+        let now = MilliSecondsSinceUnixEpoch::now();
+        let later = |duration: Duration| {
+            MilliSecondsSinceUnixEpoch::from_system_time(now.to_system_time().unwrap() + duration)
+                .unwrap()
+        };
+        let an_hour_later = later(ONE_HOUR);
+        let two_hours_later = later(ONE_HOUR * 2);
+
+        // A fallback key whose creation time isn't known is considered too old.
+        assert!(account.fallback_key_age(now).is_none());
+        assert!(account.rotate_fallback_key(ONE_HOUR * 2, now));
+        account.mark_keys_as_published();
+        assert_eq!(account.fallback_key_age(an_hour_later), Some(ONE_HOUR));
+
+        // A fallback key created at the given time replaces the used one.
+        let unused_fallback_keys = &[];
+        account.update_key_counts(&one_time_keys, Some(unused_fallback_keys.as_ref()), now);
+        account.mark_keys_as_published();
+        assert_eq!(account.fallback_key_age(an_hour_later), Some(ONE_HOUR));
+
+        // The fallback key is too young to be rotated.
+        assert!(!account.rotate_fallback_key(ONE_HOUR * 2, an_hour_later));
+        let (_, _, fallback_keys) = account.keys_for_upload();
+        assert!(fallback_keys.is_empty());
+
+        // Once it's old enough, a new fallback key needs to be uploaded.
+        assert!(account.rotate_fallback_key(ONE_HOUR / 2, an_hour_later));
+        let (_, _, fallback_keys) = account.keys_for_upload();
+        assert!(!fallback_keys.is_empty());
+        assert_eq!(account.fallback_key_age(two_hours_later), Some(ONE_HOUR));
+
+        // The creation time survives pickling.
+        let account = Account::from_pickle(account.pickle()).unwrap();
+        assert_eq!(account.fallback_key_age(two_hours_later), Some(ONE_HOUR));
+    }
+
     #[test]
     fn test_fallback_key_signing() -> Result<()> {
         let key = vodozemac::Curve25519PublicKey::from_base64(
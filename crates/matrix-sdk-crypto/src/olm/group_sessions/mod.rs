@@ -21,7 +21,8 @@ mod outbound;
 pub use inbound::{InboundGroupSession, PickledInboundGroupSession};
 pub(crate) use outbound::ShareState;
 pub use outbound::{
-    EncryptionSettings, OutboundGroupSession, PickledOutboundGroupSession, ShareInfo,
+    EncryptionSettings, KeyDistributionLogEntry, OutboundGroupSession, PickledOutboundGroupSession,
+    RoomKeyRotationLimits, ShareInfo,
 };
 use thiserror::Error;
 pub use vodozemac::megolm::{ExportedSessionKey, SessionKey};
@@ -29,8 +29,8 @@ use ruma::{
         AnyMessageLikeEventContent,
     },
     serde::Raw,
-    DeviceId, OwnedDeviceId, OwnedRoomId, OwnedTransactionId, OwnedUserId, RoomId,
-    SecondsSinceUnixEpoch, TransactionId, UserId,
+    DeviceId, MilliSecondsSinceUnixEpoch, OwnedDeviceId, OwnedRoomId, OwnedTransactionId,
+    OwnedUserId, RoomId, SecondsSinceUnixEpoch, TransactionId, UserId,
 };
 use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
@@ -86,6 +86,14 @@ pub struct EncryptionSettings {
     /// excluded from the conversation.
     #[serde(default)]
     pub only_allow_trusted_devices: bool,
+    /// Should sharing the room key fail if some devices aren't verified,
+    /// instead of silently excluding them.
+    ///
+    /// The error lists the unverified devices, which can then be verified,
+    /// blacklisted or ignored before trying again. Implies
+    /// `only_allow_trusted_devices`.
+    #[serde(default)]
+    pub error_on_unverified_devices: bool,
 }
 
 impl Default for EncryptionSettings {
@@ -96,6 +104,7 @@ impl Default for EncryptionSettings {
             rotation_period_msgs: ROTATION_MESSAGES,
             history_visibility: HistoryVisibility::Shared,
             only_allow_trusted_devices: false,
+            error_on_unverified_devices: false,
         }
     }
 }
@@ -120,8 +129,40 @@ impl EncryptionSettings {
             rotation_period_msgs,
             history_visibility,
             only_allow_trusted_devices,
+            error_on_unverified_devices: false,
         }
     }
+
+    /// Make sure the rotation periods of these settings are at least as strict
+    /// as the given limits.
+    ///
+    /// Rotation periods that are already shorter than the limits are kept as
+    /// they are.
+    pub fn with_rotation_limits(mut self, limits: RoomKeyRotationLimits) -> Self {
+        if let Some(max_rotation_period) = limits.max_rotation_period {
+            self.rotation_period = self.rotation_period.min(max_rotation_period);
+        }
+
+        if let Some(max_rotation_period_msgs) = limits.max_rotation_period_msgs {
+            self.rotation_period_msgs = self.rotation_period_msgs.min(max_rotation_period_msgs);
+        }
+
+        self
+    }
+}
+
+/// Upper bounds for the rotation periods of room keys.
+///
+/// The rotation periods of a room are picked by the room members that can send
+/// the `m.room.encryption` state event. This allows a client to rotate its room
+/// keys more often than what the room asks for, but never less often.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RoomKeyRotationLimits {
+    /// The maximum time a room key can be used for before rotating it.
+    pub max_rotation_period: Option<Duration>,
+    /// The maximum number of messages that can be encrypted with a room key
+    /// before rotating it.
+    pub max_rotation_period_msgs: Option<u64>,
 }
 
 /// Outbound group session.
@@ -183,6 +224,26 @@ pub struct SharedWith {
     pub message_index: u32,
 }
 
+/// An entry of the key distribution log of a room.
+///
+/// It records that an outbound group session was shared with a device, or
+/// withheld from it, to be able to audit who could have decrypted a message.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KeyDistributionLogEntry {
+    /// The ID of the outbound group session.
+    pub session_id: String,
+    /// The user the session was shared with or withheld from.
+    pub user_id: OwnedUserId,
+    /// The device the session was shared with or withheld from.
+    pub device_id: OwnedDeviceId,
+    /// Whether the session was shared, and from which message index, or
+    /// withheld, and why.
+    pub share_info: ShareInfo,
+    /// When the to-device message carrying the session or the withheld code
+    /// was sent.
+    pub timestamp: MilliSecondsSinceUnixEpoch,
+}
+
 impl OutboundGroupSession {
     pub(super) fn session_config(
         algorithm: &EventEncryptionAlgorithm,
@@ -282,12 +343,10 @@ impl OutboundGroupSession {
     ///
     /// This removes the request from the queue and marks the set of
     /// users/devices that received the session.
-    pub fn mark_request_as_sent(
-        &self,
-        request_id: &TransactionId,
-    ) -> BTreeMap<OwnedUserId, BTreeSet<OwnedDeviceId>> {
-        let mut no_olm_devices = BTreeMap::new();
-
+    ///
+    /// Returns the users/devices that the request shared the session with or
+    /// withheld it from, which is empty if the request is unknown.
+    pub fn mark_request_as_sent(&self, request_id: &TransactionId) -> ShareInfoSet {
         let removed = self.to_share_with_set.write().unwrap().remove(request_id);
         if let Some((to_device, request)) = removed {
             let recipients: BTreeMap<&UserId, BTreeSet<&DeviceId>> = request
@@ -302,15 +361,13 @@ impl OutboundGroupSession {
                 "Marking to-device request carrying a room key or a withheld as sent"
             );
 
-            for (user_id, info) in request {
-                let no_olms: BTreeSet<OwnedDeviceId> = info
-                    .iter()
-                    .filter(|(_, info)| matches!(info, ShareInfo::Withheld(WithheldCode::NoOlm)))
-                    .map(|(d, _)| d.to_owned())
-                    .collect();
-                no_olm_devices.insert(user_id.to_owned(), no_olms);
-
-                self.shared_with_set.write().unwrap().entry(user_id).or_default().extend(info);
+            for (user_id, info) in &request {
+                self.shared_with_set
+                    .write()
+                    .unwrap()
+                    .entry(user_id.to_owned())
+                    .or_default()
+                    .extend(info.clone());
             }
 
             if self.to_share_with_set.read().unwrap().is_empty() {
@@ -323,6 +380,8 @@ impl OutboundGroupSession {
 
                 self.mark_as_shared();
             }
+
+            request
         } else {
             let request_ids: Vec<String> =
                 self.to_share_with_set.read().unwrap().keys().map(|k| k.to_string()).collect();
@@ -333,9 +392,9 @@ impl OutboundGroupSession {
                 "Marking to-device request carrying a room key as sent but no \
                  request found with the given id"
             );
-        }
 
-        no_olm_devices
+            Default::default()
+        }
     }
 
     /// Encrypt the given plaintext using this session.
@@ -747,7 +806,7 @@ mod tests {
         room_id, uint, user_id, EventEncryptionAlgorithm,
     };
 
-    use super::{EncryptionSettings, ROTATION_MESSAGES, ROTATION_PERIOD};
+    use super::{EncryptionSettings, RoomKeyRotationLimits, ROTATION_MESSAGES, ROTATION_PERIOD};
     use crate::{Account, MegolmError};
 
     #[test]
@@ -768,6 +827,31 @@ mod tests {
         assert_eq!(settings.rotation_period_msgs, 500);
     }
 
+    #[test]
+    fn test_encryption_settings_rotation_limits() {
+        let mut content =
+            RoomEncryptionEventContent::new(EventEncryptionAlgorithm::MegolmV1AesSha2);
+        content.rotation_period_ms = Some(uint!(86_400_000));
+        content.rotation_period_msgs = Some(uint!(50));
+
+        let settings = EncryptionSettings::new(content, HistoryVisibility::Shared, false);
+
+        let unchanged = settings.clone().with_rotation_limits(RoomKeyRotationLimits::default());
+        assert_eq!(unchanged.rotation_period, Duration::from_secs(86_400));
+        assert_eq!(unchanged.rotation_period_msgs, 50);
+
+        let limits = RoomKeyRotationLimits {
+            max_rotation_period: Some(Duration::from_secs(3600)),
+            max_rotation_period_msgs: Some(500),
+        };
+        let restricted = settings.with_rotation_limits(limits);
+
+        // The limits are stricter for the period, but the room is stricter for the
+        // number of messages.
+        assert_eq!(restricted.rotation_period, Duration::from_secs(3600));
+        assert_eq!(restricted.rotation_period_msgs, 50);
+    }
+
     #[async_test]
     #[cfg(any(target_os = "linux", target_os = "macos", target_arch = "wasm32"))]
     async fn test_expiration() -> Result<(), MegolmError> {
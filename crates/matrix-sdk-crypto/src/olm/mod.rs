@@ -28,12 +28,13 @@ pub(crate) use account::{OlmDecryptionInfo, SessionType};
 pub(crate) use group_sessions::ShareState;
 pub use group_sessions::{
     BackedUpRoomKey, EncryptionSettings, ExportedRoomKey, InboundGroupSession,
-    OutboundGroupSession, PickledInboundGroupSession, PickledOutboundGroupSession,
-    SessionCreationError, SessionExportError, SessionKey, ShareInfo,
+    KeyDistributionLogEntry, OutboundGroupSession, PickledInboundGroupSession,
+    PickledOutboundGroupSession, RoomKeyRotationLimits, SessionCreationError, SessionExportError,
+    SessionKey, ShareInfo,
 };
 pub use session::{PickledSession, Session};
 pub use signing::{CrossSigningStatus, PickledCrossSigningIdentity, PrivateCrossSigningIdentity};
-pub(crate) use utility::{SignedJsonObject, VerifyJson};
+pub(crate) use utility::{to_signable_json, SignedJsonObject, VerifyJson};
 pub use vodozemac::{olm::IdentityKeys, Curve25519PublicKey};
 
 #[cfg(test)]
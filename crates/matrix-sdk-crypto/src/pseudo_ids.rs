@@ -0,0 +1,307 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers for pseudonymous identities in rooms, as defined in [MSC4014].
+//!
+//! In a room using pseudoIDs, users don't send events with their user ID but
+//! with a key pair that is specific to the room, the "user room key". The
+//! public part of this key, encoded as unpadded base64, is the pseudoID of the
+//! user in the room.
+//!
+//! The homeserver of the user vouches for the mapping between the pseudoID
+//! and the user ID with a signed `mxid_mapping` in the `m.room.member` event of
+//! the pseudoID, see [`MxidMapping`].
+//!
+//! [MSC4014]: https://github.com/matrix-org/matrix-spec-proposals/pull/4014
+
+use std::collections::BTreeMap;
+
+use ruma::{
+    serde::Raw, OwnedServerName, OwnedServerSigningKeyId, OwnedUserId, RoomId, ServerSigningKeyId,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::{debug, instrument};
+use vodozemac::{Ed25519PublicKey, Ed25519SecretKey, Ed25519Signature};
+
+use crate::{olm::to_signable_json, store::Store, CryptoStoreError, SignatureError};
+
+/// Error type for the verification of the pseudoID of a sender.
+#[derive(Debug, Error)]
+pub enum PseudoIdError {
+    /// The sender isn't the pseudoID the mapping is about.
+    #[error("the sender {sender} isn't the pseudoID of the mapping, {user_room_key}")]
+    SenderMismatch {
+        /// The sender of the event.
+        sender: String,
+        /// The pseudoID of the mapping.
+        user_room_key: String,
+    },
+
+    /// The `m.room.member` event of the sender has no `mxid_mapping`.
+    #[error("the member event of the sender has no mxid_mapping")]
+    MissingMapping,
+
+    /// The mapping isn't signed by the homeserver of the user it maps to,
+    /// with the given key.
+    #[error("the mapping isn't signed by {0} with the given key")]
+    MissingServerSignature(OwnedServerName),
+
+    /// The signature of the homeserver couldn't be verified.
+    #[error(transparent)]
+    Signature(#[from] SignatureError),
+}
+
+/// The mapping between a pseudoID and the user ID of a user, as found in the
+/// `mxid_mapping` field of the `m.room.member` event of the pseudoID.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MxidMapping {
+    /// The pseudoID of the user in the room, the unpadded base64 encoding of
+    /// the public part of their user room key.
+    pub user_room_key: String,
+
+    /// The user the pseudoID belongs to.
+    pub user_id: OwnedUserId,
+
+    /// The signatures of the mapping, keyed by server name and then by key
+    /// ID.
+    ///
+    /// The mapping must be signed by the homeserver of [`Self::user_id`].
+    #[serde(default)]
+    pub signatures: BTreeMap<OwnedServerName, BTreeMap<OwnedServerSigningKeyId, String>>,
+}
+
+impl MxidMapping {
+    /// Get the mapping of an `m.room.member` event, if it has one.
+    ///
+    /// Returns an error if the event has an `mxid_mapping` that can't be
+    /// deserialized.
+    pub fn from_member_event<T>(event: &Raw<T>) -> Result<Option<Self>, serde_json::Error> {
+        #[derive(Deserialize)]
+        struct Content {
+            mxid_mapping: Option<MxidMapping>,
+        }
+
+        Ok(event.get_field::<Content>("content")?.and_then(|content| content.mxid_mapping))
+    }
+
+    /// Verify that `sender` is the pseudoID of this mapping and that the
+    /// homeserver of the user signed the mapping.
+    ///
+    /// # Arguments
+    ///
+    /// * `sender` - The pseudoID that sent an event.
+    ///
+    /// * `server_key_id` - The ID of the signing key of the homeserver of
+    ///   [`Self::user_id`].
+    ///
+    /// * `server_key` - The signing key of the homeserver of [`Self::user_id`].
+    ///
+    /// Returns the user that sent the event.
+    pub fn verify_sender(
+        &self,
+        sender: &str,
+        server_key_id: &ServerSigningKeyId,
+        server_key: Ed25519PublicKey,
+    ) -> Result<OwnedUserId, PseudoIdError> {
+        if sender != self.user_room_key {
+            return Err(PseudoIdError::SenderMismatch {
+                sender: sender.to_owned(),
+                user_room_key: self.user_room_key.clone(),
+            });
+        }
+
+        let server_name = self.user_id.server_name();
+        let signature = self
+            .signatures
+            .get(server_name)
+            .and_then(|signatures| signatures.get(server_key_id))
+            .ok_or_else(|| PseudoIdError::MissingServerSignature(server_name.to_owned()))?;
+        let signature = Ed25519Signature::from_base64(signature)
+            .map_err(|_| SignatureError::InvalidSignature)?;
+
+        let canonical_json = to_signable_json(serde_json::to_value(self)?)?;
+        server_key
+            .verify(canonical_json.as_bytes(), &signature)
+            .map_err(SignatureError::VerificationError)?;
+
+        Ok(self.user_id.clone())
+    }
+}
+
+/// The key of the custom value of the crypto store holding our user room key
+/// in a room.
+fn user_room_key_key(room_id: &RoomId) -> String {
+    format!("pseudo_id_user_room_key:{room_id}")
+}
+
+/// The pseudoIDs manager of the [`OlmMachine`](crate::OlmMachine).
+///
+/// Get access to it with
+/// [`OlmMachine::pseudo_ids()`](crate::OlmMachine::pseudo_ids).
+#[derive(Debug)]
+pub struct PseudoIds {
+    pub(crate) store: Store,
+}
+
+impl PseudoIds {
+    /// Get the public key of our pseudoID in the given room.
+    ///
+    /// Our user room key is created the first time this is called for a room,
+    /// and persisted in the crypto store, so the same key is returned
+    /// afterwards. Like the other secrets of the crypto store, the key is
+    /// encrypted with the store cipher if the store has one.
+    #[instrument(skip(self))]
+    pub async fn public_key_for_room(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Ed25519PublicKey, CryptoStoreError> {
+        let _guard = self.store.user_room_keys_lock().await;
+
+        let key = user_room_key_key(room_id);
+
+        if let Some(secret_key) = self.store.get_value::<Ed25519SecretKey>(&key).await? {
+            return Ok(secret_key.public_key());
+        }
+
+        let secret_key = Ed25519SecretKey::new();
+        self.store.set_value(&key, &secret_key).await?;
+        debug!("Created a new user room key");
+
+        Ok(secret_key.public_key())
+    }
+
+    /// Get our pseudoID in the given room, the unpadded base64 encoding of
+    /// [`Self::public_key_for_room()`].
+    pub async fn pseudo_id_for_room(&self, room_id: &RoomId) -> Result<String, CryptoStoreError> {
+        Ok(self.public_key_for_room(room_id).await?.to_base64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use matrix_sdk_test::async_test;
+    use ruma::{device_id, room_id, serde::Raw, server_name, server_signing_key_id, user_id};
+    use serde_json::json;
+    use vodozemac::Ed25519SecretKey;
+
+    use super::{MxidMapping, PseudoIdError};
+    use crate::{olm::to_signable_json, OlmMachine};
+
+    fn signed_mapping(server_key: &Ed25519SecretKey, user_room_key: &str) -> MxidMapping {
+        let mut mapping = MxidMapping {
+            user_room_key: user_room_key.to_owned(),
+            user_id: user_id!("@alice:example.org").to_owned(),
+            signatures: Default::default(),
+        };
+
+        let canonical_json = to_signable_json(serde_json::to_value(&mapping).unwrap()).unwrap();
+        let signature = server_key.sign(canonical_json.as_bytes());
+        mapping
+            .signatures
+            .entry(server_name!("example.org").to_owned())
+            .or_default()
+            .insert(server_signing_key_id!("ed25519:1").to_owned(), signature.to_base64());
+
+        mapping
+    }
+
+    #[async_test]
+    async fn test_public_key_for_room_is_persisted() {
+        let machine = OlmMachine::new(user_id!("@alice:example.org"), device_id!("ALICE")).await;
+        let pseudo_ids = machine.pseudo_ids();
+
+        let first = pseudo_ids.public_key_for_room(room_id!("!a:example.org")).await.unwrap();
+        let again = pseudo_ids.public_key_for_room(room_id!("!a:example.org")).await.unwrap();
+        let other = pseudo_ids.public_key_for_room(room_id!("!b:example.org")).await.unwrap();
+
+        assert_eq!(first, again);
+        assert_ne!(first, other);
+        assert_eq!(
+            pseudo_ids.pseudo_id_for_room(room_id!("!a:example.org")).await.unwrap(),
+            first.to_base64()
+        );
+    }
+
+    #[test]
+    fn test_verify_sender() {
+        let server_key = Ed25519SecretKey::new();
+        let pseudo_id = Ed25519SecretKey::new().public_key().to_base64();
+        let mapping = signed_mapping(&server_key, &pseudo_id);
+        let key_id = server_signing_key_id!("ed25519:1");
+
+        let user_id = mapping.verify_sender(&pseudo_id, key_id, server_key.public_key()).unwrap();
+        assert_eq!(user_id, user_id!("@alice:example.org"));
+
+        // Another pseudoID can't use the mapping.
+        let other = Ed25519SecretKey::new().public_key().to_base64();
+        assert!(matches!(
+            mapping.verify_sender(&other, key_id, server_key.public_key()),
+            Err(PseudoIdError::SenderMismatch { .. })
+        ));
+
+        // The mapping must be signed by the homeserver of the user.
+        let wrong_server_key = Ed25519SecretKey::new();
+        assert!(matches!(
+            mapping.verify_sender(&pseudo_id, key_id, wrong_server_key.public_key()),
+            Err(PseudoIdError::Signature(_))
+        ));
+        assert!(matches!(
+            mapping.verify_sender(
+                &pseudo_id,
+                server_signing_key_id!("ed25519:2"),
+                server_key.public_key()
+            ),
+            Err(PseudoIdError::MissingServerSignature(_))
+        ));
+
+        // The mapping can't be changed after it was signed.
+        let mut tampered = mapping.clone();
+        tampered.user_id = user_id!("@mallory:example.org").to_owned();
+        assert!(tampered.verify_sender(&pseudo_id, key_id, server_key.public_key()).is_err());
+    }
+
+    #[test]
+    fn test_mapping_from_member_event() {
+        let server_key = Ed25519SecretKey::new();
+        let pseudo_id = Ed25519SecretKey::new().public_key().to_base64();
+        let mapping = signed_mapping(&server_key, &pseudo_id);
+
+        let event = Raw::new(&json!({
+            "type": "m.room.member",
+            "state_key": pseudo_id,
+            "sender": pseudo_id,
+            "content": {
+                "membership": "join",
+                "mxid_mapping": mapping,
+            },
+        }))
+        .unwrap();
+
+        let parsed = MxidMapping::from_member_event(&event).unwrap().unwrap();
+        assert_eq!(parsed.user_room_key, pseudo_id);
+        parsed
+            .verify_sender(&pseudo_id, server_signing_key_id!("ed25519:1"), server_key.public_key())
+            .unwrap();
+
+        let event = Raw::new(&json!({
+            "type": "m.room.member",
+            "state_key": "@bob:example.org",
+            "sender": "@bob:example.org",
+            "content": { "membership": "join" },
+        }))
+        .unwrap();
+        assert!(MxidMapping::from_member_event(&event).unwrap().is_none());
+    }
+}
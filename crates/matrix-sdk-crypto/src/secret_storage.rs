@@ -19,6 +19,7 @@
 
 use std::fmt;
 
+use bip39::{Language, Mnemonic};
 pub use hmac::digest::MacError;
 use hmac::Hmac;
 use pbkdf2::pbkdf2;
@@ -45,17 +46,18 @@ use serde::de::Error;
 use sha2::Sha512;
 use subtle::ConstantTimeEq;
 use thiserror::Error;
-use zeroize::{Zeroize, ZeroizeOnDrop};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
 
 use crate::ciphers::{AesHmacSha2Key, HmacSha256Mac, IV_SIZE, KEY_SIZE, MAC_SIZE};
 
 /// Error type for the decoding of a [`SecretStorageKey`].
 ///
-/// The [`SecretStorageKey`] can be restored from a Base58 encoded string or
-/// from a string containing a passphrase.
+/// The [`SecretStorageKey`] can be restored from a Base58 encoded string, from
+/// a recovery phrase, from its binary encoding or from a string containing a
+/// passphrase.
 ///
-/// This error type is used to report errors when trying to restore from either
-/// of those strings.
+/// This error type is used to report errors when trying to restore from any
+/// of those.
 #[derive(Debug, Error)]
 pub enum DecodeError {
     /// The decoded secret storage key has an invalid prefix.
@@ -70,8 +72,11 @@ pub enum DecodeError {
     /// The secret storage key isn't valid Base64.
     #[error(transparent)]
     Base64(#[from] vodozemac::Base64DecodeError),
+    /// The recovery phrase isn't a valid BIP-39 mnemonic.
+    #[error(transparent)]
+    RecoveryPhrase(#[from] bip39::Error),
     /// The secret storage key is too short, we couldn't read enough data.
-    #[error("The Base58 decoded key has an invalid length, expected {0}, got {1}")]
+    #[error("The decoded key has an invalid length, expected {0}, got {1}")]
     KeyLength(usize, usize),
     /// The typed in secret storage was incorrect, the MAC check failed.
     #[error("The MAC check for the secret storage key failed")]
@@ -102,8 +107,8 @@ pub enum DecodeError {
 /// A secret storage key which can be used to store encrypted data in the user's
 /// account data as defined in the [spec].
 ///
-/// The secret storage key can be initialized from a passphrase or from a
-/// base58-encoded string.
+/// The secret storage key can be initialized from a passphrase or from one of
+/// its encodings: a base58-encoded string, a recovery phrase or a binary blob.
 ///
 /// To bootstrap a new [`SecretStorageKey`], use the [`SecretStorageKey::new()`]
 /// or [`SecretStorageKey::new_from_passphrase()`] method.
@@ -344,7 +349,9 @@ impl SecretStorageKey {
     /// string matches to the description.
     ///
     /// The input can be a passphrase or a Base58 export of the
-    /// [`SecretStorageKey`].
+    /// [`SecretStorageKey`]. The recovery phrase isn't accepted since it is
+    /// specific to this crate, use [`SecretStorageKey::from_recovery_phrase()`]
+    /// for it.
     pub fn from_account_data(
         input: &str,
         content: SecretStorageKeyEventContent,
@@ -405,7 +412,18 @@ impl SecretStorageKey {
         let value: String = value.chars().filter(|c| !c.is_whitespace()).collect();
 
         let mut decoded = bs58::decode(value).with_alphabet(bs58::Alphabet::BITCOIN).into_vec()?;
+        let key = Self::parse_key_bytes(&decoded);
+        decoded.zeroize();
 
+        key
+    }
+
+    // Parse a secret storage key represented as its prefixed bytes with a parity
+    // byte.
+    //
+    // This method reverses the process in the [`SecretStorageKey::to_binary()`]
+    // method.
+    fn parse_key_bytes(decoded: &[u8]) -> Result<Box<[u8; 32]>, DecodeError> {
         let mut prefix = [0u8; 2];
         let mut key = Box::new([0u8; 32]);
 
@@ -418,8 +436,6 @@ impl SecretStorageKey {
             key.copy_from_slice(&decoded[2..34]);
             let expected_parity = decoded[34];
 
-            decoded.zeroize();
-
             let parity = Self::parity_byte(key.as_ref());
 
             let unexpected_choice = prefix.ct_ne(&Self::PREFIX);
@@ -447,6 +463,94 @@ impl SecretStorageKey {
         Ok(key)
     }
 
+    /// Restore a [`SecretStorageKey`] from a recovery phrase, as exported by
+    /// [`SecretStorageKey::to_recovery_phrase()`], and the description of the
+    /// key.
+    ///
+    /// Whitespace and case are insignificant in the recovery phrase.
+    pub fn from_recovery_phrase(
+        phrase: &str,
+        content: SecretStorageKeyEventContent,
+    ) -> Result<Self, DecodeError> {
+        let mut normalized =
+            phrase.split_whitespace().map(str::to_lowercase).collect::<Vec<_>>().join(" ");
+        let mnemonic = Mnemonic::parse_in_normalized(Language::English, &normalized);
+        normalized.zeroize();
+
+        let (mut entropy, entropy_len) = mnemonic?.to_entropy_array();
+
+        let secret_key = if entropy_len != KEY_SIZE {
+            Err(DecodeError::KeyLength(KEY_SIZE, entropy_len))
+        } else {
+            let mut secret_key = Box::new([0u8; KEY_SIZE]);
+            secret_key.copy_from_slice(&entropy[..KEY_SIZE]);
+            Ok(secret_key)
+        };
+        entropy.zeroize();
+
+        let key = Self { storage_key_info: content, secret_key: secret_key? };
+        key.check_zero_message()?;
+
+        Ok(key)
+    }
+
+    /// Restore a [`SecretStorageKey`] from its binary encoding, as exported by
+    /// [`SecretStorageKey::to_binary()`], and the description of the key.
+    pub fn from_binary(
+        bytes: &[u8],
+        content: SecretStorageKeyEventContent,
+    ) -> Result<Self, DecodeError> {
+        let secret_key = Self::parse_key_bytes(bytes)?;
+        let key = Self { storage_key_info: content, secret_key };
+        key.check_zero_message()?;
+
+        Ok(key)
+    }
+
+    /// Export the [`SecretStorageKey`] as a binary blob, meant to be put in a
+    /// QR code.
+    ///
+    /// This contains the same bytes as the base58-encoded variant of the key,
+    /// before they are encoded: a 2-byte prefix, the private key and a parity
+    /// byte.
+    ///
+    /// *Note*: This returns a copy of the private key material of the
+    /// [`SecretStorageKey`], which is zeroized when it is dropped.
+    pub fn to_binary(&self) -> Zeroizing<Vec<u8>> {
+        // The capacity is reserved upfront so the bytes are never reallocated,
+        // which would leave a copy of them behind.
+        let mut bytes = Zeroizing::new(Vec::with_capacity(Self::DECODED_BASE58_KEY_LEN));
+
+        // The key is prepended by the two prefix bytes, 0x8b and 0x01.
+        bytes.extend_from_slice(Self::PREFIX.as_slice());
+        bytes.extend_from_slice(self.secret_key.as_slice());
+
+        // All the bytes in the string above, including the two header bytes, are XORed
+        // together to form a parity byte. This parity byte is appended to the byte
+        // string.
+        bytes.push(Self::parity_byte(self.secret_key.as_slice()));
+
+        bytes
+    }
+
+    /// Export the [`SecretStorageKey`] as a recovery phrase of 24 words from
+    /// the English [BIP-39] word list.
+    ///
+    /// The recovery phrase isn't part of the spec, so other clients can't
+    /// restore the key from it. It can only be restored with
+    /// [`SecretStorageKey::from_recovery_phrase()`].
+    ///
+    /// *Note*: This returns a copy of the private key material of the
+    /// [`SecretStorageKey`] as a string. The caller needs to ensure that this
+    /// string is zeroized.
+    ///
+    /// [BIP-39]: https://github.com/bitcoin/bips/blob/master/bip-0039.mediawiki
+    pub fn to_recovery_phrase(&self) -> String {
+        Mnemonic::from_entropy_in(Language::English, self.secret_key.as_slice())
+            .expect("A 32-byte key should always be valid BIP-39 entropy")
+            .to_string()
+    }
+
     /// Export the [`SecretStorageKey`] as a base58-encoded string as defined in
     /// the [spec].
     ///
@@ -458,24 +562,13 @@ impl SecretStorageKey {
     pub fn to_base58(&self) -> String {
         const DISPLAY_CHUNK_SIZE: usize = 4;
 
-        let mut bytes = Box::new([0u8; Self::DECODED_BASE58_KEY_LEN]);
-
-        // The key is prepended by the two prefix bytes, 0x8b and 0x01.
-        bytes[0..2].copy_from_slice(Self::PREFIX.as_slice());
-        bytes[2..34].copy_from_slice(self.secret_key.as_slice());
-
-        // All the bytes in the string above, including the two header bytes, are XORed
-        // together to form a parity byte. This parity byte is appended to the byte
-        // string.
-        bytes[34] = Self::parity_byte(self.secret_key.as_slice());
+        let bytes = self.to_binary();
 
         // The byte string is encoded using Base58, using the same mapping as is used
         // for Bitcoin addresses.
         let base_58 =
             bs58::encode(bytes.as_slice()).with_alphabet(bs58::Alphabet::BITCOIN).into_string();
 
-        bytes.zeroize();
-
         // The string is formatted into groups of four characters separated by spaces.
         let ret = base_58
             .chars()
@@ -666,6 +759,56 @@ mod test {
         );
     }
 
+    #[test]
+    fn recovery_phrase_and_binary_roundtrip() {
+        let key = SecretStorageKey::new();
+        let content = key.event_content().to_owned();
+
+        let phrase = key.to_recovery_phrase();
+        assert_eq!(phrase.split(' ').count(), 24, "The recovery phrase should have 24 words");
+
+        // Case and whitespace don't matter.
+        let typed_phrase = format!("  {}\n", phrase.to_uppercase().replace(' ', "   "));
+        let restored = SecretStorageKey::from_recovery_phrase(&typed_phrase, content.clone())
+            .expect("We should be able to restore the key from its recovery phrase");
+        assert_eq!(restored.secret_key, key.secret_key);
+
+        // The recovery phrase isn't interoperable, it can't be used like the
+        // base58-encoded key.
+        assert!(SecretStorageKey::from_account_data(&phrase, content.clone()).is_err());
+
+        let bytes = key.to_binary();
+        assert_eq!(bytes.len(), SecretStorageKey::DECODED_BASE58_KEY_LEN);
+        assert_eq!(
+            bs58::encode(bytes.as_slice()).with_alphabet(bs58::Alphabet::BITCOIN).into_string(),
+            key.to_base58().replace(' ', ""),
+            "The binary encoding should contain the bytes of the base58 encoding"
+        );
+
+        let restored = SecretStorageKey::from_binary(&bytes, content.clone())
+            .expect("We should be able to restore the key from its binary encoding");
+        assert_eq!(restored.secret_key, key.secret_key);
+
+        let mut corrupted = bytes.to_vec();
+        corrupted[10] ^= 0xff;
+        assert_matches!(
+            SecretStorageKey::from_binary(&corrupted, content.clone()),
+            Err(DecodeError::Parity(..))
+        );
+
+        // A valid recovery phrase of another key fails the MAC check.
+        let other_phrase = SecretStorageKey::new().to_recovery_phrase();
+        assert_matches!(
+            SecretStorageKey::from_recovery_phrase(&other_phrase, content.clone()),
+            Err(DecodeError::Mac(_))
+        );
+
+        assert_matches!(
+            SecretStorageKey::from_recovery_phrase("not a recovery phrase", content),
+            Err(DecodeError::RecoveryPhrase(_))
+        );
+    }
+
     #[test]
     fn from_account_data_and_passphrase() {
         let json = to_raw_value(&json!({
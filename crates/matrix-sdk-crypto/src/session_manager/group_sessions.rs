@@ -21,7 +21,7 @@ use std::{
 
 use futures_util::future::join_all;
 use itertools::{Either, Itertools};
-use matrix_sdk_common::executor::spawn;
+use matrix_sdk_common::{clock::Clock, executor::spawn};
 use ruma::{
     events::{AnyMessageLikeEventContent, ToDeviceEventType},
     serde::Raw,
@@ -29,15 +29,19 @@ use ruma::{
     DeviceId, OwnedDeviceId, OwnedRoomId, OwnedTransactionId, OwnedUserId, RoomId, TransactionId,
     UserId,
 };
-use tracing::{debug, error, info, instrument, trace};
+use tracing::{debug, error, info, instrument, trace, warn};
 
 use crate::{
     error::{EventError, MegolmResult, OlmResult},
     identities::device::MaybeEncryptedRoomKey,
-    olm::{InboundGroupSession, OutboundGroupSession, Session, ShareInfo, ShareState},
+    olm::{
+        InboundGroupSession, KeyDistributionLogEntry, OutboundGroupSession, Session, ShareInfo,
+        ShareState,
+    },
     store::{Changes, CryptoStoreWrapper, Result as StoreResult, Store},
     types::events::{room::encrypted::RoomEncryptedEventContent, room_key_withheld::WithheldCode},
-    EncryptionSettings, OlmError, ReadOnlyDevice, ToDeviceRequest,
+    EncryptionSettings, LocalTrust, OlmError, ReadOnlyDevice, ReadOnlyUserIdentities,
+    ToDeviceRequest,
 };
 
 #[derive(Clone, Debug)]
@@ -141,13 +145,15 @@ pub(crate) struct GroupSessionManager {
     store: Store,
     /// The currently active outbound group sessions.
     sessions: GroupSessionCache,
+    /// The source of the timestamps of the key distribution log.
+    clock: Arc<dyn Clock>,
 }
 
 impl GroupSessionManager {
     const MAX_TO_DEVICE_MESSAGES: usize = 250;
 
-    pub fn new(store: Store) -> Self {
-        Self { store: store.clone(), sessions: GroupSessionCache::new(store) }
+    pub fn new(store: Store, clock: Arc<dyn Clock>) -> Self {
+        Self { store: store.clone(), sessions: GroupSessionCache::new(store), clock }
     }
 
     pub async fn invalidate_group_session(&self, room_id: &RoomId) -> StoreResult<bool> {
@@ -164,17 +170,32 @@ impl GroupSessionManager {
         }
     }
 
-    pub async fn mark_request_as_sent(&self, request_id: &TransactionId) -> StoreResult<()> {
+    /// Mark the to-device request with the given ID as sent, if it shares a
+    /// room key.
+    ///
+    /// The resulting changes are saved along with the given `changes`, in a
+    /// single batch.
+    pub async fn mark_request_as_sent(
+        &self,
+        request_id: &TransactionId,
+        mut changes: Changes,
+    ) -> StoreResult<()> {
         let Some(session) = self.sessions.remove_from_being_shared(request_id) else {
+            if !changes.is_empty() {
+                self.store.save_changes(changes).await?;
+            }
+
             return Ok(());
         };
 
-        let no_olm = session.mark_request_as_sent(request_id);
+        let share_infos = session.mark_request_as_sent(request_id);
 
-        let mut changes = Changes::default();
+        for (user_id, devices) in &share_infos {
+            let no_olm = devices
+                .iter()
+                .filter(|(_, info)| matches!(info, ShareInfo::Withheld(WithheldCode::NoOlm)));
 
-        for (user_id, devices) in &no_olm {
-            for device_id in devices {
+            for (device_id, _) in no_olm {
                 let device = self.store.get_device(user_id, device_id).await;
 
                 if let Ok(Some(device)) = device {
@@ -191,7 +212,29 @@ impl GroupSessionManager {
         }
 
         changes.outbound_group_sessions.push(session.clone());
-        self.store.save_changes(changes).await
+        self.store.save_changes(changes).await?;
+
+        // Keep track of who received the room key, or was told why they
+        // didn't, for auditing purposes. The room key was sent already, so
+        // failing to log it shouldn't fail the whole share.
+        let session_id = session.session_id();
+        let timestamp = self.clock.now_ms();
+        let entries = share_infos.into_iter().flat_map(|(user_id, devices)| {
+            devices.into_iter().map(move |(device_id, share_info)| KeyDistributionLogEntry {
+                session_id: session_id.to_owned(),
+                user_id: user_id.clone(),
+                device_id,
+                share_info,
+                timestamp,
+            })
+        });
+        if let Err(error) =
+            self.store.append_to_key_distribution_log(session.room_id(), entries).await
+        {
+            warn!(?request_id, "Couldn't append to the key distribution log: {error}");
+        }
+
+        Ok(())
     }
 
     #[cfg(test)]
@@ -343,6 +386,12 @@ impl GroupSessionManager {
     /// Returns information indicating whether the session needs to be rotated
     /// and the list of users/devices that should receive or not the session
     /// (with withheld reason).
+    ///
+    /// Returns an [`OlmError::UnverifiedDevices`] error if the settings ask
+    /// for it and some devices aren't verified. Devices whose trust state is
+    /// ignored are withheld without error. With the same settings, an
+    /// [`OlmError::IdentityViolations`] error is returned first if some users
+    /// replaced their identity and the change wasn't acknowledged.
     pub async fn collect_session_recipients(
         &self,
         users: impl Iterator<Item = &UserId>,
@@ -352,6 +401,11 @@ impl GroupSessionManager {
         let users: BTreeSet<&UserId> = users.collect();
         let mut devices: BTreeMap<OwnedUserId, Vec<ReadOnlyDevice>> = Default::default();
         let mut withheld_devices: Vec<(ReadOnlyDevice, WithheldCode)> = Default::default();
+        let mut unverified_devices: BTreeMap<OwnedUserId, Vec<OwnedDeviceId>> = Default::default();
+        let mut identity_violations: Vec<OwnedUserId> = Default::default();
+
+        let only_allow_trusted_devices =
+            settings.only_allow_trusted_devices || settings.error_on_unverified_devices;
 
         trace!(?users, ?settings, "Calculating group session recipients");
 
@@ -368,6 +422,11 @@ impl GroupSessionManager {
         let visibility_changed =
             outbound.settings().history_visibility != settings.history_visibility;
         let algorithm_changed = outbound.settings().algorithm != settings.algorithm;
+        // The session was created with rotation periods that are longer than the
+        // ones we should use now, e.g. because the room or the rotation limits
+        // of the client changed.
+        let rotation_shortened = settings.rotation_period < outbound.settings().rotation_period
+            || settings.rotation_period_msgs < outbound.settings().rotation_period_msgs;
 
         // To protect the room history we need to rotate the session if either:
         //
@@ -375,9 +434,11 @@ impl GroupSessionManager {
         // 2. Any of the users' devices got deleted or blacklisted.
         // 3. The history visibility changed.
         // 4. The encryption algorithm changed.
+        // 5. The rotation periods got shorter.
         //
         // This is calculated in the following code and stored in this variable.
-        let mut should_rotate = user_left || visibility_changed || algorithm_changed;
+        let mut should_rotate =
+            user_left || visibility_changed || algorithm_changed || rotation_shortened;
 
         let own_identity =
             self.store.get_user_identity(self.store.user_id()).await?.and_then(|i| i.into_own());
@@ -385,13 +446,27 @@ impl GroupSessionManager {
         for user_id in users {
             let user_devices = self.store.get_readonly_devices_filtered(user_id).await?;
 
-            // We only need the user identity if settings.only_allow_trusted_devices is set.
-            let device_owner_identity = if settings.only_allow_trusted_devices {
+            // We only need the user identity if only trusted devices are allowed.
+            let device_owner_identity = if only_allow_trusted_devices {
                 self.store.get_user_identity(user_id).await?
             } else {
                 None
             };
 
+            if settings.error_on_unverified_devices {
+                if let Some(ReadOnlyUserIdentities::Other(identity)) = &device_owner_identity {
+                    let is_verified = own_identity
+                        .as_ref()
+                        .is_some_and(|own| own.is_identity_signed(identity).is_ok());
+
+                    if identity.has_pin_violation()
+                        || (identity.was_previously_verified() && !is_verified)
+                    {
+                        identity_violations.push(user_id.to_owned());
+                    }
+                }
+            }
+
             // From all the devices a user has, we're splitting them into two
             // buckets, a bucket of devices that should receive the
             // room key and a bucket of devices that should receive
@@ -402,9 +477,18 @@ impl GroupSessionManager {
             ) = user_devices.into_values().partition_map(|d| {
                 if d.is_blacklisted() {
                     Either::Right((d, WithheldCode::Blacklisted))
-                } else if settings.only_allow_trusted_devices
+                } else if only_allow_trusted_devices
                     && !d.is_verified(&own_identity, &device_owner_identity)
                 {
+                    if settings.error_on_unverified_devices
+                        && d.local_trust_state() != LocalTrust::Ignored
+                    {
+                        unverified_devices
+                            .entry(d.user_id().to_owned())
+                            .or_default()
+                            .push(d.device_id().to_owned());
+                    }
+
                     Either::Right((d, WithheldCode::Unverified))
                 } else {
                     Either::Left(d)
@@ -443,6 +527,19 @@ impl GroupSessionManager {
             withheld_devices.extend(withheld_recipients);
         }
 
+        if !identity_violations.is_empty() {
+            debug!(
+                ?identity_violations,
+                "Refusing to share the room key with users whose identity changed"
+            );
+            return Err(OlmError::IdentityViolations(identity_violations));
+        }
+
+        if !unverified_devices.is_empty() {
+            debug!(?unverified_devices, "Refusing to share the room key with unverified devices");
+            return Err(OlmError::UnverifiedDevices(unverified_devices));
+        }
+
         trace!(should_rotate, "Done calculating group session recipients");
 
         Ok(CollectRecipientsResult { should_rotate, devices, withheld_devices })
@@ -850,19 +947,22 @@ mod tests {
         events::room::history_visibility::HistoryVisibility,
         room_id,
         to_device::DeviceIdOrAllDevices,
-        user_id, DeviceId, TransactionId, UserId,
+        user_id, DeviceId, MilliSecondsSinceUnixEpoch, TransactionId, UserId,
     };
     use serde_json::{json, Value};
 
     use crate::{
+        olm::{KeyDistributionLogEntry, PrivateCrossSigningIdentity, ShareInfo},
         session_manager::group_sessions::CollectRecipientsResult,
+        store::{Changes, IdentityChanges, MAX_KEY_DISTRIBUTION_LOG_CHUNKS},
         types::{
             events::room_key_withheld::{
                 RoomKeyWithheldContent, RoomKeyWithheldContent::MegolmV1AesSha2, WithheldCode,
             },
             EventEncryptionAlgorithm,
         },
-        EncryptionSettings, LocalTrust, OlmMachine, ToDeviceRequest,
+        EncryptionSettings, LocalTrust, OlmError, OlmMachine, ReadOnlyUserIdentity,
+        RoomKeyRotationLimits, ToDeviceRequest,
     };
 
     fn alice_id() -> &'static UserId {
@@ -1037,6 +1137,99 @@ mod tests {
         assert_eq!(withheld_count, 2);
     }
 
+    #[async_test]
+    async fn test_key_distribution_log() {
+        let machine = machine().await;
+        let room_id = room_id!("!test:localhost");
+        let keys_claim = keys_claim_response();
+
+        let users = keys_claim.one_time_keys.keys().map(Deref::deref);
+        let requests =
+            machine.share_room_key(room_id, users, EncryptionSettings::default()).await.unwrap();
+
+        // Nothing is logged until the requests are sent.
+        assert!(machine.key_distribution_log(room_id, None).await.unwrap().is_empty());
+
+        let response = ToDeviceResponse::new();
+        for request in &requests {
+            machine.mark_request_as_sent(&request.txn_id, &response).await.unwrap();
+        }
+
+        let session_id = machine
+            .inner
+            .group_session_manager
+            .get_outbound_group_session(room_id)
+            .unwrap()
+            .session_id()
+            .to_owned();
+
+        let log = machine.key_distribution_log(room_id, None).await.unwrap();
+        assert_eq!(log.len(), 150);
+        assert!(log.iter().all(|entry| entry.session_id == session_id));
+
+        let shared_count =
+            log.iter().filter(|entry| matches!(entry.share_info, ShareInfo::Shared(_))).count();
+        assert_eq!(shared_count, 148);
+
+        let withheld_count = log
+            .iter()
+            .filter(|entry| matches!(entry.share_info, ShareInfo::Withheld(WithheldCode::NoOlm)))
+            .count();
+        assert_eq!(withheld_count, 2);
+
+        // A new session is logged after the previous one.
+        machine.invalidate_group_session(room_id).await.unwrap();
+        let users = keys_claim.one_time_keys.keys().map(Deref::deref);
+        let requests =
+            machine.share_room_key(room_id, users, EncryptionSettings::default()).await.unwrap();
+        for request in &requests {
+            machine.mark_request_as_sent(&request.txn_id, &response).await.unwrap();
+        }
+
+        // The devices without an Olm session don't get the `m.no_olm` withheld
+        // code again.
+        let log = machine.key_distribution_log(room_id, None).await.unwrap();
+        assert_eq!(log.len(), 298);
+        assert!(log[..150].iter().all(|entry| entry.session_id == session_id));
+        assert!(log[150..].iter().all(|entry| entry.session_id != session_id));
+
+        // The log can be filtered by session.
+        let log = machine.key_distribution_log(room_id, Some(&session_id)).await.unwrap();
+        assert_eq!(log.len(), 150);
+
+        // Other rooms have their own log.
+        let other_room_id = room_id!("!other:localhost");
+        assert!(machine.key_distribution_log(other_room_id, None).await.unwrap().is_empty());
+    }
+
+    #[async_test]
+    async fn test_key_distribution_log_rotation() {
+        let machine = machine().await;
+        let room_id = room_id!("!test:localhost");
+
+        let entry = |index: u64| KeyDistributionLogEntry {
+            session_id: index.to_string(),
+            user_id: alice_id().to_owned(),
+            device_id: alice_device_id().to_owned(),
+            share_info: ShareInfo::Withheld(WithheldCode::NoOlm),
+            timestamp: MilliSecondsSinceUnixEpoch::now(),
+        };
+
+        for index in 0..=MAX_KEY_DISTRIBUTION_LOG_CHUNKS {
+            machine
+                .store()
+                .append_to_key_distribution_log(room_id, vec![entry(index)])
+                .await
+                .unwrap();
+        }
+
+        // The oldest chunk was overwritten by the latest one.
+        let log = machine.key_distribution_log(room_id, None).await.unwrap();
+        assert_eq!(log.len() as u64, MAX_KEY_DISTRIBUTION_LOG_CHUNKS);
+        assert_eq!(log.first().unwrap().session_id, "1");
+        assert_eq!(log.last().unwrap().session_id, MAX_KEY_DISTRIBUTION_LOG_CHUNKS.to_string());
+    }
+
     fn count_withheld_from(requests: &[Arc<ToDeviceRequest>], code: WithheldCode) -> usize {
         requests
             .iter()
@@ -1176,6 +1369,21 @@ mod tests {
             ..Default::default()
         };
 
+        let CollectRecipientsResult { should_rotate, .. } = machine
+            .inner
+            .group_session_manager
+            .collect_session_recipients(users.clone(), &settings, &outbound)
+            .await
+            .unwrap();
+
+        assert!(should_rotate);
+
+        let limits = RoomKeyRotationLimits {
+            max_rotation_period: None,
+            max_rotation_period_msgs: Some(outbound.settings().rotation_period_msgs - 1),
+        };
+        let settings = EncryptionSettings::default().with_rotation_limits(limits);
+
         let CollectRecipientsResult { should_rotate, .. } = machine
             .inner
             .group_session_manager
@@ -1272,6 +1480,84 @@ mod tests {
         assert_eq!(149, withheld.len());
     }
 
+    #[async_test]
+    async fn test_sharing_errors_on_unverified_devices() {
+        let machine = machine().await;
+        let room_id = room_id!("!test:localhost");
+        let keys_claim = keys_claim_response();
+        let settings =
+            EncryptionSettings { error_on_unverified_devices: true, ..Default::default() };
+
+        let users = keys_claim.one_time_keys.keys().map(Deref::deref);
+        let error = machine.share_room_key(room_id, users, settings.clone()).await.unwrap_err();
+
+        let OlmError::UnverifiedDevices(unverified_devices) = error else {
+            panic!("Expected an error about the unverified devices, got {error:?}");
+        };
+        let user_id = user_id!("@example:localhost");
+        assert_eq!(unverified_devices.keys().collect::<Vec<_>>(), [user_id]);
+        assert!(!unverified_devices[user_id].is_empty());
+
+        // Once the devices are ignored, the room key can be shared with the other
+        // devices.
+        for device_id in &unverified_devices[user_id] {
+            let device = machine.get_device(user_id, device_id, None).await.unwrap().unwrap();
+            device.set_local_trust(LocalTrust::Ignored).await.unwrap();
+        }
+
+        let users = keys_claim.one_time_keys.keys().map(Deref::deref);
+        machine.share_room_key(room_id, users, settings).await.unwrap();
+    }
+
+    #[async_test]
+    async fn test_sharing_errors_on_identity_violations() {
+        let machine = machine().await;
+        let room_id = room_id!("!test:localhost");
+        let keys_claim = keys_claim_response();
+        let settings =
+            EncryptionSettings { error_on_unverified_devices: true, ..Default::default() };
+        let user_id = user_id!("@example:localhost");
+
+        // Ignore the unverified devices, so that only the identity matters.
+        for device in machine.get_user_devices(user_id, None).await.unwrap().devices() {
+            device.set_local_trust(LocalTrust::Ignored).await.unwrap();
+        }
+
+        // The user replaced their identity since we first saw it.
+        let first = ReadOnlyUserIdentity::from_private(&PrivateCrossSigningIdentity::new(
+            user_id.to_owned(),
+        ))
+        .await;
+        let second = ReadOnlyUserIdentity::from_private(&PrivateCrossSigningIdentity::new(
+            user_id.to_owned(),
+        ))
+        .await;
+        let mut identity = first;
+        identity.update(second.master_key().clone(), second.self_signing_key().clone()).unwrap();
+
+        let changes = Changes {
+            identities: IdentityChanges { new: vec![identity.into()], ..Default::default() },
+            ..Default::default()
+        };
+        machine.store().save_changes(changes).await.unwrap();
+
+        let users = keys_claim.one_time_keys.keys().map(Deref::deref);
+        let error = machine.share_room_key(room_id, users, settings.clone()).await.unwrap_err();
+
+        let OlmError::IdentityViolations(users) = error else {
+            panic!("Expected an error about the identity violations, got {error:?}");
+        };
+        assert_eq!(users, [user_id.to_owned()]);
+
+        // Once the new identity is pinned, the room key can be shared.
+        let identity = machine.get_identity(user_id, None).await.unwrap().unwrap().other().unwrap();
+        assert!(identity.has_pin_violation());
+        identity.pin_current_identity().await.unwrap();
+
+        let users = keys_claim.one_time_keys.keys().map(Deref::deref);
+        machine.share_room_key(room_id, users, settings).await.unwrap();
+    }
+
     #[async_test]
     async fn test_sharing_withheld_only_trusted() {
         let machine = machine().await;
@@ -573,8 +573,7 @@ impl SessionManager {
             }
         }
 
-        store_transaction.commit().await?;
-        self.store.save_changes(changes).await?;
+        store_transaction.commit_with_changes(changes).await?;
         info!(sessions = ?new_sessions, "Established new Olm sessions");
 
         for (user, device_map) in new_sessions {
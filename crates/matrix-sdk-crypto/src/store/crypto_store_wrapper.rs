@@ -11,7 +11,7 @@ use tracing::warn;
 use super::{DeviceChanges, IdentityChanges, LockableCryptoStore};
 use crate::{
     store,
-    store::{Changes, DynCryptoStore, IntoCryptoStore, RoomKeyInfo},
+    store::{Changes, DynCryptoStore, IntoCryptoStore, PendingChanges, RoomKeyInfo},
     GossippedSecret, ReadOnlyOwnUserIdentity,
 };
 
@@ -64,6 +64,25 @@ impl CryptoStoreWrapper {
     ///
     /// * `changes` - The set of changes that should be stored.
     pub async fn save_changes(&self, changes: Changes) -> store::Result<()> {
+        self.save_changes_batch(PendingChanges::default(), changes).await
+    }
+
+    /// Save the pending changes and the set of changes to the store, in a
+    /// single transaction if the store supports it.
+    ///
+    /// Also responsible for sending updates to the broadcast streams, like
+    /// [`CryptoStoreWrapper::save_changes()`].
+    ///
+    /// # Arguments
+    ///
+    /// * `pending_changes` - The pending changes that should be stored.
+    ///
+    /// * `changes` - The set of changes that should be stored.
+    pub async fn save_changes_batch(
+        &self,
+        pending_changes: PendingChanges,
+        changes: Changes,
+    ) -> store::Result<()> {
         let room_key_updates: Vec<_> =
             changes.inbound_group_sessions.iter().map(RoomKeyInfo::from).collect();
 
@@ -71,7 +90,7 @@ impl CryptoStoreWrapper {
         let devices = changes.devices.to_owned();
         let identities = changes.identities.to_owned();
 
-        self.store.save_changes(changes).await?;
+        self.store.save_changes_batch(pending_changes, changes).await?;
 
         if !room_key_updates.is_empty() {
             // Ignore the result. It can only fail if there are no listeners.
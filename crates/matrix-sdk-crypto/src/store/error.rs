@@ -44,6 +44,11 @@ pub enum CryptoStoreError {
         got: (OwnedUserId, OwnedDeviceId),
     },
 
+    /// The store already contains an Olm account whose device keys were
+    /// uploaded to the homeserver, and it can't be replaced by another one.
+    #[error("the account in the store was already shared with the homeserver")]
+    AccountAlreadyShared,
+
     /// An IO error occurred.
     #[error(transparent)]
     Io(#[from] IoError),
@@ -217,6 +217,40 @@ macro_rules! cryptostore_integration_tests {
                 assert_eq!(session_id, session.session_id());
             }
 
+            #[async_test]
+            async fn save_changes_batch() {
+                let store_name = "save_changes_batch";
+                let store = get_store(store_name, None).await;
+                let (account, session) = get_account_and_session().await;
+                let sender_key = session.sender_key.to_base64();
+                let session_id = session.session_id().to_owned();
+
+                let changes = Changes {
+                    sessions: vec![session.clone()],
+                    next_batch_token: Some("first_batch".to_owned()),
+                    ..Default::default()
+                };
+                store
+                    .save_changes_batch(
+                        PendingChanges { account: Some(account.deep_clone()) },
+                        changes,
+                    )
+                    .await
+                    .expect("Can't save the changes");
+
+                drop(store);
+
+                let store = get_store(store_name, None).await;
+
+                let loaded_account = store.load_account().await.unwrap().unwrap();
+                assert_eq!(account, loaded_account);
+
+                let sessions = store.get_sessions(&sender_key).await.unwrap().unwrap();
+                assert_eq!(session_id, sessions.lock().await[0].session_id());
+
+                assert_eq!(store.next_batch_token().await.unwrap().as_deref(), Some("first_batch"));
+            }
+
             #[async_test]
             async fn load_outbound_group_session() {
                 let dir = "load_outbound_group_session";
@@ -907,6 +941,12 @@ macro_rules! cryptostore_integration_tests {
                 let restored = store.load_backup_keys().await.unwrap();
                 assert!(restored.decryption_key.is_some(), "The backup decryption key should still be known");
                 assert!(restored.backup_version.is_some(), "The backup version should now be Some as well");
+
+                store.delete_backup_keys().await.unwrap();
+
+                let restored = store.load_backup_keys().await.unwrap();
+                assert!(restored.decryption_key.is_none(), "The backup decryption key should be deleted");
+                assert!(restored.backup_version.is_none(), "The backup version should be deleted");
             }
 
             #[async_test]
@@ -147,6 +147,16 @@ impl CryptoStore for MemoryStore {
         Ok(())
     }
 
+    async fn save_changes_batch(
+        &self,
+        pending_changes: PendingChanges,
+        changes: Changes,
+    ) -> Result<()> {
+        // Saving the changes in memory can't fail, so they are all saved.
+        self.save_pending_changes(pending_changes).await?;
+        self.save_changes(changes).await
+    }
+
     async fn save_changes(&self, changes: Changes) -> Result<()> {
         self.save_sessions(changes.sessions).await;
         self.save_inbound_group_sessions(changes.inbound_group_sessions);
@@ -291,6 +301,11 @@ impl CryptoStore for MemoryStore {
         Ok(self.backup_keys.read().await.to_owned())
     }
 
+    async fn delete_backup_keys(&self) -> Result<()> {
+        *self.backup_keys.write().await = BackupKeys::default();
+        Ok(())
+    }
+
     async fn get_outbound_group_session(&self, _: &RoomId) -> Result<Option<OutboundGroupSession>> {
         Ok(None)
     }
@@ -51,7 +51,8 @@ use as_variant::as_variant;
 use futures_core::Stream;
 use futures_util::StreamExt;
 use ruma::{
-    events::secret::request::SecretName, DeviceId, OwnedDeviceId, OwnedRoomId, OwnedUserId, UserId,
+    events::secret::request::SecretName, DeviceId, OwnedDeviceId, OwnedRoomId, OwnedUserId, RoomId,
+    UserId,
 };
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use thiserror::Error;
@@ -66,8 +67,9 @@ use crate::{
         user::UserIdentities, Device, ReadOnlyDevice, ReadOnlyUserIdentities, UserDevices,
     },
     olm::{
-        Account, ExportedRoomKey, InboundGroupSession, OlmMessageHash, OutboundGroupSession,
-        PrivateCrossSigningIdentity, Session, StaticAccountData,
+        Account, ExportedRoomKey, InboundGroupSession, KeyDistributionLogEntry, OlmMessageHash,
+        OutboundGroupSession, PickledAccount, PrivateCrossSigningIdentity, Session,
+        StaticAccountData,
     },
     types::{events::room_key_withheld::RoomKeyWithheldEvent, EventEncryptionAlgorithm},
     verification::VerificationMachine,
@@ -450,14 +452,24 @@ impl StoreTransaction {
     /// Commits all dirty fields to the store, and maintains the cache so it
     /// reflects the current state of the database.
     pub async fn commit(self) -> Result<()> {
-        if self.changes.is_empty() {
+        self.commit_with_changes(Changes::default()).await
+    }
+
+    /// Commits all dirty fields to the store alongside the given set of
+    /// changes, and maintains the cache so it reflects the current state of
+    /// the database.
+    ///
+    /// Both are written at once, in a single transaction if the store
+    /// supports it, so a crash can't persist only some of them.
+    pub async fn commit_with_changes(self, changes: Changes) -> Result<()> {
+        if self.changes.is_empty() && changes.is_empty() {
             return Ok(());
         }
 
         // Save changes in the database.
         let account = self.changes.account.as_ref().map(|acc| acc.deep_clone());
 
-        self.store.save_pending_changes(self.changes).await?;
+        self.store.inner.store.save_changes_batch(self.changes, changes).await?;
 
         // Make the cache coherent with the database.
         if let Some(account) = account {
@@ -483,6 +495,14 @@ struct StoreInner {
     /// Static account data that never changes (and thus can be loaded once and
     /// for all when creating the store).
     static_account: StaticAccountData,
+
+    /// Lock making sure that concurrent appends to a key distribution log
+    /// don't lose entries, even from other processes using the same store.
+    key_distribution_log_lock: CrossProcessStoreLock<LockableCryptoStore>,
+
+    /// Lock making sure that we don't create two user room keys for the same
+    /// room, see [`crate::pseudo_ids`].
+    user_room_keys_lock: Mutex<()>,
 }
 
 /// Aggregated changes to be saved in the database.
@@ -776,7 +796,7 @@ pub struct BackupKeys {
 
 /// A struct containing private cross signing keys that can be backed up or
 /// uploaded to the secret store.
-#[derive(Default, Zeroize)]
+#[derive(Default, Zeroize, Serialize, Deserialize)]
 #[zeroize(drop)]
 pub struct CrossSigningKeyExport {
     /// The seed of the master key encoded as unpadded base64.
@@ -798,6 +818,43 @@ impl Debug for CrossSigningKeyExport {
     }
 }
 
+/// The complete crypto identity of a device, as exported by
+/// [`OlmMachine::export_crypto_identity()`].
+///
+/// It contains everything needed to restore the device on a new client with
+/// [`OlmMachine::with_crypto_identity()`], when neither secret storage nor the
+/// key backup can be used.
+///
+/// [`OlmMachine::export_crypto_identity()`]: crate::OlmMachine::export_crypto_identity
+/// [`OlmMachine::with_crypto_identity()`]: crate::OlmMachine::with_crypto_identity
+#[derive(Serialize, Deserialize)]
+pub struct CryptoIdentityExport {
+    /// The pickled Olm account of the device.
+    pub account: PickledAccount,
+    /// The private cross signing keys, if we have them.
+    pub cross_signing_keys: Option<CrossSigningKeyExport>,
+    /// The key used to decrypt backed up room keys, if we have it.
+    pub backup_decryption_key: Option<BackupDecryptionKey>,
+    /// The version of the backup the decryption key is for.
+    pub backup_version: Option<String>,
+    /// The room keys of the device.
+    pub room_keys: Vec<ExportedRoomKey>,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl Debug for CryptoIdentityExport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CryptoIdentityExport")
+            .field("user_id", &self.account.user_id)
+            .field("device_id", &self.account.device_id)
+            .field("cross_signing_keys", &self.cross_signing_keys)
+            .field("backup_decryption_key", &self.backup_decryption_key.is_some())
+            .field("backup_version", &self.backup_version)
+            .field("room_keys", &self.room_keys.len())
+            .finish_non_exhaustive()
+    }
+}
+
 /// Error describing what went wrong when importing private cross signing keys
 /// or the key backup key.
 #[derive(Debug, Error)]
@@ -877,6 +934,38 @@ impl From<&InboundGroupSession> for RoomKeyInfo {
     }
 }
 
+/// The key of the lock of the key distribution logs, in the crypto store.
+const KEY_DISTRIBUTION_LOG_LOCK_KEY: &str = "key_distribution_log_lock";
+
+/// The maximum time to wait between two attempts to take the lock of the key
+/// distribution logs, in milliseconds.
+///
+/// Appending to the log happens when sending room keys, so it shouldn't wait
+/// for long.
+const KEY_DISTRIBUTION_LOG_LOCK_MAX_BACKOFF_MS: u32 = 100;
+
+/// The maximum number of chunks kept in the key distribution log of a room.
+///
+/// A chunk is appended every time a room key is sent, the oldest chunks are
+/// overwritten once this number is reached.
+pub(crate) const MAX_KEY_DISTRIBUTION_LOG_CHUNKS: u64 = 1000;
+
+/// The key of the custom value holding the number of chunks that were ever
+/// appended to the key distribution log of a room.
+fn key_distribution_log_len_key(room_id: &RoomId) -> String {
+    format!("key_distribution_log_len:{room_id}")
+}
+
+/// The key of the custom value holding a chunk of the key distribution log of a
+/// room.
+///
+/// The chunks are stored in a ring buffer of [`MAX_KEY_DISTRIBUTION_LOG_CHUNKS`]
+/// slots.
+fn key_distribution_log_chunk_key(room_id: &RoomId, index: u64) -> String {
+    let slot = index % MAX_KEY_DISTRIBUTION_LOG_CHUNKS;
+    format!("key_distribution_log:{room_id}:{slot}")
+}
+
 impl Store {
     /// Create a new Store.
     pub(crate) fn new(
@@ -885,6 +974,13 @@ impl Store {
         store: Arc<CryptoStoreWrapper>,
         verification_machine: VerificationMachine,
     ) -> Self {
+        // The lock only needs to tell apart the instances of the store, the
+        // holder doesn't need to be stable.
+        let key_distribution_log_lock = store.create_store_lock(
+            KEY_DISTRIBUTION_LOG_LOCK_KEY.to_owned(),
+            format!("{:016x}", rand::random::<u64>()),
+        );
+
         Self {
             inner: Arc::new(StoreInner {
                 static_account: account,
@@ -897,6 +993,8 @@ impl Store {
                     loaded_tracked_users: Default::default(),
                     account: Default::default(),
                 })),
+                key_distribution_log_lock,
+                user_room_keys_lock: Default::default(),
             }),
         }
     }
@@ -966,6 +1064,16 @@ impl Store {
         self.inner.store.save_changes(changes).await
     }
 
+    /// Save the given changes, and send them to the streams of the store, as if
+    /// they were received from the homeserver.
+    ///
+    /// Useful for testing purposes only.
+    #[cfg(any(test, feature = "testing"))]
+    #[doc(hidden)]
+    pub async fn save_changes_for_testing(&self, changes: Changes) -> Result<()> {
+        self.save_changes(changes).await
+    }
+
     /// Compare the given `InboundGroupSession` with an existing session we have
     /// in the store.
     ///
@@ -1264,6 +1372,71 @@ impl Store {
         self.set_value("only_allow_trusted_devices", &block_untrusted_devices).await
     }
 
+    /// Get the key distribution log of the given room, in the order the
+    /// entries were recorded.
+    ///
+    /// The log lists the devices that each outbound group session of the room
+    /// was shared with or withheld from, to audit who could have decrypted a
+    /// given message. Only the entries of the latest
+    /// [`MAX_KEY_DISTRIBUTION_LOG_CHUNKS`] room key shares are kept.
+    pub async fn key_distribution_log(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<KeyDistributionLogEntry>> {
+        let num_chunks = self.key_distribution_log_len(room_id).await?;
+        let first = num_chunks.saturating_sub(MAX_KEY_DISTRIBUTION_LOG_CHUNKS);
+
+        let mut log = Vec::new();
+        for index in first..num_chunks {
+            let key = key_distribution_log_chunk_key(room_id, index);
+            let chunk: Vec<KeyDistributionLogEntry> =
+                self.get_value(&key).await?.unwrap_or_default();
+            log.extend(chunk);
+        }
+
+        Ok(log)
+    }
+
+    /// Append the given entries to the key distribution log of the given room.
+    ///
+    /// The entries are saved as a new chunk of the log, so the previous
+    /// entries are not rewritten. The chunk replaces the oldest one once the
+    /// log has [`MAX_KEY_DISTRIBUTION_LOG_CHUNKS`] chunks.
+    pub(crate) async fn append_to_key_distribution_log(
+        &self,
+        room_id: &RoomId,
+        entries: impl IntoIterator<Item = KeyDistributionLogEntry>,
+    ) -> Result<()> {
+        let chunk: Vec<_> = entries.into_iter().collect();
+        if chunk.is_empty() {
+            return Ok(());
+        }
+
+        let _guard = self
+            .inner
+            .key_distribution_log_lock
+            .spin_lock(Some(KEY_DISTRIBUTION_LOG_LOCK_MAX_BACKOFF_MS))
+            .await
+            .map_err(CryptoStoreError::backend)?;
+
+        let index = self.key_distribution_log_len(room_id).await?;
+
+        // Save the chunk before counting it, so the log is never missing a
+        // chunk if saving it fails.
+        self.set_value(&key_distribution_log_chunk_key(room_id, index), &chunk).await?;
+        self.set_value(&key_distribution_log_len_key(room_id), &(index + 1)).await
+    }
+
+    /// Get the number of chunks of the key distribution log of the given room.
+    async fn key_distribution_log_len(&self, room_id: &RoomId) -> Result<u64> {
+        Ok(self.get_value(&key_distribution_log_len_key(room_id)).await?.unwrap_or_default())
+    }
+
+    /// Lock the creation of user room keys, see [`crate::pseudo_ids`].
+    pub(crate) async fn user_room_keys_lock(&self) -> MutexGuard<'_, ()> {
+        self.inner.user_room_keys_lock.lock().await
+    }
+
     /// Get custom stored value associated with a key
     pub async fn get_value<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
         let Some(value) = self.get_custom_value(key).await? else {
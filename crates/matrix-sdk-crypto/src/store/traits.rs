@@ -65,6 +65,24 @@ pub trait CryptoStore: AsyncTraitDeps {
     /// * `changes` - The set of changes that should be stored.
     async fn save_pending_changes(&self, changes: PendingChanges) -> Result<(), Self::Error>;
 
+    /// Save the pending changes and the set of changes to the store, in a
+    /// single transaction.
+    ///
+    /// This is used to persist all the changes of a sync processing pass at
+    /// once, so that a crash can't leave the store with only some of them.
+    /// Implementations must save either all the changes or none of them.
+    ///
+    /// # Arguments
+    ///
+    /// * `pending_changes` - The pending changes that should be stored.
+    ///
+    /// * `changes` - The set of changes that should be stored.
+    async fn save_changes_batch(
+        &self,
+        pending_changes: PendingChanges,
+        changes: Changes,
+    ) -> Result<(), Self::Error>;
+
     /// Get all the sessions that belong to the given sender key.
     ///
     /// # Arguments
@@ -126,6 +144,10 @@ pub trait CryptoStore: AsyncTraitDeps {
     /// Get the backup keys we have stored.
     async fn load_backup_keys(&self) -> Result<BackupKeys, Self::Error>;
 
+    /// Delete the backup decryption key and the backup version we have
+    /// stored.
+    async fn delete_backup_keys(&self) -> Result<(), Self::Error>;
+
     /// Get the outbound group session we have stored that is used for the
     /// given room.
     async fn get_outbound_group_session(
@@ -312,6 +334,14 @@ impl<T: CryptoStore> CryptoStore for EraseCryptoStoreError<T> {
         self.0.save_pending_changes(changes).await.map_err(Into::into)
     }
 
+    async fn save_changes_batch(
+        &self,
+        pending_changes: PendingChanges,
+        changes: Changes,
+    ) -> Result<()> {
+        self.0.save_changes_batch(pending_changes, changes).await.map_err(Into::into)
+    }
+
     async fn get_sessions(&self, sender_key: &str) -> Result<Option<Arc<Mutex<Vec<Session>>>>> {
         self.0.get_sessions(sender_key).await.map_err(Into::into)
     }
@@ -357,6 +387,10 @@ impl<T: CryptoStore> CryptoStore for EraseCryptoStoreError<T> {
         self.0.load_backup_keys().await.map_err(Into::into)
     }
 
+    async fn delete_backup_keys(&self) -> Result<()> {
+        self.0.delete_backup_keys().await.map_err(Into::into)
+    }
+
     async fn get_outbound_group_session(
         &self,
         room_id: &RoomId,
@@ -356,53 +356,27 @@ macro_rules! impl_crypto_store {
 
 impl_crypto_store! {
     async fn save_pending_changes(&self, changes: PendingChanges) -> Result<()> {
-        // Serialize calls to `save_pending_changes`; there are multiple await points below, and we're
-        // pickling data as we go, so we don't want to invalidate data we've previously read and
-        // overwrite it in the store.
-        // TODO: #2000 should make this lock go away, or change its shape.
-        let _guard = self.save_changes_lock.lock().await;
-
-        let stores: Vec<&str> = [
-            (changes.account.is_some() , keys::CORE),
-        ]
-        .iter()
-        .filter_map(|(id, key)| if *id { Some(*key) } else { None })
-        .collect();
-
-        if stores.is_empty() {
-            // nothing to do, quit early
-            return Ok(());
-        }
-
-        let tx =
-            self.inner.transaction_on_multi_with_mode(&stores, IdbTransactionMode::Readwrite)?;
-
-        let account_pickle = if let Some(account) = changes.account {
-            *self.static_account.write().unwrap() = Some(account.static_data().clone());
-            Some(account.pickle())
-        } else {
-            None
-        };
-
-        if let Some(a) = &account_pickle {
-            tx.object_store(keys::CORE)?
-                .put_key_val(&JsValue::from_str(keys::ACCOUNT), &self.serializer.serialize_value(&a)?)?;
-        }
-
-        tx.await.into_result()?;
-
-        Ok(())
+        self.save_changes_batch(changes, Changes::default()).await
     }
 
     async fn save_changes(&self, changes: Changes) -> Result<()> {
-        // Serialize calls to `save_changes`; there are multiple await points below, and we're
-        // pickling data as we go, so we don't want to invalidate data we've previously read and
-        // overwrite it in the store.
+        self.save_changes_batch(PendingChanges::default(), changes).await
+    }
+
+    async fn save_changes_batch(&self, pending_changes: PendingChanges, changes: Changes) -> Result<()> {
+        // Serialize calls to `save_changes_batch`; there are multiple await points below, and
+        // we're pickling data as we go, so we don't want to invalidate data we've previously read
+        // and overwrite it in the store.
         // TODO: #2000 should make this lock go away, or change its shape.
         let _guard = self.save_changes_lock.lock().await;
 
         let mut stores: Vec<&str> = [
-            (changes.private_identity.is_some() || changes.next_batch_token.is_some(), keys::CORE),
+            (
+                pending_changes.account.is_some()
+                    || changes.private_identity.is_some()
+                    || changes.next_batch_token.is_some(),
+                keys::CORE,
+            ),
             (changes.backup_decryption_key.is_some() || changes.backup_version.is_some(), keys::BACKUP_KEYS),
             (!changes.sessions.is_empty(), keys::SESSION),
             (
@@ -436,9 +410,23 @@ impl_crypto_store! {
             return Ok(());
         }
 
+        // Everything is written in a single transaction, so a crash can't persist only some of
+        // the changes.
         let tx =
             self.inner.transaction_on_multi_with_mode(&stores, IdbTransactionMode::Readwrite)?;
 
+        let account_pickle = if let Some(account) = pending_changes.account {
+            *self.static_account.write().unwrap() = Some(account.static_data().clone());
+            Some(account.pickle())
+        } else {
+            None
+        };
+
+        if let Some(a) = &account_pickle {
+            tx.object_store(keys::CORE)?
+                .put_key_val(&JsValue::from_str(keys::ACCOUNT), &self.serializer.serialize_value(&a)?)?;
+        }
+
         let private_identity_pickle =
             if let Some(i) = changes.private_identity { Some(i.pickle().await) } else { None };
 
@@ -1075,6 +1063,18 @@ impl_crypto_store! {
         Ok(key)
     }
 
+    async fn delete_backup_keys(&self) -> Result<()> {
+        let tx = self
+            .inner
+            .transaction_on_one_with_mode(keys::BACKUP_KEYS, IdbTransactionMode::Readwrite)?;
+        let store = tx.object_store(keys::BACKUP_KEYS)?;
+
+        store.delete(&JsValue::from_str(keys::BACKUP_KEY_V1))?;
+        store.delete(&JsValue::from_str(keys::RECOVERY_KEY_V1))?;
+
+        tx.await.into_result().map_err(|e| e.into())
+    }
+
     async fn get_withheld_info(
         &self,
         room_id: &RoomId,
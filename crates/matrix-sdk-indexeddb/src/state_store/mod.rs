@@ -41,7 +41,8 @@ use ruma::{
         GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType, SyncStateEvent,
     },
     serde::Raw,
-    CanonicalJsonObject, EventId, MxcUri, OwnedEventId, OwnedUserId, RoomId, RoomVersionId, UserId,
+    CanonicalJsonObject, EventId, MilliSecondsSinceUnixEpoch, MxcUri, OwnedEventId, OwnedUserId,
+    RoomId, RoomVersionId, UserId,
 };
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tracing::{debug, warn};
@@ -492,6 +493,7 @@ impl_state_store!({
             (!changes.profiles.is_empty(), keys::PROFILES),
             (!changes.room_account_data.is_empty(), keys::ROOM_ACCOUNT_DATA),
             (!changes.receipts.is_empty(), keys::ROOM_EVENT_RECEIPTS),
+            (!changes.custom_values.is_empty(), keys::CUSTOM),
         ]
         .iter()
         .filter_map(|(id, key)| if *id { Some(*key) } else { None })
@@ -539,6 +541,15 @@ impl_state_store!({
             )?;
         }
 
+        if !changes.custom_values.is_empty() {
+            let store = tx.object_store(keys::CUSTOM)?;
+            for (key, value) in &changes.custom_values {
+                let jskey =
+                    JsValue::from_str(core::str::from_utf8(key).map_err(StoreError::Codec)?);
+                store.put_key_val(&jskey, &self.serialize_event(value)?)?;
+            }
+        }
+
         if !changes.ambiguity_maps.is_empty() {
             let store = tx.object_store(keys::DISPLAY_NAMES)?;
             for (room_id, ambiguity_maps) in &changes.ambiguity_maps {
@@ -875,6 +886,39 @@ impl_state_store!({
             .collect::<Vec<_>>())
     }
 
+    async fn get_all_state_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>> {
+        let stripped_range = self.encode_to_range(keys::STRIPPED_ROOM_STATE, room_id)?;
+        let stripped_events = self
+            .inner
+            .transaction_on_one_with_mode(keys::STRIPPED_ROOM_STATE, IdbTransactionMode::Readonly)?
+            .object_store(keys::STRIPPED_ROOM_STATE)?
+            .get_all_with_key(&stripped_range)?
+            .await?
+            .iter()
+            .filter_map(|f| {
+                self.deserialize_event(&f).ok().map(RawAnySyncOrStrippedState::Stripped)
+            })
+            .collect::<Vec<_>>();
+
+        if !stripped_events.is_empty() {
+            return Ok(stripped_events);
+        }
+
+        let range = self.encode_to_range(keys::ROOM_STATE, room_id)?;
+        Ok(self
+            .inner
+            .transaction_on_one_with_mode(keys::ROOM_STATE, IdbTransactionMode::Readonly)?
+            .object_store(keys::ROOM_STATE)?
+            .get_all_with_key(&range)?
+            .await?
+            .iter()
+            .filter_map(|f| self.deserialize_event(&f).ok().map(RawAnySyncOrStrippedState::Sync))
+            .collect::<Vec<_>>())
+    }
+
     async fn get_state_events_for_keys(
         &self,
         room_id: &RoomId,
@@ -1258,6 +1302,89 @@ impl_state_store!({
         tx.await.into_result().map_err(|e| e.into())
     }
 
+    async fn remove_room_receipts_before(
+        &self,
+        room_id: &RoomId,
+        before: MilliSecondsSinceUnixEpoch,
+    ) -> Result<()> {
+        let is_expired = |receipt: &Receipt| receipt.ts.is_some_and(|ts| ts < before);
+
+        let tx = self.inner.transaction_on_multi_with_mode(
+            &[keys::ROOM_USER_RECEIPTS, keys::ROOM_EVENT_RECEIPTS],
+            IdbTransactionMode::Readwrite,
+        )?;
+
+        for store_name in [keys::ROOM_USER_RECEIPTS, keys::ROOM_EVENT_RECEIPTS] {
+            let store = tx.object_store(store_name)?;
+            let range = self.encode_to_range(store_name, room_id)?;
+            let Some(cursor) = store.open_cursor_with_range(&range)?.await? else { continue };
+
+            let mut expired_keys = Vec::new();
+
+            while let Some(key) = cursor.key() {
+                // The user receipts hold the event ID and the event receipts hold the
+                // user ID, only the receipt matters here.
+                let receipt = if store_name == keys::ROOM_USER_RECEIPTS {
+                    self.deserialize_event::<(OwnedEventId, Receipt)>(&cursor.value())?.1
+                } else {
+                    self.deserialize_event::<(OwnedUserId, Receipt)>(&cursor.value())?.1
+                };
+
+                if is_expired(&receipt) {
+                    expired_keys.push(key);
+                }
+
+                if !cursor.continue_cursor()?.await? {
+                    break;
+                }
+            }
+
+            for key in expired_keys {
+                store.delete(&key)?;
+            }
+        }
+
+        tx.await.into_result().map_err(|e| e.into())
+    }
+
+    async fn try_take_leased_lock(
+        &self,
+        lease_duration_ms: u32,
+        key: &str,
+        holder: &str,
+    ) -> Result<bool> {
+        #[derive(Deserialize, Serialize)]
+        struct Lease {
+            holder: String,
+            expiration_ts: u64,
+        }
+
+        let key = self.encode_key(keys::KV, ("lease_lock", key));
+        let tx =
+            self.inner.transaction_on_one_with_mode(keys::KV, IdbTransactionMode::Readwrite)?;
+        let obj = tx.object_store(keys::KV)?;
+
+        let now_ts: u64 = MilliSecondsSinceUnixEpoch::now().get().into();
+        let expiration_ts = now_ts + lease_duration_ms as u64;
+
+        let can_take = match obj.get(&key)?.await? {
+            Some(prev) => {
+                let lease: Lease = self.deserialize_event(&prev)?;
+                lease.holder == holder || lease.expiration_ts < now_ts
+            }
+            None => true,
+        };
+
+        if can_take {
+            let lease = Lease { holder: holder.to_owned(), expiration_ts };
+            obj.put_key_val(&key, &self.serialize_event(&lease)?)?;
+        }
+
+        tx.await.into_result()?;
+
+        Ok(can_take)
+    }
+
     async fn get_user_ids(
         &self,
         room_id: &RoomId,
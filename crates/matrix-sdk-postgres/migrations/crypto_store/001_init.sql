@@ -0,0 +1,81 @@
+-- basic kv data like the database version, store cipher and account
+CREATE TABLE "crypto_kv" (
+    "key" TEXT PRIMARY KEY NOT NULL,
+    "value" BYTEA NOT NULL
+);
+
+CREATE TABLE "session" (
+    "session_id" BYTEA PRIMARY KEY NOT NULL,
+    "sender_key" BYTEA NOT NULL,
+    "data" BYTEA NOT NULL
+);
+CREATE INDEX "session_sender_key_idx"
+    ON "session" ("sender_key");
+
+CREATE TABLE "inbound_group_session" (
+    "session_id" BYTEA PRIMARY KEY NOT NULL,
+    "room_id" BYTEA NOT NULL,
+    "backed_up" BOOLEAN NOT NULL,
+    "data" BYTEA NOT NULL
+);
+CREATE INDEX "inbound_group_session_room_id_idx"
+    ON "inbound_group_session" ("room_id");
+CREATE INDEX "inbound_group_session_backed_up_idx"
+    ON "inbound_group_session" ("backed_up");
+
+CREATE TABLE "outbound_group_session" (
+    "room_id" BYTEA PRIMARY KEY NOT NULL,
+    "data" BYTEA NOT NULL
+);
+
+CREATE TABLE "device" (
+    "user_id" BYTEA NOT NULL,
+    "device_id" BYTEA NOT NULL,
+    "data" BYTEA NOT NULL,
+
+    PRIMARY KEY ("user_id", "device_id")
+);
+
+CREATE TABLE "identity" (
+    "user_id" BYTEA PRIMARY KEY NOT NULL,
+    "data" BYTEA NOT NULL
+);
+
+CREATE TABLE "tracked_user" (
+    "user_id" BYTEA PRIMARY KEY NOT NULL,
+    "data" BYTEA NOT NULL
+);
+
+CREATE TABLE "olm_hash" (
+    "data" BYTEA PRIMARY KEY NOT NULL
+);
+
+CREATE TABLE "key_requests" (
+    "request_id" BYTEA PRIMARY KEY NOT NULL,
+    "sent_out" BOOLEAN NOT NULL,
+    "data" BYTEA NOT NULL
+);
+
+CREATE TABLE "room_settings" (
+    "room_id" BYTEA PRIMARY KEY NOT NULL,
+    "data" BYTEA NOT NULL
+);
+
+CREATE TABLE "direct_withheld_info" (
+    "session_id" BYTEA PRIMARY KEY NOT NULL,
+    "room_id" BYTEA NOT NULL,
+    "data" BYTEA NOT NULL
+);
+
+CREATE TABLE "secrets" (
+    "secret_name" BYTEA NOT NULL,
+    "data" BYTEA NOT NULL
+);
+CREATE INDEX "secrets_secret_name_idx"
+    ON "secrets" ("secret_name");
+
+CREATE TABLE "crypto_lease_locks" (
+    "key" TEXT PRIMARY KEY NOT NULL,
+    "holder" TEXT NOT NULL,
+    "expiration_ts" BIGINT NOT NULL
+);
@@ -0,0 +1,103 @@
+-- basic kv data like the database version and store cipher
+CREATE TABLE "state_kv" (
+    "key" TEXT PRIMARY KEY NOT NULL,
+    "value" BYTEA NOT NULL
+);
+
+-- another general-purpose kv table, but with binary keys
+-- used for sync token, filters and other things
+CREATE TABLE "kv_blob" (
+    "key" BYTEA PRIMARY KEY NOT NULL,
+    "value" BYTEA NOT NULL
+);
+
+CREATE TABLE "room_info" (
+    "room_id" BYTEA PRIMARY KEY NOT NULL,
+    "state" BYTEA NOT NULL,
+    "data" BYTEA NOT NULL
+);
+CREATE INDEX "room_info_state"
+    ON "room_info" ("state");
+
+CREATE TABLE "state_event" (
+    "room_id" BYTEA NOT NULL,
+    "event_type" BYTEA NOT NULL,
+    "state_key" BYTEA NOT NULL,
+    "stripped" BOOLEAN NOT NULL,
+    "event_id" BYTEA,
+    "data" BYTEA NOT NULL,
+
+    PRIMARY KEY ("room_id", "event_type", "state_key")
+);
+CREATE INDEX "state_event_room_id_event_type_stripped_event_id"
+    ON "state_event" ("room_id", "event_type", "stripped", "event_id");
+CREATE INDEX "state_event_room_id_event_id"
+    ON "state_event" ("room_id", "event_id");
+
+CREATE TABLE "global_account_data" (
+    "event_type" BYTEA PRIMARY KEY NOT NULL,
+    "data" BYTEA NOT NULL
+);
+
+CREATE TABLE "room_account_data" (
+    "room_id" BYTEA NOT NULL,
+    "event_type" BYTEA NOT NULL,
+    "data" BYTEA NOT NULL,
+
+    PRIMARY KEY ("room_id", "event_type")
+);
+
+CREATE TABLE "member" (
+    "room_id" BYTEA NOT NULL,
+    "user_id" BYTEA NOT NULL,
+    "membership" BYTEA NOT NULL,
+    "stripped" BOOLEAN NOT NULL,
+    "data" BYTEA NOT NULL,
+
+    PRIMARY KEY ("room_id", "user_id")
+);
+CREATE INDEX "member_room_id_membership"
+    ON "member" ("room_id", "membership");
+
+CREATE TABLE "profile" (
+    "room_id" BYTEA NOT NULL,
+    "user_id" BYTEA NOT NULL,
+    "data" BYTEA NOT NULL,
+
+    PRIMARY KEY ("room_id", "user_id")
+);
+
+CREATE TABLE "receipt" (
+    "room_id" BYTEA NOT NULL,
+    "user_id" BYTEA NOT NULL,
+    "receipt_type" BYTEA NOT NULL,
+    "thread" BYTEA NOT NULL,
+    "event_id" BYTEA NOT NULL,
+    "data" BYTEA NOT NULL,
+
+    PRIMARY KEY ("room_id", "user_id", "receipt_type", "thread")
+);
+CREATE INDEX "receipt_room_id_receipt_type_thread_id_event_id"
+    ON "receipt" ("room_id", "receipt_type", "thread", "event_id");
+
+CREATE TABLE "display_name" (
+    "room_id" BYTEA NOT NULL,
+    "name" BYTEA NOT NULL,
+    "data" BYTEA NOT NULL,
+
+    PRIMARY KEY ("room_id", "name")
+);
+
+CREATE TABLE "media" (
+    "uri" BYTEA NOT NULL,
+    "format" BYTEA NOT NULL,
+    "data" BYTEA NOT NULL,
+
+    PRIMARY KEY ("uri", "format")
+);
+
+CREATE TABLE "state_lease_locks" (
+    "key" TEXT PRIMARY KEY NOT NULL,
+    "holder" TEXT NOT NULL,
+    "expiration_ts" BIGINT NOT NULL
+);
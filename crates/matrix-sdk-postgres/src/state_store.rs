@@ -0,0 +1,1589 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use deadpool_postgres::{GenericClient, Object as PostgresConn, Pool as PostgresPool};
+use matrix_sdk_base::{
+    deserialized_responses::RawAnySyncOrStrippedState,
+    media::{MediaRequest, UniqueKey},
+    MinimalRoomMemberEvent, RoomInfo, RoomMemberships, RoomState, StateChanges, StateStore,
+    StateStoreDataKey, StateStoreDataValue,
+};
+use matrix_sdk_store_encryption::StoreCipher;
+use ruma::{
+    canonical_json::{redact, RedactedBecause},
+    events::{
+        presence::PresenceEvent,
+        receipt::{Receipt, ReceiptThread, ReceiptType},
+        room::member::{StrippedRoomMemberEvent, SyncRoomMemberEvent},
+        AnyGlobalAccountDataEvent, AnyRoomAccountDataEvent, AnySyncStateEvent,
+        GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType,
+    },
+    serde::Raw,
+    CanonicalJsonObject, EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedUserId, RoomId,
+    RoomVersionId, UserId,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::{
+    create_pool,
+    error::{Error, Result},
+    get_or_create_store_cipher,
+    utils::{self, Migrations},
+    OpenStoreError,
+};
+
+mod keys {
+    // Tables
+    pub const KV: &str = "state_kv";
+    pub const KV_BLOB: &str = "kv_blob";
+    pub const ROOM_INFO: &str = "room_info";
+    pub const STATE_EVENT: &str = "state_event";
+    pub const GLOBAL_ACCOUNT_DATA: &str = "global_account_data";
+    pub const ROOM_ACCOUNT_DATA: &str = "room_account_data";
+    pub const MEMBER: &str = "member";
+    pub const PROFILE: &str = "profile";
+    pub const RECEIPT: &str = "receipt";
+    pub const DISPLAY_NAME: &str = "display_name";
+    pub const MEDIA: &str = "media";
+    pub const LEASE_LOCKS: &str = "state_lease_locks";
+}
+
+/// The migrations of the state store.
+pub(crate) const MIGRATIONS: Migrations = Migrations {
+    // `matrix` in ASCII, followed by the number of the store.
+    lock_id: 0x6d61_7472_6978_0001,
+    kv_table: keys::KV,
+    scripts: &[include_str!("../migrations/state_store/001_init.sql")],
+};
+
+/// A PostgreSQL based state store.
+#[derive(Clone)]
+pub struct PostgresStateStore {
+    store_cipher: Option<Arc<StoreCipher>>,
+    pool: PostgresPool,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl fmt::Debug for PostgresStateStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PostgresStateStore").finish_non_exhaustive()
+    }
+}
+
+impl PostgresStateStore {
+    /// Open the PostgreSQL-based state store in the database at the given URL
+    /// using the given passphrase to encrypt private data.
+    ///
+    /// The connections to the database don't use TLS, use
+    /// [`PostgresStateStore::open_with_pool()`] with a pool using TLS
+    /// otherwise.
+    pub async fn open(url: &str, passphrase: Option<&str>) -> Result<Self, OpenStoreError> {
+        let pool = create_pool(url).await?;
+
+        Self::open_with_pool(pool, passphrase).await
+    }
+
+    /// Create a PostgreSQL-based state store using the given database pool.
+    /// The given passphrase will be used to encrypt private data.
+    ///
+    /// The tables of the store are created or upgraded if needed.
+    pub async fn open_with_pool(
+        pool: PostgresPool,
+        passphrase: Option<&str>,
+    ) -> Result<Self, OpenStoreError> {
+        let mut conn = pool.get().await?;
+        MIGRATIONS.run(&mut conn).await?;
+
+        let store_cipher = match passphrase {
+            Some(p) => Some(Arc::new(get_or_create_store_cipher(p, &*conn, keys::KV).await?)),
+            None => None,
+        };
+
+        Ok(Self { store_cipher, pool })
+    }
+
+    fn encode_value(&self, value: Vec<u8>) -> Result<Vec<u8>> {
+        if let Some(key) = &self.store_cipher {
+            let encrypted = key.encrypt_value_data(value)?;
+            Ok(rmp_serde::to_vec_named(&encrypted)?)
+        } else {
+            Ok(value)
+        }
+    }
+
+    fn serialize_value(&self, value: &impl Serialize) -> Result<Vec<u8>> {
+        let serialized = rmp_serde::to_vec_named(value)?;
+        self.encode_value(serialized)
+    }
+
+    fn serialize_json(&self, value: &impl Serialize) -> Result<Vec<u8>> {
+        let serialized = serde_json::to_vec(value)?;
+        self.encode_value(serialized)
+    }
+
+    fn decode_value<'a>(&self, value: &'a [u8]) -> Result<Cow<'a, [u8]>> {
+        if let Some(key) = &self.store_cipher {
+            let encrypted = rmp_serde::from_slice(value)?;
+            let decrypted = key.decrypt_value_data(encrypted)?;
+            Ok(Cow::Owned(decrypted))
+        } else {
+            Ok(Cow::Borrowed(value))
+        }
+    }
+
+    fn deserialize_json<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T> {
+        let decoded = self.decode_value(data)?;
+        Ok(serde_json::from_slice(&decoded)?)
+    }
+
+    fn deserialize_value<T: DeserializeOwned>(&self, value: &[u8]) -> Result<T> {
+        let decoded = self.decode_value(value)?;
+        Ok(rmp_serde::from_slice(&decoded)?)
+    }
+
+    fn deserialize_maybe_stripped_state_event(
+        &self,
+        stripped: bool,
+        data: &[u8],
+    ) -> Result<RawAnySyncOrStrippedState> {
+        Ok(if stripped {
+            RawAnySyncOrStrippedState::Stripped(self.deserialize_json(data)?)
+        } else {
+            RawAnySyncOrStrippedState::Sync(self.deserialize_json(data)?)
+        })
+    }
+
+    fn encode_key(&self, table_name: &str, key: impl AsRef<[u8]>) -> Vec<u8> {
+        let bytes = key.as_ref();
+        if let Some(store_cipher) = &self.store_cipher {
+            store_cipher.hash_key(table_name, bytes).to_vec()
+        } else {
+            bytes.to_owned()
+        }
+    }
+
+    fn encode_state_store_data_key(&self, key: StateStoreDataKey<'_>) -> Vec<u8> {
+        let key_s = match key {
+            StateStoreDataKey::SyncToken => Cow::Borrowed(StateStoreDataKey::SYNC_TOKEN),
+            StateStoreDataKey::Filter(f) => {
+                Cow::Owned(format!("{}:{f}", StateStoreDataKey::FILTER))
+            }
+            StateStoreDataKey::UserAvatarUrl(u) => {
+                Cow::Owned(format!("{}:{u}", StateStoreDataKey::USER_AVATAR_URL))
+            }
+        };
+
+        self.encode_key(keys::KV_BLOB, &*key_s)
+    }
+
+    fn encode_presence_key(&self, user_id: &UserId) -> Vec<u8> {
+        self.encode_key(keys::KV_BLOB, format!("presence:{user_id}"))
+    }
+
+    fn encode_custom_key(&self, key: &[u8]) -> Vec<u8> {
+        let mut full_key = b"custom:".to_vec();
+        full_key.extend(key);
+        self.encode_key(keys::KV_BLOB, full_key)
+    }
+
+    async fn acquire(&self) -> Result<PostgresConn> {
+        Ok(self.pool.get().await?)
+    }
+
+    async fn remove_maybe_stripped_room_data(
+        &self,
+        client: &impl GenericClient,
+        room_id: &RoomId,
+        stripped: bool,
+    ) -> Result<()> {
+        let state_event_room_id = self.encode_key(keys::STATE_EVENT, room_id);
+        client.remove_room_state_events(&state_event_room_id, Some(stripped)).await?;
+
+        let member_room_id = self.encode_key(keys::MEMBER, room_id);
+        client.remove_room_members(&member_room_id, Some(stripped)).await
+    }
+
+    /// Get the version of the given room from its room info in the database,
+    /// defaulting to version 9.
+    async fn get_room_version(
+        &self,
+        client: &impl GenericClient,
+        room_id: &RoomId,
+    ) -> RoomVersionId {
+        let encoded_room_id = self.encode_key(keys::ROOM_INFO, room_id);
+        client
+            .get_room_info(&encoded_room_id)
+            .await
+            .ok()
+            .flatten()
+            .and_then(|v| self.deserialize_json::<RoomInfo>(&v).ok())
+            .and_then(|info| info.room_version().cloned())
+            .unwrap_or_else(|| {
+                warn!(?room_id, "Unable to find the room version, assume version 9");
+                RoomVersionId::V9
+            })
+    }
+}
+
+#[async_trait]
+trait PostgresClientStateStoreExt: GenericClient {
+    async fn get_kv_blob(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let stmt = self.prepare_cached("SELECT value FROM kv_blob WHERE key = $1").await?;
+        Ok(self.query_opt(&stmt, &[&key]).await?.map(|row| row.get(0)))
+    }
+
+    async fn get_kv_blobs(&self, keys: &[Vec<u8>]) -> Result<Vec<Vec<u8>>> {
+        let stmt = self.prepare_cached("SELECT value FROM kv_blob WHERE key = ANY($1)").await?;
+        Ok(self.query(&stmt, &[&keys]).await?.iter().map(|row| row.get(0)).collect())
+    }
+
+    async fn set_kv_blob(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        let stmt = self
+            .prepare_cached(
+                "INSERT INTO kv_blob (key, value) VALUES ($1, $2)
+                 ON CONFLICT (key) DO UPDATE SET value = $2",
+            )
+            .await?;
+        self.execute(&stmt, &[&key, &value]).await?;
+        Ok(())
+    }
+
+    async fn delete_kv_blob(&self, key: &[u8]) -> Result<()> {
+        let stmt = self.prepare_cached("DELETE FROM kv_blob WHERE key = $1").await?;
+        self.execute(&stmt, &[&key]).await?;
+        Ok(())
+    }
+
+    async fn set_global_account_data(&self, event_type: &[u8], data: &[u8]) -> Result<()> {
+        let stmt = self
+            .prepare_cached(
+                "INSERT INTO global_account_data (event_type, data) VALUES ($1, $2)
+                 ON CONFLICT (event_type) DO UPDATE SET data = $2",
+            )
+            .await?;
+        self.execute(&stmt, &[&event_type, &data]).await?;
+        Ok(())
+    }
+
+    async fn get_global_account_data(&self, event_type: &[u8]) -> Result<Option<Vec<u8>>> {
+        let stmt = self
+            .prepare_cached("SELECT data FROM global_account_data WHERE event_type = $1")
+            .await?;
+        Ok(self.query_opt(&stmt, &[&event_type]).await?.map(|row| row.get(0)))
+    }
+
+    async fn set_room_account_data(
+        &self,
+        room_id: &[u8],
+        event_type: &[u8],
+        data: &[u8],
+    ) -> Result<()> {
+        let stmt = self
+            .prepare_cached(
+                "INSERT INTO room_account_data (room_id, event_type, data) VALUES ($1, $2, $3)
+                 ON CONFLICT (room_id, event_type) DO UPDATE SET data = $3",
+            )
+            .await?;
+        self.execute(&stmt, &[&room_id, &event_type, &data]).await?;
+        Ok(())
+    }
+
+    async fn get_room_account_data(
+        &self,
+        room_id: &[u8],
+        event_type: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        let stmt = self
+            .prepare_cached(
+                "SELECT data FROM room_account_data WHERE room_id = $1 AND event_type = $2",
+            )
+            .await?;
+        Ok(self.query_opt(&stmt, &[&room_id, &event_type]).await?.map(|row| row.get(0)))
+    }
+
+    async fn remove_room_account_data(&self, room_id: &[u8]) -> Result<()> {
+        let stmt = self.prepare_cached("DELETE FROM room_account_data WHERE room_id = $1").await?;
+        self.execute(&stmt, &[&room_id]).await?;
+        Ok(())
+    }
+
+    async fn set_room_info(&self, room_id: &[u8], state: &[u8], data: &[u8]) -> Result<()> {
+        let stmt = self
+            .prepare_cached(
+                "INSERT INTO room_info (room_id, state, data) VALUES ($1, $2, $3)
+                 ON CONFLICT (room_id) DO UPDATE SET state = $2, data = $3",
+            )
+            .await?;
+        self.execute(&stmt, &[&room_id, &state, &data]).await?;
+        Ok(())
+    }
+
+    async fn get_room_info(&self, room_id: &[u8]) -> Result<Option<Vec<u8>>> {
+        let stmt = self.prepare_cached("SELECT data FROM room_info WHERE room_id = $1").await?;
+        Ok(self.query_opt(&stmt, &[&room_id]).await?.map(|row| row.get(0)))
+    }
+
+    /// Get the room infos of the rooms in the given states, or of all the rooms
+    /// if `states` is empty.
+    async fn get_room_infos(&self, states: &[Vec<u8>]) -> Result<Vec<Vec<u8>>> {
+        let rows = if states.is_empty() {
+            let stmt = self.prepare_cached("SELECT data FROM room_info").await?;
+            self.query(&stmt, &[]).await?
+        } else {
+            let stmt =
+                self.prepare_cached("SELECT data FROM room_info WHERE state = ANY($1)").await?;
+            self.query(&stmt, &[&states]).await?
+        };
+
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    /// Remove the room info for the given room.
+    async fn remove_room_info(&self, room_id: &[u8]) -> Result<()> {
+        let stmt = self.prepare_cached("DELETE FROM room_info WHERE room_id = $1").await?;
+        self.execute(&stmt, &[&room_id]).await?;
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn set_state_event(
+        &self,
+        room_id: &[u8],
+        event_type: &[u8],
+        state_key: &[u8],
+        stripped: bool,
+        event_id: Option<&[u8]>,
+        data: &[u8],
+    ) -> Result<()> {
+        let stmt = self
+            .prepare_cached(
+                "INSERT INTO state_event (room_id, event_type, state_key, stripped, event_id, data)
+                 VALUES ($1, $2, $3, $4, $5, $6)
+                 ON CONFLICT (room_id, event_type, state_key)
+                 DO UPDATE SET stripped = $4, event_id = $5, data = $6",
+            )
+            .await?;
+        self.execute(&stmt, &[&room_id, &event_type, &state_key, &stripped, &event_id, &data])
+            .await?;
+        Ok(())
+    }
+
+    async fn get_state_event_by_id(
+        &self,
+        room_id: &[u8],
+        event_id: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        let stmt = self
+            .prepare_cached("SELECT data FROM state_event WHERE room_id = $1 AND event_id = $2")
+            .await?;
+        Ok(self.query_opt(&stmt, &[&room_id, &event_id]).await?.map(|row| row.get(0)))
+    }
+
+    async fn get_maybe_stripped_state_events_for_keys(
+        &self,
+        room_id: &[u8],
+        event_type: &[u8],
+        state_keys: &[Vec<u8>],
+    ) -> Result<Vec<(bool, Vec<u8>)>> {
+        let stmt = self
+            .prepare_cached(
+                "SELECT stripped, data FROM state_event
+                 WHERE room_id = $1 AND event_type = $2 AND state_key = ANY($3)",
+            )
+            .await?;
+        Ok(self
+            .query(&stmt, &[&room_id, &event_type, &state_keys])
+            .await?
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect())
+    }
+
+    async fn get_maybe_stripped_state_events(
+        &self,
+        room_id: &[u8],
+        event_type: &[u8],
+    ) -> Result<Vec<(bool, Vec<u8>)>> {
+        let stmt = self
+            .prepare_cached(
+                "SELECT stripped, data FROM state_event WHERE room_id = $1 AND event_type = $2",
+            )
+            .await?;
+        Ok(self
+            .query(&stmt, &[&room_id, &event_type])
+            .await?
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect())
+    }
+
+    async fn get_all_maybe_stripped_state_events(
+        &self,
+        room_id: &[u8],
+    ) -> Result<Vec<(bool, Vec<u8>)>> {
+        let stmt = self
+            .prepare_cached("SELECT stripped, data FROM state_event WHERE room_id = $1")
+            .await?;
+        Ok(self
+            .query(&stmt, &[&room_id])
+            .await?
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect())
+    }
+
+    /// Remove state events for the given room.
+    ///
+    /// If `stripped` is `Some()`, only removes state events for the given
+    /// stripped state. Otherwise, state events are removed regardless of the
+    /// stripped state.
+    async fn remove_room_state_events(&self, room_id: &[u8], stripped: Option<bool>) -> Result<()> {
+        if let Some(stripped) = stripped {
+            let stmt = self
+                .prepare_cached("DELETE FROM state_event WHERE room_id = $1 AND stripped = $2")
+                .await?;
+            self.execute(&stmt, &[&room_id, &stripped]).await?;
+        } else {
+            let stmt = self.prepare_cached("DELETE FROM state_event WHERE room_id = $1").await?;
+            self.execute(&stmt, &[&room_id]).await?;
+        }
+        Ok(())
+    }
+
+    async fn set_member(
+        &self,
+        room_id: &[u8],
+        user_id: &[u8],
+        membership: &[u8],
+        stripped: bool,
+        data: &[u8],
+    ) -> Result<()> {
+        let stmt = self
+            .prepare_cached(
+                "INSERT INTO member (room_id, user_id, membership, stripped, data)
+                 VALUES ($1, $2, $3, $4, $5)
+                 ON CONFLICT (room_id, user_id)
+                 DO UPDATE SET membership = $3, stripped = $4, data = $5",
+            )
+            .await?;
+        self.execute(&stmt, &[&room_id, &user_id, &membership, &stripped, &data]).await?;
+        Ok(())
+    }
+
+    /// Get the user IDs of the members of the given room with the given
+    /// memberships, or of all the members if `memberships` is empty.
+    async fn get_user_ids(&self, room_id: &[u8], memberships: &[Vec<u8>]) -> Result<Vec<Vec<u8>>> {
+        let rows = if memberships.is_empty() {
+            let stmt = self.prepare_cached("SELECT data FROM member WHERE room_id = $1").await?;
+            self.query(&stmt, &[&room_id]).await?
+        } else {
+            let stmt = self
+                .prepare_cached(
+                    "SELECT data FROM member WHERE room_id = $1 AND membership = ANY($2)",
+                )
+                .await?;
+            self.query(&stmt, &[&room_id, &memberships]).await?
+        };
+
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    /// Remove members for the given room.
+    ///
+    /// If `stripped` is `Some()`, only removes members for the given stripped
+    /// state. Otherwise, members are removed regardless of the stripped state.
+    async fn remove_room_members(&self, room_id: &[u8], stripped: Option<bool>) -> Result<()> {
+        if let Some(stripped) = stripped {
+            let stmt = self
+                .prepare_cached("DELETE FROM member WHERE room_id = $1 AND stripped = $2")
+                .await?;
+            self.execute(&stmt, &[&room_id, &stripped]).await?;
+        } else {
+            let stmt = self.prepare_cached("DELETE FROM member WHERE room_id = $1").await?;
+            self.execute(&stmt, &[&room_id]).await?;
+        }
+        Ok(())
+    }
+
+    async fn set_profile(&self, room_id: &[u8], user_id: &[u8], data: &[u8]) -> Result<()> {
+        let stmt = self
+            .prepare_cached(
+                "INSERT INTO profile (room_id, user_id, data) VALUES ($1, $2, $3)
+                 ON CONFLICT (room_id, user_id) DO UPDATE SET data = $3",
+            )
+            .await?;
+        self.execute(&stmt, &[&room_id, &user_id, &data]).await?;
+        Ok(())
+    }
+
+    async fn get_profiles(
+        &self,
+        room_id: &[u8],
+        user_ids: &[Vec<u8>],
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let stmt = self
+            .prepare_cached(
+                "SELECT user_id, data FROM profile WHERE room_id = $1 AND user_id = ANY($2)",
+            )
+            .await?;
+        Ok(self
+            .query(&stmt, &[&room_id, &user_ids])
+            .await?
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect())
+    }
+
+    async fn remove_room_profiles(&self, room_id: &[u8]) -> Result<()> {
+        let stmt = self.prepare_cached("DELETE FROM profile WHERE room_id = $1").await?;
+        self.execute(&stmt, &[&room_id]).await?;
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn set_receipt(
+        &self,
+        room_id: &[u8],
+        user_id: &[u8],
+        receipt_type: &[u8],
+        thread: &[u8],
+        event_id: &[u8],
+        data: &[u8],
+    ) -> Result<()> {
+        let stmt = self
+            .prepare_cached(
+                "INSERT INTO receipt (room_id, user_id, receipt_type, thread, event_id, data)
+                 VALUES ($1, $2, $3, $4, $5, $6)
+                 ON CONFLICT (room_id, user_id, receipt_type, thread)
+                 DO UPDATE SET event_id = $5, data = $6",
+            )
+            .await?;
+        self.execute(&stmt, &[&room_id, &user_id, &receipt_type, &thread, &event_id, &data])
+            .await?;
+        Ok(())
+    }
+
+    async fn get_user_receipt(
+        &self,
+        room_id: &[u8],
+        receipt_type: &[u8],
+        thread: &[u8],
+        user_id: &[u8],
+    ) -> Result<Option<Vec<u8>>> {
+        let stmt = self
+            .prepare_cached(
+                "SELECT data FROM receipt
+                 WHERE room_id = $1 AND receipt_type = $2 AND thread = $3 AND user_id = $4",
+            )
+            .await?;
+        Ok(self
+            .query_opt(&stmt, &[&room_id, &receipt_type, &thread, &user_id])
+            .await?
+            .map(|row| row.get(0)))
+    }
+
+    async fn get_event_receipts(
+        &self,
+        room_id: &[u8],
+        receipt_type: &[u8],
+        thread: &[u8],
+        event_id: &[u8],
+    ) -> Result<Vec<Vec<u8>>> {
+        let stmt = self
+            .prepare_cached(
+                "SELECT data FROM receipt
+                 WHERE room_id = $1 AND receipt_type = $2 AND thread = $3 AND event_id = $4",
+            )
+            .await?;
+        Ok(self
+            .query(&stmt, &[&room_id, &receipt_type, &thread, &event_id])
+            .await?
+            .iter()
+            .map(|row| row.get(0))
+            .collect())
+    }
+
+    async fn remove_room_receipts(&self, room_id: &[u8]) -> Result<()> {
+        let stmt = self.prepare_cached("DELETE FROM receipt WHERE room_id = $1").await?;
+        self.execute(&stmt, &[&room_id]).await?;
+        Ok(())
+    }
+
+    async fn get_room_receipts(&self, room_id: &[u8]) -> Result<Vec<RoomReceiptRow>> {
+        let stmt = self
+            .prepare_cached(
+                "SELECT user_id, receipt_type, thread, data FROM receipt WHERE room_id = $1",
+            )
+            .await?;
+        Ok(self
+            .query(&stmt, &[&room_id])
+            .await?
+            .iter()
+            .map(|row| RoomReceiptRow {
+                user_id: row.get(0),
+                receipt_type: row.get(1),
+                thread: row.get(2),
+                data: row.get(3),
+            })
+            .collect())
+    }
+
+    async fn remove_receipt(
+        &self,
+        room_id: &[u8],
+        user_id: &[u8],
+        receipt_type: &[u8],
+        thread: &[u8],
+    ) -> Result<()> {
+        let stmt = self
+            .prepare_cached(
+                "DELETE FROM receipt
+                 WHERE room_id = $1 AND user_id = $2 AND receipt_type = $3 AND thread = $4",
+            )
+            .await?;
+        self.execute(&stmt, &[&room_id, &user_id, &receipt_type, &thread]).await?;
+        Ok(())
+    }
+
+    async fn set_display_name(&self, room_id: &[u8], name: &[u8], data: &[u8]) -> Result<()> {
+        let stmt = self
+            .prepare_cached(
+                "INSERT INTO display_name (room_id, name, data) VALUES ($1, $2, $3)
+                 ON CONFLICT (room_id, name) DO UPDATE SET data = $3",
+            )
+            .await?;
+        self.execute(&stmt, &[&room_id, &name, &data]).await?;
+        Ok(())
+    }
+
+    async fn get_display_names(
+        &self,
+        room_id: &[u8],
+        names: &[Vec<u8>],
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let stmt = self
+            .prepare_cached(
+                "SELECT name, data FROM display_name WHERE room_id = $1 AND name = ANY($2)",
+            )
+            .await?;
+        Ok(self
+            .query(&stmt, &[&room_id, &names])
+            .await?
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect())
+    }
+
+    async fn remove_display_name(&self, room_id: &[u8], name: &[u8]) -> Result<()> {
+        let stmt = self
+            .prepare_cached("DELETE FROM display_name WHERE room_id = $1 AND name = $2")
+            .await?;
+        self.execute(&stmt, &[&room_id, &name]).await?;
+        Ok(())
+    }
+
+    async fn remove_room_display_names(&self, room_id: &[u8]) -> Result<()> {
+        let stmt = self.prepare_cached("DELETE FROM display_name WHERE room_id = $1").await?;
+        self.execute(&stmt, &[&room_id]).await?;
+        Ok(())
+    }
+
+    async fn set_media(&self, uri: &[u8], format: &[u8], data: &[u8]) -> Result<()> {
+        let stmt = self
+            .prepare_cached(
+                "INSERT INTO media (uri, format, data) VALUES ($1, $2, $3)
+                 ON CONFLICT (uri, format) DO UPDATE SET data = $3",
+            )
+            .await?;
+        self.execute(&stmt, &[&uri, &format, &data]).await?;
+        Ok(())
+    }
+
+    async fn get_media(&self, uri: &[u8], format: &[u8]) -> Result<Option<Vec<u8>>> {
+        let stmt =
+            self.prepare_cached("SELECT data FROM media WHERE uri = $1 AND format = $2").await?;
+        Ok(self.query_opt(&stmt, &[&uri, &format]).await?.map(|row| row.get(0)))
+    }
+
+    async fn remove_media(&self, uri: &[u8], format: &[u8]) -> Result<()> {
+        let stmt = self.prepare_cached("DELETE FROM media WHERE uri = $1 AND format = $2").await?;
+        self.execute(&stmt, &[&uri, &format]).await?;
+        Ok(())
+    }
+
+    async fn remove_uri_medias(&self, uri: &[u8]) -> Result<()> {
+        let stmt = self.prepare_cached("DELETE FROM media WHERE uri = $1").await?;
+        self.execute(&stmt, &[&uri]).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<T: GenericClient> PostgresClientStateStoreExt for T {}
+
+#[async_trait]
+impl StateStore for PostgresStateStore {
+    type Error = Error;
+
+    async fn get_kv_data(&self, key: StateStoreDataKey<'_>) -> Result<Option<StateStoreDataValue>> {
+        self.acquire()
+            .await?
+            .get_kv_blob(&self.encode_state_store_data_key(key))
+            .await?
+            .map(|data| {
+                let string = self.deserialize_value(&data)?;
+                Ok(match key {
+                    StateStoreDataKey::SyncToken => StateStoreDataValue::SyncToken(string),
+                    StateStoreDataKey::Filter(_) => StateStoreDataValue::Filter(string),
+                    StateStoreDataKey::UserAvatarUrl(_) => {
+                        StateStoreDataValue::UserAvatarUrl(string)
+                    }
+                })
+            })
+            .transpose()
+    }
+
+    async fn set_kv_data(
+        &self,
+        key: StateStoreDataKey<'_>,
+        value: StateStoreDataValue,
+    ) -> Result<()> {
+        let value = match key {
+            StateStoreDataKey::SyncToken => {
+                value.into_sync_token().expect("Session data not a sync token")
+            }
+            StateStoreDataKey::Filter(_) => value.into_filter().expect("Session data not a filter"),
+            StateStoreDataKey::UserAvatarUrl(_) => {
+                value.into_user_avatar_url().expect("Session data not an user avatar url")
+            }
+        };
+
+        self.acquire()
+            .await?
+            .set_kv_blob(&self.encode_state_store_data_key(key), &self.serialize_value(&value)?)
+            .await
+    }
+
+    async fn remove_kv_data(&self, key: StateStoreDataKey<'_>) -> Result<()> {
+        self.acquire().await?.delete_kv_blob(&self.encode_state_store_data_key(key)).await
+    }
+
+    async fn save_changes(&self, changes: &StateChanges) -> Result<()> {
+        let StateChanges {
+            sync_token,
+            account_data,
+            presence,
+            profiles,
+            state,
+            room_account_data,
+            room_infos,
+            receipts,
+            redactions,
+            stripped_state,
+            ambiguity_maps,
+            custom_values,
+        } = changes;
+
+        let mut conn = self.acquire().await?;
+        let txn = conn.transaction().await?;
+
+        if let Some(sync_token) = sync_token {
+            let key = self.encode_state_store_data_key(StateStoreDataKey::SyncToken);
+            let value = self.serialize_value(sync_token)?;
+            txn.set_kv_blob(&key, &value).await?;
+        }
+
+        for (key, value) in custom_values {
+            txn.set_kv_blob(&self.encode_custom_key(key), value).await?;
+        }
+
+        for (event_type, event) in account_data {
+            let event_type = self.encode_key(keys::GLOBAL_ACCOUNT_DATA, event_type.to_string());
+            let data = self.serialize_json(event)?;
+            txn.set_global_account_data(&event_type, &data).await?;
+        }
+
+        for (room_id, events) in room_account_data {
+            let room_id = self.encode_key(keys::ROOM_ACCOUNT_DATA, room_id);
+            for (event_type, event) in events {
+                let event_type = self.encode_key(keys::ROOM_ACCOUNT_DATA, event_type.to_string());
+                let data = self.serialize_json(event)?;
+                txn.set_room_account_data(&room_id, &event_type, &data).await?;
+            }
+        }
+
+        for (user_id, event) in presence {
+            let key = self.encode_presence_key(user_id);
+            let value = self.serialize_json(event)?;
+            txn.set_kv_blob(&key, &value).await?;
+        }
+
+        for (room_id, room_info) in room_infos {
+            let stripped = room_info.state() == RoomState::Invited;
+            // Remove non-stripped data for stripped rooms and vice-versa.
+            self.remove_maybe_stripped_room_data(&txn, room_id, !stripped).await?;
+
+            let room_id = self.encode_key(keys::ROOM_INFO, room_id);
+            let state =
+                self.encode_key(keys::ROOM_INFO, serde_json::to_string(&room_info.state())?);
+            let data = self.serialize_json(room_info)?;
+            txn.set_room_info(&room_id, &state, &data).await?;
+        }
+
+        for (room_id, state_event_types) in state {
+            let profiles = profiles.get(room_id);
+            let encoded_room_id = self.encode_key(keys::STATE_EVENT, room_id);
+
+            for (event_type, state_events) in state_event_types {
+                let encoded_event_type = self.encode_key(keys::STATE_EVENT, event_type.to_string());
+
+                for (state_key, raw_state_event) in state_events {
+                    let encoded_state_key = self.encode_key(keys::STATE_EVENT, state_key);
+                    let data = self.serialize_json(raw_state_event)?;
+
+                    let event_id: Option<String> =
+                        raw_state_event.get_field("event_id").ok().flatten();
+                    let encoded_event_id =
+                        event_id.as_ref().map(|e| self.encode_key(keys::STATE_EVENT, e));
+
+                    txn.set_state_event(
+                        &encoded_room_id,
+                        &encoded_event_type,
+                        &encoded_state_key,
+                        false,
+                        encoded_event_id.as_deref(),
+                        &data,
+                    )
+                    .await?;
+
+                    if *event_type == StateEventType::RoomMember {
+                        let member_event =
+                            match raw_state_event.deserialize_as::<SyncRoomMemberEvent>() {
+                                Ok(ev) => ev,
+                                Err(e) => {
+                                    debug!(event_id, "Failed to deserialize member event: {e}");
+                                    continue;
+                                }
+                            };
+
+                        let encoded_room_id = self.encode_key(keys::MEMBER, room_id);
+                        let user_id = self.encode_key(keys::MEMBER, state_key);
+                        let membership =
+                            self.encode_key(keys::MEMBER, member_event.membership().as_str());
+                        let data = self.serialize_value(state_key)?;
+
+                        txn.set_member(&encoded_room_id, &user_id, &membership, false, &data)
+                            .await?;
+
+                        if let Some(profile) =
+                            profiles.and_then(|p| p.get(member_event.state_key()))
+                        {
+                            let room_id = self.encode_key(keys::PROFILE, room_id);
+                            let user_id = self.encode_key(keys::PROFILE, state_key);
+                            let data = self.serialize_json(profile)?;
+                            txn.set_profile(&room_id, &user_id, &data).await?;
+                        }
+                    }
+                }
+            }
+        }
+
+        for (room_id, stripped_state_event_types) in stripped_state {
+            let encoded_room_id = self.encode_key(keys::STATE_EVENT, room_id);
+
+            for (event_type, stripped_state_events) in stripped_state_event_types {
+                let encoded_event_type = self.encode_key(keys::STATE_EVENT, event_type.to_string());
+
+                for (state_key, raw_stripped_state_event) in stripped_state_events {
+                    let encoded_state_key = self.encode_key(keys::STATE_EVENT, state_key);
+                    let data = self.serialize_json(raw_stripped_state_event)?;
+                    txn.set_state_event(
+                        &encoded_room_id,
+                        &encoded_event_type,
+                        &encoded_state_key,
+                        true,
+                        None,
+                        &data,
+                    )
+                    .await?;
+
+                    if *event_type == StateEventType::RoomMember {
+                        let member_event = match raw_stripped_state_event
+                            .deserialize_as::<StrippedRoomMemberEvent>()
+                        {
+                            Ok(ev) => ev,
+                            Err(e) => {
+                                debug!("Failed to deserialize stripped member event: {e}");
+                                continue;
+                            }
+                        };
+
+                        let room_id = self.encode_key(keys::MEMBER, room_id);
+                        let user_id = self.encode_key(keys::MEMBER, state_key);
+                        let membership =
+                            self.encode_key(keys::MEMBER, member_event.content.membership.as_str());
+                        let data = self.serialize_value(state_key)?;
+
+                        txn.set_member(&room_id, &user_id, &membership, true, &data).await?;
+                    }
+                }
+            }
+        }
+
+        for (room_id, receipt_event) in receipts {
+            let room_id = self.encode_key(keys::RECEIPT, room_id);
+
+            for (event_id, receipt_types) in receipt_event.iter() {
+                let encoded_event_id = self.encode_key(keys::RECEIPT, event_id);
+
+                for (receipt_type, receipt_users) in receipt_types {
+                    let receipt_type = self.encode_key(keys::RECEIPT, receipt_type.as_str());
+
+                    for (user_id, receipt) in receipt_users {
+                        let encoded_user_id = self.encode_key(keys::RECEIPT, user_id);
+                        // We cannot have a NULL primary key so we rely on serialization
+                        // instead of the string representation.
+                        let thread = self
+                            .encode_key(keys::RECEIPT, rmp_serde::to_vec_named(&receipt.thread)?);
+                        let data = self.serialize_json(&ReceiptData {
+                            receipt: receipt.clone(),
+                            event_id: event_id.clone(),
+                            user_id: user_id.clone(),
+                        })?;
+
+                        txn.set_receipt(
+                            &room_id,
+                            &encoded_user_id,
+                            &receipt_type,
+                            &thread,
+                            &encoded_event_id,
+                            &data,
+                        )
+                        .await?;
+                    }
+                }
+            }
+        }
+
+        for (room_id, redactions) in redactions {
+            let encoded_room_id = self.encode_key(keys::STATE_EVENT, room_id);
+            let mut room_version = None;
+
+            for (event_id, redaction) in redactions {
+                let event_id = self.encode_key(keys::STATE_EVENT, event_id);
+
+                if let Some(Ok(raw_event)) = txn
+                    .get_state_event_by_id(&encoded_room_id, &event_id)
+                    .await?
+                    .map(|value| self.deserialize_json::<Raw<AnySyncStateEvent>>(&value))
+                {
+                    if room_version.is_none() {
+                        room_version = Some(self.get_room_version(&txn, room_id).await);
+                    }
+
+                    let event = raw_event.deserialize()?;
+                    let redacted = redact(
+                        raw_event.deserialize_as::<CanonicalJsonObject>()?,
+                        room_version.as_ref().expect("room version was just set"),
+                        Some(RedactedBecause::from_raw_event(redaction)?),
+                    )
+                    .map_err(Error::Redaction)?;
+                    let data = self.serialize_json(&redacted)?;
+
+                    let event_type =
+                        self.encode_key(keys::STATE_EVENT, event.event_type().to_string());
+                    let state_key = self.encode_key(keys::STATE_EVENT, event.state_key());
+
+                    txn.set_state_event(
+                        &encoded_room_id,
+                        &event_type,
+                        &state_key,
+                        false,
+                        Some(event_id.as_slice()),
+                        &data,
+                    )
+                    .await?;
+                }
+            }
+        }
+
+        for (room_id, display_names) in ambiguity_maps {
+            let room_id = self.encode_key(keys::DISPLAY_NAME, room_id);
+
+            for (name, user_ids) in display_names {
+                let name = self.encode_key(keys::DISPLAY_NAME, name);
+                let data = self.serialize_json(user_ids)?;
+
+                if user_ids.is_empty() {
+                    txn.remove_display_name(&room_id, &name).await?;
+                } else {
+                    txn.set_display_name(&room_id, &name, &data).await?;
+                }
+            }
+        }
+
+        txn.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_presence_event(&self, user_id: &UserId) -> Result<Option<Raw<PresenceEvent>>> {
+        self.acquire()
+            .await?
+            .get_kv_blob(&self.encode_presence_key(user_id))
+            .await?
+            .map(|data| self.deserialize_json(&data))
+            .transpose()
+    }
+
+    async fn get_presence_events(
+        &self,
+        user_ids: &[OwnedUserId],
+    ) -> Result<Vec<Raw<PresenceEvent>>> {
+        if user_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let user_ids: Vec<_> = user_ids.iter().map(|u| self.encode_presence_key(u)).collect();
+        self.acquire()
+            .await?
+            .get_kv_blobs(&user_ids)
+            .await?
+            .into_iter()
+            .map(|data| self.deserialize_json(&data))
+            .collect()
+    }
+
+    async fn get_state_event(
+        &self,
+        room_id: &RoomId,
+        event_type: StateEventType,
+        state_key: &str,
+    ) -> Result<Option<RawAnySyncOrStrippedState>> {
+        Ok(self
+            .get_state_events_for_keys(room_id, event_type, &[state_key])
+            .await?
+            .into_iter()
+            .next())
+    }
+
+    async fn get_state_events(
+        &self,
+        room_id: &RoomId,
+        event_type: StateEventType,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>> {
+        let room_id = self.encode_key(keys::STATE_EVENT, room_id);
+        let event_type = self.encode_key(keys::STATE_EVENT, event_type.to_string());
+        self.acquire()
+            .await?
+            .get_maybe_stripped_state_events(&room_id, &event_type)
+            .await?
+            .into_iter()
+            .map(|(stripped, data)| self.deserialize_maybe_stripped_state_event(stripped, &data))
+            .collect()
+    }
+
+    async fn get_state_events_for_keys(
+        &self,
+        room_id: &RoomId,
+        event_type: StateEventType,
+        state_keys: &[&str],
+    ) -> Result<Vec<RawAnySyncOrStrippedState>, Self::Error> {
+        if state_keys.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let room_id = self.encode_key(keys::STATE_EVENT, room_id);
+        let event_type = self.encode_key(keys::STATE_EVENT, event_type.to_string());
+        let state_keys: Vec<_> =
+            state_keys.iter().map(|k| self.encode_key(keys::STATE_EVENT, k)).collect();
+        self.acquire()
+            .await?
+            .get_maybe_stripped_state_events_for_keys(&room_id, &event_type, &state_keys)
+            .await?
+            .into_iter()
+            .map(|(stripped, data)| self.deserialize_maybe_stripped_state_event(stripped, &data))
+            .collect()
+    }
+
+    async fn get_all_state_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>, Self::Error> {
+        let room_id = self.encode_key(keys::STATE_EVENT, room_id);
+        let mut events =
+            self.acquire().await?.get_all_maybe_stripped_state_events(&room_id).await?;
+
+        // Only return the stripped state if the room has some.
+        if events.iter().any(|(stripped, _)| *stripped) {
+            events.retain(|(stripped, _)| *stripped);
+        }
+
+        events
+            .into_iter()
+            .map(|(stripped, data)| self.deserialize_maybe_stripped_state_event(stripped, &data))
+            .collect()
+    }
+
+    async fn get_profile(
+        &self,
+        room_id: &RoomId,
+        user_id: &UserId,
+    ) -> Result<Option<MinimalRoomMemberEvent>> {
+        let room_id = self.encode_key(keys::PROFILE, room_id);
+        let user_ids = [self.encode_key(keys::PROFILE, user_id)];
+
+        self.acquire()
+            .await?
+            .get_profiles(&room_id, &user_ids)
+            .await?
+            .into_iter()
+            .next()
+            .map(|(_, data)| self.deserialize_json(&data))
+            .transpose()
+    }
+
+    async fn get_profiles<'a>(
+        &self,
+        room_id: &RoomId,
+        user_ids: &'a [OwnedUserId],
+    ) -> Result<BTreeMap<&'a UserId, MinimalRoomMemberEvent>> {
+        if user_ids.is_empty() {
+            return Ok(BTreeMap::new());
+        }
+
+        let room_id = self.encode_key(keys::PROFILE, room_id);
+        let mut user_ids_map = user_ids
+            .iter()
+            .map(|u| (self.encode_key(keys::PROFILE, u), u.as_ref()))
+            .collect::<BTreeMap<_, _>>();
+        let user_ids: Vec<_> = user_ids_map.keys().cloned().collect();
+
+        self.acquire()
+            .await?
+            .get_profiles(&room_id, &user_ids)
+            .await?
+            .into_iter()
+            .map(|(user_id, data)| {
+                Ok((
+                    user_ids_map
+                        .remove(user_id.as_slice())
+                        .expect("returned user IDs were requested"),
+                    self.deserialize_json(&data)?,
+                ))
+            })
+            .collect()
+    }
+
+    async fn get_user_ids(
+        &self,
+        room_id: &RoomId,
+        membership: RoomMemberships,
+    ) -> Result<Vec<OwnedUserId>> {
+        let room_id = self.encode_key(keys::MEMBER, room_id);
+        let memberships: Vec<_> = membership
+            .as_vec()
+            .into_iter()
+            .map(|m| self.encode_key(keys::MEMBER, m.as_str()))
+            .collect();
+        self.acquire()
+            .await?
+            .get_user_ids(&room_id, &memberships)
+            .await?
+            .iter()
+            .map(|data| self.deserialize_value(data))
+            .collect()
+    }
+
+    async fn get_invited_user_ids(&self, room_id: &RoomId) -> Result<Vec<OwnedUserId>> {
+        self.get_user_ids(room_id, RoomMemberships::INVITE).await
+    }
+
+    async fn get_joined_user_ids(&self, room_id: &RoomId) -> Result<Vec<OwnedUserId>> {
+        self.get_user_ids(room_id, RoomMemberships::JOIN).await
+    }
+
+    async fn get_room_infos(&self) -> Result<Vec<RoomInfo>> {
+        self.acquire()
+            .await?
+            .get_room_infos(&[])
+            .await?
+            .into_iter()
+            .map(|data| self.deserialize_json(&data))
+            .collect()
+    }
+
+    async fn get_stripped_room_infos(&self) -> Result<Vec<RoomInfo>> {
+        let states =
+            [self.encode_key(keys::ROOM_INFO, serde_json::to_string(&RoomState::Invited)?)];
+        self.acquire()
+            .await?
+            .get_room_infos(&states)
+            .await?
+            .into_iter()
+            .map(|data| self.deserialize_json(&data))
+            .collect()
+    }
+
+    async fn get_users_with_display_name(
+        &self,
+        room_id: &RoomId,
+        display_name: &str,
+    ) -> Result<BTreeSet<OwnedUserId>> {
+        let room_id = self.encode_key(keys::DISPLAY_NAME, room_id);
+        let names = [self.encode_key(keys::DISPLAY_NAME, display_name)];
+
+        Ok(self
+            .acquire()
+            .await?
+            .get_display_names(&room_id, &names)
+            .await?
+            .into_iter()
+            .next()
+            .map(|(_, data)| self.deserialize_json(&data))
+            .transpose()?
+            .unwrap_or_default())
+    }
+
+    async fn get_users_with_display_names<'a>(
+        &self,
+        room_id: &RoomId,
+        display_names: &'a [String],
+    ) -> Result<BTreeMap<&'a str, BTreeSet<OwnedUserId>>> {
+        if display_names.is_empty() {
+            return Ok(BTreeMap::new());
+        }
+
+        let room_id = self.encode_key(keys::DISPLAY_NAME, room_id);
+        let mut names_map = display_names
+            .iter()
+            .map(|n| (self.encode_key(keys::DISPLAY_NAME, n), n.as_ref()))
+            .collect::<BTreeMap<_, _>>();
+        let names: Vec<_> = names_map.keys().cloned().collect();
+
+        self.acquire()
+            .await?
+            .get_display_names(&room_id, &names)
+            .await?
+            .into_iter()
+            .map(|(name, data)| {
+                Ok((
+                    names_map
+                        .remove(name.as_slice())
+                        .expect("returned display names were requested"),
+                    self.deserialize_json(&data)?,
+                ))
+            })
+            .collect::<Result<BTreeMap<_, _>>>()
+    }
+
+    async fn get_account_data_event(
+        &self,
+        event_type: GlobalAccountDataEventType,
+    ) -> Result<Option<Raw<AnyGlobalAccountDataEvent>>> {
+        let event_type = self.encode_key(keys::GLOBAL_ACCOUNT_DATA, event_type.to_string());
+        self.acquire()
+            .await?
+            .get_global_account_data(&event_type)
+            .await?
+            .map(|value| self.deserialize_json(&value))
+            .transpose()
+    }
+
+    async fn get_room_account_data_event(
+        &self,
+        room_id: &RoomId,
+        event_type: RoomAccountDataEventType,
+    ) -> Result<Option<Raw<AnyRoomAccountDataEvent>>> {
+        let room_id = self.encode_key(keys::ROOM_ACCOUNT_DATA, room_id);
+        let event_type = self.encode_key(keys::ROOM_ACCOUNT_DATA, event_type.to_string());
+        self.acquire()
+            .await?
+            .get_room_account_data(&room_id, &event_type)
+            .await?
+            .map(|value| self.deserialize_json(&value))
+            .transpose()
+    }
+
+    async fn get_user_room_receipt_event(
+        &self,
+        room_id: &RoomId,
+        receipt_type: ReceiptType,
+        thread: ReceiptThread,
+        user_id: &UserId,
+    ) -> Result<Option<(OwnedEventId, Receipt)>> {
+        let room_id = self.encode_key(keys::RECEIPT, room_id);
+        let receipt_type = self.encode_key(keys::RECEIPT, receipt_type.to_string());
+        // We cannot have a NULL primary key so we rely on serialization instead of the
+        // string representation.
+        let thread = self.encode_key(keys::RECEIPT, rmp_serde::to_vec_named(&thread)?);
+        let user_id = self.encode_key(keys::RECEIPT, user_id);
+
+        self.acquire()
+            .await?
+            .get_user_receipt(&room_id, &receipt_type, &thread, &user_id)
+            .await?
+            .map(|value| {
+                self.deserialize_json::<ReceiptData>(&value).map(|d| (d.event_id, d.receipt))
+            })
+            .transpose()
+    }
+
+    async fn get_event_room_receipt_events(
+        &self,
+        room_id: &RoomId,
+        receipt_type: ReceiptType,
+        thread: ReceiptThread,
+        event_id: &EventId,
+    ) -> Result<Vec<(OwnedUserId, Receipt)>> {
+        let room_id = self.encode_key(keys::RECEIPT, room_id);
+        let receipt_type = self.encode_key(keys::RECEIPT, receipt_type.to_string());
+        // We cannot have a NULL primary key so we rely on serialization instead of the
+        // string representation.
+        let thread = self.encode_key(keys::RECEIPT, rmp_serde::to_vec_named(&thread)?);
+        let event_id = self.encode_key(keys::RECEIPT, event_id);
+
+        self.acquire()
+            .await?
+            .get_event_receipts(&room_id, &receipt_type, &thread, &event_id)
+            .await?
+            .iter()
+            .map(|value| {
+                self.deserialize_json::<ReceiptData>(value).map(|d| (d.user_id, d.receipt))
+            })
+            .collect()
+    }
+
+    async fn get_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.acquire().await?.get_kv_blob(&self.encode_custom_key(key)).await
+    }
+
+    async fn set_custom_value(&self, key: &[u8], value: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        let conn = self.acquire().await?;
+        let key = self.encode_custom_key(key);
+        let previous = conn.get_kv_blob(&key).await?;
+        conn.set_kv_blob(&key, &value).await?;
+        Ok(previous)
+    }
+
+    async fn remove_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let conn = self.acquire().await?;
+        let key = self.encode_custom_key(key);
+        let previous = conn.get_kv_blob(&key).await?;
+        if previous.is_some() {
+            conn.delete_kv_blob(&key).await?;
+        }
+        Ok(previous)
+    }
+
+    async fn add_media_content(&self, request: &MediaRequest, content: Vec<u8>) -> Result<()> {
+        let uri = self.encode_key(keys::MEDIA, request.source.unique_key());
+        let format = self.encode_key(keys::MEDIA, request.format.unique_key());
+        let data = self.encode_value(content)?;
+        self.acquire().await?.set_media(&uri, &format, &data).await
+    }
+
+    async fn get_media_content(&self, request: &MediaRequest) -> Result<Option<Vec<u8>>> {
+        let uri = self.encode_key(keys::MEDIA, request.source.unique_key());
+        let format = self.encode_key(keys::MEDIA, request.format.unique_key());
+        let data = self.acquire().await?.get_media(&uri, &format).await?;
+        data.map(|v| self.decode_value(&v).map(Into::into)).transpose()
+    }
+
+    async fn remove_media_content(&self, request: &MediaRequest) -> Result<()> {
+        let uri = self.encode_key(keys::MEDIA, request.source.unique_key());
+        let format = self.encode_key(keys::MEDIA, request.format.unique_key());
+        self.acquire().await?.remove_media(&uri, &format).await
+    }
+
+    async fn remove_media_content_for_uri(&self, uri: &ruma::MxcUri) -> Result<()> {
+        let uri = self.encode_key(keys::MEDIA, uri);
+        self.acquire().await?.remove_uri_medias(&uri).await
+    }
+
+    async fn remove_room(&self, room_id: &RoomId) -> Result<()> {
+        let mut conn = self.acquire().await?;
+        let txn = conn.transaction().await?;
+
+        let room_info_room_id = self.encode_key(keys::ROOM_INFO, room_id);
+        txn.remove_room_info(&room_info_room_id).await?;
+
+        let state_event_room_id = self.encode_key(keys::STATE_EVENT, room_id);
+        txn.remove_room_state_events(&state_event_room_id, None).await?;
+
+        let member_room_id = self.encode_key(keys::MEMBER, room_id);
+        txn.remove_room_members(&member_room_id, None).await?;
+
+        let profile_room_id = self.encode_key(keys::PROFILE, room_id);
+        txn.remove_room_profiles(&profile_room_id).await?;
+
+        let room_account_data_room_id = self.encode_key(keys::ROOM_ACCOUNT_DATA, room_id);
+        txn.remove_room_account_data(&room_account_data_room_id).await?;
+
+        let receipt_room_id = self.encode_key(keys::RECEIPT, room_id);
+        txn.remove_room_receipts(&receipt_room_id).await?;
+
+        let display_name_room_id = self.encode_key(keys::DISPLAY_NAME, room_id);
+        txn.remove_room_display_names(&display_name_room_id).await?;
+
+        txn.commit().await?;
+
+        Ok(())
+    }
+
+    async fn remove_room_receipts_before(
+        &self,
+        room_id: &RoomId,
+        before: MilliSecondsSinceUnixEpoch,
+    ) -> Result<()> {
+        let mut conn = self.acquire().await?;
+        let txn = conn.transaction().await?;
+
+        let room_id = self.encode_key(keys::RECEIPT, room_id);
+
+        for row in txn.get_room_receipts(&room_id).await? {
+            let receipt = self.deserialize_json::<ReceiptData>(&row.data)?.receipt;
+
+            if receipt.ts.is_some_and(|ts| ts < before) {
+                txn.remove_receipt(&room_id, &row.user_id, &row.receipt_type, &row.thread).await?;
+            }
+        }
+
+        txn.commit().await?;
+
+        Ok(())
+    }
+
+    async fn try_take_leased_lock(
+        &self,
+        lease_duration_ms: u32,
+        key: &str,
+        holder: &str,
+    ) -> Result<bool> {
+        let mut conn = self.acquire().await?;
+        utils::try_take_leased_lock(&mut conn, keys::LEASE_LOCKS, lease_duration_ms, key, holder)
+            .await
+    }
+}
+
+/// A row of the `receipt` table, with the keys needed to remove it.
+struct RoomReceiptRow {
+    user_id: Vec<u8>,
+    receipt_type: Vec<u8>,
+    thread: Vec<u8>,
+    data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ReceiptData {
+    receipt: Receipt,
+    event_id: OwnedEventId,
+    user_id: OwnedUserId,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering::SeqCst};
+
+    use matrix_sdk_base::{statestore_integration_tests, StateStore, StoreError};
+
+    use super::PostgresStateStore;
+    use crate::test_pool;
+
+    static NUM: AtomicU32 = AtomicU32::new(0);
+
+    async fn get_store() -> Result<impl StateStore, StoreError> {
+        let name = format!("state_{}", NUM.fetch_add(1, SeqCst));
+        let pool = test_pool(&name).await;
+
+        Ok(PostgresStateStore::open_with_pool(pool, None).await.unwrap())
+    }
+
+    statestore_integration_tests!(with_media_tests);
+}
+
+#[cfg(test)]
+mod encrypted_tests {
+    use std::sync::atomic::{AtomicU32, Ordering::SeqCst};
+
+    use matrix_sdk_base::{statestore_integration_tests, StateStore, StoreError};
+
+    use super::PostgresStateStore;
+    use crate::test_pool;
+
+    static NUM: AtomicU32 = AtomicU32::new(0);
+
+    async fn get_store() -> Result<impl StateStore, StoreError> {
+        let name = format!("encrypted_state_{}", NUM.fetch_add(1, SeqCst));
+        let pool = test_pool(&name).await;
+
+        Ok(PostgresStateStore::open_with_pool(pool, Some("default_test_password")).await.unwrap())
+    }
+
+    statestore_integration_tests!(with_media_tests);
+}
+
+#[cfg(test)]
+mod migration_tests {
+    use matrix_sdk_test::async_test;
+
+    use super::{PostgresStateStore, MIGRATIONS};
+    use crate::{test_pool, utils, OpenStoreError};
+
+    #[async_test]
+    async fn test_concurrent_open() {
+        let pool = test_pool("state_concurrent_open").await;
+
+        // Both stores run the migrations, one waits for the other.
+        let (first, second) = tokio::join!(
+            PostgresStateStore::open_with_pool(pool.clone(), Some("secret")),
+            PostgresStateStore::open_with_pool(pool.clone(), Some("secret")),
+        );
+        first.unwrap();
+        second.unwrap();
+
+        let conn = pool.get().await.unwrap();
+        let version = utils::get_kv(&*conn, MIGRATIONS.kv_table, "version").await.unwrap();
+        assert_eq!(version, Some(vec![MIGRATIONS.scripts.len() as u8]));
+    }
+
+    #[async_test]
+    async fn test_open_newer_version() {
+        let pool = test_pool("state_newer_version").await;
+        PostgresStateStore::open_with_pool(pool.clone(), None).await.unwrap();
+
+        // Pretend that a newer version of the SDK upgraded the database.
+        let latest_version = MIGRATIONS.scripts.len() as u8;
+        let conn = pool.get().await.unwrap();
+        utils::set_kv(&*conn, MIGRATIONS.kv_table, "version", &[latest_version + 1]).await.unwrap();
+        drop(conn);
+
+        let result = PostgresStateStore::open_with_pool(pool, None).await;
+        let Err(OpenStoreError::UnsupportedVersion { version, latest_version: latest }) = result
+        else {
+            panic!("opening a newer database should fail");
+        };
+        assert_eq!(version, latest_version + 1);
+        assert_eq!(latest, latest_version);
+    }
+}
@@ -0,0 +1,203 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use deadpool_postgres::{GenericClient, Object as PostgresConn};
+use ruma::MilliSecondsSinceUnixEpoch;
+use tracing::debug;
+
+use crate::{
+    error::{Error, Result},
+    OpenStoreError,
+};
+
+/// The migrations of the tables of a store.
+pub(crate) struct Migrations {
+    /// The key of the advisory lock held while the migrations run.
+    pub lock_id: i64,
+    /// The table with the version of the database.
+    pub kv_table: &'static str,
+    /// The SQL scripts to upgrade the database, the version of the database
+    /// is the number of scripts that were run.
+    pub scripts: &'static [&'static str],
+}
+
+impl Migrations {
+    /// The version of the database once all the migrations ran.
+    fn latest_version(&self) -> u8 {
+        self.scripts.len().try_into().expect("too many migrations")
+    }
+
+    /// Run the migrations that were not run yet on the database of the given
+    /// connection.
+    ///
+    /// This can be called by several processes at the same time, the
+    /// migrations run in a transaction holding an advisory lock so the other
+    /// processes wait for them, and then find an up-to-date database.
+    pub(crate) async fn run(&self, conn: &mut PostgresConn) -> Result<(), OpenStoreError> {
+        let txn = conn.transaction().await.map_err(OpenStoreError::LoadVersion)?;
+
+        // The lock is released when the transaction ends.
+        txn.execute("SELECT pg_advisory_xact_lock($1)", &[&self.lock_id])
+            .await
+            .map_err(OpenStoreError::LoadVersion)?;
+
+        let version = load_db_version(&txn, self.kv_table).await?;
+        let latest_version = self.latest_version();
+
+        if version == 0 {
+            debug!("Creating database");
+        } else if version < latest_version {
+            debug!(version, new_version = latest_version, "Upgrading database");
+        } else if version > latest_version {
+            return Err(OpenStoreError::UnsupportedVersion { version, latest_version });
+        } else {
+            return Ok(());
+        }
+
+        for script in &self.scripts[usize::from(version)..] {
+            txn.batch_execute(script).await.map_err(Error::from)?;
+        }
+
+        set_kv(&txn, self.kv_table, "version", &[latest_version]).await.map_err(Error::from)?;
+        txn.commit().await.map_err(Error::from)?;
+
+        Ok(())
+    }
+}
+
+pub(crate) async fn get_kv(
+    client: &impl GenericClient,
+    table: &str,
+    key: &str,
+) -> Result<Option<Vec<u8>>, tokio_postgres::Error> {
+    let stmt = client.prepare_cached(&format!("SELECT value FROM {table} WHERE key = $1")).await?;
+    Ok(client.query_opt(&stmt, &[&key]).await?.map(|row| row.get(0)))
+}
+
+pub(crate) async fn set_kv(
+    client: &impl GenericClient,
+    table: &str,
+    key: &str,
+    value: &[u8],
+) -> Result<(), tokio_postgres::Error> {
+    let stmt = client
+        .prepare_cached(&format!(
+            "INSERT INTO {table} (key, value) VALUES ($1, $2)
+             ON CONFLICT (key) DO UPDATE SET value = $2"
+        ))
+        .await?;
+    client.execute(&stmt, &[&key, &value]).await?;
+    Ok(())
+}
+
+/// Insert the value for the given key if there is none.
+///
+/// Returns the value in the table for the key, which is the given value if it
+/// was inserted.
+pub(crate) async fn get_or_insert_kv(
+    client: &impl GenericClient,
+    table: &str,
+    key: &str,
+    value: &[u8],
+) -> Result<Vec<u8>, tokio_postgres::Error> {
+    // Updating the row to the same value is needed to return it.
+    let stmt = client
+        .prepare_cached(&format!(
+            "INSERT INTO {table} (key, value) VALUES ($1, $2)
+             ON CONFLICT (key) DO UPDATE SET value = {table}.value
+             RETURNING value"
+        ))
+        .await?;
+    Ok(client.query_one(&stmt, &[&key, &value]).await?.get(0))
+}
+
+pub(crate) async fn delete_kv(
+    client: &impl GenericClient,
+    table: &str,
+    keys: &[&str],
+) -> Result<(), tokio_postgres::Error> {
+    let stmt = client.prepare_cached(&format!("DELETE FROM {table} WHERE key = ANY($1)")).await?;
+    client.execute(&stmt, &[&keys]).await?;
+    Ok(())
+}
+
+/// Load the version of the database with the given client.
+async fn load_db_version(
+    client: &impl GenericClient,
+    kv_table: &str,
+) -> Result<u8, OpenStoreError> {
+    let kv_exists: bool = client
+        .query_one(
+            "SELECT EXISTS (
+                SELECT 1 FROM pg_tables WHERE schemaname = current_schema() AND tablename = $1
+             )",
+            &[&kv_table],
+        )
+        .await
+        .map_err(OpenStoreError::LoadVersion)?
+        .get(0);
+
+    if kv_exists {
+        match get_kv(client, kv_table, "version")
+            .await
+            .map_err(OpenStoreError::LoadVersion)?
+            .as_deref()
+        {
+            Some([v]) => Ok(*v),
+            Some(_) => Err(OpenStoreError::InvalidVersion),
+            None => Err(OpenStoreError::MissingVersion),
+        }
+    } else {
+        Ok(0)
+    }
+}
+
+/// Try to take the lease lock with the given key in the given table, for the
+/// given holder.
+///
+/// Processes trying to take the same lock at the same time are serialized with
+/// an advisory lock.
+pub(crate) async fn try_take_leased_lock(
+    conn: &mut PostgresConn,
+    table: &str,
+    lease_duration_ms: u32,
+    key: &str,
+    holder: &str,
+) -> Result<bool> {
+    let now_ts: u64 = MilliSecondsSinceUnixEpoch::now().get().into();
+    let expiration_ts = now_ts + u64::from(lease_duration_ms);
+    let (now_ts, expiration_ts) = (now_ts as i64, expiration_ts as i64);
+
+    let txn = conn.transaction().await?;
+
+    // The lock is released when the transaction ends.
+    txn.execute("SELECT pg_advisory_xact_lock(hashtextextended($1, 0))", &[&key]).await?;
+
+    let stmt = txn
+        .prepare_cached(&format!(
+            "INSERT INTO {table} (key, holder, expiration_ts)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (key)
+             DO
+                 UPDATE SET holder = $2, expiration_ts = $3
+                 WHERE {table}.holder = $2
+                 OR {table}.expiration_ts < $4"
+        ))
+        .await?;
+    let num_touched = txn.execute(&stmt, &[&key, &holder, &expiration_ts, &now_ts]).await?;
+
+    txn.commit().await?;
+
+    Ok(num_touched == 1)
+}
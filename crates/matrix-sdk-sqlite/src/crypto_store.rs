@@ -709,42 +709,31 @@ impl CryptoStore for SqliteCryptoStore {
     }
 
     async fn save_pending_changes(&self, changes: PendingChanges) -> Result<()> {
-        // Serialize calls to `save_pending_changes`; there are multiple await points
+        self.save_changes_batch(changes, Changes::default()).await
+    }
+
+    async fn save_changes(&self, changes: Changes) -> Result<()> {
+        self.save_changes_batch(PendingChanges::default(), changes).await
+    }
+
+    async fn save_changes_batch(
+        &self,
+        pending_changes: PendingChanges,
+        changes: Changes,
+    ) -> Result<()> {
+        // Serialize calls to `save_changes_batch`; there are multiple await points
         // below, and we're pickling data as we go, so we don't want to
         // invalidate data we've previously read and overwrite it in the store.
         // TODO: #2000 should make this lock go away, or change its shape.
         let _guard = self.save_changes_lock.lock().await;
 
-        let pickled_account = if let Some(account) = changes.account {
+        let pickled_account = if let Some(account) = pending_changes.account {
             *self.static_account.write().unwrap() = Some(account.static_data().clone());
             Some(account.pickle())
         } else {
             None
         };
 
-        let this = self.clone();
-        self.acquire()
-            .await?
-            .with_transaction(move |txn| {
-                if let Some(pickled_account) = pickled_account {
-                    let serialized_account = this.serialize_value(&pickled_account)?;
-                    txn.set_kv("account", &serialized_account)?;
-                }
-
-                Ok::<_, Error>(())
-            })
-            .await?;
-
-        Ok(())
-    }
-
-    async fn save_changes(&self, changes: Changes) -> Result<()> {
-        // Serialize calls to `save_changes`; there are multiple await points below, and
-        // we're pickling data as we go, so we don't want to invalidate data
-        // we've previously read and overwrite it in the store.
-        // TODO: #2000 should make this lock go away, or change its shape.
-        let _guard = self.save_changes_lock.lock().await;
-
         let pickled_private_identity =
             if let Some(i) = changes.private_identity { Some(i.pickle().await) } else { None };
 
@@ -777,6 +766,11 @@ impl CryptoStore for SqliteCryptoStore {
         self.acquire()
             .await?
             .with_transaction(move |txn| {
+                if let Some(pickled_account) = &pickled_account {
+                    let serialized_account = this.serialize_value(pickled_account)?;
+                    txn.set_kv("account", &serialized_account)?;
+                }
+
                 if let Some(pickled_private_identity) = &pickled_private_identity {
                     let serialized_private_identity =
                         this.serialize_value(pickled_private_identity)?;
@@ -998,6 +992,17 @@ impl CryptoStore for SqliteCryptoStore {
         Ok(BackupKeys { backup_version, decryption_key })
     }
 
+    async fn delete_backup_keys(&self) -> Result<()> {
+        self.acquire()
+            .await?
+            .execute(
+                "DELETE FROM kv WHERE key IN (?1, ?2)",
+                ("backup_version_v1", "recovery_key_v1"),
+            )
+            .await?;
+        Ok(())
+    }
+
     async fn get_outbound_group_session(
         &self,
         room_id: &RoomId,
@@ -1274,11 +1279,20 @@ impl CryptoStore for SqliteCryptoStore {
 
 #[cfg(test)]
 mod tests {
-    use matrix_sdk_crypto::{cryptostore_integration_tests, cryptostore_integration_tests_time};
+    use std::collections::HashMap;
+
+    use matrix_sdk_crypto::{
+        cryptostore_integration_tests, cryptostore_integration_tests_time,
+        store::{Changes, CryptoStore, PendingChanges, RoomSettings},
+        Account, OlmMachine,
+    };
+    use matrix_sdk_test::async_test;
     use once_cell::sync::Lazy;
+    use ruma::{device_id, room_id, user_id};
     use tempfile::{tempdir, TempDir};
 
     use super::SqliteCryptoStore;
+    use crate::utils::{SqliteObjectExt, SqliteObjectStoreExt};
 
     static TMP_DIR: Lazy<TempDir> = Lazy::new(|| tempdir().unwrap());
 
@@ -1292,6 +1306,66 @@ mod tests {
 
     cryptostore_integration_tests!();
     cryptostore_integration_tests_time!();
+
+    #[async_test]
+    async fn save_changes_batch_interrupted() {
+        let store_name = "save_changes_batch_interrupted";
+        let store = get_store(store_name, None).await;
+        let mut account =
+            Account::with_device_id(user_id!("@alice:example.org"), device_id!("ALICEDEVICE"));
+
+        let changes =
+            Changes { next_batch_token: Some("first_batch".to_owned()), ..Default::default() };
+        store
+            .save_changes_batch(PendingChanges { account: Some(account.deep_clone()) }, changes)
+            .await
+            .expect("Can't save the changes");
+
+        // Saving the room settings fails, after the account and the sync token were
+        // written in the transaction.
+        store.acquire().await.unwrap().execute_batch("DROP TABLE room_settings").await.unwrap();
+
+        account.generate_one_time_keys_helper(1);
+        let changes = Changes {
+            next_batch_token: Some("second_batch".to_owned()),
+            room_settings: HashMap::from([(
+                room_id!("!test:localhost").to_owned(),
+                RoomSettings::default(),
+            )]),
+            ..Default::default()
+        };
+        store
+            .save_changes_batch(PendingChanges { account: Some(account.deep_clone()) }, changes)
+            .await
+            .expect_err("Saving the room settings should fail");
+        drop(store);
+
+        // None of the changes of the failed save were written.
+        let store = get_store(store_name, None).await;
+        assert_eq!(store.next_batch_token().await.unwrap().as_deref(), Some("first_batch"));
+        assert!(store.load_account().await.unwrap().unwrap().one_time_keys().is_empty());
+    }
+
+    #[async_test]
+    async fn test_user_room_key_is_encrypted() {
+        let store = get_store("user_room_key_is_encrypted", Some("secret")).await;
+        let machine = OlmMachine::with_store(
+            user_id!("@alice:example.org"),
+            device_id!("ALICEDEVICE"),
+            store.clone(),
+        )
+        .await
+        .unwrap();
+
+        let room_id = room_id!("!test:localhost");
+        machine.pseudo_ids().public_key_for_room(room_id).await.unwrap();
+
+        // The secret key is only written encrypted with the store cipher.
+        let key = format!("pseudo_id_user_room_key:{room_id}");
+        let raw = store.acquire().await.unwrap().get_kv(&key).await.unwrap().unwrap();
+        let decrypted = store.get_custom_value(&key).await.unwrap().unwrap();
+        assert_ne!(raw, decrypted);
+    }
 }
 
 #[cfg(test)]
@@ -29,7 +29,8 @@ use ruma::{
         GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType,
     },
     serde::Raw,
-    CanonicalJsonObject, EventId, OwnedEventId, OwnedUserId, RoomId, RoomVersionId, UserId,
+    CanonicalJsonObject, EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedUserId, RoomId,
+    RoomVersionId, UserId,
 };
 use rusqlite::{OptionalExtension, Transaction};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
@@ -57,7 +58,7 @@ mod keys {
     pub const MEDIA: &str = "media";
 }
 
-const DATABASE_VERSION: u8 = 3;
+const DATABASE_VERSION: u8 = 4;
 
 /// A sqlite based cryptostore.
 #[derive(Clone)]
@@ -213,6 +214,13 @@ impl SqliteStateStore {
             .await?;
         }
 
+        if from < 4 && to >= 4 {
+            conn.with_transaction(move |txn| {
+                txn.execute_batch(include_str!("../migrations/state_store/004_lock_leases.sql"))
+            })
+            .await?;
+        }
+
         conn.set_kv("version", vec![to]).await?;
 
         Ok(())
@@ -389,6 +397,14 @@ trait SqliteConnectionStateStoreExt {
         data: &[u8],
     ) -> rusqlite::Result<()>;
     fn remove_room_receipts(&self, room_id: &[u8]) -> rusqlite::Result<()>;
+    fn get_room_receipts(&self, room_id: &[u8]) -> rusqlite::Result<Vec<RoomReceiptRow>>;
+    fn remove_receipt(
+        &self,
+        room_id: &[u8],
+        user_id: &[u8],
+        receipt_type: &[u8],
+        thread: &[u8],
+    ) -> rusqlite::Result<()>;
 
     fn set_display_name(&self, room_id: &[u8], name: &[u8], data: &[u8]) -> rusqlite::Result<()>;
     fn remove_display_name(&self, room_id: &[u8], name: &[u8]) -> rusqlite::Result<()>;
@@ -573,6 +589,38 @@ impl SqliteConnectionStateStoreExt for rusqlite::Connection {
         Ok(())
     }
 
+    fn get_room_receipts(&self, room_id: &[u8]) -> rusqlite::Result<Vec<RoomReceiptRow>> {
+        let mut stmt = self
+            .prepare("SELECT user_id, receipt_type, thread, data FROM receipt WHERE room_id = ?")?;
+        let rows = stmt
+            .query((room_id,))?
+            .mapped(|row| {
+                Ok(RoomReceiptRow {
+                    user_id: row.get(0)?,
+                    receipt_type: row.get(1)?,
+                    thread: row.get(2)?,
+                    data: row.get(3)?,
+                })
+            })
+            .collect();
+        rows
+    }
+
+    fn remove_receipt(
+        &self,
+        room_id: &[u8],
+        user_id: &[u8],
+        receipt_type: &[u8],
+        thread: &[u8],
+    ) -> rusqlite::Result<()> {
+        self.prepare_cached(
+            "DELETE FROM receipt
+             WHERE room_id = ? AND user_id = ? AND receipt_type = ? AND thread = ?",
+        )?
+        .execute((room_id, user_id, receipt_type, thread))?;
+        Ok(())
+    }
+
     fn set_display_name(&self, room_id: &[u8], name: &[u8], data: &[u8]) -> rusqlite::Result<()> {
         self.prepare_cached(
             "INSERT OR REPLACE
@@ -691,6 +739,17 @@ trait SqliteObjectStateStoreExt: SqliteObjectExt {
             .await?)
     }
 
+    async fn get_all_maybe_stripped_state_events(
+        &self,
+        room_id: Key,
+    ) -> Result<Vec<(bool, Vec<u8>)>> {
+        Ok(self
+            .prepare("SELECT stripped, data FROM state_event WHERE room_id = ?", |mut stmt| {
+                stmt.query((room_id,))?.mapped(|row| Ok((row.get(0)?, row.get(1)?))).collect()
+            })
+            .await?)
+    }
+
     async fn get_profiles(
         &self,
         room_id: Key,
@@ -928,6 +987,7 @@ impl StateStore for SqliteStateStore {
                     redactions,
                     stripped_state,
                     ambiguity_maps,
+                    custom_values,
                 } = changes;
 
                 if let Some(sync_token) = sync_token {
@@ -936,6 +996,10 @@ impl StateStore for SqliteStateStore {
                     txn.set_kv_blob(&key, &value)?;
                 }
 
+                for (key, value) in custom_values {
+                    txn.set_kv_blob(&this.encode_custom_key(&key), &value)?;
+                }
+
                 for (event_type, event) in account_data {
                     let event_type =
                         this.encode_key(keys::GLOBAL_ACCOUNT_DATA, event_type.to_string());
@@ -1284,6 +1348,32 @@ impl StateStore for SqliteStateStore {
             .collect()
     }
 
+    async fn get_all_state_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>, Self::Error> {
+        let room_id = self.encode_key(keys::STATE_EVENT, room_id);
+        let mut events = self.acquire().await?.get_all_maybe_stripped_state_events(room_id).await?;
+
+        // Only return the stripped state if the room has some.
+        if events.iter().any(|(stripped, _)| *stripped) {
+            events.retain(|(stripped, _)| *stripped);
+        }
+
+        events
+            .into_iter()
+            .map(|(stripped, data)| {
+                let ev = if stripped {
+                    RawAnySyncOrStrippedState::Stripped(self.deserialize_json(&data)?)
+                } else {
+                    RawAnySyncOrStrippedState::Sync(self.deserialize_json(&data)?)
+                };
+
+                Ok(ev)
+            })
+            .collect()
+    }
+
     async fn get_profile(
         &self,
         room_id: &RoomId,
@@ -1592,6 +1682,71 @@ impl StateStore for SqliteStateStore {
             })
             .await
     }
+
+    async fn remove_room_receipts_before(
+        &self,
+        room_id: &RoomId,
+        before: MilliSecondsSinceUnixEpoch,
+    ) -> Result<()> {
+        let this = self.clone();
+        let room_id = this.encode_key(keys::RECEIPT, room_id);
+
+        self.acquire()
+            .await?
+            .with_transaction(move |txn| {
+                for row in txn.get_room_receipts(&room_id)? {
+                    let receipt = this.deserialize_json::<ReceiptData>(&row.data)?.receipt;
+
+                    if receipt.ts.is_some_and(|ts| ts < before) {
+                        txn.remove_receipt(&room_id, &row.user_id, &row.receipt_type, &row.thread)?;
+                    }
+                }
+
+                Ok(())
+            })
+            .await
+    }
+
+    async fn try_take_leased_lock(
+        &self,
+        lease_duration_ms: u32,
+        key: &str,
+        holder: &str,
+    ) -> Result<bool> {
+        let key = key.to_owned();
+        let holder = holder.to_owned();
+
+        let now_ts: u64 = MilliSecondsSinceUnixEpoch::now().get().into();
+        let expiration_ts = now_ts + lease_duration_ms as u64;
+
+        let num_touched = self
+            .acquire()
+            .await?
+            .with_transaction(move |txn| {
+                txn.execute(
+                    "INSERT INTO lease_locks (key, holder, expiration_ts)
+                    VALUES (?1, ?2, ?3)
+                    ON CONFLICT (key)
+                    DO
+                        UPDATE SET holder = ?2, expiration_ts = ?3
+                        WHERE holder = ?2
+                        OR expiration_ts < ?4
+                ",
+                    (key, holder, expiration_ts, now_ts),
+                )
+            })
+            .await?;
+
+        Ok(num_touched == 1)
+    }
+}
+
+/// A row of the `receipt` table, with the keys needed to remove it.
+struct RoomReceiptRow {
+    user_id: Vec<u8>,
+    receipt_type: Vec<u8>,
+    thread: Vec<u8>,
+    data: Vec<u8>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
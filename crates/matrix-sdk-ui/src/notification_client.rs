@@ -22,6 +22,7 @@ use matrix_sdk::{room::Room, Client, ClientBuildError, SlidingSyncList, SlidingS
 use matrix_sdk_base::{
     crypto::{vodozemac, MegolmError},
     deserialized_responses::TimelineEvent,
+    timeout::timeout,
     RoomState, StoreError,
 };
 use ruma::{
@@ -37,7 +38,7 @@ use ruma::{
     html::RemoveReplyFallback,
     push::Action,
     serde::Raw,
-    uint, EventId, OwnedEventId, RoomId, UserId,
+    uint, EventId, OwnedEventId, OwnedRoomId, RoomId, UserId,
 };
 use thiserror::Error;
 use tokio::sync::Mutex as AsyncMutex;
@@ -144,6 +145,68 @@ impl NotificationClient {
         }
     }
 
+    /// Fetches the content of a notification, giving up once the given time
+    /// budget is exceeded.
+    ///
+    /// This is meant for push notifications in the `event_id_only` format,
+    /// which only contain the room ID and the event ID: the event has to be
+    /// fetched, decrypted if it's encrypted, and the push rules evaluated, like
+    /// with [`NotificationClient::get_notification()`]. If that takes longer
+    /// than `budget`, the information that is known locally about the room is
+    /// returned instead, so that a notification can still be displayed.
+    ///
+    /// For instance, on iOS, the Notification Service Extension must hand the
+    /// notification over within 30 seconds, so the budget should be a bit
+    /// shorter than that.
+    #[instrument(skip(self))]
+    pub async fn get_notification_within(
+        &self,
+        room_id: &RoomId,
+        event_id: &EventId,
+        budget: Duration,
+    ) -> Result<BudgetedNotification, Error> {
+        match timeout(self.get_notification(room_id, event_id), budget).await {
+            Ok(Ok(Some(item))) => Ok(BudgetedNotification::Complete(item)),
+            Ok(Ok(None)) => Ok(BudgetedNotification::FilteredOut),
+            Ok(Err(err)) => Err(err),
+            Err(_) => {
+                warn!("The notification couldn't be resolved within {budget:?}");
+                Ok(BudgetedNotification::Partial(
+                    self.partial_notification(room_id, event_id).await,
+                ))
+            }
+        }
+    }
+
+    /// Build a notification from the information that is known locally about
+    /// the room.
+    async fn partial_notification(
+        &self,
+        room_id: &RoomId,
+        event_id: &EventId,
+    ) -> PartialNotificationItem {
+        // The parent client is more likely to know about the room, but the
+        // notification sliding sync might have received it already.
+        let room = self.parent_client.get_room(room_id).or_else(|| self.client.get_room(room_id));
+
+        let room_display_name = match room {
+            Some(room) => match room.display_name().await {
+                Ok(name) => Some(name.to_string()),
+                Err(err) => {
+                    warn!("Couldn't compute the room name for a notification: {err}");
+                    None
+                }
+            },
+            None => None,
+        };
+
+        PartialNotificationItem {
+            room_id: room_id.to_owned(),
+            event_id: event_id.to_owned(),
+            room_display_name,
+        }
+    }
+
     /// Run an encryption sync loop, in case an event is still encrypted.
     ///
     /// Will return true if and only:
@@ -300,7 +363,9 @@ impl NotificationClient {
         let cloned_notif = notification.clone();
         let target_event_id = event_id.to_owned();
 
-        let timeline_event_handler =
+        // The handlers are removed when the guards are dropped, even if this
+        // future is cancelled, e.g. because the time budget is exceeded.
+        let _timeline_event_handler_guard = self.client.event_handler_drop_guard(
             self.client.add_event_handler(move |raw: Raw<AnySyncTimelineEvent>| async move {
                 match raw.get_field::<OwnedEventId>("event_id") {
                     Ok(Some(event_id)) => {
@@ -318,11 +383,12 @@ impl NotificationClient {
                         warn!("a sync event id couldn't be decoded: {err}");
                     }
                 }
-            });
+            }),
+        );
 
         let cloned_notif = notification.clone();
         let target_event_id = event_id.to_owned();
-        let stripped_member_handler =
+        let _stripped_member_handler_guard = self.client.event_handler_drop_guard(
             self.client.add_event_handler(move |raw: Raw<StrippedRoomMemberEvent>| async move {
                 match raw.get_field::<OwnedEventId>("event_id") {
                     Ok(Some(event_id)) => {
@@ -339,7 +405,8 @@ impl NotificationClient {
                         warn!("a room member event id couldn't be decoded: {err}");
                     }
                 }
-            });
+            }),
+        );
 
         // Room power levels are necessary to build the push context.
         let required_state = vec![
@@ -406,9 +473,6 @@ impl NotificationClient {
             }
         }
 
-        self.client.remove_event_handler(stripped_member_handler);
-        self.client.remove_event_handler(timeline_event_handler);
-
         let maybe_event = notification.lock().unwrap().take();
         Ok(maybe_event)
     }
@@ -527,6 +591,30 @@ pub enum NotificationStatus {
     EventFilteredOut,
 }
 
+/// The result of [`NotificationClient::get_notification_within()`].
+#[derive(Debug)]
+pub enum BudgetedNotification {
+    /// The notification was resolved within the time budget.
+    Complete(NotificationItem),
+    /// The notification was filtered out by the user's push rules.
+    FilteredOut,
+    /// The time budget was exceeded, only the information known locally about
+    /// the room is available.
+    Partial(PartialNotificationItem),
+}
+
+/// A notification that couldn't be resolved within the time budget of
+/// [`NotificationClient::get_notification_within()`].
+#[derive(Debug)]
+pub struct PartialNotificationItem {
+    /// The ID of the room of the notification.
+    pub room_id: OwnedRoomId,
+    /// The ID of the event of the notification.
+    pub event_id: OwnedEventId,
+    /// The display name of the room, if the room is known locally.
+    pub room_display_name: Option<String>,
+}
+
 /// Builder for a `NotificationClient`.
 ///
 /// Fields have the same meaning as in `NotificationClient`.
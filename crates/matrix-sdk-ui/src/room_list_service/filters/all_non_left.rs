@@ -1,16 +1,29 @@
 use matrix_sdk::{Client, RoomListEntry};
 use matrix_sdk_base::RoomState;
 
-struct NonLeftRoomMatcher<F: Fn(&RoomListEntry) -> Option<RoomState>> {
+struct NonLeftRoomMatcher<F, G>
+where
+    F: Fn(&RoomListEntry) -> Option<RoomState>,
+    G: Fn(&RoomListEntry) -> bool,
+{
     get_state: F,
+    is_spam_invite: G,
 }
 
-impl<F: Fn(&RoomListEntry) -> Option<RoomState>> NonLeftRoomMatcher<F> {
+impl<F, G> NonLeftRoomMatcher<F, G>
+where
+    F: Fn(&RoomListEntry) -> Option<RoomState>,
+    G: Fn(&RoomListEntry) -> bool,
+{
     fn matches(&self, room: &RoomListEntry) -> bool {
         if !matches!(room, RoomListEntry::Filled(_) | RoomListEntry::Invalidated(_)) {
             return false;
         }
 
+        if (self.is_spam_invite)(room) {
+            return false;
+        }
+
         if let Some(state) = (self.get_state)(room) {
             state != RoomState::Left
         } else {
@@ -20,15 +33,23 @@ impl<F: Fn(&RoomListEntry) -> Option<RoomState>> NonLeftRoomMatcher<F> {
 }
 
 /// Create a new filter that will accept all filled or invalidated entries, but
-/// filters out left rooms.
+/// filters out left rooms and invites that were shelved as spam.
 pub fn new_filter(client: &Client) -> impl Fn(&RoomListEntry) -> bool {
     let client = client.clone();
 
     let matcher = NonLeftRoomMatcher {
-        get_state: move |room| {
-            let room_id = room.as_room_id()?;
-            let room = client.get_room(room_id)?;
-            Some(room.state())
+        get_state: {
+            let client = client.clone();
+            move |room| {
+                let room_id = room.as_room_id()?;
+                let room = client.get_room(room_id)?;
+                Some(room.state())
+            }
+        },
+        is_spam_invite: move |room| {
+            room.as_room_id()
+                .and_then(|room_id| client.get_room(room_id))
+                .is_some_and(|room| room.is_spam_invite())
         },
     };
 
@@ -46,21 +67,34 @@ mod tests {
     #[test]
     fn test_all_non_left_kind_of_room_list_entry() {
         // When we can't figure out the room state, nothing matches.
-        let matcher = NonLeftRoomMatcher { get_state: |_| None };
+        let matcher = NonLeftRoomMatcher { get_state: |_| None, is_spam_invite: |_| false };
         assert!(!matcher.matches(&RoomListEntry::Empty));
         assert!(!matcher.matches(&RoomListEntry::Filled(room_id!("!r0:bar.org").to_owned())));
         assert!(!matcher.matches(&RoomListEntry::Invalidated(room_id!("!r0:bar.org").to_owned())));
 
         // When a room has been left, it doesn't match.
-        let matcher = NonLeftRoomMatcher { get_state: |_| Some(RoomState::Left) };
+        let matcher =
+            NonLeftRoomMatcher { get_state: |_| Some(RoomState::Left), is_spam_invite: |_| false };
         assert!(!matcher.matches(&RoomListEntry::Empty));
         assert!(!matcher.matches(&RoomListEntry::Filled(room_id!("!r0:bar.org").to_owned())));
         assert!(!matcher.matches(&RoomListEntry::Invalidated(room_id!("!r0:bar.org").to_owned())));
 
         // When a room has been joined, it does match (unless it's empty).
-        let matcher = NonLeftRoomMatcher { get_state: |_| Some(RoomState::Joined) };
+        let matcher = NonLeftRoomMatcher {
+            get_state: |_| Some(RoomState::Joined),
+            is_spam_invite: |_| false,
+        };
         assert!(!matcher.matches(&RoomListEntry::Empty));
         assert!(matcher.matches(&RoomListEntry::Filled(room_id!("!r0:bar.org").to_owned())));
         assert!(matcher.matches(&RoomListEntry::Invalidated(room_id!("!r0:bar.org").to_owned())));
+
+        // When an invite has been shelved as spam, it doesn't match.
+        let matcher = NonLeftRoomMatcher {
+            get_state: |_| Some(RoomState::Invited),
+            is_spam_invite: |_| true,
+        };
+        assert!(!matcher.matches(&RoomListEntry::Empty));
+        assert!(!matcher.matches(&RoomListEntry::Filled(room_id!("!r0:bar.org").to_owned())));
+        assert!(!matcher.matches(&RoomListEntry::Invalidated(room_id!("!r0:bar.org").to_owned())));
     }
 }
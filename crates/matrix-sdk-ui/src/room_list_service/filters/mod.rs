@@ -3,13 +3,17 @@ mod all_non_left;
 mod fuzzy_match_room_name;
 mod none;
 mod normalized_match_room_name;
+mod spam_invites;
+mod unread;
 
 pub use all::new_filter as new_filter_all;
 pub use all_non_left::new_filter as new_filter_all_non_left;
 pub use fuzzy_match_room_name::new_filter as new_filter_fuzzy_match_room_name;
 pub use none::new_filter as new_filter_none;
 pub use normalized_match_room_name::new_filter as new_filter_normalized_match_room_name;
+pub use spam_invites::new_filter as new_filter_spam_invites;
 use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+pub use unread::new_filter as new_filter_unread;
 
 /// Normalize a string, i.e. decompose it into NFD (Normalization Form D, i.e. a
 /// canonical decomposition, see http://www.unicode.org/reports/tr15/) and
@@ -0,0 +1,49 @@
+use matrix_sdk::{Client, RoomListEntry};
+
+struct SpamInviteMatcher<F: Fn(&RoomListEntry) -> bool> {
+    is_spam_invite: F,
+}
+
+impl<F: Fn(&RoomListEntry) -> bool> SpamInviteMatcher<F> {
+    fn matches(&self, room: &RoomListEntry) -> bool {
+        matches!(room, RoomListEntry::Filled(_) | RoomListEntry::Invalidated(_))
+            && (self.is_spam_invite)(room)
+    }
+}
+
+/// Create a new filter that will only accept the invites that were shelved as
+/// spam by an [`InviteFilter`][matrix_sdk::invite_filter::InviteFilter].
+pub fn new_filter(client: &Client) -> impl Fn(&RoomListEntry) -> bool {
+    let client = client.clone();
+
+    let matcher = SpamInviteMatcher {
+        is_spam_invite: move |room| {
+            room.as_room_id()
+                .and_then(|room_id| client.get_room(room_id))
+                .is_some_and(|room| room.is_spam_invite())
+        },
+    };
+
+    move |room_list_entry| -> bool { matcher.matches(room_list_entry) }
+}
+
+#[cfg(test)]
+mod tests {
+    use matrix_sdk::RoomListEntry;
+    use ruma::room_id;
+
+    use super::SpamInviteMatcher;
+
+    #[test]
+    fn test_spam_invites() {
+        let matcher = SpamInviteMatcher { is_spam_invite: |_| false };
+        assert!(!matcher.matches(&RoomListEntry::Empty));
+        assert!(!matcher.matches(&RoomListEntry::Filled(room_id!("!r0:bar.org").to_owned())));
+        assert!(!matcher.matches(&RoomListEntry::Invalidated(room_id!("!r0:bar.org").to_owned())));
+
+        let matcher = SpamInviteMatcher { is_spam_invite: |_| true };
+        assert!(!matcher.matches(&RoomListEntry::Empty));
+        assert!(matcher.matches(&RoomListEntry::Filled(room_id!("!r0:bar.org").to_owned())));
+        assert!(matcher.matches(&RoomListEntry::Invalidated(room_id!("!r0:bar.org").to_owned())));
+    }
+}
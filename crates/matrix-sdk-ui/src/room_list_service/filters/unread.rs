@@ -0,0 +1,71 @@
+use matrix_sdk::{Client, RoomListEntry};
+
+struct UnreadRoomMatcher<F: Fn(&RoomListEntry) -> Option<(bool, u64)>> {
+    /// Get whether the room was manually marked as unread, and its number of
+    /// unread notifications.
+    get_unread_state: F,
+}
+
+impl<F: Fn(&RoomListEntry) -> Option<(bool, u64)>> UnreadRoomMatcher<F> {
+    fn matches(&self, room: &RoomListEntry) -> bool {
+        if !matches!(room, RoomListEntry::Filled(_) | RoomListEntry::Invalidated(_)) {
+            return false;
+        }
+
+        (self.get_unread_state)(room).is_some_and(|(is_marked_unread, num_notifications)| {
+            is_marked_unread || num_notifications > 0
+        })
+    }
+}
+
+/// Create a new filter that will accept the rooms with unread notifications,
+/// or that were manually marked as unread by the user.
+pub fn new_filter(client: &Client) -> impl Fn(&RoomListEntry) -> bool {
+    let client = client.clone();
+
+    let matcher = UnreadRoomMatcher {
+        get_unread_state: move |room| {
+            let room_id = room.as_room_id()?;
+            let room = client.get_room(room_id)?;
+            Some((
+                room.is_marked_unread(),
+                room.total_unread_notification_counts().notification_count,
+            ))
+        },
+    };
+
+    move |room_list_entry| -> bool { matcher.matches(room_list_entry) }
+}
+
+#[cfg(test)]
+mod tests {
+    use matrix_sdk::RoomListEntry;
+    use ruma::room_id;
+
+    use super::UnreadRoomMatcher;
+
+    #[test]
+    fn test_unread() {
+        // When we can't figure out the room, nothing matches.
+        let matcher = UnreadRoomMatcher { get_unread_state: |_| None };
+        assert!(!matcher.matches(&RoomListEntry::Empty));
+        assert!(!matcher.matches(&RoomListEntry::Filled(room_id!("!r0:bar.org").to_owned())));
+
+        // A read room doesn't match.
+        let matcher = UnreadRoomMatcher { get_unread_state: |_| Some((false, 0)) };
+        assert!(!matcher.matches(&RoomListEntry::Filled(room_id!("!r0:bar.org").to_owned())));
+        assert!(!matcher.matches(&RoomListEntry::Invalidated(room_id!("!r0:bar.org").to_owned())));
+
+        // A room with notifications matches.
+        let matcher = UnreadRoomMatcher { get_unread_state: |_| Some((false, 3)) };
+        assert!(!matcher.matches(&RoomListEntry::Empty));
+        assert!(matcher.matches(&RoomListEntry::Filled(room_id!("!r0:bar.org").to_owned())));
+        assert!(matcher.matches(&RoomListEntry::Invalidated(room_id!("!r0:bar.org").to_owned())));
+
+        // A room marked as unread matches, even without notifications.
+        let matcher = UnreadRoomMatcher { get_unread_state: |_| Some((true, 0)) };
+        assert!(!matcher.matches(&RoomListEntry::Empty));
+        assert!(matcher.matches(&RoomListEntry::Filled(room_id!("!r0:bar.org").to_owned())));
+        assert!(matcher.matches(&RoomListEntry::Invalidated(room_id!("!r0:bar.org").to_owned())));
+    }
+}
@@ -64,6 +64,7 @@
 pub mod filters;
 mod room;
 mod room_list;
+mod sections;
 mod state;
 
 use std::{future::ready, sync::Arc, time::Duration};
@@ -73,8 +74,9 @@ use eyeball::{SharedObservable, Subscriber};
 use futures_util::{pin_mut, Stream, StreamExt};
 pub use matrix_sdk::RoomListEntry;
 use matrix_sdk::{
-    sliding_sync::Ranges, Client, Error as SlidingSyncError, SlidingSync, SlidingSyncList,
-    SlidingSyncListBuilder, SlidingSyncMode,
+    sliding_sync::{Bound, Ranges},
+    Client, Error as SlidingSyncError, SlidingSync, SlidingSyncList, SlidingSyncListBuilder,
+    SlidingSyncMode,
 };
 use matrix_sdk_base::ring_buffer::RingBuffer;
 pub use room::*;
@@ -88,6 +90,7 @@ use ruma::{
     events::{StateEventType, TimelineEventType},
     OwnedRoomId, RoomId,
 };
+pub use sections::*;
 pub use state::*;
 use thiserror::Error;
 use tokio::{
@@ -95,6 +98,8 @@ use tokio::{
     time::timeout,
 };
 
+use crate::sync_service::BandwidthProfile;
+
 /// The [`RoomListService`] type. See the module's documentation to learn more.
 #[derive(Debug)]
 pub struct RoomListService {
@@ -117,6 +122,9 @@ pub struct RoomListService {
     /// This is useful to avoid resetting the ranges to the same value,
     /// which would cancel the current in-flight sync request.
     viewport_ranges: Mutex<Ranges>,
+
+    /// The bandwidth profile the lists are configured for.
+    bandwidth_profile: SharedObservable<BandwidthProfile>,
 }
 
 impl RoomListService {
@@ -173,13 +181,8 @@ impl RoomListService {
                         SlidingSyncMode::new_selective()
                             .add_range(ALL_ROOMS_DEFAULT_SELECTIVE_RANGE),
                     )
-                    .timeline_limit(1)
-                    .required_state(vec![
-                        (StateEventType::RoomAvatar, "".to_owned()),
-                        (StateEventType::RoomEncryption, "".to_owned()),
-                        (StateEventType::RoomMember, "$LAZY".to_owned()),
-                        (StateEventType::RoomPowerLevels, "".to_owned()),
-                    ]),
+                    .timeline_limit(ALL_ROOMS_DEFAULT_TIMELINE_LIMIT)
+                    .required_state(all_rooms_required_state(BandwidthProfile::Normal)),
             ))
             .await
             .map_err(Error::SlidingSync)?
@@ -189,12 +192,7 @@ impl RoomListService {
                         SlidingSyncMode::new_selective().add_range(INVITES_DEFAULT_SELECTIVE_RANGE),
                     )
                     .timeline_limit(0)
-                    .required_state(vec![
-                        (StateEventType::RoomAvatar, "".to_owned()),
-                        (StateEventType::RoomEncryption, "".to_owned()),
-                        (StateEventType::RoomMember, "$ME".to_owned()),
-                        (StateEventType::RoomCanonicalAlias, "".to_owned()),
-                    ])
+                    .required_state(invites_required_state(BandwidthProfile::Normal))
                     .filters(Some(assign!(SyncRequestListFilters::default(), {
                         is_invite: Some(true),
                         is_tombstoned: Some(false),
@@ -213,6 +211,7 @@ impl RoomListService {
             state: SharedObservable::new(State::Init),
             rooms: Arc::new(RwLock::new(RingBuffer::new(Self::ROOM_OBJECT_CACHE_SIZE))),
             viewport_ranges: Mutex::new(vec![VISIBLE_ROOMS_DEFAULT_RANGE]),
+            bandwidth_profile: SharedObservable::new(BandwidthProfile::Normal),
         })
     }
 
@@ -247,7 +246,16 @@ impl RoomListService {
             // 4. The next state is stored.
             loop {
                 // Calculate the next state, and run the associated actions.
-                let next_state = self.state.get().next(&self.sliding_sync).await?;
+                let state = self.state.get();
+                let next_state = state.next(&self.sliding_sync).await?;
+
+                // The actions reset the lists to their default parameters, restore the
+                // ones of the low data profile.
+                if next_state != state
+                    && self.bandwidth_profile.get() == BandwidthProfile::LowData
+                {
+                    self.apply_bandwidth_profile(BandwidthProfile::LowData).await?;
+                }
 
                 // Do the sync.
                 match sync.next().await {
@@ -406,6 +414,90 @@ impl RoomListService {
         self.list_for(INVITES_LIST_NAME).await
     }
 
+    /// Get the [`RoomSections`] splitting all the rooms into sections, like
+    /// invites, favourites or direct messages.
+    pub async fn sections(&self, config: RoomSectionsConfig) -> Result<RoomSections, Error> {
+        let sliding_sync_list = self
+            .sliding_sync
+            .on_list(ALL_ROOMS_LIST_NAME, |list| ready(list.clone()))
+            .await
+            .ok_or_else(|| Error::UnknownList(ALL_ROOMS_LIST_NAME.to_owned()))?;
+
+        Ok(RoomSections::new(self.client.clone(), sliding_sync_list, config).await)
+    }
+
+    /// Get the bandwidth profile the lists are configured for.
+    pub fn bandwidth_profile(&self) -> BandwidthProfile {
+        self.bandwidth_profile.get()
+    }
+
+    /// Configure the lists for the given bandwidth profile.
+    ///
+    /// With [`BandwidthProfile::LowData`], the timelines of the visible rooms
+    /// are shorter, the latest event of the other rooms isn't synced, the
+    /// avatars of the rooms aren't synced and the sync requests are long-polled
+    /// for longer. This is applied from the next sync request.
+    ///
+    /// Most users should use [`SyncService::set_bandwidth_profile`] instead.
+    ///
+    /// [`SyncService::set_bandwidth_profile`]: crate::sync_service::SyncService::set_bandwidth_profile
+    pub async fn set_bandwidth_profile(&self, profile: BandwidthProfile) -> Result<(), Error> {
+        if self.bandwidth_profile.set_if_not_eq(profile).is_some() {
+            self.apply_bandwidth_profile(profile).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn apply_bandwidth_profile(&self, profile: BandwidthProfile) -> Result<(), Error> {
+        let (all_rooms_timeline_limit, visible_rooms_timeline_limit, poll_timeout) = match profile {
+            BandwidthProfile::Normal => (
+                ALL_ROOMS_DEFAULT_TIMELINE_LIMIT,
+                VISIBLE_ROOMS_DEFAULT_TIMELINE_LIMIT,
+                DEFAULT_POLL_TIMEOUT,
+            ),
+            BandwidthProfile::LowData => {
+                (0, VISIBLE_ROOMS_LOW_DATA_TIMELINE_LIMIT, LOW_DATA_POLL_TIMEOUT)
+            }
+        };
+
+        self.sliding_sync
+            .on_list(ALL_ROOMS_LIST_NAME, |list| {
+                list.set_timeline_limit(Some(all_rooms_timeline_limit));
+                list.set_required_state(all_rooms_required_state(profile));
+
+                ready(())
+            })
+            .await
+            .ok_or_else(|| Error::UnknownList(ALL_ROOMS_LIST_NAME.to_owned()))?;
+
+        self.sliding_sync
+            .on_list(INVITES_LIST_NAME, |list| {
+                list.set_required_state(invites_required_state(profile));
+
+                ready(())
+            })
+            .await
+            .ok_or_else(|| Error::UnknownList(INVITES_LIST_NAME.to_owned()))?;
+
+        // The list of the visible rooms only exists once the first rooms are
+        // loaded. Its timeline limit is zero while recovering, it is restored by
+        // the state machine.
+        self.sliding_sync
+            .on_list(VISIBLE_ROOMS_LIST_NAME, |list| {
+                if list.timeline_limit() != Some(0) {
+                    list.set_timeline_limit(Some(visible_rooms_timeline_limit));
+                }
+
+                ready(())
+            })
+            .await;
+
+        self.sliding_sync.set_poll_timeout(poll_timeout);
+
+        Ok(())
+    }
+
     /// Pass an [`Input`] onto the state machine.
     pub async fn apply_input(&self, input: Input) -> Result<InputResult, Error> {
         use Input::*;
@@ -464,6 +556,47 @@ impl RoomListService {
     }
 }
 
+/// Default timeline limit for the `ALL_ROOMS_LIST_NAME` list, to get the
+/// latest event of the rooms.
+const ALL_ROOMS_DEFAULT_TIMELINE_LIMIT: Bound = 1;
+
+/// Default long-polling timeout of the sync requests.
+const DEFAULT_POLL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Long-polling timeout of the sync requests with the
+/// [`BandwidthProfile::LowData`] profile.
+const LOW_DATA_POLL_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// The required state of the `ALL_ROOMS_LIST_NAME` list.
+fn all_rooms_required_state(profile: BandwidthProfile) -> Vec<(StateEventType, String)> {
+    let mut required_state = vec![
+        (StateEventType::RoomEncryption, "".to_owned()),
+        (StateEventType::RoomMember, "$LAZY".to_owned()),
+        (StateEventType::RoomPowerLevels, "".to_owned()),
+    ];
+
+    if profile == BandwidthProfile::Normal {
+        required_state.insert(0, (StateEventType::RoomAvatar, "".to_owned()));
+    }
+
+    required_state
+}
+
+/// The required state of the `INVITES_LIST_NAME` list.
+fn invites_required_state(profile: BandwidthProfile) -> Vec<(StateEventType, String)> {
+    let mut required_state = vec![
+        (StateEventType::RoomEncryption, "".to_owned()),
+        (StateEventType::RoomMember, "$ME".to_owned()),
+        (StateEventType::RoomCanonicalAlias, "".to_owned()),
+    ];
+
+    if profile == BandwidthProfile::Normal {
+        required_state.insert(0, (StateEventType::RoomAvatar, "".to_owned()));
+    }
+
+    required_state
+}
+
 /// Configure the Sliding Sync list for `ALL_ROOMS_LIST_NAME` and
 /// `VISIBLE_ROOMS_LIST_NAME`.
 ///
@@ -556,22 +689,25 @@ pub enum SyncIndicator {
 
 #[cfg(test)]
 mod tests {
-    use std::future::ready;
+    use std::{future::ready, time::Duration};
 
     use futures_util::{pin_mut, StreamExt};
     use matrix_sdk::{
         config::RequestConfig,
         matrix_auth::{MatrixSession, MatrixSessionTokens},
         reqwest::Url,
-        Client, SlidingSyncMode,
+        sliding_sync::Bound,
+        Client, SlidingSync, SlidingSyncMode,
     };
     use matrix_sdk_base::SessionMeta;
     use matrix_sdk_test::async_test;
-    use ruma::{api::MatrixVersion, device_id, user_id};
+    use ruma::{api::MatrixVersion, device_id, events::StateEventType, user_id};
     use serde_json::json;
     use wiremock::{http::Method, Match, Mock, MockServer, Request, ResponseTemplate};
 
-    use super::{Error, RoomListService, State, ALL_ROOMS_LIST_NAME};
+    use super::{
+        BandwidthProfile, Error, RoomListService, State, ALL_ROOMS_LIST_NAME, INVITES_LIST_NAME,
+    };
 
     async fn new_client() -> (Client, MockServer) {
         let session = MatrixSession {
@@ -651,6 +787,54 @@ mod tests {
         Ok(())
     }
 
+    #[async_test]
+    async fn test_bandwidth_profile() -> Result<(), Error> {
+        let room_list = new_room_list().await?;
+        let sliding_sync = room_list.sliding_sync();
+
+        async fn all_rooms(
+            sliding_sync: &SlidingSync,
+        ) -> Option<(Option<Bound>, Vec<(StateEventType, String)>)> {
+            sliding_sync
+                .on_list(ALL_ROOMS_LIST_NAME, |list| {
+                    ready((list.timeline_limit(), list.required_state()))
+                })
+                .await
+        }
+
+        fn has_avatar(required_state: &[(StateEventType, String)]) -> bool {
+            required_state.iter().any(|(event_type, _)| *event_type == StateEventType::RoomAvatar)
+        }
+
+        let (timeline_limit, required_state) = all_rooms(sliding_sync).await.unwrap();
+        assert_eq!(timeline_limit, Some(1));
+        assert!(has_avatar(&required_state));
+        assert_eq!(sliding_sync.poll_timeout(), Duration::from_secs(30));
+
+        // The low data profile drops the latest events and the avatars.
+        room_list.set_bandwidth_profile(BandwidthProfile::LowData).await?;
+        assert_eq!(room_list.bandwidth_profile(), BandwidthProfile::LowData);
+
+        let (timeline_limit, required_state) = all_rooms(sliding_sync).await.unwrap();
+        assert_eq!(timeline_limit, Some(0));
+        assert!(!has_avatar(&required_state));
+        assert!(!sliding_sync
+            .on_list(INVITES_LIST_NAME, |list| ready(has_avatar(&list.required_state())))
+            .await
+            .unwrap());
+        assert_eq!(sliding_sync.poll_timeout(), Duration::from_secs(120));
+
+        // Everything is restored with the normal profile.
+        room_list.set_bandwidth_profile(BandwidthProfile::Normal).await?;
+
+        let (timeline_limit, required_state) = all_rooms(sliding_sync).await.unwrap();
+        assert_eq!(timeline_limit, Some(1));
+        assert!(has_avatar(&required_state));
+        assert_eq!(sliding_sync.poll_timeout(), Duration::from_secs(30));
+
+        Ok(())
+    }
+
     #[async_test]
     async fn test_no_to_device_and_e2ee_if_not_explicitly_set() -> Result<(), Error> {
         let (client, _) = new_client().await;
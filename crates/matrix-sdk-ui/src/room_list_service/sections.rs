@@ -0,0 +1,890 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The sections of the room list, see [`RoomSections`].
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    fmt,
+    sync::{Arc, Mutex as StdMutex},
+};
+
+use eyeball_im::{ObservableVector, Vector, VectorDiff};
+use futures_util::{pin_mut, Stream, StreamExt as _};
+use matrix_sdk::{
+    event_handler::EventHandlerDropGuard,
+    executor::{spawn, JoinHandle},
+    Client, RoomListEntry, SlidingSyncList,
+};
+use matrix_sdk_base::{RoomInfoChangeReasons, RoomInfoUpdate, RoomState};
+use ruma::{
+    events::tag::{TagEvent, TagName, Tags},
+    MilliSecondsSinceUnixEpoch, OwnedRoomId, RoomId,
+};
+use tokio::{
+    select,
+    sync::{broadcast::error::RecvError, mpsc, Mutex as AsyncMutex},
+};
+use tracing::{debug, warn};
+
+/// A section of the room list, see [`RoomSections`].
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RoomSection {
+    /// The rooms the user is invited to.
+    Invites,
+
+    /// The rooms tagged with `m.favourite`.
+    Favourites,
+
+    /// The direct messages.
+    People,
+
+    /// The rooms that don't belong to any other section.
+    Rooms,
+
+    /// The rooms tagged with `m.lowpriority`.
+    LowPriority,
+
+    /// A section defined by a [`CustomSection`], identified by its name.
+    Custom(String),
+}
+
+impl RoomSection {
+    /// The tag whose order is used to sort this section with
+    /// [`SectionSortOrder::Manual`].
+    fn manual_order_tag(&self) -> Option<&str> {
+        match self {
+            Self::Favourites => Some("m.favourite"),
+            Self::LowPriority => Some("m.lowpriority"),
+            Self::Custom(name) => Some(name),
+            Self::Invites | Self::People | Self::Rooms => None,
+        }
+    }
+}
+
+/// How the rooms of a section are sorted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SectionSortOrder {
+    /// The rooms with the most recent activity first.
+    #[default]
+    Activity,
+
+    /// The rooms sorted by name.
+    Alphabetical,
+
+    /// The rooms sorted by the `order` of the tag of the section, as set by
+    /// the user. This is `m.favourite` for [`RoomSection::Favourites`],
+    /// `m.lowpriority` for [`RoomSection::LowPriority`] and the tag with the
+    /// name of the section for [`RoomSection::Custom`].
+    ///
+    /// The rooms without an order come last, sorted by name.
+    Manual,
+}
+
+/// A section of the room list defined by the user, with a predicate deciding
+/// which rooms belong to it.
+#[derive(Clone)]
+pub struct CustomSection {
+    name: String,
+    predicate: Arc<dyn Fn(&matrix_sdk::Room) -> bool + Send + Sync>,
+    sort_order: SectionSortOrder,
+}
+
+impl CustomSection {
+    /// Create a custom section with the given name, that contains the rooms
+    /// matching the predicate.
+    ///
+    /// The rooms are sorted by activity by default.
+    pub fn new(
+        name: impl Into<String>,
+        predicate: impl Fn(&matrix_sdk::Room) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            predicate: Arc::new(predicate),
+            sort_order: SectionSortOrder::default(),
+        }
+    }
+
+    /// Set how the rooms of this section are sorted.
+    pub fn sort_order(mut self, sort_order: SectionSortOrder) -> Self {
+        self.sort_order = sort_order;
+        self
+    }
+
+    /// The name of this section.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl fmt::Debug for CustomSection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CustomSection")
+            .field("name", &self.name)
+            .field("sort_order", &self.sort_order)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The configuration of [`RoomSections`].
+#[derive(Clone, Debug, Default)]
+pub struct RoomSectionsConfig {
+    custom_sections: Vec<CustomSection>,
+    sort_orders: BTreeMap<RoomSection, SectionSortOrder>,
+}
+
+impl RoomSectionsConfig {
+    /// Create a configuration with only the default sections, all sorted by
+    /// activity.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a custom section.
+    ///
+    /// Custom sections take precedence over all the default sections except
+    /// [`RoomSection::Invites`], and over the custom sections added after
+    /// them.
+    pub fn custom_section(mut self, section: CustomSection) -> Self {
+        self.custom_sections.push(section);
+        self
+    }
+
+    /// Set how the rooms of a default section are sorted.
+    ///
+    /// The sort order of a custom section is set with
+    /// [`CustomSection::sort_order`].
+    pub fn sort_order(mut self, section: RoomSection, sort_order: SectionSortOrder) -> Self {
+        self.sort_orders.insert(section, sort_order);
+        self
+    }
+
+    /// Whether the rooms must be assigned to sections again after the info of
+    /// a room changed for the given reasons.
+    ///
+    /// The state and the direct flag of a room aren't tracked by the reasons,
+    /// they must be checked separately with [`UntrackedFacts`].
+    fn depends_on(&self, reasons: RoomInfoChangeReasons) -> bool {
+        // The predicates of the custom sections can look at anything.
+        if !self.custom_sections.is_empty() {
+            return true;
+        }
+
+        // The display name of a room can be computed from its members.
+        reasons.intersects(
+            RoomInfoChangeReasons::NAME
+                | RoomInfoChangeReasons::MEMBERS
+                | RoomInfoChangeReasons::LATEST_EVENT,
+        )
+    }
+
+    fn sort_order_of(&self, section: &RoomSection) -> SectionSortOrder {
+        if let RoomSection::Custom(name) = section {
+            return self
+                .custom_sections
+                .iter()
+                .find(|custom| custom.name == *name)
+                .map(|custom| custom.sort_order)
+                .unwrap_or_default();
+        }
+
+        self.sort_orders.get(section).copied().unwrap_or_default()
+    }
+}
+
+/// The names of the given tags, with their order.
+fn tag_orders(tags: Tags) -> BTreeMap<String, Option<f64>> {
+    tags.into_iter().map(|(name, info)| (name.as_ref().to_owned(), info.order)).collect()
+}
+
+/// The facts about a room that aren't tracked by [`RoomInfoChangeReasons`].
+#[derive(Clone, Debug, PartialEq)]
+struct UntrackedFacts {
+    state: RoomState,
+    is_direct: bool,
+    /// The tags of the room, with their order.
+    tags: BTreeMap<String, Option<f64>>,
+}
+
+impl UntrackedFacts {
+    async fn collect(room: &matrix_sdk::Room) -> Self {
+        let tags = match room.tags().await {
+            Ok(tags) => tag_orders(tags.unwrap_or_default()),
+            Err(e) => {
+                warn!(room_id = ?room.room_id(), "Couldn't load the tags of a room: {e}");
+                BTreeMap::new()
+            }
+        };
+
+        Self { state: room.state(), is_direct: room.is_direct().await.unwrap_or(false), tags }
+    }
+
+    /// Get these facts again after the info of the room changed, without
+    /// reading the store when possible.
+    ///
+    /// The tags are kept, they are updated from the `m.tag` events instead.
+    async fn refresh(&self, room: &matrix_sdk::Room) -> Self {
+        let state = room.state();
+
+        // The direct flag of an invite comes from the invite event, which is
+        // read from the store, and doesn't change until the room is joined or
+        // left. The direct flag of the other rooms is in memory.
+        let is_direct = if state == RoomState::Invited && self.state == RoomState::Invited {
+            self.is_direct
+        } else {
+            room.is_direct().await.unwrap_or(false)
+        };
+
+        Self { state, is_direct, tags: self.tags.clone() }
+    }
+}
+
+/// What is needed to assign a room to a section and sort it.
+#[derive(Clone, Debug)]
+struct RoomFacts {
+    room_id: OwnedRoomId,
+    state: RoomState,
+    is_direct: bool,
+    /// The tags of the room, with their order.
+    tags: BTreeMap<String, Option<f64>>,
+    /// The index of the first custom section whose predicate matches the
+    /// room.
+    custom_section: Option<usize>,
+    name: String,
+    latest_activity: Option<MilliSecondsSinceUnixEpoch>,
+}
+
+impl RoomFacts {
+    async fn collect(room: &matrix_sdk::Room, config: &RoomSectionsConfig) -> Self {
+        Self::with_untracked(room, config, UntrackedFacts::collect(room).await).await
+    }
+
+    async fn with_untracked(
+        room: &matrix_sdk::Room,
+        config: &RoomSectionsConfig,
+        untracked: UntrackedFacts,
+    ) -> Self {
+        let UntrackedFacts { state, is_direct, tags } = untracked;
+
+        let name = match room.name() {
+            Some(name) => name,
+            None => room.display_name().await.map(|name| name.to_string()).unwrap_or_default(),
+        };
+
+        let latest_activity = room.latest_event().and_then(|event| {
+            event.event().event.get_field::<MilliSecondsSinceUnixEpoch>("origin_server_ts").ok()?
+        });
+
+        Self {
+            room_id: room.room_id().to_owned(),
+            state,
+            is_direct,
+            tags,
+            custom_section: config
+                .custom_sections
+                .iter()
+                .position(|section| (section.predicate)(room)),
+            name,
+            latest_activity,
+        }
+    }
+
+    /// The facts of this room that aren't tracked by
+    /// [`RoomInfoChangeReasons`].
+    fn untracked(&self) -> UntrackedFacts {
+        UntrackedFacts { state: self.state, is_direct: self.is_direct, tags: self.tags.clone() }
+    }
+
+    /// The section of this room, `None` if it doesn't belong to any.
+    fn section(&self, config: &RoomSectionsConfig) -> Option<RoomSection> {
+        match self.state {
+            RoomState::Left => return None,
+            RoomState::Invited => return Some(RoomSection::Invites),
+            RoomState::Joined => {}
+        }
+
+        if let Some(index) = self.custom_section {
+            return Some(RoomSection::Custom(config.custom_sections[index].name.clone()));
+        }
+
+        if self.tags.contains_key(TagName::Favorite.as_ref()) {
+            Some(RoomSection::Favourites)
+        } else if self.tags.contains_key(TagName::LowPriority.as_ref()) {
+            Some(RoomSection::LowPriority)
+        } else if self.is_direct {
+            Some(RoomSection::People)
+        } else {
+            Some(RoomSection::Rooms)
+        }
+    }
+}
+
+/// Compare two rooms of the same section.
+///
+/// Ties are broken by name and then by room ID, so the order is stable.
+fn compare(
+    section: &RoomSection,
+    sort_order: SectionSortOrder,
+    a: &RoomFacts,
+    b: &RoomFacts,
+) -> Ordering {
+    let by_name = || {
+        a.name.to_lowercase().cmp(&b.name.to_lowercase()).then_with(|| a.room_id.cmp(&b.room_id))
+    };
+
+    match sort_order {
+        SectionSortOrder::Activity => b.latest_activity.cmp(&a.latest_activity).then_with(by_name),
+        SectionSortOrder::Alphabetical => by_name(),
+        SectionSortOrder::Manual => {
+            let order = |facts: &RoomFacts| {
+                section.manual_order_tag().and_then(|tag| facts.tags.get(tag).copied().flatten())
+            };
+
+            match (order(a), order(b)) {
+                (Some(a), Some(b)) => a.total_cmp(&b).then_with(by_name),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => by_name(),
+            }
+        }
+    }
+}
+
+/// Assign the rooms to their sections, and sort every section.
+fn assign_sections<'a>(
+    rooms: impl IntoIterator<Item = &'a RoomFacts>,
+    config: &RoomSectionsConfig,
+) -> BTreeMap<RoomSection, Vec<OwnedRoomId>> {
+    let mut sections = BTreeMap::<RoomSection, Vec<&RoomFacts>>::new();
+
+    for room in rooms {
+        if let Some(section) = room.section(config) {
+            sections.entry(section).or_default().push(room);
+        }
+    }
+
+    sections
+        .into_iter()
+        .map(|(section, mut rooms)| {
+            let sort_order = config.sort_order_of(&section);
+            rooms.sort_by(|a, b| compare(&section, sort_order, a, b));
+            (section, rooms.into_iter().map(|room| room.room_id.clone()).collect())
+        })
+        .collect()
+}
+
+/// Update `items` to be equal to `target`, with the fewest changes that keep
+/// the items that didn't move in place.
+///
+/// The items that stay in place are the longest subsequence of `items` that
+/// is in the same order in `target`, the other ones are removed and inserted
+/// at their new position.
+fn update_vector(items: &mut ObservableVector<OwnedRoomId>, target: &[OwnedRoomId]) {
+    let target_positions: HashMap<&OwnedRoomId, usize> =
+        target.iter().enumerate().map(|(position, room_id)| (room_id, position)).collect();
+    let positions: Vec<_> = items.iter().map(|item| target_positions.get(item).copied()).collect();
+    let kept = longest_increasing_subsequence(&positions);
+
+    // Remove the rooms that left the section or that moved.
+    for index in (0..items.len()).rev() {
+        if !kept[index] {
+            items.remove(index);
+        }
+    }
+
+    // The remaining rooms are in the order of `target`, insert the other ones
+    // around them.
+    for (index, room_id) in target.iter().enumerate() {
+        if items.get(index) != Some(room_id) {
+            items.insert(index, room_id.clone());
+        }
+    }
+}
+
+/// Which of the values belong to a longest strictly increasing subsequence of
+/// the values that are set.
+fn longest_increasing_subsequence(values: &[Option<usize>]) -> Vec<bool> {
+    // The index of the last value of the best subsequence found for every
+    // length.
+    let mut tails: Vec<usize> = Vec::new();
+    // The index of the value preceding every value in its best subsequence.
+    let mut predecessors = vec![None; values.len()];
+
+    for (index, value) in values.iter().enumerate() {
+        let Some(value) = *value else {
+            continue;
+        };
+
+        let length = tails.partition_point(|&tail| values[tail] < Some(value));
+        predecessors[index] = length.checked_sub(1).map(|previous| tails[previous]);
+
+        if length == tails.len() {
+            tails.push(index);
+        } else {
+            tails[length] = index;
+        }
+    }
+
+    let mut in_subsequence = vec![false; values.len()];
+    let mut current = tails.last().copied();
+
+    while let Some(index) = current {
+        in_subsequence[index] = true;
+        current = predecessors[index];
+    }
+
+    in_subsequence
+}
+
+/// The engine splitting a room list into sections, like the ones found in
+/// most Matrix clients: invites, favourites, people, rooms and low priority,
+/// plus custom sections defined by the user.
+///
+/// Every room belongs to at most one section, by order of precedence:
+///
+/// 1. [`RoomSection::Invites`] for the rooms the user is invited to,
+/// 2. the [`CustomSection`]s, in the order they were added,
+/// 3. [`RoomSection::Favourites`] for the rooms tagged with `m.favourite`,
+/// 4. [`RoomSection::LowPriority`] for the rooms tagged with `m.lowpriority`,
+/// 5. [`RoomSection::People`] for the direct messages,
+/// 6. [`RoomSection::Rooms`] for the others.
+///
+/// Left rooms don't belong to any section.
+///
+/// The sections are updated whenever the room list or the info of a room
+/// changes. Get the rooms of a section and their updates with
+/// [`RoomSections::entries`].
+#[derive(Debug)]
+pub struct RoomSections {
+    inner: Arc<RoomSectionsInner>,
+    task: JoinHandle<()>,
+    /// The handler forwarding the `m.tag` events to the task.
+    _tags_handler_guard: EventHandlerDropGuard,
+}
+
+#[derive(Debug)]
+struct RoomSectionsInner {
+    client: Client,
+    sliding_sync_list: SlidingSyncList,
+    config: RoomSectionsConfig,
+    sections: StdMutex<BTreeMap<RoomSection, ObservableVector<OwnedRoomId>>>,
+    /// The facts of the rooms of the list, as of the last update.
+    ///
+    /// The lock is held for the whole update, so the updates of the
+    /// background task and of [`RoomSections::refresh`] don't interleave.
+    facts: AsyncMutex<BTreeMap<OwnedRoomId, RoomFacts>>,
+}
+
+impl Drop for RoomSections {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl RoomSections {
+    pub(super) async fn new(
+        client: Client,
+        sliding_sync_list: SlidingSyncList,
+        config: RoomSectionsConfig,
+    ) -> Self {
+        let inner = Arc::new(RoomSectionsInner {
+            client,
+            sliding_sync_list,
+            config,
+            sections: Default::default(),
+            facts: Default::default(),
+        });
+
+        // Subscribe before the first computation, to not miss any update.
+        let (_, entries_stream) = inner.sliding_sync_list.room_list_stream();
+        let mut room_info_updates = inner.client.subscribe_to_room_info_updates();
+
+        // The tags aren't in the room info, they are taken from the `m.tag`
+        // events rather than read from the store on every room info update.
+        let (tags_sender, mut tags_receiver) = mpsc::unbounded_channel();
+        let tags_handler =
+            inner.client.add_event_handler(move |event: TagEvent, room: matrix_sdk::Room| {
+                let tags_sender = tags_sender.clone();
+                async move {
+                    let _ = tags_sender.send((room.room_id().to_owned(), event.content.tags));
+                }
+            });
+        let tags_handler_guard = inner.client.event_handler_drop_guard(tags_handler);
+
+        inner.update_all().await;
+
+        let task = spawn({
+            let inner = inner.clone();
+
+            async move {
+                pin_mut!(entries_stream);
+
+                loop {
+                    select! {
+                        diffs = entries_stream.next() => {
+                            if diffs.is_none() {
+                                break;
+                            }
+
+                            inner.update_list().await;
+                        }
+                        update = room_info_updates.recv() => {
+                            match update {
+                                Ok(update) => inner.update_room(&update).await,
+                                Err(RecvError::Lagged(_)) => inner.update_all().await,
+                                Err(RecvError::Closed) => break,
+                            }
+                        }
+                        Some((room_id, tags)) = tags_receiver.recv() => {
+                            inner.update_tags(&room_id, tag_orders(tags)).await;
+                        }
+                    }
+                }
+            }
+        });
+
+        Self { inner, task, _tags_handler_guard: tags_handler_guard }
+    }
+
+    /// Get the rooms of a section, in addition to a [`Stream`] of their
+    /// updates.
+    pub fn entries(
+        &self,
+        section: RoomSection,
+    ) -> (Vector<OwnedRoomId>, impl Stream<Item = Vec<VectorDiff<OwnedRoomId>>>) {
+        let mut sections = self.inner.sections.lock().unwrap();
+        let items = sections.entry(section).or_default();
+
+        ((*items).clone(), items.subscribe().into_batched_stream())
+    }
+
+    /// Assign the rooms to sections again.
+    ///
+    /// The sections are updated automatically when the room list or the info
+    /// of a room changes; this is only useful when something the predicate of
+    /// a [`CustomSection`] depends on changed.
+    pub async fn refresh(&self) {
+        self.inner.update_all().await;
+    }
+}
+
+impl RoomSectionsInner {
+    /// The IDs of the rooms of the list.
+    fn room_ids(&self) -> Vec<OwnedRoomId> {
+        self.sliding_sync_list
+            .room_list::<RoomListEntry>()
+            .iter()
+            .filter_map(|entry| entry.as_room_id().map(ToOwned::to_owned))
+            .collect()
+    }
+
+    /// Collect the facts of all the rooms of the list again, and assign them
+    /// to sections.
+    async fn update_all(&self) {
+        let mut facts = self.facts.lock().await;
+        let mut updated = BTreeMap::new();
+
+        for room_id in self.room_ids() {
+            if let Some(room) = self.client.get_room(&room_id) {
+                updated.insert(room_id, RoomFacts::collect(&room, &self.config).await);
+            }
+        }
+
+        *facts = updated;
+        self.assign(&facts);
+    }
+
+    /// Update the sections after the list changed, collecting the facts of the
+    /// rooms that joined it only.
+    async fn update_list(&self) {
+        let mut facts = self.facts.lock().await;
+        let mut updated = BTreeMap::new();
+
+        for room_id in self.room_ids() {
+            let room_facts = match facts.remove(&room_id) {
+                Some(room_facts) => room_facts,
+                None => {
+                    let Some(room) = self.client.get_room(&room_id) else {
+                        continue;
+                    };
+                    RoomFacts::collect(&room, &self.config).await
+                }
+            };
+
+            updated.insert(room_id, room_facts);
+        }
+
+        *facts = updated;
+        self.assign(&facts);
+    }
+
+    /// Update the sections after the info of a room changed, if it can change
+    /// the section of the room or its position.
+    async fn update_room(&self, update: &RoomInfoUpdate) {
+        let mut facts = self.facts.lock().await;
+
+        // The rooms that aren't in the list don't belong to any section.
+        let Some(previous) = facts.get(&update.room_id).map(RoomFacts::untracked) else {
+            return;
+        };
+        let Some(room) = self.client.get_room(&update.room_id) else {
+            facts.remove(&update.room_id);
+            self.assign(&facts);
+            return;
+        };
+
+        let untracked = previous.refresh(&room).await;
+        if !self.config.depends_on(update.reasons) && untracked == previous {
+            return;
+        }
+
+        let room_facts = RoomFacts::with_untracked(&room, &self.config, untracked).await;
+        facts.insert(update.room_id.clone(), room_facts);
+        self.assign(&facts);
+    }
+
+    /// Update the sections after the tags of a room changed.
+    async fn update_tags(&self, room_id: &RoomId, tags: BTreeMap<String, Option<f64>>) {
+        let mut facts = self.facts.lock().await;
+
+        let Some(room_facts) = facts.get_mut(room_id) else {
+            return;
+        };
+        if room_facts.tags == tags {
+            return;
+        }
+
+        room_facts.tags = tags;
+        self.assign(&facts);
+    }
+
+    /// Assign the rooms to sections, and update the sections.
+    fn assign(&self, facts: &BTreeMap<OwnedRoomId, RoomFacts>) {
+        let mut assigned = assign_sections(facts.values(), &self.config);
+        let mut sections = self.sections.lock().unwrap();
+
+        // Empty the sections that don't have rooms anymore.
+        for section in sections.keys() {
+            assigned.entry(section.clone()).or_default();
+        }
+
+        for (section, room_ids) in assigned {
+            update_vector(sections.entry(section).or_default(), &room_ids);
+        }
+
+        debug!(num_sections = sections.len(), "Updated the room list sections");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use eyeball_im::{ObservableVector, VectorDiff};
+    use matrix_sdk_base::{RoomInfoChangeReasons, RoomState};
+    use ruma::{owned_room_id, MilliSecondsSinceUnixEpoch, OwnedRoomId, UInt};
+    use stream_assert::{assert_next_eq, assert_pending};
+
+    use super::{
+        assign_sections, longest_increasing_subsequence, update_vector, CustomSection, RoomFacts,
+        RoomSection, RoomSectionsConfig, SectionSortOrder,
+    };
+
+    fn room(room_id: OwnedRoomId, name: &str) -> RoomFacts {
+        RoomFacts {
+            room_id,
+            state: RoomState::Joined,
+            is_direct: false,
+            tags: BTreeMap::new(),
+            custom_section: None,
+            name: name.to_owned(),
+            latest_activity: None,
+        }
+    }
+
+    fn ts(value: u32) -> Option<MilliSecondsSinceUnixEpoch> {
+        Some(MilliSecondsSinceUnixEpoch(UInt::from(value)))
+    }
+
+    #[test]
+    fn test_section_assignment() {
+        let config =
+            RoomSectionsConfig::new().custom_section(CustomSection::new("Work", |_| false));
+
+        let mut invite = room(owned_room_id!("!invite:bar.org"), "Invite");
+        invite.state = RoomState::Invited;
+        invite.tags.insert("m.favourite".to_owned(), None);
+
+        let mut left = room(owned_room_id!("!left:bar.org"), "Left");
+        left.state = RoomState::Left;
+
+        let mut work = room(owned_room_id!("!work:bar.org"), "Work");
+        work.custom_section = Some(0);
+        work.tags.insert("m.favourite".to_owned(), None);
+
+        let mut favourite = room(owned_room_id!("!favourite:bar.org"), "Favourite");
+        favourite.tags.insert("m.favourite".to_owned(), None);
+        favourite.is_direct = true;
+
+        let mut low_priority = room(owned_room_id!("!low:bar.org"), "Low");
+        low_priority.tags.insert("m.lowpriority".to_owned(), None);
+        low_priority.is_direct = true;
+
+        let mut dm = room(owned_room_id!("!dm:bar.org"), "Alice");
+        dm.is_direct = true;
+
+        let other = room(owned_room_id!("!other:bar.org"), "Other");
+
+        let sections =
+            assign_sections(&[invite, left, work, favourite, low_priority, dm, other], &config);
+
+        assert_eq!(sections.len(), 6);
+        assert_eq!(sections[&RoomSection::Invites], [owned_room_id!("!invite:bar.org")]);
+        assert_eq!(
+            sections[&RoomSection::Custom("Work".to_owned())],
+            [owned_room_id!("!work:bar.org")]
+        );
+        assert_eq!(sections[&RoomSection::Favourites], [owned_room_id!("!favourite:bar.org")]);
+        assert_eq!(sections[&RoomSection::LowPriority], [owned_room_id!("!low:bar.org")]);
+        assert_eq!(sections[&RoomSection::People], [owned_room_id!("!dm:bar.org")]);
+        assert_eq!(sections[&RoomSection::Rooms], [owned_room_id!("!other:bar.org")]);
+    }
+
+    #[test]
+    fn test_sort_orders() {
+        let mut a = room(owned_room_id!("!a:bar.org"), "alpha");
+        a.latest_activity = ts(10);
+        a.tags.insert("m.favourite".to_owned(), Some(0.5));
+
+        let mut b = room(owned_room_id!("!b:bar.org"), "Bravo");
+        b.latest_activity = ts(30);
+        b.tags.insert("m.favourite".to_owned(), None);
+
+        let mut c = room(owned_room_id!("!c:bar.org"), "charlie");
+        c.latest_activity = ts(20);
+        c.tags.insert("m.favourite".to_owned(), Some(0.1));
+
+        let rooms = vec![a, b, c];
+        let sorted = |sort_order| {
+            let config = RoomSectionsConfig::new().sort_order(RoomSection::Favourites, sort_order);
+            assign_sections(&rooms, &config).remove(&RoomSection::Favourites).unwrap()
+        };
+
+        assert_eq!(
+            sorted(SectionSortOrder::Activity),
+            [
+                owned_room_id!("!b:bar.org"),
+                owned_room_id!("!c:bar.org"),
+                owned_room_id!("!a:bar.org")
+            ]
+        );
+        assert_eq!(
+            sorted(SectionSortOrder::Alphabetical),
+            [
+                owned_room_id!("!a:bar.org"),
+                owned_room_id!("!b:bar.org"),
+                owned_room_id!("!c:bar.org")
+            ]
+        );
+        assert_eq!(
+            sorted(SectionSortOrder::Manual),
+            [
+                owned_room_id!("!c:bar.org"),
+                owned_room_id!("!a:bar.org"),
+                owned_room_id!("!b:bar.org")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_update_vector() {
+        let a = owned_room_id!("!a:bar.org");
+        let b = owned_room_id!("!b:bar.org");
+        let c = owned_room_id!("!c:bar.org");
+        let d = owned_room_id!("!d:bar.org");
+
+        let mut items = ObservableVector::new();
+        update_vector(&mut items, &[a.clone(), b.clone(), c.clone()]);
+        assert_eq!(items.iter().cloned().collect::<Vec<_>>(), [a.clone(), b.clone(), c.clone()]);
+
+        update_vector(&mut items, &[c.clone(), a.clone(), d.clone()]);
+        assert_eq!(items.iter().cloned().collect::<Vec<_>>(), [c.clone(), a.clone(), d.clone()]);
+
+        update_vector(&mut items, &[]);
+        assert!(items.is_empty());
+    }
+
+    #[test]
+    fn test_update_vector_keeps_the_rooms_that_did_not_move() {
+        let a = owned_room_id!("!a:bar.org");
+        let b = owned_room_id!("!b:bar.org");
+        let c = owned_room_id!("!c:bar.org");
+        let d = owned_room_id!("!d:bar.org");
+        let e = owned_room_id!("!e:bar.org");
+
+        let mut items = ObservableVector::new();
+        update_vector(&mut items, &[a.clone(), b.clone(), c.clone(), d.clone()]);
+
+        let mut subscriber = items.subscribe().into_stream();
+
+        // Only the room that moved is removed and inserted again.
+        update_vector(&mut items, &[b.clone(), c.clone(), d.clone(), a.clone()]);
+        assert_next_eq!(subscriber, VectorDiff::Remove { index: 0 });
+        assert_next_eq!(subscriber, VectorDiff::Insert { index: 3, value: a.clone() });
+        assert_pending!(subscriber);
+
+        // A new room is inserted, and a room that left is removed.
+        update_vector(&mut items, &[b.clone(), e.clone(), d.clone(), a.clone()]);
+        assert_next_eq!(subscriber, VectorDiff::Remove { index: 1 });
+        assert_next_eq!(subscriber, VectorDiff::Insert { index: 1, value: e.clone() });
+        assert_pending!(subscriber);
+
+        assert_eq!(items.iter().cloned().collect::<Vec<_>>(), [b, e, d, a]);
+    }
+
+    #[test]
+    fn test_longest_increasing_subsequence() {
+        assert_eq!(longest_increasing_subsequence(&[]), Vec::<bool>::new());
+        assert_eq!(
+            longest_increasing_subsequence(&[Some(3), Some(0), None, Some(1), Some(2)]),
+            [false, true, false, true, true]
+        );
+        assert_eq!(
+            longest_increasing_subsequence(&[Some(2), Some(1), Some(0)]),
+            [false, false, true]
+        );
+    }
+
+    #[test]
+    fn test_updates_the_sections_depend_on() {
+        let config = RoomSectionsConfig::new();
+
+        // Unread counts don't change the sections of the default config.
+        assert!(!config.depends_on(RoomInfoChangeReasons::UNREAD_COUNTS));
+        assert!(!config.depends_on(RoomInfoChangeReasons::ENCRYPTION));
+        assert!(config.depends_on(RoomInfoChangeReasons::NAME));
+        assert!(config.depends_on(
+            RoomInfoChangeReasons::UNREAD_COUNTS | RoomInfoChangeReasons::LATEST_EVENT
+        ));
+        // The untracked changes are checked separately.
+        assert!(!config.depends_on(RoomInfoChangeReasons::empty()));
+
+        // The custom sections can depend on anything.
+        let config =
+            RoomSectionsConfig::new().custom_section(CustomSection::new("Work", |_| false));
+        assert!(config.depends_on(RoomInfoChangeReasons::UNREAD_COUNTS));
+    }
+}
@@ -103,6 +103,11 @@ struct AddVisibleRooms;
 /// Default timeline for the `VISIBLE_ROOMS_LIST_NAME` list.
 pub const VISIBLE_ROOMS_DEFAULT_TIMELINE_LIMIT: Bound = 20;
 
+/// Timeline limit for the `VISIBLE_ROOMS_LIST_NAME` list with the
+/// [`BandwidthProfile::LowData`](crate::sync_service::BandwidthProfile::LowData)
+/// profile.
+pub const VISIBLE_ROOMS_LOW_DATA_TIMELINE_LIMIT: Bound = 5;
+
 /// Default range for the `VISIBLE_ROOMS_LIST_NAME` list.
 pub const VISIBLE_ROOMS_DEFAULT_RANGE: Range = 0..=19;
 
@@ -22,13 +22,18 @@
 //! [`state`](SyncService::state) that the user
 //! MUST observe. Whenever an error/termination is observed, the user MUST call
 //! [`SyncService::start()`] again to restart the room list sync.
+//!
+//! The amount of data used by the syncs can be reduced with a
+//! [`BandwidthProfile`], chosen by the user or enabled automatically when the
+//! platform signals a metered connection with
+//! [`SyncService::set_connection_metered()`].
 
 use std::sync::{Arc, Mutex};
 
 use eyeball::{SharedObservable, Subscriber};
 use futures_core::Future;
 use futures_util::{pin_mut, StreamExt as _};
-use matrix_sdk::Client;
+use matrix_sdk::{features::ClientFeature, Client};
 use thiserror::Error;
 use tokio::{
     sync::{
@@ -67,7 +72,50 @@ pub enum State {
     Error,
 }
 
+/// How much data the syncs use.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BandwidthProfile {
+    /// The default profile.
+    #[default]
+    Normal,
+
+    /// A profile using as little data as possible, for metered connections.
+    ///
+    /// The timelines of the visible rooms are shorter, the latest event of the
+    /// other rooms and the avatars of the rooms aren't synced, the sync
+    /// requests are long-polled for longer, the media prefetching is deferred,
+    /// see [`Media::set_prefetch_deferred()`], and the URL previews are
+    /// suspended, see [`ClientFeatures::set_suspended()`].
+    ///
+    /// [`Media::set_prefetch_deferred()`]: matrix_sdk::media::Media::set_prefetch_deferred
+    /// [`ClientFeatures::set_suspended()`]: matrix_sdk::features::ClientFeatures::set_suspended
+    LowData,
+}
+
+/// The inputs deciding the [`BandwidthProfile`] in use.
+#[derive(Debug, Default)]
+struct BandwidthSettings {
+    /// The profile chosen by the user, if any.
+    preferred: Option<BandwidthProfile>,
+
+    /// Whether the platform signalled a metered connection.
+    metered: bool,
+}
+
+impl BandwidthSettings {
+    fn profile(&self) -> BandwidthProfile {
+        match self.preferred {
+            Some(profile) => profile,
+            None if self.metered => BandwidthProfile::LowData,
+            None => BandwidthProfile::Normal,
+        }
+    }
+}
+
 pub struct SyncService {
+    /// SDK client.
+    client: Client,
+
     /// Room list service used to synchronize the rooms state.
     room_list_service: Arc<RoomListService>,
 
@@ -109,6 +157,15 @@ pub struct SyncService {
     ///
     /// This is set at the same time as all the tasks in [`Self::start()`].
     scheduler_sender: Mutex<Option<Sender<TerminationReport>>>,
+
+    /// The inputs deciding the bandwidth profile.
+    ///
+    /// The lock is held while the profile is applied, so concurrent changes
+    /// are applied in order.
+    bandwidth_settings: AsyncMutex<BandwidthSettings>,
+
+    /// The bandwidth profile in use.
+    bandwidth_profile: SharedObservable<BandwidthProfile>,
 }
 
 impl SyncService {
@@ -128,6 +185,56 @@ impl SyncService {
         self.state.subscribe()
     }
 
+    /// Returns the bandwidth profile in use.
+    pub fn bandwidth_profile(&self) -> Subscriber<BandwidthProfile> {
+        self.bandwidth_profile.subscribe()
+    }
+
+    /// Choose the bandwidth profile to use.
+    ///
+    /// With `None`, the profile follows the connection:
+    /// [`BandwidthProfile::LowData`] is used while the connection is metered,
+    /// see [`Self::set_connection_metered()`].
+    ///
+    /// The profile can be changed while the service is running, it is applied
+    /// from the next sync requests.
+    pub async fn set_bandwidth_profile(
+        &self,
+        profile: Option<BandwidthProfile>,
+    ) -> Result<(), Error> {
+        let mut settings = self.bandwidth_settings.lock().await;
+        settings.preferred = profile;
+        self.apply_bandwidth_profile(settings.profile()).await
+    }
+
+    /// Signal whether the connection of the device is metered.
+    ///
+    /// This is meant to be called by the platform-specific code observing the
+    /// connectivity. Unless a profile was chosen with
+    /// [`Self::set_bandwidth_profile()`], [`BandwidthProfile::LowData`] is
+    /// used while the connection is metered.
+    pub async fn set_connection_metered(&self, metered: bool) -> Result<(), Error> {
+        let mut settings = self.bandwidth_settings.lock().await;
+        settings.metered = metered;
+        self.apply_bandwidth_profile(settings.profile()).await
+    }
+
+    async fn apply_bandwidth_profile(&self, profile: BandwidthProfile) -> Result<(), Error> {
+        if self.bandwidth_profile.get() == profile {
+            return Ok(());
+        }
+
+        info!(?profile, "Switching the bandwidth profile");
+
+        self.room_list_service.set_bandwidth_profile(profile).await?;
+        let low_data = profile == BandwidthProfile::LowData;
+        self.client.media().set_prefetch_deferred(low_data);
+        self.client.features().set_suspended(ClientFeature::UrlPreviews, low_data);
+        self.bandwidth_profile.set(profile);
+
+        Ok(())
+    }
+
     /// The role of the scheduler task is to wait for a termination message
     /// (`TerminationReport`), sent either because we wanted to stop both
     /// syncs, or because one of the syncs failed (in which case we'll stop
@@ -480,7 +587,7 @@ impl SyncServiceBuilder {
         let encryption_sync = Arc::new(
             EncryptionSyncService::new(
                 self.identifier,
-                self.client,
+                self.client.clone(),
                 None,
                 WithLocking::from(self.with_cross_process_lock),
             )
@@ -488,6 +595,7 @@ impl SyncServiceBuilder {
         );
 
         Ok(SyncService {
+            client: self.client,
             room_list_service: Arc::new(room_list),
             encryption_sync_service: encryption_sync,
             encryption_sync_task: Arc::new(Mutex::new(None)),
@@ -497,6 +605,8 @@ impl SyncServiceBuilder {
             state: SharedObservable::new(State::Idle),
             modifying_state: AsyncMutex::new(()),
             encryption_sync_permit,
+            bandwidth_settings: AsyncMutex::new(BandwidthSettings::default()),
+            bandwidth_profile: SharedObservable::new(BandwidthProfile::Normal),
         })
     }
 }
@@ -18,7 +18,8 @@ use eyeball::SharedObservable;
 use futures_util::{pin_mut, StreamExt};
 use imbl::Vector;
 use matrix_sdk::{
-    deserialized_responses::SyncTimelineEvent, executor::spawn, sync::RoomUpdate, Room,
+    deserialized_responses::SyncTimelineEvent,
+    encryption::trust_recomputation::TrustRecomputationState, executor::spawn, Room,
 };
 use ruma::{
     events::{receipt::ReceiptType, AnySyncTimelineEvent},
@@ -32,7 +33,7 @@ use super::to_device::{handle_forwarded_room_key_event, handle_room_key_event};
 use super::{
     inner::{TimelineInner, TimelineInnerSettings},
     queue::send_queued_messages,
-    BackPaginationStatus, Timeline, TimelineDropHandle,
+    BackPaginationStatus, Timeline, TimelineDropHandle, UnableToDecryptHook,
 };
 
 /// Builder that allows creating and configuring various parts of a
@@ -115,6 +116,26 @@ impl TimelineBuilder {
         self
     }
 
+    /// Group runs of consecutive membership and profile changes, like "3
+    /// people joined", in [`Timeline::grouped_items()`] and
+    /// [`Timeline::subscribe_grouped()`].
+    ///
+    /// Only runs of at least `min_group_size` changes are grouped, and this
+    /// is always at least 2. The items of the timeline are not affected.
+    ///
+    /// Disabled by default.
+    pub fn group_membership_changes(mut self, min_group_size: usize) -> Self {
+        self.settings.min_membership_group_size = Some(min_group_size.max(2));
+        self
+    }
+
+    /// Set a hook that gets notified when an event that couldn't be decrypted
+    /// is decrypted later, when its room key arrives.
+    pub fn with_unable_to_decrypt_hook(mut self, hook: Arc<dyn UnableToDecryptHook>) -> Self {
+        self.settings.utd_hook = Some(hook);
+        self
+    }
+
     /// Create a [`Timeline`] with the options set on this builder.
     #[tracing::instrument(
         skip(self),
@@ -169,19 +190,14 @@ impl TimelineBuilder {
                         }
                     };
 
-                    trace!("Handling a room update");
+                    let Some(update) = inner.defer_room_update_if_focused(update) else {
+                        trace!("Deferring a room update while the timeline is focused");
+                        sync_response_notify.notify_waiters();
+                        continue;
+                    };
 
-                    match update {
-                        RoomUpdate::Left { updates, .. } => {
-                            inner.handle_sync_timeline(updates.timeline).await;
-                        }
-                        RoomUpdate::Joined { updates, .. } => {
-                            inner.handle_joined_room_update(updates).await;
-                        }
-                        RoomUpdate::Invited { .. } => {
-                            warn!("Room is in invited state, can't build or update its timeline");
-                        }
-                    }
+                    trace!("Handling a room update");
+                    inner.handle_room_update(update).await;
 
                     sync_response_notify.notify_waiters();
                 }
@@ -204,6 +220,56 @@ impl TimelineBuilder {
             .instrument(span)
         });
 
+        // Redactions sent by this client, possibly from another timeline, are
+        // applied to the room before they come back via sync. The ones received
+        // via sync are handled with the rest of the sync response.
+        let mut redactions_rx = room.subscribe_to_redactions();
+        let redactions_join_handle = spawn({
+            let inner = inner.clone();
+
+            let span =
+                info_span!(parent: Span::none(), "redactions_handler", room_id = ?room.room_id());
+            span.follows_from(Span::current());
+
+            async move {
+                loop {
+                    match redactions_rx.recv().await {
+                        Ok(applied) if applied.is_local => {
+                            inner.handle_applied_redaction(applied).await;
+                        }
+                        Ok(_) => {}
+                        Err(broadcast::error::RecvError::Closed) => break,
+                        Err(broadcast::error::RecvError::Lagged(num_skipped)) => {
+                            // The missed redactions will be applied when they are
+                            // received via sync.
+                            warn!(num_skipped, "Lagged behind local redactions");
+                        }
+                    }
+                }
+            }
+            .instrument(span)
+        });
+
+        // The events older than the retention policy of the room allows are
+        // purged from the timeline too.
+        let mut purges = room.subscribe_to_purges();
+        let purges_join_handle = spawn({
+            let inner = inner.clone();
+
+            let span =
+                info_span!(parent: Span::none(), "purges_handler", room_id = ?room.room_id());
+            span.follows_from(Span::current());
+
+            async move {
+                while let Some(cutoff) = purges.next().await {
+                    if let Some(cutoff) = cutoff {
+                        inner.remove_events_before(cutoff).await;
+                    }
+                }
+            }
+            .instrument(span)
+        });
+
         // Not using room.add_event_handler here because RoomKey events are
         // to-device events that are not received in the context of a room.
 
@@ -252,6 +318,21 @@ impl TimelineBuilder {
             })
         };
 
+        // Once the trust of the devices was recomputed, the shields of the
+        // events might have changed.
+        let trust_recomputation_join_handle = {
+            let inner = inner.clone();
+            let mut state = client.encryption().trust_recomputation_state();
+
+            spawn(async move {
+                while let Some(state) = state.next().await {
+                    if matches!(state, TrustRecomputationState::Done { .. }) {
+                        inner.refresh_verification_states().await;
+                    }
+                }
+            })
+        };
+
         let (msg_sender, msg_receiver) = mpsc::channel(1);
         info!("Starting message-sending loop");
         spawn(send_queued_messages(inner.clone(), room.clone(), msg_receiver));
@@ -268,6 +349,9 @@ impl TimelineBuilder {
                 room_update_join_handle,
                 ignore_user_list_update_join_handle,
                 room_key_from_backups_join_handle,
+                redactions_join_handle,
+                purges_join_handle,
+                trust_recomputation_join_handle,
             }),
         };
 
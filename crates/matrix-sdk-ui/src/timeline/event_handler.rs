@@ -32,7 +32,7 @@ use ruma::{
         receipt::Receipt,
         relation::Replacement,
         room::{
-            encrypted::RoomEncryptedEventContent,
+            encrypted::{EncryptedEventScheme, RoomEncryptedEventContent},
             member::RoomMemberEventContent,
             message::{self, RoomMessageEventContent, RoomMessageEventContentWithoutRelation},
             redaction::{RoomRedactionEventContent, SyncRoomRedactionEvent},
@@ -58,8 +58,9 @@ use super::{
     item::timeline_item,
     polls::PollState,
     util::{rfind_event_by_id, rfind_event_item, timestamp_to_date},
-    EventTimelineItem, InReplyToDetails, Message, OtherState, ReactionGroup, ReactionSenderData,
-    Sticker, TimelineDetails, TimelineItem, TimelineItemContent, VirtualTimelineItem,
+    CallNotify, EventTimelineItem, InReplyToDetails, Message, OtherState, ReactionGroup,
+    ReactionSenderData, Sticker, TimelineDetails, TimelineItem, TimelineItemContent, UtdCause,
+    VirtualTimelineItem,
 };
 use crate::{events::SyncTimelineEventWithoutContent, DEFAULT_SANITIZER_MODE};
 
@@ -84,7 +85,7 @@ pub(super) struct TimelineEventContext {
     pub(super) timestamp: MilliSecondsSinceUnixEpoch,
     pub(super) is_own_event: bool,
     pub(super) encryption_info: Option<EncryptionInfo>,
-    pub(super) read_receipts: IndexMap<OwnedUserId, Receipt>,
+    pub(super) read_receipts: IndexMap<(OwnedUserId, Option<OwnedEventId>), Receipt>,
     pub(super) is_highlighted: bool,
     pub(super) flow: Flow,
 }
@@ -308,6 +309,12 @@ impl<'a, 'o> TimelineEventHandler<'a, 'o> {
                 ) => self.handle_poll_start(c, should_add),
                 AnyMessageLikeEventContent::UnstablePollResponse(c) => self.handle_poll_response(c),
                 AnyMessageLikeEventContent::UnstablePollEnd(c) => self.handle_poll_end(c),
+                AnyMessageLikeEventContent::CallInvite(c) => {
+                    self.add(should_add, TimelineItemContent::CallInvite(c.into()));
+                }
+                _ if CallNotify::is_event_type(&content.event_type().to_string()) => {
+                    self.handle_call_notify(should_add);
+                }
                 // TODO
                 _ => {
                     debug!(
@@ -375,6 +382,22 @@ impl<'a, 'o> TimelineEventHandler<'a, 'o> {
         self.result
     }
 
+    /// Handle an `m.call.notify` event.
+    ///
+    /// Ruma doesn't know about this event type, so its content is read from
+    /// the raw event.
+    fn handle_call_notify(&mut self, should_add: bool) {
+        let Flow::Remote { raw_event, .. } = &self.ctx.flow else {
+            debug!("Ignoring local echo of a call notification");
+            return;
+        };
+
+        match CallNotify::from_raw(raw_event) {
+            Some(notify) => self.add(should_add, TimelineItemContent::CallNotify(notify)),
+            None => debug!("Ignoring call notification with invalid content"),
+        }
+    }
+
     #[instrument(skip_all, fields(replacement_event_id = ?replacement.event_id))]
     fn handle_room_message_edit(
         &mut self,
@@ -602,7 +625,21 @@ impl<'a, 'o> TimelineEventHandler<'a, 'o> {
     #[instrument(skip_all)]
     fn handle_room_encrypted(&mut self, c: RoomEncryptedEventContent) {
         // TODO: Handle replacements if the replaced event is also UTD
-        self.add(true, TimelineItemContent::unable_to_decrypt(c));
+        let raw_event = match &self.ctx.flow {
+            Flow::Remote { raw_event, .. } => Some(raw_event),
+            Flow::Local { .. } => None,
+        };
+        let cause = UtdCause::determine(raw_event);
+
+        // Index the item by session, so its decryption can be retried as soon as
+        // the room key arrives.
+        if let (Flow::Remote { event_id, .. }, EncryptedEventScheme::MegolmV1AesSha2(scheme)) =
+            (&self.ctx.flow, &c.scheme)
+        {
+            self.meta.utd_index.insert(scheme.session_id.clone(), event_id.clone());
+        }
+
+        self.add(true, TimelineItemContent::unable_to_decrypt(c, cause));
     }
 
     // Redacted redactions are no-ops (unfortunately)
@@ -684,6 +721,9 @@ impl<'a, 'o> TimelineEventHandler<'a, 'o> {
             debug!("redaction affected no event");
         }
 
+        // A redacted event doesn't need to be decrypted anymore.
+        self.meta.utd_index.remove_events(|event_id| event_id == &*redacts);
+
         self.items.for_each(|mut entry| {
             let Some(event_item) = entry.as_event() else { return };
             let Some(message) = event_item.content.as_message() else { return };
@@ -0,0 +1,185 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ruma::{
+    events::{call::invite::CallInviteEventContent, AnySyncTimelineEvent},
+    serde::Raw,
+    UInt,
+};
+use serde::Deserialize;
+
+/// An `m.call.invite` event.
+#[derive(Clone, Debug)]
+pub struct CallInvite {
+    pub(in crate::timeline) call_id: String,
+    pub(in crate::timeline) lifetime: UInt,
+    pub(in crate::timeline) offer_type: CallOfferType,
+}
+
+impl CallInvite {
+    /// The ID of the call.
+    pub fn call_id(&self) -> &str {
+        &self.call_id
+    }
+
+    /// The time in milliseconds that the invite is valid for, from the time it
+    /// was sent.
+    pub fn lifetime(&self) -> UInt {
+        self.lifetime
+    }
+
+    /// Whether this is an invite to a voice or a video call.
+    pub fn offer_type(&self) -> CallOfferType {
+        self.offer_type
+    }
+}
+
+impl From<CallInviteEventContent> for CallInvite {
+    fn from(content: CallInviteEventContent) -> Self {
+        Self {
+            call_id: content.call_id.to_string(),
+            lifetime: content.lifetime,
+            offer_type: CallOfferType::from_sdp(&content.offer.sdp),
+        }
+    }
+}
+
+/// The type of the call offered by an `m.call.invite` event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CallOfferType {
+    /// A voice call.
+    Voice,
+    /// A video call.
+    Video,
+}
+
+impl CallOfferType {
+    /// The call is a video call if the session description of the offer has a
+    /// video media section.
+    fn from_sdp(sdp: &str) -> Self {
+        if sdp.lines().any(|line| line.starts_with("m=video")) {
+            Self::Video
+        } else {
+            Self::Voice
+        }
+    }
+}
+
+/// An `m.call.notify` event, notifying the room members about a MatrixRTC
+/// call, as defined in [MSC4075].
+///
+/// [MSC4075]: https://github.com/matrix-org/matrix-spec-proposals/pull/4075
+#[derive(Clone, Debug)]
+pub struct CallNotify {
+    pub(in crate::timeline) call_id: String,
+    pub(in crate::timeline) application: String,
+    pub(in crate::timeline) notify_type: CallNotifyType,
+}
+
+impl CallNotify {
+    /// The event types of call notifications, stable and unstable.
+    const EVENT_TYPES: [&'static str; 2] = ["m.call.notify", "org.matrix.msc4075.call.notify"];
+
+    /// Whether the given event type is the type of a call notification.
+    pub(in crate::timeline) fn is_event_type(event_type: &str) -> bool {
+        Self::EVENT_TYPES.contains(&event_type)
+    }
+
+    /// Read the call notification from the content of the given event.
+    ///
+    /// Returns `None` if the content is not a valid call notification.
+    pub(in crate::timeline) fn from_raw(raw_event: &Raw<AnySyncTimelineEvent>) -> Option<Self> {
+        let content = raw_event.get_field::<CallNotifyEventContent>("content").ok().flatten()?;
+        let CallNotifyEventContent { call_id, application, notify_type } = content;
+        Some(Self { call_id, application, notify_type })
+    }
+
+    /// The ID of the call, in the MatrixRTC session of the room.
+    pub fn call_id(&self) -> &str {
+        &self.call_id
+    }
+
+    /// The application of the call, e.g. `m.call`.
+    pub fn application(&self) -> &str {
+        &self.application
+    }
+
+    /// Whether the call should ring or only notify the room members.
+    pub fn notify_type(&self) -> CallNotifyType {
+        self.notify_type
+    }
+}
+
+/// How the room members should be notified about a MatrixRTC call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CallNotifyType {
+    /// The call should ring, like a phone call.
+    Ring,
+    /// The room members should only get a notification.
+    Notify,
+}
+
+/// The content of an `m.call.notify` event, which is not supported by the
+/// version of Ruma we use.
+#[derive(Deserialize)]
+struct CallNotifyEventContent {
+    call_id: String,
+    application: String,
+    notify_type: CallNotifyType,
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma::{events::AnySyncTimelineEvent, serde::Raw};
+    use serde_json::{json, value::to_raw_value};
+
+    use super::{CallNotify, CallNotifyType, CallOfferType};
+
+    fn raw_event(json: serde_json::Value) -> Raw<AnySyncTimelineEvent> {
+        Raw::from_json(to_raw_value(&json).unwrap())
+    }
+
+    #[test]
+    fn test_offer_type_from_sdp() {
+        let sdp = "v=0\r\nm=audio 9 UDP/TLS/RTP/SAVPF 111\r\n";
+        assert_eq!(CallOfferType::from_sdp(sdp), CallOfferType::Voice);
+
+        let sdp = "v=0\r\nm=audio 9 UDP/TLS/RTP/SAVPF 111\r\nm=video 9 UDP/TLS/RTP/SAVPF 96\r\n";
+        assert_eq!(CallOfferType::from_sdp(sdp), CallOfferType::Video);
+    }
+
+    #[test]
+    fn test_call_notify_from_raw() {
+        let event = raw_event(json!({
+            "content": {
+                "application": "m.call",
+                "call_id": "",
+                "m.mentions": { "room": true },
+                "notify_type": "ring",
+            },
+            "type": "org.matrix.msc4075.call.notify",
+        }));
+        let notify = CallNotify::from_raw(&event).unwrap();
+        assert_eq!(notify.application(), "m.call");
+        assert_eq!(notify.call_id(), "");
+        assert_eq!(notify.notify_type(), CallNotifyType::Ring);
+
+        let event = raw_event(json!({
+            "content": { "application": "m.call", "call_id": "", "notify_type": "shout" },
+            "type": "m.call.notify",
+        }));
+        assert!(CallNotify::from_raw(&event).is_none());
+    }
+}
@@ -131,6 +131,41 @@ impl Message {
         self.msgtype.body()
     }
 
+    /// Whether this message is a voice message, as defined in [MSC3245].
+    ///
+    /// Voice messages are `m.audio` messages with an `org.matrix.msc3245.voice`
+    /// content block. Use [`Message::voice_waveform()`] to get the waveform
+    /// to display with them.
+    ///
+    /// [MSC3245]: https://github.com/matrix-org/matrix-spec-proposals/pull/3245
+    pub fn is_voice_message(&self) -> bool {
+        matches!(&self.msgtype, MessageType::Audio(content) if content.voice.is_some())
+    }
+
+    /// Get the waveform of this message, if it is a voice message that has
+    /// one.
+    ///
+    /// The amplitudes are in the range `0..=1024`, as defined in [MSC1767].
+    ///
+    /// [MSC1767]: https://github.com/matrix-org/matrix-spec-proposals/pull/1767
+    pub fn voice_waveform(&self) -> Option<Vec<u16>> {
+        let MessageType::Audio(content) = &self.msgtype else {
+            return None;
+        };
+        if content.voice.is_none() {
+            return None;
+        }
+
+        let audio = content.audio.as_ref()?;
+        let waveform = audio
+            .waveform
+            .iter()
+            .map(|amplitude| u16::try_from(u64::from(amplitude.get())).unwrap_or(u16::MAX))
+            .collect();
+
+        Some(waveform)
+    }
+
     /// Get the event this message is replying to, if any.
     pub fn in_reply_to(&self) -> Option<&InReplyToDetails> {
         self.in_reply_to.as_ref()
@@ -55,9 +55,15 @@ use tracing::warn;
 
 use crate::timeline::{polls::PollState, TimelineItem};
 
+mod call;
 mod message;
+mod utd_cause;
 
-pub use self::message::{InReplyToDetails, Message, RepliedToEvent};
+pub use self::{
+    call::{CallInvite, CallNotify, CallNotifyType, CallOfferType},
+    message::{InReplyToDetails, Message, RepliedToEvent},
+    utd_cause::UtdCause,
+};
 
 /// The content of an [`EventTimelineItem`][super::EventTimelineItem].
 #[derive(Clone, Debug)]
@@ -106,6 +112,12 @@ pub enum TimelineItemContent {
 
     /// An `m.poll.start` event.
     Poll(PollState),
+
+    /// An `m.call.invite` event.
+    CallInvite(CallInvite),
+
+    /// An `m.call.notify` event.
+    CallNotify(CallNotify),
 }
 
 impl TimelineItemContent {
@@ -228,11 +240,19 @@ impl TimelineItemContent {
             TimelineItemContent::FailedToParseMessageLike { .. }
             | TimelineItemContent::FailedToParseState { .. } => "an event that couldn't be parsed",
             TimelineItemContent::Poll(_) => "a poll",
+            TimelineItemContent::CallInvite(_) => "a call invite",
+            TimelineItemContent::CallNotify(_) => "a call notification",
         }
     }
 
-    pub(crate) fn unable_to_decrypt(content: RoomEncryptedEventContent) -> Self {
-        Self::UnableToDecrypt(content.into())
+    pub(crate) fn unable_to_decrypt(content: RoomEncryptedEventContent, cause: UtdCause) -> Self {
+        let mut msg = EncryptedMessage::from(content);
+
+        if let EncryptedMessage::MegolmV1AesSha2 { cause: msg_cause, .. } = &mut msg {
+            *msg_cause = cause;
+        }
+
+        Self::UnableToDecrypt(msg)
     }
 
     pub(crate) fn room_member(
@@ -306,6 +326,8 @@ impl TimelineItemContent {
             | Self::RedactedMessage
             | Self::Sticker(_)
             | Self::Poll(_)
+            | Self::CallInvite(_)
+            | Self::CallNotify(_)
             | Self::UnableToDecrypt(_) => Self::RedactedMessage,
             Self::MembershipChange(ev) => Self::MembershipChange(ev.redact(room_version)),
             Self::ProfileChange(ev) => Self::ProfileChange(ev.redact()),
@@ -338,6 +360,9 @@ pub enum EncryptedMessage {
 
         /// The ID of the session used to encrypt the message.
         session_id: String,
+
+        /// Our best guess at the reason why the message can't be decrypted.
+        cause: UtdCause,
     },
     /// No metadata because the event uses an unknown algorithm.
     Unknown,
@@ -352,7 +377,12 @@ impl From<RoomEncryptedEventContent> for EncryptedMessage {
             #[allow(deprecated)]
             EncryptedEventScheme::MegolmV1AesSha2(s) => {
                 let MegolmV1AesSha2Content { sender_key, device_id, session_id, .. } = s;
-                Self::MegolmV1AesSha2 { sender_key, device_id, session_id }
+                Self::MegolmV1AesSha2 {
+                    sender_key,
+                    device_id,
+                    session_id,
+                    cause: UtdCause::Unknown,
+                }
             }
             _ => Self::Unknown,
         }
@@ -402,6 +432,27 @@ impl RoomMembershipChange {
         self.change
     }
 
+    /// The reason given for the membership change, if any.
+    ///
+    /// Always `None` for redacted events.
+    pub fn reason(&self) -> Option<&str> {
+        as_variant!(&self.content, FullStateEventContent::Original { content, .. } => content)?
+            .reason
+            .as_deref()
+    }
+
+    /// The display name of the third-party invite that this membership change
+    /// originates from, if any.
+    ///
+    /// Only returned if the signed data of the invite matches the user whose
+    /// membership changed.
+    pub fn third_party_invite_display_name(&self) -> Option<&str> {
+        let content =
+            as_variant!(&self.content, FullStateEventContent::Original { content, .. } => content)?;
+        let invite = content.third_party_invite.as_ref()?;
+        (invite.signed.mxid == self.user_id).then_some(invite.display_name.as_str())
+    }
+
     fn redact(&self, room_version: &RoomVersionId) -> Self {
         Self {
             user_id: self.user_id.clone(),
@@ -0,0 +1,95 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ruma::{
+    events::{room::member::MembershipState, AnySyncTimelineEvent},
+    serde::Raw,
+};
+use serde::Deserialize;
+
+/// Our best guess at the reason why an event can't be decrypted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UtdCause {
+    /// We don't have an explanation for why this UTD happened - it is probably
+    /// a bug, or a network split between the two homeservers.
+    #[default]
+    Unknown,
+
+    /// We weren't a member of the room when the event was sent, so the sender
+    /// didn't share the room key with us.
+    Membership,
+}
+
+/// The part of the `unsigned` object of an event that tells us about our
+/// membership at the time of the event, as defined in [MSC4115].
+///
+/// [MSC4115]: https://github.com/matrix-org/matrix-spec-proposals/pull/4115
+#[derive(Deserialize)]
+struct UnsignedWithMembership {
+    #[serde(rename = "io.element.msc4115.membership")]
+    membership: Option<MembershipState>,
+}
+
+impl UtdCause {
+    /// Decide the cause of this UTD, based on the evidence in the event.
+    pub(crate) fn determine(raw_event: Option<&Raw<AnySyncTimelineEvent>>) -> Self {
+        let membership = raw_event
+            .and_then(|raw| raw.get_field::<UnsignedWithMembership>("unsigned").ok().flatten())
+            .and_then(|unsigned| unsigned.membership);
+
+        match membership {
+            Some(membership) if membership != MembershipState::Join => Self::Membership,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma::{events::AnySyncTimelineEvent, serde::Raw};
+    use serde_json::{json, value::to_raw_value};
+
+    use super::UtdCause;
+
+    fn raw_event(json: serde_json::Value) -> Raw<AnySyncTimelineEvent> {
+        Raw::from_json(to_raw_value(&json).unwrap())
+    }
+
+    #[test]
+    fn test_no_event_has_unknown_cause() {
+        assert_eq!(UtdCause::determine(None), UtdCause::Unknown);
+    }
+
+    #[test]
+    fn test_no_membership_has_unknown_cause() {
+        let event = raw_event(json!({ "unsigned": { "age": 3 } }));
+        assert_eq!(UtdCause::determine(Some(&event)), UtdCause::Unknown);
+    }
+
+    #[test]
+    fn test_joined_membership_has_unknown_cause() {
+        let event = raw_event(json!({
+            "unsigned": { "io.element.msc4115.membership": "join" },
+        }));
+        assert_eq!(UtdCause::determine(Some(&event)), UtdCause::Unknown);
+    }
+
+    #[test]
+    fn test_left_membership_has_membership_cause() {
+        let event = raw_event(json!({
+            "unsigned": { "io.element.msc4115.membership": "leave" },
+        }));
+        assert_eq!(UtdCause::determine(Some(&event)), UtdCause::Membership);
+    }
+}
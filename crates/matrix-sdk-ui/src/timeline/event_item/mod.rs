@@ -33,9 +33,9 @@ mod remote;
 
 pub use self::{
     content::{
-        AnyOtherFullStateEventContent, EncryptedMessage, InReplyToDetails, MemberProfileChange,
-        MembershipChange, Message, OtherState, RepliedToEvent, RoomMembershipChange, Sticker,
-        TimelineItemContent,
+        AnyOtherFullStateEventContent, CallInvite, CallNotify, CallNotifyType, CallOfferType,
+        EncryptedMessage, InReplyToDetails, MemberProfileChange, MembershipChange, Message,
+        OtherState, RepliedToEvent, RoomMembershipChange, Sticker, TimelineItemContent, UtdCause,
     },
     local::EventSendState,
     reactions::{BundledReactions, ReactionGroup},
@@ -260,12 +260,15 @@ impl EventTimelineItem {
 
     /// Get the read receipts of this item.
     ///
-    /// The key is the ID of a room member and the value are details about the
-    /// read receipt.
+    /// The key is the ID of a room member and the ID of the root of the thread
+    /// of the receipt, or `None` for the main timeline. The value are details
+    /// about the read receipt.
     ///
-    /// Note that currently this ignores threads.
-    pub fn read_receipts(&self) -> &IndexMap<OwnedUserId, Receipt> {
-        static EMPTY_RECEIPTS: Lazy<IndexMap<OwnedUserId, Receipt>> = Lazy::new(Default::default);
+    /// A member can have a receipt on the main timeline and one for every
+    /// thread.
+    pub fn read_receipts(&self) -> &IndexMap<(OwnedUserId, Option<OwnedEventId>), Receipt> {
+        static EMPTY_RECEIPTS: Lazy<IndexMap<(OwnedUserId, Option<OwnedEventId>), Receipt>> =
+            Lazy::new(Default::default);
         match &self.kind {
             EventTimelineItemKind::Local(_) => &EMPTY_RECEIPTS,
             EventTimelineItemKind::Remote(remote_event) => &remote_event.read_receipts,
@@ -36,8 +36,10 @@ pub(in crate::timeline) struct RemoteEventTimelineItem {
     /// The key is the ID of a room member and the value are details about the
     /// read receipt.
     ///
-    /// Note that currently this ignores threads.
-    pub read_receipts: IndexMap<OwnedUserId, Receipt>,
+    /// A member can have a receipt on the main timeline and one for every
+    /// thread, so the key also contains the ID of the root of the thread of
+    /// the receipt, or `None` for the main timeline.
+    pub read_receipts: IndexMap<(OwnedUserId, Option<OwnedEventId>), Receipt>,
     /// Whether the event has been sent by the the logged-in user themselves.
     pub is_own: bool,
     /// Whether the item should be highlighted in the timeline.
@@ -67,6 +69,11 @@ impl RemoteEventTimelineItem {
         Self { reactions, ..self.clone() }
     }
 
+    /// Clone the current event item, and update its `encryption_info`.
+    pub fn with_encryption_info(&self, encryption_info: Option<EncryptionInfo>) -> Self {
+        Self { encryption_info, ..self.clone() }
+    }
+
     /// Clone the current event item, and clear its `reactions` as well as the
     /// JSON representation fields.
     pub fn redact(&self) -> Self {
@@ -14,28 +14,35 @@
 
 #[cfg(feature = "e2e-encryption")]
 use std::collections::BTreeSet;
-use std::{fmt, sync::Arc};
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{Arc, Mutex},
+};
 
 use as_variant::as_variant;
 use eyeball_im::{ObservableVectorEntry, VectorDiff};
 use eyeball_im_util::vector::VectorObserverExt;
 use futures_core::Stream;
+use futures_util::StreamExt;
 use imbl::Vector;
 use itertools::Itertools;
 #[cfg(all(test, feature = "e2e-encryption"))]
 use matrix_sdk::crypto::OlmMachine;
 use matrix_sdk::{
     deserialized_responses::{SyncTimelineEvent, TimelineEvent},
-    sync::JoinedRoom,
+    room::MessagesOptions,
+    sync::RoomUpdate,
     Error, Result, Room,
 };
-use matrix_sdk_base::sync::Timeline;
+use matrix_sdk_base::AppliedRedaction;
 #[cfg(test)]
 use ruma::events::receipt::ReceiptEventContent;
 #[cfg(all(test, feature = "e2e-encryption"))]
 use ruma::RoomId;
 use ruma::{
     api::client::receipt::create_receipt::v3::ReceiptType as SendReceiptType,
+    assign,
     events::{
         fully_read::FullyReadEvent,
         poll::unstable_start::UnstablePollStartEventContent,
@@ -49,7 +56,8 @@ use ruma::{
         AnyMessageLikeEventContent, AnySyncMessageLikeEvent, AnySyncTimelineEvent,
         MessageLikeEventType,
     },
-    EventId, OwnedEventId, OwnedTransactionId, RoomVersionId, TransactionId, UserId,
+    EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedTransactionId, RoomVersionId,
+    TransactionId, UserId,
 };
 use tokio::sync::{RwLock, RwLockWriteGuard};
 use tracing::{debug, error, field::debug, info, instrument, trace, warn};
@@ -61,12 +69,14 @@ use super::traits::Decryptor;
 use super::{
     event_item::EventItemIdentifier,
     item::timeline_item,
+    membership_group::{group_membership_changes, GroupedTimelineItem},
     pagination::PaginationTokens,
     reactions::ReactionToggleResult,
     traits::RoomDataProvider,
     util::{rfind_event_by_id, rfind_event_item, RelativePosition},
-    AnnotationKey, EventSendState, EventTimelineItem, InReplyToDetails, Message, Profile,
-    RepliedToEvent, TimelineDetails, TimelineItem, TimelineItemContent, TimelineItemKind,
+    AnnotationKey, CallNotify, EventSendState, EventTimelineItem, InReplyToDetails, Message,
+    Profile, RepliedToEvent, TimelineDetails, TimelineItem, TimelineItemContent, TimelineItemKind,
+    UnableToDecryptHook,
 };
 
 mod state;
@@ -81,8 +91,29 @@ pub(super) struct TimelineInner<P: RoomDataProvider = Room> {
     state: Arc<RwLock<TimelineInnerState>>,
     room_data_provider: P,
     settings: TimelineInnerSettings,
+    /// The state of the focus on an older event, `None` while the timeline
+    /// follows the sync.
+    focus: Arc<Mutex<Option<FocusedState>>>,
+}
+
+/// The state of a timeline that is focused on an older event, and doesn't
+/// follow the sync.
+#[derive(Debug, Default)]
+struct FocusedState {
+    /// The token to load the events after the focused ones, `None` if they
+    /// are the latest events of the room.
+    forward_pagination_token: Option<String>,
+    /// The room updates received while the timeline is focused, applied when
+    /// it follows the sync again.
+    pending_updates: VecDeque<RoomUpdate>,
 }
 
+/// The maximum number of room updates kept while the timeline is focused.
+///
+/// The events of the oldest updates are loaded again by forward pagination or
+/// when the timeline goes back to live mode, only their receipts are lost.
+const MAX_PENDING_UPDATES: usize = 100;
+
 #[derive(Debug, Clone)]
 pub(super) enum ReactionAction {
     /// Request already in progress so allow that one to resolve
@@ -110,6 +141,12 @@ pub(super) struct TimelineInnerSettings {
     pub(super) event_filter: Arc<TimelineEventFilterFn>,
     /// Are unparsable events added as timeline items of their own kind?
     pub(super) add_failed_to_parse: bool,
+    /// The minimum number of consecutive membership and profile changes that
+    /// are grouped together, if they are grouped at all.
+    pub(super) min_membership_group_size: Option<usize>,
+    /// The hook notified when an event that was unable to decrypt gets
+    /// decrypted.
+    pub(super) utd_hook: Option<Arc<dyn UnableToDecryptHook>>,
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -118,6 +155,7 @@ impl fmt::Debug for TimelineInnerSettings {
         f.debug_struct("TimelineInnerSettings")
             .field("track_read_receipts", &self.track_read_receipts)
             .field("add_failed_to_parse", &self.add_failed_to_parse)
+            .field("min_membership_group_size", &self.min_membership_group_size)
             .finish_non_exhaustive()
     }
 }
@@ -128,6 +166,8 @@ impl Default for TimelineInnerSettings {
             track_read_receipts: false,
             event_filter: Arc::new(default_event_filter),
             add_failed_to_parse: true,
+            min_membership_group_size: None,
+            utd_hook: None,
         }
     }
 }
@@ -194,9 +234,10 @@ pub fn default_event_filter(event: &AnySyncTimelineEvent, room_version: &RoomVer
                         | AnyMessageLikeEventContent::UnstablePollStart(
                             UnstablePollStartEventContent::New(_),
                         )
-                        | AnyMessageLikeEventContent::RoomEncrypted(_) => true,
+                        | AnyMessageLikeEventContent::RoomEncrypted(_)
+                        | AnyMessageLikeEventContent::CallInvite(_) => true,
 
-                        _ => false,
+                        other => CallNotify::is_event_type(&other.event_type().to_string()),
                     }
                 }
             }
@@ -214,11 +255,13 @@ pub(super) type TimelineEventFilterFn =
 
 impl<P: RoomDataProvider> TimelineInner<P> {
     pub(super) fn new(room_data_provider: P) -> Self {
-        let state = TimelineInnerState::new(room_data_provider.room_version());
+        let state =
+            TimelineInnerState::new(room_data_provider.room_version(), room_data_provider.clock());
         Self {
             state: Arc::new(RwLock::new(state)),
             room_data_provider,
             settings: TimelineInnerSettings::default(),
+            focus: Default::default(),
         }
     }
 
@@ -250,6 +293,33 @@ impl<P: RoomDataProvider> TimelineInner<P> {
         (state.items.clone(), state.items.subscribe().into_batched_stream())
     }
 
+    /// Get a copy of the current items, with the runs of membership changes
+    /// grouped according to the settings.
+    pub(super) async fn grouped_items(&self) -> Vec<GroupedTimelineItem> {
+        let items = self.items().await;
+
+        match self.settings.min_membership_group_size {
+            Some(min_group_size) => group_membership_changes(&items, min_group_size),
+            None => items.into_iter().map(GroupedTimelineItem::Single).collect(),
+        }
+    }
+
+    pub(super) async fn subscribe_grouped(
+        &self,
+    ) -> (Vec<GroupedTimelineItem>, impl Stream<Item = Vec<GroupedTimelineItem>>) {
+        trace!("Creating grouped timeline items signal");
+        let (_, stream) = self.subscribe_batched().await;
+        let items = self.grouped_items().await;
+
+        let this = self.clone();
+        let stream = stream.then(move |_| {
+            let this = this.clone();
+            async move { this.grouped_items().await }
+        });
+
+        (items, stream)
+    }
+
     pub(super) async fn subscribe_filter_map<U, F>(
         &self,
         f: F,
@@ -430,14 +500,97 @@ impl<P: RoomDataProvider> TimelineInner<P> {
         self.state.write().await.clear();
     }
 
-    pub(super) async fn handle_joined_room_update(&self, update: JoinedRoom) {
+    /// Remove the events that were sent before `cutoff`, because they are
+    /// older than the retention policy of the room allows.
+    pub(super) async fn remove_events_before(&self, cutoff: MilliSecondsSinceUnixEpoch) {
+        self.state.write().await.remove_events_before(cutoff);
+    }
+
+    /// Whether the timeline follows the sync.
+    pub(super) fn is_live(&self) -> bool {
+        self.focus.lock().unwrap().is_none()
+    }
+
+    /// Keep the given room update until the timeline follows the sync again,
+    /// if it is focused.
+    ///
+    /// Returns the update if it should be handled right away.
+    pub(super) fn defer_room_update_if_focused(&self, update: RoomUpdate) -> Option<RoomUpdate> {
+        let mut focus = self.focus.lock().unwrap();
+        let Some(focused) = focus.as_mut() else {
+            return Some(update);
+        };
+
+        if focused.pending_updates.len() == MAX_PENDING_UPDATES {
+            warn!("Too many room updates while the timeline is focused, dropping the oldest one");
+            focused.pending_updates.pop_front();
+        }
+        focused.pending_updates.push_back(update);
+
+        None
+    }
+
+    /// Replace the items of the timeline with the given events, in
+    /// chronological order, and stop following the sync.
+    ///
+    /// The `forward_pagination_token` is used to load the events after the
+    /// given ones, it should be `None` if they are the latest events of the
+    /// room.
+    pub(super) async fn focus_on_events(
+        &self,
+        events: Vector<SyncTimelineEvent>,
+        back_pagination_token: Option<String>,
+        forward_pagination_token: Option<String>,
+    ) {
         let mut state = self.state.write().await;
-        state.handle_joined_room_update(update, &self.room_data_provider, &self.settings).await;
+
+        {
+            // Keep the updates received during a previous focus.
+            let mut focus = self.focus.lock().unwrap();
+            let focused = focus.get_or_insert_with(FocusedState::default);
+            focused.forward_pagination_token = forward_pagination_token;
+        }
+
+        state.clear();
+        state
+            .add_initial_events(
+                events,
+                back_pagination_token,
+                &self.room_data_provider,
+                &self.settings,
+            )
+            .await;
     }
 
-    pub(super) async fn handle_sync_timeline(&self, timeline: Timeline) {
+    pub(super) async fn handle_room_update(&self, update: RoomUpdate) {
         let mut state = self.state.write().await;
-        state.handle_sync_timeline(timeline, &self.room_data_provider, &self.settings).await;
+        self.handle_room_update_with_state(&mut state, update).await;
+    }
+
+    async fn handle_room_update_with_state(
+        &self,
+        state: &mut TimelineInnerState,
+        update: RoomUpdate,
+    ) {
+        match update {
+            RoomUpdate::Left { updates, .. } => {
+                state
+                    .handle_sync_timeline(
+                        updates.timeline,
+                        &self.room_data_provider,
+                        &self.settings,
+                    )
+                    .await;
+            }
+            RoomUpdate::Joined { updates, .. } => {
+                state
+                    .handle_joined_room_update(updates, &self.room_data_provider, &self.settings)
+                    .await;
+            }
+            RoomUpdate::Invited { .. } => {
+                warn!("Room is in invited state, can't build or update its timeline");
+            }
+        }
     }
 
     #[cfg(test)]
@@ -460,8 +613,7 @@ impl<P: RoomDataProvider> TimelineInner<P> {
         state.handle_local_event(sender, profile, txn_id, content);
     }
 
-    /// Handle the creation of a new local event.
-    #[cfg(test)]
+    /// Handle the creation of a new local redaction.
     pub(super) async fn handle_local_redaction(
         &self,
         txn_id: OwnedTransactionId,
@@ -475,6 +627,27 @@ impl<P: RoomDataProvider> TimelineInner<P> {
         state.handle_local_redaction(sender, profile, txn_id, to_redact, content);
     }
 
+    /// Redact the item of an event whose redaction was applied to the local
+    /// data of the room, but that might not have been received by this
+    /// timeline yet.
+    pub(super) async fn handle_applied_redaction(&self, applied: AppliedRedaction) {
+        // Keep the reason of the redaction.
+        let content = match applied.redaction.get_field("content") {
+            Ok(content) => content.unwrap_or_default(),
+            Err(error) => {
+                warn!("Couldn't deserialize the content of a local redaction: {error}");
+                RoomRedactionEventContent::default()
+            }
+        };
+
+        self.handle_local_redaction(
+            TransactionId::new(),
+            EventItemIdentifier::EventId(applied.redacts),
+            content,
+        )
+        .await;
+    }
+
     /// Update the send state of a local event represented by a transaction ID.
     ///
     /// If no local event is found, a warning is raised.
@@ -657,6 +830,46 @@ impl<P: RoomDataProvider> TimelineInner<P> {
         Some(content)
     }
 
+    /// Mark the local echoes that failed because the homeserver couldn't be
+    /// reached, and the ones cancelled after them, as not sent yet.
+    ///
+    /// Contrary to [`Self::prepare_retry`], the items stay in place. Returns
+    /// their transaction IDs and contents, in order.
+    pub(super) async fn prepare_resume(&self) -> Vec<(OwnedTransactionId, TimelineItemContent)> {
+        let mut state = self.state.write().await;
+        let mut txn = state.items.transaction();
+
+        let mut after_network_error = false;
+        let mut resumed = Vec::new();
+
+        for idx in 0..txn.len() {
+            let item = txn[idx].clone();
+            let Some(event_item) = item.as_event() else { continue };
+            let Some(local_item) = event_item.as_local() else { continue };
+
+            let resume = match &local_item.send_state {
+                EventSendState::SendingFailed { error }
+                    if super::queue::is_network_error(error) =>
+                {
+                    after_network_error = true;
+                    true
+                }
+                EventSendState::Cancelled => after_network_error,
+                _ => false,
+            };
+
+            if resume {
+                resumed.push((local_item.transaction_id.clone(), event_item.content.clone()));
+                let new_event_item =
+                    event_item.with_kind(local_item.with_send_state(EventSendState::NotSentYet));
+                txn.set(idx, item.with_kind(new_event_item));
+            }
+        }
+
+        txn.commit();
+        resumed
+    }
+
     pub(super) async fn discard_local_echo(&self, txn_id: &TransactionId) -> bool {
         let mut state = self.state.write().await;
 
@@ -752,6 +965,34 @@ impl<P: RoomDataProvider> TimelineInner<P> {
 
         let mut state = self.state.clone().write_owned().await;
 
+        let retry_indices: Vec<_> = match &session_ids {
+            // Only look up the timeline items that the index knows are waiting
+            // for the sessions we received.
+            Some(session_ids) => state
+                .utd_index
+                .event_ids(session_ids)
+                .filter_map(|event_id| Some(rfind_event_by_id(&state.items, event_id)?.0))
+                .sorted()
+                .dedup()
+                .collect(),
+            None => state
+                .items
+                .iter()
+                .enumerate()
+                .filter_map(|(idx, item)| {
+                    match item.as_event()?.content().as_unable_to_decrypt()? {
+                        EncryptedMessage::MegolmV1AesSha2 { .. } => Some(idx),
+                        EncryptedMessage::OlmV1Curve25519AesSha2 { .. }
+                        | EncryptedMessage::Unknown => None,
+                    }
+                })
+                .collect(),
+        };
+
+        if retry_indices.is_empty() {
+            return;
+        }
+
         let should_retry = move |session_id: &str| {
             if let Some(session_ids) = &session_ids {
                 session_ids.contains(session_id)
@@ -760,26 +1001,6 @@ impl<P: RoomDataProvider> TimelineInner<P> {
             }
         };
 
-        let retry_indices: Vec<_> = state
-            .items
-            .iter()
-            .enumerate()
-            .filter_map(|(idx, item)| match item.as_event()?.content().as_unable_to_decrypt()? {
-                EncryptedMessage::MegolmV1AesSha2 { session_id, .. }
-                    if should_retry(session_id) =>
-                {
-                    Some(idx)
-                }
-                EncryptedMessage::MegolmV1AesSha2 { .. }
-                | EncryptedMessage::OlmV1Curve25519AesSha2 { .. }
-                | EncryptedMessage::Unknown => None,
-            })
-            .collect();
-
-        if retry_indices.is_empty() {
-            return;
-        }
-
         debug!("Retrying decryption");
 
         let settings = self.settings.clone();
@@ -910,6 +1131,12 @@ impl<P: RoomDataProvider> TimelineInner<P> {
         trace!("Done updating sender profiles");
     }
 
+    /// The number of timeline items waiting for a room key to be decrypted.
+    #[cfg(all(test, feature = "e2e-encryption"))]
+    pub(super) async fn utd_index_len(&self) -> usize {
+        self.state.read().await.utd_index.len()
+    }
+
     #[cfg(test)]
     pub(super) async fn handle_read_receipts(&self, receipt_event_content: ReceiptEventContent) {
         let own_user_id = self.room_data_provider.own_user_id();
@@ -941,6 +1168,187 @@ impl TimelineInner {
         &self.room_data_provider
     }
 
+    /// Replace the items of the timeline with the latest events of the room,
+    /// and follow the sync again.
+    ///
+    /// The timeline stays focused while the events are fetched, so the sync
+    /// updates received meanwhile are applied after them, with the ones
+    /// received before.
+    ///
+    /// Does nothing if the timeline follows the sync, or if it was focused on
+    /// other events while the latest ones were fetched.
+    pub(super) async fn restore_live(&self, num_events: u16) -> Result<()> {
+        let token = match self.focus.lock().unwrap().as_ref() {
+            Some(focused) => focused.forward_pagination_token.clone(),
+            None => return Ok(()),
+        };
+
+        let messages = self
+            .room()
+            .messages(assign!(MessagesOptions::backward(), { limit: num_events.into() }))
+            .await?;
+
+        let mut state = self.state.write().await;
+
+        let pending_updates = {
+            let mut focus = self.focus.lock().unwrap();
+            if !focus.as_ref().is_some_and(|focused| focused.forward_pagination_token == token) {
+                debug!("The focus changed while restoring the live timeline, dropping the events");
+                return Ok(());
+            }
+
+            focus.take().map(|focused| focused.pending_updates).unwrap_or_default()
+        };
+
+        let events = messages.chunk.into_iter().rev().map(Into::into).collect();
+        state.clear();
+        state
+            .add_initial_events(events, messages.end, &self.room_data_provider, &self.settings)
+            .await;
+
+        // The events of these updates are deduplicated with the ones loaded
+        // above, but their receipts and account data are still needed.
+        for update in pending_updates {
+            self.handle_room_update_with_state(&mut state, update).await;
+        }
+
+        Ok(())
+    }
+
+    /// Load the events after the ones of a focused timeline.
+    ///
+    /// When the latest event of the room is reached, the room updates received
+    /// while the timeline was focused are applied and it follows the sync
+    /// again.
+    ///
+    /// Does nothing if the timeline follows the sync.
+    pub(super) async fn paginate_forwards(&self, num_events: u16) -> Result<()> {
+        let token = match self.focus.lock().unwrap().as_ref() {
+            Some(focused) => focused.forward_pagination_token.clone(),
+            None => return Ok(()),
+        };
+
+        let messages = match &token {
+            Some(token) => Some(
+                self.room()
+                    .messages(assign!(MessagesOptions::forward(), {
+                        from: Some(token.clone()),
+                        limit: num_events.into(),
+                    }))
+                    .await?,
+            ),
+            None => None,
+        };
+
+        let mut state = self.state.write().await;
+
+        let pending_updates = {
+            let mut focus = self.focus.lock().unwrap();
+            let Some(focused) =
+                focus.as_mut().filter(|focused| focused.forward_pagination_token == token)
+            else {
+                debug!("The focus changed during forward pagination, dropping the events");
+                return Ok(());
+            };
+
+            // The server omits the token, or returns no events, at the end of the room.
+            let next_token = messages
+                .as_ref()
+                .filter(|messages| !messages.chunk.is_empty())
+                .and_then(|messages| messages.end.clone());
+
+            match next_token {
+                Some(next_token) => {
+                    focused.forward_pagination_token = Some(next_token);
+                    None
+                }
+                None => focus.take().map(|focused| focused.pending_updates),
+            }
+        };
+
+        if let Some(messages) = messages {
+            state
+                .handle_forward_paginated_events(
+                    messages.chunk,
+                    &self.room_data_provider,
+                    &self.settings,
+                )
+                .await;
+        }
+
+        if let Some(pending_updates) = pending_updates {
+            debug!("Reached the latest event of the room, following the sync again");
+            for update in pending_updates {
+                self.handle_room_update_with_state(&mut state, update).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Refresh the verification states of the decrypted events, after the
+    /// trust of the devices changed.
+    #[instrument(skip(self), fields(room_id = ?self.room().room_id()))]
+    pub(super) async fn refresh_verification_states(&self) {
+        let encryption = self.room().client().encryption();
+
+        // Don't hold the lock while the devices are loaded from the store.
+        let encrypted_events: Vec<_> = {
+            let state = self.state.read().await;
+            state
+                .items
+                .iter()
+                .filter_map(|item| {
+                    let remote_event = item.as_event()?.as_remote()?;
+                    Some((remote_event.event_id.clone(), remote_event.encryption_info.clone()?))
+                })
+                .collect()
+        };
+
+        let mut updates = Vec::new();
+
+        for (event_id, encryption_info) in encrypted_events {
+            let verification_state =
+                match encryption.refreshed_verification_state(&encryption_info).await {
+                    Ok(Some(verification_state)) => verification_state,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        warn!("Couldn't refresh the verification state of an event: {e}");
+                        continue;
+                    }
+                };
+
+            if verification_state != encryption_info.verification_state {
+                updates.push((event_id, encryption_info, verification_state));
+            }
+        }
+
+        if updates.is_empty() {
+            return;
+        }
+
+        let mut state = self.state.write().await;
+
+        for (event_id, old_encryption_info, verification_state) in updates {
+            let Some((index, item)) = rfind_event_by_id(&state.items, &event_id) else { continue };
+            let Some(remote_event) = item.as_remote() else { continue };
+            let Some(encryption_info) = &remote_event.encryption_info else { continue };
+
+            // The event changed while the verification state was computed.
+            if encryption_info.verification_state != old_encryption_info.verification_state {
+                continue;
+            }
+
+            trace!(?event_id, "Updating the verification state");
+            let mut encryption_info = encryption_info.clone();
+            encryption_info.verification_state = verification_state;
+
+            let new_item =
+                item.with_inner_kind(remote_event.with_encryption_info(Some(encryption_info)));
+            state.items.set(index, new_item);
+        }
+    }
+
     /// Get the current fully-read event.
     pub(super) async fn fully_read_event(&self) -> Option<FullyReadEvent> {
         match self.room().account_data_static().await {
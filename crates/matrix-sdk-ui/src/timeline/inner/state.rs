@@ -13,17 +13,19 @@
 // limitations under the License.
 
 use std::{
-    collections::VecDeque,
+    collections::{HashSet, VecDeque},
     future::Future,
     mem::{self, ManuallyDrop},
     ops::{Deref, DerefMut},
     sync::Arc,
 };
 
+#[cfg(feature = "e2e-encryption")]
+use as_variant::as_variant;
 use eyeball_im::{ObservableVector, ObservableVectorTransaction, ObservableVectorTransactionEntry};
 use imbl::Vector;
 use indexmap::IndexMap;
-use matrix_sdk::{deserialized_responses::SyncTimelineEvent, sync::Timeline};
+use matrix_sdk::{clock::Clock, deserialized_responses::SyncTimelineEvent, sync::Timeline};
 use matrix_sdk_base::{deserialized_responses::TimelineEvent, sync::JoinedRoom};
 #[cfg(test)]
 use ruma::events::receipt::ReceiptEventContent;
@@ -39,6 +41,8 @@ use ruma::{
 use tracing::{debug, error, instrument, trace, warn};
 
 use super::{HandleManyEventsResult, ReactionState, TimelineInnerSettings};
+#[cfg(feature = "e2e-encryption")]
+use crate::timeline::{EncryptedMessage, UnableToDecryptInfo};
 use crate::{
     events::SyncTimelineEventWithoutContent,
     timeline::{
@@ -50,8 +54,9 @@ use crate::{
         item::timeline_item,
         polls::PollPendingEvents,
         reactions::{ReactionToggleResult, Reactions},
-        read_receipts::ReadReceipts,
+        read_receipts::{event_thread_root, ReadReceipts},
         traits::RoomDataProvider,
+        utd_hook::UtdIndex,
         util::{
             find_read_marker, rfind_event_by_id, rfind_event_item, timestamp_to_date,
             RelativePosition,
@@ -68,13 +73,13 @@ pub(in crate::timeline) struct TimelineInnerState {
 }
 
 impl TimelineInnerState {
-    pub(super) fn new(room_version: RoomVersionId) -> Self {
+    pub(super) fn new(room_version: RoomVersionId, clock: Arc<dyn Clock>) -> Self {
         Self {
             // Upstream default capacity is currently 16, which is making
             // sliding-sync tests with 20 events lag. This should still be
             // small enough.
             items: ObservableVector::with_capacity(32),
-            meta: TimelineInnerMetadata::new(room_version),
+            meta: TimelineInnerMetadata::new(room_version, clock),
         }
     }
 
@@ -209,6 +214,28 @@ impl TimelineInnerState {
         Some(total)
     }
 
+    /// Add the events loaded by forward pagination, in chronological order,
+    /// at the end of a focused timeline.
+    #[instrument(skip_all)]
+    pub(super) async fn handle_forward_paginated_events<P: RoomDataProvider>(
+        &mut self,
+        events: Vec<TimelineEvent>,
+        room_data_provider: &P,
+        settings: &TimelineInnerSettings,
+    ) {
+        let mut txn = self.transaction();
+        for event in events {
+            txn.handle_remote_event(
+                event.into(),
+                TimelineItemPosition::End { from_cache: true },
+                room_data_provider,
+                settings,
+            )
+            .await;
+        }
+        txn.commit();
+    }
+
     #[cfg(test)]
     pub(super) async fn handle_live_event<P: RoomDataProvider>(
         &mut self,
@@ -309,10 +336,32 @@ impl TimelineInnerState {
         let mut offset = 0;
         for idx in retry_indices {
             let idx = idx - offset;
-            let Some(mut event) = retry_one(txn.items[idx].clone()).await else {
+            let item = txn.items[idx].clone();
+            let utd = item.as_event().and_then(|event_item| {
+                let event_id = event_item.event_id()?.to_owned();
+                let session_id = as_variant!(
+                    event_item.content().as_unable_to_decrypt()?,
+                    EncryptedMessage::MegolmV1AesSha2 { session_id, .. } => session_id.clone()
+                )?;
+                Some((event_id, session_id))
+            });
+
+            let Some(mut event) = retry_one(item).await else {
                 continue;
             };
 
+            if let Some((event_id, session_id)) = utd {
+                if let Some(time_to_decrypt) = txn.meta.utd_index.remove(&session_id, &event_id) {
+                    if let Some(hook) = &settings.utd_hook {
+                        hook.on_late_decrypt(UnableToDecryptInfo {
+                            event_id,
+                            session_id,
+                            time_to_decrypt,
+                        });
+                    }
+                }
+            }
+
             event.push_actions = push_rules_context.as_ref().map(|(push_rules, push_context)| {
                 push_rules.get_actions(&event.event, push_context).to_owned()
             });
@@ -457,6 +506,12 @@ impl TimelineInnerState {
         txn.commit();
     }
 
+    pub(super) fn remove_events_before(&mut self, cutoff: MilliSecondsSinceUnixEpoch) {
+        let mut txn = self.transaction();
+        txn.remove_events_before(cutoff);
+        txn.commit();
+    }
+
     fn transaction(&mut self) -> TimelineInnerStateTransaction<'_> {
         let items = ManuallyDrop::new(self.items.transaction());
         TimelineInnerStateTransaction { items, meta: &mut self.meta }
@@ -539,6 +594,7 @@ impl TimelineInnerStateTransaction<'_> {
         settings: &TimelineInnerSettings,
     ) -> (Option<OwnedEventId>, HandleEventResult) {
         let raw = event.event;
+        let thread_root = event_thread_root(&raw);
         let (event_id, sender, timestamp, txn_id, event_kind, should_add) = match raw.deserialize()
         {
             Ok(event) => {
@@ -575,6 +631,7 @@ impl TimelineInnerStateTransaction<'_> {
                         sender: Some(event.sender()),
                         is_own_event,
                         timestamp: Some(event.origin_server_ts()),
+                        thread_root: thread_root.as_deref(),
                         visible: false,
                     };
                     self.add_event(event_meta, position, room_data_provider, settings).await;
@@ -600,6 +657,7 @@ impl TimelineInnerStateTransaction<'_> {
                             sender: sender.as_deref(),
                             is_own_event,
                             timestamp,
+                            thread_root: thread_root.as_deref(),
                             visible: false,
                         };
                         self.add_event(event_meta, position, room_data_provider, settings).await;
@@ -617,6 +675,7 @@ impl TimelineInnerStateTransaction<'_> {
             sender: Some(&sender),
             is_own_event,
             timestamp: Some(timestamp),
+            thread_root: thread_root.as_deref(),
             visible: should_add,
         };
         self.add_event(event_meta, position, room_data_provider, settings).await;
@@ -665,18 +724,7 @@ impl TimelineInnerStateTransaction<'_> {
                 }
             });
 
-            // Remove stray day dividers
-            let mut idx = 0;
-            while idx < self.items.len() {
-                if self.items[idx].is_day_divider()
-                    && self.items.get(idx + 1).map_or(true, |item| item.is_day_divider())
-                {
-                    self.items.remove(idx);
-                    // don't increment idx because all elements have shifted
-                } else {
-                    idx += 1;
-                }
-            }
+            self.remove_stray_day_dividers();
         } else {
             self.items.clear();
         }
@@ -686,11 +734,57 @@ impl TimelineInnerStateTransaction<'_> {
         self.reactions.clear();
         self.fully_read_event = None;
         self.event_should_update_fully_read_marker = false;
+        self.utd_index.clear();
         self.back_pagination_tokens.clear();
 
         debug!(remaining_items = self.items.len(), "Timeline cleared");
     }
 
+    /// Remove the remote events that were sent before `cutoff`.
+    fn remove_events_before(&mut self, cutoff: MilliSecondsSinceUnixEpoch) {
+        let mut removed = HashSet::new();
+
+        self.items.for_each(|entry| {
+            if !entry.is_remote_event() {
+                return;
+            }
+            let Some(event) = entry.as_event() else { return };
+            if event.timestamp() >= cutoff {
+                return;
+            }
+
+            if let Some(event_id) = event.event_id() {
+                removed.insert(event_id.to_owned());
+            }
+            ObservableVectorTransactionEntry::remove(entry);
+        });
+
+        if removed.is_empty() {
+            return;
+        }
+
+        self.remove_stray_day_dividers();
+        self.all_events.retain(|event_meta| !removed.contains(&event_meta.event_id));
+        self.utd_index.remove_events(|event_id| removed.contains(event_id));
+
+        debug!(num_removed = removed.len(), "Removed the events sent before {cutoff:?}");
+    }
+
+    /// Remove the day dividers that aren't followed by an item of their day.
+    fn remove_stray_day_dividers(&mut self) {
+        let mut idx = 0;
+        while idx < self.items.len() {
+            if self.items[idx].is_day_divider()
+                && self.items.get(idx + 1).map_or(true, |item| item.is_day_divider())
+            {
+                self.items.remove(idx);
+                // don't increment idx because all elements have shifted
+            } else {
+                idx += 1;
+            }
+        }
+    }
+
     #[instrument(skip_all)]
     fn set_fully_read_event(&mut self, fully_read_event_id: OwnedEventId) {
         // A similar event has been handled already. We can ignore it.
@@ -756,7 +850,12 @@ impl TimelineInnerStateTransaction<'_> {
         if settings.track_read_receipts
             && matches!(position, TimelineItemPosition::Start | TimelineItemPosition::End { .. })
         {
-            self.load_read_receipts_for_event(event_meta.event_id, room_data_provider).await;
+            self.load_read_receipts_for_event(
+                event_meta.event_id,
+                event_meta.thread_root,
+                room_data_provider,
+            )
+            .await;
 
             self.maybe_add_implicit_read_receipt(event_meta);
         }
@@ -809,6 +908,9 @@ pub(in crate::timeline) struct TimelineInnerMetadata {
     /// the in flight reaction request state that is ongoing
     pub in_flight_reaction: IndexMap<AnnotationKey, ReactionState>,
     pub room_version: RoomVersionId,
+    /// The items that are unable to decrypt, by the session they're waiting
+    /// for.
+    pub utd_index: UtdIndex,
 
     /// Back-pagination tokens, in the same order as the associated timeline
     /// items.
@@ -818,7 +920,7 @@ pub(in crate::timeline) struct TimelineInnerMetadata {
 }
 
 impl TimelineInnerMetadata {
-    fn new(room_version: RoomVersionId) -> TimelineInnerMetadata {
+    fn new(room_version: RoomVersionId, clock: Arc<dyn Clock>) -> TimelineInnerMetadata {
         Self {
             all_events: Default::default(),
             next_internal_id: Default::default(),
@@ -830,6 +932,7 @@ impl TimelineInnerMetadata {
             reaction_state: Default::default(),
             in_flight_reaction: Default::default(),
             room_version,
+            utd_index: UtdIndex::new(clock),
             back_pagination_tokens: VecDeque::new(),
         }
     }
@@ -949,6 +1052,8 @@ pub(crate) struct FullEventMeta<'a> {
     pub is_own_event: bool,
     /// The timestamp of the event.
     pub timestamp: Option<MilliSecondsSinceUnixEpoch>,
+    /// The ID of the root of the thread the event is in, if any.
+    pub thread_root: Option<&'a EventId>,
 }
 
 impl<'a> FullEventMeta<'a> {
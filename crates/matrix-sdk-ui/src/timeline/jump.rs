@@ -0,0 +1,177 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use matrix_sdk::{deserialized_responses::SyncTimelineEvent, Result};
+use ruma::{api::Direction, EventId, MilliSecondsSinceUnixEpoch, OwnedEventId};
+use tracing::{debug, instrument};
+
+use super::{util::rfind_event_by_id, BackPaginationStatus, Timeline};
+
+/// The number of events loaded before and after the target of a jump.
+const JUMP_CONTEXT_SIZE: u16 = 20;
+
+/// The number of events loaded when the timeline goes back to live mode.
+const LIVE_EVENTS_COUNT: u16 = 20;
+
+/// The target of a jump in the timeline, returned by
+/// [`Timeline::jump_to_fully_read()`] and [`Timeline::jump_to_date()`].
+///
+/// The UI should scroll to the item of [`TimelineJump::event_id()`]. If the
+/// timeline had to be focused on it, it doesn't follow the sync anymore until
+/// [`TimelineJump::restore_live()`] is called, or until
+/// [`Timeline::paginate_forwards()`] reaches the latest event of the room.
+#[derive(Debug)]
+pub struct TimelineJump<'a> {
+    timeline: &'a Timeline,
+    event_id: OwnedEventId,
+    focused: bool,
+}
+
+impl TimelineJump<'_> {
+    /// The ID of the event the timeline jumped to.
+    pub fn event_id(&self) -> &EventId {
+        &self.event_id
+    }
+
+    /// Whether the timeline was focused on the event, because it wasn't
+    /// loaded yet.
+    ///
+    /// If `false`, the event was already in the timeline, which still follows
+    /// the sync.
+    pub fn is_focused(&self) -> bool {
+        self.focused
+    }
+
+    /// Go back to the latest events of the room, and follow the sync again.
+    pub async fn restore_live(self) -> Result<()> {
+        self.timeline.restore_live().await
+    }
+}
+
+impl Timeline {
+    /// Jump to the fully-read marker of the user.
+    ///
+    /// If the fully-read event isn't in the timeline, the timeline is focused
+    /// on it: its items are replaced with the event and the events around it.
+    ///
+    /// Returns `Ok(None)` if the room has no fully-read marker, or if its
+    /// event can't be found.
+    #[instrument(skip(self), fields(room_id = ?self.room().room_id()))]
+    pub async fn jump_to_fully_read(&self) -> Result<Option<TimelineJump<'_>>> {
+        let Some(fully_read) = self.inner.fully_read_event().await else {
+            debug!("No fully-read marker in this room");
+            return Ok(None);
+        };
+
+        self.jump_to_event(fully_read.content.event_id).await
+    }
+
+    /// Jump to the first event sent at or after the given date, or the last
+    /// event of the room if there is none, using the `/timestamp_to_event`
+    /// endpoint.
+    ///
+    /// If the event isn't in the timeline, the timeline is focused on it: its
+    /// items are replaced with the event and the events around it.
+    ///
+    /// Returns `Ok(None)` if the room has no event to jump to.
+    #[instrument(skip(self), fields(room_id = ?self.room().room_id()))]
+    pub async fn jump_to_date(
+        &self,
+        timestamp: MilliSecondsSinceUnixEpoch,
+    ) -> Result<Option<TimelineJump<'_>>> {
+        let room = self.room();
+
+        let event_id = match room.event_id_for_timestamp(timestamp, Direction::Forward).await? {
+            Some(event_id) => event_id,
+            None => match room.event_id_for_timestamp(timestamp, Direction::Backward).await? {
+                Some(event_id) => event_id,
+                None => {
+                    debug!("No event to jump to");
+                    return Ok(None);
+                }
+            },
+        };
+
+        self.jump_to_event(event_id).await
+    }
+
+    /// Whether the timeline follows the sync.
+    ///
+    /// This is `false` after a jump focused the timeline on an event that
+    /// wasn't loaded, until [`Timeline::restore_live()`] is called or
+    /// [`Timeline::paginate_forwards()`] reaches the latest event of the room.
+    pub fn is_live(&self) -> bool {
+        self.inner.is_live()
+    }
+
+    /// Go back to the latest events of the room after a jump, and follow the
+    /// sync again.
+    ///
+    /// Does nothing if the timeline already follows the sync.
+    #[instrument(skip(self), fields(room_id = ?self.room().room_id()))]
+    pub async fn restore_live(&self) -> Result<()> {
+        self.inner.restore_live(LIVE_EVENTS_COUNT).await?;
+        self.back_pagination_status.set_if_not_eq(BackPaginationStatus::Idle);
+        Ok(())
+    }
+
+    /// Load the events after the ones of a timeline focused by a jump.
+    ///
+    /// Once the latest event of the room is loaded, the timeline follows the
+    /// sync again, with the updates received while it was focused.
+    ///
+    /// Does nothing if the timeline already follows the sync.
+    #[instrument(skip(self), fields(room_id = ?self.room().room_id()))]
+    pub async fn paginate_forwards(&self, num_events: u16) -> Result<()> {
+        self.inner.paginate_forwards(num_events).await
+    }
+
+    async fn jump_to_event(&self, event_id: OwnedEventId) -> Result<Option<TimelineJump<'_>>> {
+        if rfind_event_by_id(&self.inner.items().await, &event_id).is_some() {
+            debug!(?event_id, "The target of the jump is already in the timeline");
+            return Ok(Some(TimelineJump { timeline: self, event_id, focused: false }));
+        }
+
+        let Some(context) =
+            self.room().event_with_surrounding_context(&event_id, JUMP_CONTEXT_SIZE).await?
+        else {
+            debug!(?event_id, "The target of the jump wasn't found");
+            return Ok(None);
+        };
+
+        let events = context
+            .events_before
+            .into_iter()
+            .rev()
+            .chain(Some(context.event))
+            .chain(context.events_after)
+            .map(SyncTimelineEvent::from)
+            .collect();
+
+        // Without a token, the first event of the room was loaded already.
+        let back_pagination_status = if context.prev_batch_token.is_some() {
+            BackPaginationStatus::Idle
+        } else {
+            BackPaginationStatus::TimelineStartReached
+        };
+
+        self.inner
+            .focus_on_events(events, context.prev_batch_token, context.next_batch_token)
+            .await;
+        self.back_pagination_status.set_if_not_eq(back_pagination_status);
+
+        debug!(?event_id, "Focused the timeline on the target of the jump");
+        Ok(Some(TimelineJump { timeline: self, event_id, focused: true }))
+    }
+}
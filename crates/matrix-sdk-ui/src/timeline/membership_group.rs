@@ -0,0 +1,182 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use imbl::Vector;
+use ruma::{OwnedUserId, UserId};
+
+use super::{MembershipChange, TimelineItem, TimelineItemContent};
+
+/// A timeline item, or a group of consecutive membership and profile changes
+/// that can be shown collapsed, like "3 people joined".
+///
+/// See [`TimelineBuilder::group_membership_changes()`].
+///
+/// [`TimelineBuilder::group_membership_changes()`]: super::TimelineBuilder::group_membership_changes
+#[derive(Clone, Debug)]
+pub enum GroupedTimelineItem {
+    /// A timeline item that isn't part of a group.
+    Single(Arc<TimelineItem>),
+
+    /// A group of consecutive membership and profile changes.
+    MembershipChanges(MembershipChangeGroup),
+}
+
+/// A group of consecutive membership and profile changes of the timeline.
+#[derive(Clone, Debug)]
+pub struct MembershipChangeGroup {
+    items: Vec<Arc<TimelineItem>>,
+}
+
+impl MembershipChangeGroup {
+    /// Get a unique ID for this group.
+    ///
+    /// This is the unique ID of its first item, so it stays the same as long
+    /// as the group starts with the same item.
+    pub fn unique_id(&self) -> u64 {
+        self.items[0].unique_id()
+    }
+
+    /// The number of items in this group.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Whether this group is empty, which is never the case.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// The items of this group, to show it expanded.
+    pub fn items(&self) -> &[Arc<TimelineItem>] {
+        &self.items
+    }
+
+    /// Summarize the changes of this group, to show it collapsed.
+    pub fn summary(&self) -> MembershipChangeSummary {
+        let mut summary = MembershipChangeSummary::default();
+
+        for item in &self.items {
+            let Some(event) = item.as_event() else { continue };
+
+            let user_id = match event.content() {
+                TimelineItemContent::MembershipChange(change) => {
+                    match change.change() {
+                        Some(MembershipChange::Joined | MembershipChange::InvitationAccepted) => {
+                            summary.joined += 1;
+                        }
+                        Some(MembershipChange::Left) => summary.left += 1,
+                        Some(MembershipChange::Invited) => summary.invited += 1,
+                        Some(
+                            MembershipChange::Kicked
+                            | MembershipChange::Banned
+                            | MembershipChange::KickedAndBanned,
+                        ) => summary.removed += 1,
+                        _ => summary.other += 1,
+                    }
+
+                    change.user_id()
+                }
+                TimelineItemContent::ProfileChange(change) => {
+                    summary.profile_changes += 1;
+                    change.user_id()
+                }
+                _ => continue,
+            };
+
+            summary.add_user(user_id);
+        }
+
+        summary
+    }
+}
+
+/// A summary of the changes of a [`MembershipChangeGroup`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MembershipChangeSummary {
+    /// The number of users that joined the room.
+    pub joined: usize,
+
+    /// The number of users that left the room.
+    pub left: usize,
+
+    /// The number of users that were invited to the room.
+    pub invited: usize,
+
+    /// The number of users that were kicked or banned from the room.
+    pub removed: usize,
+
+    /// The number of changes of display names or avatars.
+    pub profile_changes: usize,
+
+    /// The number of other membership changes.
+    pub other: usize,
+
+    /// The users affected by the changes, in the order of their first change.
+    pub users: Vec<OwnedUserId>,
+}
+
+impl MembershipChangeSummary {
+    fn add_user(&mut self, user_id: &UserId) {
+        if !self.users.iter().any(|u| u == user_id) {
+            self.users.push(user_id.to_owned());
+        }
+    }
+}
+
+/// Whether the item can be part of a [`MembershipChangeGroup`].
+fn is_groupable(item: &TimelineItem) -> bool {
+    item.as_event().is_some_and(|event| {
+        matches!(
+            event.content(),
+            TimelineItemContent::MembershipChange(_) | TimelineItemContent::ProfileChange(_)
+        )
+    })
+}
+
+/// Group the runs of at least `min_group_size` consecutive membership and
+/// profile changes of `items`.
+///
+/// Virtual items, like day dividers and the read marker, end a run.
+pub(super) fn group_membership_changes(
+    items: &Vector<Arc<TimelineItem>>,
+    min_group_size: usize,
+) -> Vec<GroupedTimelineItem> {
+    let mut grouped = Vec::with_capacity(items.len());
+    let mut run = Vec::new();
+
+    let flush = |run: &mut Vec<Arc<TimelineItem>>, grouped: &mut Vec<GroupedTimelineItem>| {
+        if run.len() >= min_group_size {
+            grouped.push(GroupedTimelineItem::MembershipChanges(MembershipChangeGroup {
+                items: std::mem::take(run),
+            }));
+        } else {
+            grouped.extend(run.drain(..).map(GroupedTimelineItem::Single));
+        }
+    };
+
+    for item in items {
+        if is_groupable(item) {
+            run.push(item.clone());
+        } else {
+            flush(&mut run, &mut grouped);
+            grouped.push(GroupedTimelineItem::Single(item.clone()));
+        }
+    }
+
+    flush(&mut run, &mut grouped);
+
+    grouped
+}
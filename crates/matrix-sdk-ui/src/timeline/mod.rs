@@ -33,7 +33,10 @@ use matrix_sdk_base::RoomState;
 use mime::Mime;
 use pin_project_lite::pin_project;
 use ruma::{
-    api::client::receipt::create_receipt::v3::ReceiptType,
+    api::client::{
+        receipt::create_receipt::v3::ReceiptType, relations::get_relating_events_with_rel_type,
+    },
+    assign,
     events::{
         poll::unstable_start::{
             ReplacementUnstablePollStartEventContent, UnstablePollStartContentBlock,
@@ -41,7 +44,7 @@ use ruma::{
         },
         reaction::ReactionEventContent,
         receipt::{Receipt, ReceiptThread},
-        relation::Annotation,
+        relation::{Annotation, RelationType},
         room::{
             message::{
                 AddMentions, ForwardThread, OriginalRoomMessageEvent, ReplacementMetadata,
@@ -51,9 +54,10 @@ use ruma::{
         },
         AnyMessageLikeEventContent,
     },
-    uint, EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedTransactionId, TransactionId,
-    UserId,
+    uint, EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedTransactionId, OwnedUserId,
+    TransactionId, UserId,
 };
+use serde::{de::IgnoredAny, Deserialize};
 use thiserror::Error;
 use tokio::sync::{mpsc::Sender, Mutex, Notify};
 use tracing::{debug, error, info, instrument, warn};
@@ -67,6 +71,8 @@ mod event_item;
 pub mod futures;
 mod inner;
 mod item;
+mod jump;
+mod membership_group;
 mod pagination;
 mod polls;
 mod queue;
@@ -78,6 +84,7 @@ mod tests;
 #[cfg(feature = "e2e-encryption")]
 mod to_device;
 mod traits;
+mod utd_hook;
 mod util;
 mod virtual_item;
 
@@ -85,18 +92,22 @@ pub use self::{
     builder::TimelineBuilder,
     error::{Error, UnsupportedEditItem, UnsupportedReplyItem},
     event_item::{
-        AnyOtherFullStateEventContent, BundledReactions, EncryptedMessage, EventItemOrigin,
-        EventSendState, EventTimelineItem, InReplyToDetails, MemberProfileChange, MembershipChange,
-        Message, OtherState, Profile, ReactionGroup, RepliedToEvent, RoomMembershipChange, Sticker,
-        TimelineDetails, TimelineItemContent,
+        AnyOtherFullStateEventContent, BundledReactions, CallInvite, CallNotify, CallNotifyType,
+        CallOfferType, EncryptedMessage, EventItemOrigin, EventSendState, EventTimelineItem,
+        InReplyToDetails, MemberProfileChange, MembershipChange, Message, OtherState, Profile,
+        ReactionGroup, RepliedToEvent, RoomMembershipChange, Sticker, TimelineDetails,
+        TimelineItemContent, UtdCause,
     },
     inner::default_event_filter,
     item::{TimelineItem, TimelineItemKind},
+    jump::TimelineJump,
+    membership_group::{GroupedTimelineItem, MembershipChangeGroup, MembershipChangeSummary},
     pagination::{BackPaginationStatus, PaginationOptions, PaginationOutcome},
     polls::PollResult,
     reactions::ReactionSenderData,
     sliding_sync_ext::SlidingSyncRoomExt,
     traits::RoomExt,
+    utd_hook::{UnableToDecryptHook, UnableToDecryptInfo},
     virtual_item::VirtualTimelineItem,
 };
 use self::{
@@ -274,6 +285,32 @@ impl Timeline {
         (items, stream)
     }
 
+    /// Get the current timeline items, with the runs of membership and profile
+    /// changes grouped together.
+    ///
+    /// Changes are only grouped if it was enabled with
+    /// [`TimelineBuilder::group_membership_changes()`], otherwise every item
+    /// is a [`GroupedTimelineItem::Single`].
+    pub async fn grouped_items(&self) -> Vec<GroupedTimelineItem> {
+        self.inner.grouped_items().await
+    }
+
+    /// Get the current grouped timeline items, and a stream of updates.
+    ///
+    /// Unlike [`subscribe`](Self::subscribe), this stream yields the full list
+    /// of grouped items every time the timeline changes, because a single
+    /// change can merge or split groups. See [`grouped_items`] for details
+    /// about the grouping.
+    ///
+    /// [`grouped_items`]: Self::grouped_items
+    pub async fn subscribe_grouped(
+        &self,
+    ) -> (Vec<GroupedTimelineItem>, impl Stream<Item = Vec<GroupedTimelineItem>>) {
+        let (items, stream) = self.inner.subscribe_grouped().await;
+        let stream = TimelineStream::new(stream, self.drop_handle.clone());
+        (items, stream)
+    }
+
     /// Send a message to the room, and add it to the timeline as a local echo.
     ///
     /// For simplicity, this method doesn't currently allow custom message
@@ -443,6 +480,102 @@ impl Timeline {
         Ok(())
     }
 
+    /// Redact an event, along with the events of the current user that relate
+    /// to it with one of the given relation types.
+    ///
+    /// Redacting a message but leaving behind its edits or the reactions to it
+    /// can leak what the message was about, so this is the way to redact a
+    /// message along with, for example, its edits
+    /// ([`RelationType::Replacement`]) and the reactions to it
+    /// ([`RelationType::Annotation`]).
+    ///
+    /// The relations are fetched from the homeserver, and redacted before the
+    /// event itself, so a failure leaves the event in place and the method can
+    /// be called again.
+    ///
+    /// Returns the IDs of the related events that were redacted.
+    #[instrument(skip(self, reason), fields(room_id = ?self.room().room_id()))]
+    pub async fn redact_with_relations(
+        &self,
+        event_id: &EventId,
+        kinds: &[RelationType],
+        reason: Option<&str>,
+    ) -> Result<Vec<OwnedEventId>> {
+        let mut related = Vec::new();
+
+        for kind in kinds {
+            related.extend(self.own_relations(event_id, kind.clone()).await?);
+        }
+
+        for related_event_id in &related {
+            self.room().redact(related_event_id, reason, None).await?;
+        }
+
+        self.room().redact(event_id, reason, None).await?;
+
+        debug!(redacted_relations = related.len(), "Redacted an event with its relations");
+
+        Ok(related)
+    }
+
+    /// Get the IDs of the events of the current user that relate to the given
+    /// event with the given relation type, and aren't redacted yet.
+    async fn own_relations(
+        &self,
+        event_id: &EventId,
+        rel_type: RelationType,
+    ) -> Result<Vec<OwnedEventId>> {
+        #[derive(Deserialize)]
+        struct RelatedEvent {
+            event_id: OwnedEventId,
+            sender: OwnedUserId,
+            #[serde(default)]
+            unsigned: RelatedEventUnsigned,
+        }
+
+        #[derive(Default, Deserialize)]
+        struct RelatedEventUnsigned {
+            redacted_because: Option<IgnoredAny>,
+        }
+
+        let own_user_id = self.room().own_user_id();
+        let mut event_ids = Vec::new();
+        let mut from = None;
+
+        loop {
+            let request = assign!(
+                get_relating_events_with_rel_type::v1::Request::new(
+                    self.room().room_id().to_owned(),
+                    event_id.to_owned(),
+                    rel_type.clone(),
+                ),
+                { from: from.take() }
+            );
+            let response = self.room().client().send(request, None).await?;
+
+            for event in response.chunk {
+                let event = match event.deserialize_as::<RelatedEvent>() {
+                    Ok(event) => event,
+                    Err(e) => {
+                        warn!("Couldn't deserialize a related event: {e}");
+                        continue;
+                    }
+                };
+
+                if event.sender == own_user_id && event.unsigned.redacted_because.is_none() {
+                    event_ids.push(event.event_id);
+                }
+            }
+
+            match response.next_batch {
+                Some(next_batch) => from = Some(next_batch),
+                None => break,
+            }
+        }
+
+        Ok(event_ids)
+    }
+
     /// Toggle a reaction on an event
     ///
     /// Adds or redacts a reaction based on the state of the reaction at the
@@ -553,39 +686,9 @@ impl Timeline {
     ///   `send_state()` of `SendState::FailedToSend { .. }`
     #[instrument(skip(self))]
     pub async fn retry_send(&self, txn_id: &TransactionId) -> Result<(), Error> {
-        macro_rules! error_return {
-            ($msg:literal) => {{
-                error!($msg);
-                return Ok(());
-            }};
-        }
-
         let item = self.inner.prepare_retry(txn_id).await.ok_or(Error::RetryEventNotInTimeline)?;
-        let content = match item {
-            TimelineItemContent::Message(msg) => {
-                AnyMessageLikeEventContent::RoomMessage(msg.into())
-            }
-            TimelineItemContent::RedactedMessage => {
-                error_return!("Invalid state: attempting to retry a redacted message");
-            }
-            TimelineItemContent::Sticker(sticker) => {
-                AnyMessageLikeEventContent::Sticker(sticker.content)
-            }
-            TimelineItemContent::UnableToDecrypt(_) => {
-                error_return!("Invalid state: attempting to retry a UTD item");
-            }
-            TimelineItemContent::MembershipChange(_)
-            | TimelineItemContent::ProfileChange(_)
-            | TimelineItemContent::OtherState(_) => {
-                error_return!("Retrying state events is not currently supported");
-            }
-            TimelineItemContent::FailedToParseMessageLike { .. }
-            | TimelineItemContent::FailedToParseState { .. } => {
-                error_return!("Invalid state: attempting to retry a failed-to-parse item");
-            }
-            TimelineItemContent::Poll(poll_state) => AnyMessageLikeEventContent::UnstablePollStart(
-                UnstablePollStartEventContent::New(poll_state.into()),
-            ),
+        let Some(content) = queue::content_to_retry(item) else {
+            return Ok(());
         };
 
         debug!("Retrying failed local echo");
@@ -613,6 +716,32 @@ impl Timeline {
         self.inner.discard_local_echo(txn_id).await
     }
 
+    /// Whether sending messages is paused.
+    ///
+    /// Sending is paused for the whole room, see [`Room::send_queue()`], when
+    /// a message couldn't be sent because the homeserver couldn't be reached.
+    /// The messages sent while it is paused are queued until
+    /// [`Timeline::resume_sending`] is called.
+    pub fn is_sending_paused(&self) -> bool {
+        self.room().send_queue().is_paused()
+    }
+
+    /// Subscribe to the changes of [`Timeline::is_sending_paused`].
+    pub fn subscribe_to_sending_paused(&self) -> Subscriber<bool> {
+        self.room().send_queue().subscribe_to_paused()
+    }
+
+    /// Resume sending messages after it was paused.
+    ///
+    /// The messages that couldn't be sent because the homeserver couldn't be
+    /// reached, and the ones that were cancelled after them, are retried in
+    /// their original order, before the messages that were queued while
+    /// sending was paused. This is done by every timeline of the room.
+    #[instrument(skip(self))]
+    pub async fn resume_sending(&self) {
+        self.room().send_queue().resume();
+    }
+
     /// Fetch unavailable details about the event with the given ID.
     ///
     /// This method only works for IDs of remote [`EventTimelineItem`]s,
@@ -779,6 +908,9 @@ struct TimelineDropHandle {
     room_update_join_handle: JoinHandle<()>,
     ignore_user_list_update_join_handle: JoinHandle<()>,
     room_key_from_backups_join_handle: JoinHandle<()>,
+    redactions_join_handle: JoinHandle<()>,
+    purges_join_handle: JoinHandle<()>,
+    trust_recomputation_join_handle: JoinHandle<()>,
 }
 
 impl Drop for TimelineDropHandle {
@@ -789,6 +921,9 @@ impl Drop for TimelineDropHandle {
         self.room_update_join_handle.abort();
         self.ignore_user_list_update_join_handle.abort();
         self.room_key_from_backups_join_handle.abort();
+        self.redactions_join_handle.abort();
+        self.purges_join_handle.abort();
+        self.trust_recomputation_join_handle.abort();
     }
 }
 
@@ -21,17 +21,21 @@ use std::{
     task::{Context, Poll},
 };
 
-use futures_util::future::Either;
+use futures_util::{future::Either, StreamExt};
 use matrix_sdk::{
     executor::{spawn, JoinError, JoinHandle},
-    Room,
+    room::RoomSendQueue,
+    HttpError, Room,
 };
 use matrix_sdk_base::RoomState;
-use ruma::{events::AnyMessageLikeEventContent, OwnedTransactionId};
+use ruma::{
+    events::{poll::unstable_start::UnstablePollStartEventContent, AnyMessageLikeEventContent},
+    OwnedTransactionId,
+};
 use tokio::{select, sync::mpsc::Receiver};
 use tracing::{debug, error, info, instrument, trace, warn};
 
-use super::{inner::TimelineInner, EventSendState};
+use super::{inner::TimelineInner, EventSendState, TimelineItemContent};
 
 /// A locally-created message that is supposed to be sent.
 pub(super) struct LocalMessage {
@@ -43,16 +47,63 @@ pub(super) struct LocalMessage {
     pub content: AnyMessageLikeEventContent,
 }
 
+/// Whether the given error means that the homeserver couldn't be reached.
+pub(super) fn is_network_error(error: &matrix_sdk::Error) -> bool {
+    matches!(error, matrix_sdk::Error::Http(HttpError::Reqwest(_)))
+}
+
+/// The content to send again for a local echo with the given content, `None`
+/// if it can't be sent again.
+pub(super) fn content_to_retry(content: TimelineItemContent) -> Option<AnyMessageLikeEventContent> {
+    let content = match content {
+        TimelineItemContent::Message(msg) => AnyMessageLikeEventContent::RoomMessage(msg.into()),
+        TimelineItemContent::RedactedMessage => {
+            error!("Invalid state: attempting to retry a redacted message");
+            return None;
+        }
+        TimelineItemContent::Sticker(sticker) => {
+            AnyMessageLikeEventContent::Sticker(sticker.content)
+        }
+        TimelineItemContent::UnableToDecrypt(_) => {
+            error!("Invalid state: attempting to retry a UTD item");
+            return None;
+        }
+        TimelineItemContent::MembershipChange(_)
+        | TimelineItemContent::ProfileChange(_)
+        | TimelineItemContent::OtherState(_) => {
+            error!("Retrying state events is not currently supported");
+            return None;
+        }
+        TimelineItemContent::FailedToParseMessageLike { .. }
+        | TimelineItemContent::FailedToParseState { .. } => {
+            error!("Invalid state: attempting to retry a failed-to-parse item");
+            return None;
+        }
+        TimelineItemContent::Poll(poll_state) => AnyMessageLikeEventContent::UnstablePollStart(
+            UnstablePollStartEventContent::New(poll_state.into()),
+        ),
+        TimelineItemContent::CallInvite(_) | TimelineItemContent::CallNotify(_) => {
+            error!("Retrying call events is not currently supported");
+            return None;
+        }
+    };
+
+    Some(content)
+}
+
 #[instrument(skip_all, fields(room_id = ?room.room_id()))]
 pub(super) async fn send_queued_messages(
     timeline_inner: TimelineInner,
     room: Room,
     mut msg_receiver: Receiver<LocalMessage>,
 ) {
+    // Sending is paused for the whole room, not only for this timeline.
+    let send_queue = room.send_queue();
     let mut queue = VecDeque::new();
     let mut send_task: SendMessageTask = SendMessageTask::Idle;
     let mut recv_fut: Either<_, Pending<Option<LocalMessage>>> =
         Either::Left(Box::pin(msg_receiver.recv()));
+    let mut paused_updates = send_queue.subscribe_to_paused();
 
     loop {
         select! {
@@ -64,6 +115,7 @@ pub(super) async fn send_queued_messages(
                     &mut send_task,
                     &mut queue,
                     &timeline_inner,
+                    &send_queue,
                 ).await;
             }
             recv_res = &mut recv_fut => {
@@ -75,6 +127,7 @@ pub(super) async fn send_queued_messages(
                         &mut send_task,
                         &mut queue,
                         &timeline_inner,
+                        send_queue.is_paused(),
                     ).await;
 
                     // appease the borrow checker
@@ -86,6 +139,18 @@ pub(super) async fn send_queued_messages(
                     Either::Right(pending())
                 };
             }
+            Some(paused) = paused_updates.next() => {
+                if !paused {
+                    debug!("Sending resumed");
+                    requeue_failed_messages(&timeline_inner, &mut queue).await;
+
+                    if send_task.is_idle() {
+                        if let Some(msg) = queue.pop_front() {
+                            send_task.start(room.clone(), timeline_inner.clone(), msg);
+                        }
+                    }
+                }
+            }
         }
 
         if send_task.is_idle() && matches!(recv_fut, Either::Right(_)) {
@@ -96,14 +161,36 @@ pub(super) async fn send_queued_messages(
     info!("Stopped");
 }
 
+/// Put the messages that failed because the homeserver couldn't be reached,
+/// and the ones cancelled after them, back at the front of the queue.
+///
+/// They were sent before the messages queued while sending was paused, so
+/// they are sent again first, in their original order.
+async fn requeue_failed_messages(
+    timeline_inner: &TimelineInner,
+    queue: &mut VecDeque<LocalMessage>,
+) {
+    let failed = timeline_inner.prepare_resume().await;
+    if !failed.is_empty() {
+        debug!(num_messages = failed.len(), "Retrying the messages that failed");
+    }
+
+    for (txn_id, content) in failed.into_iter().rev() {
+        if let Some(content) = content_to_retry(content) {
+            queue.push_front(LocalMessage { txn_id, content });
+        }
+    }
+}
+
 async fn handle_message(
     msg: LocalMessage,
     room: Room,
     send_task: &mut SendMessageTask,
     queue: &mut VecDeque<LocalMessage>,
     timeline_inner: &TimelineInner,
+    is_paused: bool,
 ) {
-    if queue.is_empty() && send_task.is_idle() {
+    if queue.is_empty() && send_task.is_idle() && !is_paused {
         if room.state() == RoomState::Joined {
             send_task.start(room, timeline_inner.clone(), msg);
         } else {
@@ -128,17 +215,27 @@ async fn handle_task_ready(
     send_task: &mut SendMessageTask,
     queue: &mut VecDeque<LocalMessage>,
     timeline_inner: &TimelineInner,
+    send_queue: &RoomSendQueue,
 ) {
     match result {
         SendMessageResult::Success { room } => {
+            if send_queue.is_paused() {
+                return;
+            }
+
             if let Some(msg) = queue.pop_front() {
                 send_task.start(room, timeline_inner.clone(), msg);
             }
         }
-        SendMessageResult::SendingFailed => {
+        SendMessageResult::SendingFailed { is_network_error } => {
             // Timeline items are marked as failed / cancelled in this case.
             // Clear the timeline and wait for the user to explicitly retry.
             queue.clear();
+
+            if is_network_error {
+                info!("Pausing sending, the homeserver couldn't be reached");
+                send_queue.pause();
+            }
         }
         SendMessageResult::TaskError { join_error, txn_id } => {
             error!("Message-sending task failed: {join_error}");
@@ -163,7 +260,10 @@ enum SendMessageResult {
         room: Room,
     },
     /// Sending failed, and the local echo was updated to indicate this.
-    SendingFailed,
+    SendingFailed {
+        /// Whether sending failed because the homeserver couldn't be reached.
+        is_network_error: bool,
+    },
     /// The [`SendMessageTask`] failed, likely due to a panic.
     ///
     /// This means that the timeline item was likely not updated yet, which thus
@@ -186,7 +286,7 @@ enum SendMessageTask {
         /// The transaction ID of the message that is being sent.
         txn_id: OwnedTransactionId,
         /// Handle to the task itself.
-        join_handle: JoinHandle<Option<Room>>,
+        join_handle: JoinHandle<SendMessageResult>,
     },
 }
 
@@ -201,13 +301,19 @@ impl SendMessageTask {
         let txn_id = msg.txn_id.clone();
         let join_handle = spawn(async move {
             let result = room.send(msg.content).with_transaction_id(&msg.txn_id).await;
-            let (room, send_state) = match result {
-                Ok(response) => (Some(room), EventSendState::Sent { event_id: response.event_id }),
-                Err(error) => (None, EventSendState::SendingFailed { error: Arc::new(error) }),
+            let (result, send_state) = match result {
+                Ok(response) => (
+                    SendMessageResult::Success { room },
+                    EventSendState::Sent { event_id: response.event_id },
+                ),
+                Err(error) => (
+                    SendMessageResult::SendingFailed { is_network_error: is_network_error(&error) },
+                    EventSendState::SendingFailed { error: Arc::new(error) },
+                ),
             };
 
             timeline_inner.update_event_send_state(&msg.txn_id, send_state).await;
-            room
+            result
         });
         *self = Self::Running { txn_id, join_handle };
     }
@@ -231,8 +337,7 @@ impl Future for SendMessageTask {
                     }
 
                     match result {
-                        Ok(Some(room)) => SendMessageResult::Success { room },
-                        Ok(None) => SendMessageResult::SendingFailed,
+                        Ok(result) => result,
                         Err(join_error) => SendMessageResult::TaskError { join_error, txn_id },
                     }
                 })
@@ -21,9 +21,14 @@ use std::{
 use eyeball_im::ObservableVectorTransaction;
 use indexmap::IndexMap;
 use ruma::{
-    events::receipt::{Receipt, ReceiptEventContent, ReceiptThread, ReceiptType},
+    events::{
+        receipt::{Receipt, ReceiptEventContent, ReceiptThread, ReceiptType},
+        AnySyncTimelineEvent,
+    },
+    serde::Raw,
     EventId, OwnedEventId, OwnedUserId, UserId,
 };
+use serde::Deserialize;
 use tracing::{error, warn};
 
 use super::{
@@ -37,19 +42,55 @@ use super::{
     TimelineItem,
 };
 
+/// Get the ID of the root of the thread of the given receipt thread.
+///
+/// Returns `None` for receipts on the main timeline, unthreaded or not.
+pub(super) fn receipt_thread_root(thread: &ReceiptThread) -> Option<OwnedEventId> {
+    match thread {
+        ReceiptThread::Thread(root_event_id) => Some(root_event_id.clone()),
+        _ => None,
+    }
+}
+
+/// Get the ID of the root of the thread the given event is in, if any.
+pub(super) fn event_thread_root(event: &Raw<AnySyncTimelineEvent>) -> Option<OwnedEventId> {
+    #[derive(Deserialize)]
+    struct Content {
+        #[serde(rename = "m.relates_to")]
+        relates_to: Option<Relation>,
+    }
+
+    #[derive(Deserialize)]
+    struct Relation {
+        rel_type: Option<String>,
+        event_id: Option<OwnedEventId>,
+    }
+
+    let relation = event.get_field::<Content>("content").ok().flatten()?.relates_to?;
+    (relation.rel_type.as_deref() == Some("m.thread")).then_some(relation.event_id).flatten()
+}
+
 /// In-memory caches for read receipts.
 #[derive(Clone, Debug, Default)]
 pub(super) struct ReadReceipts {
     /// Map of public read receipts on events.
     ///
-    /// Event ID => User ID => Read receipt of the user.
-    by_event: HashMap<OwnedEventId, IndexMap<OwnedUserId, Receipt>>,
+    /// Event ID => (User ID, thread root event ID) => Read receipt of the user
+    /// in the thread, the thread root is `None` for the main timeline.
+    by_event: HashMap<OwnedEventId, IndexMap<(OwnedUserId, Option<OwnedEventId>), Receipt>>,
 
     /// In-memory cache of all latest read receipts by user.
     ///
     /// User ID => Receipt type => Read receipt of the user of the given
     /// type.
     latest_by_user: HashMap<OwnedUserId, HashMap<ReceiptType, (OwnedEventId, Receipt)>>,
+
+    /// In-memory cache of all latest read receipts in threads by user.
+    ///
+    /// User ID => Thread root event ID => Receipt type => Read receipt of the
+    /// user of the given type in the thread.
+    latest_in_threads_by_user:
+        HashMap<OwnedUserId, HashMap<OwnedEventId, HashMap<ReceiptType, (OwnedEventId, Receipt)>>>,
 }
 
 impl ReadReceipts {
@@ -57,6 +98,7 @@ impl ReadReceipts {
     pub(super) fn clear(&mut self) {
         self.by_event.clear();
         self.latest_by_user.clear();
+        self.latest_in_threads_by_user.clear();
     }
 
     /// Read the latest read receipt of the given type for the given user, from
@@ -69,6 +111,27 @@ impl ReadReceipts {
         self.latest_by_user.get(user_id).and_then(|map| map.get(receipt_type))
     }
 
+    /// Read the latest read receipt of the given type for the given user in
+    /// the given thread, from the in-memory cache.
+    ///
+    /// Unthreaded receipts and receipts on the main thread are both about the
+    /// main timeline, so they are treated as the same thread.
+    fn get_latest_in_thread(
+        &self,
+        user_id: &UserId,
+        receipt_type: &ReceiptType,
+        thread: &ReceiptThread,
+    ) -> Option<&(OwnedEventId, Receipt)> {
+        match thread {
+            ReceiptThread::Thread(root_event_id) => self
+                .latest_in_threads_by_user
+                .get(user_id)
+                .and_then(|map| map.get(root_event_id))
+                .and_then(|map| map.get(receipt_type)),
+            _ => self.get_latest(user_id, receipt_type),
+        }
+    }
+
     /// Insert or update in the local cache the latest read receipt for the
     /// given user.
     pub fn upsert_latest(
@@ -80,6 +143,27 @@ impl ReadReceipts {
         self.latest_by_user.entry(user_id).or_default().insert(receipt_type, read_receipt);
     }
 
+    /// Insert or update in the local cache the latest read receipt for the
+    /// given user, in the thread of the receipt.
+    fn upsert_latest_in_thread(
+        &mut self,
+        user_id: OwnedUserId,
+        receipt_type: ReceiptType,
+        read_receipt: (OwnedEventId, Receipt),
+    ) {
+        match &read_receipt.1.thread {
+            ReceiptThread::Thread(root_event_id) => {
+                self.latest_in_threads_by_user
+                    .entry(user_id)
+                    .or_default()
+                    .entry(root_event_id.clone())
+                    .or_default()
+                    .insert(receipt_type, read_receipt);
+            }
+            _ => self.upsert_latest(user_id, receipt_type, read_receipt),
+        }
+    }
+
     /// Update the timeline items with the given read receipt if it is more
     /// recent than the current one.
     ///
@@ -96,8 +180,12 @@ impl ReadReceipts {
         all_events: &VecDeque<EventMeta>,
         timeline_items: &mut ObservableVectorTransaction<'_, Arc<TimelineItem>>,
     ) {
-        // Get old receipt.
-        let old_receipt = self.get_latest(new_receipt.user_id, &new_receipt.receipt_type);
+        // Get old receipt, in the same thread.
+        let old_receipt = self.get_latest_in_thread(
+            new_receipt.user_id,
+            &new_receipt.receipt_type,
+            &new_receipt.receipt.thread,
+        );
         if old_receipt
             .as_ref()
             .is_some_and(|(old_receipt_event_id, _)| old_receipt_event_id == new_receipt.event_id)
@@ -158,10 +246,16 @@ impl ReadReceipts {
         // - If old_receipt_pos is None and new_receipt_pos is Some, the new receipt is
         //   more recent because it has a place in the timeline.
 
+        let thread_root = receipt_thread_root(&new_receipt.receipt.thread);
+
         if !is_own_user_id {
             // Remove the old receipt from the old event.
             if let Some(old_event_id) = old_event_id.cloned() {
-                self.remove_event_receipt_for_user(&old_event_id, new_receipt.user_id);
+                self.remove_event_receipt_for_user(
+                    &old_event_id,
+                    new_receipt.user_id,
+                    thread_root.as_deref(),
+                );
             }
             // Add the new receipt to the new event.
             self.add_event_receipt_for_user(
@@ -172,7 +266,7 @@ impl ReadReceipts {
         }
 
         // Update the receipt of the user.
-        self.upsert_latest(
+        self.upsert_latest_in_thread(
             new_receipt.user_id.to_owned(),
             new_receipt.receipt_type,
             (new_receipt.event_id.to_owned(), new_receipt.receipt.clone()),
@@ -189,30 +283,42 @@ impl ReadReceipts {
         };
         timeline_update.apply(
             timeline_items,
-            new_receipt.user_id.to_owned(),
+            (new_receipt.user_id.to_owned(), thread_root),
             new_receipt.receipt.clone(),
         );
     }
 
-    /// Returns the cached receipts by user for a given `event_id`.
-    fn get_event_receipts(&self, event_id: &EventId) -> Option<&IndexMap<OwnedUserId, Receipt>> {
+    /// Returns the cached receipts by user and thread for a given `event_id`.
+    fn get_event_receipts(
+        &self,
+        event_id: &EventId,
+    ) -> Option<&IndexMap<(OwnedUserId, Option<OwnedEventId>), Receipt>> {
         self.by_event.get(event_id)
     }
 
-    /// Mark the given event as seen by the user with the given receipt.
+    /// Mark the given event as seen by the user with the given receipt, in the
+    /// thread of the receipt.
     fn add_event_receipt_for_user(
         &mut self,
         event_id: OwnedEventId,
         user_id: OwnedUserId,
         receipt: Receipt,
     ) {
-        self.by_event.entry(event_id).or_default().insert(user_id, receipt);
+        let thread_root = receipt_thread_root(&receipt.thread);
+        self.by_event.entry(event_id).or_default().insert((user_id, thread_root), receipt);
     }
 
-    /// Unmark the given event as seen by the user.
-    fn remove_event_receipt_for_user(&mut self, event_id: &EventId, user_id: &UserId) {
+    /// Unmark the given event as seen by the user in the given thread.
+    fn remove_event_receipt_for_user(
+        &mut self,
+        event_id: &EventId,
+        user_id: &UserId,
+        thread_root: Option<&EventId>,
+    ) {
         if let Some(map) = self.by_event.get_mut(event_id) {
-            map.remove(user_id);
+            map.retain(|(receipt_user_id, receipt_thread_root), _| {
+                receipt_user_id != user_id || receipt_thread_root.as_deref() != thread_root
+            });
             // Remove the entire map if this was the last entry.
             if map.is_empty() {
                 self.by_event.remove(event_id);
@@ -220,7 +326,7 @@ impl ReadReceipts {
         }
     }
 
-    /// Get the read receipts by user for the given event.
+    /// Get the read receipts by user and thread for the given event.
     ///
     /// This includes all the receipts on the event as well as all the receipts
     /// on the following events that are filtered out (not visible).
@@ -229,7 +335,7 @@ impl ReadReceipts {
         event_id: &EventId,
         all_events: &VecDeque<EventMeta>,
         at_end: bool,
-    ) -> IndexMap<OwnedUserId, Receipt> {
+    ) -> IndexMap<(OwnedUserId, Option<OwnedEventId>), Receipt> {
         let mut all_receipts = self.get_event_receipts(event_id).cloned().unwrap_or_default();
 
         if at_end {
@@ -275,15 +381,17 @@ impl ReadReceiptTimelineUpdate {
     fn remove_old_receipt(
         &self,
         items: &mut ObservableVectorTransaction<'_, Arc<TimelineItem>>,
-        user_id: &UserId,
+        key: &(OwnedUserId, Option<OwnedEventId>),
     ) {
+        let (user_id, thread_root) = key;
+
         let Some(event_id) = &self.old_event_id else {
             // Nothing to do.
             return;
         };
 
         let Some((receipt_pos, event_item)) = rfind_event_by_id(items, event_id) else {
-            error!(%event_id, %user_id, "inconsistent state: old event item for read receipt was not found");
+            error!(%event_id, %user_id, ?thread_root, "inconsistent state: old event item for read receipt was not found");
             return;
         };
 
@@ -291,9 +399,9 @@ impl ReadReceiptTimelineUpdate {
         let mut event_item = event_item.clone();
 
         if let Some(remote_event_item) = event_item.as_remote_mut() {
-            if remote_event_item.read_receipts.remove(user_id).is_none() {
+            if remote_event_item.read_receipts.shift_remove(key).is_none() {
                 error!(
-                    %event_id, %user_id,
+                    %event_id, %user_id, ?thread_root,
                     "inconsistent state: old event item for user's read \
                      receipt doesn't have a receipt for the user"
                 );
@@ -308,7 +416,7 @@ impl ReadReceiptTimelineUpdate {
     fn add_new_receipt(
         self,
         items: &mut ObservableVectorTransaction<'_, Arc<TimelineItem>>,
-        user_id: OwnedUserId,
+        key: (OwnedUserId, Option<OwnedEventId>),
         receipt: Receipt,
     ) {
         let Some(event_id) = self.new_event_id else {
@@ -326,7 +434,7 @@ impl ReadReceiptTimelineUpdate {
         let mut event_item = event_item.clone();
 
         if let Some(remote_event_item) = event_item.as_remote_mut() {
-            remote_event_item.read_receipts.insert(user_id, receipt);
+            remote_event_item.read_receipts.insert(key, receipt);
             items.set(receipt_pos, timeline_item(event_item, event_item_id));
         } else {
             warn!("received a read receipt for a local item, this should not be possible");
@@ -337,11 +445,11 @@ impl ReadReceiptTimelineUpdate {
     fn apply(
         self,
         items: &mut ObservableVectorTransaction<'_, Arc<TimelineItem>>,
-        user_id: OwnedUserId,
+        key: (OwnedUserId, Option<OwnedEventId>),
         receipt: Receipt,
     ) {
-        self.remove_old_receipt(items, &user_id);
-        self.add_new_receipt(items, user_id, receipt);
+        self.remove_old_receipt(items, &key);
+        self.add_new_receipt(items, key, receipt);
     }
 }
 
@@ -359,7 +467,8 @@ impl TimelineInnerStateTransaction<'_> {
                 }
 
                 for (user_id, receipt) in receipts {
-                    if !matches!(receipt.thread, ReceiptThread::Unthreaded | ReceiptThread::Main) {
+                    // We don't know how to handle receipts in unknown threads.
+                    if let ReceiptThread::_Custom(_) = receipt.thread {
                         continue;
                     }
 
@@ -382,23 +491,26 @@ impl TimelineInnerStateTransaction<'_> {
         }
     }
 
-    /// Load the read receipts from the store for the given event ID.
+    /// Load the read receipts from the store for the given event ID, on the
+    /// main timeline and in the thread with the given root, if any.
     ///
     /// Populates the read receipts in-memory caches.
     pub(super) async fn load_read_receipts_for_event<P: RoomDataProvider>(
         &mut self,
         event_id: &EventId,
+        thread_root: Option<&EventId>,
         room_data_provider: &P,
     ) {
-        let read_receipts = room_data_provider.load_event_receipts(event_id).await;
+        let read_receipts = room_data_provider.load_event_receipts(event_id, thread_root).await;
 
         // Filter out receipts for our own user.
         let own_user_id = room_data_provider.own_user_id();
-        let read_receipts = read_receipts.into_iter().filter(|(user_id, _)| user_id != own_user_id);
+        let read_receipts =
+            read_receipts.into_iter().filter(|((user_id, _), _)| user_id != own_user_id);
 
         // Since they are explicit read receipts, we need to check if they are
         // superseded by implicit read receipts.
-        for (user_id, receipt) in read_receipts {
+        for ((user_id, _), receipt) in read_receipts {
             let full_receipt = FullReceipt {
                 event_id,
                 user_id: &user_id,
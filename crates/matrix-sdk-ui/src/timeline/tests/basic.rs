@@ -30,13 +30,14 @@ use ruma::{
         },
         FullStateEventContent,
     },
+    uint, MilliSecondsSinceUnixEpoch,
 };
 use stream_assert::assert_next_matches;
 
 use super::TestTimeline;
 use crate::timeline::{
-    event_item::AnyOtherFullStateEventContent, MembershipChange, TimelineDetails,
-    TimelineItemContent, TimelineItemKind, VirtualTimelineItem,
+    event_item::AnyOtherFullStateEventContent, CallNotifyType, CallOfferType, MembershipChange,
+    TimelineDetails, TimelineItemContent, TimelineItemKind, VirtualTimelineItem,
 };
 
 #[async_test]
@@ -99,6 +100,115 @@ async fn sticker() {
     assert_matches!(item.content(), TimelineItemContent::Sticker(_));
 }
 
+#[async_test]
+async fn voice_message() {
+    let timeline = TestTimeline::new();
+    let mut stream = timeline.subscribe_events().await;
+
+    timeline
+        .handle_live_custom_event(sync_timeline_event!({
+            "content": {
+                "body": "Voice message",
+                "msgtype": "m.audio",
+                "url": "mxc://server.name/JWEIFJgwEIhweiWJE",
+                "info": {
+                    "duration": 4000,
+                    "mimetype": "audio/ogg",
+                },
+                "org.matrix.msc1767.audio": {
+                    "duration": 4000,
+                    "waveform": [0, 512, 1024],
+                },
+                "org.matrix.msc3245.voice": {},
+            },
+            "event_id": "$143273582443PhrSn",
+            "origin_server_ts": 143273582,
+            "sender": "@alice:server.name",
+            "type": "m.room.message",
+        }))
+        .await;
+    timeline
+        .handle_live_custom_event(sync_timeline_event!({
+            "content": {
+                "body": "song.ogg",
+                "msgtype": "m.audio",
+                "url": "mxc://server.name/AQwafuaFswefuhsfAFAgsw",
+            },
+            "event_id": "$143273582443PhrSo",
+            "origin_server_ts": 143273583,
+            "sender": "@alice:server.name",
+            "type": "m.room.message",
+        }))
+        .await;
+
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert_let!(TimelineItemContent::Message(message) = item.content());
+    assert!(message.is_voice_message());
+    assert_eq!(message.voice_waveform(), Some(vec![0, 512, 1024]));
+
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert_let!(TimelineItemContent::Message(message) = item.content());
+    assert!(!message.is_voice_message());
+    assert_eq!(message.voice_waveform(), None);
+}
+
+#[async_test]
+async fn call_invite() {
+    let timeline = TestTimeline::new();
+    let mut stream = timeline.subscribe_events().await;
+
+    timeline
+        .handle_live_custom_event(sync_timeline_event!({
+            "content": {
+                "call_id": "12345",
+                "lifetime": 60000,
+                "offer": {
+                    "sdp": "v=0\r\no=- 6584580628695956864 2 IN IP4 127.0.0.1[...]",
+                    "type": "offer",
+                },
+                "version": 0,
+            },
+            "event_id": "$143273582443PhrSn",
+            "origin_server_ts": 143273582,
+            "sender": "@alice:server.name",
+            "type": "m.call.invite",
+        }))
+        .await;
+
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert_let!(TimelineItemContent::CallInvite(invite) = item.content());
+    assert_eq!(invite.call_id(), "12345");
+    assert_eq!(invite.lifetime(), uint!(60000));
+    assert_eq!(invite.offer_type(), CallOfferType::Voice);
+}
+
+#[async_test]
+async fn call_notify() {
+    let timeline = TestTimeline::new();
+    let mut stream = timeline.subscribe_events().await;
+
+    timeline
+        .handle_live_custom_event(sync_timeline_event!({
+            "content": {
+                "application": "m.call",
+                "call_id": "",
+                "m.mentions": { "room": true },
+                "notify_type": "ring",
+            },
+            "event_id": "$143273582443PhrSo",
+            "origin_server_ts": 143273583,
+            "sender": "@alice:server.name",
+            "type": "org.matrix.msc4075.call.notify",
+        }))
+        .await;
+
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    assert_let!(TimelineItemContent::CallNotify(notify) = item.content());
+    assert_eq!(notify.application(), "m.call");
+    assert_eq!(notify.call_id(), "");
+    assert_eq!(notify.notify_type(), CallNotifyType::Ring);
+}
+
 #[async_test]
 async fn room_member() {
     let timeline = TestTimeline::new();
@@ -407,3 +517,33 @@ async fn thread() {
     assert_let!(TimelineDetails::Ready(replied_to_event) = &in_reply_to.event);
     assert_eq!(replied_to_event.sender(), *ALICE);
 }
+
+#[async_test]
+async fn remove_events_before() {
+    let timeline = TestTimeline::new();
+    let day = 24 * 60 * 60 * 1000;
+
+    for (event_id, ts) in [("$a", 0), ("$b", 2 * day)] {
+        timeline
+            .handle_live_custom_event(sync_timeline_event!({
+                "content": {
+                    "body": "Hello",
+                    "msgtype": "m.text",
+                },
+                "event_id": event_id,
+                "origin_server_ts": ts,
+                "sender": &*ALICE,
+                "type": "m.room.message",
+            }))
+            .await;
+    }
+    assert_eq!(timeline.inner.items().await.len(), 4);
+
+    timeline.inner.remove_events_before(MilliSecondsSinceUnixEpoch(uint!(86_400_000))).await;
+
+    // The first event and its day divider were removed.
+    let items = timeline.inner.items().await;
+    assert_eq!(items.len(), 2);
+    assert_matches!(&items[0].kind, TimelineItemKind::Virtual(VirtualTimelineItem::DayDivider(_)));
+    assert_eq!(items[1].as_event().unwrap().event_id().unwrap(), "$b");
+}
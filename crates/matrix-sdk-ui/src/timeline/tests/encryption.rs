@@ -14,25 +14,47 @@
 
 #![cfg(not(target_arch = "wasm32"))]
 
-use std::{io::Cursor, iter};
+use std::{
+    io::Cursor,
+    iter,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use assert_matches::assert_matches;
 use assert_matches2::assert_let;
 use eyeball_im::VectorDiff;
-use matrix_sdk::crypto::{decrypt_room_key_export, OlmMachine};
+use matrix_sdk::{
+    clock::TestClock,
+    crypto::{decrypt_room_key_export, OlmMachine},
+};
 use matrix_sdk_test::{async_test, BOB};
 use ruma::{
-    assign,
+    assign, event_id,
     events::room::encrypted::{
         EncryptedEventScheme, MegolmV1AesSha2ContentInit, Relation, Replacement,
         RoomEncryptedEventContent,
     },
-    room_id, user_id,
+    room_id, user_id, MilliSecondsSinceUnixEpoch,
 };
 use stream_assert::assert_next_matches;
 
-use super::TestTimeline;
-use crate::timeline::{EncryptedMessage, TimelineItemContent};
+use super::{TestRoomDataProvider, TestTimeline};
+use crate::timeline::{
+    inner::TimelineInnerSettings, EncryptedMessage, TimelineItemContent, UnableToDecryptHook,
+    UnableToDecryptInfo,
+};
+
+#[derive(Debug, Default)]
+struct TestUtdHook {
+    late_decrypts: Mutex<Vec<UnableToDecryptInfo>>,
+}
+
+impl UnableToDecryptHook for TestUtdHook {
+    fn on_late_decrypt(&self, info: UnableToDecryptInfo) {
+        self.late_decrypts.lock().unwrap().push(info);
+    }
+}
 
 #[async_test]
 async fn retry_message_decryption() {
@@ -117,6 +139,107 @@ async fn retry_message_decryption() {
     assert!(!event.is_highlighted());
 }
 
+#[async_test]
+async fn utd_hook_reports_late_decryptions() {
+    const SESSION_ID: &str = "gM8i47Xhu0q52xLfgUXzanCMpLinoyVyH7R58cBuVBU";
+    const SESSION_KEY: &[u8] = b"\
+        -----BEGIN MEGOLM SESSION DATA-----\n\
+        ASKcWoiAVUM97482UAi83Avce62hSLce7i5JhsqoF6xeAAAACqt2Cg3nyJPRWTTMXxXH7TXnkfdlmBXbQtq5\
+        bpHo3LRijcq2Gc6TXilESCmJN14pIsfKRJrWjZ0squ/XsoTFytuVLWwkNaW3QF6obeg2IoVtJXLMPdw3b2vO\
+        vgwGY3OMP0XafH13j1vcb6YLzvgLkZQLnYvd47hv3yK/9GmKS9tokuaQ7dCVYckYcIOS09EDTs70YdxUd5WG\
+        rQynATCLFP1p/NAGv70r9MK7Cy/mNpjD0r4qC7UEDIoi1kOWzHgnLo19wtvwsb8Fg8ATxcs3Wmtj8hIUYpDx\
+        ia4sM10zbytUuaPUAfCDf42IyxdmOnGe1CueXhgI71y+RW0s0argNqUt7jB70JT0o9CyX6UBGRaqLk2MPY9T\
+        hUu5J8X3UgIa6rcbWigzohzWm9rdbEHFrSWqjpfQYMaAKQQgETrjSy4XTrp2RhC2oNqG/hylI4ab+F4X6fpH\
+        DYP1NqNMP5g36xNu7LhDnrUB5qsPjYOmWORxGLfudpF3oLYCSlr3DgHqEIB6HjQblLZ3KQuPBse3zxyROTnS\
+        AhdPH4a/z1wioFtKNVph3hecsiKEdqnz4Y2coSIdhz58mJ9JWNQoFAENE5CSsoEZAGvafYZVpW4C75YY2zq1\
+        wIeiFi1dT43/jLAUGkslsi1VvnyfUu8qO404RxYO3XHoGLMFoFLOO+lZ+VGci2Vz10AhxJhEBHxRKxw4k2uB\
+        HztoSJUr/2Y\n\
+        -----END MEGOLM SESSION DATA-----";
+
+    let clock = TestClock::new();
+    let utd_hook = Arc::new(TestUtdHook::default());
+    let timeline =
+        TestTimeline::with_room_data_provider(TestRoomDataProvider::with_clock(clock.clone()))
+            .with_settings(TimelineInnerSettings {
+                utd_hook: Some(utd_hook.clone()),
+                ..Default::default()
+            });
+
+    let event_id = event_id!("$utd");
+    timeline.handle_live_message_event_with_id(&BOB, event_id, utd_content(SESSION_ID)).await;
+    assert_eq!(timeline.inner.utd_index_len().await, 1);
+
+    clock.advance(Duration::from_secs(5));
+
+    let own_user_id = user_id!("@example:morheus.localhost");
+    let exported_keys = decrypt_room_key_export(Cursor::new(SESSION_KEY), "1234").unwrap();
+
+    let olm_machine = OlmMachine::new(own_user_id, "SomeDeviceId".into()).await;
+    olm_machine.store().import_exported_room_keys(exported_keys, |_, _| {}).await.unwrap();
+
+    timeline
+        .inner
+        .retry_event_decryption_test(
+            room_id!("!DovneieKSTkdHKpIXy:morpheus.localhost"),
+            olm_machine,
+            Some(iter::once(SESSION_ID.to_owned()).collect()),
+        )
+        .await;
+
+    // The hook was told how long it took to decrypt the event.
+    let late_decrypts = utd_hook.late_decrypts.lock().unwrap();
+    assert_eq!(late_decrypts.len(), 1);
+    assert_eq!(late_decrypts[0].event_id, event_id);
+    assert_eq!(late_decrypts[0].session_id, SESSION_ID);
+    assert_eq!(late_decrypts[0].time_to_decrypt, Duration::from_secs(5));
+
+    assert_eq!(timeline.inner.utd_index_len().await, 0);
+}
+
+#[async_test]
+async fn utd_index_forgets_redacted_and_purged_events() {
+    const SESSION_ID: &str = "gM8i47Xhu0q52xLfgUXzanCMpLinoyVyH7R58cBuVBU";
+
+    let timeline = TestTimeline::new();
+
+    for event_id in [event_id!("$a"), event_id!("$b")] {
+        timeline.handle_live_message_event_with_id(&BOB, event_id, utd_content(SESSION_ID)).await;
+    }
+    assert_eq!(timeline.inner.utd_index_len().await, 2);
+
+    // A redacted event can't be decrypted anymore.
+    timeline.handle_live_redaction(&BOB, event_id!("$a")).await;
+    assert_eq!(timeline.inner.utd_index_len().await, 1);
+
+    // Neither can a purged event.
+    timeline.inner.remove_events_before(MilliSecondsSinceUnixEpoch::now()).await;
+    assert_eq!(timeline.inner.items().await.len(), 0);
+    assert_eq!(timeline.inner.utd_index_len().await, 0);
+}
+
+/// The content of a message encrypted with the given megolm session.
+fn utd_content(session_id: &str) -> RoomEncryptedEventContent {
+    RoomEncryptedEventContent::new(
+        EncryptedEventScheme::MegolmV1AesSha2(
+            MegolmV1AesSha2ContentInit {
+                ciphertext: "\
+                    AwgAEtABPRMavuZMDJrPo6pGQP4qVmpcuapuXtzKXJyi3YpEsjSWdzuRKIgJzD4P\
+                    cSqJM1A8kzxecTQNJsC5q22+KSFEPxPnI4ltpm7GFowSoPSW9+bFdnlfUzEP1jPq\
+                    YevHAsMJp2fRKkzQQbPordrUk1gNqEpGl4BYFeRqKl9GPdKFwy45huvQCLNNueql\
+                    CFZVoYMuhxrfyMiJJAVNTofkr2um2mKjDTlajHtr39pTG8k0eOjSXkLOSdZvNOMz\
+                    hGhSaFNeERSA2G2YbeknOvU7MvjiO0AKuxaAe1CaVhAI14FCgzrJ8g0y5nly+n7x\
+                    QzL2G2Dn8EoXM5Iqj8W99iokQoVsSrUEnaQ1WnSIfewvDDt4LCaD/w7PGETMCQ"
+                    .to_owned(),
+                sender_key: "DeHIg4gwhClxzFYcmNntPNF9YtsdZbmMy8+3kzCMXHA".to_owned(),
+                device_id: "NLAZCWIOCO".into(),
+                session_id: session_id.into(),
+            }
+            .into(),
+        ),
+        None,
+    )
+}
+
 #[async_test]
 async fn retry_edit_decryption() {
     const SESSION1_KEY: &[u8] = b"\
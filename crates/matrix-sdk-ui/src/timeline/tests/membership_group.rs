@@ -0,0 +1,143 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use assert_matches::assert_matches;
+use assert_matches2::assert_let;
+use futures_util::StreamExt;
+use matrix_sdk_test::{async_test, ALICE, BOB, CAROL};
+use ruma::{
+    events::room::{
+        member::{MembershipState, RoomMemberEventContent},
+        message::RoomMessageEventContent,
+    },
+    UserId,
+};
+
+use super::TestTimeline;
+use crate::timeline::{
+    inner::TimelineInnerSettings, GroupedTimelineItem, MembershipChangeSummary, TimelineItemContent,
+};
+
+impl TestTimeline {
+    async fn handle_live_join(&self, user_id: &UserId) {
+        self.handle_live_state_event_with_state_key(
+            user_id,
+            user_id.to_owned(),
+            RoomMemberEventContent::new(MembershipState::Join),
+            None,
+        )
+        .await;
+    }
+}
+
+fn grouped_timeline(min_group_size: usize) -> TestTimeline {
+    TestTimeline::new().with_settings(TimelineInnerSettings {
+        min_membership_group_size: Some(min_group_size),
+        ..Default::default()
+    })
+}
+
+#[async_test]
+async fn group_consecutive_membership_changes() {
+    let timeline = grouped_timeline(3);
+
+    timeline.handle_live_message_event(&ALICE, RoomMessageEventContent::text_plain("Hi!")).await;
+    timeline.handle_live_join(&ALICE).await;
+    timeline.handle_live_join(&BOB).await;
+    timeline.handle_live_join(&CAROL).await;
+    timeline.handle_live_message_event(&BOB, RoomMessageEventContent::text_plain("Hello")).await;
+
+    let items = timeline.inner.grouped_items().await;
+    assert_eq!(items.len(), 4);
+
+    assert_let!(GroupedTimelineItem::Single(day_divider) = &items[0]);
+    assert!(day_divider.is_day_divider());
+    assert_let!(GroupedTimelineItem::Single(message) = &items[1]);
+    assert_matches!(message.as_event().unwrap().content(), TimelineItemContent::Message(_));
+    assert_let!(GroupedTimelineItem::Single(message) = &items[3]);
+    assert_matches!(message.as_event().unwrap().content(), TimelineItemContent::Message(_));
+
+    assert_let!(GroupedTimelineItem::MembershipChanges(group) = &items[2]);
+    assert_eq!(group.len(), 3);
+    assert_eq!(group.unique_id(), group.items()[0].unique_id());
+
+    // The group can be expanded to the underlying items.
+    let users: Vec<_> = group
+        .items()
+        .iter()
+        .map(|item| {
+            assert_let!(
+                TimelineItemContent::MembershipChange(change) = item.as_event().unwrap().content()
+            );
+            change.user_id().to_owned()
+        })
+        .collect();
+    assert_eq!(users, [ALICE.to_owned(), BOB.to_owned(), CAROL.to_owned()]);
+
+    assert_eq!(
+        group.summary(),
+        MembershipChangeSummary {
+            joined: 3,
+            users: vec![ALICE.to_owned(), BOB.to_owned(), CAROL.to_owned()],
+            ..Default::default()
+        }
+    );
+}
+
+#[async_test]
+async fn short_runs_are_not_grouped() {
+    let timeline = grouped_timeline(3);
+
+    timeline.handle_live_join(&ALICE).await;
+    timeline.handle_live_join(&BOB).await;
+    timeline.handle_live_message_event(&BOB, RoomMessageEventContent::text_plain("Hello")).await;
+
+    let items = timeline.inner.grouped_items().await;
+    assert_eq!(items.len(), 4);
+    assert!(items.iter().all(|item| matches!(item, GroupedTimelineItem::Single(_))));
+}
+
+#[async_test]
+async fn no_grouping_by_default() {
+    let timeline = TestTimeline::new();
+
+    timeline.handle_live_join(&ALICE).await;
+    timeline.handle_live_join(&BOB).await;
+    timeline.handle_live_join(&CAROL).await;
+
+    let items = timeline.inner.grouped_items().await;
+    assert_eq!(items.len(), 4);
+    assert!(items.iter().all(|item| matches!(item, GroupedTimelineItem::Single(_))));
+}
+
+#[async_test]
+async fn subscribe_grouped_updates_groups() {
+    let timeline = grouped_timeline(2);
+    let (items, mut stream) = timeline.inner.subscribe_grouped().await;
+    assert!(items.is_empty());
+
+    timeline.handle_live_join(&ALICE).await;
+
+    // The day divider and the membership change.
+    let items = stream.next().await.unwrap();
+    assert_eq!(items.len(), 2);
+    assert_matches!(&items[1], GroupedTimelineItem::Single(_));
+
+    timeline.handle_live_join(&BOB).await;
+
+    let items = stream.next().await.unwrap();
+    assert_eq!(items.len(), 2);
+    assert_let!(GroupedTimelineItem::MembershipChanges(group) = &items[1]);
+    assert_eq!(group.len(), 2);
+}
@@ -25,7 +25,10 @@ use eyeball_im::VectorDiff;
 use futures_core::Stream;
 use futures_util::{FutureExt, StreamExt};
 use indexmap::IndexMap;
-use matrix_sdk::deserialized_responses::{SyncTimelineEvent, TimelineEvent};
+use matrix_sdk::{
+    clock::{Clock, TestClock},
+    deserialized_responses::{SyncTimelineEvent, TimelineEvent},
+};
 use matrix_sdk_base::latest_event::LatestEvent;
 use matrix_sdk_test::{EventBuilder, ALICE, BOB};
 use ruma::{
@@ -62,6 +65,7 @@ mod edit;
 mod encryption;
 mod event_filter;
 mod invalid;
+mod membership_group;
 mod pagination;
 mod polls;
 mod reaction_group;
@@ -269,11 +273,16 @@ type ReadReceiptMap =
 #[derive(Clone, Default)]
 struct TestRoomDataProvider {
     initial_user_receipts: ReadReceiptMap,
+    clock: TestClock,
 }
 
 impl TestRoomDataProvider {
     fn with_initial_user_receipts(initial_user_receipts: ReadReceiptMap) -> Self {
-        Self { initial_user_receipts }
+        Self { initial_user_receipts, ..Default::default() }
+    }
+
+    fn with_clock(clock: TestClock) -> Self {
+        Self { clock, ..Default::default() }
     }
 }
 
@@ -287,6 +296,10 @@ impl RoomDataProvider for TestRoomDataProvider {
         RoomVersionId::V10
     }
 
+    fn clock(&self) -> Arc<dyn Clock> {
+        Arc::new(self.clock.clone())
+    }
+
     async fn profile_from_user_id(&self, _user_id: &UserId) -> Option<Profile> {
         None
     }
@@ -308,9 +321,13 @@ impl RoomDataProvider for TestRoomDataProvider {
             .cloned()
     }
 
-    async fn load_event_receipts(&self, event_id: &EventId) -> IndexMap<OwnedUserId, Receipt> {
+    async fn load_event_receipts(
+        &self,
+        event_id: &EventId,
+        _thread_root: Option<&EventId>,
+    ) -> IndexMap<(OwnedUserId, Option<OwnedEventId>), Receipt> {
         if event_id == event_id!("$event_with_bob_receipt") {
-            [(BOB.to_owned(), Receipt::new(MilliSecondsSinceUnixEpoch(uint!(10))))].into()
+            [((BOB.to_owned(), None), Receipt::new(MilliSecondsSinceUnixEpoch(uint!(10))))].into()
         } else {
             IndexMap::new()
         }
@@ -59,7 +59,7 @@ async fn read_receipts_updates_on_live_events() {
     let item_b = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
     let event_b = item_b.as_event().unwrap();
     assert_eq!(event_b.read_receipts().len(), 1);
-    assert!(event_b.read_receipts().get(*BOB).is_some());
+    assert!(event_b.read_receipts().get(&(BOB.to_owned(), None)).is_some());
 
     // Implicit read receipt of Bob is updated.
     timeline.handle_live_message_event(*BOB, RoomMessageEventContent::text_plain("C")).await;
@@ -71,7 +71,7 @@ async fn read_receipts_updates_on_live_events() {
     let item_c = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
     let event_c = item_c.as_event().unwrap();
     assert_eq!(event_c.read_receipts().len(), 1);
-    assert!(event_c.read_receipts().get(*BOB).is_some());
+    assert!(event_c.read_receipts().get(&(BOB.to_owned(), None)).is_some());
 
     timeline.handle_live_message_event(*ALICE, RoomMessageEventContent::text_plain("D")).await;
 
@@ -96,7 +96,88 @@ async fn read_receipts_updates_on_live_events() {
     let item_d = assert_next_matches!(stream, VectorDiff::Set { index: 4, value } => value);
     let event_d = item_d.as_event().unwrap();
     assert_eq!(event_d.read_receipts().len(), 1);
-    assert!(event_d.read_receipts().get(*BOB).is_some());
+    assert!(event_d.read_receipts().get(&(BOB.to_owned(), None)).is_some());
+}
+
+#[async_test]
+async fn read_receipts_in_threads_are_tracked_separately() {
+    let timeline = TestTimeline::new()
+        .with_settings(TimelineInnerSettings { track_read_receipts: true, ..Default::default() });
+    let mut stream = timeline.subscribe().await;
+
+    timeline.handle_live_message_event(*ALICE, RoomMessageEventContent::text_plain("A")).await;
+    timeline.handle_live_message_event(*ALICE, RoomMessageEventContent::text_plain("B")).await;
+    timeline.handle_live_message_event(*ALICE, RoomMessageEventContent::text_plain("C")).await;
+
+    let _day_divider = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    let item_a = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    let event_a_id = item_a.as_event().unwrap().event_id().unwrap().to_owned();
+    let item_b = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    let event_b_id = item_b.as_event().unwrap().event_id().unwrap().to_owned();
+    let item_c = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    let event_c_id = item_c.as_event().unwrap().event_id().unwrap().to_owned();
+
+    // Read receipt of Bob on the main timeline.
+    timeline
+        .handle_read_receipts([(
+            event_a_id.clone(),
+            ReceiptType::Read,
+            BOB.to_owned(),
+            ReceiptThread::Unthreaded,
+        )])
+        .await;
+
+    let item_a = assert_next_matches!(stream, VectorDiff::Set { index: 1, value } => value);
+    let event_a = item_a.as_event().unwrap();
+    assert_eq!(event_a.read_receipts().len(), 1);
+    assert_eq!(
+        event_a.read_receipts().get(&(BOB.to_owned(), None)).unwrap().thread,
+        ReceiptThread::Unthreaded
+    );
+
+    // Read receipt of Bob in the thread of A, it doesn't replace the one on the
+    // main timeline.
+    let thread = ReceiptThread::Thread(event_a_id.clone());
+    let thread_key = (BOB.to_owned(), Some(event_a_id.clone()));
+    timeline
+        .handle_read_receipts([(
+            event_c_id.clone(),
+            ReceiptType::Read,
+            BOB.to_owned(),
+            thread.clone(),
+        )])
+        .await;
+
+    let item_c = assert_next_matches!(stream, VectorDiff::Set { index: 3, value } => value);
+    let event_c = item_c.as_event().unwrap();
+    assert_eq!(event_c.read_receipts().len(), 1);
+    assert_eq!(event_c.read_receipts().get(&thread_key).unwrap().thread, thread);
+    assert_pending!(stream);
+
+    // Bob's receipt on the main timeline moves to C too, next to the one in the
+    // thread.
+    timeline
+        .handle_read_receipts([(
+            event_c_id.clone(),
+            ReceiptType::Read,
+            BOB.to_owned(),
+            ReceiptThread::Unthreaded,
+        )])
+        .await;
+
+    let item_a = assert_next_matches!(stream, VectorDiff::Set { index: 1, value } => value);
+    assert!(item_a.as_event().unwrap().read_receipts().is_empty());
+
+    let item_c = assert_next_matches!(stream, VectorDiff::Set { index: 3, value } => value);
+    let event_c = item_c.as_event().unwrap();
+    assert_eq!(event_c.read_receipts().len(), 2);
+    assert!(event_c.read_receipts().get(&(BOB.to_owned(), None)).is_some());
+    assert!(event_c.read_receipts().get(&thread_key).is_some());
+    assert_pending!(stream);
+
+    // An older read receipt of Bob in the same thread is ignored.
+    timeline.handle_read_receipts([(event_b_id, ReceiptType::Read, BOB.to_owned(), thread)]).await;
+    assert_pending!(stream);
 }
 
 #[async_test]
@@ -129,12 +210,12 @@ async fn read_receipts_updates_on_back_paginated_events() {
     // Implicit read receipt of Bob.
     let event_a = items[2].as_event().unwrap();
     assert_eq!(event_a.read_receipts().len(), 1);
-    assert!(event_a.read_receipts().get(*BOB).is_some());
+    assert!(event_a.read_receipts().get(&(BOB.to_owned(), None)).is_some());
 
     // Implicit read receipt of Carol, explicit read receipt of Bob ignored.
     let event_b = items[1].as_event().unwrap();
     assert_eq!(event_b.read_receipts().len(), 1);
-    assert!(event_b.read_receipts().get(*CAROL).is_some());
+    assert!(event_b.read_receipts().get(&(CAROL.to_owned(), None)).is_some());
 }
 
 #[async_test]
@@ -160,7 +241,7 @@ async fn read_receipts_updates_on_filtered_events() {
     let item_a = assert_next_matches!(stream, VectorDiff::Set { index: 1, value } => value);
     let event_a = item_a.as_event().unwrap();
     assert_eq!(event_a.read_receipts().len(), 1);
-    assert!(event_a.read_receipts().get(*BOB).is_some());
+    assert!(event_a.read_receipts().get(&(BOB.to_owned(), None)).is_some());
 
     // Implicit read receipt of Bob is updated.
     timeline.handle_live_message_event(*BOB, RoomMessageEventContent::text_plain("C")).await;
@@ -172,7 +253,7 @@ async fn read_receipts_updates_on_filtered_events() {
     let item_c = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
     let event_c = item_c.as_event().unwrap();
     assert_eq!(event_c.read_receipts().len(), 1);
-    assert!(event_c.read_receipts().get(*BOB).is_some());
+    assert!(event_c.read_receipts().get(&(BOB.to_owned(), None)).is_some());
 
     // Populate more events.
     let event_d_id = owned_event_id!("$event_d");
@@ -217,7 +298,7 @@ async fn read_receipts_updates_on_filtered_events() {
     let item_e = assert_next_matches!(stream, VectorDiff::Set { index: 3, value } => value);
     let event_e = item_e.as_event().unwrap();
     assert_eq!(event_e.read_receipts().len(), 1);
-    assert!(event_e.read_receipts().get(*BOB).is_some());
+    assert!(event_e.read_receipts().get(&(BOB.to_owned(), None)).is_some());
 
     assert_pending!(stream);
 }
@@ -251,14 +332,14 @@ async fn read_receipts_updates_on_filtered_events_with_stored() {
     let item_a = assert_next_matches!(stream, VectorDiff::Set { index: 1, value } => value);
     let event_a = item_a.as_event().unwrap();
     assert_eq!(event_a.read_receipts().len(), 1);
-    assert!(event_a.read_receipts().get(*BOB).is_some());
+    assert!(event_a.read_receipts().get(&(BOB.to_owned(), None)).is_some());
 
     // Implicit read receipt of Carol.
     let item_a = assert_next_matches!(stream, VectorDiff::Set { index: 1, value } => value);
     let event_a = item_a.as_event().unwrap();
     assert_eq!(event_a.read_receipts().len(), 2);
-    assert!(event_a.read_receipts().get(*BOB).is_some());
-    assert!(event_a.read_receipts().get(*CAROL).is_some());
+    assert!(event_a.read_receipts().get(&(BOB.to_owned(), None)).is_some());
+    assert!(event_a.read_receipts().get(&(CAROL.to_owned(), None)).is_some());
 
     // Implicit read receipt of Bob is updated.
     timeline.handle_live_message_event(*BOB, RoomMessageEventContent::text_plain("C")).await;
@@ -270,7 +351,7 @@ async fn read_receipts_updates_on_filtered_events_with_stored() {
     let item_c = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
     let event_c = item_c.as_event().unwrap();
     assert_eq!(event_c.read_receipts().len(), 1);
-    assert!(event_c.read_receipts().get(*BOB).is_some());
+    assert!(event_c.read_receipts().get(&(BOB.to_owned(), None)).is_some());
 
     assert_pending!(stream);
 }
@@ -323,8 +404,8 @@ async fn read_receipts_updates_on_back_paginated_filtered_events() {
     let item_c = assert_next_matches!(stream, VectorDiff::Insert { index: 1, value } => value);
     let event_c = item_c.as_event().unwrap();
     assert_eq!(event_c.read_receipts().len(), 2);
-    assert!(event_c.read_receipts().get(*BOB).is_some());
-    assert!(event_c.read_receipts().get(*CAROL).is_some());
+    assert!(event_c.read_receipts().get(&(BOB.to_owned(), None)).is_some());
+    assert!(event_c.read_receipts().get(&(CAROL.to_owned(), None)).is_some());
 
     assert_pending!(stream);
 }
@@ -418,7 +499,7 @@ async fn read_receipts_updates_on_message_decryption() {
     let clear_event = clear_item.as_event().unwrap();
     assert_matches!(clear_event.content(), TimelineItemContent::Message(_));
     assert_eq!(clear_event.read_receipts().len(), 1);
-    assert!(clear_event.read_receipts().get(*CAROL).is_some());
+    assert!(clear_event.read_receipts().get(&(CAROL.to_owned(), None)).is_some());
 
     // The second event is encrypted and only has Bob's receipt.
     let encrypted_item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
@@ -431,7 +512,7 @@ async fn read_receipts_updates_on_message_decryption() {
     );
     assert_eq!(session_id, SESSION_ID);
     assert_eq!(encrypted_event.read_receipts().len(), 1);
-    assert!(encrypted_event.read_receipts().get(*BOB).is_some());
+    assert!(encrypted_event.read_receipts().get(&(BOB.to_owned(), None)).is_some());
 
     // Decrypt encrypted message.
     let own_user_id = user_id!("@example:morheus.localhost");
@@ -456,8 +537,8 @@ async fn read_receipts_updates_on_message_decryption() {
     let clear_event = clear_item.as_event().unwrap();
     assert_matches!(clear_event.content(), TimelineItemContent::Message(_));
     assert_eq!(clear_event.read_receipts().len(), 2);
-    assert!(clear_event.read_receipts().get(*CAROL).is_some());
-    assert!(clear_event.read_receipts().get(*BOB).is_some());
+    assert!(clear_event.read_receipts().get(&(CAROL.to_owned(), None)).is_some());
+    assert!(clear_event.read_receipts().get(&(BOB.to_owned(), None)).is_some());
 
     // The second event is removed.
     assert_next_matches!(stream, VectorDiff::Remove { index: 2 });
@@ -583,7 +664,7 @@ async fn clear_read_receipts() {
     // Implicit read receipt of Bob.
     let event_a = items[1].as_event().unwrap();
     assert_eq!(event_a.read_receipts().len(), 1);
-    assert!(event_a.read_receipts().get(*BOB).is_some());
+    assert!(event_a.read_receipts().get(&(BOB.to_owned(), None)).is_some());
 
     // We received a limited timeline.
     timeline.inner.clear().await;
@@ -611,5 +692,5 @@ async fn clear_read_receipts() {
     // New implicit read receipt of Bob.
     let event_b = items[2].as_event().unwrap();
     assert_eq!(event_b.read_receipts().len(), 1);
-    assert!(event_b.read_receipts().get(*BOB).is_some());
+    assert!(event_b.read_receipts().get(&(BOB.to_owned(), None)).is_some());
 }
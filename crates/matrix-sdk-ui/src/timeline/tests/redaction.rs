@@ -16,7 +16,7 @@ use assert_matches::assert_matches;
 use assert_matches2::assert_let;
 use eyeball_im::VectorDiff;
 use imbl::vector;
-use matrix_sdk_base::deserialized_responses::SyncTimelineEvent;
+use matrix_sdk_base::{deserialized_responses::SyncTimelineEvent, AppliedRedaction};
 use matrix_sdk_test::{async_test, sync_timeline_event, ALICE, BOB};
 use ruma::{
     events::{
@@ -35,7 +35,7 @@ use ruma::{
 };
 use stream_assert::assert_next_matches;
 
-use super::TestTimeline;
+use super::{assert_no_more_updates, TestTimeline};
 use crate::timeline::{AnyOtherFullStateEventContent, TimelineDetails, TimelineItemContent};
 
 #[async_test]
@@ -245,3 +245,32 @@ async fn receive_unredacted() {
     assert!(items[1].as_event().unwrap().content.is_redacted());
     assert!(items[2].as_event().unwrap().content.is_redacted());
 }
+
+#[async_test]
+async fn local_redaction_is_applied_once() {
+    let timeline = TestTimeline::new();
+    let mut stream = timeline.subscribe_events().await;
+
+    timeline
+        .handle_live_message_event(&ALICE, RoomMessageEventContent::text_plain("Hello, world!"))
+        .await;
+    let item = assert_next_matches!(stream, VectorDiff::PushBack { value } => value);
+    let event_id = item.event_id().unwrap().to_owned();
+
+    let redaction = timeline.event_builder.make_redaction_event(&ALICE, &event_id);
+    timeline
+        .inner
+        .handle_applied_redaction(AppliedRedaction {
+            redacts: event_id.clone(),
+            redaction: redaction.cast(),
+            is_local: true,
+        })
+        .await;
+
+    let item = assert_next_matches!(stream, VectorDiff::Set { index: 0, value } => value);
+    assert!(item.content().is_redacted());
+
+    // The redaction comes back via sync, the item was already redacted.
+    timeline.handle_live_redaction(&ALICE, &event_id).await;
+    assert_no_more_updates(&mut stream).await;
+}
@@ -12,9 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::sync::Arc;
+
 use async_trait::async_trait;
 use indexmap::IndexMap;
-use matrix_sdk::Room;
+use matrix_sdk::{clock::Clock, Room};
 #[cfg(feature = "e2e-encryption")]
 use matrix_sdk::{deserialized_responses::TimelineEvent, Result};
 use matrix_sdk_base::latest_event::LatestEvent;
@@ -30,7 +32,7 @@ use ruma::{
 };
 use tracing::{debug, error, warn};
 
-use super::{Profile, TimelineBuilder};
+use super::{read_receipts::receipt_thread_root, Profile, TimelineBuilder};
 use crate::timeline::Timeline;
 
 #[async_trait]
@@ -89,6 +91,7 @@ impl RoomExt for Room {
 pub(super) trait RoomDataProvider: Clone + Send + Sync + 'static {
     fn own_user_id(&self) -> &UserId;
     fn room_version(&self) -> RoomVersionId;
+    fn clock(&self) -> Arc<dyn Clock>;
     async fn profile_from_user_id(&self, user_id: &UserId) -> Option<Profile>;
     async fn profile_from_latest_event(&self, latest_event: &LatestEvent) -> Option<Profile>;
 
@@ -100,8 +103,16 @@ pub(super) trait RoomDataProvider: Clone + Send + Sync + 'static {
         user_id: &UserId,
     ) -> Option<(OwnedEventId, Receipt)>;
 
-    /// Loads read receipts for an event from the storage backend.
-    async fn load_event_receipts(&self, event_id: &EventId) -> IndexMap<OwnedUserId, Receipt>;
+    /// Loads read receipts for an event from the storage backend, on the main
+    /// timeline and in the thread with the given root, if any.
+    ///
+    /// The receipts are keyed by user and by the root of their thread, or
+    /// `None` for the main timeline.
+    async fn load_event_receipts(
+        &self,
+        event_id: &EventId,
+        thread_root: Option<&EventId>,
+    ) -> IndexMap<(OwnedUserId, Option<OwnedEventId>), Receipt>;
 
     async fn push_rules_and_context(&self) -> Option<(Ruleset, PushConditionRoomCtx)>;
 }
@@ -119,6 +130,10 @@ impl RoomDataProvider for Room {
         })
     }
 
+    fn clock(&self) -> Arc<dyn Clock> {
+        self.client().clock().clone()
+    }
+
     async fn profile_from_user_id(&self, user_id: &UserId) -> Option<Profile> {
         match self.get_member_no_sync(user_id).await {
             Ok(Some(member)) => Some(Profile {
@@ -167,31 +182,37 @@ impl RoomDataProvider for Room {
         }
     }
 
-    async fn load_event_receipts(&self, event_id: &EventId) -> IndexMap<OwnedUserId, Receipt> {
-        let mut unthreaded_receipts = match self
-            .load_event_receipts(ReceiptType::Read, ReceiptThread::Unthreaded, event_id)
-            .await
-        {
-            Ok(receipts) => receipts.into_iter().collect(),
-            Err(e) => {
-                error!(?event_id, "Failed to get unthreaded read receipts for event: {e}");
-                IndexMap::new()
-            }
-        };
+    async fn load_event_receipts(
+        &self,
+        event_id: &EventId,
+        thread_root: Option<&EventId>,
+    ) -> IndexMap<(OwnedUserId, Option<OwnedEventId>), Receipt> {
+        // The unthreaded receipts are loaded first, so the main thread receipts
+        // replace them.
+        let mut threads = vec![ReceiptThread::Unthreaded, ReceiptThread::Main];
+        if let Some(thread_root) = thread_root {
+            threads.push(ReceiptThread::Thread(thread_root.to_owned()));
+        }
 
-        let main_thread_receipts = match self
-            .load_event_receipts(ReceiptType::Read, ReceiptThread::Main, event_id)
-            .await
-        {
-            Ok(receipts) => receipts,
-            Err(e) => {
-                error!(?event_id, "Failed to get main thread read receipts for event: {e}");
-                Vec::new()
+        let mut receipts = IndexMap::new();
+
+        for thread in threads {
+            match self.load_event_receipts(ReceiptType::Read, thread.clone(), event_id).await {
+                Ok(thread_receipts) => {
+                    let thread_root = receipt_thread_root(&thread);
+                    receipts.extend(
+                        thread_receipts
+                            .into_iter()
+                            .map(|(user_id, receipt)| ((user_id, thread_root.clone()), receipt)),
+                    );
+                }
+                Err(e) => {
+                    error!(?event_id, ?thread, "Failed to get read receipts for event: {e}");
+                }
             }
-        };
+        }
 
-        unthreaded_receipts.extend(main_thread_receipts);
-        unthreaded_receipts
+        receipts
     }
 
     async fn push_rules_and_context(&self) -> Option<(Ruleset, PushConditionRoomCtx)> {
@@ -0,0 +1,121 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#[cfg(feature = "e2e-encryption")]
+use std::collections::BTreeSet;
+use std::{collections::BTreeMap, fmt, sync::Arc, time::Duration};
+
+use matrix_sdk::{clock::Clock, instant::Instant};
+use ruma::{EventId, OwnedEventId};
+
+/// A hook that is notified when an event that couldn't be decrypted when it
+/// was added to the timeline is decrypted later, for example to collect
+/// metrics on how long it takes for room keys to arrive.
+///
+/// See [`TimelineBuilder::with_unable_to_decrypt_hook()`].
+///
+/// [`TimelineBuilder::with_unable_to_decrypt_hook()`]: super::TimelineBuilder::with_unable_to_decrypt_hook
+pub trait UnableToDecryptHook: fmt::Debug + Send + Sync {
+    /// An event that was unable to decrypt has been decrypted.
+    fn on_late_decrypt(&self, info: UnableToDecryptInfo);
+}
+
+/// Information about an event that was decrypted after having been added to
+/// the timeline as unable to decrypt.
+#[derive(Clone, Debug)]
+pub struct UnableToDecryptInfo {
+    /// The ID of the event.
+    pub event_id: OwnedEventId,
+
+    /// The ID of the megolm session the event was encrypted with.
+    pub session_id: String,
+
+    /// The time between the event being added to the timeline, and it being
+    /// decrypted.
+    pub time_to_decrypt: Duration,
+}
+
+/// The timeline items that are unable to decrypt, keyed by the ID of the
+/// megolm session they're waiting for.
+pub(super) struct UtdIndex {
+    clock: Arc<dyn Clock>,
+    sessions: BTreeMap<String, BTreeMap<OwnedEventId, Instant>>,
+}
+
+impl UtdIndex {
+    pub(super) fn new(clock: Arc<dyn Clock>) -> Self {
+        Self { clock, sessions: BTreeMap::new() }
+    }
+
+    /// Remember that the given event is waiting for the given session.
+    ///
+    /// If the event was already known, the time at which it was first seen is
+    /// kept.
+    pub(super) fn insert(&mut self, session_id: String, event_id: OwnedEventId) {
+        let now = self.clock.now();
+        self.sessions.entry(session_id).or_default().entry(event_id).or_insert(now);
+    }
+
+    /// The events waiting for one of the given sessions.
+    #[cfg(feature = "e2e-encryption")]
+    pub(super) fn event_ids<'a>(
+        &'a self,
+        session_ids: &'a BTreeSet<String>,
+    ) -> impl Iterator<Item = &'a EventId> + 'a {
+        session_ids
+            .iter()
+            .filter_map(|session_id| self.sessions.get(session_id))
+            .flat_map(|events| events.keys().map(|event_id| &**event_id))
+    }
+
+    /// Forget about the given event, returning how long it has been waiting
+    /// for its session.
+    #[cfg(feature = "e2e-encryption")]
+    pub(super) fn remove(&mut self, session_id: &str, event_id: &EventId) -> Option<Duration> {
+        let events = self.sessions.get_mut(session_id)?;
+        let first_seen = events.remove(event_id)?;
+
+        if events.is_empty() {
+            self.sessions.remove(session_id);
+        }
+
+        Some(self.clock.now().duration_since(first_seen))
+    }
+
+    /// Forget about the events for which `f` returns `true`, because their
+    /// timeline item is gone or isn't unable to decrypt anymore.
+    pub(super) fn remove_events(&mut self, mut f: impl FnMut(&EventId) -> bool) {
+        self.sessions.retain(|_, events| {
+            events.retain(|event_id, _| !f(event_id));
+            !events.is_empty()
+        });
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.sessions.clear();
+    }
+
+    /// The number of events waiting for a session.
+    #[cfg(all(test, feature = "e2e-encryption"))]
+    pub(super) fn len(&self) -> usize {
+        self.sessions.values().map(BTreeMap::len).sum()
+    }
+}
+
+#[cfg(not(tarpaulin_include))]
+impl fmt::Debug for UtdIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UtdIndex").field("sessions", &self.sessions).finish_non_exhaustive()
+    }
+}
@@ -5,10 +5,13 @@ use std::{
 
 use assert_matches::assert_matches;
 use matrix_sdk::config::SyncSettings;
-use matrix_sdk_test::{async_test, sync_timeline_event, JoinedRoomBuilder, SyncResponseBuilder};
+use matrix_sdk_test::{
+    async_test, sync_timeline_event, JoinedRoomBuilder, StateTestEvent, SyncResponseBuilder,
+};
 use matrix_sdk_ui::{
     notification_client::{
-        NotificationClient, NotificationEvent, NotificationProcessSetup, NotificationStatus,
+        BudgetedNotification, NotificationClient, NotificationEvent, NotificationProcessSetup,
+        NotificationStatus,
     },
     sync_service::SyncService,
 };
@@ -309,3 +312,45 @@ async fn test_notification_client_sliding_sync() {
     assert_eq!(item.room_display_name, room_name);
     assert_eq!(item.is_noisy, Some(false));
 }
+
+#[async_test]
+async fn test_notification_client_partial_result_when_over_budget() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+
+    // The room is known locally, with its name.
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder
+        .add_joined_room(JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::RoomName));
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(SyncSettings::new()).await.unwrap();
+    server.reset().await;
+
+    // The homeserver is too slow to answer the notification sliding sync.
+    Mock::given(SlidingSyncMatcher)
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({ "pos": "0", "lists": {}, "rooms": {} }))
+                .set_delay(Duration::from_secs(10)),
+        )
+        .mount(&server)
+        .await;
+
+    let notification_client =
+        NotificationClient::builder(client, NotificationProcessSetup::MultipleProcesses)
+            .await
+            .unwrap()
+            .build();
+
+    let event_id = event_id!("$example_event_id");
+    let notification = notification_client
+        .get_notification_within(room_id, event_id, Duration::from_millis(500))
+        .await
+        .unwrap();
+
+    assert_matches!(notification, BudgetedNotification::Partial(item) => {
+        assert_eq!(item.room_id, room_id);
+        assert_eq!(item.event_id, event_id);
+        assert_eq!(item.room_display_name.as_deref(), Some("room name"));
+    });
+}
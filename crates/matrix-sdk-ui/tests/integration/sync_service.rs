@@ -17,8 +17,9 @@ use std::{
     time::Duration,
 };
 
+use matrix_sdk::features::ClientFeature;
 use matrix_sdk_test::async_test;
-use matrix_sdk_ui::sync_service::{State, SyncService};
+use matrix_sdk_ui::sync_service::{BandwidthProfile, State, SyncService};
 use serde_json::json;
 use stream_assert::{assert_next_matches, assert_pending};
 use wiremock::{Match as _, Mock, MockGuard, MockServer, Request, ResponseTemplate};
@@ -204,3 +205,41 @@ async fn test_sync_service_state() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[async_test]
+async fn test_sync_service_bandwidth_profile() -> anyhow::Result<()> {
+    let (client, _server) = logged_in_client().await;
+
+    let sync_service = SyncService::builder(client.clone()).build().await?;
+
+    let mut profile_stream = sync_service.bandwidth_profile();
+    assert_eq!(profile_stream.get(), BandwidthProfile::Normal);
+    assert!(!client.media().is_prefetch_deferred());
+
+    // A metered connection switches to the low data profile.
+    sync_service.set_connection_metered(true).await?;
+    assert_next_matches!(profile_stream, BandwidthProfile::LowData);
+    assert_eq!(sync_service.room_list_service().bandwidth_profile(), BandwidthProfile::LowData);
+    assert!(client.media().is_prefetch_deferred());
+    assert!(!client.features().is_enabled(ClientFeature::UrlPreviews));
+
+    // The profile chosen by the user wins over the connection.
+    sync_service.set_bandwidth_profile(Some(BandwidthProfile::Normal)).await?;
+    assert_next_matches!(profile_stream, BandwidthProfile::Normal);
+    assert!(!client.media().is_prefetch_deferred());
+    assert!(client.features().is_enabled(ClientFeature::UrlPreviews));
+
+    sync_service.set_connection_metered(false).await?;
+    sync_service.set_connection_metered(true).await?;
+    assert_pending!(profile_stream);
+
+    // Without a chosen profile, it follows the connection again.
+    sync_service.set_bandwidth_profile(None).await?;
+    assert_next_matches!(profile_stream, BandwidthProfile::LowData);
+
+    sync_service.set_connection_metered(false).await?;
+    assert_next_matches!(profile_stream, BandwidthProfile::Normal);
+    assert_eq!(sync_service.room_list_service().bandwidth_profile(), BandwidthProfile::Normal);
+
+    Ok(())
+}
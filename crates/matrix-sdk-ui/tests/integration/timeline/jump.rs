@@ -0,0 +1,211 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+
+use matrix_sdk::config::SyncSettings;
+use matrix_sdk_test::{
+    async_test, sync_timeline_event, JoinedRoomBuilder, RoomAccountDataTestEvent,
+    SyncResponseBuilder,
+};
+use matrix_sdk_ui::timeline::{RoomExt, Timeline};
+use ruma::{event_id, room_id, MilliSecondsSinceUnixEpoch, UInt};
+use serde_json::json;
+use tokio::time::sleep;
+use wiremock::{
+    matchers::{method, path_regex, query_param},
+    Mock, ResponseTemplate,
+};
+
+use crate::{logged_in_client, mock_sync};
+
+fn message(event_id: &str, body: &str, ts: u64) -> serde_json::Value {
+    json!({
+        "content": { "body": body, "msgtype": "m.text" },
+        "event_id": event_id,
+        "origin_server_ts": ts,
+        "sender": "@alice:example.org",
+        "type": "m.room.message",
+    })
+}
+
+async fn event_ids(timeline: &Timeline) -> Vec<String> {
+    timeline
+        .items()
+        .await
+        .iter()
+        .filter_map(|item| Some(item.as_event()?.event_id()?.to_string()))
+        .collect()
+}
+
+#[async_test]
+async fn jump_to_date_focuses_the_timeline_and_restores_live() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = room.timeline().await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/.*/rooms/.*/timestamp_to_event$"))
+        .and(query_param("dir", "f"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "event_id": "$target",
+            "origin_server_ts": 1000,
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/context/.*"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "event": message("$target", "target", 1000),
+            "events_before": [message("$before", "before", 900)],
+            "events_after": [message("$after", "after", 1100)],
+            "start": "start-token",
+            "end": "end-token",
+            "state": [],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let jump = timeline
+        .jump_to_date(MilliSecondsSinceUnixEpoch(UInt::new(1000).unwrap()))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(jump.event_id(), event_id!("$target"));
+    assert!(jump.is_focused());
+    assert!(!timeline.is_live());
+
+    assert_eq!(event_ids(&timeline).await, ["$before", "$target", "$after"]);
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [message("$latest", "latest", 5000)],
+            "start": "t392-516_47314_0_7_1_1_1_11444_1",
+            "end": "t47409-4357353_219380_26003_2269",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    jump.restore_live().await.unwrap();
+    assert!(timeline.is_live());
+    assert_eq!(event_ids(&timeline).await, ["$latest"]);
+}
+
+#[async_test]
+async fn jump_to_fully_read_paginates_forwards_back_to_live() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_account_data(
+        RoomAccountDataTestEvent::Custom(json!({
+            "content": { "event_id": "$read" },
+            "type": "m.fully_read",
+        })),
+    ));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = room.timeline().await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/context/.*"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "event": message("$read", "read", 1000),
+            "events_before": [],
+            "events_after": [message("$after", "after", 1100)],
+            "start": "start-token",
+            "end": "end-token",
+            "state": [],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let jump = timeline.jump_to_fully_read().await.unwrap().unwrap();
+    assert_eq!(jump.event_id(), event_id!("$read"));
+    assert!(jump.is_focused());
+    assert_eq!(event_ids(&timeline).await, ["$read", "$after"]);
+
+    // A live event received while the timeline is focused is kept for later.
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_timeline_event(
+        sync_timeline_event!({
+            "content": { "body": "live", "msgtype": "m.text" },
+            "event_id": "$live",
+            "origin_server_ts": 3000,
+            "sender": "@alice:example.org",
+            "type": "m.room.message",
+        }),
+    ));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    sleep(Duration::from_millis(100)).await;
+    assert_eq!(event_ids(&timeline).await, ["$read", "$after"]);
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .and(query_param("dir", "f"))
+        .and(query_param("from", "end-token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [message("$later", "later", 2000)],
+            "start": "end-token",
+            "end": "later-token",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    timeline.paginate_forwards(10).await.unwrap();
+    assert!(!timeline.is_live());
+    assert_eq!(event_ids(&timeline).await, ["$read", "$after", "$later"]);
+
+    // The end of the room is reached, the pending update is applied.
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .and(query_param("dir", "f"))
+        .and(query_param("from", "later-token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [],
+            "start": "later-token",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    timeline.paginate_forwards(10).await.unwrap();
+    assert!(timeline.is_live());
+    assert_eq!(event_ids(&timeline).await, ["$read", "$after", "$later", "$live"]);
+}
@@ -24,12 +24,18 @@ use matrix_sdk_test::{
     SyncResponseBuilder,
 };
 use matrix_sdk_ui::timeline::{RoomExt, TimelineItemContent, VirtualTimelineItem};
-use ruma::{room_id, user_id};
+use ruma::{event_id, events::relation::RelationType, owned_event_id, room_id, user_id};
+use serde_json::json;
+use wiremock::{
+    matchers::{method, path_regex},
+    Mock, ResponseTemplate,
+};
 
 use crate::{logged_in_client, mock_sync};
 
 mod echo;
 mod edit;
+mod jump;
 mod pagination;
 mod profiles;
 mod queue;
@@ -351,3 +357,130 @@ async fn sync_highlighted() {
     // `m.room.tombstone` should be highlighted by default.
     assert!(remote_event.is_highlighted());
 }
+
+#[async_test]
+async fn redact_with_relations() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (client, server) = logged_in_client().await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = room.timeline().await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/.*/rooms/.*/relations/\$message/m\.annotation"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [
+                {
+                    "content": {
+                        "m.relates_to": {
+                            "rel_type": "m.annotation",
+                            "event_id": "$message",
+                            "key": "👍",
+                        },
+                    },
+                    "event_id": "$own_reaction",
+                    "origin_server_ts": 152037280,
+                    "room_id": room_id,
+                    "sender": "@example:localhost",
+                    "type": "m.reaction",
+                },
+                {
+                    "content": {
+                        "m.relates_to": {
+                            "rel_type": "m.annotation",
+                            "event_id": "$message",
+                            "key": "👍",
+                        },
+                    },
+                    "event_id": "$bob_reaction",
+                    "origin_server_ts": 152037281,
+                    "room_id": room_id,
+                    "sender": "@bob:example.org",
+                    "type": "m.reaction",
+                },
+                {
+                    "content": {},
+                    "event_id": "$redacted_reaction",
+                    "origin_server_ts": 152037282,
+                    "room_id": room_id,
+                    "sender": "@example:localhost",
+                    "type": "m.reaction",
+                    "unsigned": {
+                        "redacted_because": {
+                            "content": {},
+                            "redacts": "$redacted_reaction",
+                            "event_id": "$redaction",
+                            "sender": "@example:localhost",
+                            "origin_server_ts": 152037283,
+                            "type": "m.room.redaction",
+                        },
+                    },
+                },
+            ],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/.*/rooms/.*/relations/\$message/m\.replace"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [
+                {
+                    "content": {
+                        "body": "* hello",
+                        "msgtype": "m.text",
+                        "m.new_content": {
+                            "body": "hello",
+                            "msgtype": "m.text",
+                        },
+                        "m.relates_to": {
+                            "rel_type": "m.replace",
+                            "event_id": "$message",
+                        },
+                    },
+                    "event_id": "$own_edit",
+                    "origin_server_ts": 152037284,
+                    "room_id": room_id,
+                    "sender": "@example:localhost",
+                    "type": "m.room.message",
+                },
+            ],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    for (event_id, expected_calls) in
+        [("own_reaction", 1), ("own_edit", 1), ("message", 1), ("bob_reaction", 0)]
+    {
+        Mock::given(method("PUT"))
+            .and(path_regex(format!(r"^/_matrix/client/r0/rooms/.*/redact/\${event_id}/.*")))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({ "event_id": format!("$redaction_of_{event_id}") })),
+            )
+            .expect(expected_calls)
+            .mount(&server)
+            .await;
+    }
+
+    let redacted = timeline
+        .redact_with_relations(
+            event_id!("$message"),
+            &[RelationType::Annotation, RelationType::Replacement],
+            Some("Oops"),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(redacted, [owned_event_id!("$own_reaction"), owned_event_id!("$own_edit")]);
+}
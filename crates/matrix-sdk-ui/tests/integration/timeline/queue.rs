@@ -18,10 +18,14 @@ use assert_matches::assert_matches;
 use assert_matches2::assert_let;
 use eyeball_im::VectorDiff;
 use futures_util::StreamExt;
-use matrix_sdk::config::SyncSettings;
+use matrix_sdk::{
+    config::{RequestConfig, SyncSettings},
+    matrix_auth::{MatrixSession, MatrixSessionTokens},
+};
+use matrix_sdk_base::SessionMeta;
 use matrix_sdk_test::{async_test, EventBuilder, JoinedRoomBuilder, SyncResponseBuilder, ALICE};
 use matrix_sdk_ui::timeline::{EventItemOrigin, EventSendState, RoomExt};
-use ruma::{events::room::message::RoomMessageEventContent, room_id};
+use ruma::{device_id, events::room::message::RoomMessageEventContent, room_id, user_id};
 use serde_json::json;
 use stream_assert::{assert_next_matches, assert_pending};
 use tokio::time::sleep;
@@ -30,7 +34,7 @@ use wiremock::{
     Mock, ResponseTemplate,
 };
 
-use crate::{logged_in_client, mock_encryption_state, mock_sync};
+use crate::{logged_in_client, mock_encryption_state, mock_sync, test_client_builder};
 
 #[async_test]
 async fn message_order() {
@@ -289,3 +293,114 @@ async fn clear_with_echoes() {
     assert_matches!(event_items[0].send_state(), Some(EventSendState::SendingFailed { .. }));
     assert_matches!(event_items[1].send_state(), Some(EventSendState::NotSentYet));
 }
+
+#[async_test]
+async fn network_error_pauses_sending() {
+    let room_id = room_id!("!a98sd12bjh:example.org");
+    let (builder, server) = test_client_builder().await;
+    let client = builder
+        .request_config(RequestConfig::new().disable_retry().timeout(Duration::from_millis(100)))
+        .build()
+        .await
+        .unwrap();
+    client
+        .restore_session(MatrixSession {
+            meta: SessionMeta {
+                user_id: user_id!("@example:localhost").to_owned(),
+                device_id: device_id!("DEVICEID").to_owned(),
+            },
+            tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        })
+        .await
+        .unwrap();
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(SyncSettings::new()).await.unwrap();
+    server.reset().await;
+
+    mock_encryption_state(&server, false).await;
+
+    let room = client.get_room(room_id).unwrap();
+    let timeline = Arc::new(room.timeline().await);
+    let (_, mut timeline_stream) =
+        timeline.subscribe_filter_map(|item| item.as_event().cloned()).await;
+
+    // The first response takes longer than the request timeout.
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(body_string_contains("First!"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(&json!({ "event_id": "$PyHxV5mYzjetBUT3qZq7V95GOzxb02EP" }))
+                .set_delay(Duration::from_millis(500)),
+        )
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+
+    timeline.send(RoomMessageEventContent::text_plain("First!").into()).await;
+
+    assert_next_matches!(timeline_stream, VectorDiff::PushBack { value } => {
+        assert_eq!(value.content().as_message().unwrap().body(), "First!");
+    });
+    assert_let!(Some(VectorDiff::Set { index: 0, value: first }) = timeline_stream.next().await);
+    assert_matches!(first.send_state().unwrap(), EventSendState::SendingFailed { .. });
+    assert!(timeline.is_sending_paused());
+
+    // Sending is paused for the whole room, including its other timelines.
+    let other_timeline = room.timeline().await;
+    assert!(other_timeline.is_sending_paused());
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(body_string_contains("First!"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(&json!({ "event_id": "$PyHxV5mYzjetBUT3qZq7V95GOzxb02EP" })),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(body_string_contains("Second."))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(&json!({ "event_id": "$5E2kLK/Sg342bgBU9ceEIEPYpbFaqJpZ" })),
+        )
+        .mount(&server)
+        .await;
+
+    // A message sent while sending is paused stays in the queue.
+    timeline.send(RoomMessageEventContent::text_plain("Second.").into()).await;
+
+    assert_next_matches!(timeline_stream, VectorDiff::PushBack { value } => {
+        assert_eq!(value.content().as_message().unwrap().body(), "Second.");
+        assert_matches!(value.send_state().unwrap(), EventSendState::NotSentYet);
+    });
+    sleep(Duration::from_millis(200)).await;
+    assert_pending!(timeline_stream);
+
+    // Resuming from another timeline retries the failed message in place,
+    // before the queued one.
+    other_timeline.resume_sending().await;
+    assert!(!timeline.is_sending_paused());
+
+    sleep(Duration::from_millis(300)).await;
+
+    assert_next_matches!(timeline_stream, VectorDiff::Set { index: 0, value } => {
+        assert_eq!(value.content().as_message().unwrap().body(), "First!");
+        assert_matches!(value.send_state().unwrap(), EventSendState::NotSentYet);
+    });
+    assert_next_matches!(timeline_stream, VectorDiff::Set { index: 0, value } => {
+        assert_eq!(value.content().as_message().unwrap().body(), "First!");
+        assert_matches!(value.send_state().unwrap(), EventSendState::Sent { .. });
+    });
+    assert_next_matches!(timeline_stream, VectorDiff::Set { index: 1, value } => {
+        assert_eq!(value.content().as_message().unwrap().body(), "Second.");
+        assert_matches!(value.send_state().unwrap(), EventSendState::Sent { .. });
+    });
+    assert_pending!(timeline_stream);
+}
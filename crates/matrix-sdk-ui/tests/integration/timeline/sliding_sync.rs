@@ -476,9 +476,9 @@ async fn test_timeline_read_receipts_are_updated_live() -> Result<()> {
         let read_receipts = event_timeline_item.read_receipts();
         assert_eq!(read_receipts.len(), 2);
         // Implicit read receipt from Alice.
-        assert!(read_receipts.get(user_id!("@alice:bar.org")).is_some());
+        assert!(read_receipts.get(&(user_id!("@alice:bar.org").to_owned(), None)).is_some());
         // Explicit read receipt from Bob.
-        assert!(read_receipts.get(user_id!("@bob:bar.org")).is_some());
+        assert!(read_receipts.get(&(user_id!("@bob:bar.org").to_owned(), None)).is_some());
     }
 
     Ok(())
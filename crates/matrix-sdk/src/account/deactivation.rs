@@ -0,0 +1,336 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deactivation of the account, with a preview of the cleanup that comes with
+//! it.
+//!
+//! [`Account::deactivation_plan()`] lists what will be done before the account
+//! is deactivated, and [`Account::deactivate_with_plan()`] performs the
+//! cleanup step by step, then deactivates the account.
+
+use ruma::{
+    api::client::{
+        account::{deactivate, unbind_3pid, ThirdPartyIdRemovalStatus},
+        uiaa::AuthData,
+    },
+    assign,
+    thirdparty::{Medium, ThirdPartyIdentifier},
+    OwnedRoomId,
+};
+use tracing::{debug, instrument};
+
+use super::Account;
+use crate::{Error, Result};
+
+/// Options for [`Account::deactivation_plan()`].
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct DeactivationOptions {
+    /// Whether to leave the joined rooms and reject the pending invites before
+    /// deactivating the account.
+    pub leave_rooms: bool,
+    /// Whether to ask the homeserver to erase the data of the account, like
+    /// the messages it sent, as far as possible.
+    pub erase: bool,
+    /// The identity server to unbind the third-party identifiers from.
+    ///
+    /// If not set, the homeserver unbinds them from the identity server they
+    /// were bound with.
+    pub id_server: Option<String>,
+}
+
+impl DeactivationOptions {
+    /// Create the default `DeactivationOptions`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Leave the joined rooms and reject the pending invites before
+    /// deactivating the account.
+    pub fn leave_rooms(mut self) -> Self {
+        self.leave_rooms = true;
+        self
+    }
+
+    /// Ask the homeserver to erase the data of the account.
+    pub fn erase(mut self) -> Self {
+        self.erase = true;
+        self
+    }
+
+    /// Unbind the third-party identifiers from the given identity server.
+    pub fn id_server(mut self, id_server: impl Into<String>) -> Self {
+        self.id_server = Some(id_server.into());
+        self
+    }
+}
+
+/// What will be done when the account is deactivated with
+/// [`Account::deactivate_with_plan()`].
+///
+/// The steps that succeed are removed from the plan, so the plan can be used
+/// again to retry the steps that failed.
+#[derive(Debug, Clone)]
+pub struct DeactivationPlan {
+    third_party_ids: Vec<ThirdPartyIdentifier>,
+    rooms_to_leave: Vec<OwnedRoomId>,
+    erase: bool,
+    id_server: Option<String>,
+}
+
+impl DeactivationPlan {
+    /// The third-party identifiers that will be unbound from the identity
+    /// server.
+    pub fn third_party_ids(&self) -> &[ThirdPartyIdentifier] {
+        &self.third_party_ids
+    }
+
+    /// The rooms that will be left, including the rooms the user is invited
+    /// to.
+    pub fn rooms_to_leave(&self) -> &[OwnedRoomId] {
+        &self.rooms_to_leave
+    }
+
+    /// Whether the homeserver will be asked to erase the data of the account.
+    pub fn erase(&self) -> bool {
+        self.erase
+    }
+}
+
+/// A step of the deactivation of the account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeactivationStep {
+    /// Unbind a third-party identifier from the identity server.
+    UnbindThirdPartyId {
+        /// The medium of the third-party identifier.
+        medium: Medium,
+        /// The address of the third-party identifier.
+        address: String,
+    },
+
+    /// Leave a room, or reject the invite to it.
+    LeaveRoom(OwnedRoomId),
+
+    /// Deactivate the account.
+    Deactivate,
+}
+
+/// The result of a step of the deactivation of the account.
+#[derive(Debug)]
+pub struct DeactivationStepResult {
+    /// The step that was performed.
+    pub step: DeactivationStep,
+    /// Whether the step succeeded.
+    pub result: Result<()>,
+}
+
+/// The result of [`Account::deactivate_with_plan()`].
+#[derive(Debug, Default)]
+pub struct DeactivationReport {
+    /// The steps that were performed, in order.
+    pub steps: Vec<DeactivationStepResult>,
+}
+
+impl DeactivationReport {
+    /// Whether the account was deactivated.
+    pub fn is_deactivated(&self) -> bool {
+        self.steps
+            .iter()
+            .any(|step| step.step == DeactivationStep::Deactivate && step.result.is_ok())
+    }
+
+    /// The error that prevented the account from being deactivated, if any.
+    ///
+    /// If the homeserver requires User-Interactive Authentication, this
+    /// contains the information needed to complete it, see
+    /// [`Error::as_uiaa_response()`].
+    pub fn deactivation_error(&self) -> Option<&Error> {
+        self.steps
+            .iter()
+            .find(|step| step.step == DeactivationStep::Deactivate)
+            .and_then(|step| step.result.as_ref().err())
+    }
+
+    fn push(&mut self, step: DeactivationStep, result: Result<()>) {
+        self.steps.push(DeactivationStepResult { step, result });
+    }
+}
+
+impl Account {
+    /// Get the list of what will be done when deactivating the account with
+    /// the given options.
+    ///
+    /// The plan can be shown to the user before calling
+    /// [`Account::deactivate_with_plan()`].
+    pub async fn deactivation_plan(
+        &self,
+        options: DeactivationOptions,
+    ) -> Result<DeactivationPlan> {
+        let third_party_ids = self.get_3pids().await?.threepids;
+
+        let rooms_to_leave = if options.leave_rooms {
+            let client = &self.client;
+            client
+                .joined_rooms()
+                .into_iter()
+                .chain(client.invited_rooms())
+                .map(|room| room.room_id().to_owned())
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        Ok(DeactivationPlan {
+            third_party_ids,
+            rooms_to_leave,
+            erase: options.erase,
+            id_server: options.id_server,
+        })
+    }
+
+    /// Deactivate this account definitively, after unbinding its third-party
+    /// identifiers and leaving the rooms of the plan.
+    ///
+    /// Without `auth_data`, nothing is cleaned up: the homeserver is only
+    /// asked to deactivate the account, to get the User-Interactive
+    /// Authentication session that is required to do it. The cleanup steps are
+    /// only performed once `auth_data` is provided, so the account isn't left
+    /// half cleaned up if the user doesn't complete the authentication. If the
+    /// homeserver doesn't require authentication, the account is deactivated
+    /// right away, the homeserver takes care of leaving the rooms and of
+    /// unbinding the third-party identifiers, and the plan is emptied.
+    ///
+    /// With `auth_data`, the account is only deactivated if all the cleanup
+    /// steps succeeded. The steps that succeeded are removed from the plan.
+    ///
+    /// # Arguments
+    ///
+    /// * `plan` - The plan returned by [`Account::deactivation_plan()`].
+    ///
+    /// * `auth_data` - The deactivation uses the [User-Interactive
+    /// Authentication API][uiaa]. Call this method without `auth_data` first
+    /// to get the authentication flows from
+    /// [`DeactivationReport::deactivation_error()`], then use the same plan
+    /// again with the completed `auth_data`.
+    ///
+    /// [uiaa]: https://spec.matrix.org/v1.2/client-server-api/#user-interactive-authentication-api
+    #[instrument(skip_all)]
+    pub async fn deactivate_with_plan(
+        &self,
+        plan: &mut DeactivationPlan,
+        auth_data: Option<AuthData>,
+    ) -> DeactivationReport {
+        let Some(auth_data) = auth_data else {
+            let mut report = DeactivationReport::default();
+            let result = self.send_deactivate(plan, None).await;
+
+            if result.is_ok() {
+                // The homeserver didn't require authentication, the account is gone and
+                // the homeserver took care of the rest.
+                debug!("The account was deactivated without authentication");
+                plan.third_party_ids.clear();
+                plan.rooms_to_leave.clear();
+            }
+
+            report.push(DeactivationStep::Deactivate, result);
+            return report;
+        };
+
+        let mut report = DeactivationReport::default();
+        let mut failed = false;
+
+        let mut remaining_third_party_ids = Vec::new();
+
+        for third_party_id in plan.third_party_ids.drain(..) {
+            let result = self.unbind_3pid(&third_party_id, plan.id_server.as_deref()).await;
+            failed |= result.is_err();
+
+            let step = DeactivationStep::UnbindThirdPartyId {
+                medium: third_party_id.medium.clone(),
+                address: third_party_id.address.clone(),
+            };
+            if result.is_err() {
+                remaining_third_party_ids.push(third_party_id);
+            }
+            report.push(step, result);
+        }
+
+        plan.third_party_ids = remaining_third_party_ids;
+
+        let mut remaining_rooms = Vec::new();
+
+        for room_id in plan.rooms_to_leave.drain(..) {
+            let result = match self.client.get_room(&room_id) {
+                Some(room) => room.leave().await,
+                // The room was forgotten in the meantime, nothing to leave.
+                None => Ok(()),
+            };
+            failed |= result.is_err();
+
+            if result.is_err() {
+                remaining_rooms.push(room_id.clone());
+            }
+            report.push(DeactivationStep::LeaveRoom(room_id), result);
+        }
+
+        plan.rooms_to_leave = remaining_rooms;
+
+        if failed {
+            debug!("Not deactivating the account, a cleanup step failed");
+            return report;
+        }
+
+        let result = self.send_deactivate(plan, Some(auth_data)).await;
+        report.push(DeactivationStep::Deactivate, result);
+
+        report
+    }
+
+    async fn send_deactivate(
+        &self,
+        plan: &DeactivationPlan,
+        auth_data: Option<AuthData>,
+    ) -> Result<()> {
+        let request = assign!(deactivate::v3::Request::new(), {
+            auth: auth_data,
+            id_server: plan.id_server.clone(),
+            erase: plan.erase,
+        });
+        self.client.send(request, None).await?;
+        Ok(())
+    }
+
+    async fn unbind_3pid(
+        &self,
+        third_party_id: &ThirdPartyIdentifier,
+        id_server: Option<&str>,
+    ) -> Result<()> {
+        let request = assign!(
+            unbind_3pid::v3::Request::new(
+                third_party_id.address.clone(),
+                third_party_id.medium.clone(),
+            ),
+            { id_server: id_server.map(ToOwned::to_owned) }
+        );
+        let response = self.client.send(request, None).await?;
+
+        if response.id_server_unbind_result == ThirdPartyIdRemovalStatus::NoSupport {
+            // The identifier may not have been bound to an identity server in the
+            // first place, there is nothing else to do.
+            debug!(address = %third_party_id.address, "The 3PID was not unbound from the identity server");
+        }
+
+        Ok(())
+    }
+}
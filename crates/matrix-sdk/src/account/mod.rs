@@ -51,6 +51,13 @@ use tracing::error;
 
 use crate::{config::RequestConfig, Client, Error, HttpError, Result};
 
+mod deactivation;
+
+pub use self::deactivation::{
+    DeactivationOptions, DeactivationPlan, DeactivationReport, DeactivationStep,
+    DeactivationStepResult,
+};
+
 /// A high-level API to manage the client owner's account.
 ///
 /// All the methods on this struct send a request to the homeserver.
@@ -12,9 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::pin::Pin;
+use std::{pin::Pin, sync::Mutex as StdMutex};
 
 use as_variant::as_variant;
+use eyeball::SharedObservable;
 use futures_core::Future;
 use matrix_sdk_base::SessionMeta;
 use tokio::sync::{broadcast, Mutex, OnceCell};
@@ -22,8 +23,8 @@ use tokio::sync::{broadcast, Mutex, OnceCell};
 #[cfg(feature = "experimental-oidc")]
 use crate::oidc::{self, Oidc, OidcAuthData, OidcCtx};
 use crate::{
-    matrix_auth::{self, MatrixAuth, MatrixAuthData},
-    Client, RefreshTokenError, SessionChange,
+    matrix_auth::{self, MatrixAuth, MatrixAuthData, TokenRefreshTask},
+    Client, RefreshTokenError, SessionChange, SessionStatus,
 };
 
 /// Session tokens, for any kind of authentication.
@@ -56,11 +57,19 @@ pub(crate) struct AuthCtx {
     /// Lock making sure we're only doing one token refresh at a time.
     pub(crate) refresh_token_lock: Mutex<Result<(), RefreshTokenError>>,
 
+    /// The task refreshing the access token before it expires, started the
+    /// first time the homeserver tells us when the access token expires.
+    pub(crate) refresh_token_task: StdMutex<Option<TokenRefreshTask>>,
+
     /// Session change publisher. Allows the subscriber to handle changes to the
     /// session such as logging out when the access token is invalid or
     /// persisting updates to the access/refresh tokens.
     pub(crate) session_change_sender: broadcast::Sender<SessionChange>,
 
+    /// The status of the session, which becomes terminal once the homeserver
+    /// confirmed that the session is gone.
+    pub(crate) session_status: SharedObservable<SessionStatus>,
+
     /// Authentication data to keep in memory.
     pub(crate) auth_data: OnceCell<AuthData>,
 
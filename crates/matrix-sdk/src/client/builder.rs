@@ -13,9 +13,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{fmt, sync::Arc};
-
-use matrix_sdk_base::{store::StoreConfig, BaseClient};
+use std::{fmt, sync::Arc, time::Duration};
+
+use eyeball::SharedObservable;
+use matrix_sdk_base::{
+    clock::Clock,
+    store::{StoreConfig, StoreError},
+    sync_metrics::SyncMetricsHook,
+    BaseClient,
+};
 use ruma::{
     api::{client::discovery::discover_homeserver, error::FromHttpResponseError, MatrixVersion},
     OwnedServerName, ServerName,
@@ -25,15 +31,23 @@ use tokio::sync::{broadcast, Mutex, OnceCell};
 use tracing::{debug, field::debug, instrument, Span};
 use url::Url;
 
-use super::{Client, ClientInner};
+use super::{Client, ClientInner, SessionStatus};
 #[cfg(feature = "e2e-encryption")]
 use crate::encryption::EncryptionSettings;
 #[cfg(not(target_arch = "wasm32"))]
 use crate::http_client::HttpSettings;
+#[cfg(all(feature = "http-recording", not(target_arch = "wasm32")))]
+use crate::http_client::{HttpRecorder, HttpRecording, HttpReplay};
 #[cfg(feature = "experimental-oidc")]
 use crate::oidc::OidcCtx;
 use crate::{
-    authentication::AuthCtx, config::RequestConfig, error::RumaApiError, http_client::HttpClient,
+    authentication::AuthCtx,
+    config::RequestConfig,
+    content_scanner::ContentScannerConfig,
+    error::RumaApiError,
+    http_client::HttpClient,
+    invite_filter::{InviteFilter, JoinThrottle},
+    store_backend::{StoreBackend, StoreBackendOptions, StoreBackendRegistry},
     HttpError,
 };
 
@@ -88,8 +102,18 @@ pub struct ClientBuilder {
     server_versions: Option<Box<[MatrixVersion]>>,
     handle_refresh_tokens: bool,
     base_client: Option<BaseClient>,
+    clock: Option<Arc<dyn Clock>>,
+    state_validation: bool,
+    sync_metrics_hook: Option<Arc<dyn SyncMetricsHook>>,
+    default_max_event_lifetime: Option<Duration>,
+    content_scanner: Option<Url>,
+    content_scanner_access_token: Option<ContentScannerAccessToken>,
+    invite_filters: Vec<Arc<dyn InviteFilter>>,
+    join_throttle: Option<JoinThrottle>,
     #[cfg(feature = "e2e-encryption")]
     encryption_settings: EncryptionSettings,
+    #[cfg(all(feature = "http-recording", not(target_arch = "wasm32")))]
+    http_recording: Option<HttpRecording>,
 }
 
 impl ClientBuilder {
@@ -105,8 +129,18 @@ impl ClientBuilder {
             server_versions: None,
             handle_refresh_tokens: false,
             base_client: None,
+            clock: None,
+            state_validation: false,
+            sync_metrics_hook: None,
+            default_max_event_lifetime: None,
+            content_scanner: None,
+            content_scanner_access_token: None,
+            invite_filters: Vec::new(),
+            join_throttle: None,
             #[cfg(feature = "e2e-encryption")]
             encryption_settings: Default::default(),
+            #[cfg(all(feature = "http-recording", not(target_arch = "wasm32")))]
+            http_recording: None,
         }
     }
 
@@ -222,6 +256,39 @@ impl ClientBuilder {
         self
     }
 
+    /// Set up the stores with a [`StoreBackend`] provided by another crate.
+    ///
+    /// The stores are opened with the given options when `.build().await` is
+    /// called.
+    pub fn store_backend(
+        mut self,
+        backend: Arc<dyn StoreBackend>,
+        options: StoreBackendOptions,
+    ) -> Self {
+        self.store_config = BuilderStoreConfig::Backend { backend, options };
+        self
+    }
+
+    /// Set up the stores with the [`StoreBackend`] registered under the given
+    /// name in `registry`.
+    ///
+    /// This is the same as [`store_backend()`](Self::store_backend), except
+    /// that `.build().await` fails with
+    /// [`ClientBuildError::UnknownStoreBackend`] if no backend is registered
+    /// under this name.
+    pub fn store_backend_by_name(
+        mut self,
+        registry: &StoreBackendRegistry,
+        name: &str,
+        options: StoreBackendOptions,
+    ) -> Self {
+        self.store_config = match registry.get(name) {
+            Some(backend) => BuilderStoreConfig::Backend { backend, options },
+            None => BuilderStoreConfig::UnknownBackend(name.to_owned()),
+        };
+        self
+    }
+
     /// Update the client's homeserver URL with the discovery information
     /// present in the login response, if any.
     pub fn respect_login_well_known(mut self, value: bool) -> Self {
@@ -281,6 +348,28 @@ impl ClientBuilder {
         self
     }
 
+    /// Record all the HTTP traffic of the client with the given recorder.
+    ///
+    /// The recording can be replayed in tests with
+    /// [`replay_http_traffic()`][Self::replay_http_traffic]. This method is
+    /// mutually exclusive with it.
+    #[cfg(all(feature = "http-recording", not(target_arch = "wasm32")))]
+    pub fn record_http_traffic(mut self, recorder: HttpRecorder) -> Self {
+        self.http_recording = Some(HttpRecording::Record(Arc::new(recorder)));
+        self
+    }
+
+    /// Answer the requests of the client with the responses of the given
+    /// recording, instead of sending them to the homeserver.
+    ///
+    /// This method is mutually exclusive with
+    /// [`record_http_traffic()`][Self::record_http_traffic].
+    #[cfg(all(feature = "http-recording", not(target_arch = "wasm32")))]
+    pub fn replay_http_traffic(mut self, replay: HttpReplay) -> Self {
+        self.http_recording = Some(HttpRecording::Replay(Arc::new(replay)));
+        self
+    }
+
     /// Specify the Matrix versions supported by the homeserver manually, rather
     /// than `build()` doing it using a `get_supported_versions` request.
     ///
@@ -328,6 +417,99 @@ impl ClientBuilder {
         self
     }
 
+    /// Set the [`Clock`] the client uses as its source of time.
+    ///
+    /// The clock is used everywhere the client needs the current time or has to
+    /// wait, e.g. for the expiry of call memberships, widget request timeouts
+    /// or the delay between backup uploads. By default, the system time is
+    /// used, tests can pass a [`TestClock`] to control the passing of time.
+    ///
+    /// [`TestClock`]: crate::clock::TestClock
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Check the room state of the sync responses for inconsistencies that a
+    /// correct homeserver can't produce, like duplicate state keys or
+    /// membership changes without a membership event.
+    ///
+    /// The inconsistencies are logged and can be received with
+    /// [`Client::subscribe_to_state_divergences()`], to report them to the
+    /// homeserver developers. This is disabled by default.
+    pub fn with_state_validation(mut self, enabled: bool) -> Self {
+        self.state_validation = enabled;
+        self
+    }
+
+    /// Send the timings of the processing of every sync response, broken down
+    /// per room, to the given hook.
+    ///
+    /// This allows to find the rooms that make the sync slow, for example on
+    /// accounts with a lot of rooms.
+    pub fn with_sync_metrics_hook(mut self, hook: Arc<dyn SyncMetricsHook>) -> Self {
+        self.sync_metrics_hook = Some(hook);
+        self
+    }
+
+    /// Set the maximum time events are kept for locally.
+    ///
+    /// Events older than that are forgotten by
+    /// [`Room::purge_expired_events`], even if the retention policy of the
+    /// room allows keeping them for longer. If the retention policy of a room
+    /// is stricter, it takes precedence.
+    ///
+    /// By default, events are only purged according to the retention policy
+    /// of their room.
+    ///
+    /// [`Room::purge_expired_events`]: crate::Room::purge_expired_events
+    pub fn default_max_event_lifetime(mut self, max_lifetime: Duration) -> Self {
+        self.default_max_event_lifetime = Some(max_lifetime);
+        self
+    }
+
+    /// Download media through the [Matrix Content Scanner] at the given URL.
+    ///
+    /// See the [`content_scanner`](crate::content_scanner) module for details.
+    ///
+    /// [Matrix Content Scanner]: https://github.com/matrix-org/matrix-content-scanner-python
+    pub fn content_scanner(mut self, url: Url) -> Self {
+        self.content_scanner = Some(url);
+        self
+    }
+
+    /// Authenticate to the content scanner with the given token.
+    ///
+    /// The access token of the homeserver is never sent to the content
+    /// scanner, so this must be a token issued for the scanner. It is only used
+    /// if a content scanner is set with [`ClientBuilder::content_scanner()`].
+    pub fn content_scanner_access_token(mut self, access_token: String) -> Self {
+        self.content_scanner_access_token = Some(ContentScannerAccessToken(access_token));
+        self
+    }
+
+    /// Add an [`InviteFilter`] to run on the invites received via sync.
+    ///
+    /// The filters are run in the order they were added, the first one that
+    /// doesn't accept an invite decides whether it is rejected or shelved as
+    /// spam.
+    pub fn add_invite_filter(mut self, filter: Arc<dyn InviteFilter>) -> Self {
+        self.invite_filters.push(filter);
+        self
+    }
+
+    /// Join at most `max_joins` rooms in any `period` of time.
+    ///
+    /// The joins over the limit wait until they are allowed, in order. This
+    /// avoids joining a flood of rooms, for example when a wave of invites is
+    /// accepted at once.
+    ///
+    /// `max_joins` is at least 1.
+    pub fn throttle_joins(mut self, max_joins: usize, period: Duration) -> Self {
+        self.join_throttle = Some(JoinThrottle { max_joins: max_joins.max(1), period });
+        self
+    }
+
     /// Enables specific encryption settings that will persist throughout the
     /// entire lifetime of the `Client`.
     #[cfg(feature = "e2e-encryption")]
@@ -368,7 +550,6 @@ impl ClientBuilder {
         let base_client = if let Some(base_client) = self.base_client {
             base_client
         } else {
-            #[allow(clippy::infallible_destructuring_match)]
             let store_config = match self.store_config {
                 #[cfg(feature = "sqlite")]
                 BuilderStoreConfig::Sqlite { path, passphrase } => {
@@ -379,11 +560,43 @@ impl ClientBuilder {
                     matrix_sdk_indexeddb::make_store_config(&name, passphrase.as_deref()).await?
                 }
                 BuilderStoreConfig::Custom(config) => config,
+                BuilderStoreConfig::Backend { backend, options } => {
+                    backend.open(&options).await.map_err(|error| {
+                        ClientBuildError::StoreBackend { name: backend.name().to_owned(), error }
+                    })?
+                }
+                BuilderStoreConfig::UnknownBackend(name) => {
+                    return Err(ClientBuildError::UnknownStoreBackend(name));
+                }
             };
             BaseClient::with_store_config(store_config)
         };
+        let base_client = match self.clock {
+            Some(clock) => base_client.with_clock(clock),
+            None => base_client,
+        };
+        // Don't disable the checks of a `BaseClient` that was passed to the
+        // builder.
+        let base_client = if self.state_validation {
+            base_client.with_state_validation(true)
+        } else {
+            base_client
+        };
+        let base_client = match self.sync_metrics_hook {
+            Some(hook) => base_client.with_sync_metrics_hook(hook),
+            None => base_client,
+        };
+        #[cfg(feature = "e2e-encryption")]
+        let base_client = base_client
+            .with_room_key_rotation_limits(self.encryption_settings.room_key_rotation_limits)
+            .with_error_on_unverified_devices(self.encryption_settings.error_on_unverified_devices);
+        #[cfg(feature = "automatic-room-key-forwarding")]
+        let base_client = base_client
+            .with_room_key_forwarding_policy(self.encryption_settings.room_key_forwarding_policy);
 
         let http_client = HttpClient::new(inner_http_client.clone(), self.request_config);
+        #[cfg(all(feature = "http-recording", not(target_arch = "wasm32")))]
+        let http_client = http_client.with_recording(self.http_recording);
 
         #[cfg(feature = "experimental-oidc")]
         let mut authentication_server_info = None;
@@ -456,7 +669,9 @@ impl ClientBuilder {
         let auth_ctx = Arc::new(AuthCtx {
             handle_refresh_tokens: self.handle_refresh_tokens,
             refresh_token_lock: Mutex::new(Ok(())),
+            refresh_token_task: Default::default(),
             session_change_sender: broadcast::Sender::new(1),
+            session_status: SharedObservable::new(SessionStatus::Active),
             auth_data: OnceCell::default(),
             reload_session_callback: OnceCell::default(),
             save_session_callback: OnceCell::default(),
@@ -473,6 +688,13 @@ impl ClientBuilder {
             base_client,
             self.server_versions,
             self.respect_login_well_known,
+            self.default_max_event_lifetime,
+            self.content_scanner.map(|url| ContentScannerConfig {
+                url,
+                access_token: self.content_scanner_access_token.map(|token| token.0),
+            }),
+            self.invite_filters,
+            self.join_throttle,
             #[cfg(feature = "e2e-encryption")]
             self.encryption_settings,
         );
@@ -543,12 +765,16 @@ enum BuilderStoreConfig {
         passphrase: Option<String>,
     },
     Custom(StoreConfig),
+    Backend {
+        backend: Arc<dyn StoreBackend>,
+        options: StoreBackendOptions,
+    },
+    UnknownBackend(String),
 }
 
 #[cfg(not(tarpaulin_include))]
 impl fmt::Debug for BuilderStoreConfig {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        #[allow(clippy::infallible_destructuring_match)]
         match self {
             #[cfg(feature = "sqlite")]
             Self::Sqlite { path, .. } => {
@@ -559,10 +785,28 @@ impl fmt::Debug for BuilderStoreConfig {
                 f.debug_struct("IndexedDb").field("name", name).finish_non_exhaustive()
             }
             Self::Custom(store_config) => f.debug_tuple("Custom").field(store_config).finish(),
+            Self::Backend { backend, options } => f
+                .debug_struct("Backend")
+                .field("name", &backend.name())
+                .field("location", &options.location)
+                .finish_non_exhaustive(),
+            Self::UnknownBackend(name) => f.debug_tuple("UnknownBackend").field(name).finish(),
         }
     }
 }
 
+/// The token to authenticate to the content scanner with, that isn't shown in
+/// the debug output of the builder.
+#[derive(Clone)]
+struct ContentScannerAccessToken(String);
+
+#[cfg(not(tarpaulin_include))]
+impl fmt::Debug for ContentScannerAccessToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ContentScannerAccessToken").finish_non_exhaustive()
+    }
+}
+
 /// Errors that can happen in [`ClientBuilder::build`].
 #[derive(Debug, Error)]
 pub enum ClientBuildError {
@@ -591,6 +835,22 @@ pub enum ClientBuildError {
     #[cfg(feature = "sqlite")]
     #[error(transparent)]
     SqliteStore(#[from] matrix_sdk_sqlite::OpenStoreError),
+
+    /// Error opening the stores with a
+    /// [`StoreBackend`](crate::store_backend::StoreBackend).
+    #[error("couldn't open the `{name}` store backend: {error}")]
+    StoreBackend {
+        /// The name of the store backend.
+        name: String,
+        /// The error returned by the store backend.
+        #[source]
+        error: StoreError,
+    },
+
+    /// No store backend is registered under the name given to
+    /// [`ClientBuilder::store_backend_by_name()`].
+    #[error("no store backend is registered under the name `{0}`")]
+    UnknownStoreBackend(String),
 }
 
 impl ClientBuildError {
@@ -20,6 +20,7 @@ use std::{
     future::Future,
     pin::Pin,
     sync::{Arc, Mutex as StdMutex, RwLock as StdRwLock},
+    time::Duration,
 };
 
 use eyeball::{SharedObservable, Subscriber};
@@ -27,10 +28,12 @@ use futures_core::Stream;
 #[cfg(feature = "e2e-encryption")]
 use matrix_sdk_base::crypto::store::LockableCryptoStore;
 use matrix_sdk_base::{
-    store::DynStateStore, BaseClient, RoomState, RoomStateFilter, SendOutsideWasm, SessionMeta,
+    state_validation::StateDivergence,
+    store::{DynStateStore, LockableStateStore},
+    BaseClient, RoomInfoUpdate, RoomState, RoomStateFilter, SendOutsideWasm, SessionMeta,
     SyncOutsideWasm,
 };
-use matrix_sdk_common::instant::Instant;
+use matrix_sdk_common::{clock::Clock, instant::Instant};
 #[cfg(feature = "e2e-encryption")]
 use ruma::events::{room::encryption::RoomEncryptionEventContent, InitialStateEvent};
 use ruma::{
@@ -38,12 +41,13 @@ use ruma::{
         client::{
             account::whoami,
             alias::get_alias,
-            device::{delete_devices, get_devices, update_device},
+            device::{delete_devices, get_device, get_devices, update_device},
             directory::{get_public_rooms, get_public_rooms_filtered},
             discovery::{
                 get_capabilities::{self, Capabilities},
                 get_supported_versions,
             },
+            error::ErrorKind,
             filter::{create_filter::v3::Request as FilterUploadRequest, FilterDefinition},
             membership::{join_room_by_id, join_room_by_id_or_alias},
             profile::get_profile,
@@ -58,50 +62,60 @@ use ruma::{
         MatrixVersion, OutgoingRequest,
     },
     assign,
+    presence::PresenceState,
     push::Ruleset,
     DeviceId, OwnedDeviceId, OwnedRoomId, OwnedServerName, RoomAliasId, RoomId, RoomOrAliasId,
     ServerName, UInt, UserId,
 };
 use serde::de::DeserializeOwned;
 use tokio::sync::{broadcast, Mutex, OnceCell, RwLock, RwLockReadGuard};
-use tracing::{debug, error, instrument, trace, Instrument, Span};
+use tracing::{debug, error, instrument, trace, warn, Instrument, Span};
 use url::Url;
 
 use self::futures::SendRequest;
+#[cfg(feature = "e2e-encryption")]
+use crate::encryption::{
+    backups::types::BackupClientState, recovery::RecoveryState, BackupDownloadStrategy, Encryption,
+    EncryptionSettings,
+};
 #[cfg(feature = "experimental-oidc")]
 use crate::oidc::Oidc;
+#[cfg(feature = "experimental-sliding-sync")]
+use crate::sliding_sync::WeakSlidingSync;
 use crate::{
     authentication::{AuthCtx, AuthData, ReloadSessionCallback, SaveSessionCallback},
-    config::RequestConfig,
+    config::{ConcurrentSyncPolicy, RequestConfig},
+    content_scanner::{ContentScannerConfig, ContentScannerState},
     deduplicating_handler::DeduplicatingHandler,
     error::{HttpError, HttpResult},
     event_handler::{
         EventHandler, EventHandlerDropGuard, EventHandlerHandle, EventHandlerStore, SyncEvent,
     },
+    executor::{spawn, JoinHandle},
+    features::{ClientFeature, ClientFeaturesState},
     http_client::HttpClient,
+    invite_filter::{InviteFilter, InviteFiltersState, JoinThrottle},
     matrix_auth::MatrixAuth,
     notification_settings::NotificationSettings,
-    sync::{RoomUpdate, SyncResponse},
+    profiles::ProfilesState,
+    room::RoomSendQueueState,
+    scheduled_messages::ScheduledMessagesState,
+    server_notices::ServerNoticesState,
+    store_cleanup::StoreCleanupState,
+    store_locks::CrossProcessStoreLock,
+    sync::{RoomUpdate, SyncResponse, SyncResponsePostProcessor},
     Account, AuthApi, AuthSession, Error, Media, RefreshTokenError, Result, Room,
     TransmissionProgress,
 };
-#[cfg(feature = "e2e-encryption")]
-use crate::{
-    encryption::{
-        backups::types::BackupClientState, recovery::RecoveryState, BackupDownloadStrategy,
-        Encryption, EncryptionSettings,
-    },
-    store_locks::CrossProcessStoreLock,
-};
 
 mod builder;
 pub(crate) mod futures;
 #[cfg(feature = "e2e-encryption")]
-mod tasks;
+pub(crate) mod tasks;
 
 pub use self::builder::{ClientBuildError, ClientBuilder};
 #[cfg(feature = "e2e-encryption")]
-use self::tasks::{BackupDownloadTask, BackupUploadingTask, ClientTasks};
+use self::tasks::{BackupDownloadTask, BackupUploadingTask, ClientTasks, KeyHealthTask};
 
 #[cfg(not(target_arch = "wasm32"))]
 type NotificationHandlerFut = Pin<Box<dyn Future<Output = ()> + Send>>;
@@ -140,6 +154,34 @@ pub enum SessionChange {
     TokensRefreshed,
 }
 
+/// The status of the session of a `Client`, see [`Client::session_status()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionStatus {
+    /// The session is not known to be invalid.
+    Active,
+    /// The homeserver confirmed that the session is gone for good.
+    ///
+    /// This is a terminal state. The background work of the `Client` is
+    /// stopped, and syncs are refused so the stores are not mutated by the dead
+    /// session anymore. The user needs to log in again.
+    SessionVerifiedGone {
+        /// Why the session is gone.
+        reason: SessionGoneReason,
+    },
+}
+
+/// Why the session of a `Client` is gone, see
+/// [`SessionStatus::SessionVerifiedGone`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionGoneReason {
+    /// The access token was rejected with an `M_UNKNOWN_TOKEN` error without
+    /// `soft_logout`, and it couldn't be refreshed.
+    UnknownToken,
+    /// The device of the session was deleted, which was noticed thanks to the
+    /// updates of the device list of the user in the sync.
+    DeviceDeleted,
+}
+
 /// An async/await enabled Matrix client.
 ///
 /// All of the state is held in an `Arc` so the `Client` can be cloned freely.
@@ -154,6 +196,9 @@ pub(crate) struct ClientLocks {
     /// Look at the [`Account::mark_as_dm()`] method for a more detailed
     /// explanation.
     pub(crate) mark_as_dm_lock: Mutex<()>,
+    /// Lock ensuring that only a single [`Client::sync_once()`] runs at a
+    /// time, so the sync tokens are stored in the order they were received.
+    pub(crate) sync_once_lock: Mutex<()>,
     /// Lock ensuring that only a single secret store is getting opened at the
     /// same time.
     ///
@@ -212,6 +257,16 @@ pub(crate) struct ClientLocks {
     /// outside the `OlmMachine`.
     #[cfg(feature = "e2e-encryption")]
     pub(crate) crypto_store_generation: Arc<Mutex<Option<u64>>>,
+    /// Lock on the state store deciding which process may advance the sync
+    /// token, if enabled with [`Client::enable_cross_process_sync_lock()`].
+    pub(crate) cross_process_sync_lock: OnceCell<CrossProcessStoreLock<LockableStateStore>>,
+    /// Latest generation of the sync token known by this process.
+    ///
+    /// This is a counter stored in the state store, that the process holding
+    /// the [`ClientLocks::cross_process_sync_lock`] increments every time it
+    /// advances the sync token. Observing a different value than this one in
+    /// the store means that another process synced under our feet.
+    pub(crate) sync_token_generation: Mutex<Option<u64>>,
 }
 
 pub(crate) struct ClientInner {
@@ -223,27 +278,58 @@ pub(crate) struct ClientInner {
     /// The sliding sync proxy that is trusted by the homeserver.
     #[cfg(feature = "experimental-sliding-sync")]
     sliding_sync_proxy: StdRwLock<Option<Url>>,
+    /// The sliding sync instances created with this client, to purge the
+    /// events they cache.
+    #[cfg(feature = "experimental-sliding-sync")]
+    pub(crate) sliding_syncs: StdMutex<Vec<WeakSlidingSync>>,
     /// The underlying HTTP client.
     pub(crate) http_client: HttpClient,
     /// User session data.
     base_client: BaseClient,
     /// The Matrix versions the server supports (well-known ones only)
     server_versions: OnceCell<Box<[MatrixVersion]>>,
+    /// The unstable features the server supports.
+    unstable_features: OnceCell<BTreeMap<String, bool>>,
     /// Collection of locks individual client methods might want to use, either
     /// to ensure that only a single call to a method happens at once or to
     /// deduplicate multiple calls to a method.
     locks: ClientLocks,
     #[cfg(feature = "e2e-encryption")]
     pub(crate) tasks: StdMutex<ClientTasks>,
+    /// The task checking that the device of the session still exists, see
+    /// [`Client::spawn_own_device_check`].
+    own_device_check: StdMutex<Option<JoinHandle<()>>>,
     pub(crate) typing_notice_times: StdRwLock<BTreeMap<OwnedRoomId, Instant>>,
     /// Event handlers. See `add_event_handler`.
     pub(crate) event_handlers: EventHandlerStore,
     /// Notification handlers. See `register_notification_handler`.
     notification_handlers: RwLock<Vec<NotificationHandlerFn>>,
     pub(crate) room_update_channels: StdMutex<BTreeMap<OwnedRoomId, broadcast::Sender<RoomUpdate>>>,
+    /// The state of the sending of events to every room, see
+    /// [`Room::send_queue()`].
+    pub(crate) room_send_queues: StdMutex<BTreeMap<OwnedRoomId, RoomSendQueueState>>,
     /// Whether the client should update its homeserver URL with the discovery
     /// information present in the login response.
     respect_login_well_known: bool,
+    /// The maximum time events are kept for locally, unless the retention
+    /// policy of a room is stricter.
+    pub(crate) default_max_event_lifetime: Option<Duration>,
+    /// The content scanner media is downloaded through, if any.
+    pub(crate) content_scanner: Option<ContentScannerState>,
+    /// The filters run on the invites received via sync.
+    pub(crate) invite_filters: InviteFiltersState,
+    /// The state of the automatic cleanup of the local stores.
+    pub(crate) store_cleanup: StoreCleanupState,
+    /// The server notices that are pinned in the server notices rooms.
+    pub(crate) server_notices: ServerNoticesState,
+    /// The features of the client that can be toggled at runtime.
+    pub(crate) features: ClientFeaturesState,
+    /// Whether the media prefetchers hold off starting new downloads.
+    pub(crate) media_prefetch_deferred: SharedObservable<bool>,
+    /// The state of the messages scheduled to be sent later.
+    pub(crate) scheduled_messages: ScheduledMessagesState,
+    /// The cache of the profiles of users.
+    pub(crate) profiles: ProfilesState,
     /// An event that can be listened on to wait for a successful sync. The
     /// event will only be fired if a sync loop is running. Can be used for
     /// synchronization, e.g. if we send out a request to create a room, we can
@@ -257,6 +343,17 @@ pub(crate) struct ClientInner {
     pub(crate) backup_state: BackupClientState,
     #[cfg(feature = "e2e-encryption")]
     pub(crate) recovery_state: SharedObservable<RecoveryState>,
+    #[cfg(feature = "e2e-encryption")]
+    pub(crate) secret_storage_status:
+        SharedObservable<crate::encryption::secret_storage::SecretStorageStatus>,
+    #[cfg(feature = "e2e-encryption")]
+    pub(crate) key_health: SharedObservable<crate::encryption::key_health::KeyHealth>,
+    #[cfg(feature = "e2e-encryption")]
+    pub(crate) trust_recomputation_state:
+        SharedObservable<crate::encryption::trust_recomputation::TrustRecomputationState>,
+    #[cfg(feature = "e2e-encryption")]
+    pub(crate) dehydrated_device_rotation_state:
+        SharedObservable<crate::encryption::dehydrated_devices::RotationState>,
 }
 
 impl ClientInner {
@@ -274,24 +371,44 @@ impl ClientInner {
         base_client: BaseClient,
         server_versions: Option<Box<[MatrixVersion]>>,
         respect_login_well_known: bool,
+        default_max_event_lifetime: Option<Duration>,
+        content_scanner: Option<ContentScannerConfig>,
+        invite_filters: Vec<Arc<dyn InviteFilter>>,
+        join_throttle: Option<JoinThrottle>,
         #[cfg(feature = "e2e-encryption")] encryption_settings: EncryptionSettings,
     ) -> Arc<Self> {
+        let store_cleanup = StoreCleanupState::new(base_client.clone());
+
         let client = Self {
             homeserver: StdRwLock::new(homeserver),
             auth_ctx,
             #[cfg(feature = "experimental-sliding-sync")]
             sliding_sync_proxy: StdRwLock::new(sliding_sync_proxy),
+            #[cfg(feature = "experimental-sliding-sync")]
+            sliding_syncs: Default::default(),
             http_client,
             base_client,
             #[cfg(feature = "e2e-encryption")]
             tasks: StdMutex::new(Default::default()),
+            own_device_check: Default::default(),
             locks: Default::default(),
             server_versions: OnceCell::new_with(server_versions),
+            unstable_features: OnceCell::new(),
             typing_notice_times: Default::default(),
             event_handlers: Default::default(),
             notification_handlers: Default::default(),
             room_update_channels: Default::default(),
+            room_send_queues: Default::default(),
             respect_login_well_known,
+            default_max_event_lifetime,
+            content_scanner: content_scanner.map(ContentScannerState::new),
+            invite_filters: InviteFiltersState::new(invite_filters, join_throttle),
+            store_cleanup,
+            server_notices: Default::default(),
+            features: Default::default(),
+            media_prefetch_deferred: Default::default(),
+            scheduled_messages: Default::default(),
+            profiles: Default::default(),
             sync_beat: event_listener::Event::new(),
             #[cfg(feature = "e2e-encryption")]
             encryption_settings,
@@ -299,6 +416,14 @@ impl ClientInner {
             backup_state: Default::default(),
             #[cfg(feature = "e2e-encryption")]
             recovery_state: Default::default(),
+            #[cfg(feature = "e2e-encryption")]
+            secret_storage_status: Default::default(),
+            #[cfg(feature = "e2e-encryption")]
+            key_health: Default::default(),
+            #[cfg(feature = "e2e-encryption")]
+            trust_recomputation_state: Default::default(),
+            #[cfg(feature = "e2e-encryption")]
+            dehydrated_device_rotation_state: Default::default(),
         };
 
         #[allow(clippy::let_and_return)]
@@ -311,6 +436,7 @@ impl ClientInner {
             let mut tasks = client.tasks.lock().unwrap();
 
             tasks.upload_room_keys = Some(BackupUploadingTask::new(weak_client.clone()));
+            tasks.check_key_health = Some(KeyHealthTask::new(weak_client.clone()));
 
             if encryption_settings.backup_download_strategy
                 == BackupDownloadStrategy::AfterDecryptionFailure
@@ -350,11 +476,46 @@ impl Client {
         self.inner.base_client.subscribe_to_ignore_user_list_changes()
     }
 
+    /// Get a receiver of the updates of the infos of all the rooms, saying
+    /// what changed in each of them.
+    ///
+    /// See [`RoomInfoUpdate`] for more details.
+    pub fn subscribe_to_room_info_updates(&self) -> broadcast::Receiver<RoomInfoUpdate> {
+        self.inner.base_client.subscribe_to_room_info_updates()
+    }
+
+    /// Get a receiver of the inconsistencies found in the room state of the
+    /// sync responses.
+    ///
+    /// Nothing is received unless the checks were enabled with
+    /// [`ClientBuilder::with_state_validation()`].
+    pub fn subscribe_to_state_divergences(&self) -> broadcast::Receiver<StateDivergence> {
+        self.inner.base_client.subscribe_to_state_divergences()
+    }
+
+    /// Register an extension that gets called on every sync response processed
+    /// by this client, before the event handlers are called.
+    ///
+    /// See [`SyncResponsePostProcessor`] for more details.
+    pub fn add_sync_response_post_processor(
+        &self,
+        post_processor: Arc<dyn SyncResponsePostProcessor>,
+    ) {
+        self.inner.base_client.add_sync_response_post_processor(post_processor);
+    }
+
     /// Create a new [`ClientBuilder`].
     pub fn builder() -> ClientBuilder {
         ClientBuilder::new()
     }
 
+    /// Get the source of time of this client.
+    ///
+    /// See [`ClientBuilder::clock()`].
+    pub fn clock(&self) -> &Arc<dyn Clock> {
+        self.base_client().clock()
+    }
+
     pub(crate) fn base_client(&self) -> &BaseClient {
         &self.inner.base_client
     }
@@ -861,6 +1022,33 @@ impl Client {
             .collect()
     }
 
+    /// Spawn a task that calls [`Room::purge_expired_events`] for all the rooms
+    /// the client knows about every `period`.
+    ///
+    /// The task stops when the client is dropped, or when the returned handle
+    /// is aborted.
+    pub fn enforce_retention_policies(&self, period: Duration) -> JoinHandle<()> {
+        let clock = self.base_client().clock().clone();
+        let weak_client = Arc::downgrade(&self.inner);
+
+        spawn(async move {
+            loop {
+                let Some(inner) = weak_client.upgrade() else {
+                    trace!("Client got dropped, stopping the retention policy task");
+                    break;
+                };
+
+                for room in (Client { inner }).rooms() {
+                    if let Err(e) = room.purge_expired_events().await {
+                        warn!(room_id = ?room.room_id(), "Couldn't purge expired events: {e}");
+                    }
+                }
+
+                clock.sleep(period).await;
+            }
+        })
+    }
+
     /// Get all the rooms the client knows about, filtered by room state.
     pub fn rooms_filtered(&self, filter: RoomStateFilter) -> Vec<Room> {
         self.base_client()
@@ -957,6 +1145,13 @@ impl Client {
 
     pub(crate) async fn set_session_meta(&self, session_meta: SessionMeta) -> Result<()> {
         self.base_client().set_session_meta(session_meta).await?;
+
+        // Load the features before anything is sent, for example a public read
+        // receipt when they are disabled.
+        if let Err(e) = self.features().load_from_store().await {
+            warn!("Couldn't load the client features: {e}");
+        }
+
         Ok(())
     }
 
@@ -1059,13 +1254,46 @@ impl Client {
     /// # Arguments
     ///
     /// * `room_id` - The `RoomId` of the room to be joined.
+    ///
+    /// If we're invited to the room, it is joined like with [`Room::join`],
+    /// through the servers from [`Client::via_servers_for_room`].
     pub async fn join_room_by_id(&self, room_id: &RoomId) -> Result<Room> {
-        let request = join_room_by_id::v3::Request::new(room_id.to_owned());
-        let response = self.send(request, None).await?;
-        let base_room = self.base_client().room_joined(&response.room_id).await?;
+        if let Some(room) = self.get_room(room_id).filter(|r| r.state() == RoomState::Invited) {
+            room.join().await?;
+            return Ok(room);
+        }
+
+        let response_room_id = self.send_join_request(room_id, Vec::new()).await?;
+        let base_room = self.base_client().room_joined(&response_room_id).await?;
         Ok(Room::new(self.clone(), base_room))
     }
 
+    /// Send a request to join the room with the given ID through the given
+    /// servers.
+    ///
+    /// Waits first until the join throttle allows it, if the joins are
+    /// throttled.
+    ///
+    /// Returns the ID of the joined room.
+    pub(crate) async fn send_join_request(
+        &self,
+        room_id: &RoomId,
+        via: Vec<OwnedServerName>,
+    ) -> HttpResult<OwnedRoomId> {
+        self.wait_for_join_slot().await;
+
+        if via.is_empty() {
+            let request = join_room_by_id::v3::Request::new(room_id.to_owned());
+            Ok(self.send(request, None).await?.room_id)
+        } else {
+            let room_id = <&RoomOrAliasId>::from(room_id).to_owned();
+            let request = assign!(join_room_by_id_or_alias::v3::Request::new(room_id), {
+                server_name: via,
+            });
+            Ok(self.send(request, None).await?.room_id)
+        }
+    }
+
     /// Join a room by `RoomId`.
     ///
     /// Returns a `join_room_by_id_or_alias::Response` consisting of the
@@ -1083,11 +1311,45 @@ impl Client {
         let request = assign!(join_room_by_id_or_alias::v3::Request::new(alias.to_owned()), {
             server_name: server_names.to_owned(),
         });
+        self.wait_for_join_slot().await;
         let response = self.send(request, None).await?;
         let base_room = self.base_client().room_joined(&response.room_id).await?;
         Ok(Room::new(self.clone(), base_room))
     }
 
+    /// Get the server of the given user, if it can be used as a `via` server
+    /// to route requests about the rooms this user is in, like invites,
+    /// permalinks or joins.
+    ///
+    /// Servers that are IP addresses are never returned, since they are
+    /// likely to change, as recommended by the [routing algorithm].
+    ///
+    /// [routing algorithm]: https://spec.matrix.org/v1.3/appendices/#routing
+    pub fn resolve_server_for_user(&self, user_id: &UserId) -> Option<OwnedServerName> {
+        let server = user_id.server_name();
+        (!server.is_ip_literal()).then(|| server.to_owned())
+    }
+
+    /// Get the best servers, at most three, to route requests about the room
+    /// with the given ID through, like joins or permalinks.
+    ///
+    /// The servers are chosen with the [routing algorithm] among the servers
+    /// of the members of the room: the server of the member with the highest
+    /// power level first, then the most populous servers. The servers denied
+    /// by the server ACLs of the room, or that aren't returned by
+    /// [`Client::resolve_server_for_user`], are skipped. When we're invited
+    /// to the room, the server of the inviter comes first.
+    ///
+    /// Returns an empty list if the room is unknown.
+    ///
+    /// [routing algorithm]: https://spec.matrix.org/v1.3/appendices/#routing
+    pub async fn via_servers_for_room(&self, room_id: &RoomId) -> Result<Vec<OwnedServerName>> {
+        match self.get_room(room_id) {
+            Some(room) => room.via_servers().await,
+            None => Ok(Vec::new()),
+        }
+    }
+
     /// Search the homeserver's directory of public rooms.
     ///
     /// Sends a request to "_matrix/client/r0/publicRooms", returns
@@ -1165,6 +1427,8 @@ impl Client {
     /// # };
     /// ```
     pub async fn create_room(&self, request: create_room::v3::Request) -> Result<Room> {
+        self.ensure_not_guest()?;
+
         let invite = request.invite.clone();
         let is_direct_room = request.is_direct;
         let response = self.send(request, None).await?;
@@ -1369,6 +1633,11 @@ impl Client {
             .auth_ctx
             .session_change_sender
             .send(SessionChange::UnknownToken { soft_logout: *soft_logout });
+
+        // With a soft logout, the user can log in again to the same session.
+        if !soft_logout {
+            self.mark_session_gone(SessionGoneReason::UnknownToken);
+        }
     }
 
     async fn request_server_versions(&self) -> HttpResult<Box<[MatrixVersion]>> {
@@ -1404,6 +1673,32 @@ impl Client {
         Ok(server_versions)
     }
 
+    /// Get the unstable features supported by the homeserver, as advertised
+    /// by its `/versions` endpoint.
+    ///
+    /// The features are only requested once, and then cached.
+    pub async fn unstable_features(&self) -> HttpResult<&BTreeMap<String, bool>> {
+        self.inner
+            .unstable_features
+            .get_or_try_init(|| async {
+                let response = self
+                    .inner
+                    .http_client
+                    .send(
+                        get_supported_versions::Request::new(),
+                        None,
+                        self.homeserver().to_string(),
+                        None,
+                        &[MatrixVersion::V1_0],
+                        Default::default(),
+                    )
+                    .await?;
+
+                Ok::<_, HttpError>(response.unstable_features)
+            })
+            .await
+    }
+
     /// Get information of all our own devices.
     ///
     /// # Examples
@@ -1551,6 +1846,9 @@ impl Client {
     ///       state events, regardless of our configured [`token`].
     ///     * [`set_presence`] - To tell the server to set the presence and to
     ///       which state.
+    ///     * [`concurrent_sync_policy`] - To decide whether to wait for another
+    ///       sync of this client that is still running, or to fail with
+    ///       [`Error::AlreadySyncing`]. Syncs never run at the same time.
     ///
     /// # Examples
     ///
@@ -1589,6 +1887,7 @@ impl Client {
     /// [`timeout`]: crate::config::SyncSettings#method.timeout
     /// [`full_state`]: crate::config::SyncSettings#method.full_state
     /// [`set_presence`]: ruma::presence::PresenceState
+    /// [`concurrent_sync_policy`]: crate::config::SyncSettings#method.concurrent_sync_policy
     /// [`filter`]: crate::config::SyncSettings#method.filter
     /// [`Filter`]: ruma::api::client::sync::sync_events::v3::Filter
     /// [`next_batch`]: SyncResponse#structfield.next_batch
@@ -1600,6 +1899,19 @@ impl Client {
         &self,
         sync_settings: crate::config::SyncSettings,
     ) -> Result<SyncResponse> {
+        // Don't let a dead session mutate the stores.
+        if self.is_session_gone() {
+            return Err(Error::SessionGone);
+        }
+
+        // Overlapping syncs would store their sync tokens in any order.
+        let _sync_once_guard = match sync_settings.concurrent_sync_policy {
+            ConcurrentSyncPolicy::Wait => self.locks().sync_once_lock.lock().await,
+            ConcurrentSyncPolicy::Fail => {
+                self.locks().sync_once_lock.try_lock().map_err(|_| Error::AlreadySyncing)?
+            }
+        };
+
         // The sync might not return for quite a while due to the timeout.
         // We'll see if there's anything crypto related to send out before we
         // sync, i.e. if we closed our client after a sync but before the
@@ -1611,11 +1923,35 @@ impl Client {
             error!(error = ?e, "Error while sending outgoing E2EE requests");
         }
 
+        // If another process shares our store, only one of us may advance the
+        // sync token, the others catch up with what it wrote in the store.
+        let sync_lock_guard = match self.locks().cross_process_sync_lock.get() {
+            Some(lock) => match lock.try_lock_once().await? {
+                Some(guard) => {
+                    self.maybe_reload_shared_sync_state().await?;
+                    Some(guard)
+                }
+                None => return self.passive_sync(sync_settings.token).await,
+            },
+            None => None,
+        };
+
+        // The token in the store is the most recent one when it's shared.
+        let since = if sync_lock_guard.is_some() {
+            self.sync_token().await.or(sync_settings.token)
+        } else {
+            sync_settings.token
+        };
+
         let request = assign!(sync_events::v3::Request::new(), {
             filter: sync_settings.filter.map(|f| *f),
-            since: sync_settings.token,
+            since,
             full_state: sync_settings.full_state,
-            set_presence: sync_settings.set_presence,
+            set_presence: if self.features().is_enabled(ClientFeature::Presence) {
+                sync_settings.set_presence
+            } else {
+                PresenceState::Offline
+            },
             timeout: sync_settings.timeout,
         });
         let mut request_config = self.request_config();
@@ -1625,7 +1961,11 @@ impl Client {
 
         let response = self.send(request, Some(request_config)).await?;
         let next_batch = response.next_batch.clone();
-        let response = self.process_sync(response).await?;
+        let response = if sync_lock_guard.is_some() {
+            self.process_shared_sync(response).await?
+        } else {
+            self.process_sync(response).await?
+        };
 
         #[cfg(feature = "e2e-encryption")]
         if let Err(e) = self.send_outgoing_requests().await {
@@ -1637,6 +1977,35 @@ impl Client {
         Ok(SyncResponse::new(next_batch, response))
     }
 
+    /// Enables the cross-process sync lock.
+    ///
+    /// This is required if several processes share the same state store and
+    /// sync with [`Client::sync_once()`], for example an app and its
+    /// notification extension. Only the process holding the lock advances the
+    /// sync token, the others don't send requests to the homeserver and
+    /// reload the sync token and the rooms from the store when it changed.
+    ///
+    /// The provided `lock_value` must be a unique identifier for this process.
+    pub fn enable_cross_process_sync_lock(&self, lock_value: String) {
+        if let Some(prev_lock) = self.locks().cross_process_sync_lock.get() {
+            let prev_holder = prev_lock.lock_holder();
+            if prev_holder != lock_value {
+                warn!(
+                    "the cross-process sync lock is already enabled with the holder value \
+                     {prev_holder}, ignoring the new value {lock_value}"
+                );
+            }
+            return;
+        }
+
+        let lock = self
+            .base_client()
+            .create_state_store_lock("cross_process_sync_lock".to_owned(), lock_value);
+
+        // If another task enabled the lock concurrently, keep its lock.
+        let _ = self.locks().cross_process_sync_lock.set(lock);
+    }
+
     /// Repeatedly synchronize the client state with the server.
     ///
     /// This method will only return on error, if cancellation is needed
@@ -1861,6 +2230,11 @@ impl Client {
             }
             trace!("Done running callback");
 
+            if self.is_session_gone() {
+                trace!("The session is gone, stopping");
+                break;
+            }
+
             Client::delay_sync(&mut last_sync_time).await
         }
 
@@ -1926,6 +2300,10 @@ impl Client {
             loop {
                 yield self.sync_loop_helper(&mut sync_settings).instrument(parent_span.clone()).await;
 
+                if self.is_session_gone() {
+                    break;
+                }
+
                 Client::delay_sync(&mut last_sync_time).await
             }
         }
@@ -1949,6 +2327,94 @@ impl Client {
         broadcast.subscribe()
     }
 
+    /// Get a subscriber to the status of the session of this client.
+    ///
+    /// Once the status is [`SessionStatus::SessionVerifiedGone`], it doesn't
+    /// change anymore.
+    pub fn session_status(&self) -> Subscriber<SessionStatus> {
+        self.inner.auth_ctx.session_status.subscribe()
+    }
+
+    /// Whether the homeserver confirmed that the session of this client is
+    /// gone.
+    pub(crate) fn is_session_gone(&self) -> bool {
+        matches!(
+            self.inner.auth_ctx.session_status.get(),
+            SessionStatus::SessionVerifiedGone { .. }
+        )
+    }
+
+    /// Mark the session of this client as gone for good, and stop all the work
+    /// that the client does in the background.
+    pub(crate) fn mark_session_gone(&self, reason: SessionGoneReason) {
+        let mut changed = false;
+        self.inner.auth_ctx.session_status.update_if(|status| {
+            // The first reason wins, the status is terminal.
+            changed = *status == SessionStatus::Active;
+            if changed {
+                *status = SessionStatus::SessionVerifiedGone { reason };
+            }
+            changed
+        });
+
+        if !changed {
+            return;
+        }
+
+        warn!(?reason, "The session is gone, stopping the background work");
+
+        #[cfg(feature = "e2e-encryption")]
+        {
+            let tasks = std::mem::take(&mut *self.inner.tasks.lock().unwrap());
+            #[cfg(not(target_arch = "wasm32"))]
+            if let Some(setup_e2ee) = &tasks.setup_e2ee {
+                setup_e2ee.abort();
+            }
+            // The other tasks are aborted when dropped.
+            drop(tasks);
+        }
+
+        self.store_cleanup().stop();
+        self.scheduled_messages().stop();
+    }
+
+    /// Check in the background that the device of the session still exists,
+    /// after the device list of the user changed, so the processing of the
+    /// sync response doesn't wait for the request.
+    pub(crate) fn spawn_own_device_check(&self) {
+        let client = self.clone();
+        let task = spawn(async move { client.check_own_device_exists().await });
+
+        // A new check supersedes the previous one, which is aborted. On wasm,
+        // dropping the handle is enough to cancel the task.
+        let previous = self.inner.own_device_check.lock().unwrap().replace(task);
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(previous) = previous {
+            previous.abort();
+        }
+        #[cfg(target_arch = "wasm32")]
+        drop(previous);
+    }
+
+    /// Check that the device of the session still exists.
+    async fn check_own_device_exists(&self) {
+        let Some(device_id) = self.device_id() else {
+            return;
+        };
+
+        let request = get_device::v3::Request::new(device_id.to_owned());
+        match self.send(request, None).await {
+            Ok(_) => {}
+            Err(error) if matches!(error.client_api_error_kind(), Some(ErrorKind::NotFound)) => {
+                self.mark_session_gone(SessionGoneReason::DeviceDeleted);
+            }
+            // An unknown token is handled when sending the request.
+            Err(error) => {
+                debug!("Couldn't check whether the device of the session still exists: {error}");
+            }
+        }
+    }
+
     /// Sets the save/restore session callbacks.
     ///
     /// This is another mechanism to get synchronous updates to session tokens,
@@ -2007,6 +2473,10 @@ impl Client {
                 self.inner.base_client.clone_with_in_memory_state_store(),
                 self.inner.server_versions.get().cloned(),
                 self.inner.respect_login_well_known,
+                self.inner.default_max_event_lifetime,
+                self.inner.content_scanner.as_ref().map(|scanner| scanner.config.clone()),
+                self.inner.invite_filters.filters.clone(),
+                self.inner.invite_filters.join_throttle,
                 #[cfg(feature = "e2e-encryption")]
                 self.inner.encryption_settings,
             ),
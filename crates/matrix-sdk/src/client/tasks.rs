@@ -12,18 +12,26 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{collections::BTreeMap, sync::Weak, time::Duration};
+use std::{collections::BTreeMap, panic::AssertUnwindSafe, sync::Weak, time::Duration};
 
-use futures_util::future::join_all;
+use futures_util::{
+    future::{self, join_all, Either},
+    pin_mut, FutureExt, StreamExt,
+};
 use matrix_sdk_common::failures_cache::FailuresCache;
-use ruma::OwnedRoomId;
+use ruma::{events::secret::request::SecretName, OwnedRoomId};
 use tokio::sync::mpsc::{self, UnboundedReceiver};
-use tracing::{trace, warn};
+use tracing::{error, trace, warn};
+use zeroize::Zeroizing;
 
 use super::ClientInner;
 use crate::{
-    encryption::backups::UploadState,
-    executor::{spawn, JoinHandle},
+    encryption::{
+        backups::UploadState, dehydrated_devices::RotationState,
+        key_health::KEY_HEALTH_CHECK_INTERVAL, secrets_stream,
+        trust_recomputation::USERS_PER_CHUNK,
+    },
+    executor::{spawn, yield_now, JoinHandle},
     Client,
 };
 
@@ -33,6 +41,14 @@ pub(crate) struct ClientTasks {
     pub(crate) upload_room_keys: Option<BackupUploadingTask>,
     #[cfg(feature = "e2e-encryption")]
     pub(crate) download_room_keys: Option<BackupDownloadTask>,
+    #[cfg(feature = "e2e-encryption")]
+    pub(crate) rotate_dehydrated_device: Option<DehydratedDeviceRotationTask>,
+    #[cfg(feature = "e2e-encryption")]
+    pub(crate) check_key_health: Option<KeyHealthTask>,
+    #[cfg(feature = "e2e-encryption")]
+    pub(crate) recompute_trust: Option<TrustRecomputationTask>,
+    #[cfg(feature = "e2e-encryption")]
+    pub(crate) receive_secrets: Option<SecretReceptionTask>,
     pub(crate) setup_e2ee: Option<JoinHandle<()>>,
 }
 
@@ -131,7 +147,11 @@ impl BackupDownloadTask {
         failures_cache: FailuresCache<RoomKeyInfo>,
     ) {
         // Wait a bit, perhaps the room key will arrive in the meantime.
-        tokio::time::sleep(Duration::from_millis(Self::DOWNLOAD_DELAY_MILLIS)).await;
+        client
+            .base_client()
+            .clock()
+            .sleep(Duration::from_millis(Self::DOWNLOAD_DELAY_MILLIS))
+            .await;
 
         if let Some(machine) = client.olm_machine().await.as_ref() {
             let (room_id, session_id) = &room_key_info;
@@ -139,7 +159,13 @@ impl BackupDownloadTask {
             if !machine.is_room_key_available(room_id, session_id).await.unwrap() {
                 match client.encryption().backups().download_room_key(room_id, session_id).await {
                     Ok(_) => failures_cache.remove(std::iter::once(&room_key_info)),
-                    Err(_) => failures_cache.insert(room_key_info),
+                    Err(e) => {
+                        warn!(
+                            ?room_key_info,
+                            "Couldn't download the room key from the backup: {e}"
+                        );
+                        failures_cache.insert(room_key_info);
+                    }
                 }
             }
         }
@@ -192,3 +218,290 @@ impl BackupDownloadTask {
         }
     }
 }
+
+/// How long the rotation of the dehydrated device waits before it is retried
+/// after it failed, or before the task is restarted after it panicked. It is
+/// doubled after every consecutive failure, up to the rotation interval.
+#[cfg(feature = "e2e-encryption")]
+const DEHYDRATED_DEVICE_ROTATION_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// The task replacing the dehydrated device periodically.
+#[cfg(feature = "e2e-encryption")]
+pub(crate) struct DehydratedDeviceRotationTask {
+    #[allow(dead_code)]
+    join_handle: JoinHandle<()>,
+}
+
+#[cfg(feature = "e2e-encryption")]
+impl Drop for DehydratedDeviceRotationTask {
+    fn drop(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.join_handle.abort();
+    }
+}
+
+#[cfg(feature = "e2e-encryption")]
+impl DehydratedDeviceRotationTask {
+    pub(crate) fn new(
+        client: Weak<ClientInner>,
+        pickle_key: Zeroizing<[u8; 32]>,
+        interval: Duration,
+    ) -> Self {
+        let join_handle = spawn(async move {
+            Self::supervise(client, pickle_key, interval).await;
+        });
+
+        Self { join_handle }
+    }
+
+    /// Run the rotation, and restart it if it panics.
+    async fn supervise(
+        client: Weak<ClientInner>,
+        pickle_key: Zeroizing<[u8; 32]>,
+        interval: Duration,
+    ) {
+        loop {
+            let rotation = AssertUnwindSafe(Self::run(client.clone(), &pickle_key, interval));
+            if rotation.catch_unwind().await.is_ok() {
+                break;
+            }
+
+            let Some(client) = client.upgrade() else {
+                trace!("Client got dropped, shutting down the task");
+                break;
+            };
+
+            error!("The rotation of the dehydrated device panicked, restarting it");
+            client.dehydrated_device_rotation_state.set(RotationState::Idle);
+
+            let clock = client.base_client.clock().clone();
+            drop(client);
+            clock.sleep(DEHYDRATED_DEVICE_ROTATION_RETRY_DELAY).await;
+        }
+    }
+
+    /// Rotate the dehydrated device every `interval`, or sooner after a
+    /// failure, until the client is dropped.
+    async fn run(client: Weak<ClientInner>, pickle_key: &[u8; 32], interval: Duration) {
+        let mut failures = 0;
+
+        loop {
+            let Some(client) = client.upgrade() else {
+                trace!("Client got dropped, shutting down the task");
+                break;
+            };
+
+            let client = Client { inner: client };
+            let clock = client.base_client().clock().clone();
+
+            // The delay starts with the rotation, however long it takes.
+            let started = clock.now();
+            let delay =
+                if client.encryption().dehydrated_devices().rotate_and_report(pickle_key).await {
+                    failures = 0;
+                    interval
+                } else {
+                    failures += 1;
+                    let backoff = 1u32 << (failures - 1).min(16);
+                    DEHYDRATED_DEVICE_ROTATION_RETRY_DELAY.saturating_mul(backoff).min(interval)
+                };
+
+            // Don't keep the client alive while sleeping.
+            drop(client);
+
+            clock.sleep((started + delay).saturating_duration_since(clock.now())).await;
+        }
+    }
+}
+
+/// The task checking the health of the one-time keys and the fallback key
+/// periodically, in case the client doesn't sync often enough.
+#[cfg(feature = "e2e-encryption")]
+pub(crate) struct KeyHealthTask {
+    #[allow(dead_code)]
+    join_handle: JoinHandle<()>,
+}
+
+#[cfg(feature = "e2e-encryption")]
+impl Drop for KeyHealthTask {
+    fn drop(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.join_handle.abort();
+    }
+}
+
+#[cfg(feature = "e2e-encryption")]
+impl KeyHealthTask {
+    pub(crate) fn new(client: Weak<ClientInner>) -> Self {
+        let join_handle = spawn(async move {
+            Self::run(client).await;
+        });
+
+        Self { join_handle }
+    }
+
+    async fn run(client: Weak<ClientInner>) {
+        loop {
+            let Some(clock) = client.upgrade().map(|c| c.base_client.clock().clone()) else {
+                trace!("Client got dropped, shutting down the task");
+                break;
+            };
+
+            clock.sleep(KEY_HEALTH_CHECK_INTERVAL).await;
+
+            let Some(client) = client.upgrade() else {
+                trace!("Client got dropped, shutting down the task");
+                break;
+            };
+
+            let client = Client { inner: client };
+
+            // The keys can't be checked before logging in.
+            if client.olm_machine().await.is_none() {
+                continue;
+            }
+
+            if let Err(e) = client.encryption().check_key_health().await {
+                warn!("Couldn't check the health of the keys of this device: {e}");
+            }
+        }
+    }
+}
+
+/// The task recomputing the trust of the devices of the tracked users in
+/// chunks, after the private cross-signing keys were imported.
+#[cfg(feature = "e2e-encryption")]
+pub(crate) struct TrustRecomputationTask {
+    #[allow(dead_code)]
+    join_handle: JoinHandle<()>,
+}
+
+#[cfg(feature = "e2e-encryption")]
+impl Drop for TrustRecomputationTask {
+    fn drop(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.join_handle.abort();
+    }
+}
+
+#[cfg(feature = "e2e-encryption")]
+impl TrustRecomputationTask {
+    pub(crate) fn new(client: Weak<ClientInner>) -> Self {
+        let join_handle = spawn(async move {
+            Self::run(client).await;
+        });
+
+        Self { join_handle }
+    }
+
+    async fn run(client: Weak<ClientInner>) {
+        let Some(inner) = client.upgrade() else {
+            trace!("Client got dropped, shutting down the task");
+            return;
+        };
+
+        let users = match (Client { inner }).encryption().users_for_trust_recomputation().await {
+            Ok(users) => users,
+            Err(e) => {
+                warn!("Couldn't load the users to recompute the trust of: {e}");
+                return;
+            }
+        };
+
+        let mut processed_users = 0;
+        let mut verified_devices = 0;
+
+        for chunk in users.chunks(USERS_PER_CHUNK) {
+            // Don't keep the client alive between chunks.
+            let Some(inner) = client.upgrade() else {
+                trace!("Client got dropped, shutting down the task");
+                return;
+            };
+
+            let encryption = Client { inner }.encryption();
+
+            match encryption.recompute_trust_of_users(chunk).await {
+                Ok(count) => verified_devices += count,
+                Err(e) => {
+                    warn!("Couldn't recompute the trust of the devices: {e}");
+                    return;
+                }
+            }
+
+            processed_users += chunk.len();
+            encryption.report_trust_recomputation_progress(processed_users);
+            drop(encryption);
+
+            yield_now().await;
+        }
+
+        if let Some(inner) = client.upgrade() {
+            (Client { inner }).encryption().finish_trust_recomputation(verified_devices);
+        }
+    }
+}
+
+/// The task handling the secrets received from our other devices.
+///
+/// It listens to the secrets stream of the [`OlmMachine`], which is
+/// re-attached every time the [`OlmMachine`] is reloaded. The secrets received
+/// while the [`OlmMachine`] is reloaded, or by another process, are only in the
+/// secret inbox, so it is checked again after every reload.
+///
+/// [`OlmMachine`]: matrix_sdk_base::crypto::OlmMachine
+#[cfg(feature = "e2e-encryption")]
+pub(crate) struct SecretReceptionTask {
+    #[allow(dead_code)]
+    join_handle: JoinHandle<()>,
+}
+
+#[cfg(feature = "e2e-encryption")]
+impl Drop for SecretReceptionTask {
+    fn drop(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.join_handle.abort();
+    }
+}
+
+#[cfg(feature = "e2e-encryption")]
+impl SecretReceptionTask {
+    pub(crate) fn new(client: Weak<ClientInner>) -> Self {
+        let join_handle = spawn(async move {
+            Self::run(client).await;
+        });
+
+        Self { join_handle }
+    }
+
+    async fn run(client: Weak<ClientInner>) {
+        let Some(mut reloads) =
+            client.upgrade().map(|inner| inner.base_client.subscribe_to_olm_machine_reloads())
+        else {
+            return;
+        };
+
+        let secrets = secrets_stream(client.clone());
+        pin_mut!(secrets);
+
+        loop {
+            let reload = reloads.next();
+            pin_mut!(reload);
+
+            let check_secret_inbox = match future::select(secrets.next(), reload).await {
+                Either::Left((Some(secret), _)) => secret.secret_name == SecretName::RecoveryKey,
+                Either::Right((Some(()), _)) => {
+                    trace!("The OlmMachine was reloaded, checking the secret inbox");
+                    true
+                }
+                Either::Left((None, _)) | Either::Right((None, _)) => break,
+            };
+
+            if check_secret_inbox {
+                let Some(inner) = client.upgrade() else { break };
+                Client { inner }.encryption().backups().check_secret_inbox().await;
+            }
+        }
+
+        trace!("The secrets stream ended, shutting down the task");
+    }
+}
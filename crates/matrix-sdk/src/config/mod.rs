@@ -19,4 +19,4 @@ mod sync;
 
 pub use matrix_sdk_base::store::StoreConfig;
 pub use request::RequestConfig;
-pub use sync::SyncSettings;
+pub use sync::{ConcurrentSyncPolicy, SyncSettings};
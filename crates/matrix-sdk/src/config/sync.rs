@@ -19,6 +19,18 @@ use ruma::{api::client::sync::sync_events, presence::PresenceState};
 
 const DEFAULT_SYNC_TIMEOUT: Duration = Duration::from_secs(30);
 
+/// What [`Client::sync_once()`](crate::Client::sync_once) does when another
+/// call to it is still running for the same client.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConcurrentSyncPolicy {
+    /// Wait for the other sync to be done, then sync.
+    #[default]
+    Wait,
+    /// Fail right away with
+    /// [`Error::AlreadySyncing`](crate::Error::AlreadySyncing).
+    Fail,
+}
+
 /// Settings for a sync call.
 #[derive(Clone)]
 pub struct SyncSettings {
@@ -28,6 +40,7 @@ pub struct SyncSettings {
     pub(crate) token: Option<String>,
     pub(crate) full_state: bool,
     pub(crate) set_presence: PresenceState,
+    pub(crate) concurrent_sync_policy: ConcurrentSyncPolicy,
 }
 
 impl Default for SyncSettings {
@@ -39,12 +52,14 @@ impl Default for SyncSettings {
 #[cfg(not(tarpaulin_include))]
 impl fmt::Debug for SyncSettings {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let Self { filter, timeout, token: _, full_state, set_presence } = self;
+        let Self { filter, timeout, token: _, full_state, set_presence, concurrent_sync_policy } =
+            self;
         f.debug_struct("SyncSettings")
             .maybe_field("filter", filter)
             .maybe_field("timeout", timeout)
             .field("full_state", full_state)
             .field("set_presence", set_presence)
+            .field("concurrent_sync_policy", concurrent_sync_policy)
             .finish()
     }
 }
@@ -59,6 +74,7 @@ impl SyncSettings {
             token: None,
             full_state: false,
             set_presence: PresenceState::Online,
+            concurrent_sync_policy: ConcurrentSyncPolicy::Wait,
         }
     }
 
@@ -128,4 +144,18 @@ impl SyncSettings {
         self.set_presence = presence;
         self
     }
+
+    /// Set what happens when another sync of the client is still running.
+    ///
+    /// Overlapping syncs would store their sync tokens in any order, so they
+    /// are never run at the same time. By default, the sync waits for the
+    /// other one to be done.
+    ///
+    /// # Arguments
+    /// * `policy` - What to do when another sync is still running.
+    #[must_use]
+    pub fn concurrent_sync_policy(mut self, policy: ConcurrentSyncPolicy) -> Self {
+        self.concurrent_sync_policy = policy;
+        self
+    }
 }
@@ -0,0 +1,470 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Downloading media through a [Matrix Content Scanner].
+//!
+//! When a content scanner is configured with
+//! [`ClientBuilder::content_scanner()`], the media is downloaded through it
+//! instead of the homeserver, and the scanner refuses to serve media that it
+//! doesn't consider clean. The verdict of the scanner can be queried with
+//! [`Media::scan()`], or obtained alongside the content with
+//! [`Media::get_scanned_media_content()`].
+//!
+//! The keys of encrypted media are sent to the scanner so it can decrypt the
+//! media. If the scanner advertises a public key, the requests containing the
+//! keys are encrypted for it, as described in [MSC3904].
+//!
+//! The access token of the homeserver is never sent to the scanner. If the
+//! scanner requires authentication, a token dedicated to it can be set with
+//! [`ClientBuilder::content_scanner_access_token()`].
+//!
+//! [Matrix Content Scanner]: https://github.com/matrix-org/matrix-content-scanner-python
+//! [MSC3904]: https://github.com/matrix-org/matrix-spec-proposals/pull/3904
+//! [`ClientBuilder::content_scanner()`]: crate::ClientBuilder::content_scanner
+//! [`ClientBuilder::content_scanner_access_token()`]: crate::ClientBuilder::content_scanner_access_token
+
+use std::{collections::BTreeMap, fmt, sync::Mutex as StdMutex};
+
+use http::{header::CONTENT_TYPE, StatusCode};
+#[cfg(feature = "e2e-encryption")]
+use matrix_sdk_base::crypto::{
+    backups::PkEncryption,
+    vodozemac::{base64_encode, Curve25519PublicKey, KeyError},
+};
+use matrix_sdk_base::media::{MediaFormat, MediaRequest};
+use ruma::{
+    events::room::{EncryptedFile, MediaSource},
+    MxcUri, OwnedMxcUri,
+};
+use serde::Deserialize;
+use serde_json::json;
+use thiserror::Error;
+#[cfg(feature = "e2e-encryption")]
+use tokio::sync::OnceCell;
+use tracing::{debug, instrument};
+use url::Url;
+
+use crate::{media::decrypt_media_content, Media, Result};
+
+/// The prefix of the paths of the content scanner API.
+const API_PREFIX: &str = "_matrix/media_proxy/unstable";
+
+/// The reason given by the content scanner when it refuses to serve media.
+const NOT_CLEAN_REASON: &str = "MCS_MEDIA_NOT_CLEAN";
+
+/// The verdict of the content scanner about a media file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScanVerdict {
+    /// The media is clean.
+    Clean,
+
+    /// The media is infected, or otherwise refused by the scanner.
+    Infected {
+        /// The details given by the scanner, if any.
+        info: Option<String>,
+    },
+}
+
+impl ScanVerdict {
+    /// Whether the media is clean.
+    pub fn is_clean(&self) -> bool {
+        matches!(self, Self::Clean)
+    }
+}
+
+/// The content of a media file downloaded through the content scanner, with
+/// the verdict of the scanner.
+#[derive(Clone, Debug)]
+pub struct ScannedMediaContent {
+    /// The verdict of the content scanner.
+    pub verdict: ScanVerdict,
+
+    /// The content of the media, only available if it is clean.
+    pub content: Option<Vec<u8>>,
+}
+
+impl ScannedMediaContent {
+    /// Get the content of the media, or an error if it isn't clean.
+    pub fn into_content(self) -> Result<Vec<u8>, ContentScannerError> {
+        match (self.content, self.verdict) {
+            (Some(content), _) => Ok(content),
+            (None, ScanVerdict::Infected { info }) => Err(ContentScannerError::NotClean { info }),
+            (None, ScanVerdict::Clean) => Err(ContentScannerError::NotClean { info: None }),
+        }
+    }
+}
+
+/// An error of the content scanner.
+#[derive(Debug, Error)]
+pub enum ContentScannerError {
+    /// No content scanner is configured.
+    #[error("no content scanner is configured")]
+    NotConfigured,
+
+    /// The content scanner refused to serve the media.
+    #[error("the media was refused by the content scanner")]
+    NotClean {
+        /// The details given by the scanner, if any.
+        info: Option<String>,
+    },
+
+    /// The MXC URI of the media is invalid.
+    #[error("the MXC URI of the media is invalid")]
+    InvalidMxcUri,
+
+    /// The content scanner responded with an unexpected status code.
+    #[error("the content scanner responded with an unexpected status code: {0}")]
+    UnexpectedStatus(StatusCode),
+
+    /// The public key of the content scanner is invalid.
+    #[cfg(feature = "e2e-encryption")]
+    #[error("the public key of the content scanner is invalid: {0}")]
+    InvalidPublicKey(#[from] KeyError),
+}
+
+/// How to reach the content scanner of a client.
+#[derive(Clone)]
+pub(crate) struct ContentScannerConfig {
+    pub(crate) url: Url,
+    /// The token to authenticate to the scanner with, if it requires one.
+    ///
+    /// This is never the access token of the homeserver.
+    pub(crate) access_token: Option<String>,
+}
+
+impl fmt::Debug for ContentScannerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ContentScannerConfig")
+            .field("url", &self.url)
+            .field("has_access_token", &self.access_token.is_some())
+            .finish()
+    }
+}
+
+/// The state of the content scanner of a client.
+#[derive(Debug)]
+pub(crate) struct ContentScannerState {
+    pub(crate) config: ContentScannerConfig,
+    /// The verdicts received so far, by MXC URI.
+    verdicts: StdMutex<BTreeMap<OwnedMxcUri, ScanVerdict>>,
+    /// The key to encrypt the requests for the scanner with, if it has one.
+    #[cfg(feature = "e2e-encryption")]
+    public_key: OnceCell<Option<Curve25519PublicKey>>,
+}
+
+impl ContentScannerState {
+    pub(crate) fn new(config: ContentScannerConfig) -> Self {
+        Self {
+            config,
+            verdicts: Default::default(),
+            #[cfg(feature = "e2e-encryption")]
+            public_key: OnceCell::new(),
+        }
+    }
+
+    fn cached_verdict(&self, uri: &MxcUri) -> Option<ScanVerdict> {
+        self.verdicts.lock().unwrap().get(uri).cloned()
+    }
+
+    fn cache_verdict(&self, uri: &MxcUri, verdict: ScanVerdict) {
+        self.verdicts.lock().unwrap().insert(uri.to_owned(), verdict);
+    }
+
+    fn endpoint(&self, path: &str) -> Result<Url> {
+        Ok(Url::parse(&format!(
+            "{}/{API_PREFIX}/{path}",
+            self.config.url.as_str().trim_end_matches('/')
+        ))?)
+    }
+}
+
+/// A response of the content scanner.
+enum ScannerResponse {
+    /// The request succeeded, with the given body.
+    Ok(Vec<u8>),
+    /// The scanner refused to serve the media.
+    NotClean { info: Option<String> },
+}
+
+/// The body of a scan response.
+#[derive(Deserialize)]
+struct ScanResponse {
+    clean: bool,
+    #[serde(default)]
+    info: Option<String>,
+}
+
+/// The body of an error response.
+#[derive(Deserialize)]
+struct ErrorResponse {
+    reason: String,
+    #[serde(default)]
+    info: Option<String>,
+}
+
+impl Media {
+    /// Ask the content scanner whether the given media is clean.
+    ///
+    /// The verdicts are cached for the lifetime of the client.
+    #[instrument(skip_all)]
+    pub async fn scan(&self, source: &MediaSource) -> Result<ScanVerdict> {
+        let scanner = self.content_scanner()?;
+        let uri = media_source_uri(source);
+
+        if let Some(verdict) = scanner.cached_verdict(uri) {
+            return Ok(verdict);
+        }
+
+        let response = match source {
+            MediaSource::Plain(uri) => {
+                let path = format!("scan/{}", media_path(uri)?);
+                self.scanner_request(scanner, &path, None).await?
+            }
+            MediaSource::Encrypted(file) => {
+                let body = self.encrypted_file_body(scanner, file).await?;
+                self.scanner_request(scanner, "scan_encrypted", Some(body)).await?
+            }
+        };
+
+        let verdict = match response {
+            ScannerResponse::Ok(body) => {
+                let response: ScanResponse = serde_json::from_slice(&body)?;
+
+                if response.clean {
+                    ScanVerdict::Clean
+                } else {
+                    ScanVerdict::Infected { info: response.info }
+                }
+            }
+            ScannerResponse::NotClean { info } => ScanVerdict::Infected { info },
+        };
+
+        scanner.cache_verdict(uri, verdict.clone());
+
+        Ok(verdict)
+    }
+
+    /// Get a media file's content through the content scanner, along with the
+    /// verdict of the scanner.
+    ///
+    /// If the content is encrypted and encryption is enabled, the content will
+    /// be decrypted.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The `MediaRequest` of the content.
+    ///
+    /// * `use_cache` - If we should use the media cache for this request. The
+    ///   content in the cache might have been downloaded before the content
+    ///   scanner was configured, so it is only returned once the scanner says
+    ///   it is clean, see [`Media::scan()`].
+    #[instrument(skip_all)]
+    pub async fn get_scanned_media_content(
+        &self,
+        request: &MediaRequest,
+        use_cache: bool,
+    ) -> Result<ScannedMediaContent> {
+        let scanner = self.content_scanner()?;
+        let uri = request.uri();
+
+        if let Some(verdict @ ScanVerdict::Infected { .. }) = scanner.cached_verdict(uri) {
+            return Ok(ScannedMediaContent { verdict, content: None });
+        }
+
+        if use_cache {
+            if let Some(content) = self.client.store().get_media_content(request).await? {
+                let verdict = self.scan(&request.source).await?;
+                let content = verdict.is_clean().then_some(content);
+
+                return Ok(ScannedMediaContent { verdict, content });
+            }
+        }
+
+        let response = match (&request.source, &request.format) {
+            (MediaSource::Plain(uri), MediaFormat::File) => {
+                let path = format!("download/{}", media_path(uri)?);
+                self.scanner_request(scanner, &path, None).await?
+            }
+            (MediaSource::Plain(uri), MediaFormat::Thumbnail(size)) => {
+                let path = format!(
+                    "thumbnail/{}?width={}&height={}&method={}",
+                    media_path(uri)?,
+                    size.width,
+                    size.height,
+                    size.method.as_str(),
+                );
+                self.scanner_request(scanner, &path, None).await?
+            }
+            (MediaSource::Encrypted(file), _) => {
+                let body = self.encrypted_file_body(scanner, file).await?;
+                self.scanner_request(scanner, "download_encrypted", Some(body)).await?
+            }
+        };
+
+        let content = match response {
+            ScannerResponse::Ok(content) => content,
+            ScannerResponse::NotClean { info } => {
+                debug!(?uri, "The content scanner refused to serve the media");
+
+                let verdict = ScanVerdict::Infected { info };
+                scanner.cache_verdict(uri, verdict.clone());
+
+                return Ok(ScannedMediaContent { verdict, content: None });
+            }
+        };
+
+        scanner.cache_verdict(uri, ScanVerdict::Clean);
+
+        let content = match &request.source {
+            MediaSource::Encrypted(file) => decrypt_media_content(file, content)?,
+            MediaSource::Plain(_) => content,
+        };
+
+        if use_cache {
+            self.client.store().add_media_content(request, content.clone()).await?;
+            self.client.store_cleanup().on_media_added(request, content.len()).await;
+        }
+
+        Ok(ScannedMediaContent { verdict: ScanVerdict::Clean, content: Some(content) })
+    }
+
+    fn content_scanner(&self) -> Result<&ContentScannerState> {
+        Ok(self.client.inner.content_scanner.as_ref().ok_or(ContentScannerError::NotConfigured)?)
+    }
+
+    /// Send a request to the content scanner, a `GET` request without body or
+    /// a `POST` request with the given JSON body.
+    async fn scanner_request(
+        &self,
+        scanner: &ContentScannerState,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> Result<ScannerResponse> {
+        let url = scanner.endpoint(path)?;
+        let http_client = &self.client.inner.http_client.inner;
+
+        let mut request = match body {
+            Some(body) => http_client
+                .post(url)
+                .header(CONTENT_TYPE, "application/json")
+                .body(serde_json::to_vec(&body)?),
+            None => http_client.get(url),
+        };
+
+        // Never send the access token of the homeserver to the scanner, it's a
+        // different service.
+        if let Some(access_token) = &scanner.config.access_token {
+            request = request.bearer_auth(access_token);
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response.bytes().await?.to_vec();
+
+        if status.is_success() {
+            return Ok(ScannerResponse::Ok(body));
+        }
+
+        match serde_json::from_slice::<ErrorResponse>(&body) {
+            Ok(error) if status == StatusCode::FORBIDDEN && error.reason == NOT_CLEAN_REASON => {
+                Ok(ScannerResponse::NotClean { info: error.info })
+            }
+            _ => Err(ContentScannerError::UnexpectedStatus(status).into()),
+        }
+    }
+
+    /// The body of a request about an encrypted file, encrypted for the
+    /// scanner if it has a public key.
+    async fn encrypted_file_body(
+        &self,
+        scanner: &ContentScannerState,
+        file: &EncryptedFile,
+    ) -> Result<serde_json::Value> {
+        let body = json!({ "file": file });
+
+        #[cfg(feature = "e2e-encryption")]
+        if let Some(public_key) = self.scanner_public_key(scanner).await? {
+            let message = PkEncryption::from_key(public_key).encrypt(&serde_json::to_vec(&body)?);
+
+            return Ok(json!({
+                "encrypted_body": {
+                    "ciphertext": base64_encode(message.ciphertext),
+                    "mac": base64_encode(message.mac),
+                    "ephemeral": message.ephemeral_key.to_base64(),
+                },
+            }));
+        }
+
+        #[cfg(not(feature = "e2e-encryption"))]
+        let _ = scanner;
+
+        Ok(body)
+    }
+
+    /// Get the public key of the scanner, if it has one.
+    #[cfg(feature = "e2e-encryption")]
+    async fn scanner_public_key(
+        &self,
+        scanner: &ContentScannerState,
+    ) -> Result<Option<Curve25519PublicKey>> {
+        #[derive(Deserialize)]
+        struct PublicKeyResponse {
+            public_key: String,
+        }
+
+        let public_key = scanner
+            .public_key
+            .get_or_try_init(|| async {
+                let body = match self.scanner_request(scanner, "public_key", None).await {
+                    Ok(ScannerResponse::Ok(body)) => body,
+                    Err(crate::Error::ContentScanner(ContentScannerError::UnexpectedStatus(
+                        status,
+                    ))) if status == StatusCode::NOT_FOUND => {
+                        debug!("The content scanner has no public key");
+                        return Ok(None);
+                    }
+                    Ok(ScannerResponse::NotClean { .. }) => {
+                        return Err(
+                            ContentScannerError::UnexpectedStatus(StatusCode::FORBIDDEN).into()
+                        );
+                    }
+                    Err(e) => return Err(e),
+                };
+
+                let response: PublicKeyResponse = serde_json::from_slice(&body)?;
+                let public_key = Curve25519PublicKey::from_base64(&response.public_key)
+                    .map_err(ContentScannerError::from)?;
+
+                Ok::<_, crate::Error>(Some(public_key))
+            })
+            .await?;
+
+        Ok(*public_key)
+    }
+}
+
+/// The URI of the given media.
+fn media_source_uri(source: &MediaSource) -> &MxcUri {
+    match source {
+        MediaSource::Plain(uri) => uri,
+        MediaSource::Encrypted(file) => &file.url,
+    }
+}
+
+/// The `{server_name}/{media_id}` part of the paths of the content scanner
+/// API.
+fn media_path(uri: &MxcUri) -> Result<String, ContentScannerError> {
+    let (server_name, media_id) = uri.parts().map_err(|_| ContentScannerError::InvalidMxcUri)?;
+    Ok(format!("{server_name}/{media_id}"))
+}
@@ -0,0 +1,80 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The declaration of custom event types.
+
+/// Declare the content of a custom event type, to use it like the event types
+/// of the specification.
+///
+/// The content is declared once, with the `#[ruma_event]` attribute of ruma's
+/// [`EventContent`](ruma::events::macros::EventContent) derive macro first,
+/// and the SDK takes care of the derives it needs. The events of that type can
+/// then be:
+///
+/// * handled with
+///   [`Client::add_event_handler()`](crate::Client::add_event_handler) and
+///   [`Room::add_event_handler()`](crate::Room::add_event_handler), with the
+///   typed events like `OriginalSyncMessageLikeEvent<C>` or
+///   `SyncStateEvent<C>`,
+/// * sent with [`Room::send_typed()`](crate::Room::send_typed) for message-like
+///   events, and with
+///   [`Room::send_state_event()`](crate::Room::send_state_event) for state
+///   events,
+/// * read from the room state with
+///   [`Room::get_state_event_static()`](crate::Room::get_state_event_static)
+///   and [`Room::get_state_events_static()`](crate::Room::get_state_events_static).
+///
+/// Only structs with named fields are supported.
+///
+/// # Examples
+///
+/// ```
+/// matrix_sdk::custom_event! {
+///     #[ruma_event(type = "org.example.ping", kind = MessageLike)]
+///     /// A ping sent by a bot, answered with the same nonce.
+///     pub struct PingEventContent {
+///         /// A random value identifying the ping.
+///         pub nonce: String,
+///     }
+/// }
+///
+/// matrix_sdk::custom_event! {
+///     #[ruma_event(type = "org.example.status", kind = State, state_key_type = String)]
+///     /// The status of a bot in a room, with its user ID as the state key.
+///     pub struct StatusEventContent {
+///         /// Whether the bot answers pings.
+///         pub online: bool,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! custom_event {
+    (
+        #[ruma_event( $( $event:tt )* )]
+        $( #[$meta:meta] )*
+        $vis:vis struct $name:ident { $( $body:tt )* }
+    ) => {
+        #[derive(
+            ::std::clone::Clone,
+            ::std::fmt::Debug,
+            $crate::__private::serde::Deserialize,
+            $crate::__private::serde::Serialize,
+            $crate::ruma::events::macros::EventContent,
+        )]
+        #[serde(crate = "::matrix_sdk::__private::serde")]
+        #[ruma_event( $( $event )* )]
+        $( #[$meta] )*
+        $vis struct $name { $( $body )* }
+    };
+}
@@ -101,7 +101,7 @@ impl<'a> IntoFuture for WaitForSteadyState<'a> {
                 let mut lock = backups.client.inner.backup_state.upload_delay.write().unwrap();
                 let old_delay = Some(lock.to_owned());
 
-                *lock = delay;
+                *lock = Some(delay);
 
                 old_delay
             } else {
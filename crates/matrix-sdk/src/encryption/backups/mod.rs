@@ -20,25 +20,31 @@
 //!
 //! [1]: https://spec.matrix.org/unstable/client-server-api/#server-side-key-backups
 
-use std::collections::{BTreeMap, BTreeSet};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
 
 use futures_core::Stream;
-use futures_util::StreamExt;
+use futures_util::{future, stream, StreamExt};
 use matrix_sdk_base::crypto::{
-    backups::MegolmV1BackupKey, store::BackupDecryptionKey, types::RoomKeyBackupInfo,
+    backups::MegolmV1BackupKey,
+    store::{BackupDecryptionKey, RoomKeyCounts},
+    types::RoomKeyBackupInfo,
     KeysBackupRequest, OlmMachine, RoomKeyImportResult,
 };
 use ruma::{
     api::client::{
         backup::{
-            add_backup_keys, create_backup_version, get_backup_keys, get_backup_keys_for_room,
-            get_backup_keys_for_session, get_latest_backup_info, RoomKeyBackup,
+            add_backup_keys, create_backup_version, get_backup_info, get_backup_keys,
+            get_backup_keys_for_room, get_backup_keys_for_session, get_latest_backup_info,
+            RoomKeyBackup,
         },
         error::ErrorKind,
     },
     events::{
         room::encrypted::{EncryptedEventScheme, SyncRoomEncryptedEvent},
-        secret::{request::SecretName, send::ToDeviceSecretSendEvent},
+        secret::request::SecretName,
     },
     serde::Raw,
     OwnedRoomId, RoomId, TransactionId,
@@ -49,10 +55,20 @@ use tracing::{error, info, instrument, trace, warn, Span};
 pub mod futures;
 pub(crate) mod types;
 
-pub use types::{BackupState, UploadState};
+pub use types::{BackupError, BackupState, BackupStatus, UploadState};
 
 use self::futures::WaitForSteadyState;
-use crate::{encryption::BackupDownloadStrategy, Client, Error, Room};
+use crate::{
+    client::tasks::SecretReceptionTask, encryption::BackupDownloadStrategy, Client, Error, Room,
+};
+
+/// An update to one of the parts of the [`BackupStatus`].
+enum StatusUpdate {
+    State(BackupState),
+    Upload(UploadState),
+    Download((usize, usize)),
+    ExistsOnServer(Option<bool>),
+}
 
 /// The backups manager for the [`Client`].
 #[derive(Debug, Clone)]
@@ -85,7 +101,7 @@ impl Backups {
     /// assert_eq!(backups.state(), BackupState::Enabled);
     /// # anyhow::Ok(()) };
     /// ```
-    pub async fn create(&self) -> Result<(), Error> {
+    pub async fn create(&self) -> Result<(), BackupError> {
         let _guard = self.client.locks().backup_modify_lock.lock().await;
 
         self.set_state(BackupState::Creating);
@@ -94,13 +110,11 @@ impl Backups {
         // state. This is a hack to get around the lack of `try` blocks in Rust.
         let future = async {
             let olm_machine = self.client.olm_machine().await;
-            let olm_machine = olm_machine.as_ref().ok_or(Error::NoOlmMachine)?;
+            let olm_machine = olm_machine.as_ref().ok_or(BackupError::NoOlmMachine)?;
 
             // Create a new backup recovery key.
-            let decryption_key = BackupDecryptionKey::new().expect(
-                "We should be able to generate enough randomness to create a new backup recovery \
-                 key",
-            );
+            let decryption_key =
+                BackupDecryptionKey::new().map_err(|_| BackupError::KeyGeneration)?;
 
             // Get the info about the new backup key, this needs to be uploaded to the
             // homeserver[1].
@@ -133,6 +147,7 @@ impl Backups {
             let request = create_backup_version::v3::Request::new(algorithm);
             let response = self.client.send(request, Default::default()).await?;
             let version = response.version;
+            self.client.inner.backup_state.exists_on_server.set(Some(true));
 
             // Reset any state we might have had before the new backup was created.
             // TODO: This should remove the old stored key and version.
@@ -163,6 +178,8 @@ impl Backups {
 
     /// Disable and delete the currently active backup.
     ///
+    /// This is the same as [`Backups::disable_and_delete()`].
+    ///
     /// # Examples
     ///
     /// ```no_run
@@ -177,8 +194,37 @@ impl Backups {
     /// assert_eq!(backups.state(), BackupState::Unknown);
     /// # anyhow::Ok(()) };
     /// ```
-    #[instrument(skip_all, fields(version))]
     pub async fn disable(&self) -> Result<(), Error> {
+        Ok(self.disable_and_delete().await?)
+    }
+
+    /// Disable the backup and delete it from the homeserver.
+    ///
+    /// This deletes the backup version from the homeserver, removes the backup
+    /// recovery key and the backup version from the crypto store, and stops
+    /// uploading room keys.
+    ///
+    /// Only the backup version this client knows about is deleted. If we
+    /// don't have a backup version locally, the backups are only disabled
+    /// locally, and the current backup of the homeserver, which might have
+    /// been created by another device, is left alone.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::{Client, encryption::backups::BackupState};
+    /// # use url::Url;
+    /// # async {
+    /// # let homeserver = Url::parse("http://example.com")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// let backups = client.encryption().backups();
+    /// backups.disable_and_delete().await?;
+    ///
+    /// assert_eq!(backups.state(), BackupState::Unknown);
+    /// # anyhow::Ok(()) };
+    /// ```
+    #[instrument(skip_all, fields(version))]
+    pub async fn disable_and_delete(&self) -> Result<(), BackupError> {
         let _guard = self.client.locks().backup_modify_lock.lock().await;
 
         self.set_state(BackupState::Disabling);
@@ -186,24 +232,32 @@ impl Backups {
         // Create a future so we can catch errors and go back to the `Unknown` state.
         let future = async {
             let olm_machine = self.client.olm_machine().await;
-            let olm_machine = olm_machine.as_ref().ok_or(Error::NoOlmMachine)?;
+            let olm_machine = olm_machine.as_ref().ok_or(BackupError::NoOlmMachine)?;
 
             let backup_keys = olm_machine.backup_machine().get_backup_keys().await?;
 
-            if let Some(version) = backup_keys.backup_version {
+            let deleted = if let Some(version) = backup_keys.backup_version {
                 Span::current().record("version", &version);
                 info!("Deleting and disabling backup");
 
                 self.delete_backup_from_server(version).await?;
                 info!("Backup successfully deleted");
 
-                olm_machine.backup_machine().disable_backup().await?;
-                self.set_state(BackupState::Unknown);
-
-                info!("Backup successfully disabled and deleted");
+                true
             } else {
-                info!("Backup is not enabled, can't disable it");
+                info!("We don't know about a backup version, only disabling backups locally");
+
+                false
+            };
+
+            olm_machine.backup_machine().delete_backup().await?;
+            self.client.inner.backup_state.upload_progress.set(UploadState::Idle);
+            if deleted {
+                self.client.inner.backup_state.exists_on_server.set(Some(false));
             }
+            self.set_state(BackupState::Unknown);
+
+            info!("Backup successfully disabled and deleted");
 
             Ok(())
         };
@@ -309,6 +363,108 @@ impl Backups {
         self.client.inner.backup_state.global_state.get()
     }
 
+    /// Get a stream of updates to the [`BackupStatus`], a summary of the state
+    /// of the backup and of the progress of the download and upload of room
+    /// keys, meant to be displayed to the user.
+    ///
+    /// This method will send out the current status as the first update.
+    pub fn status_stream(&self) -> impl Stream<Item = BackupStatus> {
+        let backup_state = &self.client.inner.backup_state;
+
+        let state = backup_state
+            .global_state
+            .subscribe()
+            .filter_map(|s| future::ready(s.ok()))
+            .map(StatusUpdate::State);
+        let upload = backup_state
+            .upload_progress
+            .subscribe()
+            .filter_map(|s| future::ready(s.ok()))
+            .map(StatusUpdate::Upload);
+        let download = backup_state
+            .download_progress
+            .subscribe()
+            .filter_map(|s| future::ready(s.ok()))
+            .map(StatusUpdate::Download);
+        let exists_on_server = backup_state
+            .exists_on_server
+            .subscribe()
+            .filter_map(|s| future::ready(s.ok()))
+            .map(StatusUpdate::ExistsOnServer);
+
+        let mut current_state = self.state();
+        let mut current_upload = self.upload_progress();
+        let mut current_download = backup_state.download_progress.get();
+        let mut current_exists_on_server = backup_state.exists_on_server.get();
+        let mut last_status = None;
+
+        let updates = stream::select(
+            stream::select(state, upload),
+            stream::select(download, exists_on_server),
+        );
+
+        updates.filter_map(move |update| {
+            match update {
+                StatusUpdate::State(state) => current_state = state,
+                StatusUpdate::Upload(upload) => current_upload = upload,
+                StatusUpdate::Download(download) => current_download = download,
+                StatusUpdate::ExistsOnServer(exists) => current_exists_on_server = exists,
+            }
+
+            // Only send out the status when it changes, since every stream sends
+            // out its current value first.
+            let status = BackupStatus::new(
+                current_state,
+                &current_upload,
+                current_download,
+                current_exists_on_server,
+            );
+            let changed = last_status.replace(status) != Some(status);
+
+            future::ready(changed.then_some(status))
+        })
+    }
+
+    /// Get the current [`BackupStatus`] for this [`Client`].
+    pub fn status(&self) -> BackupStatus {
+        let backup_state = &self.client.inner.backup_state;
+
+        BackupStatus::new(
+            self.state(),
+            &self.upload_progress(),
+            backup_state.download_progress.get(),
+            backup_state.exists_on_server.get(),
+        )
+    }
+
+    /// Get a stream of updates to the [`UploadState`], i.e. the progress of
+    /// the upload of the room keys to the backup.
+    ///
+    /// Unlike [`WaitForSteadyState::subscribe_to_progress()`], this doesn't
+    /// wait for the upload to settle down, and can be used to show the
+    /// progress of the backup at any time.
+    ///
+    /// This method will send out the current state as the first update.
+    pub fn upload_progress_stream(
+        &self,
+    ) -> impl Stream<Item = Result<UploadState, BroadcastStreamRecvError>> {
+        self.client.inner.backup_state.upload_progress.subscribe()
+    }
+
+    /// Get the current [`UploadState`] for this [`Client`].
+    pub fn upload_progress(&self) -> UploadState {
+        self.client.inner.backup_state.upload_progress.get()
+    }
+
+    /// Get the number of room keys that we know about, and how many of them
+    /// are backed up.
+    pub async fn room_key_counts(&self) -> Result<RoomKeyCounts, BackupError> {
+        let olm_machine = self.client.olm_machine().await;
+        let olm_machine = olm_machine.as_ref().ok_or(BackupError::NoOlmMachine)?;
+
+        Ok(olm_machine.backup_machine().room_key_counts().await?)
+    }
+
     /// Are backups enabled for the current [`Client`]?
     ///
     /// This method will check if we locally have an active backup key and
@@ -327,7 +483,7 @@ impl Backups {
     ///
     /// This method will request info about the current backup from the
     /// homeserver and if a backup exits return `true`, otherwise `false`.
-    pub async fn exists_on_server(&self) -> Result<bool, Error> {
+    pub async fn exists_on_server(&self) -> Result<bool, BackupError> {
         Ok(self.get_current_version().await?.is_some())
     }
 
@@ -361,14 +517,20 @@ impl Backups {
 
     /// Download all room keys for a certain room from the server-side key
     /// backup.
-    pub async fn download_room_keys_for_room(&self, room_id: &RoomId) -> Result<(), Error> {
+    ///
+    /// Fails with [`BackupError::RecoveryKeyMismatch`] or
+    /// [`BackupError::UntrustedSignature`] if our backup recovery key isn't
+    /// the one of the backup on the homeserver.
+    pub async fn download_room_keys_for_room(&self, room_id: &RoomId) -> Result<(), BackupError> {
         let olm_machine = self.client.olm_machine().await;
-        let olm_machine = olm_machine.as_ref().ok_or(Error::NoOlmMachine)?;
+        let olm_machine = olm_machine.as_ref().ok_or(BackupError::NoOlmMachine)?;
 
         let backup_keys = olm_machine.store().load_backup_keys().await?;
 
         if let Some(decryption_key) = backup_keys.decryption_key {
             if let Some(version) = backup_keys.backup_version {
+                self.check_decryption_key(olm_machine, &decryption_key, &version).await?;
+
                 let request =
                     get_backup_keys_for_room::v3::Request::new(version, room_id.to_owned());
                 let response = self.client.send(request, Default::default()).await?;
@@ -387,14 +549,24 @@ impl Backups {
     }
 
     /// Download a single room key from the server-side key backup.
-    pub async fn download_room_key(&self, room_id: &RoomId, session_id: &str) -> Result<(), Error> {
+    ///
+    /// Fails with [`BackupError::RecoveryKeyMismatch`] or
+    /// [`BackupError::UntrustedSignature`] if our backup recovery key isn't
+    /// the one of the backup on the homeserver.
+    pub async fn download_room_key(
+        &self,
+        room_id: &RoomId,
+        session_id: &str,
+    ) -> Result<(), BackupError> {
         let olm_machine = self.client.olm_machine().await;
-        let olm_machine = olm_machine.as_ref().ok_or(Error::NoOlmMachine)?;
+        let olm_machine = olm_machine.as_ref().ok_or(BackupError::NoOlmMachine)?;
 
         let backup_keys = olm_machine.store().load_backup_keys().await?;
 
         if let Some(decryption_key) = backup_keys.decryption_key {
             if let Some(version) = backup_keys.backup_version {
+                self.check_decryption_key(olm_machine, &decryption_key, &version).await?;
+
                 let request = get_backup_keys_for_session::v3::Request::new(
                     version,
                     room_id.to_owned(),
@@ -430,7 +602,7 @@ impl Backups {
         olm_machine: &OlmMachine,
         backup_key: MegolmV1BackupKey,
         version: String,
-    ) -> Result<(), Error> {
+    ) -> Result<(), BackupError> {
         backup_key.set_version(version);
         olm_machine.backup_machine().enable_backup_v1(backup_key).await?;
 
@@ -439,29 +611,93 @@ impl Backups {
         Ok(())
     }
 
+    /// Check that our backup recovery key is the one of the given backup
+    /// version, by comparing its public key with the `auth_data` of the
+    /// backup on the homeserver.
+    ///
+    /// The info of the backup version is only fetched from the homeserver
+    /// once per version.
+    async fn check_decryption_key(
+        &self,
+        olm_machine: &OlmMachine,
+        decryption_key: &BackupDecryptionKey,
+        version: &str,
+    ) -> Result<(), BackupError> {
+        let cached_backup_info = self
+            .client
+            .inner
+            .backup_state
+            .backup_info
+            .read()
+            .unwrap()
+            .as_ref()
+            .filter(|(cached_version, _)| cached_version == version)
+            .map(|(_, backup_info)| backup_info.clone());
+
+        let backup_info = match cached_backup_info {
+            Some(backup_info) => backup_info,
+            None => {
+                let request = get_backup_info::v3::Request::new(version.to_owned());
+                let response = self.client.send(request, None).await?;
+                let backup_info: RoomKeyBackupInfo = response.algorithm.deserialize_as()?;
+
+                *self.client.inner.backup_state.backup_info.write().unwrap() =
+                    Some((version.to_owned(), backup_info.clone()));
+
+                backup_info
+            }
+        };
+
+        if decryption_key.backup_key_matches(&backup_info) {
+            Ok(())
+        } else if olm_machine.backup_machine().verify_backup(backup_info, false).await?.trusted() {
+            Err(BackupError::RecoveryKeyMismatch)
+        } else {
+            Err(BackupError::UntrustedSignature)
+        }
+    }
+
     /// Decrypt and forward a response containing backed up room keys to the
     /// [`OlmMachine`].
+    ///
+    /// The room keys that can't be deserialized or decrypted are skipped.
     async fn handle_downloaded_room_keys(
         &self,
         backed_up_keys: get_backup_keys::v3::Response,
         backup_decryption_key: BackupDecryptionKey,
         olm_machine: &OlmMachine,
-    ) -> Result<(), Error> {
+    ) -> Result<(), BackupError> {
         let mut decrypted_room_keys: BTreeMap<_, BTreeMap<_, _>> = BTreeMap::new();
+        let mut skipped_room_keys = 0;
 
         for (room_id, room_keys) in backed_up_keys.rooms {
             for (session_id, room_key) in room_keys.sessions {
-                // TODO: Log that we're skipping some keys here.
-                let Ok(room_key) = room_key.deserialize() else {
-                    warn!("Couldn't deserialize a room key we downloaded from backups, session ID: {session_id}");
-                    continue;
+                let room_key = match room_key.deserialize() {
+                    Ok(room_key) => room_key,
+                    Err(e) => {
+                        warn!(
+                            %room_id,
+                            ?session_id,
+                            "Couldn't deserialize a room key we downloaded from backups: {e}"
+                        );
+                        skipped_room_keys += 1;
+                        continue;
+                    }
                 };
 
-                let Ok(room_key) =
-                    backup_decryption_key.decrypt_session_data(room_key.session_data)
-                else {
-                    continue;
-                };
+                let room_key =
+                    match backup_decryption_key.decrypt_session_data(room_key.session_data) {
+                        Ok(room_key) => room_key,
+                        Err(e) => {
+                            warn!(
+                                %room_id,
+                                ?session_id,
+                                "Couldn't decrypt a room key we downloaded from backups: {e}"
+                            );
+                            skipped_room_keys += 1;
+                            continue;
+                        }
+                    };
 
                 decrypted_room_keys
                     .entry(room_id.to_owned())
@@ -470,9 +706,17 @@ impl Backups {
             }
         }
 
+        if skipped_room_keys > 0 {
+            warn!("Skipped {skipped_room_keys} room keys we downloaded from backups");
+        }
+
+        let download_progress = &self.client.inner.backup_state.download_progress;
         let result = olm_machine
             .backup_machine()
-            .import_backed_up_room_keys(decrypted_room_keys, |_, _| {})
+            .import_backed_up_room_keys(decrypted_room_keys, |index, total| {
+                // The listener gets the index of the room key that was just processed.
+                download_progress.set((index + 1, total))
+            })
             .await?;
 
         // Since we can't use the usual room keys stream from the `OlmMachine`
@@ -487,12 +731,12 @@ impl Backups {
         &self,
         decryption_key: BackupDecryptionKey,
         version: String,
-    ) -> Result<(), Error> {
+    ) -> Result<(), BackupError> {
         let request = get_backup_keys::v3::Request::new(version);
         let response = self.client.send(request, Default::default()).await?;
 
         let olm_machine = self.client.olm_machine().await;
-        let olm_machine = olm_machine.as_ref().ok_or(Error::NoOlmMachine)?;
+        let olm_machine = olm_machine.as_ref().ok_or(BackupError::NoOlmMachine)?;
 
         self.handle_downloaded_room_keys(response, decryption_key, olm_machine).await?;
 
@@ -508,14 +752,19 @@ impl Backups {
     /// Get info about the currently active backup from the server.
     async fn get_current_version(
         &self,
-    ) -> Result<Option<get_latest_backup_info::v3::Response>, Error> {
+    ) -> Result<Option<get_latest_backup_info::v3::Response>, BackupError> {
         let request = get_latest_backup_info::v3::Request::new();
+        let exists_on_server = &self.client.inner.backup_state.exists_on_server;
 
         match self.client.send(request, None).await {
-            Ok(r) => Ok(Some(r)),
+            Ok(r) => {
+                exists_on_server.set(Some(true));
+                Ok(Some(r))
+            }
             Err(e) => {
                 if let Some(kind) = e.client_api_error_kind() {
                     if kind == &ErrorKind::NotFound {
+                        exists_on_server.set(Some(false));
                         Ok(None)
                     } else {
                         Err(e.into())
@@ -527,7 +776,7 @@ impl Backups {
         }
     }
 
-    async fn delete_backup_from_server(&self, version: String) -> Result<(), Error> {
+    async fn delete_backup_from_server(&self, version: String) -> Result<(), BackupError> {
         let request = ruma::api::client::backup::delete_backup_version::v3::Request::new(version);
 
         match self.client.send(request, Default::default()).await {
@@ -569,13 +818,6 @@ impl Backups {
                     .upload_progress
                     .set(UploadState::Uploading(new_counts));
 
-                #[cfg(not(target_arch = "wasm32"))]
-                {
-                    let delay =
-                        self.client.inner.backup_state.upload_delay.read().unwrap().to_owned();
-                    tokio::time::sleep(delay).await;
-                }
-
                 Ok(())
             }
             Err(error) => {
@@ -589,13 +831,11 @@ impl Backups {
                         ErrorKind::WrongRoomKeysVersion { current_version } => {
                             warn!(
                                 new_version = current_version,
-                                "A new backup version was found on the server, disabling backups."
+                                "A new backup version was found on the server, trying to switch \
+                                 to it."
                             );
 
-                            // TODO: If we're verified and there are other devices besides us,
-                            // request the new backup key over `m.secret.send`.
-
-                            self.handle_deleted_backup_version(olm_machine).await?;
+                            self.handle_changed_backup_version(olm_machine).await?;
                         }
 
                         _ => (),
@@ -610,17 +850,54 @@ impl Backups {
     /// Poll the [`OlmMachine`] for room keys which need to be backed up and
     /// send out the request to the homeserver.
     ///
+    /// Only one upload runs at a time for a client. If the cross-process
+    /// crypto store lock is enabled, each batch is also uploaded while holding
+    /// it, so other processes sharing the crypto store don't upload the same
+    /// room keys. The lock is released while waiting between batches.
+    ///
     /// This should only be called by the [`BackupUploadingTask`].
     ///
     /// [`BackupUploadingTask`]: crate::client::tasks::BackupUploadingTask
     pub(crate) async fn backup_room_keys(&self) -> Result<(), Error> {
         let _guard = self.client.locks().backup_upload_lock.lock().await;
 
-        let olm_machine = self.client.olm_machine().await;
-        let olm_machine = olm_machine.as_ref().ok_or(Error::NoOlmMachine)?;
+        let strategy = self.client.inner.encryption_settings.backup_upload_strategy;
+        let batch_size = strategy.batch_size.max(1);
+
+        loop {
+            let is_full_batch = {
+                // Take the lock before looking at the room keys, the `OlmMachine` is reloaded
+                // if another process marked some of them as backed up in the meantime.
+                let _store_guard = self.client.encryption().spin_lock_store(Some(60000)).await?;
+
+                let olm_machine = self.client.olm_machine().await;
+                let olm_machine = olm_machine.as_ref().ok_or(Error::NoOlmMachine)?;
+
+                let Some((request_id, request)) =
+                    olm_machine.backup_machine().backup_with_batch_size(batch_size).await?
+                else {
+                    break;
+                };
 
-        while let Some((request_id, request)) = olm_machine.backup_machine().backup().await? {
-            self.send_backup_request(olm_machine, &request_id, request).await?;
+                let key_count: usize = request.rooms.values().map(|room| room.sessions.len()).sum();
+                self.send_backup_request(olm_machine, &request_id, request).await?;
+
+                key_count >= batch_size
+            };
+
+            // A batch that isn't full means that all the room keys were uploaded, don't
+            // wait for nothing.
+            if is_full_batch {
+                let delay_override = *self.client.inner.backup_state.upload_delay.read().unwrap();
+                let delay = delay_override.unwrap_or_else(|| strategy.next_delay());
+
+                if !delay.is_zero() {
+                    // Don't hold the lock on the `OlmMachine` nor the crypto store while
+                    // waiting.
+                    trace!(?delay, "Waiting before uploading the next batch of room keys");
+                    self.client.base_client().clock().sleep(delay).await;
+                }
+            }
         }
 
         self.client.inner.backup_state.upload_progress.set(UploadState::Done);
@@ -628,12 +905,15 @@ impl Backups {
         Ok(())
     }
 
-    /// Set up a `m.secret.send` listener and re-enable backups if we have a
-    /// backup recovery key stored.
+    /// Set up a listener of the received secrets and re-enable backups if we
+    /// have a backup recovery key stored.
     pub(crate) async fn setup_and_resume(&self) -> Result<(), Error> {
         info!("Setting up secret listeners and trying to resume backups");
 
-        self.client.add_event_handler(Self::secret_send_event_handler);
+        // Listen to the secrets before checking the secret inbox, to not miss a
+        // secret received in between.
+        self.client.inner.tasks.lock().unwrap().receive_secrets =
+            Some(SecretReceptionTask::new(Arc::downgrade(&self.client.inner)));
         if self.client.inner.encryption_settings.backup_download_strategy
             == BackupDownloadStrategy::AfterDecryptionFailure
         {
@@ -663,7 +943,7 @@ impl Backups {
     pub(crate) async fn maybe_enable_backups(
         &self,
         maybe_recovery_key: &str,
-    ) -> Result<bool, Error> {
+    ) -> Result<bool, BackupError> {
         let _guard = self.client.locks().backup_modify_lock.lock().await;
 
         // Create a future here which allows us to catch any failure that might happen
@@ -672,15 +952,11 @@ impl Backups {
             self.set_state(BackupState::Enabling);
 
             let olm_machine = self.client.olm_machine().await;
-            let olm_machine = olm_machine.as_ref().ok_or(Error::NoOlmMachine)?;
+            let olm_machine = olm_machine.as_ref().ok_or(BackupError::NoOlmMachine)?;
             let backup_machine = olm_machine.backup_machine();
 
-            let decryption_key =
-                BackupDecryptionKey::from_base64(maybe_recovery_key).map_err(|e| {
-                    <serde_json::Error as serde::de::Error>::custom(format!(
-                        "Couldn't deserialize the backup recovery key: {e:?}"
-                    ))
-                })?;
+            let decryption_key = BackupDecryptionKey::from_base64(maybe_recovery_key)
+                .map_err(BackupError::InvalidRecoveryKey)?;
 
             // Let's try to see if there's a backup on the homeserver.
             let current_version = self.get_current_version().await?;
@@ -735,6 +1011,7 @@ impl Backups {
                 if self.client.inner.encryption_settings.backup_download_strategy
                     == BackupDownloadStrategy::OneShot
                 {
+                    self.client.inner.backup_state.download_progress.set((0, 0));
                     self.set_state(BackupState::Downloading);
 
                     if let Err(e) =
@@ -788,7 +1065,7 @@ impl Backups {
     async fn resume_backup_from_stored_backup_key(
         &self,
         olm_machine: &OlmMachine,
-    ) -> Result<bool, Error> {
+    ) -> Result<bool, BackupError> {
         let backup_keys = olm_machine.store().load_backup_keys().await?;
 
         if let Some(decryption_key) = backup_keys.decryption_key {
@@ -838,23 +1115,17 @@ impl Backups {
         Ok(())
     }
 
-    /// Listen for `m.secret.send` to-device messages and check the secret inbox
-    /// if we do receive one.
-    pub(crate) async fn secret_send_event_handler(_: ToDeviceSecretSendEvent, client: Client) {
-        let olm_machine = client.olm_machine().await;
+    /// Check the secret inbox for a backup recovery key received from one of
+    /// our other devices, and enable backups with it.
+    pub(crate) async fn check_secret_inbox(&self) {
+        let olm_machine = self.client.olm_machine().await;
 
-        // TODO: Because of our crude multi-process support, which reloads the whole
-        // [`OlmMachine`] the `secrets_stream` might stop giving you updates. Once
-        // that's fixed, stop listening to individual secret send events and
-        // listen to the secrets stream.
         if let Some(olm_machine) = olm_machine.as_ref() {
-            if let Err(e) =
-                client.encryption().backups().maybe_resume_from_secret_inbox(olm_machine).await
-            {
-                error!("Could not handle `m.secret.send` event: {e:?}");
+            if let Err(e) = self.maybe_resume_from_secret_inbox(olm_machine).await {
+                error!("Could not handle the received backup recovery key: {e:?}");
             }
         } else {
-            error!("Tried to handle a `m.secret.send` event but no OlmMachine was initialized");
+            error!("Received a backup recovery key but no OlmMachine was initialized");
         }
     }
 
@@ -896,26 +1167,88 @@ impl Backups {
     async fn handle_deleted_backup_version(&self, olm_machine: &OlmMachine) -> Result<(), Error> {
         olm_machine.backup_machine().disable_backup().await?;
         self.client.encryption().recovery().update_state_after_backup_disabling().await;
+        self.client.inner.backup_state.exists_on_server.set(Some(false));
         self.set_state(BackupState::Unknown);
 
         Ok(())
     }
+
+    /// Switch to the new backup version if we notice that the backup version
+    /// has changed on the homeserver, for example because another device
+    /// created a new backup.
+    ///
+    /// The backup recovery key we have stored, or one we received from another
+    /// device, might be the one used for the new backup version. If that's not
+    /// the case, we switch to the [`BackupState::VersionChanged`] state until
+    /// we receive the new backup recovery key.
+    async fn handle_changed_backup_version(&self, olm_machine: &OlmMachine) -> Result<(), Error> {
+        // Stop uploading room keys to the old backup version, but keep the backup
+        // recovery key around, it might be the one of the new version.
+        olm_machine.backup_machine().disable_backup().await?;
+
+        let stored_keys = olm_machine.backup_machine().get_backup_keys().await?;
+
+        let mut enabled = false;
+
+        if let Some(decryption_key) = stored_keys.decryption_key {
+            enabled =
+                self.maybe_enable_backups(&decryption_key.to_base64()).await.unwrap_or_else(|e| {
+                    warn!("Couldn't enable the new backup version with our stored key: {e:?}");
+                    false
+                });
+        }
+
+        if !enabled {
+            if let Err(e) = self.maybe_resume_from_secret_inbox(olm_machine).await {
+                warn!("Couldn't enable the new backup version with the received keys: {e:?}");
+            }
+
+            enabled = self.are_enabled().await;
+        }
+
+        if enabled {
+            info!("Switched to the new backup version");
+        } else {
+            info!("We don't have the backup recovery key of the new backup version");
+
+            self.client.encryption().recovery().update_state_after_backup_disabling().await;
+            self.set_state(BackupState::VersionChanged);
+        }
+
+        Ok(())
+    }
 }
 
 #[cfg(all(test, not(target_arch = "wasm32")))]
 mod test {
     use std::time::Duration;
 
-    use matrix_sdk_base::crypto::olm::ExportedRoomKey;
+    use assert_matches2::assert_matches;
+    use matrix_sdk_base::{
+        crypto::{
+            olm::ExportedRoomKey,
+            vodozemac::megolm::{GroupSession, InboundGroupSession, SessionConfig},
+        },
+        SessionMeta,
+    };
+    use matrix_sdk_common::LEASE_DURATION_MS;
     use matrix_sdk_test::async_test;
-    use serde_json::json;
+    use ruma::{device_id, user_id};
+    use serde_json::{json, Value as JsonValue};
     use wiremock::{
-        matchers::{header, method, path},
+        http::Method,
+        matchers::{header, method, path, path_regex},
         Mock, MockServer, ResponseTemplate,
     };
 
     use super::*;
-    use crate::test_utils::logged_in_client;
+    use crate::{
+        clock::TestClock,
+        config::RequestConfig,
+        encryption::{BackupUploadStrategy, EncryptionSettings},
+        matrix_auth::{MatrixSession, MatrixSessionTokens},
+        test_utils::{logged_in_client, test_client_builder},
+    };
 
     fn room_key() -> ExportedRoomKey {
         let json = json!({
@@ -1019,6 +1352,358 @@ mod test {
         server.verify().await;
     }
 
+    #[async_test]
+    async fn download_with_wrong_recovery_key() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+        let room_key = room_key();
+        let room_id = room_key.room_id.clone();
+        let session_id = room_key.session_id.clone();
+
+        {
+            let machine = client.olm_machine().await;
+            machine
+                .as_ref()
+                .unwrap()
+                .store()
+                .import_exported_room_keys(vec![room_key], |_, _| {})
+                .await
+                .expect("We should be able to import a room key");
+        }
+
+        Mock::given(method("POST"))
+            .and(path("_matrix/client/unstable/room_keys/version"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "version": "1" })))
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("_matrix/client/unstable/room_keys/keys"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(json!({ "etag": "1", "count": 1 })),
+            )
+            .mount(&server)
+            .await;
+
+        let backups = client.encryption().backups();
+        backups.create().await.expect("We should be able to create a new backup");
+        backups.backup_room_keys().await.expect("We should be able to upload the room key");
+
+        // Serve the room key that we uploaded.
+        let requests = server.received_requests().await.unwrap();
+        let upload = requests
+            .iter()
+            .find(|request| request.method == Method::Put)
+            .expect("The room key should have been uploaded");
+        let body: JsonValue = upload.body_json().unwrap();
+        let key_data = body["rooms"][room_id.as_str()]["sessions"][&session_id].clone();
+
+        Mock::given(method("GET"))
+            .and(path_regex("_matrix/client/unstable/room_keys/keys/.+/.+"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(key_data))
+            .mount(&server)
+            .await;
+
+        // Serve the backup info that we created, it's signed by our own device.
+        let creation = requests
+            .iter()
+            .find(|request| request.method == Method::Post)
+            .expect("The backup should have been created");
+        let mut backup_info: JsonValue = creation.body_json().unwrap();
+        backup_info["version"] = "1".into();
+        backup_info["count"] = 1.into();
+        backup_info["etag"] = "1".into();
+
+        Mock::given(method("GET"))
+            .and(path("_matrix/client/unstable/room_keys/version/1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(backup_info))
+            .mount(&server)
+            .await;
+
+        // Our backup recovery key was replaced by another one.
+        {
+            let machine = client.olm_machine().await;
+            let other_key = BackupDecryptionKey::new().unwrap();
+            machine
+                .as_ref()
+                .unwrap()
+                .backup_machine()
+                .save_decryption_key(Some(other_key), Some("1".to_owned()))
+                .await
+                .unwrap();
+        }
+
+        let result = backups.download_room_key(&room_id, &session_id).await;
+        assert_matches!(result, Err(BackupError::RecoveryKeyMismatch));
+    }
+
+    #[async_test]
+    async fn download_from_untrusted_backup() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+
+        {
+            let machine = client.olm_machine().await;
+            machine
+                .as_ref()
+                .unwrap()
+                .backup_machine()
+                .save_decryption_key(
+                    Some(BackupDecryptionKey::new().unwrap()),
+                    Some("1".to_owned()),
+                )
+                .await
+                .unwrap();
+        }
+
+        // The backup on the homeserver uses another key, and isn't signed by us.
+        let other_key = BackupDecryptionKey::new().unwrap();
+        let mut backup_info = serde_json::to_value(other_key.to_backup_info()).unwrap();
+        backup_info["version"] = "1".into();
+        backup_info["count"] = 1.into();
+        backup_info["etag"] = "1".into();
+
+        Mock::given(method("GET"))
+            .and(path("_matrix/client/unstable/room_keys/version/1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(backup_info))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex("_matrix/client/unstable/room_keys/keys/.+"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "sessions": {} })))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let room_id = room_key().room_id;
+        let result = client.encryption().backups().download_room_keys_for_room(&room_id).await;
+        assert_matches!(result, Err(BackupError::UntrustedSignature));
+
+        server.verify().await;
+    }
+
+    #[async_test]
+    async fn upload_waits_for_the_cross_process_lock() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+
+        {
+            let machine = client.olm_machine().await;
+            machine
+                .as_ref()
+                .unwrap()
+                .store()
+                .import_exported_room_keys(vec![room_key()], |_, _| {})
+                .await
+                .expect("We should be able to import a room key");
+        }
+
+        Mock::given(method("POST"))
+            .and(path("_matrix/client/unstable/room_keys/version"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "version": "1" })))
+            .mount(&server)
+            .await;
+        // The upload is slow, to check that the lock is held while it runs.
+        Mock::given(method("PUT"))
+            .and(path("_matrix/client/unstable/room_keys/keys"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({ "etag": "1", "count": 1 }))
+                    .set_delay(Duration::from_millis(500)),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let backups = client.encryption().backups();
+        backups.create().await.expect("We should be able to create a new backup");
+
+        client.encryption().enable_cross_process_store_lock("client".to_owned()).await.unwrap();
+
+        // Another process holds the lock on the crypto store.
+        let other_lock = client
+            .olm_machine()
+            .await
+            .as_ref()
+            .unwrap()
+            .store()
+            .create_store_lock("cross_process_lock".to_owned(), "other".to_owned());
+        let other_guard = other_lock.try_lock_once().await.unwrap();
+        assert!(other_guard.is_some());
+
+        // The upload tries to take the lock right after it started, and only yields
+        // once it waits for the lock to be released.
+        let (started_sender, started) = tokio::sync::oneshot::channel();
+        let first_upload = matrix_sdk_common::executor::spawn({
+            let backups = backups.clone();
+            async move {
+                started_sender.send(()).unwrap();
+                backups.backup_room_keys().await
+            }
+        });
+
+        started.await.unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        assert!(
+            !requests.iter().any(|request| request.method == Method::Put),
+            "The room keys shouldn't be uploaded while another process holds the lock"
+        );
+
+        // Once the lock is released, the room keys are uploaded only once, even if
+        // another upload was triggered in the meantime.
+        let second_upload = matrix_sdk_common::executor::spawn({
+            let backups = backups.clone();
+            async move { backups.backup_room_keys().await }
+        });
+        drop(other_guard);
+
+        // While the room keys are being uploaded, the other process can't take the
+        // lock.
+        while !server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .any(|request| request.method == Method::Put)
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(
+            other_lock.try_lock_once().await.unwrap().is_none(),
+            "The lock should be held while the room keys are uploaded"
+        );
+
+        first_upload.await.unwrap().expect("We should be able to upload the room key");
+        second_upload.await.unwrap().expect("The second upload should be a no-op");
+
+        // The lock is released once the upload is done, at the latest when its
+        // lease expires.
+        tokio::time::sleep(Duration::from_millis(LEASE_DURATION_MS.into())).await;
+        assert!(other_lock.try_lock_once().await.unwrap().is_some());
+
+        server.verify().await;
+    }
+
+    /// Create room keys of new sessions, that were never backed up.
+    fn new_room_keys(count: usize) -> Vec<ExportedRoomKey> {
+        (0..count)
+            .map(|_| {
+                let outbound = GroupSession::new(SessionConfig::version_1());
+                let inbound =
+                    InboundGroupSession::new(&outbound.session_key(), SessionConfig::version_1());
+                let session_key = inbound.export_at(0).expect("We should be able to export");
+
+                serde_json::from_value(json!({
+                    "algorithm": "m.megolm.v1.aes-sha2",
+                    "room_id": "!DovneieKSTkdHKpIXy:morpheus.localhost",
+                    "sender_key": "DeHIg4gwhClxzFYcmNntPNF9YtsdZbmMy8+3kzCMXHA",
+                    "session_id": outbound.session_id(),
+                    "session_key": session_key.to_base64(),
+                    "sender_claimed_keys": {},
+                    "forwarding_curve25519_key_chain": [],
+                }))
+                .expect("We should be able to deserialize our exported room key")
+            })
+            .collect()
+    }
+
+    #[async_test]
+    async fn upload_in_batches_with_a_delay() {
+        let server = MockServer::start().await;
+        let clock = TestClock::new();
+
+        let mut settings = EncryptionSettings::default();
+        settings.backup_upload_strategy = BackupUploadStrategy::new()
+            .batch_size(2)
+            .delay_between_batches(Duration::from_secs(60));
+
+        let client = test_client_builder(Some(server.uri()))
+            .request_config(RequestConfig::new().disable_retry())
+            .clock(Arc::new(clock.clone()))
+            .with_encryption_settings(settings)
+            .build()
+            .await
+            .unwrap();
+        client
+            .matrix_auth()
+            .restore_session(MatrixSession {
+                meta: SessionMeta {
+                    user_id: user_id!("@example:localhost").to_owned(),
+                    device_id: device_id!("DEVICEID").to_owned(),
+                },
+                tokens: MatrixSessionTokens {
+                    access_token: "1234".to_owned(),
+                    refresh_token: None,
+                },
+            })
+            .await
+            .unwrap();
+
+        {
+            let machine = client.olm_machine().await;
+            machine
+                .as_ref()
+                .unwrap()
+                .store()
+                .import_exported_room_keys(new_room_keys(3), |_, _| {})
+                .await
+                .expect("We should be able to import the room keys");
+        }
+
+        Mock::given(method("POST"))
+            .and(path("_matrix/client/unstable/room_keys/version"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "version": "1" })))
+            .mount(&server)
+            .await;
+
+        // Forward the number of room keys of every upload to the test.
+        let (uploads_sender, mut uploads) = tokio::sync::mpsc::unbounded_channel();
+        Mock::given(method("PUT"))
+            .and(path("_matrix/client/unstable/room_keys/keys"))
+            .respond_with(move |request: &wiremock::Request| {
+                let body: JsonValue = request.body_json().unwrap();
+                let key_count: usize = body["rooms"]
+                    .as_object()
+                    .unwrap()
+                    .values()
+                    .map(|room| room["sessions"].as_object().unwrap().len())
+                    .sum();
+                uploads_sender.send(key_count).unwrap();
+
+                ResponseTemplate::new(200).set_body_json(json!({ "etag": "1", "count": 1 }))
+            })
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let backups = client.encryption().backups();
+        backups.create().await.expect("We should be able to create a new backup");
+
+        let upload = matrix_sdk_common::executor::spawn({
+            let backups = backups.clone();
+            async move { backups.backup_room_keys().await }
+        });
+
+        // The first batch is full, so the upload waits before sending the next one.
+        assert_eq!(uploads.recv().await, Some(2));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(uploads.try_recv().is_err(), "The next batch should wait for the delay");
+
+        // The last batch isn't full, so the upload is done once it's sent.
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(uploads.recv().await, Some(1));
+
+        upload.await.unwrap().expect("We should be able to upload the room keys");
+        assert_matches!(backups.upload_progress(), UploadState::Done);
+
+        let counts = backups.room_key_counts().await.unwrap();
+        assert_eq!(counts.total, 3);
+        assert_eq!(counts.backed_up, 3);
+
+        server.verify().await;
+    }
+
     #[async_test]
     async fn exists_on_server() {
         let server = MockServer::start().await;
@@ -1050,6 +1735,7 @@ mod test {
                 .expect("We should be able to check if backups exist on the server");
 
             assert!(exists, "We should deduce that a backup exist on the server");
+            assert_eq!(client.encryption().backups().status(), BackupStatus::Unknown);
         }
 
         {
@@ -1072,6 +1758,11 @@ mod test {
                 .expect("We should be able to check if backups exist on the server");
 
             assert!(!exists, "We should deduce that no backup exist on the server");
+            assert_eq!(
+                client.encryption().backups().status(),
+                BackupStatus::Disabled,
+                "Without a backup on the server, the backup should be reported as disabled"
+            );
         }
 
         {
@@ -17,15 +17,62 @@ use std::{
     time::Duration,
 };
 
-use matrix_sdk_base::crypto::{store::RoomKeyCounts, RoomKeyImportResult};
+use matrix_sdk_base::crypto::{
+    backups::DecodeError, store::RoomKeyCounts, types::RoomKeyBackupInfo, CryptoStoreError,
+    RoomKeyImportResult,
+};
 use tokio::sync::broadcast;
 
-use crate::utils::ChannelObservable;
 #[cfg(doc)]
 use crate::{
     encryption::{backups::Backups, secret_storage::SecretStore},
     Client,
 };
+use crate::{utils::ChannelObservable, HttpError};
+
+/// Errors of the server-side key backups, returned by the methods of
+/// [`Backups`].
+#[derive(Debug, thiserror::Error)]
+pub enum BackupError {
+    /// The homeserver rejected a request, or couldn't be reached.
+    #[error("the homeserver rejected the request: {0}")]
+    ServerRejected(#[from] HttpError),
+
+    /// The crypto store failed to load or save the backup state or the room
+    /// keys.
+    #[error("the crypto store failed: {0}")]
+    Store(#[from] CryptoStoreError),
+
+    /// The backup info couldn't be serialized, or the one of the homeserver
+    /// couldn't be deserialized.
+    #[error("the backup info is invalid: {0}")]
+    InvalidBackupInfo(#[from] serde_json::Error),
+
+    /// The olm machine isn't available yet, for example because the client
+    /// isn't logged in.
+    #[error("the olm machine isn't yet available")]
+    NoOlmMachine,
+
+    /// There wasn't enough randomness to create a new backup recovery key.
+    #[error("couldn't generate a new backup recovery key")]
+    KeyGeneration,
+
+    /// The backup recovery key isn't a valid key.
+    #[error("the backup recovery key is invalid: {0}")]
+    InvalidRecoveryKey(#[from] DecodeError),
+
+    /// Our backup recovery key isn't the one of the backup on the homeserver,
+    /// which was created by one of our trusted devices or by our trusted
+    /// identity.
+    #[error("the backup recovery key doesn't match the backup")]
+    RecoveryKeyMismatch,
+
+    /// Our backup recovery key isn't the one of the backup on the homeserver,
+    /// and the backup isn't signed by any of our trusted devices nor by our
+    /// trusted identity, so it might not have been created by us.
+    #[error("the backup isn't signed by a trusted device or identity")]
+    UntrustedSignature,
+}
 
 /// The states the upload task can be in.
 ///
@@ -49,20 +96,36 @@ pub enum UploadState {
 }
 
 pub(crate) struct BackupClientState {
-    pub(super) upload_delay: Arc<RwLock<Duration>>,
+    /// Overrides the delay between the batches of the
+    /// [`BackupUploadStrategy`], while waiting for the steady state.
+    ///
+    /// [`BackupUploadStrategy`]: crate::encryption::BackupUploadStrategy
+    pub(super) upload_delay: Arc<RwLock<Option<Duration>>>,
     pub(crate) upload_progress: ChannelObservable<UploadState>,
     pub(super) global_state: ChannelObservable<BackupState>,
+    /// The number of room keys imported from the backup so far, and the total
+    /// number of room keys to import, while we're in the
+    /// [`BackupState::Downloading`] state.
+    pub(super) download_progress: ChannelObservable<(usize, usize)>,
+    /// Whether a backup exists on the homeserver, as of the last time we
+    /// asked it, `None` if we didn't ask it yet.
+    pub(super) exists_on_server: ChannelObservable<Option<bool>>,
+    /// The info of the backup version we use, as returned by the homeserver,
+    /// to check our recovery key against it without asking the homeserver
+    /// again for every room key we download.
+    pub(super) backup_info: Arc<RwLock<Option<(String, RoomKeyBackupInfo)>>>,
     pub(super) room_keys_broadcaster: broadcast::Sender<RoomKeyImportResult>,
 }
 
-const DEFAULT_BACKUP_UPLOAD_DELAY: Duration = Duration::from_millis(100);
-
 impl Default for BackupClientState {
     fn default() -> Self {
         Self {
-            upload_delay: RwLock::new(DEFAULT_BACKUP_UPLOAD_DELAY).into(),
+            upload_delay: Default::default(),
             upload_progress: ChannelObservable::new(UploadState::Idle),
             global_state: Default::default(),
+            download_progress: Default::default(),
+            exists_on_server: Default::default(),
+            backup_info: Default::default(),
             room_keys_broadcaster: broadcast::Sender::new(100),
         }
     }
@@ -118,4 +181,144 @@ pub enum BackupState {
     /// will happen when you call the [`Backups::disable()`] method. After it
     /// has been disabled, we're going to transition into the `Unknown` state.
     Disabling,
+    /// The backup version changed on the server, for example because another
+    /// device created a new backup, and we don't have the backup recovery key
+    /// of the new version.
+    ///
+    /// Backups stay disabled until we receive the new backup recovery key,
+    /// either from another device or from secret storage. The user should
+    /// likely be asked for the new recovery key.
+    VersionChanged,
+}
+
+/// A summary of the state of the room key backup, combining the
+/// [`BackupState`] with the progress of the download and upload of room keys.
+///
+/// This is meant to be displayed to the user, for example in the settings of
+/// an app. You can listen to it using the [`Backups::status_stream()`] method.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BackupStatus {
+    /// We don't know whether a backup exists on the server, and we don't use
+    /// one, see [`BackupState::Unknown`].
+    #[default]
+    Unknown,
+    /// A new backup is being created by this [`Client`].
+    Creating,
+    /// An existing backup is being enabled or resumed by this [`Client`].
+    Enabling,
+    /// Room keys are being downloaded from the backup.
+    Downloading {
+        /// The number of room keys that have been imported so far.
+        done: usize,
+        /// The total number of room keys to import.
+        total: usize,
+    },
+    /// The backup is enabled, and all the room keys we know about are backed
+    /// up.
+    Enabled,
+    /// The backup is enabled, and room keys are being uploaded to it.
+    Uploading {
+        /// The number of room keys that are backed up.
+        done: usize,
+        /// The total number of room keys we know about.
+        total: usize,
+    },
+    /// There is no backup on the server, or it is being disabled and deleted
+    /// from the server, see [`BackupState::Disabling`].
+    Disabled,
+    /// The backup version changed on the server, and we're waiting for the
+    /// backup recovery key of the new version before switching to it, see
+    /// [`BackupState::VersionChanged`].
+    Switching,
+    /// The last attempt to upload room keys to the backup failed, it will be
+    /// retried later.
+    Error,
+}
+
+impl BackupStatus {
+    pub(super) fn new(
+        state: BackupState,
+        upload: &UploadState,
+        download: (usize, usize),
+        exists_on_server: Option<bool>,
+    ) -> Self {
+        match state {
+            BackupState::Unknown if exists_on_server == Some(false) => Self::Disabled,
+            BackupState::Unknown => Self::Unknown,
+            BackupState::Creating => Self::Creating,
+            BackupState::Enabling | BackupState::Resuming => Self::Enabling,
+            BackupState::Downloading => {
+                let (done, total) = download;
+                Self::Downloading { done, total }
+            }
+            BackupState::Enabled => match upload {
+                UploadState::Idle | UploadState::Done => Self::Enabled,
+                UploadState::Uploading(counts) => {
+                    Self::Uploading { done: counts.backed_up, total: counts.total }
+                }
+                UploadState::Error => Self::Error,
+            },
+            BackupState::Disabling => Self::Disabled,
+            BackupState::VersionChanged => Self::Switching,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use matrix_sdk_base::crypto::store::RoomKeyCounts;
+
+    use super::{BackupState, BackupStatus, UploadState};
+
+    fn status(state: BackupState, upload: &UploadState, download: (usize, usize)) -> BackupStatus {
+        BackupStatus::new(state, upload, download, None)
+    }
+
+    #[test]
+    fn test_backup_status_mapping() {
+        let idle = UploadState::Idle;
+
+        assert_eq!(status(BackupState::Unknown, &idle, (0, 0)), BackupStatus::Unknown);
+        assert_eq!(status(BackupState::Creating, &idle, (0, 0)), BackupStatus::Creating);
+        assert_eq!(status(BackupState::Enabling, &idle, (0, 0)), BackupStatus::Enabling);
+        assert_eq!(status(BackupState::Resuming, &idle, (0, 0)), BackupStatus::Enabling);
+        assert_eq!(
+            status(BackupState::Downloading, &idle, (3, 10)),
+            BackupStatus::Downloading { done: 3, total: 10 }
+        );
+        assert_eq!(status(BackupState::Disabling, &idle, (0, 0)), BackupStatus::Disabled);
+        assert_eq!(status(BackupState::VersionChanged, &idle, (0, 0)), BackupStatus::Switching);
+
+        // The upload state only matters once the backup is enabled.
+        let uploading = UploadState::Uploading(RoomKeyCounts { total: 10, backed_up: 4 });
+
+        assert_eq!(status(BackupState::Enabled, &idle, (0, 0)), BackupStatus::Enabled);
+        assert_eq!(status(BackupState::Enabled, &UploadState::Done, (0, 0)), BackupStatus::Enabled);
+        assert_eq!(
+            status(BackupState::Enabled, &uploading, (0, 0)),
+            BackupStatus::Uploading { done: 4, total: 10 }
+        );
+        assert_eq!(status(BackupState::Enabled, &UploadState::Error, (0, 0)), BackupStatus::Error);
+        assert_eq!(status(BackupState::Creating, &uploading, (0, 0)), BackupStatus::Creating);
+    }
+
+    #[test]
+    fn test_backup_status_without_a_backup_on_the_server() {
+        let idle = UploadState::Idle;
+
+        assert_eq!(
+            BackupStatus::new(BackupState::Unknown, &idle, (0, 0), Some(false)),
+            BackupStatus::Disabled
+        );
+        assert_eq!(
+            BackupStatus::new(BackupState::Unknown, &idle, (0, 0), Some(true)),
+            BackupStatus::Unknown
+        );
+        // A backup we're creating or enabling isn't disabled, whatever the
+        // homeserver said before.
+        assert_eq!(
+            BackupStatus::new(BackupState::Creating, &idle, (0, 0), Some(false)),
+            BackupStatus::Creating
+        );
+    }
 }
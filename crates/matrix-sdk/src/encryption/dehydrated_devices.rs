@@ -0,0 +1,387 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Dehydrated devices, as defined in [MSC3814].
+//!
+//! A dehydrated device receives the room keys sent to the user while none of
+//! their real devices exist. Its one-time keys get used up over time, after
+//! which other devices can't create new Olm sessions with it, so it needs to
+//! be replaced regularly: see [`DehydratedDevices::start_rotation()`].
+//!
+//! [`DehydratedDevices::enable()`] takes care of everything: it keeps the
+//! pickle key of the dehydrated devices in secret storage, imports the room
+//! keys received by the previous dehydrated device, and replaces it according
+//! to a [`RotationPolicy`]. After logging in on a new device,
+//! [`DehydratedDevices::rehydrate_and_replace()`] picks up the room keys
+//! received in the meantime and replaces the dehydrated device once.
+//!
+//! [MSC3814]: https://github.com/matrix-org/matrix-spec-proposals/pull/3814
+
+use std::{sync::Arc, time::Duration};
+
+use eyeball::Subscriber;
+use matrix_sdk_base::crypto::{
+    dehydrated_devices::DehydrationError,
+    vodozemac::{base64_decode, base64_encode},
+};
+use rand::RngCore;
+use ruma::{
+    api::client::{
+        dehydrated_device::{get_dehydrated_device, get_events},
+        error::ErrorKind,
+    },
+    events::secret::request::SecretName,
+    OwnedDeviceId,
+};
+use tracing::{info, instrument, warn};
+use zeroize::Zeroizing;
+
+use crate::{
+    client::tasks::DehydratedDeviceRotationTask,
+    encryption::secret_storage::{SecretStorageError, SecretStore},
+    Client, Error, Result,
+};
+
+/// The display name of the dehydrated devices we create.
+const DEHYDRATED_DEVICE_DISPLAY_NAME: &str = "Dehydrated device";
+
+/// The name of the secret holding the pickle key of the dehydrated devices,
+/// as defined in MSC3814.
+const PICKLE_KEY_SECRET_NAME: &str = "org.matrix.msc3814";
+
+/// Error type for [`DehydratedDevices::enable()`] and
+/// [`DehydratedDevices::pickle_key()`].
+#[derive(Debug, thiserror::Error)]
+pub enum DehydratedDeviceError {
+    /// A typical SDK error.
+    #[error(transparent)]
+    Sdk(#[from] Error),
+
+    /// Error in the secret storage subsystem.
+    #[error(transparent)]
+    SecretStorage(#[from] SecretStorageError),
+
+    /// The pickle key found in secret storage isn't a base64-encoded 32 bytes
+    /// key.
+    #[error("the pickle key of the dehydrated devices in secret storage is invalid")]
+    InvalidPickleKey,
+}
+
+/// When [`DehydratedDevices::enable()`] replaces the dehydrated device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RotationPolicy {
+    /// Replace the dehydrated device once, right after rehydrating the
+    /// previous one.
+    AfterRehydration,
+
+    /// Replace the dehydrated device right after rehydrating the previous
+    /// one, and then periodically, like
+    /// [`DehydratedDevices::start_rotation()`].
+    Every(Duration),
+}
+
+/// The outcome of [`DehydratedDevices::rehydrate_and_replace()`].
+#[derive(Clone, Debug)]
+pub struct RehydrationSummary {
+    /// The number of room keys imported from the previous dehydrated device,
+    /// or `None` if the user didn't have a dehydrated device or if it couldn't
+    /// be decrypted with the pickle key.
+    pub imported_room_keys: Option<usize>,
+
+    /// The ID of the new dehydrated device.
+    pub device_id: OwnedDeviceId,
+}
+
+/// The state of the periodic rotation of the dehydrated device.
+#[derive(Clone, Debug, Default)]
+pub enum RotationState {
+    /// The dehydrated device isn't being rotated right now.
+    #[default]
+    Idle,
+
+    /// A new dehydrated device is being created and uploaded.
+    Rotating,
+
+    /// The last rotation failed with the given error.
+    ///
+    /// It is retried after a minute, and then after twice as long every time
+    /// it fails again, but never later than the next period.
+    Failed(Arc<Error>),
+}
+
+/// The dehydrated devices manager of the client.
+///
+/// Get access to this manager with [`Encryption::dehydrated_devices()`].
+///
+/// [`Encryption::dehydrated_devices()`]: crate::encryption::Encryption::dehydrated_devices
+#[derive(Debug)]
+pub struct DehydratedDevices {
+    pub(super) client: Client,
+}
+
+impl DehydratedDevices {
+    /// Set up the dehydrated devices of the user.
+    ///
+    /// This gets the pickle key of the dehydrated devices from secret storage,
+    /// or creates one and stores it there, imports the room keys received by
+    /// the current dehydrated device, if any, and replaces it according to
+    /// the given policy.
+    #[instrument(skip_all)]
+    pub async fn enable(
+        &self,
+        secret_store: &SecretStore,
+        policy: RotationPolicy,
+    ) -> Result<(), DehydratedDeviceError> {
+        let pickle_key = self.pickle_key(secret_store).await?;
+        Ok(self.rehydrate_and_rotate(&pickle_key, policy).await?)
+    }
+
+    /// Import the room keys received by the dehydrated device of the user, if
+    /// any, and replace it with a new one.
+    ///
+    /// This is meant to be called right after logging in on a new device. The
+    /// pickle key of the dehydrated devices is taken from secret storage, or
+    /// created and stored there if there is none yet.
+    #[instrument(skip_all)]
+    pub async fn rehydrate_and_replace(
+        &self,
+        secret_store: &SecretStore,
+    ) -> Result<RehydrationSummary, DehydratedDeviceError> {
+        let pickle_key = self.pickle_key(secret_store).await?;
+        Ok(self.rehydrate_and_replace_with_key(&pickle_key).await?)
+    }
+
+    async fn rehydrate_and_replace_with_key(
+        &self,
+        pickle_key: &[u8; 32],
+    ) -> Result<RehydrationSummary> {
+        // The room keys of the current dehydrated device are lost once it is
+        // replaced, so import them first.
+        let imported_room_keys = self.rehydrate_if_usable(pickle_key).await?;
+        let device_id = self.rotate(pickle_key).await?;
+
+        Ok(RehydrationSummary { imported_room_keys, device_id })
+    }
+
+    async fn rehydrate_and_rotate(
+        &self,
+        pickle_key: &[u8; 32],
+        policy: RotationPolicy,
+    ) -> Result<()> {
+        match policy {
+            RotationPolicy::AfterRehydration => {
+                self.rehydrate_and_replace_with_key(pickle_key).await?;
+            }
+            RotationPolicy::Every(interval) => {
+                // The room keys of the current dehydrated device are lost once
+                // it is replaced, so import them first.
+                self.rehydrate_if_usable(pickle_key).await?;
+                self.start_rotation(pickle_key, interval);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the pickle key of the dehydrated devices from secret storage.
+    ///
+    /// If secret storage doesn't have one, a new random key is created and
+    /// stored there.
+    ///
+    /// The key is zeroized when the returned value is dropped.
+    pub async fn pickle_key(
+        &self,
+        secret_store: &SecretStore,
+    ) -> Result<Zeroizing<[u8; 32]>, DehydratedDeviceError> {
+        let secret_name = SecretName::from(PICKLE_KEY_SECRET_NAME);
+
+        if let Some(secret) = secret_store.get_secret(secret_name.clone()).await? {
+            let secret = Zeroizing::new(secret);
+            let decoded = Zeroizing::new(
+                base64_decode(secret.as_str())
+                    .map_err(|_| DehydratedDeviceError::InvalidPickleKey)?,
+            );
+            let pickle_key = <[u8; 32]>::try_from(decoded.as_slice())
+                .map_err(|_| DehydratedDeviceError::InvalidPickleKey)?;
+
+            return Ok(Zeroizing::new(pickle_key));
+        }
+
+        let mut pickle_key = Zeroizing::new([0u8; 32]);
+        rand::thread_rng().fill_bytes(pickle_key.as_mut_slice());
+
+        let secret = Zeroizing::new(base64_encode(pickle_key.as_slice()));
+        secret_store.put_secret(secret_name, &secret).await?;
+
+        info!("Stored a new pickle key for the dehydrated devices in secret storage");
+
+        Ok(pickle_key)
+    }
+
+    /// Import the room keys received by the dehydrated device of the user.
+    ///
+    /// # Arguments
+    ///
+    /// * `pickle_key` - The key that was used to encrypt the private parts of
+    ///   the dehydrated device.
+    ///
+    /// Returns the number of imported room keys, or `None` if the user doesn't
+    /// have a dehydrated device.
+    #[instrument(skip_all)]
+    pub async fn rehydrate(&self, pickle_key: &[u8; 32]) -> Result<Option<usize>> {
+        let response =
+            match self.client.send(get_dehydrated_device::unstable::Request::new(), None).await {
+                Ok(response) => response,
+                Err(e) if e.client_api_error_kind() == Some(&ErrorKind::NotFound) => {
+                    return Ok(None);
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+        let rehydrated = {
+            let olm_machine = self.client.olm_machine().await;
+            let olm_machine = olm_machine.as_ref().ok_or(Error::NoOlmMachine)?;
+
+            olm_machine
+                .dehydrated_devices()
+                .rehydrate(pickle_key, &response.device_id, response.device_data)
+                .await?
+        };
+
+        let mut room_key_count = 0;
+        let mut next_batch = None;
+
+        loop {
+            let mut request = get_events::unstable::Request::new(response.device_id.clone());
+            request.next_batch = next_batch;
+
+            let events = self.client.send(request, None).await?;
+            if events.events.is_empty() {
+                break;
+            }
+
+            room_key_count += rehydrated.receive_events(events.events).await?.len();
+
+            next_batch = events.next_batch;
+            if next_batch.is_none() {
+                break;
+            }
+        }
+
+        info!(device_id = ?response.device_id, room_key_count, "Rehydrated the dehydrated device");
+
+        Ok(Some(room_key_count))
+    }
+
+    /// Like [`DehydratedDevices::rehydrate()`], but a dehydrated device that
+    /// can't be decrypted with the pickle key counts as no dehydrated device.
+    ///
+    /// Such a device is useless, for example because it was created with a
+    /// pickle key that was lost, so it must be replaced instead of failing
+    /// every attempt to set up the dehydrated devices.
+    async fn rehydrate_if_usable(&self, pickle_key: &[u8; 32]) -> Result<Option<usize>> {
+        match self.rehydrate(pickle_key).await {
+            Err(Error::DehydrationError(
+                e @ (DehydrationError::Pickle(_) | DehydrationError::Json(_)),
+            )) => {
+                warn!("Couldn't decrypt the dehydrated device, it will be replaced: {e}");
+                Ok(None)
+            }
+            result => result,
+        }
+    }
+
+    /// Create a new dehydrated device and upload it to the homeserver.
+    ///
+    /// The homeserver replaces the previous dehydrated device of the user, if
+    /// any, with the new one, so it isn't deleted beforehand: that would leave
+    /// the user without a dehydrated device if the upload failed.
+    ///
+    /// # Arguments
+    ///
+    /// * `pickle_key` - The key used to encrypt the private parts of the
+    ///   dehydrated device.
+    ///
+    /// Returns the ID of the new dehydrated device.
+    #[instrument(skip_all)]
+    pub async fn rotate(&self, pickle_key: &[u8; 32]) -> Result<OwnedDeviceId> {
+        let request = {
+            let olm_machine = self.client.olm_machine().await;
+            let olm_machine = olm_machine.as_ref().ok_or(Error::NoOlmMachine)?;
+
+            let device = olm_machine.dehydrated_devices().create().await?;
+            device.keys_for_upload(DEHYDRATED_DEVICE_DISPLAY_NAME.to_owned(), pickle_key).await?
+        };
+
+        let device_id = request.device_id.clone();
+        self.client.send(request, None).await?;
+
+        info!(?device_id, "Uploaded a new dehydrated device");
+
+        Ok(device_id)
+    }
+
+    /// Replace the dehydrated device right away, and then every `interval`,
+    /// until [`DehydratedDevices::stop_rotation()`] is called.
+    ///
+    /// The outcome of the rotations can be followed with
+    /// [`DehydratedDevices::rotation_state()`]. A failed rotation is retried
+    /// before the next period, see [`RotationState::Failed`], and the task is
+    /// restarted if it panics.
+    ///
+    /// Calling this again replaces the previous rotation task. The task keeps
+    /// a copy of the pickle key, which is zeroized when the task stops.
+    pub fn start_rotation(&self, pickle_key: &[u8; 32], interval: Duration) {
+        let task = DehydratedDeviceRotationTask::new(
+            Arc::downgrade(&self.client.inner),
+            Zeroizing::new(*pickle_key),
+            interval,
+        );
+
+        self.client.inner.tasks.lock().unwrap().rotate_dehydrated_device = Some(task);
+    }
+
+    /// Stop the periodic rotation of the dehydrated device.
+    pub fn stop_rotation(&self) {
+        self.client.inner.tasks.lock().unwrap().rotate_dehydrated_device = None;
+        self.client.inner.dehydrated_device_rotation_state.set(RotationState::Idle);
+    }
+
+    /// Get a subscriber to the state of the periodic rotation of the
+    /// dehydrated device.
+    pub fn rotation_state(&self) -> Subscriber<RotationState> {
+        self.client.inner.dehydrated_device_rotation_state.subscribe()
+    }
+
+    /// Rotate the dehydrated device, and report the outcome in the rotation
+    /// state.
+    ///
+    /// Returns whether the rotation succeeded.
+    pub(crate) async fn rotate_and_report(&self, pickle_key: &[u8; 32]) -> bool {
+        let state = &self.client.inner.dehydrated_device_rotation_state;
+        state.set(RotationState::Rotating);
+
+        match self.rotate(pickle_key).await {
+            Ok(_) => {
+                state.set(RotationState::Idle);
+                true
+            }
+            Err(e) => {
+                warn!("Couldn't rotate the dehydrated device: {e}");
+                state.set(RotationState::Failed(Arc::new(e)));
+                false
+            }
+        }
+    }
+}
@@ -89,7 +89,9 @@ mod users;
 
 pub use devices::{Device, DeviceUpdates, UserDevices};
 pub use matrix_sdk_base::crypto::types::MasterPubkey;
-pub use users::{IdentityUpdates, UserIdentity};
+pub use users::{
+    IdentityUpdates, IdentityVerificationState, UserIdentity, VerificationStateChange,
+};
 
 /// Error for the manual verification step, when we manually sign users or
 /// devices.
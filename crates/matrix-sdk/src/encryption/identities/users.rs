@@ -16,7 +16,7 @@ use std::collections::BTreeMap;
 
 use matrix_sdk_base::{
     crypto::{
-        types::MasterPubkey, OwnUserIdentity as InnerOwnUserIdentity,
+        store::CryptoStoreError, types::MasterPubkey, OwnUserIdentity as InnerOwnUserIdentity,
         UserIdentities as InnerUserIdentities, UserIdentity as InnerUserIdentity,
     },
     RoomMemberships,
@@ -44,13 +44,37 @@ pub struct IdentityUpdates {
     pub new: BTreeMap<OwnedUserId, UserIdentity>,
     /// The list of changed identities.
     pub changed: BTreeMap<OwnedUserId, UserIdentity>,
+    /// The users whose identity changed verification state.
+    ///
+    /// This includes the identities that were just discovered, and the
+    /// identities that didn't change themselves but were verified, or are not
+    /// verified anymore, because our own identity changed.
+    pub verification_state_changes: BTreeMap<OwnedUserId, VerificationStateChange>,
 }
 
 impl IdentityUpdates {
+    /// Convert the updates of the crypto store, using `known_states` to find
+    /// out which identities changed verification state.
     pub(crate) fn new(
         client: Client,
         updates: matrix_sdk_base::crypto::store::IdentityUpdates,
+        known_states: &mut BTreeMap<OwnedUserId, IdentityVerificationState>,
     ) -> Self {
+        let mut verification_state_changes = BTreeMap::new();
+
+        for (user_id, identity) in
+            updates.new.iter().chain(&updates.changed).chain(&updates.unchanged)
+        {
+            let current =
+                UserIdentity::new(client.to_owned(), identity.clone()).verification_state();
+            let previous = known_states.insert(user_id.clone(), current);
+
+            if previous != Some(current) {
+                verification_state_changes
+                    .insert(user_id.clone(), VerificationStateChange { previous, current });
+            }
+        }
+
         let new = updates
             .new
             .into_iter()
@@ -63,10 +87,34 @@ impl IdentityUpdates {
             .map(|(user_id, identity)| (user_id, UserIdentity::new(client.to_owned(), identity)))
             .collect();
 
-        Self { new, changed }
+        Self { new, changed, verification_state_changes }
     }
 }
 
+/// The verification state of a [`UserIdentity`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IdentityVerificationState {
+    /// The identity isn't verified.
+    Unverified,
+    /// The identity is verified.
+    Verified,
+    /// The identity was verified at some point, but the user changed their
+    /// cross-signing keys since then.
+    ///
+    /// See [`UserIdentity::has_verification_violation()`].
+    VerificationViolation,
+}
+
+/// A change of the [`IdentityVerificationState`] of a user.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VerificationStateChange {
+    /// The previous verification state, or `None` if the identity wasn't
+    /// known before.
+    pub previous: Option<IdentityVerificationState>,
+    /// The new verification state.
+    pub current: IdentityVerificationState,
+}
+
 /// A struct representing a E2EE capable identity of a user.
 ///
 /// The identity is backed by public [cross signing] keys that users upload. If
@@ -106,7 +154,7 @@ pub struct UserIdentity {
 }
 
 impl UserIdentity {
-    fn new(client: Client, identity: InnerUserIdentities) -> Self {
+    pub(crate) fn new(client: Client, identity: InnerUserIdentities) -> Self {
         match identity {
             InnerUserIdentities::Own(i) => Self::new_own(client, i),
             InnerUserIdentities::Other(i) => Self::new_other(client, i),
@@ -382,6 +430,78 @@ impl UserIdentity {
         }
     }
 
+    /// Was this identity verified at some point, but isn't verified anymore?
+    ///
+    /// This happens when another user replaced their cross-signing keys after
+    /// we verified them, and should be shown as a prominent warning. Until the
+    /// new identity is verified, or the violation is acknowledged with
+    /// [`UserIdentity::withdraw_verification()`], sharing room keys with this
+    /// user fails if [`EncryptionSettings::error_on_unverified_devices`] is
+    /// set.
+    ///
+    /// This is always `false` for our own identity.
+    ///
+    /// [`EncryptionSettings::error_on_unverified_devices`]: crate::encryption::EncryptionSettings::error_on_unverified_devices
+    pub fn has_verification_violation(&self) -> bool {
+        match &self.inner {
+            UserIdentities::Own(_) => false,
+            UserIdentities::Other(i) => i.inner.has_verification_violation(),
+        }
+    }
+
+    /// Get the verification state of this identity.
+    ///
+    /// This sums up [`UserIdentity::is_verified()`] and
+    /// [`UserIdentity::has_verification_violation()`].
+    pub fn verification_state(&self) -> IdentityVerificationState {
+        if self.is_verified() {
+            IdentityVerificationState::Verified
+        } else if self.has_verification_violation() {
+            IdentityVerificationState::VerificationViolation
+        } else {
+            IdentityVerificationState::Unverified
+        }
+    }
+
+    /// Did the identity of this user change since we first saw it?
+    ///
+    /// This happens when another user replaced their cross-signing keys, and
+    /// we neither verified the new identity nor acknowledged the change with
+    /// [`UserIdentity::pin_current_identity()`].
+    ///
+    /// This is always `false` for our own identity.
+    pub fn has_pin_violation(&self) -> bool {
+        match &self.inner {
+            UserIdentities::Own(_) => false,
+            UserIdentities::Other(i) => i.inner.has_pin_violation(),
+        }
+    }
+
+    /// Acknowledge that the identity of this user changed, resolving a pin
+    /// violation.
+    ///
+    /// This does nothing for our own identity.
+    pub async fn pin_current_identity(&self) -> Result<(), CryptoStoreError> {
+        match &self.inner {
+            UserIdentities::Own(_) => Ok(()),
+            UserIdentities::Other(i) => i.inner.pin_current_identity().await,
+        }
+    }
+
+    /// Acknowledge that a previously verified user changed their identity,
+    /// resolving a verification violation.
+    ///
+    /// The user is then treated like any other unverified user, with the new
+    /// identity pinned.
+    ///
+    /// This does nothing for our own identity.
+    pub async fn withdraw_verification(&self) -> Result<(), CryptoStoreError> {
+        match &self.inner {
+            UserIdentities::Own(_) => Ok(()),
+            UserIdentities::Other(i) => i.inner.withdraw_verification().await,
+        }
+    }
+
     /// Get the public part of the Master key of this user identity.
     ///
     /// The public part of the Master key is usually used to uniquely identify
@@ -0,0 +1,160 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Monitoring of the one-time keys and the fallback key of this device.
+//!
+//! Other devices need to claim one of our one-time keys, or our fallback key,
+//! to create an Olm session with us. The server tells us how many one-time
+//! keys it has left in every sync response, but a client that syncs rarely
+//! might run out of them in the meantime. The client thus checks the state of
+//! the keys periodically in the background, tops up the one-time keys and
+//! rotates the fallback key when it gets too old.
+//!
+//! The outcome of these checks can be followed with
+//! [`Encryption::key_health()`].
+
+use std::time::Duration;
+
+use eyeball::Subscriber;
+use ruma::api::client::keys::upload_keys;
+use tracing::{instrument, warn};
+
+use super::Encryption;
+use crate::{Error, Result};
+
+/// How often the health of the keys is checked in the background.
+pub(crate) const KEY_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// The age after which the fallback key gets replaced.
+pub(crate) const MAX_FALLBACK_KEY_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// The number of consecutive failed key uploads after which
+/// [`KeyHealth::is_upload_failing()`] returns `true`.
+const UPLOAD_FAILURES_THRESHOLD: u32 = 3;
+
+/// The state of the one-time keys and the fallback key of this device.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyHealth {
+    /// The number of one-time keys the server has for this device, as last
+    /// reported by the server.
+    pub one_time_key_count: u64,
+
+    /// The age of the fallback key of this device, if it is known.
+    pub fallback_key_age: Option<Duration>,
+
+    /// The number of key uploads that failed in a row.
+    pub consecutive_upload_failures: u32,
+}
+
+impl KeyHealth {
+    /// Whether the keys of this device have failed to be uploaded too many
+    /// times in a row.
+    ///
+    /// When this is the case, other devices might soon be unable to create
+    /// Olm sessions with this device, and thus to share room keys with it. It
+    /// is a good idea to warn the user about it.
+    pub fn is_upload_failing(&self) -> bool {
+        self.consecutive_upload_failures >= UPLOAD_FAILURES_THRESHOLD
+    }
+}
+
+impl Encryption {
+    /// Get a subscriber to the state of the one-time keys and the fallback
+    /// key of this device.
+    pub fn key_health(&self) -> Subscriber<KeyHealth> {
+        self.client.inner.key_health.subscribe()
+    }
+
+    /// Check the health of the one-time keys and the fallback key of this
+    /// device, and upload new ones if needed.
+    ///
+    /// This fetches the number of one-time keys the server has left for this
+    /// device, tops them up if needed and replaces the fallback key if it is
+    /// too old.
+    ///
+    /// This is done periodically in the background, it is only useful to call
+    /// it manually when the client doesn't sync, or syncs without
+    /// end-to-end encryption extensions.
+    #[instrument(skip_all)]
+    pub async fn check_key_health(&self) -> Result<()> {
+        // An empty upload is answered with the current one-time key counts. It
+        // must not be marked as sent, since it didn't publish any key.
+        let request = upload_keys::v3::Request::new();
+        let response = match self.client.send(request, None).await {
+            Ok(response) => response,
+            Err(e) => {
+                self.on_keys_upload_failed();
+                return Err(e.into());
+            }
+        };
+
+        {
+            let olm_machine = self.client.olm_machine().await;
+            let olm_machine = olm_machine.as_ref().ok_or(Error::NoOlmMachine)?;
+
+            // This generates new one-time keys if there aren't enough of them.
+            olm_machine.update_key_counts(&response.one_time_key_counts).await?;
+            olm_machine
+                .rotate_fallback_key(MAX_FALLBACK_KEY_AGE, self.client.clock().now_ms())
+                .await?;
+        }
+
+        self.on_keys_uploaded().await;
+        self.client.send_outgoing_requests().await
+    }
+
+    /// Update the key health after a successful key upload.
+    pub(crate) async fn on_keys_uploaded(&self) {
+        self.client.inner.key_health.update(|health| health.consecutive_upload_failures = 0);
+        self.refresh_key_health().await;
+    }
+
+    /// Update the key health after a failed key upload.
+    pub(crate) fn on_keys_upload_failed(&self) {
+        let mut failing = false;
+
+        self.client.inner.key_health.update(|health| {
+            health.consecutive_upload_failures += 1;
+            failing = health.is_upload_failing();
+        });
+
+        if failing {
+            warn!("Uploading the keys of this device keeps failing");
+        }
+    }
+
+    /// Update the key counts of the key health with the ones of the
+    /// `OlmMachine`.
+    pub(crate) async fn refresh_key_health(&self) {
+        let olm_machine = self.client.olm_machine().await;
+        let Some(olm_machine) = olm_machine.as_ref() else { return };
+
+        let now = self.client.clock().now_ms();
+        let (one_time_key_count, fallback_key_age) =
+            match (olm_machine.uploaded_key_count().await, olm_machine.fallback_key_age(now).await)
+            {
+                (Ok(count), Ok(age)) => (count, age),
+                (Err(e), _) | (_, Err(e)) => {
+                    warn!("Couldn't load the key counts of this device: {e}");
+                    return;
+                }
+            };
+
+        let mut health = self.client.inner.key_health.get();
+        health.one_time_key_count = one_time_key_count;
+        health.fallback_key_age = fallback_key_age;
+
+        self.client.inner.key_health.set_if_not_eq(health);
+    }
+}
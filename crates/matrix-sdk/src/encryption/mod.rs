@@ -21,18 +21,24 @@ use std::{
     io::{Cursor, Read, Write},
     iter,
     path::PathBuf,
+    sync::{Arc, Weak},
+    time::Duration,
 };
 
+use async_stream::stream;
 use eyeball::SharedObservable;
 use futures_core::Stream;
 use futures_util::{
-    future::try_join,
+    future::{self, try_join, Either},
+    pin_mut,
     stream::{self, StreamExt},
 };
 use matrix_sdk_base::crypto::{
-    CrossSigningBootstrapRequests, OlmMachine, OutgoingRequest, RoomMessageRequest, ToDeviceRequest,
+    CrossSigningBootstrapRequests, GossippedSecret, OlmMachine, OutgoingRequest,
+    RoomMessageRequest, ToDeviceRequest,
 };
 use matrix_sdk_common::executor::spawn;
+use rand::Rng;
 use ruma::{
     api::client::{
         backup::add_backup_keys::v3::Response as KeysBackupResponse,
@@ -60,13 +66,16 @@ use tracing::{debug, error, instrument, trace, warn};
 
 use self::{
     backups::Backups,
+    dehydrated_devices::DehydratedDevices,
     futures::PrepareEncryptedFile,
-    identities::{DeviceUpdates, IdentityUpdates},
+    identities::{DeviceUpdates, IdentityUpdates, UserIdentity},
+    pseudo_ids::PseudoIds,
     recovery::Recovery,
     secret_storage::SecretStorage,
 };
 use crate::{
     attachment::{AttachmentInfo, Thumbnail},
+    client::ClientInner,
     encryption::{
         identities::{Device, UserDevices},
         verification::{SasVerification, Verification, VerificationRequest},
@@ -77,10 +86,14 @@ use crate::{
 };
 
 pub mod backups;
+pub mod dehydrated_devices;
 pub mod futures;
 pub mod identities;
+pub mod key_health;
+pub mod pseudo_ids;
 pub mod recovery;
 pub mod secret_storage;
+pub mod trust_recomputation;
 pub mod verification;
 
 pub use matrix_sdk_base::crypto::{
@@ -89,8 +102,12 @@ pub use matrix_sdk_base::crypto::{
         SessionExportError as OlmSessionExportError,
     },
     vodozemac, CrossSigningStatus, CryptoStoreError, DecryptorError, EventError, KeyExportError,
-    LocalTrust, MediaEncryptionInfo, MegolmError, OlmError, RoomKeyImportResult, SecretImportError,
-    SessionCreationError, SignatureError, VERSION,
+    LocalTrust, MediaEncryptionInfo, MegolmError, OlmError, RoomKeyImportResult,
+    RoomKeyRotationLimits, SecretImportError, SessionCreationError, SignatureError, VERSION,
+};
+#[cfg(feature = "automatic-room-key-forwarding")]
+pub use matrix_sdk_base::crypto::{
+    KeyForwardDecision, RoomKeyForwardingPolicy, RoomKeyRequestDecision, RoomKeyRequestOutcome,
 };
 
 pub use crate::error::RoomKeyImportError;
@@ -111,8 +128,51 @@ pub struct EncryptionSettings {
     /// Take a look at the [`BackupDownloadStrategy`] enum for more options.
     pub backup_download_strategy: BackupDownloadStrategy,
 
+    /// How room keys are uploaded to the backup.
+    ///
+    /// By default, they are uploaded in batches of 100 keys, with 100
+    /// milliseconds between batches.
+    pub backup_upload_strategy: BackupUploadStrategy,
+
     /// Automatically create a backup version if no backup exists.
     pub auto_enable_backups: bool,
+
+    /// Rotate the room keys at least as often as these limits require, even if
+    /// the `m.room.encryption` state event of a room allows using them for
+    /// longer.
+    ///
+    /// By default, the rotation periods of the rooms are used as they are.
+    pub room_key_rotation_limits: RoomKeyRotationLimits,
+
+    /// Only send messages in encrypted rooms if all the devices of the members
+    /// are verified.
+    ///
+    /// When some devices aren't verified, sending fails with an
+    /// [`OlmError::UnverifiedDevices`] error listing them. They can then be
+    /// verified, blocked or ignored with
+    /// [`Encryption::set_local_trust_of_devices()`] before sending again.
+    ///
+    /// Sending also fails with an [`OlmError::IdentityViolations`] error if
+    /// some members replaced their identity, until the change is acknowledged
+    /// with [`UserIdentity::pin_current_identity()`] or
+    /// [`UserIdentity::withdraw_verification()`].
+    ///
+    /// By default, unverified devices receive the room keys like the others.
+    ///
+    /// [`UserIdentity::pin_current_identity()`]: identities::UserIdentity::pin_current_identity
+    /// [`UserIdentity::withdraw_verification()`]: identities::UserIdentity::withdraw_verification
+    pub error_on_unverified_devices: bool,
+
+    /// Which devices the room keys are forwarded to when they request them
+    /// with an `m.room_key_request`.
+    ///
+    /// By default, the room keys are forwarded to our own verified devices,
+    /// and to the devices of other users that received them when they were
+    /// shared. The policy can be changed later with
+    /// [`Encryption::set_room_key_forwarding_policy()`], and the decisions
+    /// can be audited with [`Encryption::room_key_request_outcomes_stream()`].
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    pub room_key_forwarding_policy: Option<RoomKeyForwardingPolicy>,
 }
 
 /// Settings for end-to-end encryption features.
@@ -130,7 +190,14 @@ pub enum BackupDownloadStrategy {
     /// [`SecretStore::import_secrets()`]: crate::encryption::secret_storage::SecretStore::import_secrets
     OneShot,
 
-    /// Attempt to download a single room key if an event fails to be decrypted.
+    /// Attempt to download a single room key if an event fails to be decrypted
+    /// because the room key is missing.
+    ///
+    /// Each room key is only requested once at a time, and a room key that
+    /// couldn't be downloaded is requested again with an exponential backoff.
+    /// The room keys that were downloaded are announced by
+    /// [`Backups::room_keys_for_room_stream()`], so the events can be
+    /// decrypted again.
     AfterDecryptionFailure,
 
     /// Don't download any room keys automatically. The user can manually
@@ -141,6 +208,70 @@ pub enum BackupDownloadStrategy {
     Manual,
 }
 
+/// How room keys are uploaded to the backup, see
+/// [`EncryptionSettings::backup_upload_strategy`].
+///
+/// Importing many room keys at once, for example from a key export, requires
+/// many requests to upload them all. A delay between the requests keeps this
+/// from hammering the homeserver.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct BackupUploadStrategy {
+    /// The maximum number of room keys uploaded in a single request.
+    pub batch_size: usize,
+    /// How long to wait before uploading the next batch of room keys.
+    pub delay_between_batches: Duration,
+    /// The maximum random duration added to the delay between batches, so that
+    /// several clients don't upload their room keys in lockstep.
+    pub jitter: Duration,
+}
+
+impl Default for BackupUploadStrategy {
+    fn default() -> Self {
+        Self {
+            batch_size: 100,
+            delay_between_batches: Duration::from_millis(100),
+            jitter: Duration::ZERO,
+        }
+    }
+}
+
+impl BackupUploadStrategy {
+    /// Create the default `BackupUploadStrategy`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the maximum number of room keys uploaded in a single request.
+    ///
+    /// At least one room key is always uploaded per request.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Set how long to wait before uploading the next batch of room keys.
+    pub fn delay_between_batches(mut self, delay: Duration) -> Self {
+        self.delay_between_batches = delay;
+        self
+    }
+
+    /// Set the maximum random duration added to the delay between batches.
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// The delay to wait before uploading the next batch of room keys.
+    pub(crate) fn next_delay(&self) -> Duration {
+        if self.jitter.is_zero() {
+            self.delay_between_batches
+        } else {
+            self.delay_between_batches + rand::thread_rng().gen_range(Duration::ZERO..=self.jitter)
+        }
+    }
+}
+
 impl Client {
     pub(crate) async fn olm_machine(&self) -> RwLockReadGuard<'_, Option<OlmMachine>> {
         self.base_client().olm_machine().await
@@ -366,8 +497,16 @@ impl Client {
             "Uploading public encryption keys",
         );
 
-        let response = self.send(request.clone(), None).await?;
+        let response = match self.send(request.clone(), None).await {
+            Ok(response) => response,
+            Err(e) => {
+                self.encryption().on_keys_upload_failed();
+                return Err(e.into());
+            }
+        };
+
         self.mark_request_as_sent(request_id, &response).await?;
+        self.encryption().on_keys_uploaded().await;
 
         Ok(response)
     }
@@ -507,6 +646,9 @@ impl Client {
             })
             .await;
 
+        // The key counts might have been updated by a sync response.
+        self.encryption().refresh_key_health().await;
+
         Ok(())
     }
 }
@@ -654,6 +796,33 @@ impl Encryption {
         Ok(device.map(|d| Device { inner: d, client: self.client.clone() }))
     }
 
+    /// Set the local trust state of several devices at once.
+    ///
+    /// This is meant to resolve an [`OlmError::UnverifiedDevices`] error, see
+    /// [`EncryptionSettings::error_on_unverified_devices`]: the devices can be
+    /// marked as verified, blocked with [`LocalTrust::BlackListed`], or
+    /// ignored with [`LocalTrust::Ignored`], and the message can be sent
+    /// again.
+    ///
+    /// Devices that are unknown are skipped.
+    pub async fn set_local_trust_of_devices(
+        &self,
+        devices: &BTreeMap<OwnedUserId, Vec<OwnedDeviceId>>,
+        trust_state: LocalTrust,
+    ) -> Result<(), CryptoStoreError> {
+        for (user_id, device_ids) in devices {
+            for device_id in device_ids {
+                if let Some(device) = self.get_device(user_id, device_id).await? {
+                    device.set_local_trust(trust_state).await?;
+                } else {
+                    warn!(?user_id, ?device_id, "Can't set the local trust of an unknown device");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     /// Get a map holding all the devices of an user.
     ///
     /// This will always return an empty map if the client hasn't been logged
@@ -790,6 +959,11 @@ impl Encryption {
     /// changed. Users can subscribe to this stream and receive updates in
     /// real-time.
     ///
+    /// The updates also list the identities whose verification state changed
+    /// since the stream was created, in
+    /// [`IdentityUpdates::verification_state_changes`], for example when a
+    /// user gets verified or replaces their verified identity.
+    ///
     /// # Examples
     ///
     /// ```no_run
@@ -806,6 +980,10 @@ impl Encryption {
     ///     for (_, identity) in identity_updates.new {
     ///         println!("A new identity has been added {}", identity.user_id());
     ///     }
+    ///
+    ///     for (user_id, change) in identity_updates.verification_state_changes {
+    ///         println!("{user_id} is now {:?}", change.current);
+    ///     }
     /// }
     /// # anyhow::Ok(()) };
     /// ```
@@ -814,10 +992,150 @@ impl Encryption {
         let olm = olm.as_ref().ok_or(Error::NoOlmMachine)?;
         let client = self.client.to_owned();
 
-        Ok(olm
-            .store()
-            .user_identities_stream()
-            .map(move |updates| IdentityUpdates::new(client.to_owned(), updates)))
+        // Subscribe before reading the current states, to not miss an update
+        // in between.
+        let stream = olm.store().user_identities_stream();
+
+        let mut known_states: BTreeMap<_, _> = Self::known_user_identities(&client, olm)
+            .await?
+            .into_iter()
+            .map(|identity| (identity.user_id().to_owned(), identity.verification_state()))
+            .collect();
+
+        Ok(stream.map(move |updates| {
+            IdentityUpdates::new(client.to_owned(), updates, &mut known_states)
+        }))
+    }
+
+    /// Returns a stream of the secrets received from our other devices, after
+    /// they were stored in the secret inbox.
+    ///
+    /// Unlike [`Store::secrets_stream()`] on the [`OlmMachine`], this stream
+    /// keeps giving updates when the [`OlmMachine`] is reloaded, for example
+    /// because another process modified the crypto store. The secrets that are
+    /// received while the [`OlmMachine`] is being reloaded may be missed, so
+    /// the secret inbox should be checked when the stream is created and every
+    /// time the [`OlmMachine`] is reloaded.
+    ///
+    /// The stream ends when the client is dropped.
+    ///
+    /// [`Store::secrets_stream()`]: matrix_sdk_base::crypto::store::Store::secrets_stream
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::Client;
+    /// # use futures_util::{pin_mut, StreamExt};
+    /// # let client: Client = unimplemented!();
+    /// # async {
+    /// let secrets_stream = client.encryption().secrets_stream();
+    /// pin_mut!(secrets_stream);
+    ///
+    /// while let Some(secret) = secrets_stream.next().await {
+    ///     println!("Received the secret {}", secret.secret_name);
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub fn secrets_stream(&self) -> impl Stream<Item = GossippedSecret> {
+        secrets_stream(Arc::downgrade(&self.client.inner))
+    }
+
+    /// Set which devices the room keys are forwarded to when they request
+    /// them with an `m.room_key_request`.
+    ///
+    /// If the policy is `None`, the room keys are forwarded to our own
+    /// verified devices, and to the devices of other users that received them
+    /// when they were shared. The policy is kept when the [`OlmMachine`] is
+    /// reloaded.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::{encryption::RoomKeyForwardingPolicy, Client};
+    /// # let client: Client = unimplemented!();
+    /// # async {
+    /// client
+    ///     .encryption()
+    ///     .set_room_key_forwarding_policy(Some(
+    ///         RoomKeyForwardingPolicy::OwnVerifiedDevices,
+    ///     ))
+    ///     .await;
+    /// # anyhow::Ok(()) };
+    /// ```
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    pub async fn set_room_key_forwarding_policy(&self, policy: Option<RoomKeyForwardingPolicy>) {
+        self.client.base_client().set_room_key_forwarding_policy(policy).await;
+    }
+
+    /// Get which devices the room keys are forwarded to.
+    ///
+    /// See [`Encryption::set_room_key_forwarding_policy()`].
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    pub fn room_key_forwarding_policy(&self) -> Option<RoomKeyForwardingPolicy> {
+        self.client.base_client().room_key_forwarding_policy()
+    }
+
+    /// Get a stream of the outcomes of the `m.room_key_request`s received for
+    /// the room keys we have, to audit which room keys were forwarded to
+    /// which devices and which requests were rejected.
+    ///
+    /// Like [`Encryption::secrets_stream()`], this stream keeps giving
+    /// updates when the [`OlmMachine`] is reloaded. The stream ends when the
+    /// client is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::{encryption::RoomKeyRequestDecision, Client};
+    /// # use futures_util::{pin_mut, StreamExt};
+    /// # let client: Client = unimplemented!();
+    /// # async {
+    /// let outcomes = client.encryption().room_key_request_outcomes_stream();
+    /// pin_mut!(outcomes);
+    ///
+    /// while let Some(outcome) = outcomes.next().await {
+    ///     if let RoomKeyRequestDecision::Rejected(reason) = outcome.decision {
+    ///         println!(
+    ///             "Didn't forward a room key to {} {}: {reason:?}",
+    ///             outcome.sender, outcome.requesting_device_id
+    ///         );
+    ///     }
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    pub fn room_key_request_outcomes_stream(&self) -> impl Stream<Item = RoomKeyRequestOutcome> {
+        olm_machine_stream(Arc::downgrade(&self.client.inner), "room key request outcomes", |olm| {
+            olm.room_key_request_outcomes_stream()
+        })
+    }
+
+    /// Get the known identities of all the users whose devices are tracked,
+    /// including our own.
+    ///
+    /// Use [`UserIdentity::verification_state()`] to find out whether they
+    /// are verified, and [`Encryption::user_identities_stream()`] to keep up
+    /// with their changes.
+    pub async fn user_identities(&self) -> Result<Vec<UserIdentity>, CryptoStoreError> {
+        let olm = self.client.olm_machine().await;
+        let Some(olm) = olm.as_ref() else { return Ok(Vec::new()) };
+
+        Self::known_user_identities(&self.client, olm).await
+    }
+
+    async fn known_user_identities(
+        client: &Client,
+        olm: &OlmMachine,
+    ) -> Result<Vec<UserIdentity>, CryptoStoreError> {
+        let mut identities = Vec::new();
+        for user_id in olm.tracked_users().await? {
+            if let Some(identity) = olm.get_identity(&user_id, None).await? {
+                identities.push(UserIdentity::new(client.clone(), identity));
+            }
+        }
+
+        identities.sort_by(|a, b| a.user_id().cmp(b.user_id()));
+        Ok(identities)
     }
 
     /// Create and upload a new cross signing identity.
@@ -1102,6 +1420,93 @@ impl Encryption {
         Ok(ret)
     }
 
+    /// Export the complete crypto identity of this device into a file
+    /// encrypted with the given passphrase.
+    ///
+    /// Besides the room keys, the export contains the Olm account of this
+    /// device, the private cross signing keys and the backup decryption key.
+    /// It can be imported with [`Encryption::import_crypto_identity()`] to
+    /// move this device to a new client when secret storage or the key backup
+    /// aren't available.
+    ///
+    /// **Warning**: Anyone with the file and the passphrase can impersonate
+    /// this device, and this device must not be used anymore once it was
+    /// imported elsewhere.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file path where the export will be saved.
+    ///
+    /// * `passphrase` - The passphrase that will be used to encrypt the
+    /// export.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if it isn't run on a Tokio runtime.
+    ///
+    /// This method will panic if it can't get enough randomness from the OS to
+    /// encrypt the export securely.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn export_crypto_identity(&self, path: PathBuf, passphrase: &str) -> Result<()> {
+        let olm = self.client.olm_machine().await;
+        let olm = olm.as_ref().ok_or(Error::NoOlmMachine)?;
+
+        let identity = olm.export_crypto_identity().await?;
+        let passphrase = zeroize::Zeroizing::new(passphrase.to_owned());
+
+        let encrypt = move || -> Result<()> {
+            let export: String = matrix_sdk_base::crypto::encrypt_crypto_identity_export(
+                &identity,
+                &passphrase,
+                500_000,
+            )?;
+            let mut file = std::fs::File::create(path)?;
+            file.write_all(&export.into_bytes())?;
+            Ok(())
+        };
+
+        let task = tokio::task::spawn_blocking(encrypt);
+        task.await.expect("Task join error")
+    }
+
+    /// Restore the crypto identity exported with
+    /// [`Encryption::export_crypto_identity()`] from the given file path.
+    ///
+    /// The client must be logged in with the same user and device ID as the
+    /// exported device, and this must be done before the first sync, since
+    /// it replaces the Olm account of the crypto store. Once the device keys
+    /// of the current account were uploaded, this fails with
+    /// [`CryptoStoreError::AccountAlreadyShared`].
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file path where the export can be found.
+    ///
+    /// * `passphrase` - The passphrase that should be used to decrypt the
+    /// export.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if it isn't run on a Tokio runtime.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn import_crypto_identity(&self, path: PathBuf, passphrase: &str) -> Result<()> {
+        let passphrase = zeroize::Zeroizing::new(passphrase.to_owned());
+
+        let decrypt = move || -> Result<_> {
+            let file = std::fs::File::open(path)?;
+            Ok(matrix_sdk_base::crypto::decrypt_crypto_identity_export(file, &passphrase)?)
+        };
+
+        let task = tokio::task::spawn_blocking(decrypt);
+        let identity = task.await.expect("Task join error")?;
+
+        self.client.base_client().import_crypto_identity(identity).await?;
+
+        self.backups().maybe_trigger_backup();
+
+        Ok(())
+    }
+
     /// Get the secret storage manager of the client.
     pub fn secret_storage(&self) -> SecretStorage {
         SecretStorage { client: self.client.to_owned() }
@@ -1117,6 +1522,16 @@ impl Encryption {
         Recovery { client: self.client.to_owned() }
     }
 
+    /// Get the dehydrated devices manager of the client.
+    pub fn dehydrated_devices(&self) -> DehydratedDevices {
+        DehydratedDevices { client: self.client.to_owned() }
+    }
+
+    /// Get the pseudoIDs manager of the client.
+    pub fn pseudo_ids(&self) -> PseudoIds {
+        PseudoIds { client: self.client.to_owned() }
+    }
+
     /// Enables the crypto-store cross-process lock.
     ///
     /// This may be required if there are multiple processes that may do writes
@@ -1243,6 +1658,9 @@ impl Encryption {
             if let Err(e) = this.backups().setup_and_resume().await {
                 error!("Couldn't setup and resume backups {e:?}");
             }
+            if let Err(e) = this.secret_storage().setup().await {
+                error!("Couldn't setup the secret storage status {e:?}");
+            }
             if let Err(e) = this.recovery().setup().await {
                 error!("Couldn't setup and resume recovery {e:?}");
             }
@@ -1263,33 +1681,280 @@ impl Encryption {
     }
 }
 
+/// A stream of the secrets received by the current [`OlmMachine`] of the
+/// client, that is re-attached to the new [`OlmMachine`] every time it is
+/// reloaded.
+///
+/// It only holds a weak reference to the client, so it can be used by the
+/// tasks owned by the client.
+pub(crate) fn secrets_stream(client: Weak<ClientInner>) -> impl Stream<Item = GossippedSecret> {
+    olm_machine_stream(client, "secrets", |olm| olm.store().secrets_stream())
+}
+
+/// A stream created from the current [`OlmMachine`] of the client with
+/// `make_stream`, that is created again from the new [`OlmMachine`] every
+/// time it is reloaded.
+fn olm_machine_stream<T, S>(
+    client: Weak<ClientInner>,
+    name: &'static str,
+    make_stream: impl Fn(&OlmMachine) -> S,
+) -> impl Stream<Item = T>
+where
+    S: Stream<Item = T>,
+{
+    stream! {
+        let Some(mut reloads) =
+            client.upgrade().map(|inner| inner.base_client.subscribe_to_olm_machine_reloads())
+        else {
+            return;
+        };
+
+        loop {
+            let Some(inner) = client.upgrade() else {
+                trace!("The client was dropped, stopping the {name} stream");
+                break;
+            };
+
+            // Don't keep the client alive while waiting for secrets.
+            let client = Client { inner };
+            let items = client.olm_machine().await.as_ref().map(&make_stream);
+            drop(client);
+
+            let Some(items) = items else {
+                // Wait for the `OlmMachine` to be created.
+                if reloads.next().await.is_none() {
+                    break;
+                }
+                continue;
+            };
+
+            pin_mut!(items);
+
+            loop {
+                let reload = reloads.next();
+                pin_mut!(reload);
+
+                match future::select(items.next(), reload).await {
+                    Either::Left((Some(item), _)) => yield item,
+                    // The `OlmMachine` was dropped, attach to the new one.
+                    Either::Left((None, _)) => break,
+                    Either::Right((Some(()), _)) => {
+                        debug!("The OlmMachine was reloaded, re-attaching the {name} stream");
+                        break;
+                    }
+                    Either::Right((None, _)) => return,
+                }
+            }
+        }
+    }
+}
+
 #[cfg(all(test, not(target_arch = "wasm32")))]
 mod tests {
-    use std::time::Duration;
-
-    use matrix_sdk_base::SessionMeta;
+    use std::{sync::Arc, time::Duration};
+
+    use assert_matches2::assert_matches;
+    use futures_util::{pin_mut, StreamExt};
+    use matrix_sdk_base::{
+        crypto::{
+            store::{BackupDecryptionKey, Changes, CryptoStore, MemoryStore},
+            CryptoStoreError, GossippedSecret, OlmError, OlmMachine,
+        },
+        store::StoreConfig,
+        SessionMeta,
+    };
     use matrix_sdk_test::{
         async_test, test_json, GlobalAccountDataTestEvent, JoinedRoomBuilder, StateTestEvent,
         SyncResponseBuilder, DEFAULT_TEST_ROOM_ID,
     };
     use ruma::{
         device_id, event_id,
-        events::{reaction::ReactionEventContent, relation::Annotation},
+        events::{
+            reaction::ReactionEventContent, relation::Annotation, secret::request::SecretName,
+        },
         user_id,
     };
     use serde_json::json;
+    use stream_assert::assert_pending;
+    use tokio::{
+        sync::mpsc,
+        time::{sleep, timeout},
+    };
     use wiremock::{
         matchers::{header, method, path_regex},
-        Mock, MockServer, ResponseTemplate,
+        Mock, MockServer, Request, ResponseTemplate,
     };
 
+    use super::{
+        key_health::MAX_FALLBACK_KEY_AGE, trust_recomputation::TrustRecomputationState,
+        BackupUploadStrategy,
+    };
     use crate::{
+        clock::{Clock, TestClock},
         config::RequestConfig,
         matrix_auth::{MatrixSession, MatrixSessionTokens},
-        test_utils::logged_in_client,
-        Client,
+        test_utils::{logged_in_client, test_client_builder},
+        Client, Error,
     };
 
+    #[async_test]
+    async fn test_secrets_stream_survives_olm_machine_reloads() {
+        let client = logged_in_client(None).await;
+
+        let secrets_stream = client.encryption().secrets_stream();
+        pin_mut!(secrets_stream);
+        assert_pending!(secrets_stream);
+
+        // The stream of the previous `OlmMachine` ends when it's dropped, the
+        // stream is re-attached to the new one instead of ending.
+        client.base_client().regenerate_olm().await.unwrap();
+        assert_pending!(secrets_stream);
+
+        client.base_client().regenerate_olm().await.unwrap();
+        assert_pending!(secrets_stream);
+
+        // The secrets received by the new `OlmMachine` are in the stream.
+        let secret = {
+            let olm_machine = client.olm_machine().await;
+            let olm_machine = olm_machine.as_ref().unwrap();
+            let secret = backup_recovery_key_secret(olm_machine);
+            olm_machine
+                .store()
+                .save_changes_for_testing(Changes {
+                    secrets: vec![secret.clone()],
+                    ..Default::default()
+                })
+                .await
+                .unwrap();
+            secret
+        };
+
+        let received = timeout(Duration::from_secs(5), secrets_stream.next())
+            .await
+            .expect("The secret should be sent to the stream")
+            .unwrap();
+        assert_eq!(received.secret_name, secret.secret_name);
+        assert_eq!(received.event.content.secret, secret.event.content.secret);
+    }
+
+    /// A `m.megolm_backup.v1` secret sent to us by our own device.
+    fn backup_recovery_key_secret(olm_machine: &OlmMachine) -> GossippedSecret {
+        let ed25519 = olm_machine.identity_keys().ed25519.to_base64();
+        serde_json::from_value(json!({
+            "secret_name": "m.megolm_backup.v1",
+            "gossip_request": {
+                "request_recipient": "@example:localhost",
+                "request_id": "secret_request",
+                "info": { "SecretRequest": "m.megolm_backup.v1" },
+                "sent_out": true,
+            },
+            "event": {
+                "sender": "@example:localhost",
+                "recipient": "@example:localhost",
+                "keys": { "ed25519": ed25519 },
+                "recipient_keys": { "ed25519": ed25519 },
+                "content": {
+                    "request_id": "secret_request",
+                    "secret": BackupDecryptionKey::new().unwrap().to_base64(),
+                },
+            },
+        }))
+        .unwrap()
+    }
+
+    #[async_test]
+    async fn test_secret_inbox_is_checked_after_olm_machine_reloads() {
+        let server = MockServer::start().await;
+        let crypto_store = Arc::new(MemoryStore::new());
+
+        let client = test_client_builder(Some(server.uri()))
+            .request_config(RequestConfig::new().disable_retry())
+            .store_config(StoreConfig::new().crypto_store(crypto_store.clone()))
+            .build()
+            .await
+            .unwrap();
+        client
+            .matrix_auth()
+            .restore_session(MatrixSession {
+                meta: SessionMeta {
+                    user_id: user_id!("@example:localhost").to_owned(),
+                    device_id: device_id!("DEVICEID").to_owned(),
+                },
+                tokens: MatrixSessionTokens {
+                    access_token: "1234".to_owned(),
+                    refresh_token: None,
+                },
+            })
+            .await
+            .unwrap();
+        client.encryption().wait_for_e2ee_initialization_tasks().await;
+
+        Mock::given(method("GET"))
+            .and(path_regex(r"_matrix/client/.*/room_keys/version"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+                "errcode": "M_NOT_FOUND",
+                "error": "No current backup version",
+            })))
+            .mount(&server)
+            .await;
+
+        // Another process received a backup recovery key, it is only in the
+        // secret inbox and our secrets stream never sees it.
+        let secret = backup_recovery_key_secret(client.olm_machine().await.as_ref().unwrap());
+        crypto_store
+            .save_changes(Changes { secrets: vec![secret], ..Default::default() })
+            .await
+            .unwrap();
+
+        let inbox_len = || async {
+            crypto_store.get_secrets_from_inbox(&SecretName::RecoveryKey).await.unwrap().len()
+        };
+        assert_eq!(inbox_len().await, 1);
+
+        // The secret inbox is checked, and emptied, once the `OlmMachine` is
+        // reloaded.
+        client.base_client().regenerate_olm().await.unwrap();
+
+        timeout(Duration::from_secs(5), async {
+            while inbox_len().await > 0 {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("The secret inbox should be checked after the OlmMachine was reloaded");
+    }
+
+    #[async_test]
+    #[cfg(feature = "automatic-room-key-forwarding")]
+    async fn test_room_key_forwarding_policy_survives_olm_machine_reloads() {
+        use super::RoomKeyForwardingPolicy;
+
+        let client = logged_in_client(None).await;
+        let encryption = client.encryption();
+        assert_eq!(encryption.room_key_forwarding_policy(), None);
+
+        let policy = Some(RoomKeyForwardingPolicy::OwnVerifiedDevices);
+        encryption.set_room_key_forwarding_policy(policy).await;
+        assert_eq!(encryption.room_key_forwarding_policy(), policy);
+        assert_eq!(
+            client.olm_machine().await.as_ref().unwrap().room_key_forwarding_policy(),
+            policy
+        );
+
+        let outcomes = encryption.room_key_request_outcomes_stream();
+        pin_mut!(outcomes);
+        assert_pending!(outcomes);
+
+        // The new `OlmMachine` gets the same policy, and the stream is
+        // re-attached to it.
+        client.base_client().regenerate_olm().await.unwrap();
+        assert_eq!(
+            client.olm_machine().await.as_ref().unwrap().room_key_forwarding_policy(),
+            policy
+        );
+        assert_pending!(outcomes);
+    }
+
     #[async_test]
     async fn test_reaction_sending() {
         let server = MockServer::start().await;
@@ -1336,6 +2001,157 @@ mod tests {
         room.send_raw("m.reaction", json!({})).await.expect("Sending the reaction should not fail");
     }
 
+    #[async_test]
+    async fn test_key_health() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+        let key_health = client.encryption().key_health();
+
+        {
+            let _guard = Mock::given(method("POST"))
+                .and(path_regex(r"^/_matrix/client/r0/keys/upload"))
+                .and(header("authorization", "Bearer 1234"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "one_time_key_counts": {
+                        "signed_curve25519": 42
+                    }
+                })))
+                .mount_as_scoped(&server)
+                .await;
+
+            client.encryption().check_key_health().await.unwrap();
+        }
+
+        let health = key_health.get();
+        assert_eq!(health.one_time_key_count, 42);
+        assert_eq!(health.consecutive_upload_failures, 0);
+        assert!(!health.is_upload_failing());
+
+        Mock::given(method("POST"))
+            .and(path_regex(r"^/_matrix/client/r0/keys/upload"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "errcode": "M_UNKNOWN",
+                "error": "Something went wrong",
+            })))
+            .mount(&server)
+            .await;
+
+        client.encryption().check_key_health().await.unwrap_err();
+        assert!(!key_health.get().is_upload_failing());
+
+        client.encryption().check_key_health().await.unwrap_err();
+        client.encryption().check_key_health().await.unwrap_err();
+
+        let health = key_health.get();
+        assert_eq!(health.consecutive_upload_failures, 3);
+        assert!(health.is_upload_failing());
+        // The last known key count is kept.
+        assert_eq!(health.one_time_key_count, 42);
+    }
+
+    #[async_test]
+    async fn test_key_health_task_rotates_the_fallback_key() {
+        let server = MockServer::start().await;
+        let clock = TestClock::new();
+        let client = test_client_builder(Some(server.uri()))
+            .request_config(RequestConfig::new().disable_retry())
+            .clock(Arc::new(clock.clone()))
+            .build()
+            .await
+            .unwrap();
+        client
+            .matrix_auth()
+            .restore_session(MatrixSession {
+                meta: SessionMeta {
+                    user_id: user_id!("@example:localhost").to_owned(),
+                    device_id: device_id!("DEVICEID").to_owned(),
+                },
+                tokens: MatrixSessionTokens {
+                    access_token: "1234".to_owned(),
+                    refresh_token: None,
+                },
+            })
+            .await
+            .unwrap();
+
+        // Forward the bodies of the key uploads to the test.
+        let (uploads_sender, mut uploads) = mpsc::unbounded_channel();
+        Mock::given(method("POST"))
+            .and(path_regex(r"^/_matrix/client/r0/keys/upload"))
+            .respond_with(move |request: &Request| {
+                let body: serde_json::Value = request.body_json().unwrap();
+                uploads_sender.send(body).unwrap();
+
+                ResponseTemplate::new(200).set_body_json(json!({
+                    "one_time_key_counts": {
+                        "signed_curve25519": 50
+                    }
+                }))
+            })
+            .mount(&server)
+            .await;
+
+        // The server doesn't have a fallback key for this device, so one is created
+        // and uploaded.
+        Mock::given(method("GET"))
+            .and(path_regex(r"^/_matrix/client/r0/sync"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "next_batch": "s1",
+                "device_one_time_keys_count": {
+                    "signed_curve25519": 50
+                },
+                "device_unused_fallback_key_types": []
+            })))
+            .mount(&server)
+            .await;
+
+        client.sync_once(Default::default()).await.unwrap();
+
+        let has_fallback_key = |body: &serde_json::Value| {
+            body.get("fallback_keys")
+                .and_then(|keys| keys.as_object())
+                .is_some_and(|keys| !keys.is_empty())
+        };
+
+        let mut uploaded_fallback_key = false;
+        while let Ok(body) = uploads.try_recv() {
+            uploaded_fallback_key |= has_fallback_key(&body);
+        }
+        assert!(uploaded_fallback_key, "The fallback key should be uploaded after the sync");
+        assert_eq!(client.encryption().key_health().get().fallback_key_age, Some(Duration::ZERO));
+
+        // Once the fallback key is too old, the background task checks the key counts
+        // and uploads a new fallback key.
+        clock.advance(MAX_FALLBACK_KEY_AGE);
+
+        let check = uploads.recv().await.unwrap();
+        assert!(!has_fallback_key(&check), "The key counts are checked with an empty upload");
+
+        let upload = uploads.recv().await.unwrap();
+        assert!(has_fallback_key(&upload), "A new fallback key should be uploaded");
+
+        let olm_machine = client.olm_machine().await;
+        let age = olm_machine.as_ref().unwrap().fallback_key_age(clock.now_ms()).await.unwrap();
+        assert_eq!(age, Some(Duration::ZERO));
+    }
+
+    #[async_test]
+    async fn test_trust_recomputation() {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+
+        let mut state = client.encryption().trust_recomputation_state();
+        assert_eq!(state.get(), TrustRecomputationState::Idle);
+
+        client.encryption().start_trust_recomputation();
+
+        // Only our own user is tracked, and none of its devices are verified.
+        let done = TrustRecomputationState::Done { verified_devices: 0 };
+        while state.get() != done {
+            state.next().await.unwrap();
+        }
+    }
+
     #[async_test]
     async fn test_get_dm_room_returns_the_room_we_have_with_this_user() {
         let server = MockServer::start().await;
@@ -1586,4 +2402,71 @@ mod tests {
         let after_taking_lock_second_time = client.olm_machine().await.as_ref().unwrap().clone();
         assert!(after_taking_lock_first_time.same_as(&after_taking_lock_second_time));
     }
+
+    #[async_test]
+    async fn test_import_crypto_identity_replaces_the_olm_machine() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("crypto-identity");
+
+        // The device is exported from another client.
+        let exported = logged_in_client(None).await;
+        exported.encryption().export_crypto_identity(path.clone(), "1234").await.unwrap();
+        let exported_keys = exported.olm_machine().await.as_ref().unwrap().identity_keys();
+
+        // The new client for the same device already has an account of its own, which
+        // wasn't shared yet.
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+        let own_keys = client.olm_machine().await.as_ref().unwrap().identity_keys();
+        assert_ne!(own_keys.curve25519, exported_keys.curve25519);
+
+        client.encryption().import_crypto_identity(path.clone(), "1234").await.unwrap();
+
+        let machine = client.olm_machine().await;
+        let keys = machine.as_ref().unwrap().identity_keys();
+        assert_eq!(keys.curve25519, exported_keys.curve25519);
+        assert_eq!(keys.ed25519, exported_keys.ed25519);
+        drop(machine);
+
+        // Once the device keys of the account are uploaded, it can't be replaced
+        // anymore.
+        Mock::given(method("POST"))
+            .and(path_regex(r"^/_matrix/client/r0/keys/upload"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "one_time_key_counts": {
+                    "signed_curve25519": 50
+                }
+            })))
+            .mount(&server)
+            .await;
+        client.send_outgoing_requests().await.unwrap();
+
+        let other_path = dir.path().join("other-crypto-identity");
+        let other = logged_in_client(None).await;
+        other.encryption().export_crypto_identity(other_path.clone(), "1234").await.unwrap();
+
+        assert_matches!(
+            client.encryption().import_crypto_identity(other_path, "1234").await,
+            Err(Error::OlmError(OlmError::Store(CryptoStoreError::AccountAlreadyShared)))
+        );
+        let keys = client.olm_machine().await.as_ref().unwrap().identity_keys();
+        assert_eq!(keys.curve25519, exported_keys.curve25519);
+    }
+
+    #[test]
+    fn backup_upload_strategy_delay() {
+        let strategy = BackupUploadStrategy::new()
+            .delay_between_batches(Duration::from_secs(1))
+            .jitter(Duration::from_millis(500));
+
+        for _ in 0..10 {
+            let delay = strategy.next_delay();
+            assert!(delay >= Duration::from_secs(1));
+            assert!(delay <= Duration::from_millis(1500));
+        }
+
+        let strategy = BackupUploadStrategy::new().batch_size(0);
+        assert_eq!(strategy.batch_size, 1);
+        assert_eq!(strategy.next_delay(), Duration::from_millis(100));
+    }
 }
@@ -0,0 +1,87 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Pseudonymous identities in rooms, as defined in [MSC4014].
+//!
+//! This is experimental: it only allows clients to get their pseudoID in a
+//! room and to check the pseudoIDs of the senders of events.
+//!
+//! [MSC4014]: https://github.com/matrix-org/matrix-spec-proposals/pull/4014
+
+pub use matrix_sdk_base::crypto::pseudo_ids::{MxidMapping, PseudoIdError};
+use matrix_sdk_base::{
+    crypto::vodozemac::Ed25519PublicKey, deserialized_responses::RawAnySyncOrStrippedState,
+};
+use ruma::{events::StateEventType, OwnedUserId, RoomId, ServerSigningKeyId};
+
+use crate::{Client, Error, Result, Room};
+
+/// The pseudoIDs manager of the client.
+///
+/// Get access to this manager with [`Encryption::pseudo_ids()`].
+///
+/// [`Encryption::pseudo_ids()`]: crate::encryption::Encryption::pseudo_ids
+#[derive(Debug)]
+pub struct PseudoIds {
+    pub(super) client: Client,
+}
+
+impl PseudoIds {
+    /// Get the public key of our pseudoID in the given room.
+    ///
+    /// The key is created the first time this is called for a room, and
+    /// persisted in the crypto store.
+    pub async fn public_key_for_room(&self, room_id: &RoomId) -> Result<Ed25519PublicKey> {
+        let olm_machine = self.client.olm_machine().await;
+        let olm_machine = olm_machine.as_ref().ok_or(Error::NoOlmMachine)?;
+
+        Ok(olm_machine.pseudo_ids().public_key_for_room(room_id).await?)
+    }
+
+    /// Check that the pseudoID `sender` of an event received in `room` maps
+    /// to a user, according to its `m.room.member` event in the local state
+    /// of the room.
+    ///
+    /// # Arguments
+    ///
+    /// * `room` - The room the event was received in.
+    ///
+    /// * `sender` - The `sender` of the event.
+    ///
+    /// * `server_key_id` - The ID of the signing key of the homeserver of the
+    ///   user, that signed the mapping.
+    ///
+    /// * `server_key` - The signing key of the homeserver of the user.
+    ///
+    /// Returns the user that sent the event.
+    pub async fn verify_sender(
+        &self,
+        room: &Room,
+        sender: &str,
+        server_key_id: &ServerSigningKeyId,
+        server_key: Ed25519PublicKey,
+    ) -> Result<OwnedUserId> {
+        let member_event = room
+            .get_state_event(StateEventType::RoomMember, sender)
+            .await?
+            .ok_or(PseudoIdError::MissingMapping)?;
+        let mapping = match &member_event {
+            RawAnySyncOrStrippedState::Sync(event) => MxidMapping::from_member_event(event)?,
+            RawAnySyncOrStrippedState::Stripped(event) => MxidMapping::from_member_event(event)?,
+        }
+        .ok_or(PseudoIdError::MissingMapping)?;
+
+        Ok(mapping.verify_sender(sender, server_key_id, server_key)?)
+    }
+}
@@ -258,7 +258,7 @@ impl Recovery {
     /// This method will do the following steps:
     ///
     /// 1. Disable the uploading of room keys to a currently active backup.
-    /// 2. Delete the currently active backup.
+    /// 2. Delete the currently active backup, if this client knows about it.
     /// 3. Set the `m.secret_storage.default_key` global account data event to
     ///    an empty JSON content.
     /// 4. Set a global account data event so clients won't attempt to
@@ -282,7 +282,7 @@ impl Recovery {
     /// ```
     #[instrument(skip_all)]
     pub async fn disable(&self) -> Result<()> {
-        self.client.encryption().backups().disable().await?;
+        self.client.encryption().backups().disable_and_delete().await?;
         // Why oh why, can't we delete account data events?
         self.client.account().set_account_data(SecretStorageDisabledContent {}).await?;
         self.client.account().set_account_data(BackupDisabledContent { disabled: true }).await?;
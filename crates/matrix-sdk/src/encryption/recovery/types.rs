@@ -47,6 +47,10 @@ pub enum RecoveryError {
     /// Error in the secret storage subsystem.
     #[error(transparent)]
     SecretStorage(#[from] crate::encryption::secret_storage::SecretStorageError),
+
+    /// Error in the server-side key backups.
+    #[error(transparent)]
+    Backup(#[from] crate::encryption::backups::BackupError),
 }
 
 /// Enum describing the states the [`Recovery::enable()`] method can be in.
@@ -62,27 +62,35 @@
 
 use std::string::FromUtf8Error;
 
+use futures_core::Stream;
 use matrix_sdk_base::crypto::{
     secret_storage::{DecodeError, MacError, SecretStorageKey},
     CryptoStoreError, SecretImportError,
 };
-use ruma::events::{
-    secret_storage::{
-        default_key::SecretStorageDefaultKeyEventContent, key::SecretStorageKeyEventContent,
+use ruma::{
+    events::{
+        secret_storage::{
+            default_key::SecretStorageDefaultKeyEventContent, key::SecretStorageKeyEventContent,
+        },
+        AnyGlobalAccountDataEvent, EventContentFromType, GlobalAccountDataEventType,
     },
-    EventContentFromType, GlobalAccountDataEventType,
+    serde::Raw,
 };
 use serde_json::value::to_raw_value;
 use thiserror::Error;
+use tracing::{error, instrument};
+use zeroize::Zeroizing;
 
 use super::identities::ManualVerifyError;
 use crate::Client;
 
 mod futures;
 mod secret_store;
+mod status;
 
 pub use futures::CreateStore;
 pub use secret_store::SecretStore;
+pub use status::{SecretStorageKeyInfo, SecretStorageStatus};
 
 /// Convenicence type alias for the secret-storage specific results.
 pub type Result<T, E = SecretStorageError> = std::result::Result<T, E>;
@@ -186,38 +194,108 @@ impl SecretStorage {
     /// # anyhow::Ok(()) };
     /// ```
     pub async fn open_secret_store(&self, secret_storage_key: &str) -> Result<SecretStore> {
+        let secret_key_content = self.default_key_content().await?;
+        let key = SecretStorageKey::from_account_data(secret_storage_key, secret_key_content)?;
+
+        Ok(SecretStore { client: self.client.to_owned(), key })
+    }
+
+    /// Check whether the given key or passphrase opens the default secret
+    /// storage key, without opening the [`SecretStore`].
+    ///
+    /// Only the MAC of the key info is checked, no secret is decrypted or
+    /// imported, which allows to validate the input of the user before
+    /// restoring the secrets with
+    /// [`SecretStorage::open_secret_store()`].
+    ///
+    /// Returns `Ok(false)` if the input is neither the Base58 encoded secret
+    /// storage key nor its passphrase, and an error if the info about the key
+    /// in the account data is invalid.
+    ///
+    /// The key derivation from a passphrase is slow on purpose, so it runs on
+    /// a blocking thread of the Tokio runtime, except on WASM.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # async {
+    /// # let homeserver = Url::parse("http://example.com")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// let secret_storage = client.encryption().secret_storage();
+    ///
+    /// if secret_storage.verify_key("It's a secret to everybody").await? {
+    ///     let secret_store = secret_storage
+    ///         .open_secret_store("It's a secret to everybody")
+    ///         .await?;
+    ///     secret_store.import_secrets().await?;
+    /// } else {
+    ///     println!("This isn't your recovery key");
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub async fn verify_key(&self, key_or_passphrase: &str) -> Result<bool> {
+        let secret_key_content = self.default_key_content().await?;
+
+        // Checking a Base58 encoded key is cheap, while deriving a key from a
+        // passphrase is slow on purpose, so try the former first.
+        let mut without_passphrase = secret_key_content.clone();
+        without_passphrase.passphrase = None;
+
+        match SecretStorageKey::from_account_data(key_or_passphrase, without_passphrase) {
+            Ok(_) => return Ok(true),
+            Err(e) if !is_wrong_key(&e) => return Err(e.into()),
+            Err(_) => {}
+        }
+
+        if secret_key_content.passphrase.is_none() {
+            return Ok(false);
+        }
+
+        let input = Zeroizing::new(key_or_passphrase.to_owned());
+        let derive = move || SecretStorageKey::from_account_data(&input, secret_key_content);
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let result = tokio::task::spawn_blocking(derive).await.expect("Task join error");
+        #[cfg(target_arch = "wasm32")]
+        let result = derive();
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(e) if is_wrong_key(&e) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Fetch the info about the default secret storage key from the account
+    /// data of the user.
+    async fn default_key_content(&self) -> Result<SecretStorageKeyEventContent> {
         let maybe_default_key_id = self
             .client
             .account()
             .fetch_account_data(GlobalAccountDataEventType::SecretStorageDefaultKey)
             .await?;
 
-        if let Some(default_key_id) = maybe_default_key_id {
-            let default_key_id =
-                default_key_id.deserialize_as::<SecretStorageDefaultKeyEventContent>()?;
+        let Some(default_key_id) = maybe_default_key_id else {
+            return Err(SecretStorageError::MissingKeyInfo { key_id: None });
+        };
 
-            let event_type =
-                GlobalAccountDataEventType::SecretStorageKey(default_key_id.key_id.to_owned());
-            let secret_key =
-                self.client.account().fetch_account_data(event_type.to_owned()).await?;
+        let default_key_id =
+            default_key_id.deserialize_as::<SecretStorageDefaultKeyEventContent>()?;
 
-            if let Some(secret_key_content) = secret_key {
-                let event_type = event_type.to_string();
-                let secret_key_content = to_raw_value(&secret_key_content)?;
+        let event_type =
+            GlobalAccountDataEventType::SecretStorageKey(default_key_id.key_id.to_owned());
+        let secret_key = self.client.account().fetch_account_data(event_type.to_owned()).await?;
 
-                let secret_key_content =
-                    SecretStorageKeyEventContent::from_parts(&event_type, &secret_key_content)?;
+        let Some(secret_key_content) = secret_key else {
+            return Err(SecretStorageError::MissingKeyInfo { key_id: Some(default_key_id.key_id) });
+        };
 
-                let key =
-                    SecretStorageKey::from_account_data(secret_storage_key, secret_key_content)?;
+        let event_type = event_type.to_string();
+        let secret_key_content = to_raw_value(&secret_key_content)?;
 
-                Ok(SecretStore { client: self.client.to_owned(), key })
-            } else {
-                Err(SecretStorageError::MissingKeyInfo { key_id: Some(default_key_id.key_id) })
-            }
-        } else {
-            Err(SecretStorageError::MissingKeyInfo { key_id: None })
-        }
+        Ok(SecretStorageKeyEventContent::from_parts(&event_type, &secret_key_content)?)
     }
 
     /// Create a new [`SecretStore`].
@@ -285,4 +363,99 @@ impl SecretStorage {
             Ok(false)
         }
     }
+
+    /// Get the current [`SecretStorageStatus`] of this user.
+    ///
+    /// Unlike [`SecretStorage::is_enabled()`], this doesn't make any request,
+    /// the status is kept up to date from the account data received during
+    /// sync.
+    pub fn status(&self) -> SecretStorageStatus {
+        self.client.inner.secret_storage_status.get()
+    }
+
+    /// Get info about the default secret storage key, if secret storage is
+    /// set up.
+    ///
+    /// This is a shortcut for the [`SecretStorageStatus::default_key`] of
+    /// [`SecretStorage::status()`].
+    pub fn default_key_info(&self) -> Option<SecretStorageKeyInfo> {
+        self.status().default_key
+    }
+
+    /// Get a stream of updates to the [`SecretStorageStatus`].
+    ///
+    /// This method will send out the current status as the first update.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::Client;
+    /// # use url::Url;
+    /// # async {
+    /// # let homeserver = Url::parse("http://example.com")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// use futures_util::StreamExt;
+    ///
+    /// let mut status_stream =
+    ///     client.encryption().secret_storage().status_stream();
+    ///
+    /// while let Some(status) = status_stream.next().await {
+    ///     if status.is_enabled() {
+    ///         println!("Change recovery key");
+    ///     } else {
+    ///         println!("Set up recovery");
+    ///     }
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub fn status_stream(&self) -> impl Stream<Item = SecretStorageStatus> {
+        self.client.inner.secret_storage_status.subscribe_reset()
+    }
+
+    /// Load the initial status and listen to account data changes to keep it
+    /// up to date.
+    pub(crate) async fn setup(&self) -> crate::Result<()> {
+        self.update_status().await?;
+        self.client.add_event_handler(Self::account_data_event_handler);
+
+        Ok(())
+    }
+
+    async fn update_status(&self) -> crate::Result<()> {
+        let status = SecretStorageStatus::load(&self.client).await?;
+        self.client.inner.secret_storage_status.set_if_not_eq(status);
+
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn account_data_event_handler(event: Raw<AnyGlobalAccountDataEvent>, client: Client) {
+        let Ok(Some(event_type)) = event.get_field::<String>("type") else { return };
+
+        if is_secret_storage_event_type(&event_type) {
+            if let Err(e) = client.encryption().secret_storage().update_status().await {
+                error!("Couldn't update the secret storage status: {e:?}");
+            }
+        }
+    }
+}
+
+/// Whether the given account data event type can change the
+/// [`SecretStorageStatus`].
+fn is_secret_storage_event_type(event_type: &str) -> bool {
+    event_type.starts_with("m.secret_storage.")
+        || status::WELL_KNOWN_SECRETS.iter().any(|name| name.as_str() == event_type)
+}
+
+/// Whether the error means that the input isn't the secret storage key or its
+/// passphrase, rather than that the info about the key is invalid.
+fn is_wrong_key(error: &DecodeError) -> bool {
+    matches!(
+        error,
+        DecodeError::Prefix(..)
+            | DecodeError::Parity(..)
+            | DecodeError::Base58(_)
+            | DecodeError::KeyLength(..)
+            | DecodeError::Mac(_)
+    )
 }
@@ -303,7 +303,7 @@ impl SecretStore {
 
             secret.zeroize();
 
-            Ok(ret.map(|_| ())?)
+            Ok(ret.map(|_| ()).map_err(crate::Error::from)?)
         } else {
             info!("No backup recovery key found.");
 
@@ -414,6 +414,13 @@ impl SecretStore {
             }
         }
 
+        if status.has_user_signing {
+            // The users we verified with the user-signing key are trusted now, and so are
+            // their devices. There might be a lot of them, so recompute their trust in the
+            // background.
+            self.client.encryption().start_trust_recomputation();
+        }
+
         self.maybe_enable_backups().await?;
 
         Ok(())
@@ -0,0 +1,143 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+
+use ruma::events::{
+    secret::request::SecretName, secret_storage::default_key::SecretStorageDefaultKeyEventContent,
+    GlobalAccountDataEventType,
+};
+use serde::Deserialize;
+use serde_json::value::RawValue as RawJsonValue;
+
+use crate::{Client, Result};
+
+/// The secrets whose presence in the secret store is reported by
+/// [`SecretStorageStatus::stored_secrets`].
+pub(super) const WELL_KNOWN_SECRETS: [SecretName; 4] = [
+    SecretName::CrossSigningMasterKey,
+    SecretName::CrossSigningSelfSigningKey,
+    SecretName::CrossSigningUserSigningKey,
+    SecretName::RecoveryKey,
+];
+
+/// A snapshot of the secret storage setup of the user, as found in their
+/// account data.
+///
+/// To get this, use [`SecretStorage::status()`].
+///
+/// [`SecretStorage::status()`]: super::SecretStorage::status
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SecretStorageStatus {
+    /// Info about the default secret storage key, `None` if secret storage
+    /// isn't set up.
+    pub default_key: Option<SecretStorageKeyInfo>,
+
+    /// The well-known secrets that are stored encrypted with the default key.
+    ///
+    /// Those are the private cross-signing keys and the backup recovery key.
+    pub stored_secrets: Vec<SecretName>,
+}
+
+impl SecretStorageStatus {
+    /// Is secret storage set up for this user?
+    pub fn is_enabled(&self) -> bool {
+        self.default_key.is_some()
+    }
+
+    /// Is the given secret stored encrypted with the default key?
+    pub fn is_secret_stored(&self, secret_name: &SecretName) -> bool {
+        self.stored_secrets.contains(secret_name)
+    }
+
+    /// Compute the status from the account data of the local store.
+    pub(super) async fn load(client: &Client) -> Result<Self> {
+        let account = client.account();
+
+        let Some(default_key) = account
+            .account_data::<SecretStorageDefaultKeyEventContent>()
+            .await?
+            .and_then(|raw| raw.deserialize().ok())
+        else {
+            // Since we can't delete account data events, an event that we can't
+            // deserialize means that secret storage was disabled.
+            return Ok(Self::default());
+        };
+
+        let key_id = default_key.key_id;
+
+        let Some(key) = account
+            .account_data_raw(GlobalAccountDataEventType::SecretStorageKey(key_id.clone()))
+            .await?
+            .and_then(|raw| raw.deserialize_as::<KeyContent>().ok())
+        else {
+            // The default key points to a key we don't know about, there's
+            // nothing we can open.
+            return Ok(Self::default());
+        };
+
+        let mut stored_secrets = Vec::new();
+
+        for secret_name in WELL_KNOWN_SECRETS {
+            let event_type = GlobalAccountDataEventType::from(secret_name.as_str());
+            let is_stored = account
+                .account_data_raw(event_type)
+                .await?
+                .and_then(|raw| raw.deserialize_as::<SecretContent>().ok())
+                .is_some_and(|content| content.encrypted.contains_key(&key_id));
+
+            if is_stored {
+                stored_secrets.push(secret_name);
+            }
+        }
+
+        let default_key = SecretStorageKeyInfo {
+            key_id,
+            algorithm: key.algorithm,
+            has_passphrase: key.passphrase.is_some(),
+        };
+
+        Ok(Self { default_key: Some(default_key), stored_secrets })
+    }
+}
+
+/// Info about a secret storage key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SecretStorageKeyInfo {
+    /// The ID of the key.
+    pub key_id: String,
+
+    /// The algorithm used by the key, e.g. `m.secret_storage.v1.aes-hmac-sha2`.
+    pub algorithm: String,
+
+    /// Whether the key can be derived from a passphrase, in addition to being
+    /// entered as a recovery key.
+    pub has_passphrase: bool,
+}
+
+/// The parts of an `m.secret_storage.key.*` event we care about.
+///
+/// We don't use the Ruma type since we want to report keys with an unknown
+/// algorithm as well.
+#[derive(Deserialize)]
+struct KeyContent {
+    algorithm: String,
+    passphrase: Option<Box<RawJsonValue>>,
+}
+
+/// The parts of a secret event we care about.
+#[derive(Deserialize)]
+struct SecretContent {
+    encrypted: BTreeMap<String, Box<RawJsonValue>>,
+}
@@ -0,0 +1,189 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Recomputation of the trust of the devices of the tracked users, after the
+//! private cross-signing keys were imported.
+//!
+//! Once the user-signing key is known, the users that were verified with it
+//! become trusted, and so do their cross-signed devices. An account might
+//! track thousands of devices, so going over all of them is done in a
+//! background task, in chunks, yielding to the runtime between every chunk.
+//!
+//! The progress can be followed with
+//! [`Encryption::trust_recomputation_state()`]. When it reaches
+//! [`TrustRecomputationState::Done`], the verification states of the events
+//! that were already decrypted can be refreshed with
+//! [`Encryption::refreshed_verification_state()`].
+
+use std::sync::Arc;
+
+use eyeball::Subscriber;
+use matrix_sdk_base::{
+    crypto::UserIdentities,
+    deserialized_responses::{EncryptionInfo, VerificationLevel, VerificationState},
+};
+use ruma::OwnedUserId;
+use tracing::{debug, info, instrument};
+
+use super::Encryption;
+use crate::{client::tasks::TrustRecomputationTask, Error, Result};
+
+/// The number of users whose devices are processed before yielding to the
+/// runtime.
+pub(crate) const USERS_PER_CHUNK: usize = 50;
+
+/// The state of the recomputation of the trust of the devices of the tracked
+/// users.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum TrustRecomputationState {
+    /// No recomputation happened since the client was created.
+    #[default]
+    Idle,
+
+    /// The trust of the devices is being recomputed.
+    Running {
+        /// The number of users whose devices were processed already.
+        processed_users: usize,
+        /// The number of users to process.
+        total_users: usize,
+    },
+
+    /// The trust of the devices of all the tracked users was recomputed.
+    Done {
+        /// The number of devices that are verified.
+        verified_devices: usize,
+    },
+}
+
+impl Encryption {
+    /// Get a subscriber to the state of the recomputation of the trust of
+    /// the devices of the tracked users.
+    ///
+    /// The recomputation starts after the private cross-signing keys were
+    /// imported from secret storage.
+    pub fn trust_recomputation_state(&self) -> Subscriber<TrustRecomputationState> {
+        self.client.inner.trust_recomputation_state.subscribe()
+    }
+
+    /// Start recomputing the trust of the devices of the tracked users in the
+    /// background.
+    ///
+    /// A recomputation that is still running is cancelled.
+    pub(crate) fn start_trust_recomputation(&self) {
+        let task = TrustRecomputationTask::new(Arc::downgrade(&self.client.inner));
+        self.client.inner.tasks.lock().unwrap().recompute_trust = Some(task);
+    }
+
+    /// Get the list of users whose devices need to be processed.
+    pub(crate) async fn users_for_trust_recomputation(&self) -> Result<Vec<OwnedUserId>> {
+        let olm_machine = self.client.olm_machine().await;
+        let olm_machine = olm_machine.as_ref().ok_or(Error::NoOlmMachine)?;
+
+        let mut users: Vec<_> = olm_machine.tracked_users().await?.into_iter().collect();
+        users.sort();
+
+        self.client
+            .inner
+            .trust_recomputation_state
+            .set(TrustRecomputationState::Running { processed_users: 0, total_users: users.len() });
+
+        Ok(users)
+    }
+
+    /// Recompute the trust of the devices of the given chunk of users, and
+    /// return the number of verified devices.
+    ///
+    /// The identities that are now verified by our user-signing key are
+    /// remembered as verified, so a later change of their cross-signing keys
+    /// is reported as a verification violation.
+    #[instrument(skip_all, fields(num_users = users.len()))]
+    pub(crate) async fn recompute_trust_of_users(&self, users: &[OwnedUserId]) -> Result<usize> {
+        let olm_machine = self.client.olm_machine().await;
+        let olm_machine = olm_machine.as_ref().ok_or(Error::NoOlmMachine)?;
+
+        let mut verified_devices = 0;
+        let mut newly_verified_users = 0;
+
+        for user_id in users {
+            if let Some(UserIdentities::Other(identity)) =
+                olm_machine.get_identity(user_id, None).await?
+            {
+                if identity.remember_verification().await? {
+                    newly_verified_users += 1;
+                }
+            }
+
+            let devices = olm_machine.get_user_devices(user_id, None).await?;
+            verified_devices += devices.devices().filter(|device| device.is_verified()).count();
+        }
+
+        debug!(verified_devices, newly_verified_users, "Recomputed the trust of a chunk of users");
+
+        Ok(verified_devices)
+    }
+
+    /// Record the progress of the recomputation after a chunk was processed.
+    pub(crate) fn report_trust_recomputation_progress(&self, processed_users: usize) {
+        self.client.inner.trust_recomputation_state.update(|state| {
+            if let TrustRecomputationState::Running { processed_users: processed, .. } = state {
+                *processed = processed_users;
+            }
+        });
+    }
+
+    /// Mark the recomputation as done.
+    pub(crate) fn finish_trust_recomputation(&self, verified_devices: usize) {
+        info!(verified_devices, "Done recomputing the trust of the devices");
+        self.client
+            .inner
+            .trust_recomputation_state
+            .set(TrustRecomputationState::Done { verified_devices });
+    }
+
+    /// Compute the verification state of an event that was already
+    /// decrypted, with the current trust of the device that sent it.
+    ///
+    /// Returns `None` if the event can't be linked to a device, in which case
+    /// its verification state doesn't depend on the trust of the devices.
+    pub async fn refreshed_verification_state(
+        &self,
+        encryption_info: &EncryptionInfo,
+    ) -> Result<Option<VerificationState>> {
+        if matches!(
+            encryption_info.verification_state,
+            VerificationState::Unverified(VerificationLevel::None(_))
+        ) {
+            return Ok(None);
+        }
+
+        let Some(device_id) = &encryption_info.sender_device else {
+            return Ok(None);
+        };
+
+        let Some(device) = self.get_device(&encryption_info.sender, device_id).await? else {
+            return Ok(None);
+        };
+
+        // Like when decrypting, only the cross-signing trust is considered.
+        let state = if !device.inner.is_cross_signed_by_owner() {
+            VerificationState::Unverified(VerificationLevel::UnsignedDevice)
+        } else if device.inner.is_device_owner_verified() {
+            VerificationState::Verified
+        } else {
+            VerificationState::Unverified(VerificationLevel::UnverifiedIdentity)
+        };
+
+        Ok(Some(state))
+    }
+}
@@ -14,14 +14,15 @@
 
 //! Error conditions.
 
-use std::{io::Error as IoError, sync::Arc};
+use std::{io::Error as IoError, sync::Arc, time::Duration};
 
 use as_variant::as_variant;
 #[cfg(feature = "qrcode")]
 use matrix_sdk_base::crypto::ScanError;
 #[cfg(feature = "e2e-encryption")]
 use matrix_sdk_base::crypto::{
-    CryptoStoreError, DecryptorError, KeyExportError, MegolmError, OlmError,
+    dehydrated_devices::DehydrationError, pseudo_ids::PseudoIdError, CryptoStoreError,
+    DecryptorError, KeyExportError, MegolmError, OlmError,
 };
 use matrix_sdk_base::{Error as SdkBaseError, RoomState, StoreError};
 use reqwest::Error as ReqwestError;
@@ -153,6 +154,51 @@ impl HttpError {
     pub fn as_uiaa_response(&self) -> Option<&UiaaInfo> {
         self.as_ruma_api_error().and_then(as_variant!(RumaApiError::Uiaa))
     }
+
+    /// Whether the request that failed with this error can be retried.
+    pub fn retry_kind(&self) -> RetryKind {
+        match self {
+            Self::Reqwest(_) => RetryKind::NetworkFailure,
+            Self::Api(FromHttpResponseError::Server(api_error)) => {
+                if let Some(ErrorKind::LimitExceeded { retry_after_ms }) =
+                    self.client_api_error_kind()
+                {
+                    return RetryKind::Transient { retry_after: *retry_after_ms };
+                }
+
+                let status_code = match api_error {
+                    RumaApiError::ClientApi(e) => Some(e.status_code),
+                    RumaApiError::Uiaa(_) => None,
+                    RumaApiError::Other(e) => Some(e.status_code),
+                };
+
+                if status_code.is_some_and(|status_code| status_code.is_server_error()) {
+                    RetryKind::Transient { retry_after: None }
+                } else {
+                    RetryKind::Permanent
+                }
+            }
+            _ => RetryKind::Permanent,
+        }
+    }
+}
+
+/// Whether a failed request can be retried, see [`HttpError::retry_kind()`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RetryKind {
+    /// The homeserver couldn't be reached, the request can be retried once
+    /// the network is back.
+    NetworkFailure,
+
+    /// The homeserver couldn't handle the request right now, for example
+    /// because of rate limiting, the request can be retried later.
+    Transient {
+        /// The time the homeserver asked to wait for before retrying, if any.
+        retry_after: Option<Duration>,
+    },
+
+    /// The request failed for good, retrying it would fail the same way.
+    Permanent,
 }
 
 /// Internal representation of errors.
@@ -215,6 +261,26 @@ pub enum Error {
     #[error(transparent)]
     DecryptorError(#[from] DecryptorError),
 
+    /// An error occurred while creating a dehydrated device.
+    #[cfg(feature = "e2e-encryption")]
+    #[error(transparent)]
+    DehydrationError(#[from] DehydrationError),
+
+    /// An error occurred while decrypting an encrypted export.
+    #[cfg(feature = "e2e-encryption")]
+    #[error(transparent)]
+    KeyExport(#[from] KeyExportError),
+
+    /// The pseudoID of a sender couldn't be verified.
+    #[cfg(feature = "e2e-encryption")]
+    #[error(transparent)]
+    PseudoId(#[from] PseudoIdError),
+
+    /// An error specific to the server-side key backups occurred.
+    #[cfg(feature = "e2e-encryption")]
+    #[error(transparent)]
+    Backup(#[from] crate::encryption::backups::BackupError),
+
     /// An error occurred in the state store.
     #[error(transparent)]
     StateStore(#[from] StoreError),
@@ -261,6 +327,53 @@ pub enum Error {
     #[error("session callbacks have been set multiple times")]
     MultipleSessionCallbacks,
 
+    /// The operation isn't available to guest accounts.
+    #[error("this operation is not available to guest accounts")]
+    GuestAccessForbidden,
+
+    /// An error occurred with the content scanner.
+    #[error(transparent)]
+    ContentScanner(#[from] crate::content_scanner::ContentScannerError),
+
+    /// The homeserver doesn't allow to leave its server notices room.
+    #[error("the server notices room can't be left")]
+    CannotLeaveServerNoticesRoom,
+
+    /// The user is the last admin of a room that has other members, so
+    /// leaving it would leave nobody able to administrate it.
+    #[error("the user is the last admin of the room")]
+    LastAdminOfRoom,
+
+    /// A batch of state events was rejected before sending any of them.
+    #[error(transparent)]
+    InvalidStateBatch(#[from] crate::room::StateBatchValidationError),
+
+    /// A change of the pinned events of a room failed.
+    #[error(transparent)]
+    PinnedEvents(#[from] crate::room::PinnedEventsError),
+
+    /// A room upgrade was refused before asking the homeserver.
+    #[error(transparent)]
+    RoomUpgrade(#[from] crate::room::RoomUpgradeError),
+
+    /// A change of the widgets of a room was refused before sending any
+    /// request.
+    #[cfg(feature = "experimental-widgets")]
+    #[error(transparent)]
+    RoomWidget(#[from] crate::room::RoomWidgetError),
+
+    /// The homeserver confirmed that the session is gone, see
+    /// [`SessionStatus::SessionVerifiedGone`](crate::SessionStatus::SessionVerifiedGone).
+    #[error("the session is gone, the user needs to log in again")]
+    SessionGone,
+
+    /// Another call to [`Client::sync_once()`](crate::Client::sync_once) is
+    /// still running, and the
+    /// [`ConcurrentSyncPolicy`](crate::config::ConcurrentSyncPolicy) of the
+    /// sync settings asked not to wait for it.
+    #[error("another sync is already running")]
+    AlreadySyncing,
+
     /// An error occurred interacting with the OpenID Connect API.
     #[cfg(feature = "experimental-oidc")]
     #[error(transparent)]
@@ -316,6 +429,17 @@ impl Error {
     pub fn as_uiaa_response(&self) -> Option<&UiaaInfo> {
         self.as_ruma_api_error().and_then(as_variant!(RumaApiError::Uiaa))
     }
+
+    /// Whether the request that failed with this error can be retried.
+    ///
+    /// Errors that aren't caused by an HTTP request are
+    /// [permanent](RetryKind::Permanent).
+    pub fn retry_kind(&self) -> RetryKind {
+        match self {
+            Self::Http(e) => e.retry_kind(),
+            _ => RetryKind::Permanent,
+        }
+    }
 }
 
 /// Error for the room key importing functionality.
@@ -371,6 +495,7 @@ impl From<SdkBaseError> for Error {
     fn from(e: SdkBaseError) -> Self {
         match e {
             SdkBaseError::StateStore(e) => Self::StateStore(e),
+            SdkBaseError::NotLoggedIn => Self::AuthenticationRequired,
             #[cfg(feature = "e2e-encryption")]
             SdkBaseError::CryptoStore(e) => Self::CryptoStoreError(e),
             #[cfg(feature = "e2e-encryption")]
@@ -0,0 +1,356 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Features of the client that can be enabled or disabled at runtime, like
+//! sending read receipts or typing notifications.
+//!
+//! The features are observed by the parts of the client they affect: for
+//! example when read receipts are disabled, the public read receipts are sent
+//! as private read receipts instead. The features that have a matching setting
+//! in the account data are persisted there, so they are shared with the other
+//! clients of the user, and they are updated when the setting changes during a
+//! sync. The other features are persisted in the state store.
+//!
+//! The features are loaded from the stores when a session is restored or
+//! logged in, before anything is sent.
+//!
+//! A feature can also be suspended temporarily, for this client only, with
+//! [`ClientFeatures::set_suspended()`]: it is then disabled without changing
+//! its setting.
+
+use std::sync::Mutex as StdMutex;
+
+use eyeball::{SharedObservable, Subscriber};
+use ruma::{
+    events::{AnyGlobalAccountDataEvent, GlobalAccountDataEventType},
+    serde::Raw,
+};
+use serde::Deserialize;
+use serde_json::{Map as JsonMap, Value as JsonValue};
+use tokio::sync::Mutex;
+use tracing::{debug, instrument, warn};
+
+use crate::{Client, Result};
+
+/// The account data event type of the settings of Element, shared by most
+/// clients for the settings that aren't in the spec.
+const WEB_SETTINGS_EVENT_TYPE: &str = "im.vector.web.settings";
+
+/// The account data event type of the setting of the URL previews.
+const PREVIEW_URLS_EVENT_TYPE: &str = "org.matrix.preview_urls";
+
+/// The key of the presence feature in the custom values of the state store.
+const PRESENCE_KEY: &[u8] = b"matrix-sdk.features.presence";
+
+/// A feature of the client that can be toggled at runtime.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ClientFeature {
+    /// Sending public read receipts.
+    ///
+    /// When disabled, the read receipts are sent as private read receipts.
+    /// Persisted as `sendReadReceipts` in the `im.vector.web.settings`
+    /// account data.
+    ReadReceipts,
+
+    /// Sending typing notifications.
+    ///
+    /// When disabled, [`Room::typing_notice()`](crate::Room::typing_notice)
+    /// doesn't notify the other members that the user is typing. Persisted as
+    /// `sendTypingNotifications` in the `im.vector.web.settings` account data.
+    TypingNotifications,
+
+    /// Sharing the presence of the user.
+    ///
+    /// When disabled, the user appears offline when syncing. This feature is
+    /// only local to this client, it is persisted in the state store.
+    Presence,
+
+    /// Showing previews of the URLs in messages.
+    ///
+    /// The SDK doesn't generate URL previews itself, this is meant to be
+    /// checked by the applications that do. Persisted in the
+    /// `org.matrix.preview_urls` account data. It is suspended while the sync
+    /// service of `matrix-sdk-ui` uses its low data bandwidth profile.
+    UrlPreviews,
+}
+
+/// Whether each [`ClientFeature`] is enabled.
+///
+/// All the features are enabled by default.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientFeatureFlags {
+    /// Whether public read receipts are sent.
+    pub read_receipts: bool,
+
+    /// Whether typing notifications are sent.
+    pub typing_notifications: bool,
+
+    /// Whether the presence of the user is shared.
+    pub presence: bool,
+
+    /// Whether URL previews are shown.
+    pub url_previews: bool,
+}
+
+impl Default for ClientFeatureFlags {
+    fn default() -> Self {
+        Self { read_receipts: true, typing_notifications: true, presence: true, url_previews: true }
+    }
+}
+
+impl ClientFeatureFlags {
+    /// Whether the given feature is enabled.
+    pub fn is_enabled(&self, feature: ClientFeature) -> bool {
+        match feature {
+            ClientFeature::ReadReceipts => self.read_receipts,
+            ClientFeature::TypingNotifications => self.typing_notifications,
+            ClientFeature::Presence => self.presence,
+            ClientFeature::UrlPreviews => self.url_previews,
+        }
+    }
+
+    fn set_enabled(&mut self, feature: ClientFeature, enabled: bool) {
+        let flag = match feature {
+            ClientFeature::ReadReceipts => &mut self.read_receipts,
+            ClientFeature::TypingNotifications => &mut self.typing_notifications,
+            ClientFeature::Presence => &mut self.presence,
+            ClientFeature::UrlPreviews => &mut self.url_previews,
+        };
+        *flag = enabled;
+    }
+
+    /// Apply the settings of the given account data event, if it holds some.
+    fn apply_account_data(&mut self, event_type: &str, content: &JsonMap<String, JsonValue>) {
+        let get_bool = |key: &str| content.get(key).and_then(JsonValue::as_bool);
+
+        match event_type {
+            WEB_SETTINGS_EVENT_TYPE => {
+                if let Some(enabled) = get_bool("sendReadReceipts") {
+                    self.read_receipts = enabled;
+                }
+                if let Some(enabled) = get_bool("sendTypingNotifications") {
+                    self.typing_notifications = enabled;
+                }
+            }
+            PREVIEW_URLS_EVENT_TYPE => {
+                if let Some(disable) = get_bool("disable") {
+                    self.url_previews = !disable;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The state of the client features, shared by all the handles of a client.
+#[derive(Default)]
+pub(crate) struct ClientFeaturesState {
+    /// The effective state of the features: their settings, with the
+    /// suspended features disabled.
+    flags: SharedObservable<ClientFeatureFlags>,
+    /// The settings of the features and the suspended features.
+    settings: StdMutex<FeatureSettings>,
+    /// Serializes the updates of the account data, which are read, modified
+    /// and uploaded.
+    update_lock: Mutex<()>,
+}
+
+#[derive(Default)]
+struct FeatureSettings {
+    /// The settings of the features, as persisted.
+    flags: ClientFeatureFlags,
+    /// The features suspended with [`ClientFeatures::set_suspended()`].
+    suspended: Vec<ClientFeature>,
+}
+
+impl ClientFeaturesState {
+    /// Get the settings of the features, ignoring the suspended features.
+    fn settings(&self) -> ClientFeatureFlags {
+        self.settings.lock().unwrap().flags
+    }
+
+    /// Update the settings of the features and publish their effective state.
+    fn update(&self, f: impl FnOnce(&mut FeatureSettings)) {
+        let mut settings = self.settings.lock().unwrap();
+        f(&mut settings);
+
+        let mut flags = settings.flags;
+        for feature in &settings.suspended {
+            flags.set_enabled(*feature, false);
+        }
+
+        self.flags.set_if_not_eq(flags);
+    }
+}
+
+/// The features of the client that can be toggled at runtime.
+///
+/// Get access to it with [`Client::features()`].
+#[derive(Debug)]
+pub struct ClientFeatures {
+    client: Client,
+}
+
+impl ClientFeatures {
+    pub(crate) fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    /// Get the current state of the features.
+    ///
+    /// The suspended features are reported as disabled, see
+    /// [`ClientFeatures::set_suspended()`].
+    pub fn get(&self) -> ClientFeatureFlags {
+        self.client.inner.features.flags.get()
+    }
+
+    /// Whether the given feature is enabled.
+    pub fn is_enabled(&self, feature: ClientFeature) -> bool {
+        self.get().is_enabled(feature)
+    }
+
+    /// Get a subscriber to the state of the features.
+    ///
+    /// It is updated when a feature is toggled with
+    /// [`ClientFeatures::set_enabled()`], suspended or resumed with
+    /// [`ClientFeatures::set_suspended()`], or when its setting changes in the
+    /// account data during a sync.
+    pub fn subscribe(&self) -> Subscriber<ClientFeatureFlags> {
+        self.client.inner.features.flags.subscribe()
+    }
+
+    /// Enable or disable the given feature.
+    ///
+    /// If the feature has a matching setting in the account data, the setting
+    /// is uploaded first and the feature is only toggled if that succeeded.
+    #[instrument(skip(self))]
+    pub async fn set_enabled(&self, feature: ClientFeature, enabled: bool) -> Result<()> {
+        let _guard = self.client.inner.features.update_lock.lock().await;
+
+        match feature {
+            ClientFeature::ReadReceipts => {
+                self.update_web_settings("sendReadReceipts", enabled).await?;
+            }
+            ClientFeature::TypingNotifications => {
+                self.update_web_settings("sendTypingNotifications", enabled).await?;
+            }
+            ClientFeature::UrlPreviews => {
+                let content = Raw::new(&serde_json::json!({ "disable": !enabled }))?.cast();
+                self.client
+                    .account()
+                    .set_account_data_raw(PREVIEW_URLS_EVENT_TYPE.into(), content)
+                    .await?;
+            }
+            ClientFeature::Presence => {
+                let value = serde_json::to_vec(&enabled)?;
+                self.client.store().set_custom_value(PRESENCE_KEY, value).await?;
+            }
+        }
+
+        self.client.inner.features.update(|settings| settings.flags.set_enabled(feature, enabled));
+
+        Ok(())
+    }
+
+    /// Suspend or resume the given feature.
+    ///
+    /// While it is suspended, the feature is reported as disabled, whatever
+    /// its setting. Its setting isn't changed, so this is neither uploaded to
+    /// the account data nor persisted, and the feature is back to its setting
+    /// once it is resumed.
+    pub fn set_suspended(&self, feature: ClientFeature, suspended: bool) {
+        self.client.inner.features.update(|settings| {
+            settings.suspended.retain(|f| *f != feature);
+            if suspended {
+                settings.suspended.push(feature);
+            }
+        });
+    }
+
+    /// Load the state of the features from the account data and the state
+    /// store.
+    ///
+    /// This is done automatically when a session is restored or logged in.
+    pub async fn load_from_store(&self) -> Result<()> {
+        let account = self.client.account();
+        let mut flags = self.client.inner.features.settings();
+
+        if let Some(value) = self.client.store().get_custom_value(PRESENCE_KEY).await? {
+            match serde_json::from_slice(&value) {
+                Ok(enabled) => flags.presence = enabled,
+                Err(e) => warn!("Couldn't deserialize the presence feature: {e}"),
+            }
+        }
+
+        for event_type in [WEB_SETTINGS_EVENT_TYPE, PREVIEW_URLS_EVENT_TYPE] {
+            if let Some(content) = account.account_data_raw(event_type.into()).await? {
+                match content.deserialize_as::<JsonMap<String, JsonValue>>() {
+                    Ok(content) => flags.apply_account_data(event_type, &content),
+                    Err(e) => warn!(event_type, "Couldn't deserialize the account data: {e}"),
+                }
+            }
+        }
+
+        self.client.inner.features.update(|settings| settings.flags = flags);
+
+        Ok(())
+    }
+
+    /// Set a key of the `im.vector.web.settings` account data, keeping the
+    /// other keys.
+    async fn update_web_settings(&self, key: &str, value: bool) -> Result<()> {
+        let account = self.client.account();
+        let event_type = GlobalAccountDataEventType::from(WEB_SETTINGS_EVENT_TYPE);
+
+        let mut settings = match account.fetch_account_data(event_type.clone()).await? {
+            Some(content) => content.deserialize_as::<JsonMap<String, JsonValue>>()?,
+            None => JsonMap::new(),
+        };
+        settings.insert(key.to_owned(), value.into());
+
+        account.set_account_data_raw(event_type, Raw::new(&settings)?.cast()).await?;
+
+        Ok(())
+    }
+}
+
+impl Client {
+    /// Get the features of the client that can be toggled at runtime.
+    pub fn features(&self) -> ClientFeatures {
+        ClientFeatures::new(self.clone())
+    }
+
+    /// Update the features after a sync, from the settings in the global
+    /// account data.
+    pub(crate) fn update_features(&self, account_data: &[Raw<AnyGlobalAccountDataEvent>]) {
+        #[derive(Deserialize)]
+        struct Event {
+            #[serde(rename = "type")]
+            event_type: String,
+            content: JsonMap<String, JsonValue>,
+        }
+
+        let mut flags = self.inner.features.settings();
+
+        for raw in account_data {
+            match raw.deserialize_as::<Event>() {
+                Ok(event) => flags.apply_account_data(&event.event_type, &event.content),
+                Err(e) => debug!("Couldn't deserialize an account data event: {e}"),
+            }
+        }
+
+        self.inner.features.update(|settings| settings.flags = flags);
+    }
+}
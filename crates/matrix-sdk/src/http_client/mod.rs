@@ -36,11 +36,17 @@ use crate::{config::RequestConfig, error::HttpError};
 
 #[cfg(not(target_arch = "wasm32"))]
 mod native;
+#[cfg(all(feature = "http-recording", not(target_arch = "wasm32")))]
+mod recording;
 #[cfg(target_arch = "wasm32")]
 mod wasm;
 
 #[cfg(not(target_arch = "wasm32"))]
 pub(crate) use native::HttpSettings;
+#[cfg(all(feature = "http-recording", not(target_arch = "wasm32")))]
+pub(crate) use recording::HttpRecording;
+#[cfg(all(feature = "http-recording", not(target_arch = "wasm32")))]
+pub use recording::{HttpRecorder, HttpReplay, RecordedBody, RecordedExchange};
 
 pub(crate) const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
 
@@ -49,11 +55,27 @@ pub(crate) struct HttpClient {
     pub(crate) inner: reqwest::Client,
     pub(crate) request_config: RequestConfig,
     next_request_id: Arc<AtomicU64>,
+    #[cfg(all(feature = "http-recording", not(target_arch = "wasm32")))]
+    recording: Option<HttpRecording>,
 }
 
 impl HttpClient {
     pub(crate) fn new(inner: reqwest::Client, request_config: RequestConfig) -> Self {
-        HttpClient { inner, request_config, next_request_id: AtomicU64::new(0).into() }
+        HttpClient {
+            inner,
+            request_config,
+            next_request_id: AtomicU64::new(0).into(),
+            #[cfg(all(feature = "http-recording", not(target_arch = "wasm32")))]
+            recording: None,
+        }
+    }
+
+    /// Record the traffic of this client, or replay a recording instead of
+    /// sending the requests.
+    #[cfg(all(feature = "http-recording", not(target_arch = "wasm32")))]
+    pub(crate) fn with_recording(mut self, recording: Option<HttpRecording>) -> Self {
+        self.recording = recording;
+        self
     }
 
     fn get_request_id(&self) -> String {
@@ -31,6 +31,8 @@ use ruma::api::{
 };
 use tracing::{info, warn};
 
+#[cfg(feature = "http-recording")]
+use super::HttpRecording;
 use super::{response_to_http_response, HttpClient, TransmissionProgress, DEFAULT_REQUEST_TIMEOUT};
 use crate::{config::RequestConfig, error::HttpError, RumaApiError};
 
@@ -92,7 +94,8 @@ impl HttpClient {
                     }
                 };
 
-                let response = send_request(&self.inner, &request, config.timeout, send_progress)
+                let response = self
+                    .send_http_request(&request, config.timeout, send_progress)
                     .await
                     .map_err(error_type)?;
 
@@ -122,6 +125,28 @@ impl HttpClient {
 
         retry::<_, HttpError, _, _, _>(backoff, send_request).await
     }
+
+    /// Send the request, or replay its response if a recording is replayed.
+    async fn send_http_request(
+        &self,
+        request: &http::Request<Bytes>,
+        timeout: Duration,
+        send_progress: SharedObservable<TransmissionProgress>,
+    ) -> Result<http::Response<Bytes>, HttpError> {
+        #[cfg(feature = "http-recording")]
+        if let Some(HttpRecording::Replay(replay)) = &self.recording {
+            return Ok(replay.replay(request));
+        }
+
+        let response = send_request(&self.inner, request, timeout, send_progress).await?;
+
+        #[cfg(feature = "http-recording")]
+        if let Some(HttpRecording::Record(recorder)) = &self.recording {
+            recorder.record(request, &response);
+        }
+
+        Ok(response)
+    }
 }
 
 #[cfg(not(target_arch = "wasm32"))]
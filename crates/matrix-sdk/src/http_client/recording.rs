@@ -0,0 +1,495 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Recording of the HTTP traffic of a client, and replay of a recording.
+//!
+//! An [`HttpRecorder`] writes every request sent by the client and the response
+//! it got to a file, one JSON object per line, from a dedicated thread. The
+//! secrets, like the access token or the password, are redacted. An
+//! [`HttpReplay`] answers the requests of a client with the responses of a
+//! recording instead of sending them, so a session of a user can be run again
+//! deterministically, for example to reproduce a bug in the processing of odd
+//! responses of their homeserver.
+
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    sync::{
+        mpsc::{self, TryRecvError},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use bytes::Bytes;
+use http::{header::CONTENT_TYPE, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+use tracing::{debug, warn};
+use url::form_urlencoded;
+
+/// The value that replaces the secrets in a recording.
+const REDACTED: &str = "<redacted>";
+
+/// The keys of the JSON objects whose string values are secrets.
+const SECRET_KEYS: &[&str] = &[
+    "access_token",
+    "refresh_token",
+    "id_token",
+    "login_token",
+    "password",
+    "new_password",
+    "token",
+    "client_secret",
+    "passphrase",
+];
+
+/// The path segments followed by an event type or ID, then by a transaction ID
+/// generated by the client.
+const TRANSACTION_ID_ENDPOINTS: &[&str] = &["send", "sendToDevice", "redact"];
+
+/// The value that replaces the transaction IDs of the paths when they are
+/// compared.
+const TRANSACTION_ID_PLACEHOLDER: &str = "{txnId}";
+
+/// A request sent by the client and the response it got.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecordedExchange {
+    /// The method of the request.
+    pub method: String,
+
+    /// The path and the query of the URI of the request, without the
+    /// homeserver.
+    pub path: String,
+
+    /// The body of the request, if it isn't empty.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_body: Option<RecordedBody>,
+
+    /// The status code of the response.
+    pub status: u16,
+
+    /// The `Content-Type` of the response, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+
+    /// The body of the response.
+    pub response_body: RecordedBody,
+}
+
+impl RecordedExchange {
+    fn new(request: &http::Request<Bytes>, response: &http::Response<Bytes>) -> Self {
+        Self {
+            method: request.method().to_string(),
+            path: redact_path(request.uri()),
+            request_body: (!request.body().is_empty()).then(|| RecordedBody::new(request.body())),
+            status: response.status().as_u16(),
+            content_type: response
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .map(ToOwned::to_owned),
+            response_body: RecordedBody::new(response.body()),
+        }
+    }
+
+    /// Whether this exchange is an answer to the given request.
+    ///
+    /// The query of the URIs and the transaction IDs in the paths are ignored,
+    /// because they change from one run to another, like the timeout of a sync
+    /// or the transaction ID of a sent message.
+    fn matches(&self, request: &http::Request<Bytes>) -> bool {
+        let path = self.path.split_once('?').map_or(self.path.as_str(), |(path, _)| path);
+        self.method == request.method().as_str()
+            && normalize_transaction_id(path) == normalize_transaction_id(request.uri().path())
+    }
+
+    fn to_response(&self) -> http::Response<Bytes> {
+        let mut builder = http::Response::builder().status(self.status);
+
+        if let Some(content_type) = &self.content_type {
+            builder = builder.header(CONTENT_TYPE, content_type);
+        }
+
+        builder.body(self.response_body.to_bytes()).unwrap_or_else(|e| {
+            warn!("Invalid recorded response: {e}");
+            not_found_response()
+        })
+    }
+}
+
+/// The body of a request or a response in a recording.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordedBody {
+    /// A JSON body, with the secrets redacted.
+    Json(JsonValue),
+
+    /// Any other body, encoded as base64.
+    Base64(String),
+}
+
+impl RecordedBody {
+    fn new(body: &[u8]) -> Self {
+        match serde_json::from_slice::<JsonValue>(body) {
+            Ok(mut json) => {
+                redact_json(&mut json);
+                Self::Json(json)
+            }
+            Err(_) => Self::Base64(STANDARD.encode(body)),
+        }
+    }
+
+    fn to_bytes(&self) -> Bytes {
+        match self {
+            Self::Json(json) => serde_json::to_vec(json).unwrap_or_default().into(),
+            Self::Base64(base64) => STANDARD
+                .decode(base64)
+                .unwrap_or_else(|e| {
+                    warn!("Invalid base64 body in the recording: {e}");
+                    Vec::new()
+                })
+                .into(),
+        }
+    }
+}
+
+/// Records the HTTP traffic of a client to a file.
+///
+/// Pass it to [`ClientBuilder::record_http_traffic()`] to record the traffic
+/// of a client. The recording can be replayed with an [`HttpReplay`].
+///
+/// The exchanges are written by a dedicated thread, to not block the requests
+/// of the client on the file.
+///
+/// [`ClientBuilder::record_http_traffic()`]: crate::ClientBuilder::record_http_traffic
+#[derive(Debug)]
+pub struct HttpRecorder {
+    sender: Mutex<mpsc::Sender<RecordedExchange>>,
+}
+
+impl HttpRecorder {
+    /// Create a recorder that writes to the file at the given path.
+    ///
+    /// The file is truncated if it already exists.
+    pub fn new(path: impl AsRef<Path>) -> io::Result<Self> {
+        let writer = BufWriter::new(File::create(path)?);
+        let (sender, receiver) = mpsc::channel();
+
+        thread::Builder::new()
+            .name("http-recorder".to_owned())
+            .spawn(move || write_exchanges(writer, receiver))?;
+
+        Ok(Self { sender: Mutex::new(sender) })
+    }
+
+    pub(crate) fn record(&self, request: &http::Request<Bytes>, response: &http::Response<Bytes>) {
+        let exchange = RecordedExchange::new(request, response);
+
+        if self.sender.lock().unwrap().send(exchange).is_err() {
+            warn!("Couldn't record an HTTP exchange: the recording thread stopped");
+        }
+    }
+}
+
+/// Write the exchanges received from the [`HttpRecorder`] until it is dropped.
+fn write_exchanges(mut writer: BufWriter<File>, receiver: mpsc::Receiver<RecordedExchange>) {
+    let mut next = receiver.recv();
+
+    while let Ok(exchange) = next {
+        let result = serde_json::to_writer(&mut writer, &exchange)
+            .map_err(io::Error::from)
+            .and_then(|()| writer.write_all(b"\n"));
+
+        if let Err(e) = result {
+            warn!("Couldn't record an HTTP exchange: {e}");
+        }
+
+        next = match receiver.try_recv() {
+            Ok(exchange) => Ok(exchange),
+            Err(TryRecvError::Empty) => {
+                // Flush once there is nothing more to write, so the recording
+                // is complete if the process crashes.
+                if let Err(e) = writer.flush() {
+                    warn!("Couldn't flush the HTTP recording: {e}");
+                }
+                receiver.recv()
+            }
+            Err(TryRecvError::Disconnected) => break,
+        };
+    }
+
+    if let Err(e) = writer.flush() {
+        warn!("Couldn't flush the HTTP recording: {e}");
+    }
+}
+
+/// Answers the requests of a client with the responses of a recording, instead
+/// of sending them.
+///
+/// Pass it to [`ClientBuilder::replay_http_traffic()`] to replay a recording
+/// of an [`HttpRecorder`]. Every request is answered with the first response
+/// that wasn't replayed yet for the same method and path, or with a `404` if
+/// there is none. The transaction IDs generated by the client, like the one of
+/// a sent message, are ignored in the paths.
+///
+/// [`ClientBuilder::replay_http_traffic()`]: crate::ClientBuilder::replay_http_traffic
+#[derive(Debug)]
+pub struct HttpReplay {
+    exchanges: Mutex<Vec<Option<RecordedExchange>>>,
+}
+
+impl HttpReplay {
+    /// Create a replay of the given exchanges, in the order they should be
+    /// replayed.
+    pub fn new(exchanges: Vec<RecordedExchange>) -> Self {
+        Self { exchanges: Mutex::new(exchanges.into_iter().map(Some).collect()) }
+    }
+
+    /// Load the recording written by an [`HttpRecorder`] to the file at the
+    /// given path.
+    pub fn from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut exchanges = Vec::new();
+
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let exchange = serde_json::from_str(&line)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            exchanges.push(exchange);
+        }
+
+        Ok(Self::new(exchanges))
+    }
+
+    /// The number of exchanges that weren't replayed yet.
+    ///
+    /// This is useful to check that a session was replayed until the end.
+    pub fn remaining(&self) -> usize {
+        self.exchanges.lock().unwrap().iter().flatten().count()
+    }
+
+    pub(crate) fn replay(&self, request: &http::Request<Bytes>) -> http::Response<Bytes> {
+        let mut exchanges = self.exchanges.lock().unwrap();
+
+        let exchange = exchanges
+            .iter_mut()
+            .find(|exchange| exchange.as_ref().is_some_and(|exchange| exchange.matches(request)))
+            .and_then(Option::take);
+
+        match exchange {
+            Some(exchange) => {
+                debug!(method = exchange.method, path = exchange.path, "Replaying a response");
+                exchange.to_response()
+            }
+            None => {
+                warn!(method = %request.method(), uri = %request.uri(), "No recorded response");
+                not_found_response()
+            }
+        }
+    }
+}
+
+/// How the HTTP traffic of a client is recorded or replayed.
+#[derive(Clone, Debug)]
+pub(crate) enum HttpRecording {
+    Record(Arc<HttpRecorder>),
+    Replay(Arc<HttpReplay>),
+}
+
+fn not_found_response() -> http::Response<Bytes> {
+    let body = json!({
+        "errcode": "M_UNRECOGNIZED",
+        "error": "No recorded response for this request",
+    });
+
+    http::Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .header(CONTENT_TYPE, "application/json")
+        .body(serde_json::to_vec(&body).unwrap_or_default().into())
+        .expect("The response is valid")
+}
+
+/// Get the path and the query of the given URI, with the access token
+/// redacted.
+fn redact_path(uri: &http::Uri) -> String {
+    let Some(query) = uri.query() else {
+        return uri.path().to_owned();
+    };
+
+    let query = form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(form_urlencoded::parse(query.as_bytes()).map(|(key, value)| {
+            let value = if key == "access_token" { REDACTED.into() } else { value };
+            (key, value)
+        }))
+        .finish();
+
+    format!("{}?{query}", uri.path())
+}
+
+/// Replace the transaction ID of the given path, if it has one, with a
+/// placeholder.
+fn normalize_transaction_id(path: &str) -> Cow<'_, str> {
+    let segments: Vec<_> = path.split('/').collect();
+
+    // The transaction ID is the last segment, two segments after the endpoint.
+    let has_transaction_id =
+        segments.len() >= 3 && TRANSACTION_ID_ENDPOINTS.contains(&segments[segments.len() - 3]);
+
+    if !has_transaction_id {
+        return Cow::Borrowed(path);
+    }
+
+    let prefix = &segments[..segments.len() - 1];
+    Cow::Owned(format!("{}/{TRANSACTION_ID_PLACEHOLDER}", prefix.join("/")))
+}
+
+/// Redact the secrets of the given JSON value in place.
+fn redact_json(json: &mut JsonValue) {
+    match json {
+        JsonValue::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                if value.is_string() && SECRET_KEYS.contains(&key.as_str()) {
+                    *value = REDACTED.into();
+                } else {
+                    redact_json(value);
+                }
+            }
+        }
+        JsonValue::Array(array) => array.iter_mut().for_each(redact_json),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use matrix_sdk_test::async_test;
+    use serde_json::json;
+    use tokio::time::sleep;
+    use wiremock::{
+        matchers::{header, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::{HttpRecorder, HttpReplay, RecordedBody, RecordedExchange, REDACTED};
+    use crate::test_utils::test_client_builder;
+
+    #[async_test]
+    async fn test_record_and_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let recording = dir.path().join("recording.jsonl");
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/_matrix/client/r0/login"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "access_token": "secret",
+                "device_id": "DEVICEID",
+                "user_id": "@example:localhost",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/r0/account/whoami"))
+            .and(header("authorization", "Bearer secret"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "user_id": "@example:localhost",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        // Record a session.
+        let client = test_client_builder(Some(server.uri()))
+            .record_http_traffic(HttpRecorder::new(&recording).unwrap())
+            .build()
+            .await
+            .unwrap();
+        client.matrix_auth().login_username("example", "wordpass").send().await.unwrap();
+        let whoami = client.whoami().await.unwrap();
+        assert_eq!(whoami.user_id, "@example:localhost");
+
+        // The recording is written in the background, wait until it is
+        // complete.
+        let replay = loop {
+            match HttpReplay::from_file(&recording) {
+                Ok(replay) if replay.remaining() == 2 => break replay,
+                _ => sleep(Duration::from_millis(10)).await,
+            }
+        };
+
+        // The secrets are redacted in the recording.
+        let exchanges =
+            replay.exchanges.lock().unwrap().iter().flatten().cloned().collect::<Vec<_>>();
+        assert_eq!(exchanges.len(), 2);
+        let RecordedBody::Json(login_request) = exchanges[0].request_body.clone().unwrap() else {
+            panic!("The login request should be JSON");
+        };
+        assert_eq!(login_request["password"], REDACTED);
+        let RecordedBody::Json(login_response) = &exchanges[0].response_body else {
+            panic!("The login response should be JSON");
+        };
+        assert_eq!(login_response["access_token"], REDACTED);
+        assert_eq!(login_response["user_id"], "@example:localhost");
+
+        // Replay the session without the server.
+        let client = test_client_builder(Some("http://localhost:1".to_owned()))
+            .replay_http_traffic(replay)
+            .build()
+            .await
+            .unwrap();
+        client.matrix_auth().login_username("example", "wordpass").send().await.unwrap();
+        let whoami = client.whoami().await.unwrap();
+        assert_eq!(whoami.user_id, "@example:localhost");
+
+        // There is nothing more to replay.
+        client.whoami().await.unwrap_err();
+    }
+
+    #[test]
+    fn test_transaction_ids_are_ignored_when_matching() {
+        let exchange = RecordedExchange {
+            method: "PUT".to_owned(),
+            path: "/_matrix/client/r0/rooms/!room:localhost/send/m.room.message/recorded?a=b"
+                .to_owned(),
+            request_body: None,
+            status: 200,
+            content_type: None,
+            response_body: RecordedBody::Json(json!({ "event_id": "$event" })),
+        };
+
+        let request =
+            |uri: &str| http::Request::builder().method("PUT").uri(uri).body(Bytes::new()).unwrap();
+
+        assert!(exchange.matches(&request(
+            "http://localhost/_matrix/client/r0/rooms/!room:localhost/send/m.room.message/replayed"
+        )));
+        assert!(!exchange.matches(&request(
+            "http://localhost/_matrix/client/r0/rooms/!room:localhost/send/m.reaction/replayed"
+        )));
+        assert!(!exchange.matches(&request(
+            "http://localhost/_matrix/client/r0/rooms/!other:localhost/send/m.room.message/replayed"
+        )));
+    }
+}
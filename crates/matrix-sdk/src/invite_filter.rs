@@ -0,0 +1,310 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Hooks to filter out unwanted invites.
+//!
+//! [`InviteFilter`]s are registered with
+//! [`ClientBuilder::add_invite_filter`][crate::ClientBuilder::add_invite_filter]
+//! and are run, in the order they were added, on every invite received via
+//! sync. The first filter that doesn't [accept](InviteDecision::Accept) the
+//! invite decides what happens to it.
+//!
+//! The filters run in the background, so they don't hold up the processing of
+//! the sync responses. The [`RoomUpdate::Invited`] updates and the event
+//! handlers of the stripped state of an invite are held back until the
+//! filters accepted or shelved it, they never see the rejected invites.
+//!
+//! To not join a flood of rooms, for example when the user accepts a wave of
+//! invites at once, the joins can also be throttled with
+//! [`ClientBuilder::throttle_joins`][crate::ClientBuilder::throttle_joins].
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex as StdMutex, Weak},
+    time::Duration,
+};
+
+use matrix_sdk_base::{instant::Instant, AsyncTraitDeps, RoomState};
+use ruma::{
+    api::client::sync::sync_events::v3::InvitedRoom,
+    events::{ignored_user_list::IgnoredUserListEventContent, room::member::MembershipState},
+    OwnedRoomId, UserId,
+};
+use tokio::sync::{mpsc, Mutex as AsyncMutex};
+use tracing::{debug, error, trace, warn};
+
+use crate::{
+    client::ClientInner,
+    event_handler::HandlerKind,
+    executor::{spawn, JoinHandle},
+    sync::RoomUpdate,
+    Client, Result, Room,
+};
+
+/// What to do with an invite, as decided by an [`InviteFilter`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InviteDecision {
+    /// Keep the invite as a regular invite.
+    Accept,
+
+    /// Reject the invite right away.
+    Reject,
+
+    /// Keep the invite, but shelve it as spam.
+    ///
+    /// See [`BaseRoom::is_spam_invite`][crate::BaseRoom::is_spam_invite].
+    Spam,
+}
+
+/// A hook that is called on the invites received via sync.
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+pub trait InviteFilter: AsyncTraitDeps {
+    /// Decide what to do with the invite to the given room, sent by `inviter`.
+    async fn filter_invite(&self, room: &Room, inviter: &UserId) -> Result<InviteDecision>;
+}
+
+/// An [`InviteFilter`] that rejects the invites sent by users in the ignore
+/// list of the current user.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IgnoredUsersFilter;
+
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+impl InviteFilter for IgnoredUsersFilter {
+    async fn filter_invite(&self, room: &Room, inviter: &UserId) -> Result<InviteDecision> {
+        let ignored_users = room
+            .client()
+            .account()
+            .account_data::<IgnoredUserListEventContent>()
+            .await?
+            .map(|raw| raw.deserialize())
+            .transpose()?;
+
+        let is_ignored =
+            ignored_users.is_some_and(|content| content.ignored_users.contains_key(inviter));
+
+        Ok(if is_ignored { InviteDecision::Reject } else { InviteDecision::Accept })
+    }
+}
+
+/// An [`InviteFilter`] that shelves as spam the invites sent by users the
+/// current user doesn't share any joined room with.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SharedRoomsFilter;
+
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+impl InviteFilter for SharedRoomsFilter {
+    async fn filter_invite(&self, room: &Room, inviter: &UserId) -> Result<InviteDecision> {
+        for joined_room in room.client().joined_rooms() {
+            let Some(member) = joined_room.get_member_no_sync(inviter).await? else {
+                continue;
+            };
+
+            if *member.membership() == MembershipState::Join {
+                return Ok(InviteDecision::Accept);
+            }
+        }
+
+        Ok(InviteDecision::Spam)
+    }
+}
+
+/// A limit on how many rooms can be joined in a period of time.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct JoinThrottle {
+    pub(crate) max_joins: usize,
+    pub(crate) period: Duration,
+}
+
+/// The invite filters of a client, the task running them, and the throttle
+/// of the joins.
+pub(crate) struct InviteFiltersState {
+    pub(crate) filters: Vec<Arc<dyn InviteFilter>>,
+    pub(crate) join_throttle: Option<JoinThrottle>,
+    task: StdMutex<Option<InviteFilterTask>>,
+    /// When the rooms were joined during the last period of the throttle.
+    recent_joins: AsyncMutex<VecDeque<Instant>>,
+}
+
+impl InviteFiltersState {
+    pub(crate) fn new(
+        filters: Vec<Arc<dyn InviteFilter>>,
+        join_throttle: Option<JoinThrottle>,
+    ) -> Self {
+        Self { filters, join_throttle, task: Default::default(), recent_joins: Default::default() }
+    }
+
+    pub(crate) fn has_filters(&self) -> bool {
+        !self.filters.is_empty()
+    }
+}
+
+/// The task running the invite filters on the invites received via sync.
+struct InviteFilterTask {
+    sender: mpsc::UnboundedSender<(OwnedRoomId, InvitedRoom)>,
+    #[allow(dead_code)]
+    join_handle: JoinHandle<()>,
+}
+
+impl Drop for InviteFilterTask {
+    fn drop(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.join_handle.abort();
+    }
+}
+
+impl InviteFilterTask {
+    fn new(client: Weak<ClientInner>) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let join_handle = spawn(Self::listen(client, receiver));
+
+        Self { sender, join_handle }
+    }
+
+    async fn listen(
+        client: Weak<ClientInner>,
+        mut receiver: mpsc::UnboundedReceiver<(OwnedRoomId, InvitedRoom)>,
+    ) {
+        while let Some((room_id, room_info)) = receiver.recv().await {
+            let Some(client) = client.upgrade() else {
+                trace!("Client got dropped, shutting down the task");
+                break;
+            };
+            let client = Client { inner: client };
+
+            let Some(room) = client.get_room(&room_id) else {
+                continue;
+            };
+
+            if !client.filter_invite(&room).await {
+                debug!(?room_id, "Invite rejected by a filter");
+                continue;
+            }
+
+            // The invite was accepted or shelved, let the subscribers know about it.
+            client.send_room_update(&room_id, || RoomUpdate::Invited {
+                room: room.clone(),
+                updates: room_info.clone(),
+            });
+
+            let invite_state = &room_info.invite_state.events;
+            if let Err(e) = client
+                .handle_sync_events(HandlerKind::StrippedState, Some(&room), invite_state)
+                .await
+            {
+                error!(?room_id, "Couldn't run the event handlers of an invite: {e}");
+            }
+        }
+    }
+}
+
+impl Client {
+    /// Run the invite filters on the given invite in the background, and
+    /// notify the subscribers of the room updates and the event handlers about
+    /// it if it isn't rejected.
+    pub(crate) fn queue_invite_filtering(&self, room_id: &OwnedRoomId, room_info: &InvitedRoom) {
+        let mut task = self.inner.invite_filters.task.lock().unwrap();
+        let task = task.get_or_insert_with(|| InviteFilterTask::new(Arc::downgrade(&self.inner)));
+
+        let _ = task.sender.send((room_id.clone(), room_info.clone()));
+    }
+
+    /// Wait until the join throttle allows to join another room, if the joins
+    /// are throttled.
+    pub(crate) async fn wait_for_join_slot(&self) {
+        let state = &self.inner.invite_filters;
+        let Some(throttle) = state.join_throttle else {
+            return;
+        };
+
+        let clock = self.clock();
+        // Keep the lock while waiting, so the joins happen in order.
+        let mut recent_joins = state.recent_joins.lock().await;
+
+        loop {
+            let now = clock.now();
+            while recent_joins
+                .front()
+                .is_some_and(|joined_at| now.duration_since(*joined_at) >= throttle.period)
+            {
+                recent_joins.pop_front();
+            }
+
+            let Some(oldest_join) = recent_joins.front().copied() else { break };
+            if recent_joins.len() < throttle.max_joins {
+                break;
+            }
+
+            let delay = throttle.period - now.duration_since(oldest_join);
+            debug!(
+                ?delay,
+                "Too many rooms were joined recently, waiting before joining another one"
+            );
+            clock.sleep(delay).await;
+        }
+
+        recent_joins.push_back(clock.now());
+    }
+
+    /// Run the invite filters on the invite to the given room, and apply their
+    /// decision.
+    ///
+    /// Returns `false` if the invite was rejected.
+    async fn filter_invite(&self, room: &Room) -> bool {
+        if room.state() != RoomState::Invited {
+            // The invite was handled in the meantime.
+            return true;
+        }
+
+        let inviter = match room.invite_details().await {
+            Ok(invite) => invite.invitee.event().sender().to_owned(),
+            Err(e) => {
+                warn!(room_id = ?room.room_id(), "Couldn't find the sender of an invite: {e}");
+                return true;
+            }
+        };
+
+        let mut decision = InviteDecision::Accept;
+
+        for filter in &self.inner.invite_filters.filters {
+            match filter.filter_invite(room, &inviter).await {
+                Ok(InviteDecision::Accept) => {}
+                Ok(d) => {
+                    decision = d;
+                    break;
+                }
+                Err(e) => warn!(room_id = ?room.room_id(), "Invite filter failed: {e}"),
+            }
+        }
+
+        if decision == InviteDecision::Reject {
+            if let Err(e) = room.leave().await {
+                warn!(room_id = ?room.room_id(), "Couldn't reject a filtered invite: {e}");
+            }
+
+            return false;
+        }
+
+        let is_spam = decision == InviteDecision::Spam;
+        if is_spam != room.is_spam_invite() {
+            if let Err(e) = self.base_client().set_spam_invite(room.room_id(), is_spam).await {
+                warn!(room_id = ?room.room_id(), "Couldn't shelve an invite: {e}");
+            }
+        }
+
+        true
+    }
+}
@@ -23,9 +23,9 @@ pub use matrix_sdk_base::crypto;
 pub use matrix_sdk_base::{
     deserialized_responses,
     store::{DynStateStore, MemoryStore, StateStoreExt},
-    DisplayName, Room as BaseRoom, RoomCreateWithCreatorEventContent, RoomInfo,
-    RoomMember as BaseRoomMember, RoomMemberships, RoomState, SessionMeta, StateChanges,
-    StateStore, StoreError,
+    sync_metrics, DisplayName, Room as BaseRoom, RoomCreateWithCreatorEventContent, RoomInfo,
+    RoomInfoChangeReasons, RoomInfoUpdate, RoomMember as BaseRoomMember, RoomMemberships,
+    RoomPrivacyOverrides, RoomState, SessionMeta, StateChanges, StateStore, StoreError,
 };
 pub use matrix_sdk_common::*;
 pub use reqwest;
@@ -35,40 +35,58 @@ pub mod attachment;
 mod authentication;
 mod client;
 pub mod config;
+pub mod content_scanner;
+mod custom_event;
 mod deduplicating_handler;
 #[cfg(feature = "e2e-encryption")]
 pub mod encryption;
 mod error;
 pub mod event_handler;
+pub mod features;
 mod http_client;
+pub mod invite_filter;
 pub mod matrix_auth;
 pub mod media;
 pub mod notification_settings;
 #[cfg(feature = "experimental-oidc")]
 pub mod oidc;
+pub mod profiles;
 pub mod room;
+pub mod scheduled_messages;
 pub mod utils;
 pub mod futures {
     //! Named futures returned from methods on types in [the crate root][crate].
 
     pub use super::client::futures::SendRequest;
 }
+pub mod server_notices;
 #[cfg(feature = "experimental-sliding-sync")]
 pub mod sliding_sync;
+pub mod store_backend;
+pub mod store_cleanup;
 pub mod sync;
+pub mod user_search;
 #[cfg(feature = "experimental-widgets")]
 pub mod widget;
 
-pub use account::Account;
+pub use account::{
+    Account, DeactivationOptions, DeactivationPlan, DeactivationReport, DeactivationStep,
+    DeactivationStepResult,
+};
 pub use authentication::{AuthApi, AuthSession, SessionTokens};
-pub use client::{Client, ClientBuildError, ClientBuilder, LoopCtrl, SessionChange};
+pub use client::{
+    Client, ClientBuildError, ClientBuilder, LoopCtrl, SessionChange, SessionGoneReason,
+    SessionStatus,
+};
 #[cfg(feature = "image-proc")]
 pub use error::ImageError;
 pub use error::{
-    Error, HttpError, HttpResult, NotificationSettingsError, RefreshTokenError, Result,
+    Error, HttpError, HttpResult, NotificationSettingsError, RefreshTokenError, Result, RetryKind,
     RumaApiError,
 };
 pub use http_client::TransmissionProgress;
+#[cfg(all(feature = "http-recording", not(target_arch = "wasm32")))]
+pub use http_client::{HttpRecorder, HttpReplay, RecordedBody, RecordedExchange};
 #[cfg(all(feature = "e2e-encryption", feature = "sqlite"))]
 pub use matrix_sdk_sqlite::SqliteCryptoStore;
 pub use media::Media;
@@ -83,6 +101,12 @@ pub use sliding_sync::{
 #[cfg(any(test, feature = "testing"))]
 pub mod test_utils;
 
+/// The dependencies of the macros of this crate, not part of its public API.
+#[doc(hidden)]
+pub mod __private {
+    pub use serde;
+}
+
 #[cfg(test)]
 matrix_sdk_test::init_tracing_for_tests!();
 
@@ -0,0 +1,204 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Guest accounts, and their upgrade to full accounts.
+
+use std::sync::atomic::Ordering;
+
+use matrix_sdk_base::SessionMeta;
+use ruma::{
+    api::client::{
+        account::register::{self, RegistrationKind},
+        uiaa,
+    },
+    assign,
+};
+use tracing::{info, instrument};
+
+use super::{MatrixAuth, MatrixSession, MatrixSessionTokens};
+use crate::{Client, Error, Result};
+
+/// The key of the flag telling that the session is a guest account, in the
+/// custom values of the state store.
+const IS_GUEST_KEY: &[u8] = b"matrix-sdk.matrix_auth.is_guest";
+
+impl MatrixAuth {
+    /// Register a guest account on the homeserver, and log in with it.
+    ///
+    /// Guest accounts can only perform a restricted set of operations: they
+    /// can't create rooms, invite, kick or ban users, send state events or
+    /// events other than messages, nor report content, for example. These
+    /// operations fail with [`Error::GuestAccessForbidden`]. Use
+    /// [`MatrixAuth::upgrade_guest()`] to turn a guest account into a full
+    /// account.
+    ///
+    /// That the session is a guest account is kept in the state store, so it
+    /// is known again when the session is restored with
+    /// [`MatrixAuth::restore_session()`].
+    #[instrument(skip_all)]
+    pub async fn register_guest(&self) -> Result<()> {
+        let request = assign!(register::v3::Request::new(), {
+            kind: RegistrationKind::Guest,
+        });
+
+        let response = self.register(request).await?;
+
+        let (Some(access_token), Some(device_id)) = (response.access_token, response.device_id)
+        else {
+            return Err(Error::InsufficientData);
+        };
+
+        info!(user_id = ?response.user_id, "Registered a guest account");
+
+        self.set_session(MatrixSession {
+            meta: SessionMeta { user_id: response.user_id, device_id },
+            tokens: MatrixSessionTokens { access_token, refresh_token: response.refresh_token },
+        })
+        .await?;
+        self.set_guest(true).await?;
+
+        Ok(())
+    }
+
+    /// Restore a previously logged-in guest session.
+    ///
+    /// This is only needed if the state store doesn't remember that the
+    /// session is a guest account, otherwise
+    /// [`MatrixAuth::restore_session()`] can be used.
+    pub async fn restore_guest_session(&self, session: MatrixSession) -> Result<()> {
+        self.restore_session(session).await?;
+        self.set_guest(true).await?;
+
+        Ok(())
+    }
+
+    /// Whether the current session is a guest account.
+    pub fn is_guest(&self) -> bool {
+        self.data().is_some_and(|data| data.is_guest.load(Ordering::SeqCst))
+    }
+
+    /// Upgrade the current guest account to a full account, with the given
+    /// username and password.
+    ///
+    /// The registration is sent for the current device, so the homeserver
+    /// keeps the same device ID, and thus the same encryption identity, if it
+    /// keeps the same user ID. In that case, the session is updated in place
+    /// and the client stays logged in.
+    ///
+    /// Otherwise, the client keeps the guest session and the caller has to log
+    /// in with the new account, using the returned response.
+    ///
+    /// If we don't know that the current session is a guest account, the
+    /// homeserver is asked. Returns [`Error::InconsistentState`] if it isn't a
+    /// guest account.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - The username of the full account.
+    ///
+    /// * `password` - The password of the full account.
+    ///
+    /// * `auth` - The user-interactive authentication data, if the homeserver
+    ///   requires it.
+    #[instrument(skip_all)]
+    pub async fn upgrade_guest(
+        &self,
+        username: &str,
+        password: &str,
+        auth: Option<uiaa::AuthData>,
+    ) -> Result<register::v3::Response> {
+        if !self.is_guest() {
+            // The session might have been restored without the state store knowing
+            // about the guest account.
+            if !self.client.whoami().await?.is_guest {
+                return Err(Error::InconsistentState);
+            }
+
+            self.set_guest(true).await?;
+        }
+
+        let meta = self.client.session_meta().ok_or(Error::AuthenticationRequired)?.clone();
+
+        let request = assign!(register::v3::Request::new(), {
+            username: Some(username.to_owned()),
+            password: Some(password.to_owned()),
+            device_id: Some(meta.device_id.clone()),
+            auth,
+        });
+
+        // The registration endpoint doesn't require authentication, but the
+        // homeserver needs the guest access token to upgrade the account.
+        let config = self.client.request_config().force_auth();
+        let response = self.client.send(request, Some(config)).await?;
+
+        let keeps_identity = response.user_id == meta.user_id
+            && response.device_id.as_ref().map_or(true, |device_id| *device_id == meta.device_id);
+
+        if keeps_identity {
+            if let Some(access_token) = response.access_token.clone() {
+                self.set_session_tokens(MatrixSessionTokens {
+                    access_token,
+                    refresh_token: response.refresh_token.clone(),
+                });
+            }
+
+            self.set_guest(false).await?;
+            info!("Upgraded the guest account");
+        } else {
+            info!(user_id = ?response.user_id, "Registered a new account instead of the guest");
+        }
+
+        Ok(response)
+    }
+
+    /// Set whether the current session is a guest account, and remember it in
+    /// the state store.
+    async fn set_guest(&self, is_guest: bool) -> Result<()> {
+        if let Some(data) = self.data() {
+            data.is_guest.store(is_guest, Ordering::SeqCst);
+        }
+
+        if is_guest {
+            self.client.store().set_custom_value(IS_GUEST_KEY, vec![1]).await?;
+        } else {
+            self.client.store().remove_custom_value(IS_GUEST_KEY).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Load whether the restored session is a guest account from the state
+    /// store.
+    pub(super) async fn restore_guest_flag(&self) -> Result<()> {
+        let is_guest = self.client.store().get_custom_value(IS_GUEST_KEY).await?.is_some();
+
+        if let Some(data) = self.data() {
+            data.is_guest.store(is_guest, Ordering::SeqCst);
+        }
+
+        Ok(())
+    }
+}
+
+impl Client {
+    /// Fail with [`Error::GuestAccessForbidden`] if the client is logged in
+    /// with a guest account.
+    pub(crate) fn ensure_not_guest(&self) -> Result<()> {
+        if self.matrix_auth().is_guest() {
+            Err(Error::GuestAccessForbidden)
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -16,18 +16,27 @@
 
 //! Types to interact with the native Matrix authentication API.
 
-use std::fmt;
 #[cfg(feature = "sso-login")]
 use std::future::Future;
+use std::{
+    fmt,
+    sync::{atomic::AtomicBool, Arc, Weak},
+    time::Duration,
+};
 
 use eyeball::SharedObservable;
 use futures_core::Stream;
-use futures_util::StreamExt;
-use matrix_sdk_base::SessionMeta;
+use futures_util::{
+    future::{self, Either},
+    pin_mut, StreamExt,
+};
+use matrix_sdk_base::{clock::Clock, SessionMeta};
+use matrix_sdk_common::instant::Instant;
 use ruma::{
     api::{
         client::{
             account::register,
+            error::ErrorKind,
             session::{
                 get_login_types, login, logout, refresh_token, sso_login, sso_login_with_provider,
             },
@@ -38,15 +47,18 @@ use ruma::{
     serde::JsonObject,
 };
 use serde::{Deserialize, Serialize};
-use tracing::{debug, error, info, instrument};
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, instrument, trace, warn};
 
 use crate::{
     authentication::AuthData,
-    client::SessionChange,
+    client::{ClientInner, SessionChange},
     error::{HttpError, HttpResult},
+    executor::{spawn, JoinHandle},
     Client, Error, RefreshTokenError, Result,
 };
 
+mod guest;
 mod login_builder;
 
 pub use self::login_builder::LoginBuilder;
@@ -56,6 +68,8 @@ pub use self::login_builder::SsoLoginBuilder;
 #[derive(Clone)]
 pub(crate) struct MatrixAuthData {
     pub(crate) tokens: SharedObservable<MatrixSessionTokens>,
+    /// Whether the session is a guest account.
+    pub(crate) is_guest: Arc<AtomicBool>,
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -65,6 +79,120 @@ impl fmt::Debug for MatrixAuthData {
     }
 }
 
+/// A task refreshing the access token shortly before it expires, when the
+/// homeserver told us how long it is valid for.
+pub(crate) struct TokenRefreshTask {
+    clock: Arc<dyn Clock>,
+    sender: mpsc::UnboundedSender<Instant>,
+    #[allow(dead_code)]
+    join_handle: JoinHandle<()>,
+}
+
+impl Drop for TokenRefreshTask {
+    fn drop(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.join_handle.abort();
+    }
+}
+
+impl TokenRefreshTask {
+    /// How long before its expiry the access token is refreshed.
+    pub(crate) const REFRESH_MARGIN: Duration = Duration::from_secs(30);
+
+    /// How many times a failed refresh is retried before giving up until the
+    /// next time the token is refreshed.
+    const MAX_RETRIES: u32 = 5;
+
+    fn new(client: Weak<ClientInner>, clock: Arc<dyn Clock>) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let join_handle = spawn(Self::listen(client, clock.clone(), receiver));
+
+        Self { clock, sender, join_handle }
+    }
+
+    /// Refresh the access token before it expires in `expires_in`, replacing
+    /// any refresh that was already scheduled.
+    fn schedule(&self, expires_in: Duration) {
+        // Compute the deadline now, the task might only get to it later.
+        let deadline = self.clock.now() + expires_in.saturating_sub(Self::REFRESH_MARGIN);
+        let _ = self.sender.send(deadline);
+    }
+
+    async fn listen(
+        client: Weak<ClientInner>,
+        clock: Arc<dyn Clock>,
+        mut receiver: mpsc::UnboundedReceiver<Instant>,
+    ) {
+        let Some(mut deadline) = receiver.recv().await else {
+            return;
+        };
+        let mut attempt = 0;
+
+        loop {
+            let rescheduled = {
+                let next = receiver.recv();
+                let sleep = clock.sleep(deadline.saturating_duration_since(clock.now()));
+                pin_mut!(next, sleep);
+
+                match future::select(next, sleep).await {
+                    Either::Left((Some(deadline), _)) => Some(deadline),
+                    Either::Left((None, _)) => return,
+                    Either::Right(_) => None,
+                }
+            };
+
+            if let Some(new_deadline) = rescheduled {
+                deadline = new_deadline;
+                attempt = 0;
+                continue;
+            }
+
+            let result = {
+                let Some(client) = client.upgrade() else {
+                    trace!("Client got dropped, shutting down the task");
+                    return;
+                };
+                let client = Client { inner: client };
+
+                // A successful refresh schedules the next one.
+                client.matrix_auth().refresh_access_token().await
+            };
+
+            if let Err(error) = result {
+                if attempt < Self::MAX_RETRIES && Self::is_retryable(&error) {
+                    let delay = Duration::from_secs(1 << attempt);
+                    warn!(?delay, "Couldn't refresh the access token, retrying: {error}");
+
+                    deadline = clock.now() + delay;
+                    attempt += 1;
+                    continue;
+                }
+
+                warn!("Couldn't refresh the access token before it expired: {error}");
+            }
+
+            attempt = 0;
+
+            match receiver.recv().await {
+                Some(new_deadline) => deadline = new_deadline,
+                None => return,
+            }
+        }
+    }
+
+    /// Whether trying to refresh the access token again could succeed.
+    fn is_retryable(error: &RefreshTokenError) -> bool {
+        match error {
+            RefreshTokenError::RefreshTokenRequired => false,
+            RefreshTokenError::MatrixAuth(error) => {
+                !matches!(error.client_api_error_kind(), Some(ErrorKind::UnknownToken { .. }))
+            }
+            #[cfg(feature = "experimental-oidc")]
+            RefreshTokenError::Oidc(_) => true,
+        }
+    }
+}
+
 /// A high-level API to interact with the native Matrix authentication API.
 ///
 /// To access this API, use [`Client::matrix_auth()`].
@@ -482,6 +610,7 @@ impl MatrixAuth {
                 }
 
                 self.set_session_tokens(session_tokens);
+                self.schedule_token_refresh(res.expires_in);
 
                 if let Some(save_session_callback) =
                     self.client.inner.auth_ctx.save_session_callback.get()
@@ -581,7 +710,10 @@ impl MatrixAuth {
                 .inner
                 .auth_ctx
                 .auth_data
-                .set(AuthData::Matrix(MatrixAuthData { tokens: SharedObservable::new(tokens) }))
+                .set(AuthData::Matrix(MatrixAuthData {
+                    tokens: SharedObservable::new(tokens),
+                    is_guest: Default::default(),
+                }))
                 .expect("We just checked the value was not set");
         }
     }
@@ -805,6 +937,7 @@ impl MatrixAuth {
     pub async fn restore_session(&self, session: MatrixSession) -> Result<()> {
         debug!("Restoring Matrix auth session");
         self.set_session(session).await?;
+        self.restore_guest_flag().await?;
         debug!("Done restoring Matrix auth session");
         Ok(())
     }
@@ -822,10 +955,36 @@ impl MatrixAuth {
         self.client.maybe_update_login_well_known(response.well_known.as_ref());
 
         self.set_session(response.into()).await?;
+        self.schedule_token_refresh(response.expires_in);
 
         Ok(())
     }
 
+    /// Refresh the access token shortly before it expires, if the client
+    /// handles refresh tokens and the homeserver told us when it expires.
+    fn schedule_token_refresh(&self, expires_in: Option<Duration>) {
+        let auth_ctx = &self.client.inner.auth_ctx;
+
+        let Some(expires_in) = expires_in else {
+            return;
+        };
+        if !auth_ctx.handle_refresh_tokens || self.refresh_token().is_none() {
+            return;
+        }
+
+        auth_ctx
+            .refresh_token_task
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| {
+                TokenRefreshTask::new(
+                    Arc::downgrade(&self.client.inner),
+                    self.client.base_client().clock().clone(),
+                )
+            })
+            .schedule(expires_in);
+    }
+
     async fn set_session(&self, session: MatrixSession) -> Result<()> {
         self.set_session_tokens(session.tokens);
         self.client.set_session_meta(session.meta).await?;
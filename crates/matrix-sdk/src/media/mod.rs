@@ -36,7 +36,7 @@ use ruma::{
             ImageMessageEventContent, MessageType, UnstableAudioDetailsContentBlock,
             UnstableVoiceContentBlock, VideoInfo, VideoMessageEventContent,
         },
-        ImageInfo, MediaSource, ThumbnailInfo,
+        EncryptedFile, ImageInfo, MediaSource, ThumbnailInfo,
     },
     MxcUri,
 };
@@ -45,12 +45,15 @@ use tempfile::{Builder as TempFileBuilder, NamedTempFile, TempDir};
 #[cfg(not(target_arch = "wasm32"))]
 use tokio::{fs::File as TokioFile, io::AsyncWriteExt};
 
+pub use self::prefetch::{MediaPrefetcher, PrefetchStatus};
 use crate::{
     attachment::{AttachmentInfo, Thumbnail},
     futures::SendRequest,
     Client, Result, TransmissionProgress,
 };
 
+mod prefetch;
+
 /// A conservative upload speed of 1Mbps
 const DEFAULT_UPLOAD_SPEED: u64 = 125_000;
 /// 5 min minimal upload request timeout, used to clamp the request timeout.
@@ -254,16 +257,28 @@ impl Media {
     /// If the content is encrypted and encryption is enabled, the content will
     /// be decrypted.
     ///
+    /// If a content scanner is configured, the content is downloaded through
+    /// it, and this fails with [`ContentScannerError::NotClean`] if the scanner
+    /// refuses to serve it.
+    ///
     /// # Arguments
     ///
     /// * `request` - The `MediaRequest` of the content.
     ///
     /// * `use_cache` - If we should use the media cache for this request.
+    ///
+    /// [`ContentScannerError::NotClean`]: crate::content_scanner::ContentScannerError::NotClean
     pub async fn get_media_content(
         &self,
         request: &MediaRequest,
         use_cache: bool,
     ) -> Result<Vec<u8>> {
+        // Media is only downloaded through the content scanner if there is one.
+        if self.client.inner.content_scanner.is_some() {
+            let scanned = self.get_scanned_media_content(request, use_cache).await?;
+            return Ok(scanned.into_content()?);
+        }
+
         // Read from the cache.
         if use_cache {
             if let Some(content) = self.client.store().get_media_content(request).await? {
@@ -276,21 +291,7 @@ impl Media {
                 let request = get_content::v3::Request::from_url(&file.url)?;
                 let content: Vec<u8> = self.client.send(request, None).await?.file;
 
-                #[cfg(feature = "e2e-encryption")]
-                let content = {
-                    let mut cursor = std::io::Cursor::new(content);
-                    let mut reader = matrix_sdk_base::crypto::AttachmentDecryptor::new(
-                        &mut cursor,
-                        file.as_ref().clone().into(),
-                    )?;
-
-                    let mut decrypted = Vec::new();
-                    reader.read_to_end(&mut decrypted)?;
-
-                    decrypted
-                };
-
-                content
+                decrypt_media_content(file, content)?
             }
             MediaSource::Plain(uri) => {
                 if let MediaFormat::Thumbnail(size) = &request.format {
@@ -306,18 +307,71 @@ impl Media {
 
         if use_cache {
             self.client.store().add_media_content(request, content.clone()).await?;
+            self.client.store_cleanup().on_media_added(request, content.len()).await;
         }
 
         Ok(content)
     }
 
+    /// Create a [`MediaPrefetcher`], to download the media that the UI is
+    /// about to display into the media cache in the background.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_concurrent_downloads` - The maximum number of media to download
+    ///   at the same time.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::{
+    /// #     media::{MediaFormat, MediaRequest},
+    /// #     ruma::events::room::MediaSource,
+    /// #     Client,
+    /// # };
+    /// # async {
+    /// # let client: Client = todo!();
+    /// # let visible_sources: Vec<MediaSource> = todo!();
+    /// let prefetcher = client.media().prefetcher(4);
+    ///
+    /// // Every time the visible part of the timeline changes.
+    /// prefetcher.hint(
+    ///     visible_sources
+    ///         .into_iter()
+    ///         .map(|source| MediaRequest { source, format: MediaFormat::File }),
+    /// );
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub fn prefetcher(&self, max_concurrent_downloads: usize) -> MediaPrefetcher {
+        MediaPrefetcher::new(self.client.clone(), max_concurrent_downloads)
+    }
+
+    /// Whether the media prefetchers of this client hold off starting new
+    /// downloads.
+    pub fn is_prefetch_deferred(&self) -> bool {
+        self.client.inner.media_prefetch_deferred.get()
+    }
+
+    /// Make the media prefetchers of this client hold off starting new
+    /// downloads, for example while the device is on a metered connection.
+    ///
+    /// The hinted media stay queued and the downloads in progress are not
+    /// cancelled. The queued media start downloading once the prefetching
+    /// isn't deferred anymore.
+    pub fn set_prefetch_deferred(&self, deferred: bool) {
+        self.client.inner.media_prefetch_deferred.set_if_not_eq(deferred);
+    }
+
     /// Remove a media file's content from the store.
     ///
     /// # Arguments
     ///
     /// * `request` - The `MediaRequest` of the content.
     pub async fn remove_media_content(&self, request: &MediaRequest) -> Result<()> {
-        Ok(self.client.store().remove_media_content(request).await?)
+        self.client.store().remove_media_content(request).await?;
+        self.client.store_cleanup().on_media_removed(request).await;
+
+        Ok(())
     }
 
     /// Delete all the media content corresponding to the given
@@ -327,7 +381,10 @@ impl Media {
     ///
     /// * `uri` - The `MxcUri` of the files.
     pub async fn remove_media_content_for_uri(&self, uri: &MxcUri) -> Result<()> {
-        Ok(self.client.store().remove_media_content_for_uri(uri).await?)
+        self.client.store().remove_media_content_for_uri(uri).await?;
+        self.client.store_cleanup().on_media_removed_for_uri(uri).await;
+
+        Ok(())
     }
 
     /// Get the file of the given media event content.
@@ -534,9 +591,9 @@ pub(crate) fn update_audio_message_event(
     content_type: &Mime,
     info: Option<AttachmentInfo>,
 ) -> AudioMessageEventContent {
-    if let Some(AttachmentInfo::Voice { audio_info, waveform: Some(waveform_vec) }) = &info {
+    if let Some(AttachmentInfo::Voice { audio_info, waveform }) = &info {
         if let Some(duration) = audio_info.duration {
-            let waveform = waveform_vec.iter().map(|v| (*v).into()).collect();
+            let waveform = waveform.iter().flatten().map(|v| (*v).into()).collect();
             audio_message_event_content.audio =
                 Some(UnstableAudioDetailsContentBlock::new(duration, waveform));
         }
@@ -546,3 +603,25 @@ pub(crate) fn update_audio_message_event(
     let audio_info = assign!(info.map(AudioInfo::from).unwrap_or_default(), {mimetype: Some(content_type.as_ref().to_owned()), });
     audio_message_event_content.info(Box::new(audio_info))
 }
+
+/// Decrypt the content of an encrypted media file.
+///
+/// The content is returned as is if encryption is disabled.
+pub(crate) fn decrypt_media_content(file: &EncryptedFile, content: Vec<u8>) -> Result<Vec<u8>> {
+    #[cfg(feature = "e2e-encryption")]
+    let content = {
+        let mut cursor = std::io::Cursor::new(content);
+        let mut reader =
+            matrix_sdk_base::crypto::AttachmentDecryptor::new(&mut cursor, file.clone().into())?;
+
+        let mut decrypted = Vec::new();
+        reader.read_to_end(&mut decrypted)?;
+
+        decrypted
+    };
+
+    #[cfg(not(feature = "e2e-encryption"))]
+    let _ = file;
+
+    Ok(content)
+}
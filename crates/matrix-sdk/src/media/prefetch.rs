@@ -0,0 +1,312 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Prefetching of the media that the UI is about to display.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::{Arc, Mutex as StdMutex, Weak},
+};
+
+use eyeball::{SharedObservable, Subscriber};
+use futures_util::future::{AbortHandle, Abortable, Aborted};
+use matrix_sdk_base::media::{MediaRequest, UniqueKey};
+use matrix_sdk_common::{
+    executor::{spawn, JoinHandle},
+    instant::Instant,
+};
+use tracing::{debug, trace};
+
+use crate::Client;
+
+/// How many positions a queued media moves up in the queue for every second
+/// it waits, so that media hinted low in the list are eventually downloaded
+/// even if new hints keep coming in.
+const AGING_RATE: f64 = 2.0;
+
+/// The status of a [`MediaPrefetcher`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PrefetchStatus {
+    /// The number of media waiting to be downloaded.
+    pub queued: usize,
+    /// The number of media being downloaded.
+    pub downloading: usize,
+    /// The number of media that were downloaded, or were in the media cache
+    /// already.
+    pub downloaded: u64,
+    /// The number of media that couldn't be downloaded.
+    pub failed: u64,
+}
+
+impl PrefetchStatus {
+    /// Whether there is nothing left to download.
+    pub fn is_idle(&self) -> bool {
+        self.queued == 0 && self.downloading == 0
+    }
+}
+
+/// A scheduler downloading the media that the UI is about to display into the
+/// media cache, for example the thumbnails of the visible part of a timeline.
+///
+/// The UI hints the media it wants with [`MediaPrefetcher::hint()`], and they
+/// are downloaded in the background, a bounded number at a time. Each hint
+/// supersedes the previous one: the media that aren't hinted anymore are not
+/// downloaded, or their download is cancelled. The media that were hinted
+/// for a while are downloaded first, then the ones at the start of the last
+/// hint.
+///
+/// Once downloaded, the media are returned from the cache by
+/// [`Media::get_media_content()`](super::Media::get_media_content).
+///
+/// No new download is started while the prefetching is deferred with
+/// [`Media::set_prefetch_deferred()`](super::Media::set_prefetch_deferred).
+///
+/// To get one, use [`Media::prefetcher()`](super::Media::prefetcher). The
+/// downloads are cancelled when the last clone of the `MediaPrefetcher` is
+/// dropped.
+#[derive(Clone)]
+pub struct MediaPrefetcher {
+    inner: Arc<PrefetcherInner>,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl fmt::Debug for MediaPrefetcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MediaPrefetcher")
+            .field("max_concurrent_downloads", &self.inner.max_concurrent_downloads)
+            .field("status", &self.inner.status.get())
+            .finish_non_exhaustive()
+    }
+}
+
+struct PrefetcherInner {
+    client: Client,
+    max_concurrent_downloads: usize,
+    state: StdMutex<PrefetchState>,
+    status: SharedObservable<PrefetchStatus>,
+    /// Aborts the task resuming the downloads when the prefetching isn't
+    /// deferred anymore.
+    deferral_task: AbortHandle,
+    /// The handle of the same task, which must be kept alive since dropping it
+    /// cancels the task on wasm.
+    _deferral_task_handle: JoinHandle<Result<(), Aborted>>,
+}
+
+#[derive(Default)]
+struct PrefetchState {
+    queue: Vec<QueuedMedia>,
+    /// The downloads in progress, by unique key of the media request.
+    downloads: HashMap<String, Download>,
+    next_download_id: u64,
+    downloaded: u64,
+    failed: u64,
+}
+
+struct QueuedMedia {
+    key: String,
+    request: MediaRequest,
+    /// The position of the media in the last hint.
+    position: usize,
+    /// When the media was first hinted, for its priority to increase while it
+    /// waits.
+    first_hinted: Instant,
+}
+
+impl QueuedMedia {
+    /// The priority of the media, lower is more urgent.
+    fn priority(&self, now: Instant) -> f64 {
+        let waited = now.duration_since(self.first_hinted).as_secs_f64();
+        self.position as f64 - waited * AGING_RATE
+    }
+}
+
+struct Download {
+    /// A unique ID for the download, to tell it apart from a new download of
+    /// the same media after a cancellation.
+    id: u64,
+    #[allow(dead_code)]
+    join_handle: JoinHandle<()>,
+}
+
+impl Download {
+    fn cancel(self) {
+        // On wasm, dropping the handle is enough to cancel the task.
+        #[cfg(not(target_arch = "wasm32"))]
+        self.join_handle.abort();
+    }
+}
+
+impl MediaPrefetcher {
+    pub(super) fn new(client: Client, max_concurrent_downloads: usize) -> Self {
+        let inner = Arc::new_cyclic(|weak: &Weak<PrefetcherInner>| {
+            let (deferral_task, registration) = AbortHandle::new_pair();
+            let deferral_task_handle = spawn(Abortable::new(
+                PrefetcherInner::resume_when_undeferred(
+                    weak.clone(),
+                    client.inner.media_prefetch_deferred.subscribe(),
+                ),
+                registration,
+            ));
+
+            PrefetcherInner {
+                client,
+                max_concurrent_downloads: max_concurrent_downloads.max(1),
+                state: Default::default(),
+                status: Default::default(),
+                deferral_task,
+                _deferral_task_handle: deferral_task_handle,
+            }
+        });
+
+        Self { inner }
+    }
+
+    /// Hint the media that the UI is about to display, most urgent first.
+    ///
+    /// This supersedes the previous hint: the media that aren't in `requests`
+    /// anymore are removed from the queue and their download is cancelled.
+    pub fn hint(&self, requests: impl IntoIterator<Item = MediaRequest>) {
+        let now = self.inner.client.base_client().clock().now();
+        let mut state = self.inner.state.lock().unwrap();
+
+        let mut previously_queued: HashMap<_, _> =
+            state.queue.drain(..).map(|queued| (queued.key, queued.first_hinted)).collect();
+        let mut hinted = HashSet::new();
+        let mut queue = Vec::new();
+
+        for (position, request) in requests.into_iter().enumerate() {
+            let key = request.unique_key();
+            if !hinted.insert(key.clone()) || state.downloads.contains_key(&key) {
+                continue;
+            }
+
+            let first_hinted = previously_queued.remove(&key).unwrap_or(now);
+            queue.push(QueuedMedia { key, request, position, first_hinted });
+        }
+
+        let superseded: Vec<_> =
+            state.downloads.keys().filter(|key| !hinted.contains(*key)).cloned().collect();
+        for key in superseded {
+            if let Some(download) = state.downloads.remove(&key) {
+                debug!(key, "Cancelling the download of a media that isn't hinted anymore");
+                download.cancel();
+            }
+        }
+
+        state.queue = queue;
+        self.inner.schedule(&mut state);
+    }
+
+    /// Remove all the media from the queue and cancel their downloads.
+    pub fn cancel_all(&self) {
+        self.hint([]);
+    }
+
+    /// Get the current status of the prefetcher.
+    pub fn status(&self) -> PrefetchStatus {
+        self.inner.status.get()
+    }
+
+    /// Subscribe to the updates of the status of the prefetcher.
+    pub fn subscribe_to_status(&self) -> Subscriber<PrefetchStatus> {
+        self.inner.status.subscribe()
+    }
+}
+
+impl PrefetcherInner {
+    /// Start downloading the most urgent queued media, as long as there are
+    /// free download slots.
+    fn schedule(self: &Arc<Self>, state: &mut PrefetchState) {
+        let now = self.client.base_client().clock().now();
+        let deferred = self.client.inner.media_prefetch_deferred.get();
+
+        while !deferred && state.downloads.len() < self.max_concurrent_downloads {
+            let next = state
+                .queue
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| a.priority(now).total_cmp(&b.priority(now)))
+                .map(|(index, _)| index);
+            let Some(index) = next else { break };
+
+            let QueuedMedia { key, request, .. } = state.queue.swap_remove(index);
+            let id = state.next_download_id;
+            state.next_download_id += 1;
+
+            trace!(key, "Prefetching a media");
+            let join_handle = spawn(Self::download(Arc::downgrade(self), key.clone(), id, request));
+            state.downloads.insert(key, Download { id, join_handle });
+        }
+
+        self.status.set_if_not_eq(PrefetchStatus {
+            queued: state.queue.len(),
+            downloading: state.downloads.len(),
+            downloaded: state.downloaded,
+            failed: state.failed,
+        });
+    }
+
+    async fn resume_when_undeferred(this: Weak<Self>, mut deferred: Subscriber<bool>) {
+        while let Some(is_deferred) = deferred.next().await {
+            if is_deferred {
+                continue;
+            }
+
+            let Some(this) = this.upgrade() else { return };
+            trace!("Resuming the prefetching of the media");
+            let mut state = this.state.lock().unwrap();
+            this.schedule(&mut state);
+        }
+    }
+
+    async fn download(this: Weak<Self>, key: String, id: u64, request: MediaRequest) {
+        // Don't keep the prefetcher alive during the download, so dropping it
+        // cancels the downloads.
+        let Some(client) = this.upgrade().map(|this| this.client.clone()) else { return };
+
+        let result = client.media().get_media_content(&request, true).await;
+        if let Err(e) = &result {
+            debug!(key, "Couldn't prefetch a media: {e}");
+        }
+
+        let Some(this) = this.upgrade() else { return };
+        let mut state = this.state.lock().unwrap();
+
+        // The download could have been cancelled while it was finishing.
+        if state.downloads.get(&key).map_or(true, |download| download.id != id) {
+            return;
+        }
+        state.downloads.remove(&key);
+
+        if result.is_ok() {
+            state.downloaded += 1;
+        } else {
+            state.failed += 1;
+        }
+
+        this.schedule(&mut state);
+    }
+}
+
+impl Drop for PrefetcherInner {
+    fn drop(&mut self) {
+        let state = self.state.get_mut().unwrap();
+        for (_, download) in state.downloads.drain() {
+            download.cancel();
+        }
+
+        self.deferral_task.abort();
+    }
+}
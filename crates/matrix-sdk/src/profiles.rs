@@ -0,0 +1,368 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A long-term cache of the profiles of users, for the places where they are
+//! shown outside of a room, like user pickers or mentions.
+//!
+//! The profiles are fetched from the homeserver on demand, and kept in the
+//! state store, one entry per user. They are refreshed when they are older
+//! than a time-to-live. For the users whose profile is cached, a presence event
+//! updates the parts of the profile it contains, and a member event with a
+//! different display name or avatar triggers a refresh, since the member
+//! event might only hold a name that is specific to the room.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Mutex as StdMutex, RwLock as StdRwLock},
+    time::Duration,
+};
+
+use eyeball::{SharedObservable, Subscriber};
+use matrix_sdk_base::sync::Rooms;
+use ruma::{
+    events::{
+        presence::PresenceEvent,
+        room::member::{MembershipState, SyncRoomMemberEvent},
+        AnySyncTimelineEvent,
+    },
+    serde::Raw,
+    MilliSecondsSinceUnixEpoch, OwnedMxcUri, OwnedUserId, UserId,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{debug, instrument, warn};
+
+use crate::{executor::spawn, Client, Result};
+
+/// The prefix of the keys of the cached profiles in the custom values of the
+/// state store.
+const PROFILE_KEY_PREFIX: &str = "matrix-sdk.profiles.";
+
+/// The default time after which a cached profile is fetched again.
+const DEFAULT_TIME_TO_LIVE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The profile of a user, as cached by [`Profiles`].
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct UserProfile {
+    /// The display name of the user, if any.
+    pub display_name: Option<String>,
+
+    /// The avatar URL of the user, if any.
+    pub avatar_url: Option<OwnedMxcUri>,
+
+    /// When the profile was last fetched from the homeserver, or updated by a
+    /// sync.
+    pub updated_at: MilliSecondsSinceUnixEpoch,
+}
+
+impl UserProfile {
+    fn has_same_content(&self, other: &Self) -> bool {
+        self.display_name == other.display_name && self.avatar_url == other.avatar_url
+    }
+}
+
+/// The key of the cached profile of a user in the custom values of the state
+/// store.
+fn profile_key(user_id: &UserId) -> Vec<u8> {
+    format!("{PROFILE_KEY_PREFIX}{user_id}").into_bytes()
+}
+
+/// The state of the profile cache, shared by all the handles of a client.
+pub(crate) struct ProfilesState {
+    /// The profiles that were loaded from the store, `None` for the users
+    /// whose profile isn't cached.
+    ///
+    /// The lock also serializes the updates of the stored profiles.
+    cache: Mutex<BTreeMap<OwnedUserId, Option<UserProfile>>>,
+    /// The observables of the profiles that were subscribed to.
+    observables: StdMutex<BTreeMap<OwnedUserId, SharedObservable<Option<UserProfile>>>>,
+    time_to_live: StdRwLock<Duration>,
+}
+
+impl Default for ProfilesState {
+    fn default() -> Self {
+        Self {
+            cache: Default::default(),
+            observables: Default::default(),
+            time_to_live: StdRwLock::new(DEFAULT_TIME_TO_LIVE),
+        }
+    }
+}
+
+/// The cache of the profiles of users.
+///
+/// Unlike the members of a room, it can hold the display name and avatar of
+/// any user, for example to show them in a user picker.
+///
+/// Get access to it with [`Client::profiles()`].
+#[derive(Debug)]
+pub struct Profiles {
+    client: Client,
+}
+
+impl Profiles {
+    pub(crate) fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    /// Set the time after which a cached profile is fetched again from the
+    /// homeserver.
+    ///
+    /// Defaults to one day.
+    pub fn set_time_to_live(&self, time_to_live: Duration) {
+        *self.client.inner.profiles.time_to_live.write().unwrap() = time_to_live;
+    }
+
+    /// Get the profile of the given user.
+    ///
+    /// The cached profile is returned if it is more recent than the
+    /// time-to-live, otherwise it is fetched from the homeserver. If that
+    /// fails, the outdated cached profile is returned, if any.
+    #[instrument(skip(self))]
+    pub async fn get(&self, user_id: &UserId) -> Result<UserProfile> {
+        let cached = self.cached(user_id).await?;
+
+        match cached {
+            Some(profile) if self.is_fresh(&profile) => Ok(profile),
+            Some(profile) => match self.refresh(user_id).await {
+                Ok(profile) => Ok(profile),
+                Err(e) => {
+                    warn!("Couldn't refresh an outdated profile: {e}");
+                    Ok(profile)
+                }
+            },
+            None => self.refresh(user_id).await,
+        }
+    }
+
+    /// Get the cached profile of the given user, without fetching it from the
+    /// homeserver.
+    pub async fn cached(&self, user_id: &UserId) -> Result<Option<UserProfile>> {
+        let mut cache = self.client.inner.profiles.cache.lock().await;
+        self.loaded(&mut cache, user_id).await
+    }
+
+    /// Fetch the profile of the given user from the homeserver, and update
+    /// the cache with it.
+    #[instrument(skip(self))]
+    pub async fn refresh(&self, user_id: &UserId) -> Result<UserProfile> {
+        let response = self.client.get_profile(user_id).await?;
+        let profile = UserProfile {
+            display_name: response.displayname,
+            avatar_url: response.avatar_url,
+            updated_at: self.client.base_client().clock().now_ms(),
+        };
+
+        let mut cache = self.client.inner.profiles.cache.lock().await;
+        let old = self.loaded(&mut cache, user_id).await?;
+        self.insert(&mut cache, old.as_ref(), user_id, profile.clone()).await?;
+
+        Ok(profile)
+    }
+
+    /// Get a subscriber to the profile of the given user.
+    ///
+    /// Its value is the cached profile, if any. If there is none, or if it is
+    /// outdated, the profile is fetched from the homeserver in the background.
+    pub async fn subscribe(&self, user_id: &UserId) -> Result<Subscriber<Option<UserProfile>>> {
+        let cached = self.cached(user_id).await?;
+
+        let subscriber = self
+            .client
+            .inner
+            .profiles
+            .observables
+            .lock()
+            .unwrap()
+            .entry(user_id.to_owned())
+            .or_insert_with(|| SharedObservable::new(cached.clone()))
+            .subscribe();
+
+        if !cached.is_some_and(|profile| self.is_fresh(&profile)) {
+            let profiles = Self::new(self.client.clone());
+            let user_id = user_id.to_owned();
+            spawn(async move {
+                if let Err(e) = profiles.refresh(&user_id).await {
+                    debug!(?user_id, "Couldn't fetch the profile of a user: {e}");
+                }
+            });
+        }
+
+        Ok(subscriber)
+    }
+
+    fn is_fresh(&self, profile: &UserProfile) -> bool {
+        let now = self.client.base_client().clock().now_ms();
+        let age = u64::from(now.0).saturating_sub(profile.updated_at.0.into());
+        let time_to_live = *self.client.inner.profiles.time_to_live.read().unwrap();
+
+        u128::from(age) < time_to_live.as_millis()
+    }
+
+    /// Update the cached profile of a user, and notify its subscribers if the
+    /// display name or avatar changed.
+    async fn insert(
+        &self,
+        cache: &mut BTreeMap<OwnedUserId, Option<UserProfile>>,
+        old: Option<&UserProfile>,
+        user_id: &UserId,
+        profile: UserProfile,
+    ) -> Result<()> {
+        let value = serde_json::to_vec(&profile)?;
+        self.client.store().set_custom_value(&profile_key(user_id), value).await?;
+
+        if old.map_or(true, |old| !old.has_same_content(&profile)) {
+            if let Some(observable) =
+                self.client.inner.profiles.observables.lock().unwrap().get(user_id)
+            {
+                observable.set(Some(profile.clone()));
+            }
+        }
+
+        cache.insert(user_id.to_owned(), Some(profile));
+
+        Ok(())
+    }
+
+    /// Get the cached profile of a user, loading it from the store if needed.
+    async fn loaded(
+        &self,
+        cache: &mut BTreeMap<OwnedUserId, Option<UserProfile>>,
+        user_id: &UserId,
+    ) -> Result<Option<UserProfile>> {
+        if let Some(profile) = cache.get(user_id) {
+            return Ok(profile.clone());
+        }
+
+        let profile = match self.client.store().get_custom_value(&profile_key(user_id)).await? {
+            Some(value) => Some(serde_json::from_slice(&value)?),
+            None => None,
+        };
+        cache.insert(user_id.to_owned(), profile.clone());
+
+        Ok(profile)
+    }
+
+    /// Update the cached profiles with the presence and member events of a
+    /// sync.
+    ///
+    /// Only the profiles that are cached already are updated, the cache isn't
+    /// meant to hold the profiles of all the members of all the rooms.
+    async fn update_from_sync(&self, presence: &[Raw<PresenceEvent>], rooms: &Rooms) -> Result<()> {
+        let mut presence_updates = BTreeMap::new();
+
+        for event in presence {
+            match event.deserialize() {
+                Ok(event) => {
+                    let content = event.content;
+                    if content.displayname.is_some() || content.avatar_url.is_some() {
+                        presence_updates
+                            .insert(event.sender, (content.displayname, content.avatar_url));
+                    }
+                }
+                Err(e) => debug!("Couldn't deserialize a presence event: {e}"),
+            }
+        }
+
+        let mut member_updates = BTreeMap::new();
+
+        for room_info in rooms.join.values() {
+            let state = room_info.state.iter().map(|event| event.cast_ref());
+            let timeline = room_info.timeline.events.iter().map(|event| &event.event);
+
+            for event in state.chain(timeline) {
+                if let Some((user_id, display_name, avatar_url)) = joined_member_profile(event) {
+                    member_updates.insert(user_id, (display_name, avatar_url));
+                }
+            }
+        }
+
+        if presence_updates.is_empty() && member_updates.is_empty() {
+            return Ok(());
+        }
+
+        let now = self.client.base_client().clock().now_ms();
+        let mut to_refresh = BTreeSet::new();
+
+        {
+            let mut cache = self.client.inner.profiles.cache.lock().await;
+
+            // The presence holds the global profile of the user, but maybe only a part of
+            // it.
+            for (user_id, (display_name, avatar_url)) in presence_updates {
+                let Some(old) = self.loaded(&mut cache, &user_id).await? else { continue };
+
+                let profile = UserProfile {
+                    display_name: display_name.or_else(|| old.display_name.clone()),
+                    avatar_url: avatar_url.or_else(|| old.avatar_url.clone()),
+                    updated_at: now,
+                };
+                self.insert(&mut cache, Some(&old), &user_id, profile).await?;
+            }
+
+            // The member event might hold a profile that is specific to the room, only
+            // use it as a hint that the global profile changed.
+            for (user_id, (display_name, avatar_url)) in member_updates {
+                let Some(old) = self.loaded(&mut cache, &user_id).await? else { continue };
+
+                if old.display_name != display_name || old.avatar_url != avatar_url {
+                    to_refresh.insert(user_id);
+                }
+            }
+        }
+
+        if !to_refresh.is_empty() {
+            let profiles = Self::new(self.client.clone());
+            spawn(async move {
+                for user_id in to_refresh {
+                    if let Err(e) = profiles.refresh(&user_id).await {
+                        debug!(?user_id, "Couldn't fetch the profile of a user: {e}");
+                    }
+                }
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Get the profile of the user of an `m.room.member` event, if it's a join.
+fn joined_member_profile(
+    event: &Raw<AnySyncTimelineEvent>,
+) -> Option<(OwnedUserId, Option<String>, Option<OwnedMxcUri>)> {
+    if event.get_field::<String>("type").ok().flatten().as_deref() != Some("m.room.member") {
+        return None;
+    }
+
+    let SyncRoomMemberEvent::Original(event) = event.deserialize_as().ok()? else {
+        return None;
+    };
+
+    (event.content.membership == MembershipState::Join)
+        .then(|| (event.state_key, event.content.displayname, event.content.avatar_url))
+}
+
+impl Client {
+    /// Get the cache of the profiles of users.
+    pub fn profiles(&self) -> Profiles {
+        Profiles::new(self.clone())
+    }
+
+    /// Update the cached profiles after a sync.
+    pub(crate) async fn update_profiles(&self, presence: &[Raw<PresenceEvent>], rooms: &Rooms) {
+        if let Err(e) = self.profiles().update_from_sync(presence, rooms).await {
+            warn!("Couldn't update the cached profiles: {e}");
+        }
+    }
+}
@@ -0,0 +1,324 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Delayed events, which are sent by the homeserver once a delay expired, as
+//! defined in [MSC4140].
+//!
+//! [MSC4140]: https://github.com/matrix-org/matrix-spec-proposals/pull/4140
+
+use std::time::Duration;
+
+use bytes::BufMut;
+use ruma::{
+    api::{
+        client::Error,
+        error::{FromHttpResponseError, IntoHttpError},
+        metadata, EndpointError, IncomingResponse, MatrixVersion, Metadata, OutgoingRequest,
+        SendAccessToken,
+    },
+    OwnedRoomId, OwnedTransactionId, TransactionId,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue as RawJsonValue;
+use tracing::instrument;
+
+use super::Room;
+use crate::{
+    utils::{IntoRawMessageLikeEventContent, IntoRawStateEventContent},
+    Result,
+};
+
+/// The name of the query parameter with the delay of the event, in
+/// milliseconds.
+const DELAY_QUERY_PARAMETER: &str = "org.matrix.msc4140.delay";
+
+/// An action to apply to a delayed event that wasn't sent yet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DelayedEventAction {
+    /// Don't send the event.
+    Cancel,
+    /// Start the delay again, from now.
+    Restart,
+    /// Send the event now.
+    Send,
+}
+
+impl Room {
+    /// Ask the homeserver to send a message-like event to this room once the
+    /// given delay expired, as defined in [MSC4140].
+    ///
+    /// The event is not encrypted, so this fails in encrypted rooms.
+    ///
+    /// Returns the ID of the delayed event, which can be used with
+    /// [`Room::update_delayed_event()`] to cancel it, restart its delay, or
+    /// send it right away.
+    ///
+    /// [MSC4140]: https://github.com/matrix-org/matrix-spec-proposals/pull/4140
+    #[instrument(skip_all, fields(room_id = ?self.room_id(), event_type))]
+    pub async fn send_raw_delayed(
+        &self,
+        event_type: &str,
+        content: impl IntoRawMessageLikeEventContent,
+        delay: Duration,
+    ) -> Result<String> {
+        self.ensure_room_joined()?;
+
+        if self.is_encrypted().await? {
+            return Err(crate::Error::UnknownError(
+                "delayed events can't be sent to encrypted rooms".into(),
+            ));
+        }
+
+        let request = SendDelayedMessageLikeEventRequest {
+            room_id: self.room_id().to_owned(),
+            event_type: event_type.to_owned(),
+            txn_id: TransactionId::new(),
+            delay,
+            content: content.into_raw_message_like_event_content().into_json(),
+        };
+
+        Ok(self.client.send(request, None).await?.delay_id)
+    }
+
+    /// Ask the homeserver to send a state event to this room once the given
+    /// delay expired, as defined in [MSC4140].
+    ///
+    /// Returns the ID of the delayed event, which can be used with
+    /// [`Room::update_delayed_event()`] to cancel it, restart its delay, or
+    /// send it right away.
+    ///
+    /// [MSC4140]: https://github.com/matrix-org/matrix-spec-proposals/pull/4140
+    #[instrument(skip_all, fields(room_id = ?self.room_id(), event_type))]
+    pub async fn send_state_event_raw_delayed(
+        &self,
+        event_type: &str,
+        state_key: &str,
+        content: impl IntoRawStateEventContent,
+        delay: Duration,
+    ) -> Result<String> {
+        self.ensure_room_joined()?;
+
+        let request = SendDelayedStateEventRequest {
+            room_id: self.room_id().to_owned(),
+            event_type: event_type.to_owned(),
+            state_key: state_key.to_owned(),
+            delay,
+            content: content.into_raw_state_event_content().into_json(),
+        };
+
+        Ok(self.client.send(request, None).await?.delay_id)
+    }
+
+    /// Cancel, restart or send right away a delayed event that was scheduled
+    /// with [`Room::send_raw_delayed()`] or
+    /// [`Room::send_state_event_raw_delayed()`].
+    #[instrument(skip(self), fields(room_id = ?self.room_id()))]
+    pub async fn update_delayed_event(
+        &self,
+        delay_id: &str,
+        action: DelayedEventAction,
+    ) -> Result<()> {
+        let request = UpdateDelayedEventRequest { delay_id: delay_id.to_owned(), action };
+        self.client.send(request, None).await?;
+        Ok(())
+    }
+}
+
+/// A request to send a message-like event to a room after a delay.
+#[derive(Clone, Debug)]
+struct SendDelayedMessageLikeEventRequest {
+    room_id: OwnedRoomId,
+    event_type: String,
+    txn_id: OwnedTransactionId,
+    delay: Duration,
+    content: Box<RawJsonValue>,
+}
+
+impl OutgoingRequest for SendDelayedMessageLikeEventRequest {
+    type EndpointError = Error;
+    type IncomingResponse = SendDelayedEventResponse;
+
+    const METADATA: Metadata = metadata! {
+        method: PUT,
+        rate_limited: false,
+        authentication: AccessToken,
+        history: {
+            unstable => "/_matrix/client/v3/rooms/:room_id/send/:event_type/:txn_id",
+        }
+    };
+
+    fn try_into_http_request<T: Default + BufMut>(
+        self,
+        base_url: &str,
+        access_token: SendAccessToken<'_>,
+        considering_versions: &[MatrixVersion],
+    ) -> Result<http::Request<T>, IntoHttpError> {
+        let url = Self::METADATA.make_endpoint_url(
+            considering_versions,
+            base_url,
+            &[&self.room_id, &self.event_type, &self.txn_id],
+            &delay_query_string(self.delay),
+        )?;
+
+        json_http_request(&Self::METADATA, url, access_token, &self.content)
+    }
+}
+
+/// A request to send a state event to a room after a delay.
+#[derive(Clone, Debug)]
+struct SendDelayedStateEventRequest {
+    room_id: OwnedRoomId,
+    event_type: String,
+    state_key: String,
+    delay: Duration,
+    content: Box<RawJsonValue>,
+}
+
+impl OutgoingRequest for SendDelayedStateEventRequest {
+    type EndpointError = Error;
+    type IncomingResponse = SendDelayedEventResponse;
+
+    const METADATA: Metadata = metadata! {
+        method: PUT,
+        rate_limited: false,
+        authentication: AccessToken,
+        history: {
+            unstable => "/_matrix/client/v3/rooms/:room_id/state/:event_type/:state_key",
+        }
+    };
+
+    fn try_into_http_request<T: Default + BufMut>(
+        self,
+        base_url: &str,
+        access_token: SendAccessToken<'_>,
+        considering_versions: &[MatrixVersion],
+    ) -> Result<http::Request<T>, IntoHttpError> {
+        let url = Self::METADATA.make_endpoint_url(
+            considering_versions,
+            base_url,
+            &[&self.room_id, &self.event_type, &self.state_key],
+            &delay_query_string(self.delay),
+        )?;
+
+        json_http_request(&Self::METADATA, url, access_token, &self.content)
+    }
+}
+
+/// The response to a request to send a delayed event.
+#[derive(Clone, Debug)]
+struct SendDelayedEventResponse {
+    delay_id: String,
+}
+
+impl IncomingResponse for SendDelayedEventResponse {
+    type EndpointError = Error;
+
+    fn try_from_http_response<T: AsRef<[u8]>>(
+        response: http::Response<T>,
+    ) -> Result<Self, FromHttpResponseError<Error>> {
+        #[derive(Deserialize)]
+        struct ResponseBody {
+            delay_id: String,
+        }
+
+        if response.status().is_success() {
+            let body: ResponseBody = serde_json::from_slice(response.body().as_ref())
+                .map_err(|e| FromHttpResponseError::Deserialization(e.into()))?;
+            Ok(Self { delay_id: body.delay_id })
+        } else {
+            Err(FromHttpResponseError::Server(Error::from_http_response(response)))
+        }
+    }
+}
+
+/// A request to update a delayed event.
+#[derive(Clone, Debug)]
+struct UpdateDelayedEventRequest {
+    delay_id: String,
+    action: DelayedEventAction,
+}
+
+impl OutgoingRequest for UpdateDelayedEventRequest {
+    type EndpointError = Error;
+    type IncomingResponse = UpdateDelayedEventResponse;
+
+    const METADATA: Metadata = metadata! {
+        method: POST,
+        rate_limited: true,
+        authentication: AccessToken,
+        history: {
+            unstable => "/_matrix/client/unstable/org.matrix.msc4140/delayed_events/:delay_id",
+        }
+    };
+
+    fn try_into_http_request<T: Default + BufMut>(
+        self,
+        base_url: &str,
+        access_token: SendAccessToken<'_>,
+        considering_versions: &[MatrixVersion],
+    ) -> Result<http::Request<T>, IntoHttpError> {
+        let url = Self::METADATA.make_endpoint_url(
+            considering_versions,
+            base_url,
+            &[&self.delay_id],
+            "",
+        )?;
+        let body = serde_json::value::to_raw_value(&serde_json::json!({ "action": self.action }))?;
+
+        json_http_request(&Self::METADATA, url, access_token, &body)
+    }
+}
+
+/// The response to an [`UpdateDelayedEventRequest`], which has an empty body.
+#[derive(Clone, Debug)]
+struct UpdateDelayedEventResponse;
+
+impl IncomingResponse for UpdateDelayedEventResponse {
+    type EndpointError = Error;
+
+    fn try_from_http_response<T: AsRef<[u8]>>(
+        response: http::Response<T>,
+    ) -> Result<Self, FromHttpResponseError<Error>> {
+        if response.status().is_success() {
+            Ok(Self)
+        } else {
+            Err(FromHttpResponseError::Server(Error::from_http_response(response)))
+        }
+    }
+}
+
+fn delay_query_string(delay: Duration) -> String {
+    format!("{DELAY_QUERY_PARAMETER}={}", delay.as_millis())
+}
+
+fn json_http_request<T: Default + BufMut>(
+    metadata: &Metadata,
+    url: String,
+    access_token: SendAccessToken<'_>,
+    content: &RawJsonValue,
+) -> Result<http::Request<T>, IntoHttpError> {
+    let access_token =
+        access_token.get_required_for_endpoint().ok_or(IntoHttpError::NeedsAuthentication)?;
+
+    let mut body = T::default();
+    body.put_slice(content.get().as_bytes());
+
+    Ok(http::Request::builder()
+        .method(metadata.method.clone())
+        .uri(url)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .header(http::header::AUTHORIZATION, format!("Bearer {access_token}"))
+        .body(body)?)
+}
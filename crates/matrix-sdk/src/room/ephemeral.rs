@@ -0,0 +1,97 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The endpoint to send user-defined ephemeral events to a room, as defined in
+//! [MSC2477].
+//!
+//! [MSC2477]: https://github.com/matrix-org/matrix-spec-proposals/pull/2477
+
+use bytes::BufMut;
+use ruma::{
+    api::{
+        client::Error,
+        error::{FromHttpResponseError, IntoHttpError},
+        metadata, EndpointError, IncomingResponse, MatrixVersion, Metadata, OutgoingRequest,
+        SendAccessToken,
+    },
+    OwnedRoomId, OwnedTransactionId,
+};
+use serde_json::value::RawValue as RawJsonValue;
+
+/// A request to send an ephemeral event to a room.
+#[derive(Clone, Debug)]
+pub(super) struct SendEphemeralEventRequest {
+    pub(super) room_id: OwnedRoomId,
+    pub(super) event_type: String,
+    pub(super) txn_id: OwnedTransactionId,
+    pub(super) content: Box<RawJsonValue>,
+}
+
+impl OutgoingRequest for SendEphemeralEventRequest {
+    type EndpointError = Error;
+    type IncomingResponse = SendEphemeralEventResponse;
+
+    const METADATA: Metadata = metadata! {
+        method: PUT,
+        rate_limited: true,
+        authentication: AccessToken,
+        history: {
+            unstable => "/_matrix/client/unstable/org.matrix.msc2477/rooms/:room_id/ephemeral/:event_type/:txn_id",
+        }
+    };
+
+    fn try_into_http_request<T: Default + BufMut>(
+        self,
+        base_url: &str,
+        access_token: SendAccessToken<'_>,
+        considering_versions: &[MatrixVersion],
+    ) -> Result<http::Request<T>, IntoHttpError> {
+        let url = Self::METADATA.make_endpoint_url(
+            considering_versions,
+            base_url,
+            &[&self.room_id, &self.event_type, &self.txn_id],
+            "",
+        )?;
+        let access_token =
+            access_token.get_required_for_endpoint().ok_or(IntoHttpError::NeedsAuthentication)?;
+
+        let mut body = T::default();
+        body.put_slice(self.content.get().as_bytes());
+
+        Ok(http::Request::builder()
+            .method(Self::METADATA.method)
+            .uri(url)
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .header(http::header::AUTHORIZATION, format!("Bearer {access_token}"))
+            .body(body)?)
+    }
+}
+
+/// The response to a [`SendEphemeralEventRequest`], which has an empty body.
+#[derive(Clone, Debug)]
+pub(super) struct SendEphemeralEventResponse;
+
+impl IncomingResponse for SendEphemeralEventResponse {
+    type EndpointError = Error;
+
+    fn try_from_http_response<T: AsRef<[u8]>>(
+        response: http::Response<T>,
+    ) -> Result<Self, FromHttpResponseError<Error>> {
+        if response.status().is_success() {
+            Ok(Self)
+        } else {
+            Err(FromHttpResponseError::Server(Error::from_http_response(response)))
+        }
+    }
+}
@@ -0,0 +1,587 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Export of the history of a room as a transcript.
+
+use std::{collections::BTreeMap, fmt::Write as _};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use eyeball::SharedObservable;
+use futures_util::{io::AsyncWrite, AsyncWriteExt};
+use matrix_sdk_common::deserialized_responses::TimelineEvent;
+use ruma::{
+    assign,
+    events::{
+        room::{
+            message::{MessageType, Relation},
+            MediaSource,
+        },
+        AnyMessageLikeEvent, AnyTimelineEvent, MessageLikeEvent,
+    },
+    uint, MilliSecondsSinceUnixEpoch, OwnedUserId, UInt, UserId,
+};
+use serde_json::json;
+use tracing::{debug, instrument, warn};
+
+use super::{MessagesOptions, Room};
+use crate::{
+    media::{MediaFormat, MediaRequest},
+    Result,
+};
+
+/// The format of a room history export.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// A JSON array with an object per event, containing the decrypted event
+    /// and its media, if it was bundled.
+    ///
+    /// All the events of the room are exported.
+    Json,
+    /// A plain-text transcript with a line per message.
+    ///
+    /// Only the messages are exported, edits are not applied.
+    PlainText,
+    /// An HTML document with a paragraph per message.
+    ///
+    /// Only the messages are exported, edits are not applied. Bundled images
+    /// are displayed inline.
+    Html,
+}
+
+/// The range of the history of a room to export, by the timestamps of the
+/// events.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExportRange {
+    /// Only export the events that were sent at or after this time.
+    ///
+    /// If this is `None`, the export starts at the beginning of the history
+    /// of the room that is visible to the user.
+    pub start: Option<MilliSecondsSinceUnixEpoch>,
+    /// Only export the events that were sent at or before this time.
+    ///
+    /// If this is `None`, the export stops at the most recent event.
+    pub end: Option<MilliSecondsSinceUnixEpoch>,
+}
+
+impl ExportRange {
+    /// The whole history of the room.
+    pub fn all() -> Self {
+        Self::default()
+    }
+
+    /// The events sent between `start` and `end`, inclusive.
+    pub fn between(start: MilliSecondsSinceUnixEpoch, end: MilliSecondsSinceUnixEpoch) -> Self {
+        Self { start: Some(start), end: Some(end) }
+    }
+
+    fn is_before_start(&self, ts: MilliSecondsSinceUnixEpoch) -> bool {
+        self.start.is_some_and(|start| ts < start)
+    }
+
+    fn is_after_end(&self, ts: MilliSecondsSinceUnixEpoch) -> bool {
+        self.end.is_some_and(|end| ts > end)
+    }
+}
+
+/// Options for a room history export.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct ExportOptions {
+    /// Whether to download the media of the exported messages and bundle them
+    /// in the export, as data URIs.
+    ///
+    /// This has no effect with [`ExportFormat::PlainText`].
+    pub include_media: bool,
+    /// The maximum size in bytes of a media to bundle in the export.
+    ///
+    /// Larger media are not downloaded, the export only refers to them.
+    pub max_media_size: Option<UInt>,
+}
+
+impl ExportOptions {
+    /// Create the default options, without media.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set whether to bundle the media of the exported messages in the
+    /// export.
+    pub fn include_media(mut self, include_media: bool) -> Self {
+        self.include_media = include_media;
+        self
+    }
+
+    /// Set the maximum size in bytes of a media to bundle in the export.
+    pub fn max_media_size(mut self, max_media_size: UInt) -> Self {
+        self.max_media_size = Some(max_media_size);
+        self
+    }
+}
+
+/// The progress of a room history export.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExportProgress {
+    /// The number of events that were fetched from the homeserver.
+    pub fetched_events: u64,
+    /// The number of events that were written to the export.
+    pub exported_events: u64,
+    /// The number of media that were bundled in the export.
+    pub exported_media: u64,
+}
+
+/// An export of the history of a room, returned by [`Room::export_history`].
+///
+/// The export is written with [`ExportHistory::write_to()`].
+#[allow(missing_debug_implementations)]
+pub struct ExportHistory<'a> {
+    room: &'a Room,
+    format: ExportFormat,
+    range: ExportRange,
+    options: ExportOptions,
+    progress: SharedObservable<ExportProgress>,
+}
+
+impl<'a> ExportHistory<'a> {
+    pub(super) fn new(
+        room: &'a Room,
+        format: ExportFormat,
+        range: ExportRange,
+        options: ExportOptions,
+    ) -> Self {
+        Self { room, format, range, options, progress: Default::default() }
+    }
+
+    /// Replace the default `SharedObservable` used for tracking the progress
+    /// of the export with the given one.
+    pub fn with_progress_observable(mut self, progress: SharedObservable<ExportProgress>) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Export the history of the room and write it to the given writer, as it
+    /// is fetched from the homeserver.
+    ///
+    /// The history is paginated forwards from the homeserver, and the
+    /// encrypted events are decrypted along the way. Events that can't be
+    /// decrypted are still exported, as such.
+    ///
+    /// Returns the final progress of the export.
+    #[instrument(skip_all, fields(room_id = ?self.room.room_id(), format = ?self.format))]
+    pub async fn write_to<W>(self, mut writer: W) -> Result<ExportProgress>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let Self { room, format, range, options, progress } = self;
+        let mut exporter = Exporter { room, format, options, names: BTreeMap::new() };
+
+        writer.write_all(exporter.header().as_bytes()).await?;
+
+        let mut token = None;
+        let mut is_first = true;
+        'pagination: loop {
+            let options = assign!(MessagesOptions::forward().from(token.as_deref()), {
+                limit: uint!(100),
+            });
+            let messages = room.messages(options).await?;
+            let reached_end = messages.end.is_none() || messages.chunk.is_empty();
+            token = messages.end;
+
+            progress.update(|p| p.fetched_events += messages.chunk.len() as u64);
+
+            for event in &messages.chunk {
+                let Some(ts) = origin_server_ts(event) else { continue };
+                if range.is_before_start(ts) {
+                    continue;
+                }
+                if range.is_after_end(ts) {
+                    break 'pagination;
+                }
+
+                let Some(entry) = exporter.entry(event, ts, is_first).await else { continue };
+                writer.write_all(entry.text.as_bytes()).await?;
+                is_first = false;
+
+                progress.update(|p| {
+                    p.exported_events += 1;
+                    p.exported_media += u64::from(entry.has_media);
+                });
+            }
+
+            if reached_end {
+                break;
+            }
+        }
+
+        writer.write_all(exporter.footer().as_bytes()).await?;
+        writer.flush().await?;
+
+        Ok(progress.get())
+    }
+}
+
+/// A chunk of the export, for a single event.
+struct Entry {
+    text: String,
+    has_media: bool,
+}
+
+struct Exporter<'a> {
+    room: &'a Room,
+    format: ExportFormat,
+    options: ExportOptions,
+    /// The display names of the senders, to avoid looking them up for every
+    /// message.
+    names: BTreeMap<OwnedUserId, String>,
+}
+
+impl Exporter<'_> {
+    fn header(&self) -> String {
+        match self.format {
+            ExportFormat::Json => "[".to_owned(),
+            ExportFormat::PlainText => String::new(),
+            ExportFormat::Html => {
+                let title = escape_html(self.room.name().as_deref().unwrap_or("Room"));
+                format!(
+                    "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+                     <title>{title}</title>\n</head>\n<body>\n<h1>{title}</h1>\n"
+                )
+            }
+        }
+    }
+
+    fn footer(&self) -> &'static str {
+        match self.format {
+            ExportFormat::Json => "\n]\n",
+            ExportFormat::PlainText => "",
+            ExportFormat::Html => "</body>\n</html>\n",
+        }
+    }
+
+    async fn entry(
+        &mut self,
+        event: &TimelineEvent,
+        ts: MilliSecondsSinceUnixEpoch,
+        is_first: bool,
+    ) -> Option<Entry> {
+        match self.format {
+            ExportFormat::Json => Some(self.json_entry(event, is_first).await),
+            ExportFormat::PlainText | ExportFormat::Html => self.message_entry(event, ts).await,
+        }
+    }
+
+    async fn json_entry(&self, event: &TimelineEvent, is_first: bool) -> Entry {
+        let media = match message_type(event) {
+            Some(msgtype) => self.bundled_media(&msgtype).await,
+            None => None,
+        };
+        let has_media = media.is_some();
+
+        let mut object = json!({ "event": event.event });
+        if let Some(media) = media {
+            object["media"] = json!({ "mimetype": media.mimetype, "data": media.data });
+        }
+
+        let separator = if is_first { "\n" } else { ",\n" };
+        Entry { text: format!("{separator}{object}"), has_media }
+    }
+
+    async fn message_entry(
+        &mut self,
+        event: &TimelineEvent,
+        ts: MilliSecondsSinceUnixEpoch,
+    ) -> Option<Entry> {
+        let (sender, body) = match event.event.deserialize() {
+            Ok(AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomMessage(
+                MessageLikeEvent::Original(ev),
+            ))) => {
+                // Edits replace a message that was exported already.
+                if matches!(ev.content.relates_to, Some(Relation::Replacement(_))) {
+                    return None;
+                }
+                (ev.sender, Body::Message(ev.content.msgtype))
+            }
+            Ok(AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomMessage(
+                MessageLikeEvent::Redacted(ev),
+            ))) => (ev.sender, Body::Redacted),
+            Ok(AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomEncrypted(ev))) => {
+                (ev.sender().to_owned(), Body::UnableToDecrypt)
+            }
+            Ok(_) => return None,
+            Err(e) => {
+                debug!("Couldn't deserialize an exported event: {e}");
+                return None;
+            }
+        };
+
+        let name = self.display_name(&sender).await;
+        let time = format_timestamp(ts);
+
+        match self.format {
+            ExportFormat::Html => {
+                let (html, has_media) = match body {
+                    Body::Message(msgtype) => {
+                        let media = self.bundled_media(&msgtype).await;
+                        let has_media = media.is_some();
+                        (html_body(&msgtype, media), has_media)
+                    }
+                    Body::Redacted => ("<em>Message deleted</em>".to_owned(), false),
+                    Body::UnableToDecrypt => {
+                        ("<em>Unable to decrypt message</em>".to_owned(), false)
+                    }
+                };
+
+                let text = format!(
+                    "<p><time>{time}</time> <strong title=\"{sender}\">{name}</strong>: {html}</p>\n",
+                    sender = escape_html(sender.as_str()),
+                    name = escape_html(&name),
+                );
+                Some(Entry { text, has_media })
+            }
+            _ => {
+                let text = match body {
+                    Body::Message(msgtype) => plain_text_body(&msgtype),
+                    Body::Redacted => "* Message deleted *".to_owned(),
+                    Body::UnableToDecrypt => "* Unable to decrypt message *".to_owned(),
+                };
+
+                // Keep one line per message.
+                let text = text.replace('\n', "\n    ");
+                Some(Entry { text: format!("[{time}] {name}: {text}\n"), has_media: false })
+            }
+        }
+    }
+
+    async fn display_name(&mut self, user_id: &UserId) -> String {
+        if let Some(name) = self.names.get(user_id) {
+            return name.clone();
+        }
+
+        let name = match self.room.get_member_no_sync(user_id).await {
+            Ok(Some(member)) => member.name().to_owned(),
+            Ok(None) => user_id.to_string(),
+            Err(e) => {
+                debug!(?user_id, "Couldn't load the member: {e}");
+                user_id.to_string()
+            }
+        };
+
+        self.names.insert(user_id.to_owned(), name.clone());
+        name
+    }
+
+    /// Download the media of the given message, if it should be bundled in
+    /// the export.
+    async fn bundled_media(&self, msgtype: &MessageType) -> Option<BundledMedia> {
+        if !self.options.include_media {
+            return None;
+        }
+
+        let (source, mimetype, size) = media_info(msgtype)?;
+        if let (Some(max), Some(size)) = (self.options.max_media_size, size) {
+            if size > max {
+                debug!("Not bundling a media of {size} bytes");
+                return None;
+            }
+        }
+
+        let request = MediaRequest { source, format: MediaFormat::File };
+        match self.room.client.media().get_media_content(&request, true).await {
+            Ok(content) => {
+                // The size in the event is optional and set by the sender, so
+                // it can't be trusted.
+                if let Some(max) = self.options.max_media_size {
+                    if content.len() as u64 > u64::from(max) {
+                        debug!("Not bundling a media of {} bytes", content.len());
+                        return None;
+                    }
+                }
+
+                // The mimetype is set by the sender too, only keep it if it is
+                // valid.
+                let mimetype =
+                    mimetype.and_then(|mimetype| mimetype.parse::<mime::Mime>().ok()).map_or_else(
+                        || mime::APPLICATION_OCTET_STREAM.essence_str().to_owned(),
+                        |mimetype| mimetype.essence_str().to_owned(),
+                    );
+                Some(BundledMedia { mimetype, data: BASE64.encode(content) })
+            }
+            Err(e) => {
+                warn!("Couldn't download a media to bundle in the export: {e}");
+                None
+            }
+        }
+    }
+}
+
+enum Body {
+    Message(MessageType),
+    Redacted,
+    UnableToDecrypt,
+}
+
+struct BundledMedia {
+    mimetype: String,
+    /// The base64-encoded content of the media.
+    data: String,
+}
+
+impl BundledMedia {
+    /// The data URI of the media, escaped to be used as an HTML attribute.
+    fn data_uri(&self) -> String {
+        escape_html(&format!("data:{};base64,{}", self.mimetype, self.data))
+    }
+}
+
+fn origin_server_ts(event: &TimelineEvent) -> Option<MilliSecondsSinceUnixEpoch> {
+    match event.event.get_field("origin_server_ts") {
+        Ok(ts) => ts,
+        Err(e) => {
+            debug!("Couldn't read the timestamp of an exported event: {e}");
+            None
+        }
+    }
+}
+
+fn message_type(event: &TimelineEvent) -> Option<MessageType> {
+    match event.event.deserialize().ok()? {
+        AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomMessage(
+            MessageLikeEvent::Original(ev),
+        )) => Some(ev.content.msgtype),
+        _ => None,
+    }
+}
+
+fn media_info(msgtype: &MessageType) -> Option<(MediaSource, Option<String>, Option<UInt>)> {
+    let (source, info) = match msgtype {
+        MessageType::Image(c) => (&c.source, c.info.as_ref().map(|i| (&i.mimetype, i.size))),
+        MessageType::Video(c) => (&c.source, c.info.as_ref().map(|i| (&i.mimetype, i.size))),
+        MessageType::Audio(c) => (&c.source, c.info.as_ref().map(|i| (&i.mimetype, i.size))),
+        MessageType::File(c) => (&c.source, c.info.as_ref().map(|i| (&i.mimetype, i.size))),
+        _ => return None,
+    };
+    let (mimetype, size) = info.map_or((None, None), |(mimetype, size)| (mimetype.clone(), size));
+
+    Some((source.clone(), mimetype, size))
+}
+
+fn plain_text_body(msgtype: &MessageType) -> String {
+    match msgtype {
+        MessageType::Emote(c) => format!("* {}", c.body),
+        MessageType::Image(c) => format!("[image: {}]", c.body),
+        MessageType::Video(c) => format!("[video: {}]", c.body),
+        MessageType::Audio(c) => format!("[audio: {}]", c.body),
+        MessageType::File(c) => format!("[file: {}]", c.body),
+        MessageType::Location(c) => format!("[location: {} ({})]", c.body, c.geo_uri),
+        _ => msgtype.body().to_owned(),
+    }
+}
+
+fn html_body(msgtype: &MessageType, media: Option<BundledMedia>) -> String {
+    let body = escape_html(msgtype.body()).replace('\n', "<br>");
+
+    match (msgtype, media) {
+        (MessageType::Emote(_), _) => format!("<em>* {body}</em>"),
+        (MessageType::Image(_), Some(media)) => {
+            format!("<img src=\"{}\" alt=\"{body}\">", media.data_uri())
+        }
+        (MessageType::Video(_), Some(media)) => {
+            format!("<video controls src=\"{}\" title=\"{body}\"></video>", media.data_uri())
+        }
+        (MessageType::Audio(_), Some(media)) => {
+            format!("<audio controls src=\"{}\" title=\"{body}\"></audio>", media.data_uri())
+        }
+        (MessageType::File(_), Some(media)) => {
+            format!("<a download=\"{body}\" href=\"{}\">{body}</a>", media.data_uri())
+        }
+        (
+            MessageType::Image(_)
+            | MessageType::Video(_)
+            | MessageType::Audio(_)
+            | MessageType::File(_),
+            None,
+        ) => format!("<em>[{body}]</em>"),
+        _ => body,
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Format the timestamp as `YYYY-MM-DD HH:MM:SS`, in UTC.
+fn format_timestamp(ts: MilliSecondsSinceUnixEpoch) -> String {
+    let secs: u64 = ts.as_secs().into();
+    let days = secs / 86_400;
+    let secs_of_day = secs % 86_400;
+
+    // Convert the days since the Unix epoch to a civil date, see
+    // <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    let mut formatted = String::with_capacity(19);
+    let _ = write!(
+        formatted,
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        secs_of_day / 3_600,
+        secs_of_day % 3_600 / 60,
+        secs_of_day % 60,
+    );
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma::{uint, MilliSecondsSinceUnixEpoch};
+
+    use super::{escape_html, format_timestamp};
+
+    #[test]
+    fn timestamps_are_formatted_in_utc() {
+        let ts = MilliSecondsSinceUnixEpoch(uint!(0));
+        assert_eq!(format_timestamp(ts), "1970-01-01 00:00:00");
+
+        let ts = MilliSecondsSinceUnixEpoch(uint!(951_782_400_000));
+        assert_eq!(format_timestamp(ts), "2000-02-29 00:00:00");
+
+        let ts = MilliSecondsSinceUnixEpoch(uint!(1_704_067_199_999));
+        assert_eq!(format_timestamp(ts), "2023-12-31 23:59:59");
+    }
+
+    #[test]
+    fn html_is_escaped() {
+        assert_eq!(
+            escape_html("<b>\"Tom\" & 'Jerry'</b>"),
+            "&lt;b&gt;&quot;Tom&quot; &amp; &#39;Jerry&#39;&lt;/b&gt;"
+        );
+    }
+}
@@ -147,6 +147,11 @@ impl<'a> IntoFuture for SendRawMessageLikeEvent<'a> {
         let fut = async move {
             room.ensure_room_joined()?;
 
+            // Guests can only send messages.
+            if event_type != "m.room.message" {
+                room.client.ensure_not_guest()?;
+            }
+
             let txn_id = transaction_id.unwrap_or_else(TransactionId::new);
             tracing::Span::current().record("transaction_id", tracing::field::debug(&txn_id));
 
@@ -258,6 +263,9 @@ impl<'a> IntoFuture for SendAttachment<'a> {
     fn into_future(self) -> Self::IntoFuture {
         let Self { room, body, content_type, data, config, tracing_span, send_progress } = self;
         let fut = async move {
+            // Guests can't upload media.
+            room.client.ensure_not_guest()?;
+
             if config.thumbnail.is_some() {
                 room.prepare_and_send_attachment(body, content_type, data, config, send_progress)
                     .await
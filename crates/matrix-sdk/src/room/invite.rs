@@ -0,0 +1,232 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{fmt::Debug, time::Duration};
+
+use futures_util::future::join_all;
+use ruma::{
+    api::{
+        client::{
+            error::ErrorKind,
+            membership::{invite_user, invite_user::v3::InvitationRecipient},
+            profile::get_profile,
+            user_directory::search_users,
+        },
+        error::FromHttpResponseError,
+        OutgoingRequest,
+    },
+    events::room::member::MembershipState,
+    OwnedUserId, UInt, UserId,
+};
+use tracing::{debug, instrument};
+
+use super::Room;
+use crate::{config::RequestConfig, Error, HttpError, HttpResult, Result};
+
+/// How many invites are sent at the same time by [`Room::invite_by_search`].
+const INVITE_BATCH_SIZE: usize = 10;
+
+/// How many times a request is retried when the homeserver rate-limits us.
+const MAX_RETRIES: u32 = 5;
+
+/// How many users are looked up in the user directory for a search term.
+const SEARCH_LIMIT: u32 = 10;
+
+/// The outcome of inviting a user with [`Room::invite_by_search`].
+#[derive(Debug)]
+pub enum InviteOutcome {
+    /// The user was invited.
+    Invited(OwnedUserId),
+
+    /// The user was already invited to or a member of the room, no invite was
+    /// sent.
+    AlreadyMember(OwnedUserId),
+
+    /// No user matches the search term.
+    NotFound,
+
+    /// Several users match the search term, none of them was invited.
+    Ambiguous(Vec<OwnedUserId>),
+
+    /// Looking up the search term in the user directory failed.
+    SearchFailed(Error),
+
+    /// Inviting the user failed.
+    Failed {
+        /// The user that couldn't be invited.
+        user_id: OwnedUserId,
+
+        /// Why the invite failed.
+        error: Error,
+    },
+}
+
+/// The report of the invite of a single user by [`Room::invite_by_search`].
+#[derive(Debug)]
+pub struct InviteReport {
+    /// The search term, as given to [`Room::invite_by_search`].
+    pub search_term: String,
+
+    /// What happened to the user found with the search term.
+    pub outcome: InviteOutcome,
+}
+
+/// The user a search term resolved to.
+enum Resolved {
+    User(OwnedUserId),
+    Done(InviteOutcome),
+}
+
+impl Room {
+    /// Find the users matching the given search terms and invite them to this
+    /// room.
+    ///
+    /// A search term can either be a user ID, in which case the user's profile
+    /// is looked up to make sure they exist, or anything else, which is looked
+    /// up in the user directory and must match exactly one user.
+    ///
+    /// The invites are sent in batches. When the homeserver rate-limits the
+    /// lookups or the invites, they are retried after the delay it asked for.
+    ///
+    /// Returns a report for every search term, in the same order.
+    #[instrument(skip_all, fields(room_id = ?self.room_id()))]
+    pub async fn invite_by_search<I, S>(&self, search_terms: I) -> Vec<InviteReport>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let search_terms: Vec<String> = search_terms.into_iter().map(Into::into).collect();
+        let mut reports = Vec::with_capacity(search_terms.len());
+
+        for batch in search_terms.chunks(INVITE_BATCH_SIZE) {
+            let outcomes = join_all(batch.iter().map(|term| self.invite_search_term(term))).await;
+
+            reports.extend(
+                batch
+                    .iter()
+                    .zip(outcomes)
+                    .map(|(term, outcome)| InviteReport { search_term: term.clone(), outcome }),
+            );
+        }
+
+        reports
+    }
+
+    async fn invite_search_term(&self, search_term: &str) -> InviteOutcome {
+        let user_id = match self.resolve_search_term(search_term).await {
+            Resolved::User(user_id) => user_id,
+            Resolved::Done(outcome) => return outcome,
+        };
+
+        match self.get_member_no_sync(&user_id).await {
+            Ok(Some(member))
+                if matches!(
+                    member.membership(),
+                    MembershipState::Join | MembershipState::Invite
+                ) =>
+            {
+                return InviteOutcome::AlreadyMember(user_id);
+            }
+            Ok(_) => {}
+            Err(error) => return InviteOutcome::Failed { user_id, error },
+        }
+
+        match self.invite_with_backoff(&user_id).await {
+            Ok(()) => InviteOutcome::Invited(user_id),
+            Err(error) => InviteOutcome::Failed { user_id, error },
+        }
+    }
+
+    async fn resolve_search_term(&self, search_term: &str) -> Resolved {
+        if let Ok(user_id) = UserId::parse(search_term) {
+            let request = get_profile::v3::Request::new(user_id.clone());
+
+            return match self.send_with_backoff(request).await {
+                Ok(_) => Resolved::User(user_id),
+                Err(error)
+                    if matches!(error.client_api_error_kind(), Some(ErrorKind::NotFound)) =>
+                {
+                    Resolved::Done(InviteOutcome::NotFound)
+                }
+                Err(error) => {
+                    Resolved::Done(InviteOutcome::Failed { user_id, error: error.into() })
+                }
+            };
+        }
+
+        let mut request = search_users::v3::Request::new(search_term.to_owned());
+        request.limit = UInt::from(SEARCH_LIMIT);
+
+        let results = match self.send_with_backoff(request).await {
+            Ok(response) => response.results,
+            Err(error) => {
+                debug!("Couldn't search the user directory: {error}");
+                return Resolved::Done(InviteOutcome::SearchFailed(error.into()));
+            }
+        };
+
+        let mut user_ids: Vec<_> = results.into_iter().map(|user| user.user_id).collect();
+
+        match user_ids.len() {
+            0 => Resolved::Done(InviteOutcome::NotFound),
+            1 => Resolved::User(user_ids.remove(0)),
+            _ => Resolved::Done(InviteOutcome::Ambiguous(user_ids)),
+        }
+    }
+
+    async fn invite_with_backoff(&self, user_id: &UserId) -> Result<()> {
+        self.client.ensure_not_guest()?;
+
+        let recipient = InvitationRecipient::UserId { user_id: user_id.to_owned() };
+        let request = invite_user::v3::Request::new(self.room_id().to_owned(), recipient);
+        self.send_with_backoff(request).await?;
+
+        Ok(())
+    }
+
+    /// Send the given request, retrying it after the delay asked for by the
+    /// homeserver when it is rate-limited.
+    async fn send_with_backoff<R>(&self, request: R) -> HttpResult<R::IncomingResponse>
+    where
+        R: OutgoingRequest + Clone + Debug,
+        HttpError: From<FromHttpResponseError<R::EndpointError>>,
+    {
+        // We handle the rate-limiting ourselves.
+        let config = RequestConfig::default().disable_retry();
+
+        let mut attempt = 0;
+
+        loop {
+            let error = match self.client.send(request.clone(), Some(config)).await {
+                Ok(response) => return Ok(response),
+                Err(error) => error,
+            };
+
+            let retry_after = match error.client_api_error_kind() {
+                Some(ErrorKind::LimitExceeded { retry_after_ms }) => *retry_after_ms,
+                _ => return Err(error),
+            };
+
+            if attempt >= MAX_RETRIES {
+                return Err(error);
+            }
+
+            let delay = retry_after.unwrap_or_else(|| Duration::from_secs(1 << attempt));
+            debug!(?delay, "Rate-limited, retrying later");
+
+            self.client.base_client().clock().sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
@@ -0,0 +1,273 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A live list of the media events of a room, to build "gallery" or "files"
+//! views without walking the whole timeline.
+
+use std::sync::{Arc, Mutex as StdMutex};
+
+use eyeball_im::{ObservableVector, VectorDiff};
+use futures_core::Stream;
+use imbl::Vector;
+use matrix_sdk_common::deserialized_responses::TimelineEvent;
+use ruma::{
+    api::client::filter::{RoomEventFilter, UrlFilter},
+    assign,
+    events::{
+        room::{
+            message::{MessageType, OriginalSyncRoomMessageEvent, Relation},
+            redaction::SyncRoomRedactionEvent,
+        },
+        AnyMessageLikeEvent, AnyTimelineEvent, MessageLikeEvent,
+    },
+    uint, EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedUserId, RoomVersionId,
+};
+use tokio::sync::Mutex;
+use tracing::{debug, instrument};
+
+use super::{MessagesOptions, Room};
+use crate::{event_handler::EventHandlerDropGuard, Result};
+
+/// The kinds of media a [`MediaGallery`] lists.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MediaGalleryFilter {
+    /// Images, videos, audio clips and files.
+    #[default]
+    All,
+    /// Images and videos, for a "gallery" view.
+    ImagesAndVideos,
+    /// Audio clips and files, for a "files" view.
+    Files,
+}
+
+impl MediaGalleryFilter {
+    fn matches(&self, msgtype: &MessageType) -> bool {
+        match msgtype {
+            MessageType::Image(_) | MessageType::Video(_) => {
+                matches!(self, Self::All | Self::ImagesAndVideos)
+            }
+            MessageType::Audio(_) | MessageType::File(_) => matches!(self, Self::All | Self::Files),
+            _ => false,
+        }
+    }
+}
+
+/// A media event of a room, listed by a [`MediaGallery`].
+#[derive(Clone, Debug)]
+pub struct MediaGalleryItem {
+    /// The ID of the event.
+    pub event_id: OwnedEventId,
+    /// The sender of the event.
+    pub sender: OwnedUserId,
+    /// When the event was sent, according to the homeserver of the sender.
+    pub origin_server_ts: MilliSecondsSinceUnixEpoch,
+    /// The content of the event: an image, a video, an audio clip or a file.
+    pub content: MessageType,
+}
+
+impl MediaGalleryItem {
+    fn from_event(event: &TimelineEvent, filter: MediaGalleryFilter) -> Option<Self> {
+        let event = match event.event.deserialize() {
+            Ok(event) => event,
+            Err(e) => {
+                debug!("Couldn't deserialize an event of the media gallery: {e}");
+                return None;
+            }
+        };
+
+        let AnyTimelineEvent::MessageLike(AnyMessageLikeEvent::RoomMessage(
+            MessageLikeEvent::Original(event),
+        )) = event
+        else {
+            return None;
+        };
+
+        // Edits are not new media, and the original event is listed already.
+        if matches!(event.content.relates_to, Some(Relation::Replacement(_))) {
+            return None;
+        }
+
+        filter.matches(&event.content.msgtype).then(|| Self {
+            event_id: event.event_id,
+            sender: event.sender,
+            origin_server_ts: event.origin_server_ts,
+            content: event.content.msgtype,
+        })
+    }
+
+    fn from_sync_event(
+        event: OriginalSyncRoomMessageEvent,
+        filter: MediaGalleryFilter,
+    ) -> Option<Self> {
+        if matches!(event.content.relates_to, Some(Relation::Replacement(_))) {
+            return None;
+        }
+
+        filter.matches(&event.content.msgtype).then(|| Self {
+            event_id: event.event_id,
+            sender: event.sender,
+            origin_server_ts: event.origin_server_ts,
+            content: event.content.msgtype,
+        })
+    }
+}
+
+/// A live list of the media events of a room, most recent first.
+///
+/// The list starts empty, older media events are loaded with
+/// [`MediaGallery::paginate_backwards()`]. The media events received via sync
+/// are added to the front of the list, and the redacted ones are removed
+/// from it, as long as the `MediaGallery` is alive.
+///
+/// To get one, use [`Room::media_gallery()`].
+#[derive(Clone, Debug)]
+pub struct MediaGallery {
+    room: Room,
+    filter: MediaGalleryFilter,
+    items: Arc<StdMutex<ObservableVector<MediaGalleryItem>>>,
+    pagination: Arc<Mutex<PaginationState>>,
+    _event_handler_guards: Arc<[EventHandlerDropGuard; 2]>,
+}
+
+#[derive(Debug, Default)]
+struct PaginationState {
+    /// The token to continue the back-pagination from.
+    token: Option<String>,
+    /// Whether the start of the room was reached.
+    reached_start: bool,
+}
+
+impl MediaGallery {
+    pub(super) fn new(room: Room, filter: MediaGalleryFilter) -> Self {
+        let items = Arc::new(StdMutex::new(ObservableVector::new()));
+
+        let message_handle = room.add_event_handler({
+            let items = items.clone();
+            move |event: OriginalSyncRoomMessageEvent| async move {
+                if let Some(item) = MediaGalleryItem::from_sync_event(event, filter) {
+                    let mut items = items.lock().unwrap();
+                    if !contains(&items, &item.event_id) {
+                        items.push_front(item);
+                    }
+                }
+            }
+        });
+
+        let redaction_handle = room.add_event_handler({
+            let items = items.clone();
+            move |event: SyncRoomRedactionEvent, room: Room| async move {
+                let room_version =
+                    room.clone_info().room_version().cloned().unwrap_or(RoomVersionId::V1);
+                if let Some(redacts) = event.redacts(&room_version) {
+                    remove(&mut items.lock().unwrap(), redacts);
+                }
+            }
+        });
+
+        let client = &room.client;
+        let guards = [
+            client.event_handler_drop_guard(message_handle),
+            client.event_handler_drop_guard(redaction_handle),
+        ];
+
+        Self {
+            room,
+            filter,
+            items,
+            pagination: Default::default(),
+            _event_handler_guards: Arc::new(guards),
+        }
+    }
+
+    /// Get the current media events and a stream of updates to them.
+    pub fn subscribe(
+        &self,
+    ) -> (Vector<MediaGalleryItem>, impl Stream<Item = Vec<VectorDiff<MediaGalleryItem>>>) {
+        let items = self.items.lock().unwrap();
+        ((*items).clone(), items.subscribe().into_batched_stream())
+    }
+
+    /// Load older media events, until at least `num_items` were added to the
+    /// list or the start of the room is reached.
+    ///
+    /// Returns `true` if the start of the room was reached, in which case
+    /// there are no more media events to load.
+    ///
+    /// In unencrypted rooms, the homeserver only returns the events with a
+    /// media. In encrypted rooms, it can't tell which events have one, so more
+    /// requests might be needed.
+    #[instrument(skip(self), fields(room_id = ?self.room.room_id()))]
+    pub async fn paginate_backwards(&self, num_items: u16) -> Result<bool> {
+        let mut pagination = self.pagination.lock().await;
+        let filter = self.room_event_filter().await?;
+
+        let mut added = 0;
+        while !pagination.reached_start && added < num_items {
+            let options = assign!(MessagesOptions::backward().from(pagination.token.as_deref()), {
+                limit: uint!(20),
+                filter: filter.clone(),
+            });
+            let messages = self.room.messages(options).await?;
+
+            pagination.reached_start = messages.end.is_none() || messages.chunk.is_empty();
+            pagination.token = messages.end;
+
+            let mut items = self.items.lock().unwrap();
+            for event in &messages.chunk {
+                let Some(item) = MediaGalleryItem::from_event(event, self.filter) else {
+                    continue;
+                };
+
+                if !contains(&items, &item.event_id) {
+                    items.push_back(item);
+                    added += 1;
+                }
+            }
+        }
+
+        Ok(pagination.reached_start)
+    }
+
+    /// Whether the start of the room was reached by the back-pagination.
+    pub async fn reached_start(&self) -> bool {
+        self.pagination.lock().await.reached_start
+    }
+
+    async fn room_event_filter(&self) -> Result<RoomEventFilter> {
+        let filter = if self.room.is_encrypted().await? {
+            // The homeserver can't look into encrypted events, they are
+            // filtered once decrypted.
+            assign!(RoomEventFilter::default(), {
+                types: Some(vec!["m.room.message".to_owned(), "m.room.encrypted".to_owned()]),
+            })
+        } else {
+            assign!(RoomEventFilter::default(), {
+                types: Some(vec!["m.room.message".to_owned()]),
+                url_filter: Some(UrlFilter::EventsWithUrl),
+            })
+        };
+
+        Ok(filter)
+    }
+}
+
+fn contains(items: &ObservableVector<MediaGalleryItem>, event_id: &EventId) -> bool {
+    items.iter().any(|item| item.event_id == event_id)
+}
+
+fn remove(items: &mut ObservableVector<MediaGalleryItem>, event_id: &EventId) {
+    if let Some(index) = items.iter().position(|item| item.event_id == event_id) {
+        items.remove(index);
+    }
+}
@@ -139,3 +139,33 @@ pub struct Messages {
     /// A list of state events relevant to showing the `chunk`.
     pub state: Vec<Raw<AnyStateEvent>>,
 }
+
+/// The result of a [`Room::event_with_surrounding_context`] call.
+///
+/// In short, this is a possibly decrypted version of the response of a
+/// `room/context` api call.
+///
+/// [`Room::event_with_surrounding_context`]: super::Room::event_with_surrounding_context
+#[derive(Debug)]
+pub struct EventWithContext {
+    /// The requested event.
+    pub event: TimelineEvent,
+
+    /// The events that happened just before the requested event, in reverse
+    /// chronological order.
+    pub events_before: Vec<TimelineEvent>,
+
+    /// The events that happened just after the requested event, in
+    /// chronological order.
+    pub events_after: Vec<TimelineEvent>,
+
+    /// The token to paginate backwards from the first event of
+    /// `events_before`.
+    pub prev_batch_token: Option<String>,
+
+    /// The token to paginate forwards from the last event of `events_after`.
+    pub next_batch_token: Option<String>,
+
+    /// A list of state events relevant to showing the events.
+    pub state: Vec<Raw<AnyStateEvent>>,
+}
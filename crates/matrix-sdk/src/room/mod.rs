@@ -1,44 +1,54 @@
 //! High-level room API
 
-use std::{borrow::Borrow, collections::BTreeMap, ops::Deref, time::Duration};
+use std::{
+    borrow::Borrow,
+    collections::{BTreeMap, BTreeSet},
+    future::ready,
+    ops::Deref,
+    time::Duration,
+};
 
 use eyeball::SharedObservable;
 use futures_core::Stream;
-use futures_util::stream::FuturesUnordered;
+use futures_util::{stream::FuturesUnordered, StreamExt};
 use matrix_sdk_base::{
     deserialized_responses::{
-        RawAnySyncOrStrippedState, RawSyncOrStrippedState, SyncOrStrippedState, TimelineEvent,
+        RawAnySyncOrStrippedState, RawSyncOrStrippedState, SyncOrStrippedState, SyncTimelineEvent,
+        TimelineEvent,
     },
     instant::Instant,
     store::StateStoreExt,
-    RoomMemberships, StateChanges,
+    MarkedUnreadEventContent, RoomInfo, RoomInfoChangeReasons, RoomMemberships,
+    RoomPrivacyOverrides, RoomPrivacyOverridesEventContent, RoomProfileChange, StateChanges,
 };
 use matrix_sdk_common::timeout::timeout;
 use mime::Mime;
 #[cfg(feature = "e2e-encryption")]
 use ruma::events::{
-    room::encrypted::OriginalSyncRoomEncryptedEvent, AnySyncMessageLikeEvent, AnySyncTimelineEvent,
-    SyncMessageLikeEvent,
+    room::encrypted::OriginalSyncRoomEncryptedEvent, AnySyncMessageLikeEvent, SyncMessageLikeEvent,
 };
 use ruma::{
-    api::client::{
-        config::set_global_account_data,
-        context,
-        error::ErrorKind,
-        filter::LazyLoadOptions,
-        membership::{
-            ban_user, forget_room, get_member_events,
-            invite_user::{self, v3::InvitationRecipient},
-            join_room_by_id, kick_user, leave_room, unban_user, Invite3pid,
+    api::{
+        client::{
+            config::{set_global_account_data, set_room_account_data},
+            context,
+            error::ErrorKind,
+            filter::LazyLoadOptions,
+            membership::{
+                ban_user, forget_room, get_member_events,
+                invite_user::{self, v3::InvitationRecipient},
+                kick_user, leave_room, unban_user, Invite3pid,
+            },
+            message::send_message_event,
+            read_marker::set_read_marker,
+            receipt::create_receipt,
+            redact::redact_event,
+            room::{get_event_by_timestamp, get_room_event, report_content},
+            state::{get_state_events_for_key, send_state_event},
+            tag::{create_tag, delete_tag},
+            typing::create_typing_event::{self, v3::Typing},
         },
-        message::send_message_event,
-        read_marker::set_read_marker,
-        receipt::create_receipt,
-        redact::redact_event,
-        room::{get_room_event, report_content},
-        state::{get_state_events_for_key, send_state_event},
-        tag::{create_tag, delete_tag},
-        typing::create_typing_event::{self, v3::Typing},
+        Direction,
     },
     assign,
     events::{
@@ -47,36 +57,49 @@ use ruma::{
         room::{
             avatar::{self, RoomAvatarEventContent},
             encryption::RoomEncryptionEventContent,
-            history_visibility::HistoryVisibility,
+            guest_access::{GuestAccess, RoomGuestAccessEventContent},
+            history_visibility::{HistoryVisibility, RoomHistoryVisibilityEventContent},
+            member::MembershipState,
             message::RoomMessageEventContent,
             name::RoomNameEventContent,
+            pinned_events::RoomPinnedEventsEventContent,
             power_levels::{RoomPowerLevels, RoomPowerLevelsEventContent},
+            redaction::SyncRoomRedactionEvent,
             server_acl::RoomServerAclEventContent,
             topic::RoomTopicEventContent,
             MediaSource,
         },
         space::{child::SpaceChildEventContent, parent::SpaceParentEventContent},
         tag::{TagInfo, TagName},
-        AnyRoomAccountDataEvent, AnyStateEvent, EmptyStateKey, MessageLikeEventContent,
-        MessageLikeEventType, RedactContent, RedactedStateEventContent, RoomAccountDataEvent,
-        RoomAccountDataEventContent, RoomAccountDataEventType, StateEventContent, StateEventType,
-        StaticEventContent, StaticStateEventContent, SyncStateEvent,
+        AnyRoomAccountDataEvent, AnyStateEvent, AnySyncEphemeralRoomEvent, AnySyncTimelineEvent,
+        AnyTimelineEvent, EmptyStateKey, MessageLikeEventContent, MessageLikeEventType,
+        OriginalSyncMessageLikeEvent, RedactContent, RedactedStateEventContent,
+        RoomAccountDataEvent, RoomAccountDataEventContent, RoomAccountDataEventType,
+        StateEventContent, StateEventType, StaticEventContent, StaticStateEventContent,
+        SyncStateEvent,
     },
     push::{Action, PushConditionRoomCtx},
     serde::Raw,
-    uint, EventId, Int, MatrixToUri, MatrixUri, MxcUri, OwnedEventId, OwnedRoomId, OwnedServerName,
-    OwnedTransactionId, OwnedUserId, TransactionId, UInt, UserId,
+    uint, EventId, Int, MatrixToUri, MatrixUri, MilliSecondsSinceUnixEpoch, MxcUri, OwnedEventId,
+    OwnedMxcUri, OwnedRoomId, OwnedServerName, OwnedTransactionId, OwnedUserId, RoomVersionId,
+    TransactionId, UInt, UserId,
 };
-use serde::de::DeserializeOwned;
+use serde::{de::DeserializeOwned, Serialize};
 use thiserror::Error;
 use tokio::sync::broadcast;
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
 use tracing::{debug, info, instrument, warn};
 
-use self::futures::{SendAttachment, SendMessageLikeEvent, SendRawMessageLikeEvent};
+pub(crate) use self::send_queue::RoomSendQueueState;
+use self::{
+    ephemeral::SendEphemeralEventRequest,
+    futures::{SendAttachment, SendMessageLikeEvent, SendRawMessageLikeEvent},
+};
 use crate::{
-    attachment::AttachmentConfig,
+    attachment::{AttachmentConfig, AttachmentInfo, BaseAudioInfo},
     error::WrongRoomState,
     event_handler::{EventHandler, EventHandlerHandle, SyncEvent},
+    features::ClientFeature,
     media::{MediaFormat, MediaRequest},
     notification_settings::{IsEncrypted, IsOneToOne, RoomNotificationMode},
     sync::RoomUpdate,
@@ -84,13 +107,44 @@ use crate::{
     BaseRoom, Client, Error, HttpError, HttpResult, Result, RoomState, TransmissionProgress,
 };
 
+mod delayed_events;
+mod ephemeral;
+mod export;
 pub mod futures;
+mod invite;
+mod media_gallery;
 mod member;
 mod messages;
-
+mod pinned_events;
+mod report;
+mod retention;
+mod send_queue;
+mod state_batch;
+mod state_snapshot;
+mod threads;
+mod upgrade;
+#[cfg(feature = "experimental-widgets")]
+mod widgets;
+
+#[cfg(feature = "experimental-widgets")]
+pub use self::widgets::RoomWidgetError;
 pub use self::{
+    delayed_events::DelayedEventAction,
+    export::{ExportFormat, ExportHistory, ExportOptions, ExportProgress, ExportRange},
+    invite::{InviteOutcome, InviteReport},
+    media_gallery::{MediaGallery, MediaGalleryFilter, MediaGalleryItem},
     member::RoomMember,
-    messages::{Messages, MessagesOptions},
+    messages::{EventWithContext, Messages, MessagesOptions},
+    pinned_events::{PinnedEvent, PinnedEvents, PinnedEventsError},
+    report::{EventReportBundle, ReportedEvent, ReportedMedia, ReportedSender},
+    retention::RoomRetentionEventContent,
+    send_queue::RoomSendQueue,
+    state_batch::{
+        StateBatchReport, StateBatchValidationError, StateEventOutcome, StateEventToSend,
+    },
+    state_snapshot::{StateSnapshot, StateSnapshotEntry, StateSnapshotOptions},
+    threads::{IncludeThreads, ThreadSummary, ThreadUpdate, Threads, ThreadsOptions},
+    upgrade::{RoomUpgradeError, RoomUpgradeOptions, RoomUpgradeReport},
 };
 
 /// A struct containing methods that are common for Joined, Invited and Left
@@ -126,6 +180,9 @@ impl Room {
     /// Leave this room.
     ///
     /// Only invited and joined rooms can be left.
+    ///
+    /// Returns [`Error::CannotLeaveServerNoticesRoom`] if this is the server
+    /// notices room and the homeserver doesn't allow to leave it.
     #[doc(alias = "reject_invitation")]
     pub async fn leave(&self) -> Result<()> {
         let state = self.state();
@@ -134,7 +191,17 @@ impl Room {
         }
 
         let request = leave_room::v3::Request::new(self.inner.room_id().to_owned());
-        self.client.send(request, None).await?;
+
+        if let Err(error) = self.client.send(request, None).await {
+            if error.client_api_error_kind() == Some(&ErrorKind::Forbidden)
+                && self.is_server_notices_room().await.unwrap_or(false)
+            {
+                return Err(Error::CannotLeaveServerNoticesRoom);
+            }
+
+            return Err(error.into());
+        }
+
         self.client.base_client().room_left(self.room_id()).await?;
         Ok(())
     }
@@ -157,9 +224,19 @@ impl Room {
                 false
             });
 
-        let request = join_room_by_id::v3::Request::new(self.inner.room_id().to_owned());
-        let response = self.client.send(request, None).await?;
-        self.client.base_client().room_joined(&response.room_id).await?;
+        // Our homeserver might not be in the room yet when we're invited, so tell it
+        // which servers can help it join.
+        let via = if prev_room_state == RoomState::Invited {
+            self.via_servers().await.unwrap_or_else(|e| {
+                warn!(room_id = ?self.room_id(), "Couldn't compute the via servers: {e}");
+                Vec::new()
+            })
+        } else {
+            Vec::new()
+        };
+
+        let response_room_id = self.client.send_join_request(self.room_id(), via).await?;
+        self.client.base_client().room_joined(&response_room_id).await?;
 
         if mark_as_direct {
             self.set_is_direct(true).await?;
@@ -290,6 +367,429 @@ impl Room {
         Ok(response)
     }
 
+    /// Get the list of threads of this room, from the most recently active one
+    /// to the least recently active one.
+    ///
+    /// Each thread root is returned along with a summary of its activity: the
+    /// latest event of the thread, the number of replies and whether it has
+    /// unread events for the current user.
+    ///
+    /// See [`Room::subscribe_to_thread_updates`] to get notified of new events
+    /// in the threads of this room as they arrive via sync.
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - Options for the request, including the token to continue
+    ///   paginating from.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use matrix_sdk::{room::ThreadsOptions, Client};
+    /// # use matrix_sdk::ruma::room_id;
+    /// # use url::Url;
+    ///
+    /// # let homeserver = Url::parse("http://example.com").unwrap();
+    /// # async {
+    /// # let client = Client::new(homeserver).await.unwrap();
+    /// # let room_id = room_id!("!test:localhost");
+    /// let room = client.get_room(room_id).unwrap();
+    ///
+    /// let threads = room.threads(ThreadsOptions::participated()).await.unwrap();
+    /// for thread in threads.chunk {
+    ///     println!(
+    ///         "{:?} has {} replies",
+    ///         thread.root_event_id(),
+    ///         thread.num_replies
+    ///     );
+    /// }
+    /// # };
+    /// ```
+    pub async fn threads(&self, options: ThreadsOptions) -> Result<Threads> {
+        let request = options.into_request(self.room_id());
+        let response = self.client.send(request, None).await?;
+
+        let mut chunk = Vec::with_capacity(response.chunk.len());
+        let thread_notification_counts = self.thread_notification_counts();
+
+        for root in response.chunk {
+            let bundled = threads::bundled_thread(&root);
+            let root = self.try_decrypt_event(root).await?;
+
+            let (latest_event, num_replies, current_user_participated) = match bundled {
+                Some(thread) => (
+                    Some(thread.latest_event.cast()),
+                    thread.count,
+                    thread.current_user_participated,
+                ),
+                None => (None, uint!(0), false),
+            };
+
+            let mut summary = ThreadSummary {
+                root,
+                latest_event,
+                num_replies,
+                current_user_participated,
+                is_unread: false,
+                notification_counts: Default::default(),
+            };
+
+            if let Some(root_id) = summary.root_event_id() {
+                summary.notification_counts =
+                    thread_notification_counts.get(&root_id).copied().unwrap_or_default();
+
+                summary.is_unread = self.is_thread_unread(&root_id, &summary).await?;
+            }
+
+            chunk.push(summary);
+        }
+
+        Ok(Threads { chunk, next_batch: response.next_batch })
+    }
+
+    /// Get a live list of the media events of this room, to show them in a
+    /// "gallery" or a "files" view.
+    ///
+    /// The list is loaded by paginating backwards with a filter, instead of
+    /// walking the timeline, and kept up to date with the events received via
+    /// sync, as long as the returned [`MediaGallery`] is alive.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use futures_util::StreamExt;
+    /// # use matrix_sdk::{room::MediaGalleryFilter, Room};
+    /// # async {
+    /// # let room: Room = todo!();
+    /// let gallery = room.media_gallery(MediaGalleryFilter::ImagesAndVideos);
+    /// let (items, mut updates) = gallery.subscribe();
+    ///
+    /// // Load the 20 most recent images and videos.
+    /// gallery.paginate_backwards(20).await?;
+    ///
+    /// while let Some(diffs) = updates.next().await {
+    ///     // Update the view.
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub fn media_gallery(&self, filter: MediaGalleryFilter) -> MediaGallery {
+        MediaGallery::new(self.clone(), filter)
+    }
+
+    /// Get a live list of the pinned events of this room.
+    ///
+    /// The pinned events are loaded with [`PinnedEvents::load_more()`], and
+    /// the list follows the changes of the pinned events received via sync,
+    /// as long as the returned [`PinnedEvents`] is alive.
+    pub fn pinned_events(&self) -> PinnedEvents {
+        PinnedEvents::new(self.clone())
+    }
+
+    /// Get the IDs of the events that are pinned in this room, from the local
+    /// state.
+    pub async fn pinned_event_ids(&self) -> Result<Vec<OwnedEventId>> {
+        let pinned = self
+            .get_state_event_static::<RoomPinnedEventsEventContent>()
+            .await?
+            .and_then(|ev| ev.deserialize().ok())
+            .and_then(|ev| match ev {
+                SyncOrStrippedState::Sync(ev) => {
+                    ev.as_original().map(|ev| ev.content.pinned.clone())
+                }
+                SyncOrStrippedState::Stripped(_) => None,
+            })
+            .unwrap_or_default();
+
+        Ok(pinned)
+    }
+
+    /// Export the history of this room as a transcript, for example to keep
+    /// an archive of it outside of Matrix.
+    ///
+    /// The SDK doesn't keep the history of the room locally, so it is
+    /// paginated from the homeserver and written as it is received, with
+    /// [`ExportHistory::write_to()`]. Encrypted events are decrypted along the
+    /// way, if the keys are available.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - The format of the transcript.
+    ///
+    /// * `range` - The range of the history to export.
+    ///
+    /// * `options` - Whether to bundle the media in the export.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::{
+    /// #     room::{ExportFormat, ExportOptions, ExportRange},
+    /// #     Room,
+    /// # };
+    /// # async {
+    /// # let room: Room = todo!();
+    /// let mut transcript = Vec::new();
+    /// let progress = room
+    ///     .export_history(
+    ///         ExportFormat::Html,
+    ///         ExportRange::all(),
+    ///         ExportOptions::new().include_media(true),
+    ///     )
+    ///     .write_to(&mut transcript)
+    ///     .await?;
+    ///
+    /// println!("Exported {} events", progress.exported_events);
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub fn export_history(
+        &self,
+        format: ExportFormat,
+        range: ExportRange,
+        options: ExportOptions,
+    ) -> ExportHistory<'_> {
+        ExportHistory::new(self, format, range, options)
+    }
+
+    /// Subscribe to the changes of the info of this room.
+    ///
+    /// Unlike [`Room::subscribe_info()`], the returned stream says which parts
+    /// of the room info changed, so the recomputations that don't depend on
+    /// them can be skipped. An empty set means that only details that aren't
+    /// tracked by [`RoomInfoChangeReasons`] might have changed.
+    ///
+    /// If the stream lagged behind, the missed updates are reported as a
+    /// change of everything.
+    ///
+    /// [`Room::subscribe_info()`]: matrix_sdk_base::Room::subscribe_info
+    pub fn subscribe_to_info_changes(&self) -> impl Stream<Item = RoomInfoChangeReasons> {
+        let room_id = self.room_id().to_owned();
+        let updates = BroadcastStream::new(self.client.subscribe_to_room_info_updates());
+
+        updates.filter_map(move |update| {
+            let reasons = match update {
+                Ok(update) => (update.room_id == room_id).then_some(update.reasons),
+                Err(BroadcastStreamRecvError::Lagged(n)) => {
+                    debug!("Lagged behind {n} room info updates");
+                    Some(RoomInfoChangeReasons::all())
+                }
+            };
+
+            ready(reasons)
+        })
+    }
+
+    /// Subscribe to the new events of the threads of this room.
+    ///
+    /// The returned stream yields an item for every threaded event that is
+    /// received via sync for this room, which allows keeping a list of threads
+    /// fetched with [`Room::threads`] up-to-date.
+    pub fn subscribe_to_thread_updates(&self) -> impl Stream<Item = ThreadUpdate> {
+        let updates = BroadcastStream::new(self.subscribe_to_updates());
+
+        updates.flat_map(|update| {
+            let events = match update {
+                Ok(RoomUpdate::Joined { updates, .. }) => updates.timeline.events,
+                Ok(RoomUpdate::Left { updates, .. }) => updates.timeline.events,
+                Ok(RoomUpdate::Invited { .. }) => Vec::new(),
+                Err(BroadcastStreamRecvError::Lagged(n)) => {
+                    warn!("Lagged behind {n} room updates, some thread updates were missed");
+                    Vec::new()
+                }
+            };
+
+            futures_util::stream::iter(events.into_iter().filter_map(|event| {
+                let root_event_id = threads::thread_root_of(&event.event)?;
+                Some(ThreadUpdate { root_event_id, event })
+            }))
+        })
+    }
+
+    /// Subscribe to the ephemeral events of the given type received in this
+    /// room, for example the ones sent with [`Room::send_ephemeral_raw()`].
+    ///
+    /// The returned stream yields an item for every ephemeral event of this
+    /// type that is received via sync for this room.
+    pub fn subscribe_to_ephemeral_events(
+        &self,
+        event_type: &str,
+    ) -> impl Stream<Item = Raw<AnySyncEphemeralRoomEvent>> {
+        let event_type = event_type.to_owned();
+        let updates = BroadcastStream::new(self.subscribe_to_updates());
+
+        updates.flat_map(move |update| {
+            let events = match update {
+                Ok(RoomUpdate::Joined { updates, .. }) => updates.ephemeral,
+                Ok(RoomUpdate::Left { .. } | RoomUpdate::Invited { .. }) => Vec::new(),
+                Err(BroadcastStreamRecvError::Lagged(n)) => {
+                    warn!("Lagged behind {n} room updates, some ephemeral events were missed");
+                    Vec::new()
+                }
+            };
+
+            let event_type = event_type.clone();
+            futures_util::stream::iter(events.into_iter().filter(move |event| {
+                matches!(event.get_field::<String>("type"), Ok(Some(t)) if t == event_type)
+            }))
+        })
+    }
+
+    /// Whether the latest event of the thread with the given root hasn't been
+    /// read by the current user.
+    ///
+    /// A thread is read if the current user sent its latest event, or if it
+    /// has no unread notifications and the threaded receipt of the current
+    /// user is on its latest event.
+    async fn is_thread_unread(&self, root_id: &EventId, summary: &ThreadSummary) -> Result<bool> {
+        let Some(latest_event) = &summary.latest_event else {
+            return Ok(false);
+        };
+        let Some(latest_id) = summary.latest_event_id() else {
+            return Ok(false);
+        };
+
+        let own_user_id = self.own_user_id();
+
+        // Sending an event doesn't create a receipt, but it is read anyway.
+        if latest_event.get_field::<OwnedUserId>("sender").ok().flatten().as_deref()
+            == Some(own_user_id)
+        {
+            return Ok(false);
+        }
+
+        let counts = summary.notification_counts;
+        if counts.notification_count > 0 || counts.highlight_count > 0 {
+            return Ok(true);
+        }
+
+        let thread = ReceiptThread::Thread(root_id.to_owned());
+        for receipt_type in [ReceiptType::Read, ReceiptType::ReadPrivate] {
+            if let Some((event_id, _)) =
+                self.load_user_receipt(receipt_type, thread.clone(), own_user_id).await?
+            {
+                if event_id == latest_id {
+                    return Ok(false);
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Try to decrypt the given event, falling back to the event as-is if it is
+    /// not encrypted or couldn't be decrypted.
+    pub(crate) async fn try_decrypt_event(
+        &self,
+        event: Raw<AnyTimelineEvent>,
+    ) -> Result<TimelineEvent> {
+        #[cfg(feature = "e2e-encryption")]
+        if let Ok(AnySyncTimelineEvent::MessageLike(AnySyncMessageLikeEvent::RoomEncrypted(
+            SyncMessageLikeEvent::Original(_),
+        ))) = event.deserialize_as::<AnySyncTimelineEvent>()
+        {
+            if let Ok(event) = self.decrypt_event(event.cast_ref()).await {
+                return Ok(event);
+            }
+        }
+
+        let push_actions = self.event_push_actions(&event).await?;
+
+        Ok(TimelineEvent { event, encryption_info: None, push_actions })
+    }
+
+    /// Get the retention policy of this room, as set by its
+    /// `m.room.retention` state event, if any.
+    pub async fn retention_policy(&self) -> Result<Option<RoomRetentionEventContent>> {
+        let event = self
+            .get_state_event_static::<RoomRetentionEventContent>()
+            .await?
+            .and_then(|ev| ev.deserialize().ok());
+
+        Ok(event.and_then(|ev| match ev {
+            SyncOrStrippedState::Sync(ev) => ev.as_original().map(|ev| ev.content.clone()),
+            SyncOrStrippedState::Stripped(ev) => Some(ev.content),
+        }))
+    }
+
+    /// Get the maximum time the events of this room are kept for locally.
+    ///
+    /// This is the stricter of the room's retention policy and the default set
+    /// with [`ClientBuilder::default_max_event_lifetime`], or `None` if events
+    /// should be kept forever.
+    ///
+    /// [`ClientBuilder::default_max_event_lifetime`]: crate::ClientBuilder::default_max_event_lifetime
+    pub async fn max_event_lifetime(&self) -> Result<Option<Duration>> {
+        let room_max_lifetime =
+            self.retention_policy().await?.and_then(|policy| policy.max_lifetime());
+
+        Ok(retention::effective_max_lifetime(
+            room_max_lifetime,
+            self.client.inner.default_max_event_lifetime,
+        ))
+    }
+
+    /// Forget the locally stored events of this room that are older than what
+    /// [`Room::max_event_lifetime`] allows.
+    ///
+    /// The events are removed from the caches of the client, including the
+    /// sliding sync caches and the pinned events, along with the receipts and
+    /// the media files that were sent before them. The timelines of the
+    /// room are told to drop them too.
+    ///
+    /// Does nothing if the events of this room should be kept forever.
+    ///
+    /// Returns the number of events that were removed.
+    pub async fn purge_expired_events(&self) -> Result<usize> {
+        let Some(max_lifetime) = self.max_event_lifetime().await? else {
+            return Ok(0);
+        };
+
+        let now = self.client.base_client().clock().now_ms();
+        self.purge_events_before(retention::retention_cutoff(now, max_lifetime)).await
+    }
+
+    /// Forget the locally stored events of this room that were sent before
+    /// `cutoff`.
+    ///
+    /// See [`Room::purge_expired_events`] for what is removed. Returns the
+    /// number of events that were removed.
+    pub(crate) async fn purge_events_before(
+        &self,
+        cutoff: MilliSecondsSinceUnixEpoch,
+    ) -> Result<usize> {
+        let purged =
+            self.client.base_client().purge_room_events_before(self.room_id(), cutoff).await?;
+
+        #[cfg(feature = "experimental-sliding-sync")]
+        let purged = {
+            let mut purged = purged;
+            for sliding_sync in self.client.sliding_syncs() {
+                purged.extend(sliding_sync.purge_room_events_before(self.room_id(), cutoff).await?);
+            }
+            purged
+        };
+
+        self.purge_cached_pinned_events_before(cutoff).await?;
+
+        // The same event can be cached in several places, only remove its media once.
+        let uris: BTreeSet<OwnedMxcUri> = purged
+            .iter()
+            .flat_map(|event| report::media_sources_of(&event.event))
+            .map(|source| match source {
+                MediaSource::Plain(uri) => uri,
+                MediaSource::Encrypted(file) => file.url,
+            })
+            .collect();
+
+        for uri in uris {
+            self.client.media().remove_media_content_for_uri(&uri).await?;
+        }
+
+        let event_ids: BTreeSet<_> = purged.iter().filter_map(|event| event.event_id()).collect();
+        debug!(num_events = event_ids.len(), "Purged the events sent before {cutoff:?}");
+
+        Ok(event_ids.len())
+    }
+
     /// Register a handler for events of a specific type, within this room.
     ///
     /// This method works the same way as [`Client::add_event_handler`], except
@@ -321,19 +821,7 @@ impl Room {
             get_room_event::v3::Request::new(self.room_id().to_owned(), event_id.to_owned());
         let event = self.client.send(request, None).await?.event;
 
-        #[cfg(feature = "e2e-encryption")]
-        if let Ok(AnySyncTimelineEvent::MessageLike(AnySyncMessageLikeEvent::RoomEncrypted(
-            SyncMessageLikeEvent::Original(_),
-        ))) = event.deserialize_as::<AnySyncTimelineEvent>()
-        {
-            if let Ok(event) = self.decrypt_event(event.cast_ref()).await {
-                return Ok(event);
-            }
-        }
-
-        let push_actions = self.event_push_actions(&event).await?;
-
-        Ok(TimelineEvent { event, encryption_info: None, push_actions })
+        self.try_decrypt_event(event).await
     }
 
     /// Fetch the event with the given `EventId` in this room, using the
@@ -374,6 +862,138 @@ impl Room {
         Ok(Some((TimelineEvent { event, encryption_info: None, push_actions }, response.state)))
     }
 
+    /// Fetch the event with the given `EventId` in this room, along with up to
+    /// `context_size` events before and after it.
+    ///
+    /// Returns `Ok(None)` if the homeserver didn't return the event.
+    pub async fn event_with_surrounding_context(
+        &self,
+        event_id: &EventId,
+        context_size: u16,
+    ) -> Result<Option<EventWithContext>> {
+        let mut request =
+            context::get_context::v3::Request::new(self.room_id().to_owned(), event_id.to_owned());
+
+        request.limit = context_size.into();
+        request.filter.lazy_load_options =
+            LazyLoadOptions::Enabled { include_redundant_members: false };
+
+        let response = self.client.send(request, None).await?;
+
+        let Some(event) = response.event else {
+            return Ok(None);
+        };
+        let event = self.try_decrypt_event(event).await?;
+
+        let mut events_before = Vec::with_capacity(response.events_before.len());
+        for event in response.events_before {
+            events_before.push(self.try_decrypt_event(event).await?);
+        }
+
+        let mut events_after = Vec::with_capacity(response.events_after.len());
+        for event in response.events_after {
+            events_after.push(self.try_decrypt_event(event).await?);
+        }
+
+        Ok(Some(EventWithContext {
+            event,
+            events_before,
+            events_after,
+            prev_batch_token: response.start,
+            next_batch_token: response.end,
+            state: response.state,
+        }))
+    }
+
+    /// Get the ID of the closest event to the given timestamp in this room,
+    /// looking in the given direction.
+    ///
+    /// With [`Direction::Forward`], this is the first event sent at or after
+    /// the timestamp, with [`Direction::Backward`] the last event sent at or
+    /// before it. Returns `Ok(None)` if there is no such event.
+    pub async fn event_id_for_timestamp(
+        &self,
+        timestamp: MilliSecondsSinceUnixEpoch,
+        direction: Direction,
+    ) -> Result<Option<OwnedEventId>> {
+        let request = get_event_by_timestamp::v1::Request::new(
+            self.room_id().to_owned(),
+            direction,
+            timestamp,
+        );
+
+        match self.client.send(request, None).await {
+            Ok(response) => Ok(Some(response.event_id)),
+            Err(err) if err.client_api_error_kind() == Some(&ErrorKind::NotFound) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Gather everything that is known about the event with the given ID, to
+    /// submit it to moderation tooling.
+    ///
+    /// The bundle contains the event and a few of the events around it, all
+    /// decrypted if possible, the profile and membership events of its sender
+    /// and the media attached to it.
+    pub async fn event_report_bundle(&self, event_id: &EventId) -> Result<EventReportBundle> {
+        let mut request =
+            context::get_context::v3::Request::new(self.room_id().to_owned(), event_id.to_owned());
+
+        request.limit = uint!(10);
+        request.filter.lazy_load_options =
+            LazyLoadOptions::Enabled { include_redundant_members: false };
+
+        let response = self.client.send(request, None).await?;
+        let event = response.event.ok_or(Error::InsufficientData)?;
+
+        let sender_id: OwnedUserId = event.get_field("sender")?.ok_or(Error::InsufficientData)?;
+
+        // The media of an encrypted event are only known once it is decrypted.
+        let event = self.try_decrypt_event(event).await?;
+        let media = report::media_of(&event.event);
+        let event = ReportedEvent::from(event);
+
+        let mut events_before = Vec::with_capacity(response.events_before.len());
+        for event in response.events_before {
+            events_before.push(self.try_decrypt_event(event).await?.into());
+        }
+
+        let mut events_after = Vec::with_capacity(response.events_after.len());
+        for event in response.events_after {
+            events_after.push(self.try_decrypt_event(event).await?.into());
+        }
+
+        let membership_events = response
+            .state
+            .into_iter()
+            .filter(|event| {
+                event.get_field::<StateEventType>("type").ok().flatten()
+                    == Some(StateEventType::RoomMember)
+                    && event.get_field::<OwnedUserId>("state_key").ok().flatten().as_ref()
+                        == Some(&sender_id)
+            })
+            .collect();
+
+        let member = self.get_member_no_sync(&sender_id).await?;
+        let sender = ReportedSender {
+            display_name: member.as_ref().and_then(|m| m.display_name()).map(ToOwned::to_owned),
+            avatar_url: member.as_ref().and_then(|m| m.avatar_url()).map(ToOwned::to_owned),
+            membership: member.map(|m| m.membership().clone()),
+            user_id: sender_id,
+            membership_events,
+        };
+
+        Ok(EventReportBundle {
+            room_id: self.room_id().to_owned(),
+            event_id: event_id.to_owned(),
+            event,
+            events_before,
+            events_after,
+            sender,
+            media,
+        })
+    }
+
     pub(crate) async fn request_members(&self) -> Result<()> {
         self.client
             .locks()
@@ -440,6 +1060,58 @@ impl Room {
         Ok(self.inner.is_encrypted())
     }
 
+    /// Get the settings that are effectively used to create the room keys of
+    /// this room, if it is encrypted. If the room encryption state is not
+    /// synced yet, it will send a request to fetch it.
+    ///
+    /// The rotation periods are the ones of the `m.room.encryption` state
+    /// event of the room, restricted by
+    /// [`EncryptionSettings::room_key_rotation_limits`]. The content of the
+    /// state event itself is available with
+    /// [`BaseRoom::encryption_settings`].
+    ///
+    /// [`EncryptionSettings::room_key_rotation_limits`]: crate::encryption::EncryptionSettings::room_key_rotation_limits
+    #[cfg(feature = "e2e-encryption")]
+    pub async fn effective_encryption_settings(
+        &self,
+    ) -> Result<Option<matrix_sdk_base::crypto::EncryptionSettings>> {
+        if !self.is_encryption_state_synced() {
+            self.request_encryption_state().await?;
+        }
+
+        Ok(self.client.base_client().room_encryption_settings(self.room_id()))
+    }
+
+    /// Get the members of this room who replaced their identity, without the
+    /// change being acknowledged.
+    ///
+    /// Those are the members whose [`UserIdentity`] has a verification
+    /// violation or a pin violation. This is meant to show a warning in the
+    /// room, and to resolve an [`OlmError::IdentityViolations`] error when
+    /// [`EncryptionSettings::error_on_unverified_devices`] is set.
+    ///
+    /// [`UserIdentity`]: crate::encryption::identities::UserIdentity
+    /// [`OlmError::IdentityViolations`]: matrix_sdk_base::crypto::OlmError::IdentityViolations
+    /// [`EncryptionSettings::error_on_unverified_devices`]: crate::encryption::EncryptionSettings::error_on_unverified_devices
+    #[cfg(feature = "e2e-encryption")]
+    pub async fn identity_violations(&self) -> Result<Vec<OwnedUserId>> {
+        let mut violations = Vec::new();
+
+        for member in self.members(RoomMemberships::ACTIVE).await? {
+            let Some(identity) =
+                self.client.encryption().get_user_identity(member.user_id()).await?
+            else {
+                continue;
+            };
+
+            if identity.has_verification_violation() || identity.has_pin_violation() {
+                violations.push(member.user_id().to_owned());
+            }
+        }
+
+        Ok(violations)
+    }
+
     fn are_events_visible(&self) -> bool {
         if let RoomState::Invited = self.inner.state() {
             return matches!(
@@ -937,6 +1609,69 @@ impl Room {
         self.client.send(request, None).await
     }
 
+    /// Manually mark this room as unread, or clear that flag ([MSC2867]).
+    ///
+    /// The flag is stored in the room account data, so other clients of the
+    /// user see it too. It is also cleared automatically when a read receipt
+    /// is sent for this room.
+    ///
+    /// # Arguments
+    /// * `unread` - Whether the room should be marked as unread.
+    ///
+    /// [MSC2867]: https://github.com/matrix-org/matrix-spec-proposals/pull/2867
+    pub async fn set_unread(&self, unread: bool) -> Result<()> {
+        let user_id =
+            self.client.user_id().ok_or_else(|| Error::from(HttpError::AuthenticationRequired))?;
+
+        let request = set_room_account_data::v3::Request::new(
+            user_id.to_owned(),
+            self.inner.room_id().to_owned(),
+            &MarkedUnreadEventContent::new(unread),
+        )?;
+
+        self.client.send(request, None).await?;
+        Ok(())
+    }
+
+    /// Set what the user doesn't share with the other members of this room,
+    /// like read receipts or typing notifications.
+    ///
+    /// The overrides are stored locally. If `share_with_other_clients` is
+    /// `true`, they are also stored in the room account data, so the other
+    /// clients of the user apply them too.
+    ///
+    /// The read receipts are still sent as private read receipts while they
+    /// are suppressed, so the read state of the room is kept.
+    ///
+    /// # Arguments
+    /// * `overrides` - The new overrides of this room.
+    /// * `share_with_other_clients` - Whether to store the overrides in the
+    ///   room account data.
+    pub async fn set_privacy_overrides(
+        &self,
+        overrides: RoomPrivacyOverrides,
+        share_with_other_clients: bool,
+    ) -> Result<()> {
+        if share_with_other_clients {
+            let user_id = self
+                .client
+                .user_id()
+                .ok_or_else(|| Error::from(HttpError::AuthenticationRequired))?;
+
+            let request = set_room_account_data::v3::Request::new(
+                user_id.to_owned(),
+                self.inner.room_id().to_owned(),
+                &RoomPrivacyOverridesEventContent::new(overrides),
+            )?;
+
+            self.client.send(request, None).await?;
+        }
+
+        self.client.base_client().set_room_privacy_overrides(self.room_id(), overrides).await?;
+
+        Ok(())
+    }
+
     /// Sets whether this room is a DM.
     ///
     /// When setting this room as DM, it will be marked as DM for all active
@@ -996,6 +1731,7 @@ impl Room {
         &self,
         event: &Raw<OriginalSyncRoomEncryptedEvent>,
     ) -> Result<TimelineEvent> {
+        use matrix_sdk_base::crypto::MegolmError;
         use ruma::events::room::encrypted::EncryptedEventScheme;
 
         let machine = self.client.olm_machine().await;
@@ -1005,7 +1741,14 @@ impl Room {
                     Ok(event) => event,
                     Err(e) => {
                         let event = event.deserialize()?;
-                        if let EncryptedEventScheme::MegolmV1AesSha2(c) = event.content.scheme {
+
+                        // Only a missing room key can be fixed by downloading it from the
+                        // backup.
+                        if let (
+                            EncryptedEventScheme::MegolmV1AesSha2(c),
+                            MegolmError::MissingRoomKey(_),
+                        ) = (event.content.scheme, &e)
+                        {
                             self.client
                                 .encryption()
                                 .backups()
@@ -1024,6 +1767,28 @@ impl Room {
         }
     }
 
+    /// Get the key distribution log of this room, to audit who could have
+    /// decrypted the messages sent from this device.
+    ///
+    /// The log lists every device that a room key of this room was shared
+    /// with, or withheld from along with the reason, in the order the room
+    /// keys were sent out.
+    ///
+    /// # Arguments
+    ///
+    /// * `session_id` - Only return the entries of the room key with this
+    ///   session ID, as found in the content of an encrypted event.
+    #[cfg(feature = "e2e-encryption")]
+    pub async fn key_distribution_log(
+        &self,
+        session_id: Option<&str>,
+    ) -> Result<Vec<matrix_sdk_base::crypto::olm::KeyDistributionLogEntry>> {
+        let machine = self.client.olm_machine().await;
+        let machine = machine.as_ref().ok_or(Error::NoOlmMachine)?;
+
+        Ok(machine.key_distribution_log(self.room_id(), session_id).await?)
+    }
+
     /// Ban the user with `UserId` from this room.
     ///
     /// # Arguments
@@ -1033,6 +1798,8 @@ impl Room {
     /// * `reason` - The reason for banning this user.
     #[instrument(skip_all)]
     pub async fn ban_user(&self, user_id: &UserId, reason: Option<&str>) -> Result<()> {
+        self.client.ensure_not_guest()?;
+
         let request = assign!(
             ban_user::v3::Request::new(self.room_id().to_owned(), user_id.to_owned()),
             { reason: reason.map(ToOwned::to_owned) }
@@ -1050,6 +1817,8 @@ impl Room {
     /// * `reason` - The reason for unbanning this user.
     #[instrument(skip_all)]
     pub async fn unban_user(&self, user_id: &UserId, reason: Option<&str>) -> Result<()> {
+        self.client.ensure_not_guest()?;
+
         let request = assign!(
             unban_user::v3::Request::new(self.room_id().to_owned(), user_id.to_owned()),
             { reason: reason.map(ToOwned::to_owned) }
@@ -1068,6 +1837,8 @@ impl Room {
     /// * `reason` - Optional reason why the room member is being kicked out.
     #[instrument(skip_all)]
     pub async fn kick_user(&self, user_id: &UserId, reason: Option<&str>) -> Result<()> {
+        self.client.ensure_not_guest()?;
+
         let request = assign!(
             kick_user::v3::Request::new(self.room_id().to_owned(), user_id.to_owned()),
             { reason: reason.map(ToOwned::to_owned) }
@@ -1083,6 +1854,8 @@ impl Room {
     /// * `user_id` - The `UserId` of the user to invite to the room.
     #[instrument(skip_all)]
     pub async fn invite_user_by_id(&self, user_id: &UserId) -> Result<()> {
+        self.client.ensure_not_guest()?;
+
         let recipient = InvitationRecipient::UserId { user_id: user_id.to_owned() };
         let request = invite_user::v3::Request::new(self.room_id().to_owned(), recipient);
         self.client.send(request, None).await?;
@@ -1097,6 +1870,8 @@ impl Room {
     /// * `invite_id` - A third party id of a user to invite to the room.
     #[instrument(skip_all)]
     pub async fn invite_user_by_3pid(&self, invite_id: Invite3pid) -> Result<()> {
+        self.client.ensure_not_guest()?;
+
         let recipient = InvitationRecipient::ThirdPartyId(invite_id);
         let request = invite_user::v3::Request::new(self.room_id().to_owned(), recipient);
         self.client.send(request, None).await?;
@@ -1112,6 +1887,10 @@ impl Room {
     /// called on every key stroke, since it will do nothing while typing is
     /// active.
     ///
+    /// Nothing is sent when the user starts typing while
+    /// [`ClientFeature::TypingNotifications`] is disabled, or while they are
+    /// suppressed in this room with [`Room::set_privacy_overrides()`].
+    ///
     /// # Arguments
     ///
     /// * `typing` - Whether the user is typing or has stopped typing.
@@ -1141,6 +1920,13 @@ impl Room {
     pub async fn typing_notice(&self, typing: bool) -> Result<()> {
         self.ensure_room_joined()?;
 
+        if typing
+            && (!self.client.features().is_enabled(ClientFeature::TypingNotifications)
+                || self.privacy_overrides().suppress_typing_notifications)
+        {
+            return Ok(());
+        }
+
         // Only send a request to the homeserver if the old timeout has elapsed
         // or the typing notice changed state within the `TYPING_NOTICE_TIMEOUT`
         let send = if let Some(typing_time) =
@@ -1207,18 +1993,29 @@ impl Room {
     ///   [`ReceiptType::FullyRead`][create_receipt::v3::ReceiptType::FullyRead].
     ///
     /// * `event_id` - The `EventId` of the event to set the receipt on.
+    ///
+    /// A public read receipt is sent as a private read receipt while
+    /// [`ClientFeature::ReadReceipts`] is disabled, or while they are
+    /// suppressed in this room with [`Room::set_privacy_overrides()`].
     #[instrument(skip_all)]
     pub async fn send_single_receipt(
         &self,
-        receipt_type: create_receipt::v3::ReceiptType,
+        mut receipt_type: create_receipt::v3::ReceiptType,
         thread: ReceiptThread,
         event_id: OwnedEventId,
     ) -> Result<()> {
+        if receipt_type == create_receipt::v3::ReceiptType::Read && !self.sends_read_receipts() {
+            receipt_type = create_receipt::v3::ReceiptType::ReadPrivate;
+        }
+
         let mut request =
             create_receipt::v3::Request::new(self.room_id().to_owned(), receipt_type, event_id);
         request.thread = thread;
 
         self.client.send(request, None).await?;
+
+        self.clear_marked_unread().await;
+
         Ok(())
     }
 
@@ -1229,23 +2026,56 @@ impl Room {
     /// * `receipts` - The `Receipts` to send.
     ///
     /// If `receipts` is empty, this is a no-op.
+    ///
+    /// The public read receipt is sent as a private read receipt while
+    /// [`ClientFeature::ReadReceipts`] is disabled, or while they are
+    /// suppressed in this room with [`Room::set_privacy_overrides()`].
     #[instrument(skip_all)]
     pub async fn send_multiple_receipts(&self, receipts: Receipts) -> Result<()> {
         if receipts.is_empty() {
             return Ok(());
         }
 
-        let Receipts { fully_read, public_read_receipt, private_read_receipt } = receipts;
+        let Receipts { fully_read, mut public_read_receipt, mut private_read_receipt } = receipts;
+
+        if !self.sends_read_receipts() {
+            private_read_receipt = private_read_receipt.or(public_read_receipt.take());
+        }
+
         let request = assign!(set_read_marker::v3::Request::new(self.room_id().to_owned()), {
             fully_read,
             read_receipt: public_read_receipt,
             private_read_receipt,
         });
 
-        self.client.send(request, None).await?;
+        self.client.send(request, None).await?;
+
+        self.clear_marked_unread().await;
+
         Ok(())
     }
 
+    /// Clear the flag that marks this room as unread, after a read receipt
+    /// was sent.
+    ///
+    /// The receipt was sent already, so failing to clear the flag is only
+    /// logged.
+    async fn clear_marked_unread(&self) {
+        if self.is_marked_unread() {
+            if let Err(error) = self.set_unread(false).await {
+                warn!(
+                    "Couldn't clear the unread flag of the room after sending a receipt: {error}"
+                );
+            }
+        }
+    }
+
+    /// Whether public read receipts are sent in this room.
+    fn sends_read_receipts(&self) -> bool {
+        self.client.features().is_enabled(ClientFeature::ReadReceipts)
+            && !self.privacy_overrides().suppress_read_receipts
+    }
+
     /// Enable End-to-end encryption in this room.
     ///
     /// This method will be a noop if encryption is already enabled, otherwise
@@ -1455,6 +2285,55 @@ impl Room {
         SendMessageLikeEvent::new(self, content)
     }
 
+    /// Send a message-like event to this room and get it back as a typed
+    /// event.
+    ///
+    /// This works like [`Room::send()`], except that the event is fetched
+    /// back from the homeserver once it was sent, and decrypted if the room is
+    /// encrypted. The returned event is the same that an event handler
+    /// registered for `OriginalSyncMessageLikeEvent<C>` receives, with the
+    /// timestamp and the unsigned data of the homeserver. This is handy for
+    /// custom events, that can be declared once with the
+    /// [`custom_event!`](crate::custom_event) macro and then be used with
+    /// [`Client::add_event_handler()`], this method and
+    /// [`Room::get_state_event_static()`] without going through raw JSON.
+    ///
+    /// # Arguments
+    ///
+    /// * `content` - The content of the message event.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use matrix_sdk::{Client, ruma::room_id};
+    /// # use url::Url;
+    /// matrix_sdk::custom_event! {
+    ///     #[ruma_event(type = "org.example.ping", kind = MessageLike)]
+    ///     struct PingEventContent {
+    ///         nonce: String,
+    ///     }
+    /// }
+    ///
+    /// # async {
+    /// # let homeserver = Url::parse("http://localhost:8080")?;
+    /// # let client = Client::new(homeserver).await?;
+    /// # let room = client.get_room(room_id!("!test:localhost")).unwrap();
+    /// let event =
+    ///     room.send_typed(PingEventContent { nonce: "1234".to_owned() }).await?;
+    /// println!("Sent ping {} with nonce {}", event.event_id, event.content.nonce);
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub async fn send_typed<C>(&self, content: C) -> Result<OriginalSyncMessageLikeEvent<C>>
+    where
+        C: MessageLikeEventContent,
+        OriginalSyncMessageLikeEvent<C>: DeserializeOwned,
+    {
+        let response = self.send(content).await?;
+        let event = self.event(&response.event_id).await?;
+
+        Ok(event.event.deserialize_as()?)
+    }
+
     /// Run /keys/query requests for all the non-tracked users.
     #[cfg(feature = "e2e-encryption")]
     async fn query_keys_for_untracked_users(&self) -> Result<()> {
@@ -1600,6 +2479,67 @@ impl Room {
         SendAttachment::new(self, body, content_type, data, config)
     }
 
+    /// Send a voice message to this room.
+    ///
+    /// This uploads the audio clip like [`send_attachment()`] and sends an
+    /// `m.audio` message with the `org.matrix.msc1767.audio` and
+    /// `org.matrix.msc3245.voice` content blocks defined in [MSC3245], so
+    /// that clients can display it as a voice message rather than as a plain
+    /// audio file.
+    ///
+    /// # Arguments
+    /// * `content_type` - The type of the audio clip, usually `audio/ogg`.
+    ///
+    /// * `data` - The raw bytes of the audio clip.
+    ///
+    /// * `waveform` - The amplitudes of the audio clip, each in the range
+    /// `0..=1024`. It's recommended to provide between 30 and 120 values.
+    ///
+    /// * `duration` - The duration of the audio clip.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use std::{fs, time::Duration};
+    /// # use matrix_sdk::{Client, ruma::room_id};
+    /// # use url::Url;
+    /// # async {
+    /// # let homeserver = Url::parse("http://localhost:8080")?;
+    /// # let mut client = Client::new(homeserver).await?;
+    /// # let room_id = room_id!("!test:localhost");
+    /// let recording = fs::read("/home/example/recording.ogg")?;
+    /// let waveform = vec![0, 256, 512, 1024, 512, 256, 0];
+    ///
+    /// if let Some(room) = client.get_room(&room_id) {
+    ///     room.send_voice_message(
+    ///         &"audio/ogg".parse()?,
+    ///         recording,
+    ///         waveform,
+    ///         Duration::from_secs(4),
+    ///     )
+    ///     .await?;
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    ///
+    /// [`send_attachment()`]: Self::send_attachment
+    /// [MSC3245]: https://github.com/matrix-org/matrix-spec-proposals/pull/3245
+    #[instrument(skip_all)]
+    pub fn send_voice_message<'a>(
+        &'a self,
+        content_type: &'a Mime,
+        data: Vec<u8>,
+        waveform: Vec<u16>,
+        duration: Duration,
+    ) -> SendAttachment<'a> {
+        let audio_info =
+            BaseAudioInfo { duration: Some(duration), size: UInt::new(data.len() as u64) };
+        let config = AttachmentConfig::new()
+            .info(AttachmentInfo::Voice { audio_info, waveform: Some(waveform) });
+
+        SendAttachment::new(self, "Voice message", content_type, data, config)
+    }
+
     /// Prepare and send an attachment to this room.
     ///
     /// This will upload the given data that the reader produces using the
@@ -1714,13 +2654,60 @@ impl Room {
     }
 
     /// Sets the name of this room.
+    ///
+    /// The new name is visible in the room info right away, and reverted if
+    /// sending it to the homeserver fails.
     pub async fn set_name(&self, name: String) -> Result<send_state_event::v3::Response> {
-        self.send_state_event(RoomNameEventContent::new(name)).await
+        self.send_profile_state_event(RoomProfileChange::Name(name)).await
     }
 
     /// Sets a new topic for this room.
+    ///
+    /// The new topic is visible in the room info right away, and reverted if
+    /// sending it to the homeserver fails.
     pub async fn set_room_topic(&self, topic: &str) -> Result<send_state_event::v3::Response> {
-        self.send_state_event(RoomTopicEventContent::new(topic.into())).await
+        self.send_profile_state_event(RoomProfileChange::Topic(topic.to_owned())).await
+    }
+
+    /// Sets who can read the history of this room.
+    ///
+    /// Making the history of an encrypted room world-readable is allowed, but
+    /// it doesn't achieve much: users who aren't members of the room don't
+    /// get the room keys, so they can't decrypt the history anyway. A warning
+    /// is logged in that case.
+    pub async fn set_history_visibility(
+        &self,
+        history_visibility: HistoryVisibility,
+    ) -> Result<send_state_event::v3::Response> {
+        if matches!(history_visibility, HistoryVisibility::WorldReadable)
+            && self.is_encrypted().await?
+        {
+            warn!(
+                room_id = ?self.room_id(),
+                "Making the history of an encrypted room world-readable, \
+                 it can't be decrypted by users who aren't members of the room"
+            );
+        }
+
+        self.send_state_event(RoomHistoryVisibilityEventContent::new(history_visibility)).await
+    }
+
+    /// Sets whether guest users are allowed to join this room.
+    ///
+    /// Guest users can only join rooms whose join rule is public, a warning is
+    /// logged if guests are allowed in a room that isn't public.
+    pub async fn set_guest_access(
+        &self,
+        guest_access: GuestAccess,
+    ) -> Result<send_state_event::v3::Response> {
+        if matches!(guest_access, GuestAccess::CanJoin) && !self.is_public() {
+            warn!(
+                room_id = ?self.room_id(),
+                "Allowing guests in a room that isn't public, they won't be able to join it"
+            );
+        }
+
+        self.send_state_event(RoomGuestAccessEventContent::new(guest_access)).await
     }
 
     /// Sets the new avatar url for this room.
@@ -1735,16 +2722,90 @@ impl Room {
     ) -> Result<send_state_event::v3::Response> {
         self.ensure_room_joined()?;
 
-        let mut room_avatar_event = RoomAvatarEventContent::new();
-        room_avatar_event.url = Some(url.to_owned());
-        room_avatar_event.info = info.map(Box::new);
-
-        self.send_state_event(room_avatar_event).await
+        self.send_profile_state_event(RoomProfileChange::Avatar {
+            url: Some(url.to_owned()),
+            info: info.map(Box::new),
+        })
+        .await
     }
 
     /// Removes the avatar from the room
+    ///
+    /// The avatar is removed from the room info right away, and restored if
+    /// sending the change to the homeserver fails.
     pub async fn remove_avatar(&self) -> Result<send_state_event::v3::Response> {
-        self.send_state_event(RoomAvatarEventContent::new()).await
+        self.send_profile_state_event(RoomProfileChange::Avatar { url: None, info: None }).await
+    }
+
+    /// Send a state event changing the name, topic or avatar of the room, and
+    /// apply the change to the room info until the homeserver confirms it.
+    ///
+    /// This avoids showing the old value until the event comes back via sync.
+    /// The change is persisted in the state store, and gets the ID of the
+    /// state event once it was sent, like the state events received via sync.
+    /// If sending the event fails, the change is reverted, unless it was
+    /// overwritten by a sync in the meantime.
+    async fn send_profile_state_event(
+        &self,
+        change: RoomProfileChange,
+    ) -> Result<send_state_event::v3::Response> {
+        match &change {
+            RoomProfileChange::Name(name) => {
+                let content = RoomNameEventContent::new(name.clone());
+                self.send_profile_state_event_with(change, content).await
+            }
+            RoomProfileChange::Topic(topic) => {
+                let content = RoomTopicEventContent::new(topic.clone());
+                self.send_profile_state_event_with(change, content).await
+            }
+            RoomProfileChange::Avatar { url, info } => {
+                let mut content = RoomAvatarEventContent::new();
+                content.url = url.clone();
+                content.info = info.clone();
+                self.send_profile_state_event_with(change, content).await
+            }
+        }
+    }
+
+    async fn send_profile_state_event_with(
+        &self,
+        change: RoomProfileChange,
+        content: impl StateEventContent<StateKey = EmptyStateKey>,
+    ) -> Result<send_state_event::v3::Response> {
+        let pending = {
+            let _sync_lock = self.client.base_client().sync_lock().read().await;
+            let mut room_info = self.clone_info();
+            let pending = room_info.apply_profile_change(change);
+            self.save_room_info(room_info).await?;
+            pending
+        };
+
+        let result = self.send_state_event(content).await;
+
+        let _sync_lock = self.client.base_client().sync_lock().read().await;
+        let mut room_info = self.clone_info();
+        let changed = match &result {
+            Ok(response) => room_info.confirm_profile_change(&pending, response.event_id.clone()),
+            Err(_) => room_info.revert_profile_change(pending),
+        };
+
+        if changed {
+            if let Err(error) = self.save_room_info(room_info).await {
+                warn!(room_id = ?self.room_id(), "Couldn't persist the room profile change: {error}");
+            }
+        }
+
+        result
+    }
+
+    /// Persist the room info in the state store, and update it in memory.
+    async fn save_room_info(&self, room_info: RoomInfo) -> Result<()> {
+        let mut changes = StateChanges::default();
+        changes.add_room(room_info.clone());
+        self.client.store().save_changes(&changes).await?;
+        self.set_room_info(room_info);
+
+        Ok(())
     }
 
     /// Uploads a new avatar for this room.
@@ -1872,6 +2933,8 @@ impl Room {
         K: AsRef<str> + ?Sized,
     {
         self.ensure_room_joined()?;
+        self.client.ensure_not_guest()?;
+
         let request =
             send_state_event::v3::Request::new(self.room_id().to_owned(), state_key, &content)?;
         let response = self.client.send(request, None).await?;
@@ -1920,6 +2983,7 @@ impl Room {
         content: impl IntoRawStateEventContent,
     ) -> Result<send_state_event::v3::Response> {
         self.ensure_room_joined()?;
+        self.client.ensure_not_guest()?;
 
         let request = send_state_event::v3::Request::new_raw(
             self.room_id().to_owned(),
@@ -1931,6 +2995,64 @@ impl Room {
         Ok(self.client.send(request, None).await?)
     }
 
+    /// Send a user-defined ephemeral event to this room, as defined in
+    /// [MSC2477].
+    ///
+    /// Ephemeral events are not persisted in the room history, they are only
+    /// delivered via sync to the members of the room that are online, which
+    /// makes them suitable for data that changes at a high frequency, like
+    /// the cursors of a collaborative whiteboard. They are not encrypted,
+    /// even in encrypted rooms.
+    ///
+    /// The other members receive them with
+    /// [`Room::subscribe_to_ephemeral_events()`].
+    ///
+    /// # Arguments
+    ///
+    /// * `event_type` - The type of the event.
+    ///
+    /// * `content` - The content of the event, as any type that can be
+    ///   serialized to a JSON object.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use serde_json::json;
+    ///
+    /// # async {
+    /// # let homeserver = url::Url::parse("http://localhost:8080")?;
+    /// # let mut client = matrix_sdk::Client::new(homeserver).await?;
+    /// # let room_id = matrix_sdk::ruma::room_id!("!test:localhost");
+    ///
+    /// if let Some(room) = client.get_room(&room_id) {
+    ///     room.send_ephemeral_raw("org.example.whiteboard.cursor", json!({
+    ///         "x": 120,
+    ///         "y": 42,
+    ///     })).await?;
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    ///
+    /// [MSC2477]: https://github.com/matrix-org/matrix-spec-proposals/pull/2477
+    #[instrument(skip_all)]
+    pub async fn send_ephemeral_raw(
+        &self,
+        event_type: &str,
+        content: impl Serialize,
+    ) -> Result<()> {
+        self.ensure_room_joined()?;
+
+        let request = SendEphemeralEventRequest {
+            room_id: self.room_id().to_owned(),
+            event_type: event_type.to_owned(),
+            txn_id: TransactionId::new(),
+            content: serde_json::value::to_raw_value(&content)?,
+        };
+
+        self.client.send(request, None).await?;
+        Ok(())
+    }
+
     /// Strips all information out of an event of the room.
     ///
     /// Returns the [`redact_event::v3::Response`] from the server.
@@ -1939,6 +3061,13 @@ impl Room {
     /// with a power level greater than or equal to the redact power level of
     /// the room may redact events there.
     ///
+    /// Once the server accepted the redaction, it is applied to the locally
+    /// stored data right away, without waiting for it to come back via sync,
+    /// including the cached pinned events and the timeline queues of sliding
+    /// sync. See
+    /// [`subscribe_to_redactions()`](BaseRoom::subscribe_to_redactions) to be
+    /// notified about redactions.
+    ///
     /// # Arguments
     ///
     /// * `event_id` - The ID of the event to redact
@@ -1978,7 +3107,87 @@ impl Room {
             { reason: reason.map(ToOwned::to_owned) }
         );
 
-        self.client.send(request, None).await
+        let response = self.client.send(request, None).await?;
+        self.apply_local_redaction(event_id, &response.event_id, reason).await;
+
+        Ok(response)
+    }
+
+    /// Apply a redaction the server accepted to the local data, by building
+    /// the redaction event as it will come back via sync.
+    async fn apply_local_redaction(
+        &self,
+        redacts: &EventId,
+        redaction_event_id: &EventId,
+        reason: Option<&str>,
+    ) {
+        let mut content = serde_json::json!({ "redacts": redacts });
+        if let Some(reason) = reason {
+            content["reason"] = reason.into();
+        }
+
+        let redaction = Raw::new(&serde_json::json!({
+            "type": "m.room.redaction",
+            "event_id": redaction_event_id,
+            "sender": self.own_user_id(),
+            "origin_server_ts": self.client.base_client().clock().now_ms(),
+            "redacts": redacts,
+            "content": content,
+        }));
+
+        let redaction = match redaction {
+            Ok(redaction) => redaction.cast(),
+            Err(error) => {
+                warn!("Couldn't apply the redaction of {redacts} locally: {error}");
+                return;
+            }
+        };
+
+        if let Err(error) =
+            self.client.base_client().receive_local_redaction(self.room_id(), &redaction).await
+        {
+            warn!("Couldn't apply the redaction of {redacts} locally: {error}");
+            return;
+        }
+
+        // The timeline queues of sliding sync apply the redactions they receive
+        // themselves, but this one won't be received before the next response.
+        let room_version = self.clone_info().room_version().cloned().unwrap_or(RoomVersionId::V1);
+
+        #[cfg(feature = "experimental-sliding-sync")]
+        for sliding_sync in self.client.sliding_syncs() {
+            if let Err(error) = sliding_sync
+                .redact_room_event(self.room_id(), redacts, &redaction, &room_version)
+                .await
+            {
+                warn!("Couldn't apply the redaction of {redacts} to sliding sync: {error}");
+            }
+        }
+
+        if let Err(error) =
+            self.redact_cached_pinned_event(redacts, &redaction, &room_version).await
+        {
+            warn!("Couldn't apply the redaction of {redacts} to the pinned events: {error}");
+        }
+    }
+
+    /// Apply the redactions among the given events of this room, received via
+    /// sync, to the pinned events kept in the state store.
+    ///
+    /// The state store and the room info are redacted by the base client, and
+    /// the timeline queues of sliding sync redact their own events.
+    pub(crate) async fn redact_cached_events_from_sync(&self, events: &[SyncTimelineEvent]) {
+        let room_version = self.clone_info().room_version().cloned().unwrap_or(RoomVersionId::V1);
+
+        for (redacts, redaction) in
+            events.iter().filter_map(|event| redaction_of(&event.event, &room_version))
+        {
+            if let Err(error) =
+                self.redact_cached_pinned_event(&redacts, &redaction, &room_version).await
+            {
+                warn!("Couldn't apply the redaction of {redacts} to the pinned events: {error}");
+            }
+        }
     }
 
     /// Returns true if the user with the given user_id is able to redact
@@ -2065,14 +3274,16 @@ impl Room {
 
         // Filter out server names that:
         // - Are blocked due to server ACLs
-        // - Are IP addresses
+        // - Can't be used to route requests, like IP addresses
         let members: Vec<_> = self
             .members_no_sync(RoomMemberships::JOIN)
             .await?
             .into_iter()
             .filter(|member| {
-                let server = member.user_id().server_name();
-                acl.filter(|acl| !acl.is_allowed(server)).is_none() && !server.is_ip_literal()
+                let Some(server) = self.client.resolve_server_for_user(member.user_id()) else {
+                    return false;
+                };
+                acl.filter(|acl| !acl.is_allowed(&server)).is_none()
             })
             .collect();
 
@@ -2104,10 +3315,40 @@ impl Room {
             .collect())
     }
 
+    /// Get the servers to route requests about this room through.
+    ///
+    /// When we're invited to the room, the server of the inviter is known to
+    /// be in the room, so it comes first, followed by the servers from
+    /// [`Room::route()`].
+    ///
+    /// See [`Client::via_servers_for_room()`].
+    pub(crate) async fn via_servers(&self) -> Result<Vec<OwnedServerName>> {
+        let mut via = Vec::new();
+
+        if self.state() == RoomState::Invited {
+            let invitee = self
+                .get_member_no_sync(self.own_user_id())
+                .await?
+                .ok_or_else(|| Error::UnknownError(Box::new(InvitationError::EventMissing)))?;
+            via.extend(self.client.resolve_server_for_user(invitee.event().sender()));
+        }
+
+        for server in self.route().await? {
+            if !via.contains(&server) {
+                via.push(server);
+            }
+        }
+
+        via.truncate(3);
+
+        Ok(via)
+    }
+
     /// Get a `matrix.to` permalink to this room.
     ///
     /// If this room has an alias, we use it. Otherwise, we try to use the
-    /// synced members in the room for [routing] the room ID.
+    /// synced members in the room, and the inviter if we're invited to it, for
+    /// [routing] the room ID.
     ///
     /// [routing]: https://spec.matrix.org/v1.3/appendices/#routing
     pub async fn matrix_to_permalink(&self) -> Result<MatrixToUri> {
@@ -2115,14 +3356,15 @@ impl Room {
             return Ok(alias.matrix_to_uri());
         }
 
-        let via = self.route().await?;
+        let via = self.via_servers().await?;
         Ok(self.room_id().matrix_to_uri_via(via))
     }
 
     /// Get a `matrix:` permalink to this room.
     ///
     /// If this room has an alias, we use it. Otherwise, we try to use the
-    /// synced members in the room for [routing] the room ID.
+    /// synced members in the room, and the inviter if we're invited to it, for
+    /// [routing] the room ID.
     ///
     /// # Arguments
     ///
@@ -2134,13 +3376,14 @@ impl Room {
             return Ok(alias.matrix_uri(join));
         }
 
-        let via = self.route().await?;
+        let via = self.via_servers().await?;
         Ok(self.room_id().matrix_uri_via(via, join))
     }
 
     /// Get a `matrix.to` permalink to an event in this room.
     ///
-    /// We try to use the synced members in the room for [routing] the room ID.
+    /// We try to use the synced members in the room, and the inviter if we're
+    /// invited to it, for [routing] the room ID.
     ///
     /// *Note*: This method does not check if the given event ID is actually
     /// part of this room. It needs to be checked before calling this method
@@ -2157,13 +3400,14 @@ impl Room {
     ) -> Result<MatrixToUri> {
         // Don't use the alias because an event is tied to a room ID, but an
         // alias might point to another room, e.g. after a room upgrade.
-        let via = self.route().await?;
+        let via = self.via_servers().await?;
         Ok(self.room_id().matrix_to_event_uri_via(event_id, via))
     }
 
     /// Get a `matrix:` permalink to an event in this room.
     ///
-    /// We try to use the synced members in the room for [routing] the room ID.
+    /// We try to use the synced members in the room, and the inviter if we're
+    /// invited to it, for [routing] the room ID.
     ///
     /// *Note*: This method does not check if the given event ID is actually
     /// part of this room. It needs to be checked before calling this method
@@ -2180,7 +3424,7 @@ impl Room {
     ) -> Result<MatrixUri> {
         // Don't use the alias because an event is tied to a room ID, but an
         // alias might point to another room, e.g. after a room upgrade.
-        let via = self.route().await?;
+        let via = self.via_servers().await?;
         Ok(self.room_id().matrix_event_uri_via(event_id, via))
     }
 
@@ -2314,6 +3558,100 @@ impl Room {
         Ok(())
     }
 
+    /// Leave this room if needed, and forget it.
+    ///
+    /// Once the room is left, this also removes it from the user's direct chats
+    /// and removes all of its tags, so it doesn't linger in other clients' room
+    /// lists.
+    ///
+    /// Unless [`LeaveAndForgetOptions::allow_last_admin()`] is set, returns
+    /// [`Error::LastAdminOfRoom`] without doing anything if the user is the
+    /// last admin of a room that has other members, since nobody would be
+    /// able to administrate it after that.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # async {
+    /// # let homeserver = url::Url::parse("http://localhost:8080")?;
+    /// # let client = matrix_sdk::Client::new(homeserver).await?;
+    /// # let room_id = matrix_sdk::ruma::room_id!("!test:localhost");
+    /// use matrix_sdk::{room::LeaveAndForgetOptions, Error};
+    ///
+    /// if let Some(room) = client.get_room(&room_id) {
+    ///     match room.leave_and_forget(LeaveAndForgetOptions::new()).await {
+    ///         Err(Error::LastAdminOfRoom) => {
+    ///             println!("Promote someone else before leaving the room");
+    ///         }
+    ///         result => result?,
+    ///     }
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    pub async fn leave_and_forget(&self, options: LeaveAndForgetOptions) -> Result<()> {
+        let state = self.state();
+
+        if state == RoomState::Joined && !options.allow_last_admin && self.is_last_admin().await? {
+            return Err(Error::LastAdminOfRoom);
+        }
+
+        // Leave first, so the room keeps its tags and its direct flag if it can't
+        // be left, like a server notices room.
+        if state != RoomState::Left {
+            self.leave().await?;
+        }
+
+        if let Some(tags) = self.tags().await? {
+            for tag in tags.into_keys() {
+                self.remove_tag(tag).await?;
+            }
+        }
+
+        if self.is_direct().await? {
+            self.set_is_direct(false).await?;
+        }
+
+        self.forget().await?;
+
+        if options.purge_local_data {
+            for message in self.scheduled_messages().await? {
+                self.client.scheduled_messages().cancel(&message.id).await?;
+            }
+
+            self.forget_cached_pinned_events().await?;
+            self.client.base_client().forget_room(self.room_id()).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether the user is the only admin of this room while there are other
+    /// members in it.
+    ///
+    /// The admins are the users with the highest power level of the room, if
+    /// they can change the power levels.
+    async fn is_last_admin(&self) -> Result<bool> {
+        let power_levels = self.get_room_power_levels().await?;
+        let admin_level =
+            power_levels.users.values().copied().fold(power_levels.users_default, Int::max);
+        let is_admin = |user_id: &UserId| {
+            power_levels.for_user(user_id) >= admin_level
+                && power_levels.user_can_send_state(user_id, StateEventType::RoomPowerLevels)
+        };
+
+        if !is_admin(self.own_user_id()) {
+            return Ok(false);
+        }
+
+        let mut other_members = self.members(RoomMemberships::ACTIVE).await?;
+        other_members.retain(|member| member.user_id() != self.own_user_id());
+
+        Ok(!other_members.is_empty()
+            && !other_members.iter().any(|member| {
+                *member.membership() == MembershipState::Join && is_admin(member.user_id())
+            }))
+    }
+
     fn ensure_room_joined(&self) -> Result<()> {
         let state = self.state();
         if state == RoomState::Joined {
@@ -2383,6 +3721,7 @@ impl Room {
         if state != RoomState::Joined {
             return Err(Error::WrongRoomState(WrongRoomState::new("Joined", state)));
         }
+        self.client.ensure_not_guest()?;
 
         let request = report_content::v3::Request::new(
             self.inner.room_id().to_owned(),
@@ -2409,6 +3748,22 @@ enum InvitationError {
     EventMissing,
 }
 
+/// Get the ID of the event redacted by `event`, and `event` as a redaction, if
+/// it is a redaction.
+pub(crate) fn redaction_of(
+    event: &Raw<AnySyncTimelineEvent>,
+    room_version: &RoomVersionId,
+) -> Option<(OwnedEventId, Raw<SyncRoomRedactionEvent>)> {
+    if event.get_field::<String>("type").ok().flatten().as_deref() != Some("m.room.redaction") {
+        return None;
+    }
+
+    let redaction = event.clone().cast::<SyncRoomRedactionEvent>();
+    let redacts = redaction.deserialize().ok()?.redacts(room_version)?.to_owned();
+
+    Some((redacts, redaction))
+}
+
 /// Receipts to send all at once.
 #[derive(Debug, Clone, Default)]
 #[non_exhaustive]
@@ -2466,6 +3821,40 @@ impl Receipts {
     }
 }
 
+/// Options for [`Room::leave_and_forget()`].
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct LeaveAndForgetOptions {
+    /// Whether to leave the room even if the user is its last admin.
+    pub allow_last_admin: bool,
+    /// Whether to remove everything known locally about the room.
+    pub purge_local_data: bool,
+}
+
+impl LeaveAndForgetOptions {
+    /// Create the default `LeaveAndForgetOptions`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Leave the room even if the user is its last admin, instead of returning
+    /// [`Error::LastAdminOfRoom`].
+    pub fn allow_last_admin(mut self) -> Self {
+        self.allow_last_admin = true;
+        self
+    }
+
+    /// Remove everything known locally about the room after forgetting it.
+    ///
+    /// The room won't be returned by [`Client::get_room()`] anymore, the
+    /// messages scheduled in it are cancelled and its pinned events are
+    /// removed from the state store.
+    pub fn purge_local_data(mut self) -> Self {
+        self.purge_local_data = true;
+        self
+    }
+}
+
 /// [Parent space](https://spec.matrix.org/v1.8/client-server-api/#mspaceparent-relationships)
 /// listed by a room, possibly validated by checking the space's state.
 #[derive(Debug)]
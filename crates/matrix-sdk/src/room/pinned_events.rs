@@ -0,0 +1,485 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A live list of the pinned events of a room, and the helpers to change
+//! them.
+//!
+//! The pinned events are often older than anything the client synced, so
+//! once they are fetched from the homeserver they are kept in the state
+//! store, one entry per room, until they are unpinned. They are redacted
+//! there when the events are redacted.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex as StdMutex},
+};
+
+use eyeball_im::{ObservableVector, VectorDiff};
+use futures_core::Stream;
+use imbl::Vector;
+use matrix_sdk_base::apply_redaction;
+use matrix_sdk_common::deserialized_responses::{EncryptionInfo, TimelineEvent};
+use ruma::{
+    api::client::{error::ErrorKind, state::get_state_events_for_key},
+    events::{
+        room::{
+            pinned_events::{RoomPinnedEventsEventContent, SyncRoomPinnedEventsEvent},
+            redaction::SyncRoomRedactionEvent,
+        },
+        AnyTimelineEvent, StateEventType,
+    },
+    serde::Raw,
+    EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, RoomId, RoomVersionId,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{debug, instrument, warn};
+
+use super::Room;
+use crate::{event_handler::EventHandlerDropGuard, Result};
+
+/// How many times [`Room::pin_event()`] and [`Room::unpin_event()`] send the
+/// `m.room.pinned_events` state event when it is changed concurrently.
+const MAX_PINNED_EVENTS_ATTEMPTS: usize = 3;
+
+/// The prefix of the keys of the cached pinned events in the custom values of
+/// the state store.
+const PINNED_EVENTS_KEY_PREFIX: &str = "matrix-sdk.pinned_events.";
+
+/// The key of the cached pinned events of a room in the custom values of the
+/// state store.
+fn pinned_events_key(room_id: &RoomId) -> Vec<u8> {
+    format!("{PINNED_EVENTS_KEY_PREFIX}{room_id}").into_bytes()
+}
+
+/// A pinned event, as kept in the state store.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct CachedPinnedEvent {
+    event: Raw<AnyTimelineEvent>,
+    encryption_info: Option<EncryptionInfo>,
+}
+
+impl CachedPinnedEvent {
+    /// Get the event to keep in the state store, unless it couldn't be
+    /// decrypted, in which case it must be fetched again to retry.
+    fn new(event: &TimelineEvent) -> Option<Self> {
+        let is_encrypted = event.event.get_field::<String>("type").ok().flatten().as_deref()
+            == Some("m.room.encrypted");
+        (event.encryption_info.is_some() || !is_encrypted).then(|| Self {
+            event: event.event.clone(),
+            encryption_info: event.encryption_info.clone(),
+        })
+    }
+
+    fn origin_server_ts(&self) -> Option<MilliSecondsSinceUnixEpoch> {
+        self.event.get_field("origin_server_ts").ok().flatten()
+    }
+}
+
+impl From<CachedPinnedEvent> for TimelineEvent {
+    fn from(cached: CachedPinnedEvent) -> Self {
+        Self { event: cached.event, encryption_info: cached.encryption_info, push_actions: None }
+    }
+}
+
+/// Why [`Room::pin_event()`] or [`Room::unpin_event()`] failed to change the
+/// pinned events of a room.
+#[derive(Debug, thiserror::Error)]
+pub enum PinnedEventsError {
+    /// The power levels of the room don't allow the user to send the
+    /// `m.room.pinned_events` state event, so the homeserver would refuse
+    /// the change. No request was sent.
+    #[error("the user isn't allowed to change the pinned events of the room")]
+    Forbidden,
+
+    /// The pinned events of the room were changed concurrently every time the
+    /// change was sent, and the change was overwritten.
+    #[error("the pinned events of the room kept changing concurrently")]
+    Conflict,
+}
+
+/// A pinned event of a room, listed by [`PinnedEvents`].
+#[derive(Clone, Debug)]
+pub struct PinnedEvent {
+    /// The ID of the event.
+    pub event_id: OwnedEventId,
+    /// The event, decrypted if possible.
+    pub event: TimelineEvent,
+}
+
+/// A live list of the pinned events of a room, in the order of the
+/// `m.room.pinned_events` state event.
+///
+/// The list starts empty, the pinned events are loaded with
+/// [`PinnedEvents::load_more()`]. When the pinned events of the room change
+/// via sync, the list is updated to match them, as long as the
+/// `PinnedEvents` is alive.
+///
+/// To get one, use [`Room::pinned_events()`].
+#[derive(Clone, Debug)]
+pub struct PinnedEvents {
+    inner: Arc<PinnedEventsInner>,
+    _event_handler_guard: Arc<EventHandlerDropGuard>,
+}
+
+#[derive(Debug)]
+struct PinnedEventsInner {
+    room: Room,
+    items: StdMutex<ObservableVector<PinnedEvent>>,
+    state: Mutex<LoadState>,
+}
+
+#[derive(Debug, Default)]
+struct LoadState {
+    /// The IDs of the pinned events, `None` if they weren't read from the
+    /// room state yet.
+    pinned: Option<Vec<OwnedEventId>>,
+    /// How many of the pinned events should be in the list.
+    wanted: usize,
+    /// The events that were loaded already, to avoid fetching them again.
+    loaded: BTreeMap<OwnedEventId, TimelineEvent>,
+}
+
+impl PinnedEvents {
+    pub(super) fn new(room: Room) -> Self {
+        let inner = Arc::new(PinnedEventsInner {
+            room: room.clone(),
+            items: StdMutex::new(ObservableVector::new()),
+            state: Default::default(),
+        });
+
+        let handle = room.add_event_handler({
+            let inner = Arc::downgrade(&inner);
+            move |event: SyncRoomPinnedEventsEvent| async move {
+                let Some(inner) = inner.upgrade() else { return };
+                let pinned = event.as_original().map(|ev| ev.content.pinned.clone());
+
+                inner.state.lock().await.pinned = Some(pinned.unwrap_or_default());
+                inner.update().await;
+            }
+        });
+        let guard = room.client.event_handler_drop_guard(handle);
+
+        Self { inner, _event_handler_guard: Arc::new(guard) }
+    }
+
+    /// Get the currently loaded pinned events and a stream of updates to them.
+    pub fn subscribe(
+        &self,
+    ) -> (Vector<PinnedEvent>, impl Stream<Item = Vec<VectorDiff<PinnedEvent>>>) {
+        let items = self.inner.items.lock().unwrap();
+        ((*items).clone(), items.subscribe().into_batched_stream())
+    }
+
+    /// Load up to `count` more pinned events into the list.
+    ///
+    /// Returns `true` if all the pinned events of the room are loaded.
+    ///
+    /// The pinned events are read from the state store if they were fetched
+    /// before, and from the homeserver otherwise. Pinned events that can't be
+    /// fetched, for example because they were purged by the homeserver, are
+    /// skipped.
+    #[instrument(skip(self), fields(room_id = ?self.inner.room.room_id()))]
+    pub async fn load_more(&self, count: usize) -> Result<bool> {
+        {
+            let mut state = self.inner.state.lock().await;
+            if state.pinned.is_none() {
+                state.pinned = Some(self.inner.room.pinned_event_ids().await?);
+            }
+            state.wanted += count;
+        }
+
+        self.inner.update().await;
+
+        let state = self.inner.state.lock().await;
+        let num_pinned = state.pinned.as_ref().map_or(0, Vec::len);
+        Ok(state.wanted >= num_pinned)
+    }
+}
+
+impl Room {
+    /// Pin the event with the given ID in this room, at the end of the
+    /// pinned events.
+    ///
+    /// Returns `false` if the event was already pinned.
+    ///
+    /// Returns [`PinnedEventsError::Forbidden`] if the user isn't allowed to
+    /// change the pinned events, and [`PinnedEventsError::Conflict`] if the
+    /// pinned events kept being changed concurrently.
+    #[instrument(skip(self), fields(room_id = ?self.room_id()))]
+    pub async fn pin_event(&self, event_id: &EventId) -> Result<bool> {
+        self.change_pinned_events(|pinned| {
+            if pinned.iter().any(|pinned_id| pinned_id == event_id) {
+                false
+            } else {
+                pinned.push(event_id.to_owned());
+                true
+            }
+        })
+        .await
+    }
+
+    /// Unpin the event with the given ID in this room.
+    ///
+    /// Returns `false` if the event wasn't pinned.
+    ///
+    /// Returns [`PinnedEventsError::Forbidden`] if the user isn't allowed to
+    /// change the pinned events, and [`PinnedEventsError::Conflict`] if the
+    /// pinned events kept being changed concurrently.
+    #[instrument(skip(self), fields(room_id = ?self.room_id()))]
+    pub async fn unpin_event(&self, event_id: &EventId) -> Result<bool> {
+        self.change_pinned_events(|pinned| {
+            let len = pinned.len();
+            pinned.retain(|pinned_id| pinned_id != event_id);
+            pinned.len() != len
+        })
+        .await
+    }
+
+    /// Apply `change` to the pinned events of the homeserver and send them
+    /// back, if `change` returns `true`.
+    ///
+    /// The homeserver can't update a state event conditionally, so the pinned
+    /// events are read again after sending them, and the change is sent again
+    /// if a concurrent update overwrote it.
+    async fn change_pinned_events(
+        &self,
+        change: impl Fn(&mut Vec<OwnedEventId>) -> bool,
+    ) -> Result<bool> {
+        self.ensure_room_joined()?;
+
+        if !self.can_user_send_state(self.own_user_id(), StateEventType::RoomPinnedEvents).await? {
+            return Err(PinnedEventsError::Forbidden.into());
+        }
+
+        let mut pinned = self.fetch_pinned_event_ids().await?;
+        if !change(&mut pinned) {
+            return Ok(false);
+        }
+
+        for _ in 0..MAX_PINNED_EVENTS_ATTEMPTS {
+            self.send_state_event(RoomPinnedEventsEventContent::new(pinned)).await?;
+
+            pinned = self.fetch_pinned_event_ids().await?;
+            if !change(&mut pinned) {
+                return Ok(true);
+            }
+
+            debug!("The pinned events were changed concurrently, sending them again");
+        }
+
+        Err(PinnedEventsError::Conflict.into())
+    }
+
+    /// Get the IDs of the events that are pinned in this room, from the
+    /// homeserver.
+    async fn fetch_pinned_event_ids(&self) -> Result<Vec<OwnedEventId>> {
+        let request = get_state_events_for_key::v3::Request::new(
+            self.room_id().to_owned(),
+            StateEventType::RoomPinnedEvents,
+            String::new(),
+        );
+
+        match self.client.send(request, None).await {
+            Ok(response) => {
+                Ok(response.content.deserialize_as::<RoomPinnedEventsEventContent>()?.pinned)
+            }
+            Err(err) if err.client_api_error_kind() == Some(&ErrorKind::NotFound) => Ok(Vec::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+impl LoadState {
+    /// The IDs of the pinned events that should be in the list.
+    fn wanted_event_ids(&self) -> Vec<OwnedEventId> {
+        self.pinned.iter().flatten().take(self.wanted).cloned().collect()
+    }
+}
+
+impl PinnedEventsInner {
+    /// Fetch the missing events and replace the list with the wanted pinned
+    /// events.
+    ///
+    /// The events are fetched without holding the lock on the state, so the
+    /// pinned events can change in the meantime. The list is built with the
+    /// pinned events at the time the fetched events are added.
+    async fn update(&self) {
+        let missing: Vec<_> = {
+            let state = self.state.lock().await;
+            state
+                .wanted_event_ids()
+                .into_iter()
+                .filter(|event_id| !state.loaded.contains_key(event_id))
+                .collect()
+        };
+
+        let mut fetched = BTreeMap::new();
+        if !missing.is_empty() {
+            let mut cached = self.room.cached_pinned_events().await;
+
+            for event_id in missing {
+                if let Some(event) = cached.remove(&event_id) {
+                    fetched.insert(event_id, event.into());
+                    continue;
+                }
+
+                match self.room.event(&event_id).await {
+                    Ok(event) => {
+                        fetched.insert(event_id, event);
+                    }
+                    Err(e) => debug!(?event_id, "Couldn't load a pinned event: {e}"),
+                }
+            }
+        }
+
+        let mut state = self.state.lock().await;
+        let wanted = state.wanted_event_ids();
+        let num_loaded = state.loaded.len();
+        let num_fetched = fetched.len();
+
+        state.loaded.extend(fetched);
+        // Forget about the events that aren't pinned anymore.
+        state.loaded.retain(|event_id, _| wanted.contains(event_id));
+
+        let events: Vector<_> = wanted
+            .into_iter()
+            .filter_map(|event_id| {
+                let event = state.loaded.get(&event_id)?.clone();
+                Some(PinnedEvent { event_id, event })
+            })
+            .collect();
+
+        {
+            let mut items = self.items.lock().unwrap();
+            let unchanged = items.len() == events.len()
+                && items.iter().zip(&events).all(|(a, b)| a.event_id == b.event_id);
+            if !unchanged {
+                items.clear();
+                items.append(events);
+            }
+        }
+
+        // The state is still locked, so the concurrent updates write the state
+        // store in order.
+        if num_fetched > 0 || state.loaded.len() != num_loaded + num_fetched {
+            let pinned = state.pinned.as_deref().unwrap_or_default();
+            if let Err(e) = self.room.update_cached_pinned_events(pinned, &state.loaded).await {
+                warn!("Couldn't save the pinned events in the state store: {e}");
+            }
+        }
+    }
+}
+
+impl Room {
+    /// Get the pinned events of this room that are kept in the state store.
+    async fn cached_pinned_events(&self) -> BTreeMap<OwnedEventId, CachedPinnedEvent> {
+        let key = pinned_events_key(self.room_id());
+        let value = match self.client.store().get_custom_value(&key).await {
+            Ok(Some(value)) => value,
+            Ok(None) => return BTreeMap::new(),
+            Err(e) => {
+                warn!("Couldn't read the pinned events from the state store: {e}");
+                return BTreeMap::new();
+            }
+        };
+
+        match serde_json::from_slice(&value) {
+            Ok(cached) => cached,
+            Err(e) => {
+                warn!("Couldn't deserialize the pinned events from the state store: {e}");
+                BTreeMap::new()
+            }
+        }
+    }
+
+    /// Add the `loaded` events to the pinned events of this room that are
+    /// kept in the state store, and remove the ones that aren't `pinned`
+    /// anymore.
+    async fn update_cached_pinned_events(
+        &self,
+        pinned: &[OwnedEventId],
+        loaded: &BTreeMap<OwnedEventId, TimelineEvent>,
+    ) -> Result<()> {
+        let mut cached = self.cached_pinned_events().await;
+        cached.retain(|event_id, _| pinned.contains(event_id));
+        cached.extend(loaded.iter().filter_map(|(event_id, event)| {
+            Some((event_id.clone(), CachedPinnedEvent::new(event)?))
+        }));
+
+        self.save_cached_pinned_events(&cached).await
+    }
+
+    /// Replace the pinned events of this room that are kept in the state store.
+    async fn save_cached_pinned_events(
+        &self,
+        cached: &BTreeMap<OwnedEventId, CachedPinnedEvent>,
+    ) -> Result<()> {
+        let key = pinned_events_key(self.room_id());
+        if cached.is_empty() {
+            self.client.store().remove_custom_value(&key).await?;
+        } else {
+            self.client.store().set_custom_value(&key, serde_json::to_vec(cached)?).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove the pinned events of this room that were sent before `cutoff`
+    /// from the state store.
+    pub(super) async fn purge_cached_pinned_events_before(
+        &self,
+        cutoff: MilliSecondsSinceUnixEpoch,
+    ) -> Result<()> {
+        let mut cached = self.cached_pinned_events().await;
+        let len = cached.len();
+        cached.retain(|_, event| event.origin_server_ts().is_some_and(|ts| ts >= cutoff));
+
+        if cached.len() != len {
+            self.save_cached_pinned_events(&cached).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Replace the pinned event of this room that is kept in the state store
+    /// and redacted by `redaction` with its redacted form.
+    pub(super) async fn redact_cached_pinned_event(
+        &self,
+        redacts: &EventId,
+        redaction: &Raw<SyncRoomRedactionEvent>,
+        room_version: &RoomVersionId,
+    ) -> Result<()> {
+        let mut cached = self.cached_pinned_events().await;
+        let Some(pinned) = cached.get_mut(redacts) else {
+            return Ok(());
+        };
+
+        match apply_redaction(&pinned.event.clone().cast(), redaction, room_version) {
+            Some(redacted) => pinned.event = redacted.cast(),
+            // It will be fetched again, redacted by the homeserver.
+            None => {
+                cached.remove(redacts);
+            }
+        }
+
+        self.save_cached_pinned_events(&cached).await
+    }
+
+    /// Remove all the pinned events of this room from the state store.
+    pub(super) async fn forget_cached_pinned_events(&self) -> Result<()> {
+        self.client.store().remove_custom_value(&pinned_events_key(self.room_id())).await?;
+        Ok(())
+    }
+}
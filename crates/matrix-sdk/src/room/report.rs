@@ -0,0 +1,232 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use matrix_sdk_common::deserialized_responses::{EncryptionInfo, TimelineEvent};
+use ruma::{
+    events::{
+        room::{
+            member::MembershipState,
+            message::{MessageType, RoomMessageEventContent},
+            MediaSource,
+        },
+        AnyStateEvent, AnyTimelineEvent,
+    },
+    serde::Raw,
+    OwnedEventId, OwnedMxcUri, OwnedRoomId, OwnedUserId,
+};
+use serde::Serialize;
+
+/// Everything that is known about an event, gathered to be submitted to
+/// moderation tooling.
+///
+/// See [`Room::event_report_bundle`][super::Room::event_report_bundle].
+#[derive(Clone, Debug, Serialize)]
+pub struct EventReportBundle {
+    /// The room the event was sent in.
+    pub room_id: OwnedRoomId,
+
+    /// The ID of the reported event.
+    pub event_id: OwnedEventId,
+
+    /// The reported event, decrypted if possible.
+    pub event: ReportedEvent,
+
+    /// A few of the events that came right before the reported event, in
+    /// reverse-chronological order.
+    pub events_before: Vec<ReportedEvent>,
+
+    /// A few of the events that came right after the reported event, in
+    /// chronological order.
+    pub events_after: Vec<ReportedEvent>,
+
+    /// The sender of the reported event.
+    pub sender: ReportedSender,
+
+    /// The media attached to the reported event.
+    pub media: Vec<ReportedMedia>,
+}
+
+/// An event of an [`EventReportBundle`].
+#[derive(Clone, Debug, Serialize)]
+pub struct ReportedEvent {
+    /// The event, decrypted if possible.
+    pub event: Raw<AnyTimelineEvent>,
+
+    /// How the event was decrypted, if it was encrypted.
+    pub encryption_info: Option<EncryptionInfo>,
+}
+
+impl From<TimelineEvent> for ReportedEvent {
+    fn from(event: TimelineEvent) -> Self {
+        Self { event: event.event, encryption_info: event.encryption_info }
+    }
+}
+
+/// The sender of the event of an [`EventReportBundle`].
+#[derive(Clone, Debug, Serialize)]
+pub struct ReportedSender {
+    /// The ID of the sender.
+    pub user_id: OwnedUserId,
+
+    /// The display name of the sender in the room, if known.
+    pub display_name: Option<String>,
+
+    /// The avatar of the sender in the room, if known.
+    pub avatar_url: Option<OwnedMxcUri>,
+
+    /// The current membership of the sender in the room, if known.
+    pub membership: Option<MembershipState>,
+
+    /// The `m.room.member` events of the sender that were in effect around the
+    /// reported event.
+    ///
+    /// The previous membership of the sender can be found in the
+    /// `unsigned.prev_content` field of these events.
+    pub membership_events: Vec<Raw<AnyStateEvent>>,
+}
+
+/// A media attached to the event of an [`EventReportBundle`].
+#[derive(Clone, Debug, Serialize)]
+pub struct ReportedMedia {
+    /// Where to find the media.
+    pub source: MediaSource,
+
+    /// The unpadded base64-encoded SHA-256 hash of the media, if the event
+    /// contains it.
+    ///
+    /// Only encrypted media come with a hash.
+    pub sha256: Option<String>,
+}
+
+impl From<MediaSource> for ReportedMedia {
+    fn from(source: MediaSource) -> Self {
+        let sha256 = match &source {
+            MediaSource::Plain(_) => None,
+            MediaSource::Encrypted(file) => file.hashes.get("sha256").map(|hash| hash.encode()),
+        };
+
+        Self { source, sha256 }
+    }
+}
+
+/// Collect the media attached to the given event, including thumbnails.
+pub(super) fn media_of(event: &Raw<AnyTimelineEvent>) -> Vec<ReportedMedia> {
+    media_sources_of(event).into_iter().map(Into::into).collect()
+}
+
+/// Collect the sources of the media attached to the given event, including
+/// thumbnails.
+///
+/// Only the type and the content of the event are looked at, so this works for
+/// the events received via sync too.
+pub(super) fn media_sources_of<T>(event: &Raw<T>) -> Vec<MediaSource> {
+    if event.get_field::<String>("type").ok().flatten().as_deref() != Some("m.room.message") {
+        return Vec::new();
+    }
+
+    // The content of a redacted event is empty, so it doesn't deserialize.
+    let Ok(Some(RoomMessageEventContent { msgtype, .. })) = event.get_field("content") else {
+        return Vec::new();
+    };
+
+    let (source, thumbnail_source) = match msgtype {
+        MessageType::Audio(content) => (content.source, None),
+        MessageType::File(content) => {
+            (content.source, content.info.and_then(|info| info.thumbnail_source))
+        }
+        MessageType::Image(content) => {
+            (content.source, content.info.and_then(|info| info.thumbnail_source))
+        }
+        MessageType::Video(content) => {
+            (content.source, content.info.and_then(|info| info.thumbnail_source))
+        }
+        _ => return Vec::new(),
+    };
+
+    [Some(source), thumbnail_source].into_iter().flatten().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches2::assert_let;
+    use ruma::{events::room::MediaSource, mxc_uri, serde::Raw};
+    use serde_json::json;
+
+    use super::media_of;
+
+    #[test]
+    fn test_media_of_image() {
+        let event = Raw::new(&json!({
+            "type": "m.room.message",
+            "event_id": "$image",
+            "room_id": "!room:localhost",
+            "sender": "@alice:localhost",
+            "origin_server_ts": 1,
+            "content": {
+                "msgtype": "m.image",
+                "body": "cat.png",
+                "url": "mxc://localhost/cat",
+                "info": {
+                    "thumbnail_file": {
+                        "url": "mxc://localhost/thumbnail",
+                        "key": {
+                            "kty": "oct",
+                            "key_ops": ["encrypt", "decrypt"],
+                            "alg": "A256CTR",
+                            "k": "qWXHg2PmKDOAWH7ikhz0hhpZMHYOL1RBxYo2pz3W8nw",
+                            "ext": true,
+                        },
+                        "iv": "X85+XgHN+HEAAAAAAAAAAA",
+                        "hashes": {
+                            "sha256": "5qG4fFnbbVdlAB1Q72JDKwCagV6Dbkx9uds4rSak37c",
+                        },
+                        "v": "v2",
+                    },
+                },
+            },
+        }))
+        .unwrap()
+        .cast();
+
+        let media = media_of(&event);
+        assert_eq!(media.len(), 2);
+
+        assert_let!(MediaSource::Plain(uri) = &media[0].source);
+        assert_eq!(uri, mxc_uri!("mxc://localhost/cat"));
+        assert_eq!(media[0].sha256, None);
+
+        assert_let!(MediaSource::Encrypted(file) = &media[1].source);
+        assert_eq!(file.url, mxc_uri!("mxc://localhost/thumbnail"));
+        assert_eq!(media[1].sha256.as_deref(), Some("5qG4fFnbbVdlAB1Q72JDKwCagV6Dbkx9uds4rSak37c"));
+    }
+
+    #[test]
+    fn test_media_of_text() {
+        let event = Raw::new(&json!({
+            "type": "m.room.message",
+            "event_id": "$text",
+            "room_id": "!room:localhost",
+            "sender": "@alice:localhost",
+            "origin_server_ts": 1,
+            "content": {
+                "msgtype": "m.text",
+                "body": "hello",
+            },
+        }))
+        .unwrap()
+        .cast();
+
+        assert!(media_of(&event).is_empty());
+    }
+}
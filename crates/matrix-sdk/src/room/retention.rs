@@ -0,0 +1,127 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Room retention policies, as defined in [MSC1763].
+//!
+//! [MSC1763]: https://github.com/matrix-org/matrix-spec-proposals/pull/1763
+
+use std::time::Duration;
+
+use ruma::{
+    events::{macros::EventContent, EmptyStateKey},
+    MilliSecondsSinceUnixEpoch, UInt,
+};
+use serde::{Deserialize, Serialize};
+
+/// The content of an `m.room.retention` state event.
+///
+/// It defines for how long the events of a room should be kept around, by the
+/// homeserver as well as by the clients.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, EventContent)]
+#[ruma_event(type = "m.room.retention", kind = State, state_key_type = EmptyStateKey)]
+pub struct RoomRetentionEventContent {
+    /// The minimum time, in milliseconds, events should be kept for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_lifetime: Option<UInt>,
+
+    /// The maximum time, in milliseconds, events should be kept for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_lifetime: Option<UInt>,
+}
+
+impl RoomRetentionEventContent {
+    /// Creates an empty `RoomRetentionEventContent`, i.e. a policy that keeps
+    /// events forever.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The maximum time events should be kept for, if any.
+    pub fn max_lifetime(&self) -> Option<Duration> {
+        self.max_lifetime.map(|ms| Duration::from_millis(ms.into()))
+    }
+
+    /// The minimum time events should be kept for, if any.
+    pub fn min_lifetime(&self) -> Option<Duration> {
+        self.min_lifetime.map(|ms| Duration::from_millis(ms.into()))
+    }
+}
+
+/// Combine the maximum lifetime of the room's retention policy with the
+/// client-wide default, the stricter one wins.
+pub(super) fn effective_max_lifetime(
+    room_max_lifetime: Option<Duration>,
+    client_max_lifetime: Option<Duration>,
+) -> Option<Duration> {
+    match (room_max_lifetime, client_max_lifetime) {
+        (Some(room), Some(client)) => Some(room.min(client)),
+        (room, client) => room.or(client),
+    }
+}
+
+/// The timestamp before which events expire, given their maximum lifetime.
+pub(super) fn retention_cutoff(
+    now: MilliSecondsSinceUnixEpoch,
+    max_lifetime: Duration,
+) -> MilliSecondsSinceUnixEpoch {
+    let max_lifetime = u64::try_from(max_lifetime.as_millis()).unwrap_or(u64::MAX);
+    MilliSecondsSinceUnixEpoch(UInt::new_saturating(u64::from(now.0).saturating_sub(max_lifetime)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ruma::{uint, MilliSecondsSinceUnixEpoch};
+    use serde_json::json;
+
+    use super::{effective_max_lifetime, retention_cutoff, RoomRetentionEventContent};
+
+    #[test]
+    fn test_retention_content_deserialization() {
+        let content: RoomRetentionEventContent = serde_json::from_value(json!({
+            "max_lifetime": 86_400_000,
+        }))
+        .unwrap();
+
+        assert_eq!(content.max_lifetime(), Some(Duration::from_secs(86_400)));
+        assert_eq!(content.min_lifetime(), None);
+    }
+
+    #[test]
+    fn test_effective_max_lifetime() {
+        let day = Duration::from_secs(86_400);
+        let week = day * 7;
+
+        assert_eq!(effective_max_lifetime(None, None), None);
+        assert_eq!(effective_max_lifetime(Some(week), None), Some(week));
+        assert_eq!(effective_max_lifetime(None, Some(day)), Some(day));
+        assert_eq!(effective_max_lifetime(Some(week), Some(day)), Some(day));
+        assert_eq!(effective_max_lifetime(Some(day), Some(week)), Some(day));
+    }
+
+    #[test]
+    fn test_retention_cutoff() {
+        let now = MilliSecondsSinceUnixEpoch(uint!(10_000));
+
+        assert_eq!(
+            retention_cutoff(now, Duration::from_secs(4)),
+            MilliSecondsSinceUnixEpoch(uint!(6_000))
+        );
+        assert_eq!(
+            retention_cutoff(now, Duration::from_secs(20)),
+            MilliSecondsSinceUnixEpoch(uint!(0))
+        );
+    }
+}
@@ -0,0 +1,101 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The state of the sending of events to a room, shared by everything that
+//! sends events to it.
+
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex as StdMutex},
+};
+
+use eyeball::{SharedObservable, Subscriber};
+use ruma::{OwnedTransactionId, TransactionId};
+
+use super::Room;
+
+/// The state of the [`RoomSendQueue`] of a room, kept by the client.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RoomSendQueueState {
+    paused: SharedObservable<bool>,
+    aborted: Arc<StdMutex<HashSet<OwnedTransactionId>>>,
+}
+
+/// The state of the sending of events to a room, obtained with
+/// [`Room::send_queue()`].
+///
+/// It is shared by all the timelines and queues sending events to the room,
+/// so they all stop sending when one of them can't reach the homeserver.
+#[derive(Clone, Debug)]
+pub struct RoomSendQueue {
+    state: RoomSendQueueState,
+}
+
+impl RoomSendQueue {
+    /// Whether sending events to the room is paused, because the homeserver
+    /// couldn't be reached.
+    pub fn is_paused(&self) -> bool {
+        self.state.paused.get()
+    }
+
+    /// Subscribe to the changes of [`RoomSendQueue::is_paused()`].
+    pub fn subscribe_to_paused(&self) -> Subscriber<bool> {
+        self.state.paused.subscribe()
+    }
+
+    /// Pause sending events to the room.
+    pub fn pause(&self) {
+        self.state.paused.set_if_not_eq(true);
+    }
+
+    /// Resume sending events to the room.
+    ///
+    /// The events that failed because the homeserver couldn't be reached are
+    /// sent again by their queue, before the ones queued while sending was
+    /// paused.
+    pub fn resume(&self) {
+        self.state.paused.set_if_not_eq(false);
+    }
+
+    /// Remember that the event with the given transaction ID is being removed
+    /// from its queue on purpose.
+    pub fn mark_aborted(&self, txn_id: &TransactionId) {
+        self.state.aborted.lock().unwrap().insert(txn_id.to_owned());
+    }
+
+    /// Forget about the event with the given transaction ID being removed from
+    /// its queue on purpose.
+    ///
+    /// Returns whether [`RoomSendQueue::mark_aborted()`] was called for it.
+    pub fn take_aborted(&self, txn_id: &TransactionId) -> bool {
+        self.state.aborted.lock().unwrap().remove(txn_id)
+    }
+}
+
+impl Room {
+    /// Get the state of the sending of events to this room.
+    pub fn send_queue(&self) -> RoomSendQueue {
+        let state = self
+            .client
+            .inner
+            .room_send_queues
+            .lock()
+            .unwrap()
+            .entry(self.room_id().to_owned())
+            .or_default()
+            .clone();
+
+        RoomSendQueue { state }
+    }
+}
@@ -0,0 +1,393 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{borrow::Borrow, collections::BTreeSet};
+
+use matrix_sdk_base::deserialized_responses::RawAnySyncOrStrippedState;
+use ruma::{
+    api::client::state::send_state_event,
+    events::{
+        room::power_levels::{RoomPowerLevels, RoomPowerLevelsEventContent},
+        AnyStateEventContent, AnySyncStateEvent, EmptyStateKey, EventContent, StateEventContent,
+        StateEventType,
+    },
+    serde::Raw,
+    Int, OwnedEventId, UserId,
+};
+use serde_json::json;
+use tracing::{debug, instrument, warn};
+
+use super::Room;
+use crate::{utils::IntoRawStateEventContent, Error, Result};
+
+/// A state event to send with [`Room::send_state_events_atomically`].
+#[derive(Clone, Debug)]
+pub struct StateEventToSend {
+    /// The type of the state event.
+    pub event_type: StateEventType,
+
+    /// The state key of the state event.
+    pub state_key: String,
+
+    /// The content of the state event.
+    pub content: Raw<AnyStateEventContent>,
+}
+
+impl StateEventToSend {
+    /// Create a state event with an empty state key.
+    pub fn new<C>(content: &C) -> serde_json::Result<Self>
+    where
+        C: StateEventContent<StateKey = EmptyStateKey>,
+    {
+        Self::for_key(&EmptyStateKey, content)
+    }
+
+    /// Create a state event with the given state key.
+    pub fn for_key<C, K>(state_key: &K, content: &C) -> serde_json::Result<Self>
+    where
+        C: StateEventContent,
+        C::StateKey: Borrow<K>,
+        K: AsRef<str> + ?Sized,
+    {
+        Ok(Self {
+            event_type: content.event_type(),
+            state_key: state_key.as_ref().to_owned(),
+            content: Raw::new(content)?.cast(),
+        })
+    }
+
+    /// Create a state event from its raw parts.
+    ///
+    /// The argument type of `content` can be `serde_json::Value`, but also
+    /// other raw JSON types; for the full list check the documentation of
+    /// [`IntoRawStateEventContent`].
+    pub fn raw(event_type: &str, state_key: &str, content: impl IntoRawStateEventContent) -> Self {
+        Self {
+            event_type: event_type.into(),
+            state_key: state_key.to_owned(),
+            content: content.into_raw_state_event_content(),
+        }
+    }
+}
+
+/// Why a batch of state events was rejected by
+/// [`Room::send_state_events_atomically`] before sending any of them.
+#[derive(Debug, thiserror::Error)]
+pub enum StateBatchValidationError {
+    /// The content of a state event doesn't match the schema of its type.
+    #[error("the state event at index {index} is not a valid `{event_type}` event: {error}")]
+    InvalidEvent {
+        /// The index of the state event in the batch.
+        index: usize,
+
+        /// The type of the state event.
+        event_type: StateEventType,
+
+        /// Why the state event is invalid.
+        #[source]
+        error: serde_json::Error,
+    },
+
+    /// The power levels of the room don't allow the user to send a state
+    /// event, taking into account the power levels changes of the previous
+    /// state events of the batch.
+    ///
+    /// This is also the case for an `m.room.power_levels` event that changes
+    /// levels that the user isn't allowed to change, like raising a level
+    /// above their own.
+    #[error("the user isn't allowed to send the `{event_type}` state event at index {index}")]
+    Forbidden {
+        /// The index of the state event in the batch.
+        index: usize,
+
+        /// The type of the state event.
+        event_type: StateEventType,
+    },
+}
+
+/// The outcome of sending a single state event with
+/// [`Room::send_state_events_atomically`].
+#[derive(Debug)]
+pub enum StateEventOutcome {
+    /// The state event was sent.
+    Sent(OwnedEventId),
+
+    /// Sending the state event failed.
+    Failed(Error),
+
+    /// The state event wasn't sent, because sending a previous state event of
+    /// the batch failed.
+    NotSent,
+}
+
+/// The report of [`Room::send_state_events_atomically`].
+#[derive(Debug)]
+pub struct StateBatchReport {
+    /// The outcome of every state event of the batch, in the same order.
+    pub outcomes: Vec<StateEventOutcome>,
+
+    /// The state events that restore the state of the room as it was before
+    /// the batch, if only part of it was sent.
+    ///
+    /// They are in the order they should be sent in. A state event that
+    /// didn't exist before the batch is restored with an empty content, since
+    /// state events can't be removed, if its type allows it. This is empty if
+    /// all the state events were sent, or none of them.
+    pub rollback: Vec<StateEventToSend>,
+
+    /// The state events that were sent but can't be rolled back, because the
+    /// content restoring them isn't valid for their type. This is the case of
+    /// an `m.room.join_rules` event that didn't exist before the batch, since
+    /// it can't have an empty content.
+    pub not_rolled_back: Vec<StateEventToSend>,
+}
+
+impl StateBatchReport {
+    /// Whether all the state events of the batch were sent.
+    pub fn is_complete(&self) -> bool {
+        self.outcomes.iter().all(|outcome| matches!(outcome, StateEventOutcome::Sent(_)))
+    }
+}
+
+impl Room {
+    /// Send several state events to this room, as one logical operation.
+    ///
+    /// The batch is validated before sending anything: the content of the
+    /// state events must match the schema of their type, and the power
+    /// levels of the room, as updated by the `m.room.power_levels` events of
+    /// the batch, must allow the user to send them. If that fails, a
+    /// [`StateBatchValidationError`] is returned.
+    ///
+    /// The state events are then sent in order, and the batch stops at the
+    /// first one that fails. Since the homeserver has no way to apply them
+    /// atomically, the returned [`StateBatchReport`] lists the state events
+    /// to send to restore the previous state of the room in that case.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use matrix_sdk::room::StateEventToSend;
+    /// use ruma::events::room::{
+    ///     name::RoomNameEventContent, topic::RoomTopicEventContent,
+    /// };
+    ///
+    /// # async {
+    /// # let room: matrix_sdk::Room = todo!();
+    /// let report = room
+    ///     .send_state_events_atomically(vec![
+    ///         StateEventToSend::new(&RoomNameEventContent::new(
+    ///             "Bots".to_owned(),
+    ///         ))?,
+    ///         StateEventToSend::new(&RoomTopicEventContent::new(
+    ///             "Beep".to_owned(),
+    ///         ))?,
+    ///     ])
+    ///     .await?;
+    ///
+    /// if !report.is_complete() {
+    ///     room.send_state_events_atomically(report.rollback).await?;
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    #[instrument(skip_all, fields(room_id = ?self.room_id()))]
+    pub async fn send_state_events_atomically(
+        &self,
+        events: Vec<StateEventToSend>,
+    ) -> Result<StateBatchReport> {
+        self.ensure_room_joined()?;
+        self.validate_state_batch(&events).await?;
+
+        let mut outcomes = Vec::with_capacity(events.len());
+        let mut previous_states = Vec::new();
+        let mut not_rolled_back = Vec::new();
+        let mut failed = false;
+
+        for event in &events {
+            if failed {
+                outcomes.push(StateEventOutcome::NotSent);
+                continue;
+            }
+
+            let previous =
+                match self.get_state_event(event.event_type.clone(), &event.state_key).await {
+                    Ok(previous) => previous,
+                    Err(error) => {
+                        failed = true;
+                        outcomes.push(StateEventOutcome::Failed(error));
+                        continue;
+                    }
+                };
+
+            let request = send_state_event::v3::Request::new_raw(
+                self.room_id().to_owned(),
+                event.event_type.clone(),
+                event.state_key.clone(),
+                event.content.clone(),
+            );
+
+            match self.client.send(request, None).await {
+                Ok(response) => {
+                    match rollback_event(event, previous, self.own_user_id()) {
+                        Some(rollback) => previous_states.push(rollback),
+                        None => not_rolled_back.push(event.clone()),
+                    }
+                    outcomes.push(StateEventOutcome::Sent(response.event_id));
+                }
+                Err(error) => {
+                    warn!(event_type = %event.event_type, "Couldn't send a state event of a batch: {error}");
+                    failed = true;
+                    outcomes.push(StateEventOutcome::Failed(error.into()));
+                }
+            }
+        }
+
+        let (rollback, not_rolled_back) = if failed {
+            (previous_states.into_iter().rev().collect(), not_rolled_back)
+        } else {
+            (Vec::new(), Vec::new())
+        };
+
+        Ok(StateBatchReport { outcomes, rollback, not_rolled_back })
+    }
+
+    async fn validate_state_batch(&self, events: &[StateEventToSend]) -> Result<()> {
+        let own_user_id = self.own_user_id();
+        let mut power_levels = self.get_room_power_levels().await?;
+
+        for (index, event) in events.iter().enumerate() {
+            let invalid = |error| StateBatchValidationError::InvalidEvent {
+                index,
+                event_type: event.event_type.clone(),
+                error,
+            };
+            let forbidden = || StateBatchValidationError::Forbidden {
+                index,
+                event_type: event.event_type.clone(),
+            };
+
+            validate_state_event(event, own_user_id).map_err(invalid)?;
+
+            if !power_levels.user_can_send_state(own_user_id, event.event_type.clone()) {
+                return Err(forbidden().into());
+            }
+
+            if event.event_type == StateEventType::RoomPowerLevels {
+                let content = event
+                    .content
+                    .deserialize_as::<RoomPowerLevelsEventContent>()
+                    .map_err(invalid)?;
+                let new_power_levels = RoomPowerLevels::from(content);
+
+                if !can_change_power_levels(&power_levels, &new_power_levels, own_user_id) {
+                    return Err(forbidden().into());
+                }
+
+                power_levels = new_power_levels;
+            }
+        }
+
+        debug!(num_events = events.len(), "Validated a batch of state events");
+        Ok(())
+    }
+}
+
+/// Check that the content and state key of the state event match its type, by
+/// deserializing it like it would come back from sync.
+fn validate_state_event(event: &StateEventToSend, sender: &UserId) -> serde_json::Result<()> {
+    let full_event = json!({
+        "type": event.event_type,
+        "state_key": event.state_key,
+        "content": event.content,
+        "event_id": "$batch",
+        "sender": sender,
+        "origin_server_ts": 0,
+    });
+    serde_json::from_value::<AnySyncStateEvent>(full_event)?;
+    Ok(())
+}
+
+/// Whether the user is allowed to replace the `current` power levels with the
+/// `new` ones, according to the authorization rules of the
+/// `m.room.power_levels` event.
+///
+/// The user can only change the levels that are at most their own level, to
+/// values that are at most their own level, and can't change the level of the
+/// other users that have at least their level.
+fn can_change_power_levels(
+    current: &RoomPowerLevels,
+    new: &RoomPowerLevels,
+    user_id: &UserId,
+) -> bool {
+    let own_level = current.for_user(user_id);
+    let can_change = |old: Option<Int>, new: Option<Int>| {
+        old == new || (old.map_or(true, |l| l <= own_level) && new.map_or(true, |l| l <= own_level))
+    };
+
+    let levels = [
+        (current.ban, new.ban),
+        (current.invite, new.invite),
+        (current.kick, new.kick),
+        (current.redact, new.redact),
+        (current.state_default, new.state_default),
+        (current.events_default, new.events_default),
+        (current.users_default, new.users_default),
+        (current.notifications.room, new.notifications.room),
+    ];
+    if !levels.into_iter().all(|(old, new)| can_change(Some(old), Some(new))) {
+        return false;
+    }
+
+    let event_types: BTreeSet<_> = current.events.keys().chain(new.events.keys()).collect();
+    if !event_types.into_iter().all(|event_type| {
+        can_change(current.events.get(event_type).copied(), new.events.get(event_type).copied())
+    }) {
+        return false;
+    }
+
+    let users: BTreeSet<_> = current.users.keys().chain(new.users.keys()).collect();
+    users.into_iter().all(|user| {
+        let old = current.users.get(user).copied();
+        let new = new.users.get(user).copied();
+
+        old == new
+            || ((user == user_id || old.map_or(true, |l| l < own_level))
+                && new.map_or(true, |l| l <= own_level))
+    })
+}
+
+/// The state event restoring the state that `event` replaces.
+///
+/// Returns `None` if the state event didn't exist before and an empty content
+/// isn't valid for its type.
+fn rollback_event(
+    event: &StateEventToSend,
+    previous: Option<RawAnySyncOrStrippedState>,
+    own_user_id: &UserId,
+) -> Option<StateEventToSend> {
+    let previous_content = previous.and_then(|previous| match previous {
+        RawAnySyncOrStrippedState::Sync(raw) => raw.get_field("content").ok().flatten(),
+        RawAnySyncOrStrippedState::Stripped(raw) => raw.get_field("content").ok().flatten(),
+    });
+
+    let rollback = StateEventToSend {
+        event_type: event.event_type.clone(),
+        state_key: event.state_key.clone(),
+        content: previous_content
+            .unwrap_or_else(|| Raw::new(&json!({})).expect("an empty object is valid JSON").cast()),
+    };
+
+    // The previous content was valid when it was sent, but an empty content
+    // might not be.
+    validate_state_event(&rollback, own_user_id).is_ok().then_some(rollback)
+}
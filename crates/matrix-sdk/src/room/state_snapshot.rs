@@ -0,0 +1,227 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Snapshots of the whole current state of a room, read from the store at
+//! once.
+
+use matrix_sdk_base::deserialized_responses::RawAnySyncOrStrippedState;
+use ruma::events::StateEventType;
+use serde::{de::DeserializeOwned, Deserialize};
+use tracing::{debug, instrument};
+
+use super::Room;
+use crate::Result;
+
+/// Options for [`Room::state_snapshot()`] and [`Room::state_snapshot_raw()`].
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct StateSnapshotOptions {
+    /// Only include the state events whose type starts with one of these
+    /// prefixes, like `m.room.` or `org.example.`.
+    ///
+    /// All the state events are included if this is empty.
+    pub type_prefixes: Vec<String>,
+}
+
+impl StateSnapshotOptions {
+    /// Create the default `StateSnapshotOptions`, that include all the state
+    /// events.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only include the state events whose type starts with the given prefix,
+    /// in addition to the other prefixes.
+    pub fn type_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.type_prefixes.push(prefix.into());
+        self
+    }
+
+    fn includes(&self, event_type: &str) -> bool {
+        self.type_prefixes.is_empty()
+            || self.type_prefixes.iter().any(|prefix| event_type.starts_with(prefix.as_str()))
+    }
+}
+
+/// A state event of a [`StateSnapshot`].
+#[derive(Debug, Clone)]
+pub struct StateSnapshotEntry<T> {
+    /// The type of the state event.
+    pub event_type: StateEventType,
+
+    /// The state key of the state event.
+    pub state_key: String,
+
+    /// The state event.
+    pub event: T,
+}
+
+/// The current state of a room, as it was in the store when the snapshot was
+/// taken.
+///
+/// The state events are sorted by type, then by state key, so the order is
+/// the same for every snapshot of the same state.
+#[derive(Debug, Clone)]
+pub struct StateSnapshot<T> {
+    entries: Vec<StateSnapshotEntry<T>>,
+}
+
+impl<T> StateSnapshot<T> {
+    /// The number of state events in the snapshot.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the snapshot doesn't contain any state event.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The state events of the snapshot.
+    pub fn entries(&self) -> &[StateSnapshotEntry<T>] {
+        &self.entries
+    }
+
+    /// Get the page of state events with the given index, starting at 0.
+    ///
+    /// Returns an empty page if the index is past the last page.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `page_size` is 0.
+    pub fn page(&self, index: usize, page_size: usize) -> &[StateSnapshotEntry<T>] {
+        self.entries.chunks(page_size).nth(index).unwrap_or_default()
+    }
+
+    /// Iterate over the pages of state events of the snapshot, with at most
+    /// `page_size` state events each.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `page_size` is 0.
+    pub fn pages(&self, page_size: usize) -> impl Iterator<Item = &[StateSnapshotEntry<T>]> {
+        self.entries.chunks(page_size)
+    }
+
+    /// Get the state events of the snapshot.
+    pub fn into_entries(self) -> Vec<StateSnapshotEntry<T>> {
+        self.entries
+    }
+}
+
+impl<T> IntoIterator for StateSnapshot<T> {
+    type Item = StateSnapshotEntry<T>;
+    type IntoIter = std::vec::IntoIter<StateSnapshotEntry<T>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+/// The fields of a state event that identify it.
+#[derive(Deserialize)]
+struct StateKeyFields {
+    #[serde(rename = "type")]
+    event_type: String,
+    state_key: String,
+}
+
+impl Room {
+    /// Get all the current state events of this room with a single read of
+    /// the store, deserialized as `T`.
+    ///
+    /// `T` can be a type covering all the state events, like
+    /// [`AnySyncStateEvent`](ruma::events::AnySyncStateEvent), or a specific
+    /// type combined with a filter on the event types. The state events that
+    /// fail to deserialize as `T` are skipped, use
+    /// [`Room::state_snapshot_raw()`] to get all of them.
+    ///
+    /// Note that the state events of a room the user is invited to are
+    /// stripped.
+    pub async fn state_snapshot<T: DeserializeOwned>(
+        &self,
+        options: StateSnapshotOptions,
+    ) -> Result<StateSnapshot<T>> {
+        let raw = self.state_snapshot_raw(options).await?;
+
+        let entries = raw
+            .into_iter()
+            .filter_map(|entry| {
+                let event = match &entry.event {
+                    RawAnySyncOrStrippedState::Sync(raw) => raw.deserialize_as::<T>(),
+                    RawAnySyncOrStrippedState::Stripped(raw) => raw.deserialize_as::<T>(),
+                };
+
+                match event {
+                    Ok(event) => Some(StateSnapshotEntry {
+                        event_type: entry.event_type,
+                        state_key: entry.state_key,
+                        event,
+                    }),
+                    Err(e) => {
+                        debug!(
+                            event_type = %entry.event_type,
+                            state_key = %entry.state_key,
+                            "Skipping a state event from the snapshot: {e}"
+                        );
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        Ok(StateSnapshot { entries })
+    }
+
+    /// Get all the current state events of this room with a single read of
+    /// the store, as raw JSON.
+    #[instrument(skip_all, fields(room_id = ?self.room_id()))]
+    pub async fn state_snapshot_raw(
+        &self,
+        options: StateSnapshotOptions,
+    ) -> Result<StateSnapshot<RawAnySyncOrStrippedState>> {
+        let events = self.client.store().get_all_state_events(self.room_id()).await?;
+
+        let mut entries = Vec::with_capacity(events.len());
+
+        for event in events {
+            let fields = match &event {
+                RawAnySyncOrStrippedState::Sync(raw) => raw.deserialize_as::<StateKeyFields>(),
+                RawAnySyncOrStrippedState::Stripped(raw) => raw.deserialize_as::<StateKeyFields>(),
+            };
+
+            let fields = match fields {
+                Ok(fields) => fields,
+                Err(e) => {
+                    debug!("Skipping a malformed state event from the snapshot: {e}");
+                    continue;
+                }
+            };
+
+            if !options.includes(&fields.event_type) {
+                continue;
+            }
+
+            entries.push(StateSnapshotEntry {
+                event_type: fields.event_type.into(),
+                state_key: fields.state_key,
+                event,
+            });
+        }
+
+        entries.sort_by_cached_key(|entry| (entry.event_type.to_string(), entry.state_key.clone()));
+
+        Ok(StateSnapshot { entries })
+    }
+}
@@ -0,0 +1,266 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use matrix_sdk_base::sync::UnreadNotificationsCount;
+use matrix_sdk_common::deserialized_responses::{SyncTimelineEvent, TimelineEvent};
+pub use ruma::api::client::threads::get_threads::v1::IncludeThreads;
+use ruma::{
+    api::client::threads::get_threads,
+    assign,
+    events::{relation::BundledThread, AnySyncTimelineEvent},
+    serde::Raw,
+    OwnedEventId, RoomId, UInt,
+};
+use serde::Deserialize;
+
+/// Options for [`threads`][super::Room::threads].
+///
+/// See that method and
+/// <https://spec.matrix.org/v1.9/client-server-api/#get_matrixclientv1roomsroomidthreads>
+/// for details.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct ThreadsOptions {
+    /// Which thread roots to return: all of them, or only the ones the current
+    /// user participated in.
+    pub include: IncludeThreads,
+
+    /// The token to start returning thread roots from.
+    ///
+    /// This token can be obtained from the `next_batch` field of a previous
+    /// `threads` call.
+    pub from: Option<String>,
+
+    /// The maximum number of thread roots to return.
+    ///
+    /// If unset, the homeserver picks a default.
+    pub limit: Option<UInt>,
+}
+
+impl ThreadsOptions {
+    /// Creates `ThreadsOptions` with the given filter.
+    ///
+    /// All other parameters will be defaulted.
+    pub fn new(include: IncludeThreads) -> Self {
+        Self { include, from: None, limit: None }
+    }
+
+    /// Creates `ThreadsOptions` returning all the threads of the room.
+    pub fn all() -> Self {
+        Self::new(IncludeThreads::All)
+    }
+
+    /// Creates `ThreadsOptions` returning only the threads the current user
+    /// participated in.
+    pub fn participated() -> Self {
+        Self::new(IncludeThreads::Participated)
+    }
+
+    /// Creates a new `ThreadsOptions` from `self` with the `from` field set to
+    /// the given value.
+    pub fn from<'a>(self, from: impl Into<Option<&'a str>>) -> Self {
+        Self { from: from.into().map(ToOwned::to_owned), ..self }
+    }
+
+    pub(super) fn into_request(self, room_id: &RoomId) -> get_threads::v1::Request {
+        assign!(get_threads::v1::Request::new(room_id.to_owned()), {
+            from: self.from,
+            include: self.include,
+            limit: self.limit,
+        })
+    }
+}
+
+/// The result of a [`Room::threads`][super::Room::threads] call.
+#[derive(Debug)]
+pub struct Threads {
+    /// The thread roots, ordered by the most recent activity first.
+    pub chunk: Vec<ThreadSummary>,
+
+    /// The token to use to fetch the next batch of thread roots, if any.
+    pub next_batch: Option<String>,
+}
+
+/// A thread root along with a summary of the thread's activity.
+#[derive(Debug)]
+pub struct ThreadSummary {
+    /// The event that started the thread, decrypted if possible.
+    pub root: TimelineEvent,
+
+    /// The latest event in the thread, as bundled by the homeserver.
+    pub latest_event: Option<Raw<AnySyncTimelineEvent>>,
+
+    /// The number of events in the thread, not counting the root.
+    pub num_replies: UInt,
+
+    /// Whether the current user participated in the thread.
+    pub current_user_participated: bool,
+
+    /// Whether the latest event of the thread hasn't been read by the current
+    /// user yet.
+    ///
+    /// This is computed from the sender of the latest event, the notification
+    /// counts of the thread and the threaded read receipt of the current user
+    /// that is known locally.
+    pub is_unread: bool,
+
+    /// The unread notification counts of the thread, as reported by the
+    /// server.
+    ///
+    /// These are only available if the sync filter enabled
+    /// `unread_thread_notifications`, otherwise they are always zero.
+    pub notification_counts: UnreadNotificationsCount,
+}
+
+impl ThreadSummary {
+    /// The ID of the thread root event.
+    pub fn root_event_id(&self) -> Option<OwnedEventId> {
+        self.root.event.get_field("event_id").ok().flatten()
+    }
+
+    /// The ID of the latest event in the thread, if known.
+    pub fn latest_event_id(&self) -> Option<OwnedEventId> {
+        self.latest_event.as_ref()?.get_field("event_id").ok().flatten()
+    }
+}
+
+/// An update to a thread of a room, received via sync.
+#[derive(Clone, Debug)]
+pub struct ThreadUpdate {
+    /// The ID of the root event of the thread that received a new event.
+    pub root_event_id: OwnedEventId,
+
+    /// The new event in the thread.
+    pub event: SyncTimelineEvent,
+}
+
+/// Extract the thread summary that the homeserver bundled in the `unsigned`
+/// field of a thread root.
+pub(super) fn bundled_thread(event: &Raw<impl Sized>) -> Option<BundledThread> {
+    #[derive(Deserialize)]
+    struct Unsigned {
+        #[serde(rename = "m.relations")]
+        relations: Option<Relations>,
+    }
+
+    #[derive(Deserialize)]
+    struct Relations {
+        #[serde(rename = "m.thread")]
+        thread: Option<BundledThread>,
+    }
+
+    let unsigned = event.get_field::<Unsigned>("unsigned").ok().flatten()?;
+    unsigned.relations?.thread
+}
+
+/// Get the ID of the thread root an event belongs to, if the event is part of
+/// a thread.
+///
+/// This works for encrypted events too, since `m.relates_to` is kept in the
+/// clear.
+pub(crate) fn thread_root_of(event: &Raw<AnySyncTimelineEvent>) -> Option<OwnedEventId> {
+    #[derive(Deserialize)]
+    struct Content {
+        #[serde(rename = "m.relates_to")]
+        relates_to: Option<RelatesTo>,
+    }
+
+    #[derive(Deserialize)]
+    struct RelatesTo {
+        rel_type: Option<String>,
+        event_id: Option<OwnedEventId>,
+    }
+
+    let relates_to = event.get_field::<Content>("content").ok().flatten()?.relates_to?;
+    (relates_to.rel_type.as_deref() == Some("m.thread")).then_some(relates_to.event_id).flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma::{event_id, serde::Raw, uint};
+    use serde_json::json;
+
+    use super::{bundled_thread, thread_root_of};
+
+    #[test]
+    fn test_thread_root_of() {
+        let threaded = Raw::new(&json!({
+            "type": "m.room.message",
+            "event_id": "$reply",
+            "sender": "@alice:localhost",
+            "origin_server_ts": 1,
+            "content": {
+                "msgtype": "m.text",
+                "body": "hello",
+                "m.relates_to": {
+                    "rel_type": "m.thread",
+                    "event_id": "$root",
+                },
+            },
+        }))
+        .unwrap()
+        .cast();
+        assert_eq!(thread_root_of(&threaded).as_deref(), Some(event_id!("$root")));
+
+        let reaction = Raw::new(&json!({
+            "type": "m.reaction",
+            "event_id": "$reaction",
+            "sender": "@alice:localhost",
+            "origin_server_ts": 1,
+            "content": {
+                "m.relates_to": {
+                    "rel_type": "m.annotation",
+                    "event_id": "$root",
+                    "key": "👍",
+                },
+            },
+        }))
+        .unwrap()
+        .cast();
+        assert_eq!(thread_root_of(&reaction), None);
+    }
+
+    #[test]
+    fn test_bundled_thread() {
+        let root = Raw::new(&json!({
+            "type": "m.room.message",
+            "event_id": "$root",
+            "room_id": "!room:localhost",
+            "sender": "@alice:localhost",
+            "origin_server_ts": 1,
+            "content": { "msgtype": "m.text", "body": "root" },
+            "unsigned": {
+                "m.relations": {
+                    "m.thread": {
+                        "latest_event": {
+                            "type": "m.room.message",
+                            "event_id": "$latest",
+                            "room_id": "!room:localhost",
+                            "sender": "@bob:localhost",
+                            "origin_server_ts": 2,
+                            "content": { "msgtype": "m.text", "body": "reply" },
+                        },
+                        "count": 3,
+                        "current_user_participated": true,
+                    },
+                },
+            },
+        }))
+        .unwrap();
+
+        let thread = bundled_thread(&root).unwrap();
+        assert_eq!(thread.count, uint!(3));
+        assert!(thread.current_user_participated);
+    }
+}
@@ -0,0 +1,378 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use matrix_sdk_base::{deserialized_responses::RawAnySyncOrStrippedState, RoomMemberships};
+use ruma::{
+    api::client::{
+        discovery::get_capabilities::RoomVersionStability,
+        membership::invite_user::{self, v3::InvitationRecipient},
+        room::upgrade_room,
+        state::{get_state_events_for_key, send_state_event},
+    },
+    events::{room::power_levels::RoomPowerLevelsEventContent, EmptyStateKey, StateEventType},
+    serde::{JsonObject, Raw},
+    OwnedRoomId, OwnedUserId, RoomId, RoomVersionId,
+};
+use serde_json::value::RawValue as RawJsonValue;
+use tracing::{debug, instrument, warn};
+
+use super::Room;
+use crate::{Error, Result};
+
+/// The types of the state events of the widgets of a room.
+const WIDGETS_EVENT_TYPES: [&str; 2] = ["m.widget", "im.vector.modular.widgets"];
+
+/// The type of the state event of the layout of the widgets of a room.
+const WIDGETS_LAYOUT_EVENT_TYPE: &str = "io.element.widgets.layout";
+
+/// Why [`Room::upgrade`] refused to upgrade a room, before asking the
+/// homeserver to do it.
+#[derive(Debug, thiserror::Error)]
+pub enum RoomUpgradeError {
+    /// The power levels of the room don't allow the user to send the
+    /// `m.room.tombstone` state event, so the homeserver would refuse the
+    /// upgrade.
+    #[error("the user isn't allowed to upgrade the room")]
+    Forbidden,
+
+    /// The homeserver doesn't support the requested room version.
+    #[error("the homeserver doesn't support the room version {0}")]
+    UnsupportedVersion(RoomVersionId),
+
+    /// The homeserver considers the requested room version unstable, and
+    /// [`RoomUpgradeOptions::allow_unstable_version()`] wasn't set.
+    #[error("the room version {0} is unstable")]
+    UnstableVersion(RoomVersionId),
+}
+
+/// Options for [`Room::upgrade()`].
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct RoomUpgradeOptions {
+    /// Whether to upgrade to a room version that the homeserver considers
+    /// unstable.
+    pub allow_unstable_version: bool,
+    /// Whether to invite the joined and invited members of the room to the
+    /// new room.
+    pub reinvite_members: bool,
+    /// Whether to copy the widgets of the room to the new room.
+    pub copy_widgets: bool,
+    /// Whether to restore the power levels of the users of the room in the
+    /// new room.
+    pub copy_power_levels: bool,
+}
+
+impl RoomUpgradeOptions {
+    /// Create the default `RoomUpgradeOptions`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Upgrade the room even if the homeserver considers the new room version
+    /// unstable, instead of returning [`RoomUpgradeError::UnstableVersion`].
+    pub fn allow_unstable_version(mut self) -> Self {
+        self.allow_unstable_version = true;
+        self
+    }
+
+    /// Invite the joined and invited members of the room to the new room,
+    /// since the homeserver doesn't do it.
+    pub fn reinvite_members(mut self) -> Self {
+        self.reinvite_members = true;
+        self
+    }
+
+    /// Copy the widgets of the room, and their layout, to the new room, since
+    /// the homeserver doesn't do it.
+    pub fn copy_widgets(mut self) -> Self {
+        self.copy_widgets = true;
+        self
+    }
+
+    /// Restore the power levels of the users of the room in the new room,
+    /// for the ones that the homeserver didn't carry over.
+    pub fn copy_power_levels(mut self) -> Self {
+        self.copy_power_levels = true;
+        self
+    }
+}
+
+/// The report of [`Room::upgrade()`].
+#[derive(Debug)]
+pub struct RoomUpgradeReport {
+    /// The ID of the room that replaces the upgraded room.
+    pub replacement_room_id: OwnedRoomId,
+
+    /// The users that were invited to the new room.
+    pub reinvited_users: Vec<OwnedUserId>,
+
+    /// The number of state events of widgets that were copied to the new
+    /// room.
+    pub copied_widgets: usize,
+
+    /// Whether the power levels of the new room were updated.
+    pub restored_power_levels: bool,
+
+    /// The errors that happened after the room was upgraded, while applying
+    /// the options.
+    ///
+    /// They don't undo the upgrade, the failed steps can be done manually in
+    /// the new room.
+    pub errors: Vec<Error>,
+}
+
+impl Room {
+    /// Get the room version that the homeserver recommends for upgrading this
+    /// room, according to its capabilities.
+    ///
+    /// Returns `None` if the room already uses the recommended version or a
+    /// newer one, or if the version of the room is unknown.
+    pub async fn recommended_upgrade_version(&self) -> Result<Option<RoomVersionId>> {
+        let Some(current) = self.clone_info().room_version().cloned() else {
+            return Ok(None);
+        };
+
+        let capabilities = self.client.get_capabilities().await?;
+        let recommended = capabilities.room_versions.default;
+
+        Ok(is_newer_room_version(&recommended, &current).then_some(recommended))
+    }
+
+    /// Upgrade this room to the given room version.
+    ///
+    /// The homeserver creates a new room with the new version, and sends a
+    /// `m.room.tombstone` event to this room pointing to the new room. The
+    /// tombstone can be read afterwards with
+    /// [`tombstone()`](matrix_sdk_base::Room::tombstone).
+    ///
+    /// Before asking the homeserver, this checks that the power levels of the
+    /// room allow the user to upgrade it, and that the homeserver supports the
+    /// new version, returning a [`RoomUpgradeError`] otherwise.
+    ///
+    /// The homeserver only carries over part of the state of the room, the
+    /// `options` allow to copy more of it. Failing to do so doesn't fail the
+    /// upgrade, the errors are listed in the returned [`RoomUpgradeReport`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use matrix_sdk::room::RoomUpgradeOptions;
+    ///
+    /// # async {
+    /// # let room: matrix_sdk::Room = todo!();
+    /// if let Some(version) = room.recommended_upgrade_version().await? {
+    ///     let options =
+    ///         RoomUpgradeOptions::new().reinvite_members().copy_widgets();
+    ///     let report = room.upgrade(version, options).await?;
+    ///
+    ///     for error in report.errors {
+    ///         eprintln!("Couldn't prepare the new room: {error}");
+    ///     }
+    /// }
+    /// # anyhow::Ok(()) };
+    /// ```
+    #[instrument(skip(self, options), fields(room_id = ?self.room_id()))]
+    pub async fn upgrade(
+        &self,
+        new_version: RoomVersionId,
+        options: RoomUpgradeOptions,
+    ) -> Result<RoomUpgradeReport> {
+        self.ensure_room_joined()?;
+
+        if !self.can_user_send_state(self.own_user_id(), StateEventType::RoomTombstone).await? {
+            return Err(RoomUpgradeError::Forbidden.into());
+        }
+
+        let capabilities = self.client.get_capabilities().await?;
+        match capabilities.room_versions.available.get(&new_version) {
+            None => return Err(RoomUpgradeError::UnsupportedVersion(new_version).into()),
+            Some(RoomVersionStability::Stable) => {}
+            Some(_) if options.allow_unstable_version => {}
+            Some(_) => return Err(RoomUpgradeError::UnstableVersion(new_version).into()),
+        }
+
+        let request = upgrade_room::v3::Request::new(self.room_id().to_owned(), new_version);
+        let replacement_room_id = self.client.send(request, None).await?.replacement_room;
+        debug!(?replacement_room_id, "The room was upgraded");
+
+        let mut report = RoomUpgradeReport {
+            replacement_room_id,
+            reinvited_users: Vec::new(),
+            copied_widgets: 0,
+            restored_power_levels: false,
+            errors: Vec::new(),
+        };
+
+        if options.copy_power_levels {
+            match self.restore_power_levels(&report.replacement_room_id).await {
+                Ok(restored) => report.restored_power_levels = restored,
+                Err(error) => report.errors.push(error),
+            }
+        }
+
+        if options.copy_widgets {
+            self.copy_widgets(&mut report).await;
+        }
+
+        if options.reinvite_members {
+            self.reinvite_members(&mut report).await;
+        }
+
+        Ok(report)
+    }
+
+    /// Add the users of the power levels of this room that are missing from
+    /// the power levels of the new room.
+    ///
+    /// Returns whether the power levels of the new room were updated.
+    async fn restore_power_levels(&self, new_room_id: &RoomId) -> Result<bool> {
+        let old_power_levels = self.get_room_power_levels().await?;
+
+        let request = get_state_events_for_key::v3::Request::new(
+            new_room_id.to_owned(),
+            StateEventType::RoomPowerLevels,
+            String::new(),
+        );
+        let response = self.client.send(request, None).await?;
+        let mut content: RoomPowerLevelsEventContent = response.content.deserialize_as()?;
+
+        let mut changed = false;
+        for (user_id, level) in old_power_levels.users {
+            if user_id != *self.own_user_id() && !content.users.contains_key(&user_id) {
+                content.users.insert(user_id, level);
+                changed = true;
+            }
+        }
+
+        if changed {
+            let request = send_state_event::v3::Request::new(
+                new_room_id.to_owned(),
+                &EmptyStateKey,
+                &content,
+            )?;
+            self.client.send(request, None).await?;
+        }
+
+        Ok(changed)
+    }
+
+    /// Copy the state events of the widgets of this room to the new room.
+    async fn copy_widgets(&self, report: &mut RoomUpgradeReport) {
+        for event_type in WIDGETS_EVENT_TYPES.into_iter().chain([WIDGETS_LAYOUT_EVENT_TYPE]) {
+            let events = match self.get_state_events(event_type.into()).await {
+                Ok(events) => events,
+                Err(error) => {
+                    report.errors.push(error);
+                    continue;
+                }
+            };
+
+            for event in events {
+                let RawAnySyncOrStrippedState::Sync(event) = event else {
+                    continue;
+                };
+
+                let (Ok(Some(state_key)), Ok(Some(content))) = (
+                    event.get_field::<String>("state_key"),
+                    event.get_field::<Box<RawJsonValue>>("content"),
+                ) else {
+                    warn!(event_type, "Skipping a widget state event without content");
+                    continue;
+                };
+
+                // Removed widgets have an empty content.
+                let is_empty = serde_json::from_str::<JsonObject>(content.get())
+                    .map_or(true, |content| content.is_empty());
+                if is_empty {
+                    continue;
+                }
+
+                let request = send_state_event::v3::Request::new_raw(
+                    report.replacement_room_id.clone(),
+                    event_type.into(),
+                    state_key,
+                    Raw::from_json(content),
+                );
+
+                match self.client.send(request, None).await {
+                    Ok(_) => report.copied_widgets += 1,
+                    Err(error) => report.errors.push(error.into()),
+                }
+            }
+        }
+    }
+
+    /// Invite the joined and invited members of this room to the new room.
+    ///
+    /// The members are fetched from the homeserver first if they weren't
+    /// synced, so the ones that were lazy-loaded away are invited too.
+    async fn reinvite_members(&self, report: &mut RoomUpgradeReport) {
+        let members = match self.members(RoomMemberships::JOIN | RoomMemberships::INVITE).await {
+            Ok(members) => members,
+            Err(error) => {
+                report.errors.push(error);
+                return;
+            }
+        };
+
+        for member in members {
+            let user_id = member.user_id().to_owned();
+            if user_id == *self.own_user_id() {
+                continue;
+            }
+
+            let request = invite_user::v3::Request::new(
+                report.replacement_room_id.clone(),
+                InvitationRecipient::UserId { user_id: user_id.clone() },
+            );
+
+            match self.client.send(request, None).await {
+                Ok(_) => report.reinvited_users.push(user_id),
+                Err(error) => report.errors.push(error.into()),
+            }
+        }
+    }
+}
+
+/// Whether `new` is a newer room version than `current`.
+///
+/// Versions that aren't numbers are unstable versions, that can't be ordered.
+/// A room using one of them can move to any stable version.
+fn is_newer_room_version(new: &RoomVersionId, current: &RoomVersionId) -> bool {
+    match (new.as_str().parse::<u32>(), current.as_str().parse::<u32>()) {
+        (Ok(new), Ok(current)) => new > current,
+        (Ok(_), Err(_)) => true,
+        (Err(_), _) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ruma::RoomVersionId;
+
+    use super::is_newer_room_version;
+
+    #[test]
+    fn test_is_newer_room_version() {
+        let unstable = RoomVersionId::try_from("org.example.experimental").unwrap();
+
+        assert!(is_newer_room_version(&RoomVersionId::V10, &RoomVersionId::V9));
+        assert!(is_newer_room_version(&RoomVersionId::V10, &unstable));
+
+        // Room versions are not ordered as strings.
+        assert!(!is_newer_room_version(&RoomVersionId::V9, &RoomVersionId::V10));
+        assert!(!is_newer_room_version(&RoomVersionId::V10, &RoomVersionId::V10));
+        assert!(!is_newer_room_version(&unstable, &RoomVersionId::V1));
+    }
+}
@@ -0,0 +1,191 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeSet;
+
+use matrix_sdk_base::deserialized_responses::RawAnySyncOrStrippedState;
+use ruma::events::StateEventType;
+use serde_json::json;
+use tracing::{debug, instrument};
+
+use super::Room;
+use crate::{
+    widget::{WidgetSettings, WidgetUrlError, WidgetUrlPolicy, WIDGET_STATE_EVENT_TYPES},
+    Result,
+};
+
+/// The type of the state events sent by [`Room::add_widget()`].
+const WIDGETS_EVENT_TYPE: &str = "im.vector.modular.widgets";
+
+/// Why [`Room::add_widget()`] or [`Room::remove_widget()`] refused to change
+/// the widgets of a room, before sending any request.
+#[derive(Debug, thiserror::Error)]
+pub enum RoomWidgetError {
+    /// The power levels of the room don't allow the user to change its
+    /// widgets.
+    #[error("the user isn't allowed to change the widgets of the room")]
+    Forbidden,
+
+    /// The room already has a widget with this ID.
+    #[error("the room already has a widget with the ID `{0}`")]
+    AlreadyExists(String),
+
+    /// The room doesn't have a widget with this ID.
+    #[error("the room doesn't have a widget with the ID `{0}`")]
+    NotFound(String),
+}
+
+impl Room {
+    /// Get the widgets of this room, from its `m.widget` and
+    /// `im.vector.modular.widgets` state events.
+    ///
+    /// The URLs of the widgets are validated with the default
+    /// [`WidgetUrlPolicy`], which accepts widgets from any domain.
+    pub async fn get_widgets(&self) -> Result<Vec<WidgetSettings>> {
+        self.get_widgets_with_url_policy(&WidgetUrlPolicy::new()).await
+    }
+
+    /// Get the widgets of this room, from its `m.widget` and
+    /// `im.vector.modular.widgets` state events, validating their URLs with the
+    /// given policy.
+    ///
+    /// Removed widgets and widgets with a URL that doesn't follow the policy
+    /// are skipped. If both event types define a widget with the same ID, the
+    /// `m.widget` one is used.
+    #[instrument(skip_all, fields(room_id = ?self.room_id()))]
+    pub async fn get_widgets_with_url_policy(
+        &self,
+        policy: &WidgetUrlPolicy,
+    ) -> Result<Vec<WidgetSettings>> {
+        let mut widget_ids = BTreeSet::new();
+        let mut widgets = Vec::new();
+
+        for event_type in WIDGET_STATE_EVENT_TYPES {
+            for event in self.get_state_events(event_type.into()).await? {
+                let RawAnySyncOrStrippedState::Sync(event) = event else {
+                    continue;
+                };
+
+                match WidgetSettings::from_state_event(&event, policy) {
+                    Ok(widget) => {
+                        if widget_ids.insert(widget.widget_id().to_owned()) {
+                            widgets.push(widget);
+                        }
+                    }
+                    Err(WidgetUrlError::NotAWidget) => {}
+                    Err(error) => debug!(event_type, "Skipping widget: {error}"),
+                }
+            }
+        }
+
+        Ok(widgets)
+    }
+
+    /// Add a widget to this room, by sending a `im.vector.modular.widgets`
+    /// state event with the widget ID as state key, and the
+    /// [type](WidgetSettings::widget_type) and [data](WidgetSettings::data) of
+    /// the widget.
+    ///
+    /// Returns [`RoomWidgetError::Forbidden`] if the user isn't allowed to send
+    /// this event, and [`RoomWidgetError::AlreadyExists`] if the room already
+    /// has a widget with the same ID, which must be removed first.
+    #[instrument(skip_all, fields(room_id = ?self.room_id(), widget_id = widget.widget_id()))]
+    pub async fn add_widget(&self, widget: WidgetSettings) -> Result<()> {
+        self.ensure_room_joined()?;
+        self.ensure_user_can_send_widget_events(&[WIDGETS_EVENT_TYPE]).await?;
+
+        let widget_id = widget.widget_id();
+        if !self.widget_event_types(widget_id).await?.is_empty() {
+            return Err(RoomWidgetError::AlreadyExists(widget_id.to_owned()).into());
+        }
+
+        let mut content = json!({
+            "id": widget_id,
+            "type": widget.widget_type(),
+            "url": widget.raw_url().as_str(),
+            "waitForIframeLoad": !widget.init_on_content_load(),
+            "creatorUserId": self.own_user_id(),
+        });
+        if !widget.data().is_empty() {
+            content["data"] = widget.data().clone().into();
+        }
+        self.send_state_event_raw(WIDGETS_EVENT_TYPE, widget_id, content).await?;
+
+        Ok(())
+    }
+
+    /// Remove the widget with the given ID from this room, by sending empty
+    /// state events for every widget state event that defines it.
+    ///
+    /// Returns [`RoomWidgetError::Forbidden`] if the user isn't allowed to send
+    /// these events, and [`RoomWidgetError::NotFound`] if the room doesn't have
+    /// a widget with this ID.
+    #[instrument(skip(self), fields(room_id = ?self.room_id()))]
+    pub async fn remove_widget(&self, widget_id: &str) -> Result<()> {
+        self.ensure_room_joined()?;
+
+        let event_types = self.widget_event_types(widget_id).await?;
+        if event_types.is_empty() {
+            return Err(RoomWidgetError::NotFound(widget_id.to_owned()).into());
+        }
+
+        // Check every event type first, to not remove the widget only partially.
+        self.ensure_user_can_send_widget_events(&event_types).await?;
+
+        for event_type in event_types {
+            self.send_state_event_raw(event_type, widget_id, json!({})).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Check that the power levels of this room allow the user to send state
+    /// events of all the given widget event types.
+    async fn ensure_user_can_send_widget_events(&self, event_types: &[&str]) -> Result<()> {
+        for &event_type in event_types {
+            let event_type = StateEventType::from(event_type);
+            if !self.can_user_send_state(self.own_user_id(), event_type).await? {
+                return Err(RoomWidgetError::Forbidden.into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the types of the state events of this room that define a widget
+    /// with the given ID.
+    async fn widget_event_types(&self, widget_id: &str) -> Result<Vec<&'static str>> {
+        let mut event_types = Vec::new();
+
+        for event_type in WIDGET_STATE_EVENT_TYPES {
+            let Some(RawAnySyncOrStrippedState::Sync(event)) =
+                self.get_state_event(event_type.into(), widget_id).await?
+            else {
+                continue;
+            };
+
+            // Removed widgets have an empty content.
+            let has_url = event
+                .get_field::<serde_json::Value>("content")
+                .ok()
+                .flatten()
+                .is_some_and(|content| content.get("url").is_some());
+            if has_url {
+                event_types.push(event_type);
+            }
+        }
+
+        Ok(event_types)
+    }
+}
@@ -0,0 +1,519 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Messages scheduled to be sent later.
+//!
+//! A message is scheduled with [`Room::schedule_message()`], and then stored
+//! in the state store until it is due. The client sends the due messages in
+//! the background as long as it is running, and reports the outcome of every
+//! send to the subscribers of [`ScheduledMessages::subscribe_to_dispatches()`].
+//! Messages that became due while the client wasn't running are sent as soon
+//! as [`ScheduledMessages::start()`] is called.
+//!
+//! Every scheduled message is sent with its ID as transaction ID, so retrying
+//! a send after an interruption doesn't duplicate the message. A message stays
+//! in the store until it was sent, or until sending it failed for good.
+
+use std::{
+    collections::BTreeSet,
+    future::Future,
+    pin::pin,
+    sync::{Arc, Mutex as StdMutex, Weak},
+    time::{Duration, SystemTime},
+};
+
+use futures_util::future::{select, Either};
+use matrix_sdk_base::RoomState;
+use ruma::{
+    events::room::message::RoomMessageEventContent, MilliSecondsSinceUnixEpoch, OwnedEventId,
+    OwnedRoomId, OwnedTransactionId, TransactionId, UInt,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::{broadcast, Mutex, Notify};
+use tracing::{debug, info, instrument, trace, warn};
+
+use crate::{
+    client::ClientInner,
+    executor::{spawn, JoinHandle},
+    Client, Error, Result, RetryKind, Room,
+};
+
+/// The key of the scheduled messages in the custom values of the state store.
+const SCHEDULED_MESSAGES_KEY: &[u8] = b"matrix-sdk.scheduled-messages";
+
+/// The longest time the dispatcher sleeps without checking the scheduled
+/// messages, in case the clock jumped.
+const MAX_DISPATCHER_SLEEP: Duration = Duration::from_secs(60 * 60);
+
+/// The time after which the dispatcher tries again to send a message that
+/// couldn't be sent because of a network error, or a transient error of the
+/// homeserver that didn't say when to retry.
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// A message scheduled to be sent later.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ScheduledMessage {
+    /// The ID of this scheduled message.
+    ///
+    /// It is also used as the transaction ID of the message when it is sent.
+    pub id: OwnedTransactionId,
+
+    /// The room the message is sent to.
+    pub room_id: OwnedRoomId,
+
+    /// The content of the message.
+    pub content: RoomMessageEventContent,
+
+    /// The time the message is sent at.
+    pub send_at: MilliSecondsSinceUnixEpoch,
+}
+
+/// The outcome of sending a scheduled message.
+#[derive(Clone, Debug)]
+pub struct ScheduledMessageDispatch {
+    /// The message that was scheduled.
+    pub message: ScheduledMessage,
+
+    /// The ID of the event that was sent, or the reason the message couldn't
+    /// be sent.
+    pub result: Result<OwnedEventId, ScheduledMessageError>,
+}
+
+/// The reason a scheduled message couldn't be sent.
+#[derive(Clone, Debug, Error)]
+pub enum ScheduledMessageError {
+    /// We aren't in the room anymore.
+    #[error("the room of the scheduled message is not joined")]
+    RoomNotJoined,
+
+    /// Sending the message failed.
+    ///
+    /// If it was a network error or a transient error of the homeserver, like
+    /// rate limiting, the message stays scheduled and sending it is retried
+    /// later.
+    #[error(transparent)]
+    Send(Arc<Error>),
+}
+
+/// The state of the scheduled messages, shared by all the handles of a
+/// client.
+pub(crate) struct ScheduledMessagesState {
+    task: StdMutex<Option<ScheduledMessagesTask>>,
+    /// Serializes the updates of the stored messages.
+    lock: Mutex<()>,
+    /// The IDs of the messages that are being sent.
+    sending: StdMutex<BTreeSet<OwnedTransactionId>>,
+    /// Wakes the dispatcher up when the scheduled messages change.
+    wakeup: Arc<Notify>,
+    dispatches: broadcast::Sender<ScheduledMessageDispatch>,
+}
+
+impl Default for ScheduledMessagesState {
+    fn default() -> Self {
+        Self {
+            task: Default::default(),
+            lock: Default::default(),
+            sending: Default::default(),
+            wakeup: Default::default(),
+            dispatches: broadcast::Sender::new(100),
+        }
+    }
+}
+
+/// The manager of the messages scheduled to be sent later.
+///
+/// Get access to this manager with [`Client::scheduled_messages()`].
+#[derive(Debug)]
+pub struct ScheduledMessages {
+    client: Client,
+}
+
+impl ScheduledMessages {
+    pub(crate) fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    /// Get all the scheduled messages, sorted by the time they are sent at.
+    pub async fn list(&self) -> Result<Vec<ScheduledMessage>> {
+        self.load().await
+    }
+
+    /// Cancel the scheduled message with the given ID.
+    ///
+    /// Returns `false` if there was no such message, for example because it
+    /// was already sent, or if it is being sent.
+    pub async fn cancel(&self, id: &TransactionId) -> Result<bool> {
+        self.update(|messages| {
+            if self.is_sending(id) {
+                return false;
+            }
+
+            let len = messages.len();
+            messages.retain(|message| message.id != id);
+            messages.len() != len
+        })
+        .await
+    }
+
+    /// Replace the content and the time of the scheduled message with the
+    /// given ID.
+    ///
+    /// Returns `false` if there was no such message, for example because it
+    /// was already sent, or if it is being sent.
+    pub async fn edit(
+        &self,
+        id: &TransactionId,
+        content: RoomMessageEventContent,
+        at: SystemTime,
+    ) -> Result<bool> {
+        let send_at = to_timestamp(at);
+
+        let edited = self
+            .update(|messages| {
+                if self.is_sending(id) {
+                    return false;
+                }
+
+                let Some(message) = messages.iter_mut().find(|message| message.id == id) else {
+                    return false;
+                };
+
+                message.content = content;
+                message.send_at = send_at;
+
+                true
+            })
+            .await?;
+
+        if edited {
+            self.wake_up_dispatcher();
+        }
+
+        Ok(edited)
+    }
+
+    /// Send the scheduled messages that are due now.
+    ///
+    /// This is done automatically in the background after
+    /// [`ScheduledMessages::start()`] is called.
+    #[instrument(skip_all)]
+    pub async fn dispatch_due(&self) -> Result<Vec<ScheduledMessageDispatch>> {
+        let now = self.client.base_client().clock().now_ms();
+
+        // Mark the due messages as being sent, so that they can't be edited or
+        // cancelled, and aren't sent twice by concurrent calls.
+        let due: Vec<_> = {
+            let _lock = self.client.inner.scheduled_messages.lock.lock().await;
+            let messages = self.load().await?;
+            let mut sending = self.client.inner.scheduled_messages.sending.lock().unwrap();
+
+            messages
+                .into_iter()
+                .filter(|message| message.send_at <= now && sending.insert(message.id.clone()))
+                .collect()
+        };
+        let _sending_guard = SendingGuard {
+            state: &self.client.inner.scheduled_messages,
+            ids: due.iter().map(|message| message.id.clone()).collect(),
+        };
+
+        let mut dispatches = Vec::with_capacity(due.len());
+
+        for message in due {
+            let result = self.send(&message).await;
+
+            // Only remove the message from the store once it was sent, or once
+            // sending it failed for good.
+            let retry_after = match &result {
+                Err(ScheduledMessageError::Send(error)) => match error.retry_kind() {
+                    RetryKind::NetworkFailure => Some(RETRY_DELAY),
+                    RetryKind::Transient { retry_after } => {
+                        Some(retry_after.unwrap_or(RETRY_DELAY))
+                    }
+                    RetryKind::Permanent => None,
+                },
+                _ => None,
+            };
+
+            if let Some(retry_after) = retry_after {
+                debug!(id = ?message.id, ?retry_after, "Couldn't send a scheduled message, retrying later");
+
+                let send_at = add_duration(now, retry_after);
+                self.update(|messages| {
+                    if let Some(stored) = messages.iter_mut().find(|m| m.id == message.id) {
+                        stored.send_at = send_at;
+                    }
+                })
+                .await?;
+            } else {
+                self.update(|messages| messages.retain(|m| m.id != message.id)).await?;
+            }
+
+            let dispatch = ScheduledMessageDispatch { message, result };
+            // It's fine if there are no subscribers.
+            let _ = self.client.inner.scheduled_messages.dispatches.send(dispatch.clone());
+            dispatches.push(dispatch);
+        }
+
+        info!(count = dispatches.len(), "Dispatched the due scheduled messages");
+
+        Ok(dispatches)
+    }
+
+    /// Send the scheduled messages in the background when they are due, until
+    /// [`ScheduledMessages::stop()`] is called.
+    ///
+    /// This is called automatically when a message is scheduled, but it must
+    /// be called to send the messages that were scheduled before the client
+    /// was restored.
+    pub fn start(&self) {
+        let mut task = self.client.inner.scheduled_messages.task.lock().unwrap();
+
+        if task.is_none() {
+            *task = Some(ScheduledMessagesTask::new(
+                Arc::downgrade(&self.client.inner),
+                self.client.inner.scheduled_messages.wakeup.clone(),
+            ));
+        }
+    }
+
+    /// Stop sending the scheduled messages in the background.
+    ///
+    /// The messages stay scheduled, and are sent once
+    /// [`ScheduledMessages::start()`] is called again.
+    pub fn stop(&self) {
+        *self.client.inner.scheduled_messages.task.lock().unwrap() = None;
+    }
+
+    /// Subscribe to the outcome of the sends of the scheduled messages.
+    pub fn subscribe_to_dispatches(&self) -> broadcast::Receiver<ScheduledMessageDispatch> {
+        self.client.inner.scheduled_messages.dispatches.subscribe()
+    }
+
+    async fn schedule(&self, message: ScheduledMessage) -> Result<()> {
+        self.update(|messages| messages.push(message)).await?;
+
+        self.start();
+        self.wake_up_dispatcher();
+
+        Ok(())
+    }
+
+    /// Whether the message with the given ID is being sent.
+    fn is_sending(&self, id: &TransactionId) -> bool {
+        self.client.inner.scheduled_messages.sending.lock().unwrap().contains(id)
+    }
+
+    /// Wake the dispatcher up, so it notices that a message might be due
+    /// earlier than it thought.
+    fn wake_up_dispatcher(&self) {
+        self.client.inner.scheduled_messages.wakeup.notify_one();
+    }
+
+    async fn send(
+        &self,
+        message: &ScheduledMessage,
+    ) -> Result<OwnedEventId, ScheduledMessageError> {
+        let room = self
+            .client
+            .get_room(&message.room_id)
+            .filter(|room| room.state() == RoomState::Joined)
+            .ok_or(ScheduledMessageError::RoomNotJoined)?;
+
+        let response = room
+            .send(message.content.clone())
+            .with_transaction_id(&message.id)
+            .await
+            .map_err(|e| ScheduledMessageError::Send(Arc::new(e)))?;
+
+        Ok(response.event_id)
+    }
+
+    /// Update the stored messages, keeping them sorted.
+    async fn update<T>(&self, f: impl FnOnce(&mut Vec<ScheduledMessage>) -> T) -> Result<T> {
+        let _lock = self.client.inner.scheduled_messages.lock.lock().await;
+
+        let mut messages = self.load().await?;
+        let output = f(&mut messages);
+        messages.sort_by_key(|message| message.send_at);
+
+        let value = serde_json::to_vec(&messages)?;
+        self.client.store().set_custom_value(SCHEDULED_MESSAGES_KEY, value).await?;
+
+        Ok(output)
+    }
+
+    async fn load(&self) -> Result<Vec<ScheduledMessage>> {
+        let Some(value) = self.client.store().get_custom_value(SCHEDULED_MESSAGES_KEY).await?
+        else {
+            return Ok(Vec::new());
+        };
+
+        Ok(serde_json::from_slice(&value)?)
+    }
+
+    /// The time the next scheduled message is due at, if any.
+    async fn next_due(&self) -> Result<Option<MilliSecondsSinceUnixEpoch>> {
+        Ok(self.load().await?.first().map(|message| message.send_at))
+    }
+}
+
+impl Client {
+    /// Get the manager of the messages scheduled to be sent later.
+    pub fn scheduled_messages(&self) -> ScheduledMessages {
+        ScheduledMessages::new(self.clone())
+    }
+}
+
+impl Room {
+    /// Schedule a message to be sent to this room at the given time.
+    ///
+    /// The message is stored until it is due, and sent in the background if
+    /// the client is running at that time, or as soon as
+    /// [`ScheduledMessages::start()`] is called afterwards. A time in the past
+    /// sends the message right away.
+    ///
+    /// Use [`Client::scheduled_messages()`] to list, edit or cancel the
+    /// scheduled messages.
+    pub async fn schedule_message(
+        &self,
+        content: RoomMessageEventContent,
+        at: SystemTime,
+    ) -> Result<ScheduledMessage> {
+        let message = ScheduledMessage {
+            id: TransactionId::new(),
+            room_id: self.room_id().to_owned(),
+            content,
+            send_at: to_timestamp(at),
+        };
+
+        self.client.scheduled_messages().schedule(message.clone()).await?;
+
+        Ok(message)
+    }
+
+    /// Get the messages scheduled to be sent to this room, sorted by the time
+    /// they are sent at.
+    pub async fn scheduled_messages(&self) -> Result<Vec<ScheduledMessage>> {
+        let mut messages = self.client.scheduled_messages().list().await?;
+        messages.retain(|message| message.room_id == self.room_id());
+
+        Ok(messages)
+    }
+}
+
+/// Unmarks the messages as being sent when a dispatch is done, or when it is
+/// interrupted.
+struct SendingGuard<'a> {
+    state: &'a ScheduledMessagesState,
+    ids: Vec<OwnedTransactionId>,
+}
+
+impl Drop for SendingGuard<'_> {
+    fn drop(&mut self) {
+        let mut sending = self.state.sending.lock().unwrap();
+
+        for id in &self.ids {
+            sending.remove(id);
+        }
+    }
+}
+
+/// The task sending the scheduled messages when they are due.
+struct ScheduledMessagesTask {
+    #[allow(dead_code)]
+    join_handle: JoinHandle<()>,
+}
+
+impl Drop for ScheduledMessagesTask {
+    fn drop(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.join_handle.abort();
+    }
+}
+
+impl ScheduledMessagesTask {
+    fn new(client: Weak<ClientInner>, wakeup: Arc<Notify>) -> Self {
+        let join_handle = spawn(async move {
+            Self::run(client, wakeup).await;
+        });
+
+        Self { join_handle }
+    }
+
+    async fn run(client: Weak<ClientInner>, wakeup: Arc<Notify>) {
+        loop {
+            let Some(client) = client.upgrade() else {
+                trace!("Client got dropped, shutting down the task");
+                break;
+            };
+
+            let client = Client { inner: client };
+            let scheduled_messages = client.scheduled_messages();
+
+            if let Err(e) = scheduled_messages.dispatch_due().await {
+                warn!("Couldn't dispatch the scheduled messages: {e}");
+            }
+
+            let sleep_duration = match scheduled_messages.next_due().await {
+                Ok(Some(next_due)) => {
+                    let now = client.base_client().clock().now_ms();
+                    duration_between(now, next_due).min(MAX_DISPATCHER_SLEEP)
+                }
+                Ok(None) => MAX_DISPATCHER_SLEEP,
+                Err(e) => {
+                    warn!("Couldn't load the scheduled messages: {e}");
+                    RETRY_DELAY
+                }
+            };
+
+            // Don't keep the client alive while sleeping.
+            let clock = client.base_client().clock().clone();
+            drop(scheduled_messages);
+            drop(client);
+
+            sleep_or_wake_up(clock.sleep(sleep_duration), &wakeup).await;
+        }
+    }
+}
+
+/// Wait until the sleep future resolves or the dispatcher is woken up.
+async fn sleep_or_wake_up(sleep: impl Future<Output = ()>, wakeup: &Notify) {
+    match select(pin!(sleep), pin!(wakeup.notified())).await {
+        Either::Left(_) => {}
+        Either::Right(_) => trace!("Scheduled messages changed, waking up"),
+    }
+}
+
+fn to_timestamp(time: SystemTime) -> MilliSecondsSinceUnixEpoch {
+    // Times before the epoch are in the past anyway.
+    MilliSecondsSinceUnixEpoch::from_system_time(time)
+        .unwrap_or(MilliSecondsSinceUnixEpoch(UInt::MIN))
+}
+
+fn add_duration(
+    time: MilliSecondsSinceUnixEpoch,
+    duration: Duration,
+) -> MilliSecondsSinceUnixEpoch {
+    let duration = u64::try_from(duration.as_millis()).unwrap_or(u64::MAX);
+    MilliSecondsSinceUnixEpoch(UInt::new_saturating(u64::from(time.0).saturating_add(duration)))
+}
+
+fn duration_between(
+    start: MilliSecondsSinceUnixEpoch,
+    end: MilliSecondsSinceUnixEpoch,
+) -> Duration {
+    Duration::from_millis(u64::from(end.0).saturating_sub(u64::from(start.0)))
+}
@@ -0,0 +1,235 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! [Server notices], the messages a homeserver sends to its users in a
+//! dedicated room.
+//!
+//! The server notices room is tagged with `m.server_notice`. The notices that
+//! are still relevant, like the one telling that the homeserver reached its
+//! usage limit, are pinned in that room until they don't apply anymore. The
+//! client keeps track of these pinned notices, so they can be shown as
+//! banners: see [`Client::server_notices()`].
+//!
+//! [Server notices]: https://spec.matrix.org/v1.9/client-server-api/#server-notices
+
+use std::sync::{Arc, Mutex as StdMutex, Weak};
+
+use eyeball::{SharedObservable, Subscriber};
+use matrix_sdk_base::{
+    sync::{Rooms, Timeline},
+    RoomState,
+};
+use ruma::{
+    events::{
+        room::message::{MessageType, RoomMessageEventContent, ServerNoticeMessageEventContent},
+        AnyRoomAccountDataEvent, AnySyncStateEvent,
+    },
+    serde::Raw,
+    OwnedEventId, OwnedRoomId, RoomId,
+};
+use tokio::sync::mpsc;
+use tracing::{debug, instrument, trace, warn};
+
+use crate::{
+    client::ClientInner,
+    executor::{spawn, JoinHandle},
+    Client, Result, Room,
+};
+
+/// A server notice that is still relevant, because it is pinned in the server
+/// notices room.
+#[derive(Clone, Debug)]
+pub struct ServerNotice {
+    /// The ID of the server notices room.
+    pub room_id: OwnedRoomId,
+
+    /// The ID of the event of the notice.
+    pub event_id: OwnedEventId,
+
+    /// The content of the notice.
+    pub content: ServerNoticeMessageEventContent,
+}
+
+/// The server notices of a client, and the task loading them.
+#[derive(Default)]
+pub(crate) struct ServerNoticesState {
+    notices: SharedObservable<Vec<ServerNotice>>,
+    task: StdMutex<Option<ServerNoticesTask>>,
+}
+
+/// The task loading the pinned server notices of the rooms whose tags or
+/// pinned events changed during a sync.
+struct ServerNoticesTask {
+    sender: mpsc::UnboundedSender<OwnedRoomId>,
+    #[allow(dead_code)]
+    join_handle: JoinHandle<()>,
+}
+
+impl Drop for ServerNoticesTask {
+    fn drop(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.join_handle.abort();
+    }
+}
+
+impl ServerNoticesTask {
+    fn new(client: Weak<ClientInner>) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let join_handle = spawn(Self::listen(client, receiver));
+
+        Self { sender, join_handle }
+    }
+
+    async fn listen(client: Weak<ClientInner>, mut receiver: mpsc::UnboundedReceiver<OwnedRoomId>) {
+        while let Some(room_id) = receiver.recv().await {
+            let Some(client) = client.upgrade() else {
+                trace!("Client got dropped, shutting down the task");
+                break;
+            };
+            let client = Client { inner: client };
+
+            let Some(room) = client.get_room(&room_id) else {
+                continue;
+            };
+
+            let notices = match room.is_server_notices_room().await {
+                Ok(true) => room.pinned_server_notices().await,
+                Ok(false) => Ok(Vec::new()),
+                Err(e) => Err(e.into()),
+            };
+
+            match notices {
+                Ok(notices) => client.replace_server_notices(&room_id, notices),
+                Err(e) => warn!(?room_id, "Couldn't load the server notices: {e}"),
+            }
+        }
+    }
+}
+
+impl Client {
+    /// Get a subscriber to the server notices that are still relevant, like
+    /// the one telling that the homeserver reached its usage limit.
+    ///
+    /// The list is updated when the pinned events of the server notices room
+    /// change during a sync. Call [`Client::refresh_server_notices()`] to load
+    /// it after restoring a session.
+    pub fn server_notices(&self) -> Subscriber<Vec<ServerNotice>> {
+        self.inner.server_notices.notices.subscribe()
+    }
+
+    /// Reload the server notices that are still relevant from the server
+    /// notices rooms.
+    #[instrument(skip_all)]
+    pub async fn refresh_server_notices(&self) -> Result<()> {
+        let mut notices = Vec::new();
+
+        for room in self.joined_rooms() {
+            if room.is_server_notices_room().await? {
+                notices.extend(room.pinned_server_notices().await?);
+            }
+        }
+
+        self.inner.server_notices.notices.set(notices);
+
+        Ok(())
+    }
+
+    /// Update the server notices after a sync, for the server notices rooms
+    /// whose tags or pinned events changed.
+    ///
+    /// Loading the pinned events can require requests to the homeserver, so
+    /// it is done in the background, to not hold up the sync.
+    pub(crate) fn update_server_notices(&self, rooms: &Rooms) {
+        for room_id in rooms.leave.keys() {
+            self.replace_server_notices(room_id, Vec::new());
+        }
+
+        let changed_rooms = rooms.join.iter().filter(|(_, room_info)| {
+            room_info.account_data.iter().any(is_tag_event)
+                || room_info.state.iter().any(is_pinned_events_event)
+                || timeline_has_pinned_events_event(&room_info.timeline)
+        });
+
+        let mut task = self.inner.server_notices.task.lock().unwrap();
+
+        for (room_id, _) in changed_rooms {
+            let task =
+                task.get_or_insert_with(|| ServerNoticesTask::new(Arc::downgrade(&self.inner)));
+            let _ = task.sender.send(room_id.clone());
+        }
+    }
+
+    fn replace_server_notices(&self, room_id: &RoomId, notices: Vec<ServerNotice>) {
+        let server_notices = &self.inner.server_notices.notices;
+        let has_notices = server_notices.get().iter().any(|n| n.room_id == room_id);
+
+        if !has_notices && notices.is_empty() {
+            return;
+        }
+
+        server_notices.update(|all| {
+            all.retain(|n| n.room_id != room_id);
+            all.extend(notices);
+        });
+    }
+}
+
+impl Room {
+    /// Get the server notices that are pinned in this room.
+    async fn pinned_server_notices(&self) -> Result<Vec<ServerNotice>> {
+        if self.state() != RoomState::Joined {
+            return Ok(Vec::new());
+        }
+
+        let mut notices = Vec::new();
+
+        for event_id in self.pinned_event_ids().await? {
+            let event = self.event(&event_id).await?;
+
+            let content = match event.event.get_field::<RoomMessageEventContent>("content") {
+                Ok(Some(content)) => content,
+                Ok(None) => continue,
+                Err(e) => {
+                    debug!(?event_id, "Couldn't deserialize a pinned event: {e}");
+                    continue;
+                }
+            };
+
+            if let MessageType::ServerNotice(content) = content.msgtype {
+                notices.push(ServerNotice {
+                    room_id: self.room_id().to_owned(),
+                    event_id,
+                    content,
+                });
+            }
+        }
+
+        Ok(notices)
+    }
+}
+
+fn is_tag_event(event: &Raw<AnyRoomAccountDataEvent>) -> bool {
+    event.get_field::<String>("type").ok().flatten().as_deref() == Some("m.tag")
+}
+
+fn is_pinned_events_event(event: &Raw<AnySyncStateEvent>) -> bool {
+    event.get_field::<String>("type").ok().flatten().as_deref() == Some("m.room.pinned_events")
+}
+
+fn timeline_has_pinned_events_event(timeline: &Timeline) -> bool {
+    timeline.events.iter().any(|ev| {
+        ev.event.get_field::<String>("type").ok().flatten().as_deref()
+            == Some("m.room.pinned_events")
+    })
+}
@@ -20,7 +20,7 @@ use super::{
     cache::{format_storage_key_prefix, restore_sliding_sync_state},
     sticky_parameters::SlidingSyncStickyManager,
     Error, SlidingSync, SlidingSyncInner, SlidingSyncListBuilder, SlidingSyncPositionMarkers,
-    SlidingSyncRoom,
+    SlidingSyncRoom, SlidingSyncVersion,
 };
 use crate::{sliding_sync::SlidingSyncStickyParameters, Client, Result};
 
@@ -33,6 +33,7 @@ pub struct SlidingSyncBuilder {
     id: String,
     storage_key: String,
     sliding_sync_proxy: Option<Url>,
+    version: Option<SlidingSyncVersion>,
     client: Client,
     lists: Vec<SlidingSyncListBuilder>,
     extensions: Option<ExtensionsConfig>,
@@ -57,6 +58,7 @@ impl SlidingSyncBuilder {
                 id,
                 storage_key,
                 sliding_sync_proxy: None,
+                version: None,
                 client,
                 lists: Vec::new(),
                 extensions: None,
@@ -81,6 +83,19 @@ impl SlidingSyncBuilder {
         self
     }
 
+    /// Set the version of sliding sync to use.
+    ///
+    /// By default, the version is discovered when building the sliding sync:
+    /// native sliding sync is used if the homeserver supports it, otherwise
+    /// the proxy is used. See [`SlidingSyncVersion::discover()`].
+    ///
+    /// When native sliding sync is used but the homeserver turns out not to
+    /// support it, the sliding sync falls back to the proxy.
+    pub fn version(mut self, version: SlidingSyncVersion) -> Self {
+        self.version = Some(version);
+        self
+    }
+
     /// Add the given list to the lists.
     ///
     /// Replace any list with the same name.
@@ -257,9 +272,14 @@ impl SlidingSyncBuilder {
             lists.insert(list.name().to_owned(), list);
         }
 
+        let version = match self.version {
+            Some(version) => version,
+            None => SlidingSyncVersion::discover(&client).await,
+        };
+
         // Reload existing state from the cache.
         let restored_fields =
-            restore_sliding_sync_state(&client, &self.storage_key, &lists).await?;
+            restore_sliding_sync_state(&client, &self.storage_key, &lists, version).await?;
 
         let (delta_token, pos) = if let Some(fields) = restored_fields {
             #[cfg(feature = "e2e-encryption")]
@@ -287,6 +307,7 @@ impl SlidingSyncBuilder {
         Ok(SlidingSync::new(SlidingSyncInner {
             id: self.id,
             sliding_sync_proxy,
+            version: StdRwLock::new(version),
 
             client,
             storage_key: self.storage_key,
@@ -308,7 +329,7 @@ impl SlidingSyncBuilder {
 
             internal_channel: internal_channel_sender,
 
-            poll_timeout: self.poll_timeout,
+            poll_timeout: StdRwLock::new(self.poll_timeout),
             network_timeout: self.network_timeout,
         }))
     }
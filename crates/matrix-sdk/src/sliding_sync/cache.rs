@@ -9,11 +9,11 @@ use std::collections::BTreeMap;
 use matrix_sdk_base::{StateStore, StoreError};
 use matrix_sdk_common::timer;
 use ruma::UserId;
-use tracing::{trace, warn};
+use tracing::{info, trace, warn};
 
 use super::{
     FrozenSlidingSync, FrozenSlidingSyncList, SlidingSync, SlidingSyncList,
-    SlidingSyncPositionMarkers,
+    SlidingSyncPositionMarkers, SlidingSyncVersion,
 };
 #[cfg(feature = "e2e-encryption")]
 use crate::sliding_sync::FrozenSlidingSyncPos;
@@ -88,7 +88,7 @@ pub(super) async fn store_sliding_sync_state(
     storage
         .set_custom_value(
             instance_storage_key.as_bytes(),
-            serde_json::to_vec(&FrozenSlidingSync::new(position).await)?,
+            serde_json::to_vec(&FrozenSlidingSync::new(position, sliding_sync.version()).await)?,
         )
         .await?;
 
@@ -104,6 +104,15 @@ pub(super) async fn store_sliding_sync_state(
         }
     }
 
+    store_sliding_sync_lists(sliding_sync).await
+}
+
+/// Store the `SlidingSyncList`s of a `SlidingSync` that are configured for
+/// caching in the storage.
+pub(super) async fn store_sliding_sync_lists(sliding_sync: &SlidingSync) -> Result<()> {
+    let storage_key = &sliding_sync.inner.storage_key;
+    let storage = sliding_sync.inner.client.store();
+
     // Write every `SlidingSyncList` that's configured for caching into the store.
     let frozen_lists = {
         let rooms_lock = sliding_sync.inner.rooms.read().await;
@@ -192,10 +201,15 @@ pub(super) struct RestoredFields {
 ///
 /// If one cache is obsolete (corrupted, and cannot be deserialized or
 /// anything), the entire `SlidingSync` cache is removed.
+///
+/// If the cache was created with another version of sliding sync than
+/// `version`, the connection it describes can't be resumed: its tokens are
+/// dropped, but the cached lists are kept.
 pub(super) async fn restore_sliding_sync_state(
     client: &Client,
     storage_key: &str,
     lists: &BTreeMap<String, SlidingSyncList>,
+    version: SlidingSyncVersion,
 ) -> Result<Option<RestoredFields>> {
     let _timer = timer!(format!("loading sliding sync {storage_key} state from DB"));
 
@@ -220,8 +234,27 @@ pub(super) async fn restore_sliding_sync_state(
         .await?
         .map(|custom_value| serde_json::from_slice::<FrozenSlidingSync>(&custom_value))
     {
+        // `SlidingSync` has been found, but for another version of sliding sync, e.g.
+        // because the homeserver started supporting native sliding sync. The tokens
+        // of the previous connection are meaningless for the new one.
+        Some(Ok(FrozenSlidingSync { version: frozen_version, .. }))
+            if frozen_version != version =>
+        {
+            info!(
+                ?frozen_version,
+                ?version,
+                "Migrating the `SlidingSync` cache to another version, starting a new connection"
+            );
+
+            // The to-device token might have been restored from the crypto store, but it
+            // belongs to the previous connection too.
+            restored_fields.to_device_token = None;
+        }
+
         // `SlidingSync` has been found and successfully deserialized.
-        Some(Ok(FrozenSlidingSync { to_device_since, delta_token: frozen_delta_token })) => {
+        Some(Ok(FrozenSlidingSync {
+            to_device_since, delta_token: frozen_delta_token, ..
+        })) => {
             trace!("Successfully read the `SlidingSync` from the cache");
             // Only update the to-device token if we failed to read it from the crypto store
             // above.
@@ -285,7 +318,9 @@ mod tests {
         format_storage_key_for_sliding_sync_list, format_storage_key_prefix,
         restore_sliding_sync_state, store_sliding_sync_state,
     };
-    use crate::{test_utils::logged_in_client, Result, SlidingSyncList};
+    use crate::{
+        sliding_sync::SlidingSyncVersion, test_utils::logged_in_client, Result, SlidingSyncList,
+    };
 
     #[allow(clippy::await_holding_lock)]
     #[async_test]
@@ -485,9 +520,14 @@ mod tests {
         // Ok, forget about the sliding sync, let's recreate one from scratch.
         drop(sliding_sync);
 
-        let restored_fields = restore_sliding_sync_state(&client, &storage_key_prefix, &[].into())
-            .await?
-            .expect("must have restored sliding sync fields");
+        let restored_fields = restore_sliding_sync_state(
+            &client,
+            &storage_key_prefix,
+            &[].into(),
+            SlidingSyncVersion::Proxy,
+        )
+        .await?
+        .expect("must have restored sliding sync fields");
 
         // After restoring, the delta token and to-device token could be read.
         assert_eq!(restored_fields.delta_token.unwrap(), delta_token);
@@ -513,13 +553,19 @@ mod tests {
                 serde_json::to_vec(&FrozenSlidingSync {
                     to_device_since: Some(to_device_token.clone()),
                     delta_token: Some(delta_token.clone()),
+                    version: SlidingSyncVersion::Proxy,
                 })?,
             )
             .await?;
 
-        let restored_fields = restore_sliding_sync_state(&client, &storage_key_prefix, &[].into())
-            .await?
-            .expect("must have restored fields");
+        let restored_fields = restore_sliding_sync_state(
+            &client,
+            &storage_key_prefix,
+            &[].into(),
+            SlidingSyncVersion::Proxy,
+        )
+        .await?
+        .expect("must have restored fields");
 
         // After restoring, the delta token, the to-device since token, and stream
         // position could be read from the state store.
@@ -529,4 +575,48 @@ mod tests {
 
         Ok(())
     }
+
+    #[async_test]
+    async fn test_sliding_sync_cache_of_another_version_drops_the_tokens() -> Result<()> {
+        let client = logged_in_client(Some("https://foo.bar".to_owned())).await;
+
+        let sync_id = "test-sync-id";
+        let storage_key_prefix = format_storage_key_prefix(sync_id, client.user_id().unwrap());
+        let full_storage_key = format_storage_key_for_sliding_sync(&storage_key_prefix);
+
+        // A cache from before native sliding sync existed has no version.
+        client
+            .store()
+            .set_custom_value(
+                full_storage_key.as_bytes(),
+                serde_json::to_vec(&serde_json::json!({ "delta_token": "delta_token" }))?,
+            )
+            .await?;
+
+        // It belongs to the proxy.
+        let restored_fields = restore_sliding_sync_state(
+            &client,
+            &storage_key_prefix,
+            &[].into(),
+            SlidingSyncVersion::Proxy,
+        )
+        .await?
+        .expect("must have restored fields");
+        assert_eq!(restored_fields.delta_token.as_deref(), Some("delta_token"));
+
+        // Its connection can't be resumed with native sliding sync.
+        let restored_fields = restore_sliding_sync_state(
+            &client,
+            &storage_key_prefix,
+            &[].into(),
+            SlidingSyncVersion::Native,
+        )
+        .await?
+        .expect("must have restored fields");
+        assert!(restored_fields.delta_token.is_none());
+        assert!(restored_fields.to_device_token.is_none());
+        assert!(restored_fields.pos.is_none());
+
+        Ok(())
+    }
 }
@@ -1,11 +1,11 @@
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, sync::Weak};
 
 use imbl::Vector;
 use matrix_sdk_base::{sync::SyncResponse, PreviousEventsProvider};
 use ruma::{api::client::sync::sync_events::v4, events::AnyToDeviceEvent, serde::Raw, OwnedRoomId};
 
 use super::{SlidingSync, SlidingSyncBuilder};
-use crate::{Client, Result, SlidingSyncRoom};
+use crate::{Client, Error, Result, SlidingSyncRoom};
 
 impl Client {
     /// Create a [`SlidingSyncBuilder`] tied to this client, with the given
@@ -16,6 +16,15 @@ impl Client {
         Ok(SlidingSync::builder(id.into(), self.clone())?)
     }
 
+    /// Get the [`SlidingSync`] instances created with this client that are
+    /// still alive.
+    pub(crate) fn sliding_syncs(&self) -> Vec<SlidingSync> {
+        let mut instances = self.inner.sliding_syncs.lock().unwrap();
+        instances.retain(|inner| inner.strong_count() > 0);
+
+        instances.iter().filter_map(Weak::upgrade).map(|inner| SlidingSync { inner }).collect()
+    }
+
     /// Handle all the information provided in a sliding sync response, except
     /// for the e2ee bits.
     ///
@@ -71,16 +80,32 @@ impl<'a> SlidingSyncResponseProcessor<'a> {
         // handle_room_response before this function), so panic is fine.
         assert!(self.response.is_none());
 
+        // The session might have been invalidated while the request was in flight.
+        if self.client.is_session_gone() {
+            return Err(Error::SessionGone);
+        }
+
         self.to_device_events =
             self.client.base_client().process_sliding_sync_e2ee(extensions).await?;
 
         // Some new keys might have been received, so trigger a backup if needed.
         self.client.encryption().backups().maybe_trigger_backup();
 
+        let own_devices_changed = self.client.user_id().is_some_and(|user_id| {
+            extensions.e2ee.device_lists.changed.iter().any(|u| u == user_id)
+        });
+        if own_devices_changed {
+            self.client.spawn_own_device_check();
+        }
+
         Ok(())
     }
 
     pub async fn handle_room_response(&mut self, response: &v4::Response) -> Result<()> {
+        if self.client.is_session_gone() {
+            return Err(Error::SessionGone);
+        }
+
         self.response = Some(
             self.client
                 .base_client()
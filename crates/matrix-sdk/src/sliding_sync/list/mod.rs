@@ -16,7 +16,9 @@ use eyeball::{Observable, Subscriber};
 use eyeball_im::{ObservableVector, ObservableVectorTransaction, VectorDiff};
 use futures_core::Stream;
 use imbl::Vector;
-use ruma::{api::client::sync::sync_events::v4, assign, OwnedRoomId, TransactionId};
+use ruma::{
+    api::client::sync::sync_events::v4, assign, events::StateEventType, OwnedRoomId, TransactionId,
+};
 use serde::{Deserialize, Serialize};
 use tokio::sync::broadcast::Sender;
 use tracing::{instrument, warn};
@@ -128,6 +130,16 @@ impl SlidingSyncList {
         self.inner.sticky.write().unwrap().data_mut().set_timeline_limit(timeline);
     }
 
+    /// Get the required state.
+    pub fn required_state(&self) -> Vec<(StateEventType, String)> {
+        self.inner.sticky.read().unwrap().data().required_state().to_vec()
+    }
+
+    /// Set the required state.
+    pub fn set_required_state(&self, required_state: Vec<(StateEventType, String)>) {
+        self.inner.sticky.write().unwrap().data_mut().set_required_state(required_state);
+    }
+
     /// Get the current room list.
     pub fn room_list<R>(&self) -> Vec<R>
     where
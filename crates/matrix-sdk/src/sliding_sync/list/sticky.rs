@@ -49,6 +49,14 @@ impl SlidingSyncListStickyParameters {
     pub(super) fn set_timeline_limit(&mut self, timeline: Option<Bound>) {
         self.timeline_limit = timeline;
     }
+
+    pub(super) fn required_state(&self) -> &[(StateEventType, String)] {
+        &self.required_state
+    }
+
+    pub(super) fn set_required_state(&mut self, required_state: Vec<(StateEventType, String)>) {
+        self.required_state = required_state;
+    }
 }
 
 impl StickyData for SlidingSyncListStickyParameters {
@@ -23,6 +23,7 @@ mod list;
 mod room;
 mod sticky_parameters;
 mod utils;
+mod version;
 
 use std::{
     collections::{BTreeMap, BTreeSet, HashSet},
@@ -34,13 +35,17 @@ use std::{
 
 use async_stream::stream;
 use futures_core::stream::Stream;
+use matrix_sdk_base::deserialized_responses::SyncTimelineEvent;
 use matrix_sdk_common::{ring_buffer::RingBuffer, timer};
 use ruma::{
     api::client::{
         error::ErrorKind,
         sync::sync_events::v4::{self, ExtensionsConfig},
     },
-    assign, OwnedEventId, OwnedRoomId, RoomId,
+    assign,
+    events::room::redaction::SyncRoomRedactionEvent,
+    serde::Raw,
+    EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomId, RoomId, RoomVersionId,
 };
 use serde::{Deserialize, Serialize};
 use tokio::{
@@ -52,13 +57,14 @@ use url::Url;
 
 #[cfg(feature = "e2e-encryption")]
 use self::utils::JoinHandleExt as _;
-pub use self::{builder::*, error::*, list::*, room::*};
+pub use self::{builder::*, error::*, list::*, room::*, version::SlidingSyncVersion};
 use self::{
     cache::restore_sliding_sync_state,
     client::SlidingSyncResponseProcessor,
     sticky_parameters::{LazyTransactionId, SlidingSyncStickyManager, StickyData},
+    version::{is_unsupported_endpoint_error, NativeRequest},
 };
-use crate::{config::RequestConfig, Client, Result};
+use crate::{config::RequestConfig, Client, HttpError, Result};
 
 /// The Sliding Sync instance.
 ///
@@ -69,6 +75,9 @@ pub struct SlidingSync {
     inner: Arc<SlidingSyncInner>,
 }
 
+/// A weak reference to a [`SlidingSync`] instance, held by its client.
+pub(crate) type WeakSlidingSync = std::sync::Weak<SlidingSyncInner>;
+
 #[derive(Debug)]
 pub(super) struct SlidingSyncInner {
     /// A unique identifier for this instance of sliding sync.
@@ -79,11 +88,20 @@ pub(super) struct SlidingSyncInner {
     /// Customize the sliding sync proxy URL.
     sliding_sync_proxy: Option<Url>,
 
+    /// The version of sliding sync in use.
+    ///
+    /// It changes from [`SlidingSyncVersion::Native`] to
+    /// [`SlidingSyncVersion::Proxy`] if the homeserver turns out not to
+    /// support native sliding sync.
+    version: StdRwLock<SlidingSyncVersion>,
+
     /// The HTTP Matrix client.
     client: Client,
 
     /// Long-polling timeout that appears the sliding sync proxy request.
-    poll_timeout: Duration,
+    ///
+    /// It can be changed at runtime with [`SlidingSync::set_poll_timeout`].
+    poll_timeout: StdRwLock<Duration>,
 
     /// Extra duration for the sliding sync request to timeout. This is added to
     /// the [`Self::proxy_timeout`].
@@ -136,13 +154,59 @@ pub(super) struct SlidingSyncInner {
 
 impl SlidingSync {
     pub(super) fn new(inner: SlidingSyncInner) -> Self {
-        Self { inner: Arc::new(inner) }
+        let inner = Arc::new(inner);
+        inner.client.inner.sliding_syncs.lock().unwrap().push(Arc::downgrade(&inner));
+
+        Self { inner }
     }
 
     async fn cache_to_storage(&self, position: &SlidingSyncPositionMarkers) -> Result<()> {
         cache::store_sliding_sync_state(self, position).await
     }
 
+    /// Remove the events of the given room that were sent before `cutoff` from
+    /// the timeline queue of this instance, and from its cache.
+    ///
+    /// Returns the removed events.
+    pub(crate) async fn purge_room_events_before(
+        &self,
+        room_id: &RoomId,
+        cutoff: MilliSecondsSinceUnixEpoch,
+    ) -> Result<Vec<SyncTimelineEvent>> {
+        let purged = match self.inner.rooms.read().await.get(room_id) {
+            Some(room) => room.purge_events_before(cutoff),
+            None => return Ok(Vec::new()),
+        };
+
+        if !purged.is_empty() {
+            cache::store_sliding_sync_lists(self).await?;
+        }
+
+        Ok(purged)
+    }
+
+    /// Replace the event of the given room that is redacted by `redaction`
+    /// with its redacted form, in the timeline queue of this instance and in
+    /// its cache.
+    pub(crate) async fn redact_room_event(
+        &self,
+        room_id: &RoomId,
+        redacts: &EventId,
+        redaction: &Raw<SyncRoomRedactionEvent>,
+        room_version: &RoomVersionId,
+    ) -> Result<()> {
+        let redacted = match self.inner.rooms.read().await.get(room_id) {
+            Some(room) => room.redact_event(redacts, redaction, room_version),
+            None => return Ok(()),
+        };
+
+        if redacted {
+            cache::store_sliding_sync_lists(self).await?;
+        }
+
+        Ok(())
+    }
+
     /// Create a new [`SlidingSyncBuilder`].
     pub fn builder(id: String, client: Client) -> Result<SlidingSyncBuilder, Error> {
         SlidingSyncBuilder::new(id, client)
@@ -281,6 +345,19 @@ impl SlidingSync {
         self.inner.rooms.read().await.values().cloned().collect()
     }
 
+    /// Get the long-polling timeout of the sync requests.
+    pub fn poll_timeout(&self) -> Duration {
+        *self.inner.poll_timeout.read().unwrap()
+    }
+
+    /// Set the long-polling timeout of the sync requests.
+    ///
+    /// It is used from the next sync request, the request in flight keeps the
+    /// previous timeout.
+    pub fn set_poll_timeout(&self, timeout: Duration) {
+        *self.inner.poll_timeout.write().unwrap() = timeout;
+    }
+
     /// Handle the HTTP response.
     #[instrument(skip_all)]
     async fn handle_response(
@@ -481,7 +558,13 @@ impl SlidingSync {
 
         let restored_fields = if self.inner.share_pos || to_device_enabled {
             let lists = self.inner.lists.read().await;
-            restore_sliding_sync_state(&self.inner.client, &self.inner.storage_key, &lists).await?
+            restore_sliding_sync_state(
+                &self.inner.client,
+                &self.inner.storage_key,
+                &lists,
+                self.version(),
+            )
+            .await?
         } else {
             None
         };
@@ -511,12 +594,13 @@ impl SlidingSync {
 
         // Collect other data.
         let room_unsubscriptions = self.inner.room_unsubscriptions.read().unwrap().clone();
+        let poll_timeout = self.poll_timeout();
 
         let mut request = assign!(v4::Request::new(), {
             conn_id: Some(self.inner.id.clone()),
             delta_token,
             pos,
-            timeout: Some(self.inner.poll_timeout),
+            timeout: Some(poll_timeout),
             lists: requests_lists,
             unsubscribe_rooms: room_unsubscriptions.iter().cloned().collect(),
         });
@@ -540,7 +624,7 @@ impl SlidingSync {
             request,
             // Configure long-polling. We need some time for the long-poll itself,
             // and extra time for the network delays.
-            RequestConfig::default().timeout(self.inner.poll_timeout + self.inner.network_timeout),
+            RequestConfig::default().timeout(poll_timeout + self.inner.network_timeout),
             room_unsubscriptions,
             position_guard,
         ))
@@ -573,11 +657,7 @@ impl SlidingSync {
         debug!("Sending request");
 
         // Prepare the request.
-        let request = self.inner.client.send_with_homeserver(
-            request,
-            Some(request_config),
-            self.inner.sliding_sync_proxy.as_ref().map(ToString::to_string),
-        );
+        let request = self.send_sync_request(request, request_config);
 
         // Send the request and get a response with end-to-end encryption support.
         //
@@ -686,6 +766,40 @@ impl SlidingSync {
         spawn(future.instrument(Span::current())).await.unwrap()
     }
 
+    /// Send the sliding sync request to the endpoint of the version in use.
+    async fn send_sync_request(
+        &self,
+        request: v4::Request,
+        request_config: RequestConfig,
+    ) -> Result<v4::Response, HttpError> {
+        let client = &self.inner.client;
+
+        match self.version() {
+            SlidingSyncVersion::Proxy => {
+                let proxy = self.inner.sliding_sync_proxy.as_ref().map(ToString::to_string);
+                client.send_with_homeserver(request, Some(request_config), proxy).await
+            }
+            SlidingSyncVersion::Native => {
+                client.send(NativeRequest(request), Some(request_config)).await
+            }
+        }
+    }
+
+    /// Switch from native sliding sync to the proxy, if `error` means that the
+    /// homeserver doesn't support native sliding sync.
+    ///
+    /// Returns whether the version changed.
+    fn fall_back_to_proxy_if_unsupported(&self, error: &crate::Error) -> bool {
+        if self.version() != SlidingSyncVersion::Native || !is_unsupported_endpoint_error(error) {
+            return false;
+        }
+
+        warn!("The homeserver doesn't support native sliding sync, falling back to the proxy");
+        *self.inner.version.write().unwrap() = SlidingSyncVersion::Proxy;
+
+        true
+    }
+
     /// Create a _new_ Sliding Sync sync loop.
     ///
     /// This method returns a `Stream`, which will send requests and will handle
@@ -740,6 +854,16 @@ impl SlidingSync {
                                 continue;
                             }
 
+                            // The homeserver doesn't support native sliding sync after all, start a
+                            // new session with the proxy.
+                            Err(error) if self.fall_back_to_proxy_if_unsupported(&error) => {
+                                sync_span.in_scope(|| async {
+                                    self.expire_session().await;
+                                }).await;
+
+                                continue;
+                            }
+
                             // Here, errors we **cannot** ignore, and that must stop the sync loop.
                             Err(error) => {
                                 if error.client_api_error_kind() == Some(&ErrorKind::UnknownPos) {
@@ -856,6 +980,11 @@ impl SlidingSync {
         self.inner.sliding_sync_proxy.clone()
     }
 
+    /// The version of sliding sync in use.
+    pub fn version(&self) -> SlidingSyncVersion {
+        *self.inner.version.read().unwrap()
+    }
+
     /// Read the static extension configuration for this Sliding Sync.
     ///
     /// Note: this is not the next content of the sticky parameters, but rightly
@@ -891,12 +1020,18 @@ struct FrozenSlidingSync {
     to_device_since: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     delta_token: Option<String>,
+    /// The version of sliding sync the tokens belong to.
+    ///
+    /// It is missing from the caches created before native sliding sync was
+    /// supported, which were all created with the proxy.
+    #[serde(default)]
+    version: SlidingSyncVersion,
 }
 
 impl FrozenSlidingSync {
-    async fn new(position: &SlidingSyncPositionMarkers) -> Self {
+    async fn new(position: &SlidingSyncPositionMarkers, version: SlidingSyncVersion) -> Self {
         // The to-device token must be saved in the `FrozenCryptoSlidingSync` now.
-        Self { delta_token: position.delta_token.clone(), to_device_since: None }
+        Self { delta_token: position.delta_token.clone(), to_device_since: None, version }
     }
 }
 
@@ -1035,7 +1170,14 @@ mod tests {
     };
 
     use assert_matches::assert_matches;
-    use futures_util::{future::join_all, pin_mut, StreamExt};
+    use futures_util::{
+        future::{join, join_all},
+        pin_mut, StreamExt,
+    };
+    use matrix_sdk_base::{
+        media::{MediaFormat, MediaRequest},
+        RoomState,
+    };
     use matrix_sdk_common::deserialized_responses::SyncTimelineEvent;
     use matrix_sdk_test::async_test;
     use ruma::{
@@ -1043,23 +1185,30 @@ mod tests {
             error::ErrorKind,
             sync::sync_events::v4::{self, ExtensionsConfig, ToDeviceConfig},
         },
-        assign, owned_room_id, room_id,
+        assign, event_id,
+        events::room::MediaSource,
+        mxc_uri, owned_room_id, room_id,
         serde::Raw,
-        uint, DeviceKeyAlgorithm, OwnedRoomId, TransactionId,
+        uint, DeviceKeyAlgorithm, MilliSecondsSinceUnixEpoch, OwnedRoomId, TransactionId,
     };
     use serde::Deserialize;
     use serde_json::json;
     use url::Url;
-    use wiremock::{http::Method, Match, Mock, MockServer, Request, ResponseTemplate};
+    use wiremock::{
+        http::Method,
+        matchers::{method, path},
+        Match, Mock, MockServer, Request, ResponseTemplate,
+    };
 
     use super::{
         compute_limited,
         sticky_parameters::{LazyTransactionId, SlidingSyncStickyManager},
         FrozenSlidingSync, SlidingSync, SlidingSyncList, SlidingSyncListBuilder, SlidingSyncMode,
-        SlidingSyncRoom, SlidingSyncStickyParameters,
+        SlidingSyncRoom, SlidingSyncStickyParameters, SlidingSyncVersion,
     };
     use crate::{
-        sliding_sync::cache::restore_sliding_sync_state, test_utils::logged_in_client, Result,
+        config::SyncSettings, sliding_sync::cache::restore_sliding_sync_state,
+        test_utils::logged_in_client, Result,
     };
 
     #[derive(Copy, Clone)]
@@ -1208,7 +1357,7 @@ mod tests {
         // FrozenSlidingSync doesn't contain the to_device_token anymore, as it's saved
         // in the crypto store since PR #2323.
         let position_guard = sliding_sync.inner.position.lock().await;
-        let frozen = FrozenSlidingSync::new(&position_guard).await;
+        let frozen = FrozenSlidingSync::new(&position_guard, SlidingSyncVersion::Proxy).await;
         assert!(frozen.to_device_since.is_none());
 
         Ok(())
@@ -1679,6 +1828,7 @@ mod tests {
             &client,
             &sliding_sync.inner.storage_key,
             &*sliding_sync.inner.lists.read().await,
+            sliding_sync.version(),
         )
         .await?
         .expect("must have restored fields");
@@ -1774,6 +1924,7 @@ mod tests {
             &client,
             &sliding_sync.inner.storage_key,
             &*sliding_sync.inner.lists.read().await,
+            sliding_sync.version(),
         )
         .await?
         .expect("must have restored fields");
@@ -1906,6 +2057,82 @@ mod tests {
         Ok(())
     }
 
+    #[async_test]
+    async fn test_native_version_is_discovered() -> Result<()> {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+
+        // The homeserver doesn't advertise native sliding sync, the proxy is used.
+        let sync = client.sliding_sync("unknown")?.build().await?;
+        assert_eq!(sync.version(), SlidingSyncVersion::Proxy);
+
+        Mock::given(method("GET"))
+            .and(path("/_matrix/client/versions"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "versions": ["v1.1"],
+                "unstable_features": { "org.matrix.simplified_msc3575": true },
+            })))
+            .mount(&server)
+            .await;
+
+        // The feature is cached, another client is needed.
+        let client = logged_in_client(Some(server.uri())).await;
+        let sync = client.sliding_sync("native")?.build().await?;
+        assert_eq!(sync.version(), SlidingSyncVersion::Native);
+
+        // The version can still be forced.
+        let sync = client.sliding_sync("proxy")?.version(SlidingSyncVersion::Proxy).build().await?;
+        assert_eq!(sync.version(), SlidingSyncVersion::Proxy);
+
+        Ok(())
+    }
+
+    #[async_test]
+    async fn test_native_version_falls_back_to_the_proxy() -> Result<()> {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+
+        let sliding_sync = client
+            .sliding_sync("test-slidingsync")?
+            .version(SlidingSyncVersion::Native)
+            .add_list(
+                SlidingSyncList::builder("foo")
+                    .sync_mode(SlidingSyncMode::new_selective().add_range(0..=10)),
+            )
+            .build()
+            .await?;
+
+        Mock::given(method("POST"))
+            .and(path("/_matrix/client/unstable/org.matrix.simplified_msc3575/sync"))
+            .respond_with(ResponseTemplate::new(404).set_body_json(json!({
+                "errcode": "M_UNRECOGNIZED",
+                "error": "Unrecognized request",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        Mock::given(SlidingSyncMatcher)
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "pos": "0",
+                "lists": {},
+                "rooms": {},
+                "extensions": {},
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let stream = sliding_sync.sync();
+        pin_mut!(stream);
+
+        // The sync loop doesn't stop, it continues with the proxy.
+        assert_matches!(stream.next().await, Some(Ok(_)));
+        assert_eq!(sliding_sync.version(), SlidingSyncVersion::Proxy);
+
+        Ok(())
+    }
+
     #[async_test]
     async fn test_limited_flag_computation() {
         let server = MockServer::start().await;
@@ -2251,6 +2478,54 @@ mod tests {
         Ok(())
     }
 
+    #[async_test]
+    async fn test_sliding_sync_isnt_blocked_by_sync_once() -> Result<()> {
+        let server = MockServer::start().await;
+        let client = logged_in_client(Some(server.uri())).await;
+
+        let _sliding_sync_mock_guard = Mock::given(SlidingSyncMatcher)
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "pos": "0",
+                "lists": {},
+                "rooms": {},
+            })))
+            .mount_as_scoped(&server)
+            .await;
+        let _sync_mock_guard = Mock::given(method("GET"))
+            .and(path("/_matrix/client/r0/sync"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({ "next_batch": "batch_1" }))
+                    .set_delay(Duration::from_millis(200)),
+            )
+            .mount_as_scoped(&server)
+            .await;
+
+        let sliding_sync = client.sliding_sync("test")?.build().await?;
+
+        // The sliding sync is done while the slower v2 sync is still running.
+        let done = Mutex::new(Vec::new());
+        let (v2_result, sliding_sync_result) = join(
+            async {
+                let result = client.sync_once(SyncSettings::new()).await;
+                done.lock().unwrap().push("v2");
+                result
+            },
+            async {
+                let result = sliding_sync.sync_once().await;
+                done.lock().unwrap().push("sliding sync");
+                result
+            },
+        )
+        .await;
+
+        assert_eq!(v2_result?.next_batch, "batch_1");
+        sliding_sync_result?;
+        assert_eq!(*done.lock().unwrap(), ["sliding sync", "v2"]);
+
+        Ok(())
+    }
+
     #[cfg(not(target_arch = "wasm32"))] // b/o tokio::time::sleep
     #[async_test]
     async fn test_aborted_request_doesnt_update_future_requests() -> Result<()> {
@@ -2362,4 +2637,73 @@ mod tests {
 
         Ok(())
     }
+
+    #[async_test]
+    async fn test_purge_room_events_removes_cached_events_and_media() -> Result<()> {
+        let (_server, sliding_sync) = new_sliding_sync(vec![]).await?;
+        let client = sliding_sync.inner.client.clone();
+        let room_id = room_id!("!purge:example.org");
+
+        client.base_client().get_or_create_room(room_id, RoomState::Joined);
+        let room = client.get_room(room_id).unwrap();
+
+        let old_event = SyncTimelineEvent::new(
+            Raw::new(&json!({
+                "event_id": "$old",
+                "sender": "@johnmastodon:example.org",
+                "origin_server_ts": 1_000,
+                "type": "m.room.message",
+                "content": {
+                    "body": "cat.png",
+                    "msgtype": "m.image",
+                    "url": "mxc://example.org/cat",
+                },
+            }))
+            .unwrap()
+            .cast(),
+        );
+        let recent_event = SyncTimelineEvent::new(
+            Raw::new(&json!({
+                "event_id": "$recent",
+                "sender": "@johnmastodon:example.org",
+                "origin_server_ts": 3_000,
+                "type": "m.room.message",
+                "content": {
+                    "body": "Hello, world!",
+                    "msgtype": "m.text",
+                },
+            }))
+            .unwrap()
+            .cast(),
+        );
+
+        sliding_sync.inner.rooms.write().await.insert(
+            room_id.to_owned(),
+            SlidingSyncRoom::new(
+                client.clone(),
+                room_id.to_owned(),
+                v4::SlidingSyncRoom::default(),
+                vec![old_event, recent_event],
+            ),
+        );
+
+        let media_request = MediaRequest {
+            source: MediaSource::Plain(mxc_uri!("mxc://example.org/cat").to_owned()),
+            format: MediaFormat::File,
+        };
+        client.store().add_media_content(&media_request, b"cat".to_vec()).await?;
+
+        let purged = room.purge_events_before(MilliSecondsSinceUnixEpoch(uint!(2_000))).await?;
+        assert_eq!(purged, 1);
+
+        // Only the recent event is left in the timeline queue.
+        let timeline_queue = sliding_sync.get_room(room_id).await.unwrap().timeline_queue();
+        assert_eq!(timeline_queue.len(), 1);
+        assert_eq!(timeline_queue[0].event_id().as_deref(), Some(event_id!("$recent")));
+
+        // The media of the old event was removed.
+        assert!(client.store().get_media_content(&media_request).await?.is_none());
+
+        Ok(())
+    }
 }
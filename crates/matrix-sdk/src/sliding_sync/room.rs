@@ -5,16 +5,44 @@ use std::{
 };
 
 use eyeball_im::Vector;
-use matrix_sdk_base::{deserialized_responses::SyncTimelineEvent, latest_event::LatestEvent};
+use matrix_sdk_base::{
+    apply_redaction, deserialized_responses::SyncTimelineEvent, latest_event::LatestEvent,
+};
 use ruma::{
     api::client::sync::sync_events::{v4, UnreadNotificationsCount},
-    events::AnySyncStateEvent,
+    events::{room::redaction::SyncRoomRedactionEvent, AnySyncStateEvent},
     serde::Raw,
-    OwnedMxcUri, OwnedRoomId, RoomId,
+    EventId, MilliSecondsSinceUnixEpoch, OwnedMxcUri, OwnedRoomId, RoomId, RoomVersionId,
 };
 use serde::{Deserialize, Serialize};
 
-use crate::Client;
+use crate::{room::redaction_of, Client};
+
+/// Replace the event of `timeline_queue` that is redacted by `redaction` with
+/// its redacted form, or remove it if it can't be redacted.
+///
+/// Returns whether the event was in the timeline queue.
+fn redact_in_queue(
+    timeline_queue: &mut Vector<SyncTimelineEvent>,
+    redacts: &EventId,
+    redaction: &Raw<SyncRoomRedactionEvent>,
+    room_version: &RoomVersionId,
+) -> bool {
+    let Some(index) =
+        timeline_queue.iter().position(|event| event.event_id().as_deref() == Some(redacts))
+    else {
+        return false;
+    };
+
+    match apply_redaction(&timeline_queue[index].event, redaction, room_version) {
+        Some(redacted) => timeline_queue[index].event = redacted,
+        None => {
+            timeline_queue.remove(index);
+        }
+    }
+
+    true
+}
 
 /// The state of a [`SlidingSyncRoom`].
 #[derive(Copy, Clone, Debug, Default, PartialEq)]
@@ -191,11 +219,29 @@ impl SlidingSyncRoom {
             }
         }
 
+        // The redactions of the events received before must be applied to
+        // them, the ones of the events received with them are applied by the
+        // consumers of the timeline queue.
+        let room_version = self
+            .inner
+            .client
+            .get_room(&self.inner.room_id)
+            .and_then(|room| room.clone_info().room_version().cloned())
+            .unwrap_or(RoomVersionId::V1);
+        let redactions: Vec<_> = timeline_updates
+            .iter()
+            .filter_map(|event| redaction_of(&event.event, &room_version))
+            .collect();
+
         let mut state = self.inner.state.write().unwrap();
 
         {
             let mut timeline_queue = self.inner.timeline_queue.write().unwrap();
 
+            for (redacts, redaction) in &redactions {
+                redact_in_queue(&mut timeline_queue, redacts, redaction, &room_version);
+            }
+
             // There is timeline updates.
             if !timeline_updates.is_empty() {
                 if let SlidingSyncRoomState::Preloaded = *state {
@@ -227,6 +273,43 @@ impl SlidingSyncRoom {
         *state = SlidingSyncRoomState::Loaded;
     }
 
+    /// Remove the events sent before `cutoff` from the timeline queue.
+    ///
+    /// Returns the removed events.
+    pub(super) fn purge_events_before(
+        &self,
+        cutoff: MilliSecondsSinceUnixEpoch,
+    ) -> Vec<SyncTimelineEvent> {
+        let mut timeline_queue = self.inner.timeline_queue.write().unwrap();
+
+        let (expired, kept): (Vector<_>, Vector<_>) =
+            timeline_queue.iter().cloned().partition(|event| {
+                event
+                    .event
+                    .get_field::<MilliSecondsSinceUnixEpoch>("origin_server_ts")
+                    .ok()
+                    .flatten()
+                    .is_some_and(|ts| ts < cutoff)
+            });
+        *timeline_queue = kept;
+
+        expired.into_iter().collect()
+    }
+
+    /// Replace the event of the timeline queue that is redacted by
+    /// `redaction` with its redacted form.
+    ///
+    /// Returns whether the event was in the timeline queue.
+    pub(super) fn redact_event(
+        &self,
+        redacts: &EventId,
+        redaction: &Raw<SyncRoomRedactionEvent>,
+        room_version: &RoomVersionId,
+    ) -> bool {
+        let mut timeline_queue = self.inner.timeline_queue.write().unwrap();
+        redact_in_queue(&mut timeline_queue, redacts, redaction, room_version)
+    }
+
     pub(super) fn from_frozen(frozen_room: FrozenSlidingSyncRoom, client: Client) -> Self {
         let FrozenSlidingSyncRoom { room_id, inner, timeline_queue } = frozen_room;
 
@@ -330,8 +413,9 @@ mod tests {
     use matrix_sdk_common::deserialized_responses::SyncTimelineEvent;
     use matrix_sdk_test::async_test;
     use ruma::{
-        api::client::sync::sync_events::v4, assign, events::room::message::RoomMessageEventContent,
-        mxc_uri, room_id, serde::Raw, uint, JsOption, RoomId,
+        api::client::sync::sync_events::v4, assign, event_id,
+        events::room::message::RoomMessageEventContent, mxc_uri, room_id, serde::Raw, uint,
+        JsOption, RoomId, RoomVersionId,
     };
     use serde_json::json;
     use wiremock::MockServer;
@@ -916,6 +1000,67 @@ mod tests {
         }
     }
 
+    #[async_test]
+    async fn test_timeline_queue_update_redacts_queued_events() {
+        let mut room = new_room_with_timeline(
+            room_id!("!foo:bar.org"),
+            room_response!({}),
+            vec![
+                timeline_event!(from "@alice:baz.org" with id "$x0:baz.org" at 0: "message 0"),
+                timeline_event!(from "@alice:baz.org" with id "$x1:baz.org" at 1: "message 1"),
+            ],
+        )
+        .await;
+
+        let redaction: SyncTimelineEvent = TimelineEvent::new(
+            Raw::new(&json!({
+                "content": { "redacts": "$x0:baz.org" },
+                "redacts": "$x0:baz.org",
+                "type": "m.room.redaction",
+                "event_id": "$x2:baz.org",
+                "room_id": "!foo:bar.org",
+                "origin_server_ts": 2,
+                "sender": "@alice:baz.org",
+            }))
+            .unwrap()
+            .cast(),
+        )
+        .into();
+        room.update(room_response!({}), vec![redaction]);
+
+        // The redacted event is replaced by its redacted form, the redaction is
+        // appended.
+        let timeline_queue = room.timeline_queue();
+        assert_eq!(timeline_queue.len(), 3);
+        assert_timeline_queue_event_ids!(
+            with timeline_queue {
+                0 => "$x0:baz.org",
+                1 => "$x1:baz.org",
+                2 => "$x2:baz.org",
+            }
+        );
+        let content = |event: &SyncTimelineEvent| {
+            event.event.get_field::<serde_json::Value>("content").unwrap().unwrap()
+        };
+        assert_eq!(content(&timeline_queue[0]), json!({}));
+        assert_eq!(content(&timeline_queue[1])["body"], "message 1");
+
+        // A redaction sent by this client is applied right away.
+        let redaction = Raw::new(&json!({
+            "content": { "redacts": "$x1:baz.org" },
+            "redacts": "$x1:baz.org",
+            "type": "m.room.redaction",
+            "event_id": "$x3:baz.org",
+            "origin_server_ts": 3,
+            "sender": "@alice:baz.org",
+        }))
+        .unwrap()
+        .cast();
+        assert!(room.redact_event(event_id!("$x1:baz.org"), &redaction, &RoomVersionId::V1));
+        assert_eq!(content(&room.timeline_queue()[1]), json!({}));
+        assert!(!room.redact_event(event_id!("$unknown:baz.org"), &redaction, &RoomVersionId::V1));
+    }
+
     #[test]
     fn test_frozen_sliding_sync_room_serialization() {
         let frozen_room = FrozenSlidingSyncRoom {
@@ -0,0 +1,120 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The versions of sliding sync a [`SlidingSync`](super::SlidingSync) instance
+//! can talk.
+
+use bytes::BufMut;
+use ruma::api::{
+    client::{error::ErrorKind, sync::sync_events::v4},
+    metadata, MatrixVersion, Metadata, OutgoingRequest, SendAccessToken,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::Client;
+
+/// The unstable feature advertised by homeservers that implement sliding sync
+/// natively, as defined in [MSC4186].
+///
+/// [MSC4186]: https://github.com/matrix-org/matrix-spec-proposals/pull/4186
+const NATIVE_SLIDING_SYNC_FEATURE: &str = "org.matrix.simplified_msc3575";
+
+/// The path of the sliding sync endpoint of the proxy.
+const PROXY_PATH: &str = "/_matrix/client/unstable/org.matrix.msc3575/sync";
+
+/// The path of the native sliding sync endpoint of the homeserver.
+const NATIVE_PATH: &str = "/_matrix/client/unstable/org.matrix.simplified_msc3575/sync";
+
+/// The version of sliding sync used by a [`SlidingSync`](super::SlidingSync)
+/// instance.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlidingSyncVersion {
+    /// The sliding sync proxy, as defined in [MSC3575].
+    ///
+    /// [MSC3575]: https://github.com/matrix-org/matrix-spec-proposals/pull/3575
+    #[default]
+    Proxy,
+
+    /// Sliding sync implemented natively by the homeserver, also known as
+    /// Simplified Sliding Sync, as defined in [MSC4186].
+    ///
+    /// [MSC4186]: https://github.com/matrix-org/matrix-spec-proposals/pull/4186
+    Native,
+}
+
+impl SlidingSyncVersion {
+    /// Find the best version supported by the homeserver.
+    ///
+    /// This is [`SlidingSyncVersion::Native`] if the homeserver advertises
+    /// it, and [`SlidingSyncVersion::Proxy`] otherwise, including when the
+    /// homeserver can't be reached.
+    pub async fn discover(client: &Client) -> Self {
+        match client.unstable_features().await {
+            Ok(features) if features.get(NATIVE_SLIDING_SYNC_FEATURE) == Some(&true) => {
+                Self::Native
+            }
+            _ => Self::Proxy,
+        }
+    }
+}
+
+/// Whether the error means that the homeserver doesn't know about the native
+/// sliding sync endpoint.
+pub(super) fn is_unsupported_endpoint_error(error: &crate::Error) -> bool {
+    error.client_api_error_kind() == Some(&ErrorKind::Unrecognized)
+        || error
+            .as_client_api_error()
+            .is_some_and(|error| error.status_code == http::StatusCode::NOT_FOUND)
+}
+
+/// A sliding sync request sent to the native endpoint of the homeserver.
+///
+/// The body of the request and of the response are the same as with the
+/// proxy, only the path differs.
+#[derive(Clone, Debug)]
+pub(super) struct NativeRequest(pub v4::Request);
+
+impl OutgoingRequest for NativeRequest {
+    type EndpointError = <v4::Request as OutgoingRequest>::EndpointError;
+    type IncomingResponse = v4::Response;
+
+    const METADATA: Metadata = metadata! {
+        method: POST,
+        rate_limited: false,
+        authentication: AccessToken,
+        history: {
+            unstable => "/_matrix/client/unstable/org.matrix.simplified_msc3575/sync",
+        }
+    };
+
+    fn try_into_http_request<T: Default + BufMut>(
+        self,
+        base_url: &str,
+        access_token: SendAccessToken<'_>,
+        considering_versions: &[MatrixVersion],
+    ) -> Result<http::Request<T>, ruma::api::error::IntoHttpError> {
+        let request = self.0.try_into_http_request(base_url, access_token, considering_versions)?;
+        let (mut parts, body) = request.into_parts();
+
+        parts.uri = parts
+            .uri
+            .to_string()
+            .replacen(PROXY_PATH, NATIVE_PATH, 1)
+            .parse()
+            .expect("replacing a valid path by another valid path keeps a valid URI");
+
+        Ok(http::Request::from_parts(parts, body))
+    }
+}
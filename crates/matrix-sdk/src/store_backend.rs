@@ -0,0 +1,184 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Store backends provided by other crates.
+//!
+//! The SQLite and IndexedDB stores are built into the [`ClientBuilder`], other
+//! crates can provide their own stores, for example backed by a database
+//! server for bots, by implementing [`StoreBackend`]. A backend is either
+//! given directly to [`ClientBuilder::store_backend()`], or registered under
+//! its name in a [`StoreBackendRegistry`] so it can be selected by name, for
+//! example from a configuration file, with
+//! [`ClientBuilder::store_backend_by_name()`].
+//!
+//! A backend opens the state store and, when `e2e-encryption` is enabled, the
+//! crypto store, which must implement the [`StateStore`] and [`CryptoStore`]
+//! traits.
+//!
+//! [`ClientBuilder`]: crate::ClientBuilder
+//! [`ClientBuilder::store_backend()`]: crate::ClientBuilder::store_backend
+//! [`ClientBuilder::store_backend_by_name()`]: crate::ClientBuilder::store_backend_by_name
+//! [`StateStore`]: matrix_sdk_base::store::StateStore
+//! [`CryptoStore`]: matrix_sdk_base::crypto::store::CryptoStore
+
+use std::{collections::BTreeMap, fmt, sync::Arc};
+
+use matrix_sdk_base::{
+    store::{StoreConfig, StoreError},
+    AsyncTraitDeps,
+};
+
+/// The options passed to [`StoreBackend::open()`].
+#[derive(Clone)]
+#[non_exhaustive]
+pub struct StoreBackendOptions {
+    /// Where the stores are, whose meaning depends on the backend, like a path
+    /// or the URL of a database server.
+    pub location: String,
+
+    /// The passphrase used to encrypt the stores, if any.
+    pub passphrase: Option<String>,
+}
+
+impl StoreBackendOptions {
+    /// Create options for the stores at the given location, without
+    /// passphrase.
+    pub fn new(location: impl Into<String>) -> Self {
+        Self { location: location.into(), passphrase: None }
+    }
+
+    /// Set the passphrase used to encrypt the stores.
+    pub fn passphrase(mut self, passphrase: impl Into<String>) -> Self {
+        self.passphrase = Some(passphrase.into());
+        self
+    }
+}
+
+#[cfg(not(tarpaulin_include))]
+impl fmt::Debug for StoreBackendOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StoreBackendOptions")
+            .field("location", &self.location)
+            .finish_non_exhaustive()
+    }
+}
+
+/// An implementation of the stores of the client, provided by another crate.
+#[cfg_attr(target_arch = "wasm32", async_trait::async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
+pub trait StoreBackend: AsyncTraitDeps {
+    /// The name of the backend in a [`StoreBackendRegistry`], like
+    /// `"postgres"`.
+    fn name(&self) -> &str;
+
+    /// Open the stores with the given options.
+    ///
+    /// This is called once, when the client is built.
+    async fn open(&self, options: &StoreBackendOptions) -> Result<StoreConfig, StoreError>;
+}
+
+/// A list of [`StoreBackend`]s, indexed by their name.
+#[derive(Clone, Default)]
+pub struct StoreBackendRegistry {
+    backends: BTreeMap<String, Arc<dyn StoreBackend>>,
+}
+
+impl StoreBackendRegistry {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a backend under its [name](StoreBackend::name).
+    ///
+    /// Returns the backend that was registered under the same name, if any.
+    pub fn register(&mut self, backend: Arc<dyn StoreBackend>) -> Option<Arc<dyn StoreBackend>> {
+        self.backends.insert(backend.name().to_owned(), backend)
+    }
+
+    /// Get the backend registered under the given name.
+    pub fn get(&self, name: &str) -> Option<Arc<dyn StoreBackend>> {
+        self.backends.get(name).cloned()
+    }
+
+    /// The names of the registered backends.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.backends.keys().map(String::as_str)
+    }
+}
+
+#[cfg(not(tarpaulin_include))]
+impl fmt::Debug for StoreBackendRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use assert_matches2::assert_let;
+    use matrix_sdk_base::store::{MemoryStore, StoreConfig, StoreError};
+    use matrix_sdk_test::async_test;
+
+    use super::{StoreBackend, StoreBackendOptions, StoreBackendRegistry};
+    use crate::{Client, ClientBuildError};
+
+    #[derive(Default)]
+    struct MemoryBackend {
+        opened: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl StoreBackend for MemoryBackend {
+        fn name(&self) -> &str {
+            "memory"
+        }
+
+        async fn open(&self, options: &StoreBackendOptions) -> Result<StoreConfig, StoreError> {
+            assert_eq!(options.location, "bot");
+            self.opened.fetch_add(1, Ordering::SeqCst);
+            Ok(StoreConfig::new().state_store(MemoryStore::new()))
+        }
+    }
+
+    #[async_test]
+    async fn test_store_backend_by_name() {
+        let backend = Arc::new(MemoryBackend::default());
+        let mut registry = StoreBackendRegistry::new();
+        assert!(registry.register(backend.clone()).is_none());
+        assert_eq!(registry.names().collect::<Vec<_>>(), ["memory"]);
+
+        Client::builder()
+            .homeserver_url("http://localhost")
+            .store_backend_by_name(&registry, "memory", StoreBackendOptions::new("bot"))
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(backend.opened.load(Ordering::SeqCst), 1);
+
+        let error = Client::builder()
+            .homeserver_url("http://localhost")
+            .store_backend_by_name(&registry, "postgres", StoreBackendOptions::new("bot"))
+            .build()
+            .await
+            .unwrap_err();
+        assert_let!(ClientBuildError::UnknownStoreBackend(name) = error);
+        assert_eq!(name, "postgres");
+    }
+}
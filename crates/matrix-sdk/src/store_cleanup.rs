@@ -0,0 +1,651 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Automatic cleanup of the local stores.
+//!
+//! A client that runs for a long time, like a bot, accumulates events, media
+//! and the data of rooms it left in its stores, which then grow without bound.
+//! The [`StoreCleanup`] manager prunes this data according to a
+//! [`CleanupPolicy`], either on demand with [`StoreCleanup::run()`] or
+//! periodically in the background with [`StoreCleanup::start()`].
+//!
+//! The state store doesn't know when media was cached nor how large it is, so
+//! the client keeps an index of the media it caches from the moment this
+//! module exists. Media cached before that isn't subject to the quotas. The
+//! index is kept in memory, and only written to the state store by the cleanup,
+//! at most once every minute when media is cached, when
+//! [`StoreCleanup::flush()`] is called and when the client is dropped.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Mutex as StdMutex, Weak},
+    time::Duration,
+};
+
+use eyeball::{SharedObservable, Subscriber};
+use matrix_sdk_base::{
+    media::{MediaRequest, UniqueKey},
+    BaseClient, RoomState,
+};
+use ruma::{MilliSecondsSinceUnixEpoch, MxcUri, OwnedMxcUri, OwnedRoomId, RoomId, UInt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{debug, info, instrument, trace, warn};
+
+use crate::{
+    client::ClientInner,
+    executor::{spawn, JoinHandle},
+    Client, Result,
+};
+
+/// The key of the cleanup index in the custom values of the state store.
+const CLEANUP_INDEX_KEY: &[u8] = b"matrix-sdk.store-cleanup.index";
+
+/// The default period of the background cleanup.
+const DEFAULT_CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// The minimum time between two writes of the cleanup index to the state
+/// store, when media is cached or removed.
+const INDEX_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What the cleanup does with the data of a specific room, instead of the
+/// defaults of the [`CleanupPolicy`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoomCleanupOverride {
+    /// Never remove anything from this room.
+    Pinned,
+
+    /// Keep the events of this room for this long, instead of what
+    /// [`Room::max_event_lifetime()`] allows.
+    ///
+    /// [`Room::max_event_lifetime()`]: crate::Room::max_event_lifetime
+    MaxEventAge(Duration),
+}
+
+/// Which data the cleanup removes from the local stores.
+#[derive(Clone, Debug)]
+pub struct CleanupPolicy {
+    /// How often the cleanup runs in the background.
+    ///
+    /// Defaults to one hour.
+    pub interval: Duration,
+
+    /// The time after which cached media is removed, counted from the last
+    /// time it was cached.
+    pub media_max_age: Option<Duration>,
+
+    /// The maximum size, in bytes, of the cached media. When it is exceeded,
+    /// the media that was cached the longest ago is removed first.
+    pub media_max_size: Option<u64>,
+
+    /// The time after which the data of a room we left is removed, including
+    /// its state and read receipts, counted from the first cleanup that saw
+    /// the room as left.
+    pub left_room_max_age: Option<Duration>,
+
+    /// The time after which the read receipts of the rooms we are still in
+    /// are removed.
+    ///
+    /// The receipts older than the events that are pruned are removed with
+    /// them anyway, this also prunes the receipts of the rooms whose events
+    /// are kept.
+    pub receipt_max_age: Option<Duration>,
+
+    /// Per-room exceptions to this policy.
+    ///
+    /// The events of the rooms that aren't listed here are pruned according
+    /// to [`Room::max_event_lifetime()`].
+    ///
+    /// [`Room::max_event_lifetime()`]: crate::Room::max_event_lifetime
+    pub room_overrides: BTreeMap<OwnedRoomId, RoomCleanupOverride>,
+}
+
+impl Default for CleanupPolicy {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_CLEANUP_INTERVAL,
+            media_max_age: None,
+            media_max_size: None,
+            left_room_max_age: None,
+            receipt_max_age: None,
+            room_overrides: BTreeMap::new(),
+        }
+    }
+}
+
+impl CleanupPolicy {
+    fn is_pinned(&self, room_id: &RoomId) -> bool {
+        self.room_overrides.get(room_id) == Some(&RoomCleanupOverride::Pinned)
+    }
+}
+
+/// What a run of the cleanup removed from the local stores.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CleanupReport {
+    /// The number of events that were removed from the local stores.
+    pub removed_events: usize,
+
+    /// The number of media files that were removed from the cache.
+    pub removed_media: usize,
+
+    /// The size, in bytes, of the media that was removed from the cache.
+    pub reclaimed_media_bytes: u64,
+
+    /// The rooms we left whose data was removed.
+    pub removed_rooms: Vec<OwnedRoomId>,
+}
+
+/// The state of the store cleanup, shared by all the handles of a client.
+pub(crate) struct StoreCleanupState {
+    task: StdMutex<Option<StoreCleanupTask>>,
+    /// The cleanup index, loaded from the state store the first time it is
+    /// needed.
+    index: Mutex<Option<LoadedIndex>>,
+    last_report: SharedObservable<Option<CleanupReport>>,
+    /// The base client, to write the index that wasn't saved yet when the
+    /// client is dropped.
+    base_client: BaseClient,
+}
+
+impl StoreCleanupState {
+    pub(crate) fn new(base_client: BaseClient) -> Self {
+        Self {
+            task: Default::default(),
+            index: Default::default(),
+            last_report: Default::default(),
+            base_client,
+        }
+    }
+}
+
+impl Drop for StoreCleanupState {
+    fn drop(&mut self) {
+        let Some(loaded) = self.index.get_mut().take() else { return };
+
+        if !loaded.is_dirty {
+            return;
+        }
+
+        let value = match serde_json::to_vec(&loaded.index) {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("Couldn't serialize the cleanup index: {e}");
+                return;
+            }
+        };
+
+        let base_client = self.base_client.clone();
+        let save = async move {
+            if let Err(e) = base_client.store().set_custom_value(CLEANUP_INDEX_KEY, value).await {
+                warn!("Couldn't save the cleanup index: {e}");
+            }
+        };
+
+        // The client can be dropped outside of a runtime, in which case the
+        // changes since the last save are lost.
+        #[cfg(not(target_arch = "wasm32"))]
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(save);
+            }
+            Err(_) => warn!("No runtime to save the cleanup index, the latest changes are lost"),
+        }
+
+        #[cfg(target_arch = "wasm32")]
+        spawn(save);
+    }
+}
+
+/// The cleanup index, and whether it was written to the state store.
+struct LoadedIndex {
+    index: CleanupIndex,
+    /// Whether the index changed since it was last written.
+    is_dirty: bool,
+    /// When the index was last written.
+    saved_at: Option<MilliSecondsSinceUnixEpoch>,
+}
+
+/// A media file of the cleanup index, in all the formats it was cached in.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct CachedMedia {
+    /// The last time a format of this media was cached.
+    last_added_at: MilliSecondsSinceUnixEpoch,
+    /// The size of every cached format, by their unique key.
+    sizes: BTreeMap<String, u64>,
+}
+
+impl CachedMedia {
+    fn size(&self) -> u64 {
+        self.sizes.values().sum()
+    }
+}
+
+/// What the cleanup knows about the local stores, in addition to what the
+/// stores know themselves.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct CleanupIndex {
+    media: BTreeMap<OwnedMxcUri, CachedMedia>,
+    /// The rooms we left, with the time the cleanup first saw them as left.
+    left_rooms: BTreeMap<OwnedRoomId, MilliSecondsSinceUnixEpoch>,
+}
+
+/// The manager of the automatic cleanup of the local stores.
+///
+/// Get access to this manager with [`Client::store_cleanup()`].
+#[derive(Debug)]
+pub struct StoreCleanup {
+    client: Client,
+}
+
+impl StoreCleanup {
+    pub(crate) fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    /// Remove the data that the given policy doesn't allow to keep anymore
+    /// from the local stores.
+    ///
+    /// This is done periodically in the background after
+    /// [`StoreCleanup::start()`] is called.
+    #[instrument(skip_all)]
+    pub async fn run(&self, policy: &CleanupPolicy) -> Result<CleanupReport> {
+        let mut report = CleanupReport::default();
+
+        for room in self.client.rooms() {
+            let room_id = room.room_id().to_owned();
+
+            report.removed_events += match policy.room_overrides.get(&room_id) {
+                Some(RoomCleanupOverride::Pinned) => continue,
+                Some(RoomCleanupOverride::MaxEventAge(max_age)) => {
+                    room.purge_events_before(age_cutoff(self.now(), *max_age)).await?
+                }
+                None => room.purge_expired_events().await?,
+            };
+
+            if let Some(max_age) = policy.receipt_max_age {
+                if room.state() == RoomState::Joined {
+                    self.client
+                        .store()
+                        .remove_room_receipts_before(&room_id, age_cutoff(self.now(), max_age))
+                        .await?;
+                }
+            }
+        }
+
+        let mut guard = self.client.inner.store_cleanup.index.lock().await;
+        let loaded = match guard.take() {
+            Some(loaded) => loaded,
+            None => self.load_index().await?,
+        };
+        let loaded = guard.insert(loaded);
+        let index = &mut loaded.index;
+        let now = self.now();
+
+        for uri in media_to_remove(&index.media, now, policy) {
+            self.client.store().remove_media_content_for_uri(&uri).await?;
+
+            if let Some(media) = index.media.remove(&uri) {
+                report.removed_media += 1;
+                report.reclaimed_media_bytes += media.size();
+            }
+        }
+
+        // Only keep the rooms that are still left in the index, they might have
+        // been joined again or forgotten in the meantime.
+        let left_rooms: BTreeSet<_> = self
+            .client
+            .rooms()
+            .into_iter()
+            .filter(|room| room.state() == RoomState::Left)
+            .map(|room| room.room_id().to_owned())
+            .collect();
+        index.left_rooms.retain(|room_id, _| left_rooms.contains(room_id));
+
+        for room_id in left_rooms {
+            let left_at = *index.left_rooms.entry(room_id.clone()).or_insert(now);
+
+            let Some(max_age) = policy.left_room_max_age else { continue };
+
+            if policy.is_pinned(&room_id) || left_at > age_cutoff(now, max_age) {
+                continue;
+            }
+
+            self.client.base_client().forget_room(&room_id).await?;
+            index.left_rooms.remove(&room_id);
+            report.removed_rooms.push(room_id);
+        }
+
+        loaded.is_dirty = true;
+        self.save_index(loaded).await?;
+
+        info!(
+            removed_events = report.removed_events,
+            removed_media = report.removed_media,
+            reclaimed_media_bytes = report.reclaimed_media_bytes,
+            removed_rooms = report.removed_rooms.len(),
+            "Cleaned up the local stores"
+        );
+
+        Ok(report)
+    }
+
+    /// Run the cleanup right away, and then every [`CleanupPolicy::interval`],
+    /// until [`StoreCleanup::stop()`] is called.
+    ///
+    /// The outcome of the cleanups can be followed with
+    /// [`StoreCleanup::last_report()`].
+    ///
+    /// Calling this again replaces the previous cleanup task.
+    pub fn start(&self, policy: CleanupPolicy) {
+        let task = StoreCleanupTask::new(Arc::downgrade(&self.client.inner), policy);
+        *self.client.inner.store_cleanup.task.lock().unwrap() = Some(task);
+    }
+
+    /// Stop the periodic cleanup of the local stores.
+    pub fn stop(&self) {
+        *self.client.inner.store_cleanup.task.lock().unwrap() = None;
+    }
+
+    /// Get a subscriber to the report of the last background cleanup that
+    /// succeeded.
+    pub fn last_report(&self) -> Subscriber<Option<CleanupReport>> {
+        self.client.inner.store_cleanup.last_report.subscribe()
+    }
+
+    /// Write the changes of the cleanup index to the state store right away.
+    ///
+    /// The index is written at most once every minute when media is cached,
+    /// call this before closing the client to not lose the latest changes.
+    /// They are also written when the client is dropped, but without a way to
+    /// wait for it.
+    pub async fn flush(&self) -> Result<()> {
+        let mut guard = self.client.inner.store_cleanup.index.lock().await;
+
+        match guard.as_mut() {
+            Some(loaded) => self.save_index(loaded).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Record that a media file was added to the cache.
+    pub(crate) async fn on_media_added(&self, request: &MediaRequest, size: usize) {
+        let now = self.now();
+
+        let result = self
+            .update_index(|index| {
+                let media = index
+                    .media
+                    .entry(request.uri().to_owned())
+                    .or_insert_with(|| CachedMedia { last_added_at: now, sizes: BTreeMap::new() });
+
+                media.last_added_at = now;
+                media.sizes.insert(request.format.unique_key(), size as u64);
+            })
+            .await;
+
+        if let Err(e) = result {
+            warn!("Couldn't record the cached media in the cleanup index: {e}");
+        }
+    }
+
+    /// Record that a media file was removed from the cache.
+    pub(crate) async fn on_media_removed(&self, request: &MediaRequest) {
+        let result = self
+            .update_index(|index| {
+                let uri = request.uri();
+
+                if let Some(media) = index.media.get_mut(uri) {
+                    media.sizes.remove(&request.format.unique_key());
+
+                    if media.sizes.is_empty() {
+                        index.media.remove(uri);
+                    }
+                }
+            })
+            .await;
+
+        if let Err(e) = result {
+            warn!("Couldn't remove the media from the cleanup index: {e}");
+        }
+    }
+
+    /// Record that all the formats of a media file were removed from the
+    /// cache.
+    pub(crate) async fn on_media_removed_for_uri(&self, uri: &MxcUri) {
+        let result = self
+            .update_index(|index| {
+                index.media.remove(uri);
+            })
+            .await;
+
+        if let Err(e) = result {
+            warn!("Couldn't remove the media from the cleanup index: {e}");
+        }
+    }
+
+    /// Run the cleanup, and report its outcome to the subscribers of the last
+    /// report.
+    async fn run_and_report(&self, policy: &CleanupPolicy) {
+        match self.run(policy).await {
+            Ok(report) => self.client.inner.store_cleanup.last_report.set(Some(report)),
+            Err(e) => warn!("Couldn't clean up the local stores: {e}"),
+        }
+    }
+
+    /// Update the cleanup index, and write it to the state store if it wasn't
+    /// for a while.
+    async fn update_index(&self, f: impl FnOnce(&mut CleanupIndex)) -> Result<()> {
+        let mut guard = self.client.inner.store_cleanup.index.lock().await;
+        let loaded = match guard.take() {
+            Some(loaded) => loaded,
+            None => self.load_index().await?,
+        };
+        let loaded = guard.insert(loaded);
+
+        f(&mut loaded.index);
+        loaded.is_dirty = true;
+
+        let save_cutoff = age_cutoff(self.now(), INDEX_SAVE_INTERVAL);
+        if loaded.saved_at.map_or(true, |saved_at| saved_at <= save_cutoff) {
+            self.save_index(loaded).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn load_index(&self) -> Result<LoadedIndex> {
+        let index = match self.client.store().get_custom_value(CLEANUP_INDEX_KEY).await? {
+            Some(value) => serde_json::from_slice(&value).unwrap_or_else(|e| {
+                // Losing the index only means that the media cached so far
+                // isn't subject to the quotas anymore.
+                debug!("Couldn't deserialize the cleanup index, starting over: {e}");
+                CleanupIndex::default()
+            }),
+            None => CleanupIndex::default(),
+        };
+
+        Ok(LoadedIndex { index, is_dirty: false, saved_at: None })
+    }
+
+    /// Write the cleanup index to the state store, if it changed.
+    async fn save_index(&self, loaded: &mut LoadedIndex) -> Result<()> {
+        if !loaded.is_dirty {
+            return Ok(());
+        }
+
+        let value = serde_json::to_vec(&loaded.index)?;
+        self.client.store().set_custom_value(CLEANUP_INDEX_KEY, value).await?;
+
+        loaded.is_dirty = false;
+        loaded.saved_at = Some(self.now());
+
+        Ok(())
+    }
+
+    fn now(&self) -> MilliSecondsSinceUnixEpoch {
+        self.client.base_client().clock().now_ms()
+    }
+}
+
+impl Client {
+    /// Get the manager of the automatic cleanup of the local stores.
+    pub fn store_cleanup(&self) -> StoreCleanup {
+        StoreCleanup::new(self.clone())
+    }
+}
+
+/// The task cleaning up the local stores periodically.
+struct StoreCleanupTask {
+    #[allow(dead_code)]
+    join_handle: JoinHandle<()>,
+}
+
+impl Drop for StoreCleanupTask {
+    fn drop(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        self.join_handle.abort();
+    }
+}
+
+impl StoreCleanupTask {
+    fn new(client: Weak<ClientInner>, policy: CleanupPolicy) -> Self {
+        let join_handle = spawn(async move {
+            Self::run(client, policy).await;
+        });
+
+        Self { join_handle }
+    }
+
+    async fn run(client: Weak<ClientInner>, policy: CleanupPolicy) {
+        loop {
+            let Some(client) = client.upgrade() else {
+                trace!("Client got dropped, shutting down the task");
+                break;
+            };
+
+            let client = Client { inner: client };
+            client.store_cleanup().run_and_report(&policy).await;
+
+            // Don't keep the client alive while sleeping.
+            let clock = client.base_client().clock().clone();
+            drop(client);
+
+            clock.sleep(policy.interval).await;
+        }
+    }
+}
+
+/// The timestamp before which data is older than `max_age`.
+fn age_cutoff(now: MilliSecondsSinceUnixEpoch, max_age: Duration) -> MilliSecondsSinceUnixEpoch {
+    let max_age = u64::try_from(max_age.as_millis()).unwrap_or(u64::MAX);
+    MilliSecondsSinceUnixEpoch(UInt::new_saturating(u64::from(now.0).saturating_sub(max_age)))
+}
+
+/// The media that must be removed from the cache to respect the quotas of
+/// the policy, the oldest first.
+fn media_to_remove(
+    media: &BTreeMap<OwnedMxcUri, CachedMedia>,
+    now: MilliSecondsSinceUnixEpoch,
+    policy: &CleanupPolicy,
+) -> Vec<OwnedMxcUri> {
+    let mut by_age: Vec<_> = media.iter().collect();
+    by_age.sort_by_key(|(_, media)| media.last_added_at);
+
+    let mut total_size: u64 = by_age.iter().map(|(_, media)| media.size()).sum();
+    let cutoff = policy.media_max_age.map(|max_age| age_cutoff(now, max_age));
+
+    let mut to_remove = Vec::new();
+
+    for (uri, media) in by_age {
+        let too_old = cutoff.is_some_and(|cutoff| media.last_added_at < cutoff);
+        let too_large = policy.media_max_size.is_some_and(|max_size| total_size > max_size);
+
+        if !too_old && !too_large {
+            // The media is sorted by age, so the rest of it is kept too.
+            break;
+        }
+
+        total_size -= media.size();
+        to_remove.push(uri.clone());
+    }
+
+    to_remove
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, time::Duration};
+
+    use ruma::{mxc_uri, uint, MilliSecondsSinceUnixEpoch, OwnedMxcUri};
+
+    use super::{media_to_remove, CachedMedia, CleanupPolicy};
+
+    fn media(entries: &[(&str, u64, u64)]) -> BTreeMap<OwnedMxcUri, CachedMedia> {
+        entries
+            .iter()
+            .map(|(uri, added_at, size)| {
+                let media = CachedMedia {
+                    last_added_at: MilliSecondsSinceUnixEpoch((*added_at).try_into().unwrap()),
+                    sizes: BTreeMap::from([("file".to_owned(), *size)]),
+                };
+                (OwnedMxcUri::from(*uri), media)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_old_media_is_removed() {
+        let media =
+            media(&[("mxc://localhost/old", 1_000, 10), ("mxc://localhost/recent", 9_000, 10)]);
+        let policy =
+            CleanupPolicy { media_max_age: Some(Duration::from_secs(5)), ..Default::default() };
+
+        let to_remove = media_to_remove(&media, MilliSecondsSinceUnixEpoch(uint!(10_000)), &policy);
+
+        assert_eq!(to_remove, vec![mxc_uri!("mxc://localhost/old").to_owned()]);
+    }
+
+    #[test]
+    fn test_oldest_media_is_removed_above_max_size() {
+        let media = media(&[
+            ("mxc://localhost/a", 3_000, 40),
+            ("mxc://localhost/b", 1_000, 40),
+            ("mxc://localhost/c", 2_000, 40),
+        ]);
+        let policy = CleanupPolicy { media_max_size: Some(50), ..Default::default() };
+
+        let to_remove = media_to_remove(&media, MilliSecondsSinceUnixEpoch(uint!(10_000)), &policy);
+
+        assert_eq!(
+            to_remove,
+            vec![
+                mxc_uri!("mxc://localhost/b").to_owned(),
+                mxc_uri!("mxc://localhost/c").to_owned()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nothing_is_removed_without_quotas() {
+        let media = media(&[("mxc://localhost/a", 0, u64::MAX / 2)]);
+
+        let to_remove = media_to_remove(
+            &media,
+            MilliSecondsSinceUnixEpoch(uint!(10_000)),
+            &CleanupPolicy::default(),
+        );
+
+        assert!(to_remove.is_empty());
+    }
+}
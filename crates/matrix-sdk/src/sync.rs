@@ -38,7 +38,11 @@ use ruma::{
 };
 use tracing::{debug, error, warn};
 
-use crate::{event_handler::HandlerKind, Client, Result, Room};
+use crate::{event_handler::HandlerKind, Client, Error, Result, Room};
+
+/// The key of the generation of the sync token in the custom values of the
+/// state store.
+const SYNC_TOKEN_GENERATION_KEY: &[u8] = b"sync_token_generation";
 
 /// The processed response of a `/sync` request.
 #[derive(Clone, Default)]
@@ -58,6 +62,12 @@ pub struct SyncResponse {
     pub ambiguity_changes: AmbiguityChanges,
     /// New notifications per room.
     pub notifications: BTreeMap<OwnedRoomId, Vec<Notification>>,
+    /// Whether no request was sent to the homeserver, because another process
+    /// holds the cross-process sync lock.
+    ///
+    /// The response is empty then, what the other process synced was reloaded
+    /// from the store.
+    pub is_passive: bool,
 }
 
 impl SyncResponse {
@@ -79,6 +89,7 @@ impl SyncResponse {
             to_device,
             ambiguity_changes,
             notifications,
+            is_passive: false,
         }
     }
 }
@@ -93,6 +104,7 @@ impl fmt::Debug for SyncResponse {
             .field("to_device", &DebugListOfRawEventsNoId(&self.to_device))
             .field("ambiguity_changes", &self.ambiguity_changes)
             .field("notifications", &DebugNotificationMap(&self.notifications))
+            .field("is_passive", &self.is_passive)
             .finish_non_exhaustive()
     }
 }
@@ -149,13 +161,59 @@ impl Client {
         &self,
         response: sync_events::v3::Response,
     ) -> Result<BaseSyncResponse> {
-        let response = Box::pin(self.base_client().receive_sync_response(response)).await?;
+        self.process_sync_with_custom_values(response, BTreeMap::new()).await
+    }
+
+    /// Process a sync response while holding the cross-process sync lock.
+    ///
+    /// The generation of the sync token is incremented in the same write as
+    /// the sync token, to let the other processes know that we advanced it.
+    pub(crate) async fn process_shared_sync(
+        &self,
+        response: sync_events::v3::Response,
+    ) -> Result<BaseSyncResponse> {
+        let mut known_generation = self.locks().sync_token_generation.lock().await;
+        let next_generation = known_generation.map_or(0, |generation| generation.wrapping_add(1));
+        let custom_values = BTreeMap::from([(
+            SYNC_TOKEN_GENERATION_KEY.to_vec(),
+            next_generation.to_le_bytes().to_vec(),
+        )]);
+
+        let response = self.process_sync_with_custom_values(response, custom_values).await?;
+        *known_generation = Some(next_generation);
+
+        Ok(response)
+    }
+
+    async fn process_sync_with_custom_values(
+        &self,
+        response: sync_events::v3::Response,
+        custom_values: BTreeMap<Vec<u8>, Vec<u8>>,
+    ) -> Result<BaseSyncResponse> {
+        // The session might have been invalidated while the request was in flight.
+        if self.is_session_gone() {
+            return Err(Error::SessionGone);
+        }
+
+        let own_devices_changed = self
+            .user_id()
+            .is_some_and(|user_id| response.device_lists.changed.iter().any(|u| u == user_id));
+
+        let response = Box::pin(
+            self.base_client().receive_sync_response_with_custom_values(response, custom_values),
+        )
+        .await?;
 
         // Some new keys might have been received, so trigger a backup if needed.
         #[cfg(feature = "e2e-encryption")]
         self.encryption().backups().maybe_trigger_backup();
 
         self.handle_sync_response(&response).await?;
+
+        if own_devices_changed {
+            self.spawn_own_device_check();
+        }
+
         Ok(response)
     }
 
@@ -189,6 +247,8 @@ impl Client {
             let JoinedRoom { unread_notifications: _, timeline, state, account_data, ephemeral } =
                 room_info;
 
+            room.redact_cached_events_from_sync(&timeline.events).await;
+
             let room = Some(&room);
             self.handle_sync_events(HandlerKind::RoomAccountData, room, account_data).await?;
             self.handle_sync_state_events(room, state).await?;
@@ -211,6 +271,8 @@ impl Client {
 
             let LeftRoom { timeline, state, account_data } = room_info;
 
+            room.redact_cached_events_from_sync(&timeline.events).await;
+
             let room = Some(&room);
             self.handle_sync_events(HandlerKind::RoomAccountData, room, account_data).await?;
             self.handle_sync_state_events(room, state).await?;
@@ -223,6 +285,13 @@ impl Client {
                 continue;
             };
 
+            // The invite filters decide whether the invite is seen at all, they run in the
+            // background.
+            if self.inner.invite_filters.has_filters() {
+                self.queue_invite_filtering(room_id, room_info);
+                continue;
+            }
+
             self.send_room_update(room_id, || RoomUpdate::Invited {
                 room: room.clone(),
                 updates: room_info.clone(),
@@ -234,6 +303,10 @@ impl Client {
 
         debug!("Ran event handlers in {:?}", now.elapsed());
 
+        self.update_features(account_data);
+        self.update_server_notices(rooms);
+        self.update_profiles(presence, rooms).await;
+
         let now = Instant::now();
 
         // Construct notification event handler futures
@@ -262,7 +335,7 @@ impl Client {
         Ok(())
     }
 
-    fn send_room_update(&self, room_id: &RoomId, make_msg: impl FnOnce() -> RoomUpdate) {
+    pub(crate) fn send_room_update(&self, room_id: &RoomId, make_msg: impl FnOnce() -> RoomUpdate) {
         if let btree_map::Entry::Occupied(entry) =
             self.inner.room_update_channels.lock().unwrap().entry(room_id.to_owned())
         {
@@ -283,6 +356,47 @@ impl Client {
         tokio::time::sleep(Duration::from_secs(1)).await;
     }
 
+    /// Reload the sync token and the rooms from the store if another process
+    /// advanced the sync token since we last looked.
+    pub(crate) async fn maybe_reload_shared_sync_state(&self) -> Result<()> {
+        let mut known_generation = self.locks().sync_token_generation.lock().await;
+        let stored_generation = self.load_sync_token_generation().await?;
+
+        if *known_generation != stored_generation {
+            debug!(
+                ?known_generation,
+                ?stored_generation,
+                "The sync token was advanced by another process, reloading the store"
+            );
+            self.base_client().reload_from_store().await?;
+            *known_generation = stored_generation;
+        }
+
+        Ok(())
+    }
+
+    async fn load_sync_token_generation(&self) -> Result<Option<u64>> {
+        Ok(self
+            .store()
+            .get_custom_value(SYNC_TOKEN_GENERATION_KEY)
+            .await?
+            .and_then(|bytes| Some(u64::from_le_bytes(bytes.try_into().ok()?))))
+    }
+
+    /// Catch up with the sync of another process, without sending a request to
+    /// the homeserver.
+    ///
+    /// This is used when another process holds the cross-process sync lock.
+    pub(crate) async fn passive_sync(&self, token: Option<String>) -> Result<SyncResponse> {
+        self.maybe_reload_shared_sync_state().await?;
+
+        let next_batch = self.sync_token().await.or(token).unwrap_or_default();
+        let mut response = SyncResponse::new(next_batch, BaseSyncResponse::default());
+        response.is_passive = true;
+
+        Ok(response)
+    }
+
     pub(crate) async fn sync_loop_helper(
         &self,
         sync_settings: &mut crate::config::SyncSettings,
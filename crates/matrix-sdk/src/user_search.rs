@@ -0,0 +1,313 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A user search that combines the user directory of the homeserver with the
+//! members of the rooms the user is in.
+//!
+//! The user directory of the homeserver only knows about the users that share
+//! a room with the user or that are in public rooms it participates in, so it
+//! often misses users from other servers the user already talks to. The
+//! members of the joined rooms are matched locally, and ranked by the number
+//! of rooms they share with the user and by how recently they were active in
+//! them.
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet},
+};
+
+use matrix_sdk_base::deserialized_responses::RawAnySyncOrStrippedState;
+use ruma::{
+    events::{
+        receipt::{ReceiptThread, ReceiptType},
+        room::member::MembershipState,
+        StateEventType,
+    },
+    MilliSecondsSinceUnixEpoch, OwnedMxcUri, OwnedUserId, UserId,
+};
+use serde::Deserialize;
+use tracing::{instrument, warn};
+
+use crate::{Client, Room};
+
+/// A user found by a [`UserSearch`].
+#[derive(Clone, Debug)]
+pub struct UserSearchResult {
+    /// The ID of the user.
+    pub user_id: OwnedUserId,
+
+    /// The display name of the user, if any.
+    pub display_name: Option<String>,
+
+    /// The avatar URL of the user, if any.
+    pub avatar_url: Option<OwnedMxcUri>,
+
+    /// The number of joined rooms the user is a member of.
+    pub shared_rooms: usize,
+
+    /// Whether the user was returned by the user directory of the homeserver.
+    ///
+    /// The user directory is paginated along with the search, so this is only
+    /// accurate for the users it returned before this result.
+    pub in_directory: bool,
+
+    /// The most recent activity of the user in the shared rooms: their latest
+    /// read receipt, or the time they joined if they didn't send any.
+    last_active: Option<MilliSecondsSinceUnixEpoch>,
+}
+
+impl UserSearchResult {
+    fn new(user_id: OwnedUserId) -> Self {
+        Self {
+            user_id,
+            display_name: None,
+            avatar_url: None,
+            shared_rooms: 0,
+            in_directory: false,
+            last_active: None,
+        }
+    }
+}
+
+/// A paginated search for users, in the user directory of the homeserver and
+/// in the members of the joined rooms.
+///
+/// The members of the joined rooms are matched once, with the first call to
+/// [`UserSearch::next_page`], and come first. The other users of the user
+/// directory follow in the order of the homeserver, which is queried again
+/// with a bigger limit when more of them are needed.
+#[derive(Debug)]
+pub struct UserSearch {
+    client: Client,
+    search_term: String,
+    /// The members of the joined rooms matching the search term, sorted.
+    local: Option<Vec<UserSearchResult>>,
+    /// The users of the user directory that aren't in `local`, in the order
+    /// of the homeserver, deduplicated across the requests.
+    directory: Vec<UserSearchResult>,
+    /// The IDs of the users in `local` and `directory`.
+    known: BTreeSet<OwnedUserId>,
+    /// The limit of the last request to the user directory.
+    directory_limit: u64,
+    /// Whether the user directory may have more results.
+    directory_limited: bool,
+    position: usize,
+}
+
+impl UserSearch {
+    fn new(client: Client, search_term: String) -> Self {
+        Self {
+            client,
+            search_term,
+            local: None,
+            directory: Vec::new(),
+            known: BTreeSet::new(),
+            directory_limit: 0,
+            directory_limited: true,
+            position: 0,
+        }
+    }
+
+    /// The search term of this search.
+    pub fn search_term(&self) -> &str {
+        &self.search_term
+    }
+
+    /// Get the next page of results, with at most `page_size` users.
+    ///
+    /// An empty page is returned once all the results were returned. A failure
+    /// of the user directory doesn't fail the search, the members of the
+    /// joined rooms are still returned.
+    #[instrument(skip(self), fields(search_term = %self.search_term))]
+    pub async fn next_page(&mut self, page_size: usize) -> Vec<UserSearchResult> {
+        if self.local.is_none() {
+            let local = self.client.match_room_members(&self.search_term).await;
+            self.known.extend(local.iter().map(|result| result.user_id.clone()));
+            self.local = Some(local);
+        }
+
+        let end = self.position + page_size;
+
+        // The user directory is always queried for the first page, to know
+        // which of the members of the joined rooms it returns.
+        while self.directory_limited && (self.directory_limit == 0 || self.len() < end) {
+            self.fetch_directory_page(page_size).await;
+        }
+
+        let end = end.min(self.len());
+        let page = (self.position..end).map(|index| self.get(index).clone()).collect();
+        self.position = end;
+
+        page
+    }
+
+    /// Whether all the results were returned already.
+    pub fn is_at_last_page(&self) -> bool {
+        self.local.is_some() && !self.directory_limited && self.position >= self.len()
+    }
+
+    fn len(&self) -> usize {
+        self.local.as_ref().map_or(0, Vec::len) + self.directory.len()
+    }
+
+    fn get(&self, index: usize) -> &UserSearchResult {
+        let local = self.local.as_deref().unwrap_or_default();
+        local.get(index).unwrap_or_else(|| &self.directory[index - local.len()])
+    }
+
+    /// Query the user directory for `page_size` more users than the last time.
+    async fn fetch_directory_page(&mut self, page_size: usize) {
+        let previous_limit = self.directory_limit;
+        self.directory_limit += page_size.max(1) as u64;
+
+        let response = match self.client.search_users(&self.search_term, self.directory_limit).await
+        {
+            Ok(response) => response,
+            Err(error) => {
+                warn!("Failed to search the user directory: {error}");
+                self.directory_limited = false;
+                return;
+            }
+        };
+
+        // The homeserver may not return more users even if it says there are
+        // more, don't query it forever.
+        self.directory_limited = response.limited && response.results.len() as u64 > previous_limit;
+
+        for user in response.results {
+            if let Some(result) = self
+                .local
+                .as_mut()
+                .and_then(|local| local.iter_mut().find(|result| result.user_id == user.user_id))
+            {
+                result.in_directory = true;
+                continue;
+            }
+
+            if !self.known.insert(user.user_id.clone()) {
+                continue;
+            }
+
+            self.directory.push(UserSearchResult {
+                display_name: user.display_name,
+                avatar_url: user.avatar_url,
+                in_directory: true,
+                ..UserSearchResult::new(user.user_id)
+            });
+        }
+    }
+}
+
+/// The fields of an `m.room.member` event used by the search, to avoid loading
+/// the full members of the rooms.
+#[derive(Deserialize)]
+struct MemberEvent {
+    state_key: OwnedUserId,
+    origin_server_ts: MilliSecondsSinceUnixEpoch,
+    content: MemberEventContent,
+}
+
+#[derive(Deserialize)]
+struct MemberEventContent {
+    membership: MembershipState,
+    displayname: Option<String>,
+    avatar_url: Option<OwnedMxcUri>,
+}
+
+impl Client {
+    /// Search for users in the user directory of the homeserver and in the
+    /// members of the joined rooms.
+    ///
+    /// Unlike [`Client::search_users`], this finds the users the user shares
+    /// rooms with even if the user directory doesn't know about them. The
+    /// search is performed case-insensitively on user IDs and display names.
+    pub fn user_search(&self, search_term: &str) -> UserSearch {
+        UserSearch::new(self.clone(), search_term.to_owned())
+    }
+
+    /// Find the members of the joined rooms matching the search term, sorted
+    /// by the number of rooms they share with the user and by their most
+    /// recent activity.
+    async fn match_room_members(&self, search_term: &str) -> Vec<UserSearchResult> {
+        let search_term = search_term.to_lowercase();
+        let own_user_id = self.user_id();
+
+        let mut results = BTreeMap::<OwnedUserId, UserSearchResult>::new();
+
+        for room in self.joined_rooms() {
+            let events = match room.get_state_events(StateEventType::RoomMember).await {
+                Ok(events) => events,
+                Err(error) => {
+                    warn!(room_id = ?room.room_id(), "Failed to load the members of the room: {error}");
+                    continue;
+                }
+            };
+
+            for event in events {
+                let RawAnySyncOrStrippedState::Sync(raw) = event else {
+                    continue;
+                };
+                let Ok(event) = raw.deserialize_as::<MemberEvent>() else {
+                    continue;
+                };
+
+                let user_id = &event.state_key;
+
+                if event.content.membership != MembershipState::Join
+                    || Some(&**user_id) == own_user_id
+                    || !member_matches(user_id, event.content.displayname.as_deref(), &search_term)
+                {
+                    continue;
+                }
+
+                let last_active =
+                    last_read_receipt_ts(&room, user_id).await.unwrap_or(event.origin_server_ts);
+
+                let result = results
+                    .entry(user_id.clone())
+                    .or_insert_with(|| UserSearchResult::new(user_id.clone()));
+
+                result.shared_rooms += 1;
+                result.last_active = result.last_active.max(Some(last_active));
+
+                if result.display_name.is_none() {
+                    result.display_name = event.content.displayname;
+                }
+                if result.avatar_url.is_none() {
+                    result.avatar_url = event.content.avatar_url;
+                }
+            }
+        }
+
+        let mut results: Vec<_> = results.into_values().collect();
+        results.sort_by_key(|result| (Reverse(result.shared_rooms), Reverse(result.last_active)));
+
+        results
+    }
+}
+
+/// The time of the latest unthreaded read receipt of the user in the room.
+async fn last_read_receipt_ts(room: &Room, user_id: &UserId) -> Option<MilliSecondsSinceUnixEpoch> {
+    room.load_user_receipt(ReceiptType::Read, ReceiptThread::Unthreaded, user_id)
+        .await
+        .ok()
+        .flatten()
+        .and_then(|(_, receipt)| receipt.ts)
+}
+
+/// Whether the member matches the lowercased search term.
+fn member_matches(user_id: &UserId, display_name: Option<&str>, search_term: &str) -> bool {
+    user_id.as_str().to_lowercase().contains(search_term)
+        || display_name.is_some_and(|name| name.to_lowercase().contains(search_term))
+}
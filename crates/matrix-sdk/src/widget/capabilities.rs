@@ -18,23 +18,186 @@
 use std::fmt;
 
 use async_trait::async_trait;
-use ruma::{events::AnyTimelineEvent, serde::Raw};
+use matrix_sdk_common::{SendOutsideWasm, SyncOutsideWasm};
+use ruma::{
+    events::{
+        AnyEphemeralRoomEvent, AnyTimelineEvent, AnyToDeviceEvent, EphemeralRoomEventType,
+        ToDeviceEventType,
+    },
+    serde::Raw,
+    OwnedRoomId, RoomId,
+};
 use serde::{ser::SerializeSeq, Deserialize, Deserializer, Serialize, Serializer};
+use tokio::sync::oneshot;
 use tracing::{debug, error};
+use uuid::Uuid;
 
 use super::{
-    filter::MatrixEventFilterInput, EventFilter, MessageLikeEventFilter, StateEventFilter,
+    filter::MatrixEventFilterInput, EphemeralEventFilter, EventFilter, MessageLikeEventFilter,
+    StateEventFilter, ToDeviceEventFilter,
 };
+use crate::{Client, Result};
 
 /// Must be implemented by a component that provides functionality of deciding
 /// whether a widget is allowed to use certain capabilities (typically by
 /// providing a prompt to the user).
-#[async_trait]
-pub trait CapabilitiesProvider: Send + Sync + 'static {
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait CapabilitiesProvider: SendOutsideWasm + SyncOutsideWasm + 'static {
     /// Receives a request for given capabilities and returns the actual
     /// capabilities that the clients grants to a given widget (usually by
     /// prompting the user).
     async fn acquire_capabilities(&self, capabilities: Capabilities) -> Capabilities;
+
+    /// Receives a request for given capabilities and either returns the
+    /// capabilities that the client grants to a given widget, or defers the
+    /// decision, for example because the user can't be prompted while the
+    /// application is in the background.
+    ///
+    /// While the decision is deferred, the requests of the widget that need
+    /// capabilities are answered with an error telling it to wait.
+    ///
+    /// The pending request is kept in the state store until the decision is
+    /// made. If the widget driver stops before that, the request is restored
+    /// when a driver is started again for the same widget, and given to
+    /// [`CapabilitiesProvider::resume_deferred_capabilities()`] instead of
+    /// this method.
+    ///
+    /// The default implementation never defers the decision and calls
+    /// [`CapabilitiesProvider::acquire_capabilities()`].
+    async fn acquire_capabilities_or_defer(
+        &self,
+        capabilities: Capabilities,
+    ) -> CapabilitiesDecision {
+        CapabilitiesDecision::Granted(self.acquire_capabilities(capabilities).await)
+    }
+
+    /// Receives the capabilities of a widget whose decision was deferred by a
+    /// previous run of the widget driver, and never made.
+    ///
+    /// This is called instead of
+    /// [`CapabilitiesProvider::acquire_capabilities_or_defer()`] when the
+    /// widget asks for the same capabilities again, for example to avoid
+    /// prompting the user twice. The decision can be deferred again.
+    ///
+    /// The default implementation calls
+    /// [`CapabilitiesProvider::acquire_capabilities_or_defer()`].
+    async fn resume_deferred_capabilities(
+        &self,
+        capabilities: Capabilities,
+    ) -> CapabilitiesDecision {
+        self.acquire_capabilities_or_defer(capabilities).await
+    }
+}
+
+/// The decision of a [`CapabilitiesProvider`] about the capabilities of a
+/// widget.
+#[derive(Debug)]
+pub enum CapabilitiesDecision {
+    /// The capabilities granted to the widget.
+    Granted(Capabilities),
+
+    /// The decision will be made later, and sent with the
+    /// [`DeferredCapabilitiesSender`] created alongside this token.
+    Deferred(DeferredCapabilities),
+}
+
+/// A token for a capabilities decision that will be made later.
+///
+/// It only lives as long as the widget driver that received it. The request
+/// itself is kept in the state store, see
+/// [`CapabilitiesProvider::resume_deferred_capabilities()`].
+#[derive(Debug)]
+pub struct DeferredCapabilities {
+    pub(super) receiver: oneshot::Receiver<Capabilities>,
+}
+
+impl DeferredCapabilities {
+    /// Create a new token for a deferred decision, and the sender to use once
+    /// the decision is made.
+    pub fn new() -> (Self, DeferredCapabilitiesSender) {
+        let (sender, receiver) = oneshot::channel();
+        (Self { receiver }, DeferredCapabilitiesSender { sender })
+    }
+}
+
+/// The sender of a deferred capabilities decision.
+///
+/// If it is dropped without sending a decision, the widget doesn't get any
+/// capability.
+#[derive(Debug)]
+pub struct DeferredCapabilitiesSender {
+    sender: oneshot::Sender<Capabilities>,
+}
+
+impl DeferredCapabilitiesSender {
+    /// Send the capabilities that the client grants to the widget.
+    ///
+    /// This does nothing if the widget driver is no longer running.
+    pub fn send(self, capabilities: Capabilities) {
+        let _ = self.sender.send(capabilities);
+    }
+}
+
+/// A capabilities request whose decision was deferred, kept in the state store
+/// until the decision is made.
+#[derive(Debug, Serialize, Deserialize)]
+pub(super) struct PendingCapabilitiesRequest {
+    /// The ID of the request of the widget machine waiting for the decision.
+    pub(super) request_id: Uuid,
+    /// The capabilities asked for by the widget.
+    pub(super) capabilities: Capabilities,
+}
+
+impl PendingCapabilitiesRequest {
+    fn store_key(widget_id: &str) -> String {
+        format!("{PENDING_CAPABILITIES_KEY_PREFIX}{widget_id}")
+    }
+
+    /// Load the pending request of the given widget, if any.
+    pub(super) async fn load(client: &Client, widget_id: &str) -> Result<Option<Self>> {
+        let key = Self::store_key(widget_id);
+        let Some(value) = client.store().get_custom_value(key.as_bytes()).await? else {
+            return Ok(None);
+        };
+
+        Ok(Some(serde_json::from_slice(&value)?))
+    }
+
+    /// Store this request as the pending request of the given widget.
+    pub(super) async fn save(&self, client: &Client, widget_id: &str) -> Result<()> {
+        let key = Self::store_key(widget_id);
+        client.store().set_custom_value(key.as_bytes(), serde_json::to_vec(self)?).await?;
+        Ok(())
+    }
+
+    /// Forget the pending request of the given widget.
+    ///
+    /// If `request_id` is set, the stored request is only forgotten if it is
+    /// this one, so that a newer request isn't forgotten when an older one
+    /// gets its decision.
+    pub(super) async fn remove(
+        client: &Client,
+        widget_id: &str,
+        request_id: Option<Uuid>,
+    ) -> Result<()> {
+        if let Some(request_id) = request_id {
+            match Self::load(client, widget_id).await? {
+                Some(pending) if pending.request_id == request_id => {}
+                _ => return Ok(()),
+            }
+        }
+
+        client.store().remove_custom_value(Self::store_key(widget_id).as_bytes()).await?;
+        Ok(())
+    }
+
+    /// Whether this request is for the same capabilities as `capabilities`.
+    pub(super) fn is_for(&self, capabilities: &Capabilities) -> bool {
+        // Capabilities can't be compared directly, but their serialization is
+        // stable.
+        serde_json::to_value(&self.capabilities).ok() == serde_json::to_value(capabilities).ok()
+    }
 }
 
 /// Capabilities that a widget can request from a client.
@@ -51,6 +214,56 @@ pub struct Capabilities {
     /// This means clients should not offer to open the widget in a separate
     /// browser/tab/webview that is not connected to the postmessage widget-api.
     pub requires_client: bool,
+    /// If this capability is requested by the widget, it can send events
+    /// with a delay, as defined in [MSC4157].
+    ///
+    /// The events must still match the `send` filters.
+    ///
+    /// [MSC4157]: https://github.com/matrix-org/matrix-spec-proposals/pull/4157
+    pub send_delayed_event: bool,
+    /// If this capability is requested by the widget, it can cancel, restart
+    /// or send right away the delayed events, as defined in [MSC4157].
+    ///
+    /// [MSC4157]: https://github.com/matrix-org/matrix-spec-proposals/pull/4157
+    pub update_delayed_event: bool,
+    /// If this capability is requested by the widget, it can ask the user to
+    /// pick a file, which is uploaded by the client, with a [`FilePicker`].
+    ///
+    /// [`FilePicker`]: super::FilePicker
+    pub pick_file: bool,
+    /// The rooms, besides the room of the widget, in which the `read` and
+    /// `send` filters apply, as defined in [MSC2762].
+    ///
+    /// [MSC2762]: https://github.com/matrix-org/matrix-spec-proposals/pull/2762
+    pub timeline_rooms: TimelineRooms,
+}
+
+/// The rooms in which a widget can read and send events.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum TimelineRooms {
+    /// Only the room of the widget.
+    #[default]
+    OwnRoom,
+    /// The room of the widget, and these other rooms.
+    Rooms(Vec<OwnedRoomId>),
+    /// All the rooms the user is joined to.
+    AllJoined,
+}
+
+impl TimelineRooms {
+    /// Whether a widget in the room `own_room_id` can access the room
+    /// `room_id`.
+    pub fn allows(&self, room_id: &RoomId, own_room_id: &RoomId) -> bool {
+        if room_id == own_room_id {
+            return true;
+        }
+
+        match self {
+            Self::OwnRoom => false,
+            Self::Rooms(room_ids) => room_ids.iter().any(|id| id == room_id),
+            Self::AllJoined => true,
+        }
+    }
 }
 
 impl Capabilities {
@@ -66,13 +279,85 @@ impl Capabilities {
 
         self.read.iter().any(|f| f.matches(&filter_in))
     }
+
+    /// Tells if a given raw event matches the read filter, and comes from a
+    /// room that the widget in the room `own_room_id` can access.
+    ///
+    /// Events without a `room_id` are considered to be from the room of the
+    /// widget.
+    pub(crate) fn raw_event_is_readable(
+        &self,
+        raw: &Raw<AnyTimelineEvent>,
+        own_room_id: &RoomId,
+    ) -> bool {
+        let room_allowed = match raw.get_field::<OwnedRoomId>("room_id") {
+            Ok(Some(room_id)) => self.timeline_rooms.allows(&room_id, own_room_id),
+            Ok(None) => true,
+            Err(err) => {
+                error!("Failed to deserialize the room ID of a raw event: {err}");
+                false
+            }
+        };
+
+        room_allowed && self.raw_event_matches_read_filter(raw)
+    }
+
+    /// Tells if a given raw ephemeral event matches the read filter.
+    pub fn raw_ephemeral_event_matches_read_filter(
+        &self,
+        raw: &Raw<AnyEphemeralRoomEvent>,
+    ) -> bool {
+        let event_type = match raw.get_field::<EphemeralRoomEventType>("type") {
+            Ok(Some(event_type)) => event_type,
+            Ok(None) => {
+                error!("Ephemeral event without a type");
+                return false;
+            }
+            Err(err) => {
+                error!("Failed to deserialize the type of a raw ephemeral event: {err}");
+                return false;
+            }
+        };
+
+        self.read.iter().any(|f| f.matches_ephemeral_event_type(&event_type))
+    }
+
+    /// Tells if a given raw to-device event matches the read filter.
+    pub fn raw_to_device_event_matches_read_filter(&self, raw: &Raw<AnyToDeviceEvent>) -> bool {
+        let event_type = match raw.get_field::<ToDeviceEventType>("type") {
+            Ok(Some(event_type)) => event_type,
+            Ok(None) => {
+                error!("To-device event without a type");
+                return false;
+            }
+            Err(err) => {
+                error!("Failed to deserialize the type of a raw to-device event: {err}");
+                return false;
+            }
+        };
+
+        self.read.iter().any(|f| f.matches_to_device_event_type(&event_type))
+    }
 }
 
 const SEND_EVENT: &str = "org.matrix.msc2762.send.event";
 const READ_EVENT: &str = "org.matrix.msc2762.receive.event";
 const SEND_STATE: &str = "org.matrix.msc2762.send.state_event";
 const READ_STATE: &str = "org.matrix.msc2762.receive.state_event";
+const SEND_EPHEMERAL: &str = "org.matrix.msc2477.send.ephemeral_event";
+const READ_EPHEMERAL: &str = "org.matrix.msc2477.receive.ephemeral_event";
+const SEND_TO_DEVICE: &str = "org.matrix.msc3819.send.to_device";
+const READ_TO_DEVICE: &str = "org.matrix.msc3819.receive.to_device";
 const REQUIRES_CLIENT: &str = "io.element.requires_client";
+const SEND_DELAYED_EVENT: &str = "org.matrix.msc4157.send.delayed_event";
+const UPDATE_DELAYED_EVENT: &str = "org.matrix.msc4157.update_delayed_event";
+const PICK_FILE: &str = "io.element.pick_file";
+const TIMELINE: &str = "org.matrix.msc2762.timeline";
+const ALL_ROOMS: &str = "*";
+
+/// The prefix of the keys of the pending capabilities requests in the state
+/// store, followed by the ID of the widget.
+const PENDING_CAPABILITIES_KEY_PREFIX: &str = "matrix-sdk.widget.pending-capabilities.";
 
 impl Serialize for Capabilities {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -85,6 +370,12 @@ impl Serialize for Capabilities {
                 match self.0 {
                     EventFilter::MessageLike(filter) => PrintMessageLikeEventFilter(filter).fmt(f),
                     EventFilter::State(filter) => PrintStateEventFilter(filter).fmt(f),
+                    EventFilter::Ephemeral(EphemeralEventFilter::WithType(event_type)) => {
+                        write!(f, "{event_type}")
+                    }
+                    EventFilter::ToDevice(ToDeviceEventFilter::WithType(event_type)) => {
+                        write!(f, "{event_type}")
+                    }
                 }
             }
         }
@@ -117,16 +408,42 @@ impl Serialize for Capabilities {
             }
         }
 
-        let seq_len = self.requires_client as usize + self.read.len() + self.send.len();
+        let timeline_rooms = match &self.timeline_rooms {
+            TimelineRooms::OwnRoom => Vec::new(),
+            TimelineRooms::Rooms(room_ids) => room_ids.iter().map(|id| id.as_str()).collect(),
+            TimelineRooms::AllJoined => vec![ALL_ROOMS],
+        };
+
+        let seq_len = self.requires_client as usize
+            + self.send_delayed_event as usize
+            + self.update_delayed_event as usize
+            + self.pick_file as usize
+            + timeline_rooms.len()
+            + self.read.len()
+            + self.send.len();
         let mut seq = serializer.serialize_seq(Some(seq_len))?;
 
         if self.requires_client {
             seq.serialize_element(REQUIRES_CLIENT)?;
         }
+        if self.send_delayed_event {
+            seq.serialize_element(SEND_DELAYED_EVENT)?;
+        }
+        if self.update_delayed_event {
+            seq.serialize_element(UPDATE_DELAYED_EVENT)?;
+        }
+        if self.pick_file {
+            seq.serialize_element(PICK_FILE)?;
+        }
+        for room in timeline_rooms {
+            seq.serialize_element(&format!("{TIMELINE}:{room}"))?;
+        }
         for filter in &self.read {
             let name = match filter {
                 EventFilter::MessageLike(_) => READ_EVENT,
                 EventFilter::State(_) => READ_STATE,
+                EventFilter::Ephemeral(_) => READ_EPHEMERAL,
+                EventFilter::ToDevice(_) => READ_TO_DEVICE,
             };
             seq.serialize_element(&format!("{name}:{}", PrintEventFilter(filter)))?;
         }
@@ -134,6 +451,8 @@ impl Serialize for Capabilities {
             let name = match filter {
                 EventFilter::MessageLike(_) => SEND_EVENT,
                 EventFilter::State(_) => SEND_STATE,
+                EventFilter::Ephemeral(_) => SEND_EPHEMERAL,
+                EventFilter::ToDevice(_) => SEND_TO_DEVICE,
             };
             seq.serialize_element(&format!("{name}:{}", PrintEventFilter(filter)))?;
         }
@@ -149,6 +468,11 @@ impl<'de> Deserialize<'de> for Capabilities {
     {
         enum Permission {
             RequiresClient,
+            SendDelayedEvent,
+            UpdateDelayedEvent,
+            PickFile,
+            Timeline(OwnedRoomId),
+            AllTimelines,
             Read(EventFilter),
             Send(EventFilter),
             Unknown,
@@ -160,11 +484,23 @@ impl<'de> Deserialize<'de> for Capabilities {
                 D: Deserializer<'de>,
             {
                 let s = ruma::serde::deserialize_cow_str(deserializer)?;
-                if s == REQUIRES_CLIENT {
-                    return Ok(Self::RequiresClient);
+                match &*s {
+                    REQUIRES_CLIENT => return Ok(Self::RequiresClient),
+                    SEND_DELAYED_EVENT => return Ok(Self::SendDelayedEvent),
+                    UPDATE_DELAYED_EVENT => return Ok(Self::UpdateDelayedEvent),
+                    PICK_FILE => return Ok(Self::PickFile),
+                    _ => {}
                 }
 
                 match s.split_once(':') {
+                    Some((TIMELINE, ALL_ROOMS)) => Ok(Permission::AllTimelines),
+                    Some((TIMELINE, room_id)) => match RoomId::parse(room_id) {
+                        Ok(room_id) => Ok(Permission::Timeline(room_id)),
+                        Err(_) => {
+                            debug!("Invalid room ID in capability `{s}`");
+                            Ok(Self::Unknown)
+                        }
+                    },
                     Some((READ_EVENT, filter_s)) => Ok(Permission::Read(EventFilter::MessageLike(
                         parse_message_event_filter(filter_s),
                     ))),
@@ -177,6 +513,18 @@ impl<'de> Deserialize<'de> for Capabilities {
                     Some((SEND_STATE, filter_s)) => {
                         Ok(Permission::Send(EventFilter::State(parse_state_event_filter(filter_s))))
                     }
+                    Some((READ_EPHEMERAL, event_type)) => Ok(Permission::Read(
+                        EventFilter::Ephemeral(EphemeralEventFilter::WithType(event_type.into())),
+                    )),
+                    Some((SEND_EPHEMERAL, event_type)) => Ok(Permission::Send(
+                        EventFilter::Ephemeral(EphemeralEventFilter::WithType(event_type.into())),
+                    )),
+                    Some((READ_TO_DEVICE, event_type)) => Ok(Permission::Read(
+                        EventFilter::ToDevice(ToDeviceEventFilter::WithType(event_type.into())),
+                    )),
+                    Some((SEND_TO_DEVICE, event_type)) => Ok(Permission::Send(
+                        EventFilter::ToDevice(ToDeviceEventFilter::WithType(event_type.into())),
+                    )),
                     _ => {
                         debug!("Unknown capability `{s}`");
                         Ok(Self::Unknown)
@@ -207,6 +555,17 @@ impl<'de> Deserialize<'de> for Capabilities {
         for capability in Vec::<Permission>::deserialize(deserializer)? {
             match capability {
                 Permission::RequiresClient => capabilities.requires_client = true,
+                Permission::SendDelayedEvent => capabilities.send_delayed_event = true,
+                Permission::UpdateDelayedEvent => capabilities.update_delayed_event = true,
+                Permission::PickFile => capabilities.pick_file = true,
+                Permission::Timeline(room_id) => match &mut capabilities.timeline_rooms {
+                    TimelineRooms::OwnRoom => {
+                        capabilities.timeline_rooms = TimelineRooms::Rooms(vec![room_id]);
+                    }
+                    TimelineRooms::Rooms(room_ids) => room_ids.push(room_id),
+                    TimelineRooms::AllJoined => {}
+                },
+                Permission::AllTimelines => capabilities.timeline_rooms = TimelineRooms::AllJoined,
                 Permission::Read(filter) => capabilities.read.push(filter),
                 Permission::Send(filter) => capabilities.send.push(filter),
                 // ignore unknown capabilities
@@ -220,7 +579,7 @@ impl<'de> Deserialize<'de> for Capabilities {
 
 #[cfg(test)]
 mod tests {
-    use ruma::events::StateEventType;
+    use ruma::{events::StateEventType, owned_room_id, room_id};
 
     use super::*;
 
@@ -233,7 +592,15 @@ mod tests {
             "org.matrix.msc2762.receive.state_event:m.room.member",
             "org.matrix.msc2762.receive.state_event:org.matrix.msc3401.call.member",
             "org.matrix.msc2762.send.event:org.matrix.rageshake_request",
-            "org.matrix.msc2762.send.state_event:org.matrix.msc3401.call.member#@user:matrix.server"
+            "org.matrix.msc2762.send.state_event:org.matrix.msc3401.call.member#@user:matrix.server",
+            "org.matrix.msc2477.receive.ephemeral_event:org.example.cursor",
+            "org.matrix.msc2477.send.ephemeral_event:org.example.cursor",
+            "org.matrix.msc3819.receive.to_device:io.element.call.encryption_keys",
+            "org.matrix.msc3819.send.to_device:io.element.call.encryption_keys",
+            "org.matrix.msc4157.send.delayed_event",
+            "org.matrix.msc4157.update_delayed_event",
+            "io.element.pick_file",
+            "org.matrix.msc2762.timeline:!other:matrix.server"
         ]"#;
 
         let parsed = serde_json::from_str::<Capabilities>(capabilities_str).unwrap();
@@ -246,6 +613,10 @@ mod tests {
                 EventFilter::State(StateEventFilter::WithType(
                     "org.matrix.msc3401.call.member".into(),
                 )),
+                EventFilter::Ephemeral(EphemeralEventFilter::WithType("org.example.cursor".into())),
+                EventFilter::ToDevice(ToDeviceEventFilter::WithType(
+                    "io.element.call.encryption_keys".into(),
+                )),
             ],
             send: vec![
                 EventFilter::MessageLike(MessageLikeEventFilter::WithType(
@@ -255,8 +626,16 @@ mod tests {
                     "org.matrix.msc3401.call.member".into(),
                     "@user:matrix.server".into(),
                 )),
+                EventFilter::Ephemeral(EphemeralEventFilter::WithType("org.example.cursor".into())),
+                EventFilter::ToDevice(ToDeviceEventFilter::WithType(
+                    "io.element.call.encryption_keys".into(),
+                )),
             ],
             requires_client: true,
+            send_delayed_event: true,
+            update_delayed_event: true,
+            pick_file: true,
+            timeline_rooms: TimelineRooms::Rooms(vec![owned_room_id!("!other:matrix.server")]),
         };
 
         assert_eq!(parsed, expected);
@@ -274,21 +653,59 @@ mod tests {
                     "org.matrix.msc3401.call.member".into(),
                     "@user:matrix.server".into(),
                 )),
+                EventFilter::Ephemeral(EphemeralEventFilter::WithType("io.element.custom".into())),
             ],
             send: vec![
                 EventFilter::MessageLike(MessageLikeEventFilter::WithType(
                     "io.element.custom".into(),
                 )),
+                EventFilter::Ephemeral(EphemeralEventFilter::WithType("io.element.custom".into())),
                 EventFilter::State(StateEventFilter::WithTypeAndStateKey(
                     "org.matrix.msc3401.call.member".into(),
                     "@user:matrix.server".into(),
                 )),
             ],
             requires_client: true,
+            send_delayed_event: true,
+            update_delayed_event: true,
+            pick_file: true,
+            timeline_rooms: TimelineRooms::Rooms(vec![
+                owned_room_id!("!a:matrix.server"),
+                owned_room_id!("!b:matrix.server"),
+            ]),
         };
 
         let capabilities_str = serde_json::to_string(&capabilities).unwrap();
         let parsed = serde_json::from_str::<Capabilities>(&capabilities_str).unwrap();
         assert_eq!(parsed, capabilities);
     }
+
+    #[test]
+    fn all_timelines_override_specific_rooms() {
+        let capabilities_str = r#"[
+            "org.matrix.msc2762.timeline:!a:matrix.server",
+            "org.matrix.msc2762.timeline:*",
+            "org.matrix.msc2762.timeline:!b:matrix.server"
+        ]"#;
+
+        let parsed = serde_json::from_str::<Capabilities>(capabilities_str).unwrap();
+        assert_eq!(parsed.timeline_rooms, TimelineRooms::AllJoined);
+        assert_eq!(serde_json::to_string(&parsed).unwrap(), r#"["org.matrix.msc2762.timeline:*"]"#);
+    }
+
+    #[test]
+    fn timeline_rooms_always_allow_own_room() {
+        let own_room_id = room_id!("!own:matrix.server");
+        let other_room_id = room_id!("!other:matrix.server");
+
+        assert!(TimelineRooms::OwnRoom.allows(own_room_id, own_room_id));
+        assert!(!TimelineRooms::OwnRoom.allows(other_room_id, own_room_id));
+
+        let rooms = TimelineRooms::Rooms(vec![other_room_id.to_owned()]);
+        assert!(rooms.allows(own_room_id, own_room_id));
+        assert!(rooms.allows(other_room_id, own_room_id));
+        assert!(!rooms.allows(room_id!("!third:matrix.server"), own_room_id));
+
+        assert!(TimelineRooms::AllJoined.allows(other_room_id, own_room_id));
+    }
 }
@@ -0,0 +1,49 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Types and traits related to the selection of files by the user on behalf of
+//! a widget.
+
+use async_trait::async_trait;
+use matrix_sdk_common::{SendOutsideWasm, SyncOutsideWasm};
+use mime::Mime;
+
+/// Must be implemented by a component that lets the user pick a file for a
+/// widget, typically with the file picker of the platform.
+///
+/// The widget never gets access to the filesystem: the picked file is uploaded
+/// to the media repository by the SDK, encrypted in encrypted rooms, and the
+/// widget only receives its MXC URI and the keys to decrypt it.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait FilePicker: SendOutsideWasm + SyncOutsideWasm + 'static {
+    /// Lets the user pick a file, and returns it, or `None` if the user
+    /// didn't pick any.
+    ///
+    /// `accepted_types` contains the MIME types or file extensions that the
+    /// widget asked for, like the `accept` attribute of an HTML file input. It
+    /// is empty if the widget accepts any file.
+    async fn pick_file(&self, accepted_types: Vec<String>) -> Option<PickedFile>;
+}
+
+/// A file picked by the user with a [`FilePicker`].
+#[derive(Clone, Debug)]
+pub struct PickedFile {
+    /// The name of the file.
+    pub name: String,
+    /// The MIME type of the file.
+    pub content_type: Mime,
+    /// The content of the file.
+    pub data: Vec<u8>,
+}
@@ -14,10 +14,13 @@
 
 #![allow(dead_code)] // temporary
 
-use ruma::events::{MessageLikeEventType, StateEventType, TimelineEventType};
+use ruma::events::{
+    EphemeralRoomEventType, MessageLikeEventType, StateEventType, TimelineEventType,
+    ToDeviceEventType,
+};
 use serde::Deserialize;
 
-/// Different kinds of filters for timeline events.
+/// Different kinds of filters for room events.
 #[derive(Clone, Debug)]
 #[cfg_attr(test, derive(PartialEq))]
 pub enum EventFilter {
@@ -25,6 +28,10 @@ pub enum EventFilter {
     MessageLike(MessageLikeEventFilter),
     /// Filter for state events.
     State(StateEventFilter),
+    /// Filter for ephemeral events.
+    Ephemeral(EphemeralEventFilter),
+    /// Filter for to-device events.
+    ToDevice(ToDeviceEventFilter),
 }
 
 impl EventFilter {
@@ -32,6 +39,8 @@ impl EventFilter {
         match self {
             EventFilter::MessageLike(message_filter) => message_filter.matches(matrix_event),
             EventFilter::State(state_filter) => state_filter.matches(matrix_event),
+            // Ephemeral and to-device events are not timeline events.
+            EventFilter::Ephemeral(_) | EventFilter::ToDevice(_) => false,
         }
     }
 
@@ -54,6 +63,14 @@ impl EventFilter {
             Self::MessageLike(filter) if filter.matches_message_like_event_type(event_type)
         )
     }
+
+    pub(super) fn matches_ephemeral_event_type(&self, event_type: &EphemeralRoomEventType) -> bool {
+        matches!(self, Self::Ephemeral(filter) if filter.matches(event_type))
+    }
+
+    pub(super) fn matches_to_device_event_type(&self, event_type: &ToDeviceEventType) -> bool {
+        matches!(self, Self::ToDevice(filter) if filter.matches(event_type))
+    }
 }
 
 /// Filter for message-like events.
@@ -127,6 +144,38 @@ impl StateEventFilter {
     }
 }
 
+/// Filter for ephemeral events.
+#[derive(Clone, Debug)]
+#[cfg_attr(test, derive(PartialEq))]
+pub enum EphemeralEventFilter {
+    /// Matches ephemeral events with the given `type`.
+    WithType(EphemeralRoomEventType),
+}
+
+impl EphemeralEventFilter {
+    fn matches(&self, event_type: &EphemeralRoomEventType) -> bool {
+        match self {
+            EphemeralEventFilter::WithType(filter_event_type) => filter_event_type == event_type,
+        }
+    }
+}
+
+/// Filter for to-device events.
+#[derive(Clone, Debug)]
+#[cfg_attr(test, derive(PartialEq))]
+pub enum ToDeviceEventFilter {
+    /// Matches to-device events with the given `type`.
+    WithType(ToDeviceEventType),
+}
+
+impl ToDeviceEventFilter {
+    fn matches(&self, event_type: &ToDeviceEventType) -> bool {
+        match self {
+            ToDeviceEventFilter::WithType(filter_event_type) => filter_event_type == event_type,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub(super) struct MatrixEventFilterInput {
     #[serde(rename = "type")]
@@ -142,11 +191,13 @@ pub(super) struct MatrixEventContent {
 
 #[cfg(test)]
 mod tests {
-    use ruma::events::{MessageLikeEventType, StateEventType, TimelineEventType};
+    use ruma::events::{
+        EphemeralRoomEventType, MessageLikeEventType, StateEventType, TimelineEventType,
+    };
 
     use super::{
-        EventFilter, MatrixEventContent, MatrixEventFilterInput, MessageLikeEventFilter,
-        StateEventFilter,
+        EphemeralEventFilter, EventFilter, MatrixEventContent, MatrixEventFilterInput,
+        MessageLikeEventFilter, StateEventFilter,
     };
 
     fn message_event(event_type: TimelineEventType) -> MatrixEventFilterInput {
@@ -372,4 +423,31 @@ mod tests {
             !room_message_filter().matches_message_like_event_type(&MessageLikeEventType::Reaction)
         );
     }
+
+    fn cursor_ephemeral_event_filter() -> EventFilter {
+        EventFilter::Ephemeral(EphemeralEventFilter::WithType("org.example.cursor".into()))
+    }
+
+    #[test]
+    fn ephemeral_event_filter_matches_ephemeral_event_type() {
+        assert!(cursor_ephemeral_event_filter()
+            .matches_ephemeral_event_type(&"org.example.cursor".into()));
+        assert!(!cursor_ephemeral_event_filter()
+            .matches_ephemeral_event_type(&EphemeralRoomEventType::Typing));
+    }
+
+    #[test]
+    fn ephemeral_event_filter_does_not_match_timeline_events() {
+        assert!(!cursor_ephemeral_event_filter()
+            .matches(&message_event(TimelineEventType::from("org.example.cursor"))));
+        assert!(!cursor_ephemeral_event_filter()
+            .matches_message_like_event_type(&"org.example.cursor".into()));
+    }
+
+    #[test]
+    fn message_like_event_filter_does_not_match_ephemeral_event_type() {
+        let filter =
+            EventFilter::MessageLike(MessageLikeEventFilter::WithType("org.example.cursor".into()));
+        assert!(!filter.matches_ephemeral_event_type(&"org.example.cursor".into()));
+    }
 }
@@ -14,20 +14,28 @@
 
 //! A high-level API for requests that we send to the matrix driver.
 
-use std::marker::PhantomData;
+use std::{collections::BTreeMap, marker::PhantomData, time::Duration};
 
 use ruma::{
-    api::client::account::request_openid_token,
-    events::{AnyTimelineEvent, MessageLikeEventType, StateEventType, TimelineEventType},
+    api::{client::account::request_openid_token, Direction},
+    events::{
+        relation::RelationType, room::EncryptedFile, AnyTimelineEvent, AnyToDeviceEventContent,
+        EphemeralRoomEventType, MessageLikeEventType, StateEventType, TimelineEventType,
+        ToDeviceEventType,
+    },
     serde::Raw,
-    OwnedEventId,
+    to_device::DeviceIdOrAllDevices,
+    OwnedDeviceId, OwnedEventId, OwnedMxcUri, OwnedRoomId, OwnedUserId,
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::value::RawValue as RawJsonValue;
 use tracing::error;
 
 use super::{incoming::MatrixDriverResponse, Action, MatrixDriverRequestMeta, WidgetMachine};
-use crate::widget::{Capabilities, StateKeySelector};
+use crate::{
+    room::DelayedEventAction,
+    widget::{Capabilities, StateKeySelector},
+};
 
 #[derive(Clone, Debug)]
 pub(crate) enum MatrixDriverRequestData {
@@ -47,8 +55,23 @@ pub(crate) enum MatrixDriverRequestData {
     /// Read state event(s).
     ReadStateEvent(ReadStateEventRequest),
 
+    /// Read the events related to an event.
+    ReadRelations(ReadRelationsRequest),
+
     /// Send matrix event that corresponds to the given description.
     SendMatrixEvent(SendEventRequest),
+
+    /// Send an ephemeral event that corresponds to the given description.
+    SendEphemeralEvent(SendEphemeralEventRequest),
+
+    /// Send to-device messages that correspond to the given description.
+    SendToDeviceMessage(SendToDeviceRequest),
+
+    /// Cancel, restart or send a delayed event.
+    UpdateDelayedEvent(UpdateDelayedEventRequest),
+
+    /// Let the user pick a file, and upload it.
+    PickFile(PickFileRequest),
 }
 
 /// A handle to a pending `toWidget` request.
@@ -90,6 +113,10 @@ where
 /// Represents a request that the widget API state machine can send.
 pub(crate) trait MatrixDriverRequest: Into<MatrixDriverRequestData> {
     type Response: FromMatrixDriverResponse;
+
+    /// How long the request can stay unanswered, if it's longer than the
+    /// default response timeout, for example because it waits for the user.
+    const RESPONSE_TIMEOUT: Option<Duration> = None;
 }
 
 pub(crate) trait FromMatrixDriverResponse: Sized {
@@ -155,6 +182,9 @@ impl FromMatrixDriverResponse for request_openid_token::v3::Response {
 /// description and return a list of events as a response.
 #[derive(Clone, Debug)]
 pub(crate) struct ReadMessageLikeEventRequest {
+    /// The rooms to read the events from.
+    pub(crate) rooms: ReadRooms,
+
     /// The event type to read.
     pub(crate) event_type: MessageLikeEventType,
 
@@ -188,6 +218,9 @@ impl FromMatrixDriverResponse for Vec<Raw<AnyTimelineEvent>> {
 /// description and return a list of events as a response.
 #[derive(Clone, Debug)]
 pub(crate) struct ReadStateEventRequest {
+    /// The rooms to read the events from.
+    pub(crate) rooms: ReadRooms,
+
     /// The event type to read.
     pub(crate) event_type: StateEventType,
 
@@ -206,10 +239,81 @@ impl MatrixDriverRequest for ReadStateEventRequest {
     type Response = Vec<Raw<AnyTimelineEvent>>;
 }
 
+/// The rooms in which a [`ReadMessageLikeEventRequest`] or a
+/// [`ReadStateEventRequest`] reads events.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum ReadRooms {
+    /// The given rooms.
+    Rooms(Vec<OwnedRoomId>),
+    /// All the rooms the user is joined to.
+    AllJoined,
+}
+
+/// Ask the client to read the events related to an event, with the
+/// `/relations` endpoint, as defined by [MSC3869].
+///
+/// [MSC3869]: https://github.com/matrix-org/matrix-spec-proposals/pull/3869
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct ReadRelationsRequest {
+    /// The ID of the event whose related events are read.
+    pub(crate) event_id: OwnedEventId,
+    /// The room of the event, the room of the widget if `None`.
+    pub(crate) room_id: Option<OwnedRoomId>,
+    /// Only read the events with this type of relation.
+    pub(crate) rel_type: Option<RelationType>,
+    /// Only read the events of this type.
+    pub(crate) event_type: Option<TimelineEventType>,
+    /// The maximum number of events to return.
+    pub(crate) limit: Option<u32>,
+    /// The pagination token to start from.
+    pub(crate) from: Option<String>,
+    /// The pagination token to stop at.
+    pub(crate) to: Option<String>,
+    /// The direction of the pagination, backwards by default.
+    pub(crate) direction: Option<Direction>,
+}
+
+impl From<ReadRelationsRequest> for MatrixDriverRequestData {
+    fn from(value: ReadRelationsRequest) -> Self {
+        MatrixDriverRequestData::ReadRelations(value)
+    }
+}
+
+impl MatrixDriverRequest for ReadRelationsRequest {
+    type Response = ReadRelationsResponse;
+}
+
+/// The related events read with a [`ReadRelationsRequest`].
+#[derive(Debug, Serialize)]
+pub(crate) struct ReadRelationsResponse {
+    /// The related events.
+    pub(crate) chunk: Vec<Raw<AnyTimelineEvent>>,
+    /// The token to read the next page of related events, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) next_batch: Option<String>,
+    /// The token to read the previous page of related events, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) prev_batch: Option<String>,
+}
+
+impl FromMatrixDriverResponse for ReadRelationsResponse {
+    fn from_response(ev: MatrixDriverResponse) -> Option<Self> {
+        match ev {
+            MatrixDriverResponse::RelationsRead(response) => Some(response),
+            _ => {
+                error!("bug in MatrixDriver, received wrong event response");
+                None
+            }
+        }
+    }
+}
+
 /// Ask the client to send matrix event that corresponds to the given
 /// description and return an event ID as a response.
 #[derive(Clone, Debug, Deserialize)]
 pub(crate) struct SendEventRequest {
+    /// The room to send the event to, the room of the widget if `None`.
+    pub(crate) room_id: Option<OwnedRoomId>,
     /// The type of the event.
     #[serde(rename = "type")]
     pub(crate) event_type: TimelineEventType,
@@ -217,6 +321,11 @@ pub(crate) struct SendEventRequest {
     pub(crate) state_key: Option<String>,
     /// Raw content of an event.
     pub(crate) content: Box<RawJsonValue>,
+    /// The delay after which the homeserver sends the event, in milliseconds,
+    /// as defined in [MSC4157].
+    ///
+    /// [MSC4157]: https://github.com/matrix-org/matrix-spec-proposals/pull/4157
+    pub(crate) delay: Option<u64>,
 }
 
 impl From<SendEventRequest> for MatrixDriverRequestData {
@@ -226,13 +335,175 @@ impl From<SendEventRequest> for MatrixDriverRequestData {
 }
 
 impl MatrixDriverRequest for SendEventRequest {
-    type Response = OwnedEventId;
+    type Response = SentEvent;
+}
+
+/// The result of a [`SendEventRequest`].
+#[derive(Clone, Debug)]
+pub(crate) enum SentEvent {
+    /// The event was sent right away, with this ID.
+    Sent(OwnedEventId),
+    /// The event will be sent by the homeserver after its delay, it can be
+    /// updated with this delay ID.
+    Delayed(String),
+}
+
+impl FromMatrixDriverResponse for SentEvent {
+    fn from_response(ev: MatrixDriverResponse) -> Option<Self> {
+        match ev {
+            MatrixDriverResponse::MatrixEventSent(event_id) => Some(Self::Sent(event_id)),
+            MatrixDriverResponse::DelayedEventSent(delay_id) => Some(Self::Delayed(delay_id)),
+            _ => {
+                error!("bug in MatrixDriver, received wrong event response");
+                None
+            }
+        }
+    }
+}
+
+/// Ask the client to send an ephemeral event that corresponds to the given
+/// description.
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct SendEphemeralEventRequest {
+    /// The type of the event.
+    #[serde(rename = "type")]
+    pub(crate) event_type: EphemeralRoomEventType,
+    /// Raw content of an event.
+    pub(crate) content: Box<RawJsonValue>,
+}
+
+impl From<SendEphemeralEventRequest> for MatrixDriverRequestData {
+    fn from(value: SendEphemeralEventRequest) -> Self {
+        MatrixDriverRequestData::SendEphemeralEvent(value)
+    }
+}
+
+impl MatrixDriverRequest for SendEphemeralEventRequest {
+    type Response = ();
+}
+
+/// Ask the client to send to-device messages that correspond to the given
+/// description.
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct SendToDeviceRequest {
+    /// The type of the to-device events.
+    #[serde(rename = "type")]
+    pub(crate) event_type: ToDeviceEventType,
+    /// Whether the messages must be encrypted with Olm.
+    #[serde(default)]
+    pub(crate) encrypted: bool,
+    /// The contents of the messages, by recipient user and device.
+    pub(crate) messages:
+        BTreeMap<OwnedUserId, BTreeMap<DeviceIdOrAllDevices, Raw<AnyToDeviceEventContent>>>,
+}
+
+impl From<SendToDeviceRequest> for MatrixDriverRequestData {
+    fn from(value: SendToDeviceRequest) -> Self {
+        MatrixDriverRequestData::SendToDeviceMessage(value)
+    }
+}
+
+impl MatrixDriverRequest for SendToDeviceRequest {
+    type Response = UnreachableDevices;
+}
+
+/// The devices that to-device messages couldn't be encrypted for, by user.
+pub(crate) type UnreachableDevices = BTreeMap<OwnedUserId, Vec<OwnedDeviceId>>;
+
+impl FromMatrixDriverResponse for UnreachableDevices {
+    fn from_response(ev: MatrixDriverResponse) -> Option<Self> {
+        match ev {
+            MatrixDriverResponse::ToDeviceSent(unreachable_devices) => Some(unreachable_devices),
+            _ => {
+                error!("bug in MatrixDriver, received wrong event response");
+                None
+            }
+        }
+    }
+}
+
+/// Ask the client to cancel, restart or send right away a delayed event, as
+/// defined in [MSC4157].
+///
+/// [MSC4157]: https://github.com/matrix-org/matrix-spec-proposals/pull/4157
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct UpdateDelayedEventRequest {
+    /// The ID of the delayed event.
+    pub(crate) delay_id: String,
+    /// What to do with the delayed event.
+    pub(crate) action: DelayedEventAction,
+}
+
+impl From<UpdateDelayedEventRequest> for MatrixDriverRequestData {
+    fn from(value: UpdateDelayedEventRequest) -> Self {
+        MatrixDriverRequestData::UpdateDelayedEvent(value)
+    }
+}
+
+impl MatrixDriverRequest for UpdateDelayedEventRequest {
+    type Response = ();
+}
+
+impl FromMatrixDriverResponse for () {
+    fn from_response(ev: MatrixDriverResponse) -> Option<Self> {
+        match ev {
+            MatrixDriverResponse::EphemeralEventSent
+            | MatrixDriverResponse::DelayedEventUpdated => Some(()),
+            _ => {
+                error!("bug in MatrixDriver, received wrong event response");
+                None
+            }
+        }
+    }
+}
+
+/// Ask the client to let the user pick a file with the [`FilePicker`], and to
+/// upload it to the media repository.
+///
+/// [`FilePicker`]: crate::widget::FilePicker
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct PickFileRequest {
+    /// The MIME types or file extensions that the widget accepts, any file if
+    /// it is empty.
+    #[serde(default)]
+    pub(crate) accept: Vec<String>,
+}
+
+impl From<PickFileRequest> for MatrixDriverRequestData {
+    fn from(value: PickFileRequest) -> Self {
+        MatrixDriverRequestData::PickFile(value)
+    }
+}
+
+impl MatrixDriverRequest for PickFileRequest {
+    type Response = Option<UploadedFile>;
+
+    // The user might take a while to pick the file, and uploading it can be
+    // slow too.
+    const RESPONSE_TIMEOUT: Option<Duration> = Some(Duration::from_secs(10 * 60));
+}
+
+/// A file picked by the user and uploaded for a widget.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct UploadedFile {
+    /// The MXC URI of the uploaded file.
+    pub(crate) content_uri: OwnedMxcUri,
+    /// The name of the file.
+    pub(crate) name: String,
+    /// The MIME type of the file.
+    pub(crate) mimetype: String,
+    /// The size of the file, in bytes.
+    pub(crate) size: u64,
+    /// The information needed to decrypt the file, if it was encrypted
+    /// because the room is encrypted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) file: Option<EncryptedFile>,
 }
 
-impl FromMatrixDriverResponse for OwnedEventId {
+impl FromMatrixDriverResponse for Option<UploadedFile> {
     fn from_response(ev: MatrixDriverResponse) -> Option<Self> {
         match ev {
-            MatrixDriverResponse::MatrixEventSent(response) => Some(response),
+            MatrixDriverResponse::FilePicked(file) => Some(file),
             _ => {
                 error!("bug in MatrixDriver, received wrong event response");
                 None
@@ -12,16 +12,20 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::fmt;
+use std::{collections::BTreeMap, fmt};
 
 use ruma::{
     events::{AnyTimelineEvent, MessageLikeEventType, StateEventType},
     serde::Raw,
-    OwnedEventId, RoomId,
+    OwnedEventId, OwnedRoomId, RoomId,
 };
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize};
 
-use super::SendEventRequest;
+use super::{
+    driver_req::{UnreachableDevices, UploadedFile},
+    PickFileRequest, ReadRelationsRequest, SendEphemeralEventRequest, SendEventRequest,
+    SendToDeviceRequest, UpdateDelayedEventRequest, ValidationProblem,
+};
 use crate::widget::StateKeySelector;
 
 #[derive(Deserialize)]
@@ -33,7 +37,35 @@ pub(super) enum FromWidgetRequest {
     GetOpenId {},
     #[serde(rename = "org.matrix.msc2876.read_events")]
     ReadEvent(ReadEventRequest),
+    #[serde(rename = "org.matrix.msc3869.read_relations")]
+    ReadRelations(ReadRelationsRequest),
     SendEvent(SendEventRequest),
+    #[serde(rename = "org.matrix.msc2477.send_ephemeral_event")]
+    SendEphemeralEvent(SendEphemeralEventRequest),
+    SendToDevice(SendToDeviceRequest),
+    #[serde(rename = "org.matrix.msc4157.update_delayed_event")]
+    UpdateDelayedEvent(UpdateDelayedEventRequest),
+    #[serde(rename = "io.element.pick_file")]
+    PickFile(PickFileRequest),
+}
+
+impl FromWidgetRequest {
+    /// Whether this request counts towards the rate limits of the widget.
+    ///
+    /// Only the requests that end up making requests to the homeserver do.
+    pub(super) fn is_rate_limited(&self) -> bool {
+        matches!(
+            self,
+            Self::GetOpenId {}
+                | Self::ReadEvent(_)
+                | Self::ReadRelations(_)
+                | Self::SendEvent(_)
+                | Self::SendEphemeralEvent(_)
+                | Self::SendToDevice(_)
+                | Self::UpdateDelayedEvent(_)
+                | Self::PickFile(_)
+        )
+    }
 }
 
 #[derive(Serialize)]
@@ -43,13 +75,24 @@ pub(super) struct FromWidgetErrorResponse {
 
 impl FromWidgetErrorResponse {
     pub(super) fn new(e: impl fmt::Display) -> Self {
-        Self { error: FromWidgetError { message: e.to_string() } }
+        Self { error: FromWidgetError { message: e.to_string(), problems: Vec::new() } }
+    }
+
+    /// An error response for an invalid request, with the problems found in
+    /// it.
+    pub(super) fn invalid_request(problems: Vec<ValidationProblem>) -> Self {
+        let details = problems.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ");
+        let message = format!("Invalid request: {details}");
+
+        Self { error: FromWidgetError { message, problems } }
     }
 }
 
 #[derive(Serialize)]
 struct FromWidgetError {
     message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    problems: Vec<ValidationProblem>,
 }
 
 #[derive(Serialize)]
@@ -63,9 +106,12 @@ impl SupportedApiVersionsResponse {
             supported_versions: vec![
                 ApiVersion::V0_0_1,
                 ApiVersion::V0_0_2,
+                ApiVersion::MSC2477,
                 ApiVersion::MSC2762,
                 ApiVersion::MSC2871,
                 ApiVersion::MSC3819,
+                ApiVersion::MSC3869,
+                ApiVersion::MSC4157,
             ],
         }
     }
@@ -82,6 +128,10 @@ pub(super) enum ApiVersion {
     #[serde(rename = "0.0.2")]
     V0_0_2,
 
+    /// Supports sending and receiving of user-defined ephemeral events.
+    #[serde(rename = "org.matrix.msc2477")]
+    MSC2477,
+
     /// Supports sending and receiving of events.
     #[serde(rename = "org.matrix.msc2762")]
     MSC2762,
@@ -109,6 +159,14 @@ pub(super) enum ApiVersion {
     /// Supports access to the TURN servers.
     #[serde(rename = "town.robin.msc3846")]
     MSC3846,
+
+    /// Supports reading the events related to an event.
+    #[serde(rename = "org.matrix.msc3869")]
+    MSC3869,
+
+    /// Supports sending delayed events.
+    #[serde(rename = "org.matrix.msc4157")]
+    MSC4157,
 }
 
 #[derive(Deserialize)]
@@ -118,15 +176,52 @@ pub(super) enum ReadEventRequest {
         #[serde(rename = "type")]
         event_type: StateEventType,
         state_key: StateKeySelector,
+        #[serde(default)]
+        room_ids: Option<ReadRoomIds>,
     },
     #[allow(dead_code)]
     ReadMessageLikeEvent {
         #[serde(rename = "type")]
         event_type: MessageLikeEventType,
         limit: Option<u32>,
+        #[serde(default)]
+        room_ids: Option<ReadRoomIds>,
     },
 }
 
+/// The rooms to read events from, as defined in [MSC2762].
+///
+/// [MSC2762]: https://github.com/matrix-org/matrix-spec-proposals/pull/2762
+#[derive(Debug)]
+pub(super) enum ReadRoomIds {
+    /// All the rooms the widget can access, with `"*"`.
+    All,
+    /// The given rooms.
+    Rooms(Vec<OwnedRoomId>),
+}
+
+impl<'de> Deserialize<'de> for ReadRoomIds {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Helper {
+            Rooms(Vec<OwnedRoomId>),
+            Wildcard(String),
+        }
+
+        match Helper::deserialize(deserializer)? {
+            Helper::Rooms(room_ids) => Ok(Self::Rooms(room_ids)),
+            Helper::Wildcard(s) if s == "*" => Ok(Self::All),
+            Helper::Wildcard(s) => {
+                Err(de::Error::invalid_value(de::Unexpected::Str(&s), &"`*` or a list of room IDs"))
+            }
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub(super) struct ReadEventResponse {
     pub(super) events: Vec<Raw<AnyTimelineEvent>>,
@@ -135,5 +230,30 @@ pub(super) struct ReadEventResponse {
 #[derive(Serialize)]
 pub(super) struct SendEventResponse<'a> {
     pub(super) room_id: &'a RoomId,
-    pub(super) event_id: OwnedEventId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) event_id: Option<OwnedEventId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) delay_id: Option<String>,
+}
+
+#[derive(Serialize)]
+pub(super) struct SendEphemeralEventResponse<'a> {
+    pub(super) room_id: &'a RoomId,
+}
+
+#[derive(Serialize)]
+pub(super) struct SendToDeviceResponse {
+    /// The devices that the messages couldn't be encrypted for, by user.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub(super) failures: UnreachableDevices,
+}
+
+#[derive(Serialize)]
+pub(super) struct UpdateDelayedEventResponse {}
+
+#[derive(Serialize)]
+pub(super) struct PickFileResponse {
+    /// The uploaded file, or `None` if the user didn't pick any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) file: Option<UploadedFile>,
 }
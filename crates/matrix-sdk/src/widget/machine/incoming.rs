@@ -13,13 +13,20 @@
 // limitations under the License.
 
 use ruma::{
-    api::client::account::request_openid_token, events::AnyTimelineEvent, serde::Raw, OwnedEventId,
+    api::client::account::request_openid_token,
+    events::{AnyEphemeralRoomEvent, AnyTimelineEvent, AnyToDeviceEvent},
+    serde::Raw,
+    OwnedEventId,
 };
 use serde::{de, Deserialize, Deserializer};
 use serde_json::value::RawValue as RawJsonValue;
 use uuid::Uuid;
 
-use super::{from_widget::FromWidgetRequest, to_widget::ToWidgetResponse};
+use super::{
+    driver_req::{ReadRelationsResponse, UnreachableDevices, UploadedFile},
+    from_widget::FromWidgetRequest,
+    to_widget::ToWidgetResponse,
+};
 use crate::widget::Capabilities;
 
 /// Incoming event that the client API must process.
@@ -36,11 +43,44 @@ pub(crate) enum IncomingMessage {
         response: Result<MatrixDriverResponse, String>,
     },
 
+    /// The decision about the capabilities requested with the given
+    /// `Action::AcquireCapabilities` command was deferred.
+    ///
+    /// The response to the request will arrive later, as a
+    /// `MatrixDriverResponse`, once the decision is made.
+    CapabilitiesDeferred {
+        /// The ID of the request that was deferred.
+        request_id: Uuid,
+    },
+
     /// The `MatrixDriver` notified the `WidgetMachine` of a new matrix event.
     ///
     /// This means that the machine previously subscribed to some events
     /// (`Action::Subscribe` request).
     MatrixEventReceived(Raw<AnyTimelineEvent>),
+
+    /// The `MatrixDriver` notified the `WidgetMachine` of a new ephemeral
+    /// event.
+    ///
+    /// Like with `MatrixEventReceived`, the machine previously subscribed to
+    /// the events of the room.
+    EphemeralEventReceived(Raw<AnyEphemeralRoomEvent>),
+
+    /// The `MatrixDriver` notified the `WidgetMachine` of a new to-device
+    /// event.
+    ///
+    /// Like with `MatrixEventReceived`, the machine previously subscribed to
+    /// the events.
+    ToDeviceEventReceived(Raw<AnyToDeviceEvent>),
+
+    /// The `MatrixDriver` missed some updates of the state of the room, the
+    /// state events the widget can read must be sent to it again.
+    RoomStateLagged,
+
+    /// A reminder to check the pending requests for expired ones, sent
+    /// periodically so that an unresponsive widget is noticed even when
+    /// nothing else happens.
+    CheckExpiredRequests,
 }
 
 pub(crate) enum MatrixDriverResponse {
@@ -54,9 +94,29 @@ pub(crate) enum MatrixDriverResponse {
     /// Client read some matrix event(s).
     /// A response to an `Action::ReadMatrixEvent` commands.
     MatrixEventRead(Vec<Raw<AnyTimelineEvent>>),
+    /// Client read the events related to an event.
+    /// A response to an `Action::ReadRelations` command.
+    RelationsRead(ReadRelationsResponse),
     /// Client sent some matrix event. The response contains the event ID.
     /// A response to an `Action::SendMatrixEvent` command.
     MatrixEventSent(OwnedEventId),
+    /// Client scheduled some delayed matrix event. The response contains the
+    /// delay ID.
+    /// A response to an `Action::SendMatrixEvent` command with a delay.
+    DelayedEventSent(String),
+    /// Client sent some ephemeral event.
+    /// A response to an `Action::SendEphemeralEvent` command.
+    EphemeralEventSent,
+    /// Client sent some to-device messages. The response contains the devices
+    /// they couldn't be encrypted for.
+    /// A response to an `Action::SendToDeviceMessage` command.
+    ToDeviceSent(UnreachableDevices),
+    /// Client updated some delayed event.
+    /// A response to an `Action::UpdateDelayedEvent` command.
+    DelayedEventUpdated,
+    /// Client let the user pick a file, and uploaded it if one was picked.
+    /// A response to an `Action::PickFile` command.
+    FilePicked(Option<UploadedFile>),
 }
 
 pub(super) struct IncomingWidgetMessage {
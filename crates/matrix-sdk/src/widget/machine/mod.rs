@@ -16,55 +16,71 @@
 
 #![warn(unreachable_pub)]
 
-use std::{fmt, iter, time::Duration};
+use std::{fmt, iter, mem, sync::Arc};
 
 use indexmap::IndexMap;
+use matrix_sdk_common::clock::Clock;
 use ruma::{
     serde::{JsonObject, Raw},
     OwnedRoomId,
 };
 use serde::Serialize;
-use serde_json::value::RawValue as RawJsonValue;
+use serde_json::{value::RawValue as RawJsonValue, Value as JsonValue};
 use tracing::{debug, error, info, instrument, warn};
 use uuid::Uuid;
 
 use self::{
     driver_req::{
         AcquireCapabilities, MatrixDriverRequest, MatrixDriverRequestHandle,
-        ReadMessageLikeEventRequest, RequestOpenId,
+        ReadMessageLikeEventRequest, RequestOpenId, SentEvent,
     },
     from_widget::{
-        FromWidgetErrorResponse, FromWidgetRequest, ReadEventRequest, ReadEventResponse,
-        SendEventResponse, SupportedApiVersionsResponse,
+        FromWidgetErrorResponse, FromWidgetRequest, PickFileResponse, ReadEventRequest,
+        ReadEventResponse, ReadRoomIds, SendEphemeralEventResponse, SendEventResponse,
+        SendToDeviceResponse, SupportedApiVersionsResponse, UpdateDelayedEventResponse,
     },
     incoming::{IncomingWidgetMessage, IncomingWidgetMessageKind},
     openid::{OpenIdResponse, OpenIdState},
-    pending::{PendingRequests, RequestLimits},
+    pending::PendingRequests,
+    rate_limit::{RateLimiter, Rejection},
     to_widget::{
-        NotifyCapabilitiesChanged, NotifyNewMatrixEvent, NotifyOpenIdChanged, RequestCapabilities,
-        ToWidgetRequest, ToWidgetRequestHandle, ToWidgetResponse,
+        NotifyCapabilitiesChanged, NotifyNewEphemeralEvent, NotifyNewMatrixEvent,
+        NotifyNewToDeviceEvent, NotifyOpenIdChanged, RequestCapabilities, ToWidgetRequest,
+        ToWidgetRequestHandle, ToWidgetResponse,
     },
 };
 #[cfg(doc)]
 use super::WidgetDriver;
 use super::{
     filter::{MatrixEventContent, MatrixEventFilterInput},
-    Capabilities, StateKeySelector,
+    Capabilities, StateKeySelector, TimelineRooms,
 };
-use crate::widget::EventFilter;
+use crate::widget::{EventFilter, StateEventFilter};
 
 mod driver_req;
 mod from_widget;
 mod incoming;
 mod openid;
 mod pending;
+mod rate_limit;
 #[cfg(test)]
 mod tests;
 mod to_widget;
+mod validation;
 
+/// The error sent to the widget when it makes a request that needs
+/// capabilities, while the decision about them is deferred.
+const WAITING_FOR_CAPABILITIES: &str = "Waiting for the capabilities to be approved";
+
+pub use self::validation::{ValidationProblem, ValidationProblemKind, WidgetDiagnostic};
 pub(crate) use self::{
-    driver_req::{MatrixDriverRequestData, ReadStateEventRequest, SendEventRequest},
+    driver_req::{
+        MatrixDriverRequestData, PickFileRequest, ReadRelationsRequest, ReadRelationsResponse,
+        ReadRooms, ReadStateEventRequest, SendEphemeralEventRequest, SendEventRequest,
+        SendToDeviceRequest, UnreachableDevices, UpdateDelayedEventRequest, UploadedFile,
+    },
     incoming::{IncomingMessage, MatrixDriverResponse},
+    pending::RequestLimits,
 };
 
 /// Action (a command) that client (driver) must perform.
@@ -91,12 +107,34 @@ pub(crate) enum Action {
     /// Subscribe to the events in the *current* room, i.e. a room which this
     /// widget is instantiated with. The client is aware of the room.
     #[allow(dead_code)]
-    Subscribe,
+    Subscribe {
+        /// The other rooms to subscribe to, if any.
+        timeline_rooms: TimelineRooms,
+    },
 
     /// Unsuscribe from the events in the *current* room. Symmetrical to
     /// `Subscribe`.
     #[allow(dead_code)]
     Unsubscribe,
+
+    /// Let the host application know that the widget keeps making requests
+    /// over its rate limits.
+    NotifyRateLimitExceeded {
+        /// The number of requests that were rejected since the widget started
+        /// hitting the limits.
+        rejected_requests: u64,
+    },
+
+    /// Let the host application know that the widget didn't answer a
+    /// `toWidget` request in time, even after sending it again if it could be.
+    NotifyWidgetUnresponsive {
+        /// The action of the request that wasn't answered.
+        action: &'static str,
+    },
+
+    /// Let the host application know that the widget sent a message that
+    /// couldn't be processed, and why.
+    ReportDiagnostic(WidgetDiagnostic),
 }
 
 /// No I/O state machine.
@@ -108,6 +146,10 @@ pub(crate) struct WidgetMachine {
     pending_to_widget_requests: PendingRequests<ToWidgetRequestMeta>,
     pending_matrix_driver_requests: PendingRequests<MatrixDriverRequestMeta>,
     capabilities: CapabilitiesState,
+    rate_limiter: Option<RateLimiter>,
+    /// How many times the idempotent `toWidget` requests are sent again when
+    /// the widget doesn't answer them in time.
+    to_widget_retries: u32,
 }
 
 impl WidgetMachine {
@@ -119,18 +161,23 @@ impl WidgetMachine {
         room_id: OwnedRoomId,
         init_on_content_load: bool,
         limits: Option<RequestLimits>,
+        clock: Arc<dyn Clock>,
     ) -> (Self, Vec<Action>) {
-        let limits = limits.unwrap_or_else(|| RequestLimits {
-            max_pending_requests: 15,
-            response_timeout: Duration::from_secs(10),
-        });
+        let limits = limits.unwrap_or_default();
+        let rate_limiter =
+            limits.rate_limits.map(|rate_limits| RateLimiter::new(rate_limits, clock.clone()));
+        let to_widget_retries = limits.to_widget_retries;
+        let to_widget_limits =
+            RequestLimits { response_timeout: limits.to_widget_response_timeout, ..limits.clone() };
 
         let mut machine = Self {
             widget_id,
             room_id,
-            pending_to_widget_requests: PendingRequests::new(limits.clone()),
-            pending_matrix_driver_requests: PendingRequests::new(limits),
+            pending_to_widget_requests: PendingRequests::new(to_widget_limits, clock.clone()),
+            pending_matrix_driver_requests: PendingRequests::new(limits, clock),
             capabilities: CapabilitiesState::Unset,
+            rate_limiter,
+            to_widget_retries,
         };
 
         let actions = (!init_on_content_load).then(|| machine.negotiate_capabilities());
@@ -140,14 +187,53 @@ impl WidgetMachine {
     /// Main entry point to drive the state machine.
     pub(crate) fn process(&mut self, event: IncomingMessage) -> Vec<Action> {
         // Clean up stale requests.
-        self.pending_to_widget_requests.remove_expired();
+        let mut actions = self.process_expired_to_widget_requests();
         self.pending_matrix_driver_requests.remove_expired();
 
+        actions.extend(self.process_incoming_message(event));
+        actions
+    }
+
+    /// Send again the expired `toWidget` requests that can be, and notify the
+    /// host application about the other ones.
+    fn process_expired_to_widget_requests(&mut self) -> Vec<Action> {
+        let mut actions = Vec::new();
+
+        for (request_id, mut meta) in self.pending_to_widget_requests.remove_expired() {
+            let action = meta.action;
+            let retry = meta.retry.as_mut().filter(|retry| retry.retries_left > 0);
+
+            let Some(retry) = retry else {
+                actions.push(Action::NotifyWidgetUnresponsive { action });
+                continue;
+            };
+
+            retry.retries_left -= 1;
+            let message = retry.message.clone();
+            debug!(?request_id, action, "Sending again a toWidget request that wasn't answered");
+
+            if self.pending_to_widget_requests.insert(request_id, meta).is_some() {
+                actions.push(Action::SendToWidget(message));
+            } else {
+                warn!("Reached limits of pending requests for toWidget requests");
+                actions.push(Action::NotifyWidgetUnresponsive { action });
+            }
+        }
+
+        actions
+    }
+
+    fn process_incoming_message(&mut self, event: IncomingMessage) -> Vec<Action> {
         match event {
+            IncomingMessage::CheckExpiredRequests => Vec::new(),
             IncomingMessage::WidgetMessage(raw) => self.process_widget_message(&raw),
             IncomingMessage::MatrixDriverResponse { request_id, response } => {
                 self.process_matrix_driver_response(request_id, response)
             }
+            IncomingMessage::CapabilitiesDeferred { request_id } => {
+                self.defer_capabilities(request_id);
+                Vec::new()
+            }
             IncomingMessage::MatrixEventReceived(event) => {
                 let CapabilitiesState::Negotiated(capabilities) = &self.capabilities else {
                     error!("Received matrix event before capabilities negotiation");
@@ -155,13 +241,40 @@ impl WidgetMachine {
                 };
 
                 capabilities
-                    .raw_event_matches_read_filter(&event)
+                    .raw_event_is_readable(&event, &self.room_id)
                     .then(|| {
                         let action = self.send_to_widget_request(NotifyNewMatrixEvent(event)).1;
                         action.map(|a| vec![a]).unwrap_or_default()
                     })
                     .unwrap_or_default()
             }
+            IncomingMessage::EphemeralEventReceived(event) => {
+                let CapabilitiesState::Negotiated(capabilities) = &self.capabilities else {
+                    error!("Received ephemeral event before capabilities negotiation");
+                    return Vec::new();
+                };
+
+                if !capabilities.raw_ephemeral_event_matches_read_filter(&event) {
+                    return Vec::new();
+                }
+
+                let action = self.send_to_widget_request(NotifyNewEphemeralEvent(event)).1;
+                action.map(|a| vec![a]).unwrap_or_default()
+            }
+            IncomingMessage::RoomStateLagged => self.resync_room_state(),
+            IncomingMessage::ToDeviceEventReceived(event) => {
+                let CapabilitiesState::Negotiated(capabilities) = &self.capabilities else {
+                    error!("Received to-device event before capabilities negotiation");
+                    return Vec::new();
+                };
+
+                if !capabilities.raw_to_device_event_matches_read_filter(&event) {
+                    return Vec::new();
+                }
+
+                let action = self.send_to_widget_request(NotifyNewToDeviceEvent(event)).1;
+                action.map(|a| vec![a]).unwrap_or_default()
+            }
         }
     }
 
@@ -169,10 +282,16 @@ impl WidgetMachine {
         let message = match serde_json::from_str::<IncomingWidgetMessage>(raw) {
             Ok(msg) => msg,
             Err(e) => {
-                // TODO: There is a special error handling required for the invalid
-                // messages. Refer to the `widget-api-poc` for implementation notes.
                 error!("Failed to parse incoming message: {e}");
-                return Vec::new();
+                let diagnostic = validation::validate_message(raw, &e);
+
+                // The widget can only be answered if the ID of its request could
+                // be found, otherwise only the host application is told about it.
+                let response = self.respond_to_malformed_request(raw, &diagnostic);
+                return response
+                    .into_iter()
+                    .chain(iter::once(Action::ReportDiagnostic(diagnostic)))
+                    .collect();
             }
         };
 
@@ -199,9 +318,25 @@ impl WidgetMachine {
     ) -> Vec<Action> {
         let request = match raw_request.deserialize() {
             Ok(r) => r,
-            Err(e) => return vec![self.send_from_widget_error_response(raw_request, e)],
+            Err(e) => {
+                debug!("Received an invalid request: {e}");
+                let diagnostic = validation::validate_message(raw_request.json().get(), &e);
+                let response =
+                    FromWidgetErrorResponse::invalid_request(diagnostic.problems.clone());
+
+                return vec![
+                    self.send_from_widget_response(raw_request, response),
+                    Action::ReportDiagnostic(diagnostic),
+                ];
+            }
         };
 
+        if request.is_rate_limited() {
+            if let Some(Err(rejection)) = self.rate_limiter.as_mut().map(RateLimiter::try_acquire) {
+                return self.reject_rate_limited_request(raw_request, rejection);
+            }
+        }
+
         match request {
             FromWidgetRequest::SupportedApiVersions {} => {
                 let response = SupportedApiVersionsResponse::new();
@@ -221,11 +356,36 @@ impl WidgetMachine {
                 .map(|a| vec![a])
                 .unwrap_or_default(),
 
+            FromWidgetRequest::ReadRelations(req) => self
+                .process_read_relations_request(req, raw_request)
+                .map(|a| vec![a])
+                .unwrap_or_default(),
+
             FromWidgetRequest::SendEvent(req) => self
                 .process_send_event_request(req, raw_request)
                 .map(|a| vec![a])
                 .unwrap_or_default(),
 
+            FromWidgetRequest::SendEphemeralEvent(req) => self
+                .process_send_ephemeral_event_request(req, raw_request)
+                .map(|a| vec![a])
+                .unwrap_or_default(),
+
+            FromWidgetRequest::SendToDevice(req) => self
+                .process_send_to_device_request(req, raw_request)
+                .map(|a| vec![a])
+                .unwrap_or_default(),
+
+            FromWidgetRequest::UpdateDelayedEvent(req) => self
+                .process_update_delayed_event_request(req, raw_request)
+                .map(|a| vec![a])
+                .unwrap_or_default(),
+
+            FromWidgetRequest::PickFile(req) => self
+                .process_pick_file_request(req, raw_request)
+                .map(|a| vec![a])
+                .unwrap_or_default(),
+
             FromWidgetRequest::GetOpenId {} => {
                 let (request, request_action) = self.send_matrix_driver_request(RequestOpenId);
                 request.then(|res, machine| {
@@ -247,26 +407,59 @@ impl WidgetMachine {
         }
     }
 
+    fn reject_rate_limited_request(
+        &self,
+        raw_request: Raw<FromWidgetRequest>,
+        rejection: Rejection,
+    ) -> Vec<Action> {
+        let message = match rejection.retry_after {
+            Some(retry_after) => {
+                format!("Too many requests, retry after {} ms", retry_after.as_millis())
+            }
+            None => "Too many requests".to_owned(),
+        };
+        let response = self.send_from_widget_error_response(raw_request, message);
+
+        let notification = rejection.report.map(|rejected_requests| {
+            warn!(rejected_requests, "The widget keeps making requests over its rate limits");
+            Action::NotifyRateLimitExceeded { rejected_requests }
+        });
+
+        iter::once(response).chain(notification).collect()
+    }
+
     fn process_read_event_request(
         &mut self,
         request: ReadEventRequest,
         raw_request: Raw<FromWidgetRequest>,
     ) -> Option<Action> {
-        let CapabilitiesState::Negotiated(capabilities) = &self.capabilities else {
-            let text = "Received read event request before capabilities were negotiated";
-            return Some(self.send_from_widget_error_response(raw_request, text));
+        let capabilities = match &self.capabilities {
+            CapabilitiesState::Negotiated(capabilities) => capabilities,
+            CapabilitiesState::Deferred { .. } => {
+                return Some(
+                    self.send_from_widget_error_response(raw_request, WAITING_FOR_CAPABILITIES),
+                );
+            }
+            _ => {
+                let text = "Received read event request before capabilities were negotiated";
+                return Some(self.send_from_widget_error_response(raw_request, text));
+            }
         };
 
         match request {
-            ReadEventRequest::ReadMessageLikeEvent { event_type, limit } => {
+            ReadEventRequest::ReadMessageLikeEvent { event_type, limit, room_ids } => {
                 let filter_fn = |f: &EventFilter| f.matches_message_like_event_type(&event_type);
                 if !capabilities.read.iter().any(filter_fn) {
                     return Some(self.send_from_widget_error_response(raw_request, "Not allowed"));
                 }
 
+                let Some(rooms) = self.read_rooms(capabilities, room_ids) else {
+                    return Some(self.send_from_widget_error_response(raw_request, "Not allowed"));
+                };
+
                 const DEFAULT_EVENT_LIMIT: u32 = 50;
                 let limit = limit.unwrap_or(DEFAULT_EVENT_LIMIT);
-                let request = ReadMessageLikeEventRequest { event_type, limit };
+                let request = ReadMessageLikeEventRequest { rooms, event_type, limit };
                 let (request, action) = self.send_matrix_driver_request(request);
                 request.then(|result, machine| {
                     let response = result.and_then(|mut events| {
@@ -276,14 +469,14 @@ impl WidgetMachine {
                             return Err(err.into());
                         };
 
-                        events.retain(|e| capabilities.raw_event_matches_read_filter(e));
+                        events.retain(|e| capabilities.raw_event_is_readable(e, &machine.room_id));
                         Ok(ReadEventResponse { events })
                     });
                     vec![machine.send_from_widget_result_response(raw_request, response)]
                 });
                 action
             }
-            ReadEventRequest::ReadStateEvent { event_type, state_key } => {
+            ReadEventRequest::ReadStateEvent { event_type, state_key, room_ids } => {
                 let allowed = match &state_key {
                     StateKeySelector::Any => capabilities
                         .read
@@ -302,8 +495,9 @@ impl WidgetMachine {
                     }
                 };
 
-                if allowed {
-                    let request = ReadStateEventRequest { event_type, state_key };
+                let rooms = self.read_rooms(capabilities, room_ids);
+                if let Some(rooms) = rooms.filter(|_| allowed) {
+                    let request = ReadStateEventRequest { rooms, event_type, state_key };
                     let (request, action) = self.send_matrix_driver_request(request);
                     request.then(|result, machine| {
                         let response = result.map(|events| ReadEventResponse { events });
@@ -317,14 +511,149 @@ impl WidgetMachine {
         }
     }
 
+    /// Read again the state events of the room that the widget can read, and
+    /// send them to the widget, after some updates of the state were missed.
+    fn resync_room_state(&mut self) -> Vec<Action> {
+        let CapabilitiesState::Negotiated(capabilities) = &self.capabilities else {
+            return Vec::new();
+        };
+
+        let requests: Vec<_> = capabilities
+            .read
+            .iter()
+            .filter_map(|filter| {
+                let EventFilter::State(filter) = filter else { return None };
+                let (event_type, state_key) = match filter {
+                    StateEventFilter::WithType(event_type) => {
+                        (event_type.clone(), StateKeySelector::Any)
+                    }
+                    StateEventFilter::WithTypeAndStateKey(event_type, state_key) => {
+                        (event_type.clone(), StateKeySelector::Key(state_key.clone()))
+                    }
+                };
+                let rooms = ReadRooms::Rooms(vec![self.room_id.clone()]);
+                Some(ReadStateEventRequest { rooms, event_type, state_key })
+            })
+            .collect();
+
+        debug!(requests = requests.len(), "Reading the room state again after missing updates");
+
+        requests
+            .into_iter()
+            .filter_map(|request| {
+                let (request, action) = self.send_matrix_driver_request(request);
+                request.then(|result, machine| {
+                    let events = match result {
+                        Ok(events) => events,
+                        Err(e) => {
+                            warn!("Couldn't read the room state again: {e}");
+                            return Vec::new();
+                        }
+                    };
+
+                    events
+                        .into_iter()
+                        .filter_map(|event| {
+                            machine.send_to_widget_request(NotifyNewMatrixEvent(event)).1
+                        })
+                        .collect()
+                });
+                action
+            })
+            .collect()
+    }
+
+    /// The rooms to read events from for the `room_ids` of a read request, or
+    /// `None` if the widget can't access some of them.
+    fn read_rooms(
+        &self,
+        capabilities: &Capabilities,
+        room_ids: Option<ReadRoomIds>,
+    ) -> Option<ReadRooms> {
+        match room_ids {
+            None => Some(ReadRooms::Rooms(vec![self.room_id.clone()])),
+            Some(ReadRoomIds::Rooms(room_ids)) => room_ids
+                .iter()
+                .all(|room_id| capabilities.timeline_rooms.allows(room_id, &self.room_id))
+                .then_some(ReadRooms::Rooms(room_ids)),
+            Some(ReadRoomIds::All) => Some(match &capabilities.timeline_rooms {
+                TimelineRooms::OwnRoom => ReadRooms::Rooms(vec![self.room_id.clone()]),
+                TimelineRooms::Rooms(room_ids) => {
+                    let other_room_ids = room_ids.iter().filter(|id| **id != self.room_id);
+                    let room_ids = iter::once(&self.room_id).chain(other_room_ids);
+                    ReadRooms::Rooms(room_ids.cloned().collect())
+                }
+                TimelineRooms::AllJoined => ReadRooms::AllJoined,
+            }),
+        }
+    }
+
+    fn process_read_relations_request(
+        &mut self,
+        mut request: ReadRelationsRequest,
+        raw_request: Raw<FromWidgetRequest>,
+    ) -> Option<Action> {
+        let capabilities = match &self.capabilities {
+            CapabilitiesState::Negotiated(capabilities) => capabilities,
+            CapabilitiesState::Deferred { .. } => {
+                return Some(
+                    self.send_from_widget_error_response(raw_request, WAITING_FOR_CAPABILITIES),
+                );
+            }
+            _ => {
+                let text = "Received read relations request before capabilities were negotiated";
+                return Some(self.send_from_widget_error_response(raw_request, text));
+            }
+        };
+
+        if capabilities.read.is_empty() {
+            return Some(self.send_from_widget_error_response(raw_request, "Not allowed"));
+        }
+
+        if request.room_id.as_ref().is_some_and(|room_id| *room_id != self.room_id) {
+            let text = "Reading the relations of events in other rooms is not supported";
+            return Some(self.send_from_widget_error_response(raw_request, text));
+        }
+
+        const DEFAULT_RELATIONS_LIMIT: u32 = 50;
+        const MAX_RELATIONS_LIMIT: u32 = 200;
+        request.limit =
+            Some(request.limit.map_or(DEFAULT_RELATIONS_LIMIT, |l| l.min(MAX_RELATIONS_LIMIT)));
+
+        let (request, action) = self.send_matrix_driver_request(request);
+        request.then(|result, machine| {
+            let response = result.and_then(|mut response| {
+                let CapabilitiesState::Negotiated(capabilities) = &machine.capabilities else {
+                    let err = "Received read relations request before capabilities negotiation";
+                    return Err(err.into());
+                };
+
+                // Only return the related events the widget is allowed to read,
+                // the pagination tokens are still valid.
+                response.chunk.retain(|e| capabilities.raw_event_matches_read_filter(e));
+                Ok(response)
+            });
+            vec![machine.send_from_widget_result_response(raw_request, response)]
+        });
+        action
+    }
+
     fn process_send_event_request(
         &mut self,
         request: SendEventRequest,
         raw_request: Raw<FromWidgetRequest>,
     ) -> Option<Action> {
-        let CapabilitiesState::Negotiated(capabilities) = &self.capabilities else {
-            error!("Received send event request before capabilities negotiation");
-            return None;
+        let capabilities = match &self.capabilities {
+            CapabilitiesState::Negotiated(capabilities) => capabilities,
+            CapabilitiesState::Deferred { .. } => {
+                return Some(
+                    self.send_from_widget_error_response(raw_request, WAITING_FOR_CAPABILITIES),
+                );
+            }
+            _ => {
+                error!("Received send event request before capabilities negotiation");
+                return None;
+            }
         };
 
         let filter_in = MatrixEventFilterInput {
@@ -342,10 +671,155 @@ impl WidgetMachine {
             return Some(self.send_from_widget_error_response(raw_request, "Not allowed"));
         }
 
+        let room_id = request.room_id.clone().unwrap_or_else(|| self.room_id.clone());
+        if !capabilities.timeline_rooms.allows(&room_id, &self.room_id) {
+            return Some(self.send_from_widget_error_response(
+                raw_request,
+                "Not allowed to send events to this room",
+            ));
+        }
+
+        if request.delay.is_some() && !capabilities.send_delayed_event {
+            return Some(self.send_from_widget_error_response(
+                raw_request,
+                "Not allowed to send delayed events",
+            ));
+        }
+
+        let (request, action) = self.send_matrix_driver_request(request);
+        request.then(move |result, machine| {
+            let room_id = &room_id;
+            let response = result.map(|sent| match sent {
+                SentEvent::Sent(event_id) => {
+                    SendEventResponse { room_id, event_id: Some(event_id), delay_id: None }
+                }
+                SentEvent::Delayed(delay_id) => {
+                    SendEventResponse { room_id, event_id: None, delay_id: Some(delay_id) }
+                }
+            });
+            vec![machine.send_from_widget_result_response(raw_request, response)]
+        });
+        action
+    }
+
+    fn process_send_ephemeral_event_request(
+        &mut self,
+        request: SendEphemeralEventRequest,
+        raw_request: Raw<FromWidgetRequest>,
+    ) -> Option<Action> {
+        let capabilities = match &self.capabilities {
+            CapabilitiesState::Negotiated(capabilities) => capabilities,
+            CapabilitiesState::Deferred { .. } => {
+                return Some(
+                    self.send_from_widget_error_response(raw_request, WAITING_FOR_CAPABILITIES),
+                );
+            }
+            _ => {
+                error!("Received send ephemeral event request before capabilities negotiation");
+                return None;
+            }
+        };
+
+        let filter_fn = |f: &EventFilter| f.matches_ephemeral_event_type(&request.event_type);
+        if !capabilities.send.iter().any(filter_fn) {
+            return Some(self.send_from_widget_error_response(raw_request, "Not allowed"));
+        }
+
         let (request, action) = self.send_matrix_driver_request(request);
         request.then(|result, machine| {
             let room_id = &machine.room_id;
-            let response = result.map(|event_id| SendEventResponse { event_id, room_id });
+            let response = result.map(|()| SendEphemeralEventResponse { room_id });
+            vec![machine.send_from_widget_result_response(raw_request, response)]
+        });
+        action
+    }
+
+    fn process_send_to_device_request(
+        &mut self,
+        request: SendToDeviceRequest,
+        raw_request: Raw<FromWidgetRequest>,
+    ) -> Option<Action> {
+        let capabilities = match &self.capabilities {
+            CapabilitiesState::Negotiated(capabilities) => capabilities,
+            CapabilitiesState::Deferred { .. } => {
+                return Some(
+                    self.send_from_widget_error_response(raw_request, WAITING_FOR_CAPABILITIES),
+                );
+            }
+            _ => {
+                error!("Received send to-device request before capabilities negotiation");
+                return None;
+            }
+        };
+
+        let filter_fn = |f: &EventFilter| f.matches_to_device_event_type(&request.event_type);
+        if !capabilities.send.iter().any(filter_fn) {
+            return Some(self.send_from_widget_error_response(raw_request, "Not allowed"));
+        }
+
+        let (request, action) = self.send_matrix_driver_request(request);
+        request.then(|result, machine| {
+            let response = result.map(|failures| SendToDeviceResponse { failures });
+            vec![machine.send_from_widget_result_response(raw_request, response)]
+        });
+        action
+    }
+
+    fn process_update_delayed_event_request(
+        &mut self,
+        request: UpdateDelayedEventRequest,
+        raw_request: Raw<FromWidgetRequest>,
+    ) -> Option<Action> {
+        let capabilities = match &self.capabilities {
+            CapabilitiesState::Negotiated(capabilities) => capabilities,
+            CapabilitiesState::Deferred { .. } => {
+                return Some(
+                    self.send_from_widget_error_response(raw_request, WAITING_FOR_CAPABILITIES),
+                );
+            }
+            _ => {
+                error!("Received update delayed event request before capabilities negotiation");
+                return None;
+            }
+        };
+
+        if !capabilities.update_delayed_event {
+            return Some(self.send_from_widget_error_response(raw_request, "Not allowed"));
+        }
+
+        let (request, action) = self.send_matrix_driver_request(request);
+        request.then(|result, machine| {
+            let response = result.map(|()| UpdateDelayedEventResponse {});
+            vec![machine.send_from_widget_result_response(raw_request, response)]
+        });
+        action
+    }
+
+    fn process_pick_file_request(
+        &mut self,
+        request: PickFileRequest,
+        raw_request: Raw<FromWidgetRequest>,
+    ) -> Option<Action> {
+        let capabilities = match &self.capabilities {
+            CapabilitiesState::Negotiated(capabilities) => capabilities,
+            CapabilitiesState::Deferred { .. } => {
+                return Some(
+                    self.send_from_widget_error_response(raw_request, WAITING_FOR_CAPABILITIES),
+                );
+            }
+            _ => {
+                error!("Received pick file request before capabilities negotiation");
+                return None;
+            }
+        };
+
+        if !capabilities.pick_file {
+            return Some(self.send_from_widget_error_response(raw_request, "Not allowed"));
+        }
+
+        let (request, action) = self.send_matrix_driver_request(request);
+        request.then(|result, machine| {
+            let response = result.map(|file| PickFileResponse { file });
             vec![machine.send_from_widget_result_response(raw_request, response)]
         });
         action
@@ -390,6 +864,23 @@ impl WidgetMachine {
         request_id: Uuid,
         response: Result<MatrixDriverResponse, String>,
     ) -> Vec<Action> {
+        if matches!(
+            &self.capabilities,
+            CapabilitiesState::Deferred { request_id: deferred_id, .. } if *deferred_id == request_id
+        ) {
+            let CapabilitiesState::Deferred { meta, .. } =
+                mem::replace(&mut self.capabilities, CapabilitiesState::Negotiating)
+            else {
+                unreachable!("the capabilities state was just checked");
+            };
+
+            info!("Received the deferred capabilities decision");
+            return meta
+                .response_fn
+                .map(|response_fn| response_fn(response, self))
+                .unwrap_or_default();
+        }
+
         match self.pending_matrix_driver_requests.extract(&request_id) {
             Ok(request) => request
                 .response_fn
@@ -402,6 +893,19 @@ impl WidgetMachine {
         }
     }
 
+    /// Keep the request for the capabilities, without letting it expire,
+    /// until the decision about them is made.
+    #[instrument(skip_all, fields(?request_id))]
+    fn defer_capabilities(&mut self, request_id: Uuid) {
+        match self.pending_matrix_driver_requests.extract(&request_id) {
+            Ok(meta) => {
+                info!("The capabilities decision was deferred, waiting for it");
+                self.capabilities = CapabilitiesState::Deferred { request_id, meta };
+            }
+            Err(e) => warn!("Could not defer the capabilities request: {e}"),
+        }
+    }
+
     #[instrument(skip_all, fields(request_id))]
     fn send_from_widget_response(
         &self,
@@ -418,6 +922,29 @@ impl WidgetMachine {
         Action::SendToWidget(serialized)
     }
 
+    /// Answer a `fromWidget` request whose envelope couldn't be parsed, if it
+    /// has a request ID and isn't meant for another widget.
+    fn respond_to_malformed_request(
+        &self,
+        raw: &str,
+        diagnostic: &WidgetDiagnostic,
+    ) -> Option<Action> {
+        diagnostic.request_id.as_ref()?;
+
+        let message = serde_json::from_str::<JsonObject>(raw).ok()?;
+        if message.get("api").and_then(JsonValue::as_str) != Some("fromWidget") {
+            return None;
+        }
+        if message.get("widgetId").is_some_and(|id| id.as_str() != Some(&self.widget_id)) {
+            return None;
+        }
+
+        let raw_request = Raw::from_json(RawJsonValue::from_string(raw.to_owned()).ok()?);
+        let response = FromWidgetErrorResponse::invalid_request(diagnostic.problems.clone());
+
+        Some(self.send_from_widget_response(raw_request, response))
+    }
+
     fn send_from_widget_error_response(
         &self,
         raw_request: Raw<FromWidgetRequest>,
@@ -459,13 +986,21 @@ impl WidgetMachine {
             data: to_widget_request,
         };
 
-        let request_meta = ToWidgetRequestMeta::new(T::ACTION);
+        let serialized = serde_json::to_string(&full_request).expect("Failed to serialize request");
+
+        let mut request_meta = ToWidgetRequestMeta::new(T::ACTION);
+        if T::IDEMPOTENT && self.to_widget_retries > 0 {
+            request_meta.retry = Some(ToWidgetRetry {
+                message: serialized.clone(),
+                retries_left: self.to_widget_retries,
+            });
+        }
+
         let Some(meta) = self.pending_to_widget_requests.insert(request_id, request_meta) else {
             warn!("Reached limits of pending requests for toWidget requests");
             return (ToWidgetRequestHandle::null(), None);
         };
 
-        let serialized = serde_json::to_string(&full_request).expect("Failed to serialize request");
         (ToWidgetRequestHandle::new(meta), Some(Action::SendToWidget(serialized)))
     }
 
@@ -476,8 +1011,11 @@ impl WidgetMachine {
     ) -> (MatrixDriverRequestHandle<'_, T::Response>, Option<Action>) {
         let request_id = Uuid::new_v4();
         let request_meta = MatrixDriverRequestMeta::new();
-        let Some(meta) = self.pending_matrix_driver_requests.insert(request_id, request_meta)
-        else {
+        let Some(meta) = self.pending_matrix_driver_requests.insert_with_timeout(
+            request_id,
+            request_meta,
+            T::RESPONSE_TIMEOUT,
+        ) else {
             warn!("Reached limits of pending requests for matrix driver requests");
             return (MatrixDriverRequestHandle::null(), None);
         };
@@ -505,12 +1043,14 @@ impl WidgetMachine {
                 });
 
                 let subscribe_required = !approved.read.is_empty();
+                let timeline_rooms = approved.timeline_rooms.clone();
                 machine.capabilities = CapabilitiesState::Negotiated(approved.clone());
 
                 let update = NotifyCapabilitiesChanged { approved, requested };
                 let (_request, action) = machine.send_to_widget_request(update);
 
-                (subscribe_required).then(|| Action::Subscribe).into_iter().chain(action).collect()
+                let subscribe = subscribe_required.then(|| Action::Subscribe { timeline_rooms });
+                subscribe.into_iter().chain(action).collect()
             });
 
             action.map(|a| vec![a]).unwrap_or_default()
@@ -526,14 +1066,23 @@ type ToWidgetResponseFn =
 pub(crate) struct ToWidgetRequestMeta {
     action: &'static str,
     response_fn: Option<ToWidgetResponseFn>,
+    /// How to send the request again if the widget doesn't answer it in
+    /// time, if it can be.
+    retry: Option<ToWidgetRetry>,
 }
 
 impl ToWidgetRequestMeta {
     fn new(action: &'static str) -> Self {
-        Self { action, response_fn: None }
+        Self { action, response_fn: None, retry: None }
     }
 }
 
+struct ToWidgetRetry {
+    /// The serialized request, sent again as is.
+    message: String,
+    retries_left: u32,
+}
+
 type MatrixDriverResponseFn =
     Box<dyn FnOnce(Result<MatrixDriverResponse, String>, &mut WidgetMachine) -> Vec<Action> + Send>;
 
@@ -550,6 +1099,12 @@ impl MatrixDriverRequestMeta {
 enum CapabilitiesState {
     Unset,
     Negotiating,
+    /// The decision about the capabilities was deferred, the request waits
+    /// here for it instead of expiring.
+    Deferred {
+        request_id: Uuid,
+        meta: MatrixDriverRequestMeta,
+    },
     Negotiated(Capabilities),
 }
 
@@ -15,13 +15,16 @@
 //! A wrapper around a hash map that tracks pending requests and makes sure
 //! that expired requests are removed.
 
-use std::time::{Duration, Instant};
+use std::{mem, sync::Arc, time::Duration};
 
 use indexmap::{map::Entry, IndexMap};
+use matrix_sdk_common::{clock::Clock, instant::Instant};
 use tracing::warn;
 use uuid::Uuid;
 
-/// Configuration of limits for the outgoing request handling.
+use crate::widget::RateLimits;
+
+/// Configuration of limits for the request handling.
 #[derive(Clone, Debug)]
 pub(crate) struct RequestLimits {
     /// Maximum amount of unanswered (pending) requests that the client widget
@@ -33,6 +36,30 @@ pub(crate) struct RequestLimits {
     /// it is dropped. This ensures that requests that are not answered within
     /// a ceratin amount of time, are dropped/cleaned up (considered as failed).
     pub(crate) response_timeout: Duration,
+    /// For how long the widget has to answer a `toWidget` request before it is
+    /// dropped, like `response_timeout` for the requests to the
+    /// `MatrixDriver`.
+    pub(crate) to_widget_response_timeout: Duration,
+    /// How many times a `toWidget` request that can safely be repeated is sent
+    /// again when the widget doesn't answer it in time, before giving up on
+    /// it.
+    pub(crate) to_widget_retries: u32,
+    /// Limits on the rate of requests coming from the widget, if any. This
+    /// ensures that a misbehaving widget cannot flood the homeserver through
+    /// the client.
+    pub(crate) rate_limits: Option<RateLimits>,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self {
+            max_pending_requests: 15,
+            response_timeout: Duration::from_secs(10),
+            to_widget_response_timeout: Duration::from_secs(10),
+            to_widget_retries: 0,
+            rate_limits: None,
+        }
+    }
 }
 
 /// A wrapper around a hash map that ensures that the request limits
@@ -43,17 +70,31 @@ pub(crate) struct RequestLimits {
 pub(super) struct PendingRequests<T> {
     requests: IndexMap<Uuid, Expirable<T>>,
     limits: RequestLimits,
+    clock: Arc<dyn Clock>,
 }
 
 impl<T> PendingRequests<T> {
-    pub(super) fn new(limits: RequestLimits) -> Self {
-        Self { requests: IndexMap::with_capacity(limits.max_pending_requests), limits }
+    pub(super) fn new(limits: RequestLimits, clock: Arc<dyn Clock>) -> Self {
+        Self { requests: IndexMap::with_capacity(limits.max_pending_requests), limits, clock }
     }
 
     /// Inserts a new request into the map.
     ///
     /// Returns `None` if the maximum allowed capacity is reached.
     pub(super) fn insert(&mut self, key: Uuid, value: T) -> Option<&mut T> {
+        self.insert_with_timeout(key, value, None)
+    }
+
+    /// Inserts a new request into the map, that expires after `timeout`
+    /// instead of the response timeout of the limits, if it is longer.
+    ///
+    /// Returns `None` if the maximum allowed capacity is reached.
+    pub(super) fn insert_with_timeout(
+        &mut self,
+        key: Uuid,
+        value: T,
+        timeout: Option<Duration>,
+    ) -> Option<&mut T> {
         if self.requests.len() >= self.limits.max_pending_requests {
             return None;
         }
@@ -62,7 +103,10 @@ impl<T> PendingRequests<T> {
             panic!("uuid collision");
         };
 
-        let expirable = Expirable::new(value, Instant::now() + self.limits.response_timeout);
+        let timeout = timeout.map_or(self.limits.response_timeout, |timeout| {
+            timeout.max(self.limits.response_timeout)
+        });
+        let expirable = Expirable::new(value, self.clock.now() + timeout);
         let inserted = entry.insert(expirable);
         Some(&mut inserted.value)
     }
@@ -72,18 +116,25 @@ impl<T> PendingRequests<T> {
     /// Returns `None` if the value is not present or expired.
     pub(super) fn extract(&mut self, key: &Uuid) -> Result<T, &'static str> {
         let value = self.requests.remove(key).ok_or("Received response for an unknown request")?;
-        value.value().ok_or("Dropping response for an expired request")
+        value.value(self.clock.now()).ok_or("Dropping response for an expired request")
     }
 
     /// Removes all expired requests from the map.
-    pub(super) fn remove_expired(&mut self) {
-        self.requests.retain(|id, req| {
-            let expired = req.expired();
-            if expired {
+    ///
+    /// Returns the removed requests, in the order they were inserted.
+    pub(super) fn remove_expired(&mut self) -> Vec<(Uuid, T)> {
+        let now = self.clock.now();
+        let (expired, pending): (IndexMap<_, _>, _) =
+            mem::take(&mut self.requests).into_iter().partition(|(_, req)| req.expired(now));
+        self.requests = pending;
+
+        expired
+            .into_iter()
+            .map(|(id, req)| {
                 warn!(?id, "Dropping response for an expired request");
-            }
-            !expired
-        });
+                (id, req.value)
+            })
+            .collect()
     }
 }
 
@@ -97,19 +148,20 @@ impl<T> Expirable<T> {
         Self { value, expires_at }
     }
 
-    fn value(self) -> Option<T> {
-        (!self.expired()).then_some(self.value)
+    fn value(self, now: Instant) -> Option<T> {
+        (!self.expired(now)).then_some(self.value)
     }
 
-    fn expired(&self) -> bool {
-        Instant::now() >= self.expires_at
+    fn expired(&self, now: Instant) -> bool {
+        now >= self.expires_at
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::time::Duration;
+    use std::{sync::Arc, time::Duration};
 
+    use matrix_sdk_common::clock::{system_clock, TestClock};
     use uuid::Uuid;
 
     use super::{PendingRequests, RequestLimits};
@@ -118,10 +170,14 @@ mod tests {
 
     #[test]
     fn insertion_limits_for_pending_requests_work() {
-        let mut pending: PendingRequests<Dummy> = PendingRequests::new(RequestLimits {
-            max_pending_requests: 1,
-            response_timeout: Duration::from_secs(10),
-        });
+        let mut pending: PendingRequests<Dummy> = PendingRequests::new(
+            RequestLimits {
+                max_pending_requests: 1,
+                response_timeout: Duration::from_secs(10),
+                ..Default::default()
+            },
+            system_clock(),
+        );
 
         // First insert is ok.
         let first = Uuid::new_v4();
@@ -147,17 +203,22 @@ mod tests {
 
     #[test]
     fn time_limits_for_pending_requests_work() {
-        let mut pending: PendingRequests<Dummy> = PendingRequests::new(RequestLimits {
-            max_pending_requests: 10,
-            response_timeout: Duration::from_secs(1),
-        });
+        let clock = TestClock::new();
+        let mut pending: PendingRequests<Dummy> = PendingRequests::new(
+            RequestLimits {
+                max_pending_requests: 10,
+                response_timeout: Duration::from_secs(1),
+                ..Default::default()
+            },
+            Arc::new(clock.clone()),
+        );
 
         // Insert a request, it's fine, limits are high.
         let key = Uuid::new_v4();
         assert!(pending.insert(key, Dummy).is_some());
 
         // Wait for 2 seconds, the inserted request should lapse.
-        std::thread::sleep(Duration::from_secs(2));
+        clock.advance(Duration::from_secs(2));
         assert!(pending.extract(&key).is_err());
 
         // Insert 2 requests. Should be fine, limits are high.
@@ -167,7 +228,7 @@ mod tests {
         // Wait for half a second, remove expired ones (none must be removed).
         // Then, add another one (should also be fine, limits are high). So
         // we should have 3 requests in a hash map.
-        std::thread::sleep(Duration::from_millis(500));
+        clock.advance(Duration::from_millis(500));
         pending.remove_expired();
         let key = Uuid::new_v4();
         assert!(pending.insert(key, Dummy).is_some());
@@ -175,7 +236,7 @@ mod tests {
 
         // Wait for another half a second. First two requests should lapse.
         // But the last one should still be in the map.
-        std::thread::sleep(Duration::from_millis(500));
+        clock.advance(Duration::from_millis(500));
         pending.remove_expired();
         assert!(pending.requests.len() == 1);
         assert!(pending.extract(&key).is_ok());
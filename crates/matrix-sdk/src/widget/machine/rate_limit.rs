@@ -0,0 +1,125 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A token bucket limiting the rate of requests coming from the widget.
+
+use std::{sync::Arc, time::Duration};
+
+use matrix_sdk_common::{clock::Clock, instant::Instant};
+
+use crate::widget::RateLimits;
+
+/// For how long a widget must keep hitting the rate limits before it is
+/// reported to the host application.
+const PERSISTENT_ABUSE_DELAY: Duration = Duration::from_secs(10);
+
+/// A request that was rejected by the [`RateLimiter`].
+#[derive(Debug)]
+pub(super) struct Rejection {
+    /// How long the widget should wait before its next request is accepted,
+    /// `None` if the limits never let new requests through.
+    pub(super) retry_after: Option<Duration>,
+    /// The number of requests rejected since the widget started hitting the
+    /// limits, if it has been doing so for long enough that the host
+    /// application should know about it.
+    ///
+    /// This is only set once per abuse period.
+    pub(super) report: Option<u64>,
+}
+
+pub(super) struct RateLimiter {
+    limits: RateLimits,
+    clock: Arc<dyn Clock>,
+    /// The number of requests that can be made right now.
+    tokens: f64,
+    last_refill: Instant,
+    /// When the widget started hitting the limits, `None` if it has been
+    /// well-behaved long enough to fill the bucket again.
+    limited_since: Option<Instant>,
+    rejected_requests: u64,
+    reported: bool,
+}
+
+impl RateLimiter {
+    pub(super) fn new(limits: RateLimits, clock: Arc<dyn Clock>) -> Self {
+        let last_refill = clock.now();
+        Self {
+            tokens: Self::capacity(&limits),
+            limits,
+            clock,
+            last_refill,
+            limited_since: None,
+            rejected_requests: 0,
+            reported: false,
+        }
+    }
+
+    /// Try to consume a token for a new request.
+    pub(super) fn try_acquire(&mut self) -> Result<(), Rejection> {
+        let now = self.clock.now();
+        self.refill(now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+
+        let limited_since = *self.limited_since.get_or_insert(now);
+        self.rejected_requests += 1;
+
+        let report = (!self.reported
+            && elapsed_between(limited_since, now) >= PERSISTENT_ABUSE_DELAY)
+            .then(|| {
+                self.reported = true;
+                self.rejected_requests
+            });
+
+        let retry_after = (self.limits.requests_per_second > 0).then(|| {
+            Duration::from_secs_f64(
+                (1.0 - self.tokens) / f64::from(self.limits.requests_per_second),
+            )
+        });
+
+        Err(Rejection { retry_after, report })
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = elapsed_between(self.last_refill, now);
+        self.last_refill = now;
+
+        let capacity = Self::capacity(&self.limits);
+        let refilled = elapsed.as_secs_f64() * f64::from(self.limits.requests_per_second);
+        self.tokens = (self.tokens + refilled).min(capacity);
+
+        // The widget calmed down, start a new abuse period next time it hits
+        // the limits.
+        if self.tokens >= capacity {
+            self.limited_since = None;
+            self.rejected_requests = 0;
+            self.reported = false;
+        }
+    }
+
+    fn capacity(limits: &RateLimits) -> f64 {
+        f64::from(limits.burst.max(1))
+    }
+}
+
+fn elapsed_between(earlier: Instant, later: Instant) -> Duration {
+    if later > earlier {
+        later - earlier
+    } else {
+        Duration::ZERO
+    }
+}
@@ -14,6 +14,7 @@
 
 use assert_matches::assert_matches;
 use assert_matches2::assert_let;
+use matrix_sdk_common::clock::system_clock;
 use ruma::owned_room_id;
 use serde_json::{from_value, json};
 
@@ -25,14 +26,16 @@ use crate::widget::machine::{
 #[test]
 fn machine_can_negotiate_capabilities_immediately() {
     let room_id = owned_room_id!("!a98sd12bjh:example.org");
-    let (mut machine, actions) = WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None);
+    let (mut machine, actions) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None, system_clock());
     assert_capabilities_dance(&mut machine, actions, None);
 }
 
 #[test]
 fn machine_can_request_capabilities_on_content_load() {
     let room_id = owned_room_id!("!a98sd12bjh:example.org");
-    let (mut machine, actions) = WidgetMachine::new(WIDGET_ID.to_owned(), room_id, true, None);
+    let (mut machine, actions) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, true, None, system_clock());
     assert!(actions.is_empty());
 
     // Content loaded event processed.
@@ -69,7 +72,8 @@ fn machine_can_request_capabilities_on_content_load() {
 #[test]
 fn capabilities_failure_results_into_empty_capabilities() {
     let room_id = owned_room_id!("!a98sd12bjh:example.org");
-    let (mut machine, actions) = WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None);
+    let (mut machine, actions) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None, system_clock());
 
     // Ask widget to provide desired capabilities.
     let actions = {
@@ -136,6 +140,86 @@ fn capabilities_failure_results_into_empty_capabilities() {
     );
 }
 
+#[test]
+fn deferred_capabilities_make_the_widget_wait() {
+    let room_id = owned_room_id!("!a98sd12bjh:example.org");
+    let (mut machine, actions) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None, system_clock());
+    let capability = "org.matrix.msc2762.receive.event:m.room.message";
+
+    // Ask widget to provide desired capabilities.
+    let actions = {
+        let [action]: [Action; 1] = actions.try_into().unwrap();
+        assert_let!(Action::SendToWidget(msg) = action);
+        let (_msg, request_id) = parse_msg(&msg);
+
+        machine.process(IncomingMessage::WidgetMessage(json_string!({
+            "api": "toWidget",
+            "widgetId": WIDGET_ID,
+            "requestId": request_id,
+            "action": "capabilities",
+            "data": {},
+            "response": {
+                "capabilities": [capability],
+            },
+        })))
+    };
+
+    // The decision about the capabilities is deferred.
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(
+        Action::MatrixDriverRequest {
+            request_id,
+            data: MatrixDriverRequestData::AcquireCapabilities(data)
+        } = action
+    );
+    let actions = machine.process(IncomingMessage::CapabilitiesDeferred { request_id });
+    assert!(actions.is_empty());
+
+    // The widget is told to wait in the meantime.
+    let actions = machine.process(IncomingMessage::WidgetMessage(json_string!({
+        "api": "fromWidget",
+        "widgetId": WIDGET_ID,
+        "requestId": "get-me-some-messages",
+        "action": "org.matrix.msc2876.read_events",
+        "data": {
+            "type": "m.room.message",
+        },
+    })));
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::SendToWidget(msg) = action);
+    let (msg, request_id_str) = parse_msg(&msg);
+    assert_eq!(request_id_str, "get-me-some-messages");
+    assert_eq!(
+        msg["response"]["error"]["message"].as_str().unwrap(),
+        "Waiting for the capabilities to be approved"
+    );
+
+    // The negotiation resumes once the decision is made.
+    let response = Ok(MatrixDriverResponse::CapabilitiesAcquired(data.desired_capabilities));
+    let mut actions =
+        machine.process(IncomingMessage::MatrixDriverResponse { request_id, response });
+
+    let action = actions.remove(0);
+    assert_matches!(action, Action::Subscribe { .. });
+
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::SendToWidget(msg) = action);
+    let (msg, _request_id) = parse_msg(&msg);
+    assert_eq!(
+        msg,
+        json!({
+            "api": "toWidget",
+            "widgetId": WIDGET_ID,
+            "action": "notify_capabilities",
+            "data": {
+                "requested": [capability],
+                "approved": [capability],
+            },
+        }),
+    );
+}
+
 /// Performs a capability "dance", if no capability is specified, we assume that
 /// it's: `org.matrix.msc2762.receive.state_event:m.room.member`.
 pub(super) fn assert_capabilities_dance(
@@ -145,7 +229,17 @@ pub(super) fn assert_capabilities_dance(
 ) {
     let capability =
         capability_str.unwrap_or("org.matrix.msc2762.receive.state_event:m.room.member");
+    assert_capabilities_dance_with(machine, actions, &[capability]);
+}
 
+/// Like [`assert_capabilities_dance`], with several capabilities.
+///
+/// The capabilities must be in the order in which they are serialized.
+pub(super) fn assert_capabilities_dance_with(
+    machine: &mut WidgetMachine,
+    actions: Vec<Action>,
+    capabilities: &[&str],
+) {
     // Ask widget to provide desired capabilities.
     let actions = {
         let [action]: [Action; 1] = actions.try_into().unwrap();
@@ -168,7 +262,7 @@ pub(super) fn assert_capabilities_dance(
             "action": "capabilities",
             "data": {},
             "response": {
-                "capabilities": [capability],
+                "capabilities": capabilities,
             },
         })))
     };
@@ -182,10 +276,10 @@ pub(super) fn assert_capabilities_dance(
                 data: MatrixDriverRequestData::AcquireCapabilities(data)
             } = action
         );
-        let capabilities = data.desired_capabilities;
-        assert_eq!(capabilities, from_value(json!([capability])).unwrap());
+        let desired_capabilities = data.desired_capabilities;
+        assert_eq!(desired_capabilities, from_value(json!(capabilities)).unwrap());
 
-        let response = Ok(MatrixDriverResponse::CapabilitiesAcquired(capabilities));
+        let response = Ok(MatrixDriverResponse::CapabilitiesAcquired(desired_capabilities));
         let message = IncomingMessage::MatrixDriverResponse { request_id, response };
         machine.process(message)
     };
@@ -193,10 +287,10 @@ pub(super) fn assert_capabilities_dance(
     // We get the `Subscribe` command if we requested some reading capabilities.
     if ["org.matrix.msc2762.receive.state_event", "org.matrix.msc2762.receive.event"]
         .into_iter()
-        .any(|c| capability.starts_with(c))
+        .any(|c| capabilities.iter().any(|capability| capability.starts_with(c)))
     {
         let action = actions.remove(0);
-        assert_matches!(action, Action::Subscribe);
+        assert_matches!(action, Action::Subscribe { .. });
     }
 
     // Inform the widget about the acquired capabilities.
@@ -211,8 +305,8 @@ pub(super) fn assert_capabilities_dance(
                 "widgetId": WIDGET_ID,
                 "action": "notify_capabilities",
                 "data": {
-                    "requested": [capability],
-                    "approved": [capability],
+                    "requested": capabilities,
+                    "approved": capabilities,
                 },
             }),
         );
@@ -223,8 +317,8 @@ pub(super) fn assert_capabilities_dance(
             "requestId": request_id,
             "action": "notify_capabilities",
             "data": {
-                "requested": [capability],
-                "approved": [capability],
+                "requested": capabilities,
+                "approved": capabilities,
             },
             "response": {},
         })));
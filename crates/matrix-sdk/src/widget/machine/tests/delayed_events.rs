@@ -0,0 +1,178 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use assert_matches2::assert_let;
+use matrix_sdk_common::clock::system_clock;
+use ruma::owned_room_id;
+use serde_json::json;
+
+use super::{
+    capabilities::{assert_capabilities_dance, assert_capabilities_dance_with},
+    parse_msg, WIDGET_ID,
+};
+use crate::{
+    room::DelayedEventAction,
+    widget::machine::{
+        incoming::MatrixDriverResponse, Action, IncomingMessage, MatrixDriverRequestData,
+        WidgetMachine,
+    },
+};
+
+const SEND_CALL_MEMBER: &str = "org.matrix.msc2762.send.state_event:org.matrix.msc3401.call.member";
+
+#[test]
+fn send_delayed_state_event() {
+    let room_id = owned_room_id!("!a98sd12bjh:example.org");
+    let (mut machine, actions) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None, system_clock());
+    assert_capabilities_dance_with(
+        &mut machine,
+        actions,
+        &["org.matrix.msc4157.send.delayed_event", SEND_CALL_MEMBER],
+    );
+
+    let actions = machine.process(IncomingMessage::WidgetMessage(json_string!({
+        "api": "fromWidget",
+        "widgetId": WIDGET_ID,
+        "requestId": "send-leave",
+        "action": "send_event",
+        "data": {
+            "type": "org.matrix.msc3401.call.member",
+            "state_key": "@alice:example.org",
+            "content": {},
+            "delay": 5000,
+        },
+    })));
+
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(
+        Action::MatrixDriverRequest {
+            request_id,
+            data: MatrixDriverRequestData::SendMatrixEvent(request)
+        } = action
+    );
+    assert_eq!(request.event_type.to_string(), "org.matrix.msc3401.call.member");
+    assert_eq!(request.state_key.as_deref(), Some("@alice:example.org"));
+    assert_eq!(request.delay, Some(5000));
+
+    let response = Ok(MatrixDriverResponse::DelayedEventSent("delay-1".to_owned()));
+    let actions = machine.process(IncomingMessage::MatrixDriverResponse { request_id, response });
+
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::SendToWidget(msg) = action);
+    let (msg, request_id) = parse_msg(&msg);
+    assert_eq!(request_id, "send-leave");
+    assert_eq!(
+        msg["response"],
+        json!({
+            "room_id": "!a98sd12bjh:example.org",
+            "delay_id": "delay-1",
+        })
+    );
+}
+
+#[test]
+fn send_delayed_event_without_capability_is_rejected() {
+    let room_id = owned_room_id!("!a98sd12bjh:example.org");
+    let (mut machine, actions) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None, system_clock());
+    assert_capabilities_dance(&mut machine, actions, Some(SEND_CALL_MEMBER));
+
+    let actions = machine.process(IncomingMessage::WidgetMessage(json_string!({
+        "api": "fromWidget",
+        "widgetId": WIDGET_ID,
+        "requestId": "send-leave",
+        "action": "send_event",
+        "data": {
+            "type": "org.matrix.msc3401.call.member",
+            "state_key": "@alice:example.org",
+            "content": {},
+            "delay": 5000,
+        },
+    })));
+
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::SendToWidget(msg) = action);
+    let (msg, _) = parse_msg(&msg);
+    assert_eq!(
+        msg["response"]["error"]["message"].as_str().unwrap(),
+        "Not allowed to send delayed events"
+    );
+}
+
+#[test]
+fn update_delayed_event() {
+    let room_id = owned_room_id!("!a98sd12bjh:example.org");
+    let (mut machine, actions) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None, system_clock());
+    assert_capabilities_dance(
+        &mut machine,
+        actions,
+        Some("org.matrix.msc4157.update_delayed_event"),
+    );
+
+    let actions = machine.process(IncomingMessage::WidgetMessage(json_string!({
+        "api": "fromWidget",
+        "widgetId": WIDGET_ID,
+        "requestId": "restart-leave",
+        "action": "org.matrix.msc4157.update_delayed_event",
+        "data": {
+            "delay_id": "delay-1",
+            "action": "restart",
+        },
+    })));
+
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(
+        Action::MatrixDriverRequest {
+            request_id,
+            data: MatrixDriverRequestData::UpdateDelayedEvent(request)
+        } = action
+    );
+    assert_eq!(request.delay_id, "delay-1");
+    assert_eq!(request.action, DelayedEventAction::Restart);
+
+    let response = Ok(MatrixDriverResponse::DelayedEventUpdated);
+    let actions = machine.process(IncomingMessage::MatrixDriverResponse { request_id, response });
+
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::SendToWidget(msg) = action);
+    let (msg, request_id) = parse_msg(&msg);
+    assert_eq!(request_id, "restart-leave");
+    assert_eq!(msg["response"], json!({}));
+}
+
+#[test]
+fn update_delayed_event_without_capability_is_rejected() {
+    let room_id = owned_room_id!("!a98sd12bjh:example.org");
+    let (mut machine, actions) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None, system_clock());
+    assert_capabilities_dance(&mut machine, actions, Some(SEND_CALL_MEMBER));
+
+    let actions = machine.process(IncomingMessage::WidgetMessage(json_string!({
+        "api": "fromWidget",
+        "widgetId": WIDGET_ID,
+        "requestId": "cancel-leave",
+        "action": "org.matrix.msc4157.update_delayed_event",
+        "data": {
+            "delay_id": "delay-1",
+            "action": "cancel",
+        },
+    })));
+
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::SendToWidget(msg) = action);
+    let (msg, _) = parse_msg(&msg);
+    assert_eq!(msg["response"]["error"]["message"].as_str().unwrap(), "Not allowed");
+}
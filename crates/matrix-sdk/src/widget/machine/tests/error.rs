@@ -12,17 +12,21 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use assert_matches2::assert_let;
+use assert_matches2::{assert_let, assert_matches};
+use matrix_sdk_common::clock::system_clock;
 use ruma::owned_room_id;
 use serde_json::json;
 
 use super::{capabilities::assert_capabilities_dance, parse_msg, WIDGET_ID};
-use crate::widget::machine::{Action, IncomingMessage, WidgetMachine};
+use crate::widget::machine::{
+    Action, IncomingMessage, ValidationProblem, ValidationProblemKind, WidgetMachine,
+};
 
 #[test]
 fn machine_sends_error_for_unknown_request() {
     let room_id = owned_room_id!("!a98sd12bjh:example.org");
-    let (mut machine, _) = WidgetMachine::new(WIDGET_ID.to_owned(), room_id, true, None);
+    let (mut machine, _) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, true, None, system_clock());
 
     let actions = machine.process(IncomingMessage::WidgetMessage(json_string!({
         "api": "fromWidget",
@@ -34,7 +38,7 @@ fn machine_sends_error_for_unknown_request() {
         },
     })));
 
-    let [action]: [Action; 1] = actions.try_into().unwrap();
+    let [action, diagnostic]: [Action; 2] = actions.try_into().unwrap();
     assert_let!(Action::SendToWidget(msg) = action);
     let (msg, request_id) = parse_msg(&msg);
     assert_eq!(request_id, "invalid-req");
@@ -42,7 +46,166 @@ fn machine_sends_error_for_unknown_request() {
     assert_eq!(msg["widgetId"], WIDGET_ID);
     assert_eq!(msg["action"], "I AM ERROR");
     assert_eq!(msg["data"], json!({ "some": "field" }));
-    assert!(msg["response"]["error"]["message"].is_string());
+    assert_eq!(
+        msg["response"]["error"]["message"],
+        "Invalid request: action: unknown action `I AM ERROR`"
+    );
+    assert_eq!(
+        msg["response"]["error"]["problems"],
+        json!([{ "path": "action", "kind": "unknown_action", "action": "I AM ERROR" }])
+    );
+
+    assert_let!(Action::ReportDiagnostic(diagnostic) = diagnostic);
+    assert_eq!(diagnostic.request_id.as_deref(), Some("invalid-req"));
+    assert_eq!(diagnostic.action.as_deref(), Some("I AM ERROR"));
+    assert_eq!(
+        diagnostic.problems,
+        [ValidationProblem {
+            path: "action".to_owned(),
+            kind: ValidationProblemKind::UnknownAction { action: "I AM ERROR".to_owned() },
+        }]
+    );
+}
+
+#[test]
+fn machine_reports_missing_fields_and_wrong_types() {
+    let room_id = owned_room_id!("!a98sd12bjh:example.org");
+    let (mut machine, _) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, true, None, system_clock());
+
+    let actions = machine.process(IncomingMessage::WidgetMessage(json_string!({
+        "api": "fromWidget",
+        "widgetId": WIDGET_ID,
+        "requestId": "invalid-send",
+        "action": "send_event",
+        "data": {
+            "state_key": 42,
+        },
+    })));
+
+    let [action, diagnostic]: [Action; 2] = actions.try_into().unwrap();
+    assert_let!(Action::SendToWidget(msg) = action);
+    let (msg, request_id) = parse_msg(&msg);
+    assert_eq!(request_id, "invalid-send");
+    assert_eq!(
+        msg["response"]["error"]["message"],
+        "Invalid request: data.type: missing field; \
+         data.state_key: expected a string, found a number; data.content: missing field"
+    );
+
+    assert_let!(Action::ReportDiagnostic(diagnostic) = diagnostic);
+    assert_eq!(
+        diagnostic.problems,
+        [
+            ValidationProblem {
+                path: "data.type".to_owned(),
+                kind: ValidationProblemKind::MissingField,
+            },
+            ValidationProblem {
+                path: "data.state_key".to_owned(),
+                kind: ValidationProblemKind::WrongType { expected: "a string", found: "a number" },
+            },
+            ValidationProblem {
+                path: "data.content".to_owned(),
+                kind: ValidationProblemKind::MissingField,
+            },
+        ]
+    );
+}
+
+#[test]
+fn machine_reports_invalid_values() {
+    let room_id = owned_room_id!("!a98sd12bjh:example.org");
+    let (mut machine, _) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, true, None, system_clock());
+
+    // The schema doesn't know about the format of room IDs, the parsing error
+    // is reported instead.
+    let actions = machine.process(IncomingMessage::WidgetMessage(json_string!({
+        "api": "fromWidget",
+        "widgetId": WIDGET_ID,
+        "requestId": "invalid-room",
+        "action": "send_event",
+        "data": {
+            "type": "m.room.message",
+            "room_id": "not a room ID",
+            "content": {},
+        },
+    })));
+
+    let [action, diagnostic]: [Action; 2] = actions.try_into().unwrap();
+    assert_let!(Action::SendToWidget(msg) = action);
+    let (msg, _) = parse_msg(&msg);
+    assert!(msg["response"]["error"]["message"].as_str().unwrap().starts_with("Invalid request"));
+
+    assert_let!(Action::ReportDiagnostic(diagnostic) = diagnostic);
+    assert_let!([problem] = diagnostic.problems.as_slice());
+    assert_eq!(problem.path, "data");
+    assert_matches!(problem.kind, ValidationProblemKind::InvalidValue { .. });
+}
+
+#[test]
+fn machine_reports_unparseable_messages() {
+    let room_id = owned_room_id!("!a98sd12bjh:example.org");
+    let (mut machine, _) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, true, None, system_clock());
+
+    // Without a request ID, the widget can't be answered.
+    let actions = machine.process(IncomingMessage::WidgetMessage(json_string!({
+        "api": "fromWidget",
+        "widgetId": WIDGET_ID,
+        "action": "content_loaded",
+        "data": {},
+    })));
+
+    let [diagnostic]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::ReportDiagnostic(diagnostic) = diagnostic);
+    assert_eq!(diagnostic.request_id, None);
+    assert_eq!(diagnostic.action.as_deref(), Some("content_loaded"));
+    assert_eq!(
+        diagnostic.problems,
+        [ValidationProblem {
+            path: "requestId".to_owned(),
+            kind: ValidationProblemKind::MissingField,
+        }]
+    );
+
+    // With a request ID, the widget is answered even if the envelope is invalid.
+    let actions = machine.process(IncomingMessage::WidgetMessage(json_string!({
+        "api": "fromWidget",
+        "requestId": "no-widget-id",
+        "action": "content_loaded",
+        "data": {},
+    })));
+
+    let [action, diagnostic]: [Action; 2] = actions.try_into().unwrap();
+    assert_let!(Action::SendToWidget(msg) = action);
+    let (msg, request_id) = parse_msg(&msg);
+    assert_eq!(request_id, "no-widget-id");
+    assert_eq!(msg["response"]["error"]["message"], "Invalid request: widgetId: missing field");
+
+    assert_let!(Action::ReportDiagnostic(diagnostic) = diagnostic);
+    assert_eq!(diagnostic.request_id.as_deref(), Some("no-widget-id"));
+
+    // A message meant for another widget isn't answered.
+    let actions = machine.process(IncomingMessage::WidgetMessage(json_string!({
+        "api": "fromWidget",
+        "widgetId": "another-widget",
+        "requestId": "wrong-widget",
+        "action": 42,
+        "data": {},
+    })));
+
+    let [diagnostic]: [Action; 1] = actions.try_into().unwrap();
+    assert_matches!(diagnostic, Action::ReportDiagnostic(_));
+
+    let actions = machine.process(IncomingMessage::WidgetMessage("{ this isn't JSON".to_owned()));
+
+    let [diagnostic]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::ReportDiagnostic(diagnostic) = diagnostic);
+    assert_let!([problem] = diagnostic.problems.as_slice());
+    assert_eq!(problem.path, "");
+    assert_matches!(problem.kind, ValidationProblemKind::InvalidJson { .. });
 }
 
 #[test]
@@ -52,6 +215,7 @@ fn read_messages_without_capabilities() {
         owned_room_id!("!a98sd12bjh:example.org"),
         true,
         None,
+        system_clock(),
     );
 
     let actions = machine.process(IncomingMessage::WidgetMessage(json_string!({
@@ -79,7 +243,8 @@ fn read_messages_without_capabilities() {
 #[test]
 fn read_request_for_non_allowed_message_like_events() {
     let room_id = owned_room_id!("!a98sd12bjh:example.org");
-    let (mut machine, actions) = WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None);
+    let (mut machine, actions) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None, system_clock());
     assert_capabilities_dance(&mut machine, actions, None);
 
     let actions = machine.process(IncomingMessage::WidgetMessage(json_string!({
@@ -104,7 +269,8 @@ fn read_request_for_non_allowed_message_like_events() {
 #[test]
 fn read_request_for_non_allowed_state_events() {
     let room_id = owned_room_id!("!a98sd12bjh:example.org");
-    let (mut machine, actions) = WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None);
+    let (mut machine, actions) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None, system_clock());
     assert_capabilities_dance(&mut machine, actions, None);
 
     let actions = machine.process(IncomingMessage::WidgetMessage(json_string!({
@@ -130,7 +296,8 @@ fn read_request_for_non_allowed_state_events() {
 #[test]
 fn send_request_for_non_allowed_state_events() {
     let room_id = owned_room_id!("!a98sd12bjh:example.org");
-    let (mut machine, actions) = WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None);
+    let (mut machine, actions) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None, system_clock());
     assert_capabilities_dance(
         &mut machine,
         actions,
@@ -162,7 +329,8 @@ fn send_request_for_non_allowed_state_events() {
 #[test]
 fn send_request_for_non_allowed_message_like_events() {
     let room_id = owned_room_id!("!a98sd12bjh:example.org");
-    let (mut machine, actions) = WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None);
+    let (mut machine, actions) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None, system_clock());
     assert_capabilities_dance(
         &mut machine,
         actions,
@@ -194,7 +362,8 @@ fn send_request_for_non_allowed_message_like_events() {
 #[test]
 fn read_request_for_message_like_with_disallowed_msg_type_fails() {
     let room_id = owned_room_id!("!a98sd12bjh:example.org");
-    let (mut machine, actions) = WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None);
+    let (mut machine, actions) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None, system_clock());
     assert_capabilities_dance(
         &mut machine,
         actions,
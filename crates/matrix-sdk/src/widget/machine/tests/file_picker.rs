@@ -0,0 +1,128 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{sync::Arc, time::Duration};
+
+use assert_matches2::assert_let;
+use matrix_sdk_common::clock::{system_clock, TestClock};
+use ruma::{owned_mxc_uri, owned_room_id};
+use serde_json::json;
+
+use super::{
+    capabilities::{assert_capabilities_dance, assert_capabilities_dance_with},
+    parse_msg, WIDGET_ID,
+};
+use crate::widget::machine::{
+    incoming::MatrixDriverResponse, Action, IncomingMessage, MatrixDriverRequestData, UploadedFile,
+    WidgetMachine,
+};
+
+const PICK_FILE: &str = "io.element.pick_file";
+
+fn pick_file_request() -> IncomingMessage {
+    IncomingMessage::WidgetMessage(json_string!({
+        "api": "fromWidget",
+        "widgetId": WIDGET_ID,
+        "requestId": "pick-a-file",
+        "action": "io.element.pick_file",
+        "data": {
+            "accept": ["image/*"],
+        },
+    }))
+}
+
+#[test]
+fn pick_file_without_capability_is_rejected() {
+    let room_id = owned_room_id!("!a98sd12bjh:example.org");
+    let (mut machine, actions) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None, system_clock());
+    assert_capabilities_dance(&mut machine, actions, None);
+
+    let actions = machine.process(pick_file_request());
+
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::SendToWidget(msg) = action);
+    let (msg, request_id) = parse_msg(&msg);
+    assert_eq!(request_id, "pick-a-file");
+    assert_eq!(msg["response"]["error"]["message"], "Not allowed");
+}
+
+#[test]
+fn picked_file_is_sent_to_widget() {
+    let room_id = owned_room_id!("!a98sd12bjh:example.org");
+    let (mut machine, actions) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None, system_clock());
+    assert_capabilities_dance_with(&mut machine, actions, &[PICK_FILE]);
+
+    let actions = machine.process(pick_file_request());
+
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(
+        Action::MatrixDriverRequest {
+            request_id,
+            data: MatrixDriverRequestData::PickFile(request)
+        } = action
+    );
+    assert_eq!(request.accept, ["image/*"]);
+
+    let file = UploadedFile {
+        content_uri: owned_mxc_uri!("mxc://example.org/cat"),
+        name: "cat.png".to_owned(),
+        mimetype: "image/png".to_owned(),
+        size: 42,
+        file: None,
+    };
+    let response = Ok(MatrixDriverResponse::FilePicked(Some(file)));
+    let actions = machine.process(IncomingMessage::MatrixDriverResponse { request_id, response });
+
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::SendToWidget(msg) = action);
+    let (msg, request_id) = parse_msg(&msg);
+    assert_eq!(request_id, "pick-a-file");
+    assert_eq!(
+        msg["response"],
+        json!({
+            "file": {
+                "content_uri": "mxc://example.org/cat",
+                "name": "cat.png",
+                "mimetype": "image/png",
+                "size": 42,
+            },
+        })
+    );
+}
+
+#[test]
+fn pick_file_waits_longer_than_other_requests() {
+    let clock = TestClock::new();
+    let room_id = owned_room_id!("!a98sd12bjh:example.org");
+    let (mut machine, actions) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None, Arc::new(clock.clone()));
+    assert_capabilities_dance_with(&mut machine, actions, &[PICK_FILE]);
+
+    let actions = machine.process(pick_file_request());
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::MatrixDriverRequest { request_id, .. } = action);
+
+    // The user takes a while to pick a file, and cancels.
+    clock.advance(Duration::from_secs(60));
+    let response = Ok(MatrixDriverResponse::FilePicked(None));
+    let actions = machine.process(IncomingMessage::MatrixDriverResponse { request_id, response });
+
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::SendToWidget(msg) = action);
+    let (msg, request_id) = parse_msg(&msg);
+    assert_eq!(request_id, "pick-a-file");
+    assert_eq!(msg["response"], json!({}));
+}
@@ -24,8 +24,16 @@ macro_rules! json_string {
 
 mod api_versions;
 mod capabilities;
+mod delayed_events;
 mod error;
+mod file_picker;
 mod openid;
+mod rate_limit;
+mod relations;
+mod room_state;
+mod timeline_rooms;
+mod to_device;
+mod unresponsive;
 
 const WIDGET_ID: &str = "test-widget";
 
@@ -0,0 +1,132 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{sync::Arc, time::Duration};
+
+use assert_matches2::assert_let;
+use matrix_sdk_common::clock::TestClock;
+use ruma::owned_room_id;
+
+use super::{parse_msg, WIDGET_ID};
+use crate::widget::{
+    machine::{Action, IncomingMessage, MatrixDriverRequestData, RequestLimits, WidgetMachine},
+    RateLimits,
+};
+
+fn rate_limited_machine(clock: &TestClock) -> WidgetMachine {
+    let limits = RequestLimits {
+        max_pending_requests: 100,
+        rate_limits: Some(RateLimits { requests_per_second: 1, burst: 2 }),
+        ..Default::default()
+    };
+    let (machine, _) = WidgetMachine::new(
+        WIDGET_ID.to_owned(),
+        owned_room_id!("!a98sd12bjh:example.org"),
+        true,
+        Some(limits),
+        Arc::new(clock.clone()),
+    );
+    machine
+}
+
+fn request_openid(machine: &mut WidgetMachine) -> Vec<Action> {
+    machine.process(IncomingMessage::WidgetMessage(json_string!({
+        "api": "fromWidget",
+        "widgetId": WIDGET_ID,
+        "requestId": "openid-request-id",
+        "action": "get_openid",
+        "data": {},
+    })))
+}
+
+fn assert_rate_limited(action: &Action) {
+    assert_let!(Action::SendToWidget(msg) = action);
+    let (msg, _request_id) = parse_msg(msg);
+    let message = msg["response"]["error"]["message"].as_str().unwrap();
+    assert!(message.starts_with("Too many requests"), "unexpected error: {message}");
+}
+
+#[test]
+fn requests_over_the_rate_limits_are_rejected() {
+    let clock = TestClock::new();
+    let mut machine = rate_limited_machine(&clock);
+
+    // The burst goes through.
+    for _ in 0..2 {
+        let actions = request_openid(&mut machine);
+        let [_response, driver_request]: [Action; 2] = actions.try_into().unwrap();
+        assert_let!(
+            Action::MatrixDriverRequest { data: MatrixDriverRequestData::GetOpenId, .. } =
+                driver_request
+        );
+    }
+
+    // The next request is rejected without reaching the driver.
+    let actions = request_openid(&mut machine);
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_rate_limited(&action);
+
+    // Once enough time passed, the widget can make a new request.
+    clock.advance(Duration::from_secs(1));
+    let actions = request_openid(&mut machine);
+    assert_eq!(actions.len(), 2);
+
+    // Requests that don't reach the homeserver are never rate limited.
+    let actions = machine.process(IncomingMessage::WidgetMessage(json_string!({
+        "api": "fromWidget",
+        "widgetId": WIDGET_ID,
+        "requestId": "get-supported-api-versions",
+        "action": "supported_api_versions",
+        "data": {},
+    })));
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::SendToWidget(msg) = action);
+    let (msg, _request_id) = parse_msg(&msg);
+    assert!(msg["response"]["supported_versions"].is_array());
+}
+
+#[test]
+fn persistent_abuse_is_reported_once() {
+    let clock = TestClock::new();
+    let mut machine = rate_limited_machine(&clock);
+
+    // Use up the burst.
+    for _ in 0..2 {
+        request_openid(&mut machine);
+    }
+
+    // The widget keeps making requests twice as fast as it is allowed to, so
+    // every other request is rejected.
+    let mut notifications = Vec::new();
+    for _ in 0..40 {
+        clock.advance(Duration::from_millis(500));
+
+        let actions = request_openid(&mut machine);
+        notifications.extend(actions.into_iter().filter_map(|action| match action {
+            Action::NotifyRateLimitExceeded { rejected_requests } => {
+                Some((clock.elapsed(), rejected_requests))
+            }
+            _ => None,
+        }));
+    }
+
+    // It is reported once, after hitting the limits for 10 seconds, when 11
+    // requests were rejected.
+    assert_eq!(notifications, [(Duration::from_millis(10_500), 11)]);
+
+    // Once it calms down, it starts with a clean slate.
+    clock.advance(Duration::from_secs(5));
+    let actions = request_openid(&mut machine);
+    assert_eq!(actions.len(), 2);
+}
@@ -0,0 +1,156 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use assert_matches2::assert_let;
+use matrix_sdk_common::clock::system_clock;
+use ruma::{api::Direction, event_id, events::relation::RelationType, owned_room_id, serde::Raw};
+use serde_json::json;
+
+use super::{capabilities::assert_capabilities_dance, parse_msg, WIDGET_ID};
+use crate::widget::machine::{
+    incoming::MatrixDriverResponse, Action, IncomingMessage, MatrixDriverRequestData,
+    ReadRelationsResponse, WidgetMachine,
+};
+
+#[test]
+fn read_relations_filters_events_by_capabilities() {
+    let room_id = owned_room_id!("!a98sd12bjh:example.org");
+    let (mut machine, actions) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None, system_clock());
+    assert_capabilities_dance(
+        &mut machine,
+        actions,
+        Some("org.matrix.msc2762.receive.event:m.reaction"),
+    );
+
+    let actions = machine.process(IncomingMessage::WidgetMessage(json_string!({
+        "api": "fromWidget",
+        "widgetId": WIDGET_ID,
+        "requestId": "get-me-some-relations",
+        "action": "org.matrix.msc3869.read_relations",
+        "data": {
+            "event_id": "$poll",
+            "rel_type": "m.annotation",
+            "from": "page-1",
+            "direction": "f",
+        },
+    })));
+
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(
+        Action::MatrixDriverRequest {
+            request_id,
+            data: MatrixDriverRequestData::ReadRelations(request)
+        } = action
+    );
+    assert_eq!(request.event_id, event_id!("$poll"));
+    assert_eq!(request.rel_type, Some(RelationType::Annotation));
+    assert_eq!(request.from.as_deref(), Some("page-1"));
+    assert_eq!(request.direction, Some(Direction::Forward));
+    assert_eq!(request.limit, Some(50));
+
+    let reaction = json!({
+        "type": "m.reaction",
+        "event_id": "$reaction",
+        "room_id": "!a98sd12bjh:example.org",
+        "sender": "@alice:example.org",
+        "origin_server_ts": 0,
+        "content": {
+            "m.relates_to": { "rel_type": "m.annotation", "event_id": "$poll", "key": "👍" },
+        },
+    });
+    let edit = json!({
+        "type": "m.room.message",
+        "event_id": "$edit",
+        "room_id": "!a98sd12bjh:example.org",
+        "sender": "@alice:example.org",
+        "origin_server_ts": 0,
+        "content": {
+            "body": "* edited",
+            "msgtype": "m.text",
+            "m.relates_to": { "rel_type": "m.replace", "event_id": "$poll" },
+        },
+    });
+
+    let response = Ok(MatrixDriverResponse::RelationsRead(ReadRelationsResponse {
+        chunk: vec![Raw::new(&reaction).unwrap().cast(), Raw::new(&edit).unwrap().cast()],
+        next_batch: Some("page-2".to_owned()),
+        prev_batch: None,
+    }));
+    let actions = machine.process(IncomingMessage::MatrixDriverResponse { request_id, response });
+
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::SendToWidget(msg) = action);
+    let (msg, request_id) = parse_msg(&msg);
+    assert_eq!(request_id, "get-me-some-relations");
+    assert_eq!(msg["action"], "org.matrix.msc3869.read_relations");
+    assert_eq!(msg["response"], json!({ "chunk": [reaction], "next_batch": "page-2" }));
+}
+
+#[test]
+fn read_relations_in_other_rooms_is_rejected() {
+    let room_id = owned_room_id!("!a98sd12bjh:example.org");
+    let (mut machine, actions) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None, system_clock());
+    assert_capabilities_dance(&mut machine, actions, None);
+
+    let actions = machine.process(IncomingMessage::WidgetMessage(json_string!({
+        "api": "fromWidget",
+        "widgetId": WIDGET_ID,
+        "requestId": "get-me-some-relations",
+        "action": "org.matrix.msc3869.read_relations",
+        "data": {
+            "event_id": "$poll",
+            "room_id": "!other:example.org",
+        },
+    })));
+
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::SendToWidget(msg) = action);
+    let (msg, _) = parse_msg(&msg);
+    assert_eq!(
+        msg["response"]["error"]["message"].as_str().unwrap(),
+        "Reading the relations of events in other rooms is not supported"
+    );
+}
+
+#[test]
+fn read_relations_limit_is_clamped() {
+    let room_id = owned_room_id!("!a98sd12bjh:example.org");
+    let (mut machine, actions) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None, system_clock());
+    assert_capabilities_dance(
+        &mut machine,
+        actions,
+        Some("org.matrix.msc2762.receive.event:m.reaction"),
+    );
+
+    let actions = machine.process(IncomingMessage::WidgetMessage(json_string!({
+        "api": "fromWidget",
+        "widgetId": WIDGET_ID,
+        "requestId": "get-me-all-the-relations",
+        "action": "org.matrix.msc3869.read_relations",
+        "data": {
+            "event_id": "$poll",
+            "limit": 100000,
+        },
+    })));
+
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(
+        Action::MatrixDriverRequest { data: MatrixDriverRequestData::ReadRelations(request), .. } =
+            action
+    );
+    assert_eq!(request.limit, Some(200));
+}
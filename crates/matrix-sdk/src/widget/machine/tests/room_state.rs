@@ -0,0 +1,83 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use assert_matches2::{assert_let, assert_matches};
+use matrix_sdk_common::clock::system_clock;
+use ruma::{events::StateEventType, owned_room_id, serde::Raw};
+use serde_json::json;
+
+use super::{capabilities::assert_capabilities_dance_with, parse_msg, WIDGET_ID};
+use crate::widget::{
+    machine::{
+        incoming::MatrixDriverResponse, Action, IncomingMessage, MatrixDriverRequestData,
+        ReadRooms, WidgetMachine,
+    },
+    StateKeySelector,
+};
+
+const READ_MEMBERS: &str = "org.matrix.msc2762.receive.state_event:m.room.member";
+const READ_TOPIC: &str = "org.matrix.msc2762.receive.state_event:m.room.topic#";
+
+#[test]
+fn room_state_is_read_again_after_missing_updates() {
+    let room_id = owned_room_id!("!a98sd12bjh:example.org");
+    let (mut machine, actions) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id.clone(), false, None, system_clock());
+    assert_capabilities_dance_with(&mut machine, actions, &[READ_MEMBERS, READ_TOPIC]);
+
+    let actions = machine.process(IncomingMessage::RoomStateLagged);
+
+    // The state is read again for every state event filter of the widget.
+    let [members, topic]: [Action; 2] = actions.try_into().unwrap();
+    assert_let!(
+        Action::MatrixDriverRequest {
+            request_id,
+            data: MatrixDriverRequestData::ReadStateEvent(request)
+        } = members
+    );
+    assert_eq!(request.rooms, ReadRooms::Rooms(vec![room_id]));
+    assert_eq!(request.event_type, StateEventType::RoomMember);
+    assert_matches!(request.state_key, StateKeySelector::Any);
+
+    assert_let!(
+        Action::MatrixDriverRequest {
+            data: MatrixDriverRequestData::ReadStateEvent(request),
+            ..
+        } = topic
+    );
+    assert_eq!(request.event_type, StateEventType::RoomTopic);
+    assert_let!(StateKeySelector::Key(state_key) = request.state_key);
+    assert_eq!(state_key, "");
+
+    // The current state is sent to the widget like new events.
+    let member = json!({
+        "type": "m.room.member",
+        "event_id": "$member",
+        "room_id": "!a98sd12bjh:example.org",
+        "sender": "@alice:example.org",
+        "state_key": "@alice:example.org",
+        "origin_server_ts": 0,
+        "content": { "membership": "join" },
+    });
+    let response =
+        Ok(MatrixDriverResponse::MatrixEventRead(vec![Raw::new(&member).unwrap().cast()]));
+    let actions = machine.process(IncomingMessage::MatrixDriverResponse { request_id, response });
+
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::SendToWidget(msg) = action);
+    let (msg, _request_id) = parse_msg(&msg);
+    assert_eq!(msg["api"], "toWidget");
+    assert_eq!(msg["action"], "send_event");
+    assert_eq!(msg["data"], member);
+}
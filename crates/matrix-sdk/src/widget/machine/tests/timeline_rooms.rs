@@ -0,0 +1,190 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use assert_matches2::assert_let;
+use matrix_sdk_common::clock::system_clock;
+use ruma::{owned_event_id, owned_room_id, serde::Raw};
+use serde_json::json;
+
+use super::{capabilities::assert_capabilities_dance_with, parse_msg, WIDGET_ID};
+use crate::widget::machine::{
+    incoming::MatrixDriverResponse, Action, IncomingMessage, MatrixDriverRequestData, ReadRooms,
+    WidgetMachine,
+};
+
+const TIMELINE_OTHER: &str = "org.matrix.msc2762.timeline:!other:example.org";
+const READ_MESSAGE: &str = "org.matrix.msc2762.receive.event:m.room.message";
+const SEND_MESSAGE: &str = "org.matrix.msc2762.send.event:m.room.message";
+
+fn message_in(room_id: &str) -> serde_json::Value {
+    json!({
+        "type": "m.room.message",
+        "event_id": "$message",
+        "room_id": room_id,
+        "sender": "@alice:example.org",
+        "origin_server_ts": 0,
+        "content": { "body": "hello", "msgtype": "m.text" },
+    })
+}
+
+#[test]
+fn read_events_in_all_accessible_rooms() {
+    let room_id = owned_room_id!("!a98sd12bjh:example.org");
+    let (mut machine, actions) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id.clone(), false, None, system_clock());
+    assert_capabilities_dance_with(&mut machine, actions, &[TIMELINE_OTHER, READ_MESSAGE]);
+
+    let actions = machine.process(IncomingMessage::WidgetMessage(json_string!({
+        "api": "fromWidget",
+        "widgetId": WIDGET_ID,
+        "requestId": "get-me-some-messages",
+        "action": "org.matrix.msc2876.read_events",
+        "data": {
+            "type": "m.room.message",
+            "room_ids": "*",
+        },
+    })));
+
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(
+        Action::MatrixDriverRequest {
+            request_id,
+            data: MatrixDriverRequestData::ReadMessageLikeEvent(request)
+        } = action
+    );
+    assert_eq!(
+        request.rooms,
+        ReadRooms::Rooms(vec![room_id, owned_room_id!("!other:example.org")])
+    );
+
+    // The events of rooms the widget can't access are filtered out.
+    let own = message_in("!a98sd12bjh:example.org");
+    let other = message_in("!other:example.org");
+    let third = message_in("!third:example.org");
+    let response = Ok(MatrixDriverResponse::MatrixEventRead(vec![
+        Raw::new(&own).unwrap().cast(),
+        Raw::new(&other).unwrap().cast(),
+        Raw::new(&third).unwrap().cast(),
+    ]));
+    let actions = machine.process(IncomingMessage::MatrixDriverResponse { request_id, response });
+
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::SendToWidget(msg) = action);
+    let (msg, request_id) = parse_msg(&msg);
+    assert_eq!(request_id, "get-me-some-messages");
+    assert_eq!(msg["response"], json!({ "events": [own, other] }));
+}
+
+#[test]
+fn read_events_in_inaccessible_room_is_rejected() {
+    let room_id = owned_room_id!("!a98sd12bjh:example.org");
+    let (mut machine, actions) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None, system_clock());
+    assert_capabilities_dance_with(&mut machine, actions, &[TIMELINE_OTHER, READ_MESSAGE]);
+
+    let actions = machine.process(IncomingMessage::WidgetMessage(json_string!({
+        "api": "fromWidget",
+        "widgetId": WIDGET_ID,
+        "requestId": "get-me-some-messages",
+        "action": "org.matrix.msc2876.read_events",
+        "data": {
+            "type": "m.room.message",
+            "room_ids": ["!other:example.org", "!third:example.org"],
+        },
+    })));
+
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::SendToWidget(msg) = action);
+    let (msg, request_id) = parse_msg(&msg);
+    assert_eq!(request_id, "get-me-some-messages");
+    assert_eq!(msg["response"]["error"]["message"], "Not allowed");
+}
+
+#[test]
+fn send_event_to_other_room() {
+    let room_id = owned_room_id!("!a98sd12bjh:example.org");
+    let (mut machine, actions) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None, system_clock());
+    assert_capabilities_dance_with(&mut machine, actions, &[TIMELINE_OTHER, SEND_MESSAGE]);
+
+    // Sending to a room that wasn't granted is rejected.
+    let actions = machine.process(IncomingMessage::WidgetMessage(json_string!({
+        "api": "fromWidget",
+        "widgetId": WIDGET_ID,
+        "requestId": "send-to-third",
+        "action": "send_event",
+        "data": {
+            "type": "m.room.message",
+            "room_id": "!third:example.org",
+            "content": { "body": "hello", "msgtype": "m.text" },
+        },
+    })));
+
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::SendToWidget(msg) = action);
+    let (msg, _request_id) = parse_msg(&msg);
+    assert_eq!(msg["response"]["error"]["message"], "Not allowed to send events to this room");
+
+    // Sending to a granted room is forwarded to the driver, and the response
+    // contains that room.
+    let actions = machine.process(IncomingMessage::WidgetMessage(json_string!({
+        "api": "fromWidget",
+        "widgetId": WIDGET_ID,
+        "requestId": "send-to-other",
+        "action": "send_event",
+        "data": {
+            "type": "m.room.message",
+            "room_id": "!other:example.org",
+            "content": { "body": "hello", "msgtype": "m.text" },
+        },
+    })));
+
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(
+        Action::MatrixDriverRequest {
+            request_id,
+            data: MatrixDriverRequestData::SendMatrixEvent(request)
+        } = action
+    );
+    assert_eq!(request.room_id, Some(owned_room_id!("!other:example.org")));
+
+    let response = Ok(MatrixDriverResponse::MatrixEventSent(owned_event_id!("$sent")));
+    let actions = machine.process(IncomingMessage::MatrixDriverResponse { request_id, response });
+
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::SendToWidget(msg) = action);
+    let (msg, request_id) = parse_msg(&msg);
+    assert_eq!(request_id, "send-to-other");
+    assert_eq!(msg["response"], json!({ "room_id": "!other:example.org", "event_id": "$sent" }));
+}
+
+#[test]
+fn events_of_inaccessible_rooms_are_not_forwarded() {
+    let room_id = owned_room_id!("!a98sd12bjh:example.org");
+    let (mut machine, actions) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None, system_clock());
+    assert_capabilities_dance_with(&mut machine, actions, &[TIMELINE_OTHER, READ_MESSAGE]);
+
+    let event = Raw::new(&message_in("!third:example.org")).unwrap().cast();
+    let actions = machine.process(IncomingMessage::MatrixEventReceived(event));
+    assert!(actions.is_empty());
+
+    let event = Raw::new(&message_in("!other:example.org")).unwrap().cast();
+    let actions = machine.process(IncomingMessage::MatrixEventReceived(event));
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::SendToWidget(msg) = action);
+    let (msg, _request_id) = parse_msg(&msg);
+    assert_eq!(msg["action"], "send_event");
+    assert_eq!(msg["data"]["room_id"], "!other:example.org");
+}
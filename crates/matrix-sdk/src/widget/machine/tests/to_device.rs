@@ -0,0 +1,151 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+
+use assert_matches2::assert_let;
+use matrix_sdk_common::clock::system_clock;
+use ruma::{
+    owned_device_id, owned_room_id, owned_user_id, serde::Raw, to_device::DeviceIdOrAllDevices,
+    user_id,
+};
+use serde_json::json;
+
+use super::{capabilities::assert_capabilities_dance, parse_msg, WIDGET_ID};
+use crate::widget::machine::{
+    incoming::MatrixDriverResponse, Action, IncomingMessage, MatrixDriverRequestData, WidgetMachine,
+};
+
+#[test]
+fn send_to_device() {
+    let room_id = owned_room_id!("!a98sd12bjh:example.org");
+    let (mut machine, actions) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None, system_clock());
+    assert_capabilities_dance(
+        &mut machine,
+        actions,
+        Some("org.matrix.msc3819.send.to_device:io.element.call.encryption_keys"),
+    );
+
+    let actions = machine.process(IncomingMessage::WidgetMessage(json_string!({
+        "api": "fromWidget",
+        "widgetId": WIDGET_ID,
+        "requestId": "send-keys",
+        "action": "send_to_device",
+        "data": {
+            "type": "io.element.call.encryption_keys",
+            "encrypted": true,
+            "messages": {
+                "@bob:example.org": {
+                    "*": { "keys": [] },
+                },
+            },
+        },
+    })));
+
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(
+        Action::MatrixDriverRequest {
+            request_id,
+            data: MatrixDriverRequestData::SendToDeviceMessage(request)
+        } = action
+    );
+    assert_eq!(request.event_type.to_string(), "io.element.call.encryption_keys");
+    assert!(request.encrypted);
+    let device_messages = &request.messages[user_id!("@bob:example.org")];
+    assert!(device_messages.contains_key(&DeviceIdOrAllDevices::AllDevices));
+
+    let response = Ok(MatrixDriverResponse::ToDeviceSent(BTreeMap::from([(
+        owned_user_id!("@bob:example.org"),
+        vec![owned_device_id!("UNREACHABLE")],
+    )])));
+    let actions = machine.process(IncomingMessage::MatrixDriverResponse { request_id, response });
+
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::SendToWidget(msg) = action);
+    let (msg, request_id) = parse_msg(&msg);
+    assert_eq!(request_id, "send-keys");
+    assert_eq!(msg["action"], "send_to_device");
+    assert_eq!(msg["response"], json!({ "failures": { "@bob:example.org": ["UNREACHABLE"] } }));
+}
+
+#[test]
+fn send_to_device_without_capability_is_rejected() {
+    let room_id = owned_room_id!("!a98sd12bjh:example.org");
+    let (mut machine, actions) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None, system_clock());
+    assert_capabilities_dance(
+        &mut machine,
+        actions,
+        Some("org.matrix.msc3819.send.to_device:io.element.call.encryption_keys"),
+    );
+
+    let actions = machine.process(IncomingMessage::WidgetMessage(json_string!({
+        "api": "fromWidget",
+        "widgetId": WIDGET_ID,
+        "requestId": "send-secrets",
+        "action": "send_to_device",
+        "data": {
+            "type": "m.secret.send",
+            "messages": {
+                "@bob:example.org": {
+                    "BOBDEVICE": { "secret": "hunter2" },
+                },
+            },
+        },
+    })));
+
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::SendToWidget(msg) = action);
+    let (msg, _) = parse_msg(&msg);
+    assert_eq!(msg["response"]["error"]["message"].as_str().unwrap(), "Not allowed");
+}
+
+#[test]
+fn received_to_device_events_are_forwarded() {
+    let room_id = owned_room_id!("!a98sd12bjh:example.org");
+    let (mut machine, actions) =
+        WidgetMachine::new(WIDGET_ID.to_owned(), room_id, false, None, system_clock());
+    assert_capabilities_dance(
+        &mut machine,
+        actions,
+        Some("org.matrix.msc3819.receive.to_device:io.element.call.encryption_keys"),
+    );
+
+    let event = Raw::new(&json!({
+        "type": "m.secret.send",
+        "sender": "@bob:example.org",
+        "content": { "secret": "hunter2", "request_id": "request" },
+    }))
+    .unwrap()
+    .cast();
+    let actions = machine.process(IncomingMessage::ToDeviceEventReceived(event));
+    assert!(actions.is_empty());
+
+    let event = Raw::new(&json!({
+        "type": "io.element.call.encryption_keys",
+        "sender": "@bob:example.org",
+        "content": { "keys": [] },
+    }))
+    .unwrap()
+    .cast();
+    let actions = machine.process(IncomingMessage::ToDeviceEventReceived(event));
+
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::SendToWidget(msg) = action);
+    let (msg, _request_id) = parse_msg(&msg);
+    assert_eq!(msg["action"], "send_to_device");
+    assert_eq!(msg["data"]["type"], "io.element.call.encryption_keys");
+    assert_eq!(msg["data"]["sender"], "@bob:example.org");
+}
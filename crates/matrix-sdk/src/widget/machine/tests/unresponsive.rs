@@ -0,0 +1,118 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{sync::Arc, time::Duration};
+
+use assert_matches2::assert_let;
+use matrix_sdk_common::clock::TestClock;
+use ruma::{owned_room_id, serde::Raw};
+use serde_json::json;
+
+use super::{capabilities::assert_capabilities_dance_with, parse_msg, WIDGET_ID};
+use crate::widget::machine::{Action, IncomingMessage, RequestLimits, WidgetMachine};
+
+fn machine_with_retries(clock: &TestClock, retries: u32) -> (WidgetMachine, Vec<Action>) {
+    let limits = RequestLimits {
+        to_widget_response_timeout: Duration::from_secs(2),
+        to_widget_retries: retries,
+        ..Default::default()
+    };
+    WidgetMachine::new(
+        WIDGET_ID.to_owned(),
+        owned_room_id!("!a98sd12bjh:example.org"),
+        false,
+        Some(limits),
+        Arc::new(clock.clone()),
+    )
+}
+
+#[test]
+fn unanswered_capabilities_request_is_sent_again() {
+    let clock = TestClock::new();
+    let (mut machine, actions) = machine_with_retries(&clock, 1);
+
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::SendToWidget(first) = action);
+
+    // The widget doesn't answer in time, the same request is sent again.
+    clock.advance(Duration::from_secs(3));
+    let actions = machine.process(IncomingMessage::CheckExpiredRequests);
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::SendToWidget(second) = action);
+    assert_eq!(second, first);
+
+    // It still doesn't, the widget is unresponsive.
+    clock.advance(Duration::from_secs(3));
+    let actions = machine.process(IncomingMessage::CheckExpiredRequests);
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::NotifyWidgetUnresponsive { action } = action);
+    assert_eq!(action, "capabilities");
+}
+
+#[test]
+fn answer_to_request_sent_again_is_processed() {
+    let clock = TestClock::new();
+    let (mut machine, actions) = machine_with_retries(&clock, 1);
+
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::SendToWidget(msg) = action);
+    let (_msg, request_id) = parse_msg(&msg);
+
+    clock.advance(Duration::from_secs(3));
+    let actions = machine.process(IncomingMessage::CheckExpiredRequests);
+    assert_eq!(actions.len(), 1);
+
+    // The widget finally answers, the capabilities are acquired.
+    let actions = machine.process(IncomingMessage::WidgetMessage(json_string!({
+        "api": "toWidget",
+        "widgetId": WIDGET_ID,
+        "requestId": request_id,
+        "action": "capabilities",
+        "data": {},
+        "response": {
+            "capabilities": [],
+        },
+    })));
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::MatrixDriverRequest { .. } = action);
+}
+
+#[test]
+fn unanswered_event_is_not_sent_again() {
+    let clock = TestClock::new();
+    let (mut machine, actions) = machine_with_retries(&clock, 3);
+    assert_capabilities_dance_with(
+        &mut machine,
+        actions,
+        &["org.matrix.msc2762.receive.event:m.room.message"],
+    );
+
+    let event = json!({
+        "type": "m.room.message",
+        "event_id": "$message",
+        "room_id": "!a98sd12bjh:example.org",
+        "sender": "@alice:example.org",
+        "origin_server_ts": 0,
+        "content": { "body": "hello", "msgtype": "m.text" },
+    });
+    let actions =
+        machine.process(IncomingMessage::MatrixEventReceived(Raw::new(&event).unwrap().cast()));
+    assert_eq!(actions.len(), 1);
+
+    clock.advance(Duration::from_secs(3));
+    let actions = machine.process(IncomingMessage::CheckExpiredRequests);
+    let [action]: [Action; 1] = actions.try_into().unwrap();
+    assert_let!(Action::NotifyWidgetUnresponsive { action } = action);
+    assert_eq!(action, "send_event");
+}
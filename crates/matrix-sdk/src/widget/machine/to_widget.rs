@@ -14,7 +14,10 @@
 
 use std::marker::PhantomData;
 
-use ruma::{events::AnyTimelineEvent, serde::Raw};
+use ruma::{
+    events::{AnyEphemeralRoomEvent, AnyTimelineEvent, AnyToDeviceEvent},
+    serde::Raw,
+};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::value::RawValue as RawJsonValue;
 use tracing::error;
@@ -80,6 +83,11 @@ pub(super) struct ToWidgetResponse {
 pub(crate) trait ToWidgetRequest: Serialize {
     const ACTION: &'static str;
     type ResponseData: DeserializeOwned;
+
+    /// Whether the request can be sent again if the widget doesn't answer it
+    /// in time, because receiving it twice doesn't change anything for the
+    /// widget.
+    const IDEMPOTENT: bool = false;
 }
 
 /// Request the widget to send the list of capabilities that it wants to have.
@@ -89,6 +97,7 @@ pub(super) struct RequestCapabilities {}
 impl ToWidgetRequest for RequestCapabilities {
     const ACTION: &'static str = "capabilities";
     type ResponseData = RequestCapabilitiesResponse;
+    const IDEMPOTENT: bool = true;
 }
 
 #[derive(Deserialize)]
@@ -106,6 +115,7 @@ pub(super) struct NotifyCapabilitiesChanged {
 impl ToWidgetRequest for NotifyCapabilitiesChanged {
     const ACTION: &'static str = "notify_capabilities";
     type ResponseData = Empty;
+    const IDEMPOTENT: bool = true;
 }
 
 /// Notify the widget that the OpenID credentials changed.
@@ -115,6 +125,7 @@ pub(crate) struct NotifyOpenIdChanged(pub(crate) OpenIdResponse);
 impl ToWidgetRequest for NotifyOpenIdChanged {
     const ACTION: &'static str = "openid_credentials";
     type ResponseData = OpenIdResponse;
+    const IDEMPOTENT: bool = true;
 }
 
 /// Notify the widget that we received a new matrix event.
@@ -128,5 +139,29 @@ impl ToWidgetRequest for NotifyNewMatrixEvent {
     type ResponseData = Empty;
 }
 
+/// Notify the widget that we received a new ephemeral event.
+/// Like [`NotifyNewMatrixEvent`], this is a "response" to the widget
+/// subscribing to the events in the room.
+#[derive(Serialize)]
+#[serde(transparent)]
+pub(crate) struct NotifyNewEphemeralEvent(pub(crate) Raw<AnyEphemeralRoomEvent>);
+
+impl ToWidgetRequest for NotifyNewEphemeralEvent {
+    const ACTION: &'static str = "org.matrix.msc2477.ephemeral_event";
+    type ResponseData = Empty;
+}
+
+/// Notify the widget that we received a new to-device event.
+/// Like [`NotifyNewMatrixEvent`], this is a "response" to the widget
+/// subscribing to the events.
+#[derive(Serialize)]
+#[serde(transparent)]
+pub(crate) struct NotifyNewToDeviceEvent(pub(crate) Raw<AnyToDeviceEvent>);
+
+impl ToWidgetRequest for NotifyNewToDeviceEvent {
+    const ACTION: &'static str = "send_to_device";
+    type ResponseData = Empty;
+}
+
 #[derive(Deserialize)]
 pub(crate) struct Empty {}
@@ -0,0 +1,396 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Validation of the messages received from the widget that couldn't be
+//! parsed, to tell the widget developers what is wrong with them.
+//!
+//! The messages are only validated against a schema once parsing them failed,
+//! the schema is a best effort to find the problems and is less strict than
+//! the parsing itself, like for the format of identifiers.
+
+use std::fmt;
+
+use serde::Serialize;
+use serde_json::{Map as JsonMap, Value as JsonValue};
+
+/// A message received from a widget that couldn't be processed, with the
+/// problems found in it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WidgetDiagnostic {
+    /// The ID of the request, if it could be found in the message.
+    pub request_id: Option<String>,
+
+    /// The action of the request, if it could be found in the message.
+    pub action: Option<String>,
+
+    /// The problems found in the message.
+    pub problems: Vec<ValidationProblem>,
+}
+
+/// A problem found in a message received from a widget.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ValidationProblem {
+    /// The path of the problematic field in the message, like `data.type`.
+    ///
+    /// It is empty if the problem is about the whole message.
+    pub path: String,
+
+    /// What is wrong with the field.
+    #[serde(flatten)]
+    pub kind: ValidationProblemKind,
+}
+
+impl ValidationProblem {
+    fn new(path: impl Into<String>, kind: ValidationProblemKind) -> Self {
+        Self { path: path.into(), kind }
+    }
+}
+
+impl fmt::Display for ValidationProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = if self.path.is_empty() { "message" } else { &self.path };
+
+        match &self.kind {
+            ValidationProblemKind::InvalidJson { error } => {
+                write!(f, "{path}: invalid JSON: {error}")
+            }
+            ValidationProblemKind::UnknownAction { action } => {
+                write!(f, "{path}: unknown action `{action}`")
+            }
+            ValidationProblemKind::MissingField => write!(f, "{path}: missing field"),
+            ValidationProblemKind::WrongType { expected, found } => {
+                write!(f, "{path}: expected {expected}, found {found}")
+            }
+            ValidationProblemKind::InvalidValue { error } => write!(f, "{path}: {error}"),
+        }
+    }
+}
+
+/// The kind of a [`ValidationProblem`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum ValidationProblemKind {
+    /// The message isn't valid JSON.
+    InvalidJson {
+        /// The error of the JSON parser.
+        error: String,
+    },
+
+    /// The action of the request isn't supported.
+    UnknownAction {
+        /// The action of the request.
+        action: String,
+    },
+
+    /// A required field is missing.
+    MissingField,
+
+    /// The field doesn't have the expected type.
+    WrongType {
+        /// The expected type, like `"a string"`.
+        expected: &'static str,
+        /// The type of the field, like `"a number"`.
+        found: &'static str,
+    },
+
+    /// The field has the expected type, but its value is invalid.
+    InvalidValue {
+        /// Why the value is invalid.
+        error: String,
+    },
+}
+
+#[derive(Clone, Copy)]
+enum FieldType {
+    String,
+    Boolean,
+    Integer,
+    Object,
+    Array,
+    StringOrArray,
+    StringOrTrue,
+}
+
+impl FieldType {
+    fn matches(self, value: &JsonValue) -> bool {
+        match self {
+            Self::String => value.is_string(),
+            Self::Boolean => value.is_boolean(),
+            Self::Integer => value.is_u64(),
+            Self::Object => value.is_object(),
+            Self::Array => value.is_array(),
+            Self::StringOrArray => value.is_string() || value.is_array(),
+            Self::StringOrTrue => value.is_string() || value == &JsonValue::Bool(true),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::String => "a string",
+            Self::Boolean => "a boolean",
+            Self::Integer => "a non-negative integer",
+            Self::Object => "an object",
+            Self::Array => "an array",
+            Self::StringOrArray => "a string or an array",
+            Self::StringOrTrue => "a string or `true`",
+        }
+    }
+}
+
+struct Field {
+    name: &'static str,
+    ty: FieldType,
+    required: bool,
+}
+
+const fn required(name: &'static str, ty: FieldType) -> Field {
+    Field { name, ty, required: true }
+}
+
+const fn optional(name: &'static str, ty: FieldType) -> Field {
+    Field { name, ty, required: false }
+}
+
+/// The fields common to all the messages.
+const ENVELOPE_FIELDS: &[Field] = &[
+    required("api", FieldType::String),
+    required("widgetId", FieldType::String),
+    required("requestId", FieldType::String),
+    required("action", FieldType::String),
+];
+
+/// The fields of the responses to the `toWidget` requests, besides the ones of
+/// the envelope.
+const TO_WIDGET_RESPONSE_FIELDS: &[Field] = &[required("response", FieldType::Object)];
+
+/// The fields of the `data` of the `fromWidget` requests with the given
+/// action, or `None` if the action isn't supported.
+fn request_data_fields(action: &str) -> Option<&'static [Field]> {
+    use FieldType::{Array, Boolean, Integer, Object, StringOrArray, StringOrTrue};
+
+    const READ_EVENTS: &[Field] = &[
+        required("type", FieldType::String),
+        optional("state_key", StringOrTrue),
+        optional("limit", Integer),
+        optional("room_ids", StringOrArray),
+    ];
+    const READ_RELATIONS: &[Field] = &[
+        required("event_id", FieldType::String),
+        optional("room_id", FieldType::String),
+        optional("rel_type", FieldType::String),
+        optional("event_type", FieldType::String),
+        optional("limit", Integer),
+        optional("from", FieldType::String),
+        optional("to", FieldType::String),
+        optional("direction", FieldType::String),
+    ];
+    const SEND_EVENT: &[Field] = &[
+        required("type", FieldType::String),
+        optional("state_key", FieldType::String),
+        required("content", Object),
+        optional("room_id", FieldType::String),
+        optional("delay", Integer),
+    ];
+    const SEND_EPHEMERAL_EVENT: &[Field] =
+        &[required("type", FieldType::String), required("content", Object)];
+    const SEND_TO_DEVICE: &[Field] = &[
+        required("type", FieldType::String),
+        optional("encrypted", Boolean),
+        required("messages", Object),
+    ];
+    const UPDATE_DELAYED_EVENT: &[Field] =
+        &[required("delay_id", FieldType::String), required("action", FieldType::String)];
+    const PICK_FILE: &[Field] = &[optional("accept", Array)];
+
+    Some(match action {
+        "supported_api_versions" | "content_loaded" | "get_openid" => &[],
+        "org.matrix.msc2876.read_events" => READ_EVENTS,
+        "org.matrix.msc3869.read_relations" => READ_RELATIONS,
+        "send_event" => SEND_EVENT,
+        "org.matrix.msc2477.send_ephemeral_event" => SEND_EPHEMERAL_EVENT,
+        "send_to_device" => SEND_TO_DEVICE,
+        "org.matrix.msc4157.update_delayed_event" => UPDATE_DELAYED_EVENT,
+        "io.element.pick_file" => PICK_FILE,
+        _ => return None,
+    })
+}
+
+/// Find the problems of a message received from the widget that failed to be
+/// parsed with the given error.
+///
+/// At least one problem is always returned, the parsing error itself if the
+/// schema didn't catch anything.
+pub(super) fn validate_message(raw: &str, error: &serde_json::Error) -> WidgetDiagnostic {
+    let message = match serde_json::from_str::<JsonValue>(raw) {
+        Ok(JsonValue::Object(message)) => message,
+        Ok(value) => {
+            return WidgetDiagnostic {
+                request_id: None,
+                action: None,
+                problems: vec![wrong_type("", FieldType::Object, &value)],
+            }
+        }
+        Err(e) => {
+            return WidgetDiagnostic {
+                request_id: None,
+                action: None,
+                problems: vec![ValidationProblem::new(
+                    "",
+                    ValidationProblemKind::InvalidJson { error: e.to_string() },
+                )],
+            }
+        }
+    };
+
+    let mut problems = Vec::new();
+    check_fields(&message, "", ENVELOPE_FIELDS, &mut problems);
+
+    let is_request = match message.get("api").and_then(JsonValue::as_str) {
+        Some("fromWidget") => {
+            check_request_data(&message, &mut problems);
+            true
+        }
+        Some("toWidget") => {
+            check_fields(&message, "", TO_WIDGET_RESPONSE_FIELDS, &mut problems);
+            false
+        }
+        Some(api) => {
+            problems.push(ValidationProblem::new(
+                "api",
+                ValidationProblemKind::InvalidValue {
+                    error: format!("unknown API `{api}`, expected `fromWidget` or `toWidget`"),
+                },
+            ));
+            false
+        }
+        // Already reported with the envelope.
+        None => false,
+    };
+
+    if problems.is_empty() {
+        let path = if is_request { "data" } else { "" };
+        problems.push(ValidationProblem::new(
+            path,
+            ValidationProblemKind::InvalidValue { error: error.to_string() },
+        ));
+    }
+
+    WidgetDiagnostic {
+        request_id: message.get("requestId").and_then(JsonValue::as_str).map(ToOwned::to_owned),
+        action: message.get("action").and_then(JsonValue::as_str).map(ToOwned::to_owned),
+        problems,
+    }
+}
+
+fn check_request_data(message: &JsonMap<String, JsonValue>, problems: &mut Vec<ValidationProblem>) {
+    let Some(action) = message.get("action").and_then(JsonValue::as_str) else {
+        // Already reported with the envelope.
+        return;
+    };
+
+    let Some(fields) = request_data_fields(action) else {
+        problems.push(ValidationProblem::new(
+            "action",
+            ValidationProblemKind::UnknownAction { action: action.to_owned() },
+        ));
+        return;
+    };
+
+    match message.get("data") {
+        Some(JsonValue::Object(data)) => check_fields(data, "data", fields, problems),
+        Some(value) => problems.push(wrong_type("data", FieldType::Object, value)),
+        None => problems.push(ValidationProblem::new("data", ValidationProblemKind::MissingField)),
+    }
+}
+
+fn check_fields(
+    object: &JsonMap<String, JsonValue>,
+    prefix: &str,
+    fields: &[Field],
+    problems: &mut Vec<ValidationProblem>,
+) {
+    for field in fields {
+        let path = if prefix.is_empty() {
+            field.name.to_owned()
+        } else {
+            format!("{prefix}.{}", field.name)
+        };
+
+        match object.get(field.name) {
+            // Optional fields can be null.
+            None | Some(JsonValue::Null) if !field.required => {}
+            None => {
+                problems.push(ValidationProblem::new(path, ValidationProblemKind::MissingField))
+            }
+            Some(value) if !field.ty.matches(value) => {
+                problems.push(wrong_type(path, field.ty, value));
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+fn wrong_type(
+    path: impl Into<String>,
+    expected: FieldType,
+    value: &JsonValue,
+) -> ValidationProblem {
+    let found = match value {
+        JsonValue::Null => "null",
+        JsonValue::Bool(_) => "a boolean",
+        JsonValue::Number(_) => "a number",
+        JsonValue::String(_) => "a string",
+        JsonValue::Array(_) => "an array",
+        JsonValue::Object(_) => "an object",
+    };
+
+    ValidationProblem::new(
+        path,
+        ValidationProblemKind::WrongType { expected: expected.name(), found },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::request_data_fields;
+    use crate::widget::machine::from_widget::FromWidgetRequest;
+
+    /// The actions of the `fromWidget` requests, as listed by serde when the
+    /// action is unknown.
+    fn supported_actions() -> Vec<String> {
+        let Err(error) =
+            serde_json::from_value::<FromWidgetRequest>(json!({ "action": "unknown", "data": {} }))
+        else {
+            panic!("an unknown action should fail to deserialize");
+        };
+        let error = error.to_string();
+
+        let (_, actions) =
+            error.split_once("expected one of ").expect("the error should list the actions");
+        actions.split(", ").map(|action| action.trim_matches('`').to_owned()).collect()
+    }
+
+    #[test]
+    fn every_action_has_a_schema() {
+        let actions = supported_actions();
+        assert!(actions.len() > 1, "couldn't find the actions in the error");
+
+        for action in actions {
+            assert!(request_data_fields(&action).is_some(), "`{action}` has no schema");
+        }
+    }
+}
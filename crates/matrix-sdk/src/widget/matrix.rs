@@ -15,33 +15,61 @@
 //! Matrix driver implementation that exposes Matrix functionality
 //! that is relevant for the widget API.
 
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, time::Duration};
 
-use matrix_sdk_base::deserialized_responses::RawAnySyncOrStrippedState;
+use futures_util::{stream, Stream, StreamExt};
+use matrix_sdk_base::{deserialized_responses::RawAnySyncOrStrippedState, RoomState};
 use ruma::{
-    api::client::{
-        account::request_openid_token::v3::{Request as OpenIdRequest, Response as OpenIdResponse},
-        filter::RoomEventFilter,
+    api::{
+        client::{
+            account::request_openid_token::v3::{
+                Request as OpenIdRequest, Response as OpenIdResponse,
+            },
+            filter::RoomEventFilter,
+            relations::{
+                get_relating_events, get_relating_events_with_rel_type,
+                get_relating_events_with_rel_type_and_event_type,
+            },
+            to_device::send_event_to_device::v3::{
+                Messages as ToDeviceMessages, Request as ToDeviceRequest,
+            },
+        },
+        Direction,
     },
     assign,
     events::{
-        AnySyncTimelineEvent, AnyTimelineEvent, MessageLikeEventType, StateEventType,
-        TimelineEventType,
+        AnyEphemeralRoomEvent, AnySyncEphemeralRoomEvent, AnySyncTimelineEvent, AnyTimelineEvent,
+        AnyToDeviceEvent, EphemeralRoomEventType, MessageLikeEventType, StateEventType,
+        TimelineEventType, ToDeviceEventType,
     },
     serde::Raw,
-    OwnedEventId, RoomId,
+    OwnedEventId, RoomId, TransactionId,
 };
 use serde_json::value::RawValue as RawJsonValue;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
-use tracing::error;
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
+use tracing::{error, warn};
 
-use super::StateKeySelector;
+use super::{
+    machine::{
+        ReadRelationsRequest, ReadRelationsResponse, ReadRooms, UnreachableDevices, UploadedFile,
+    },
+    PickedFile, StateKeySelector, TimelineRooms,
+};
 use crate::{
-    event_handler::EventHandlerDropGuard, room::MessagesOptions, HttpResult, Result, Room,
+    event_handler::EventHandlerDropGuard,
+    room::{DelayedEventAction, MessagesOptions},
+    sync::RoomUpdate,
+    Error, HttpResult, Result, Room,
 };
 
+/// The maximum number of rooms read at the same time when a widget reads
+/// events from several rooms.
+const MAX_CONCURRENT_ROOM_READS: usize = 8;
+
 /// Thin wrapper around a [`Room`] that provides functionality relevant for
 /// widgets.
+#[derive(Clone)]
 pub(crate) struct MatrixDriver {
     room: Room,
 }
@@ -58,71 +86,349 @@ impl MatrixDriver {
         self.room.client.send(OpenIdRequest::new(user_id), None).await
     }
 
-    /// Reads the latest `limit` events of a given `event_type` from the room.
+    /// Returns the given joined room, or the room of the widget if `room_id`
+    /// is `None`.
+    fn room(&self, room_id: Option<&RoomId>) -> Result<Room> {
+        let Some(room_id) = room_id else {
+            return Ok(self.room.clone());
+        };
+
+        match self.room.client.get_room(room_id) {
+            Some(room) if room.state() == RoomState::Joined => Ok(room),
+            _ => Err(Error::UnknownError(format!("the user isn't joined to {room_id}").into())),
+        }
+    }
+
+    /// Returns the rooms selected by `rooms`, or an error for the ones the
+    /// user isn't joined to.
+    fn rooms(&self, rooms: &ReadRooms) -> Vec<Result<Room>> {
+        match rooms {
+            ReadRooms::Rooms(room_ids) => {
+                room_ids.iter().map(|room_id| self.room(Some(room_id))).collect()
+            }
+            ReadRooms::AllJoined => self.room.client.joined_rooms().into_iter().map(Ok).collect(),
+        }
+    }
+
+    /// Reads the latest `limit` events of a given `event_type` from each of
+    /// the given rooms.
+    ///
+    /// The rooms are read concurrently, and the rooms that can't be read are
+    /// skipped, unless none of them can be read.
     pub(crate) async fn read_message_like_events(
         &self,
+        rooms: &ReadRooms,
         event_type: MessageLikeEventType,
         limit: u32,
     ) -> Result<Vec<Raw<AnyTimelineEvent>>> {
-        let options = assign!(MessagesOptions::backward(), {
-            limit: limit.into(),
-            filter: assign!(RoomEventFilter::default(), {
-                types: Some(vec![event_type.to_string()])
-            }),
-        });
+        let results = stream::iter(self.rooms(rooms))
+            .map(|room| {
+                let event_type = event_type.to_string();
+                async move {
+                    let options = assign!(MessagesOptions::backward(), {
+                        limit: limit.into(),
+                        filter: assign!(RoomEventFilter::default(), {
+                            types: Some(vec![event_type])
+                        }),
+                    });
 
-        let messages = self.room.messages(options).await?;
-        Ok(messages.chunk.into_iter().map(|ev| ev.event.cast()).collect())
+                    let messages = room?.messages(options).await?;
+                    Ok::<Vec<_>, Error>(
+                        messages.chunk.into_iter().map(|ev| ev.event.cast()).collect(),
+                    )
+                }
+            })
+            .buffered(MAX_CONCURRENT_ROOM_READS)
+            .collect::<Vec<_>>()
+            .await;
+
+        skip_failed_rooms(results)
     }
 
+    /// Reads the state events of a given `event_type` from each of the given
+    /// rooms.
+    ///
+    /// The rooms that can't be read are skipped, unless none of them can be
+    /// read.
     pub(crate) async fn read_state_events(
         &self,
+        rooms: &ReadRooms,
         event_type: StateEventType,
         state_key: &StateKeySelector,
     ) -> Result<Vec<Raw<AnyTimelineEvent>>> {
-        let room_id = self.room.room_id();
-        let convert = |sync_or_stripped_state| match sync_or_stripped_state {
-            RawAnySyncOrStrippedState::Sync(ev) => Some(attach_room_id(ev.cast_ref(), room_id)),
-            RawAnySyncOrStrippedState::Stripped(_) => {
-                error!("MatrixDriver can't operate in invited rooms");
-                None
+        let mut results = Vec::new();
+        for room in self.rooms(rooms) {
+            let events = match room {
+                Ok(room) => read_room_state_events(&room, &event_type, state_key).await,
+                Err(error) => Err(error),
+            };
+            results.push(events);
+        }
+
+        skip_failed_rooms(results)
+    }
+
+    /// Reads the events related to an event of the room.
+    pub(crate) async fn read_relations(
+        &self,
+        request: ReadRelationsRequest,
+    ) -> Result<ReadRelationsResponse> {
+        let ReadRelationsRequest {
+            event_id, rel_type, event_type, limit, from, to, direction, ..
+        } = request;
+        let room_id = self.room.room_id().to_owned();
+        let limit = limit.map(Into::into);
+        let dir = direction.unwrap_or(Direction::Backward);
+
+        // The homeserver can only filter by event type along with a relation
+        // type, otherwise the events are filtered here.
+        let (chunk, next_batch, prev_batch, filter_type) = match (rel_type, event_type) {
+            (Some(rel_type), Some(event_type)) => {
+                let request = assign!(
+                    get_relating_events_with_rel_type_and_event_type::v1::Request::new(
+                        room_id, event_id, rel_type, event_type,
+                    ),
+                    { from, to, limit, dir }
+                );
+                let response = self.room.client.send(request, None).await?;
+                (response.chunk, response.next_batch, response.prev_batch, None)
+            }
+            (Some(rel_type), None) => {
+                let request = assign!(
+                    get_relating_events_with_rel_type::v1::Request::new(room_id, event_id, rel_type),
+                    { from, to, limit, dir }
+                );
+                let response = self.room.client.send(request, None).await?;
+                (response.chunk, response.next_batch, response.prev_batch, None)
+            }
+            (None, event_type) => {
+                let request = assign!(
+                    get_relating_events::v1::Request::new(room_id, event_id),
+                    { from, to, limit, dir }
+                );
+                let response = self.room.client.send(request, None).await?;
+                (response.chunk, response.next_batch, response.prev_batch, event_type)
             }
         };
 
-        let events = match state_key {
-            StateKeySelector::Key(state_key) => self
-                .room
-                .get_state_event(event_type, state_key)
-                .await?
-                .and_then(convert)
-                .into_iter()
-                .collect(),
-            StateKeySelector::Any => {
-                let events = self.room.get_state_events(event_type).await?;
-                events.into_iter().filter_map(convert).collect()
+        let mut events = Vec::with_capacity(chunk.len());
+        for event in chunk {
+            // Encrypted events only have their real type once decrypted.
+            let event = self.room.try_decrypt_event(event.cast()).await?.event;
+
+            if let Some(event_type) = &filter_type {
+                let matches_type = event
+                    .get_field::<String>("type")
+                    .ok()
+                    .flatten()
+                    .is_some_and(|t| t == event_type.to_string());
+                if !matches_type {
+                    continue;
+                }
             }
-        };
 
-        Ok(events)
+            events.push(event);
+        }
+
+        Ok(ReadRelationsResponse { chunk: events, next_batch, prev_batch })
     }
 
-    /// Sends a given `event` to the room.
+    /// Sends a given `event` to the given room, or to the room of the widget
+    /// if `room_id` is `None`.
     pub(crate) async fn send(
         &self,
+        room_id: Option<&RoomId>,
         event_type: TimelineEventType,
         state_key: Option<String>,
         content: Box<RawJsonValue>,
     ) -> Result<OwnedEventId> {
+        let room = self.room(room_id)?;
         let type_str = event_type.to_string();
         Ok(match state_key {
-            Some(key) => self.room.send_state_event_raw(&type_str, &key, content).await?.event_id,
-            None => self.room.send_raw(&type_str, content).await?.event_id,
+            Some(key) => room.send_state_event_raw(&type_str, &key, content).await?.event_id,
+            None => room.send_raw(&type_str, content).await?.event_id,
         })
     }
 
+    /// Sends a given `event` to the given room, or to the room of the widget
+    /// if `room_id` is `None`, after the given `delay`, and returns the ID of
+    /// the delayed event.
+    pub(crate) async fn send_delayed(
+        &self,
+        room_id: Option<&RoomId>,
+        event_type: TimelineEventType,
+        state_key: Option<String>,
+        content: Box<RawJsonValue>,
+        delay: Duration,
+    ) -> Result<String> {
+        let room = self.room(room_id)?;
+        let type_str = event_type.to_string();
+        match state_key {
+            Some(key) => room.send_state_event_raw_delayed(&type_str, &key, content, delay).await,
+            None => room.send_raw_delayed(&type_str, content, delay).await,
+        }
+    }
+
+    /// Cancels, restarts or sends right away a delayed event.
+    pub(crate) async fn update_delayed_event(
+        &self,
+        delay_id: &str,
+        action: DelayedEventAction,
+    ) -> Result<()> {
+        self.room.update_delayed_event(delay_id, action).await
+    }
+
+    /// Sends a given ephemeral event to the room.
+    pub(crate) async fn send_ephemeral(
+        &self,
+        event_type: EphemeralRoomEventType,
+        content: Box<RawJsonValue>,
+    ) -> Result<()> {
+        self.room.send_ephemeral_raw(&event_type.to_string(), content).await
+    }
+
+    /// Uploads a file picked by the user to the media repository.
+    ///
+    /// In encrypted rooms, the file is encrypted with
+    /// `Client::prepare_encrypted_file()` before it is uploaded, and the
+    /// widget gets the key to reference it in its events.
+    pub(crate) async fn upload_file(&self, file: PickedFile) -> Result<UploadedFile> {
+        let PickedFile { name, content_type, data } = file;
+        let size = data.len() as u64;
+
+        // The file is encrypted in encrypted rooms, the widget gets the key to
+        // reference it in its events.
+        #[cfg(feature = "e2e-encryption")]
+        if self.room.is_encrypted().await? {
+            let mut cursor = std::io::Cursor::new(data);
+            let file = self.room.client.prepare_encrypted_file(&content_type, &mut cursor).await?;
+
+            return Ok(UploadedFile {
+                content_uri: file.url.clone(),
+                name,
+                mimetype: content_type.to_string(),
+                size,
+                file: Some(file),
+            });
+        }
+
+        let response = self.room.client.media().upload(&content_type, data).await?;
+
+        Ok(UploadedFile {
+            content_uri: response.content_uri,
+            name,
+            mimetype: content_type.to_string(),
+            size,
+            file: None,
+        })
+    }
+
+    /// Sends the given to-device messages, encrypting them with Olm first if
+    /// `encrypted` is `true`.
+    ///
+    /// Returns the devices that the messages couldn't be encrypted for, by
+    /// user.
+    pub(crate) async fn send_to_device(
+        &self,
+        event_type: ToDeviceEventType,
+        encrypted: bool,
+        messages: ToDeviceMessages,
+    ) -> Result<UnreachableDevices> {
+        let mut unreachable_devices = UnreachableDevices::new();
+
+        let request = if encrypted {
+            #[cfg(feature = "e2e-encryption")]
+            {
+                let messages = self
+                    .encrypt_to_device_messages(&event_type, messages, &mut unreachable_devices)
+                    .await?;
+                ToDeviceRequest::new_raw(
+                    ToDeviceEventType::RoomEncrypted,
+                    TransactionId::new(),
+                    messages,
+                )
+            }
+
+            #[cfg(not(feature = "e2e-encryption"))]
+            return Err(Error::UnknownError(
+                "sending encrypted to-device messages requires the e2e-encryption feature".into(),
+            ));
+        } else {
+            ToDeviceRequest::new_raw(event_type, TransactionId::new(), messages)
+        };
+
+        self.room.client.send(request, None).await?;
+        Ok(unreachable_devices)
+    }
+
+    /// Encrypts the given to-device messages for every targeted device.
+    ///
+    /// The devices that are unknown, or for which no Olm session could be
+    /// established, are skipped and added to `unreachable_devices`.
+    #[cfg(feature = "e2e-encryption")]
+    async fn encrypt_to_device_messages(
+        &self,
+        event_type: &ToDeviceEventType,
+        messages: ToDeviceMessages,
+        unreachable_devices: &mut UnreachableDevices,
+    ) -> Result<ToDeviceMessages> {
+        use ruma::to_device::DeviceIdOrAllDevices;
+
+        let client = &self.room.client;
+        client.claim_one_time_keys(messages.keys().map(|user_id| &**user_id)).await?;
+
+        let event_type = event_type.to_string();
+        let mut encrypted_messages = ToDeviceMessages::new();
+
+        for (user_id, device_messages) in messages {
+            let user_devices = client.encryption().get_user_devices(&user_id).await?;
+
+            for (target, content) in device_messages {
+                let devices: Vec<_> = match &target {
+                    DeviceIdOrAllDevices::DeviceId(device_id) => {
+                        let device = user_devices.get(device_id);
+                        if device.is_none() {
+                            warn!(?user_id, ?device_id, "Unknown device for a to-device message");
+                            unreachable_devices
+                                .entry(user_id.clone())
+                                .or_default()
+                                .push(device_id.clone());
+                        }
+                        device.into_iter().collect()
+                    }
+                    _ => user_devices.devices().collect(),
+                };
+
+                for device in devices {
+                    match device.inner.encrypt_event_raw(&event_type, &content).await {
+                        Ok(encrypted) => {
+                            encrypted_messages.entry(user_id.clone()).or_default().insert(
+                                DeviceIdOrAllDevices::DeviceId(device.device_id().to_owned()),
+                                encrypted.cast(),
+                            );
+                        }
+                        Err(e) => {
+                            warn!(
+                                ?user_id,
+                                device_id = ?device.device_id(),
+                                "Couldn't encrypt a to-device message: {e}"
+                            );
+                            unreachable_devices
+                                .entry(user_id.clone())
+                                .or_default()
+                                .push(device.device_id().to_owned());
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(encrypted_messages)
+    }
+
     /// Starts forwarding new room events. Once the returned `EventReceiver`
     /// is dropped, forwarding will be stopped.
-    pub(crate) fn events(&self) -> EventReceiver {
+    pub(crate) fn events(&self) -> EventReceiver<AnyTimelineEvent> {
         let (tx, rx) = unbounded_channel();
         let room_id = self.room.room_id().to_owned();
         let handle = self.room.add_event_handler(move |raw: Raw<AnySyncTimelineEvent>| {
@@ -133,23 +439,186 @@ impl MatrixDriver {
         let drop_guard = self.room.client().event_handler_drop_guard(handle);
         EventReceiver { rx, _drop_guard: drop_guard }
     }
+
+    /// Starts forwarding new events of the other rooms that the widget can
+    /// access, according to `timeline_rooms`. Once the returned
+    /// `EventReceiver` is dropped, forwarding will be stopped.
+    ///
+    /// The events of the room of the widget are forwarded by
+    /// [`Self::events()`].
+    pub(crate) fn other_rooms_events(
+        &self,
+        timeline_rooms: TimelineRooms,
+    ) -> EventReceiver<AnyTimelineEvent> {
+        let (tx, rx) = unbounded_channel();
+        let own_room_id = self.room.room_id().to_owned();
+        let handle = self.room.client.add_event_handler(
+            move |raw: Raw<AnySyncTimelineEvent>, room: Room| {
+                let room_id = room.room_id();
+                if *room_id != *own_room_id && timeline_rooms.allows(room_id, &own_room_id) {
+                    let _ = tx.send(attach_room_id(&raw, room_id));
+                }
+                async {}
+            },
+        );
+
+        let drop_guard = self.room.client().event_handler_drop_guard(handle);
+        EventReceiver { rx, _drop_guard: drop_guard }
+    }
+
+    /// Starts forwarding new ephemeral events of the room. Once the returned
+    /// `EventReceiver` is dropped, forwarding will be stopped.
+    pub(crate) fn ephemeral_events(&self) -> EventReceiver<AnyEphemeralRoomEvent> {
+        let (tx, rx) = unbounded_channel();
+        let room_id = self.room.room_id().to_owned();
+        let handle = self.room.add_event_handler(move |raw: Raw<AnySyncEphemeralRoomEvent>| {
+            let _ = tx.send(attach_room_id(&raw, &room_id));
+            async {}
+        });
+
+        let drop_guard = self.room.client().event_handler_drop_guard(handle);
+        EventReceiver { rx, _drop_guard: drop_guard }
+    }
+
+    /// Starts forwarding the to-device events received by the client, after
+    /// their decryption. Once the returned `EventReceiver` is dropped,
+    /// forwarding will be stopped.
+    pub(crate) fn to_device_events(&self) -> EventReceiver<AnyToDeviceEvent> {
+        let (tx, rx) = unbounded_channel();
+        let handle = self.room.client.add_event_handler(move |raw: Raw<AnyToDeviceEvent>| {
+            let _ = tx.send(strip_to_device_event(&raw));
+            async {}
+        });
+
+        let drop_guard = self.room.client().event_handler_drop_guard(handle);
+        EventReceiver { rx, _drop_guard: drop_guard }
+    }
+
+    /// Starts forwarding the state events of the room that are received in
+    /// the state section of the sync, for example after a gap in the timeline
+    /// or when the room is joined.
+    ///
+    /// The state events of the timeline are forwarded by [`Self::events()`].
+    /// If some room updates were missed, [`StateUpdate::Lagged`] is forwarded
+    /// so the current state can be read again. Once the returned stream is
+    /// dropped, forwarding will be stopped.
+    pub(crate) fn state_events(&self) -> impl Stream<Item = StateUpdate> {
+        let room_id = self.room.room_id().to_owned();
+        let updates = BroadcastStream::new(self.room.subscribe_to_updates());
+
+        updates.flat_map(move |update| {
+            let events = match update {
+                Ok(RoomUpdate::Joined { updates, .. }) => updates.state,
+                Ok(RoomUpdate::Left { .. } | RoomUpdate::Invited { .. }) => Vec::new(),
+                Err(BroadcastStreamRecvError::Lagged(n)) => {
+                    warn!("Lagged behind {n} room updates, some state events were missed");
+                    return stream::iter(vec![StateUpdate::Lagged]);
+                }
+            };
+
+            stream::iter(
+                events
+                    .iter()
+                    .map(|event| StateUpdate::Event(attach_room_id(event, &room_id)))
+                    .collect::<Vec<_>>(),
+            )
+        })
+    }
+}
+
+/// An update of the state of the room, forwarded by
+/// [`MatrixDriver::state_events()`].
+pub(crate) enum StateUpdate {
+    /// A state event received in the state section of the sync.
+    Event(Raw<AnyTimelineEvent>),
+    /// Some room updates were missed, the current state must be read again.
+    Lagged,
 }
 
 /// A simple entity that wraps an `UnboundedReceiver`
 /// along with the drop guard for the room event handler.
-pub(crate) struct EventReceiver {
-    rx: UnboundedReceiver<Raw<AnyTimelineEvent>>,
+pub(crate) struct EventReceiver<T> {
+    rx: UnboundedReceiver<Raw<T>>,
     _drop_guard: EventHandlerDropGuard,
 }
 
-impl EventReceiver {
-    pub(crate) async fn recv(&mut self) -> Option<Raw<AnyTimelineEvent>> {
+impl<T> EventReceiver<T> {
+    pub(crate) async fn recv(&mut self) -> Option<Raw<T>> {
         self.rx.recv().await
     }
 }
 
-fn attach_room_id(raw_ev: &Raw<AnySyncTimelineEvent>, room_id: &RoomId) -> Raw<AnyTimelineEvent> {
+/// Reads the state events of a given `event_type` from a room.
+async fn read_room_state_events(
+    room: &Room,
+    event_type: &StateEventType,
+    state_key: &StateKeySelector,
+) -> Result<Vec<Raw<AnyTimelineEvent>>> {
+    let room_id = room.room_id();
+    let convert = |sync_or_stripped_state| match sync_or_stripped_state {
+        RawAnySyncOrStrippedState::Sync(ev) => Some(attach_room_id(ev.cast_ref(), room_id)),
+        RawAnySyncOrStrippedState::Stripped(_) => {
+            error!("MatrixDriver can't operate in invited rooms");
+            None
+        }
+    };
+
+    Ok(match state_key {
+        StateKeySelector::Key(state_key) => room
+            .get_state_event(event_type.clone(), state_key)
+            .await?
+            .and_then(convert)
+            .into_iter()
+            .collect(),
+        StateKeySelector::Any => {
+            let room_events = room.get_state_events(event_type.clone()).await?;
+            room_events.into_iter().filter_map(convert).collect()
+        }
+    })
+}
+
+/// Merges the events read from several rooms, skipping the rooms that
+/// couldn't be read.
+///
+/// Returns the last error if no room could be read.
+fn skip_failed_rooms(
+    results: Vec<Result<Vec<Raw<AnyTimelineEvent>>>>,
+) -> Result<Vec<Raw<AnyTimelineEvent>>> {
+    let mut events = Vec::new();
+    let mut last_error = None;
+    let mut num_read = 0;
+
+    for result in results {
+        match result {
+            Ok(room_events) => {
+                num_read += 1;
+                events.extend(room_events);
+            }
+            Err(error) => {
+                warn!("Skipping a room the widget can't read: {error}");
+                last_error = Some(error);
+            }
+        }
+    }
+
+    match last_error {
+        Some(error) if num_read == 0 => Err(error),
+        _ => Ok(events),
+    }
+}
+
+fn attach_room_id<T, U>(raw_ev: &Raw<T>, room_id: &RoomId) -> Raw<U> {
     let mut ev_obj = raw_ev.deserialize_as::<BTreeMap<String, Box<RawJsonValue>>>().unwrap();
     ev_obj.insert("room_id".to_owned(), serde_json::value::to_raw_value(room_id).unwrap());
     Raw::new(&ev_obj).unwrap().cast()
 }
+
+/// Keep only the fields of a to-device event that are given to widgets, as
+/// defined in [MSC3819].
+///
+/// [MSC3819]: https://github.com/matrix-org/matrix-spec-proposals/pull/3819
+fn strip_to_device_event(raw_ev: &Raw<AnyToDeviceEvent>) -> Raw<AnyToDeviceEvent> {
+    let mut ev_obj = raw_ev.deserialize_as::<BTreeMap<String, Box<RawJsonValue>>>().unwrap();
+    ev_obj.retain(|key, _| matches!(key.as_str(), "type" | "sender" | "content"));
+    Raw::new(&ev_obj).unwrap().cast()
+}
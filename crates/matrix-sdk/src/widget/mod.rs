@@ -14,42 +14,77 @@
 
 //! Widget API implementation.
 
-use std::fmt;
+use std::{
+    fmt,
+    pin::pin,
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
 
 use async_channel::{Receiver, Sender};
+use futures_core::Stream;
+use futures_util::{future::join5, StreamExt};
 use serde::de::{self, Deserialize, Deserializer, Visitor};
-use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
-use tokio_util::sync::{CancellationToken, DropGuard};
+use tokio::sync::{
+    broadcast,
+    mpsc::{unbounded_channel, UnboundedSender},
+};
+use tokio_stream::wrappers::BroadcastStream;
+use tracing::warn;
+use uuid::Uuid;
 
 use self::{
+    capabilities::PendingCapabilitiesRequest,
     machine::{
-        Action, IncomingMessage, MatrixDriverRequestData, MatrixDriverResponse, SendEventRequest,
-        WidgetMachine,
+        Action, IncomingMessage, MatrixDriverRequestData, MatrixDriverResponse, PickFileRequest,
+        RequestLimits, SendEphemeralEventRequest, SendEventRequest, SendToDeviceRequest,
+        UpdateDelayedEventRequest, WidgetMachine,
     },
-    matrix::MatrixDriver,
+    matrix::{MatrixDriver, StateUpdate},
+};
+use crate::{
+    executor::{spawn, JoinHandle},
+    room::Room,
+    Client, Result,
 };
-use crate::{room::Room, Result};
 
 mod capabilities;
+mod file_picker;
 mod filter;
 mod machine;
 mod matrix;
 mod settings;
 
+/// How often the pending requests are checked for expired ones, while the
+/// widget driver runs.
+const EXPIRED_REQUESTS_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+pub(crate) use self::settings::WIDGET_STATE_EVENT_TYPES;
 pub use self::{
-    capabilities::{Capabilities, CapabilitiesProvider},
-    filter::{EventFilter, MessageLikeEventFilter, StateEventFilter},
+    capabilities::{
+        Capabilities, CapabilitiesDecision, CapabilitiesProvider, DeferredCapabilities,
+        DeferredCapabilitiesSender, TimelineRooms,
+    },
+    file_picker::{FilePicker, PickedFile},
+    filter::{
+        EphemeralEventFilter, EventFilter, MessageLikeEventFilter, StateEventFilter,
+        ToDeviceEventFilter,
+    },
+    machine::{ValidationProblem, ValidationProblemKind, WidgetDiagnostic},
     settings::{
-        ClientProperties, EncryptionSystem, VirtualElementCallWidgetOptions, WidgetSettings,
+        ClientProperties, EncryptionSystem, Intent, RateLimits, VirtualElementCallWidgetOptions,
+        WidgetSettings, WidgetUrlError, WidgetUrlPolicy,
     },
 };
 
 /// An object that handles all interactions of a widget living inside a webview
 /// or iframe with the Matrix world.
-#[derive(Debug)]
 pub struct WidgetDriver {
     settings: WidgetSettings,
 
+    /// The settings of the driver itself.
+    driver_settings: WidgetDriverSettings,
+
     /// Raw incoming messages from the widget (normally formatted as JSON).
     ///
     /// These can be both requests and responses.
@@ -60,6 +95,74 @@ pub struct WidgetDriver {
     ///
     /// These can be both requests and responses.
     to_widget_tx: Sender<String>,
+
+    /// Events about the widget, for the host application.
+    driver_events_tx: broadcast::Sender<WidgetDriverEvent>,
+
+    /// The problems of the messages of the widget that couldn't be processed.
+    diagnostics_tx: broadcast::Sender<WidgetDiagnostic>,
+
+    /// The component that lets the user pick files for the widget, if any.
+    file_picker: Option<Arc<dyn FilePicker>>,
+}
+
+#[cfg(not(tarpaulin_include))]
+impl fmt::Debug for WidgetDriver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WidgetDriver")
+            .field("settings", &self.settings)
+            .field("driver_settings", &self.driver_settings)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Settings of the [`WidgetDriver`] itself, as opposed to the
+/// [`WidgetSettings`] of the widget that it runs.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct WidgetDriverSettings {
+    /// For how long the widget has to answer a request from the client,
+    /// before it is considered unresponsive.
+    ///
+    /// Defaults to 10 seconds.
+    pub reply_timeout: Duration,
+
+    /// How many times a request that the widget didn't answer in time is sent
+    /// again, before the widget is considered unresponsive.
+    ///
+    /// Only the requests that can safely be received twice by the widget are
+    /// sent again, like the request for its capabilities. The events
+    /// forwarded to the widget never are.
+    ///
+    /// Defaults to 0.
+    pub retries: u32,
+}
+
+impl Default for WidgetDriverSettings {
+    fn default() -> Self {
+        Self { reply_timeout: Duration::from_secs(10), retries: 0 }
+    }
+}
+
+impl WidgetDriverSettings {
+    /// Create the default `WidgetDriverSettings`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set for how long the widget has to answer a request from the client,
+    /// before it is considered unresponsive.
+    pub fn reply_timeout(mut self, reply_timeout: Duration) -> Self {
+        self.reply_timeout = reply_timeout;
+        self
+    }
+
+    /// Set how many times a request that the widget didn't answer in time is
+    /// sent again, if it can be.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
 }
 
 /// A handle that encapsulates the communication between a widget driver and the
@@ -81,6 +184,40 @@ pub struct WidgetDriverHandle {
     /// care what's what though because they are only supposed to forward
     /// messages between the webview / iframe, and the SDK's widget driver.
     from_widget_tx: Sender<String>,
+
+    /// Events about the widget, for the host application.
+    driver_events_tx: broadcast::Sender<WidgetDriverEvent>,
+}
+
+/// An event about a widget, that the host application might want to act
+/// upon.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum WidgetDriverEvent {
+    /// The widget keeps making requests over its [`RateLimits`].
+    ///
+    /// This is sent once the widget has been hitting the limits for a while,
+    /// and not again until it calms down. The host application might want to
+    /// warn the user, or to close the widget.
+    RateLimitExceeded {
+        /// The number of requests that were rejected since the widget
+        /// started hitting the limits.
+        rejected_requests: u64,
+    },
+
+    /// The widget didn't answer a request from the client within the
+    /// [`WidgetDriverSettings::reply_timeout`], even after the
+    /// [`WidgetDriverSettings::retries`] when the request could be sent
+    /// again.
+    ///
+    /// This is sent for every request that isn't answered. The host
+    /// application might want to tell the user that the widget is
+    /// unresponsive.
+    Unresponsive {
+        /// The action of the request that wasn't answered, like
+        /// `"capabilities"`.
+        action: String,
+    },
 }
 
 impl WidgetDriverHandle {
@@ -99,6 +236,11 @@ impl WidgetDriverHandle {
     pub async fn send(&self, message: String) -> bool {
         self.from_widget_tx.send(message).await.is_ok()
     }
+
+    /// Subscribe to the events about the widget, see [`WidgetDriverEvent`].
+    pub fn subscribe_to_events(&self) -> broadcast::Receiver<WidgetDriverEvent> {
+        self.driver_events_tx.subscribe()
+    }
 }
 
 impl WidgetDriver {
@@ -107,13 +249,57 @@ impl WidgetDriver {
     pub fn new(settings: WidgetSettings) -> (Self, WidgetDriverHandle) {
         let (from_widget_tx, from_widget_rx) = async_channel::unbounded();
         let (to_widget_tx, to_widget_rx) = async_channel::unbounded();
-
-        let driver = Self { settings, from_widget_rx, to_widget_tx };
-        let channels = WidgetDriverHandle { from_widget_tx, to_widget_rx };
+        let (driver_events_tx, _) = broadcast::channel(8);
+        let (diagnostics_tx, _) = broadcast::channel(32);
+
+        let driver = Self {
+            settings,
+            driver_settings: WidgetDriverSettings::default(),
+            from_widget_rx,
+            to_widget_tx,
+            driver_events_tx: driver_events_tx.clone(),
+            diagnostics_tx,
+            file_picker: None,
+        };
+        let channels = WidgetDriverHandle { from_widget_tx, to_widget_rx, driver_events_tx };
 
         (driver, channels)
     }
 
+    /// Use the given [`WidgetDriverSettings`] instead of the default ones.
+    pub fn with_driver_settings(mut self, driver_settings: WidgetDriverSettings) -> Self {
+        self.driver_settings = driver_settings;
+        self
+    }
+
+    /// Let the widget ask the user to pick a file with the given
+    /// [`FilePicker`], if it has the [`Capabilities::pick_file`] capability.
+    ///
+    /// The picked file is uploaded to the media repository, encrypted if the
+    /// room is encrypted, and the widget only receives its MXC URI and the
+    /// keys to decrypt it. Without a file picker, the requests of the widget
+    /// to pick a file fail.
+    ///
+    /// A new request of the widget to pick a file cancels the previous one,
+    /// which fails.
+    pub fn with_file_picker(mut self, file_picker: impl FilePicker) -> Self {
+        self.file_picker = Some(Arc::new(file_picker));
+        self
+    }
+
+    /// Get a stream of the problems found in the messages of the widget that
+    /// couldn't be processed, like unknown actions, missing fields or fields
+    /// with the wrong type.
+    ///
+    /// This is meant to help the developers embedding third-party widgets. The
+    /// widget is also told about the problems of its invalid requests, in the
+    /// error response. Diagnostics that aren't consumed fast enough are
+    /// dropped.
+    pub fn diagnostics(&self) -> impl Stream<Item = WidgetDiagnostic> {
+        BroadcastStream::new(self.diagnostics_tx.subscribe())
+            .filter_map(|diagnostic| std::future::ready(diagnostic.ok()))
+    }
+
     /// Starts a client widget API state machine for a given `widget` in a given
     /// joined `room`. The function returns once the widget is disconnected or
     /// any terminal error occurs.
@@ -130,27 +316,70 @@ impl WidgetDriver {
         let (events_tx, mut events_rx) = unbounded_channel();
 
         // Forward all of the incoming messages from the widget to the `events_tx`.
+        //
+        // The handle must be kept around while the driver runs, dropping it
+        // cancels the task on wasm.
         let tx = events_tx.clone();
-        tokio::spawn(async move {
+        let _widget_messages_task = spawn(async move {
             while let Ok(msg) = self.from_widget_rx.recv().await {
                 let _ = tx.send(IncomingMessage::WidgetMessage(msg));
             }
         });
 
+        // Regularly remind the widget machine to check for the requests that the
+        // widget didn't answer, since it only does so when processing a message.
+        let clock = room.client().base_client().clock().clone();
+        let tx = events_tx.clone();
+        let _expired_requests_task = spawn(async move {
+            loop {
+                clock.sleep(EXPIRED_REQUESTS_CHECK_INTERVAL).await;
+                if tx.send(IncomingMessage::CheckExpiredRequests).is_err() {
+                    break;
+                }
+            }
+        });
+
         // Create widget API machine.
+        let limits = RequestLimits {
+            to_widget_response_timeout: self.driver_settings.reply_timeout,
+            to_widget_retries: self.driver_settings.retries,
+            rate_limits: self.settings.rate_limits(),
+            ..Default::default()
+        };
         let (client_api, initial_actions) = WidgetMachine::new(
             self.settings.widget_id().to_owned(),
             room.room_id().to_owned(),
             self.settings.init_on_content_load(),
-            None,
+            Some(limits),
+            room.client().base_client().clock().clone(),
         );
 
+        // A capabilities decision deferred by a previous run of the driver.
+        let client = room.client();
+        let widget_id = self.settings.widget_id().to_owned();
+        let restored_capabilities_request =
+            match PendingCapabilitiesRequest::load(&client, &widget_id).await {
+                Ok(pending) => pending,
+                Err(e) => {
+                    warn!("Couldn't load the pending capabilities request of the widget: {e}");
+                    None
+                }
+            };
+
         // The environment for the processing of actions from the widget machine.
         let mut ctx = ProcessingContext {
+            client,
+            widget_id,
             widget_machine: client_api,
             matrix_driver: MatrixDriver::new(room.clone()),
-            event_forwarding_guard: None,
+            event_forwarding_task: None,
+            restored_capabilities_request,
+            deferred_capabilities_task: None,
+            file_picker: self.file_picker,
+            file_picking: None,
             to_widget_tx: self.to_widget_tx,
+            driver_events_tx: self.driver_events_tx,
+            diagnostics_tx: self.diagnostics_tx,
             events_tx,
             capabilities_provider,
         };
@@ -171,10 +400,22 @@ impl WidgetDriver {
 
 /// A small wrapper of all the data that we need to process an incoming event.
 struct ProcessingContext<T> {
+    client: Client,
+    widget_id: String,
     widget_machine: WidgetMachine,
     matrix_driver: MatrixDriver,
-    event_forwarding_guard: Option<DropGuard>,
+    event_forwarding_task: Option<DriverTask>,
+    /// The capabilities request deferred by a previous run of the driver, if
+    /// any, until the widget asks for capabilities.
+    restored_capabilities_request: Option<PendingCapabilitiesRequest>,
+    /// The task waiting for a deferred capabilities decision, if any.
+    deferred_capabilities_task: Option<DriverTask>,
+    file_picker: Option<Arc<dyn FilePicker>>,
+    /// The file picking in progress, if any.
+    file_picking: Option<FilePicking>,
     to_widget_tx: Sender<String>,
+    driver_events_tx: broadcast::Sender<WidgetDriverEvent>,
+    diagnostics_tx: broadcast::Sender<WidgetDiagnostic>,
     events_tx: UnboundedSender<IncomingMessage>,
     capabilities_provider: T,
 }
@@ -196,11 +437,42 @@ impl<T: CapabilitiesProvider> ProcessingContext<T> {
             Action::MatrixDriverRequest { request_id, data } => {
                 let response = match data {
                     MatrixDriverRequestData::AcquireCapabilities(cmd) => {
-                        let obtained = self
-                            .capabilities_provider
-                            .acquire_capabilities(cmd.desired_capabilities)
-                            .await;
-                        Ok(MatrixDriverResponse::CapabilitiesAcquired(obtained))
+                        let desired = cmd.desired_capabilities;
+                        let restored = self
+                            .restored_capabilities_request
+                            .take()
+                            .filter(|pending| pending.is_for(&desired));
+
+                        let decision = if restored.is_some() {
+                            self.capabilities_provider
+                                .resume_deferred_capabilities(desired.clone())
+                                .await
+                        } else {
+                            self.capabilities_provider
+                                .acquire_capabilities_or_defer(desired.clone())
+                                .await
+                        };
+
+                        match decision {
+                            CapabilitiesDecision::Granted(obtained) => {
+                                // Whatever request was pending is settled now.
+                                if let Err(e) = PendingCapabilitiesRequest::remove(
+                                    &self.client,
+                                    &self.widget_id,
+                                    None,
+                                )
+                                .await
+                                {
+                                    warn!("Couldn't forget the pending capabilities request: {e}");
+                                }
+
+                                Ok(MatrixDriverResponse::CapabilitiesAcquired(obtained))
+                            }
+                            CapabilitiesDecision::Deferred(deferred) => {
+                                self.defer_capabilities(request_id, desired, deferred).await?;
+                                return Ok(());
+                            }
+                        }
                     }
 
                     MatrixDriverRequestData::GetOpenId => self
@@ -212,24 +484,79 @@ impl<T: CapabilitiesProvider> ProcessingContext<T> {
 
                     MatrixDriverRequestData::ReadMessageLikeEvent(cmd) => self
                         .matrix_driver
-                        .read_message_like_events(cmd.event_type.clone(), cmd.limit)
+                        .read_message_like_events(&cmd.rooms, cmd.event_type.clone(), cmd.limit)
                         .await
                         .map(MatrixDriverResponse::MatrixEventRead)
                         .map_err(|e| e.to_string()),
 
                     MatrixDriverRequestData::ReadStateEvent(cmd) => self
                         .matrix_driver
-                        .read_state_events(cmd.event_type.clone(), &cmd.state_key)
+                        .read_state_events(&cmd.rooms, cmd.event_type.clone(), &cmd.state_key)
                         .await
                         .map(MatrixDriverResponse::MatrixEventRead)
                         .map_err(|e| e.to_string()),
 
+                    MatrixDriverRequestData::ReadRelations(req) => self
+                        .matrix_driver
+                        .read_relations(req)
+                        .await
+                        .map(MatrixDriverResponse::RelationsRead)
+                        .map_err(|e| e.to_string()),
+
                     MatrixDriverRequestData::SendMatrixEvent(req) => {
-                        let SendEventRequest { event_type, state_key, content } = req;
+                        let SendEventRequest { room_id, event_type, state_key, content, delay } =
+                            req;
+                        let room_id = room_id.as_deref();
+                        match delay {
+                            Some(delay) => self
+                                .matrix_driver
+                                .send_delayed(
+                                    room_id,
+                                    event_type,
+                                    state_key,
+                                    content,
+                                    Duration::from_millis(delay),
+                                )
+                                .await
+                                .map(MatrixDriverResponse::DelayedEventSent),
+                            None => self
+                                .matrix_driver
+                                .send(room_id, event_type, state_key, content)
+                                .await
+                                .map(MatrixDriverResponse::MatrixEventSent),
+                        }
+                        .map_err(|e| e.to_string())
+                    }
+
+                    MatrixDriverRequestData::SendEphemeralEvent(req) => {
+                        let SendEphemeralEventRequest { event_type, content } = req;
+                        self.matrix_driver
+                            .send_ephemeral(event_type, content)
+                            .await
+                            .map(|()| MatrixDriverResponse::EphemeralEventSent)
+                            .map_err(|e| e.to_string())
+                    }
+
+                    MatrixDriverRequestData::SendToDeviceMessage(req) => {
+                        let SendToDeviceRequest { event_type, encrypted, messages } = req;
+                        self.matrix_driver
+                            .send_to_device(event_type, encrypted, messages)
+                            .await
+                            .map(MatrixDriverResponse::ToDeviceSent)
+                            .map_err(|e| e.to_string())
+                    }
+
+                    MatrixDriverRequestData::PickFile(req) => {
+                        self.pick_file(request_id, req);
+                        return Ok(());
+                    }
+
+                    MatrixDriverRequestData::UpdateDelayedEvent(req) => {
+                        let UpdateDelayedEventRequest { delay_id, action } = req;
                         self.matrix_driver
-                            .send(event_type, state_key, content)
+                            .update_delayed_event(&delay_id, action)
                             .await
-                            .map(MatrixDriverResponse::MatrixEventSent)
+                            .map(|()| MatrixDriverResponse::DelayedEventUpdated)
                             .map_err(|e| e.to_string())
                     }
                 };
@@ -238,36 +565,219 @@ impl<T: CapabilitiesProvider> ProcessingContext<T> {
                     .send(IncomingMessage::MatrixDriverResponse { request_id, response })
                     .map_err(|_| ())?;
             }
-            Action::Subscribe => {
+            Action::Subscribe { timeline_rooms } => {
                 // Only subscribe if we are not already subscribed.
-                if self.event_forwarding_guard.is_none() {
-                    let (stop_forwarding, guard) = {
-                        let token = CancellationToken::new();
-                        (token.child_token(), token.drop_guard())
-                    };
-
-                    self.event_forwarding_guard = Some(guard);
-                    let (mut matrix, events_tx) =
-                        (self.matrix_driver.events(), self.events_tx.clone());
-                    tokio::spawn(async move {
-                        loop {
-                            tokio::select! {
-                                _ = stop_forwarding.cancelled() => { return }
-                                Some(event) = matrix.recv() => {
-                                    let _ = events_tx.send(IncomingMessage::MatrixEventReceived(event));
-                                }
+                if self.event_forwarding_task.is_none() {
+                    let mut matrix = self.matrix_driver.events();
+                    let mut other_rooms = (timeline_rooms != TimelineRooms::OwnRoom)
+                        .then(|| self.matrix_driver.other_rooms_events(timeline_rooms));
+                    let mut ephemeral = self.matrix_driver.ephemeral_events();
+                    let mut to_device = self.matrix_driver.to_device_events();
+                    let state = self.matrix_driver.state_events();
+                    let events_tx = self.events_tx.clone();
+
+                    let join_handle = spawn(async move {
+                        let mut state = pin!(state);
+
+                        let timeline_events = async {
+                            while let Some(event) = matrix.recv().await {
+                                let _ = events_tx.send(IncomingMessage::MatrixEventReceived(event));
                             }
-                        }
+                        };
+                        let ephemeral_events = async {
+                            while let Some(event) = ephemeral.recv().await {
+                                let _ =
+                                    events_tx.send(IncomingMessage::EphemeralEventReceived(event));
+                            }
+                        };
+
+                        let to_device_events = async {
+                            while let Some(event) = to_device.recv().await {
+                                let _ =
+                                    events_tx.send(IncomingMessage::ToDeviceEventReceived(event));
+                            }
+                        };
+
+                        // The state events of the state section of the sync, which
+                        // aren't part of the timeline.
+                        let state_events = async {
+                            while let Some(update) = state.next().await {
+                                let message = match update {
+                                    StateUpdate::Event(event) => {
+                                        IncomingMessage::MatrixEventReceived(event)
+                                    }
+                                    StateUpdate::Lagged => IncomingMessage::RoomStateLagged,
+                                };
+                                let _ = events_tx.send(message);
+                            }
+                        };
+
+                        // The timeline events of the other rooms that the widget can
+                        // access, if any.
+                        let other_rooms_events = async {
+                            let Some(other_rooms) = &mut other_rooms else { return };
+                            while let Some(event) = other_rooms.recv().await {
+                                let _ = events_tx.send(IncomingMessage::MatrixEventReceived(event));
+                            }
+                        };
+
+                        join5(
+                            timeline_events,
+                            ephemeral_events,
+                            to_device_events,
+                            state_events,
+                            other_rooms_events,
+                        )
+                        .await;
                     });
+
+                    self.event_forwarding_task = Some(DriverTask { join_handle });
                 }
             }
             Action::Unsubscribe => {
-                self.event_forwarding_guard = None;
+                self.event_forwarding_task = None;
+            }
+            Action::NotifyRateLimitExceeded { rejected_requests } => {
+                // It's fine if nobody is listening.
+                let _ = self
+                    .driver_events_tx
+                    .send(WidgetDriverEvent::RateLimitExceeded { rejected_requests });
+            }
+            Action::NotifyWidgetUnresponsive { action } => {
+                // It's fine if nobody is listening.
+                let _ = self
+                    .driver_events_tx
+                    .send(WidgetDriverEvent::Unresponsive { action: action.to_owned() });
+            }
+            Action::ReportDiagnostic(diagnostic) => {
+                // It's fine if nobody is listening.
+                let _ = self.diagnostics_tx.send(diagnostic);
             }
         }
 
         Ok(())
     }
+
+    /// Let the user pick a file in a separate task, so the widget isn't
+    /// blocked meanwhile, and forward the uploaded file to the widget machine
+    /// once it's done.
+    fn pick_file(&mut self, request_id: Uuid, request: PickFileRequest) {
+        let events_tx = self.events_tx.clone();
+        let Some(file_picker) = self.file_picker.clone() else {
+            let response = Err("The client doesn't support picking files".to_owned());
+            let _ = events_tx.send(IncomingMessage::MatrixDriverResponse { request_id, response });
+            return;
+        };
+
+        // A new file picking supersedes the previous one, which is stopped and
+        // answered with an error if it wasn't answered yet.
+        if let Some(previous) = self.file_picking.take() {
+            let previous_request_id = previous.request_id.lock().unwrap().take();
+            drop(previous);
+
+            if let Some(request_id) = previous_request_id {
+                let response = Err("The file picking was superseded by a new one".to_owned());
+                let _ =
+                    events_tx.send(IncomingMessage::MatrixDriverResponse { request_id, response });
+            }
+        }
+
+        let pending_request_id = Arc::new(StdMutex::new(Some(request_id)));
+        let matrix_driver = self.matrix_driver.clone();
+        let join_handle = spawn({
+            let pending_request_id = pending_request_id.clone();
+            async move {
+                let response = match file_picker.pick_file(request.accept).await {
+                    Some(file) => matrix_driver
+                        .upload_file(file)
+                        .await
+                        .map(|file| MatrixDriverResponse::FilePicked(Some(file)))
+                        .map_err(|e| e.to_string()),
+                    None => Ok(MatrixDriverResponse::FilePicked(None)),
+                };
+
+                // The request was already answered if it was superseded meanwhile.
+                if let Some(request_id) = pending_request_id.lock().unwrap().take() {
+                    let _ = events_tx
+                        .send(IncomingMessage::MatrixDriverResponse { request_id, response });
+                }
+            }
+        });
+
+        self.file_picking =
+            Some(FilePicking { request_id: pending_request_id, _task: DriverTask { join_handle } });
+    }
+
+    /// Park the widget until the capabilities decision is made, and forward
+    /// the decision to the widget machine once it is.
+    ///
+    /// The request is kept in the state store meanwhile, so it can be restored
+    /// if the driver stops before the decision is made.
+    async fn defer_capabilities(
+        &mut self,
+        request_id: Uuid,
+        capabilities: Capabilities,
+        deferred: DeferredCapabilities,
+    ) -> Result<(), ()> {
+        self.events_tx
+            .send(IncomingMessage::CapabilitiesDeferred { request_id })
+            .map_err(|_| ())?;
+
+        let pending = PendingCapabilitiesRequest { request_id, capabilities };
+        if let Err(e) = pending.save(&self.client, &self.widget_id).await {
+            warn!("Couldn't store the pending capabilities request: {e}");
+        }
+
+        let events_tx = self.events_tx.clone();
+        let client = self.client.clone();
+        let widget_id = self.widget_id.clone();
+        let join_handle = spawn(async move {
+            let response = deferred
+                .receiver
+                .await
+                .map(MatrixDriverResponse::CapabilitiesAcquired)
+                .map_err(|_| "The capabilities decision was dropped".to_owned());
+
+            // The decision was made, or never will be.
+            if let Err(e) =
+                PendingCapabilitiesRequest::remove(&client, &widget_id, Some(request_id)).await
+            {
+                warn!("Couldn't forget the pending capabilities request: {e}");
+            }
+
+            let _ = events_tx.send(IncomingMessage::MatrixDriverResponse { request_id, response });
+        });
+
+        // A new deferred decision supersedes the previous one.
+        self.deferred_capabilities_task = Some(DriverTask { join_handle });
+
+        Ok(())
+    }
+}
+
+/// A file picking in progress.
+struct FilePicking {
+    /// The ID of the request of the widget, until it is answered.
+    request_id: Arc<StdMutex<Option<Uuid>>>,
+    /// The task waiting for the user to pick a file and uploading it.
+    _task: DriverTask,
+}
+
+/// A background task of the widget driver, stopped when it is dropped.
+///
+/// All the tasks of the driver are stopped when the driver stops, or when they
+/// are superseded by a new task of the same kind.
+struct DriverTask {
+    #[allow(dead_code)]
+    join_handle: JoinHandle<()>,
+}
+
+impl Drop for DriverTask {
+    fn drop(&mut self) {
+        // On wasm, dropping the handle is enough to cancel the task.
+        #[cfg(not(target_arch = "wasm32"))]
+        self.join_handle.abort();
+    }
 }
 
 // TODO: Decide which module this type should live in
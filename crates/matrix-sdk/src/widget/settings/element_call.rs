@@ -20,9 +20,8 @@
 // in the rust sdk. Find a better solution for this.
 
 use serde::Serialize;
-use url::Url;
 
-use super::{url_params, WidgetSettings};
+use super::{url_params, WidgetSettings, WidgetUrlError, WidgetUrlPolicy, CUSTOM_WIDGET_TYPE};
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -49,6 +48,13 @@ struct ElementCallParams {
     #[serde(rename = "perParticipantE2EE")]
     per_participant_e2ee: bool,
     password: Option<String>,
+    intent: Option<Intent>,
+    posthog_user_id: Option<String>,
+    posthog_api_host: Option<String>,
+    posthog_api_key: Option<String>,
+    rageshake_submit_url: Option<String>,
+    sentry_dsn: Option<String>,
+    sentry_environment: Option<String>,
 }
 
 /// Defines if a call is encrypted and which encryption system should be used.
@@ -70,6 +76,18 @@ pub enum EncryptionSystem {
     },
 }
 
+/// Defines the intent of showing the call.
+///
+/// This controls whether to show or skip the lobby.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Intent {
+    /// The user wants to start a call.
+    StartCall,
+    /// The user wants to join an existing call.
+    JoinExisting,
+}
+
 /// Properties to create a new virtual Element Call widget.
 #[derive(Debug)]
 pub struct VirtualElementCallWidgetOptions {
@@ -138,6 +156,30 @@ pub struct VirtualElementCallWidgetOptions {
     ///
     /// Use `EncryptionSystem::Unencrypted` to disable encryption.
     pub encryption: EncryptionSystem,
+
+    /// The intent of showing the call.
+    ///
+    /// If the user wants to start a call or join an existing one.
+    pub intent: Option<Intent>,
+
+    /// The PostHog user id, to send the analytics of Element Call with the
+    /// same id as the client.
+    pub posthog_user_id: Option<String>,
+
+    /// The url of the PostHog server to send the analytics to.
+    pub posthog_api_host: Option<String>,
+
+    /// The key of the PostHog project to send the analytics to.
+    pub posthog_api_key: Option<String>,
+
+    /// The url where the rageshakes (bug reports) are submitted.
+    pub rageshake_submit_url: Option<String>,
+
+    /// The Sentry DSN to report the errors of Element Call to.
+    pub sentry_dsn: Option<String>,
+
+    /// The Sentry environment of the reported errors.
+    pub sentry_environment: Option<String>,
 }
 
 impl WidgetSettings {
@@ -154,10 +196,23 @@ impl WidgetSettings {
     ///
     /// * - `props` A struct containing the configuration parameters for a
     /// element call widget.
+    ///
+    /// The URL of Element Call is validated with the default
+    /// [`WidgetUrlPolicy`], which accepts widgets from any domain.
     pub fn new_virtual_element_call_widget(
         props: VirtualElementCallWidgetOptions,
-    ) -> Result<Self, url::ParseError> {
-        let mut raw_url: Url = Url::parse(&format!("{}/room", props.element_call_url))?;
+    ) -> Result<Self, WidgetUrlError> {
+        Self::new_virtual_element_call_widget_with_url_policy(props, &WidgetUrlPolicy::new())
+    }
+
+    /// Create the `WidgetSettings` of an Element Call widget, like
+    /// [`WidgetSettings::new_virtual_element_call_widget()`], validating the
+    /// URL of Element Call with the given policy.
+    pub fn new_virtual_element_call_widget_with_url_policy(
+        props: VirtualElementCallWidgetOptions,
+        policy: &WidgetUrlPolicy,
+    ) -> Result<Self, WidgetUrlError> {
+        let mut raw_url = policy.validate(&format!("{}/room", props.element_call_url))?;
 
         let query_params = ElementCallParams {
             user_id: url_params::USER_ID.to_owned(),
@@ -184,6 +239,13 @@ impl WidgetSettings {
                 EncryptionSystem::SharedSecret { secret } => Some(secret),
                 _ => None,
             },
+            intent: props.intent,
+            posthog_user_id: props.posthog_user_id,
+            posthog_api_host: props.posthog_api_host,
+            posthog_api_key: props.posthog_api_key,
+            rageshake_submit_url: props.rageshake_submit_url,
+            sentry_dsn: props.sentry_dsn,
+            sentry_environment: props.sentry_environment,
         };
 
         let query =
@@ -198,7 +260,14 @@ impl WidgetSettings {
         raw_url.set_fragment(Some(&format!("?{}", query)));
 
         // for EC we always want init on content load to be true.
-        Ok(Self { widget_id: props.widget_id, init_on_content_load: true, raw_url })
+        Ok(Self {
+            widget_id: props.widget_id,
+            init_on_content_load: true,
+            raw_url,
+            widget_type: CUSTOM_WIDGET_TYPE.to_owned(),
+            data: Default::default(),
+            rate_limits: None,
+        })
     }
 }
 
@@ -206,15 +275,21 @@ impl WidgetSettings {
 mod tests {
     use std::collections::BTreeSet;
 
+    use assert_matches::assert_matches;
     use ruma::api::client::profile::get_profile;
     use url::Url;
 
-    use crate::widget::{ClientProperties, WidgetSettings};
+    use crate::widget::{ClientProperties, WidgetSettings, WidgetUrlError, WidgetUrlPolicy};
 
     const WIDGET_ID: &str = "1/@#w23";
 
     fn get_widget_settings(encryption: Option<EncryptionSystem>) -> WidgetSettings {
-        WidgetSettings::new_virtual_element_call_widget(VirtualElementCallWidgetOptions {
+        WidgetSettings::new_virtual_element_call_widget(get_widget_options(encryption))
+            .expect("could not parse virtual element call widget")
+    }
+
+    fn get_widget_options(encryption: Option<EncryptionSystem>) -> VirtualElementCallWidgetOptions {
+        VirtualElementCallWidgetOptions {
             element_call_url: "https://call.element.io".to_owned(),
             widget_id: WIDGET_ID.to_owned(),
             parent_url: None,
@@ -227,8 +302,14 @@ mod tests {
             font: None,
             analytics_id: None,
             encryption: encryption.unwrap_or(EncryptionSystem::PerParticipantKeys),
-        })
-        .expect("could not parse virtual element call widget")
+            intent: None,
+            posthog_user_id: None,
+            posthog_api_host: None,
+            posthog_api_key: None,
+            rageshake_submit_url: None,
+            sentry_dsn: None,
+            sentry_environment: None,
+        }
     }
 
     trait FragmentQuery {
@@ -247,7 +328,7 @@ mod tests {
 
     use serde_html_form::from_str;
 
-    use super::{EncryptionSystem, VirtualElementCallWidgetOptions};
+    use super::{EncryptionSystem, Intent, VirtualElementCallWidgetOptions};
 
     fn get_query_sets(url: &Url) -> Option<(QuerySet, QuerySet)> {
         let fq = from_str::<QuerySet>(url.fragment_query().unwrap_or_default()).ok()?;
@@ -298,6 +379,40 @@ mod tests {
         assert_eq!(get_widget_settings(None).widget_id(), WIDGET_ID);
     }
 
+    #[test]
+    fn new_virtual_element_call_widget_url_policy() {
+        let mut options = get_widget_options(None);
+        options.element_call_url = "javascript:alert(1)//".to_owned();
+        assert_matches!(
+            WidgetSettings::new_virtual_element_call_widget(options),
+            Err(WidgetUrlError::ForbiddenScheme(_))
+        );
+
+        let mut options = get_widget_options(None);
+        options.element_call_url = "http://call.element.io".to_owned();
+        assert_matches!(
+            WidgetSettings::new_virtual_element_call_widget(options),
+            Err(WidgetUrlError::ForbiddenScheme(_))
+        );
+
+        let policy = WidgetUrlPolicy::new().with_allowed_domains(["element.io"]);
+        let settings = WidgetSettings::new_virtual_element_call_widget_with_url_policy(
+            get_widget_options(None),
+            &policy,
+        )
+        .unwrap();
+        assert_eq!(settings.base_url().unwrap().as_str(), "https://call.element.io/");
+
+        let policy = WidgetUrlPolicy::new().with_allowed_domains(["example.org"]);
+        assert_matches!(
+            WidgetSettings::new_virtual_element_call_widget_with_url_policy(
+                get_widget_options(None),
+                &policy,
+            ),
+            Err(WidgetUrlError::DomainNotAllowed(_))
+        );
+    }
+
     fn build_url_from_widget_settings(settings: WidgetSettings) -> String {
         settings
             ._generate_webview_url(
@@ -397,4 +512,51 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn telemetry_url_props_from_widget_settings() {
+        let widget_settings =
+            WidgetSettings::new_virtual_element_call_widget(VirtualElementCallWidgetOptions {
+                element_call_url: "https://call.element.io".to_owned(),
+                widget_id: WIDGET_ID.to_owned(),
+                parent_url: None,
+                hide_header: None,
+                preload: None,
+                font_scale: None,
+                app_prompt: None,
+                skip_lobby: None,
+                confine_to_room: None,
+                font: None,
+                analytics_id: None,
+                encryption: EncryptionSystem::Unencrypted,
+                intent: Some(Intent::JoinExisting),
+                posthog_user_id: Some("POSTHOG_USER_ID".to_owned()),
+                posthog_api_host: Some("https://posthog.element.io".to_owned()),
+                posthog_api_key: Some("POSTHOG_KEY".to_owned()),
+                rageshake_submit_url: Some("https://rageshake.element.io/submit".to_owned()),
+                sentry_dsn: Some("SENTRY_DSN".to_owned()),
+                sentry_environment: Some("production".to_owned()),
+            })
+            .unwrap();
+
+        let query_set = get_query_sets(widget_settings.raw_url()).unwrap().1;
+        let expected_elements = [
+            ("intent", "join_existing"),
+            ("posthogUserId", "POSTHOG_USER_ID"),
+            ("posthogApiHost", "https://posthog.element.io"),
+            ("posthogApiKey", "POSTHOG_KEY"),
+            ("rageshakeSubmitUrl", "https://rageshake.element.io/submit"),
+            ("sentryDsn", "SENTRY_DSN"),
+            ("sentryEnvironment", "production"),
+        ];
+        for (key, value) in expected_elements {
+            let e = (key.to_owned(), value.to_owned());
+            assert!(
+                query_set.contains(&e),
+                "The query elements: \n{:?}\nDid not contain: \n{:?}",
+                query_set,
+                e
+            );
+        }
+    }
 }
@@ -13,15 +13,26 @@
 // limitations under the License.
 
 use language_tags::LanguageTag;
-use ruma::{api::client::profile::get_profile, DeviceId, RoomId, UserId};
+use ruma::{
+    api::client::profile::get_profile,
+    events::AnySyncStateEvent,
+    serde::{JsonObject, Raw},
+    DeviceId, RoomId, UserId,
+};
+use serde::Deserialize;
 use url::Url;
 
 use crate::Room;
 
 mod element_call;
 mod url_params;
+mod url_policy;
 
-pub use self::element_call::{EncryptionSystem, VirtualElementCallWidgetOptions};
+pub(crate) use self::url_policy::WIDGET_STATE_EVENT_TYPES;
+pub use self::{
+    element_call::{EncryptionSystem, Intent, VirtualElementCallWidgetOptions},
+    url_policy::{WidgetUrlError, WidgetUrlPolicy},
+};
 
 /// Settings of the widget.
 #[derive(Debug, Clone)]
@@ -29,16 +40,142 @@ pub struct WidgetSettings {
     widget_id: String,
     init_on_content_load: bool,
     raw_url: Url,
+    widget_type: String,
+    data: JsonObject,
+    rate_limits: Option<RateLimits>,
+}
+
+/// The type of the widgets that don't have a more specific one.
+const CUSTOM_WIDGET_TYPE: &str = "m.custom";
+
+/// Limits on the rate of requests a widget can make to the widget driver.
+///
+/// Requests that read or send events, or that ask for an OpenID token, count
+/// towards the limits. Requests made over the limits are answered with an
+/// error, without reaching the homeserver.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimits {
+    /// The average number of requests the widget can make per second.
+    pub requests_per_second: u32,
+    /// The number of requests the widget can make at once, above the average
+    /// rate. It is at least 1.
+    pub burst: u32,
 }
 
 impl WidgetSettings {
-    /// Create a new WidgetSettings instance
+    /// Create a new WidgetSettings instance.
+    ///
+    /// The URL is validated with the default [`WidgetUrlPolicy`], which
+    /// accepts widgets from any domain.
     pub fn new(
         id: String,
         init_on_content_load: bool,
         raw_url: &str,
-    ) -> Result<Self, url::ParseError> {
-        Ok(Self { widget_id: id, init_on_content_load, raw_url: Url::parse(raw_url)? })
+    ) -> Result<Self, WidgetUrlError> {
+        Self::new_with_url_policy(id, init_on_content_load, raw_url, &WidgetUrlPolicy::new())
+    }
+
+    /// Create a new WidgetSettings instance, validating the URL with the given
+    /// policy, for example to only accept widgets from some domains.
+    pub fn new_with_url_policy(
+        id: String,
+        init_on_content_load: bool,
+        raw_url: &str,
+        policy: &WidgetUrlPolicy,
+    ) -> Result<Self, WidgetUrlError> {
+        Ok(Self {
+            widget_id: id,
+            init_on_content_load,
+            raw_url: policy.validate(raw_url)?,
+            widget_type: CUSTOM_WIDGET_TYPE.to_owned(),
+            data: JsonObject::new(),
+            rate_limits: None,
+        })
+    }
+
+    /// Create a new WidgetSettings instance from a `m.widget` or
+    /// `im.vector.modular.widgets` state event of a room.
+    ///
+    /// The ID of the widget is the state key of the event, and its URL is
+    /// validated with the given policy. Returns [`WidgetUrlError::NotAWidget`]
+    /// if the event doesn't define a widget, for example because the widget
+    /// was removed from the room.
+    pub fn from_state_event(
+        event: &Raw<AnySyncStateEvent>,
+        policy: &WidgetUrlPolicy,
+    ) -> Result<Self, WidgetUrlError> {
+        let raw_url = policy.check_widget_state_event(event)?;
+        let widget_id = event
+            .get_field::<String>("state_key")
+            .ok()
+            .flatten()
+            .ok_or(WidgetUrlError::NotAWidget)?;
+
+        #[derive(Deserialize)]
+        struct WidgetContent {
+            #[serde(rename = "type")]
+            widget_type: Option<String>,
+            #[serde(rename = "waitForIframeLoad")]
+            wait_for_iframe_load: Option<bool>,
+            data: Option<JsonObject>,
+        }
+
+        let content = event.get_field::<WidgetContent>("content").ok().flatten();
+        let (widget_type, wait_for_iframe_load, data) = content
+            .map(|content| (content.widget_type, content.wait_for_iframe_load, content.data))
+            .unwrap_or_default();
+
+        Ok(Self {
+            widget_id,
+            // Unless told otherwise, the widget is ready once its iframe is
+            // loaded, instead of waiting for it to send `content_loaded`.
+            init_on_content_load: !wait_for_iframe_load.unwrap_or(true),
+            raw_url,
+            widget_type: widget_type.unwrap_or_else(|| CUSTOM_WIDGET_TYPE.to_owned()),
+            data: data.unwrap_or_default(),
+            rate_limits: None,
+        })
+    }
+
+    /// Set the type of the widget, like `m.etherpad` or `m.jitsi`.
+    ///
+    /// By default, the type is `m.custom`.
+    pub fn with_widget_type(mut self, widget_type: impl Into<String>) -> Self {
+        self.widget_type = widget_type.into();
+        self
+    }
+
+    /// The type of the widget, `m.custom` unless it has a more specific one.
+    pub fn widget_type(&self) -> &str {
+        &self.widget_type
+    }
+
+    /// Set the data of the widget.
+    ///
+    /// The string, number and boolean values of the data can be used as
+    /// template variables in the URL of the widget, like `$key`, except for the
+    /// ones that are set by the client.
+    pub fn with_data(mut self, data: JsonObject) -> Self {
+        self.data = data;
+        self
+    }
+
+    /// The data of the widget, from the `data` field of its state event.
+    pub fn data(&self) -> &JsonObject {
+        &self.data
+    }
+
+    /// Limit the rate of requests the widget can make.
+    ///
+    /// By default, requests from the widget are not rate limited.
+    pub fn with_rate_limits(mut self, rate_limits: RateLimits) -> Self {
+        self.rate_limits = Some(rate_limits);
+        self
+    }
+
+    /// The limits on the rate of requests the widget can make, if any.
+    pub fn rate_limits(&self) -> Option<RateLimits> {
+        self.rate_limits
     }
 
     /// Widget's unique identifier.
@@ -56,7 +193,8 @@ impl WidgetSettings {
     /// This contains the url from the widget state event.
     /// In this url placeholders can be used to pass information from the client
     /// to the widget. Possible values are: `$matrix_widget_id`,
-    /// `$matrix_display_name`, etc.
+    /// `$matrix_display_name`, etc., and the keys of the
+    /// [data of the widget](Self::data).
     ///
     /// # Examples
     ///
@@ -82,9 +220,6 @@ impl WidgetSettings {
     /// * `room` - A matrix room which is used to query the logged in username
     /// * `props` - Properties from the client that can be used by a widget to
     ///   adapt to the client. e.g. language, font-scale...
-    //
-    // TODO: add `From<WidgetStateEvent>`, so that `WidgetSettings` can be built
-    // by using the room state.
     pub async fn generate_webview_url(
         &self,
         room: &Room,
@@ -123,6 +258,20 @@ impl WidgetSettings {
             client_id: client_props.client_id,
             device_id: device_id.into(),
             homeserver_url: homeserver_url.into(),
+            data: self
+                .data
+                .iter()
+                .filter_map(|(key, value)| {
+                    let value = match value {
+                        serde_json::Value::String(value) => value.clone(),
+                        serde_json::Value::Number(_) | serde_json::Value::Bool(_) => {
+                            value.to_string()
+                        }
+                        _ => return None,
+                    };
+                    Some((key.clone(), value))
+                })
+                .collect(),
         };
         let mut generated_url = self.raw_url.clone();
         url_params::replace_properties(&mut generated_url, query_props);
@@ -12,6 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::BTreeMap;
+
 use url::Url;
 use urlencoding::encode;
 
@@ -37,10 +39,12 @@ pub struct QueryProperties {
     pub(crate) client_id: String,
     pub(crate) device_id: String,
     pub(crate) homeserver_url: String,
+    /// The template variables from the `data` of the widget, without the `$`.
+    pub(crate) data: BTreeMap<String, String>,
 }
 
 pub fn replace_properties(url: &mut Url, props: QueryProperties) {
-    let replace_map: [(&str, String); 10] = [
+    let mut replace_map: Vec<(&str, String)> = [
         (WIDGET_ID, encode(&props.widget_id).into()),
         (AVATAR_URL, encode(&props.avatar_url).into()),
         (DEVICE_ID, encode(&props.device_id).into()),
@@ -55,7 +59,15 @@ pub fn replace_properties(url: &mut Url, props: QueryProperties) {
     .map(|to_replace| {
         // It's safe to unwrap here since we know all replace strings start with `$`
         (to_replace.0.get(1..).unwrap(), to_replace.1)
-    });
+    })
+    .into();
+
+    // The data of the widget can't override the variables set by the client.
+    for (key, value) in &props.data {
+        if !key.is_empty() && replace_map.iter().all(|(old, _)| old != key) {
+            replace_map.push((key, encode(value).into()));
+        }
+    }
 
     let s = url.as_str();
     let Some(beginning) = s.split_once('$').map(|s| s.0) else {
@@ -64,20 +76,18 @@ pub fn replace_properties(url: &mut Url, props: QueryProperties) {
     };
     let mut result = String::from(beginning);
     for section in s.split('$').skip(1) {
-        let mut section_added = false;
-        for (old, new) in &replace_map {
-            section.split_once(|c: char| !(c.is_ascii_alphanumeric() || c == '.' || c == '_'));
-            // It's safe to unwrap here since we know all replace strings start with `$`
-            if section.starts_with(old) {
+        // Use the longest variable, so `$foo_bar` isn't replaced as `$foo`.
+        let replacement = replace_map
+            .iter()
+            .filter(|(old, _)| section.starts_with(old))
+            .max_by_key(|(old, _)| old.len());
+
+        match replacement {
+            Some((old, new)) => {
                 result.push_str(new);
-                if let Some(rest) = section.get(old.len()..) {
-                    result.push_str(rest);
-                }
-                section_added = true;
+                result.push_str(&section[old.len()..]);
             }
-        }
-        if !section_added {
-            result.push_str(section);
+            None => result.push_str(section),
         }
     }
     *url = Url::parse(&result).unwrap();
@@ -85,6 +95,8 @@ pub fn replace_properties(url: &mut Url, props: QueryProperties) {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
+
     use url::Url;
 
     use super::{replace_properties, QueryProperties};
@@ -114,6 +126,7 @@ mod tests {
             client_id: "12345678".to_owned(),
             device_id: "!@/abc_device_id".to_owned(),
             homeserver_url: "https://abc_base_url/".to_owned(),
+            data: BTreeMap::new(),
         }
     }
 
@@ -141,4 +154,26 @@ mod tests {
         replace_properties(&mut url, get_example_props());
         assert_eq!(url.as_str(), CONVERTED_URL);
     }
+
+    #[test]
+    fn replace_data_properties() {
+        let mut url = Url::parse(
+            "https://my.widget.org/?pad=$padName&padId=$padName_id&user=$matrix_user_id",
+        )
+        .unwrap();
+
+        let mut props = get_example_props();
+        props.data = BTreeMap::from([
+            ("padName".to_owned(), "my pad".to_owned()),
+            ("padName_id".to_owned(), "42".to_owned()),
+            // The variables of the client can't be overridden.
+            ("matrix_user_id".to_owned(), "@mallory:example.org".to_owned()),
+        ]);
+
+        replace_properties(&mut url, props);
+        assert_eq!(
+            url.as_str(),
+            "https://my.widget.org/?pad=my%20pad&padId=42&user=%21%40%2Fabc_user_id"
+        );
+    }
 }
@@ -1,14 +1,29 @@
-use std::{collections::BTreeMap, time::Duration};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use assert_matches2::assert_let;
-use futures_util::FutureExt;
+use assert_matches2::{assert_let, assert_matches};
+use futures_util::{future::join, pin_mut, FutureExt, StreamExt};
 use matrix_sdk::{
-    config::SyncSettings,
+    clock::TestClock,
+    config::{ConcurrentSyncPolicy, RequestConfig, StoreConfig, SyncSettings},
+    features::ClientFeature,
+    invite_filter::{IgnoredUsersFilter, SharedRoomsFilter},
+    matrix_auth::{MatrixSession, MatrixSessionTokens},
     media::{MediaFormat, MediaRequest, MediaThumbnailSize},
+    store_cleanup::CleanupPolicy,
     sync::RoomUpdate,
+    Client, DeactivationOptions, DeactivationStep, Error, LoopCtrl, SessionGoneReason,
+    SessionStatus,
+};
+use matrix_sdk_base::{store::MemoryStore, RoomState, SessionMeta};
+use matrix_sdk_test::{
+    async_test, test_json, EphemeralTestEvent, GlobalAccountDataTestEvent, InvitedRoomBuilder,
+    JoinedRoomBuilder, PresenceTestEvent, RoomAccountDataTestEvent, StateTestEvent,
+    StrippedStateTestEvent, SyncResponseBuilder, DEFAULT_TEST_ROOM_ID,
 };
-use matrix_sdk_base::RoomState;
-use matrix_sdk_test::{async_test, test_json, DEFAULT_TEST_ROOM_ID};
 use ruma::{
     api::client::{
         directory::{
@@ -16,26 +31,31 @@ use ruma::{
             get_public_rooms_filtered::{self, v3::Request as PublicRoomsFilterRequest},
         },
         media::get_content_thumbnail::v3::Method,
-        uiaa,
+        receipt::create_receipt::v3::ReceiptType,
+        uiaa, MatrixVersion,
     },
     assign, device_id,
     directory::Filter,
     events::{
         direct::DirectEventContent,
+        receipt::ReceiptThread,
         room::{message::ImageMessageEventContent, ImageInfo, MediaSource},
-        AnyInitialStateEvent,
+        AnyInitialStateEvent, AnySyncStateEvent,
     },
-    mxc_uri, room_id,
+    mxc_uri, owned_event_id, room_id,
     serde::Raw,
-    uint, user_id, OwnedUserId,
+    server_name,
+    thirdparty::Medium,
+    uint, user_id, OwnedUserId, RoomId,
 };
 use serde_json::{json, Value as JsonValue};
+use tokio::time::timeout;
 use wiremock::{
-    matchers::{header, method, path, path_regex},
+    matchers::{body_json, body_partial_json, header, method, path, path_regex, query_param},
     Mock, Request, ResponseTemplate,
 };
 
-use crate::{logged_in_client, mock_sync, no_retry_test_client};
+use crate::{logged_in_client, mock_sync, no_retry_test_client, test_client_builder};
 
 #[async_test]
 async fn sync() {
@@ -50,6 +70,184 @@ async fn sync() {
     assert_ne!(response.next_batch, "");
 }
 
+#[async_test]
+async fn sync_with_cross_process_sync_lock() {
+    let store = Arc::new(MemoryStore::new());
+    let session = MatrixSession {
+        meta: SessionMeta {
+            user_id: user_id!("@example:localhost").to_owned(),
+            device_id: device_id!("DEVICEID").to_owned(),
+        },
+        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+    };
+
+    let (builder, server) = test_client_builder().await;
+    let client = builder
+        .store_config(StoreConfig::new().state_store(store.clone()))
+        .request_config(RequestConfig::new().disable_retry())
+        .build()
+        .await
+        .unwrap();
+    client.restore_session(session.clone()).await.unwrap();
+    client.enable_cross_process_sync_lock("main".to_owned());
+
+    let other_client = Client::builder()
+        .homeserver_url(server.uri())
+        .server_versions([MatrixVersion::V1_0])
+        .store_config(StoreConfig::new().state_store(store))
+        .request_config(RequestConfig::new().disable_retry())
+        .build()
+        .await
+        .unwrap();
+    other_client.restore_session(session).await.unwrap();
+    other_client.enable_cross_process_sync_lock("extension".to_owned());
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/sync"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::SYNC))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let response = client.sync_once(SyncSettings::new()).await.unwrap();
+    assert_ne!(response.next_batch, "");
+    assert!(!response.is_passive);
+
+    // The first client still holds the lock, so the other one doesn't hit the
+    // server but picks up the sync token from the shared store.
+    let other_response = other_client.sync_once(SyncSettings::new()).await.unwrap();
+    assert_eq!(other_response.next_batch, response.next_batch);
+    assert!(other_response.is_passive);
+
+    server.verify().await;
+}
+
+#[async_test]
+async fn passive_sync_forgets_the_rooms_forgotten_by_the_other_process() {
+    let store = Arc::new(MemoryStore::new());
+    let session = MatrixSession {
+        meta: SessionMeta {
+            user_id: user_id!("@example:localhost").to_owned(),
+            device_id: device_id!("DEVICEID").to_owned(),
+        },
+        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+    };
+
+    let (builder, server) = test_client_builder().await;
+    let client = builder
+        .store_config(StoreConfig::new().state_store(store.clone()))
+        .request_config(RequestConfig::new().disable_retry())
+        .build()
+        .await
+        .unwrap();
+    client.restore_session(session.clone()).await.unwrap();
+    client.enable_cross_process_sync_lock("main".to_owned());
+
+    let other_client = Client::builder()
+        .homeserver_url(server.uri())
+        .server_versions([MatrixVersion::V1_0])
+        .store_config(StoreConfig::new().state_store(store))
+        .request_config(RequestConfig::new().disable_retry())
+        .build()
+        .await
+        .unwrap();
+    other_client.restore_session(session).await.unwrap();
+    other_client.enable_cross_process_sync_lock("extension".to_owned());
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/sync"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::LEAVE_SYNC))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/sync"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "next_batch": "next" })))
+        .mount(&server)
+        .await;
+
+    client.sync_once(SyncSettings::new()).await.unwrap();
+    other_client.sync_once(SyncSettings::new()).await.unwrap();
+    assert_eq!(other_client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap().state(), RoomState::Left);
+
+    // The first client forgets the room it left, and syncs again.
+    let policy = CleanupPolicy { left_room_max_age: Some(Duration::ZERO), ..Default::default() };
+    client.store_cleanup().run(&policy).await.unwrap();
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    let response = other_client.sync_once(SyncSettings::new()).await.unwrap();
+    assert!(response.is_passive);
+    assert!(other_client.get_room(&DEFAULT_TEST_ROOM_ID).is_none());
+}
+
+#[async_test]
+async fn concurrent_syncs_are_serialized() {
+    let (client, server) = logged_in_client().await;
+
+    // The first sync takes longer than the second one, so their tokens would be
+    // stored in the wrong order if they overlapped.
+    let count = Mutex::new(0);
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/sync"))
+        .respond_with(move |_: &Request| {
+            let mut count = count.lock().unwrap();
+            *count += 1;
+            let delay = if *count == 1 { 200 } else { 0 };
+            ResponseTemplate::new(200)
+                .set_body_json(json!({ "next_batch": format!("batch_{count}") }))
+                .set_delay(Duration::from_millis(delay))
+        })
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let (first, second) =
+        join(client.sync_once(SyncSettings::new()), client.sync_once(SyncSettings::new())).await;
+
+    assert_eq!(first.unwrap().next_batch, "batch_1");
+    assert_eq!(second.unwrap().next_batch, "batch_2");
+
+    // The token of the second sync is the one that was stored.
+    server.reset().await;
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/sync"))
+        .and(query_param("since", "batch_2"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "next_batch": "batch_3" })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    client.sync_with_callback(SyncSettings::new(), |_| async { LoopCtrl::Break }).await.unwrap();
+}
+
+#[async_test]
+async fn concurrent_sync_can_fail_instead_of_waiting() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/sync"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(&*test_json::SYNC)
+                .set_delay(Duration::from_millis(100)),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let settings = SyncSettings::new().concurrent_sync_policy(ConcurrentSyncPolicy::Fail);
+    let (first, second) =
+        join(client.sync_once(SyncSettings::new()), client.sync_once(settings.clone())).await;
+
+    first.unwrap();
+    assert_matches!(second, Err(Error::AlreadySyncing));
+
+    // Once the first sync is done, syncing works again.
+    server.reset().await;
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    client.sync_once(settings).await.unwrap();
+}
+
 #[async_test]
 async fn devices() {
     let (client, server) = logged_in_client().await;
@@ -211,6 +409,88 @@ async fn join_room_by_id_or_alias() {
     );
 }
 
+#[async_test]
+async fn join_invited_room_via_inviter_server() {
+    let (client, server) = logged_in_client().await;
+    let room_id = room_id!("!invited:example.org");
+
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_invited_room(InvitedRoomBuilder::new(room_id).add_state_event(
+        StrippedStateTestEvent::Custom(json!({
+            "content": {
+                "membership": "invite",
+            },
+            "sender": "@alice:example.org",
+            "state_key": "@example:localhost",
+            "type": "m.room.member",
+        })),
+    ));
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/join/"))
+        .and(query_param("server_name", "example.org"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "room_id": room_id })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let room = client.get_room(room_id).unwrap();
+    room.join().await.unwrap();
+}
+
+#[async_test]
+async fn join_invited_room_by_id_via_inviter_server() {
+    let (client, server) = logged_in_client().await;
+    let room_id = room_id!("!invited:example.org");
+
+    assert!(client.via_servers_for_room(room_id).await.unwrap().is_empty());
+
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_invited_room(InvitedRoomBuilder::new(room_id).add_state_event(
+        StrippedStateTestEvent::Custom(json!({
+            "content": {
+                "membership": "invite",
+            },
+            "sender": "@alice:example.org",
+            "state_key": "@example:localhost",
+            "type": "m.room.member",
+        })),
+    ));
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    assert_eq!(
+        client.via_servers_for_room(room_id).await.unwrap(),
+        [server_name!("example.org").to_owned()]
+    );
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/join/"))
+        .and(query_param("server_name", "example.org"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "room_id": room_id })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let room = client.join_room_by_id(room_id).await.unwrap();
+    assert_eq!(room.room_id(), room_id);
+}
+
+#[async_test]
+async fn resolve_server_for_user() {
+    let (client, _server) = logged_in_client().await;
+
+    assert_eq!(
+        client.resolve_server_for_user(user_id!("@alice:example.org")).as_deref(),
+        Some(server_name!("example.org"))
+    );
+    assert_eq!(client.resolve_server_for_user(user_id!("@alice:127.0.0.1")), None);
+}
+
 #[async_test]
 async fn room_search_all() {
     let (client, server) = no_retry_test_client().await;
@@ -898,3 +1178,860 @@ async fn create_dm_error() {
 
     assert_eq!(client_api_error.status_code, 404);
 }
+
+#[async_test]
+async fn test_invite_from_stranger_is_shelved_as_spam() {
+    let (builder, server) = test_client_builder().await;
+    let client = builder
+        .request_config(RequestConfig::new().disable_retry())
+        .add_invite_filter(Arc::new(SharedRoomsFilter))
+        .build()
+        .await
+        .unwrap();
+    client
+        .restore_session(MatrixSession {
+            meta: SessionMeta {
+                user_id: user_id!("@example:localhost").to_owned(),
+                device_id: device_id!("DEVICEID").to_owned(),
+            },
+            tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        })
+        .await
+        .unwrap();
+
+    let room_id = room_id!("!spam:localhost");
+    let mut room_updates = client.subscribe_to_room_updates(room_id);
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_invited_room(InvitedRoomBuilder::new(room_id).add_state_event(
+        StrippedStateTestEvent::Custom(json!({
+            "content": {
+                "membership": "invite",
+            },
+            "sender": "@stranger:localhost",
+            "state_key": "@example:localhost",
+            "type": "m.room.member",
+        })),
+    ));
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    // The filters run in the background, after the sync response was processed.
+    let room = client.get_room(room_id).unwrap();
+    let mut room_info = room.subscribe_info();
+    timeout(Duration::from_secs(1), async {
+        while !room.is_spam_invite() {
+            room_info.next().await.unwrap();
+        }
+    })
+    .await
+    .expect("the invite should be shelved as spam");
+
+    assert_eq!(room.state(), RoomState::Invited);
+    assert!(room.is_spam_invite());
+
+    // The subscribers hear about the invite once it was shelved.
+    let update = timeout(Duration::from_secs(1), room_updates.recv())
+        .await
+        .expect("the invite should be sent to the subscribers")
+        .unwrap();
+    assert_matches!(update, RoomUpdate::Invited { .. });
+}
+
+#[async_test]
+async fn test_rejected_invite_is_not_sent_to_subscribers() {
+    let (builder, server) = test_client_builder().await;
+    let client = builder
+        .request_config(RequestConfig::new().disable_retry())
+        .add_invite_filter(Arc::new(IgnoredUsersFilter))
+        .build()
+        .await
+        .unwrap();
+    client
+        .restore_session(MatrixSession {
+            meta: SessionMeta {
+                user_id: user_id!("@example:localhost").to_owned(),
+                device_id: device_id!("DEVICEID").to_owned(),
+            },
+            tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        })
+        .await
+        .unwrap();
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/leave"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let room_id = room_id!("!spam:localhost");
+    let mut room_updates = client.subscribe_to_room_updates(room_id);
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder
+        .add_global_account_data_event(GlobalAccountDataTestEvent::Custom(json!({
+            "type": "m.ignored_user_list",
+            "content": { "ignored_users": { "@spammer:localhost": {} } },
+        })))
+        .add_invited_room(InvitedRoomBuilder::new(room_id).add_state_event(
+            StrippedStateTestEvent::Custom(json!({
+                "content": {
+                    "membership": "invite",
+                },
+                "sender": "@spammer:localhost",
+                "state_key": "@example:localhost",
+                "type": "m.room.member",
+            })),
+        ));
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    // The filters run in the background, after the sync response was processed.
+    let room = client.get_room(room_id).unwrap();
+    let mut room_info = room.subscribe_info();
+    timeout(Duration::from_secs(1), async {
+        while room.state() == RoomState::Invited {
+            room_info.next().await.unwrap();
+        }
+    })
+    .await
+    .expect("the invite should be rejected");
+
+    assert_eq!(room.state(), RoomState::Left);
+    assert!(room_updates.try_recv().is_err(), "the rejected invite shouldn't be sent");
+}
+
+#[async_test]
+async fn test_joins_are_throttled() {
+    let clock = TestClock::new();
+    let (builder, server) = test_client_builder().await;
+    let client = builder
+        .request_config(RequestConfig::new().disable_retry())
+        .clock(Arc::new(clock.clone()))
+        .throttle_joins(1, Duration::from_secs(60))
+        .build()
+        .await
+        .unwrap();
+    client
+        .restore_session(MatrixSession {
+            meta: SessionMeta {
+                user_id: user_id!("@example:localhost").to_owned(),
+                device_id: device_id!("DEVICEID").to_owned(),
+            },
+            tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        })
+        .await
+        .unwrap();
+
+    for room_id in ["first", "second"] {
+        Mock::given(method("POST"))
+            .and(path_regex(format!(r"^/_matrix/client/r0/rooms/.*{room_id}.*/join")))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({ "room_id": format!("!{room_id}:localhost") })),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
+
+    client.join_room_by_id(room_id!("!first:localhost")).await.unwrap();
+
+    // The second join waits until the period of the throttle is over.
+    let second_join = client.join_room_by_id(room_id!("!second:localhost"));
+    pin_mut!(second_join);
+    assert!(second_join.as_mut().now_or_never().is_none());
+
+    clock.advance(Duration::from_secs(59));
+    assert!(second_join.as_mut().now_or_never().is_none());
+
+    clock.advance(Duration::from_secs(1));
+    let room = timeout(Duration::from_secs(1), second_join)
+        .await
+        .expect("the second join should be sent once the period is over")
+        .unwrap();
+    assert_eq!(room.room_id(), room_id!("!second:localhost"));
+}
+
+fn server_notices_room_sync(room_id: &RoomId, pinned: JsonValue) -> JsonValue {
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_account_data(RoomAccountDataTestEvent::Custom(json!({
+                "type": "m.tag",
+                "content": { "tags": { "m.server_notice": {} } },
+            })))
+            .add_state_event(StateTestEvent::Custom(json!({
+                "type": "m.room.pinned_events",
+                "state_key": "",
+                "event_id": "$pinned",
+                "sender": "@notices:localhost",
+                "origin_server_ts": 152039280,
+                "content": { "pinned": pinned },
+            }))),
+    );
+    sync_builder.build_json_sync_response()
+}
+
+#[async_test]
+async fn server_notices_follow_the_pinned_events() {
+    let (client, server) = logged_in_client().await;
+    let room_id = room_id!("!notices:localhost");
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/event/\$notice$"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "type": "m.room.message",
+            "event_id": "$notice",
+            "room_id": room_id,
+            "sender": "@notices:localhost",
+            "origin_server_ts": 152039280,
+            "content": {
+                "msgtype": "m.server_notice",
+                "body": "The monthly active user limit was reached",
+                "server_notice_type": "m.server_notice.usage_limit_reached",
+                "admin_contact": "mailto:admin@localhost",
+                "limit_type": "monthly_active_user",
+            },
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut notices = client.server_notices();
+    assert!(notices.get().is_empty());
+
+    // The notices are loaded in the background after the sync.
+    mock_sync(&server, server_notices_room_sync(room_id, json!(["$notice"])), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    let active = timeout(Duration::from_secs(1), notices.next()).await.unwrap().unwrap();
+    server.reset().await;
+
+    assert_eq!(active.len(), 1);
+    assert_eq!(active[0].room_id, room_id);
+    assert_eq!(active[0].event_id.as_str(), "$notice");
+    assert_eq!(active[0].content.body, "The monthly active user limit was reached");
+
+    // Once the notice is unpinned, it isn't active anymore.
+    mock_sync(&server, server_notices_room_sync(room_id, json!([])), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    let active = timeout(Duration::from_secs(1), notices.next()).await.unwrap().unwrap();
+    assert!(active.is_empty());
+}
+
+#[async_test]
+async fn leaving_server_notices_room_can_be_forbidden() {
+    let (client, server) = logged_in_client().await;
+    let room_id = room_id!("!notices:localhost");
+
+    mock_sync(&server, server_notices_room_sync(room_id, json!([])), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/leave$"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(403).set_body_json(json!({
+            "errcode": "M_FORBIDDEN",
+            "error": "You cannot leave the server notices room",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let room = client.get_room(room_id).unwrap();
+    assert!(room.is_server_notices_room().await.unwrap());
+
+    assert_matches!(room.leave().await, Err(Error::CannotLeaveServerNoticesRoom));
+    assert_eq!(room.state(), RoomState::Joined);
+}
+
+#[async_test]
+async fn profiles_are_cached() {
+    let (client, server) = logged_in_client().await;
+    let user_id = user_id!("@alice:localhost");
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/profile/@alice:localhost"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "displayname": "Alice",
+            "avatar_url": "mxc://localhost/alice",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let profiles = client.profiles();
+    assert_eq!(profiles.cached(user_id).await.unwrap(), None);
+
+    let profile = profiles.get(user_id).await.unwrap();
+    assert_eq!(profile.display_name.as_deref(), Some("Alice"));
+    assert_eq!(profile.avatar_url.as_deref(), Some(mxc_uri!("mxc://localhost/alice")));
+
+    // The second time, the profile is returned from the cache.
+    assert_eq!(profiles.get(user_id).await.unwrap(), profile);
+    assert_eq!(profiles.cached(user_id).await.unwrap(), Some(profile));
+}
+
+#[async_test]
+async fn cached_profiles_follow_presence_and_member_events() {
+    let (client, server) = logged_in_client().await;
+    let alice = user_id!("@alice:localhost");
+    let bob = user_id!("@bob:localhost");
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/profile/@alice:localhost"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "displayname": "Alice",
+            "avatar_url": "mxc://localhost/alice",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let profiles = client.profiles();
+    profiles.get(alice).await.unwrap();
+
+    let mut subscriber = profiles.subscribe(alice).await.unwrap();
+    assert_eq!(subscriber.get().unwrap().display_name.as_deref(), Some("Alice"));
+
+    // The presence only holds the display name, the avatar is kept.
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_presence_event(PresenceTestEvent::Custom(json!({
+        "type": "m.presence",
+        "sender": alice,
+        "content": {
+            "displayname": "Alice in Wonderland",
+            "presence": "online",
+        },
+    })));
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    let profile = subscriber.next().await.unwrap().unwrap();
+    assert_eq!(profile.display_name.as_deref(), Some("Alice in Wonderland"));
+    assert_eq!(profile.avatar_url.as_deref(), Some(mxc_uri!("mxc://localhost/alice")));
+
+    // The member events only have names specific to the room, the global profile
+    // is fetched again instead of using them.
+    server.reset().await;
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/profile/@alice:localhost"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "displayname": "Alice Liddell",
+            "avatar_url": "mxc://localhost/alice",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    sync_builder.add_joined_room(JoinedRoomBuilder::new(&DEFAULT_TEST_ROOM_ID).add_state_bulk(
+        [alice, bob].map(|user_id| {
+            Raw::new(&json!({
+                "type": "m.room.member",
+                "state_key": user_id,
+                "event_id": format!("$member_{}", user_id.localpart()),
+                "sender": user_id,
+                "origin_server_ts": 152039280,
+                "content": {
+                    "membership": "join",
+                    "displayname": "Alice at work",
+                    "avatar_url": "mxc://localhost/alice",
+                },
+            }))
+            .unwrap()
+            .cast()
+        }),
+    ));
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    let profile = subscriber.next().await.unwrap().unwrap();
+    assert_eq!(profile.display_name.as_deref(), Some("Alice Liddell"));
+    assert_eq!(profile.avatar_url.as_deref(), Some(mxc_uri!("mxc://localhost/alice")));
+
+    // Only the profiles that were cached already are updated.
+    assert_eq!(profiles.cached(bob).await.unwrap(), None);
+}
+
+#[async_test]
+async fn session_is_gone_after_unknown_token_without_soft_logout() {
+    let (client, server) = logged_in_client().await;
+    let status = client.session_status();
+    assert_eq!(status.get(), SessionStatus::Active);
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/account/whoami"))
+        .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+            "errcode": "M_UNKNOWN_TOKEN",
+            "error": "Invalid access token passed.",
+            "soft_logout": false,
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    client.whoami().await.unwrap_err();
+    assert_eq!(
+        status.get(),
+        SessionStatus::SessionVerifiedGone { reason: SessionGoneReason::UnknownToken }
+    );
+
+    // The dead session doesn't sync anymore, without reaching the homeserver.
+    assert_matches!(client.sync_once(SyncSettings::default()).await, Err(Error::SessionGone));
+}
+
+#[async_test]
+async fn session_is_gone_after_device_deletion() {
+    let (client, server) = logged_in_client().await;
+    let mut status = client.session_status();
+
+    let mut sync_builder = SyncResponseBuilder::new();
+    let mut response_body = sync_builder.build_json_sync_response();
+    response_body["device_lists"] = json!({ "changed": ["@example:localhost"] });
+    mock_sync(&server, response_body, None).await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/.*/devices/DEVICEID$"))
+        .respond_with(ResponseTemplate::new(404).set_body_json(&*test_json::NOT_FOUND))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    // The device is checked in the background, after the sync response was
+    // processed.
+    let status = timeout(Duration::from_secs(1), status.next())
+        .await
+        .expect("the device should be checked")
+        .unwrap();
+    assert_eq!(
+        status,
+        SessionStatus::SessionVerifiedGone { reason: SessionGoneReason::DeviceDeleted }
+    );
+
+    assert_matches!(client.sync_once(SyncSettings::default()).await, Err(Error::SessionGone));
+}
+
+#[async_test]
+async fn user_search_includes_known_members() {
+    let (client, server) = logged_in_client().await;
+
+    let member = |user_id: &str, display_name: &str, ts: u64| -> Raw<AnySyncStateEvent> {
+        Raw::new(&json!({
+            "type": "m.room.member",
+            "state_key": user_id,
+            "event_id": format!("$member_{ts}"),
+            "sender": user_id,
+            "origin_server_ts": ts,
+            "content": {
+                "membership": "join",
+                "displayname": display_name,
+            },
+        }))
+        .unwrap()
+        .cast()
+    };
+
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder
+        .add_joined_room(JoinedRoomBuilder::new(&DEFAULT_TEST_ROOM_ID).add_state_bulk([
+            member("@carol:other.org", "Carol", 1),
+            member("@caroline:localhost", "Caroline", 2),
+            member("@dave:localhost", "Dave", 3),
+        ]))
+        .add_joined_room(
+            JoinedRoomBuilder::new(room_id!("!other:localhost")).add_state_bulk([member(
+                "@carol:other.org",
+                "Carol",
+                4,
+            )]),
+        );
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    // The directory of the homeserver doesn't know about the user on the other
+    // server.
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/user_directory/search"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "limited": false,
+            "results": [
+                { "user_id": "@carol2:localhost", "display_name": "Carol Two" },
+                { "user_id": "@caroline:localhost", "display_name": "Caroline" },
+            ],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut search = client.user_search("CAROL");
+    assert!(!search.is_at_last_page());
+
+    let page = search.next_page(2).await;
+    let user_ids: Vec<_> = page.iter().map(|result| result.user_id.as_str()).collect();
+    assert_eq!(user_ids, ["@carol:other.org", "@caroline:localhost"]);
+    assert_eq!(page[0].shared_rooms, 2);
+    assert!(!page[0].in_directory);
+    assert_eq!(page[0].display_name.as_deref(), Some("Carol"));
+    assert_eq!(page[1].shared_rooms, 1);
+    assert!(page[1].in_directory);
+    assert!(!search.is_at_last_page());
+
+    let page = search.next_page(2).await;
+    assert_eq!(page.len(), 1);
+    assert_eq!(page[0].user_id, "@carol2:localhost");
+    assert_eq!(page[0].shared_rooms, 0);
+    assert!(search.is_at_last_page());
+
+    assert!(search.next_page(2).await.is_empty());
+}
+
+#[async_test]
+async fn user_search_returns_known_members_when_the_directory_fails() {
+    let (client, server) = logged_in_client().await;
+
+    let member = |user_id: &str, ts: u64| {
+        StateTestEvent::Custom(json!({
+            "type": "m.room.member",
+            "state_key": user_id,
+            "event_id": format!("$member_{ts}"),
+            "sender": user_id,
+            "origin_server_ts": ts,
+            "content": { "membership": "join" },
+        }))
+    };
+
+    // Carol joined before Caroline, but read the room more recently.
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_joined_room(
+        JoinedRoomBuilder::new(&DEFAULT_TEST_ROOM_ID)
+            .add_state_event(member("@carol:other.org", 1))
+            .add_state_event(member("@caroline:localhost", 2))
+            .add_ephemeral_event(EphemeralTestEvent::Custom(json!({
+                "type": "m.receipt",
+                "content": {
+                    "$member_2": {
+                        "m.read": {
+                            "@carol:other.org": { "ts": 10 },
+                        },
+                    },
+                },
+            }))),
+    );
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/user_directory/search"))
+        .respond_with(ResponseTemplate::new(403).set_body_json(json!({
+            "errcode": "M_FORBIDDEN",
+            "error": "The user directory is disabled",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut search = client.user_search("carol");
+
+    let page = search.next_page(10).await;
+    let user_ids: Vec<_> = page.iter().map(|result| result.user_id.as_str()).collect();
+    assert_eq!(user_ids, ["@carol:other.org", "@caroline:localhost"]);
+    assert!(!page[0].in_directory);
+    assert!(search.is_at_last_page());
+}
+
+#[async_test]
+async fn user_search_paginates_the_directory() {
+    let (client, server) = logged_in_client().await;
+
+    let user = |index: u64| json!({ "user_id": format!("@user{index}:localhost") });
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/user_directory/search"))
+        .and(body_partial_json(json!({ "limit": 2 })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "limited": true,
+            "results": [user(0), user(1)],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    // The homeserver returns the users of the first page again.
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/user_directory/search"))
+        .and(body_partial_json(json!({ "limit": 4 })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "limited": false,
+            "results": [user(0), user(1), user(2)],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut search = client.user_search("user");
+
+    let page = search.next_page(2).await;
+    let user_ids: Vec<_> = page.iter().map(|result| result.user_id.as_str()).collect();
+    assert_eq!(user_ids, ["@user0:localhost", "@user1:localhost"]);
+    assert!(!search.is_at_last_page());
+
+    let page = search.next_page(2).await;
+    let user_ids: Vec<_> = page.iter().map(|result| result.user_id.as_str()).collect();
+    assert_eq!(user_ids, ["@user2:localhost"]);
+    assert!(search.is_at_last_page());
+
+    assert!(search.next_page(2).await.is_empty());
+}
+
+#[async_test]
+async fn deactivate_with_plan() {
+    let (client, server) = logged_in_client().await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/account/3pid"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "threepids": [{
+                "medium": "email",
+                "address": "example@localhost",
+                "validated_at": 1535176800000u64,
+                "added_at": 1535336848756u64,
+            }],
+        })))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/account/3pid/unbind"))
+        .and(body_partial_json(json!({ "medium": "email", "address": "example@localhost" })))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({ "id_server_unbind_result": "success" })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/leave$"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/account/deactivate"))
+        .and(body_partial_json(json!({
+            "erase": true,
+            "auth": { "type": "m.login.dummy", "session": "deactivation" },
+        })))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({ "id_server_unbind_result": "success" })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/account/deactivate"))
+        .respond_with(ResponseTemplate::new(401).set_body_json(json!({
+            "flows": [{ "stages": ["m.login.dummy"] }],
+            "params": {},
+            "session": "deactivation",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let account = client.account();
+    let mut plan =
+        account.deactivation_plan(DeactivationOptions::new().leave_rooms().erase()).await.unwrap();
+
+    assert_eq!(plan.third_party_ids().len(), 1);
+    assert_eq!(plan.rooms_to_leave(), [DEFAULT_TEST_ROOM_ID.to_owned()]);
+    assert!(plan.erase());
+
+    // Without authentication, the homeserver is only probed for the UIAA session
+    // and nothing is cleaned up.
+    let report = account.deactivate_with_plan(&mut plan, None).await;
+    assert_eq!(report.steps.len(), 1);
+    assert_eq!(report.steps[0].step, DeactivationStep::Deactivate);
+    assert!(!report.is_deactivated());
+    let info = report.deactivation_error().unwrap().as_uiaa_response().unwrap();
+    assert_eq!(info.session.as_deref(), Some("deactivation"));
+
+    assert_eq!(plan.third_party_ids().len(), 1);
+    assert_eq!(plan.rooms_to_leave(), [DEFAULT_TEST_ROOM_ID.to_owned()]);
+
+    let auth_data = uiaa::AuthData::Dummy(assign!(uiaa::Dummy::new(), {
+        session: Some("deactivation".to_owned()),
+    }));
+    let report = account.deactivate_with_plan(&mut plan, Some(auth_data)).await;
+    let steps: Vec<_> = report.steps.iter().map(|step| step.step.clone()).collect();
+    assert_eq!(
+        steps,
+        [
+            DeactivationStep::UnbindThirdPartyId {
+                medium: Medium::Email,
+                address: "example@localhost".to_owned(),
+            },
+            DeactivationStep::LeaveRoom(DEFAULT_TEST_ROOM_ID.to_owned()),
+            DeactivationStep::Deactivate,
+        ]
+    );
+    assert!(report.is_deactivated());
+
+    assert!(plan.third_party_ids().is_empty());
+    assert!(plan.rooms_to_leave().is_empty());
+}
+
+#[async_test]
+async fn client_features_follow_the_account_data() {
+    let (client, server) = logged_in_client().await;
+
+    let features = client.features();
+    assert!(features.is_enabled(ClientFeature::ReadReceipts));
+
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_joined_room(JoinedRoomBuilder::default()).add_global_account_data_event(
+        GlobalAccountDataTestEvent::Custom(json!({
+            "type": "im.vector.web.settings",
+            "content": { "sendReadReceipts": false, "theme": "dark" },
+        })),
+    );
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    assert!(!features.is_enabled(ClientFeature::ReadReceipts));
+    assert!(features.is_enabled(ClientFeature::TypingNotifications));
+
+    // The public read receipt is sent as a private one.
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/receipt/m\.read\.private/\$event$"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+    room.send_single_receipt(
+        ReceiptType::Read,
+        ReceiptThread::Unthreaded,
+        owned_event_id!("$event"),
+    )
+    .await
+    .unwrap();
+
+    // Enabling the feature again keeps the other settings.
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/user/@example:localhost/account_data/im.vector.web.settings"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({ "sendReadReceipts": false, "theme": "dark" })),
+        )
+        .mount(&server)
+        .await;
+
+    Mock::given(method("PUT"))
+        .and(path("/_matrix/client/r0/user/@example:localhost/account_data/im.vector.web.settings"))
+        .and(body_json(json!({ "sendReadReceipts": true, "theme": "dark" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut subscriber = features.subscribe();
+    features.set_enabled(ClientFeature::ReadReceipts, true).await.unwrap();
+
+    assert!(subscriber.next().now_or_never().unwrap().unwrap().read_receipts);
+}
+
+#[async_test]
+async fn client_features_can_be_suspended() {
+    let (client, server) = logged_in_client().await;
+    let features = client.features();
+    let mut subscriber = features.subscribe();
+
+    features.set_suspended(ClientFeature::UrlPreviews, true);
+    assert!(!features.is_enabled(ClientFeature::UrlPreviews));
+    assert!(!subscriber.next().now_or_never().unwrap().unwrap().url_previews);
+
+    // The setting keeps being updated while the feature is suspended.
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_global_account_data_event(GlobalAccountDataTestEvent::Custom(json!({
+        "type": "org.matrix.preview_urls",
+        "content": { "disable": false },
+    })));
+    sync_builder.add_global_account_data_event(GlobalAccountDataTestEvent::Custom(json!({
+        "type": "im.vector.web.settings",
+        "content": { "sendTypingNotifications": false },
+    })));
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    let flags = features.get();
+    assert!(!flags.url_previews);
+    assert!(!flags.typing_notifications);
+
+    // Resuming the feature brings its setting back, without uploading it.
+    features.set_suspended(ClientFeature::UrlPreviews, false);
+    assert!(features.is_enabled(ClientFeature::UrlPreviews));
+    assert!(!features.is_enabled(ClientFeature::TypingNotifications));
+}
+
+#[async_test]
+async fn client_features_are_loaded_when_restoring_the_session() {
+    let store = Arc::new(MemoryStore::new());
+    let session = MatrixSession {
+        meta: SessionMeta {
+            user_id: user_id!("@example:localhost").to_owned(),
+            device_id: device_id!("DEVICEID").to_owned(),
+        },
+        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+    };
+
+    let (builder, server) = test_client_builder().await;
+    let client = builder
+        .store_config(StoreConfig::new().state_store(store.clone()))
+        .request_config(RequestConfig::new().disable_retry())
+        .build()
+        .await
+        .unwrap();
+    client.restore_session(session.clone()).await.unwrap();
+
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_global_account_data_event(GlobalAccountDataTestEvent::Custom(json!({
+        "type": "im.vector.web.settings",
+        "content": { "sendReadReceipts": false },
+    })));
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    client.features().set_enabled(ClientFeature::Presence, false).await.unwrap();
+    drop(client);
+
+    let client = Client::builder()
+        .homeserver_url(server.uri())
+        .server_versions([MatrixVersion::V1_0])
+        .store_config(StoreConfig::new().state_store(store))
+        .request_config(RequestConfig::new().disable_retry())
+        .build()
+        .await
+        .unwrap();
+    assert!(client.features().is_enabled(ClientFeature::ReadReceipts));
+
+    client.restore_session(session).await.unwrap();
+
+    let flags = client.features().get();
+    assert!(!flags.read_receipts);
+    assert!(!flags.presence);
+    assert!(flags.typing_notifications);
+}
@@ -1,4 +1,5 @@
 mod backups;
+mod dehydrated_devices;
 mod recovery;
 mod secret_storage;
 mod verification;
@@ -20,7 +20,7 @@ use futures_util::{pin_mut, FutureExt, StreamExt};
 use matrix_sdk::{
     config::RequestConfig,
     encryption::{
-        backups::{futures::SteadyStateError, BackupState, UploadState},
+        backups::{futures::SteadyStateError, BackupState, BackupStatus, UploadState},
         BackupDownloadStrategy, EncryptionSettings,
     },
     matrix_auth::{MatrixSession, MatrixSessionTokens},
@@ -278,6 +278,12 @@ async fn disabling() {
 
     client.encryption().backups().disable().await.expect("We should be able to disable our backup");
 
+    assert_eq!(
+        client.encryption().backups().status(),
+        BackupStatus::Disabled,
+        "The backup should be reported as disabled once it was deleted"
+    );
+
     let task = spawn(async move {
         pin_mut!(states);
 
@@ -320,6 +326,91 @@ async fn disabling() {
     server.verify().await;
 }
 
+#[async_test]
+async fn disabling_and_deleting() {
+    let user_id = user_id!("@example:morpheus.localhost");
+
+    let session = MatrixSession {
+        meta: SessionMeta { user_id: user_id.into(), device_id: device_id!("DEVICEID").to_owned() },
+        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+    };
+    let (client, server) = no_retry_test_client().await;
+    client.restore_session(session).await.unwrap();
+
+    mount_once(
+        &server,
+        "POST",
+        "_matrix/client/unstable/room_keys/version",
+        ResponseTemplate::new(200).set_body_json(json!({ "version": "1"})),
+    )
+    .await;
+
+    mount_once(
+        &server,
+        "DELETE",
+        "_matrix/client/r0/room_keys/version/1",
+        ResponseTemplate::new(200).set_body_json(json!({})),
+    )
+    .await;
+
+    client.encryption().backups().create().await.expect("We should be able to create a new backup");
+
+    client
+        .encryption()
+        .backups()
+        .disable_and_delete()
+        .await
+        .expect("We should be able to disable and delete our backup");
+
+    assert_eq!(client.encryption().backups().state(), BackupState::Unknown);
+    assert_eq!(client.encryption().backups().status(), BackupStatus::Disabled);
+    assert!(!client.encryption().backups().are_enabled().await);
+
+    // The recovery key and the version of the deleted backup are forgotten.
+    {
+        let olm_machine = client.olm_machine_for_testing().await;
+        let backup_keys =
+            olm_machine.as_ref().unwrap().backup_machine().get_backup_keys().await.unwrap();
+        assert!(backup_keys.decryption_key.is_none());
+        assert!(backup_keys.backup_version.is_none());
+    }
+
+    server.verify().await;
+}
+
+#[async_test]
+async fn disabling_a_backup_that_is_not_enabled_locally() {
+    let user_id = user_id!("@example:morpheus.localhost");
+
+    let session = MatrixSession {
+        meta: SessionMeta { user_id: user_id.into(), device_id: device_id!("DEVICEID").to_owned() },
+        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+    };
+    let (client, server) = no_retry_test_client().await;
+    client.restore_session(session).await.unwrap();
+
+    // The backup of the homeserver might have been created by another device, it
+    // must not be deleted since we never used it.
+    Mock::given(method("DELETE"))
+        .and(path_regex(r"_matrix/client/r0/room_keys/version/.*"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    client
+        .encryption()
+        .backups()
+        .disable_and_delete()
+        .await
+        .expect("We should be able to disable the backups locally");
+
+    assert_eq!(client.encryption().backups().state(), BackupState::Unknown);
+    assert!(!client.encryption().backups().are_enabled().await);
+
+    server.verify().await;
+}
+
 #[async_test]
 #[cfg(feature = "sqlite")]
 async fn backup_resumption() {
@@ -671,6 +762,101 @@ async fn incremental_upload_of_keys() -> Result<()> {
     Ok(())
 }
 
+#[async_test]
+async fn room_key_counts_and_upload_progress() -> Result<()> {
+    let user_id = user_id!("@example:morpheus.localhost");
+
+    let session = MatrixSession {
+        meta: SessionMeta { user_id: user_id.into(), device_id: device_id!("DEVICEID").to_owned() },
+        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+    };
+    let (client, server) = no_retry_test_client().await;
+    client.restore_session(session).await.unwrap();
+
+    let backups = client.encryption().backups();
+
+    mount_once(
+        &server,
+        "PUT",
+        "_matrix/client/unstable/room_keys/keys",
+        ResponseTemplate::new(200).set_body_json(json!({
+            "count": 1,
+            "etag": "abcdefg",
+        }
+        )),
+    )
+    .await;
+
+    setup_create_room_and_send_message_mocks(&server).await;
+
+    backups.create().await.expect("We should be able to create a new backup");
+
+    // We don't know about any room keys yet.
+    let counts = backups.room_key_counts().await?;
+    assert_eq!(counts.total, 0);
+    assert_eq!(counts.backed_up, 0);
+
+    let alice_room = client
+        .create_room(assign!(CreateRoomRequest::new(), {
+            invite: vec![],
+            is_direct: true,
+        }))
+        .await?;
+
+    alice_room.enable_encryption().await?;
+
+    // Sending a message creates an outbound session, which isn't backed up until
+    // the next sync.
+    let content = RoomMessageEventContent::text_plain("Hello world");
+    let txn_id = TransactionId::new();
+    let _ = alice_room.send(content).with_transaction_id(&txn_id).await?;
+
+    let counts = backups.room_key_counts().await?;
+    assert_eq!(counts.total, 1);
+    assert_eq!(counts.backed_up, 0);
+
+    // The current progress is sent out first.
+    let progress_stream = backups.upload_progress_stream();
+    pin_mut!(progress_stream);
+    assert_matches!(progress_stream.next().await, Some(Ok(UploadState::Idle)));
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/sync"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "next_batch": "sfooBar",
+            "device_one_time_keys_count": {
+                "signed_curve25519": 50
+            },
+            "device_unused_fallback_key_types": [
+                "signed_curve25519"
+            ]
+        })))
+        .mount(&server)
+        .await;
+
+    client.sync_once(Default::default()).await?;
+
+    // The sync triggers the upload of the room key, the stream tells us how many
+    // room keys got backed up and when the upload is done.
+    assert_matches!(
+        progress_stream.next().await,
+        Some(Ok(UploadState::Uploading(counts))) => {
+            assert_eq!(counts.total, 1);
+            assert_eq!(counts.backed_up, 1);
+        }
+    );
+    assert_matches!(progress_stream.next().await, Some(Ok(UploadState::Done)));
+    assert_matches!(backups.upload_progress(), UploadState::Done | UploadState::Idle);
+
+    let counts = backups.room_key_counts().await?;
+    assert_eq!(counts.total, 1);
+    assert_eq!(counts.backed_up, 1);
+
+    server.verify().await;
+    Ok(())
+}
+
 #[async_test]
 #[cfg(feature = "experimental-sliding-sync")]
 async fn incremental_upload_of_keys_sliding_sync() -> Result<()> {
@@ -858,6 +1044,67 @@ async fn steady_state_waiting_errors() {
     task.await.unwrap();
 }
 
+#[async_test]
+async fn backup_version_changed_on_the_server() {
+    let user_id = user_id!("@example:morpheus.localhost");
+
+    let session = MatrixSession {
+        meta: SessionMeta { user_id: user_id.into(), device_id: device_id!("DEVICEID").to_owned() },
+        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+    };
+    let (client, server) = no_retry_test_client().await;
+    client.restore_session(session).await.unwrap();
+
+    setup_backups(&client, &server).await;
+
+    mount_once(
+        &server,
+        "PUT",
+        "_matrix/client/unstable/room_keys/keys",
+        ResponseTemplate::new(403).set_body_json(json!({
+            "errcode": "M_WRONG_ROOM_KEYS_VERSION",
+            "error": "Wrong backup version.",
+            "current_version": "2",
+        })),
+    )
+    .await;
+
+    // The new backup version uses a key we don't have.
+    mount_once(
+        &server,
+        "GET",
+        "_matrix/client/r0/room_keys/version",
+        ResponseTemplate::new(200).set_body_json(json!({
+            "algorithm": "m.megolm_backup.v1.curve25519-aes-sha2",
+            "auth_data": {
+                "public_key": "SISFU86lzyzyS0RpkVZRDot/TScaShnbILRYfw1uVSk",
+                "signatures": {}
+            },
+            "count": 0,
+            "etag": "1",
+            "version": "2",
+        })),
+    )
+    .await;
+
+    let backups = client.encryption().backups();
+    let result = backups.wait_for_steady_state().await;
+
+    assert_matches!(
+        result,
+        Err(SteadyStateError::BackupDisabled),
+        "The steady state method should tell us that the backup got disabled"
+    );
+    assert_eq!(
+        backups.state(),
+        BackupState::VersionChanged,
+        "The backup state should tell us that we need the key of the new backup version"
+    );
+    assert!(!backups.are_enabled().await);
+
+    server.verify().await;
+}
+
 #[async_test]
 async fn enable_from_secret_storage() {
     const SECRET_STORE_KEY: &str = "mypassphrase";
@@ -995,6 +1242,9 @@ async fn enable_from_secret_storage() {
     let room_key_stream = client.encryption().backups().room_keys_for_room_stream(room_id);
     pin_mut!(room_key_stream);
 
+    let status_stream = client.encryption().backups().status_stream();
+    pin_mut!(status_stream);
+
     store
         .import_secrets()
         .await
@@ -1007,6 +1257,17 @@ async fn enable_from_secret_storage() {
         panic!("Failed to get an update about room keys being imported from the backup")
     }
 
+    let mut statuses = Vec::new();
+    while let Some(Some(status)) = status_stream.next().now_or_never() {
+        statuses.push(status);
+    }
+
+    assert_eq!(statuses.first(), Some(&BackupStatus::Unknown));
+    assert!(
+        statuses.contains(&BackupStatus::Downloading { done: 1, total: 1 }),
+        "The progress of the download should be reported, got {statuses:?}"
+    );
+
     let event = room.event(event_id).await.expect("We should be able to fetch our encrypted event");
 
     assert_matches!(event.encryption_info, Some(..), "The event should now be decrypted");
@@ -1236,6 +1497,25 @@ async fn enable_from_secret_storage_and_manual_download() {
 
     store.import_secrets().await.unwrap();
 
+    // The backup recovery key is checked against the backup before the downloads, the
+    // info of the backup is only fetched once.
+    Mock::given(method("GET"))
+        .and(path("_matrix/client/r0/room_keys/version/6"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "algorithm": "m.megolm_backup.v1.curve25519-aes-sha2",
+            "auth_data": {
+                "public_key": "hdx5rSn94rBuvJI5cwnhKAVmFyZgfJjk7vwEBD6mIHc",
+                "signatures": {}
+            },
+            "count": 1,
+            "etag": "1",
+            "version": "6"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
     Mock::given(method("GET"))
         .and(path("/_matrix/client/r0/room_keys/keys/!DovneieKSTkdHKpIXy:morpheus.localhost"))
         .and(header("authorization", "Bearer 1234"))
@@ -1479,3 +1759,211 @@ async fn enable_from_secret_storage_and_download_after_utd() {
 
     server.verify().await;
 }
+
+#[async_test]
+async fn download_after_utd_only_for_missing_room_keys() {
+    const SECRET_STORE_KEY: &str = "mypassphrase";
+    const KEY_ID: &str = "yJWwBm2Ts8jHygTBslKpABFyykavhhfA";
+    const CIPHERTEXT: &str = "AwgAEpABhetEzzZzyYrxtEVUtlJnZtJcURBlQUQJ9irVeklCTs06LwgTMQj61PMUS4Vy\
+                              YOX+PD67+hhU40/8olOww+Ud0m2afjMjC3wFX+4fFfSkoWPVHEmRVucfcdSF1RSB4EmK\
+                              PIP4eo1X6x8kCIMewBvxl2sI9j4VNvDvAN7M3zkLJfFLOFHbBviI4FN7hSFHFeM739Zg\
+                              iwxEs3hIkUXEiAfrobzaMEM/zY7SDrTdyffZndgJo7CZOVhoV6vuaOhmAy4X2t4UnbuV\
+                              JGJjKfV57NAhp8W+9oT7ugwO";
+    // The same ciphertext, with a flipped character, so it can't be authenticated.
+    const TAMPERED_CIPHERTEXT: &str = "AwgAEpABhetEzzZzyYrxtEVUtlJnZtJcURBlQUQJ9irVeklCTs06LwgTMQj61PMUS4Vy\
+                                       YOX+PD67+hhU40/8olOww+Ud0m2afjMjC3wFX+4fFfSkoWPVHEmRVucfcdSF1RSB4EmK\
+                                       PIP4eo1Y6x8kCIMewBvxl2sI9j4VNvDvAN7M3zkLJfFLOFHbBviI4FN7hSFHFeM739Zg\
+                                       iwxEs3hIkUXEiAfrobzaMEM/zY7SDrTdyffZndgJo7CZOVhoV6vuaOhmAy4X2t4UnbuV\
+                                       JGJjKfV57NAhp8W+9oT7ugwO";
+    const SESSION_ID: &str = "64H7XKokIx0ASkYDHZKlT5zd/Zccz/cQspPNdvnNULA";
+    const OTHER_SESSION_ID: &str = "D5SdVi/nyxdkl97K6EZrpb5N6GcF3YzmvE9EegkVDns";
+
+    let user_id = user_id!("@example2:morpheus.localhost");
+    let room_id = room_id!("!DovneieKSTkdHKpIXy:morpheus.localhost");
+    let event_id = event_id!("$JbFHtZpEJiH8uaajZjPLz0QUZc1xtBR9rPGBOjF6WFM");
+    let tampered_event_id = event_id!("$tampered:morpheus.localhost");
+    let other_event_id = event_id!("$other:morpheus.localhost");
+
+    let session = MatrixSession {
+        meta: SessionMeta { user_id: user_id.into(), device_id: device_id!("DEVICEID").to_owned() },
+        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+    };
+    let (builder, server) = test_client_builder().await;
+    let encryption_settings = EncryptionSettings {
+        backup_download_strategy: BackupDownloadStrategy::AfterDecryptionFailure,
+        ..Default::default()
+    };
+    let client = builder
+        .request_config(RequestConfig::new().disable_retry())
+        .with_encryption_settings(encryption_settings)
+        .build()
+        .await
+        .unwrap();
+
+    client.restore_session(session).await.unwrap();
+
+    let sync = SyncResponseBuilder::new()
+        .add_joined_room(JoinedRoomBuilder::new(room_id))
+        .build_json_sync_response();
+    mock_sync(&server, sync, None).await;
+
+    client.sync_once(Default::default()).await.expect("We should be able to sync with the server");
+
+    mock_secret_store_with_backup_key(user_id, KEY_ID, &server).await;
+
+    let store = client
+        .encryption()
+        .secret_storage()
+        .open_secret_store(SECRET_STORE_KEY)
+        .await
+        .expect("We should be able to open our secret store");
+
+    Mock::given(method("GET"))
+        .and(path("_matrix/client/r0/room_keys/version"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "algorithm": "m.megolm_backup.v1.curve25519-aes-sha2",
+            "auth_data": {
+                "public_key": "hdx5rSn94rBuvJI5cwnhKAVmFyZgfJjk7vwEBD6mIHc",
+                "signatures": {}
+            },
+            "count": 1,
+            "etag": "1",
+            "version": "6"
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    store.import_secrets().await.unwrap();
+
+    let encrypted_event = |event_id: &str, session_id: &str, ciphertext: &str| {
+        json!({
+            "content": {
+                "algorithm": "m.megolm.v1.aes-sha2",
+                "ciphertext": ciphertext,
+                "device_id": "KIUVQQSDTM",
+                "sender_key": "LvryVyoCjdONdBCi2vvoSbI34yTOx7YrCFACUEKoXnc",
+                "session_id": session_id,
+            },
+            "event_id": event_id,
+            "origin_server_ts": 1698579035927u64,
+            "sender": "@example2:morpheus.localhost",
+            "type": "m.room.encrypted",
+            "unsigned": {
+                "age": 14393491
+            }
+        })
+    };
+
+    for (event_id, session_id, ciphertext) in [
+        (event_id.as_str(), SESSION_ID, CIPHERTEXT),
+        (tampered_event_id.as_str(), SESSION_ID, TAMPERED_CIPHERTEXT),
+        (other_event_id.as_str(), OTHER_SESSION_ID, CIPHERTEXT),
+    ] {
+        Mock::given(method("GET"))
+            .and(path(format!("_matrix/client/r0/rooms/{room_id}/event/{event_id}")))
+            .and(header("authorization", "Bearer 1234"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(encrypted_event(event_id, session_id, ciphertext)),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
+
+    // Each room key must only be downloaded once.
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/room_keys/keys/!DovneieKSTkdHKpIXy:morpheus.localhost/64H7XKokIx0ASkYDHZKlT5zd%2FZccz%2FcQspPNdvnNULA"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "first_message_index": 0,
+            "forwarded_count": 0,
+            "is_verified": true,
+            "session_data": {
+                "ciphertext": "UaxxJxPZN5jqhSoFw59s83KlK0k77KJRxowPUC3P2/bS+TIBXw2y\
+                               qMHCpv01s+8mE95XU6RZO2/elktHiW1/mzx/2vqb4pFuARtj3rxF\
+                               zCBO7cpVhmrSU6uKW9KH2HirZMZzyXLqr3v6xoOTe5roIF5scPR0\
+                               cWxPcS/4+BZz4xGhGCVuTPFjWDszY1/iz4JAVosAF7XZLGh7aVhF\
+                               +ciDDoaaqwkD2nnMUlGEl2uchWuZv7v2q9Pmmd+qzRCdLx5c+GK3\
+                               OyT8qCSxubOvuSruwTliBl++drlMnh4vRO8UKPTuMNvEN89YKiSC\
+                               MVzXVDCS6tnjligxUENYkyUqYCKdASLDFs1cCXJDED16oQGonkU8\
+                               Lf7ccGg6XboJCmJfobrmDc3s/9IymtKaxquA2Vw2pW8Otoy4x9PK\
+                               17xHLo2nT2nf3Amp6xaCYx+tblGkLIqw8H3YZZVPVuKAVpPdAhgC\
+                               +aJA9n8qow3BLcCJSdGRMSV9MquidGgbEA/DCd6Eq3jokshcXR4v\
+                               Ma5nT4CokeZ6OdAtMWgZSaGltyNNoc+b6hk6AqcYaoMslG58DC32\
+                               EVSiFFwtSpKx7I6+J+hlV813Vx6IK0DoqTcYyVm4kFMvKnIoyAKJ\
+                               yoCSik4NQpL7DcokDhs56UJ1LcDgQTnGLqhH2Q",
+                "ephemeral": "+KmnQw7ECkCD+s2Hc0hhntT8n9zTLJvFHgX7g3XKBjs",
+                "mac": "xdzih3IkRv4"
+            }
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/room_keys/keys/!DovneieKSTkdHKpIXy:morpheus.localhost/D5SdVi%2Fnyxdkl97K6EZrpb5N6GcF3YzmvE9EegkVDns"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "first_message_index": 0,
+            "forwarded_count": 0,
+            "is_verified": true,
+            "session_data": {
+                "ciphertext": "JSPY1qaa8QwuurezB8l2QsK+wcwXJ6Rm3gA5AHQYrJCK1wnbIexJMx6vKFklpobTFiV6\
+                               9fh7VtcpYlZoiWTjiqwPU8ceUsmI7+Q1ZXjwS6Z6PbKszvWbUdaTKY7gcJKQWz93NAmV\
+                               PkAh/xjRqkKeJBlKZWzWctZ2k6QkwH5c9gHbPgQBe1usQefln7RHsEjM0+6nSV6+6qBm\
+                               20uK+xfpElMBZ8d3IZvbapoT11UktzUikSQ0E6DXMj+cAfX9CftXbA5BsStXvThNldad\
+                               49ZByrntoJ0yMLMk6G0uom4NaPTt75u8tX+AEHrgxFV8C7hICUPFsOFPU2ykb5qvK0JU\
+                               JdJ0qkZ2GJybhCZiQdLOC5Ciwm12k4eYBKktJAGYlPhh9oWTlITGoaDpHorDFwZpSZqY\
+                               rXaHyuCpAtd8Gc8L5HuZXDt9uN29ZTCGr3R8zpMqUG4DbpV1aV2QBrLfIZGt9OURU502\
+                               OSonHf+USrfR3ap+Yunde8gYnkyMuydRZ/0dvWqBKST0CtRQrQ+uWbPP1ATcjdhs3XnI\
+                               +N5FRIOrcrJtxbqDk1Lz+sRbFBnMZzuYTJZpPazu94AZx/t1CZyk9NZ5qbnE3wNxp2mj\
+                               YvMjwbEEQ98zvwdF7PzeDoMa/9M+tXzEOuM/A+LjMpczxKFAqQ",
+                "ephemeral": "Kv+mvdiIk4gvrocQWM5kdr5FzyFLgwJ4o6WL/r1EC0s",
+                "mac": "5MTP4/BAzXc"
+            }
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let room_key_stream = client.encryption().backups().room_keys_for_room_stream(room_id);
+    pin_mut!(room_key_stream);
+
+    let room = client.get_room(room_id).expect("We should have access to the room after the sync");
+
+    // The room key is missing, so it's downloaded from the backup.
+    let event = room.event(event_id).await.expect("We should be able to fetch our encrypted event");
+    assert_matches!(event.encryption_info, None);
+
+    if let Some(Ok(room_keys)) = room_key_stream.next().await {
+        let (_, room_key_set) = room_keys.first_key_value().unwrap();
+        assert!(room_key_set.contains(SESSION_ID));
+    } else {
+        panic!("Failed to get an update about room keys being imported from the backup")
+    }
+
+    // We have the room key now, so failing to decrypt this event isn't caused by a
+    // missing room key, and the backup doesn't help.
+    let event =
+        room.event(tampered_event_id).await.expect("We should be able to fetch the tampered event");
+    assert_matches!(event.encryption_info, None);
+
+    // Another missing room key is downloaded. The requests are handled in order,
+    // so once it's imported, a download for the tampered event would have
+    // been started already.
+    let event =
+        room.event(other_event_id).await.expect("We should be able to fetch the other event");
+    assert_matches!(event.encryption_info, None);
+
+    if let Some(Ok(room_keys)) = room_key_stream.next().await {
+        let (_, room_key_set) = room_keys.first_key_value().unwrap();
+        assert!(room_key_set.contains(OTHER_SESSION_ID));
+    } else {
+        panic!("Failed to get an update about room keys being imported from the backup")
+    }
+
+    server.verify().await;
+}
@@ -0,0 +1,641 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    collections::BTreeMap,
+    iter,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use assert_matches::assert_matches;
+use matrix_sdk::{
+    clock::TestClock,
+    config::RequestConfig,
+    crypto::{EncryptionSettings, OlmMachine},
+    encryption::{
+        dehydrated_devices::{DehydratedDeviceError, RotationPolicy, RotationState},
+        secret_storage::SecretStore,
+    },
+    matrix_auth::{MatrixSession, MatrixSessionTokens},
+    Client,
+};
+use matrix_sdk_base::SessionMeta;
+use matrix_sdk_test::async_test;
+use ruma::{
+    api::client::keys::{claim_keys, get_keys},
+    assign, device_id,
+    encryption::{DeviceKeys, OneTimeKey},
+    room_id,
+    serde::Raw,
+    to_device::DeviceIdOrAllDevices,
+    user_id, OwnedDeviceId, OwnedDeviceKeyId, RoomId, TransactionId,
+};
+use serde_json::{json, Value};
+use tokio::time::{sleep, timeout};
+use wiremock::{
+    matchers::{header, method, path, path_regex},
+    Mock, MockServer, Request, ResponseTemplate,
+};
+
+use crate::test_client_builder;
+
+const SECRET_STORE_KEY: &str = "EsTj 3yST y93F SLpB jJsz eAXc 2XzA ygD3 w69H fGaN TKBj jXEd";
+
+const DEHYDRATED_DEVICE_PATH: &str =
+    "/_matrix/client/unstable/org.matrix.msc3814.v1/dehydrated_device";
+
+const PICKLE_KEY_PATH: &str =
+    "/_matrix/client/r0/user/@example:localhost/account_data/org.matrix.msc3814";
+
+/// A logged-in client with cross-signing keys, which are needed to sign the
+/// dehydrated devices.
+async fn test_client() -> (Client, MockServer) {
+    test_client_with_clock(TestClock::new()).await
+}
+
+async fn test_client_with_clock(clock: TestClock) -> (Client, MockServer) {
+    let session = MatrixSession {
+        meta: SessionMeta {
+            user_id: user_id!("@example:localhost").to_owned(),
+            device_id: device_id!("DEVICEID").to_owned(),
+        },
+        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+    };
+
+    let (builder, server) = test_client_builder().await;
+    let client = builder
+        .request_config(RequestConfig::new().disable_retry())
+        .clock(Arc::new(clock))
+        .build()
+        .await
+        .unwrap();
+
+    Mock::given(method("GET"))
+        .and(path(
+            "_matrix/client/r0/user/@example:localhost/account_data/m.secret_storage.default_key",
+        ))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "key": "bmur2d9ypPUH1msSwCxQOJkuKRmJI55e",
+        })))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path(
+            "_matrix/client/r0/user/@example:localhost/account_data/m.secret_storage.key.bmur2d9ypPUH1msSwCxQOJkuKRmJI55e",
+        ))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "algorithm": "m.secret_storage.v1.aes-hmac-sha2",
+            "iv": "xv5b6/p3ExEw++wTyfSHEg==",
+            "mac": "ujBBbXahnTAMkmPUX2/0+VTfUh63pGyVRuBcDMgmJC8=",
+        })))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("_matrix/client/r0/keys/upload"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "one_time_key_counts": {
+                "signed_curve25519": 50
+            }
+        })))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("_matrix/client/unstable/keys/device_signing/upload"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("_matrix/client/unstable/keys/signatures/upload"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "failures": {},
+        })))
+        .mount(&server)
+        .await;
+
+    client.restore_session(session).await.unwrap();
+    client.encryption().wait_for_e2ee_initialization_tasks().await;
+    client
+        .encryption()
+        .bootstrap_cross_signing(None)
+        .await
+        .expect("We should be able to bootstrap our cross-signing");
+
+    (client, server)
+}
+
+async fn open_secret_store(client: &Client) -> SecretStore {
+    client
+        .encryption()
+        .secret_storage()
+        .open_secret_store(SECRET_STORE_KEY)
+        .await
+        .expect("We should be able to open our secret store")
+}
+
+/// What the homeserver knows about the dehydrated devices of the user.
+#[derive(Clone, Default)]
+struct DehydratedDeviceServer {
+    /// The bodies of the requests uploading a dehydrated device, in order.
+    uploaded_devices: Arc<Mutex<Vec<Value>>>,
+    /// The to-device events sent to the current dehydrated device.
+    to_device_events: Arc<Mutex<Vec<Value>>>,
+    /// The encrypted pickle key in the account data of the user.
+    pickle_key_secret: Arc<Mutex<Option<Value>>>,
+}
+
+impl DehydratedDeviceServer {
+    async fn mount(&self, server: &MockServer) {
+        let uploaded_devices = self.uploaded_devices.clone();
+        Mock::given(method("PUT"))
+            .and(path(DEHYDRATED_DEVICE_PATH))
+            .and(header("authorization", "Bearer 1234"))
+            .respond_with(move |request: &Request| {
+                let body: Value = request.body_json().unwrap();
+                let device_id = body["device_id"].clone();
+                uploaded_devices.lock().unwrap().push(body);
+
+                ResponseTemplate::new(200).set_body_json(json!({ "device_id": device_id }))
+            })
+            .named("dehydrated device PUT")
+            .mount(server)
+            .await;
+
+        let uploaded_devices = self.uploaded_devices.clone();
+        Mock::given(method("GET"))
+            .and(path(DEHYDRATED_DEVICE_PATH))
+            .and(header("authorization", "Bearer 1234"))
+            .respond_with(move |_: &Request| match uploaded_devices.lock().unwrap().last() {
+                Some(device) => ResponseTemplate::new(200).set_body_json(json!({
+                    "device_id": device["device_id"],
+                    "device_data": device["device_data"],
+                })),
+                None => ResponseTemplate::new(404).set_body_json(json!({
+                    "errcode": "M_NOT_FOUND",
+                    "error": "No dehydrated device",
+                })),
+            })
+            .named("dehydrated device GET")
+            .mount(server)
+            .await;
+
+        let to_device_events = self.to_device_events.clone();
+        Mock::given(method("POST"))
+            .and(path_regex(format!("^{DEHYDRATED_DEVICE_PATH}/.*/events$")))
+            .and(header("authorization", "Bearer 1234"))
+            .respond_with(move |_: &Request| {
+                let events = to_device_events.lock().unwrap().drain(..).collect::<Vec<_>>();
+                ResponseTemplate::new(200).set_body_json(json!({ "events": events }))
+            })
+            .named("dehydrated device events POST")
+            .mount(server)
+            .await;
+
+        let pickle_key_secret = self.pickle_key_secret.clone();
+        Mock::given(method("PUT"))
+            .and(path(PICKLE_KEY_PATH))
+            .and(header("authorization", "Bearer 1234"))
+            .respond_with(move |request: &Request| {
+                *pickle_key_secret.lock().unwrap() = Some(request.body_json().unwrap());
+                ResponseTemplate::new(200).set_body_json(json!({}))
+            })
+            .named("pickle key account data PUT")
+            .mount(server)
+            .await;
+
+        let pickle_key_secret = self.pickle_key_secret.clone();
+        Mock::given(method("GET"))
+            .and(path(PICKLE_KEY_PATH))
+            .and(header("authorization", "Bearer 1234"))
+            .respond_with(move |_: &Request| match &*pickle_key_secret.lock().unwrap() {
+                Some(secret) => ResponseTemplate::new(200).set_body_json(secret),
+                None => ResponseTemplate::new(404).set_body_json(json!({
+                    "errcode": "M_NOT_FOUND",
+                    "error": "Account data not found",
+                })),
+            })
+            .named("pickle key account data GET")
+            .mount(server)
+            .await;
+    }
+
+    /// Wait until `count` dehydrated devices have been uploaded in total.
+    async fn wait_for_uploads(&self, count: usize) {
+        timeout(Duration::from_secs(5), async {
+            while self.uploaded_devices.lock().unwrap().len() < count {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("The dehydrated device should have been uploaded");
+    }
+
+    fn uploaded_device_ids(&self) -> Vec<OwnedDeviceId> {
+        self.uploaded_devices
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|device| serde_json::from_value(device["device_id"].clone()).unwrap())
+            .collect()
+    }
+
+    /// Share a room key with the current dehydrated device, from another
+    /// device of the user.
+    async fn send_room_key(&self, room_id: &RoomId) {
+        let user_id = user_id!("@example:localhost");
+        let device = self.uploaded_devices.lock().unwrap().last().cloned().unwrap();
+        let device_id: OwnedDeviceId = serde_json::from_value(device["device_id"].clone()).unwrap();
+
+        let sender = OlmMachine::new(user_id, device_id!("OTHERDEVICE")).await;
+
+        // Let the other device know about the dehydrated device, and create an
+        // Olm session with it.
+        let device_keys: Raw<DeviceKeys> =
+            serde_json::from_value(device["device_keys"].clone()).unwrap();
+        let keys_query = assign!(get_keys::v3::Response::new(), {
+            device_keys: BTreeMap::from([(
+                user_id.to_owned(),
+                BTreeMap::from([(device_id.clone(), device_keys)]),
+            )]),
+        });
+        sender.mark_request_as_sent(&TransactionId::new(), &keys_query).await.unwrap();
+
+        let one_time_keys: BTreeMap<OwnedDeviceKeyId, Raw<OneTimeKey>> =
+            serde_json::from_value(device["one_time_keys"].clone()).unwrap();
+        let one_time_key = one_time_keys.into_iter().next().unwrap();
+        let keys_claim = claim_keys::v3::Response::new(BTreeMap::from([(
+            user_id.to_owned(),
+            BTreeMap::from([(device_id.clone(), BTreeMap::from([one_time_key]))]),
+        )]));
+        sender.mark_request_as_sent(&TransactionId::new(), &keys_claim).await.unwrap();
+
+        let requests = sender
+            .share_room_key(room_id, iter::once(user_id), EncryptionSettings::default())
+            .await
+            .unwrap();
+        let content =
+            requests[0].messages[user_id][&DeviceIdOrAllDevices::DeviceId(device_id)].clone();
+
+        self.to_device_events.lock().unwrap().push(json!({
+            "sender": user_id,
+            "type": "m.room.encrypted",
+            "content": content,
+        }));
+    }
+}
+
+#[async_test]
+async fn test_rehydrate_and_replace() {
+    let room_id = room_id!("!test:localhost");
+    let (client, server) = test_client().await;
+    let dehydrated_device_server = DehydratedDeviceServer::default();
+    dehydrated_device_server.mount(&server).await;
+
+    let secret_store = open_secret_store(&client).await;
+    let dehydrated_devices = client.encryption().dehydrated_devices();
+
+    // A previous device of the user created a dehydrated device, which then
+    // received a room key.
+    let pickle_key = dehydrated_devices.pickle_key(&secret_store).await.unwrap();
+    let previous_device_id = dehydrated_devices.rotate(&pickle_key).await.unwrap();
+    dehydrated_device_server.send_room_key(room_id).await;
+
+    let summary = dehydrated_devices.rehydrate_and_replace(&secret_store).await.unwrap();
+
+    // The room key was imported.
+    assert_eq!(summary.imported_room_keys, Some(1));
+    {
+        let olm_machine = client.olm_machine_for_testing().await;
+        let room_keys = olm_machine
+            .as_ref()
+            .unwrap()
+            .export_room_keys(|session| session.room_id() == room_id)
+            .await
+            .unwrap();
+        assert_eq!(room_keys.len(), 1);
+    }
+
+    // The dehydrated device was replaced.
+    assert_ne!(summary.device_id, previous_device_id);
+    assert_eq!(
+        dehydrated_device_server.uploaded_device_ids(),
+        vec![previous_device_id, summary.device_id]
+    );
+}
+
+#[async_test]
+async fn test_rehydrate_and_replace_without_dehydrated_device() {
+    let (client, server) = test_client().await;
+    let dehydrated_device_server = DehydratedDeviceServer::default();
+    dehydrated_device_server.mount(&server).await;
+
+    let secret_store = open_secret_store(&client).await;
+
+    let summary = client
+        .encryption()
+        .dehydrated_devices()
+        .rehydrate_and_replace(&secret_store)
+        .await
+        .unwrap();
+
+    // Nothing was imported, but a dehydrated device was created anyway.
+    assert_eq!(summary.imported_room_keys, None);
+    assert_eq!(dehydrated_device_server.uploaded_device_ids(), vec![summary.device_id]);
+
+    // There was no dehydrated device, so there were no events to fetch.
+    let requests = server.received_requests().await.unwrap();
+    assert!(!requests.iter().any(|request| request.url.path().ends_with("/events")));
+}
+
+#[async_test]
+async fn test_undecryptable_dehydrated_device_is_replaced() {
+    let (client, server) = test_client().await;
+    let dehydrated_device_server = DehydratedDeviceServer::default();
+    dehydrated_device_server.mount(&server).await;
+
+    let secret_store = open_secret_store(&client).await;
+    let dehydrated_devices = client.encryption().dehydrated_devices();
+
+    // The dehydrated device was created with a pickle key that was lost.
+    let previous_device_id = dehydrated_devices.rotate(&[1; 32]).await.unwrap();
+
+    let summary = dehydrated_devices.rehydrate_and_replace(&secret_store).await.unwrap();
+
+    // Nothing could be imported, but the device was replaced with one that
+    // uses the pickle key of secret storage.
+    assert_eq!(summary.imported_room_keys, None);
+    assert_eq!(
+        dehydrated_device_server.uploaded_device_ids(),
+        vec![previous_device_id, summary.device_id]
+    );
+
+    let pickle_key = dehydrated_devices.pickle_key(&secret_store).await.unwrap();
+    assert_eq!(dehydrated_devices.rehydrate(&pickle_key).await.unwrap(), Some(0));
+}
+
+#[async_test]
+async fn test_enable_replaces_an_undecryptable_dehydrated_device() {
+    let (client, server) = test_client().await;
+    let dehydrated_device_server = DehydratedDeviceServer::default();
+    dehydrated_device_server.mount(&server).await;
+
+    let secret_store = open_secret_store(&client).await;
+    let dehydrated_devices = client.encryption().dehydrated_devices();
+
+    // The dehydrated device was created with a pickle key that was lost.
+    let previous_device_id = dehydrated_devices.rotate(&[1; 32]).await.unwrap();
+
+    dehydrated_devices.enable(&secret_store, RotationPolicy::AfterRehydration).await.unwrap();
+
+    let uploaded = dehydrated_device_server.uploaded_device_ids();
+    assert_eq!(uploaded.len(), 2);
+    assert_eq!(uploaded[0], previous_device_id);
+}
+
+#[async_test]
+async fn test_pickle_key_is_kept_in_secret_storage() {
+    let (client, server) = test_client().await;
+    let dehydrated_device_server = DehydratedDeviceServer::default();
+    dehydrated_device_server.mount(&server).await;
+
+    let secret_store = open_secret_store(&client).await;
+    let dehydrated_devices = client.encryption().dehydrated_devices();
+
+    // There is no pickle key yet, a new one is stored under the name defined by
+    // MSC3814.
+    let pickle_key = dehydrated_devices.pickle_key(&secret_store).await.unwrap();
+    assert!(dehydrated_device_server.pickle_key_secret.lock().unwrap().is_some());
+
+    let secret = secret_store.get_secret("org.matrix.msc3814").await.unwrap().unwrap();
+    assert_eq!(secret, matrix_sdk::crypto::vodozemac::base64_encode(pickle_key.as_slice()));
+
+    // The next time, the same key is read from secret storage.
+    assert_eq!(dehydrated_devices.pickle_key(&secret_store).await.unwrap(), pickle_key);
+
+    let requests = server.received_requests().await.unwrap();
+    let uploads = requests
+        .iter()
+        .filter(|request| {
+            request.method == wiremock::http::Method::Put && request.url.path() == PICKLE_KEY_PATH
+        })
+        .count();
+    assert_eq!(uploads, 1);
+}
+
+#[async_test]
+async fn test_invalid_pickle_key_in_secret_storage() {
+    let (client, server) = test_client().await;
+    let dehydrated_device_server = DehydratedDeviceServer::default();
+    dehydrated_device_server.mount(&server).await;
+
+    let secret_store = open_secret_store(&client).await;
+    secret_store.put_secret("org.matrix.msc3814", "not a pickle key").await.unwrap();
+
+    let result = client
+        .encryption()
+        .dehydrated_devices()
+        .enable(&secret_store, RotationPolicy::AfterRehydration)
+        .await;
+
+    assert_matches!(result, Err(DehydratedDeviceError::InvalidPickleKey));
+    assert!(dehydrated_device_server.uploaded_device_ids().is_empty());
+}
+
+#[async_test]
+async fn test_enable_replaces_the_dehydrated_device_once_after_rehydration() {
+    let room_id = room_id!("!test:localhost");
+    let clock = TestClock::new();
+    let (client, server) = test_client_with_clock(clock.clone()).await;
+    let dehydrated_device_server = DehydratedDeviceServer::default();
+    dehydrated_device_server.mount(&server).await;
+
+    let secret_store = open_secret_store(&client).await;
+    let dehydrated_devices = client.encryption().dehydrated_devices();
+
+    let pickle_key = dehydrated_devices.pickle_key(&secret_store).await.unwrap();
+    let previous_device_id = dehydrated_devices.rotate(&pickle_key).await.unwrap();
+    dehydrated_device_server.send_room_key(room_id).await;
+
+    dehydrated_devices.enable(&secret_store, RotationPolicy::AfterRehydration).await.unwrap();
+
+    // The room key of the previous dehydrated device was imported, and the
+    // device was replaced right away.
+    {
+        let olm_machine = client.olm_machine_for_testing().await;
+        let room_keys = olm_machine
+            .as_ref()
+            .unwrap()
+            .export_room_keys(|session| session.room_id() == room_id)
+            .await
+            .unwrap();
+        assert_eq!(room_keys.len(), 1);
+    }
+
+    let uploaded = dehydrated_device_server.uploaded_device_ids();
+    assert_eq!(uploaded.len(), 2);
+    assert_eq!(uploaded[0], previous_device_id);
+
+    // It isn't replaced again later.
+    clock.advance(Duration::from_secs(30 * 24 * 60 * 60));
+    sleep(Duration::from_millis(100)).await;
+
+    assert_eq!(dehydrated_device_server.uploaded_device_ids().len(), 2);
+    assert_matches!(dehydrated_devices.rotation_state().get(), RotationState::Idle);
+}
+
+#[async_test]
+async fn test_enable_with_periodic_rotation() {
+    let room_id = room_id!("!test:localhost");
+    let (client, server) = test_client().await;
+    let dehydrated_device_server = DehydratedDeviceServer::default();
+    dehydrated_device_server.mount(&server).await;
+
+    let secret_store = open_secret_store(&client).await;
+    let dehydrated_devices = client.encryption().dehydrated_devices();
+
+    let pickle_key = dehydrated_devices.pickle_key(&secret_store).await.unwrap();
+    let previous_device_id = dehydrated_devices.rotate(&pickle_key).await.unwrap();
+    dehydrated_device_server.send_room_key(room_id).await;
+
+    dehydrated_devices
+        .enable(&secret_store, RotationPolicy::Every(Duration::from_secs(7 * 24 * 60 * 60)))
+        .await
+        .unwrap();
+
+    // The room key of the previous dehydrated device was imported before the
+    // rotation task replaced it.
+    {
+        let olm_machine = client.olm_machine_for_testing().await;
+        let room_keys = olm_machine
+            .as_ref()
+            .unwrap()
+            .export_room_keys(|session| session.room_id() == room_id)
+            .await
+            .unwrap();
+        assert_eq!(room_keys.len(), 1);
+    }
+
+    dehydrated_device_server.wait_for_uploads(2).await;
+    assert_eq!(dehydrated_device_server.uploaded_device_ids()[0], previous_device_id);
+
+    dehydrated_devices.stop_rotation();
+}
+
+#[async_test]
+async fn test_periodic_rotation() {
+    let clock = TestClock::new();
+    let (client, server) = test_client_with_clock(clock.clone()).await;
+    let dehydrated_device_server = DehydratedDeviceServer::default();
+    dehydrated_device_server.mount(&server).await;
+
+    let secret_store = open_secret_store(&client).await;
+    let dehydrated_devices = client.encryption().dehydrated_devices();
+    let pickle_key = dehydrated_devices.pickle_key(&secret_store).await.unwrap();
+
+    let interval = Duration::from_secs(24 * 60 * 60);
+    dehydrated_devices.start_rotation(&pickle_key, interval);
+
+    // The dehydrated device is replaced right away.
+    dehydrated_device_server.wait_for_uploads(1).await;
+
+    // It isn't replaced again before the interval elapsed.
+    clock.advance(interval / 2);
+    sleep(Duration::from_millis(100)).await;
+    assert_eq!(dehydrated_device_server.uploaded_device_ids().len(), 1);
+
+    // It is replaced once the interval elapsed, and again at the next one.
+    clock.advance(interval / 2);
+    dehydrated_device_server.wait_for_uploads(2).await;
+
+    clock.advance(interval);
+    dehydrated_device_server.wait_for_uploads(3).await;
+
+    // Every new device replaced the previous one, which is gone from the
+    // homeserver without having to be deleted.
+    let uploaded = dehydrated_device_server.uploaded_device_ids();
+    let rehydrated = dehydrated_devices.rehydrate(&pickle_key).await.unwrap();
+    assert_eq!(rehydrated, Some(0));
+
+    let requests = server.received_requests().await.unwrap();
+    assert!(!requests.iter().any(|request| request.method == wiremock::http::Method::Delete));
+    let events_request =
+        requests.iter().rev().find(|request| request.url.path().ends_with("/events")).unwrap();
+    assert!(events_request.url.path().contains(uploaded[2].as_str()));
+
+    // No more devices are uploaded once the rotation is stopped.
+    dehydrated_devices.stop_rotation();
+    clock.advance(interval);
+    sleep(Duration::from_millis(100)).await;
+    assert_eq!(dehydrated_device_server.uploaded_device_ids().len(), 3);
+    assert_matches!(dehydrated_devices.rotation_state().get(), RotationState::Idle);
+}
+
+#[async_test]
+async fn test_failed_rotation_is_retried_before_the_next_period() {
+    let clock = TestClock::new();
+    let (client, server) = test_client_with_clock(clock.clone()).await;
+
+    // The first upload of a dehydrated device fails.
+    Mock::given(method("PUT"))
+        .and(path(DEHYDRATED_DEVICE_PATH))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(500).set_body_json(json!({
+            "errcode": "M_UNKNOWN",
+            "error": "Internal server error",
+        })))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&server)
+        .await;
+
+    let dehydrated_device_server = DehydratedDeviceServer::default();
+    dehydrated_device_server.mount(&server).await;
+
+    let secret_store = open_secret_store(&client).await;
+    let dehydrated_devices = client.encryption().dehydrated_devices();
+    let pickle_key = dehydrated_devices.pickle_key(&secret_store).await.unwrap();
+
+    let mut rotation_state = dehydrated_devices.rotation_state();
+    let interval = Duration::from_secs(24 * 60 * 60);
+    dehydrated_devices.start_rotation(&pickle_key, interval);
+
+    // The failure is reported with its error.
+    timeout(Duration::from_secs(5), async {
+        while !matches!(rotation_state.next().await, Some(RotationState::Failed(_))) {}
+    })
+    .await
+    .expect("The rotation should have failed");
+    assert!(dehydrated_device_server.uploaded_device_ids().is_empty());
+
+    // The rotation is retried a minute later, long before the next period.
+    clock.advance(Duration::from_secs(60));
+    dehydrated_device_server.wait_for_uploads(1).await;
+
+    timeout(Duration::from_secs(5), async {
+        while !matches!(rotation_state.next().await, Some(RotationState::Idle)) {}
+    })
+    .await
+    .expect("The rotation should have succeeded");
+
+    dehydrated_devices.stop_rotation();
+}
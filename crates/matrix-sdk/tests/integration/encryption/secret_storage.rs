@@ -1,11 +1,13 @@
 use std::sync::{Arc, Mutex};
 
 use assert_matches::assert_matches;
+use futures_util::StreamExt;
 use matrix_sdk::{
-    encryption::secret_storage::SecretStorageError,
+    config::SyncSettings,
+    encryption::secret_storage::{SecretStorageError, SecretStorageKeyInfo},
     matrix_auth::{MatrixSession, MatrixSessionTokens},
 };
-use matrix_sdk_base::SessionMeta;
+use matrix_sdk_base::{crypto::secret_storage::DecodeError, SessionMeta};
 use matrix_sdk_test::async_test;
 use ruma::{
     device_id,
@@ -23,7 +25,7 @@ use wiremock::{
     Mock, MockServer, ResponseTemplate,
 };
 
-use crate::{logged_in_client, no_retry_test_client};
+use crate::{logged_in_client, mock_sync, no_retry_test_client};
 
 const SECRET_STORE_KEY: &str = "EsTj 3yST y93F SLpB jJsz eAXc 2XzA ygD3 w69H fGaN TKBj jXEd";
 
@@ -267,6 +269,63 @@ async fn secret_store_opening() {
     server.verify().await;
 }
 
+#[async_test]
+async fn secret_store_key_verification() {
+    let (client, server) = logged_in_client().await;
+
+    mock_secret_store_key(
+        &server,
+        client.user_id().unwrap(),
+        "bmur2d9ypPUH1msSwCxQOJkuKRmJI55e",
+        "xv5b6/p3ExEw++wTyfSHEg==",
+        "ujBBbXahnTAMkmPUX2/0+VTfUh63pGyVRuBcDMgmJC8=",
+    )
+    .await;
+
+    // No secret is fetched while verifying the key.
+    Mock::given(method("GET"))
+        .and(path("_matrix/client/r0/user/@example:localhost/account_data/m.cross_signing.master"))
+        .respond_with(ResponseTemplate::new(404))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let secret_storage = client.encryption().secret_storage();
+
+    assert!(secret_storage.verify_key(SECRET_STORE_KEY).await.unwrap());
+    assert!(!secret_storage
+        .verify_key("EsTj 3yST y93F SLpB jJsz eAXc 2XzA ygD3 w69H fGaN TKBj jXEe")
+        .await
+        .unwrap());
+    assert!(!secret_storage.verify_key("not a key").await.unwrap());
+
+    server.verify().await;
+}
+
+#[async_test]
+async fn secret_store_key_verification_with_invalid_key_info() {
+    let (client, server) = logged_in_client().await;
+
+    // The MAC is too short to be checked.
+    mock_secret_store_key(
+        &server,
+        client.user_id().unwrap(),
+        "bmur2d9ypPUH1msSwCxQOJkuKRmJI55e",
+        "xv5b6/p3ExEw++wTyfSHEg==",
+        "AAAA",
+    )
+    .await;
+
+    let secret_storage = client.encryption().secret_storage();
+
+    assert_matches!(
+        secret_storage.verify_key(SECRET_STORE_KEY).await,
+        Err(SecretStorageError::SecretStorageKey(DecodeError::MacLength(..)))
+    );
+
+    server.verify().await;
+}
+
 #[async_test]
 async fn set_in_secret_store() {
     let (client, server) = logged_in_client().await;
@@ -662,3 +721,110 @@ async fn is_secret_storage_enabled() {
         );
     }
 }
+
+#[async_test]
+async fn secret_storage_status_follows_account_data() {
+    let session = MatrixSession {
+        meta: SessionMeta {
+            user_id: user_id!("@example:morpheus.localhost").to_owned(),
+            device_id: device_id!("DEVICEID").to_owned(),
+        },
+        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+    };
+    let (client, server) = no_retry_test_client().await;
+    client.restore_session(session).await.unwrap();
+    client.encryption().wait_for_e2ee_initialization_tasks().await;
+
+    let secret_storage = client.encryption().secret_storage();
+    let mut status_stream = secret_storage.status_stream();
+
+    // Nothing is set up in the account data yet.
+    assert!(!status_stream.next().await.unwrap().is_enabled());
+    assert_eq!(secret_storage.default_key_info(), None);
+
+    mock_sync(
+        &server,
+        json!({
+            "next_batch": "s1",
+            "account_data": {
+                "events": [
+                    {
+                        "type": "m.secret_storage.default_key",
+                        "content": { "key": "bmur2d9ypPUH1msSwCxQOJkuKRmJI55e" }
+                    },
+                    {
+                        "type": "m.secret_storage.key.bmur2d9ypPUH1msSwCxQOJkuKRmJI55e",
+                        "content": {
+                            "algorithm": "m.secret_storage.v1.aes-hmac-sha2",
+                            "iv": "xv5b6/p3ExEw++wTyfSHEg==",
+                            "mac": "ujBBbXahnTAMkmPUX2/0+VTfUh63pGyVRuBcDMgmJC8=",
+                            "passphrase": {
+                                "algorithm": "m.pbkdf2",
+                                "iterations": 500000,
+                                "salt": "fake-salt"
+                            }
+                        }
+                    },
+                    {
+                        "type": "m.cross_signing.master",
+                        "content": {
+                            "encrypted": {
+                                "bmur2d9ypPUH1msSwCxQOJkuKRmJI55e": {
+                                    "iv": "xv5b6/p3ExEw++wTyfSHEg==",
+                                    "ciphertext": "ZAZ7O7fP9nyTkwhZ",
+                                    "mac": "ujBBbXahnTAMkmPUX2/0+VTfUh63pGyVRuBcDMgmJC8="
+                                }
+                            }
+                        }
+                    },
+                    {
+                        "type": "m.megolm_backup.v1",
+                        "content": {
+                            "encrypted": {
+                                "some_other_key": {
+                                    "iv": "xv5b6/p3ExEw++wTyfSHEg==",
+                                    "ciphertext": "ZAZ7O7fP9nyTkwhZ",
+                                    "mac": "ujBBbXahnTAMkmPUX2/0+VTfUh63pGyVRuBcDMgmJC8="
+                                }
+                            }
+                        }
+                    }
+                ]
+            }
+        }),
+        None,
+    )
+    .await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    let status = status_stream.next().await.unwrap();
+    assert!(status.is_enabled());
+    assert_eq!(
+        status.default_key,
+        Some(SecretStorageKeyInfo {
+            key_id: "bmur2d9ypPUH1msSwCxQOJkuKRmJI55e".to_owned(),
+            algorithm: "m.secret_storage.v1.aes-hmac-sha2".to_owned(),
+            has_passphrase: true,
+        })
+    );
+    // The backup key is encrypted with another key, so it isn't reachable.
+    assert_eq!(status.stored_secrets, vec![SecretName::CrossSigningMasterKey]);
+    assert_eq!(secret_storage.status(), status);
+
+    // Disabling secret storage is done by overwriting the default key event.
+    mock_sync(
+        &server,
+        json!({
+            "next_batch": "s2",
+            "account_data": {
+                "events": [{ "type": "m.secret_storage.default_key", "content": {} }]
+            }
+        }),
+        Some("s1".to_owned()),
+    )
+    .await;
+    client.sync_once(SyncSettings::default().token("s1")).await.unwrap();
+
+    assert!(!status_stream.next().await.unwrap().is_enabled());
+    assert_eq!(secret_storage.default_key_info(), None);
+}
@@ -3,14 +3,16 @@ use std::{
     sync::{Arc, Mutex},
 };
 
+use futures_util::{pin_mut, FutureExt, Stream, StreamExt};
 use imbl::HashSet;
 use matrix_sdk::{
     config::RequestConfig,
+    encryption::identities::{IdentityUpdates, IdentityVerificationState, VerificationStateChange},
     matrix_auth::{MatrixSession, MatrixSessionTokens},
     Client,
 };
 use matrix_sdk_base::{crypto::EncryptionSyncChanges, SessionMeta};
-use matrix_sdk_test::{async_test, SyncResponseBuilder};
+use matrix_sdk_test::{async_test, JoinedRoomBuilder, StateTestEvent, SyncResponseBuilder};
 use ruma::{
     api::{
         client::{keys::upload_signatures::v3::SignedKeys, sync::sync_events::DeviceLists},
@@ -18,13 +20,13 @@ use ruma::{
     },
     assign,
     encryption::{CrossSigningKey, DeviceKeys},
-    owned_device_id, owned_user_id,
+    owned_device_id, owned_user_id, room_id,
     serde::Raw,
     DeviceId, DeviceKeyId, OwnedDeviceId, OwnedUserId,
 };
 use serde_json::json;
 use wiremock::{
-    matchers::{method, path},
+    matchers::{method, path, path_regex},
     Mock, MockServer, Request, ResponseTemplate,
 };
 
@@ -514,3 +516,283 @@ async fn test_unchecked_mutual_verification() {
     assert!(alice_bob_device.is_verified());
     assert!(alice_bob_device.is_verified_with_cross_signing());
 }
+
+/// Collect the verification state changes of the updates that are ready in
+/// the stream.
+fn pending_verification_state_changes(
+    stream: &mut (impl Stream<Item = IdentityUpdates> + Unpin),
+) -> BTreeMap<OwnedUserId, VerificationStateChange> {
+    let mut changes = BTreeMap::new();
+    while let Some(Some(updates)) = stream.next().now_or_never() {
+        changes.extend(updates.verification_state_changes);
+    }
+    changes
+}
+
+#[async_test]
+async fn test_user_identities_stream_reports_verification_state_changes() {
+    let mut server = MockedServer::new().await;
+
+    let alice_user_id = owned_user_id!("@alice:example.org");
+    let alice_device_id = owned_device_id!("4L1C3");
+    let alice = Client::builder()
+        .homeserver_url(server.server.uri())
+        .server_versions([MatrixVersion::V1_0])
+        .request_config(RequestConfig::new().disable_retry())
+        .build()
+        .await
+        .unwrap();
+    alice
+        .restore_session(MatrixSession {
+            meta: SessionMeta {
+                user_id: alice_user_id.clone(),
+                device_id: alice_device_id.clone(),
+            },
+            tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        })
+        .await
+        .unwrap();
+
+    let bob = Client::builder()
+        .homeserver_url(server.server.uri())
+        .server_versions([MatrixVersion::V1_0])
+        .request_config(RequestConfig::new().disable_retry())
+        .build()
+        .await
+        .unwrap();
+
+    let bob_user_id = owned_user_id!("@bob:example.org");
+    let bob_device_id = owned_device_id!("B0B0B0B0B");
+    bob.restore_session(MatrixSession {
+        meta: SessionMeta { user_id: bob_user_id.clone(), device_id: bob_device_id.clone() },
+        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+    })
+    .await
+    .unwrap();
+
+    server.add_known_device(&alice_device_id);
+    server.add_known_device(&bob_device_id);
+
+    bootstrap_cross_signing(&alice).await;
+    bootstrap_cross_signing(&bob).await;
+
+    {
+        let mut sync_response_builder = SyncResponseBuilder::new();
+        mock_sync(&server.server, sync_response_builder.build_json_sync_response(), None).await;
+        alice.sync_once(Default::default()).await.unwrap();
+        bob.sync_once(Default::default()).await.unwrap();
+    }
+
+    let identities_stream = alice.encryption().user_identities_stream().await.unwrap();
+    pin_mut!(identities_stream);
+
+    // Bob isn't tracked yet, only the own identity of Alice is known.
+    let identities = alice.encryption().user_identities().await.unwrap();
+    assert_eq!(identities.len(), 1);
+    assert_eq!(identities[0].user_id(), alice_user_id);
+    assert_eq!(identities[0].verification_state(), IdentityVerificationState::Verified);
+
+    {
+        let alice_olm = alice.olm_machine_for_testing().await;
+        let alice_olm = alice_olm.as_ref().unwrap();
+        alice_olm.update_tracked_users([bob_user_id.as_ref()]).await.unwrap();
+    }
+
+    let mut sync_response_builder = SyncResponseBuilder::new();
+    mock_sync(&server.server, sync_response_builder.build_json_sync_response(), None).await;
+    alice.sync_once(Default::default()).await.unwrap();
+
+    // The identity of Bob is discovered, unverified.
+    let changes = pending_verification_state_changes(&mut identities_stream);
+    assert_eq!(
+        changes.get(&bob_user_id),
+        Some(&VerificationStateChange {
+            previous: None,
+            current: IdentityVerificationState::Unverified,
+        })
+    );
+    assert!(!changes.contains_key(&alice_user_id));
+
+    let identities = alice.encryption().user_identities().await.unwrap();
+    assert_eq!(identities.len(), 2);
+    assert_eq!(identities[1].user_id(), bob_user_id);
+    assert_eq!(identities[1].verification_state(), IdentityVerificationState::Unverified);
+
+    identities[1].verify().await.unwrap();
+
+    {
+        let alice_olm = alice.olm_machine_for_testing().await;
+        let alice_olm = alice_olm.as_ref().unwrap();
+        let changed_devices = &assign!(DeviceLists::default(), {
+            changed: vec![bob_user_id.clone()]
+        });
+        alice_olm
+            .receive_sync_changes(EncryptionSyncChanges {
+                to_device_events: Default::default(),
+                changed_devices,
+                one_time_keys_counts: &Default::default(),
+                unused_fallback_keys: Default::default(),
+                next_batch_token: None,
+            })
+            .await
+            .unwrap();
+    }
+
+    mock_sync(&server.server, sync_response_builder.build_json_sync_response(), None).await;
+    alice.sync_once(Default::default()).await.unwrap();
+
+    // Once the signature of Alice is on the identity of Bob, it's verified.
+    let changes = pending_verification_state_changes(&mut identities_stream);
+    assert_eq!(
+        changes.get(&bob_user_id),
+        Some(&VerificationStateChange {
+            previous: Some(IdentityVerificationState::Unverified),
+            current: IdentityVerificationState::Verified,
+        })
+    );
+}
+
+/// A member event of the given user in a room, as it's returned by sync and by
+/// `/members`.
+fn member_event(user_id: &OwnedUserId) -> serde_json::Value {
+    json!({
+        "content": { "membership": "join" },
+        "event_id": format!("$member_{}", user_id.localpart()),
+        "origin_server_ts": 151800140,
+        "sender": user_id,
+        "state_key": user_id,
+        "type": "m.room.member",
+    })
+}
+
+#[async_test]
+async fn test_room_identity_violations() {
+    let mut server = MockedServer::new().await;
+
+    let alice_user_id = owned_user_id!("@alice:example.org");
+    let alice_device_id = owned_device_id!("4L1C3");
+    let alice = Client::builder()
+        .homeserver_url(server.server.uri())
+        .server_versions([MatrixVersion::V1_0])
+        .request_config(RequestConfig::new().disable_retry())
+        .build()
+        .await
+        .unwrap();
+    alice
+        .restore_session(MatrixSession {
+            meta: SessionMeta {
+                user_id: alice_user_id.clone(),
+                device_id: alice_device_id.clone(),
+            },
+            tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        })
+        .await
+        .unwrap();
+
+    let bob = Client::builder()
+        .homeserver_url(server.server.uri())
+        .server_versions([MatrixVersion::V1_0])
+        .request_config(RequestConfig::new().disable_retry())
+        .build()
+        .await
+        .unwrap();
+
+    let bob_user_id = owned_user_id!("@bob:example.org");
+    let bob_device_id = owned_device_id!("B0B0B0B0B");
+    bob.restore_session(MatrixSession {
+        meta: SessionMeta { user_id: bob_user_id.clone(), device_id: bob_device_id.clone() },
+        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+    })
+    .await
+    .unwrap();
+
+    server.add_known_device(&alice_device_id);
+    server.add_known_device(&bob_device_id);
+
+    bootstrap_cross_signing(&alice).await;
+    bootstrap_cross_signing(&bob).await;
+
+    // Alice and Bob are in the same room.
+    let room_id = room_id!("!test:example.org");
+    let mut sync_response_builder = SyncResponseBuilder::new();
+    sync_response_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_state_event(StateTestEvent::Custom(member_event(&alice_user_id)))
+            .add_state_event(StateTestEvent::Custom(member_event(&bob_user_id))),
+    );
+    mock_sync(&server.server, sync_response_builder.build_json_sync_response(), None).await;
+    alice.sync_once(Default::default()).await.unwrap();
+    bob.sync_once(Default::default()).await.unwrap();
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/members"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [member_event(&alice_user_id), member_event(&bob_user_id)],
+        })))
+        .mount(&server.server)
+        .await;
+
+    {
+        let alice_olm = alice.olm_machine_for_testing().await;
+        let alice_olm = alice_olm.as_ref().unwrap();
+        alice_olm.update_tracked_users([bob_user_id.as_ref()]).await.unwrap();
+    }
+
+    mock_sync(&server.server, sync_response_builder.build_json_sync_response(), None).await;
+    alice.sync_once(Default::default()).await.unwrap();
+
+    // The first identity of Bob is pinned, there is no violation.
+    let room = alice.get_room(room_id).unwrap();
+    assert!(room.identity_violations().await.unwrap().is_empty());
+
+    // Bob replaces his identity from a new device.
+    let bob_new_device = Client::builder()
+        .homeserver_url(server.server.uri())
+        .server_versions([MatrixVersion::V1_0])
+        .request_config(RequestConfig::new().disable_retry())
+        .build()
+        .await
+        .unwrap();
+    let bob_new_device_id = owned_device_id!("B0B0B0B1");
+    bob_new_device
+        .restore_session(MatrixSession {
+            meta: SessionMeta {
+                user_id: bob_user_id.clone(),
+                device_id: bob_new_device_id.clone(),
+            },
+            tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        })
+        .await
+        .unwrap();
+    server.add_known_device(&bob_new_device_id);
+    bootstrap_cross_signing(&bob_new_device).await;
+
+    {
+        let alice_olm = alice.olm_machine_for_testing().await;
+        let alice_olm = alice_olm.as_ref().unwrap();
+        let changed_devices = &assign!(DeviceLists::default(), {
+            changed: vec![bob_user_id.clone()]
+        });
+        alice_olm
+            .receive_sync_changes(EncryptionSyncChanges {
+                to_device_events: Default::default(),
+                changed_devices,
+                one_time_keys_counts: &Default::default(),
+                unused_fallback_keys: Default::default(),
+                next_batch_token: None,
+            })
+            .await
+            .unwrap();
+    }
+
+    mock_sync(&server.server, sync_response_builder.build_json_sync_response(), None).await;
+    alice.sync_once(Default::default()).await.unwrap();
+
+    // The new identity of Bob violates the pinned one.
+    assert_eq!(room.identity_violations().await.unwrap(), vec![bob_user_id.clone()]);
+
+    // Once Alice accepts the new identity, the violation is gone.
+    let bob_identity = alice.encryption().get_user_identity(&bob_user_id).await.unwrap().unwrap();
+    bob_identity.pin_current_identity().await.unwrap();
+    assert!(room.identity_violations().await.unwrap().is_empty());
+}
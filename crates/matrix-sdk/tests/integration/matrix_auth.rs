@@ -1,12 +1,15 @@
-use std::{collections::BTreeMap, sync::Mutex};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
 
 use assert_matches::assert_matches;
 use matrix_sdk::{
-    config::RequestConfig,
+    config::{RequestConfig, StoreConfig},
     matrix_auth::{MatrixSession, MatrixSessionTokens},
     AuthApi, AuthSession, Client, RumaApiError,
 };
-use matrix_sdk_base::SessionMeta;
+use matrix_sdk_base::{store::MemoryStore, SessionMeta};
 use matrix_sdk_test::{async_test, test_json};
 use ruma::{
     api::{
@@ -27,7 +30,7 @@ use ruma::{
 use serde_json::{from_value as from_json_value, json, to_value as to_json_value};
 use url::Url;
 use wiremock::{
-    matchers::{method, path},
+    matchers::{header, method, path, query_param},
     Mock, MockServer, Request, ResponseTemplate,
 };
 
@@ -223,6 +226,141 @@ async fn test_login_error() {
     }
 }
 
+#[async_test]
+async fn test_register_guest_and_upgrade() {
+    let (client, server) = no_retry_test_client().await;
+    let auth = client.matrix_auth();
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/register"))
+        .and(query_param("kind", "guest"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "user_id": "@1234:example.org",
+            "access_token": "guest_token",
+            "device_id": "GUESTDEVICE",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    auth.register_guest().await.unwrap();
+
+    assert!(auth.is_guest());
+    assert_eq!(client.user_id().unwrap(), user_id!("@1234:example.org"));
+    assert_eq!(client.device_id().unwrap(), device_id!("GUESTDEVICE"));
+
+    // Guests can't create rooms.
+    assert_matches!(
+        client.create_room(client_api::room::create_room::v3::Request::new()).await,
+        Err(matrix_sdk::Error::GuestAccessForbidden)
+    );
+
+    // The homeserver upgrades the account in place, thanks to the guest access
+    // token.
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/register"))
+        .and(header("authorization", "Bearer guest_token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "user_id": "@1234:example.org",
+            "access_token": "full_token",
+            "device_id": "GUESTDEVICE",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    auth.upgrade_guest("1234", "password", None).await.unwrap();
+
+    assert!(!auth.is_guest());
+    assert_eq!(auth.access_token().unwrap(), "full_token");
+    assert_eq!(client.device_id().unwrap(), device_id!("GUESTDEVICE"));
+}
+
+#[async_test]
+async fn test_guest_session_is_restored() {
+    let store = Arc::new(MemoryStore::new());
+    let (builder, server) = test_client_builder().await;
+    let client = builder
+        .store_config(StoreConfig::new().state_store(store.clone()))
+        .request_config(RequestConfig::new().disable_retry())
+        .build()
+        .await
+        .unwrap();
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/register"))
+        .and(query_param("kind", "guest"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "user_id": "@1234:example.org",
+            "access_token": "guest_token",
+            "device_id": "GUESTDEVICE",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    client.matrix_auth().register_guest().await.unwrap();
+    let session = client.matrix_auth().session().unwrap();
+
+    // The state store remembers that the session is a guest account.
+    let restored_client = Client::builder()
+        .homeserver_url(server.uri())
+        .server_versions([MatrixVersion::V1_0])
+        .store_config(StoreConfig::new().state_store(store))
+        .request_config(RequestConfig::new().disable_retry())
+        .build()
+        .await
+        .unwrap();
+    restored_client.matrix_auth().restore_session(session).await.unwrap();
+
+    assert!(restored_client.matrix_auth().is_guest());
+}
+
+#[async_test]
+async fn test_upgrade_guest_asks_the_homeserver() {
+    let (client, server) = no_retry_test_client().await;
+    let auth = client.matrix_auth();
+
+    // The state store doesn't know that the session is a guest account.
+    auth.restore_session(MatrixSession {
+        meta: SessionMeta {
+            user_id: user_id!("@1234:example.org").to_owned(),
+            device_id: device_id!("GUESTDEVICE").to_owned(),
+        },
+        tokens: MatrixSessionTokens { access_token: "guest_token".to_owned(), refresh_token: None },
+    })
+    .await
+    .unwrap();
+    assert!(!auth.is_guest());
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/account/whoami"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "user_id": "@1234:example.org",
+            "device_id": "GUESTDEVICE",
+            "is_guest": true,
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/register"))
+        .and(header("authorization", "Bearer guest_token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "user_id": "@1234:example.org",
+            "access_token": "full_token",
+            "device_id": "GUESTDEVICE",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    auth.upgrade_guest("1234", "password", None).await.unwrap();
+
+    assert!(!auth.is_guest());
+    assert_eq!(auth.access_token().unwrap(), "full_token");
+}
+
 #[async_test]
 async fn test_register_error() {
     let (client, server) = no_retry_test_client().await;
@@ -0,0 +1,300 @@
+use std::{sync::Arc, time::Duration};
+
+use matrix_sdk::{
+    config::{RequestConfig, StoreConfig},
+    content_scanner::ScanVerdict,
+    matrix_auth::{MatrixSession, MatrixSessionTokens},
+    media::{MediaFormat, MediaRequest},
+    store_cleanup::CleanupPolicy,
+    Client, ClientBuilder, Error,
+};
+use matrix_sdk_base::{store::MemoryStore, SessionMeta};
+use matrix_sdk_test::async_test;
+use ruma::{api::MatrixVersion, device_id, events::room::MediaSource, mxc_uri, user_id};
+use serde_json::json;
+use tokio::time::{sleep, timeout};
+use url::Url;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::{logged_in_client, test_client_builder};
+
+#[async_test]
+async fn media_is_downloaded_through_the_content_scanner() {
+    let (builder, server) = test_client_builder().await;
+    let client = builder.content_scanner(Url::parse(&server.uri()).unwrap()).build().await.unwrap();
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/media_proxy/unstable/download/localhost/clean"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"clean content".to_vec()))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/media_proxy/unstable/download/localhost/infected"))
+        .respond_with(ResponseTemplate::new(403).set_body_json(json!({
+            "reason": "MCS_MEDIA_NOT_CLEAN",
+            "info": "***VIRUS DETECTED***",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let media = client.media();
+
+    let request = MediaRequest {
+        source: MediaSource::Plain(mxc_uri!("mxc://localhost/clean").to_owned()),
+        format: MediaFormat::File,
+    };
+    let content = media.get_media_content(&request, false).await.unwrap();
+    assert_eq!(content, b"clean content");
+
+    // The verdict is cached.
+    let verdict = media.scan(&request.source).await.unwrap();
+    assert_eq!(verdict, ScanVerdict::Clean);
+
+    let request = MediaRequest {
+        source: MediaSource::Plain(mxc_uri!("mxc://localhost/infected").to_owned()),
+        format: MediaFormat::File,
+    };
+    let scanned = media.get_scanned_media_content(&request, false).await.unwrap();
+    assert_eq!(
+        scanned.verdict,
+        ScanVerdict::Infected { info: Some("***VIRUS DETECTED***".to_owned()) }
+    );
+    assert!(scanned.content.is_none());
+
+    // The infected media isn't requested again.
+    let error = media.get_media_content(&request, false).await.unwrap_err();
+    assert!(matches!(error, Error::ContentScanner(_)), "unexpected error: {error:?}");
+}
+
+#[async_test]
+async fn content_scanner_never_gets_the_homeserver_access_token() {
+    let (builder, server) = test_client_builder().await;
+    let client = builder
+        .content_scanner(Url::parse(&server.uri()).unwrap())
+        .content_scanner_access_token("scanner_token".to_owned())
+        .build()
+        .await
+        .unwrap();
+    client
+        .restore_session(MatrixSession {
+            meta: SessionMeta {
+                user_id: user_id!("@example:localhost").to_owned(),
+                device_id: device_id!("DEVICEID").to_owned(),
+            },
+            tokens: MatrixSessionTokens {
+                access_token: "homeserver_token".to_owned(),
+                refresh_token: None,
+            },
+        })
+        .await
+        .unwrap();
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/media_proxy/unstable/download/localhost/clean"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"clean content".to_vec()))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let request = MediaRequest {
+        source: MediaSource::Plain(mxc_uri!("mxc://localhost/clean").to_owned()),
+        format: MediaFormat::File,
+    };
+    client.media().get_media_content(&request, false).await.unwrap();
+
+    // The scanner only gets its own token.
+    let requests = server.received_requests().await.unwrap();
+    let scanner_request = requests
+        .iter()
+        .find(|request| request.url.path().starts_with("/_matrix/media_proxy"))
+        .unwrap();
+    let authorization = scanner_request.headers.get("authorization").unwrap();
+    assert_eq!(authorization.to_str().unwrap(), "Bearer scanner_token");
+}
+
+#[async_test]
+async fn media_cached_before_the_content_scanner_is_scanned() {
+    let (builder, server) = test_client_builder().await;
+    let client = builder.content_scanner(Url::parse(&server.uri()).unwrap()).build().await.unwrap();
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/media_proxy/unstable/scan/localhost/clean"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "clean": true })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/media_proxy/unstable/scan/localhost/infected"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "clean": false,
+            "info": "***VIRUS DETECTED***",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let clean = MediaRequest {
+        source: MediaSource::Plain(mxc_uri!("mxc://localhost/clean").to_owned()),
+        format: MediaFormat::File,
+    };
+    let infected = MediaRequest {
+        source: MediaSource::Plain(mxc_uri!("mxc://localhost/infected").to_owned()),
+        format: MediaFormat::File,
+    };
+
+    // The media was cached before the content scanner was configured.
+    let store = client.store();
+    store.add_media_content(&clean, b"clean content".to_vec()).await.unwrap();
+    store.add_media_content(&infected, b"infected content".to_vec()).await.unwrap();
+
+    let media = client.media();
+
+    // The cached media is only returned once the scanner says it's clean.
+    let scanned = media.get_scanned_media_content(&clean, true).await.unwrap();
+    assert_eq!(scanned.verdict, ScanVerdict::Clean);
+    assert_eq!(scanned.content.unwrap(), b"clean content");
+
+    let scanned = media.get_scanned_media_content(&infected, true).await.unwrap();
+    assert_eq!(
+        scanned.verdict,
+        ScanVerdict::Infected { info: Some("***VIRUS DETECTED***".to_owned()) }
+    );
+    assert!(scanned.content.is_none());
+
+    // The verdicts are cached.
+    media.get_media_content(&clean, true).await.unwrap();
+    media.get_media_content(&infected, true).await.unwrap_err();
+}
+
+#[async_test]
+async fn scan_without_content_scanner_fails() {
+    let (builder, _server) = test_client_builder().await;
+    let client = builder.build().await.unwrap();
+
+    let source = MediaSource::Plain(mxc_uri!("mxc://localhost/media").to_owned());
+    let error = client.media().scan(&source).await.unwrap_err();
+    assert!(matches!(error, Error::ContentScanner(_)), "unexpected error: {error:?}");
+}
+
+#[async_test]
+async fn prefetched_media_are_cached() {
+    let (client, server) = logged_in_client().await;
+
+    // The first media is slow to download, and might not even be requested
+    // before its download is cancelled.
+    Mock::given(method("GET"))
+        .and(path("/_matrix/media/r0/download/localhost/a"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_bytes(b"a".to_vec())
+                .set_delay(Duration::from_secs(10)),
+        )
+        .expect(..=1)
+        .mount(&server)
+        .await;
+
+    for name in ["b", "c"] {
+        Mock::given(method("GET"))
+            .and(path(format!("/_matrix/media/r0/download/localhost/{name}")))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(name.as_bytes()))
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
+
+    let request = |name: &str| MediaRequest {
+        source: MediaSource::Plain(format!("mxc://localhost/{name}").into()),
+        format: MediaFormat::File,
+    };
+
+    // Only one media is downloaded at a time.
+    let prefetcher = client.media().prefetcher(1);
+    prefetcher.hint([request("a"), request("b")]);
+
+    let status = prefetcher.status();
+    assert_eq!(status.downloading, 1);
+    assert_eq!(status.queued, 1);
+
+    // The slow media isn't visible anymore, its download is cancelled.
+    prefetcher.hint([request("b"), request("c")]);
+
+    let mut subscriber = prefetcher.subscribe_to_status();
+    timeout(Duration::from_secs(5), async {
+        while !subscriber.get().is_idle() {
+            subscriber.next().await;
+        }
+    })
+    .await
+    .expect("the prefetcher should be idle");
+
+    let status = prefetcher.status();
+    assert_eq!(status.downloaded, 2);
+    assert_eq!(status.failed, 0);
+
+    let store = client.store();
+    assert!(store.get_media_content(&request("a")).await.unwrap().is_none());
+    assert_eq!(store.get_media_content(&request("b")).await.unwrap().unwrap(), b"b");
+    assert_eq!(store.get_media_content(&request("c")).await.unwrap().unwrap(), b"c");
+}
+
+#[async_test]
+async fn media_cleanup_index_is_flushed() {
+    let store = Arc::new(MemoryStore::new());
+    let (builder, server) = test_client_builder().await;
+    let client_builder = |builder: ClientBuilder| {
+        builder
+            .store_config(StoreConfig::new().state_store(store.clone()))
+            .request_config(RequestConfig::new().disable_retry())
+    };
+
+    for name in ["a", "b", "c", "d"] {
+        Mock::given(method("GET"))
+            .and(path(format!("/_matrix/media/r0/download/localhost/{name}")))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(name.as_bytes()))
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
+
+    let request = |name: &str| MediaRequest {
+        source: MediaSource::Plain(format!("mxc://localhost/{name}").into()),
+        format: MediaFormat::File,
+    };
+
+    // The index is written when the first media is cached, but not right after
+    // the second one.
+    let client = client_builder(builder).build().await.unwrap();
+    client.media().get_media_content(&request("a"), true).await.unwrap();
+    client.media().get_media_content(&request("b"), true).await.unwrap();
+    client.store_cleanup().flush().await.unwrap();
+
+    // The same goes for the second client, whose last media is written when it
+    // is dropped.
+    let client = client_builder(
+        Client::builder().homeserver_url(server.uri()).server_versions([MatrixVersion::V1_0]),
+    )
+    .build()
+    .await
+    .unwrap();
+    client.media().get_media_content(&request("c"), true).await.unwrap();
+    client.media().get_media_content(&request("d"), true).await.unwrap();
+    drop(client);
+    sleep(Duration::from_millis(50)).await;
+
+    let client = client_builder(
+        Client::builder().homeserver_url(server.uri()).server_versions([MatrixVersion::V1_0]),
+    )
+    .build()
+    .await
+    .unwrap();
+    let policy = CleanupPolicy { media_max_size: Some(0), ..Default::default() };
+    let report = client.store_cleanup().run(&policy).await.unwrap();
+    assert_eq!(report.removed_media, 4);
+}
@@ -7,6 +7,7 @@ use assert_matches::assert_matches;
 use assert_matches2::assert_let;
 use futures_util::StreamExt;
 use matrix_sdk::{
+    clock::TestClock,
     config::RequestConfig,
     executor::spawn,
     matrix_auth::{MatrixSession, MatrixSessionTokens},
@@ -22,7 +23,10 @@ use ruma::{
     assign, device_id, user_id,
 };
 use serde_json::json;
-use tokio::sync::{broadcast::error::TryRecvError, mpsc};
+use tokio::{
+    sync::{broadcast::error::TryRecvError, mpsc},
+    time::timeout,
+};
 use wiremock::{
     matchers::{body_partial_json, header, method, path},
     Mock, ResponseTemplate,
@@ -561,3 +565,126 @@ async fn refresh_token_handled_other_error() {
 
     client.whoami().await.unwrap_err();
 }
+
+#[async_test]
+async fn test_refresh_token_before_expiry() {
+    let clock = TestClock::new();
+    let (builder, server) = test_client_builder().await;
+    let client = builder
+        .request_config(RequestConfig::new().disable_retry())
+        .server_versions([MatrixVersion::V1_3])
+        .handle_refresh_tokens()
+        .clock(Arc::new(clock.clone()))
+        .build()
+        .await
+        .unwrap();
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/v3/login"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(&*test_json::LOGIN_WITH_REFRESH_TOKEN),
+        )
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/v3/refresh"))
+        .and(body_partial_json(json!({
+            "refresh_token": "zyx987",
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::REFRESH_TOKEN))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    client
+        .matrix_auth()
+        .login_username("example", "wordpass")
+        .request_refresh_token()
+        .send()
+        .await
+        .unwrap();
+    let mut session_changes = client.subscribe_to_session_changes();
+
+    // The refresh deadline is computed when logging in, so advancing the clock
+    // before the refresh task started waiting doesn't make it miss it.
+
+    // The access token expires after 5 days, it isn't refreshed an hour before.
+    let expires_in = Duration::from_millis(432_000_000);
+    clock.advance(expires_in - Duration::from_secs(60 * 60));
+    timeout(Duration::from_millis(100), session_changes.recv()).await.unwrap_err();
+    assert_eq!(client.access_token().as_deref(), Some("abc123"));
+
+    // It is refreshed shortly before it expires.
+    clock.advance(Duration::from_secs(60 * 60 - 10));
+    let change = timeout(Duration::from_secs(5), session_changes.recv()).await.unwrap().unwrap();
+    assert_eq!(change, SessionChange::TokensRefreshed);
+    assert_eq!(client.access_token().as_deref(), Some("5678"));
+}
+
+#[async_test]
+async fn test_refresh_token_before_expiry_retries_after_failure() {
+    let clock = TestClock::new();
+    let (builder, server) = test_client_builder().await;
+    let client = builder
+        .request_config(RequestConfig::new().disable_retry())
+        .server_versions([MatrixVersion::V1_3])
+        .handle_refresh_tokens()
+        .clock(Arc::new(clock.clone()))
+        .build()
+        .await
+        .unwrap();
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/v3/login"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(&*test_json::LOGIN_WITH_REFRESH_TOKEN),
+        )
+        .mount(&server)
+        .await;
+
+    // The first refresh fails, the next one succeeds.
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/v3/refresh"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/v3/refresh"))
+        .and(body_partial_json(json!({
+            "refresh_token": "zyx987",
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::REFRESH_TOKEN))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    client
+        .matrix_auth()
+        .login_username("example", "wordpass")
+        .request_refresh_token()
+        .send()
+        .await
+        .unwrap();
+    let mut session_changes = client.subscribe_to_session_changes();
+
+    // Reach the time of the refresh, then let the clock run until the retry
+    // succeeded.
+    clock.advance(Duration::from_millis(432_000_000) - Duration::from_secs(10));
+
+    let change = timeout(Duration::from_secs(10), async {
+        loop {
+            match timeout(Duration::from_millis(50), session_changes.recv()).await {
+                Ok(change) => break change.unwrap(),
+                Err(_) => clock.advance(Duration::from_secs(1)),
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(change, SessionChange::TokensRefreshed);
+    assert_eq!(client.access_token().as_deref(), Some("5678"));
+}
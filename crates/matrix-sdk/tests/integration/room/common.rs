@@ -1,7 +1,14 @@
 use std::time::Duration;
 
 use assert_matches2::assert_let;
-use matrix_sdk::{config::SyncSettings, room::RoomMember, DisplayName, RoomMemberships};
+use matrix_sdk::{
+    config::SyncSettings,
+    room::{
+        ExportFormat, ExportOptions, ExportRange, MediaGalleryFilter, RoomMember,
+        StateSnapshotOptions, ThreadsOptions,
+    },
+    DisplayName, RoomMemberships,
+};
 use matrix_sdk_test::{
     async_test, bulk_room_members, sync_timeline_event, test_json, JoinedRoomBuilder,
     StateTestEvent, SyncResponseBuilder, DEFAULT_TEST_ROOM_ID,
@@ -9,18 +16,19 @@ use matrix_sdk_test::{
 use ruma::{
     event_id,
     events::{
-        room::member::MembershipState, AnyStateEvent, AnySyncStateEvent, AnyTimelineEvent,
-        StateEventType,
+        room::{member::MembershipState, message::MessageType},
+        AnyStateEvent, AnySyncStateEvent, AnyTimelineEvent, StateEventType,
     },
-    room_id,
+    room_id, uint, MilliSecondsSinceUnixEpoch,
 };
+use serde::Deserialize;
 use serde_json::json;
 use wiremock::{
-    matchers::{header, method, path_regex},
+    matchers::{header, method, path_regex, query_param},
     Mock, ResponseTemplate,
 };
 
-use crate::{logged_in_client, mock_sync};
+use crate::{logged_in_client, mock_encryption_state, mock_sync};
 
 #[async_test]
 async fn user_presence() {
@@ -175,6 +183,91 @@ async fn test_state_event_getting() {
     );
 }
 
+#[async_test]
+async fn state_snapshot() {
+    let (client, server) = logged_in_client().await;
+
+    let note = |state_key: &str, body: &str| {
+        json!({
+            "type": "m.custom.note",
+            "sender": "@example:localhost",
+            "content": { "body": body },
+            "state_key": state_key,
+            "origin_server_ts": 1611853078727u64,
+            "event_id": format!("${state_key}"),
+        })
+    };
+    let sync = json!({
+        "next_batch": "1234",
+        "rooms": {
+            "join": {
+                *DEFAULT_TEST_ROOM_ID: {
+                    "state": {
+                        "events": [
+                            note("note.2", "Note 2"),
+                            {
+                                "type": "m.room.encryption",
+                                "sender": "@example:localhost",
+                                "content": { "algorithm": "m.megolm.v1.aes-sha2" },
+                                "state_key": "",
+                                "origin_server_ts": 1586437448151u64,
+                                "event_id": "$encryption",
+                            },
+                            note("note.1", "Note 1"),
+                            note("note.3", "Note 3"),
+                        ]
+                    }
+                }
+            }
+        }
+    });
+
+    mock_sync(&server, sync, None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+
+    // All the state, sorted by type and state key.
+    let snapshot = room.state_snapshot_raw(StateSnapshotOptions::new()).await.unwrap();
+    let keys: Vec<_> = snapshot
+        .entries()
+        .iter()
+        .map(|entry| (entry.event_type.to_string(), entry.state_key.as_str()))
+        .collect();
+    assert_eq!(
+        keys,
+        [
+            ("m.custom.note".to_owned(), "note.1"),
+            ("m.custom.note".to_owned(), "note.2"),
+            ("m.custom.note".to_owned(), "note.3"),
+            ("m.room.encryption".to_owned(), ""),
+        ]
+    );
+
+    // Only the notes, deserialized.
+    #[derive(Deserialize)]
+    struct Note {
+        content: NoteContent,
+    }
+    #[derive(Deserialize)]
+    struct NoteContent {
+        body: String,
+    }
+
+    let snapshot = room
+        .state_snapshot::<Note>(StateSnapshotOptions::new().type_prefix("m.custom."))
+        .await
+        .unwrap();
+    assert_eq!(snapshot.len(), 3);
+
+    let pages: Vec<Vec<_>> = snapshot
+        .pages(2)
+        .map(|page| page.iter().map(|entry| entry.event.content.body.as_str()).collect())
+        .collect();
+    assert_eq!(pages, [vec!["Note 1", "Note 2"], vec!["Note 3"]]);
+    assert_eq!(snapshot.page(1, 2).len(), 1);
+    assert!(snapshot.page(2, 2).is_empty());
+}
+
 #[async_test]
 async fn room_route() {
     let (client, server) = logged_in_client().await;
@@ -553,3 +646,519 @@ async fn event() {
     assert!(push_actions.iter().any(|a| a.is_highlight()));
     assert!(push_actions.iter().any(|a| a.should_notify()));
 }
+
+#[async_test]
+async fn media_gallery() {
+    let (client, server) = logged_in_client().await;
+    let room_id = &*DEFAULT_TEST_ROOM_ID;
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    mock_encryption_state(&server, false).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+    server.reset().await;
+
+    let room = client.get_room(room_id).unwrap();
+    let gallery = room.media_gallery(MediaGalleryFilter::All);
+    assert!(gallery.subscribe().0.is_empty());
+
+    mock_encryption_state(&server, false).await;
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .and(query_param("dir", "b"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "start": "t392-516_47314_0_7_1_1_1_11444_1",
+            "chunk": [
+                {
+                    "type": "m.room.message",
+                    "event_id": "$image",
+                    "room_id": room_id,
+                    "sender": "@bob:localhost",
+                    "origin_server_ts": 152039280,
+                    "content": {
+                        "msgtype": "m.image",
+                        "body": "cat.jpg",
+                        "url": "mxc://localhost/cat",
+                    },
+                },
+            ],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    assert!(gallery.paginate_backwards(10).await.unwrap());
+
+    let (items, _) = gallery.subscribe();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0].event_id, "$image");
+    assert_let!(MessageType::Image(_) = &items[0].content);
+
+    // New media events from sync are added to the front, the other events are
+    // ignored.
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_timeline_event(sync_timeline_event!({
+                "type": "m.room.message",
+                "event_id": "$file",
+                "sender": "@bob:localhost",
+                "origin_server_ts": 152039290,
+                "content": {
+                    "msgtype": "m.file",
+                    "body": "report.pdf",
+                    "url": "mxc://localhost/report",
+                },
+            }))
+            .add_timeline_event(sync_timeline_event!({
+                "type": "m.room.message",
+                "event_id": "$text",
+                "sender": "@bob:localhost",
+                "origin_server_ts": 152039291,
+                "content": {
+                    "msgtype": "m.text",
+                    "body": "Here is the report",
+                },
+            })),
+    );
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+    server.reset().await;
+
+    let (items, _) = gallery.subscribe();
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[0].event_id, "$file");
+    assert_eq!(items[1].event_id, "$image");
+
+    // Redacted media events are removed.
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_timeline_event(
+        sync_timeline_event!({
+            "type": "m.room.redaction",
+            "event_id": "$redaction",
+            "redacts": "$image",
+            "sender": "@bob:localhost",
+            "origin_server_ts": 152039300,
+            "content": {},
+        }),
+    ));
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    let (items, _) = gallery.subscribe();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0].event_id, "$file");
+}
+
+#[async_test]
+async fn pinned_events() {
+    let (client, server) = logged_in_client().await;
+    let room_id = &*DEFAULT_TEST_ROOM_ID;
+
+    let pinned_events_event = |pinned: serde_json::Value| {
+        json!({
+            "type": "m.room.pinned_events",
+            "event_id": format!("$pinned_{}", pinned.as_array().unwrap().len()),
+            "state_key": "",
+            "sender": "@bob:localhost",
+            "origin_server_ts": 152039280,
+            "content": { "pinned": pinned },
+        })
+    };
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_state_event(StateTestEvent::Custom(pinned_events_event(json!(["$a", "$b"])))),
+    );
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+    server.reset().await;
+
+    for event_id in ["$a", "$b"] {
+        Mock::given(method("GET"))
+            .and(path_regex(format!(r"^/_matrix/client/r0/rooms/.*/event/\{event_id}$")))
+            .and(header("authorization", "Bearer 1234"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "type": "m.room.message",
+                "event_id": event_id,
+                "room_id": room_id,
+                "sender": "@bob:localhost",
+                "origin_server_ts": 152039280,
+                "content": {
+                    "msgtype": "m.text",
+                    "body": "Read the rules",
+                },
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
+
+    let room = client.get_room(room_id).unwrap();
+    assert_eq!(room.pinned_event_ids().await.unwrap(), ["$a", "$b"]);
+
+    let pinned = room.pinned_events();
+    assert!(!pinned.load_more(1).await.unwrap());
+    let (items, _) = pinned.subscribe();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0].event_id, "$a");
+
+    assert!(pinned.load_more(5).await.unwrap());
+    let (items, _) = pinned.subscribe();
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[1].event_id, "$b");
+
+    // Once an event is unpinned, it is removed without fetching the other one
+    // again.
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id)
+            .add_timeline_event(sync_timeline_event!(pinned_events_event(json!(["$b"])))),
+    );
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    let (items, _) = pinned.subscribe();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0].event_id, "$b");
+
+    // The fetched events were kept in the state store, so a new list doesn't
+    // fetch them again.
+    drop(pinned);
+    let pinned = room.pinned_events();
+    assert!(pinned.load_more(5).await.unwrap());
+    let (items, _) = pinned.subscribe();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0].event_id, "$b");
+    assert_let!(AnyTimelineEvent::MessageLike(event) = items[0].event.event.deserialize().unwrap());
+    assert_eq!(event.event_id(), "$b");
+}
+
+#[async_test]
+async fn pinned_events_are_redacted_in_the_state_store() {
+    let (client, server) = logged_in_client().await;
+    let room_id = &*DEFAULT_TEST_ROOM_ID;
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_state_event(
+        StateTestEvent::Custom(json!({
+            "type": "m.room.pinned_events",
+            "event_id": "$pinned",
+            "state_key": "",
+            "sender": "@bob:localhost",
+            "origin_server_ts": 152039280,
+            "content": { "pinned": ["$a", "$b"] },
+        })),
+    ));
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+    server.reset().await;
+
+    // The events are only fetched once, the redacted ones are read from the
+    // state store afterwards.
+    for event_id in ["$a", "$b"] {
+        Mock::given(method("GET"))
+            .and(path_regex(format!(r"^/_matrix/client/r0/rooms/.*/event/\{event_id}$")))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "type": "m.room.message",
+                "event_id": event_id,
+                "room_id": room_id,
+                "sender": "@bob:localhost",
+                "origin_server_ts": 152039280,
+                "content": {
+                    "msgtype": "m.text",
+                    "body": "Read the rules",
+                },
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
+
+    let room = client.get_room(room_id).unwrap();
+    assert!(room.pinned_events().load_more(5).await.unwrap());
+
+    // `$a` is redacted by someone else.
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_timeline_event(
+        sync_timeline_event!({
+            "type": "m.room.redaction",
+            "event_id": "$redaction_a",
+            "sender": "@bob:localhost",
+            "origin_server_ts": 152039281,
+            "redacts": "$a",
+            "content": { "redacts": "$a" },
+        }),
+    ));
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    // `$b` is redacted by this client.
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/redact/\$b/.*?"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .mount(&server)
+        .await;
+    room.redact(event_id!("$b"), None, None).await.unwrap();
+
+    let pinned = room.pinned_events();
+    assert!(pinned.load_more(5).await.unwrap());
+    let (items, _) = pinned.subscribe();
+    assert_eq!(items.len(), 2);
+
+    for (item, redaction_id) in items.iter().zip(["$redaction_a", "$h29iv0s8:example.com"]) {
+        let event = &item.event.event;
+        assert_eq!(event.get_field::<serde_json::Value>("content").unwrap().unwrap(), json!({}));
+        let unsigned = event.get_field::<serde_json::Value>("unsigned").unwrap().unwrap();
+        assert_eq!(unsigned["redacted_because"]["event_id"], redaction_id);
+    }
+}
+
+#[async_test]
+async fn export_history() {
+    let (client, server) = logged_in_client().await;
+    let room_id = &*DEFAULT_TEST_ROOM_ID;
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    let message = |event_id: &str, ts: u64, content| {
+        json!({
+            "type": "m.room.message",
+            "event_id": event_id,
+            "room_id": room_id,
+            "sender": "@bob:localhost",
+            "origin_server_ts": ts,
+            "content": content,
+        })
+    };
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .and(query_param("dir", "f"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "start": "t392-516_47314_0_7_1_1_1_11444_1",
+            "chunk": [
+                message("$old", 1_000, json!({ "msgtype": "m.text", "body": "Too old" })),
+                message("$hello", 86_400_000, json!({ "msgtype": "m.text", "body": "Hello\nworld" })),
+                {
+                    "type": "m.reaction",
+                    "event_id": "$reaction",
+                    "room_id": room_id,
+                    "sender": "@bob:localhost",
+                    "origin_server_ts": 86_401_000,
+                    "content": {
+                        "m.relates_to": {
+                            "rel_type": "m.annotation",
+                            "event_id": "$hello",
+                            "key": "👍",
+                        },
+                    },
+                },
+                message("$image", 86_402_000, json!({
+                    "msgtype": "m.image",
+                    "body": "cat.jpg",
+                    "url": "mxc://localhost/cat",
+                })),
+                message("$new", 172_800_000, json!({ "msgtype": "m.text", "body": "Too new" })),
+            ],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let room = client.get_room(room_id).unwrap();
+    let range = ExportRange::between(
+        MilliSecondsSinceUnixEpoch(uint!(86_400_000)),
+        MilliSecondsSinceUnixEpoch(uint!(86_500_000)),
+    );
+
+    let mut transcript = Vec::new();
+    let progress = room
+        .export_history(ExportFormat::PlainText, range, ExportOptions::new())
+        .write_to(&mut transcript)
+        .await
+        .unwrap();
+
+    // Only the messages in the range are exported, and the export stops at
+    // the first event after the range.
+    assert_eq!(
+        String::from_utf8(transcript).unwrap(),
+        "[1970-01-02 00:00:00] @bob:localhost: Hello\n    world\n\
+         [1970-01-02 00:00:02] @bob:localhost: [image: cat.jpg]\n"
+    );
+    assert_eq!(progress.fetched_events, 5);
+    assert_eq!(progress.exported_events, 2);
+    assert_eq!(progress.exported_media, 0);
+}
+
+#[async_test]
+async fn export_history_html_with_media() {
+    let (client, server) = logged_in_client().await;
+    let room_id = &*DEFAULT_TEST_ROOM_ID;
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(room_id));
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    let message = |event_id: &str, ts: u64, content| {
+        json!({
+            "type": "m.room.message",
+            "event_id": event_id,
+            "room_id": room_id,
+            "sender": "@bob:localhost",
+            "origin_server_ts": ts,
+            "content": content,
+        })
+    };
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .and(query_param("dir", "f"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "start": "t392-516_47314_0_7_1_1_1_11444_1",
+            "chunk": [
+                message("$image", 1_000, json!({
+                    "msgtype": "m.image",
+                    "body": "cat.png",
+                    "url": "mxc://localhost/cat",
+                    "info": { "mimetype": "image/png\" onerror=\"alert(1)" },
+                })),
+                // The size is not announced, but the media is too big.
+                message("$file", 2_000, json!({
+                    "msgtype": "m.file",
+                    "body": "big.bin",
+                    "url": "mxc://localhost/big",
+                })),
+            ],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/(media/r0|media/v3|client/v1/media)/download/localhost/cat"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(b"cat".to_vec(), "image/png"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/(media/r0|media/v3|client/v1/media)/download/localhost/big"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_raw(vec![0; 64], "application/octet-stream"),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let room = client.get_room(room_id).unwrap();
+    let options = ExportOptions::new().include_media(true).max_media_size(uint!(16));
+
+    let mut transcript = Vec::new();
+    let progress = room
+        .export_history(ExportFormat::Html, ExportRange::all(), options)
+        .write_to(&mut transcript)
+        .await
+        .unwrap();
+    let transcript = String::from_utf8(transcript).unwrap();
+
+    // The invalid mimetype is replaced, so it can't inject attributes.
+    assert!(!transcript.contains("onerror"));
+    assert!(transcript
+        .contains("<img src=\"data:application/octet-stream;base64,Y2F0\" alt=\"cat.png\">"));
+    // The media that is too big is not bundled.
+    assert!(transcript.contains("<em>[big.bin]</em>"));
+    assert_eq!(progress.exported_events, 2);
+    assert_eq!(progress.exported_media, 1);
+}
+
+#[async_test]
+async fn threads_unread() {
+    let (client, server) = logged_in_client().await;
+    let room_id = &*DEFAULT_TEST_ROOM_ID;
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(
+        JoinedRoomBuilder::new(room_id).set_unread_thread_notifications_count(json!({
+            "$root_mention": {
+                "highlight_count": 1,
+                "notification_count": 1,
+            },
+        })),
+    );
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    let thread = |root_id: &str, reply_id: &str, reply_sender: &str| {
+        json!({
+            "type": "m.room.message",
+            "event_id": root_id,
+            "room_id": room_id,
+            "sender": "@bob:localhost",
+            "origin_server_ts": 1_000,
+            "content": { "msgtype": "m.text", "body": "Thread root" },
+            "unsigned": {
+                "m.relations": {
+                    "m.thread": {
+                        "latest_event": {
+                            "type": "m.room.message",
+                            "event_id": reply_id,
+                            "room_id": room_id,
+                            "sender": reply_sender,
+                            "origin_server_ts": 2_000,
+                            "content": {
+                                "msgtype": "m.text",
+                                "body": "Reply",
+                                "m.relates_to": { "rel_type": "m.thread", "event_id": root_id },
+                            },
+                        },
+                        "count": 1,
+                        "current_user_participated": true,
+                    },
+                },
+            },
+        })
+    };
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/(v1|unstable/org.matrix.msc3856)/rooms/.*/threads$"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [
+                thread("$root_own", "$reply_own", "@example:localhost"),
+                thread("$root_mention", "$reply_mention", "@bob:localhost"),
+                thread("$root_other", "$reply_other", "@bob:localhost"),
+            ],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let room = client.get_room(room_id).unwrap();
+    let threads = room.threads(ThreadsOptions::all()).await.unwrap();
+    let unread = threads
+        .chunk
+        .iter()
+        .map(|thread| (thread.root_event_id().unwrap().to_string(), thread.is_unread))
+        .collect::<Vec<_>>();
+
+    // Our own reply doesn't make the thread unread, even without a receipt.
+    assert_eq!(
+        unread,
+        [
+            ("$root_own".to_owned(), false),
+            ("$root_mention".to_owned(), true),
+            ("$root_other".to_owned(), true),
+        ]
+    );
+    assert_eq!(threads.chunk[1].notification_counts.highlight_count, 1);
+}
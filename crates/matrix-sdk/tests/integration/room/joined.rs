@@ -1,29 +1,60 @@
-use std::time::Duration;
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
-use futures_util::future::join_all;
+use assert_matches::assert_matches;
+use assert_matches2::assert_let;
+use futures_util::{future::join_all, pin_mut, StreamExt};
 use matrix_sdk::{
     attachment::{
         AttachmentConfig, AttachmentInfo, BaseImageInfo, BaseThumbnailInfo, BaseVideoInfo,
         Thumbnail,
     },
-    config::SyncSettings,
-    room::{Receipts, ReportedContentScore},
+    clock::TestClock,
+    config::{RequestConfig, SyncSettings},
+    deserialized_responses::SyncOrStrippedState,
+    matrix_auth::{MatrixSession, MatrixSessionTokens},
+    room::{
+        DelayedEventAction, InviteOutcome, LeaveAndForgetOptions, PinnedEventsError, Receipts,
+        ReportedContentScore, RoomUpgradeError, RoomUpgradeOptions, StateBatchValidationError,
+        StateEventOutcome, StateEventToSend,
+    },
+    scheduled_messages::ScheduledMessageError,
+    Error, RetryKind, RoomPrivacyOverrides,
+};
+use matrix_sdk_base::{RoomInfoChangeReasons, RoomState, SessionMeta};
+use matrix_sdk_test::{
+    async_test, test_json, EphemeralTestEvent, JoinedRoomBuilder, RoomAccountDataTestEvent,
+    StateTestEvent, SyncResponseBuilder, DEFAULT_TEST_ROOM_ID,
 };
-use matrix_sdk_base::RoomState;
-use matrix_sdk_test::{async_test, test_json, DEFAULT_TEST_ROOM_ID};
 use ruma::{
     api::client::{membership::Invite3pidInit, receipt::create_receipt::v3::ReceiptType},
-    assign, event_id,
-    events::{receipt::ReceiptThread, room::message::RoomMessageEventContent},
-    int, mxc_uri, owned_event_id, thirdparty, uint, user_id, TransactionId,
+    assign, device_id, event_id,
+    events::{
+        receipt::ReceiptThread,
+        room::{
+            guest_access::{GuestAccess, RoomGuestAccessEventContent},
+            history_visibility::HistoryVisibility,
+            message::RoomMessageEventContent,
+            name::RoomNameEventContent,
+            topic::RoomTopicEventContent,
+        },
+        tag::TagName,
+        StateEventType, SyncStateEvent,
+    },
+    int, mxc_uri, owned_event_id, room_id, thirdparty, uint, user_id, RoomVersionId, TransactionId,
 };
 use serde_json::json;
+use tokio::time::timeout;
 use wiremock::{
-    matchers::{body_json, body_partial_json, header, method, path, path_regex},
+    matchers::{body_json, body_partial_json, header, method, path, path_regex, query_param},
     Mock, ResponseTemplate,
 };
 
-use crate::{logged_in_client, mock_encryption_state, mock_sync, synced_client};
+use crate::{
+    logged_in_client, mock_encryption_state, mock_sync, synced_client, test_client_builder,
+};
 
 #[async_test]
 async fn invite_user_by_id() {
@@ -80,6 +111,133 @@ async fn invite_user_by_3pid() {
     .unwrap();
 }
 
+#[async_test]
+async fn invite_by_search() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("GET"))
+        .and(path("/_matrix/client/r0/profile/@bob:localhost"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "displayname": "Bob" })))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/user_directory/search"))
+        .and(body_partial_json(json!({ "search_term": "carol" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "limited": false,
+            "results": [{ "user_id": "@carol:localhost", "display_name": "Carol" }],
+        })))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/user_directory/search"))
+        .and(body_partial_json(json!({ "search_term": "nobody" })))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({ "limited": false, "results": [] })),
+        )
+        .mount(&server)
+        .await;
+
+    // The first invite is rate-limited, and must be retried.
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/invite$"))
+        .respond_with(ResponseTemplate::new(429).set_body_json(json!({
+            "errcode": "M_LIMIT_EXCEEDED",
+            "error": "Too many requests",
+            "retry_after_ms": 1,
+        })))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/invite$"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+
+    let reports = room.invite_by_search(["@bob:localhost", "carol", "nobody"]).await;
+    assert_eq!(reports.len(), 3);
+
+    assert_eq!(reports[0].search_term, "@bob:localhost");
+    assert_let!(InviteOutcome::Invited(user_id) = &reports[0].outcome);
+    assert_eq!(user_id, user_id!("@bob:localhost"));
+
+    assert_let!(InviteOutcome::Invited(user_id) = &reports[1].outcome);
+    assert_eq!(user_id, user_id!("@carol:localhost"));
+
+    assert_matches!(reports[2].outcome, InviteOutcome::NotFound);
+}
+
+#[async_test]
+async fn invite_by_search_reports_search_errors() {
+    let (client, server) = logged_in_client().await;
+
+    // The first search is rate-limited, and must be retried.
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/user_directory/search"))
+        .and(body_partial_json(json!({ "search_term": "carol" })))
+        .respond_with(ResponseTemplate::new(429).set_body_json(json!({
+            "errcode": "M_LIMIT_EXCEEDED",
+            "error": "Too many requests",
+            "retry_after_ms": 1,
+        })))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/user_directory/search"))
+        .and(body_partial_json(json!({ "search_term": "carol" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "limited": false,
+            "results": [{ "user_id": "@carol:localhost", "display_name": "Carol" }],
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/client/r0/user_directory/search"))
+        .and(body_partial_json(json!({ "search_term": "dave" })))
+        .respond_with(ResponseTemplate::new(500).set_body_json(json!({
+            "errcode": "M_UNKNOWN",
+            "error": "Internal server error",
+        })))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/invite$"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+
+    let reports = room.invite_by_search(["carol", "dave"]).await;
+    assert_eq!(reports.len(), 2);
+
+    assert_let!(InviteOutcome::Invited(user_id) = &reports[0].outcome);
+    assert_eq!(user_id, user_id!("@carol:localhost"));
+
+    // The error isn't mistaken for the user not being found.
+    assert_let!(InviteOutcome::SearchFailed(error) = &reports[1].outcome);
+    assert_eq!(error.as_client_api_error().unwrap().status_code, 500);
+}
+
 #[async_test]
 async fn leave_room() -> Result<(), anyhow::Error> {
     let (client, server) = logged_in_client().await;
@@ -223,37 +381,51 @@ async fn send_multiple_receipts() {
 }
 
 #[async_test]
-async fn typing_notice() {
+async fn marked_unread_is_cleared_by_read_receipt() {
     let (client, server) = logged_in_client().await;
+    let room_id = &*DEFAULT_TEST_ROOM_ID;
 
-    Mock::given(method("PUT"))
-        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/typing"))
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_account_data(
+        RoomAccountDataTestEvent::Custom(json!({
+            "type": "m.marked_unread",
+            "content": { "unread": true },
+        })),
+    ));
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    let room = client.get_room(room_id).unwrap();
+    assert!(room.is_marked_unread());
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/receipt"))
         .and(header("authorization", "Bearer 1234"))
         .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
         .mount(&server)
         .await;
 
-    mock_sync(&server, &*test_json::SYNC, None).await;
-
-    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
-
-    let _response = client.sync_once(sync_settings).await.unwrap();
-
-    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/user/.*/rooms/.*/account_data/m.marked_unread$"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(body_json(json!({ "unread": false })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
+        .mount(&server)
+        .await;
 
-    room.typing_notice(true).await.unwrap();
+    let event_id = event_id!("$xxxxxx:example.org").to_owned();
+    room.send_single_receipt(ReceiptType::Read, ReceiptThread::Unthreaded, event_id).await.unwrap();
 }
 
 #[async_test]
-async fn room_state_event_send() {
-    use ruma::events::room::member::{MembershipState, RoomMemberEventContent};
-
+async fn typing_notice() {
     let (client, server) = logged_in_client().await;
 
     Mock::given(method("PUT"))
-        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/.*"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/typing"))
         .and(header("authorization", "Bearer 1234"))
-        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
         .mount(&server)
         .await;
 
@@ -265,154 +437,505 @@ async fn room_state_event_send() {
 
     let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
 
-    let avatar_url = mxc_uri!("mxc://example.org/avA7ar");
-    let member_event = assign!(RoomMemberEventContent::new(MembershipState::Join), {
-        avatar_url: Some(avatar_url.to_owned())
-    });
-    let response =
-        room.send_state_event_for_key(user_id!("@foo:bar.com"), member_event).await.unwrap();
-    assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id);
+    room.typing_notice(true).await.unwrap();
 }
 
 #[async_test]
-async fn room_message_send() {
+async fn privacy_overrides_suppress_receipts_and_typing() {
     let (client, server) = logged_in_client().await;
+    let room_id = &*DEFAULT_TEST_ROOM_ID;
+
+    // The overrides are received from the room account data.
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_account_data(
+        RoomAccountDataTestEvent::Custom(json!({
+            "type": "org.matrix.rust-sdk.privacy_overrides",
+            "content": { "suppress_read_receipts": true },
+        })),
+    ));
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    let room = client.get_room(room_id).unwrap();
+    assert!(room.privacy_overrides().suppress_read_receipts);
+    assert!(!room.privacy_overrides().suppress_typing_notifications);
+
+    // The public read receipt is sent as a private one.
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/receipt/m\.read\.private/"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let event_id = event_id!("$xxxxxx:example.org").to_owned();
+    room.send_single_receipt(ReceiptType::Read, ReceiptThread::Unthreaded, event_id).await.unwrap();
 
+    // Suppress the typing notifications locally only, nothing is sent.
     Mock::given(method("PUT"))
-        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
-        .and(header("authorization", "Bearer 1234"))
-        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/typing"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(0)
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/user/.*/rooms/.*/account_data/"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(0)
         .mount(&server)
         .await;
 
-    mock_sync(&server, &*test_json::SYNC, None).await;
-    mock_encryption_state(&server, false).await;
+    let overrides =
+        RoomPrivacyOverrides { suppress_read_receipts: true, suppress_typing_notifications: true };
+    room.set_privacy_overrides(overrides, false).await.unwrap();
+    assert_eq!(room.privacy_overrides(), overrides);
+
+    room.typing_notice(true).await.unwrap();
+}
+
+#[async_test]
+async fn send_and_receive_ephemeral_events() {
+    let (client, server) = logged_in_client().await;
 
+    mock_sync(&server, &*test_json::SYNC, None).await;
     let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+    let _response = client.sync_once(sync_settings.clone()).await.unwrap();
+    server.reset().await;
 
-    let _response = client.sync_once(sync_settings).await.unwrap();
+    Mock::given(method("PUT"))
+        .and(path_regex(
+            r"^/_matrix/client/unstable/org.matrix.msc2477/rooms/.*/ephemeral/org.example.cursor/.*",
+        ))
+        .and(header("authorization", "Bearer 1234"))
+        .and(body_json(json!({ "x": 12, "y": 34 })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
+        .mount(&server)
+        .await;
 
     let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+    let mut ephemeral_events = Box::pin(room.subscribe_to_ephemeral_events("org.example.cursor"));
+
+    room.send_ephemeral_raw("org.example.cursor", json!({ "x": 12, "y": 34 })).await.unwrap();
+
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_joined_room(
+        JoinedRoomBuilder::new(&DEFAULT_TEST_ROOM_ID)
+            .add_ephemeral_event(EphemeralTestEvent::Typing)
+            .add_ephemeral_event(EphemeralTestEvent::Custom(json!({
+                "type": "org.example.cursor",
+                "content": { "x": 56, "y": 78 },
+            }))),
+    );
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(sync_settings).await.unwrap();
 
-    let content = RoomMessageEventContent::text_plain("Hello world");
-    let txn_id = TransactionId::new();
-    let response = room.send(content).with_transaction_id(&txn_id).await.unwrap();
-
-    assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id)
+    // Only the event of the subscribed type is received.
+    let event = timeout(Duration::from_secs(1), ephemeral_events.next()).await.unwrap().unwrap();
+    assert_eq!(event.get_field::<String>("type").unwrap().as_deref(), Some("org.example.cursor"));
+    assert_eq!(
+        event.get_field::<serde_json::Value>("content").unwrap(),
+        Some(json!({ "x": 56, "y": 78 }))
+    );
+    assert!(timeout(Duration::from_millis(100), ephemeral_events.next()).await.is_err());
 }
 
 #[async_test]
-async fn room_attachment_send() {
+async fn send_and_update_delayed_state_event() {
     let (client, server) = logged_in_client().await;
 
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+    let _response = client.sync_once(sync_settings).await.unwrap();
+
     Mock::given(method("PUT"))
-        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(path_regex(r"^/_matrix/client/v3/rooms/.*/state/org.matrix.msc3401.call.member/.*"))
+        .and(query_param("org.matrix.msc4140.delay", "5000"))
         .and(header("authorization", "Bearer 1234"))
-        .and(body_partial_json(json!({
-            "info": {
-                "mimetype": "image/jpeg",
-            }
-        })))
-        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .and(body_json(json!({})))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "delay_id": "delay-1" })))
+        .expect(1)
         .mount(&server)
         .await;
 
     Mock::given(method("POST"))
-        .and(path("/_matrix/media/r0/upload"))
+        .and(path("/_matrix/client/unstable/org.matrix.msc4140/delayed_events/delay-1"))
         .and(header("authorization", "Bearer 1234"))
-        .and(header("content-type", "image/jpeg"))
-        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
-          "content_uri": "mxc://example.com/AQwafuaFswefuhsfAFAgsw"
-        })))
+        .and(body_json(json!({ "action": "restart" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
         .mount(&server)
         .await;
 
-    mock_sync(&server, &*test_json::SYNC, None).await;
-    mock_encryption_state(&server, false).await;
-
-    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
-
-    let _response = client.sync_once(sync_settings).await.unwrap();
-
     let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
 
-    let response = room
-        .send_attachment(
-            "image",
-            &mime::IMAGE_JPEG,
-            b"Hello world".to_vec(),
-            AttachmentConfig::new(),
+    let delay_id = room
+        .send_state_event_raw_delayed(
+            "org.matrix.msc3401.call.member",
+            "@example:localhost",
+            json!({}),
+            Duration::from_secs(5),
         )
         .await
         .unwrap();
+    assert_eq!(delay_id, "delay-1");
 
-    assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id)
+    room.update_delayed_event(&delay_id, DelayedEventAction::Restart).await.unwrap();
+}
+
+async fn mock_room_versions_capabilities(server: &wiremock::MockServer) {
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/.*/capabilities"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "capabilities": {
+                "m.room_versions": {
+                    "default": "10",
+                    "available": {
+                        "1": "stable",
+                        "10": "stable",
+                        "org.example.experimental": "unstable",
+                    },
+                },
+            },
+        })))
+        .mount(server)
+        .await;
 }
 
 #[async_test]
-async fn room_attachment_send_info() {
+async fn upgrade_room_and_reinvite_members() {
     let (client, server) = logged_in_client().await;
 
-    Mock::given(method("PUT"))
-        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+    let _response = client.sync_once(sync_settings).await.unwrap();
+
+    mock_room_versions_capabilities(&server).await;
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/.*/rooms/.*/upgrade"))
         .and(header("authorization", "Bearer 1234"))
-        .and(body_partial_json(json!({
-            "info": {
-                "mimetype": "image/jpeg",
-                "h": 600,
-                "w": 800,
-            }
-        })))
-        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .and(body_json(json!({ "new_version": "10" })))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({ "replacement_room": "!new:localhost" })),
+        )
+        .expect(1)
         .mount(&server)
         .await;
 
-    Mock::given(method("POST"))
-        .and(path("/_matrix/media/r0/upload"))
-        .and(header("authorization", "Bearer 1234"))
-        .and(header("content-type", "image/jpeg"))
+    // The member list of the room wasn't synced, so it is fetched.
+    let member_event = |user_id: &str, membership: &str| {
+        json!({
+            "content": { "membership": membership },
+            "event_id": format!("${user_id}"),
+            "origin_server_ts": 151800140,
+            "sender": user_id,
+            "state_key": user_id,
+            "type": "m.room.member",
+        })
+    };
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/members"))
         .respond_with(ResponseTemplate::new(200).set_body_json(json!({
-          "content_uri": "mxc://example.com/AQwafuaFswefuhsfAFAgsw"
+            "chunk": [
+                member_event("@example:localhost", "join"),
+                member_event("@example2:localhost", "join"),
+                member_event("@lazy:localhost", "invite"),
+                member_event("@gone:localhost", "leave"),
+            ]
         })))
+        .expect(1)
         .mount(&server)
         .await;
 
-    mock_sync(&server, &*test_json::SYNC, None).await;
-    mock_encryption_state(&server, false).await;
-
-    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
-
-    let _response = client.sync_once(sync_settings).await.unwrap();
+    for user_id in ["@example2:localhost", "@lazy:localhost"] {
+        Mock::given(method("POST"))
+            .and(path_regex(r"^/_matrix/client/.*/rooms/.*/invite"))
+            .and(body_json(json!({ "user_id": user_id })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
 
     let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
-
-    let config = AttachmentConfig::new().info(AttachmentInfo::Image(BaseImageInfo {
-        height: Some(uint!(600)),
-        width: Some(uint!(800)),
-        size: None,
-        blurhash: None,
-    }));
-
-    let response = room
-        .send_attachment("image", &mime::IMAGE_JPEG, b"Hello world".to_vec(), config)
+    let report = room
+        .upgrade(RoomVersionId::V10, RoomUpgradeOptions::new().reinvite_members())
         .await
         .unwrap();
 
-    assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id)
+    assert_eq!(report.replacement_room_id, "!new:localhost");
+    let mut reinvited_users = report.reinvited_users;
+    reinvited_users.sort();
+    assert_eq!(
+        reinvited_users,
+        vec![user_id!("@example2:localhost").to_owned(), user_id!("@lazy:localhost").to_owned()]
+    );
+    assert!(report.errors.is_empty());
 }
 
 #[async_test]
-async fn room_attachment_send_wrong_info() {
+async fn upgrade_room_and_copy_widgets() {
     let (client, server) = logged_in_client().await;
 
+    let widget_event = |event_type: &str, state_key: &str, content: serde_json::Value| {
+        StateTestEvent::Custom(json!({
+            "content": content,
+            "event_id": format!("${state_key}"),
+            "origin_server_ts": 151800140,
+            "sender": "@example:localhost",
+            "state_key": state_key,
+            "type": event_type,
+        }))
+    };
+
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_joined_room(
+        JoinedRoomBuilder::new(&DEFAULT_TEST_ROOM_ID)
+            .add_state_event(StateTestEvent::PowerLevels)
+            .add_state_event(widget_event(
+                "m.widget",
+                "stable",
+                json!({ "type": "m.custom", "url": "https://widget.localhost" }),
+            ))
+            .add_state_event(widget_event(
+                "im.vector.modular.widgets",
+                "legacy",
+                json!({ "type": "m.custom", "url": "https://legacy.localhost" }),
+            ))
+            // A removed widget.
+            .add_state_event(widget_event("m.widget", "removed", json!({}))),
+    );
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    let _response = client.sync_once(SyncSettings::default()).await.unwrap();
+
+    mock_room_versions_capabilities(&server).await;
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/.*/rooms/.*/upgrade"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({ "replacement_room": "!new:localhost" })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    for (event_type, state_key) in [("m.widget", "stable"), ("im.vector.modular.widgets", "legacy")]
+    {
+        Mock::given(method("PUT"))
+            .and(path_regex(format!(
+                r"^/_matrix/client/r0/rooms/.*/state/{event_type}/{state_key}$"
+            )))
+            .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
+
     Mock::given(method("PUT"))
-        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m.widget/removed$"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+    let report =
+        room.upgrade(RoomVersionId::V10, RoomUpgradeOptions::new().copy_widgets()).await.unwrap();
+
+    assert_eq!(report.copied_widgets, 2);
+    assert!(report.errors.is_empty());
+}
+
+#[async_test]
+async fn upgrade_room_to_unstable_version_is_refused() {
+    let (client, server) = logged_in_client().await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+    let _response = client.sync_once(sync_settings).await.unwrap();
+
+    mock_room_versions_capabilities(&server).await;
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/.*/rooms/.*/upgrade"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({ "replacement_room": "!new:localhost" })),
+        )
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+
+    let version = RoomVersionId::try_from("org.example.experimental").unwrap();
+    let error = room.upgrade(version.clone(), RoomUpgradeOptions::new()).await.unwrap_err();
+    assert_matches!(error, Error::RoomUpgrade(RoomUpgradeError::UnstableVersion(v)) if v == version);
+
+    let error = room.upgrade(RoomVersionId::V9, RoomUpgradeOptions::new()).await.unwrap_err();
+    assert_matches!(error, Error::RoomUpgrade(RoomUpgradeError::UnsupportedVersion(_)));
+}
+
+#[async_test]
+async fn room_state_event_send() {
+    use ruma::events::room::member::{MembershipState, RoomMemberEventContent};
+
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let _response = client.sync_once(sync_settings).await.unwrap();
+
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+
+    let avatar_url = mxc_uri!("mxc://example.org/avA7ar");
+    let member_event = assign!(RoomMemberEventContent::new(MembershipState::Join), {
+        avatar_url: Some(avatar_url.to_owned())
+    });
+    let response =
+        room.send_state_event_for_key(user_id!("@foo:bar.com"), member_event).await.unwrap();
+    assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id);
+}
+
+#[async_test]
+async fn room_message_send() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    mock_encryption_state(&server, false).await;
+
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let _response = client.sync_once(sync_settings).await.unwrap();
+
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+
+    let content = RoomMessageEventContent::text_plain("Hello world");
+    let txn_id = TransactionId::new();
+    let response = room.send(content).with_transaction_id(&txn_id).await.unwrap();
+
+    assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id)
+}
+
+matrix_sdk::custom_event! {
+    #[ruma_event(type = "org.example.ping", kind = MessageLike)]
+    struct PingEventContent {
+        nonce: String,
+    }
+}
+
+matrix_sdk::custom_event! {
+    #[ruma_event(type = "org.example.status", kind = State, state_key_type = String)]
+    struct StatusEventContent {
+        online: bool,
+    }
+}
+
+#[async_test]
+async fn room_custom_message_send_typed() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/org.example.ping/.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(body_json(json!({ "nonce": "1234" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    // The event is returned as the homeserver serves it.
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/event/\$h29iv0s8:example.com"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "type": "org.example.ping",
+            "event_id": "$h29iv0s8:example.com",
+            "room_id": &*DEFAULT_TEST_ROOM_ID,
+            "sender": "@example:localhost",
+            "origin_server_ts": 152039280,
+            "unsigned": { "transaction_id": "txn" },
+            "content": { "nonce": "1234" },
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    mock_encryption_state(&server, false).await;
+
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let _response = client.sync_once(sync_settings).await.unwrap();
+
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+
+    let event = room.send_typed(PingEventContent { nonce: "1234".to_owned() }).await.unwrap();
+
+    assert_eq!(event.event_id, event_id!("$h29iv0s8:example.com"));
+    assert_eq!(event.sender, client.user_id().unwrap());
+    assert_eq!(event.origin_server_ts.0, uint!(152039280));
+    assert_eq!(event.unsigned.transaction_id.as_deref().map(|id| id.as_str()), Some("txn"));
+    assert_eq!(event.content.nonce, "1234");
+}
+
+#[async_test]
+async fn room_custom_state_event_static() {
+    let (client, server) = logged_in_client().await;
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(&DEFAULT_TEST_ROOM_ID).add_state_event(
+        StateTestEvent::Custom(json!({
+            "type": "org.example.status",
+            "event_id": "$status",
+            "state_key": "@bot:localhost",
+            "sender": "@bot:localhost",
+            "origin_server_ts": 152039280,
+            "content": { "online": true },
+        })),
+    ));
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+    let events = room.get_state_events_static::<StatusEventContent>().await.unwrap();
+    assert_eq!(events.len(), 1);
+    assert_let!(
+        SyncOrStrippedState::Sync(SyncStateEvent::Original(event)) =
+            events[0].deserialize().unwrap()
+    );
+    assert_eq!(event.state_key, "@bot:localhost");
+    assert!(event.content.online);
+}
+
+#[async_test]
+async fn room_attachment_send() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
         .and(header("authorization", "Bearer 1234"))
         .and(body_partial_json(json!({
             "info": {
                 "mimetype": "image/jpeg",
-                "h": 600,
-                "w": 800,
             }
         })))
         .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
@@ -438,22 +961,21 @@ async fn room_attachment_send_wrong_info() {
 
     let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
 
-    let config = AttachmentConfig::new().info(AttachmentInfo::Video(BaseVideoInfo {
-        height: Some(uint!(600)),
-        width: Some(uint!(800)),
-        duration: Some(Duration::from_millis(3600)),
-        size: None,
-        blurhash: None,
-    }));
-
-    let response =
-        room.send_attachment("image", &mime::IMAGE_JPEG, b"Hello world".to_vec(), config).await;
+    let response = room
+        .send_attachment(
+            "image",
+            &mime::IMAGE_JPEG,
+            b"Hello world".to_vec(),
+            AttachmentConfig::new(),
+        )
+        .await
+        .unwrap();
 
-    response.unwrap_err();
+    assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id)
 }
 
 #[async_test]
-async fn room_attachment_send_info_thumbnail() {
+async fn room_attachment_send_info() {
     let (client, server) = logged_in_client().await;
 
     Mock::given(method("PUT"))
@@ -464,13 +986,6 @@ async fn room_attachment_send_info_thumbnail() {
                 "mimetype": "image/jpeg",
                 "h": 600,
                 "w": 800,
-                "thumbnail_info": {
-                    "h": 360,
-                    "w": 480,
-                    "mimetype":"image/jpeg",
-                    "size": 3600,
-                },
-                "thumbnail_url": "mxc://example.com/AQwafuaFswefuhsfAFAgsw",
             }
         })))
         .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
@@ -484,7 +999,6 @@ async fn room_attachment_send_info_thumbnail() {
         .respond_with(ResponseTemplate::new(200).set_body_json(json!({
           "content_uri": "mxc://example.com/AQwafuaFswefuhsfAFAgsw"
         })))
-        .expect(2)
         .mount(&server)
         .await;
 
@@ -497,16 +1011,7 @@ async fn room_attachment_send_info_thumbnail() {
 
     let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
 
-    let config = AttachmentConfig::with_thumbnail(Thumbnail {
-        data: b"Thumbnail".to_vec(),
-        content_type: mime::IMAGE_JPEG,
-        info: Some(BaseThumbnailInfo {
-            height: Some(uint!(360)),
-            width: Some(uint!(480)),
-            size: Some(uint!(3600)),
-        }),
-    })
-    .info(AttachmentInfo::Image(BaseImageInfo {
+    let config = AttachmentConfig::new().info(AttachmentInfo::Image(BaseImageInfo {
         height: Some(uint!(600)),
         width: Some(uint!(800)),
         size: None,
@@ -522,84 +1027,261 @@ async fn room_attachment_send_info_thumbnail() {
 }
 
 #[async_test]
-async fn room_redact() {
-    let (client, server) = synced_client().await;
+async fn room_voice_message_send() {
+    let (client, server) = logged_in_client().await;
 
     Mock::given(method("PUT"))
-        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/redact/.*?/.*?"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
         .and(header("authorization", "Bearer 1234"))
+        .and(body_partial_json(json!({
+            "msgtype": "m.audio",
+            "info": {
+                "mimetype": "audio/ogg",
+                "duration": 4000,
+                "size": 11,
+            },
+            "org.matrix.msc1767.audio": {
+                "duration": 4000,
+                "waveform": [0, 512, 1024],
+            },
+            "org.matrix.msc3245.voice": {},
+        })))
         .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(1)
         .mount(&server)
         .await;
 
-    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
-
-    let event_id = event_id!("$xxxxxxxx:example.com");
-
-    let txn_id = TransactionId::new();
-    let reason = Some("Indecent material");
-    let response = room.redact(event_id, reason, Some(txn_id)).await.unwrap();
-
-    assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id)
-}
-
-#[cfg(not(target_arch = "wasm32"))]
-#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
-async fn fetch_members_deduplication() {
-    let (client, server) = synced_client().await;
-
-    // We don't need any members, we're just checking if we're correctly
-    // deduplicating calls to the method.
-    let response_body = json!({
-        "chunk": [],
-    });
-
-    Mock::given(method("GET"))
-        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/members"))
+    Mock::given(method("POST"))
+        .and(path("/_matrix/media/r0/upload"))
         .and(header("authorization", "Bearer 1234"))
-        .respond_with(ResponseTemplate::new(200).set_body_json(response_body))
-        // Expect that we're only going to send the request out once.
-        .expect(1..=1)
+        .and(header("content-type", "audio/ogg"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+          "content_uri": "mxc://example.com/AQwafuaFswefuhsfAFAgsw"
+        })))
         .mount(&server)
         .await;
 
-    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    mock_encryption_state(&server, false).await;
 
-    let mut tasks = Vec::new();
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
 
-    // Create N tasks that try to fetch the members.
-    for _ in 0..5 {
-        #[allow(unknown_lints, clippy::redundant_async_block)] // false positive
-        let task = tokio::spawn({
-            let room = room.clone();
-            async move { room.sync_members().await }
-        });
+    let _response = client.sync_once(sync_settings).await.unwrap();
 
-        tasks.push(task);
-    }
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
 
-    // Wait on all of them at once.
-    join_all(tasks).await;
+    let response = room
+        .send_voice_message(
+            &"audio/ogg".parse().unwrap(),
+            b"Hello world".to_vec(),
+            vec![0, 512, 1024],
+            Duration::from_secs(4),
+        )
+        .await
+        .unwrap();
 
-    // Ensure we called the endpoint exactly once.
-    server.verify().await;
+    assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id)
 }
 
 #[async_test]
-async fn set_name() {
-    let (client, server) = synced_client().await;
-
-    mock_sync(&server, &*test_json::SYNC, None).await;
-    let sync_settings = SyncSettings::new();
-    client.sync_once(sync_settings).await.unwrap();
-
-    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
-    let name = "The room name";
+async fn room_attachment_send_wrong_info() {
+    let (client, server) = logged_in_client().await;
 
     Mock::given(method("PUT"))
-        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m.room.name/$"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
         .and(header("authorization", "Bearer 1234"))
-        .and(body_json(json!({
+        .and(body_partial_json(json!({
+            "info": {
+                "mimetype": "image/jpeg",
+                "h": 600,
+                "w": 800,
+            }
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/media/r0/upload"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(header("content-type", "image/jpeg"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+          "content_uri": "mxc://example.com/AQwafuaFswefuhsfAFAgsw"
+        })))
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    mock_encryption_state(&server, false).await;
+
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let _response = client.sync_once(sync_settings).await.unwrap();
+
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+
+    let config = AttachmentConfig::new().info(AttachmentInfo::Video(BaseVideoInfo {
+        height: Some(uint!(600)),
+        width: Some(uint!(800)),
+        duration: Some(Duration::from_millis(3600)),
+        size: None,
+        blurhash: None,
+    }));
+
+    let response =
+        room.send_attachment("image", &mime::IMAGE_JPEG, b"Hello world".to_vec(), config).await;
+
+    response.unwrap_err();
+}
+
+#[async_test]
+async fn room_attachment_send_info_thumbnail() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(body_partial_json(json!({
+            "info": {
+                "mimetype": "image/jpeg",
+                "h": 600,
+                "w": 800,
+                "thumbnail_info": {
+                    "h": 360,
+                    "w": 480,
+                    "mimetype":"image/jpeg",
+                    "size": 3600,
+                },
+                "thumbnail_url": "mxc://example.com/AQwafuaFswefuhsfAFAgsw",
+            }
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path("/_matrix/media/r0/upload"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(header("content-type", "image/jpeg"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+          "content_uri": "mxc://example.com/AQwafuaFswefuhsfAFAgsw"
+        })))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    mock_encryption_state(&server, false).await;
+
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+
+    let _response = client.sync_once(sync_settings).await.unwrap();
+
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+
+    let config = AttachmentConfig::with_thumbnail(Thumbnail {
+        data: b"Thumbnail".to_vec(),
+        content_type: mime::IMAGE_JPEG,
+        info: Some(BaseThumbnailInfo {
+            height: Some(uint!(360)),
+            width: Some(uint!(480)),
+            size: Some(uint!(3600)),
+        }),
+    })
+    .info(AttachmentInfo::Image(BaseImageInfo {
+        height: Some(uint!(600)),
+        width: Some(uint!(800)),
+        size: None,
+        blurhash: None,
+    }));
+
+    let response = room
+        .send_attachment("image", &mime::IMAGE_JPEG, b"Hello world".to_vec(), config)
+        .await
+        .unwrap();
+
+    assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id)
+}
+
+#[async_test]
+async fn room_redact() {
+    let (client, server) = synced_client().await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/redact/.*?/.*?"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .mount(&server)
+        .await;
+
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+
+    let event_id = event_id!("$xxxxxxxx:example.com");
+
+    let txn_id = TransactionId::new();
+    let reason = Some("Indecent material");
+    let response = room.redact(event_id, reason, Some(txn_id)).await.unwrap();
+
+    assert_eq!(event_id!("$h29iv0s8:example.com"), response.event_id)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn fetch_members_deduplication() {
+    let (client, server) = synced_client().await;
+
+    // We don't need any members, we're just checking if we're correctly
+    // deduplicating calls to the method.
+    let response_body = json!({
+        "chunk": [],
+    });
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/members"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(response_body))
+        // Expect that we're only going to send the request out once.
+        .expect(1..=1)
+        .mount(&server)
+        .await;
+
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+
+    let mut tasks = Vec::new();
+
+    // Create N tasks that try to fetch the members.
+    for _ in 0..5 {
+        #[allow(unknown_lints, clippy::redundant_async_block)] // false positive
+        let task = tokio::spawn({
+            let room = room.clone();
+            async move { room.sync_members().await }
+        });
+
+        tasks.push(task);
+    }
+
+    // Wait on all of them at once.
+    join_all(tasks).await;
+
+    // Ensure we called the endpoint exactly once.
+    server.verify().await;
+}
+
+#[async_test]
+async fn set_name() {
+    let (client, server) = synced_client().await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    let sync_settings = SyncSettings::new();
+    client.sync_once(sync_settings).await.unwrap();
+
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+    let name = "The room name";
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m.room.name/$"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(body_json(json!({
             "name": name,
         })))
         .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
@@ -608,6 +1290,91 @@ async fn set_name() {
         .await;
 
     room.set_name(name.to_owned()).await.unwrap();
+
+    // The new name is visible before it comes back via sync.
+    assert_eq!(room.name().as_deref(), Some(name));
+
+    // It is persisted with the ID of the state event, so it survives a restart.
+    let room_infos = client.store().get_room_infos().await.unwrap();
+    let room_info = room_infos.iter().find(|info| info.room_id() == *DEFAULT_TEST_ROOM_ID).unwrap();
+    assert_eq!(room_info.name(), Some(name));
+    let room_info = serde_json::to_value(room_info).unwrap();
+    assert_eq!(
+        room_info.pointer("/base_info/name/Original/event_id"),
+        Some(&json!("$h29iv0s8:example.com"))
+    );
+}
+
+#[async_test]
+async fn set_room_topic_failure_reverts_local_echo() {
+    let (client, server) = synced_client().await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    let sync_settings = SyncSettings::new();
+    client.sync_once(sync_settings).await.unwrap();
+
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+    let previous_topic = room.topic();
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m.room.topic/$"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(403).set_body_json(json!({
+            "errcode": "M_FORBIDDEN",
+            "error": "You don't have permission to change the topic",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    room.set_room_topic("A new topic").await.unwrap_err();
+
+    assert_eq!(room.topic(), previous_topic);
+
+    // The revert is persisted too.
+    let room_infos = client.store().get_room_infos().await.unwrap();
+    let room_info = room_infos.iter().find(|info| info.room_id() == *DEFAULT_TEST_ROOM_ID).unwrap();
+    let room_info = serde_json::to_value(room_info).unwrap();
+    assert_eq!(
+        room_info.pointer("/base_info/topic/Original/content/topic").and_then(|t| t.as_str()),
+        previous_topic.as_deref()
+    );
+}
+
+#[async_test]
+async fn set_history_visibility_and_guest_access() {
+    let (client, server) = synced_client().await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    let sync_settings = SyncSettings::new();
+    client.sync_once(sync_settings).await.unwrap();
+
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m.room.history_visibility/$"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(body_json(json!({
+            "history_visibility": "joined",
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m.room.guest_access/$"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(body_json(json!({
+            "guest_access": "forbidden",
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    room.set_history_visibility(HistoryVisibility::Joined).await.unwrap();
+    room.set_guest_access(GuestAccess::Forbidden).await.unwrap();
 }
 
 #[async_test]
@@ -642,3 +1409,621 @@ async fn report_content() {
 
     room.report_content(event_id, Some(score), Some(reason.to_owned())).await.unwrap();
 }
+
+#[async_test]
+async fn scheduled_message_is_sent_when_due() {
+    let (client, server) = logged_in_client().await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .and(body_partial_json(json!({ "body": "Good morning!" })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    mock_encryption_state(&server, false).await;
+
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+    let _response = client.sync_once(sync_settings).await.unwrap();
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+
+    let mut dispatches = client.scheduled_messages().subscribe_to_dispatches();
+
+    // A message scheduled in the past is sent right away.
+    let content = RoomMessageEventContent::text_plain("Good morning!");
+    let scheduled =
+        room.schedule_message(content, SystemTime::now() - Duration::from_secs(1)).await.unwrap();
+
+    let dispatch = timeout(Duration::from_secs(5), dispatches.recv()).await.unwrap().unwrap();
+    assert_eq!(dispatch.message.id, scheduled.id);
+    assert_eq!(dispatch.result.unwrap(), event_id!("$h29iv0s8:example.com"));
+
+    assert!(room.scheduled_messages().await.unwrap().is_empty());
+}
+
+#[async_test]
+async fn scheduled_message_is_kept_and_retried_when_rate_limited() {
+    let clock = TestClock::new();
+    let (builder, server) = test_client_builder().await;
+    let client = builder
+        .request_config(RequestConfig::new().disable_retry())
+        .clock(Arc::new(clock.clone()))
+        .build()
+        .await
+        .unwrap();
+    client
+        .restore_session(MatrixSession {
+            meta: SessionMeta {
+                user_id: user_id!("@example:localhost").to_owned(),
+                device_id: device_id!("DEVICEID").to_owned(),
+            },
+            tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+        })
+        .await
+        .unwrap();
+
+    // The first send is rate limited, the second one succeeds.
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(429).set_body_json(json!({
+            "errcode": "M_LIMIT_EXCEEDED",
+            "error": "Too many requests",
+            "retry_after_ms": 2000,
+        })))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    mock_encryption_state(&server, false).await;
+
+    client.sync_once(SyncSettings::new()).await.unwrap();
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+
+    let mut dispatches = client.scheduled_messages().subscribe_to_dispatches();
+
+    let content = RoomMessageEventContent::text_plain("Good morning!");
+    let scheduled =
+        room.schedule_message(content, SystemTime::now() - Duration::from_secs(1)).await.unwrap();
+
+    // The rate limited send is reported, but the message stays scheduled until
+    // the homeserver allows us to retry.
+    let dispatch = timeout(Duration::from_secs(5), dispatches.recv()).await.unwrap().unwrap();
+    assert_eq!(dispatch.message.id, scheduled.id);
+    assert_let!(Err(ScheduledMessageError::Send(error)) = dispatch.result);
+    assert_eq!(
+        error.retry_kind(),
+        RetryKind::Transient { retry_after: Some(Duration::from_millis(2000)) }
+    );
+
+    let messages = room.scheduled_messages().await.unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].id, scheduled.id);
+    assert!(messages[0].send_at > scheduled.send_at);
+
+    // Once the retry delay is over, the message is sent.
+    clock.advance(Duration::from_secs(2));
+
+    let dispatch = timeout(Duration::from_secs(5), dispatches.recv()).await.unwrap().unwrap();
+    assert_eq!(dispatch.message.id, scheduled.id);
+    assert_eq!(dispatch.result.unwrap(), event_id!("$h29iv0s8:example.com"));
+
+    assert!(room.scheduled_messages().await.unwrap().is_empty());
+    server.verify().await;
+}
+
+#[async_test]
+async fn scheduled_message_is_dropped_when_rejected() {
+    let (client, server) = synced_client().await;
+    mock_encryption_state(&server, false).await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/send/.*"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(403).set_body_json(json!({
+            "errcode": "M_FORBIDDEN",
+            "error": "You are not allowed to send messages here",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+    let mut dispatches = client.scheduled_messages().subscribe_to_dispatches();
+
+    let content = RoomMessageEventContent::text_plain("Good morning!");
+    room.schedule_message(content, SystemTime::now() - Duration::from_secs(1)).await.unwrap();
+
+    // Retrying would fail the same way, so the message isn't kept.
+    let dispatch = timeout(Duration::from_secs(5), dispatches.recv()).await.unwrap().unwrap();
+    assert_let!(Err(ScheduledMessageError::Send(error)) = dispatch.result);
+    assert_eq!(error.retry_kind(), RetryKind::Permanent);
+
+    assert!(room.scheduled_messages().await.unwrap().is_empty());
+}
+
+#[async_test]
+async fn scheduled_messages_can_be_edited_and_cancelled() {
+    let (client, _server) = synced_client().await;
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+    let scheduled_messages = client.scheduled_messages();
+
+    let later = SystemTime::now() + Duration::from_secs(60 * 60);
+    let first =
+        room.schedule_message(RoomMessageEventContent::text_plain("First"), later).await.unwrap();
+    let second = room
+        .schedule_message(
+            RoomMessageEventContent::text_plain("Second"),
+            later + Duration::from_secs(60),
+        )
+        .await
+        .unwrap();
+
+    let ids: Vec<_> = room.scheduled_messages().await.unwrap().into_iter().map(|m| m.id).collect();
+    assert_eq!(ids, [first.id.clone(), second.id.clone()]);
+
+    // Moving the second message before the first one changes the order.
+    let edited = scheduled_messages
+        .edit(
+            &second.id,
+            RoomMessageEventContent::text_plain("Second, edited"),
+            later - Duration::from_secs(60),
+        )
+        .await
+        .unwrap();
+    assert!(edited);
+
+    let messages = scheduled_messages.list().await.unwrap();
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[0].id, second.id);
+    assert_eq!(messages[0].content.body(), "Second, edited");
+
+    assert!(scheduled_messages.cancel(&first.id).await.unwrap());
+    assert!(!scheduled_messages.cancel(&first.id).await.unwrap());
+
+    let ids: Vec<_> = scheduled_messages.list().await.unwrap().into_iter().map(|m| m.id).collect();
+    assert_eq!(ids, [second.id]);
+}
+
+#[async_test]
+async fn leave_and_forget_as_last_admin() {
+    let (client, server) = synced_client().await;
+
+    // The members are already known from the sync.
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/members"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "chunk": [] })))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/leave"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/forget"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+
+    // We're the only admin and @example2 is still around.
+    assert_matches!(
+        room.leave_and_forget(LeaveAndForgetOptions::new()).await,
+        Err(Error::LastAdminOfRoom)
+    );
+    assert_eq!(room.state(), RoomState::Joined);
+
+    room.leave_and_forget(LeaveAndForgetOptions::new().allow_last_admin().purge_local_data())
+        .await
+        .unwrap();
+
+    assert_eq!(room.state(), RoomState::Left);
+    assert!(client.get_room(&DEFAULT_TEST_ROOM_ID).is_none());
+
+    server.verify().await;
+}
+
+#[async_test]
+async fn leave_and_forget_keeps_the_tags_when_the_leave_fails() {
+    let (client, server) = synced_client().await;
+
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(&DEFAULT_TEST_ROOM_ID).add_account_data(
+        RoomAccountDataTestEvent::Custom(json!({
+            "content": { "tags": { "m.favourite": { "order": 0.5 } } },
+            "type": "m.tag",
+        })),
+    ));
+    server.reset().await;
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/leave"))
+        .respond_with(ResponseTemplate::new(403).set_body_json(json!({
+            "errcode": "M_FORBIDDEN",
+            "error": "You can't leave this room",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("DELETE"))
+        .and(path_regex(r"^/_matrix/client/r0/user/.*/rooms/.*/tags/.*"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+    room.leave_and_forget(LeaveAndForgetOptions::new().allow_last_admin()).await.unwrap_err();
+
+    assert_eq!(room.state(), RoomState::Joined);
+    assert!(room.tags().await.unwrap().unwrap().contains_key(&TagName::Favorite));
+
+    server.verify().await;
+}
+
+#[async_test]
+async fn leave_and_forget_uses_the_highest_power_level_of_the_room() {
+    let (client, server) = synced_client().await;
+
+    // Everybody has the same level as us, so we aren't the last admin.
+    let mut ev_builder = SyncResponseBuilder::new();
+    ev_builder.add_joined_room(JoinedRoomBuilder::new(&DEFAULT_TEST_ROOM_ID).add_state_event(
+        StateTestEvent::Custom(json!({
+            "content": {
+                "state_default": 50,
+                "users": { "@example:localhost": 50 },
+                "users_default": 50,
+            },
+            "event_id": "$power_levels",
+            "origin_server_ts": 151393755000000_u64,
+            "sender": "@example:localhost",
+            "state_key": "",
+            "type": "m.room.power_levels",
+        })),
+    ));
+    server.reset().await;
+    mock_sync(&server, ev_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/members"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "chunk": [] })))
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/leave"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/forget"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EMPTY))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+    room.leave_and_forget(LeaveAndForgetOptions::new()).await.unwrap();
+    assert_eq!(room.state(), RoomState::Left);
+
+    server.verify().await;
+}
+
+#[async_test]
+async fn send_state_events_atomically_reports_partial_failure() {
+    let (client, server) = synced_client().await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+    let previous_topic = room.topic().unwrap();
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m.room.topic/$"))
+        .and(header("authorization", "Bearer 1234"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m.room.name/$"))
+        .respond_with(ResponseTemplate::new(403).set_body_json(json!({
+            "errcode": "M_FORBIDDEN",
+            "error": "You don't have permission to change the name",
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m.room.guest_access/$"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let report = room
+        .send_state_events_atomically(vec![
+            StateEventToSend::new(&RoomTopicEventContent::new("Bots only".to_owned())).unwrap(),
+            StateEventToSend::new(&RoomNameEventContent::new("Bots".to_owned())).unwrap(),
+            StateEventToSend::new(&RoomGuestAccessEventContent::new(GuestAccess::Forbidden))
+                .unwrap(),
+        ])
+        .await
+        .unwrap();
+
+    assert!(!report.is_complete());
+    assert_matches!(&report.outcomes[0], StateEventOutcome::Sent(event_id) => {
+        assert_eq!(event_id.as_str(), "$h29iv0s8:example.com");
+    });
+    assert_matches!(&report.outcomes[1], StateEventOutcome::Failed(_));
+    assert_matches!(&report.outcomes[2], StateEventOutcome::NotSent);
+
+    // Only the topic was changed, so only the topic needs to be restored.
+    assert_eq!(report.rollback.len(), 1);
+    assert_eq!(report.rollback[0].event_type, StateEventType::RoomTopic);
+    assert_eq!(
+        report.rollback[0].content.get_field::<String>("topic").unwrap(),
+        Some(previous_topic)
+    );
+}
+
+#[async_test]
+async fn send_state_events_atomically_validates_the_batch() {
+    let (client, server) = synced_client().await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    // The content doesn't match the schema of the event type.
+    let error = room
+        .send_state_events_atomically(vec![
+            StateEventToSend::new(&RoomTopicEventContent::new("Bots only".to_owned())).unwrap(),
+            StateEventToSend::raw("m.room.name", "", json!({ "name": 42 })),
+        ])
+        .await
+        .unwrap_err();
+    assert_matches!(
+        error,
+        Error::InvalidStateBatch(StateBatchValidationError::InvalidEvent { index: 1, .. })
+    );
+
+    // Once the batch demotes the user, they can't send the next state events.
+    let error = room
+        .send_state_events_atomically(vec![
+            StateEventToSend::raw(
+                "m.room.power_levels",
+                "",
+                json!({ "state_default": 50, "users": { "@example:localhost": 0 } }),
+            ),
+            StateEventToSend::new(&RoomTopicEventContent::new("Bots only".to_owned())).unwrap(),
+        ])
+        .await
+        .unwrap_err();
+    assert_matches!(
+        error,
+        Error::InvalidStateBatch(StateBatchValidationError::Forbidden { index: 1, .. })
+    );
+
+    // The user can't give another user a higher level than their own.
+    let error = room
+        .send_state_events_atomically(vec![StateEventToSend::raw(
+            "m.room.power_levels",
+            "",
+            json!({
+                "state_default": 50,
+                "users": { "@example:localhost": 100, "@bob:localhost": 150 },
+            }),
+        )])
+        .await
+        .unwrap_err();
+    assert_matches!(
+        error,
+        Error::InvalidStateBatch(StateBatchValidationError::Forbidden { index: 0, .. })
+    );
+
+    // Once the batch demotes the user, they can't raise a level above their new
+    // level.
+    let error = room
+        .send_state_events_atomically(vec![
+            StateEventToSend::raw(
+                "m.room.power_levels",
+                "",
+                json!({ "users": { "@example:localhost": 50 } }),
+            ),
+            StateEventToSend::raw(
+                "m.room.power_levels",
+                "",
+                json!({ "ban": 100, "users": { "@example:localhost": 50 } }),
+            ),
+        ])
+        .await
+        .unwrap_err();
+    assert_matches!(
+        error,
+        Error::InvalidStateBatch(StateBatchValidationError::Forbidden { index: 1, .. })
+    );
+}
+
+#[async_test]
+async fn send_state_events_atomically_lists_events_that_cant_be_rolled_back() {
+    let (client, server) = synced_client().await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+
+    for event_type in ["m.room.topic", "m.room.guest_access"] {
+        Mock::given(method("PUT"))
+            .and(path_regex(format!(r"^/_matrix/client/r0/rooms/.*/state/{event_type}/$")))
+            .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m.room.name/$"))
+        .respond_with(ResponseTemplate::new(403).set_body_json(json!({
+            "errcode": "M_FORBIDDEN",
+            "error": "You don't have permission to change the name",
+        })))
+        .mount(&server)
+        .await;
+
+    let report = room
+        .send_state_events_atomically(vec![
+            StateEventToSend::new(&RoomTopicEventContent::new("Bots only".to_owned())).unwrap(),
+            StateEventToSend::new(&RoomGuestAccessEventContent::new(GuestAccess::Forbidden))
+                .unwrap(),
+            StateEventToSend::new(&RoomNameEventContent::new("Bots".to_owned())).unwrap(),
+        ])
+        .await
+        .unwrap();
+
+    assert!(!report.is_complete());
+
+    // The room had a topic, but no guest access, which can't be empty.
+    assert_eq!(report.rollback.len(), 1);
+    assert_eq!(report.rollback[0].event_type, StateEventType::RoomTopic);
+    assert_eq!(report.not_rolled_back.len(), 1);
+    assert_eq!(report.not_rolled_back[0].event_type, StateEventType::RoomGuestAccess);
+}
+
+#[async_test]
+async fn pin_event_sends_again_on_concurrent_change() {
+    let (client, server) = synced_client().await;
+
+    mock_sync(&server, &*test_json::SYNC, None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+
+    // Someone else pins `$c` while `$b` is being pinned.
+    for pinned in [json!(["$a"]), json!(["$c"]), json!(["$c", "$b"])] {
+        Mock::given(method("GET"))
+            .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m.room.pinned_events/"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "pinned": pinned })))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m.room.pinned_events/"))
+        .and(body_json(json!({ "pinned": ["$a", "$b"] })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m.room.pinned_events/"))
+        .and(body_json(json!({ "pinned": ["$c", "$b"] })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    assert!(room.pin_event(event_id!("$b")).await.unwrap());
+}
+
+#[async_test]
+async fn pin_event_checks_power_levels() {
+    let (client, server) = logged_in_client().await;
+
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_joined_room(JoinedRoomBuilder::new(&DEFAULT_TEST_ROOM_ID).add_state_event(
+        StateTestEvent::Custom(json!({
+            "content": {
+                "state_default": 50,
+                "users": { "@example:localhost": 0 },
+            },
+            "event_id": "$power_levels",
+            "origin_server_ts": 151393755000000_u64,
+            "sender": "@bob:localhost",
+            "state_key": "",
+            "type": "m.room.power_levels",
+        })),
+    ));
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/m.room.pinned_events/"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(&*test_json::EVENT_ID))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+    let error = room.unpin_event(event_id!("$a")).await.unwrap_err();
+    assert_matches!(error, Error::PinnedEvents(PinnedEventsError::Forbidden));
+}
+
+#[async_test]
+async fn subscribe_to_info_changes() {
+    let (client, server) = logged_in_client().await;
+    let other_room_id = room_id!("!other:localhost");
+
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder
+        .add_joined_room(JoinedRoomBuilder::new(&DEFAULT_TEST_ROOM_ID))
+        .add_joined_room(JoinedRoomBuilder::new(other_room_id));
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+    let changes = room.subscribe_to_info_changes();
+    pin_mut!(changes);
+
+    // The changes of the other rooms are ignored.
+    let other_room = client.get_room(other_room_id).unwrap();
+    let mut other_room_info = other_room.clone_info();
+    other_room_info.update_name("Other room".to_owned());
+    other_room.set_room_info(other_room_info);
+
+    let mut room_info = room.clone_info();
+    room_info.update_name("New name".to_owned());
+    room.set_room_info(room_info);
+
+    assert_eq!(changes.next().await, Some(RoomInfoChangeReasons::NAME));
+}
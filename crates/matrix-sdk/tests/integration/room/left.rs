@@ -1,6 +1,9 @@
 use std::time::Duration;
 
-use matrix_sdk::config::SyncSettings;
+use matrix_sdk::{
+    config::SyncSettings,
+    store_cleanup::{CleanupPolicy, RoomCleanupOverride},
+};
 use matrix_sdk_base::RoomState;
 use matrix_sdk_test::{async_test, test_json, DEFAULT_TEST_ROOM_ID};
 use serde_json::json;
@@ -33,6 +36,35 @@ async fn forget_room() {
     room.forget().await.unwrap();
 }
 
+#[async_test]
+async fn store_cleanup_purges_and_forgets_left_rooms() {
+    let (client, server) = logged_in_client().await;
+
+    mock_sync(&server, &*test_json::LEAVE_SYNC, None).await;
+
+    let sync_settings = SyncSettings::new().timeout(Duration::from_millis(3000));
+    let _response = client.sync_once(sync_settings).await.unwrap();
+
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+    assert_eq!(room.state(), RoomState::Left);
+    let purges = room.subscribe_to_purges();
+
+    let mut policy = CleanupPolicy::default();
+    policy.left_room_max_age = Some(Duration::ZERO);
+    policy
+        .room_overrides
+        .insert(DEFAULT_TEST_ROOM_ID.to_owned(), RoomCleanupOverride::MaxEventAge(Duration::ZERO));
+
+    let report = client.store_cleanup().run(&policy).await.unwrap();
+
+    // The timelines of the room are told about the purge.
+    assert!(purges.get().is_some());
+    // The room is forgotten, both in memory and in the store.
+    assert_eq!(report.removed_rooms, [DEFAULT_TEST_ROOM_ID.to_owned()]);
+    assert!(client.get_room(&DEFAULT_TEST_ROOM_ID).is_none());
+    assert!(client.store().get_room_infos().await.unwrap().is_empty());
+}
+
 #[async_test]
 async fn rejoin_room() {
     let (client, server) = logged_in_client().await;
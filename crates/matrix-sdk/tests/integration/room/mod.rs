@@ -2,4 +2,5 @@ mod common;
 mod joined;
 mod left;
 mod notification_mode;
+mod retention;
 mod spaces;
@@ -0,0 +1,116 @@
+use std::{sync::Arc, time::Duration};
+
+use matrix_sdk::{
+    config::{RequestConfig, StoreConfig, SyncSettings},
+    matrix_auth::{MatrixSession, MatrixSessionTokens},
+    store_cleanup::CleanupPolicy,
+    Client,
+};
+use matrix_sdk_base::{store::MemoryStore, SessionMeta};
+use matrix_sdk_test::{
+    async_test, EphemeralTestEvent, JoinedRoomBuilder, SyncResponseBuilder, DEFAULT_TEST_ROOM_ID,
+};
+use ruma::{
+    api::MatrixVersion,
+    device_id,
+    events::receipt::{ReceiptThread, ReceiptType},
+    user_id,
+};
+
+use crate::{logged_in_client, mock_sync, test_client_builder};
+
+#[async_test]
+async fn purged_events_are_gone_after_a_restart() {
+    let store = Arc::new(MemoryStore::new());
+    let session = MatrixSession {
+        meta: SessionMeta {
+            user_id: user_id!("@example:localhost").to_owned(),
+            device_id: device_id!("DEVICEID").to_owned(),
+        },
+        tokens: MatrixSessionTokens { access_token: "1234".to_owned(), refresh_token: None },
+    };
+
+    let (builder, server) = test_client_builder().await;
+    let client = builder
+        .store_config(StoreConfig::new().state_store(store.clone()))
+        .request_config(RequestConfig::new().disable_retry())
+        .default_max_event_lifetime(Duration::from_secs(24 * 60 * 60))
+        .build()
+        .await
+        .unwrap();
+    client.restore_session(session.clone()).await.unwrap();
+
+    // The read receipt was sent in 1970, so it is older than a day.
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_joined_room(
+        JoinedRoomBuilder::new(&DEFAULT_TEST_ROOM_ID)
+            .add_ephemeral_event(EphemeralTestEvent::ReadReceipt),
+    );
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    let receipt_user_id = user_id!("@example:localhost");
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+    assert!(room
+        .load_user_receipt(ReceiptType::Read, ReceiptThread::Unthreaded, receipt_user_id)
+        .await
+        .unwrap()
+        .is_some());
+
+    room.purge_expired_events().await.unwrap();
+
+    // A client restored from the same store doesn't know about the expired
+    // receipt anymore.
+    let restored_client = Client::builder()
+        .homeserver_url(server.uri())
+        .server_versions([MatrixVersion::V1_0])
+        .store_config(StoreConfig::new().state_store(store))
+        .request_config(RequestConfig::new().disable_retry())
+        .build()
+        .await
+        .unwrap();
+    restored_client.restore_session(session).await.unwrap();
+
+    let room = restored_client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+    assert!(room
+        .load_user_receipt(ReceiptType::Read, ReceiptThread::Unthreaded, receipt_user_id)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[async_test]
+async fn store_cleanup_prunes_old_receipts_of_joined_rooms() {
+    let (client, server) = logged_in_client().await;
+
+    // The read receipt was sent in 1970, and the room has no retention policy.
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_joined_room(
+        JoinedRoomBuilder::new(&DEFAULT_TEST_ROOM_ID)
+            .add_ephemeral_event(EphemeralTestEvent::ReadReceipt),
+    );
+    mock_sync(&server, sync_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::default()).await.unwrap();
+
+    let receipt_user_id = user_id!("@example:localhost");
+    let room = client.get_room(&DEFAULT_TEST_ROOM_ID).unwrap();
+
+    // Without a maximum age, the receipts of the joined rooms are kept.
+    let report = client.store_cleanup().run(&CleanupPolicy::default()).await.unwrap();
+    assert_eq!(report.removed_events, 0);
+    assert!(room
+        .load_user_receipt(ReceiptType::Read, ReceiptThread::Unthreaded, receipt_user_id)
+        .await
+        .unwrap()
+        .is_some());
+
+    let policy =
+        CleanupPolicy { receipt_max_age: Some(Duration::from_secs(60)), ..Default::default() };
+    client.store_cleanup().run(&policy).await.unwrap();
+
+    assert!(room
+        .load_user_receipt(ReceiptType::Read, ReceiptThread::Unthreaded, receipt_user_id)
+        .await
+        .unwrap()
+        .is_none());
+}
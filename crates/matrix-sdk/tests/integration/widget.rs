@@ -12,21 +12,32 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{pin::pin, time::Duration};
+use std::{
+    pin::pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 
 use assert_matches::assert_matches;
 use async_trait::async_trait;
 use futures_util::FutureExt;
 use matrix_sdk::{
     config::SyncSettings,
+    room::RoomWidgetError,
     widget::{
-        Capabilities, CapabilitiesProvider, WidgetDriver, WidgetDriverHandle, WidgetSettings,
+        Capabilities, CapabilitiesDecision, CapabilitiesProvider, DeferredCapabilities,
+        DeferredCapabilitiesSender, FilePicker, PickedFile, WidgetDriver, WidgetDriverHandle,
+        WidgetSettings,
     },
-    Client,
+    Client, Error,
 };
 use matrix_sdk_common::{executor::spawn, timeout::timeout};
 use matrix_sdk_test::{
-    async_test, EventBuilder, JoinedRoomBuilder, SyncResponseBuilder, ALICE, BOB,
+    async_test, EphemeralTestEvent, EventBuilder, JoinedRoomBuilder, StateTestEvent,
+    SyncResponseBuilder, ALICE, BOB,
 };
 use once_cell::sync::Lazy;
 use ruma::{
@@ -44,7 +55,7 @@ use serde::Serialize;
 use serde_json::{json, Value as JsonValue};
 use tracing::error;
 use wiremock::{
-    matchers::{header, method, path_regex, query_param},
+    matchers::{body_json, body_partial_json, header, method, path_regex, query_param},
     Mock, MockServer, ResponseTemplate,
 };
 
@@ -60,6 +71,15 @@ const WIDGET_ID: &str = "test-widget";
 static ROOM_ID: Lazy<OwnedRoomId> = Lazy::new(|| owned_room_id!("!a98sd12bjh:example.org"));
 
 async fn run_test_driver(init_on_content_load: bool) -> (Client, MockServer, WidgetDriverHandle) {
+    run_test_driver_with(init_on_content_load, |driver| driver).await
+}
+
+/// Like [`run_test_driver`], but lets the test configure the driver before it
+/// runs.
+async fn run_test_driver_with(
+    init_on_content_load: bool,
+    configure: impl FnOnce(WidgetDriver) -> WidgetDriver,
+) -> (Client, MockServer, WidgetDriverHandle) {
     struct DummyCapabilitiesProvider;
 
     #[async_trait]
@@ -88,6 +108,7 @@ async fn run_test_driver(init_on_content_load: bool) -> (Client, MockServer, Wid
         WidgetSettings::new(WIDGET_ID.to_owned(), init_on_content_load, "https://foo.bar/widget")
             .unwrap(),
     );
+    let driver = configure(driver);
 
     spawn(async move {
         if let Err(()) = driver.run(room, DummyCapabilitiesProvider).await {
@@ -285,6 +306,58 @@ async fn read_messages() {
     mock_server.verify().await;
 }
 
+#[async_test]
+async fn read_messages_skips_rooms_that_cant_be_read() {
+    let (_, mock_server, driver_handle) = run_test_driver(false).await;
+
+    negotiate_capabilities(
+        &driver_handle,
+        json!([
+            "org.matrix.msc2762.receive.event:m.room.message",
+            "org.matrix.msc2762.timeline:!other:example.org",
+        ]),
+    )
+    .await;
+
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/messages$"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "chunk": [{
+                "content": { "body": "hello", "msgtype": "m.text" },
+                "event_id": "$msda7m0df9E9op3",
+                "origin_server_ts": 152037280,
+                "sender": "@example:localhost",
+                "type": "m.room.message",
+                "room_id": &*ROOM_ID,
+            }],
+            "end": "t47409-4357353_219380_26003_2269",
+            "start": "t392-516_47314_0_7_1_1_1_11444_1"
+        })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    // The user isn't joined to the other room, which doesn't fail the request.
+    send_request(
+        &driver_handle,
+        "read-messages",
+        "org.matrix.msc2876.read_events",
+        json!({
+            "type": "m.room.message",
+            "room_ids": "*",
+        }),
+    )
+    .await;
+
+    let msg = recv_message(&driver_handle).await;
+    assert_eq!(msg["action"], "org.matrix.msc2876.read_events");
+    let events = msg["response"]["events"].as_array().unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["content"]["body"], "hello");
+
+    mock_server.verify().await;
+}
+
 #[async_test]
 async fn read_messages_with_msgtype_capabilities() {
     let (_, mock_server, driver_handle) = run_test_driver(true).await;
@@ -519,6 +592,82 @@ async fn receive_live_events() {
     assert_matches!(recv_message(&driver_handle).now_or_never(), None);
 }
 
+#[async_test]
+async fn receive_state_section_events() {
+    let (client, mock_server, driver_handle) = run_test_driver(false).await;
+
+    negotiate_capabilities(
+        &driver_handle,
+        json!([
+            "org.matrix.msc2762.receive.state_event:m.room.name#",
+            "org.matrix.msc2762.receive.state_event:m.room.member#@example:localhost",
+        ]),
+    )
+    .await;
+
+    // No messages from the driver yet
+    assert_matches!(recv_message(&driver_handle).now_or_never(), None);
+
+    let mut sync_builder = SyncResponseBuilder::new();
+    // bump the internal batch counter, otherwise the response will be seen as
+    // identical to the one done in `run_test_driver`
+    sync_builder.build_json_sync_response();
+
+    let event_builder = EventBuilder::new();
+    sync_builder.add_joined_room(
+        JoinedRoomBuilder::new(&ROOM_ID).add_state_bulk([
+            // set room name - matches filter #1
+            event_builder
+                .make_sync_state_event(
+                    &BOB,
+                    "",
+                    RoomNameEventContent::new("Room Name From State".to_owned()),
+                    None,
+                )
+                .cast(),
+            // member event of bob - doesn't match because of the state key
+            event_builder
+                .make_sync_state_event(
+                    &BOB,
+                    BOB.as_str(),
+                    RoomMemberEventContent::new(MembershipState::Join),
+                    None,
+                )
+                .cast(),
+            // member event of example - matches filter #2
+            event_builder
+                .make_sync_state_event(
+                    user_id!("@example:localhost"),
+                    "@example:localhost",
+                    RoomMemberEventContent::new(MembershipState::Join),
+                    None,
+                )
+                .cast(),
+        ]),
+    );
+
+    mock_sync(&mock_server, sync_builder.build_json_sync_response(), None).await;
+    let _response =
+        client.sync_once(SyncSettings::new().timeout(Duration::from_millis(3000))).await.unwrap();
+
+    let msg = recv_message(&driver_handle).await;
+    assert_eq!(msg["api"], "toWidget");
+    assert_eq!(msg["action"], "send_event");
+    assert_eq!(msg["data"]["type"], "m.room.name");
+    assert_eq!(msg["data"]["room_id"], ROOM_ID.as_str());
+    assert_eq!(msg["data"]["content"]["name"], "Room Name From State");
+
+    let msg = recv_message(&driver_handle).await;
+    assert_eq!(msg["api"], "toWidget");
+    assert_eq!(msg["action"], "send_event");
+    assert_eq!(msg["data"]["type"], "m.room.member");
+    assert_eq!(msg["data"]["room_id"], ROOM_ID.as_str());
+    assert_eq!(msg["data"]["state_key"], "@example:localhost");
+
+    // No more messages from the driver
+    assert_matches!(recv_message(&driver_handle).now_or_never(), None);
+}
+
 #[async_test]
 async fn send_room_message() {
     let (_, mock_server, driver_handle) = run_test_driver(false).await;
@@ -600,6 +749,457 @@ async fn send_room_name() {
     mock_server.verify().await;
 }
 
+#[async_test]
+async fn receive_ephemeral_events() {
+    let (client, mock_server, driver_handle) = run_test_driver(false).await;
+
+    negotiate_capabilities(
+        &driver_handle,
+        json!(["org.matrix.msc2477.receive.ephemeral_event:org.example.cursor"]),
+    )
+    .await;
+
+    let mut sync_builder = SyncResponseBuilder::new();
+    // bump the internal batch counter, otherwise the response will be seen as
+    // identical to the one done in `run_test_driver`
+    sync_builder.build_json_sync_response();
+
+    sync_builder.add_joined_room(
+        JoinedRoomBuilder::new(&ROOM_ID)
+            // typing notification - doesn't match
+            .add_ephemeral_event(EphemeralTestEvent::Typing)
+            // cursor position - matches
+            .add_ephemeral_event(EphemeralTestEvent::Custom(json!({
+                "type": "org.example.cursor",
+                "content": { "x": 12, "y": 34 },
+            }))),
+    );
+
+    mock_sync(&mock_server, sync_builder.build_json_sync_response(), None).await;
+    let _response =
+        client.sync_once(SyncSettings::new().timeout(Duration::from_millis(3000))).await.unwrap();
+
+    let msg = recv_message(&driver_handle).await;
+    assert_eq!(msg["api"], "toWidget");
+    assert_eq!(msg["action"], "org.matrix.msc2477.ephemeral_event");
+    assert_eq!(msg["data"]["type"], "org.example.cursor");
+    assert_eq!(msg["data"]["room_id"], ROOM_ID.as_str());
+    assert_eq!(msg["data"]["content"], json!({ "x": 12, "y": 34 }));
+
+    // No more messages from the driver
+    assert_matches!(recv_message(&driver_handle).now_or_never(), None);
+}
+
+#[async_test]
+async fn send_ephemeral_event() {
+    let (_, mock_server, driver_handle) = run_test_driver(false).await;
+
+    negotiate_capabilities(
+        &driver_handle,
+        json!(["org.matrix.msc2477.send.ephemeral_event:org.example.cursor"]),
+    )
+    .await;
+
+    Mock::given(method("PUT"))
+        .and(path_regex(
+            r"^/_matrix/client/unstable/org.matrix.msc2477/rooms/.*/ephemeral/org.example.cursor/.*$",
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    send_request(
+        &driver_handle,
+        "send-cursor",
+        "org.matrix.msc2477.send_ephemeral_event",
+        json!({
+            "type": "org.example.cursor",
+            "content": { "x": 12, "y": 34 },
+        }),
+    )
+    .await;
+
+    let msg = recv_message(&driver_handle).await;
+    assert_eq!(msg["api"], "fromWidget");
+    assert_eq!(msg["action"], "org.matrix.msc2477.send_ephemeral_event");
+    assert_eq!(msg["response"]["room_id"], ROOM_ID.as_str());
+
+    // Sending other types of ephemeral events is not allowed
+    send_request(
+        &driver_handle,
+        "send-typing",
+        "org.matrix.msc2477.send_ephemeral_event",
+        json!({
+            "type": "m.typing",
+            "content": { "user_ids": [] },
+        }),
+    )
+    .await;
+
+    let msg = recv_message(&driver_handle).await;
+    assert_eq!(msg["action"], "org.matrix.msc2477.send_ephemeral_event");
+    assert_eq!(msg["response"]["error"]["message"], "Not allowed");
+
+    // Make sure the ephemeral endpoint was hit exactly once
+    mock_server.verify().await;
+}
+
+#[async_test]
+async fn get_widgets_from_room_state() {
+    let (client, mock_server) = logged_in_client().await;
+
+    let widget_event = |event_type: &str, widget_id: &str, content: JsonValue| {
+        StateTestEvent::Custom(json!({
+            "type": event_type,
+            "state_key": widget_id,
+            "event_id": format!("${event_type}-{widget_id}"),
+            "sender": "@alice:example.org",
+            "origin_server_ts": 0,
+            "content": content,
+        }))
+    };
+
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_joined_room(
+        JoinedRoomBuilder::new(&ROOM_ID)
+            .add_state_event(widget_event(
+                "m.widget",
+                "call",
+                json!({ "type": "m.custom", "url": "https://call.example.org/?widgetId=$matrix_widget_id" }),
+            ))
+            // Same ID as the `m.widget` one, which is preferred.
+            .add_state_event(widget_event(
+                "im.vector.modular.widgets",
+                "call",
+                json!({ "type": "m.custom", "url": "https://old-call.example.org" }),
+            ))
+            .add_state_event(widget_event(
+                "im.vector.modular.widgets",
+                "etherpad",
+                json!({
+                    "type": "m.etherpad",
+                    "url": "https://pad.example.org/p/$padName",
+                    "waitForIframeLoad": false,
+                    "data": { "padName": "notes" },
+                }),
+            ))
+            // Removed widget.
+            .add_state_event(widget_event("im.vector.modular.widgets", "removed", json!({})))
+            // Widget with a forbidden URL.
+            .add_state_event(widget_event(
+                "im.vector.modular.widgets",
+                "script",
+                json!({ "type": "m.custom", "url": "javascript:alert(1)" }),
+            )),
+    );
+
+    mock_sync(&mock_server, sync_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::new().timeout(Duration::from_millis(3000))).await.unwrap();
+
+    let room = client.get_room(&ROOM_ID).unwrap();
+    let widgets = room.get_widgets().await.unwrap();
+    assert_eq!(widgets.len(), 2);
+
+    assert_eq!(widgets[0].widget_id(), "call");
+    assert_eq!(
+        widgets[0].raw_url().as_str(),
+        "https://call.example.org/?widgetId=$matrix_widget_id"
+    );
+    assert!(!widgets[0].init_on_content_load());
+
+    assert_eq!(widgets[1].widget_id(), "etherpad");
+    assert_eq!(widgets[1].raw_url().as_str(), "https://pad.example.org/p/$padName");
+    assert_eq!(widgets[1].widget_type(), "m.etherpad");
+    assert_eq!(widgets[1].data()["padName"], "notes");
+    assert!(widgets[1].init_on_content_load());
+}
+
+#[async_test]
+async fn add_and_remove_widgets() {
+    let (client, mock_server) = logged_in_client().await;
+
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_joined_room(
+        JoinedRoomBuilder::new(&ROOM_ID)
+            .add_state_event(StateTestEvent::PowerLevels)
+            .add_state_event(StateTestEvent::Custom(json!({
+                "type": "im.vector.modular.widgets",
+                "state_key": "existing",
+                "event_id": "$existing",
+                "sender": "@example:localhost",
+                "origin_server_ts": 0,
+                "content": { "type": "m.custom", "url": "https://existing.example.org" },
+            }))),
+    );
+    mock_sync(&mock_server, sync_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::new().timeout(Duration::from_millis(3000))).await.unwrap();
+    let room = client.get_room(&ROOM_ID).unwrap();
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/im.vector.modular.widgets/new$"))
+        .and(body_partial_json(json!({
+            "id": "new",
+            "type": "m.etherpad",
+            "url": "https://new.example.org/?widgetId=$matrix_widget_id",
+            "waitForIframeLoad": true,
+            "data": { "padName": "notes" },
+        })))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "event_id": "$new" })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    let widget = WidgetSettings::new(
+        "new".to_owned(),
+        false,
+        "https://new.example.org/?widgetId=$matrix_widget_id",
+    )
+    .unwrap()
+    .with_widget_type("m.etherpad")
+    .with_data(json!({ "padName": "notes" }).as_object().unwrap().clone());
+    room.add_widget(widget).await.unwrap();
+
+    // A widget with the same ID must be removed first.
+    let widget =
+        WidgetSettings::new("existing".to_owned(), false, "https://other.example.org").unwrap();
+    assert_matches!(
+        room.add_widget(widget).await,
+        Err(Error::RoomWidget(RoomWidgetError::AlreadyExists(_)))
+    );
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/im.vector.modular.widgets/existing$"))
+        .and(body_json(json!({})))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "event_id": "$removed" })))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    room.remove_widget("existing").await.unwrap();
+
+    assert_matches!(
+        room.remove_widget("unknown").await,
+        Err(Error::RoomWidget(RoomWidgetError::NotFound(_)))
+    );
+}
+
+#[async_test]
+async fn remove_widget_checks_the_power_levels_of_every_event_type() {
+    let (client, mock_server) = logged_in_client().await;
+
+    let widget_event = |event_type: &str| {
+        StateTestEvent::Custom(json!({
+            "type": event_type,
+            "state_key": "legacy",
+            "event_id": format!("${event_type}-legacy"),
+            "sender": "@example:localhost",
+            "origin_server_ts": 0,
+            "content": { "type": "m.custom", "url": "https://legacy.example.org" },
+        }))
+    };
+
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_joined_room(
+        JoinedRoomBuilder::new(&ROOM_ID)
+            .add_state_event(StateTestEvent::Custom(json!({
+                "type": "m.room.power_levels",
+                "state_key": "",
+                "event_id": "$power_levels",
+                "sender": "@example:localhost",
+                "origin_server_ts": 0,
+                "content": {
+                    "events": { "m.widget": 100 },
+                    "state_default": 50,
+                    "users": { "@example:localhost": 50 },
+                },
+            })))
+            .add_state_event(widget_event("m.widget"))
+            .add_state_event(widget_event("im.vector.modular.widgets")),
+    );
+    mock_sync(&mock_server, sync_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::new().timeout(Duration::from_millis(3000))).await.unwrap();
+    let room = client.get_room(&ROOM_ID).unwrap();
+
+    Mock::given(method("PUT"))
+        .and(path_regex(r"^/_matrix/client/r0/rooms/.*/state/"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "event_id": "$removed" })))
+        .expect(0)
+        .mount(&mock_server)
+        .await;
+
+    // The user can send `im.vector.modular.widgets` but not `m.widget`, so the
+    // widget isn't removed at all.
+    assert_matches!(
+        room.remove_widget("legacy").await,
+        Err(Error::RoomWidget(RoomWidgetError::Forbidden))
+    );
+}
+
+#[async_test]
+async fn deferred_capabilities_are_restored_after_restart() {
+    #[derive(Clone, Default)]
+    struct DeferringCapabilitiesProvider {
+        deferred: Arc<Mutex<Vec<DeferredCapabilitiesSender>>>,
+        resumed: Arc<Mutex<Vec<Capabilities>>>,
+    }
+
+    #[async_trait]
+    impl CapabilitiesProvider for DeferringCapabilitiesProvider {
+        async fn acquire_capabilities(&self, capabilities: Capabilities) -> Capabilities {
+            capabilities
+        }
+
+        async fn acquire_capabilities_or_defer(
+            &self,
+            _capabilities: Capabilities,
+        ) -> CapabilitiesDecision {
+            let (deferred, sender) = DeferredCapabilities::new();
+            self.deferred.lock().unwrap().push(sender);
+            CapabilitiesDecision::Deferred(deferred)
+        }
+
+        async fn resume_deferred_capabilities(
+            &self,
+            capabilities: Capabilities,
+        ) -> CapabilitiesDecision {
+            self.resumed.lock().unwrap().push(capabilities.clone());
+            CapabilitiesDecision::Granted(capabilities)
+        }
+    }
+
+    let (client, mock_server) = logged_in_client().await;
+    let mut sync_builder = SyncResponseBuilder::new();
+    sync_builder.add_joined_room(JoinedRoomBuilder::new(&ROOM_ID));
+    mock_sync(&mock_server, sync_builder.build_json_sync_response(), None).await;
+    client.sync_once(SyncSettings::new()).await.unwrap();
+    mock_encryption_state(&mock_server, false).await;
+
+    let provider = DeferringCapabilitiesProvider::default();
+    let start_driver = || {
+        let room = client.get_room(&ROOM_ID).unwrap();
+        let (driver, handle) = WidgetDriver::new(
+            WidgetSettings::new(WIDGET_ID.to_owned(), false, "https://foo.bar/widget").unwrap(),
+        );
+        let provider = provider.clone();
+        let task = spawn(async move {
+            let _ = driver.run(room, provider).await;
+        });
+        (task, handle)
+    };
+    let caps = json!(["org.matrix.msc2762.receive.event:m.room.message"]);
+
+    // The decision is deferred, and the driver stops before it is made.
+    let (task, driver_handle) = start_driver();
+    {
+        let msg = recv_message(&driver_handle).await;
+        assert_eq!(msg["action"], "capabilities");
+        let request_id = msg["requestId"].as_str().unwrap();
+        let response = json!({ "capabilities": caps });
+        send_response(&driver_handle, request_id, "capabilities", &msg["data"], &response).await;
+    }
+
+    timeout(
+        async {
+            while provider.deferred.lock().unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        },
+        Duration::from_secs(1),
+    )
+    .await
+    .unwrap();
+    task.abort();
+
+    // The next driver of the widget resumes the pending request.
+    let (task, driver_handle) = start_driver();
+    negotiate_capabilities(&driver_handle, caps.clone()).await;
+    assert_eq!(provider.resumed.lock().unwrap().len(), 1);
+    task.abort();
+
+    // The request isn't pending anymore once the capabilities are granted.
+    let (_task, driver_handle) = start_driver();
+    {
+        let msg = recv_message(&driver_handle).await;
+        assert_eq!(msg["action"], "capabilities");
+        let request_id = msg["requestId"].as_str().unwrap();
+        let response = json!({ "capabilities": caps });
+        send_response(&driver_handle, request_id, "capabilities", &msg["data"], &response).await;
+    }
+
+    timeout(
+        async {
+            while provider.deferred.lock().unwrap().len() < 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        },
+        Duration::from_secs(1),
+    )
+    .await
+    .unwrap();
+    assert_eq!(provider.resumed.lock().unwrap().len(), 1);
+}
+
+#[async_test]
+async fn superseded_file_picking_fails() {
+    /// A file picker where the user never picks the first file.
+    #[derive(Default)]
+    struct SlowFilePicker {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl FilePicker for SlowFilePicker {
+        async fn pick_file(&self, _accepted_types: Vec<String>) -> Option<PickedFile> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                std::future::pending::<()>().await;
+            }
+
+            Some(PickedFile {
+                name: "cat.png".to_owned(),
+                content_type: mime::IMAGE_PNG,
+                data: b"meow".to_vec(),
+            })
+        }
+    }
+
+    let (_, mock_server, driver_handle) =
+        run_test_driver_with(false, |driver| driver.with_file_picker(SlowFilePicker::default()))
+            .await;
+
+    negotiate_capabilities(&driver_handle, json!(["io.element.pick_file"])).await;
+
+    Mock::given(method("POST"))
+        .and(path_regex(r"^/_matrix/media/.*/upload"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({ "content_uri": "mxc://example.org/cat" })),
+        )
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+    send_request(&driver_handle, "pick-1", "io.element.pick_file", json!({})).await;
+    send_request(&driver_handle, "pick-2", "io.element.pick_file", json!({})).await;
+
+    // The first file picking is answered with an error as soon as it's
+    // superseded.
+    let msg = recv_message(&driver_handle).await;
+    assert_eq!(msg["requestId"], "pick-1");
+    assert!(msg["response"]["error"]["message"].is_string());
+
+    // The second one gets the uploaded file.
+    let msg = recv_message(&driver_handle).await;
+    assert_eq!(msg["requestId"], "pick-2");
+    assert_eq!(msg["response"]["file"]["content_uri"], "mxc://example.org/cat");
+    assert_eq!(msg["response"]["file"]["name"], "cat.png");
+
+    // No more messages from the driver.
+    assert_matches!(recv_message(&driver_handle).now_or_never(), None);
+
+    mock_server.verify().await;
+}
+
 async fn negotiate_capabilities(driver_handle: &WidgetDriverHandle, caps: JsonValue) {
     {
         // Receive toWidget capabilities request
@@ -3,13 +3,13 @@ use clap::Parser;
 use futures_util::{pin_mut, StreamExt};
 use matrix_sdk::{
     config::SyncSettings,
-    encryption::{backups::BackupState, secret_storage::SecretStore},
+    encryption::{backups::BackupState, recovery::RecoveryState},
     Client,
 };
 use url::Url;
 
-/// A command line example showcasing how to resume backups by importing the
-/// backup key from secret storage.
+/// A command line example showcasing how to resume backups by recovering the
+/// secrets, including the backup key, from secret storage.
 #[derive(Parser, Debug)]
 struct Cli {
     /// The homeserver to connect to.
@@ -32,27 +32,10 @@ struct Cli {
     #[clap(short, long, action)]
     verbose: bool,
 
-    /// The secret storage key, this key will be used to open the secret-store.
+    /// The recovery key or passphrase, it will be used to open the secret
+    /// storage and import the secrets.
     #[clap(long, action)]
-    secret_store_key: String,
-}
-
-async fn import_known_secrets(client: &Client, secret_store: SecretStore) -> Result<()> {
-    secret_store.import_secrets().await?;
-
-    let status = client
-        .encryption()
-        .cross_signing_status()
-        .await
-        .expect("We should be able to get our cross-signing status");
-
-    if status.is_complete() {
-        println!("Successfully imported all the cross-signing keys");
-    } else {
-        eprintln!("Couldn't import all the cross-signing keys: {status:?}");
-    }
-
-    Ok(())
+    recovery_key: String,
 }
 
 async fn login(cli: &Cli) -> Result<Client> {
@@ -90,6 +73,20 @@ async fn listen_for_backup_state_changes(client: Client) {
     }
 }
 
+async fn listen_for_recovery_state_changes(client: Client) {
+    let stream = client.encryption().recovery().state_stream();
+    pin_mut!(stream);
+
+    while let Some(state) = stream.next().await {
+        match state {
+            RecoveryState::Unknown => (),
+            RecoveryState::Enabled => println!("Successfully recovered all the secrets"),
+            RecoveryState::Disabled => println!("Recovery isn't set up for this account"),
+            RecoveryState::Incomplete => println!("Some secrets couldn't be recovered"),
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
@@ -102,15 +99,18 @@ async fn main() -> Result<()> {
 
     client.sync_once(Default::default()).await?;
 
-    let secret_store =
-        client.encryption().secret_storage().open_secret_store(&cli.secret_store_key).await?;
-
-    let _task = tokio::spawn({
+    let _backup_task = tokio::spawn({
         let client = client.clone();
         async move { listen_for_backup_state_changes(client).await }
     });
+    let _recovery_task = tokio::spawn({
+        let client = client.clone();
+        async move { listen_for_recovery_state_changes(client).await }
+    });
 
-    import_known_secrets(&client, secret_store).await?;
+    // Recovering imports the cross-signing keys and the backup key from the
+    // secret storage, the backups are then resumed automatically.
+    client.encryption().recovery().recover(&cli.recovery_key).await?;
 
     loop {
         if let Err(e) = client.sync(SyncSettings::new()).await {
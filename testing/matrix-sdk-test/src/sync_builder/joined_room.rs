@@ -123,6 +123,18 @@ impl JoinedRoomBuilder {
         self.inner.unread_notifications = from_json_value(unread_notifications).unwrap();
         self
     }
+
+    /// Set the unread notifications count of the threads of the room.
+    ///
+    /// The JSON object must be keyed by thread root event ID.
+    pub fn set_unread_thread_notifications_count(
+        mut self,
+        unread_thread_notifications: JsonValue,
+    ) -> Self {
+        self.inner.unread_thread_notifications =
+            from_json_value(unread_thread_notifications).unwrap();
+        self
+    }
 }
 
 impl Default for JoinedRoomBuilder {
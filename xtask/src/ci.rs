@@ -82,6 +82,8 @@ enum WasmFeatureSet {
     MatrixSdkCommon,
     /// Check `matrix-sdk` crate with no default features
     MatrixSdkNoDefault,
+    /// Check `matrix-sdk` crate with the `experimental-widgets` feature
+    MatrixSdkWidgets,
     /// Check `matrix-sdk` crate with `indexeddb` feature (but not
     /// `e2e-encryption`)
     MatrixSdkIndexeddbStoresNoCrypto,
@@ -280,6 +282,10 @@ fn run_wasm_checks(cmd: Option<WasmFeatureSet>) -> Result<()> {
             WasmFeatureSet::MatrixSdkNoDefault,
             "-p matrix-sdk --no-default-features --features js,rustls-tls",
         ),
+        (
+            WasmFeatureSet::MatrixSdkWidgets,
+            "-p matrix-sdk --no-default-features --features js,rustls-tls,experimental-widgets",
+        ),
         (WasmFeatureSet::MatrixSdkBase, "-p matrix-sdk-base --features js"),
         (WasmFeatureSet::MatrixSdkCommon, "-p matrix-sdk-common --features js"),
         (
@@ -331,6 +337,13 @@ fn run_wasm_pack_tests(cmd: Option<WasmFeatureSet>) -> Result<()> {
             WasmFeatureSet::MatrixSdkNoDefault,
             ("crates/matrix-sdk", "--no-default-features --features js,rustls-tls --lib"),
         ),
+        (
+            WasmFeatureSet::MatrixSdkWidgets,
+            (
+                "crates/matrix-sdk",
+                "--no-default-features --features js,rustls-tls,experimental-widgets --lib",
+            ),
+        ),
         (WasmFeatureSet::MatrixSdkBase, ("crates/matrix-sdk-base", "--features js")),
         (WasmFeatureSet::MatrixSdkCommon, ("crates/matrix-sdk-common", "--features js")),
         (